@@ -22,7 +22,7 @@ mkdir target/disk_image
 sudo mount `cat loopback_dev`p1 target/disk_image
 sudo cp -r -v sysroot/. target/disk_image/
 pushd target/disk_image
-sudo mkdir dev proc tmp
+sudo mkdir -p dev proc tmp sys/block sys/fs/cgroup sys/kernel/tracing sys/firmware/efi/efivars
 popd
 sync
 sudo umount target/disk_image/
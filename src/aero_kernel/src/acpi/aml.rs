@@ -1,15 +1,58 @@
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::Once;
 
 /// ## Reference
 /// * [ACPI Sleeping States](https://uefi.org/specs/ACPI/6.4/16_Waking_and_Sleeping/sleeping-states.html)
 #[repr(u8)]
 pub enum SleepState {
+    S3 = 3,
     S5 = 5,
 }
 
+/// The result of evaluating an object in the ACPI namespace.
+#[derive(Debug, Clone)]
+pub enum AmlValue {
+    Integer(u64),
+    String(String),
+    Package(Vec<AmlValue>),
+    /// Buffers, references and other objects that the kernel does not need to inspect.
+    Unsupported,
+}
+
+impl AmlValue {
+    pub fn as_integer(&self) -> Option<u64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_package(&self) -> Option<&[AmlValue]> {
+        match self {
+            Self::Package(elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+/// An opaque handle to a node in the ACPI namespace.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AmlHandle(pub usize);
+
 pub trait AmlSubsystem: Send + Sync {
     fn enter_state(&self, state: SleepState);
+    /// Returns whether the firmware supports `state` (i.e. the DSDT has an `\_Sx` package).
+    fn supports_state(&self, state: SleepState) -> bool;
+    /// Informs the firmware that the machine woke up from `state` by evaluating `\_WAK`.
+    fn leave_state(&self, state: SleepState);
     /// Ensures that the system control interrupt (SCI) is properly
     /// configured, disables SCI event sources, installs the SCI handler, and
     /// transfers the system hardware into ACPI mode.
@@ -18,6 +61,15 @@ pub trait AmlSubsystem: Send + Sync {
     /// * `mode` - IRQ mode (ACPI spec section 5.8.1)
     fn enable_acpi(&self, mode: u32);
     fn pci_route_pin(&self, seg: u16, bus: u8, slot: u8, function: u8, pin: u8) -> u8;
+
+    /// Returns the devices that have a child object called `name` (e.g. `_BST` for batteries),
+    /// in namespace order.
+    fn find_devices(&self, name: &str) -> Vec<AmlHandle>;
+    /// Returns the node at the absolute `path` (e.g. `\_GPE._L0D`), if it exists.
+    fn resolve(&self, path: &str) -> Option<AmlHandle>;
+    /// Evaluates the child object `name` of `device`. Returns [`None`] if it does not exist or
+    /// if its evaluation failed.
+    fn evaluate(&self, device: AmlHandle, name: &str) -> Option<AmlValue>;
 }
 
 static AML_SUBSYSTEM: Once<Arc<dyn AmlSubsystem>> = Once::new();
@@ -26,6 +78,12 @@ pub fn get_subsystem() -> Arc<dyn AmlSubsystem> {
     AML_SUBSYSTEM.get().unwrap().clone()
 }
 
+/// Returns the AML subsystem or [`None`] if it has not been initialized (e.g. if the machine
+/// does not support ACPI).
+pub fn try_get_subsystem() -> Option<Arc<dyn AmlSubsystem>> {
+    AML_SUBSYSTEM.get().cloned()
+}
+
 pub fn init(subsystem: Arc<dyn AmlSubsystem>) {
     assert!(
         AML_SUBSYSTEM.get().is_none(),
@@ -0,0 +1,356 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! ACPI events: the fixed-feature power button and the general-purpose events (GPEs), which the
+//! firmware uses to signal e.g. that the lid was opened or closed.
+//!
+//! The events are delivered to userland through the input core (see [`input`]): presses of the
+//! power button are reported as `KEY_POWER` by the "Power Button" device, so that init can shut
+//! the system down cleanly, and the state of the lid as `SW_LID` by the "Lid Switch" device.
+//!
+//! The GPE handlers (`\_GPE._Exx` and `\_GPE._Lxx`) are run on the system workqueue, as AML
+//! cannot be evaluated in the SCI handler. Power buttons that are implemented with control
+//! methods (`PNP0C0C`) report presses with `Notify`, which the AML interpreter does not deliver
+//! to the kernel, so they are not supported.
+//!
+//! **Notes**: <https://uefi.org/specs/ACPI/6.5/04_ACPI_Hardware_Specification.html#acpi-hardware-features>
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Once;
+use uapi::input::{InputId, BUS_HOST, EV_KEY, EV_SW, KEY_POWER, SW_LID};
+
+use crate::arch::interrupts::{self, InterruptStack};
+use crate::arch::{apic, io};
+use crate::drivers::input::{self, Capabilities, InputDevice};
+use crate::userland::workqueue;
+
+use super::aml::{self, AmlHandle};
+use super::fadt;
+
+/// The power button status (`PM1_STS`) and enable (`PM1_EN`) bit.
+const PWRBTN: u16 = 1 << 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Trigger {
+    /// Handled by `_Exx`; the status is cleared before running it.
+    Edge,
+    /// Handled by `_Lxx`; the status is cleared after running it.
+    Level,
+}
+
+/// A GPE register block, which has a status register followed by an enable register for each
+/// group of 8 GPEs.
+struct GpeBlock {
+    port: u16,
+    /// Number of status (or enable) registers.
+    len: u16,
+    /// Number of the first GPE of the block.
+    base: u16,
+}
+
+impl GpeBlock {
+    fn status_port(&self, register: u16) -> u16 {
+        self.port + register
+    }
+
+    fn enable_port(&self, register: u16) -> u16 {
+        self.port + self.len + register
+    }
+
+    /// Returns the register and the bit of `gpe` if it belongs to this block.
+    fn locate(&self, gpe: u16) -> Option<(u16, u8)> {
+        let index = gpe.checked_sub(self.base)?;
+        (index < self.len * 8).then_some((index / 8, 1 << (index % 8)))
+    }
+
+    fn set_enabled(&self, gpe: u16, enabled: bool) {
+        let Some((register, bit)) = self.locate(gpe) else {
+            return;
+        };
+
+        unsafe {
+            let value = io::inb(self.enable_port(register));
+            let value = if enabled { value | bit } else { value & !bit };
+
+            io::outb(self.enable_port(register), value);
+        }
+    }
+
+    fn clear_status(&self, gpe: u16) {
+        if let Some((register, bit)) = self.locate(gpe) {
+            unsafe { io::outb(self.status_port(register), bit) }
+        }
+    }
+}
+
+struct AcpiEvents {
+    /// The `PM1a` and `PM1b` event blocks, if present.
+    pm1_blocks: Vec<u16>,
+    /// Offset of `PM1_EN` in the event blocks.
+    pm1_enable: u16,
+
+    gpe_blocks: Vec<GpeBlock>,
+    /// The GPEs that have a handler, which are the only ones that are enabled.
+    handlers: BTreeMap<u16, Trigger>,
+    gpe_scope: Option<AmlHandle>,
+
+    power_button: Option<Arc<InputDevice>>,
+    lid: Option<(AmlHandle, Arc<InputDevice>)>,
+}
+
+impl AcpiEvents {
+    fn gpe_block(&self, gpe: u16) -> Option<&GpeBlock> {
+        self.gpe_blocks
+            .iter()
+            .find(|block| block.locate(gpe).is_some())
+    }
+
+    /// Runs the handler of `gpe` and enables it again.
+    fn dispatch_gpe(&self, gpe: u16) {
+        let (Some(block), Some(&trigger), Some(scope)) =
+            (self.gpe_block(gpe), self.handlers.get(&gpe), self.gpe_scope)
+        else {
+            return;
+        };
+
+        let subsystem = aml::get_subsystem();
+
+        match trigger {
+            Trigger::Edge => {
+                block.clear_status(gpe);
+                subsystem.evaluate(scope, &alloc::format!("_E{gpe:02X}"));
+            }
+
+            Trigger::Level => {
+                subsystem.evaluate(scope, &alloc::format!("_L{gpe:02X}"));
+                block.clear_status(gpe);
+            }
+        }
+
+        block.set_enabled(gpe, true);
+
+        // The handler notifies the lid device if the lid was opened or closed, which is not
+        // delivered to the kernel, so check whether its state changed.
+        self.update_lid();
+    }
+
+    fn update_lid(&self) {
+        let Some((handle, device)) = self.lid.as_ref() else {
+            return;
+        };
+
+        // `_LID` returns zero if the lid is closed, while `SW_LID` is set if it is closed.
+        if let Some(open) = aml::get_subsystem()
+            .evaluate(*handle, "_LID")
+            .and_then(|value| value.as_integer())
+        {
+            device.report(EV_SW, SW_LID, (open == 0) as i32);
+            device.sync();
+        }
+    }
+}
+
+static EVENTS: Once<AcpiEvents> = Once::new();
+
+fn report_power_button() {
+    let Some(device) = EVENTS.get().and_then(|events| events.power_button.as_ref()) else {
+        return;
+    };
+
+    log::info!("acpi: power button pressed");
+
+    device.report_key(KEY_POWER, true);
+    device.sync();
+    device.report_key(KEY_POWER, false);
+    device.sync();
+}
+
+fn sci_handler(_stack: &mut InterruptStack) {
+    let Some(events) = EVENTS.get() else {
+        return;
+    };
+
+    for &block in events.pm1_blocks.iter() {
+        let status = unsafe { io::inw(block) };
+
+        if status & PWRBTN != 0 {
+            // The status bits are cleared by writing ones to them.
+            unsafe { io::outw(block, PWRBTN) };
+            workqueue::schedule_work(report_power_button);
+        }
+    }
+
+    for block in events.gpe_blocks.iter() {
+        for register in 0..block.len {
+            let pending = unsafe {
+                io::inb(block.status_port(register)) & io::inb(block.enable_port(register))
+            };
+
+            if pending == 0 {
+                continue;
+            }
+
+            // Disable the GPEs until their handlers have run, as level-triggered GPEs stay
+            // asserted until then.
+            unsafe {
+                let enabled = io::inb(block.enable_port(register));
+                io::outb(block.enable_port(register), enabled & !pending);
+            }
+
+            for bit in (0..8).filter(|bit| pending & (1 << bit) != 0) {
+                let gpe = block.base + register * 8 + bit;
+
+                workqueue::schedule_work(move || {
+                    if let Some(events) = EVENTS.get() {
+                        events.dispatch_gpe(gpe);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Returns the GPEs of `blocks` that have a handler.
+fn find_handlers(blocks: &[GpeBlock]) -> BTreeMap<u16, Trigger> {
+    let subsystem = aml::get_subsystem();
+    let mut handlers = BTreeMap::new();
+
+    for block in blocks {
+        for gpe in block.base..block.base + block.len * 8 {
+            if subsystem
+                .resolve(&alloc::format!("\\_GPE._E{gpe:02X}"))
+                .is_some()
+            {
+                handlers.insert(gpe, Trigger::Edge);
+            } else if subsystem
+                .resolve(&alloc::format!("\\_GPE._L{gpe:02X}"))
+                .is_some()
+            {
+                handlers.insert(gpe, Trigger::Level);
+            }
+        }
+    }
+
+    handlers
+}
+
+/// Enables the power button and the GPEs that have a handler, and installs the SCI handler.
+/// Must be called once the system has been transitioned into ACPI mode.
+pub fn init() {
+    let Some((fadt, _)) = fadt::get() else {
+        return;
+    };
+
+    let pm1_blocks = [fadt.pm1a_event_block, fadt.pm1b_event_block]
+        .into_iter()
+        .filter(|&block| block != 0)
+        .map(|block| block as u16)
+        .collect::<Vec<_>>();
+
+    let pm1_enable = u16::from(fadt.pm1_event_length / 2);
+
+    let mut gpe_blocks = Vec::new();
+
+    if fadt.gpe0_block != 0 && fadt.gpe0_ength != 0 {
+        gpe_blocks.push(GpeBlock {
+            port: fadt.gpe0_block as u16,
+            len: u16::from(fadt.gpe0_ength / 2),
+            base: 0,
+        });
+    }
+
+    if fadt.gpe1_block != 0 && fadt.gpe1_length != 0 {
+        gpe_blocks.push(GpeBlock {
+            port: fadt.gpe1_block as u16,
+            len: u16::from(fadt.gpe1_length / 2),
+            base: u16::from(fadt.gpe1_base),
+        });
+    }
+
+    let subsystem = aml::get_subsystem();
+    let flags = fadt.flags;
+
+    // Otherwise, the power button is a control method device.
+    let power_button = (flags & fadt::PWR_BUTTON == 0).then(|| {
+        let mut capabilities = Capabilities::new();
+        capabilities.set(EV_KEY, KEY_POWER);
+
+        let id = InputId {
+            bustype: BUS_HOST,
+            vendor: 0x0000,
+            product: 0x0001,
+            version: 0x0000,
+        };
+
+        input::register("Power Button", id, capabilities)
+    });
+
+    let lid = subsystem.find_devices("_LID").first().map(|&handle| {
+        let mut capabilities = Capabilities::new();
+        capabilities.set(EV_SW, SW_LID);
+
+        let id = InputId {
+            bustype: BUS_HOST,
+            vendor: 0x0000,
+            product: 0x0005,
+            version: 0x0000,
+        };
+
+        (handle, input::register("Lid Switch", id, capabilities))
+    });
+
+    let events = EVENTS.call_once(|| AcpiEvents {
+        pm1_blocks,
+        pm1_enable,
+        handlers: find_handlers(&gpe_blocks),
+        gpe_scope: subsystem.resolve("\\_GPE"),
+        gpe_blocks,
+        power_button,
+        lid,
+    });
+
+    events.update_lid();
+
+    let vector = interrupts::allocate_vector();
+    interrupts::register_handler(vector, sci_handler);
+    apic::io_apic_setup_legacy_irq(fadt.sci_interrupt as u8, vector, 1);
+
+    if events.power_button.is_some() {
+        for &block in events.pm1_blocks.iter() {
+            unsafe {
+                io::outw(block, PWRBTN);
+
+                let enabled = io::inw(block + events.pm1_enable);
+                io::outw(block + events.pm1_enable, enabled | PWRBTN);
+            }
+        }
+    }
+
+    for &gpe in events.handlers.keys() {
+        if let Some(block) = events.gpe_block(gpe) {
+            block.clear_status(gpe);
+            block.set_enabled(gpe, true);
+        }
+    }
+
+    log::debug!("acpi: enabled {} GPEs (sci={})", events.handlers.len(), {
+        fadt.sci_interrupt
+    });
+}
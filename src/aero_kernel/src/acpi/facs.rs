@@ -0,0 +1,75 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The FACS (Firmware ACPI Control Structure) is a structure in read/write memory that contains
+//! the waking vector, which the firmware jumps to when the machine wakes up from a sleep state.
+//!
+//! **Notes**: <https://uefi.org/specs/ACPI/6.4/05_ACPI_Software_Programming_Model/ACPI_Software_Programming_Model.html#firmware-acpi-control-structure-facs>
+
+use crate::mem::paging::PhysAddr;
+
+use super::fadt;
+
+pub const SIGNATURE: &[u8; 4] = b"FACS";
+
+#[repr(C, packed)]
+pub struct Facs {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub hardware_signature: u32,
+    /// The real mode address (`segment << 4 | offset`) that the firmware jumps to on wakeup.
+    pub firmware_waking_vector: u32,
+    pub global_lock: u32,
+    pub flags: u32,
+    /// Used instead of `firmware_waking_vector` if it is not zero (ACPI 2.0+).
+    pub x_firmware_waking_vector: u64,
+    pub version: u8,
+}
+
+/// Returns the FACS referenced by the FADT, if it exists and is valid.
+fn get() -> Option<*mut Facs> {
+    let (fadt, length) = fadt::get()?;
+    let address = fadt.facs_address(length)?;
+
+    let facs = PhysAddr::new(address).as_hhdm_virt().as_mut_ptr::<Facs>();
+    let signature = unsafe { core::ptr::addr_of!((*facs).signature).read_unaligned() };
+
+    (&signature == SIGNATURE).then_some(facs)
+}
+
+/// Returns whether the machine has a FACS, which is required to wake up from a sleep state.
+pub fn is_available() -> bool {
+    get().is_some()
+}
+
+/// Sets the real mode waking vector to `address`, which has to be below 1MiB. The 64-bit waking
+/// vector is cleared so that the firmware uses the real mode one.
+pub fn set_waking_vector(address: u32) {
+    let Some(facs) = get() else {
+        return;
+    };
+
+    unsafe {
+        core::ptr::addr_of_mut!((*facs).firmware_waking_vector).write_volatile(address);
+
+        let length = core::ptr::addr_of!((*facs).length).read_volatile();
+
+        if length as usize >= core::mem::offset_of!(Facs, version) {
+            core::ptr::addr_of_mut!((*facs).x_firmware_waking_vector).write_unaligned(0);
+        }
+    }
+}
@@ -21,9 +21,15 @@
 //! **Notes**: <https://wiki.osdev.org/FADT>
 
 use super::sdt::Sdt;
+use super::GenericAddressStructure;
 
 pub const SIGNATURE: &str = "FACP";
 
+/// The power button is a control method device, instead of a fixed-feature button.
+pub const PWR_BUTTON: u32 = 1 << 4;
+/// The reset register is supported (see [`Fadt::reset_register`]).
+pub const RESET_REG_SUP: u32 = 1 << 10;
+
 #[repr(C, packed)]
 pub struct Fadt {
     pub header: Sdt,
@@ -71,4 +77,52 @@ pub struct Fadt {
     reserved2: u8,
 
     pub flags: u32,
+
+    // Used since ACPI 2.0+
+    pub reset_reg: GenericAddressStructure,
+    pub reset_value: u8,
+    pub arm_boot_architecture_flags: u16,
+    pub minor_version: u8,
+    pub x_firmware_ctrl: u64,
+    pub x_dsdt: u64,
+}
+
+impl Fadt {
+    /// Returns the reset register and the value that has to be written to it to reset the
+    /// system, if supported. `length` is the length of the table in bytes (see [`get`]).
+    pub fn reset_register(&self, length: usize) -> Option<(GenericAddressStructure, u8)> {
+        if length < core::mem::offset_of!(Fadt, reset_value) + 1 {
+            return None;
+        }
+
+        let flags = self.flags;
+
+        if flags & RESET_REG_SUP == 0 {
+            return None;
+        }
+
+        Some((self.reset_reg, self.reset_value))
+    }
+
+    /// Returns the physical address of the FACS, preferring the 64-bit address if the table is
+    /// long enough to have one. `length` is the length of the table in bytes (see [`get`]).
+    pub fn facs_address(&self, length: usize) -> Option<u64> {
+        if length >= core::mem::offset_of!(Fadt, x_firmware_ctrl) + 8 {
+            let address = self.x_firmware_ctrl;
+
+            if address != 0 {
+                return Some(address);
+            }
+        }
+
+        Some(self.firmware_ctrl as u64).filter(|&address| address != 0)
+    }
+}
+
+/// Returns the FADT and its length in bytes, if the ACPI tables have one.
+pub fn get() -> Option<(&'static Fadt, usize)> {
+    let header = super::get_acpi_table().lookup_entry(SIGNATURE, 0)?;
+    let length = core::mem::size_of::<Sdt>() + header.data_len();
+
+    Some((unsafe { header.as_ref() }, length))
 }
@@ -15,13 +15,35 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+//! The HPET (High Precision Event Timer) provides a monotonic main counter that runs at a fixed
+//! frequency (of at least 10MHz), which is used as the high-resolution clock source.
+//!
+//! **Notes**: <https://wiki.osdev.org/HPET>
+
 use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::mem::paging::{PhysAddr, VirtAddr};
 
 use super::sdt::Sdt;
 use super::GenericAddressStructure;
 
 pub const SIGNATURE: &str = "HPET";
 
+const REG_CAPABILITIES: u64 = 0x00;
+const REG_CONFIG: u64 = 0x10;
+const REG_MAIN_COUNTER: u64 = 0xf0;
+
+/// Set if the main counter is 64 bits wide.
+const CAP_COUNT_SIZE: u64 = 1 << 13;
+/// Starts the main counter.
+const CONFIG_ENABLE: u64 = 1;
+
+/// Virtual address of the HPET registers; zero if the HPET is not available.
+static BASE_ADDRESS: AtomicU64 = AtomicU64::new(0);
+/// Period of the main counter in femtoseconds.
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+
 #[repr(C, packed)]
 pub(super) struct Hpet {
     header: Sdt,
@@ -38,4 +60,100 @@ impl Hpet {
     pub fn new(sdt: &'static Sdt) -> Self {
         unsafe { ptr::read((sdt as *const Sdt) as *const Self) }
     }
+
+    /// Starts the main counter of the HPET and makes it available as a clock source.
+    pub fn init(&self) {
+        let address = self.base_address;
+
+        // The HPET registers must be memory mapped.
+        if address.address_space != 0 {
+            log::warn!("hpet: unsupported address space {}", address.address_space);
+            return;
+        }
+
+        let base = PhysAddr::new(address.address).as_hhdm_virt();
+
+        unsafe {
+            let capabilities = read(base, REG_CAPABILITIES);
+
+            // A 32-bit main counter wraps around in a few minutes, which makes it unusable as
+            // a monotonic clock.
+            if capabilities & CAP_COUNT_SIZE == 0 {
+                log::warn!("hpet: 32-bit main counter is not supported");
+                return;
+            }
+
+            write(base, REG_CONFIG, read(base, REG_CONFIG) | CONFIG_ENABLE);
+
+            let period = capabilities >> 32;
+            PERIOD_FS.store(period, Ordering::SeqCst);
+
+            log::debug!("hpet: enabled main counter (period={period}fs)");
+        }
+
+        BASE_ADDRESS.store(base.as_u64(), Ordering::SeqCst);
+    }
+}
+
+unsafe fn read(base: VirtAddr, register: u64) -> u64 {
+    ptr::read_volatile((base + register).as_ptr::<u64>())
+}
+
+unsafe fn write(base: VirtAddr, register: u64, value: u64) {
+    ptr::write_volatile((base + register).as_mut_ptr::<u64>(), value)
+}
+
+/// Returns the amount of nanoseconds elapsed since the main counter of the HPET was started or
+/// [`None`] if the HPET is not available.
+pub fn nanos() -> Option<u64> {
+    let base = BASE_ADDRESS.load(Ordering::Relaxed);
+
+    if base == 0 {
+        return None;
+    }
+
+    let counter = unsafe { read(VirtAddr::new(base), REG_MAIN_COUNTER) };
+    let period = PERIOD_FS.load(Ordering::Relaxed);
+
+    Some((counter as u128 * period as u128 / 1000000) as u64)
+}
+
+/// Returns the physical address of the main counter register and the period of the counter in
+/// femtoseconds or [`None`] if the HPET is not available.
+pub fn main_counter() -> Option<(PhysAddr, u64)> {
+    let base = BASE_ADDRESS.load(Ordering::Relaxed);
+
+    if base == 0 {
+        return None;
+    }
+
+    let counter = (VirtAddr::new(base) + REG_MAIN_COUNTER).as_hhdm_phys();
+    Some((counter, PERIOD_FS.load(Ordering::Relaxed)))
+}
+
+/// Returns the value of the main counter, which is lost when the machine sleeps, or [`None`] if
+/// the HPET is not available.
+pub fn save_counter() -> Option<u64> {
+    let base = BASE_ADDRESS.load(Ordering::Relaxed);
+    (base != 0).then(|| unsafe { read(VirtAddr::new(base), REG_MAIN_COUNTER) })
+}
+
+/// Restarts the main counter at `counter` after waking up.
+pub fn restore_counter(counter: u64) {
+    let base = BASE_ADDRESS.load(Ordering::Relaxed);
+
+    if base == 0 {
+        return;
+    }
+
+    let base = VirtAddr::new(base);
+
+    // The main counter can only be written while it is halted.
+    unsafe {
+        let config = read(base, REG_CONFIG);
+
+        write(base, REG_CONFIG, config & !CONFIG_ENABLE);
+        write(base, REG_MAIN_COUNTER, counter);
+        write(base, REG_CONFIG, config | CONFIG_ENABLE);
+    }
 }
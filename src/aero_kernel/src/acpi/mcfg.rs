@@ -49,6 +49,16 @@ impl Mcfg {
     pub fn entry_count(&self) -> usize {
         (self.header.length as usize - mem::size_of::<Self>()) / mem::size_of::<DeviceConfig>()
     }
+
+    /// Returns the configuration space base address allocation structures, one for each range
+    /// of buses in a PCI segment group.
+    pub fn entries(&self) -> &[DeviceConfig] {
+        // SAFETY: The entries directly follow the table header.
+        unsafe {
+            let start = (self as *const Self).add(1).cast::<DeviceConfig>();
+            core::slice::from_raw_parts(start, self.entry_count())
+        }
+    }
 }
 
 /// Returns true if the ACPI table contains the MCFG entry.
@@ -31,10 +31,14 @@ use self::mcfg::Mcfg;
 use self::sdt::Sdt;
 
 pub mod aml;
+#[cfg(target_arch = "x86_64")]
+pub mod events;
+pub mod facs;
 pub mod fadt;
 pub mod hpet;
 pub mod madt;
 pub mod mcfg;
+pub mod power_supply;
 pub mod rsdp;
 pub mod sdt;
 
@@ -110,12 +114,6 @@ pub fn init(rsdp_address: VirtAddr) {
 
     let acpi_table = get_acpi_table();
 
-    macro init_table($sig:path => $ty:ty) {
-        if let Some(table) = acpi_table.lookup_entry($sig, 0) {
-            <$ty>::new(table);
-        }
-    }
-
     if let Some(header) = acpi_table.lookup_entry(mcfg::SIGNATURE, 0) {
         unsafe {
             let mcfg: &'static Mcfg = header.as_ref();
@@ -138,5 +136,7 @@ pub fn init(rsdp_address: VirtAddr) {
         }
     }
 
-    init_table!(hpet::SIGNATURE => Hpet);
+    if let Some(header) = acpi_table.lookup_entry(hpet::SIGNATURE, 0) {
+        Hpet::new(header).init();
+    }
 }
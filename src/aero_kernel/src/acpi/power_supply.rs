@@ -0,0 +1,301 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Batteries (`PNP0C0A`) and AC adapters (`ACPI0003`) reported by the ACPI namespace. The state
+//! is read from the `_BST`/`_BIX` (or `_BIF`) objects of each battery and the `_PSR` object of
+//! each AC adapter whenever it is requested, so it is never stale.
+//!
+//! The state is formatted like the `uevent` files of Linux' power supply class, so that status
+//! bars can parse it without much effort.
+//!
+//! **Notes**: <https://uefi.org/specs/ACPI/6.5/10_Power_Source_and_Power_Meter_Devices.html>
+
+use core::fmt::Write;
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use super::aml::{self, AmlHandle, AmlValue};
+
+/// Returned by `_BIF`, `_BIX` and `_BST` for values that are unknown.
+const UNKNOWN: u64 = 0xffff_ffff;
+
+/// `_BST` battery state bits.
+const BST_DISCHARGING: u64 = 1 << 0;
+const BST_CHARGING: u64 = 1 << 1;
+const BST_CRITICAL: u64 = 1 << 2;
+
+/// `_STA` bit that is set if a battery is inserted.
+const STA_BATTERY_PRESENT: u64 = 1 << 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Battery,
+    Mains,
+}
+
+struct PowerSupply {
+    name: String,
+    kind: Kind,
+    handle: AmlHandle,
+}
+
+/// The static information about a battery, from `_BIX` or `_BIF`.
+struct BatteryInfo {
+    /// Whether the capacities are in mAh (instead of mWh) and the rates in mA (instead of mW).
+    charge_units: bool,
+    design_capacity: u64,
+    full_capacity: u64,
+    design_voltage: u64,
+    cycle_count: Option<u64>,
+    model: Option<String>,
+    manufacturer: Option<String>,
+}
+
+impl BatteryInfo {
+    fn read(subsystem: &dyn aml::AmlSubsystem, handle: AmlHandle) -> Option<Self> {
+        // `_BIX` has the same fields as `_BIF`, preceded by a revision and with some additional
+        // fields (such as the cycle count) after the design voltage.
+        if let Some(bix) = subsystem.evaluate(handle, "_BIX") {
+            let bix = bix.as_package()?;
+
+            return Some(Self {
+                charge_units: bix.get(1)?.as_integer()? == 1,
+                design_capacity: bix.get(2)?.as_integer()?,
+                full_capacity: bix.get(3)?.as_integer()?,
+                design_voltage: bix.get(5)?.as_integer()?,
+                cycle_count: bix.get(8).and_then(AmlValue::as_integer),
+                model: bix.get(16).and_then(string),
+                manufacturer: bix.get(19).and_then(string),
+            });
+        }
+
+        let bif = subsystem.evaluate(handle, "_BIF")?;
+        let bif = bif.as_package()?;
+
+        Some(Self {
+            charge_units: bif.first()?.as_integer()? == 1,
+            design_capacity: bif.get(1)?.as_integer()?,
+            full_capacity: bif.get(2)?.as_integer()?,
+            design_voltage: bif.get(4)?.as_integer()?,
+            cycle_count: None,
+            model: bif.get(9).and_then(string),
+            manufacturer: bif.get(12).and_then(string),
+        })
+    }
+}
+
+/// Returns the contents of a non-empty string object.
+fn string(value: &AmlValue) -> Option<String> {
+    value
+        .as_str()
+        .map(|value| value.trim_end_matches('\0').trim())
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// Returns `value` if it is known.
+fn known(value: u64) -> Option<u64> {
+    (value != UNKNOWN).then_some(value)
+}
+
+static SUPPLIES: Once<Vec<PowerSupply>> = Once::new();
+
+/// Returns the power supplies, which are enumerated on first use since the AML subsystem is
+/// initialized by a module.
+fn supplies() -> &'static [PowerSupply] {
+    SUPPLIES.call_once(|| {
+        let Some(subsystem) = aml::try_get_subsystem() else {
+            return Vec::new();
+        };
+
+        let batteries = subsystem.find_devices("_BST").into_iter().enumerate();
+        let adapters = subsystem.find_devices("_PSR").into_iter().enumerate();
+
+        let supplies = batteries
+            .map(|(index, handle)| PowerSupply {
+                name: alloc::format!("BAT{index}"),
+                kind: Kind::Battery,
+                handle,
+            })
+            .chain(adapters.map(|(index, handle)| PowerSupply {
+                name: alloc::format!("AC{index}"),
+                kind: Kind::Mains,
+                handle,
+            }))
+            .collect::<Vec<_>>();
+
+        for supply in supplies.iter() {
+            log::debug!(
+                "acpi: found power supply {} ({:?})",
+                supply.name,
+                supply.kind
+            );
+        }
+
+        supplies
+    })
+}
+
+fn write_mains(out: &mut String, subsystem: &dyn aml::AmlSubsystem, supply: &PowerSupply) {
+    let online = subsystem
+        .evaluate(supply.handle, "_PSR")
+        .and_then(|value| value.as_integer())
+        .unwrap_or(0);
+
+    let _ = writeln!(out, "POWER_SUPPLY_ONLINE={}", (online != 0) as u8);
+}
+
+fn write_battery(out: &mut String, subsystem: &dyn aml::AmlSubsystem, supply: &PowerSupply) {
+    // A battery without `_STA` is always present.
+    let present = subsystem
+        .evaluate(supply.handle, "_STA")
+        .and_then(|value| value.as_integer())
+        .map_or(true, |status| status & STA_BATTERY_PRESENT != 0);
+
+    let _ = writeln!(out, "POWER_SUPPLY_PRESENT={}", present as u8);
+
+    if !present {
+        return;
+    }
+
+    let info = BatteryInfo::read(subsystem, supply.handle);
+    let state = subsystem.evaluate(supply.handle, "_BST").and_then(|bst| {
+        let bst = bst.as_package()?;
+
+        Some([
+            bst.first()?.as_integer()?,
+            bst.get(1)?.as_integer()?,
+            bst.get(2)?.as_integer()?,
+            bst.get(3)?.as_integer()?,
+        ])
+    });
+
+    let Some([flags, rate, remaining, voltage]) = state else {
+        let _ = writeln!(out, "POWER_SUPPLY_STATUS=Unknown");
+        return;
+    };
+
+    let full = info
+        .as_ref()
+        .and_then(|info| known(info.full_capacity))
+        .filter(|full| *full != 0);
+
+    let capacity =
+        known(remaining).and_then(|remaining| full.map(|full| (remaining * 100 / full).min(100)));
+
+    let status = if flags & BST_CHARGING != 0 {
+        "Charging"
+    } else if flags & BST_DISCHARGING != 0 {
+        "Discharging"
+    } else if capacity == Some(100) {
+        "Full"
+    } else {
+        "Not charging"
+    };
+
+    let _ = writeln!(out, "POWER_SUPPLY_STATUS={status}");
+
+    if flags & BST_CRITICAL != 0 {
+        let _ = writeln!(out, "POWER_SUPPLY_CAPACITY_LEVEL=Critical");
+    }
+
+    if let Some(capacity) = capacity {
+        let _ = writeln!(out, "POWER_SUPPLY_CAPACITY={capacity}");
+    }
+
+    // The values are reported in micro units, like on Linux.
+    let charge_units = info.as_ref().is_some_and(|info| info.charge_units);
+    let (capacity_name, rate_name) = if charge_units {
+        ("CHARGE", "CURRENT")
+    } else {
+        ("ENERGY", "POWER")
+    };
+
+    if let Some(info) = info.as_ref() {
+        if let Some(design) = known(info.design_capacity) {
+            let _ = writeln!(
+                out,
+                "POWER_SUPPLY_{capacity_name}_FULL_DESIGN={}",
+                design * 1000
+            );
+        }
+
+        if let Some(full) = full {
+            let _ = writeln!(out, "POWER_SUPPLY_{capacity_name}_FULL={}", full * 1000);
+        }
+
+        if let Some(voltage) = known(info.design_voltage) {
+            let _ = writeln!(out, "POWER_SUPPLY_VOLTAGE_MIN_DESIGN={}", voltage * 1000);
+        }
+
+        if let Some(cycle_count) = info.cycle_count.and_then(known) {
+            let _ = writeln!(out, "POWER_SUPPLY_CYCLE_COUNT={cycle_count}");
+        }
+
+        if let Some(model) = info.model.as_ref() {
+            let _ = writeln!(out, "POWER_SUPPLY_MODEL_NAME={model}");
+        }
+
+        if let Some(manufacturer) = info.manufacturer.as_ref() {
+            let _ = writeln!(out, "POWER_SUPPLY_MANUFACTURER={manufacturer}");
+        }
+    }
+
+    if let Some(remaining) = known(remaining) {
+        let _ = writeln!(out, "POWER_SUPPLY_{capacity_name}_NOW={}", remaining * 1000);
+    }
+
+    if let Some(rate) = known(rate) {
+        let _ = writeln!(out, "POWER_SUPPLY_{rate_name}_NOW={}", rate * 1000);
+    }
+
+    if let Some(voltage) = known(voltage) {
+        let _ = writeln!(out, "POWER_SUPPLY_VOLTAGE_NOW={}", voltage * 1000);
+    }
+}
+
+/// Returns the current state of all power supplies, as blocks of `KEY=value` lines that are
+/// separated by an empty line.
+pub fn report() -> String {
+    let mut out = String::new();
+
+    let Some(subsystem) = aml::try_get_subsystem() else {
+        return out;
+    };
+
+    for (index, supply) in supplies().iter().enumerate() {
+        if index != 0 {
+            out.push('\n');
+        }
+
+        let kind = match supply.kind {
+            Kind::Battery => "Battery",
+            Kind::Mains => "Mains",
+        };
+
+        let _ = writeln!(out, "POWER_SUPPLY_NAME={}", supply.name);
+        let _ = writeln!(out, "POWER_SUPPLY_TYPE={kind}");
+
+        match supply.kind {
+            Kind::Battery => write_battery(&mut out, &*subsystem, supply),
+            Kind::Mains => write_mains(&mut out, &*subsystem, supply),
+        }
+    }
+
+    out
+}
@@ -0,0 +1,36 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+pub struct IdleState {
+    pub name: &'static str,
+    pub exit_latency: u64,
+    pub target_residency: u64,
+}
+
+static STATES: [IdleState; 1] = [IdleState {
+    name: "WFI",
+    exit_latency: 1000,
+    target_residency: 1000,
+}];
+
+pub fn states() -> &'static [IdleState] {
+    &STATES
+}
+
+pub fn enter(_state: &IdleState) {
+    unsafe { asm!("wfi", options(nostack)) }
+}
@@ -0,0 +1,27 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+use crate::efi::RuntimeServices;
+
+pub fn is_available() -> bool {
+    false
+}
+
+pub fn with_runtime_services<R>(_f: impl FnOnce(&RuntimeServices) -> R) -> Option<R> {
+    None
+}
+
+pub fn init() {}
@@ -0,0 +1,55 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! kexec is not supported on aarch64 yet, so no physical range is accepted as a destination.
+
+use crate::mem::paging::PhysAddr;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct Copy {
+    pub source: u64,
+    pub destination: u64,
+    pub size: u64,
+}
+
+pub fn memory_ranges() -> impl Iterator<Item = (u64, u64)> {
+    core::iter::empty()
+}
+
+pub fn is_ram(_start: u64, _size: u64) -> bool {
+    false
+}
+
+pub fn crash_region() -> Option<(u64, u64)> {
+    None
+}
+
+pub struct Purgatory;
+
+impl Purgatory {
+    pub fn new(
+        _copies: &[Copy],
+        _alloc: &mut dyn FnMut(usize) -> Option<PhysAddr>,
+    ) -> Option<Self> {
+        None
+    }
+
+    pub unsafe fn execute(&self, _entry: u64) -> ! {
+        unimplemented!()
+    }
+}
@@ -15,8 +15,15 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod cpuidle;
 pub mod dtb;
+pub mod efi;
+pub mod hotplug;
 pub mod interrupts;
+pub mod kexec;
+pub mod pmu;
+pub mod power;
+pub mod suspend;
 pub mod task;
 pub mod time;
 pub mod tls;
@@ -68,7 +75,7 @@ extern "C" fn arch_aero_main() -> ! {
         let elf_slice = unsafe { core::slice::from_raw_parts(start, kernel_file.length as usize) };
         let elf = ElfFile::new(elf_slice).expect("limine: invalid kernel file");
 
-        UnwindInfo::new(elf)
+        UnwindInfo::new(elf, 0)
     });
 
     // Now that we have unwind info, we can initialize the COM ports. This
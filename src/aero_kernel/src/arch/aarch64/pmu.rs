@@ -0,0 +1,62 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hardware performance counters. The PMU is not supported on aarch64 yet, so no counters are
+//! reported and every event is rejected.
+
+pub fn num_counters() -> usize {
+    0
+}
+
+pub fn counter_width() -> u32 {
+    0
+}
+
+pub fn max_period() -> u64 {
+    0
+}
+
+pub fn hardware_event(_config: u64) -> Option<u64> {
+    None
+}
+
+pub fn raw_event(_config: u64) -> Option<u64> {
+    None
+}
+
+pub unsafe fn start_counter(
+    _index: usize,
+    _event: u64,
+    _user: bool,
+    _kernel: bool,
+    _interrupt: bool,
+    _value: u64,
+) {
+    unimplemented!()
+}
+
+pub unsafe fn stop_counter(_index: usize) {
+    unimplemented!()
+}
+
+pub fn read_counter(_index: usize) -> u64 {
+    unimplemented!()
+}
+
+pub unsafe fn write_counter(_index: usize, _value: u64) {
+    unimplemented!()
+}
@@ -31,7 +31,7 @@ impl ArchTask {
         unimplemented!()
     }
 
-    pub fn dealloc(&self) {
+    pub fn dealloc(&self, _release_pt: bool) {
         unimplemented!()
     }
 
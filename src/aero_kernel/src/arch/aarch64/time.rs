@@ -21,10 +21,42 @@ pub fn get_uptime_ticks() -> usize {
     unimplemented!()
 }
 
+pub fn get_uptime_ns() -> u64 {
+    unimplemented!()
+}
+
 pub fn get_realtime_clock() -> TimeSpec {
     unimplemented!()
 }
 
+pub fn get_realtime_ns() -> u64 {
+    unimplemented!()
+}
+
+pub fn set_realtime_ns(_realtime: u64) {
+    unimplemented!()
+}
+
+pub fn step_realtime(_delta: i64) {
+    unimplemented!()
+}
+
+pub fn slew_realtime(_offset: i64) -> i64 {
+    unimplemented!()
+}
+
+pub fn realtime_offset() -> i64 {
+    unimplemented!()
+}
+
+pub fn set_realtime_frequency(_frequency: i64) -> i64 {
+    unimplemented!()
+}
+
+pub fn realtime_frequency() -> i64 {
+    unimplemented!()
+}
+
 pub fn init() {
     unimplemented!()
 }
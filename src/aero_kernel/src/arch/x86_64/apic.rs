@@ -15,8 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use alloc::vec::Vec;
+
 use core::ptr;
-use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 
 use crate::arch::interrupts;
 use crate::arch::interrupts::InterruptStack;
@@ -50,6 +52,9 @@ const XAPIC_TPR: u32 = 0x080;
 /// Local APIC ID register. Read-only. See Section 10.12.5.1 for initial values.
 const XAPIC_ID: u32 = 0x020;
 
+/// LVT Performance Monitoring Counters register. Read/write. See Figure 10-8 for reserved bits.
+const XAPIC_LVT_PERF: u32 = 0x340;
+
 /// LVT Timer register. Read/write. See Figure 10-8 for reserved bits.
 const XAPIC_LVT_TIMER: u32 = 0x320;
 
@@ -63,6 +68,23 @@ const XAPIC_TIMER_DIV_CONF: u32 = 0x3E0;
 /// Current Count register (for Timer). Read-only.
 pub const XAPIC_TIMER_CURRENT_COUNT: u32 = 0x390;
 
+/// Interrupt Command Register (ICR). Read/write. The destination is in the high 32 bits
+/// (`0x310`) in XAPIC mode; in X2APIC mode it is a single 64-bit MSR.
+const XAPIC_ICR: u32 = 0x300;
+const XAPIC_ICR_HIGH: u32 = 0x310;
+
+/// ICR delivery mode: non-maskable interrupt.
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+/// ICR delivery mode: INIT, which resets the target CPU into the wait-for-SIPI state.
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+/// ICR delivery mode: start-up IPI (SIPI), the vector is the page number of the start-up code.
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+/// ICR delivery status: set while the interrupt has not been accepted yet (XAPIC only).
+const ICR_SEND_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+/// ICR destination shorthand: all of the CPUs excluding the current one.
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
 const X2APIC_BASE_MSR: u32 = 0x800;
 
 static LOCAL_APIC: Once<Mutex<LocalApic>> = Once::new();
@@ -73,6 +95,11 @@ pub static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 static BSP_READY: AtomicBool = AtomicBool::new(false);
 
+/// Set once the other CPUs are requested to stop (see [`stop_other_cpus`]).
+static STOPPING_CPUS: AtomicBool = AtomicBool::new(false);
+/// Number of CPUs that stopped.
+static STOPPED_CPUS: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ApicType {
     Xapic,
@@ -107,12 +134,14 @@ fn lapic_error_handler(_stack: &mut InterruptStack) {
     log::error!("ESR={:#0x}", self::get_local_apic().get_esr());
 }
 
-#[cpu_local]
-static mut LAPIC_TIMER_FREQUENCY: u32 = 0;
+/// Frequency of the local APIC timer. The timer is only calibrated on the BSP as all of the
+/// CPUs share the same bus frequency.
+static LAPIC_TIMER_FREQUENCY: AtomicU32 = AtomicU32::new(0);
 
 pub struct LocalApic {
     address: VirtAddr,
     apic_type: ApicType,
+    error_vector: u8,
 }
 
 impl LocalApic {
@@ -121,11 +150,15 @@ impl LocalApic {
     /// ## Safety
     /// The provided `address` points to a valid local APIC memory region and
     /// the `apic_type` is valid.
-    fn new(address: VirtAddr, apic_type: ApicType) -> Self {
-        Self { address, apic_type }
+    fn new(address: VirtAddr, apic_type: ApicType, error_vector: u8) -> Self {
+        Self {
+            address,
+            apic_type,
+            error_vector,
+        }
     }
 
-    /// This function is responsible for initializing this instance of the local APIC.
+    /// This function is responsible for initializing the local APIC of the current CPU.
     ///
     /// ## Panics
     /// * If the APIC type is set to [`ApicType::None`].
@@ -143,11 +176,8 @@ impl LocalApic {
             // Enable local APIC; set spurious interrupt vector.
             self.write(XAPIC_SVR, 0x100 | APIC_SPURIOUS_VECTOR);
 
-            let lvt_err_vector = interrupts::allocate_vector();
-            interrupts::register_handler(lvt_err_vector, lapic_error_handler);
-
             // Set up LVT (Local Vector Table) error.
-            self.write(XAPIC_LVT_ERROR, lvt_err_vector as u32);
+            self.write(XAPIC_LVT_ERROR, self.error_vector as u32);
         }
     }
 
@@ -185,6 +215,64 @@ impl LocalApic {
         }
     }
 
+    /// Routes the overflow interrupts of the performance monitoring counters to `vector`.
+    ///
+    /// ## Notes
+    /// Intel CPUs mask the entry when the interrupt is delivered, so it has to be set again
+    /// by the interrupt handler.
+    pub fn set_lvt_perf(&mut self, vector: u8) {
+        unsafe {
+            self.write(XAPIC_LVT_PERF, vector as u32);
+        }
+    }
+
+    /// Sends an inter-processor interrupt described by `command` (the low 32 bits of the ICR)
+    /// to the local APIC with the ID `apic_id`.
+    fn send_command(&mut self, apic_id: u32, command: u32) {
+        unsafe {
+            match self.apic_type {
+                ApicType::X2apic => {
+                    let value = (apic_id as u64) << 32 | command as u64;
+                    io::wrmsr(self.register_to_x2apic_msr(XAPIC_ICR), value);
+                }
+
+                ApicType::Xapic => {
+                    self.write(XAPIC_ICR_HIGH, apic_id << 24);
+                    self.write(XAPIC_ICR, command);
+
+                    while self.read(XAPIC_ICR) & ICR_SEND_PENDING != 0 {
+                        core::hint::spin_loop();
+                    }
+                }
+
+                ApicType::None => unreachable!(),
+            }
+        }
+    }
+
+    /// Sends a non-maskable interrupt to all of the other CPUs.
+    pub fn send_nmi_all_excluding_self(&mut self) {
+        let command = ICR_ALL_EXCLUDING_SELF | ICR_LEVEL_ASSERT | ICR_DELIVERY_NMI;
+        self.send_command(0, command);
+    }
+
+    /// Sends the interrupt `vector` to the CPU with the local APIC ID `apic_id`.
+    pub fn send_ipi(&mut self, apic_id: u32, vector: u8) {
+        self.send_command(apic_id, ICR_LEVEL_ASSERT | vector as u32);
+    }
+
+    /// Resets the CPU with the local APIC ID `apic_id` into the wait-for-SIPI state.
+    pub fn send_init(&mut self, apic_id: u32) {
+        self.send_command(apic_id, ICR_LEVEL_ASSERT | ICR_DELIVERY_INIT);
+    }
+
+    /// Starts the CPU with the local APIC ID `apic_id`, which has to be in the wait-for-SIPI
+    /// state, in real mode at the physical address `address` (page aligned, below 1MiB).
+    pub fn send_startup(&mut self, apic_id: u32, address: u64) {
+        let page = (address >> 12) as u32 & 0xff;
+        self.send_command(apic_id, ICR_LEVEL_ASSERT | ICR_DELIVERY_STARTUP | page);
+    }
+
     /// Stops the APIC timer.
     pub fn timer_stop(&mut self) {
         unsafe {
@@ -196,7 +284,7 @@ impl LocalApic {
     pub fn timer_oneshot(&mut self, vec: u8, us: usize) {
         self.timer_stop();
 
-        let lapic_timer_frequency = unsafe { *LAPIC_TIMER_FREQUENCY };
+        let lapic_timer_frequency = LAPIC_TIMER_FREQUENCY.load(Ordering::Relaxed);
         let ticks = us * (lapic_timer_frequency / 1000000) as usize;
 
         unsafe {
@@ -227,7 +315,7 @@ impl LocalApic {
             let pit_ticks = initial_pit_tick - final_pit_tick;
             let timer_frequency = (SAMPLES / pit_ticks as u32) * time::PIT_DIVIDEND as u32;
 
-            *LAPIC_TIMER_FREQUENCY = timer_frequency;
+            LAPIC_TIMER_FREQUENCY.store(timer_frequency, Ordering::Relaxed);
         }
 
         self.timer_stop();
@@ -380,6 +468,53 @@ pub fn io_apic_from_redirect(gsi: u32) -> Option<usize> {
     None
 }
 
+/// Returns the redirection entries of all I/O APICs, which are lost when the machine sleeps.
+pub fn io_apic_save() -> Vec<Vec<u32>> {
+    let count = madt::IO_APICS.read().len();
+
+    (0..count)
+        .map(|io_apic| {
+            let registers = (io_apic_get_max_redirect(io_apic) + 1) * 2;
+            (0..registers)
+                .map(|i| unsafe { io_apic_read(io_apic, i + 16) })
+                .collect()
+        })
+        .collect()
+}
+
+/// Restores the redirection entries saved by [`io_apic_save`].
+pub fn io_apic_restore(entries: &[Vec<u32>]) {
+    for (io_apic, registers) in entries.iter().enumerate() {
+        for (i, &value) in registers.iter().enumerate() {
+            unsafe { io_apic_write(io_apic, i as u32 + 16, value) }
+        }
+    }
+}
+
+/// Steers the interrupts of all I/O APICs that are delivered to the local APIC `from` to the
+/// local APIC `to`. Returns the number of redirection entries changed.
+pub fn io_apic_retarget(from: u32, to: u32) -> usize {
+    let count = madt::IO_APICS.read().len();
+    let mut changed = 0;
+
+    for io_apic in 0..count {
+        for entry in 0..=io_apic_get_max_redirect(io_apic) {
+            let high = entry * 2 + 17;
+
+            unsafe {
+                let destination = io_apic_read(io_apic, high);
+
+                if destination >> 24 == from {
+                    io_apic_write(io_apic, high, (destination & 0x00ff_ffff) | to << 24);
+                    changed += 1;
+                }
+            }
+        }
+    }
+
+    changed
+}
+
 pub fn io_apic_set_redirect(vec: u8, gsi: u32, flags: u16, status: i32) {
     if let Some(io_apic) = io_apic_from_redirect(gsi) {
         let mut redirect = 0x00;
@@ -431,6 +566,68 @@ pub fn io_apic_setup_legacy_irq(irq: u8, vec: u8, status: i32) {
     io_apic_set_redirect(vec, irq as u32, 0, status)
 }
 
+/// Stops all of the other CPUs; they are halted with interrupts disabled. Used before the
+/// current CPU jumps into another kernel. Returns `false` if not all of the CPUs stopped within
+/// about a second.
+///
+/// ## Safety
+/// The other CPUs are stopped wherever they are, so the locks they hold are never released.
+pub unsafe fn stop_other_cpus() -> bool {
+    let others = get_cpu_count().saturating_sub(1);
+
+    if STOPPING_CPUS.swap(true, Ordering::SeqCst) {
+        return true;
+    }
+
+    // The local APIC might be locked by the interrupted code on this CPU.
+    let local_apic = LOCAL_APIC.get().expect("apic: not initialized");
+    local_apic.force_unlock();
+
+    if others == 0 {
+        return true;
+    }
+
+    local_apic.lock().send_nmi_all_excluding_self();
+
+    for _ in 0..1_000_000 {
+        if STOPPED_CPUS.load(Ordering::SeqCst) == others {
+            return true;
+        }
+
+        io::wait();
+    }
+
+    false
+}
+
+/// Halts the current CPU if it was requested to stop by [`stop_other_cpus`]; called by the NMI
+/// handler.
+pub fn handle_stop_nmi() {
+    if !STOPPING_CPUS.load(Ordering::SeqCst) {
+        return;
+    }
+
+    STOPPED_CPUS.fetch_add(1, Ordering::SeqCst);
+
+    unsafe {
+        interrupts::disable_interrupts();
+
+        loop {
+            interrupts::halt();
+        }
+    }
+}
+
+/// Initialize the local APIC of an application processor. The local APIC must have already
+/// been initialized on the BSP.
+pub fn init_ap() {
+    LOCAL_APIC
+        .get()
+        .expect("apic: attempted to initialize an AP before the BSP")
+        .lock_irq()
+        .init();
+}
+
 /// Initialize the local apic.
 pub fn init() -> ApicType {
     let feature_info = CpuId::new()
@@ -450,7 +647,10 @@ pub fn init() -> ApicType {
     log::debug!("apic: detected APIC (addr={address_phys:?}, type={apic_type:?})");
 
     let address_virt = address_phys.as_hhdm_virt();
-    let mut local_apic = LocalApic::new(address_virt, apic_type);
+    let error_vector = interrupts::allocate_vector();
+    interrupts::register_handler(error_vector, lapic_error_handler);
+
+    let mut local_apic = LocalApic::new(address_virt, apic_type, error_vector);
 
     local_apic.init();
 
@@ -17,6 +17,7 @@
 
 use core::alloc::Layout;
 use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::extern_sym;
 use crate::mem::paging::VirtAddr;
@@ -75,6 +76,9 @@ static SELF_PTR: u64 = 0;
 #[cpu_local]
 static mut CPUID: usize = 0;
 
+/// Number of CPUs that have initialized their CPU-local data.
+static NR_INITIALIZED: AtomicUsize = AtomicUsize::new(0);
+
 pub fn init(cpu_id: usize) {
     let start = VirtAddr::new(extern_sym!(__cpu_local_start).addr() as u64);
     let end = VirtAddr::new(extern_sym!(__cpu_local_end).addr() as u64);
@@ -91,4 +95,27 @@ pub fn init(cpu_id: usize) {
         io::wrmsr(io::IA32_GS_BASE, data as u64);
         *CPUID = cpu_id;
     }
+
+    NR_INITIALIZED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Returns whether the current CPU has initialized its CPU-local data. Until then, the CPU-local
+/// data must not be accessed on this CPU.
+///
+/// **Note**: [`init`] switches the GS base only after it is done allocating, so no critical
+/// section of the current CPU can be entered before and left after the switch.
+pub fn is_initialized() -> bool {
+    let count = NR_INITIALIZED.load(Ordering::SeqCst);
+
+    // Once every CPU is initialized, the answer cannot change anymore; skip reading the MSR.
+    if count != 0 && count == super::apic::get_cpu_count() {
+        return true;
+    }
+
+    unsafe { io::rdmsr(io::IA32_GS_BASE) != 0 }
+}
+
+/// Returns the logical ID of the current CPU.
+pub fn get_cpuid() -> usize {
+    unsafe { *CPUID }
 }
@@ -0,0 +1,127 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! CPU idle states, entered by the idle loop (see [`crate::userland::scheduler::idle`]).
+//!
+//! HLT (C1) is always available. If the CPU supports using interrupts as break events for MWAIT
+//! even when they are disabled, the deeper C-states enumerated by CPUID are used as well. The
+//! CPU does not report the exit latencies of the C-states, so typical values are used.
+
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+
+use raw_cpuid::CpuId;
+use spin::Once;
+
+pub struct IdleState {
+    pub name: &'static str,
+    /// Time it takes to wake up from the state, in nanoseconds.
+    pub exit_latency: u64,
+    /// Minimum time to stay in the state for it to be worth entering, in nanoseconds.
+    pub target_residency: u64,
+    /// The MWAIT hint; HLT is used if [`None`].
+    hint: Option<u32>,
+}
+
+/// Names, exit latencies and target residencies (in microseconds) of the C-states entered with
+/// MWAIT, starting from C2.
+const MWAIT_STATES: [(&str, u64, u64); 6] = [
+    ("C2", 10, 20),
+    ("C3", 70, 100),
+    ("C4", 85, 200),
+    ("C5", 124, 800),
+    ("C6", 200, 800),
+    ("C7", 480, 5000),
+];
+
+/// The address monitored by MWAIT. It is never written to; the CPU is woken up by interrupts.
+static MONITOR: AtomicU64 = AtomicU64::new(0);
+
+static STATES: Once<Vec<IdleState>> = Once::new();
+
+/// Returns the idle states supported by the CPU, from the shallowest to the deepest one.
+pub fn states() -> &'static [IdleState] {
+    STATES.call_once(|| {
+        let mut states = alloc::vec![IdleState {
+            name: "HLT",
+            exit_latency: 1000,
+            target_residency: 1000,
+            hint: None,
+        }];
+
+        let cpuid = CpuId::new();
+
+        let has_mwait = cpuid
+            .get_feature_info()
+            .is_some_and(|info| info.has_monitor_mwait());
+
+        let Some(mwait) = cpuid.get_monitor_mwait_info().filter(|_| has_mwait) else {
+            return states;
+        };
+
+        if !mwait.extensions_supported() || !mwait.interrupts_as_break_event() {
+            return states;
+        }
+
+        let substates = [
+            mwait.supported_c2_states(),
+            mwait.supported_c3_states(),
+            mwait.supported_c4_states(),
+            mwait.supported_c5_states(),
+            mwait.supported_c6_states(),
+            mwait.supported_c7_states(),
+        ];
+
+        for (i, (name, exit_latency, target_residency)) in MWAIT_STATES.into_iter().enumerate() {
+            if substates[i] == 0 {
+                continue;
+            }
+
+            // Bits 4 to 7 of the hint are the C-state minus one; the sub-state is zero.
+            states.push(IdleState {
+                name,
+                exit_latency: exit_latency * 1000,
+                target_residency: target_residency * 1000,
+                hint: Some((i as u32 + 1) << 4),
+            });
+        }
+
+        log::debug!(
+            "cpuidle: states: {:?}",
+            states.iter().map(|state| state.name).collect::<Vec<_>>()
+        );
+
+        states
+    })
+}
+
+/// Puts the CPU into the provided idle state until the next interrupt. Must be called with
+/// interrupts disabled; they are still disabled when this function returns, so the pending
+/// interrupt is only handled once they are enabled again.
+pub fn enter(state: &IdleState) {
+    match state.hint {
+        // The interrupt is handled right after HLT, as interrupts are enabled for it.
+        None => unsafe { asm!("sti", "hlt", "cli", options(nostack)) },
+
+        Some(hint) => unsafe {
+            asm!("monitor", in("rax") MONITOR.as_ptr(), in("ecx") 0, in("edx") 0, options(nostack));
+            // Setting bit 0 of ECX makes interrupts wake up the CPU even though they are
+            // disabled.
+            asm!("mwait", in("eax") hint, in("ecx") 1, options(nostack));
+        },
+    }
+}
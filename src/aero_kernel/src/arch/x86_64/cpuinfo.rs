@@ -0,0 +1,498 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! CPU identification.
+//!
+//! Each CPU enumerates its topology, model, caches and feature flags with CPUID while it is
+//! brought up. The information is exposed through `/proc/cpuinfo`; the feature flags use the
+//! names that Linux uses, so that they are familiar to userland.
+//!
+//! ## Notes
+//! * Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 2A (CPUID)
+//! * <https://github.com/torvalds/linux/blob/master/arch/x86/include/asm/cpufeatures.h>
+
+use alloc::vec::Vec;
+use core::arch::x86_64::{__cpuid_count, CpuidResult};
+
+use crate::utils::sync::Mutex;
+
+use self::Register::*;
+
+#[derive(Debug, Copy, Clone)]
+enum Register {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// A feature flag: CPUID leaf, subleaf, register and bit of the flag and its name.
+type Feature = (u32, u32, Register, u32, &'static str);
+
+const FEATURES: &[Feature] = &[
+    // Standard features (EDX).
+    (0x1, 0, Edx, 0, "fpu"),
+    (0x1, 0, Edx, 1, "vme"),
+    (0x1, 0, Edx, 2, "de"),
+    (0x1, 0, Edx, 3, "pse"),
+    (0x1, 0, Edx, 4, "tsc"),
+    (0x1, 0, Edx, 5, "msr"),
+    (0x1, 0, Edx, 6, "pae"),
+    (0x1, 0, Edx, 7, "mce"),
+    (0x1, 0, Edx, 8, "cx8"),
+    (0x1, 0, Edx, 9, "apic"),
+    (0x1, 0, Edx, 11, "sep"),
+    (0x1, 0, Edx, 12, "mtrr"),
+    (0x1, 0, Edx, 13, "pge"),
+    (0x1, 0, Edx, 14, "mca"),
+    (0x1, 0, Edx, 15, "cmov"),
+    (0x1, 0, Edx, 16, "pat"),
+    (0x1, 0, Edx, 17, "pse36"),
+    (0x1, 0, Edx, 18, "pn"),
+    (0x1, 0, Edx, 19, "clflush"),
+    (0x1, 0, Edx, 21, "dts"),
+    (0x1, 0, Edx, 22, "acpi"),
+    (0x1, 0, Edx, 23, "mmx"),
+    (0x1, 0, Edx, 24, "fxsr"),
+    (0x1, 0, Edx, 25, "sse"),
+    (0x1, 0, Edx, 26, "sse2"),
+    (0x1, 0, Edx, 27, "ss"),
+    (0x1, 0, Edx, 28, "ht"),
+    (0x1, 0, Edx, 29, "tm"),
+    (0x1, 0, Edx, 31, "pbe"),
+    // Standard features (ECX).
+    (0x1, 0, Ecx, 0, "pni"),
+    (0x1, 0, Ecx, 1, "pclmulqdq"),
+    (0x1, 0, Ecx, 2, "dtes64"),
+    (0x1, 0, Ecx, 3, "monitor"),
+    (0x1, 0, Ecx, 4, "ds_cpl"),
+    (0x1, 0, Ecx, 5, "vmx"),
+    (0x1, 0, Ecx, 6, "smx"),
+    (0x1, 0, Ecx, 7, "est"),
+    (0x1, 0, Ecx, 8, "tm2"),
+    (0x1, 0, Ecx, 9, "ssse3"),
+    (0x1, 0, Ecx, 10, "cid"),
+    (0x1, 0, Ecx, 11, "sdbg"),
+    (0x1, 0, Ecx, 12, "fma"),
+    (0x1, 0, Ecx, 13, "cx16"),
+    (0x1, 0, Ecx, 14, "xtpr"),
+    (0x1, 0, Ecx, 15, "pdcm"),
+    (0x1, 0, Ecx, 17, "pcid"),
+    (0x1, 0, Ecx, 18, "dca"),
+    (0x1, 0, Ecx, 19, "sse4_1"),
+    (0x1, 0, Ecx, 20, "sse4_2"),
+    (0x1, 0, Ecx, 21, "x2apic"),
+    (0x1, 0, Ecx, 22, "movbe"),
+    (0x1, 0, Ecx, 23, "popcnt"),
+    (0x1, 0, Ecx, 24, "tsc_deadline_timer"),
+    (0x1, 0, Ecx, 25, "aes"),
+    (0x1, 0, Ecx, 26, "xsave"),
+    (0x1, 0, Ecx, 28, "avx"),
+    (0x1, 0, Ecx, 29, "f16c"),
+    (0x1, 0, Ecx, 30, "rdrand"),
+    (0x1, 0, Ecx, 31, "hypervisor"),
+    // Thermal and power management.
+    (0x6, 0, Eax, 0, "dtherm"),
+    (0x6, 0, Eax, 1, "ida"),
+    (0x6, 0, Eax, 2, "arat"),
+    (0x6, 0, Eax, 4, "pln"),
+    (0x6, 0, Eax, 6, "pts"),
+    (0x6, 0, Eax, 7, "hwp"),
+    // Structured extended features (EBX).
+    (0x7, 0, Ebx, 0, "fsgsbase"),
+    (0x7, 0, Ebx, 1, "tsc_adjust"),
+    (0x7, 0, Ebx, 2, "sgx"),
+    (0x7, 0, Ebx, 3, "bmi1"),
+    (0x7, 0, Ebx, 4, "hle"),
+    (0x7, 0, Ebx, 5, "avx2"),
+    (0x7, 0, Ebx, 7, "smep"),
+    (0x7, 0, Ebx, 8, "bmi2"),
+    (0x7, 0, Ebx, 9, "erms"),
+    (0x7, 0, Ebx, 10, "invpcid"),
+    (0x7, 0, Ebx, 11, "rtm"),
+    (0x7, 0, Ebx, 12, "cqm"),
+    (0x7, 0, Ebx, 14, "mpx"),
+    (0x7, 0, Ebx, 15, "rdt_a"),
+    (0x7, 0, Ebx, 16, "avx512f"),
+    (0x7, 0, Ebx, 17, "avx512dq"),
+    (0x7, 0, Ebx, 18, "rdseed"),
+    (0x7, 0, Ebx, 19, "adx"),
+    (0x7, 0, Ebx, 20, "smap"),
+    (0x7, 0, Ebx, 21, "avx512ifma"),
+    (0x7, 0, Ebx, 23, "clflushopt"),
+    (0x7, 0, Ebx, 24, "clwb"),
+    (0x7, 0, Ebx, 25, "intel_pt"),
+    (0x7, 0, Ebx, 26, "avx512pf"),
+    (0x7, 0, Ebx, 27, "avx512er"),
+    (0x7, 0, Ebx, 28, "avx512cd"),
+    (0x7, 0, Ebx, 29, "sha_ni"),
+    (0x7, 0, Ebx, 30, "avx512bw"),
+    (0x7, 0, Ebx, 31, "avx512vl"),
+    // Structured extended features (ECX).
+    (0x7, 0, Ecx, 1, "avx512vbmi"),
+    (0x7, 0, Ecx, 2, "umip"),
+    (0x7, 0, Ecx, 3, "pku"),
+    (0x7, 0, Ecx, 4, "ospke"),
+    (0x7, 0, Ecx, 5, "waitpkg"),
+    (0x7, 0, Ecx, 6, "avx512_vbmi2"),
+    (0x7, 0, Ecx, 7, "shstk"),
+    (0x7, 0, Ecx, 8, "gfni"),
+    (0x7, 0, Ecx, 9, "vaes"),
+    (0x7, 0, Ecx, 10, "vpclmulqdq"),
+    (0x7, 0, Ecx, 11, "avx512_vnni"),
+    (0x7, 0, Ecx, 12, "avx512_bitalg"),
+    (0x7, 0, Ecx, 13, "tme"),
+    (0x7, 0, Ecx, 14, "avx512_vpopcntdq"),
+    (0x7, 0, Ecx, 16, "la57"),
+    (0x7, 0, Ecx, 22, "rdpid"),
+    (0x7, 0, Ecx, 24, "bus_lock_detect"),
+    (0x7, 0, Ecx, 25, "cldemote"),
+    (0x7, 0, Ecx, 27, "movdiri"),
+    (0x7, 0, Ecx, 28, "movdir64b"),
+    (0x7, 0, Ecx, 29, "enqcmd"),
+    (0x7, 0, Ecx, 30, "sgx_lc"),
+    // Structured extended features (EDX).
+    (0x7, 0, Edx, 2, "avx512_4vnniw"),
+    (0x7, 0, Edx, 3, "avx512_4fmaps"),
+    (0x7, 0, Edx, 4, "fsrm"),
+    (0x7, 0, Edx, 8, "avx512_vp2intersect"),
+    (0x7, 0, Edx, 10, "md_clear"),
+    (0x7, 0, Edx, 14, "serialize"),
+    (0x7, 0, Edx, 16, "tsxldtrk"),
+    (0x7, 0, Edx, 18, "pconfig"),
+    (0x7, 0, Edx, 19, "arch_lbr"),
+    (0x7, 0, Edx, 20, "ibt"),
+    (0x7, 0, Edx, 22, "amx_bf16"),
+    (0x7, 0, Edx, 23, "avx512_fp16"),
+    (0x7, 0, Edx, 24, "amx_tile"),
+    (0x7, 0, Edx, 25, "amx_int8"),
+    (0x7, 0, Edx, 27, "stibp"),
+    (0x7, 0, Edx, 28, "flush_l1d"),
+    (0x7, 0, Edx, 29, "arch_capabilities"),
+    (0x7, 0, Edx, 31, "ssbd"),
+    // Processor extended state.
+    (0xd, 1, Eax, 0, "xsaveopt"),
+    (0xd, 1, Eax, 1, "xsavec"),
+    (0xd, 1, Eax, 2, "xgetbv1"),
+    (0xd, 1, Eax, 3, "xsaves"),
+    // Extended features (EDX).
+    (0x8000_0001, 0, Edx, 11, "syscall"),
+    (0x8000_0001, 0, Edx, 20, "nx"),
+    (0x8000_0001, 0, Edx, 22, "mmxext"),
+    (0x8000_0001, 0, Edx, 25, "fxsr_opt"),
+    (0x8000_0001, 0, Edx, 26, "pdpe1gb"),
+    (0x8000_0001, 0, Edx, 27, "rdtscp"),
+    (0x8000_0001, 0, Edx, 29, "lm"),
+    (0x8000_0001, 0, Edx, 30, "3dnowext"),
+    (0x8000_0001, 0, Edx, 31, "3dnow"),
+    // Extended features (ECX).
+    (0x8000_0001, 0, Ecx, 0, "lahf_lm"),
+    (0x8000_0001, 0, Ecx, 1, "cmp_legacy"),
+    (0x8000_0001, 0, Ecx, 2, "svm"),
+    (0x8000_0001, 0, Ecx, 3, "extapic"),
+    (0x8000_0001, 0, Ecx, 4, "cr8_legacy"),
+    (0x8000_0001, 0, Ecx, 5, "abm"),
+    (0x8000_0001, 0, Ecx, 6, "sse4a"),
+    (0x8000_0001, 0, Ecx, 7, "misalignsse"),
+    (0x8000_0001, 0, Ecx, 8, "3dnowprefetch"),
+    (0x8000_0001, 0, Ecx, 9, "osvw"),
+    (0x8000_0001, 0, Ecx, 10, "ibs"),
+    (0x8000_0001, 0, Ecx, 11, "xop"),
+    (0x8000_0001, 0, Ecx, 12, "skinit"),
+    (0x8000_0001, 0, Ecx, 13, "wdt"),
+    (0x8000_0001, 0, Ecx, 15, "lwp"),
+    (0x8000_0001, 0, Ecx, 16, "fma4"),
+    (0x8000_0001, 0, Ecx, 17, "tce"),
+    (0x8000_0001, 0, Ecx, 19, "nodeid_msr"),
+    (0x8000_0001, 0, Ecx, 21, "tbm"),
+    (0x8000_0001, 0, Ecx, 22, "topoext"),
+    (0x8000_0001, 0, Ecx, 23, "perfctr_core"),
+    (0x8000_0001, 0, Ecx, 24, "perfctr_nb"),
+    (0x8000_0001, 0, Ecx, 26, "bpext"),
+    (0x8000_0001, 0, Ecx, 28, "perfctr_llc"),
+    (0x8000_0001, 0, Ecx, 29, "mwaitx"),
+    // Advanced power management: an invariant TSC runs at a constant rate in all ACPI P-,
+    // C- and T-states.
+    (0x8000_0007, 0, Edx, 8, "constant_tsc"),
+    (0x8000_0007, 0, Edx, 8, "nonstop_tsc"),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+impl CacheKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Data => "data",
+            Self::Instruction => "instruction",
+            Self::Unified => "unified",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    pub level: u32,
+    pub kind: CacheKind,
+    /// Size of the cache in bytes.
+    pub size: usize,
+    pub line_size: usize,
+    pub ways: usize,
+}
+
+pub struct CpuInfo {
+    /// Logical ID of the CPU assigned by the kernel.
+    pub cpu: usize,
+
+    pub apic_id: u32,
+    /// ID of the physical package (socket) the CPU is part of.
+    pub package_id: u32,
+    /// ID of the core within the package.
+    pub core_id: u32,
+    /// ID of the hardware thread within the core.
+    pub thread_id: u32,
+
+    pub vendor: Option<String>,
+    pub brand: Option<String>,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    /// Base frequency of the CPU in MHz, if reported by CPUID.
+    pub base_frequency: Option<u32>,
+
+    pub caches: Vec<Cache>,
+    pub features: Vec<&'static str>,
+}
+
+static CPU_INFO: Mutex<Vec<CpuInfo>> = Mutex::new(Vec::new());
+
+fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    // SAFETY: CPUID is supported by all x86_64 CPUs.
+    unsafe { __cpuid_count(leaf, subleaf) }
+}
+
+/// Returns whether the CPUID `leaf` is supported.
+fn has_leaf(leaf: u32) -> bool {
+    // The highest supported standard (extended) leaf is returned by leaf 0 (0x8000_0000).
+    let max = cpuid(leaf & 0x8000_0000, 0).eax;
+    leaf <= max
+}
+
+fn register(result: &CpuidResult, register: Register) -> u32 {
+    match register {
+        Eax => result.eax,
+        Ebx => result.ebx,
+        Ecx => result.ecx,
+        Edx => result.edx,
+    }
+}
+
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    let mut cached: Option<(u32, u32, CpuidResult)> = None;
+
+    for &(leaf, subleaf, reg, bit, name) in FEATURES {
+        if !has_leaf(leaf) {
+            continue;
+        }
+
+        let result = match cached {
+            Some((l, s, result)) if l == leaf && s == subleaf => result,
+            _ => {
+                let result = cpuid(leaf, subleaf);
+                cached = Some((leaf, subleaf, result));
+                result
+            }
+        };
+
+        if register(&result, reg) & (1 << bit) != 0 {
+            features.push(name);
+        }
+    }
+
+    features
+}
+
+/// Returns the family, model and stepping of the CPU.
+fn signature() -> (u32, u32, u32) {
+    let eax = cpuid(0x1, 0).eax;
+
+    let stepping = eax & 0xf;
+    let mut model = (eax >> 4) & 0xf;
+    let mut family = (eax >> 8) & 0xf;
+
+    if family == 0xf {
+        family += (eax >> 20) & 0xff;
+    }
+
+    if family >= 0x6 {
+        model += ((eax >> 16) & 0xf) << 4;
+    }
+
+    (family, model, stepping)
+}
+
+/// Returns the APIC ID of the CPU and its package, core and thread IDs.
+fn topology() -> (u32, u32, u32, u32) {
+    // Leaf 0xB enumerates the topology levels; each level reports how many bits of the x2APIC
+    // ID have to be shifted out to get the ID of the next level.
+    if has_leaf(0xb) && cpuid(0xb, 0).ebx != 0 {
+        let apic_id = cpuid(0xb, 0).edx;
+
+        let mut smt_shift = 0;
+        let mut core_shift = 0;
+
+        for subleaf in 0..8 {
+            let result = cpuid(0xb, subleaf);
+            let level_type = (result.ecx >> 8) & 0xff;
+            let shift = result.eax & 0x1f;
+
+            match level_type {
+                // Invalid; no more levels.
+                0 => break,
+                1 => smt_shift = shift,
+                2 => core_shift = shift,
+                _ => {}
+            }
+        }
+
+        let core_shift = core_shift.max(smt_shift);
+        let thread_id = apic_id & ((1 << smt_shift) - 1);
+        let core_id = (apic_id >> smt_shift) & ((1 << (core_shift - smt_shift)) - 1);
+        let package_id = apic_id.checked_shr(core_shift).unwrap_or(0);
+
+        return (apic_id, package_id, core_id, thread_id);
+    }
+
+    // Legacy enumeration: leaf 1 reports the initial APIC ID and the maximum number of
+    // logical processors in the package, which are treated as single-threaded cores.
+    let ebx = cpuid(0x1, 0).ebx;
+    let apic_id = ebx >> 24;
+    let logical = ((ebx >> 16) & 0xff).max(1);
+    let shift = logical.next_power_of_two().trailing_zeros();
+
+    (apic_id, apic_id >> shift, apic_id & ((1 << shift) - 1), 0)
+}
+
+/// Returns the caches of the CPU, enumerated with the deterministic cache parameters leaf
+/// (leaf 0x4 on Intel and leaf 0x8000_001D on AMD, which have the same layout).
+fn caches(vendor: Option<&str>) -> Vec<Cache> {
+    let leaf = if vendor == Some("AuthenticAMD") {
+        0x8000_001d
+    } else {
+        0x4
+    };
+
+    let mut caches = Vec::new();
+
+    if !has_leaf(leaf) {
+        return caches;
+    }
+
+    for subleaf in 0..16 {
+        let result = cpuid(leaf, subleaf);
+
+        let kind = match result.eax & 0x1f {
+            1 => CacheKind::Data,
+            2 => CacheKind::Instruction,
+            3 => CacheKind::Unified,
+            // No more caches.
+            _ => break,
+        };
+
+        let ways = ((result.ebx >> 22) & 0x3ff) as usize + 1;
+        let partitions = ((result.ebx >> 12) & 0x3ff) as usize + 1;
+        let line_size = (result.ebx & 0xfff) as usize + 1;
+        let sets = result.ecx as usize + 1;
+
+        caches.push(Cache {
+            level: (result.eax >> 5) & 0x7,
+            kind,
+            size: ways * partitions * line_size * sets,
+            line_size,
+            ways,
+        });
+    }
+
+    caches
+}
+
+/// Returns the base frequency of the CPU in MHz, if reported by CPUID.
+fn base_frequency() -> Option<u32> {
+    if !has_leaf(0x16) {
+        return None;
+    }
+
+    Some(cpuid(0x16, 0).eax & 0xffff).filter(|&frequency| frequency != 0)
+}
+
+/// Enumerates the current CPU.
+pub fn init() {
+    let raw = raw_cpuid::CpuId::new();
+
+    let vendor = raw.get_vendor_info().map(|e| String::from(e.as_str()));
+    let brand = raw
+        .get_processor_brand_string()
+        .map(|e| String::from(e.as_str().trim()));
+
+    let (apic_id, package_id, core_id, thread_id) = topology();
+    let (family, model, stepping) = signature();
+
+    CPU_INFO.lock().push(CpuInfo {
+        cpu: super::tls::get_cpuid(),
+
+        apic_id,
+        package_id,
+        core_id,
+        thread_id,
+
+        caches: caches(vendor.as_deref()),
+        vendor,
+        brand,
+        family,
+        model,
+        stepping,
+        base_frequency: base_frequency(),
+
+        features: features(),
+    })
+}
+
+/// Returns the local APIC ID of the CPU with the logical ID `cpu`.
+pub fn apic_id(cpu: usize) -> Option<u32> {
+    CPU_INFO
+        .lock()
+        .iter()
+        .find(|info| info.cpu == cpu)
+        .map(|info| info.apic_id)
+}
+
+/// Calls `f` with the information of each CPU, in the order the CPUs were brought up.
+pub fn for_each_cpu<F>(mut f: F)
+where
+    F: FnMut(&CpuInfo),
+{
+    let lock = CPU_INFO.lock();
+
+    for info in lock.iter() {
+        f(info);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Calling the UEFI runtime services.
+//!
+//! The bootloader does not call `SetVirtualAddressMap`, so the runtime services expect their
+//! memory to be identity mapped. They are called from a separate address space, which identity
+//! maps the runtime regions of the EFI memory map and shares the higher half with the kernel (so
+//! the arguments can be passed in kernel memory). The runtime services are called with
+//! interrupts disabled and one at a time, as required by the specification.
+
+use spin::Once;
+
+use crate::efi::{RuntimeServices, SystemTable, SYSTEM_TABLE_SIGNATURE};
+use crate::mem::paging::{self, Mapper, PageTableFlags, PhysAddr, PhysFrame, Size4KiB, VirtAddr};
+use crate::mem::AddressSpace;
+use crate::utils::sync::Mutex;
+
+use super::task::{self, FpuState};
+use super::{EFI_MEMMAP, EFI_SYSTEM_TABLE};
+
+/// The memory region is used by the runtime services.
+const MEMORY_RUNTIME: u64 = 1 << 63;
+
+const RUNTIME_SERVICES_CODE: u32 = 5;
+const MEMORY_MAPPED_IO: u32 = 11;
+const MEMORY_MAPPED_IO_PORT_SPACE: u32 = 12;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct MemoryDescriptor {
+    typ: u32,
+    physical_start: u64,
+    virtual_start: u64,
+    number_of_pages: u64,
+    attribute: u64,
+}
+
+struct Runtime {
+    address_space: Mutex<AddressSpace>,
+    services: &'static RuntimeServices,
+}
+
+static RUNTIME: Once<Runtime> = Once::new();
+
+/// Returns whether the UEFI runtime services are available.
+pub fn is_available() -> bool {
+    RUNTIME.get().is_some()
+}
+
+/// Calls `f` with the runtime services table, from the address space of the runtime services.
+/// Returns `None` if the runtime services are not available.
+pub fn with_runtime_services<R>(f: impl FnOnce(&RuntimeServices) -> R) -> Option<R> {
+    let runtime = RUNTIME.get()?;
+    let mut address_space = runtime.address_space.lock_irq();
+
+    // Kernel mappings might have been added to the higher half since the last call.
+    let current = unsafe { paging::active_level_4_table() };
+    let table = address_space.page_table();
+
+    for i in 256..512 {
+        table[i] = current[i].clone();
+    }
+
+    // The runtime services are allowed to use the FPU and SSE registers.
+    let mut fpu = FpuState::default();
+    task::xsave(&mut fpu);
+
+    let previous = AddressSpace::this();
+
+    address_space.switch();
+    let result = f(runtime.services);
+    previous.switch();
+
+    task::xrstor(&fpu);
+    Some(result)
+}
+
+/// Creates the address space of the runtime services from the EFI memory map.
+fn map_runtime_regions() -> Option<AddressSpace> {
+    let memmap = EFI_MEMMAP.get_response()?;
+
+    let mut address_space = AddressSpace::new().ok()?;
+    let mut offset_table = address_space.offset_page_table();
+
+    let base = paging::boot_to_hhdm(VirtAddr::new(memmap.memmap() as u64));
+    let count = memmap.memmap_size() / memmap.desc_size();
+
+    for i in 0..count {
+        // NOTE: The descriptor size can be larger than the size of the structure.
+        let descriptor = unsafe {
+            (base + i * memmap.desc_size())
+                .as_ptr::<MemoryDescriptor>()
+                .read_unaligned()
+        };
+
+        if descriptor.attribute & MEMORY_RUNTIME == 0 {
+            continue;
+        }
+
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        match descriptor.typ {
+            RUNTIME_SERVICES_CODE => {}
+            MEMORY_MAPPED_IO | MEMORY_MAPPED_IO_PORT_SPACE => {
+                flags |= PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE;
+            }
+            _ => flags |= PageTableFlags::NO_EXECUTE,
+        }
+
+        for page in 0..descriptor.number_of_pages {
+            let frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(PhysAddr::new(
+                descriptor.physical_start + page * PAGE_SIZE,
+            ));
+
+            // The address space is not active, so the TLB does not have to be flushed. The
+            // regions might overlap, so the pages that are already mapped are skipped.
+            if let Ok(flush) = unsafe { offset_table.identity_map(frame, flags) } {
+                flush.ignore();
+            }
+        }
+    }
+
+    Some(address_space)
+}
+
+pub fn init() {
+    let Some(response) = EFI_SYSTEM_TABLE.get_response() else {
+        log::debug!("efi: not booted through UEFI");
+        return;
+    };
+
+    let system_table = paging::boot_to_hhdm(VirtAddr::new(response.address() as u64));
+    let system_table = unsafe { &*system_table.as_ptr::<SystemTable>() };
+
+    if system_table.header.signature != SYSTEM_TABLE_SIGNATURE || system_table.runtime_services == 0
+    {
+        log::warn!("efi: invalid system table");
+        return;
+    }
+
+    let Some(address_space) = map_runtime_regions() else {
+        log::warn!("efi: failed to map the runtime services");
+        return;
+    };
+
+    let services = PhysAddr::new(system_table.runtime_services).as_hhdm_virt();
+    let services = unsafe { &*services.as_ptr::<RuntimeServices>() };
+
+    log::info!(
+        "efi: runtime services revision {}.{}",
+        services.header.revision >> 16,
+        services.header.revision & 0xffff
+    );
+
+    RUNTIME.call_once(|| Runtime {
+        address_space: Mutex::new(address_space),
+        services,
+    });
+}
@@ -30,6 +30,8 @@ use core::ptr::addr_of;
 
 use alloc::alloc::alloc_zeroed;
 
+use crate::mem::KernelStack;
+
 bitflags::bitflags! {
     #[derive(Debug, Copy, Clone)]
     struct GdtEntryFlags: u8 {
@@ -360,6 +362,11 @@ pub fn init_boot() {
 
 static STK: [u8; 4096 * 16] = [0; 4096 * 16];
 
+/// Interrupt stack table index of the stack used by the double fault handler.
+pub const DOUBLE_FAULT_IST: u16 = 1;
+/// Size of the double fault stack, in pages.
+const DOUBLE_FAULT_STACK_PAGES: usize = 8;
+
 pub const USER_SS: SegmentSelector =
     SegmentSelector::new(GdtEntryIndex::USER_DATA, PrivilegeLevel::Ring3);
 
@@ -394,6 +401,14 @@ pub fn init() {
 
         TSS.rsp[0] = STK.as_ptr().offset(4096 * 16) as u64;
 
+        // The double fault stack is required for the whole lifetime of the CPU, so it is
+        // never deallocated.
+        let df_stack = KernelStack::new(DOUBLE_FAULT_STACK_PAGES)
+            .expect("gdt: failed to allocate the double fault stack");
+
+        TSS.ist[DOUBLE_FAULT_IST as usize - 1] = df_stack.top().as_u64();
+        mem::forget(df_stack);
+
         let gdt_descriptor = GdtDescriptor::new(
             (mem::size_of::<[GdtEntry; GDT_ENTRY_COUNT]>() - 1) as u16,
             gdt.as_ptr() as u64,
@@ -0,0 +1,232 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parking and restarting CPUs that are taken offline.
+//!
+//! An offline CPU is parked in a `hlt` loop with its local APIC timer stopped, once the scheduler
+//! moved its tasks to the other CPUs (see [`crate::userland::scheduler::hotplug`]). It is woken
+//! up with an IPI when it is brought back online.
+//!
+//! The parked CPUs are reset by the firmware when the machine sleeps (see [`super::suspend`]).
+//! So, the state of a CPU is saved before it is parked, and a CPU that was reset is restarted
+//! with INIT and start-up IPIs through the wakeup trampoline, which restores the saved state and
+//! makes the CPU return from [`park`] as if it was woken up.
+//!
+//! ## Notes
+//! * The MTRRs of a restarted CPU are left as programmed by the firmware.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Once;
+
+use crate::arch::interrupts::{self, InterruptStack, INTERRUPT_CONTROLLER};
+use crate::utils::sync::Mutex;
+use crate::utils::PerCpu;
+
+use super::suspend::{self, ProcessorState, SAVED_RSP, SAVED_STATE};
+use super::{apic, cpuinfo, pmu, time, tls, vdso};
+
+/// Time to wait after the INIT IPI before sending the start-up IPIs.
+const INIT_DELAY_NS: u64 = 10_000_000;
+/// Time to wait between the start-up IPIs.
+const STARTUP_DELAY_NS: u64 = 200_000;
+
+struct ParkedCpu {
+    /// The processor state and stack pointer saved before parking.
+    saved: Mutex<Option<(ProcessorState, u64)>>,
+    /// Set while the CPU is parked.
+    parked: AtomicBool,
+    /// Set to make the CPU leave [`park`].
+    wake: AtomicBool,
+    /// Set if the CPU was reset while it was parked.
+    reset: AtomicBool,
+}
+
+impl ParkedCpu {
+    fn new() -> Self {
+        Self {
+            saved: Mutex::new(None),
+            parked: AtomicBool::new(false),
+            wake: AtomicBool::new(false),
+            reset: AtomicBool::new(false),
+        }
+    }
+}
+
+static PARKED_CPUS: Once<PerCpu<ParkedCpu>> = Once::new();
+static WAKEUP_VECTOR: Once<u8> = Once::new();
+
+fn parked_cpu(cpu: usize) -> &'static ParkedCpu {
+    PARKED_CPUS
+        .get()
+        .expect("hotplug: not initialized")
+        .get_cpu(cpu)
+}
+
+fn wakeup_handler(_stack: &mut InterruptStack) {
+    INTERRUPT_CONTROLLER.eoi();
+}
+
+/// Parks the current CPU until [`wake`] is called. Publishes the state saved by [`park`] and
+/// waits with interrupts enabled, so that the wakeup IPI can be received.
+extern "C" fn park_loop() {
+    let this = parked_cpu(tls::get_cpuid());
+
+    unsafe {
+        let state = *core::ptr::addr_of!(SAVED_STATE);
+        let rsp = *core::ptr::addr_of!(SAVED_RSP);
+
+        *this.saved.lock_irq() = Some((state, rsp));
+    }
+
+    this.parked.store(true, Ordering::SeqCst);
+
+    while !this.wake.load(Ordering::SeqCst) {
+        // NOTE: `sti` only takes effect after the next instruction, so the wakeup IPI cannot be
+        // received between checking the flag and halting.
+        unsafe { asm!("sti", "hlt", "cli", options(nomem, nostack)) }
+    }
+}
+
+/// Parks the current CPU, which has to be offline, until it is woken up by [`wake`]. Must be
+/// called with interrupts disabled.
+pub fn park() {
+    let cpu = tls::get_cpuid();
+    let this = parked_cpu(cpu);
+
+    apic::get_local_apic().timer_stop();
+
+    // NOTE: The parked CPUs are serialized by the caller, so the global state used by the
+    // trampoline is not saved by two CPUs at once.
+    let reset = unsafe {
+        suspend::save_processor_state(&mut *core::ptr::addr_of_mut!(SAVED_STATE));
+        suspend::suspend_lowlevel(park_loop) == 0
+    };
+
+    if reset {
+        apic::init_ap();
+        vdso::init_cpu();
+        pmu::init_ap();
+
+        log::info!("hotplug: restarted CPU {cpu}");
+    }
+
+    this.wake.store(false, Ordering::SeqCst);
+    this.parked.store(false, Ordering::SeqCst);
+}
+
+/// Returns whether CPUs can be taken offline.
+pub fn is_supported() -> bool {
+    true
+}
+
+/// Returns whether the CPU `cpu` is parked.
+pub fn is_parked(cpu: usize) -> bool {
+    parked_cpu(cpu).parked.load(Ordering::SeqCst)
+}
+
+/// Returns whether all of the CPUs, except for the current one, are parked.
+pub fn others_parked() -> bool {
+    let this = tls::get_cpuid();
+    (0..apic::get_cpu_count()).all(|cpu| cpu == this || is_parked(cpu))
+}
+
+/// Marks the parked CPUs as reset. Called after waking up from a sleep state.
+pub fn mark_reset() {
+    let this = tls::get_cpuid();
+
+    for cpu in (0..apic::get_cpu_count()).filter(|&cpu| cpu != this && is_parked(cpu)) {
+        parked_cpu(cpu).reset.store(true, Ordering::SeqCst);
+    }
+}
+
+fn delay(ns: u64) {
+    let end = time::get_uptime_ns() + ns;
+
+    while time::get_uptime_ns() < end {
+        core::hint::spin_loop();
+    }
+}
+
+/// Restarts the CPU `cpu`, which was reset while it was parked, through the wakeup trampoline.
+fn restart(cpu: usize, apic_id: u32) {
+    let Some(base) = suspend::wakeup_memory() else {
+        log::error!("hotplug: cannot restart CPU {cpu} without the wakeup trampoline");
+        return;
+    };
+
+    let Some((state, rsp)) = *parked_cpu(cpu).saved.lock_irq() else {
+        return;
+    };
+
+    unsafe {
+        *core::ptr::addr_of_mut!(SAVED_STATE) = state;
+        *core::ptr::addr_of_mut!(SAVED_RSP) = rsp;
+
+        suspend::prepare_trampoline(base);
+    }
+
+    // NOTE: The local APIC must not be locked while waiting, as the timer interrupt handler
+    // uses it.
+    apic::get_local_apic().send_init(apic_id);
+    delay(INIT_DELAY_NS);
+
+    // A CPU that already started ignores the second start-up IPI.
+    for _ in 0..2 {
+        apic::get_local_apic().send_startup(apic_id, base);
+        delay(STARTUP_DELAY_NS);
+    }
+}
+
+/// Wakes up the parked CPU `cpu`, restarting it if it was reset.
+pub fn wake(cpu: usize) {
+    let this = parked_cpu(cpu);
+
+    let Some(apic_id) = cpuinfo::apic_id(cpu) else {
+        return;
+    };
+
+    this.wake.store(true, Ordering::SeqCst);
+
+    if this.reset.swap(false, Ordering::SeqCst) {
+        restart(cpu, apic_id);
+    } else {
+        apic::get_local_apic().send_ipi(apic_id, *WAKEUP_VECTOR.get().unwrap());
+    }
+}
+
+/// Steers the interrupts that are delivered to the CPU `cpu` to the BSP.
+pub fn migrate_irqs(cpu: usize) {
+    let (Some(from), Some(to)) = (cpuinfo::apic_id(cpu), cpuinfo::apic_id(0)) else {
+        return;
+    };
+
+    let changed = apic::io_apic_retarget(from, to);
+
+    if changed != 0 {
+        log::debug!("hotplug: moved {changed} interrupts from CPU {cpu} to CPU 0");
+    }
+}
+
+pub fn init() {
+    PARKED_CPUS.call_once(|| PerCpu::new(ParkedCpu::new));
+
+    let vector = interrupts::allocate_vector();
+    interrupts::register_handler(vector, wakeup_handler);
+
+    WAKEUP_VECTOR.call_once(|| vector);
+}
@@ -15,6 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use aero_syscall::signal::*;
+
 use super::{io, InterruptErrorStack};
 
 use crate::arch::controlregs;
@@ -28,8 +30,25 @@ pub static mut PF_RESUME: VirtAddr = VirtAddr::new(0);
 
 const LOG_PF_PTABLE: bool = true;
 
-macro interrupt_exception(fn $name:ident() => $message:expr) {
+/// Sends the provided fault `signal` to the current task, where `addr` is the address of the
+/// fault (see `si_addr`). The signal is delivered once the exception handler returns.
+fn signal_fault(signal: usize, code: i32, addr: u64) {
+    let mut info = SigInfo::new(signal, code);
+    info.set_addr(addr);
+
+    scheduler::get_scheduler().current_task().force_signal(info);
+}
+
+macro interrupt_exception(fn $name:ident() => $message:expr $(, $signal:expr, $code:expr)?) {
     pub fn $name(stack: &mut InterruptErrorStack) {
+        $(
+            // Faults in userland are reported to the faulting process instead.
+            if stack.stack.iret.is_user() {
+                signal_fault($signal, $code, stack.stack.iret.rip);
+                return;
+            }
+        )?
+
         unwind::prepare_panic();
 
         log::error!("EXCEPTION: {}", $message);
@@ -62,24 +81,91 @@ macro interrupt_exception(fn $name:ident() => $message:expr) {
     }
 }
 
-interrupt_exception!(fn divide_by_zero() => "Division by zero");
+interrupt_exception!(fn divide_by_zero() => "Division by zero", SIGFPE, FPE_INTDIV);
 interrupt_exception!(fn debug() => "Debug");
-interrupt_exception!(fn non_maskable() => "Non Maskable");
-interrupt_exception!(fn overflow() => "Stack Overflow");
-interrupt_exception!(fn bound_range() => "Out of Bounds");
+interrupt_exception!(fn unexpected_nmi() => "Non Maskable");
+interrupt_exception!(fn overflow() => "Stack Overflow", SIGSEGV, SI_KERNEL);
+interrupt_exception!(fn bound_range() => "Out of Bounds", SIGSEGV, SI_KERNEL);
 interrupt_exception!(fn device_not_available() => "Device not Available");
-interrupt_exception!(fn double_fault() => "Double Fault");
 interrupt_exception!(fn invalid_tss() => "Invalid TSS");
-interrupt_exception!(fn segment_not_present() => "Segment not Present");
-interrupt_exception!(fn stack_segment() => "Stack Segment Fault");
-interrupt_exception!(fn protection() => "Protection Fault");
-interrupt_exception!(fn fpu_fault() => "FPU floating point fault");
-interrupt_exception!(fn alignment_check() => "Alignment check fault");
+interrupt_exception!(fn segment_not_present() => "Segment not Present", SIGBUS, SI_KERNEL);
+interrupt_exception!(fn stack_segment() => "Stack Segment Fault", SIGBUS, SI_KERNEL);
+interrupt_exception!(fn protection() => "Protection Fault", SIGSEGV, SI_KERNEL);
+interrupt_exception!(fn fpu_fault() => "FPU floating point fault", SIGFPE, FPE_FLTINV);
+interrupt_exception!(fn alignment_check() => "Alignment check fault", SIGBUS, BUS_ADRALN);
 interrupt_exception!(fn machine_check() => "Machine check fault");
 interrupt_exception!(fn virtualization() => "Virtualization fault");
 interrupt_exception!(fn security() => "Security exception");
 
+pub fn non_maskable(stack: &mut InterruptErrorStack) {
+    // NMIs are sent to stop the other CPUs before jumping into another kernel.
+    crate::arch::apic::handle_stop_nmi();
+    unexpected_nmi(stack)
+}
+
+/// Panics with the offending task identified, if the provided address lies in the guard
+/// page of one of the current task's kernel stacks.
+fn check_stack_overflow(accessed_address: VirtAddr) {
+    if !scheduler::is_initialized() {
+        return;
+    }
+
+    let task = scheduler::get_scheduler().current_task();
+
+    if !task.arch_task().is_stack_guard_page(accessed_address) {
+        return;
+    }
+
+    unwind::prepare_panic();
+
+    log::error!("EXCEPTION: Kernel stack overflow");
+    log::error!("accessed address: {:#x}", accessed_address);
+    log::error!(
+        "task: (tid={}, pid={})",
+        task.tid().as_usize(),
+        task.pid().as_usize()
+    );
+
+    if let Some(path) = task.path() {
+        log::error!("task: (path=`{}`)", path);
+    }
+
+    unwind::unwind_stack_trace();
+
+    unsafe {
+        loop {
+            super::halt();
+        }
+    }
+}
+
+pub fn double_fault(stack: &mut InterruptErrorStack) {
+    // Overflowing a kernel stack results in a page fault which cannot be delivered, since
+    // the CPU is unable to push the exception frame onto the stack. The double fault handler
+    // runs on its own stack (see `gdt::DOUBLE_FAULT_IST`), so we can report the overflow
+    // here.
+    check_stack_overflow(controlregs::read_cr2());
+
+    unwind::prepare_panic();
+
+    log::error!("EXCEPTION: Double Fault");
+    log::error!("Stack: {:#x?}", stack);
+
+    unwind::unwind_stack_trace();
+
+    unsafe {
+        loop {
+            super::halt();
+        }
+    }
+}
+
 pub fn simd(stack: &mut InterruptErrorStack) {
+    if stack.stack.iret.is_user() {
+        signal_fault(SIGFPE, FPE_FLTINV, stack.stack.iret.rip);
+        return;
+    }
+
     unwind::prepare_panic();
 
     log::error!("EXCEPTION: SIMD floating point fault");
@@ -116,6 +202,11 @@ pub fn invalid_opcode(stack: &mut InterruptErrorStack) {
 
     // Otherwise handle the exception as normal.
 
+    if stack.stack.iret.is_user() {
+        signal_fault(SIGILL, ILL_ILLOPN, stack.stack.iret.rip);
+        return;
+    }
+
     unwind::prepare_panic();
 
     log::error!("EXCEPTION: Invalid Opcode");
@@ -203,8 +294,13 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
 
             unwind::unwind_stack_trace();
 
-            let task = scheduler::get_scheduler().current_task();
-            task.signal(aero_syscall::signal::SIGSEGV);
+            let code = if reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+                SEGV_ACCERR
+            } else {
+                SEGV_MAPERR
+            };
+
+            signal_fault(SIGSEGV, code, accessed_address.as_u64());
             return;
         } else if !signal {
         } else {
@@ -212,6 +308,8 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
         }
     }
 
+    check_stack_overflow(accessed_address);
+
     unwind::prepare_panic();
 
     log::error!("Page fault");
@@ -119,7 +119,9 @@ interrupt_handler_error_code 30
 %assign i i + 1
 %endrep
 
-section .rodata
+; The table contains absolute addresses which are relocated at load time, so it cannot be
+; placed in a read-only section.
+section .data.rel.ro
 
 interrupt_handlers:
     dq interrupt_handler_0
@@ -28,7 +28,7 @@ use core::ptr::addr_of;
 
 use bit_field::BitField;
 
-use crate::arch::gdt::{GdtEntryIndex, PrivilegeLevel, SegmentSelector};
+use crate::arch::gdt::{self, GdtEntryIndex, PrivilegeLevel, SegmentSelector};
 use crate::utils::sync::Mutex;
 
 #[repr(C, packed)]
@@ -76,6 +76,13 @@ impl EntryOptions {
     fn set_present(&mut self, present: bool) {
         self.bits.set_bit(15, present);
     }
+
+    /// Sets the interrupt stack table (IST) index, where zero means that the IST is not
+    /// used and the stack is not switched.
+    #[inline]
+    fn set_stack_index(&mut self, index: u16) {
+        self.bits.set_bits(0..3, index);
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -112,6 +119,10 @@ impl IdtEntry {
 
         self.options.set_present(true);
     }
+
+    pub(crate) fn set_stack_index(&mut self, index: u16) {
+        self.options.set_stack_index(index);
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -200,6 +211,10 @@ pub fn init() {
 
             IDT[index].set_function(handler);
         }
+
+        // Double faults are handled on a separate stack, so that kernel stack overflows
+        // can be reported instead of causing a triple fault.
+        IDT[8].set_stack_index(gdt::DOUBLE_FAULT_IST);
     }
 
     INTERRUPT_HANDLERS.lock()[0] = IrqHandler::ErrorHandler(exceptions::divide_by_zero);
@@ -247,6 +262,19 @@ pub fn init() {
     }
 }
 
+/// Loads the IDT on an application processor. The IDT is shared between all of the CPUs and
+/// is initialized by the BSP in [`init`].
+pub fn init_ap() {
+    unsafe {
+        let idt_descriptor = IdtDescriptor::new(
+            ((IDT.len() * size_of::<IdtEntry>()) - 1) as u16,
+            addr_of!(IDT).addr() as u64,
+        );
+
+        load_idt(&idt_descriptor);
+    }
+}
+
 #[inline(always)]
 unsafe fn load_idt(idt_descriptor: &IdtDescriptor) {
     asm!("lidt [{}]", in(reg) idt_descriptor, options(nostack));
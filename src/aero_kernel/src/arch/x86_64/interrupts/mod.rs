@@ -23,6 +23,7 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 pub use idt::*;
 
 use crate::arch::apic;
+use crate::userland::scheduler::preempt;
 use crate::utils::sync::Mutex;
 
 use super::{controlregs, io};
@@ -218,6 +219,15 @@ impl PicController {
 #[no_mangle]
 extern "C" fn generic_interrupt_handler(isr: usize, stack_frame: *mut InterruptErrorStack) {
     let stack_frame = unsafe { &mut *stack_frame };
+
+    preempt::irq_enter();
+
+    // Exceptions are handled in the context of the code that raised them.
+    #[cfg(feature = "lockdep")]
+    if isr >= 32 {
+        crate::utils::lockdep::hardirq_enter();
+    }
+
     let handlers = idt::INTERRUPT_HANDLERS.lock();
 
     match &handlers[isr] {
@@ -236,6 +246,19 @@ extern "C" fn generic_interrupt_handler(isr: usize, stack_frame: *mut InterruptE
         IrqHandler::None => log::warn!("unhandled interrupt {}", isr),
     }
 
+    // Exceptions can be triggered at will, so only the timing of external interrupts is used.
+    if isr >= 32 {
+        crate::random::add_interrupt_randomness(isr);
+        crate::userland::scheduler::stats::interrupt(isr);
+    }
+
+    #[cfg(feature = "lockdep")]
+    if isr >= 32 {
+        crate::utils::lockdep::hardirq_exit();
+    }
+
+    preempt::irq_exit();
+
     // Check and evaluate any pending signals.
     super::signals::interrupt_check_signals(&mut stack_frame.stack);
     INTERRUPT_CONTROLLER.eoi();
@@ -30,6 +30,12 @@ pub const IA32_GS_BASE: u32 = 0xc0000101;
 /// Swap Target of BASE Address of GS (R/W) See Table 35-2.
 pub const IA32_KERNEL_GSBASE: u32 = 0xc0000102;
 
+/// Time Stamp Counter (R/W).
+pub const IA32_TIME_STAMP_COUNTER: u32 = 0x10;
+
+/// Auxiliary TSC signature, returned by RDTSCP (R/W).
+pub const IA32_TSC_AUX: u32 = 0xc0000103;
+
 /// System Call Target Address (R/W).
 pub const IA32_STAR: u32 = 0xc0000081;
 
@@ -0,0 +1,314 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Architecture specific part of kexec: the purgatory trampoline which copies the staged
+//! segments of the new kernel to their final location and jumps to its entry point.
+//!
+//! The new kernel is entered in long mode with interrupts disabled, with all of the general
+//! purpose registers zeroed and with an identity map of the physical memory (using 2MiB pages)
+//! loaded in `CR3`. The kernel is expected to set up its own page tables, GDT and IDT.
+
+use alloc::vec::Vec;
+
+use limine::memory_map::EntryType;
+use limine::response::MemoryMapResponse;
+use spin::Once;
+
+use crate::mem::paging::{order_size, PhysAddr, FRAME_ALLOCATOR, MAX_ORDER};
+use crate::mem::AddressSpace;
+
+use super::{apic, interrupts, MEMMAP};
+
+const PAGE_SIZE: u64 = 0x1000;
+const HUGE_PAGE_SIZE: u64 = 0x200000;
+const GIB: u64 = 0x40000000;
+
+/// The identity map is created by a single PML4 entry.
+const MAX_IDENTITY_MAP: u64 = 512 * GIB;
+
+const PRESENT_WRITABLE: u64 = 0b11;
+const HUGE_PAGE: u64 = 1 << 7;
+
+/// Physical memory reserved for the crash kernel as `(base, size)`.
+static CRASH_REGION: Once<(u64, u64)> = Once::new();
+
+/// A copy performed by the purgatory. Both of the addresses are physical.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct Copy {
+    pub source: u64,
+    pub destination: u64,
+    pub size: u64,
+}
+
+extern "C" {
+    fn kexec_purgatory_start();
+    fn kexec_purgatory_end();
+}
+
+// Runs from the identity map, so it must be position independent. Arguments:
+//
+// * `rdi` - physical address of the copy list.
+// * `rsi` - number of entries in the copy list.
+// * `rdx` - entry point of the new kernel.
+// * `rcx` - top of the purgatory stack.
+core::arch::global_asm!(
+    ".global kexec_purgatory_start",
+    ".global kexec_purgatory_end",
+    "kexec_purgatory_start:",
+    "mov rsp, rcx",
+    "mov r12, rdi",
+    "mov r13, rsi",
+    "mov r14, rdx",
+    "cld",
+    "2:",
+    "test r13, r13",
+    "jz 3f",
+    "mov rsi, qword ptr [r12]",
+    "mov rdi, qword ptr [r12 + 8]",
+    "mov rcx, qword ptr [r12 + 16]",
+    "rep movsb",
+    "add r12, 24",
+    "dec r13",
+    "jmp 2b",
+    "3:",
+    "xor eax, eax",
+    "xor ebx, ebx",
+    "xor ecx, ecx",
+    "xor edx, edx",
+    "xor esi, esi",
+    "xor edi, edi",
+    "xor ebp, ebp",
+    "xor r8d, r8d",
+    "xor r9d, r9d",
+    "xor r10d, r10d",
+    "xor r11d, r11d",
+    "xor r12d, r12d",
+    "xor r13d, r13d",
+    "jmp r14",
+    "kexec_purgatory_end:",
+);
+
+/// Returns the ranges of usable RAM as `(base, length)` pairs.
+pub fn memory_ranges() -> impl Iterator<Item = (u64, u64)> {
+    // SAFETY: The memory map is only mutably borrowed during early boot.
+    let memmap = unsafe { &*MEMMAP.get() }
+        .get_response()
+        .expect("kexec: no memory map");
+
+    memmap
+        .entries()
+        .iter()
+        .filter(|entry| entry.entry_type == EntryType::USABLE)
+        .map(|entry| (entry.base, entry.length))
+}
+
+/// Returns whether the provided physical range lies entirely in usable RAM.
+pub fn is_ram(start: u64, size: u64) -> bool {
+    let Some(end) = start.checked_add(size) else {
+        return false;
+    };
+
+    memory_ranges().any(|(base, length)| start >= base && end <= base + length)
+}
+
+/// Removes `size` bytes (rounded up to 2MiB) of usable memory from the memory map and reserves
+/// them for the crash kernel. Called before the frame allocator is initialized.
+pub fn reserve_crash_region(memmap: &mut MemoryMapResponse, size: u64) {
+    let size = size.next_multiple_of(HUGE_PAGE_SIZE);
+
+    // Take the memory from the end of the highest usable entry that is large enough.
+    let entry = memmap
+        .entries_mut()
+        .iter_mut()
+        .filter(|entry| entry.entry_type == EntryType::USABLE)
+        .filter(|entry| {
+            let end = entry.base + entry.length;
+            end >= size && (end - size) / HUGE_PAGE_SIZE * HUGE_PAGE_SIZE >= entry.base
+        })
+        .max_by_key(|entry| entry.base);
+
+    let Some(entry) = entry else {
+        log::warn!("kexec: no memory to reserve {size:#x} bytes for the crash kernel");
+        return;
+    };
+
+    // The memory after the 2MiB aligned region (less than 2MiB) is left unused.
+    let base = (entry.base + entry.length - size) / HUGE_PAGE_SIZE * HUGE_PAGE_SIZE;
+    entry.length = base - entry.base;
+
+    log::info!(
+        "kexec: reserved {}MiB at {base:#x} for the crash kernel",
+        size >> 20
+    );
+
+    CRASH_REGION.call_once(|| (base, size));
+}
+
+/// Returns the memory reserved for the crash kernel as `(base, size)`, if any.
+pub fn crash_region() -> Option<(u64, u64)> {
+    CRASH_REGION.get().copied()
+}
+
+/// Returns the smallest order that can hold `size` bytes.
+fn order_for(size: usize) -> Option<usize> {
+    (0..=MAX_ORDER).find(|&order| order_size(order) >= size)
+}
+
+/// The purgatory code, its stack, the copy list and the identity map. None of the frames may
+/// overlap a copy destination, so they are allocated with the caller provided allocator.
+pub struct Purgatory {
+    control: PhysAddr,
+    list: PhysAddr,
+    list_order: usize,
+    count: usize,
+    pml4: PhysAddr,
+    tables: Vec<PhysAddr>,
+}
+
+impl Purgatory {
+    /// Prepares the purgatory for the provided copies. `alloc` must return zeroed blocks of the
+    /// requested order. Returns `None` if out of memory.
+    pub fn new(copies: &[Copy], alloc: &mut dyn FnMut(usize) -> Option<PhysAddr>) -> Option<Self> {
+        let code_size = kexec_purgatory_end as usize - kexec_purgatory_start as usize;
+        let list_size = core::mem::size_of_val(copies).max(1);
+        let list_order = order_for(list_size)?;
+
+        let mut this = Self {
+            control: PhysAddr::zero(),
+            list: PhysAddr::zero(),
+            list_order,
+            count: copies.len(),
+            pml4: PhysAddr::zero(),
+            tables: Vec::new(),
+        };
+
+        // Any of the frames allocated so far are freed by the destructor on failure.
+        this.control = alloc(0)?;
+        this.list = alloc(list_order)?;
+        this.pml4 = alloc(0)?;
+
+        unsafe {
+            let code = core::slice::from_raw_parts(kexec_purgatory_start as *const u8, code_size);
+            this.control
+                .as_hhdm_virt()
+                .as_bytes_mut(code_size)
+                .copy_from_slice(code);
+
+            let list = this.list.as_hhdm_virt().as_mut_ptr::<Copy>();
+            core::ptr::copy_nonoverlapping(copies.as_ptr(), list, copies.len());
+        }
+
+        // Identity map all of the RAM, rounded up to 1GiB.
+        let end = memory_ranges()
+            .chain(crash_region())
+            .map(|(base, length)| base + length)
+            .max()
+            .unwrap_or(0)
+            .min(MAX_IDENTITY_MAP);
+
+        let pdpt = alloc(0)?;
+        this.tables.push(pdpt);
+
+        let pml4 = this.pml4.as_hhdm_virt().as_mut_ptr::<u64>();
+        let pdpt_table = pdpt.as_hhdm_virt().as_mut_ptr::<u64>();
+
+        unsafe { pml4.write(pdpt.as_u64() | PRESENT_WRITABLE) };
+
+        for gib in 0..end.div_ceil(GIB) {
+            let pd = alloc(0)?;
+            this.tables.push(pd);
+
+            let pd_table = pd.as_hhdm_virt().as_mut_ptr::<u64>();
+
+            unsafe {
+                pdpt_table
+                    .add(gib as usize)
+                    .write(pd.as_u64() | PRESENT_WRITABLE);
+
+                for i in 0..512 {
+                    let addr = gib * GIB + i * HUGE_PAGE_SIZE;
+                    pd_table
+                        .add(i as usize)
+                        .write(addr | PRESENT_WRITABLE | HUGE_PAGE);
+                }
+            }
+        }
+
+        Some(this)
+    }
+
+    /// Jumps into the purgatory, which copies the segments and enters the new kernel.
+    ///
+    /// ## Safety
+    /// Nothing of the current kernel survives this; the caller must have shut down the devices
+    /// that could still perform DMA.
+    pub unsafe fn execute(&self, entry: u64) -> ! {
+        interrupts::disable_interrupts();
+
+        if !apic::stop_other_cpus() {
+            log::warn!("kexec: not all of the CPUs stopped");
+        }
+
+        apic::get_local_apic().timer_stop();
+
+        // The higher half is kept mapped so the kernel can keep running until it jumps into
+        // the purgatory.
+        let current = AddressSpace::this()
+            .cr3()
+            .start_address()
+            .as_hhdm_virt()
+            .as_ptr::<u64>();
+        let pml4 = self.pml4.as_hhdm_virt().as_mut_ptr::<u64>();
+
+        for i in 256..512 {
+            pml4.add(i).write(current.add(i).read());
+        }
+
+        let stack_top = self.control.as_u64() + PAGE_SIZE;
+
+        core::arch::asm!(
+            "mov cr3, {pml4}",
+            "jmp {purgatory}",
+            pml4 = in(reg) self.pml4.as_u64(),
+            purgatory = in(reg) self.control.as_u64(),
+            in("rdi") self.list.as_u64(),
+            in("rsi") self.count,
+            in("rdx") entry,
+            in("rcx") stack_top,
+            options(noreturn)
+        );
+    }
+}
+
+impl Drop for Purgatory {
+    fn drop(&mut self) {
+        let frames = [
+            (self.control, 0),
+            (self.list, self.list_order),
+            (self.pml4, 0),
+        ];
+
+        let tables = self.tables.iter().map(|&table| (table, 0));
+
+        for (frame, order) in frames.into_iter().chain(tables) {
+            if frame != PhysAddr::zero() {
+                FRAME_ALLOCATOR.dealloc_order(frame, order);
+            }
+        }
+    }
+}
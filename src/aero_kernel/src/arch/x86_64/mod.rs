@@ -19,21 +19,31 @@ pub mod cpu_local;
 
 pub mod apic;
 pub mod controlregs;
+pub mod cpuidle;
+pub mod cpuinfo;
+pub mod efi;
 pub mod gdt;
+pub mod hotplug;
 pub mod interrupts;
 pub mod io;
+pub mod kexec;
 pub mod mem;
+pub mod pmu;
+pub mod power;
+pub mod rtc;
 pub mod signals;
+pub mod suspend;
 pub mod syscall;
 pub mod task;
 pub mod time;
 pub mod tls;
 pub mod user_copy;
+pub mod vdso;
 
 mod asm_macros;
 
 use core::cell::SyncUnsafeCell;
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::acpi::aml;
 use crate::{acpi, cmdline};
@@ -56,15 +66,34 @@ static SMP: SyncUnsafeCell<SmpRequest> = SyncUnsafeCell::new(SmpRequest::new());
 static MEMMAP: SyncUnsafeCell<MemoryMapRequest> = SyncUnsafeCell::new(MemoryMapRequest::new());
 
 static KERNEL_FILE: KernelFileRequest = KernelFileRequest::new();
+static KERNEL_ADDRESS: KernelAddressRequest = KernelAddressRequest::new();
 static MODULES: ModuleRequest = ModuleRequest::new();
 static FRAMEBUFFER: FramebufferRequest = FramebufferRequest::new();
 static RSDP: RsdpRequest = RsdpRequest::new();
 static BOOT_TIME: BootTimeRequest = BootTimeRequest::new();
 static STACK: StackSizeRequest = StackSizeRequest::new().with_size(0x1000 * 32); // 16KiB of stack for both the BSP and the APs
 static HHDM: HhdmRequest = HhdmRequest::new();
+static EFI_SYSTEM_TABLE: EfiSystemTableRequest = EfiSystemTableRequest::new();
+static EFI_MEMMAP: EfiMemoryMapRequest = EfiMemoryMapRequest::new();
 
 #[no_mangle]
+#[no_sanitize(address)]
 extern "C" fn arch_aero_main() -> ! {
+    // The instrumented code poisons its stack frames in the shadow memory, so it has to be
+    // mapped before anything else runs.
+    #[cfg(feature = "kasan")]
+    unsafe {
+        let kernel_address = KERNEL_ADDRESS
+            .get_response()
+            .expect("limine: invalid kernel address response");
+
+        crate::mem::kasan::init_early(
+            HHDM.get_response().unwrap().offset(),
+            kernel_address.virtual_base(),
+            kernel_address.physical_base(),
+        );
+    }
+
     let kernel_file_resp = KERNEL_FILE
         .get_response()
         .expect("limine: invalid kernel file response");
@@ -76,6 +105,7 @@ extern "C" fn arch_aero_main() -> ! {
     // panic, it will be able to unwind the stack.
     crate::unwind::UNWIND_INFO.call_once(|| {
         use crate::unwind::UnwindInfo;
+        use xmas_elf::program::Type;
         use xmas_elf::ElfFile;
 
         let start = kernel_file.addr();
@@ -84,7 +114,20 @@ extern "C" fn arch_aero_main() -> ! {
         let elf_slice = unsafe { core::slice::from_raw_parts(start, kernel_file.size() as usize) };
         let elf = ElfFile::new(elf_slice).expect("limine: invalid kernel file");
 
-        UnwindInfo::new(elf)
+        // The bootloader loads the kernel at a random address if KASLR is enabled.
+        let link_base = elf
+            .program_iter()
+            .filter(|header| header.get_type() == Ok(Type::Load))
+            .map(|header| header.virtual_addr())
+            .min()
+            .expect("limine: kernel file has no loadable segments");
+
+        let virtual_base = KERNEL_ADDRESS
+            .get_response()
+            .expect("limine: invalid kernel address response")
+            .virtual_base();
+
+        UnwindInfo::new(elf, virtual_base.wrapping_sub(link_base) as usize)
     });
 
     crate::relocate_self();
@@ -121,25 +164,33 @@ extern "C" fn arch_aero_main() -> ! {
     let command_line = core::str::from_utf8(kernel_file.cmdline()).unwrap();
     let command_line = cmdline::parse(command_line, modules);
 
+    // The crash kernel region has to be carved out of the memory map before the frame
+    // allocator takes over the usable memory.
+    if let Some(size) = command_line.crashkernel {
+        kexec::reserve_crash_region(memmap, size as u64);
+    }
+
+    // The wakeup trampoline for suspend-to-RAM has to be below 1MiB.
+    suspend::reserve_wakeup_memory(memmap);
+
     paging::init(memmap).unwrap();
     log::info!("loaded paging");
 
-    crate::mem::alloc::init_heap();
-    log::info!("loaded heap");
-
-    // SMP initialization.
-    let smp_response = unsafe { &mut *SMP.get() }.get_response_mut().unwrap();
-    let bsp_lapic_id = smp_response.bsp_lapic_id();
+    if command_line.kaslr {
+        paging::randomize_physical_map(kaslr_seed()).unwrap();
+    }
 
-    for cpu in smp_response.cpus_mut() {
-        apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+    #[cfg(feature = "kasan")]
+    crate::mem::kasan::init(memmap);
 
-        if cpu.lapic_id == bsp_lapic_id {
-            continue;
-        }
+    crate::mem::alloc::init_heap();
+    log::info!("loaded heap");
 
-        cpu.goto_address.write(x86_64_aero_ap_main);
-    }
+    // SMP initialization. The application processors are only started once the BSP is
+    // done with the architecture specific initialization (see `start_aps`), but the CPU
+    // count is required before that to allocate the per-CPU data.
+    let smp_response = unsafe { &*SMP.get() }.get_response().unwrap();
+    apic::CPU_COUNT.store(smp_response.cpus().len(), Ordering::SeqCst);
 
     gdt::init_boot();
     log::info!("loaded bootstrap GDT");
@@ -162,13 +213,18 @@ extern "C" fn arch_aero_main() -> ! {
     apic::init();
     log::info!("loaded APIC");
 
+    pmu::init();
+
     let rsdp = VirtAddr::new(RSDP.get_response().unwrap().address().addr() as u64);
 
     acpi::init(rsdp);
     log::info!("loaded ACPI");
 
-    tls::init();
+    efi::init();
+
     cpu_local::init(0);
+    cpuinfo::init();
+    vdso::init_cpu();
     log::info!("loaded TLS");
 
     crate::unwind::set_panic_hook_ready(true);
@@ -181,21 +237,46 @@ extern "C" fn arch_aero_main() -> ! {
     let boot_time = BOOT_TIME.get_response().unwrap();
     time::EPOCH.store(boot_time.boot_time().as_secs() as usize, Ordering::SeqCst);
 
+    start_aps();
+
     // Architecture init is done. Now we can initialize and start the init
     // process in the non-architecture specific part of the kernel.
     crate::aero_main();
 }
 
+/// Logical ID of the next application processor, the BSP is always CPU 0.
+static NEXT_AP_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Starts the application processors. They wait for the BSP to initialize the scheduler before
+/// they start scheduling tasks.
+fn start_aps() {
+    let smp_response = unsafe { &mut *SMP.get() }.get_response_mut().unwrap();
+    let bsp_lapic_id = smp_response.bsp_lapic_id();
+
+    for cpu in smp_response.cpus_mut() {
+        if cpu.lapic_id == bsp_lapic_id {
+            continue;
+        }
+
+        cpu.goto_address.write(x86_64_aero_ap_main);
+    }
+}
+
 extern "C" fn x86_64_aero_ap_main(cpu: &Cpu) -> ! {
-    let ap_id = cpu.id as usize;
+    // NOTE: The processor ID provided by the bootloader is not guaranteed to be contiguous,
+    // so each AP is assigned its own logical ID which is used to index the per-CPU data.
+    let ap_id = NEXT_AP_ID.fetch_add(1, Ordering::SeqCst);
 
-    log::debug!("booting CPU {}", ap_id);
+    log::debug!("booting CPU {} (lapic_id={})", ap_id, cpu.lapic_id);
+
+    init_cpu();
 
     gdt::init_boot();
     log::info!("AP{}: loaded boot GDT", ap_id);
 
-    tls::init();
     cpu_local::init(ap_id);
+    cpuinfo::init();
+    vdso::init_cpu();
     log::info!("AP{}: loaded TLS", ap_id);
 
     gdt::init();
@@ -209,6 +290,14 @@ extern "C" fn x86_64_aero_ap_main(cpu: &Cpu) -> ! {
         core::hint::spin_loop();
     }
 
+    interrupts::init_ap();
+    log::info!("AP{}: loaded IDT", ap_id);
+
+    apic::init_ap();
+    log::info!("AP{}: loaded APIC", ap_id);
+
+    pmu::init_ap();
+
     // Architecture init is done. Now move on to the non-architecture specific
     // initialization of the AP.
     crate::aero_ap_main(ap_id);
@@ -216,6 +305,7 @@ extern "C" fn x86_64_aero_ap_main(cpu: &Cpu) -> ! {
 
 pub fn enable_acpi() {
     aml::get_subsystem().enable_acpi(INTERRUPT_CONTROLLER.method() as _);
+    acpi::events::init();
 }
 
 fn enable_xsave() {
@@ -233,6 +323,83 @@ fn enable_xsave() {
     unsafe { controlregs::write_xcr0(xcr0) }
 }
 
+/// Returns a random number generated by RDRAND; [`None`] if it is not supported by the CPU or did
+/// not return a number.
+pub fn rdrand() -> Option<u64> {
+    /// Number of times to retry RDRAND before giving up, as recommended by Intel.
+    const RDRAND_RETRIES: usize = 10;
+
+    let has_rdrand = CpuId::new()
+        .get_feature_info()
+        .is_some_and(|features| features.has_rdrand());
+
+    if !has_rdrand {
+        return None;
+    }
+
+    for _ in 0..RDRAND_RETRIES {
+        let mut value = 0;
+
+        // SAFETY: RDRAND is supported by the CPU.
+        if unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Returns a random number generated by RDSEED; [`None`] if it is not supported by the CPU or
+/// did not return a number.
+///
+/// Unlike RDRAND, which returns the output of a DRBG that is only periodically reseeded, RDSEED
+/// returns conditioned output of the entropy source itself and is therefore suited for seeding
+/// other random number generators.
+pub fn rdseed() -> Option<u64> {
+    /// RDSEED fails more often than RDRAND when the entropy source is exhausted, so it is
+    /// retried more often before giving up.
+    const RDSEED_RETRIES: usize = 100;
+
+    let has_rdseed = CpuId::new()
+        .get_extended_feature_info()
+        .is_some_and(|features| features.has_rdseed());
+
+    if !has_rdseed {
+        return None;
+    }
+
+    for _ in 0..RDSEED_RETRIES {
+        let mut value = 0;
+
+        // SAFETY: RDSEED is supported by the CPU.
+        if unsafe { core::arch::x86_64::_rdseed64_step(&mut value) } == 1 {
+            return Some(value);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    None
+}
+
+/// Returns a random seed for KASLR. RDRAND is used if it is supported by the CPU; the time stamp
+/// counter and the bootloader's kernel load address (randomized by the bootloader) are mixed in.
+fn kaslr_seed() -> u64 {
+    let mut seed = unsafe { core::arch::x86_64::_rdtsc() };
+
+    if let Some(virtual_base) = KERNEL_ADDRESS.get_response().map(|e| e.virtual_base()) {
+        seed ^= virtual_base.rotate_left(32);
+    }
+
+    if let Some(value) = rdrand() {
+        seed ^= value;
+    } else {
+        log::warn!("kaslr: RDRAND is not supported, using the time stamp counter as the seed");
+    }
+
+    seed
+}
+
 pub fn has_fsgsbase() -> bool {
     static HAS_FSGSBASE: Once<bool> = Once::new();
 
@@ -0,0 +1,391 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hardware performance monitoring counters.
+//!
+//! Both the Intel architectural PMU (CPUID leaf 0xA) and the AMD core performance counters
+//! are supported. Only the general purpose counters are used; the fixed counters of Intel
+//! CPUs are left disabled. Counter overflows are delivered through the performance counter
+//! entry of the local APIC.
+//!
+//! ## Notes
+//! * Intel® 64 and IA-32 Architectures Software Developer's Manual, Volume 3B (Chapter 20)
+//! * AMD64 Architecture Programmer's Manual, Volume 2 (Section 13.2)
+
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use aero_syscall::perf::*;
+use spin::Once;
+
+use super::interrupts::{self, InterruptStack};
+use super::{apic, io};
+
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PMC0: u32 = 0xc1;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38e;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+/// Legacy AMD counters; `PERF_CTL0..3` followed by `PERF_CTR0..3`.
+const MSR_K7_EVNTSEL0: u32 = 0xc001_0000;
+const MSR_K7_PERFCTR0: u32 = 0xc001_0004;
+/// Extended AMD counters; `PERF_CTLn` and `PERF_CTRn` are interleaved.
+const MSR_F15H_PERF_CTL0: u32 = 0xc001_0200;
+
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+/// Bits of the event select register which can be set by `PERF_TYPE_RAW` events: the event
+/// select, unit mask, edge detect, invert and counter mask fields.
+const EVTSEL_RAW_MASK: u64 = 0xff | 0xff << 8 | 1 << 18 | 1 << 23 | 0xff << 24;
+/// AMD CPUs extend the event select field with bits 32 to 35.
+const AMD_EVTSEL_RAW_MASK: u64 = EVTSEL_RAW_MASK | 0xf << 32;
+
+/// Event select and unit mask of the architectural events, indexed by the `PERF_COUNT_HW_*`
+/// generalized event. The bit of the event in EBX of CPUID leaf 0xA is set if the event is
+/// not available.
+const INTEL_EVENTS: &[(u64, u64, u32)] = &[
+    (PERF_COUNT_HW_CPU_CYCLES, 0x003c, 0),
+    (PERF_COUNT_HW_INSTRUCTIONS, 0x00c0, 1),
+    (PERF_COUNT_HW_CACHE_REFERENCES, 0x4f2e, 3),
+    (PERF_COUNT_HW_CACHE_MISSES, 0x412e, 4),
+    (PERF_COUNT_HW_BRANCH_INSTRUCTIONS, 0x00c4, 5),
+    (PERF_COUNT_HW_BRANCH_MISSES, 0x00c5, 6),
+    (PERF_COUNT_HW_REF_CPU_CYCLES, 0x013c, 2),
+];
+
+/// Events of AMD CPUs before Zen (family 0x17).
+const AMD_EVENTS: &[(u64, u64)] = &[
+    (PERF_COUNT_HW_CPU_CYCLES, 0x0076),
+    (PERF_COUNT_HW_INSTRUCTIONS, 0x00c0),
+    (PERF_COUNT_HW_CACHE_REFERENCES, 0x077d),
+    (PERF_COUNT_HW_CACHE_MISSES, 0x077e),
+    (PERF_COUNT_HW_BRANCH_INSTRUCTIONS, 0x00c2),
+    (PERF_COUNT_HW_BRANCH_MISSES, 0x00c3),
+];
+
+const AMD_ZEN_EVENTS: &[(u64, u64)] = &[
+    (PERF_COUNT_HW_CPU_CYCLES, 0x0076),
+    (PERF_COUNT_HW_INSTRUCTIONS, 0x00c0),
+    (PERF_COUNT_HW_CACHE_REFERENCES, 0xff60),
+    (PERF_COUNT_HW_CACHE_MISSES, 0x0964),
+    (PERF_COUNT_HW_BRANCH_INSTRUCTIONS, 0x00c2),
+    (PERF_COUNT_HW_BRANCH_MISSES, 0x00c3),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Vendor {
+    Intel {
+        version: u32,
+        /// Mask of the architectural events which are *not* available.
+        unavailable: u32,
+    },
+    Amd {
+        zen: bool,
+        /// Whether the extended (core performance counter extensions) MSRs are used.
+        extended: bool,
+    },
+}
+
+struct Pmu {
+    vendor: Vendor,
+    counters: usize,
+    width: u32,
+}
+
+impl Pmu {
+    fn detect() -> Option<Self> {
+        let leaf0 = unsafe { __cpuid_count(0, 0) };
+        let vendor = [leaf0.ebx, leaf0.edx, leaf0.ecx];
+
+        let vendor = unsafe { core::mem::transmute::<[u32; 3], [u8; 12]>(vendor) };
+
+        match &vendor {
+            b"GenuineIntel" if leaf0.eax >= 0xa => {
+                let leaf = unsafe { __cpuid_count(0xa, 0) };
+                let version = leaf.eax & 0xff;
+
+                if version == 0 {
+                    return None;
+                }
+
+                // EAX[31:24] is the length of the EBX bit vector.
+                let length = (leaf.eax >> 24) & 0xff;
+                let unavailable = leaf.ebx | !((1u32 << length.min(31)) - 1);
+
+                Some(Self {
+                    vendor: Vendor::Intel {
+                        version,
+                        unavailable,
+                    },
+                    counters: ((leaf.eax >> 8) & 0xff) as usize,
+                    width: (leaf.eax >> 16) & 0xff,
+                })
+            }
+
+            b"AuthenticAMD" => {
+                let family = {
+                    let eax = unsafe { __cpuid_count(1, 0) }.eax;
+                    let family = (eax >> 8) & 0xf;
+
+                    if family == 0xf {
+                        family + ((eax >> 20) & 0xff)
+                    } else {
+                        family
+                    }
+                };
+
+                let extended = unsafe { __cpuid_count(0x8000_0000, 0) }.eax >= 0x8000_0001
+                    && unsafe { __cpuid_count(0x8000_0001, 0) }.ecx & (1 << 23) != 0;
+
+                Some(Self {
+                    vendor: Vendor::Amd {
+                        zen: family >= 0x17,
+                        extended,
+                    },
+                    counters: if extended { 6 } else { 4 },
+                    width: 48,
+                })
+            }
+
+            _ => None,
+        }
+    }
+
+    fn evtsel_msr(&self, index: usize) -> u32 {
+        match self.vendor {
+            Vendor::Intel { .. } => IA32_PERFEVTSEL0 + index as u32,
+            Vendor::Amd { extended: true, .. } => MSR_F15H_PERF_CTL0 + 2 * index as u32,
+            Vendor::Amd { .. } => MSR_K7_EVNTSEL0 + index as u32,
+        }
+    }
+
+    fn counter_msr(&self, index: usize) -> u32 {
+        match self.vendor {
+            Vendor::Intel { .. } => IA32_PMC0 + index as u32,
+            Vendor::Amd { extended: true, .. } => MSR_F15H_PERF_CTL0 + 2 * index as u32 + 1,
+            Vendor::Amd { .. } => MSR_K7_PERFCTR0 + index as u32,
+        }
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.width) - 1
+    }
+}
+
+static PMU: Once<Option<Pmu>> = Once::new();
+static PMI_VECTOR: AtomicU8 = AtomicU8::new(0);
+
+fn pmu() -> Option<&'static Pmu> {
+    PMU.get().and_then(Option::as_ref)
+}
+
+fn pmi_handler(stack: &mut InterruptStack) {
+    let pmu = pmu().expect("pmu: overflow interrupt without a PMU");
+
+    let ip = stack.iret.rip;
+    let user = stack.iret.is_user();
+
+    match pmu.vendor {
+        Vendor::Intel { .. } => {
+            let status = unsafe { io::rdmsr(IA32_PERF_GLOBAL_STATUS) };
+
+            for index in (0..pmu.counters).filter(|index| status & (1 << index) != 0) {
+                crate::perf::counter_overflow(index, ip, user);
+            }
+
+            unsafe { io::wrmsr(IA32_PERF_GLOBAL_OVF_CTRL, status) };
+        }
+
+        // AMD CPUs have no overflow status register; the counters are preloaded with a negative
+        // value, so a counter overflowed if its sign bit is clear.
+        Vendor::Amd { .. } => {
+            for index in 0..pmu.counters {
+                let evtsel = unsafe { io::rdmsr(pmu.evtsel_msr(index)) };
+
+                if evtsel & (EVTSEL_EN | EVTSEL_INT) != EVTSEL_EN | EVTSEL_INT {
+                    continue;
+                }
+
+                if read_counter(index) & (1 << (pmu.width - 1)) == 0 {
+                    crate::perf::counter_overflow(index, ip, user);
+                }
+            }
+        }
+    }
+
+    apic::get_local_apic().set_lvt_perf(PMI_VECTOR.load(Ordering::Relaxed));
+}
+
+/// Detects the PMU and routes its overflow interrupts on the BSP.
+pub fn init() {
+    let pmu = PMU.call_once(Pmu::detect);
+
+    let Some(pmu) = pmu else {
+        log::debug!("pmu: no performance counters found");
+        return;
+    };
+
+    log::info!(
+        "pmu: {:?} with {} counters ({} bits)",
+        pmu.vendor,
+        pmu.counters,
+        pmu.width
+    );
+
+    let vector = interrupts::allocate_vector();
+    interrupts::register_handler(vector, pmi_handler);
+    PMI_VECTOR.store(vector, Ordering::Relaxed);
+
+    init_ap();
+}
+
+/// Stops the counters and routes the overflow interrupts of the current CPU.
+pub fn init_ap() {
+    let Some(pmu) = pmu() else {
+        return;
+    };
+
+    for index in 0..pmu.counters {
+        unsafe {
+            io::wrmsr(pmu.evtsel_msr(index), 0);
+            io::wrmsr(pmu.counter_msr(index), 0);
+        }
+    }
+
+    // Starting with version 2, the counters also have to be enabled globally. They are all left
+    // enabled, since a counter only counts while the enable bit of its event select is set.
+    if let Vendor::Intel { version: 2.., .. } = pmu.vendor {
+        unsafe { io::wrmsr(IA32_PERF_GLOBAL_CTRL, (1 << pmu.counters) - 1) };
+    }
+
+    apic::get_local_apic().set_lvt_perf(PMI_VECTOR.load(Ordering::Relaxed));
+}
+
+/// Returns the number of general purpose counters; zero if there is no PMU.
+pub fn num_counters() -> usize {
+    pmu().map(|pmu| pmu.counters).unwrap_or(0)
+}
+
+/// Returns the width of the counters in bits.
+pub fn counter_width() -> u32 {
+    pmu().map(|pmu| pmu.width).unwrap_or(0)
+}
+
+/// Returns the longest sample period that can be programmed into a counter.
+pub fn max_period() -> u64 {
+    match pmu() {
+        // Writes to the legacy counter MSRs are sign extended from bit 31.
+        Some(Pmu {
+            vendor: Vendor::Intel { .. },
+            ..
+        }) => (1 << 31) - 1,
+        Some(pmu) => pmu.mask() >> 1,
+        None => 0,
+    }
+}
+
+/// Returns the event select of the `PERF_COUNT_HW_*` event (`config`); [`None`] if the event
+/// is not supported by the CPU.
+pub fn hardware_event(config: u64) -> Option<u64> {
+    match pmu()?.vendor {
+        Vendor::Intel { unavailable, .. } => INTEL_EVENTS
+            .iter()
+            .find(|(event, _, bit)| *event == config && unavailable & (1 << bit) == 0)
+            .map(|(_, evtsel, _)| *evtsel),
+
+        Vendor::Amd { zen, .. } => {
+            let events = if zen { AMD_ZEN_EVENTS } else { AMD_EVENTS };
+
+            events
+                .iter()
+                .find(|(event, _)| *event == config)
+                .map(|(_, evtsel)| *evtsel)
+        }
+    }
+}
+
+/// Returns the event select of the `PERF_TYPE_RAW` event (`config`); [`None`] if it sets bits
+/// which are reserved or controlled by the kernel.
+pub fn raw_event(config: u64) -> Option<u64> {
+    let mask = match pmu()?.vendor {
+        Vendor::Intel { .. } => EVTSEL_RAW_MASK,
+        Vendor::Amd { .. } => AMD_EVTSEL_RAW_MASK,
+    };
+
+    (config & !mask == 0).then_some(config)
+}
+
+/// Starts counting `event` on the counter at `index` of the current CPU, starting from
+/// `value`. If `interrupt` is set, an overflow interrupt is raised when the counter wraps.
+///
+/// ## Safety
+/// The counter must not be in use and `event` must have been returned by
+/// [`hardware_event`] or [`raw_event`].
+pub unsafe fn start_counter(
+    index: usize,
+    event: u64,
+    user: bool,
+    kernel: bool,
+    interrupt: bool,
+    value: u64,
+) {
+    let pmu = pmu().expect("pmu: no performance counters");
+    let mut evtsel = event | EVTSEL_EN;
+
+    if user {
+        evtsel |= EVTSEL_USR;
+    }
+
+    if kernel {
+        evtsel |= EVTSEL_OS;
+    }
+
+    if interrupt {
+        evtsel |= EVTSEL_INT;
+    }
+
+    io::wrmsr(pmu.counter_msr(index), value & pmu.mask());
+    io::wrmsr(pmu.evtsel_msr(index), evtsel);
+}
+
+/// Stops the counter at `index` of the current CPU. The value of the counter is preserved.
+///
+/// ## Safety
+/// The counter must not be in use by anything else.
+pub unsafe fn stop_counter(index: usize) {
+    let pmu = pmu().expect("pmu: no performance counters");
+    io::wrmsr(pmu.evtsel_msr(index), 0);
+}
+
+/// Returns the value of the counter at `index` of the current CPU.
+pub fn read_counter(index: usize) -> u64 {
+    let pmu = pmu().expect("pmu: no performance counters");
+    unsafe { io::rdmsr(pmu.counter_msr(index)) & pmu.mask() }
+}
+
+/// Sets the value of the counter at `index` of the current CPU.
+///
+/// ## Safety
+/// The counter must not be in use by anything else.
+pub unsafe fn write_counter(index: usize, value: u64) {
+    let pmu = pmu().expect("pmu: no performance counters");
+    io::wrmsr(pmu.counter_msr(index), value & pmu.mask());
+}
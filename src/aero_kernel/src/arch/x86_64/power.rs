@@ -0,0 +1,193 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Powering off and rebooting the machine.
+//!
+//! The machine is powered off by entering the ACPI S5 (soft off) sleep state, which is done by
+//! the AML interpreter as the `SLP_TYP` values have to be read from the `\_S5` package of the
+//! DSDT. It is rebooted using the reset register from the FADT, falling back to the keyboard
+//! controller, the reset control register of the chipset and finally a triple fault.
+//!
+//! **Notes**: <https://wiki.osdev.org/Reboot>
+
+use crate::acpi::{aml, fadt, GenericAddressStructure};
+use crate::mem::paging::PhysAddr;
+
+use super::{interrupts, io};
+
+// Address spaces of a generic address structure:
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+const ADDRESS_SPACE_IO: u8 = 1;
+const ADDRESS_SPACE_PCI: u8 = 2;
+
+const PCI_CONFIG_ADDRESS: u16 = 0xcf8;
+const PCI_CONFIG_DATA: u16 = 0xcfc;
+
+const PS2_STATUS: u16 = 0x64;
+const PS2_COMMAND: u16 = 0x64;
+/// Set while the keyboard controller has not consumed the last command.
+const PS2_STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Pulses the reset line of the CPU.
+const PS2_COMMAND_RESET: u8 = 0xfe;
+
+/// The reset control register, which is implemented by most chipsets.
+const RESET_CONTROL: u16 = 0xcf9;
+const RESET_CONTROL_SYS_RST: u8 = 1 << 1;
+const RESET_CONTROL_RST_CPU: u8 = 1 << 2;
+
+/// Gives the hardware about 100 milliseconds to act on a reset request.
+fn settle() {
+    for _ in 0..100_000 {
+        unsafe { io::wait() }
+    }
+}
+
+/// Writes `value` to the (byte wide) register described by `register`. Returns `false` if the
+/// address space is not supported.
+unsafe fn write_register(register: GenericAddressStructure, value: u8) -> bool {
+    let address = register.address;
+
+    match register.address_space {
+        ADDRESS_SPACE_MEMORY => {
+            let address = PhysAddr::new(address).as_hhdm_virt();
+            core::ptr::write_volatile(address.as_mut_ptr::<u8>(), value);
+        }
+
+        ADDRESS_SPACE_IO => io::outb(address as u16, value),
+
+        // The device is on bus 0 of segment 0 and its address is encoded as
+        // `device << 32 | function << 16 | offset`.
+        ADDRESS_SPACE_PCI => {
+            let device = (address >> 32) as u32 & 0x1f;
+            let function = (address >> 16) as u32 & 0x7;
+            let offset = address as u32 & 0xff;
+
+            let config = 0x8000_0000 | (device << 11) | (function << 8) | (offset & 0xfc);
+            let shift = (offset & 0b11) * 8;
+
+            io::outl(PCI_CONFIG_ADDRESS, config);
+            let current = io::inl(PCI_CONFIG_DATA) & !(0xff << shift);
+
+            io::outl(PCI_CONFIG_ADDRESS, config);
+            io::outl(PCI_CONFIG_DATA, current | ((value as u32) << shift));
+        }
+
+        _ => return false,
+    }
+
+    true
+}
+
+/// Resets the machine using the reset register from the FADT.
+fn acpi_reset() {
+    let Some((register, value)) =
+        fadt::get().and_then(|(fadt, length)| fadt.reset_register(length))
+    else {
+        return;
+    };
+
+    let address_space = register.address_space;
+    log::info!("power: resetting using the ACPI reset register (address_space={address_space})");
+
+    if unsafe { write_register(register, value) } {
+        settle();
+    }
+}
+
+/// Resets the machine by pulsing the reset line through the keyboard controller.
+fn ps2_reset() {
+    log::info!("power: resetting using the keyboard controller");
+
+    unsafe {
+        for _ in 0..0x10000 {
+            if io::inb(PS2_STATUS) & PS2_STATUS_INPUT_FULL == 0 {
+                break;
+            }
+
+            io::wait();
+        }
+
+        io::outb(PS2_COMMAND, PS2_COMMAND_RESET);
+    }
+
+    settle();
+}
+
+/// Resets the machine using the reset control register of the chipset.
+fn chipset_reset() {
+    log::info!("power: resetting using the reset control register");
+
+    unsafe {
+        io::outb(RESET_CONTROL, RESET_CONTROL_SYS_RST);
+        io::outb(RESET_CONTROL, RESET_CONTROL_SYS_RST | RESET_CONTROL_RST_CPU);
+    }
+
+    settle();
+}
+
+/// Resets the CPU by loading an empty IDT and raising an exception.
+fn triple_fault() -> ! {
+    log::info!("power: resetting using a triple fault");
+
+    let idt = [0u16; 5];
+
+    unsafe {
+        asm!("lidt [{}]", "int3", in(reg) idt.as_ptr(), options(noreturn));
+    }
+}
+
+/// Reboots the machine.
+pub fn reboot() -> ! {
+    unsafe { interrupts::disable_interrupts() }
+
+    acpi_reset();
+    ps2_reset();
+    chipset_reset();
+    triple_fault()
+}
+
+/// Powers off the machine by entering the S5 sleep state. If that is not possible, the machine
+/// is halted instead.
+pub fn poweroff() -> ! {
+    unsafe { interrupts::disable_interrupts() }
+
+    match aml::try_get_subsystem() {
+        Some(subsystem) => {
+            log::info!("power: entering sleep state S5");
+            subsystem.enter_state(aml::SleepState::S5);
+
+            log::error!("power: failed to enter sleep state S5");
+        }
+
+        None => log::error!("power: ACPI is not available"),
+    }
+
+    halt()
+}
+
+/// Halts the machine.
+pub fn halt() -> ! {
+    log::info!("power: system halted");
+
+    unsafe {
+        interrupts::disable_interrupts();
+
+        loop {
+            interrupts::halt();
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The CMOS real-time clock (RTC) keeps the date and time while the machine is powered off. It
+//! is read once at boot to initialize the wall-clock time and written back when the wall-clock
+//! time is set.
+//!
+//! The RTC does not have a standard century register; its index is provided by the `century`
+//! field of the FADT if the firmware maintains one. The FADT also reports whether the CMOS RTC
+//! is present at all, in which case the kernel falls back to the boot time provided by the
+//! bootloader.
+//!
+//! **Notes**: <https://wiki.osdev.org/CMOS>
+
+use crate::acpi::fadt;
+use crate::utils::sync::Mutex;
+
+use super::io;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Disables NMIs while a CMOS register is selected.
+const CMOS_NMI_DISABLE: u8 = 1 << 7;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY_OF_MONTH: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+const STATUS_B_SET: u8 = 1 << 7;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+
+/// Set in the hours register for PM times in the 12-hour mode.
+const HOURS_PM: u8 = 1 << 7;
+
+/// Set in the IA-PC boot architecture flags of the FADT if the CMOS RTC is not present.
+const IAPC_BOOT_ARCH_CMOS_RTC_NOT_PRESENT: u16 = 1 << 5;
+
+/// Serializes accesses to the CMOS, as selecting a register and accessing it are two separate
+/// operations.
+static CMOS: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// The month, in the range `1..=12`.
+    pub month: u8,
+    /// The day of the month, in the range `1..=31`.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns whether all of the fields are in range.
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Converts the date and time (in UTC) to seconds since the Unix epoch.
+    pub fn to_unix(&self) -> u64 {
+        // Days since the epoch of the civil date, with the year starting in March so the leap
+        // day is the last day of the year.
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        let seconds = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        (days * 86400 + seconds).max(0) as u64
+    }
+
+    /// Converts seconds since the Unix epoch to the date and time in UTC.
+    pub fn from_unix(time: u64) -> Self {
+        let days = (time / 86400) as i64 + 719468;
+        let seconds = time % 86400;
+
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+fn cmos_read(register: u8) -> u8 {
+    unsafe {
+        io::outb(CMOS_ADDRESS, CMOS_NMI_DISABLE | register);
+        io::inb(CMOS_DATA)
+    }
+}
+
+fn cmos_write(register: u8, value: u8) {
+    unsafe {
+        io::outb(CMOS_ADDRESS, CMOS_NMI_DISABLE | register);
+        io::outb(CMOS_DATA, value);
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0f) + (value >> 4) * 10
+}
+
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Returns whether the CMOS RTC is present, as reported by the FADT.
+fn is_present() -> bool {
+    let Some((fadt, length)) = fadt::get() else {
+        // Assume that the RTC is present on legacy systems.
+        return true;
+    };
+
+    // The IA-PC boot architecture flags are only present since ACPI 2.0.
+    if length < core::mem::offset_of!(fadt::Fadt, boot_architecture_flags) + 2 {
+        return true;
+    }
+
+    let flags = fadt.boot_architecture_flags;
+    flags & IAPC_BOOT_ARCH_CMOS_RTC_NOT_PRESENT == 0
+}
+
+/// Returns the index of the century register, if the firmware maintains one.
+fn century_register() -> Option<u8> {
+    fadt::get()
+        .map(|(fadt, _)| fadt.century)
+        .filter(|century| *century != 0)
+}
+
+/// Reads the raw date and time registers, in the order (seconds, minutes, hours, day, month,
+/// year, century).
+fn read_registers(century: Option<u8>) -> [u8; 7] {
+    // Wait for the update cycle to finish, as the registers are inconsistent during it.
+    while cmos_read(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    [
+        cmos_read(RTC_SECONDS),
+        cmos_read(RTC_MINUTES),
+        cmos_read(RTC_HOURS),
+        cmos_read(RTC_DAY_OF_MONTH),
+        cmos_read(RTC_MONTH),
+        cmos_read(RTC_YEAR),
+        century.map_or(0, cmos_read),
+    ]
+}
+
+/// Reads the date and time (in UTC) from the RTC. Returns [`None`] if the RTC is not present or
+/// holds an invalid date.
+pub fn read() -> Option<DateTime> {
+    if !is_present() {
+        return None;
+    }
+
+    let century = century_register();
+    let _guard = CMOS.lock_irq();
+
+    // An update cycle may start right after the update-in-progress flag was checked, so read
+    // the registers until two consecutive reads agree.
+    let mut registers = read_registers(century);
+
+    loop {
+        let next = read_registers(century);
+
+        if next == registers {
+            break;
+        }
+
+        registers = next;
+    }
+
+    let [second, minute, hour, day, month, year, century_value] = registers;
+    let status_b = cmos_read(RTC_STATUS_B);
+
+    let is_pm = hour & HOURS_PM != 0;
+    let hour = hour & !HOURS_PM;
+
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+
+    let mut hour = decode(hour);
+
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight and 12 PM is noon.
+        hour = (hour % 12) + if is_pm { 12 } else { 0 };
+    }
+
+    let year = decode(year) as u16;
+    let year = match century {
+        Some(_) => decode(century_value) as u16 * 100 + year,
+        // Without a century register, assume that the RTC is in the 21st century.
+        None => 2000 + year,
+    };
+
+    let time = DateTime {
+        year,
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    };
+
+    if !time.is_valid() {
+        log::warn!("rtc: invalid date and time ({time:?})");
+        return None;
+    }
+
+    Some(time)
+}
+
+/// Writes the date and time (in UTC) to the RTC, using the data format the RTC is configured
+/// with.
+pub fn write(time: &DateTime) {
+    if !is_present() {
+        return;
+    }
+
+    let century = century_register();
+    let _guard = CMOS.lock_irq();
+
+    let status_b = cmos_read(RTC_STATUS_B);
+
+    let encode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            binary_to_bcd(value)
+        }
+    };
+
+    let hour = if status_b & STATUS_B_24_HOUR == 0 {
+        let is_pm = time.hour >= 12;
+        let hour = match time.hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+
+        encode(hour) | if is_pm { HOURS_PM } else { 0 }
+    } else {
+        encode(time.hour)
+    };
+
+    // Inhibit the update cycle while the registers are written.
+    cmos_write(RTC_STATUS_B, status_b | STATUS_B_SET);
+
+    cmos_write(RTC_SECONDS, encode(time.second));
+    cmos_write(RTC_MINUTES, encode(time.minute));
+    cmos_write(RTC_HOURS, hour);
+    cmos_write(RTC_DAY_OF_MONTH, encode(time.day));
+    cmos_write(RTC_MONTH, encode(time.month));
+    cmos_write(RTC_YEAR, encode((time.year % 100) as u8));
+
+    if let Some(century) = century {
+        cmos_write(century, encode((time.year / 100) as u8));
+    }
+
+    cmos_write(RTC_STATUS_B, status_b & !STATUS_B_SET);
+}
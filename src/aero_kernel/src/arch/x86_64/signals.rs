@@ -15,11 +15,16 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::signal::{SigProcMask, SignalFlags};
+use aero_syscall::signal::{
+    MContext, SigInfo, SigProcMask, SignalFlags, SignalHandler, StackT, UContext, MINSIGSTKSZ,
+    SIGSEGV, SI_KERNEL, SS_DISABLE, SS_ONSTACK,
+};
 use aero_syscall::SyscallError;
 
+use crate::arch::task::userland_last_address;
 use crate::userland;
 use crate::userland::scheduler;
+use crate::userland::signals::{AltStack, SignalEntry};
 use crate::utils::StackHelper;
 
 use super::interrupts::InterruptStack;
@@ -27,20 +32,32 @@ use super::interrupts::InterruptStack;
 const REDZONE_SIZE: u64 = 128;
 const SYSCALL_INSTRUCTION_SIZE: u64 = 2;
 
+/// The flags in RFLAGS that a signal handler is allowed to change through the user context:
+/// AC, RF, OF, DF, TF, SF, ZF, AF, PF and CF.
+const USER_RFLAGS: u64 = 0x50dd5;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct SignalFrame {
     restart_syscall: u64,
     frame: InterruptStack,
     sigmask: u64,
+    /// Address of the user context passed to the handler, or zero if the handler was not
+    /// installed with `SA_SIGINFO`.
+    ucontext: u64,
+    _pad: u64,
 }
 
+const _: () = assert!(core::mem::size_of::<SignalFrame>() % 16 == 0);
+
 impl SignalFrame {
     fn from_interrupt(frame: &mut InterruptStack, sigmask: u64) -> SignalFrame {
         SignalFrame {
             restart_syscall: u64::MAX,
             frame: *frame,
             sigmask,
+            ucontext: 0,
+            _pad: 0,
         }
     }
 
@@ -58,6 +75,8 @@ impl SignalFrame {
             },
             frame: *frame,
             sigmask,
+            ucontext: 0,
+            _pad: 0,
         };
 
         if !restart {
@@ -66,6 +85,166 @@ impl SignalFrame {
 
         frame
     }
+
+    /// Returns the user context describing the interrupted code, which was executing on the
+    /// stack described by `stack`.
+    fn ucontext(&self, stack: StackT) -> UContext {
+        let regs = &self.frame;
+
+        UContext {
+            uc_stack: stack,
+            uc_mcontext: MContext {
+                r8: regs.scratch.r8,
+                r9: regs.scratch.r9,
+                r10: regs.scratch.r10,
+                r11: regs.scratch.r11,
+                r12: regs.preserved.r12,
+                r13: regs.preserved.r13,
+                r14: regs.preserved.r14,
+                r15: regs.preserved.r15,
+                rdi: regs.scratch.rdi,
+                rsi: regs.scratch.rsi,
+                rbp: regs.preserved.rbp,
+                rbx: regs.preserved.rbx,
+                rdx: regs.scratch.rdx,
+                rax: regs.scratch.rax,
+                rcx: regs.scratch.rcx,
+                rsp: regs.iret.rsp,
+                rip: regs.iret.rip,
+                eflags: regs.iret.rflags,
+                csgsfs: regs.iret.cs | (regs.iret.ss << 48),
+                oldmask: self.sigmask,
+                ..Default::default()
+            },
+            uc_sigmask: self.sigmask,
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the saved registers and signal mask with the ones in the user context `uc`,
+    /// which might have been changed by the signal handler. Returns `false` if the context
+    /// does not describe valid user code.
+    fn restore_ucontext(&mut self, uc: &UContext) -> bool {
+        let mc = &uc.uc_mcontext;
+        let limit = userland_last_address().as_u64();
+
+        if mc.rip >= limit || mc.rsp >= limit {
+            return false;
+        }
+
+        let regs = &mut self.frame;
+
+        regs.scratch.r8 = mc.r8;
+        regs.scratch.r9 = mc.r9;
+        regs.scratch.r10 = mc.r10;
+        regs.scratch.r11 = mc.r11;
+        regs.preserved.r12 = mc.r12;
+        regs.preserved.r13 = mc.r13;
+        regs.preserved.r14 = mc.r14;
+        regs.preserved.r15 = mc.r15;
+        regs.scratch.rdi = mc.rdi;
+        regs.scratch.rsi = mc.rsi;
+        regs.preserved.rbp = mc.rbp;
+        regs.preserved.rbx = mc.rbx;
+        regs.scratch.rdx = mc.rdx;
+        regs.scratch.rax = mc.rax;
+        regs.scratch.rcx = mc.rcx;
+        regs.iret.rsp = mc.rsp;
+        regs.iret.rip = mc.rip;
+        regs.iret.rflags = (regs.iret.rflags & !USER_RFLAGS) | (mc.eflags & USER_RFLAGS);
+
+        self.sigmask = uc.uc_sigmask;
+        true
+    }
+}
+
+/// Sets up the user stack and registers to invoke the handler of the signal described by
+/// `info`.
+fn setup_signal_frame(
+    stack: &mut InterruptStack,
+    info: SigInfo,
+    entry: SignalEntry,
+    mut signal_frame: SignalFrame,
+) {
+    let SignalHandler::Handle(func) = entry.handler() else {
+        unreachable!()
+    };
+
+    let signal = info.si_signo as usize;
+    let task = scheduler::get_scheduler().current_task();
+    let signals = task.signals();
+
+    signals.set_mask(SigProcMask::Block, Some(entry.handler_mask(signal)), None);
+
+    // The handler is executed on the alternate signal stack if requested, unless it is already
+    // in use (i.e. the signal was raised by another handler running on it).
+    let alt_stack = signals.alt_stack().filter(|alt| {
+        entry.flags().contains(SignalFlags::SA_ONSTACK) && !alt.contains(stack.iret.rsp)
+    });
+
+    // We cannot straight away update the stack pointer from the stack
+    // helper, since it will created a reference to a packed field which
+    // is undefined behavior. So we create a copy of the current rsp and
+    // update the actual rsp with the updated rsp.
+    let mut ptr = match alt_stack {
+        Some(alt) => alt.top(),
+
+        // Signal handlers are executed on the same stack, but 128 bytes
+        // known as the red zone is subtracted from the stack before
+        // anything is pushed to the stack. This allows small leaf
+        // functions to use 128 bytes of stack space without reserving
+        // stack space by subtracting from the stack pointer.
+        None => stack.iret.rsp - REDZONE_SIZE,
+    };
+
+    // The stack that the interrupted code was running on.
+    let uc_stack = match signals.alt_stack() {
+        Some(alt) => StackT {
+            ss_sp: alt.base(),
+            ss_flags: if alt.contains(stack.iret.rsp) {
+                SS_ONSTACK
+            } else {
+                0
+            },
+            ss_size: alt.size(),
+        },
+
+        None => StackT {
+            ss_flags: SS_DISABLE,
+            ..Default::default()
+        },
+    };
+
+    let mut writer = StackHelper::new(&mut ptr);
+
+    // With `SA_SIGINFO`, the handler also receives the information of the signal and the user
+    // context of the interrupted code.
+    let (siginfo, ucontext) = if entry.flags().contains(SignalFlags::SA_SIGINFO) {
+        unsafe { writer.write(info) };
+        let siginfo = writer.top();
+
+        unsafe { writer.write(signal_frame.ucontext(uc_stack)) };
+        (siginfo, writer.top())
+    } else {
+        (0, 0)
+    };
+
+    signal_frame.ucontext = ucontext;
+
+    // The size of the signal frame is a multiple of 16, so the stack is aligned as required
+    // by the ABI once the return address has been pushed.
+    writer.align_down();
+
+    unsafe {
+        writer.write(signal_frame);
+        writer.write(entry.sigreturn());
+    }
+
+    stack.iret.rsp = ptr;
+    stack.iret.rip = func as u64;
+    stack.scratch.rdi = signal as u64;
+    stack.scratch.rsi = siginfo;
+    stack.scratch.rdx = ucontext;
 }
 
 pub fn interrupt_check_signals(stack: &mut InterruptStack) {
@@ -75,85 +254,87 @@ pub fn interrupt_check_signals(stack: &mut InterruptStack) {
         return;
     }
 
-    if let Some((signal, entry)) = userland::signals::check_for_signals() {
-        if let aero_syscall::signal::SignalHandler::Handle(func) = entry.handler() {
-            let task = scheduler::get_scheduler().current_task();
-
-            let signals = task.signals();
-            let old_mask = signals.blocked_mask();
-
-            let signal_frame = SignalFrame::from_interrupt(stack, old_mask);
-            signals.set_mask(SigProcMask::Block, Some(1u64 << signal), None);
-
-            // We cannot straight away update the stack pointer from the stack
-            // helper, since it will created a reference to a packed field which
-            // is undefined behavior. So we create a copy of the current rsp and
-            // update the actual rsp with the updated rsp.
-            let mut ptr = stack.iret.rsp;
-            let mut writer = StackHelper::new(&mut ptr);
-
-            // Signal handlers are executed on the same stack, but 128 bytes
-            // known as the red zone is subtracted from the stack before
-            // anything is pushed to the stack. This allows small leaf
-            // functions to use 128 bytes of stack space without reserving
-            // stack space by subtracting from the stack pointer.
-            writer.skip_by(REDZONE_SIZE);
-
-            unsafe {
-                writer.write(signal_frame);
-                writer.write(entry.sigreturn());
-            }
+    if let Some((info, entry)) = userland::signals::check_for_signals() {
+        let task = scheduler::get_scheduler().current_task();
+        let old_mask = task.signals().blocked_mask();
 
-            stack.iret.rsp = ptr;
-            stack.iret.rip = func as u64;
-            stack.scratch.rdi = signal as u64;
-        }
+        let signal_frame = SignalFrame::from_interrupt(stack, old_mask);
+        setup_signal_frame(stack, info, entry, signal_frame);
     }
 }
 
 pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack) {
-    if let Some((signal, entry)) = userland::signals::check_for_signals() {
-        if let aero_syscall::signal::SignalHandler::Handle(func) = entry.handler() {
-            let task = scheduler::get_scheduler().current_task();
-
-            let signals = task.signals();
-            let old_mask = signals.blocked_mask();
-
-            let syscall_rresult = aero_syscall::isize_as_syscall_result(syscall_result);
-            let restart_syscall = syscall_rresult == Err(SyscallError::EINTR)
-                && entry.flags().contains(SignalFlags::SA_RESTART);
-
-            #[cfg(feature = "syslog")]
-            log::warn!("syscall routine signaled: (restart={restart_syscall})");
-
-            let signal_frame =
-                SignalFrame::from_syscall(restart_syscall, syscall_result as _, stack, old_mask);
-            signals.set_mask(SigProcMask::Block, Some(1u64 << signal), None);
-
-            // We cannot straight away update the stack pointer from the stack
-            // helper, since it will created a reference to a packed field which
-            // is undefined behavior. So we create a copy of the current rsp and
-            // update the actual rsp with the updated rsp.
-            let mut ptr = stack.iret.rsp;
-            let mut writer = StackHelper::new(&mut ptr);
-
-            // Signal handlers are executed on the same stack, but 128 bytes
-            // known as the red zone is subtracted from the stack before
-            // anything is pushed to the stack. This allows small leaf
-            // functions to use 128 bytes of stack space without reserving
-            // stack space by subtracting from the stack pointer.
-            writer.skip_by(REDZONE_SIZE);
-
-            unsafe {
-                writer.write(signal_frame);
-                writer.write(entry.sigreturn());
+    if let Some((info, entry)) = userland::signals::check_for_signals() {
+        let task = scheduler::get_scheduler().current_task();
+        let old_mask = task.signals().blocked_mask();
+
+        let syscall_rresult = aero_syscall::isize_as_syscall_result(syscall_result);
+        let restart_syscall = syscall_rresult == Err(SyscallError::EINTR)
+            && entry.flags().contains(SignalFlags::SA_RESTART);
+
+        #[cfg(feature = "syslog")]
+        log::warn!("syscall routine signaled: (restart={restart_syscall})");
+
+        let signal_frame =
+            SignalFrame::from_syscall(restart_syscall, syscall_result as _, stack, old_mask);
+        setup_signal_frame(stack, info, entry, signal_frame);
+    }
+}
+
+/// Sets and/or gets the alternate signal stack of the current thread (see `sigaltstack(2)`),
+/// where `rsp` is the user stack pointer at the time of the system call.
+pub fn sigaltstack(
+    rsp: u64,
+    new: usize, // FIXME: Option<&StackT>
+    old: usize, // FIXME: Option<&mut StackT>
+) -> Result<usize, SyscallError> {
+    let task = scheduler::get_scheduler().current_task();
+    let signals = task.signals();
+    let current = signals.alt_stack();
+
+    let on_stack = current.is_some_and(|alt| alt.contains(rsp));
+
+    if old != 0x00 {
+        let old = crate::utils::validate_mut_ptr(old as *mut StackT)?;
+
+        *old = match current {
+            Some(alt) => StackT {
+                ss_sp: alt.base(),
+                ss_flags: if on_stack { SS_ONSTACK } else { 0 },
+                ss_size: alt.size(),
+            },
+
+            None => StackT {
+                ss_flags: SS_DISABLE,
+                ..Default::default()
+            },
+        };
+    }
+
+    if new != 0x00 {
+        let new = crate::utils::validate_ptr(new as *const StackT)?;
+
+        // The alternate signal stack cannot be changed while it is in use.
+        if on_stack {
+            return Err(SyscallError::EPERM);
+        }
+
+        match new.ss_flags {
+            SS_DISABLE => signals.set_alt_stack(None),
+
+            0 | SS_ONSTACK => {
+                if new.ss_size < MINSIGSTKSZ {
+                    return Err(SyscallError::ENOMEM);
+                }
+
+                signals.set_alt_stack(Some(AltStack::new(new.ss_sp, new.ss_size)));
             }
 
-            stack.iret.rsp = ptr;
-            stack.iret.rip = func as u64;
-            stack.scratch.rdi = signal as u64;
+            _ => return Err(SyscallError::EINVAL),
         }
     }
+
+    Ok(0)
 }
 
 pub fn sigreturn(stack: &mut InterruptStack) {
@@ -162,6 +343,19 @@ pub fn sigreturn(stack: &mut InterruptStack) {
 
     let current_task = scheduler::get_scheduler().current_task();
 
+    // The handler might have changed the user context, which is restored instead of the saved
+    // registers. An invalid context is ignored and the task is sent a `SIGSEGV`. The context is
+    // copied first, so it cannot be changed by another thread after it has been checked.
+    if signal_frame.ucontext != 0 {
+        let valid = crate::utils::validate_ptr(signal_frame.ucontext as *const UContext)
+            .map(|uc| *uc)
+            .is_ok_and(|uc| signal_frame.restore_ucontext(&uc));
+
+        if !valid {
+            current_task.force_signal(SigInfo::new(SIGSEGV, SI_KERNEL));
+        }
+    }
+
     current_task.signals().set_mask(
         aero_syscall::signal::SigProcMask::Set,
         Some(signal_frame.sigmask),
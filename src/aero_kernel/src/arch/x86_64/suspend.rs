@@ -0,0 +1,422 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Entering and leaving the ACPI S3 sleep state (suspend-to-RAM).
+//!
+//! The CPU loses all of its state in S3, so the registers that are not saved by the compiler are
+//! saved before sleeping. On wakeup, the firmware jumps to the waking vector from the FACS in real
+//! mode, which points to the trampoline in `wakeup.asm`. The trampoline is copied to memory that
+//! is reserved below 1MiB at boot, together with page tables that identity map it and map the
+//! higher half of the kernel. It enters long mode and jumps to [`resume_lowlevel`], which
+//! restores the saved state and returns from [`suspend_lowlevel`] a second time.
+//!
+//! ## Notes
+//! * The other CPUs have to be offline. They are reset by the firmware and restarted through the
+//!   same trampoline when they are brought back online (see [`super::hotplug`]).
+//! * 5-level paging is not supported, as the trampoline enables 4-level paging.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use limine::memory_map::EntryType;
+use limine::response::MemoryMapResponse;
+use spin::Once;
+
+use crate::acpi::aml::{self, SleepState};
+use crate::acpi::facs;
+use crate::mem::paging::{self, PhysAddr};
+use crate::mem::AddressSpace;
+use crate::power::SuspendError;
+
+use super::task::{self, FpuState};
+use super::{apic, hotplug, interrupts, io, pmu, time, vdso};
+
+const PAGE_SIZE: u64 = 0x1000;
+/// The trampoline, the level 4 page table, the level 3 page table and the level 2 page table.
+const WAKEUP_SIZE: u64 = 4 * PAGE_SIZE;
+/// The trampoline is entered in real mode, so it has to be below 1MiB.
+const WAKEUP_LIMIT: u64 = 0x100000;
+
+const PRESENT_WRITABLE: u64 = 0b11;
+const HUGE_PAGE: u64 = 1 << 7;
+
+const IA32_PAT: u32 = 0x277;
+
+/// The MSRs that are restored on wakeup.
+const MSRS: [u32; 9] = [
+    io::IA32_EFER,
+    io::IA32_STAR,
+    io::IA32_LSTAR,
+    io::IA32_FMASK,
+    io::IA32_SYSENTER_CS,
+    io::IA32_SYSENTER_ESP,
+    io::IA32_SYSENTER_EIP,
+    IA32_PAT,
+    // Restored after the segment registers are loaded, which clear the bases.
+    io::IA32_KERNEL_GSBASE,
+];
+
+/// Physical address of the memory reserved for the trampoline.
+static WAKEUP_MEMORY: Once<u64> = Once::new();
+
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C, packed)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub(super) struct ProcessorState {
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    xcr0: u64,
+    gdt: DescriptorTablePointer,
+    idt: DescriptorTablePointer,
+    cs: u16,
+    ss: u16,
+    tr: u16,
+    fs_base: u64,
+    gs_base: u64,
+    msrs: [u64; MSRS.len()],
+}
+
+/// The stack pointer of [`suspend_lowlevel`], restored by [`resume_lowlevel`].
+pub(super) static mut SAVED_RSP: u64 = 0;
+pub(super) static mut SAVED_STATE: ProcessorState = ProcessorState {
+    cr0: 0,
+    cr3: 0,
+    cr4: 0,
+    xcr0: 0,
+    gdt: DescriptorTablePointer { limit: 0, base: 0 },
+    idt: DescriptorTablePointer { limit: 0, base: 0 },
+    cs: 0,
+    ss: 0,
+    tr: 0,
+    fs_base: 0,
+    gs_base: 0,
+    msrs: [0; MSRS.len()],
+};
+
+/// Set while the machine is entering the sleep state.
+static SLEEPING: AtomicBool = AtomicBool::new(false);
+
+/// Removes [`WAKEUP_SIZE`] bytes of usable memory below 1MiB from the memory map for the wakeup
+/// trampoline. Called before the frame allocator is initialized.
+pub fn reserve_wakeup_memory(memmap: &mut MemoryMapResponse) {
+    let entry = memmap.entries_mut().iter_mut().find(|entry| {
+        entry.entry_type == EntryType::USABLE
+            && entry.base % PAGE_SIZE == 0
+            && entry.length >= WAKEUP_SIZE
+            && entry.base + WAKEUP_SIZE <= WAKEUP_LIMIT
+    });
+
+    let Some(entry) = entry else {
+        log::warn!("suspend: no memory below 1MiB for the wakeup trampoline");
+        return;
+    };
+
+    let base = entry.base;
+
+    entry.base += WAKEUP_SIZE;
+    entry.length -= WAKEUP_SIZE;
+
+    WAKEUP_MEMORY.call_once(|| base);
+}
+
+/// Returns the physical address of the memory reserved for the wakeup trampoline.
+pub(super) fn wakeup_memory() -> Option<u64> {
+    WAKEUP_MEMORY.get().copied()
+}
+
+/// Returns whether the machine can be suspended to RAM.
+pub fn is_supported() -> bool {
+    WAKEUP_MEMORY.get().is_some()
+        && !paging::level_5_paging_enabled()
+        && facs::is_available()
+        && aml::try_get_subsystem().is_some_and(|aml| aml.supports_state(SleepState::S3))
+}
+
+/// Copies the trampoline to the reserved memory at `base` and fills in its fields and page
+/// tables.
+pub(super) unsafe fn prepare_trampoline(base: u64) {
+    let start = crate::extern_sym!(wakeup_start).cast::<u8>();
+    let end = crate::extern_sym!(wakeup_end).cast::<u8>();
+    let size = end as usize - start as usize;
+
+    let memory = PhysAddr::new(base).as_hhdm_virt();
+    let code = core::slice::from_raw_parts(start, size);
+
+    memory.as_bytes_mut(size).copy_from_slice(code);
+
+    let offset_of = |symbol: *const core::ffi::c_void| symbol as usize - start as usize;
+    let field = |symbol| memory.as_mut_ptr::<u8>().add(offset_of(symbol));
+
+    let gdt = base + offset_of(crate::extern_sym!(wakeup_gdt)) as u64;
+    let long_mode = base + offset_of(crate::extern_sym!(wakeup_long_mode)) as u64;
+    let pml4 = base + PAGE_SIZE;
+    let pdpt = base + 2 * PAGE_SIZE;
+    let pd = base + 3 * PAGE_SIZE;
+
+    // The GDT pointer is preceded by its 16-bit limit.
+    field(crate::extern_sym!(wakeup_gdt_pointer))
+        .add(2)
+        .cast::<u32>()
+        .write_unaligned(gdt as u32);
+    field(crate::extern_sym!(wakeup_long_jump))
+        .cast::<u32>()
+        .write_unaligned(long_mode as u32);
+    field(crate::extern_sym!(wakeup_page_table))
+        .cast::<u32>()
+        .write_unaligned(pml4 as u32);
+    field(crate::extern_sym!(wakeup_resume))
+        .cast::<u64>()
+        .write_unaligned(resume_lowlevel as usize as u64);
+
+    // Identity map the first 2MiB, which contain the trampoline, and the higher half of the
+    // kernel.
+    let table = |address: u64| {
+        let table = PhysAddr::new(address).as_hhdm_virt().as_mut_ptr::<u64>();
+        core::ptr::write_bytes(table, 0, 512);
+        table
+    };
+
+    let pml4_table = table(pml4);
+    let pdpt_table = table(pdpt);
+    let pd_table = table(pd);
+
+    pd_table.write(HUGE_PAGE | PRESENT_WRITABLE);
+    pdpt_table.write(pd | PRESENT_WRITABLE);
+    pml4_table.write(pdpt | PRESENT_WRITABLE);
+
+    let current = AddressSpace::this()
+        .cr3()
+        .start_address()
+        .as_hhdm_virt()
+        .as_ptr::<u64>();
+
+    for i in 256..512 {
+        pml4_table.add(i).write(current.add(i).read());
+    }
+}
+
+pub(super) unsafe fn save_processor_state(state: &mut ProcessorState) {
+    asm!("mov {}, cr0", out(reg) state.cr0, options(nomem, nostack));
+    asm!("mov {}, cr3", out(reg) state.cr3, options(nomem, nostack));
+    asm!("mov {}, cr4", out(reg) state.cr4, options(nomem, nostack));
+    asm!("sgdt [{}]", in(reg) &mut state.gdt, options(nostack));
+    asm!("sidt [{}]", in(reg) &mut state.idt, options(nostack));
+    asm!("mov {:x}, cs", out(reg) state.cs, options(nomem, nostack));
+    asm!("mov {:x}, ss", out(reg) state.ss, options(nomem, nostack));
+    asm!("str {:x}", out(reg) state.tr, options(nomem, nostack));
+
+    state.xcr0 = super::controlregs::read_xcr0().bits();
+    state.fs_base = io::rdmsr(io::IA32_FS_BASE);
+    state.gs_base = io::rdmsr(io::IA32_GS_BASE);
+
+    for (value, msr) in state.msrs.iter_mut().zip(MSRS) {
+        *value = io::rdmsr(msr);
+    }
+}
+
+/// Restores the state saved by [`save_processor_state`]. Called on the stack of
+/// [`suspend_lowlevel`] before the GS base is restored, so it must not access any CPU local
+/// data.
+extern "C" fn restore_processor_state() {
+    unsafe {
+        let state = &*core::ptr::addr_of!(SAVED_STATE);
+
+        asm!("mov cr4, {}", in(reg) state.cr4, options(nostack));
+        asm!("mov cr0, {}", in(reg) state.cr0, options(nostack));
+
+        asm!(
+            "xsetbv",
+            in("ecx") 0,
+            in("eax") state.xcr0 as u32,
+            in("edx") (state.xcr0 >> 32) as u32,
+            options(nomem, nostack)
+        );
+
+        asm!("lgdt [{}]", in(reg) &state.gdt, options(nostack));
+        asm!("lidt [{}]", in(reg) &state.idt, options(nostack));
+
+        // Reload the code segment with a far return.
+        asm!(
+            "push {cs}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            cs = in(reg) state.cs as u64,
+            tmp = lateout(reg) _,
+        );
+
+        asm!(
+            "mov ss, {0:x}",
+            "mov ds, {1:x}",
+            "mov es, {1:x}",
+            "mov fs, {1:x}",
+            "mov gs, {1:x}",
+            in(reg) state.ss,
+            in(reg) 0u16,
+            options(nostack)
+        );
+
+        // The TSS is still marked as busy in the GDT, which makes `ltr` fault.
+        let descriptor = (state.gdt.base + state.tr as u64) as *mut u64;
+        descriptor.write_volatile(descriptor.read_volatile() & !(1 << 41));
+        asm!("ltr {:x}", in(reg) state.tr, options(nostack));
+
+        asm!("mov cr3, {}", in(reg) state.cr3, options(nostack));
+
+        for (&value, msr) in state.msrs.iter().zip(MSRS) {
+            io::wrmsr(msr, value);
+        }
+
+        io::wrmsr(io::IA32_FS_BASE, state.fs_base);
+        io::wrmsr(io::IA32_GS_BASE, state.gs_base);
+    }
+}
+
+/// Saves the callee saved registers and calls `enter`, which puts the machine to sleep. Returns
+/// `0` after waking up (through [`resume_lowlevel`]) or `1` if `enter` returned.
+#[naked]
+pub(super) unsafe extern "C" fn suspend_lowlevel(enter: extern "C" fn()) -> u64 {
+    asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // Align the stack for the call.
+        "sub rsp, 8",
+        "mov [rip + {saved_rsp}], rsp",
+        "call rdi",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "mov eax, 1",
+        "ret",
+        saved_rsp = sym SAVED_RSP,
+        options(noreturn)
+    );
+}
+
+/// Jumped to by the trampoline after waking up, with interrupts disabled and the trampoline's
+/// page tables and GDT loaded.
+#[naked]
+unsafe extern "C" fn resume_lowlevel() -> ! {
+    asm!(
+        "mov rsp, [rip + {saved_rsp}]",
+        "call {restore}",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "xor eax, eax",
+        "ret",
+        saved_rsp = sym SAVED_RSP,
+        restore = sym restore_processor_state,
+        options(noreturn)
+    );
+}
+
+/// Writes back the caches and enters S3. Only returns if the machine did not go to sleep.
+extern "C" fn enter_sleep() {
+    let aml = aml::get_subsystem();
+
+    unsafe { asm!("wbinvd", options(nomem, nostack)) }
+    aml.enter_state(SleepState::S3);
+}
+
+/// Puts the machine to sleep and returns after it woke up. The devices must be suspended.
+pub fn enter_s3() -> Result<(), SuspendError> {
+    if !is_supported() {
+        return Err(SuspendError::NotSupported);
+    }
+
+    if !hotplug::others_parked() {
+        log::warn!("suspend: the other CPUs have to be offline");
+        return Err(SuspendError::Busy);
+    }
+
+    if SLEEPING.swap(true, Ordering::SeqCst) {
+        return Err(SuspendError::Busy);
+    }
+
+    let base = *WAKEUP_MEMORY.get().unwrap();
+    let irqs_enabled = interrupts::is_enabled();
+
+    // The kernel does not use the FPU, so only the state of the current task is live.
+    let mut fpu = FpuState::default();
+
+    let result = unsafe {
+        interrupts::disable_interrupts();
+
+        prepare_trampoline(base);
+        facs::set_waking_vector(base as u32);
+
+        let io_apics = apic::io_apic_save();
+
+        task::xsave(&mut fpu);
+        save_processor_state(&mut *core::ptr::addr_of_mut!(SAVED_STATE));
+        time::save_clocks();
+
+        log::info!("suspend: entering sleep state S3");
+
+        let slept = suspend_lowlevel(enter_sleep) == 0;
+
+        task::xrstor(&fpu);
+
+        if slept {
+            time::restore_clocks();
+            hotplug::mark_reset();
+
+            // The local APIC and the I/O APICs lost their configuration.
+            apic::init_ap();
+            apic::io_apic_restore(&io_apics);
+            vdso::init_cpu();
+            pmu::init_ap();
+
+            aml::get_subsystem().leave_state(SleepState::S3);
+            log::info!("suspend: woke up from sleep state S3");
+
+            Ok(())
+        } else {
+            log::error!("suspend: failed to enter sleep state S3");
+            Err(SuspendError::NotSupported)
+        }
+    };
+
+    facs::set_waking_vector(0);
+    SLEEPING.store(false, Ordering::SeqCst);
+
+    if irqs_enabled {
+        unsafe { interrupts::enable_interrupts() }
+    }
+
+    result
+}
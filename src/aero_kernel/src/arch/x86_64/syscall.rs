@@ -227,8 +227,18 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
     let e = stack.scratch.r8 as usize; // argument 5
     let f = stack.scratch.r9 as usize; // argument 6
 
+    // run the seccomp filters of the process (if any) before the syscall is dispatched:
+    let ip = stack.iret.rip as usize;
+    if let Some(result_usize) =
+        crate::userland::seccomp::check(syscall_number, [a, b, c, d, e, f], ip)
+    {
+        super::signals::syscall_check_signals(result_usize as isize, stack);
+        stack.scratch.rax = result_usize as _;
+        return;
+    }
+
     match syscall_number {
-        // handle arch-specific syscalls (`sigreturn` and `arch_prctl`):
+        // handle arch-specific syscalls (`sigreturn`, `arch_prctl` and `sigaltstack`):
         aero_syscall::prelude::SYS_SIGRETURN => {
             super::signals::sigreturn(stack);
             return;
@@ -242,6 +252,14 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
             return;
         }
 
+        aero_syscall::prelude::SYS_SIGALTSTACK => {
+            let result = super::signals::sigaltstack(stack.iret.rsp, a, b);
+            let result_usize = aero_syscall::syscall_result_as_usize(result);
+
+            stack.scratch.rax = result_usize as _;
+            return;
+        }
+
         aero_syscall::prelude::SYS_EXIT => {}
         _ => unsafe { super::interrupts::enable_interrupts() },
     }
@@ -30,13 +30,10 @@
 //! does not have to worry about clobbering the user mode register values since
 //! they are safely stored on the kernel stack.
 
-use alloc::alloc::alloc_zeroed;
-
 use aero_syscall::{MMapFlags, MMapProt};
 use alloc::vec::Vec;
 use raw_cpuid::CpuId;
 
-use core::alloc::Layout;
 use core::ptr::Unique;
 
 use crate::arch::interrupts::InterruptErrorStack;
@@ -48,7 +45,7 @@ use crate::utils::StackHelper;
 
 use super::{asm_macros, controlregs, io};
 
-use crate::mem::AddressSpace;
+use crate::mem::{AddressSpace, KernelStack};
 
 #[derive(Default)]
 #[repr(C)]
@@ -73,8 +70,22 @@ pub enum AuxvType {
     Phdr = 3,
     PhEnt = 4,
     PhNum = 5,
+    PageSz = 6,
+    Base = 7,
+    Flags = 8,
     Entry = 9,
     Secure = 23,
+    Random = 25,
+    ExecFn = 31,
+    SysInfoEhdr = 33,
+}
+
+/// Returns the random bytes passed to a new program (see `AT_RANDOM`), which the C library uses
+/// to seed the stack protector.
+fn auxv_random() -> [u8; 16] {
+    let mut random = [0u8; 16];
+    crate::random::fill_bytes(&mut random);
+    random
 }
 
 /// Returns the first address outside the user range.
@@ -189,6 +200,11 @@ unsafe extern "C" fn fork_init() {
     )
 }
 
+/// Size of the context switch stack, in pages.
+const SWITCH_STACK_PAGES: usize = 4;
+/// Size of the stack used by kernel tasks, in pages.
+const KERNEL_TASK_STACK_PAGES: usize = 16;
+
 pub struct ArchTask {
     context: Unique<Context>,
 
@@ -196,6 +212,9 @@ pub struct ArchTask {
     context_switch_rsp: VirtAddr,
     user: bool,
 
+    switch_stack: Option<KernelStack>,
+    task_stack: Option<KernelStack>,
+
     fs_base: VirtAddr,
     gs_base: VirtAddr,
 
@@ -208,6 +227,9 @@ impl ArchTask {
             context: Unique::dangling(),
             context_switch_rsp: VirtAddr::zero(),
 
+            switch_stack: None,
+            task_stack: None,
+
             // Since the IDLE task is a special kernel task, we use the kernel's
             // address space here and we also use the kernel privilege level here.
             address_space: AddressSpace::this(),
@@ -221,16 +243,13 @@ impl ArchTask {
     }
 
     pub fn new_kernel(entry_point: VirtAddr, enable_interrupts: bool) -> Self {
-        let switch_stack = Self::alloc_switch_stack().unwrap().as_mut_ptr::<u8>();
-
-        let task_stack = unsafe {
-            let layout = Layout::from_size_align_unchecked(4096 * 16, 0x1000);
-            alloc_zeroed(layout).add(layout.size())
-        };
+        let switch_stack = Self::alloc_switch_stack().unwrap();
+        let task_stack = KernelStack::new(KERNEL_TASK_STACK_PAGES)
+            .expect("new_kernel: failed to allocate the task stack");
 
         let address_space = AddressSpace::this();
 
-        let mut stack_ptr = switch_stack as u64;
+        let mut stack_ptr = switch_stack.top().as_u64();
         let mut stack = StackHelper::new(&mut stack_ptr);
 
         let kframe = unsafe { stack.offset::<InterruptErrorStack>() };
@@ -238,7 +257,7 @@ impl ArchTask {
         kframe.stack.iret.ss = 0x10; // kernel stack segment
         kframe.stack.iret.cs = 0x08; // kernel code segment
         kframe.stack.iret.rip = entry_point.as_u64();
-        kframe.stack.iret.rsp = task_stack as u64;
+        kframe.stack.iret.rsp = task_stack.top().as_u64();
         kframe.stack.iret.rflags = if enable_interrupts { 0x200 } else { 0x00 };
 
         let context = unsafe { stack.offset::<Context>() };
@@ -250,9 +269,12 @@ impl ArchTask {
         Self {
             context: unsafe { Unique::new_unchecked(context) },
             address_space,
-            context_switch_rsp: VirtAddr::new(switch_stack as u64),
+            context_switch_rsp: switch_stack.top(),
             user: false,
 
+            switch_stack: Some(switch_stack),
+            task_stack: Some(task_stack),
+
             fs_base: VirtAddr::zero(),
             gs_base: VirtAddr::zero(),
 
@@ -270,9 +292,9 @@ impl ArchTask {
         assert!(self.user, "cannot clone a kernel task");
 
         let address_space = AddressSpace::this();
-        let switch_stack = Self::alloc_switch_stack()?.as_mut_ptr::<u8>();
+        let switch_stack = Self::alloc_switch_stack()?;
 
-        let mut new_stack_ptr = switch_stack as u64;
+        let mut new_stack_ptr = switch_stack.top().as_u64();
         let mut new_stack = StackHelper::new(&mut new_stack_ptr);
 
         let mut old_stack_ptr = self.context_switch_rsp.as_u64();
@@ -301,10 +323,13 @@ impl ArchTask {
 
         Ok(Self {
             context: unsafe { Unique::new_unchecked(context) },
-            context_switch_rsp: VirtAddr::new(switch_stack as u64),
+            context_switch_rsp: switch_stack.top(),
             address_space,
             user: true,
 
+            switch_stack: Some(switch_stack),
+            task_stack: None,
+
             // The FS and GS bases are inherited from the parent process.
             fs_base: VirtAddr::new(1),
             gs_base: self.gs_base,
@@ -323,12 +348,12 @@ impl ArchTask {
             asm!("mov cr3, {}", in(reg) controlregs::read_cr3_raw(), options(nostack));
         }
 
-        let switch_stack = Self::alloc_switch_stack()?.as_mut_ptr::<u8>();
+        let switch_stack = Self::alloc_switch_stack()?;
 
         let mut old_stack_ptr = self.context_switch_rsp.as_u64();
         let mut old_stack = StackHelper::new(&mut old_stack_ptr);
 
-        let mut new_stack_ptr = switch_stack as u64;
+        let mut new_stack_ptr = switch_stack.top().as_u64();
         let mut new_stack = StackHelper::new(&mut new_stack_ptr);
 
         unsafe {
@@ -350,10 +375,13 @@ impl ArchTask {
 
         Ok(Self {
             context: unsafe { Unique::new_unchecked(context) },
-            context_switch_rsp: VirtAddr::new(switch_stack as u64),
+            context_switch_rsp: switch_stack.top(),
             address_space,
             user: true,
 
+            switch_stack: Some(switch_stack),
+            task_stack: None,
+
             // The FS and GS bases are inherited from the parent process.
             fs_base: self.fs_base,
             gs_base: self.gs_base,
@@ -362,6 +390,31 @@ impl ArchTask {
         })
     }
 
+    /// Creates a copy of the task like [`ArchTask::fork`], which starts on the user stack `stack`
+    /// and with the FS base `tls`, if provided.
+    pub fn clone_task(
+        &self,
+        address_space: AddressSpace,
+        stack: Option<usize>,
+        tls: Option<usize>,
+    ) -> Result<Self, MapToError<Size4KiB>> {
+        let mut this = self.fork(address_space)?;
+
+        if let Some(stack) = stack {
+            let mut stack_ptr = this.context_switch_rsp.as_u64();
+            let mut helper = StackHelper::new(&mut stack_ptr);
+
+            let registers_frame = unsafe { helper.offset::<InterruptErrorStack>() };
+            registers_frame.stack.iret.rsp = stack as _;
+        }
+
+        if let Some(tls) = tls {
+            this.fs_base = VirtAddr::new(tls as u64);
+        }
+
+        Ok(this)
+    }
+
     pub fn exec(
         &mut self,
         vm: &Vm,
@@ -377,6 +430,8 @@ impl ArchTask {
             AddressSpace::new()?
         };
 
+        vm.set_address_space(&address_space);
+
         let loaded_binary = vm
             .load_bin(executable, argv, envv)
             .expect("exec: failed to load ELF");
@@ -394,6 +449,8 @@ impl ArchTask {
             None,
         );
 
+        let vdso = super::vdso::map(vm);
+
         address_space.switch(); // Perform the address space switch
 
         self.context = Unique::dangling();
@@ -432,6 +489,17 @@ impl ArchTask {
         let mut stack_addr = USERLAND_STACK_TOP.as_u64();
         let mut stack = StackHelper::new(&mut stack_addr);
 
+        let execfn = unsafe {
+            stack.write(0u8);
+            stack.write_bytes(executable.absolute_path().as_bytes());
+            stack.top()
+        };
+
+        let random = unsafe {
+            stack.write(auxv_random());
+            stack.top()
+        };
+
         let mut envp = Vec::new();
         let mut argp = Vec::new();
 
@@ -456,15 +524,26 @@ impl ArchTask {
         let p2_header = loaded_binary.elf.header.pt2;
 
         unsafe {
-            let hdr: [(AuxvType, usize); 5] = [
-                (
-                    AuxvType::Phdr,
-                    (p2_header.ph_offset() + loaded_binary.base_addr.as_u64()) as usize,
-                ),
+            let hdr: [(AuxvType, usize); 11] = [
+                (AuxvType::Phdr, loaded_binary.phdr.as_u64() as usize),
                 (AuxvType::PhEnt, p2_header.ph_entry_size() as usize),
                 (AuxvType::PhNum, p2_header.ph_count() as usize),
-                (AuxvType::Entry, p2_header.entry_point() as usize),
+                (AuxvType::PageSz, Size4KiB::SIZE as usize),
+                (
+                    AuxvType::Base,
+                    loaded_binary
+                        .interp_base
+                        .map_or(0, |base| base.as_u64() as usize),
+                ),
+                (AuxvType::Flags, 0),
+                (
+                    AuxvType::Entry,
+                    loaded_binary.program_entry.as_u64() as usize,
+                ),
                 (AuxvType::Secure, 0),
+                (AuxvType::Random, random as usize),
+                (AuxvType::ExecFn, execfn as usize),
+                (AuxvType::SysInfoEhdr, vdso.as_u64() as usize),
             ];
 
             stack.write(0usize); // Make it 16 bytes aligned
@@ -490,6 +569,11 @@ impl ArchTask {
 
         assert_eq!(stack.top() % 16, 0);
 
+        #[cfg(feature = "kasan")]
+        for stack in [&self.switch_stack, &self.task_stack].into_iter().flatten() {
+            stack.unpoison();
+        }
+
         unsafe {
             jump_userland_exec(VirtAddr::new(stack.top()), loaded_binary.entry_point, 0x200);
         }
@@ -497,12 +581,10 @@ impl ArchTask {
         Ok(())
     }
 
-    /// Allocates a new context switch stack for the process and returns the stack
-    /// top address. See the module level documentation for more information.
-    fn alloc_switch_stack() -> Result<VirtAddr, MapToError<Size4KiB>> {
-        let frame = FRAME_ALLOCATOR.alloc_zeroed(4096 * 4).unwrap();
-
-        Ok(frame.as_hhdm_virt() + (4096u64 * 4))
+    /// Allocates a new context switch stack for the process. See the module level
+    /// documentation for more information.
+    fn alloc_switch_stack() -> Result<KernelStack, MapToError<Size4KiB>> {
+        KernelStack::new(SWITCH_STACK_PAGES).ok_or(MapToError::FrameAllocationFailed)
     }
 
     fn unref_pt(&mut self) {
@@ -524,20 +606,30 @@ impl ArchTask {
     }
 
     /// Deallocates the architecture-specific task resources. This function is called
-    /// when the process is turned into a zombie.
-    pub fn dealloc(&mut self) {
-        if self.user {
+    /// when the process is turned into a zombie. The user page table is only released if
+    /// `release_pt` is set, as it might still be used by other tasks sharing the address space.
+    pub fn dealloc(&mut self, release_pt: bool) {
+        if self.user && release_pt {
             self.unref_pt();
         }
 
-        // deallocate the switch stack
-        {
-            let frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(
-                (self.context_switch_rsp - Size4KiB::SIZE).as_hhdm_phys(),
-            );
+        // deallocate the kernel stacks
+        self.switch_stack = None;
+        self.task_stack = None;
+    }
 
-            FRAME_ALLOCATOR.deallocate_frame(frame);
-        }
+    /// Returns `true` if the provided address lies in the guard page of one of the
+    /// task's kernel stacks.
+    pub fn is_stack_guard_page(&self, addr: VirtAddr) -> bool {
+        self.switch_stack
+            .iter()
+            .chain(self.task_stack.iter())
+            .any(|stack| stack.is_guard_page(addr))
+    }
+
+    /// Returns the address space of this task.
+    pub fn address_space(&mut self) -> &mut AddressSpace {
+        &mut self.address_space
     }
 
     /// Returns the saved GS base for this task.
@@ -646,7 +738,7 @@ impl Default for FpuState {
     }
 }
 
-fn xsave(fpu: &mut FpuState) {
+pub(super) fn xsave(fpu: &mut FpuState) {
     // The implicit EDX:EAX register pair specifies a 64-bit instruction mask. The specific state
     // components saved correspond to the bits set in the requested-feature bitmap (RFBM), which is
     // the logical-AND of EDX:EAX and XCR0.
@@ -659,7 +751,7 @@ fn xsave(fpu: &mut FpuState) {
     unsafe { _fxsave64((fpu as *mut FpuState).cast()) }
 }
 
-fn xrstor(fpu: &FpuState) {
+pub(super) fn xrstor(fpu: &FpuState) {
     // unsafe {
     //     asm!("xrstor [{}]", in(reg) fpu.as_ptr(), in("eax") u32::MAX, in("edx") u32::MAX,
     // options(nomem, nostack)); }
@@ -21,16 +21,20 @@
 //!
 //! **Notes**: <https://wiki.osdev.org/Programmable_Interval_Timer>
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
 
 use aero_syscall::TimeSpec;
+use raw_cpuid::CpuId;
 
-use super::apic;
+use super::{apic, rtc};
 
 use crate::arch::interrupts;
 use crate::arch::interrupts::InterruptStack;
 
 use crate::arch::io;
+use crate::power::{self, PowerOps, SuspendError};
 use crate::utils::sync::Mutex;
 
 const PIT_FREQUENCY_HZ: usize = 1000;
@@ -39,18 +43,370 @@ pub const PIT_DIVIDEND: usize = 1193182;
 static UPTIME_RAW: AtomicUsize = AtomicUsize::new(0);
 static UPTIME_SEC: AtomicUsize = AtomicUsize::new(0);
 
+/// Frequency of the TSC in kHz or zero if it has not been calibrated.
+static TSC_FREQUENCY_KHZ: AtomicU64 = AtomicU64::new(0);
+static TSC_START: AtomicU64 = AtomicU64::new(0);
+/// Whether the TSC runs at a constant rate in all ACPI P-, C- and T-states.
+static TSC_INVARIANT: AtomicBool = AtomicBool::new(false);
+
+/// The current clock source (see [`Clocksource`]).
+static CLOCKSOURCE: AtomicU8 = AtomicU8::new(Clocksource::Pit as u8);
+/// Added to the value read from the clock source, so that the monotonic clock does not jump
+/// when the clock source is switched.
+static CLOCK_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Sequence number of the clock source and its offset, odd while they are being updated.
+static CLOCK_SEQ: AtomicU32 = AtomicU32::new(0);
+
+/// The boot time provided by the bootloader, in seconds since the epoch. Only used if the RTC
+/// cannot be read.
 pub static EPOCH: AtomicUsize = AtomicUsize::new(usize::MAX);
-pub static REALTIME_CLOCK: Mutex<aero_syscall::TimeSpec> = Mutex::new(aero_syscall::TimeSpec {
-    tv_sec: 0,
-    tv_nsec: 0,
+
+/// The maximum rate at which an offset is slewed, in parts per million (the same as for
+/// `adjtime(3)`).
+const MAX_SLEW_PPM: i64 = 500;
+/// The maximum frequency adjustment, in parts per billion.
+const MAX_FREQUENCY_PPB: i64 = 500_000;
+
+/// The wall-clock time, which is kept relative to the monotonic clock so that it does not drift
+/// from it. It is advanced on every timer tick, which is also when the NTP adjustments are
+/// applied.
+struct Realtime {
+    /// The wall-clock time at `base_mono`, in nanoseconds since the epoch.
+    base: u64,
+    /// The monotonic time at which `base` was last advanced.
+    base_mono: u64,
+    /// The frequency adjustment, in parts per billion.
+    frequency: i64,
+    /// The offset (in nanoseconds) that is yet to be slewed into the clock.
+    offset: i64,
+}
+
+impl Realtime {
+    /// Advances the wall-clock time to the monotonic time `now`.
+    fn advance(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.base_mono) as i64;
+        let max_slew = elapsed * MAX_SLEW_PPM / 1_000_000;
+        let slew = self.offset.clamp(-max_slew, max_slew);
+
+        self.offset -= slew;
+        self.base = self
+            .base
+            .saturating_add_signed(elapsed + elapsed * self.frequency / 1_000_000_000 + slew);
+        self.base_mono = now;
+    }
+
+    /// Returns the wall-clock time at the monotonic time `now`.
+    fn at(&self, now: u64) -> u64 {
+        self.base + now.saturating_sub(self.base_mono)
+    }
+}
+
+static REALTIME: Mutex<Realtime> = Mutex::new(Realtime {
+    base: 0,
+    base_mono: 0,
+    frequency: 0,
+    offset: 0,
 });
 
 pub fn get_uptime_ticks() -> usize {
     UPTIME_SEC.load(Ordering::SeqCst)
 }
 
+/// A counter that is used to keep track of the monotonic clock.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Clocksource {
+    /// Counts the interrupts of the PIT, so it only has a resolution of one millisecond.
+    Pit = 0,
+    Hpet = 1,
+    Tsc = 2,
+}
+
+impl Clocksource {
+    pub const ALL: [Clocksource; 3] = [Self::Pit, Self::Hpet, Self::Tsc];
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Hpet,
+            2 => Self::Tsc,
+            _ => Self::Pit,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pit => "pit",
+            Self::Hpet => "hpet",
+            Self::Tsc => "tsc",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|source| source.name() == name)
+    }
+
+    /// Returns how suitable the clock source is; the available clock source with the highest
+    /// rating is used by default.
+    fn rating(self) -> u32 {
+        match self {
+            Self::Pit => 50,
+            Self::Hpet => 250,
+            // The rate of a TSC that is not invariant changes with the frequency of the CPU.
+            Self::Tsc if TSC_INVARIANT.load(Ordering::Relaxed) => 300,
+            Self::Tsc => 100,
+        }
+    }
+
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Pit => true,
+            Self::Hpet => crate::acpi::hpet::nanos().is_some(),
+            Self::Tsc => TSC_FREQUENCY_KHZ.load(Ordering::Relaxed) != 0,
+        }
+    }
+
+    /// Reads the clock source in nanoseconds, without the offset of the monotonic clock.
+    fn read(self) -> u64 {
+        match self {
+            Self::Pit => {
+                UPTIME_RAW.load(Ordering::Relaxed) as u64 * (1000000000 / PIT_FREQUENCY_HZ as u64)
+            }
+
+            Self::Hpet => crate::acpi::hpet::nanos().unwrap_or(0),
+
+            Self::Tsc => {
+                // The TSC of this CPU might be slightly behind the one of the CPU it was
+                // calibrated on.
+                let ticks = rdtsc().saturating_sub(TSC_START.load(Ordering::Relaxed));
+                let frequency = TSC_FREQUENCY_KHZ.load(Ordering::Relaxed);
+
+                (ticks as u128 * 1000000 / frequency as u128) as u64
+            }
+        }
+    }
+}
+
+/// Returns the clock source that is currently used for the monotonic clock.
+pub fn current_clocksource() -> Clocksource {
+    Clocksource::from_u8(CLOCKSOURCE.load(Ordering::Relaxed))
+}
+
+/// Returns the clock sources that can be used on this machine.
+pub fn available_clocksources() -> impl Iterator<Item = Clocksource> {
+    Clocksource::ALL
+        .into_iter()
+        .filter(|source| source.is_available())
+}
+
+/// Switches the monotonic clock to `source`. Returns `false` if it is not available.
+pub fn set_clocksource(source: Clocksource) -> bool {
+    if !source.is_available() {
+        return false;
+    }
+
+    // NOTE: The lock also disables interrupts, so the clock cannot be read on this CPU while the
+    // sequence number is odd.
+    let this = REALTIME.lock_irq();
+
+    let now = get_uptime_ns();
+    let offset = now.wrapping_sub(source.read());
+
+    let seq = CLOCK_SEQ.load(Ordering::Relaxed);
+    CLOCK_SEQ.store(seq.wrapping_add(1), Ordering::Relaxed);
+    core::sync::atomic::fence(Ordering::Release);
+
+    CLOCKSOURCE.store(source as u8, Ordering::Relaxed);
+    CLOCK_OFFSET.store(offset, Ordering::Relaxed);
+
+    CLOCK_SEQ.store(seq.wrapping_add(2), Ordering::Release);
+
+    super::vdso::set_clocksource(source, offset);
+    core::mem::drop(this);
+
+    log::info!("time: switched clock source to {}", source.name());
+    true
+}
+
+/// Returns the amount of nanoseconds elapsed since boot, read from the current clock source.
+pub fn get_uptime_ns() -> u64 {
+    loop {
+        let seq = CLOCK_SEQ.load(Ordering::Acquire);
+
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+
+        let source = current_clocksource();
+        let offset = CLOCK_OFFSET.load(Ordering::Relaxed);
+
+        core::sync::atomic::fence(Ordering::Acquire);
+
+        if CLOCK_SEQ.load(Ordering::Relaxed) == seq {
+            return source.read().wrapping_add(offset);
+        }
+    }
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+fn has_invariant_tsc() -> bool {
+    CpuId::new()
+        .get_advanced_power_mgmt_info()
+        .is_some_and(|info| info.has_invariant_tsc())
+}
+
+/// Returns the frequency of the TSC in kHz, measured against the HPET.
+fn tsc_calibrate_hpet() -> Option<u64> {
+    // The TSC is sampled over 10 milliseconds.
+    const SAMPLE_NS: u64 = 10_000_000;
+
+    let initial_hpet = crate::acpi::hpet::nanos()?;
+    let initial_tsc = rdtsc();
+
+    let mut elapsed = 0;
+
+    while elapsed < SAMPLE_NS {
+        elapsed = crate::acpi::hpet::nanos()? - initial_hpet;
+    }
+
+    let tsc_ticks = rdtsc() - initial_tsc;
+    Some((tsc_ticks as u128 * 1000000 / elapsed as u128) as u64)
+}
+
+/// Returns the frequency of the TSC in kHz, measured against the programmable interval timer.
+fn tsc_calibrate_pit() -> u64 {
+    const SAMPLES: u16 = 0x8000;
+
+    set_reload_value(0xffff);
+
+    let initial_pit_tick = get_current_count();
+    let initial_tsc = rdtsc();
+
+    // NOTE: The PIT counts down.
+    while initial_pit_tick.wrapping_sub(get_current_count()) < SAMPLES {}
+
+    let pit_ticks = initial_pit_tick.wrapping_sub(get_current_count()) as u64;
+    let tsc_ticks = rdtsc() - initial_tsc;
+
+    tsc_ticks * PIT_DIVIDEND as u64 / pit_ticks / 1000
+}
+
+/// Calibrates the TSC against the HPET if available, otherwise against the PIT.
+fn tsc_calibrate() {
+    let (frequency, reference) = match tsc_calibrate_hpet() {
+        Some(frequency) => (frequency, "hpet"),
+        None => (tsc_calibrate_pit(), "pit"),
+    };
+
+    if frequency == 0 {
+        log::warn!("tsc: calibration against the {reference} failed");
+        return;
+    }
+
+    TSC_START.store(rdtsc(), Ordering::Relaxed);
+    TSC_FREQUENCY_KHZ.store(frequency, Ordering::Relaxed);
+
+    log::debug!("tsc: calibrated frequency to {frequency}kHz against the {reference}");
+}
+
+/// Returns the start value and the frequency (in kHz) of the TSC or [`None`] if it has not been
+/// calibrated.
+pub fn tsc_clock() -> Option<(u64, u64)> {
+    let frequency = TSC_FREQUENCY_KHZ.load(Ordering::Relaxed);
+
+    if frequency == 0 {
+        return None;
+    }
+
+    Some((TSC_START.load(Ordering::Relaxed), frequency))
+}
+
+/// Returns the wall-clock time in nanoseconds since the epoch.
+pub fn get_realtime_ns() -> u64 {
+    REALTIME.lock_irq().at(get_uptime_ns())
+}
+
 pub fn get_realtime_clock() -> TimeSpec {
-    REALTIME_CLOCK.lock_irq().clone()
+    let realtime = get_realtime_ns();
+
+    TimeSpec {
+        tv_sec: (realtime / 1000000000) as isize,
+        tv_nsec: (realtime % 1000000000) as isize,
+    }
+}
+
+/// Steps the wall-clock time to `realtime` (in nanoseconds since the epoch), discarding any
+/// offset that is being slewed. The new time is also written to the RTC.
+pub fn set_realtime_ns(realtime: u64) {
+    {
+        let mut this = REALTIME.lock_irq();
+
+        this.base = realtime;
+        this.base_mono = get_uptime_ns();
+        this.offset = 0;
+
+        super::vdso::update(this.base, this.base_mono);
+    }
+
+    rtc::write(&rtc::DateTime::from_unix(realtime / 1000000000));
+}
+
+/// Steps the wall-clock time by `delta` nanoseconds.
+pub fn step_realtime(delta: i64) {
+    set_realtime_ns(get_realtime_ns().saturating_add_signed(delta));
+}
+
+/// Starts slewing the wall-clock time by `offset` nanoseconds, replacing any offset that is
+/// still being slewed. Returns the offset that was remaining.
+pub fn slew_realtime(offset: i64) -> i64 {
+    let mut this = REALTIME.lock_irq();
+
+    this.advance(get_uptime_ns());
+    core::mem::replace(&mut this.offset, offset)
+}
+
+/// Returns the offset (in nanoseconds) that is yet to be slewed into the wall-clock time.
+pub fn realtime_offset() -> i64 {
+    REALTIME.lock_irq().offset
+}
+
+/// Sets the frequency adjustment of the wall-clock time, in parts per billion. The adjustment
+/// is clamped to ±500 ppm. Returns the effective adjustment.
+pub fn set_realtime_frequency(frequency: i64) -> i64 {
+    let mut this = REALTIME.lock_irq();
+
+    this.advance(get_uptime_ns());
+    this.frequency = frequency.clamp(-MAX_FREQUENCY_PPB, MAX_FREQUENCY_PPB);
+    this.frequency
+}
+
+/// Returns the frequency adjustment of the wall-clock time, in parts per billion.
+pub fn realtime_frequency() -> i64 {
+    REALTIME.lock_irq().frequency
+}
+
+/// Initializes the wall-clock time from the RTC, falling back to the boot time provided by the
+/// bootloader if it cannot be read.
+fn init_realtime() {
+    let seconds = match rtc::read() {
+        Some(time) => {
+            log::info!("rtc: {time:?}");
+            time.to_unix()
+        }
+
+        None => {
+            log::warn!("rtc: not available, using the boot time from the bootloader");
+            EPOCH.load(Ordering::SeqCst) as u64
+        }
+    };
+
+    let mut this = REALTIME.lock_irq();
+
+    this.base = seconds * 1000000000;
+    this.base_mono = get_uptime_ns();
 }
 
 /// Returns the current amount of PIT ticks.
@@ -86,30 +442,61 @@ pub fn set_frequency(frequency: usize) {
 
 fn pit_irq_handler(_stack: &mut InterruptStack) {
     {
-        let interval = aero_syscall::TimeSpec {
-            tv_sec: 0,
-            tv_nsec: (1000000000 / PIT_FREQUENCY_HZ) as isize,
-        };
-
-        let mut this = REALTIME_CLOCK.lock_irq();
+        let mut this = REALTIME.lock_irq();
 
-        if this.tv_nsec + interval.tv_nsec > 999999999 {
-            let diff = (this.tv_nsec + interval.tv_nsec) - 1000000000;
-
-            this.tv_nsec = diff;
-            this.tv_sec += 1;
-        } else {
-            this.tv_nsec += interval.tv_nsec
-        }
-
-        this.tv_sec += interval.tv_sec;
+        this.advance(get_uptime_ns());
+        super::vdso::update(this.base, this.base_mono);
     }
 
     let value = UPTIME_RAW.fetch_add(1, Ordering::Relaxed); // Increment uptime raw ticks.
 
     if value % PIT_FREQUENCY_HZ == 0 {
         UPTIME_SEC.fetch_add(1, Ordering::Relaxed); // Increment uptime seconds
-        crate::syscall::time::check_timers();
+    }
+}
+
+/// The counters of the TSC and the HPET saved before sleeping.
+static SAVED_TSC: AtomicU64 = AtomicU64::new(0);
+static SAVED_HPET: AtomicU64 = AtomicU64::new(0);
+
+/// Saves the counters of the clock sources, which are reset when the machine sleeps. Called
+/// with interrupts disabled right before entering the sleep state.
+pub fn save_clocks() {
+    SAVED_TSC.store(rdtsc(), Ordering::Relaxed);
+
+    if let Some(counter) = crate::acpi::hpet::save_counter() {
+        SAVED_HPET.store(counter, Ordering::Relaxed);
+    }
+}
+
+/// Restores the counters saved by [`save_clocks`] after waking up, so that the monotonic clock
+/// (which does not count the time spent sleeping) continues where it stopped.
+pub fn restore_clocks() {
+    unsafe {
+        io::wrmsr(
+            io::IA32_TIME_STAMP_COUNTER,
+            SAVED_TSC.load(Ordering::Relaxed),
+        )
+    }
+    crate::acpi::hpet::restore_counter(SAVED_HPET.load(Ordering::Relaxed));
+}
+
+struct Timers;
+
+impl PowerOps for Timers {
+    fn name(&self) -> &'static str {
+        "timers"
+    }
+
+    fn suspend(&self) -> Result<(), SuspendError> {
+        Ok(())
+    }
+
+    fn resume(&self) {
+        set_frequency(PIT_FREQUENCY_HZ);
+
+        // The wall-clock time kept running while the machine was sleeping.
+        init_realtime();
     }
 }
 
@@ -118,7 +505,27 @@ fn pit_irq_handler(_stack: &mut InterruptStack) {
 pub fn init() {
     apic::get_local_apic().timer_calibrate();
 
-    REALTIME_CLOCK.lock().tv_sec = EPOCH.load(Ordering::SeqCst) as _;
+    let invariant = has_invariant_tsc();
+    TSC_INVARIANT.store(invariant, Ordering::Relaxed);
+
+    // A TSC that is not invariant is only worth calibrating if there is no HPET to fall back to.
+    if invariant || crate::acpi::hpet::nanos().is_none() {
+        tsc_calibrate();
+    }
+
+    // Nothing has read the monotonic clock yet, so the clock source can be selected without
+    // adjusting its offset.
+    let source = available_clocksources()
+        .max_by_key(|source| source.rating())
+        .unwrap_or(Clocksource::Pit);
+
+    CLOCKSOURCE.store(source as u8, Ordering::Relaxed);
+    log::info!(
+        "time: using the {} as the clock source (invariant tsc: {invariant})",
+        source.name()
+    );
+
+    init_realtime();
 
     set_frequency(PIT_FREQUENCY_HZ);
 
@@ -126,4 +533,7 @@ pub fn init() {
     interrupts::register_handler(pit_vector, pit_irq_handler);
 
     apic::io_apic_setup_legacy_irq(0, pit_vector, 1); // Set up the IRQ.
+
+    super::vdso::init();
+    power::register(Arc::new(Timers));
 }
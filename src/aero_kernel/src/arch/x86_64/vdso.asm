@@ -0,0 +1,252 @@
+; Copyright (C) 2021-2024 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; The vDSO image (see `vdso.rs`): a small position independent shared object that is mapped
+; into every process. It is copied out of the kernel image at boot, so it must not contain any
+; relocations. The data is referenced relative to the local `ehdr` label, so the references are
+; resolved by the assembler.
+
+bits 64
+
+SYS_GETTIME equ 30
+SYS_GETCPU  equ 116
+
+CLOCK_MONOTONIC equ 1
+
+; Must be kept in sync with `ClockMode` in `vdso.rs`.
+CLOCK_MODE_TSC  equ 1
+CLOCK_MODE_HPET equ 2
+
+; The data page and the HPET page are mapped right before the image.
+VVAR_SIZE equ 0x2000
+HPET_PAGE equ 0x1000
+
+; Offsets of the fields of `VdsoData` in `vdso.rs`.
+VDSO_SEQ            equ 0
+VDSO_CLOCK_MODE     equ 4
+VDSO_TSC_START      equ 8
+VDSO_TSC_KHZ        equ 16
+VDSO_HPET_PERIOD_FS equ 24
+VDSO_HPET_COUNTER   equ 32
+VDSO_REALTIME_NS    equ 40
+VDSO_REALTIME_BASE  equ 48
+VDSO_HAS_RDTSCP     equ 56
+VDSO_CLOCK_OFFSET   equ 64
+
+%define vvar(field) [rel ehdr - VVAR_SIZE + field]
+
+global vdso_start
+global vdso_end
+
+section .rodata
+
+align 16, db 0
+vdso_start:
+ehdr:
+    db 0x7f, "ELF"
+    db 2                                ; ELFCLASS64
+    db 1                                ; ELFDATA2LSB
+    db 1                                ; EV_CURRENT
+    db 0                                ; ELFOSABI_SYSV
+    times 8 db 0
+    dw 3                                ; e_type = ET_DYN
+    dw 62                               ; e_machine = EM_X86_64
+    dd 1                                ; e_version
+    dq 0                                ; e_entry
+    dq phdrs - vdso_start               ; e_phoff
+    dq 0                                ; e_shoff
+    dd 0                                ; e_flags
+    dw 64                               ; e_ehsize
+    dw 56                               ; e_phentsize
+    dw 2                                ; e_phnum
+    dw 64                               ; e_shentsize
+    dw 0                                ; e_shnum
+    dw 0                                ; e_shstrndx
+
+phdrs:
+    ; PT_LOAD: the whole image, linked at address zero.
+    dd 1                                ; p_type
+    dd 5                                ; p_flags = PF_R | PF_X
+    dq 0                                ; p_offset
+    dq 0                                ; p_vaddr
+    dq 0                                ; p_paddr
+    dq vdso_end - vdso_start            ; p_filesz
+    dq vdso_end - vdso_start            ; p_memsz
+    dq 0x1000                           ; p_align
+
+    ; PT_DYNAMIC
+    dd 2                                ; p_type
+    dd 4                                ; p_flags = PF_R
+    dq dynamic - vdso_start             ; p_offset
+    dq dynamic - vdso_start             ; p_vaddr
+    dq dynamic - vdso_start             ; p_paddr
+    dq dynamic_end - dynamic            ; p_filesz
+    dq dynamic_end - dynamic            ; p_memsz
+    dq 8                                ; p_align
+
+align 8, db 0
+dynamic:
+    dq 4, hash - vdso_start             ; DT_HASH
+    dq 5, dynstr - vdso_start           ; DT_STRTAB
+    dq 6, dynsym - vdso_start           ; DT_SYMTAB
+    dq 10, dynstr_end - dynstr          ; DT_STRSZ
+    dq 11, 24                           ; DT_SYMENT
+    dq 14, soname - dynstr              ; DT_SONAME
+    dq 0, 0                             ; DT_NULL
+dynamic_end:
+
+; All of the symbols are in a single bucket.
+align 8, db 0
+hash:
+    dd 1                                ; nbucket
+    dd 3                                ; nchain
+    dd 1                                ; bucket[0]
+    dd 0, 2, 0                          ; chain
+
+%macro symbol 2
+    dd %1 - dynstr                      ; st_name
+    db 0x12                             ; st_info = STB_GLOBAL | STT_FUNC
+    db 0                                ; st_other = STV_DEFAULT
+    dw 1                                ; st_shndx (anything but SHN_UNDEF and SHN_ABS)
+    dq %2 - vdso_start                  ; st_value
+    dq %2 %+ _end - %2                  ; st_size
+%endmacro
+
+align 8, db 0
+dynsym:
+    times 24 db 0                       ; STN_UNDEF
+    symbol clock_gettime_name, vdso_clock_gettime
+    symbol getcpu_name, vdso_getcpu
+
+dynstr:
+    db 0
+clock_gettime_name:
+    db "__vdso_clock_gettime", 0
+getcpu_name:
+    db "__vdso_getcpu", 0
+soname:
+    db "aero-vdso.so.1", 0
+dynstr_end:
+
+; int __vdso_clock_gettime(clockid_t clock, struct timespec *tp)
+align 16, db 0xcc
+vdso_clock_gettime:
+    ; Only CLOCK_REALTIME (0) and CLOCK_MONOTONIC are handled here.
+    cmp edi, CLOCK_MONOTONIC
+    ja .fallback
+
+.retry:
+    ; The data page is being updated if the sequence number is odd.
+    mov r9d, vvar(VDSO_SEQ)
+    test r9d, 1
+    jnz .busy
+
+    mov eax, vvar(VDSO_CLOCK_MODE)
+    cmp eax, CLOCK_MODE_TSC
+    je .tsc
+    cmp eax, CLOCK_MODE_HPET
+    je .hpet
+    jmp .fallback
+
+.tsc:
+    ; ns = (tsc - tsc_start) * 1000000 / tsc_khz
+    rdtsc
+    shl rdx, 32
+    or rax, rdx
+    sub rax, vvar(VDSO_TSC_START)
+    ; The TSC of this CPU might be slightly behind the one of the CPU it was calibrated on.
+    mov ecx, 0
+    cmovb rax, rcx
+    mov rcx, 1000000
+    mul rcx
+    div qword vvar(VDSO_TSC_KHZ)
+    jmp .monotonic
+
+.hpet:
+    ; ns = counter * period_fs / 1000000
+    lea rdx, [rel ehdr - VVAR_SIZE + HPET_PAGE]
+    add rdx, vvar(VDSO_HPET_COUNTER)
+    mov rax, [rdx]
+    mul qword vvar(VDSO_HPET_PERIOD_FS)
+    mov rcx, 1000000
+    div rcx
+
+.monotonic:
+    ; The offset keeps the monotonic clock from jumping when the clock source is switched.
+    add rax, vvar(VDSO_CLOCK_OFFSET)
+
+    test edi, edi
+    jnz .check
+
+    ; The realtime clock is advanced by the time elapsed since it was last updated.
+    sub rax, vvar(VDSO_REALTIME_BASE)
+    add rax, vvar(VDSO_REALTIME_NS)
+
+.check:
+    ; Start over if the data page was updated while it was being read.
+    cmp r9d, vvar(VDSO_SEQ)
+    jne .retry
+
+    xor edx, edx
+    mov rcx, 1000000000
+    div rcx
+    mov [rsi], rax                      ; tv_sec
+    mov [rsi + 8], rdx                  ; tv_nsec
+
+    xor eax, eax
+    ret
+
+.busy:
+    pause
+    jmp .retry
+
+.fallback:
+    mov eax, SYS_GETTIME
+    syscall
+    ret
+vdso_clock_gettime_end:
+
+; int __vdso_getcpu(unsigned *cpu, unsigned *node, void *unused)
+align 16, db 0xcc
+vdso_getcpu:
+    cmp dword vvar(VDSO_HAS_RDTSCP), 0
+    je .fallback
+
+    ; The kernel stores the ID of each CPU in its IA32_TSC_AUX MSR, which is returned in ECX.
+    rdtscp
+
+    test rdi, rdi
+    jz .node
+    mov [rdi], ecx
+
+.node:
+    ; There is only a single NUMA node.
+    test rsi, rsi
+    jz .done
+    mov dword [rsi], 0
+
+.done:
+    xor eax, eax
+    ret
+
+.fallback:
+    mov eax, SYS_GETCPU
+    syscall
+    ret
+vdso_getcpu_end:
+
+vdso_end:
@@ -0,0 +1,313 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The vDSO (virtual dynamic shared object) is a small shared library that is mapped into every
+//! process. It implements `__vdso_clock_gettime` and `__vdso_getcpu` without entering the kernel,
+//! which saves a system call for the most frequently used time reads. The image is assembled from
+//! `vdso.asm` and its address is passed to the program in `AT_SYSINFO_EHDR`.
+//!
+//! The image is preceded by two read-only pages:
+//!
+//! * The data page ([`VdsoData`]), which is updated on every timer tick.
+//! * The registers of the HPET, if it is available.
+//!
+//! The clocks are read using the same clock source and offset as the kernel (see
+//! [`super::time::get_uptime_ns`]), which are updated when the clock source is switched. If it
+//! cannot be read from user space (i.e. the PIT is used), the vDSO falls back to the system call.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use aero_syscall::MMapProt;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use raw_cpuid::CpuId;
+use spin::Once;
+
+use crate::acpi::hpet;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::inode::{DirEntry, INodeInterface, MMapPage};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::*;
+use crate::userland::vm::Vm;
+
+use super::time::Clocksource;
+use super::{io, time, tls};
+
+/// Address of the data page, which is followed by the HPET page and the image. It is placed
+/// between the executable and the program interpreter.
+const VVAR_ADDRESS: VirtAddr = VirtAddr::new(0x6ffe_0000_0000);
+const VDSO_ADDRESS: VirtAddr = VirtAddr::new(0x6ffe_0000_2000);
+
+#[repr(u32)]
+#[derive(Copy, Clone)]
+enum ClockMode {
+    /// The clock source cannot be read from user space.
+    None = 0,
+    Tsc = 1,
+    Hpet = 2,
+}
+
+impl From<Clocksource> for ClockMode {
+    fn from(source: Clocksource) -> Self {
+        match source {
+            Clocksource::Pit => Self::None,
+            Clocksource::Hpet => Self::Hpet,
+            Clocksource::Tsc => Self::Tsc,
+        }
+    }
+}
+
+/// The data page of the vDSO. The layout must be kept in sync with `vdso.asm`.
+#[repr(C)]
+struct VdsoData {
+    /// Sequence number, odd while the page is being updated.
+    seq: AtomicU32,
+    /// The [`ClockMode`] of the current clock source.
+    clock_mode: AtomicU32,
+    tsc_start: u64,
+    tsc_khz: u64,
+    hpet_period_fs: u64,
+    /// Offset of the main counter register in the HPET page.
+    hpet_counter: u64,
+    /// The realtime clock in nanoseconds, as of the last timer tick.
+    realtime_ns: AtomicU64,
+    /// The monotonic clock at the time `realtime_ns` was updated.
+    realtime_base: AtomicU64,
+    /// Whether the ID of the CPU can be read using RDTSCP (see [`init_cpu`]).
+    has_rdtscp: u32,
+    /// Added to the value read from the clock source (see [`time::get_uptime_ns`]).
+    clock_offset: AtomicU64,
+}
+
+const _: () = assert!(core::mem::offset_of!(VdsoData, realtime_base) == 48);
+const _: () = assert!(core::mem::offset_of!(VdsoData, has_rdtscp) == 56);
+const _: () = assert!(core::mem::offset_of!(VdsoData, clock_offset) == 64);
+
+struct Vdso {
+    data: PhysFrame,
+    hpet: Option<PhysFrame>,
+    image: Vec<PhysFrame>,
+    file: DirCacheItem,
+}
+
+impl Vdso {
+    fn data(&self) -> &'static VdsoData {
+        unsafe {
+            &*self
+                .data
+                .start_address()
+                .as_hhdm_virt()
+                .as_ptr::<VdsoData>()
+        }
+    }
+
+    /// Returns the page at `offset` in the vDSO area.
+    fn page(&self, offset: usize) -> Option<PhysFrame> {
+        match offset / Size4KiB::SIZE as usize {
+            0 => Some(self.data),
+            1 => self.hpet,
+            index => self.image.get(index - 2).copied(),
+        }
+    }
+}
+
+/// Provides the pages of the vDSO area to the page fault handler.
+struct VdsoINode;
+
+impl INodeInterface for VdsoINode {
+    fn mmap_v2(&self, offset: usize) -> fs::Result<MMapPage> {
+        VDSO.get()
+            .and_then(|vdso| vdso.page(offset))
+            .map(MMapPage::Direct)
+            .ok_or(FileSystemError::NotSupported)
+    }
+}
+
+static VDSO: Once<Vdso> = Once::new();
+
+fn has_rdtscp() -> bool {
+    CpuId::new()
+        .get_extended_processor_and_feature_identifiers()
+        .is_some_and(|info| info.has_rdtscp())
+}
+
+/// Allocates a zeroed page that is never deallocated.
+fn alloc_page() -> PhysFrame {
+    let frame = FRAME_ALLOCATOR
+        .alloc_zeroed(Size4KiB::SIZE as usize)
+        .expect("vdso: failed to allocate a page");
+
+    // Hold an extra reference to the frame, so it is not deallocated when it is unmapped from a
+    // process.
+    frame.as_vm_frame().unwrap().inc_ref_count();
+    PhysFrame::containing_address(frame)
+}
+
+/// Copies the vDSO image out of the kernel and initializes the data page. Must be called after
+/// the clock source has been set up.
+pub fn init() {
+    let start = crate::extern_sym!(vdso_start).cast::<u8>();
+    let end = crate::extern_sym!(vdso_end).cast::<u8>();
+
+    // SAFETY: The image is in the read-only data of the kernel.
+    let image = unsafe { core::slice::from_raw_parts(start, end.addr() - start.addr()) };
+
+    let image = image
+        .chunks(Size4KiB::SIZE as usize)
+        .map(|chunk| {
+            let frame = alloc_page();
+
+            frame.as_slice_mut::<u8>()[..chunk.len()].copy_from_slice(chunk);
+            frame
+        })
+        .collect::<Vec<_>>();
+
+    let mut data = VdsoData {
+        seq: AtomicU32::new(0),
+        clock_mode: AtomicU32::new(ClockMode::from(time::current_clocksource()) as u32),
+        tsc_start: 0,
+        tsc_khz: 0,
+        hpet_period_fs: 0,
+        hpet_counter: 0,
+        realtime_ns: AtomicU64::new(0),
+        realtime_base: AtomicU64::new(0),
+        has_rdtscp: has_rdtscp() as u32,
+        clock_offset: AtomicU64::new(0),
+    };
+
+    let mut hpet = None;
+
+    // NOTE: Both the HPET and the TSC are set up if available, so the clock source can be
+    // switched at runtime.
+    if let Some((counter, period_fs)) = hpet::main_counter() {
+        let frame = PhysFrame::containing_address(counter);
+
+        // Make sure the frame is never handed out by the frame allocator, in case the HPET
+        // registers are covered by the frame array.
+        if let Some(vm_frame) = counter.as_vm_frame() {
+            vm_frame.inc_ref_count();
+        }
+
+        data.hpet_period_fs = period_fs;
+        data.hpet_counter = counter - frame.start_address();
+
+        hpet = Some(frame);
+    }
+
+    if let Some((tsc_start, tsc_khz)) = time::tsc_clock() {
+        data.tsc_start = tsc_start;
+        data.tsc_khz = tsc_khz;
+    }
+
+    let data_frame = alloc_page();
+
+    // SAFETY: The page has just been allocated and is large enough to hold the data.
+    unsafe {
+        data_frame
+            .start_address()
+            .as_hhdm_virt()
+            .as_mut_ptr::<VdsoData>()
+            .write(data)
+    }
+
+    VDSO.call_once(|| Vdso {
+        data: data_frame,
+        hpet,
+        image,
+        file: DirEntry::from_inode(Arc::new(VdsoINode), String::from("<vdso>")),
+    });
+
+    log::debug!("vdso: initialized");
+}
+
+/// Stores the ID of the current CPU in its `IA32_TSC_AUX` MSR, which is read by `__vdso_getcpu`
+/// using RDTSCP.
+pub fn init_cpu() {
+    if has_rdtscp() {
+        unsafe { io::wrmsr(io::IA32_TSC_AUX, tls::get_cpuid() as u64) }
+    }
+}
+
+/// Publishes the realtime clock, which was `realtime` nanoseconds since the epoch at the
+/// monotonic time `base`. Called on every timer tick.
+pub fn update(realtime: u64, base: u64) {
+    let Some(vdso) = VDSO.get() else {
+        return;
+    };
+
+    let data = vdso.data();
+    let seq = data.seq.load(Ordering::Relaxed);
+
+    data.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+    core::sync::atomic::fence(Ordering::Release);
+
+    data.realtime_ns.store(realtime, Ordering::Relaxed);
+    data.realtime_base.store(base, Ordering::Relaxed);
+
+    data.seq.store(seq.wrapping_add(2), Ordering::Release);
+}
+
+/// Publishes that the monotonic clock is read from `source`, with `offset` nanoseconds added.
+/// Must be serialized with [`update`].
+pub fn set_clocksource(source: Clocksource, offset: u64) {
+    let Some(vdso) = VDSO.get() else {
+        return;
+    };
+
+    let data = vdso.data();
+    let seq = data.seq.load(Ordering::Relaxed);
+
+    data.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+    core::sync::atomic::fence(Ordering::Release);
+
+    data.clock_mode
+        .store(ClockMode::from(source) as u32, Ordering::Relaxed);
+    data.clock_offset.store(offset, Ordering::Relaxed);
+
+    data.seq.store(seq.wrapping_add(2), Ordering::Release);
+}
+
+/// Maps the vDSO into `vm`. Returns the address of the image.
+pub fn map(vm: &Vm) -> VirtAddr {
+    let vdso = VDSO.get().expect("vdso: not initialized");
+    let page_size = Size4KiB::SIZE as usize;
+
+    vm.map_special(VVAR_ADDRESS, page_size, MMapProt::PROT_READ, 0, &vdso.file)
+        .expect("vdso: failed to map the data page");
+
+    if vdso.hpet.is_some() {
+        vm.map_special(
+            VVAR_ADDRESS + Size4KiB::SIZE,
+            page_size,
+            MMapProt::PROT_READ,
+            page_size,
+            &vdso.file,
+        )
+        .expect("vdso: failed to map the HPET page");
+    }
+
+    vm.map_special(
+        VDSO_ADDRESS,
+        vdso.image.len() * page_size,
+        MMapProt::PROT_READ | MMapProt::PROT_EXEC,
+        page_size * 2,
+        &vdso.file,
+    )
+    .expect("vdso: failed to map the image");
+
+    VDSO_ADDRESS
+}
@@ -0,0 +1,118 @@
+; Copyright (C) 2021-2024 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; The ACPI S3 wakeup trampoline (see `suspend.rs`). The firmware jumps to the waking vector in
+; real mode with `CS = vector >> 4` and `IP = 0`. The trampoline is copied below 1MiB before
+; sleeping and the fields at the end are filled in by the kernel, so the code only has to be
+; position independent within its segment. It switches straight to long mode using the page
+; tables set up by the kernel, which identity map the trampoline and map the higher half, and
+; jumps to the kernel's resume function.
+
+global wakeup_start
+global wakeup_end
+global wakeup_gdt
+global wakeup_gdt_pointer
+global wakeup_long_jump
+global wakeup_long_mode
+global wakeup_page_table
+global wakeup_resume
+
+EFER            equ 0xc0000080
+EFER_LME        equ 1 << 8
+EFER_NXE        equ 1 << 11
+
+CR0_PE          equ 1 << 0
+CR0_PG          equ 1 << 31
+CR4_PAE         equ 1 << 5
+
+CODE_SELECTOR   equ 0x08
+DATA_SELECTOR   equ 0x10
+
+section .rodata
+
+bits 16
+
+wakeup_start:
+    cli
+    cld
+
+    mov ax, cs
+    mov ds, ax
+
+    o32 lgdt [wakeup_gdt_pointer - wakeup_start]
+
+    mov eax, cr4
+    or eax, CR4_PAE
+    mov cr4, eax
+
+    mov eax, [wakeup_page_table - wakeup_start]
+    mov cr3, eax
+
+    ; The kernel's page tables use the no-execute bit.
+    mov ecx, EFER
+    rdmsr
+    or eax, EFER_LME | EFER_NXE
+    wrmsr
+
+    mov eax, cr0
+    or eax, CR0_PE | CR0_PG
+    mov cr0, eax
+
+    jmp dword far [wakeup_long_jump - wakeup_start]
+
+bits 64
+
+wakeup_long_mode:
+    mov ax, DATA_SELECTOR
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    mov fs, ax
+    mov gs, ax
+
+    mov rax, [rel wakeup_resume]
+    jmp rax
+
+align 8
+
+; Null descriptor, 64-bit code segment and data segment.
+wakeup_gdt:
+    dq 0
+    dq 0x00209a0000000000
+    dq 0x0000920000000000
+
+; Filled in by the kernel: the physical address of `wakeup_gdt`.
+wakeup_gdt_pointer:
+    dw 3 * 8 - 1
+    dd 0
+
+; Filled in by the kernel: the physical address of `wakeup_long_mode`.
+wakeup_long_jump:
+    dd 0
+    dw CODE_SELECTOR
+
+; Filled in by the kernel: the physical address of the level 4 page table.
+wakeup_page_table:
+    dd 0
+
+align 8
+
+; Filled in by the kernel: the address of the resume function.
+wakeup_resume:
+    dq 0
+
+wakeup_end:
@@ -15,15 +15,61 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::fmt;
 use core::num::ParseIntError;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use spin::Once;
 
 use limine::file::File;
 
 use crate::rendy;
+use crate::utils::uuid::Uuid;
 
 static RAW_CMDLINE_STR: Once<&'static str> = Once::new();
+static ROOT: Once<RootSpec> = Once::new();
+static SELFTEST: AtomicBool = AtomicBool::new(false);
+static RAMDISK_COUNT: Once<usize> = Once::new();
+static RAMDISK_SIZE: Once<usize> = Once::new();
+
+/// The root filesystem, given with the `root=` option. Identifying the filesystem by UUID or
+/// label keeps the boot working when the order in which the disks are found changes.
+#[derive(Debug, PartialEq)]
+pub enum RootSpec {
+    /// `root=UUID=...`: the UUID of the filesystem.
+    Uuid(Uuid),
+    /// `root=PARTUUID=...`: the unique GUID of the GPT partition.
+    PartUuid(Uuid),
+    /// `root=LABEL=...`: the label of the filesystem.
+    Label(&'static str),
+    /// `root=/dev/NAME` or `root=NAME`: the name of the block device.
+    Device(&'static str),
+}
+
+impl RootSpec {
+    fn parse(value: &'static str) -> Option<Self> {
+        if let Some(uuid) = value.strip_prefix("UUID=") {
+            uuid.parse().ok().map(Self::Uuid)
+        } else if let Some(uuid) = value.strip_prefix("PARTUUID=") {
+            uuid.parse().ok().map(Self::PartUuid)
+        } else if let Some(label) = value.strip_prefix("LABEL=") {
+            Some(Self::Label(label))
+        } else {
+            Some(Self::Device(value.trim_start_matches("/dev/")))
+        }
+    }
+}
+
+impl fmt::Display for RootSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uuid(uuid) => write!(f, "UUID={uuid}"),
+            Self::PartUuid(uuid) => write!(f, "PARTUUID={uuid}"),
+            Self::Label(label) => write!(f, "LABEL={label}"),
+            Self::Device(name) => write!(f, "/dev/{name}"),
+        }
+    }
+}
 
 pub struct CommandLine {
     /// If set, then the kernel logs will be redirected onto the framebuffer until
@@ -33,6 +79,16 @@ pub struct CommandLine {
     pub rendy_debug: bool,
     pub term_background: Option<&'static [u8]>,
     pub theme_background: u32,
+    /// If set, the physical memory map is mapped at a random address. Disabled with the
+    /// `nokaslr` option.
+    ///
+    /// By default, KASLR is enabled.
+    pub kaslr: bool,
+    /// Size of the memory reserved for the crash kernel, set with the `crashkernel=SIZE`
+    /// option (see [`crate::kexec`]). The size accepts the `K`, `M` and `G` suffixes.
+    ///
+    /// By default, no memory is reserved.
+    pub crashkernel: Option<usize>,
 }
 
 impl CommandLine {
@@ -41,6 +97,8 @@ impl CommandLine {
             rendy_debug: false,
             term_background: None,
             theme_background: rendy::DEFAULT_THEME_BACKGROUND,
+            kaslr: true,
+            crashkernel: None,
         }
     }
 }
@@ -71,6 +129,17 @@ fn parse_number(mut string: &str) -> Result<usize, ParseIntError> {
     }
 }
 
+fn parse_size(string: &str) -> Result<usize, ParseIntError> {
+    let (number, shift) = match string.as_bytes().last() {
+        Some(b'K' | b'k') => (&string[..string.len() - 1], 10),
+        Some(b'M' | b'm') => (&string[..string.len() - 1], 20),
+        Some(b'G' | b'g') => (&string[..string.len() - 1], 30),
+        _ => (string, 0),
+    };
+
+    parse_number(number).map(|size| size << shift)
+}
+
 pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
     RAW_CMDLINE_STR.call_once(|| cmdline);
 
@@ -83,6 +152,8 @@ pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
     for argument in cmdline.split_whitespace() {
         match argument {
             "rendy-dbg" => result.rendy_debug = true,
+            "nokaslr" => result.kaslr = false,
+            "--test" => SELFTEST.store(true, Ordering::Relaxed),
 
             _ => {
                 let mut pair = argument.splitn(2, '=');
@@ -110,6 +181,35 @@ pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
                                 result.theme_background = theme_bg as u32;
                             }
 
+                            "crashkernel" => match parse_size(value) {
+                                Ok(size) => result.crashkernel = Some(size),
+                                Err(e) => log::warn!("crashkernel: invalid size {value}: {e}"),
+                            },
+
+                            "brd.rd_nr" => match parse_number(value) {
+                                Ok(count) => {
+                                    RAMDISK_COUNT.call_once(|| count);
+                                }
+
+                                Err(e) => log::warn!("brd.rd_nr: invalid count {value}: {e}"),
+                            },
+
+                            "brd.rd_size" => match parse_size(value) {
+                                Ok(size) => {
+                                    RAMDISK_SIZE.call_once(|| size);
+                                }
+
+                                Err(e) => log::warn!("brd.rd_size: invalid size {value}: {e}"),
+                            },
+
+                            "root" => match RootSpec::parse(value) {
+                                Some(root) => {
+                                    ROOT.call_once(|| root);
+                                }
+
+                                None => log::warn!("root: invalid operand {value}"),
+                            },
+
                             _ => bail(argument),
                         }
                     }
@@ -133,8 +233,34 @@ pub fn get_raw_cmdline() -> &'static str {
         .expect("get_raw_cmdline: called before cmdline was parsed")
 }
 
+/// Returns the root filesystem given on the kernel command line, if any. Otherwise, the first
+/// filesystem found is used as the root filesystem.
+pub fn root() -> Option<&'static RootSpec> {
+    ROOT.get()
+}
+
+/// Returns whether the kernel self tests (see [`crate::selftest`]) should be run at boot, which
+/// is requested with the `--test` option.
+pub fn selftest() -> bool {
+    SELFTEST.load(Ordering::Relaxed)
+}
+
+/// Returns the number of RAM disks (see [`crate::drivers::block::brd`]) given with the
+/// `brd.rd_nr=N` option.
+pub fn ramdisk_count() -> Option<usize> {
+    RAMDISK_COUNT.get().copied()
+}
+
+/// Returns the size of the RAM disks given with the `brd.rd_size=SIZE` option. The size accepts
+/// the `K`, `M` and `G` suffixes.
+pub fn ramdisk_size() -> Option<usize> {
+    RAMDISK_SIZE.get().copied()
+}
+
 #[cfg(test)]
 mod tests {
+    use alloc::string::ToString;
+
     use super::*;
 
     #[test]
@@ -147,4 +273,45 @@ mod tests {
         assert!(parse_number("0xinvalid").is_err());
         assert!(parse_number("0oinvalid").is_err());
     }
+
+    #[test]
+    fn size_parser_test() {
+        assert_eq!(parse_size("256M").unwrap(), 256 << 20);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size("0x1000").unwrap(), 0x1000);
+
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn root_parser_test() {
+        let uuid = Uuid([
+            0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x12, 0xd3, 0xa4, 0x56, 0x42, 0x66, 0x14, 0x17,
+            0x40, 0x00,
+        ]);
+
+        assert_eq!(
+            RootSpec::parse("UUID=123e4567-e89b-12d3-a456-426614174000"),
+            Some(RootSpec::Uuid(uuid))
+        );
+        assert_eq!(
+            RootSpec::parse("PARTUUID=123E4567-E89B-12D3-A456-426614174000"),
+            Some(RootSpec::PartUuid(uuid))
+        );
+        assert_eq!(RootSpec::parse("LABEL=aero"), Some(RootSpec::Label("aero")));
+        assert_eq!(
+            RootSpec::parse("/dev/nvme0n1p1"),
+            Some(RootSpec::Device("nvme0n1p1"))
+        );
+
+        assert_eq!(
+            RootSpec::parse("UUID=123e4567e89b12d3a456426614174000"),
+            None
+        );
+        assert_eq!(
+            RootSpec::parse("UUID=123e4567-e89b-12d3-a456-42661417400"),
+            None
+        );
+        assert_eq!(uuid.to_string(), "123e4567-e89b-12d3-a456-426614174000");
+    }
 }
@@ -15,14 +15,20 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::mem::MaybeUninit;
+
 use alloc::sync::Arc;
 
 use alloc::vec::Vec;
 use bit_field::BitField;
 use spin::Once;
 
+use crate::arch::time;
+use crate::fs::block::{self, AtaProtocol, AtaTaskfile, BlockDevice, BlockDeviceInterface};
 use crate::mem::paging::*;
+use crate::mem::slab::{self, SlabCache};
 use crate::mem::AddressSpace;
+use crate::power::{self, PowerOps, SuspendError};
 
 use crate::utils::sync::Mutex;
 use crate::utils::VolatileCell;
@@ -30,6 +36,9 @@ use crate::utils::VolatileCell;
 use crate::drivers::pci::*;
 
 static DRIVER: Once<Arc<AhciDriver>> = Once::new();
+/// DMA requests are allocated for every block I/O operation (also used by the IDE driver).
+static DMA_REQUEST_SLAB: SlabCache =
+    SlabCache::new("dma_request", slab::arc_layout::<DmaRequest>());
 
 bitflags::bitflags! {
     struct HbaEnclosureCtrl: u32 {
@@ -69,6 +78,7 @@ bitflags::bitflags! {
 }
 
 bitflags::bitflags! {
+    #[derive(Copy, Clone)]
     struct HbaCapabilities: u32 {
         const SXS           = 1 << 5;  // Supports External SATA
         const EMS           = 1 << 6;  // Enclosure Management Supported
@@ -196,8 +206,53 @@ impl HbaCmdHeaderFlags {
     }
 }
 
+/// Size of each DMA buffer of a request; a single PRDT entry describes one.
+const DMA_BUFFER_SIZE: usize = 0x2000;
+/// Number of PRDT entries in each command table.
+const PRDT_ENTRIES: usize = 8;
+
+/// Time given to a spun up port to establish the link with its device.
+const LINK_TIMEOUT: u64 = 10_000_000; // 10ms
+/// Cold-attached devices are only powered on when the port is spun up, so
+/// the link takes longer to come up.
+const COLD_LINK_TIMEOUT: u64 = 1_000_000_000; // 1s
+/// Time given to the device to spin up its platters and clear BSY.
+const READY_TIMEOUT: u64 = 10_000_000_000; // 10s
+
+/// Time given to the device to complete a command.
+const COMMAND_TIMEOUT: u64 = 5_000_000_000; // 5s
+
+/// Offset of the last received D2H register FIS in the received FIS area.
+const D2H_FIS_OFFSET: usize = 0x40;
+
+const ATA_DEV_BUSY: u32 = 0x80;
+const ATA_DEV_DRQ: u32 = 0x08;
+
+/// Busy-waits until `condition` holds or `timeout` nanoseconds have passed, returning
+/// whether the condition was met. The driver is started with interrupts disabled, so we
+/// cannot sleep here.
+fn wait_for(timeout: u64, mut condition: impl FnMut() -> bool) -> bool {
+    let end = time::get_uptime_ns() + timeout;
+
+    while !condition() {
+        if time::get_uptime_ns() >= end {
+            return false;
+        }
+
+        core::hint::spin_loop();
+    }
+
+    true
+}
+
 enum DmaCommand {
     Read,
+    Write,
+    Identify,
+    /// Flushes the write cache of the device, with `FLUSH CACHE EXT` if `ext` is set.
+    Flush {
+        ext: bool,
+    },
 }
 
 pub struct DmaBuffer {
@@ -205,11 +260,14 @@ pub struct DmaBuffer {
     start: PhysAddr,
     /// The data size of the DMA buffer.
     data_size: usize,
+    /// The buddy order the DMA buffer was allocated with.
+    order: usize,
+    sector_size: usize,
 }
 
 impl DmaBuffer {
     pub fn sectors(&self) -> usize {
-        self.data_size.div_ceil(512)
+        self.data_size.div_ceil(self.sector_size)
     }
 
     pub fn start(&self) -> PhysAddr {
@@ -224,49 +282,121 @@ impl DmaBuffer {
 pub struct DmaRequest {
     sector: usize,
     pub count: usize,
+    sector_size: usize,
     buffer: Vec<DmaBuffer>,
     command: DmaCommand,
+    /// Time given to the device to complete each command of the request, in nanoseconds.
+    timeout: u64,
 }
 
 impl DmaRequest {
-    /// Creates a new DMA request for the given sector and count.
+    /// Moves the request into an [`Arc`] allocated from the DMA request cache.
+    pub fn into_arc(self) -> Arc<Self> {
+        DMA_REQUEST_SLAB.alloc_with(|| Arc::new(self))
+    }
+
+    /// Creates a new DMA request for the given sector and count, with 512 byte sectors.
     pub fn new(sector: usize, count: usize) -> Self {
-        let mut size = count * 512;
-        let mut buffer = Vec::<DmaBuffer>::new();
+        Self::with_sector_size(sector, count, 512)
+    }
 
-        while size > 0 {
-            let data_size = core::cmp::min(size, 0x2000);
-            let ordering = if size > 0x1000 {
-                BuddyOrdering::Size8KiB
-            } else {
-                BuddyOrdering::Size4KiB
-            };
+    /// Creates a new DMA request for `count` sectors of `sector_size` bytes starting at `sector`.
+    /// The sector size must divide [`DMA_BUFFER_SIZE`], so that a sector never spans two DMA
+    /// buffers.
+    pub fn with_sector_size(sector: usize, count: usize, sector_size: usize) -> Self {
+        assert!(
+            DMA_BUFFER_SIZE % sector_size == 0,
+            "ahci: unsupported sector size {sector_size}"
+        );
 
-            let start = pmm_alloc(ordering);
+        let mut size = count * sector_size;
+        let mut buffer = Vec::<DmaBuffer>::new();
 
-            buffer.push(DmaBuffer { start, data_size });
+        while size > 0 {
+            let data_size = core::cmp::min(size, DMA_BUFFER_SIZE);
+            let order = if size > 0x1000 { 1 } else { 0 };
+
+            let start = FRAME_ALLOCATOR
+                .alloc_order(order)
+                .expect("ahci: failed to allocate DMA buffer");
+
+            buffer.push(DmaBuffer {
+                start,
+                data_size,
+                order,
+                sector_size,
+            });
             size -= data_size; // Subtract the data size from the total size.
         }
 
         Self {
             sector,
             count,
+            sector_size,
             buffer,
             command: DmaCommand::Read,
+            timeout: COMMAND_TIMEOUT,
         }
     }
 
+    /// Creates a new DMA request that writes `count` sectors of `sector_size` bytes, which are
+    /// filled in with [`DmaRequest::copy_from`], starting at `sector`.
+    fn write(sector: usize, count: usize, sector_size: usize) -> Self {
+        let mut request = Self::with_sector_size(sector, count, sector_size);
+        request.command = DmaCommand::Write;
+        request
+    }
+
+    /// Creates a new `IDENTIFY DEVICE` request, which transfers a single 512 byte block
+    /// regardless of the sector size of the device.
+    fn identify() -> Self {
+        let mut request = Self::new(0, 1);
+        request.command = DmaCommand::Identify;
+        request
+    }
+
+    /// Creates a new request that flushes the write cache of the device. It does not transfer
+    /// any data.
+    fn flush(ext: bool) -> Self {
+        let mut request = Self::new(0, 0);
+        request.command = DmaCommand::Flush { ext };
+        request
+    }
+
+    /// Creates a new request for a passed through command, which transfers `count` 512 byte
+    /// blocks to the device if `write` is set or from it otherwise, and has to complete within
+    /// `timeout` nanoseconds.
+    fn passthrough(count: usize, write: bool, timeout: u64) -> Self {
+        let mut request = if write {
+            Self::write(0, count, 512)
+        } else {
+            Self::new(0, count)
+        };
+
+        request.timeout = timeout;
+        request
+    }
+
     pub fn sector(&self) -> usize {
         self.sector
     }
 
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    /// Returns the number of sectors that fit in a single DMA buffer.
+    pub fn sectors_per_buffer(&self) -> usize {
+        DMA_BUFFER_SIZE / self.sector_size
+    }
+
     /// Copies the data from the DMA buffer into the given buffer.
     pub fn copy_into(&self, into: &mut [u8]) {
         let mut offset = 0x00; // Keep track of the offset
         let mut remaining = into.len(); // Keep track of the remaining data
 
         for buffer in self.buffer.iter() {
-            let count = core::cmp::min(remaining, 0x2000);
+            let count = core::cmp::min(remaining, DMA_BUFFER_SIZE);
 
             let buffer_pointer = buffer.start.as_hhdm_virt().as_ptr();
             let buffer = unsafe { core::slice::from_raw_parts::<u8>(buffer_pointer, count) };
@@ -280,8 +410,29 @@ impl DmaRequest {
         }
     }
 
+    /// Copies the data from the given buffer into the DMA buffer.
+    pub fn copy_from(&self, from: &[u8]) {
+        let mut offset = 0x00;
+        let mut remaining = from.len();
+
+        for buffer in self.buffer.iter() {
+            let count = core::cmp::min(remaining, buffer.data_size);
+
+            let buffer_pointer = buffer.start.as_hhdm_virt().as_mut_ptr();
+            let buffer =
+                unsafe { core::slice::from_raw_parts_mut::<u8>(buffer_pointer, buffer.data_size) };
+
+            buffer[..count].copy_from_slice(&from[offset..offset + count]);
+            buffer[count..].fill(0); // Pad a partially filled last sector with zeros.
+
+            remaining -= count;
+            offset += count;
+        }
+    }
+
     pub fn into_command(&self) -> AtaCommand {
-        let lba48 = self.sector > 0x0FFF_FFFF;
+        // The last sector of the request must be addressable with 28-bit LBA as well.
+        let lba48 = self.sector + self.count > 0x0FFF_FFFF;
 
         match self.command {
             DmaCommand::Read => {
@@ -291,11 +442,62 @@ impl DmaRequest {
                     AtaCommand::ReadDma
                 }
             }
+
+            DmaCommand::Write => {
+                if lba48 {
+                    AtaCommand::WriteDmaExt
+                } else {
+                    AtaCommand::WriteDma
+                }
+            }
+
+            DmaCommand::Identify => AtaCommand::IdentifyDevice,
+            DmaCommand::Flush { ext: true } => AtaCommand::FlushCacheExt,
+            DmaCommand::Flush { ext: false } => AtaCommand::FlushCache,
         }
     }
 
+    /// Returns the registers of the command that transfers `count` sectors starting at `offset`
+    /// (relative to the first sector of the request).
+    fn taskfile(&self, offset: usize, count: usize) -> AtaTaskfile {
+        AtaTaskfile {
+            command: self.into_command() as u8,
+            count: count as u16,
+            lba: (self.sector + offset) as u64,
+            device: 1 << 6,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the request transfers data to the device.
+    fn is_write(&self) -> bool {
+        matches!(self.command, DmaCommand::Write)
+    }
+
+    /// Returns the DMA buffers starting with the one that holds the sector at `offset` (relative
+    /// to the first sector of the request).
     pub fn at_offset(&self, offset: usize) -> &[DmaBuffer] {
-        &self.buffer[offset / 16..]
+        &self.buffer[offset / self.sectors_per_buffer()..]
+    }
+
+    /// Splits the sectors of the request, starting at `offset`, into the `(offset, count)` ranges
+    /// transferred by a single command. A command can address at most [`PRDT_ENTRIES`] DMA
+    /// buffers.
+    fn commands(&self, offset: usize) -> impl Iterator<Item = (usize, usize)> {
+        let count = self.count;
+        let per_command = PRDT_ENTRIES * self.sectors_per_buffer();
+
+        (offset..count)
+            .step_by(per_command)
+            .map(move |offset| (offset, core::cmp::min(count - offset, per_command)))
+    }
+}
+
+impl Drop for DmaRequest {
+    fn drop(&mut self) {
+        for buffer in self.buffer.iter() {
+            FRAME_ALLOCATOR.dealloc_order(buffer.start, buffer.order);
+        }
     }
 }
 
@@ -380,7 +582,7 @@ struct HbaMemory {
 struct FisRegH2D {
     fis_type: VolatileCell<FisType>,
     flags: VolatileCell<u8>,
-    command: VolatileCell<AtaCommand>,
+    command: VolatileCell<u8>,
     featurel: VolatileCell<u8>,
 
     lba0: VolatileCell<u8>,
@@ -420,6 +622,36 @@ impl FisRegH2D {
     }
 }
 
+/// Register FIS sent by the device when it completes a command.
+#[repr(C)]
+struct FisRegD2H {
+    _header: [u8; 4], // FIS type, flags, status and error (also in `PxTFD`)
+
+    lba0: VolatileCell<u8>,
+    lba1: VolatileCell<u8>,
+    lba2: VolatileCell<u8>,
+    device: VolatileCell<u8>,
+
+    lba3: VolatileCell<u8>,
+    lba4: VolatileCell<u8>,
+    lba5: VolatileCell<u8>,
+    _reserved0: u8,
+
+    count: VolatileCell<u16>,
+    _reserved1: [u8; 6],
+}
+
+impl FisRegD2H {
+    fn lba(&self) -> u64 {
+        [
+            &self.lba0, &self.lba1, &self.lba2, &self.lba3, &self.lba4, &self.lba5,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |lba, (i, byte)| lba | ((byte.get() as u64) << (8 * i)))
+    }
+}
+
 #[repr(C)]
 struct HbaCmdTbl {
     cfis: [u8; 64],
@@ -567,7 +799,9 @@ impl HbaPort {
 
         // size = sizeof(CTB) * 32 == 4KiB * 2 (so we need to allocate
         // two 4KiB size frames).
-        let frame_addr = pmm_alloc(BuddyOrdering::Size8KiB);
+        let frame_addr = FRAME_ALLOCATOR
+            .alloc_order_zeroed(1)
+            .ok_or(MapToError::FrameAllocationFailed)?;
         let page_addr = crate::IO_VIRTUAL_BASE + frame_addr.as_u64();
 
         for size in (0..0x2000u64).step_by(0x1000) {
@@ -590,7 +824,7 @@ impl HbaPort {
 
             // 8 prdt entries per command table
             // 256 bytes per command table, 64 + 16 + 48 + 16 * 8
-            command_header.prdtl.set(8);
+            command_header.prdtl.set(PRDT_ENTRIES as _);
             command_header.prdbc.set(0);
             command_header.ctb.set(PhysAddr::new(
                 (frame_addr.as_u64() as usize + 256 * i) as u64,
@@ -623,19 +857,68 @@ impl HbaPort {
         }
     }
 
-    fn probe(
-        &mut self,
-        offset_table: &mut OffsetPageTable,
-        port: usize,
-    ) -> Result<bool, MapToError<Size4KiB>> {
+    /// Returns whether a device is present and the link is active.
+    fn link_up(&self) -> bool {
         let status = self.ssts.get();
 
         let ipm = status.interface_power_management();
         let dd = status.device_detection();
 
+        matches!((dd, ipm), (HbaPortDd::PresentAndE, HbaPortIpm::Active))
+    }
+
+    /// Spins up the device attached to the port and waits for the link to come
+    /// up. Returns `false` if no device is attached.
+    ///
+    /// Only required if the HBA supports staggered spin-up ([`HbaCapabilities::SSS`])
+    /// or the port supports cold presence detection ([`HbaPortCmd::CPD`]), otherwise
+    /// the device is already spun up and powered on by the time we get here.
+    fn spin_up(&mut self, port: usize) -> bool {
+        let mut cmd = self.cmd.get();
+        let cold_presence = cmd.contains(HbaPortCmd::CPD);
+
+        if cold_presence && !cmd.contains(HbaPortCmd::CPS) {
+            // The port is able to detect cold-attached devices and there is none.
+            return false;
+        }
+
+        // `POD` is read-only unless the port supports cold presence detection.
+        cmd.insert(HbaPortCmd::POD | HbaPortCmd::SUD);
+        self.cmd.set(cmd);
+
+        let timeout = if cold_presence {
+            log::trace!("ahci: powering on cold-attached device on port {port}");
+            COLD_LINK_TIMEOUT
+        } else {
+            LINK_TIMEOUT
+        };
+
+        if !wait_for(timeout, || self.link_up()) {
+            return false;
+        }
+
+        // Establishing the link latches errors in PxSERR, clear them (RWC).
+        self.serr.set(u32::MAX);
+        true
+    }
+
+    fn probe(
+        &mut self,
+        offset_table: &mut OffsetPageTable,
+        port: usize,
+    ) -> Result<bool, MapToError<Size4KiB>> {
         // Check if the port is active and is present. If that's the case
         // we can start the AHCI port.
-        if let (HbaPortDd::PresentAndE, HbaPortIpm::Active) = (dd, ipm) {
+        if self.link_up() {
+            // A freshly spun up device keeps BSY set until it is ready to accept
+            // commands.
+            if !wait_for(READY_TIMEOUT, || {
+                self.tfd.get() & (ATA_DEV_BUSY | ATA_DEV_DRQ) == 0
+            }) {
+                log::warn!("ahci: device on port {port} did not become ready");
+                return Ok(false);
+            }
+
             log::trace!("ahci: enabling port {}", port);
 
             self.start(offset_table)?;
@@ -646,18 +929,29 @@ impl HbaPort {
         }
     }
 
+    /// Issues the command in `taskfile`, which transfers `count` sectors of `request` starting at
+    /// `offset`, in `slot` and waits for it to complete. The output registers of the device are
+    /// stored in `taskfile`.
     fn run_command(
         &mut self,
-        command: AtaCommand,
-        sector: usize,
-        count: usize,
+        request: &DmaRequest,
+        taskfile: &mut AtaTaskfile,
         slot: usize,
-        buffer: &[DmaBuffer],
-    ) {
+        offset: usize,
+        count: usize,
+    ) -> Option<()> {
+        // Make sure the port is not busy.
+        if !wait_for(COMMAND_TIMEOUT, || {
+            self.tfd.get() & (ATA_DEV_BUSY | ATA_DEV_DRQ) == 0
+        }) {
+            log::warn!("ahci: port hung");
+            return None;
+        }
+
         let header = self.cmd_header_at(slot);
         let mut flags = header.flags.get();
 
-        if command == AtaCommand::WriteDmaExt || command == AtaCommand::WriteDma {
+        if request.is_write() {
             flags.insert(HbaCmdHeaderFlags::W); // If its a write command add the write flag.
         } else {
             flags.remove(HbaCmdHeaderFlags::W); // If its a read command remove the write flag.
@@ -668,7 +962,8 @@ impl HbaPort {
 
         header.flags.set(flags); // Update command header flags.
 
-        let length = ((count - 1) >> 4) + 1;
+        let buffer = request.at_offset(offset);
+        let length = count.div_ceil(request.sectors_per_buffer());
         header.prdtl.set(length as _); // Update the number of PRD entries.
 
         let command_table_addr = crate::IO_VIRTUAL_BASE + header.ctb.get().as_u64();
@@ -686,41 +981,73 @@ impl HbaPort {
 
         fis.control.set(0x00);
         fis.icc.set(0x00);
-        fis.featurel.set(0x00);
-        fis.featureh.set(0x00);
+        fis.featurel.set(taskfile.features as u8);
+        fis.featureh.set((taskfile.features >> 8) as u8);
         fis._reserved.fill(0x00);
 
         fis.fis_type.set(FisType::RegH2D);
-        fis.device.set(1 << 6);
-        fis.command.set(command);
-        fis.count.set(count as _);
+        fis.device.set(taskfile.device);
+        fis.command.set(taskfile.command);
+        fis.count.set(taskfile.count);
 
-        fis.set_lba(sector);
+        fis.set_lba(taskfile.lba as usize);
         fis.set_command(true);
 
+        // Clear the stale interrupt status, so a previous error is not reported for this
+        // command (RWC).
+        self.is.set(HbaPortIS::all());
+
         // Issue the command!
         self.ci.set(1 << slot);
 
-        let mut spin = 100;
-
-        // Make sure the port is not busy.
-        while self.tfd.get() & 0x80 | 0x08 == 1 && spin > 0 {
-            core::hint::spin_loop();
-            spin -= 1;
+        // Wait for the command to complete.
+        let completed = wait_for(request.timeout, || {
+            self.ci.get() & (1 << slot) == 0 || self.is.get().contains(HbaPortIS::TFES)
+        });
+
+        self.read_taskfile(taskfile);
+
+        if self.is.get().contains(HbaPortIS::TFES) {
+            log::warn!(
+                "ahci: command {:#x} failed (tfd={:#x}, serr={:#x})",
+                taskfile.command,
+                self.tfd.get(),
+                self.serr.get()
+            );
+        } else if !completed {
+            log::warn!("ahci: command {:#x} timed out", taskfile.command);
+        } else {
+            return Some(());
         }
 
-        if spin == 0 {
-            log::warn!("ahci: port hung");
-            return;
-        }
+        self.recover();
+        None
+    }
 
-        // Wait for the command to complete.
-        while self.ci.get() & (1 << slot) == 1 {
-            if self.is.get().contains(HbaPortIS::TFES) {
-                log::warn!("ahci: disk error (serr={:#x})", self.serr.get());
-                break;
-            }
-        }
+    /// Stores the output registers of the last command in `taskfile`.
+    fn read_taskfile(&self, taskfile: &mut AtaTaskfile) {
+        let tfd = self.tfd.get();
+
+        taskfile.status = tfd as u8;
+        taskfile.error = (tfd >> 8) as u8;
+
+        let fis_addr = self.fb.get().as_hhdm_virt() + D2H_FIS_OFFSET;
+        let fis = unsafe { &*fis_addr.as_ptr::<FisRegD2H>() };
+
+        taskfile.count = fis.count.get();
+        taskfile.lba = fis.lba();
+        taskfile.device = fis.device.get();
+    }
+
+    /// Restarts the command list after a failed command, as the HBA does not process any
+    /// further commands until then. This also aborts the outstanding commands.
+    fn recover(&mut self) {
+        self.stop_cmd();
+
+        self.serr.set(u32::MAX);
+        self.is.set(HbaPortIS::all());
+
+        self.start_cmd();
     }
 }
 
@@ -745,95 +1072,261 @@ impl AhciPortProtected {
         unsafe { &mut *(self.address.as_mut_ptr::<HbaPort>()) }
     }
 
-    fn run_request(&mut self, request: Arc<DmaRequest>, mut offset: usize) -> usize {
-        let mut remaining = request.count - offset;
+    /// Issues the command in `taskfile` for `count` sectors of `request` starting at `offset`
+    /// (relative to the first sector of the request) and waits for it to complete. Returns
+    /// `None` if the command failed.
+    fn run_command(
+        &mut self,
+        request: &Arc<DmaRequest>,
+        taskfile: &mut AtaTaskfile,
+        offset: usize,
+        count: usize,
+    ) -> Option<()> {
+        let slot = self.cmds.iter().position(Option::is_none)?;
+
+        self.cmds[slot] = Some(AhciCommand {
+            request: request.clone(),
+        });
 
-        while remaining > 0 {
-            let slot = {
-                let command =
-                    self.cmds
-                        .iter()
-                        .enumerate()
-                        .find_map(|(i, e)| if e.is_none() { Some(i) } else { None });
+        self.free_cmds -= 1;
 
-                if let Some(i) = command {
-                    let hba = self.hba_port();
-                    let count = core::cmp::min(remaining, 128);
+        let result = self
+            .hba_port()
+            .run_command(request, taskfile, slot, offset, count);
 
-                    hba.run_command(
-                        request.into_command(),
-                        request.sector + offset,
-                        count,
-                        i,
-                        request.at_offset(offset),
-                    );
+        // The command is not in flight anymore, so the slot can be reused.
+        self.cmds[slot] = None;
+        self.free_cmds += 1;
 
-                    remaining -= count;
-                    offset += count;
+        result
+    }
 
-                    i
-                } else {
-                    return offset;
-                }
-            };
+    fn run_request(&mut self, request: Arc<DmaRequest>, offset: usize) -> Option<usize> {
+        for (offset, count) in request.commands(offset) {
+            let mut taskfile = request.taskfile(offset, count);
+            self.run_command(&request, &mut taskfile, offset, count)?;
+        }
 
-            self.cmds[slot] = Some(AhciCommand {
-                request: request.clone(),
-            });
+        Some(request.count)
+    }
+}
 
-            self.free_cmds -= 1;
+/// Data returned by the `IDENTIFY DEVICE` command.
+struct IdentifyData([u16; 256]);
+
+impl IdentifyData {
+    /// Returns the size of a logical sector in bytes.
+    fn sector_size(&self) -> usize {
+        let info = self.0[106];
+
+        // The word is valid if bit 14 is set and bit 15 is clear. Bit 12 is set if the logical
+        // sector is longer than 256 words, in which case its size is given in words 117..=118.
+        if info & 0xc000 == 0x4000 && info.get_bit(12) {
+            (self.0[117] as usize | ((self.0[118] as usize) << 16)) * 2
+        } else {
+            512
         }
+    }
 
-        offset
+    /// Returns whether the 48-bit address feature set is supported.
+    fn supports_lba48(&self) -> bool {
+        self.0[83].get_bit(10)
+    }
+
+    /// Returns whether the `FLUSH CACHE EXT` command is supported.
+    fn supports_flush_ext(&self) -> bool {
+        self.0[83].get_bit(13)
+    }
+
+    /// Returns the number of addressable logical sectors.
+    fn sector_count(&self) -> usize {
+        if self.supports_lba48() {
+            (0..4).fold(0, |count, i| {
+                count | ((self.0[100 + i] as usize) << (16 * i))
+            })
+        } else {
+            self.0[60] as usize | ((self.0[61] as usize) << 16)
+        }
     }
 }
 
 struct AhciPort {
     inner: Mutex<AhciPortProtected>,
+    /// The logical sector size of the device in bytes.
+    sector_size: usize,
+    /// The number of addressable logical sectors.
+    sector_count: usize,
+    /// Whether the write cache is flushed with `FLUSH CACHE EXT`.
+    flush_ext: bool,
 }
 
 impl AhciPort {
-    #[inline]
-    fn new(address: VirtAddr) -> Self {
+    /// Creates a new port for the device attached to the HBA port at `address`. Returns `None`
+    /// if the sector size of the device is not supported.
+    fn new(address: VirtAddr) -> Option<Self> {
         const EMPTY: Option<AhciCommand> = None;
 
-        Self {
+        let mut port = Self {
             inner: Mutex::new(AhciPortProtected {
                 address,
                 cmds: [EMPTY; 32],
                 free_cmds: 32,
             }),
+            sector_size: 512,
+            sector_count: 0,
+            flush_ext: false,
+        };
+
+        // Fails for ATAPI devices, which are not supported.
+        let identify = port.identify()?;
+        let sector_size = identify.sector_size();
+
+        log::info!(
+            "ahci: found device (sectors={}, sector_size={})",
+            identify.sector_count(),
+            sector_size
+        );
+
+        if !sector_size.is_power_of_two() || !(512..=DMA_BUFFER_SIZE).contains(&sector_size) {
+            log::warn!("ahci: unsupported sector size {sector_size}");
+            return None;
         }
+
+        port.sector_size = sector_size;
+        port.sector_count = identify.sector_count();
+        port.flush_ext = identify.supports_lba48() && identify.supports_flush_ext();
+
+        Some(port)
     }
 
-    fn run_request(&self, request: Arc<DmaRequest>) -> Option<usize> {
-        let mut offset = 0x00;
+    fn identify(&self) -> Option<IdentifyData> {
+        let request = DmaRequest::identify().into_arc();
+        self.run_request(request.clone())?;
 
-        // Run request and wait for it to complete.
-        while offset < request.count {
-            offset = self.inner.lock().run_request(request.clone(), offset);
+        let mut buffer = [0u8; 512];
+        request.copy_into(&mut buffer);
+
+        let mut words = [0u16; 256];
+
+        for (word, bytes) in words.iter_mut().zip(buffer.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
         }
 
-        Some(request.count * 512)
+        Some(IdentifyData(words))
+    }
+
+    fn run_request(&self, request: Arc<DmaRequest>) -> Option<usize> {
+        // Run request and wait for it to complete.
+        self.inner.lock().run_request(request.clone(), 0)?;
+        Some(request.count * request.sector_size())
     }
 
     fn read(&self, sector: usize, buffer: &mut [u8]) -> Option<usize> {
-        let count = (buffer.len() + 512 - 1) / 512;
-        let request = Arc::new(DmaRequest::new(sector, count));
+        let count = buffer.len().div_ceil(self.sector_size);
+        let request = DmaRequest::with_sector_size(sector, count, self.sector_size).into_arc();
+
+        self.run_request(request.clone())?; // Perform the DMA request.
+        request.copy_into(buffer); // Copy the result into the provided buffer.
+
+        Some(buffer.len())
+    }
+
+    fn write(&self, sector: usize, buffer: &[u8]) -> Option<usize> {
+        let count = buffer.len().div_ceil(self.sector_size);
+        let request = DmaRequest::write(sector, count, self.sector_size).into_arc();
+
+        request.copy_from(buffer);
+        self.run_request(request)?;
+
+        Some(buffer.len())
+    }
+}
+
+impl BlockDeviceInterface for AhciPort {
+    fn block_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.sector_count
+    }
 
-        let result = self.run_request(request.clone()); // Perform the DMA request.
+    fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        let buffer =
+            unsafe { core::slice::from_raw_parts_mut(start.as_hhdm_virt().as_mut_ptr(), size) };
 
-        if result.is_some() {
-            request.copy_into(buffer); // Copy the result into the provided buffer.
+        self.read(sector, buffer)
+    }
+
+    fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        let buffer = unsafe { core::slice::from_raw_parts(start.as_hhdm_virt().as_ptr(), size) };
+        self.write(sector, buffer)
+    }
+
+    fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        // SAFETY: The buffer is only written to.
+        let buffer =
+            unsafe { core::slice::from_raw_parts_mut(dest.as_mut_ptr().cast::<u8>(), dest.len()) };
+
+        self.read(sector, buffer)
+    }
+
+    fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
+        self.write(sector, buf)
+    }
+
+    fn flush(&self) -> Option<()> {
+        let request = DmaRequest::flush(self.flush_ext).into_arc();
+        let mut taskfile = request.taskfile(0, 0);
+
+        self.inner.lock().run_command(&request, &mut taskfile, 0, 0)
+    }
+
+    fn ata_passthrough(
+        &self,
+        taskfile: &mut AtaTaskfile,
+        protocol: AtaProtocol,
+        data: &mut [u8],
+        timeout: u64,
+    ) -> Option<bool> {
+        // The data has to fit in the PRDT of a single command.
+        if data.len() % 512 != 0 || data.len() > PRDT_ENTRIES * DMA_BUFFER_SIZE {
+            return Some(false);
         }
 
-        result
+        let write = protocol == AtaProtocol::PioOut;
+        let request = DmaRequest::passthrough(data.len() / 512, write, timeout).into_arc();
+
+        if write {
+            request.copy_from(data);
+        }
+
+        let completed = self
+            .inner
+            .lock()
+            .run_command(&request, taskfile, 0, request.count)
+            .is_some();
+
+        if completed && protocol == AtaProtocol::PioIn {
+            request.copy_into(data);
+        }
+
+        Some(completed)
     }
 }
 
+/// The registers of a port that are lost when the machine sleeps.
+struct SavedPort {
+    port: usize,
+    clb: PhysAddr,
+    fb: PhysAddr,
+    ie: HbaPortIE,
+}
+
 struct AhciProtected {
     ports: [Option<Arc<AhciPort>>; 32],
     hba: VirtAddr,
+    saved: Vec<SavedPort>,
 }
 
 impl AhciProtected {
@@ -862,18 +1355,28 @@ impl AhciProtected {
         );
 
         let pi = hba.ports_implemented.get();
+        let staggered = hba.host_capability.get().contains(HbaCapabilities::SSS);
 
+        if staggered {
+            log::info!("ahci: staggered spin-up supported");
+        }
+
+        // Ports are brought up one at a time: with staggered spin-up, the next device
+        // is only spun up after the previous one is ready, to limit the power draw.
         for i in 0..32 {
             if pi.get_bit(i) {
                 let port = hba.port_mut(i);
 
+                if (staggered || port.cmd.get().contains(HbaPortCmd::CPD)) && !port.spin_up(i) {
+                    continue;
+                }
+
                 if port.probe(offset_table, i)? {
                     // Get the address of the HBA port.
                     let address = VirtAddr::new(port as *const _ as _);
-                    let port = Arc::new(AhciPort::new(address));
 
                     // Add the port to the ports array.
-                    self.ports[i] = Some(port);
+                    self.ports[i] = AhciPort::new(address).map(Arc::new);
 
                     // Workaround to get access to the HBA and still satisfy the
                     // borrow checker.
@@ -931,6 +1434,61 @@ struct AhciDriver {
     inner: Mutex<AhciProtected>,
 }
 
+impl PowerOps for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+
+    fn suspend(&self) -> Result<(), SuspendError> {
+        let mut this = self.inner.lock_irq();
+        let hba = this.hba_mem();
+        let mut saved = Vec::new();
+
+        for i in (0..32).filter(|&i| this.ports[i].is_some()) {
+            let port = hba.port_mut(i);
+
+            // Commands that are still running would be lost.
+            if port.ci.get() != 0 || port.sact.get() != 0 {
+                for saved in saved.iter() {
+                    hba.port_mut(saved.port).start_cmd();
+                }
+
+                return Err(SuspendError::Busy);
+            }
+
+            port.stop_cmd();
+            saved.push(SavedPort {
+                port: i,
+                clb: port.clb.get(),
+                fb: port.fb.get(),
+                ie: port.ie.get(),
+            });
+        }
+
+        this.saved = saved;
+        Ok(())
+    }
+
+    fn resume(&self) {
+        let this = self.inner.lock_irq();
+        let hba = this.hba_mem();
+
+        hba.global_host_control
+            .set(HbaHostCont::AE | HbaHostCont::IE);
+
+        for saved in this.saved.iter() {
+            let port = hba.port_mut(saved.port);
+
+            port.stop_cmd();
+            port.clb.set(saved.clb);
+            port.fb.set(saved.fb);
+            port.is.set(HbaPortIS::all());
+            port.ie.set(saved.ie);
+            port.start_cmd();
+        }
+    }
+}
+
 impl PciDeviceHandle for AhciDriver {
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
         matches!(
@@ -943,12 +1501,21 @@ impl PciDeviceHandle for AhciDriver {
         log::info!("ahci: starting driver...");
 
         get_ahci().inner.lock_irq().start_driver(header).unwrap(); // Start and initialize the AHCI controller.
+        power::register(get_ahci().clone());
+
+        let ports = get_ahci().inner.lock().ports.clone();
+
+        for port in ports.into_iter().flatten() {
+            let name = block::alloc_disk_name();
+
+            log::info!(
+                "ahci: {name}: {} sectors of {} bytes",
+                port.sector_count,
+                port.sector_size
+            );
 
-        // Temporary testing...
-        if let Some(port) = get_ahci().inner.lock().ports[0].clone() {
-            let buffer = &mut [0u8; 512];
-            port.read(0, buffer);
-            log::info!("Read sector 0: {:?}", buffer);
+            block::install_block_device(BlockDevice::new(name, port))
+                .expect("ahci: failed to install the block device");
         }
     }
 }
@@ -970,6 +1537,7 @@ pub fn ahci_init() {
             inner: Mutex::new(AhciProtected {
                 ports: [EMPTY; 32],    // Initialize the AHCI ports to an empty slice.
                 hba: VirtAddr::zero(), // Initialize the AHCI HBA address to zero.
+                saved: Vec::new(),
             }),
         })
     });
@@ -979,3 +1547,82 @@ pub fn ahci_init() {
 }
 
 crate::module_init!(ahci_init, ModuleType::Block);
+
+mod selftests {
+    use alloc::vec;
+
+    use super::*;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    fn buffer_sizes(request: &DmaRequest) -> Vec<usize> {
+        request.buffer.iter().map(DmaBuffer::data_size).collect()
+    }
+
+    fn dma_buffer_split() -> selftest::Result {
+        let request = DmaRequest::new(0, 40);
+
+        selftest_assert_eq!(request.sectors_per_buffer(), 16);
+        selftest_assert_eq!(buffer_sizes(&request), [0x2000, 0x2000, 0x1000]);
+        selftest_assert_eq!(request.at_offset(16).len(), 2);
+        selftest_assert_eq!(request.at_offset(39)[0].sectors(), 8);
+
+        let request = DmaRequest::with_sector_size(0, 3, 4096);
+
+        selftest_assert_eq!(request.sectors_per_buffer(), 2);
+        selftest_assert_eq!(buffer_sizes(&request), [0x2000, 0x1000]);
+        selftest_assert_eq!(request.at_offset(2)[0].sectors(), 1);
+
+        Ok(())
+    }
+
+    fn dma_command_split() -> selftest::Result {
+        let request = DmaRequest::new(0, 300);
+
+        selftest_assert!(request.commands(0).eq([(0, 128), (128, 128), (256, 44)]));
+        selftest_assert!(request.commands(100).eq([(100, 128), (228, 72)]));
+        selftest_assert_eq!(request.commands(300).count(), 0);
+
+        let request = DmaRequest::with_sector_size(0, 20, 4096);
+        selftest_assert!(request.commands(0).eq([(0, 16), (16, 4)]));
+
+        Ok(())
+    }
+
+    fn dma_copy() -> selftest::Result {
+        let request = DmaRequest::new(0, 20);
+        let data = (0..700).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut buffer = vec![0xff; 20 * 512];
+        request.copy_from(&buffer);
+        request.copy_from(&data);
+        request.copy_into(&mut buffer);
+
+        // The rest of the request is padded with zeros.
+        selftest_assert_eq!(buffer[..700], data[..]);
+        selftest_assert!(buffer[700..].iter().all(|&byte| byte == 0));
+
+        Ok(())
+    }
+
+    fn dma_lba48() -> selftest::Result {
+        let command = DmaRequest::new(0x0FFF_FFFE, 1).into_command();
+        selftest_assert_eq!(command, AtaCommand::ReadDma);
+
+        let command = DmaRequest::new(0x0FFF_FFFF, 1).into_command();
+        selftest_assert_eq!(command, AtaCommand::ReadDmaExt);
+
+        let command = DmaRequest::write(0x0FFF_FFFF, 1, 512).into_command();
+        selftest_assert_eq!(command, AtaCommand::WriteDmaExt);
+
+        Ok(())
+    }
+
+    crate::selftest!(
+        "ahci",
+        dma_buffer_split,
+        dma_command_split,
+        dma_copy,
+        dma_lba48
+    );
+}
@@ -0,0 +1,249 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! RAM-backed block devices (`/dev/ramN`), which make it possible to exercise filesystems,
+//! partition scanning and `mkfs` tools without touching real disks.
+//!
+//! The number of disks and their size are set with the `brd.rd_nr=N` and `brd.rd_size=SIZE`
+//! command line options. The memory backing a disk is allocated a page at a time when it is first
+//! written to; sectors that were never written to read as zeros.
+
+use core::mem::MaybeUninit;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use crate::cmdline;
+use crate::fs::block::{self, BlockDevice, BlockDeviceInterface};
+use crate::mem::paging::*;
+use crate::utils::sync::Mutex;
+
+const SECTOR_SIZE: usize = 512;
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+const DEFAULT_COUNT: usize = 1;
+const DEFAULT_SIZE: usize = 16 << 20;
+
+struct RamDisk {
+    /// The capacity of the disk in bytes.
+    size: usize,
+    /// The pages that have been written to, by their index.
+    pages: Mutex<BTreeMap<usize, PhysAddr>>,
+}
+
+impl RamDisk {
+    fn new(size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            size,
+            pages: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns whether `len` bytes at `offset` are within the disk.
+    fn contains(&self, offset: usize, len: usize) -> bool {
+        offset.checked_add(len).is_some_and(|end| end <= self.size)
+    }
+
+    /// Calls `f` with each page index, offset into the page and range of the buffer covering the
+    /// `len` bytes at `offset`.
+    fn for_each_page(
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(usize, usize, core::ops::Range<usize>) -> Option<()>,
+    ) -> Option<()> {
+        let mut progress = 0;
+
+        while progress < len {
+            let page_offset = (offset + progress) % PAGE_SIZE;
+            let chunk = core::cmp::min(PAGE_SIZE - page_offset, len - progress);
+
+            f(
+                (offset + progress) / PAGE_SIZE,
+                page_offset,
+                progress..progress + chunk,
+            )?;
+
+            progress += chunk;
+        }
+
+        Some(())
+    }
+
+    fn read(&self, offset: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        if !self.contains(offset, dest.len()) {
+            return None;
+        }
+
+        let pages = self.pages.lock();
+
+        Self::for_each_page(offset, dest.len(), |index, page_offset, range| {
+            let dest = &mut dest[range];
+
+            match pages.get(&index) {
+                Some(page) => {
+                    let data = &page.as_hhdm_virt().as_bytes_mut(PAGE_SIZE)[page_offset..];
+
+                    for (dest, byte) in dest.iter_mut().zip(data) {
+                        dest.write(*byte);
+                    }
+                }
+
+                None => dest.fill(MaybeUninit::new(0)),
+            }
+
+            Some(())
+        })?;
+
+        Some(dest.len())
+    }
+
+    fn write(&self, offset: usize, buffer: &[u8]) -> Option<usize> {
+        if !self.contains(offset, buffer.len()) {
+            return None;
+        }
+
+        let mut pages = self.pages.lock();
+
+        Self::for_each_page(offset, buffer.len(), |index, page_offset, range| {
+            let page = match pages.get(&index) {
+                Some(page) => *page,
+                None => {
+                    let page = FRAME_ALLOCATOR.alloc_zeroed(PAGE_SIZE)?;
+                    pages.insert(index, page);
+                    page
+                }
+            };
+
+            let data = &mut page.as_hhdm_virt().as_bytes_mut(PAGE_SIZE)[page_offset..];
+            data[..range.len()].copy_from_slice(&buffer[range]);
+
+            Some(())
+        })?;
+
+        Some(buffer.len())
+    }
+}
+
+impl BlockDeviceInterface for RamDisk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> usize {
+        self.size / SECTOR_SIZE
+    }
+
+    fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        let dest = start.as_hhdm_virt().as_bytes_mut(size);
+        let dest = unsafe {
+            core::slice::from_raw_parts_mut(dest.as_mut_ptr().cast::<MaybeUninit<u8>>(), size)
+        };
+
+        self.read(sector * SECTOR_SIZE, dest)
+    }
+
+    fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        self.write(
+            sector * SECTOR_SIZE,
+            start.as_hhdm_virt().as_bytes_mut(size),
+        )
+    }
+
+    fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        self.read(sector * SECTOR_SIZE, dest)
+    }
+
+    fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
+        self.write(sector * SECTOR_SIZE, buf)
+    }
+}
+
+impl Drop for RamDisk {
+    fn drop(&mut self) {
+        for page in self.pages.lock().values() {
+            FRAME_ALLOCATOR.dealloc(*page, PAGE_SIZE);
+        }
+    }
+}
+
+fn brd_init() {
+    let count = cmdline::ramdisk_count().unwrap_or(DEFAULT_COUNT);
+    // The page cache reads and writes whole pages.
+    let size = cmdline::ramdisk_size()
+        .unwrap_or(DEFAULT_SIZE)
+        .next_multiple_of(PAGE_SIZE);
+
+    for i in 0..count {
+        let device = BlockDevice::new(alloc::format!("ram{i}"), RamDisk::new(size));
+        block::install_block_device(device).expect("brd: failed to install the block device");
+    }
+
+    log::info!("brd: created {count} RAM disks of {} KiB", size >> 10);
+}
+
+crate::module_init!(brd_init, ModuleType::Block);
+
+mod selftests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    fn read(disk: &RamDisk, offset: usize, len: usize) -> Option<Vec<u8>> {
+        let mut buffer = vec![0xff; len];
+        let dest = unsafe {
+            core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast::<MaybeUninit<u8>>(), len)
+        };
+
+        disk.read(offset, dest)?;
+        Some(buffer)
+    }
+
+    fn read_write() -> selftest::Result {
+        let disk = RamDisk::new(4 * PAGE_SIZE);
+
+        // Unwritten sectors read as zeros without allocating any memory.
+        selftest_assert_eq!(read(&disk, 0, 1024), Some(vec![0; 1024]));
+        selftest_assert!(disk.pages.lock().is_empty());
+
+        // A write spanning two pages.
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        selftest_assert_eq!(disk.write(PAGE_SIZE - 500, &data), Some(1000));
+        selftest_assert_eq!(disk.pages.lock().len(), 2);
+
+        selftest_assert_eq!(read(&disk, PAGE_SIZE - 500, 1000), Some(data));
+        selftest_assert_eq!(read(&disk, PAGE_SIZE + 500, 4), Some(vec![0; 4]));
+
+        Ok(())
+    }
+
+    fn out_of_range() -> selftest::Result {
+        let disk = RamDisk::new(4 * PAGE_SIZE);
+
+        selftest_assert_eq!(disk.block_count(), 32);
+        selftest_assert!(read(&disk, 4 * PAGE_SIZE - 512, 512).is_some());
+        selftest_assert!(read(&disk, 4 * PAGE_SIZE - 512, 513).is_none());
+        selftest_assert!(disk.write(4 * PAGE_SIZE, &[0]).is_none());
+        selftest_assert!(disk.write(usize::MAX, &[0]).is_none());
+
+        Ok(())
+    }
+
+    crate::selftest!("brd", read_write, out_of_range);
+}
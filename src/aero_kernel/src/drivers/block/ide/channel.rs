@@ -112,7 +112,8 @@ impl IdeChannelData {
         self.ctrl.software_reset();
     }
 
-    pub fn detect(&mut self, slave: bool) -> bool {
+    /// Returns the number of sectors of the drive, if there is an ATA drive attached.
+    pub fn detect(&mut self, slave: bool) -> Option<usize> {
         self.software_reset();
 
         let mut sel = BaseDriveSelReg::new();
@@ -127,30 +128,53 @@ impl IdeChannelData {
         let status = self.base.status();
 
         if status.is_empty() {
-            return false;
+            return None;
         }
 
         loop {
             if let Some(status) = self.base.try_status() {
                 if status.contains(BaseStatusReg::ERR) {
-                    return false;
+                    return None;
                 }
                 if !status.contains(BaseStatusReg::BSY) && status.contains(BaseStatusReg::DRQ) {
                     break;
                 }
             } else {
-                return false;
+                return None;
             }
         }
 
         let lm = self.base.lba_mid();
         let lh = self.base.lba_hi();
 
-        lm == 0 && lh == 0
+        // ATAPI and SATA devices report a non-zero signature in the LBA registers.
+        if lm != 0 || lh != 0 {
+            return None;
+        }
+
+        let mut identify = [0u16; 256];
+
+        for word in identify.iter_mut() {
+            *word = self.base.read_data();
+        }
+
+        // Use the 48-bit sector count if the drive supports LBA48.
+        let sectors = if identify[83].get_bit(10) {
+            identify[100..104]
+                .iter()
+                .rev()
+                .fold(0, |count, &word| (count << 16) | word as usize)
+        } else {
+            ((identify[61] as usize) << 16) | identify[60] as usize
+        };
+
+        Some(sectors)
     }
 
     pub fn setup_prdt(&mut self) {
-        let prdt = pmm_alloc(BuddyOrdering::Size4KiB);
+        let prdt = FRAME_ALLOCATOR
+            .alloc_order(0)
+            .expect("ide: failed to allocate the PRDT");
 
         self.bmide.load_prdt(prdt);
         self.prdt_addr = prdt;
@@ -240,7 +264,7 @@ impl IdeChannel {
         })
     }
 
-    pub fn detect(&self, slave: bool) -> bool {
+    pub fn detect(&self, slave: bool) -> Option<usize> {
         self.data.lock_irq().detect(slave)
     }
 
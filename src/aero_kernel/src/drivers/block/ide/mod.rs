@@ -40,18 +40,24 @@ static DRIVER: Once<Arc<Ide>> = Once::new();
 pub struct IdeDrive {
     slave: bool,
     channel: Arc<IdeChannel>,
+    /// Number of sectors reported by the drive in its IDENTIFY data.
+    sectors: usize,
 }
 
 impl IdeDrive {
-    pub fn new(slave: bool, channel: Arc<IdeChannel>) -> Arc<IdeDrive> {
-        Arc::new(IdeDrive { slave, channel })
+    pub fn new(slave: bool, channel: Arc<IdeChannel>, sectors: usize) -> Arc<IdeDrive> {
+        Arc::new(IdeDrive {
+            slave,
+            channel,
+            sectors,
+        })
     }
 }
 
 impl BlockDeviceInterface for IdeDrive {
     fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
         let count = dest.len().div_ceil(512);
-        let request = Arc::new(DmaRequest::new(sector, count));
+        let request = DmaRequest::new(sector, count).into_arc();
 
         let res = self.channel.run_request(request, self.slave);
 
@@ -67,6 +73,10 @@ impl BlockDeviceInterface for IdeDrive {
         todo!()
     }
 
+    fn block_count(&self) -> usize {
+        self.sectors
+    }
+
     fn read_dma(
         &self,
         _sector: usize,
@@ -162,8 +172,8 @@ impl IdeDevice {
         let mut idx = 0;
         for (ci, c) in [c1, c2].iter().enumerate() {
             for &s in [false, true].iter() {
-                if c.detect(s) {
-                    self.ide_devs[idx] = Some(IdeDrive::new(s, c.clone()));
+                if let Some(sectors) = c.detect(s) {
+                    self.ide_devs[idx] = Some(IdeDrive::new(s, c.clone(), sectors));
                     idx += 1;
 
                     if self.channels[ci].is_none() {
@@ -23,6 +23,7 @@ use crate::mem::paging::PhysAddr;
 use crate::arch::io;
 use crate::arch::io::BasedPort;
 
+const BASE_DATA: u16 = 0;
 const BASE_FEATURE: u16 = 1;
 const BASE_SECTOR_COUNT: u16 = 2;
 const BASE_LBA_LO: u16 = 3;
@@ -202,6 +203,10 @@ impl DevBaseReg {
             .write_offset(BASE_LBA_HI, sector.get_bits(16..24) as u8);
     }
 
+    pub fn read_data(&self) -> u16 {
+        self.base.read_offset::<u16>(BASE_DATA)
+    }
+
     pub fn lba_mid(&self) -> u8 {
         self.base.read_offset::<u8>(BASE_LBA_MID)
     }
@@ -1,4 +1,5 @@
 pub mod ahci;
+pub mod brd;
 // FIXME: aarch64 port
 #[cfg(target_arch = "x86_64")]
 pub mod ide;
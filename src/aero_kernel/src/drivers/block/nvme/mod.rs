@@ -501,6 +501,10 @@ impl<'a> BlockDeviceInterface for Controller<'a> {
         self.namespaces.lock()[0].block_size
     }
 
+    fn block_count(&self) -> usize {
+        self.namespaces.lock()[0].blocks
+    }
+
     fn write_block(&self, _sector: usize, _buf: &[u8]) -> Option<usize> {
         unimplemented!()
     }
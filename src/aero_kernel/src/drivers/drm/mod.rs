@@ -25,6 +25,7 @@ use bit_field::BitField;
 use hashbrown::HashMap;
 
 use crate::arch::user_copy::UserRef;
+use crate::drivers::modeset::VideoMode;
 use crate::fs;
 use crate::fs::inode::INodeInterface;
 use crate::fs::{devfs, FileSystemError};
@@ -72,6 +73,8 @@ trait DrmDevice: Send + Sync {
     fn dumb_create(&self, width: u32, height: u32, bpp: u32) -> (BufferObject, u32);
     fn framebuffer_create(&self, buffer_object: &BufferObject, width: u32, height: u32, pitch: u32);
     fn commit(&self, buffer_obj: &BufferObject);
+    /// Programs the display with the provided mode.
+    fn set_mode(&self, mode: &DrmModeInfo) -> fs::Result<()>;
 
     /// Returns tuple containing the minimum dimensions (`xmin`, `ymin`).
     fn min_dim(&self) -> (usize, usize);
@@ -125,6 +128,11 @@ impl BufferObject {
 struct Crtc {
     sref: Weak<Self>,
 
+    /// The current display mode, if one has been set.
+    mode: Mutex<Option<DrmModeInfo>>,
+    /// The framebuffer that is being scanned out, if any.
+    framebuffer: Mutex<Option<Arc<Framebuffer>>>,
+
     object_id: u32,
     index: u32,
 }
@@ -134,6 +142,9 @@ impl Crtc {
         Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
 
+            mode: Mutex::new(None),
+            framebuffer: Mutex::new(None),
+
             object_id,
             index: drm.crtcs.lock().len() as _,
         })
@@ -489,16 +500,34 @@ impl INodeInterface for Drm {
             }
 
             DRM_IOCTL_GET_CRTC => {
-                let struc = unsafe { UserRef::<DrmModeCrtc>::new(VirtAddr::new(arg as u64)) };
-                let _object = self.find_object(struc.crtc_id).unwrap().as_crtc().unwrap();
+                let mut struc = unsafe { UserRef::<DrmModeCrtc>::new(VirtAddr::new(arg as u64)) };
+                let crtc = self.find_object(struc.crtc_id).unwrap().as_crtc().unwrap();
+
+                struc.fb_id = crtc.framebuffer.lock().as_ref().map_or(0, |fb| fb.id());
+                struc.x = 0;
+                struc.y = 0;
+                struc.gamma_size = 0;
+
+                if let Some(mode) = crtc.mode.lock().clone() {
+                    struc.mode_valid = 1;
+                    struc.mode = mode;
+                } else {
+                    struc.mode_valid = 0;
+                }
 
-                log::warn!("drm::get_crtc: is a stub!");
                 Ok(0)
             }
 
             DRM_IOCTL_SET_CRTC => {
                 let struc = unsafe { UserRef::<DrmModeCrtc>::new(VirtAddr::new(arg as u64)) };
-                let _object = self.find_object(struc.crtc_id).unwrap().as_crtc().unwrap();
+                let crtc = self.find_object(struc.crtc_id).unwrap().as_crtc().unwrap();
+
+                // Switch the display mode before committing the framebuffer, so the framebuffer
+                // is scanned out with the new geometry.
+                if struc.mode_valid != 0 {
+                    self.device.set_mode(&struc.mode)?;
+                    *crtc.mode.lock() = Some(struc.mode.clone());
+                }
 
                 let object = self
                     .find_object(struc.fb_id)
@@ -507,7 +536,7 @@ impl INodeInterface for Drm {
                     .unwrap();
 
                 self.device.commit(&object.buffer_obj);
-                log::warn!("drm::set_crtc: is a stub!");
+                *crtc.framebuffer.lock() = Some(object);
 
                 Ok(0)
             }
@@ -674,6 +703,10 @@ impl devfs::Device for Drm {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         self.sref.upgrade().unwrap()
     }
+
+    fn device_number(&self) -> Option<devfs::DeviceNumber> {
+        Some(devfs::DeviceNumber::Char(226, self.card_id as u32))
+    }
 }
 
 fn make_mode_info(
@@ -717,6 +750,49 @@ fn make_mode_info(
     this
 }
 
+/// Returns the mode info for each of the provided video modes. The timings are taken from the
+/// matching DMT mode if there is one; otherwise, a mode without any blanking is made up as the
+/// timings are not known.
+fn make_video_modes(modes: &[VideoMode]) -> Vec<DrmModeInfo> {
+    let dmt_modes = make_dmt_modes(u16::MAX, u16::MAX);
+
+    modes
+        .iter()
+        .map(|mode| {
+            let (width, height) = (mode.width as u16, mode.height as u16);
+
+            dmt_modes
+                .iter()
+                .find(|e| {
+                    e.hdisplay == width
+                        && e.vdisplay == height
+                        && e.flags & DRM_MODE_FLAG_INTERLACE == 0
+                })
+                .cloned()
+                .unwrap_or_else(|| {
+                    let name = alloc::format!("{width}x{height}");
+
+                    make_mode_info(
+                        &name,
+                        DRM_MODE_TYPE_DRIVER,
+                        0,
+                        width,
+                        width,
+                        width,
+                        width,
+                        0,
+                        height,
+                        height,
+                        height,
+                        height,
+                        0,
+                        0,
+                    )
+                })
+        })
+        .collect()
+}
+
 fn make_dmt_modes(max_width: u16, max_height: u16) -> Vec<DrmModeInfo> {
     #[rustfmt::skip] // with formatting this gets way too long.
     let modes = &[
@@ -16,14 +16,14 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use alloc::sync::Arc;
-use uapi::drm::DrmModeConStatus;
+use uapi::drm::{DrmModeConStatus, DrmModeInfo};
 
-use crate::fs::{devfs, FileSystem};
+use crate::drivers::modeset::{self, VideoMode};
+use crate::fs::{self, devfs, FileSystem};
 
 use crate::mem::paging::*;
 
-use super::{make_dmt_modes, BufferObject, Connector, Crtc, Drm, DrmDevice, Encoder};
-use crate::rendy;
+use super::{make_video_modes, BufferObject, Connector, Crtc, Drm, DrmDevice, Encoder};
 
 struct RawFramebuffer {}
 
@@ -50,14 +50,23 @@ impl DrmDevice for RawFramebuffer {
             .map(|e| {
                 let mut lock = e.lock_irq();
                 let fb = lock.get_framebuffer();
+                let fb_len = core::mem::size_of_val(fb);
 
                 for (i, frame) in buffer_obj.memory.iter().enumerate() {
+                    let offset = i * Size4KiB::SIZE as usize;
+
+                    // The buffer object may be larger than the framebuffer in the current mode.
+                    if offset >= fb_len {
+                        break;
+                    }
+
+                    let size = (fb_len - offset).min(Size4KiB::SIZE as usize);
+
                     unsafe {
                         core::ptr::copy_nonoverlapping(
                             frame.as_slice_mut::<u8>().as_mut_ptr(),
-                            (fb.as_mut_ptr().cast::<u8>())
-                                .offset(i as isize * Size4KiB::SIZE as isize),
-                            4096,
+                            (fb.as_mut_ptr().cast::<u8>()).add(offset),
+                            size,
                         )
                     }
                 }
@@ -65,6 +74,13 @@ impl DrmDevice for RawFramebuffer {
             .unwrap();
     }
 
+    fn set_mode(&self, mode: &DrmModeInfo) -> fs::Result<()> {
+        let mode = VideoMode::new(mode.hdisplay as usize, mode.vdisplay as usize, 32);
+
+        modeset::set_mode(mode)?;
+        Ok(())
+    }
+
     fn framebuffer_create(
         &self,
         buffer_object: &BufferObject,
@@ -85,22 +101,31 @@ impl DrmDevice for RawFramebuffer {
     }
 
     fn min_dim(&self) -> (usize, usize) {
-        // NOTE: for rawfb drm device, the max and min dimensions are the same.
-        self.max_dim()
+        let modes = modeset::modes();
+
+        let xmin = modes.iter().map(|mode| mode.width).min().unwrap_or(0);
+        let ymin = modes.iter().map(|mode| mode.height).min().unwrap_or(0);
+
+        (xmin, ymin)
     }
 
     fn max_dim(&self) -> (usize, usize) {
-        let info = rendy::get_rendy_info();
-        (info.horizontal_resolution, info.vertical_resolution)
+        let modes = modeset::modes();
+
+        let xmax = modes.iter().map(|mode| mode.width).max().unwrap_or(0);
+        let ymax = modes.iter().map(|mode| mode.height).max().unwrap_or(0);
+
+        (xmax, ymax)
     }
 }
 
 fn init() {
-    let info = rendy::get_rendy_info();
-
     let rfb = Drm::new(Arc::new(RawFramebuffer {}));
     let crtc = Crtc::new(&rfb, rfb.allocate_object_id());
 
+    // The display is left in the mode set by the bootloader.
+    *crtc.mode.lock() = make_video_modes(&[modeset::current_mode()]).pop();
+
     let encoder = Encoder::new(
         &rfb,
         crtc.clone(),
@@ -111,10 +136,7 @@ fn init() {
     let connector = Connector::new(
         encoder.clone(),
         alloc::vec![encoder.clone()],
-        make_dmt_modes(
-            info.horizontal_resolution as u16,
-            info.vertical_resolution as u16,
-        ),
+        make_video_modes(&modeset::modes()),
         DrmModeConStatus::Connected,
         rfb.allocate_object_id(),
     );
@@ -132,4 +154,6 @@ fn init() {
     devfs::install_device_at(dri, rfb).expect("ramfs: failed to install DRM device");
 }
 
-crate::module_init!(init, ModuleType::Block);
+// The display backends are registered by the PCI drivers, so this must run after the PCI bus
+// has been enumerated.
+crate::module_init!(init, ModuleType::Other);
@@ -29,6 +29,7 @@ use crate::userland::scheduler;
 use crate::utils::dma::DmaAllocator;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use crate::net::offload::{Features, RxChecksum, TxOffload};
 use crate::net::{self, NetworkDevice, NetworkDriver};
 use crabnet::data_link::MacAddr;
 
@@ -75,6 +76,8 @@ enum Register {
     RxDescHead = 0x2810,
     /// Tail pointer for the receive descriptor buffer.
     RxDescTail = 0x2818,
+    /// Controls the receive checksum offload.
+    RxCsum = 0x5000,
 
     TCtrl = 0x400,
     /// Lower bits of the 64 bit descriptor base address.
@@ -113,6 +116,39 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    struct TCmd: u8 {
+        const EOP  = 1 << 0; // End of Packet
+        const IFCS = 1 << 1; // Insert FCS
+        const IC   = 1 << 2; // Insert Checksum
+        const RS   = 1 << 3; // Report Status
+    }
+}
+
+bitflags::bitflags! {
+    struct RStatus: u8 {
+        const DD    = 1 << 0; // Descriptor Done
+        const EOP   = 1 << 1; // End of Packet
+        const IXSM  = 1 << 2; // Ignore Checksum Indication
+        const TCPCS = 1 << 5; // TCP/UDP Checksum Calculated
+        const IPCS  = 1 << 6; // IP Checksum Calculated
+    }
+}
+
+bitflags::bitflags! {
+    struct RErrors: u8 {
+        const TCPE = 1 << 5; // TCP/UDP Checksum Error
+        const IPE  = 1 << 6; // IP Checksum Error
+    }
+}
+
+bitflags::bitflags! {
+    struct RxCsum: u32 {
+        const IPOFL = 1 << 8; // IP Checksum Offload Enable
+        const TUOFL = 1 << 9; // TCP/UDP Checksum Offload Enable
+    }
+}
+
 bitflags::bitflags! {
     struct ECtl: u32 {
         const LRST    = 1 << 3;
@@ -381,16 +417,27 @@ impl E1000 {
         }
     }
 
-    fn send(&mut self, packet: Box<[u8], DmaAllocator>) {
+    fn send(&mut self, packet: Box<[u8], DmaAllocator>, offload: TxOffload) {
         let cur = self.tx_cur;
         let ring = self.tx_ring();
 
         ring[cur].addr =
             unsafe { VirtAddr::new(packet.as_ptr() as u64) - crate::PHYSICAL_MEMORY_OFFSET };
         ring[cur].length = packet.len() as _;
-        ring[cur].cmd = 0b1011;
+        ring[cur].cmd = (TCmd::EOP | TCmd::IFCS | TCmd::RS).bits();
         ring[cur].status = TStatus::empty();
 
+        // The legacy descriptor checksums the packet from `css` to its end and inserts the
+        // result at `cso`.
+        if let Some(csum) = offload.csum {
+            ring[cur].cmd |= TCmd::IC.bits();
+            ring[cur].css = csum.start as u8;
+            ring[cur].cso = (csum.start + csum.offset) as u8;
+        } else {
+            ring[cur].css = 0;
+            ring[cur].cso = 0;
+        }
+
         self.tx_cur = (self.tx_cur + 1) % TX_DESC_NUM as usize;
 
         self.write(Register::TxDescTail, self.tx_cur as u32);
@@ -409,7 +456,24 @@ impl E1000 {
             .as_hhdm_virt()
             .as_bytes_mut(desc.length as usize);
 
-        Some(net::RecvPacket { packet, id })
+        let status = RStatus::from_bits_truncate(desc.status);
+        let errors = RErrors::from_bits_truncate(desc.errors);
+
+        // Both the IPv4 header checksum and the TCP or UDP checksum have to be verified.
+        let checksum = if !status.contains(RStatus::IXSM)
+            && status.contains(RStatus::IPCS | RStatus::TCPCS)
+            && errors.is_empty()
+        {
+            RxChecksum::Unnecessary
+        } else {
+            RxChecksum::None
+        };
+
+        Some(net::RecvPacket {
+            packet,
+            id,
+            checksum,
+        })
     }
 
     fn recv_end(&mut self, id: usize) {
@@ -521,6 +585,8 @@ impl E1000 {
             | RCtl::BSIZE_4096;
 
         self.write(Register::RCtrl, flags.bits());
+        self.write(Register::RxCsum, (RxCsum::IPOFL | RxCsum::TUOFL).bits());
+
         Ok(())
     }
 
@@ -602,8 +668,8 @@ impl Device {
 }
 
 impl NetworkDriver for Device {
-    fn send(&self, packet: Box<[u8], DmaAllocator>) {
-        self.e1000.lock_irq().send(packet)
+    fn send(&self, packet: Box<[u8], DmaAllocator>, offload: TxOffload) {
+        self.e1000.lock_irq().send(packet, offload)
     }
 
     fn recv(&self) -> net::RecvPacket {
@@ -629,6 +695,10 @@ impl NetworkDriver for Device {
     fn mac(&self) -> MacAddr {
         self.e1000.lock_irq().mac
     }
+
+    fn features(&self) -> Features {
+        Features::TX_CSUM | Features::RX_CSUM
+    }
 }
 
 struct Handler;
@@ -0,0 +1,500 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! HD Audio codecs.
+//!
+//! A codec is made of function groups, which are made of widgets. Each widget has a node ID
+//! (NID) and is connected to the widgets in its connection list. To play audio, a path is found
+//! from an output pin back to an audio output converter (DAC), possibly through mixers and
+//! selectors, and the amplifiers along the path are unmuted.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use bit_field::BitField;
+
+use super::{CommandRing, Error};
+
+/// Node ID of the root node of a codec.
+const ROOT_NODE: u8 = 0;
+
+/// Maximum number of widgets between an output pin and its converter.
+const MAX_PATH_DEPTH: usize = 8;
+
+// Verbs with a 12-bit identifier and an 8-bit payload.
+const VERB_GET_PARAMETER: u32 = 0xf00;
+const VERB_SET_CONNECTION_SELECT: u32 = 0x701;
+const VERB_GET_CONNECTION_LIST_ENTRY: u32 = 0xf02;
+const VERB_SET_POWER_STATE: u32 = 0x705;
+const VERB_SET_STREAM_CHANNEL: u32 = 0x706;
+const VERB_SET_PIN_WIDGET_CONTROL: u32 = 0x707;
+const VERB_SET_EAPD_BTL_ENABLE: u32 = 0x70c;
+const VERB_GET_CONFIG_DEFAULT: u32 = 0xf1c;
+
+// Verbs with a 4-bit identifier and a 16-bit payload.
+const VERB_SET_CONVERTER_FORMAT: u32 = 0x2;
+const VERB_SET_AMPLIFIER_GAIN_MUTE: u32 = 0x3;
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+enum Parameter {
+    VendorId = 0x00,
+    SubordinateNodeCount = 0x04,
+    FunctionGroupType = 0x05,
+    AudioWidgetCapabilities = 0x09,
+    SupportedPcmRates = 0x0a,
+    PinCapabilities = 0x0c,
+    InputAmplifierCapabilities = 0x0d,
+    ConnectionListLength = 0x0e,
+    OutputAmplifierCapabilities = 0x12,
+}
+
+/// Function group type of the audio function group.
+const FUNCTION_GROUP_AUDIO: u32 = 0x01;
+
+// Pin widget control bits.
+const PIN_OUTPUT_ENABLE: u32 = 1 << 6;
+const PIN_HEADPHONE_ENABLE: u32 = 1 << 7;
+
+// Pin capabilities.
+const PIN_CAP_OUTPUT: usize = 4;
+const PIN_CAP_EAPD: usize = 16;
+
+// Audio widget capabilities.
+const WIDGET_CAP_IN_AMP: usize = 1;
+const WIDGET_CAP_OUT_AMP: usize = 2;
+const WIDGET_CAP_AMP_OVERRIDE: usize = 3;
+
+/// Returns the payload of the Set Amplifier Gain/Mute verb that unmutes both channels of the
+/// output amplifier (or the input amplifier at `index`) at the provided gain.
+fn amplifier_gain(output: bool, index: u8, gain: u8) -> u32 {
+    let mut payload = 0u32;
+
+    payload.set_bit(15, output);
+    payload.set_bit(14, !output);
+    payload.set_bit(13, true); // left channel
+    payload.set_bit(12, true); // right channel
+    payload.set_bits(8..12, index as u32);
+    payload.set_bits(0..7, gain as u32);
+
+    payload
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum WidgetType {
+    AudioOutput,
+    AudioInput,
+    Mixer,
+    Selector,
+    PinComplex,
+    Other,
+}
+
+impl WidgetType {
+    fn new(capabilities: u32) -> Self {
+        match capabilities.get_bits(20..24) {
+            0x0 => Self::AudioOutput,
+            0x1 => Self::AudioInput,
+            0x2 => Self::Mixer,
+            0x3 => Self::Selector,
+            0x4 => Self::PinComplex,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Default device of a pin complex, from its configuration default.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PinDevice {
+    LineOut,
+    Speaker,
+    Headphone,
+    Other,
+}
+
+impl PinDevice {
+    /// Returns the preference of the device as the output, lower is better.
+    fn priority(&self) -> usize {
+        match self {
+            Self::Speaker => 0,
+            Self::Headphone => 1,
+            Self::LineOut => 2,
+            Self::Other => 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Widget {
+    typ: WidgetType,
+    capabilities: u32,
+    connections: Vec<u8>,
+    /// Pin capabilities and configuration default, for pin complexes.
+    pin: Option<(u32, u32)>,
+}
+
+impl Widget {
+    /// Returns the default device of the pin complex, or [`None`] if the widget is not a pin
+    /// complex or if nothing is connected to it.
+    fn pin_device(&self) -> Option<PinDevice> {
+        let (_, config) = self.pin?;
+
+        // Port connectivity of one means that no physical connection is present.
+        if config.get_bits(30..32) == 1 {
+            return None;
+        }
+
+        Some(match config.get_bits(20..24) {
+            0x0 => PinDevice::LineOut,
+            0x1 => PinDevice::Speaker,
+            0x2 => PinDevice::Headphone,
+            _ => PinDevice::Other,
+        })
+    }
+
+    fn is_output_pin(&self) -> bool {
+        self.pin
+            .is_some_and(|(capabilities, _)| capabilities.get_bit(PIN_CAP_OUTPUT))
+            && self.pin_device().is_some()
+    }
+}
+
+/// A path from an output pin to an audio output converter. Each node is paired with the
+/// index of the connection that leads towards the converter.
+#[derive(Debug)]
+pub(super) struct OutputPath {
+    pub dac: u8,
+    pin: u8,
+    device: PinDevice,
+    nodes: Vec<(u8, usize)>,
+}
+
+pub(super) struct Codec {
+    address: u8,
+    afg: u8,
+    widgets: BTreeMap<u8, Widget>,
+}
+
+impl Codec {
+    /// Enumerates the widgets of the audio function group of the codec at `address`.
+    pub fn probe(ring: &mut CommandRing, address: u8) -> Result<Self, Error> {
+        let mut this = Self {
+            address,
+            afg: 0,
+            widgets: BTreeMap::new(),
+        };
+
+        let vendor = this.parameter(ring, ROOT_NODE, Parameter::VendorId)?;
+        let (start, count) = this.subordinate_nodes(ring, ROOT_NODE)?;
+
+        let mut afg = None;
+
+        for nid in start..start + count {
+            let typ = this.parameter(ring, nid, Parameter::FunctionGroupType)?;
+
+            if typ.get_bits(0..8) == FUNCTION_GROUP_AUDIO {
+                afg = Some(nid);
+                break;
+            }
+        }
+
+        this.afg = afg.ok_or(Error::NoAudioFunctionGroup)?;
+
+        // Power up the function group, which also powers up its widgets.
+        this.command(ring, this.afg, VERB_SET_POWER_STATE << 8)?;
+
+        let (start, count) = this.subordinate_nodes(ring, this.afg)?;
+
+        for nid in start..start + count {
+            let capabilities = this.parameter(ring, nid, Parameter::AudioWidgetCapabilities)?;
+            let typ = WidgetType::new(capabilities);
+
+            let connections = if capabilities.get_bit(8) {
+                this.connections(ring, nid)?
+            } else {
+                Vec::new()
+            };
+
+            let pin = if typ == WidgetType::PinComplex {
+                let capabilities = this.parameter(ring, nid, Parameter::PinCapabilities)?;
+                let config = this.command(ring, nid, VERB_GET_CONFIG_DEFAULT << 8)?;
+
+                Some((capabilities, config))
+            } else {
+                None
+            };
+
+            this.widgets.insert(
+                nid,
+                Widget {
+                    typ,
+                    capabilities,
+                    connections,
+                    pin,
+                },
+            );
+        }
+
+        log::trace!(
+            "hda: codec {address}: vendor={:#06x}, device={:#06x}, widgets={}",
+            vendor.get_bits(16..32),
+            vendor.get_bits(0..16),
+            this.widgets.len()
+        );
+
+        Ok(this)
+    }
+
+    /// Sends a command to the node `nid` of the codec and returns its response. `verb` holds
+    /// the verb and its payload.
+    fn command(&self, ring: &mut CommandRing, nid: u8, verb: u32) -> Result<u32, Error> {
+        let mut command = verb;
+        command.set_bits(20..28, nid as u32);
+        command.set_bits(28..32, self.address as u32);
+
+        ring.send(command)
+    }
+
+    fn parameter(&self, ring: &mut CommandRing, nid: u8, param: Parameter) -> Result<u32, Error> {
+        self.command(ring, nid, VERB_GET_PARAMETER << 8 | param as u32)
+    }
+
+    /// Returns the NID of the first subordinate node of `nid` and the number of them.
+    fn subordinate_nodes(&self, ring: &mut CommandRing, nid: u8) -> Result<(u8, u8), Error> {
+        let count = self.parameter(ring, nid, Parameter::SubordinateNodeCount)?;
+        Ok((count.get_bits(16..24) as u8, count.get_bits(0..8) as u8))
+    }
+
+    /// Reads the connection list of the widget `nid`.
+    fn connections(&self, ring: &mut CommandRing, nid: u8) -> Result<Vec<u8>, Error> {
+        let length = self.parameter(ring, nid, Parameter::ConnectionListLength)?;
+
+        let long_form = length.get_bit(7);
+        let count = length.get_bits(0..7) as usize;
+
+        // Each response holds four short form entries or two long form entries.
+        let (per_response, entry_bits) = if long_form { (2, 16) } else { (4, 8) };
+
+        let mut connections = Vec::with_capacity(count);
+        let mut previous = 0;
+        let mut index = 0;
+
+        while index < count {
+            let response = self.command(
+                ring,
+                nid,
+                VERB_GET_CONNECTION_LIST_ENTRY << 8 | index as u32,
+            )?;
+
+            for i in 0..per_response.min(count - index) {
+                let entry = response.get_bits(i * entry_bits..(i + 1) * entry_bits);
+
+                // The range bit means that all of the NIDs between the previous entry and
+                // this one are connected.
+                let range = entry.get_bit(entry_bits - 1);
+                let entry = entry.get_bits(0..entry_bits - 1) as u8;
+
+                if range && previous != 0 {
+                    connections.extend(previous + 1..=entry);
+                } else {
+                    connections.push(entry);
+                }
+
+                previous = entry;
+            }
+
+            index += per_response;
+        }
+
+        Ok(connections)
+    }
+
+    /// Returns the amplifier capabilities of the widget `nid`, which are inherited from the
+    /// function group unless the widget overrides them.
+    fn amplifier_capabilities(
+        &self,
+        ring: &mut CommandRing,
+        nid: u8,
+        output: bool,
+    ) -> Result<u32, Error> {
+        let param = if output {
+            Parameter::OutputAmplifierCapabilities
+        } else {
+            Parameter::InputAmplifierCapabilities
+        };
+
+        let overrides = self.widgets[&nid]
+            .capabilities
+            .get_bit(WIDGET_CAP_AMP_OVERRIDE);
+
+        self.parameter(ring, if overrides { nid } else { self.afg }, param)
+    }
+
+    /// Looks for a path from an output pin to an audio output converter, preferring speakers
+    /// over headphones and line outputs.
+    pub fn find_output(&self) -> Option<OutputPath> {
+        let mut pins = self
+            .widgets
+            .iter()
+            .filter(|(_, widget)| widget.is_output_pin())
+            .map(|(nid, widget)| (*nid, widget.pin_device().unwrap()))
+            .collect::<Vec<_>>();
+
+        pins.sort_by_key(|(_, device)| device.priority());
+
+        pins.into_iter().find_map(|(pin, device)| {
+            let mut nodes = Vec::new();
+            let dac = self.find_converter(pin, &mut nodes, 0)?;
+
+            Some(OutputPath {
+                dac,
+                pin,
+                device,
+                nodes,
+            })
+        })
+    }
+
+    /// Walks the connections of `nid` depth-first until an audio output converter is found.
+    /// The widgets leading to it are appended to `nodes`.
+    fn find_converter(&self, nid: u8, nodes: &mut Vec<(u8, usize)>, depth: usize) -> Option<u8> {
+        let widget = self.widgets.get(&nid)?;
+
+        if widget.typ == WidgetType::AudioOutput {
+            return Some(nid);
+        }
+
+        if depth == MAX_PATH_DEPTH {
+            return None;
+        }
+
+        // Only pins, mixers and selectors route audio towards the output.
+        if !matches!(
+            widget.typ,
+            WidgetType::PinComplex | WidgetType::Mixer | WidgetType::Selector
+        ) || (depth != 0 && widget.typ == WidgetType::PinComplex)
+        {
+            return None;
+        }
+
+        for (index, connection) in widget.connections.iter().enumerate() {
+            nodes.push((nid, index));
+
+            if let Some(dac) = self.find_converter(*connection, nodes, depth + 1) {
+                return Some(dac);
+            }
+
+            nodes.pop();
+        }
+
+        None
+    }
+
+    /// Routes the output path, enables the output pin and unmutes the amplifiers on the path.
+    pub fn enable_output(&self, ring: &mut CommandRing, path: &OutputPath) -> Result<(), Error> {
+        for (nid, index) in path.nodes.iter().copied() {
+            let widget = &self.widgets[&nid];
+
+            self.command(ring, nid, VERB_SET_POWER_STATE << 8)?;
+
+            // Mixers sum all of their inputs, while the other widgets select one of them.
+            if widget.typ != WidgetType::Mixer && widget.connections.len() > 1 {
+                self.command(ring, nid, VERB_SET_CONNECTION_SELECT << 8 | index as u32)?;
+            }
+
+            if widget.capabilities.get_bit(WIDGET_CAP_IN_AMP) {
+                let gain = self
+                    .amplifier_capabilities(ring, nid, false)?
+                    .get_bits(0..7);
+                let payload = amplifier_gain(false, index as u8, gain as u8);
+
+                self.command(ring, nid, VERB_SET_AMPLIFIER_GAIN_MUTE << 16 | payload)?;
+            }
+
+            self.unmute_output(ring, nid)?;
+        }
+
+        self.command(ring, path.dac, VERB_SET_POWER_STATE << 8)?;
+        self.unmute_output(ring, path.dac)?;
+
+        let mut control = PIN_OUTPUT_ENABLE;
+
+        if path.device == PinDevice::Headphone {
+            control |= PIN_HEADPHONE_ENABLE;
+        }
+
+        self.command(ring, path.pin, VERB_SET_PIN_WIDGET_CONTROL << 8 | control)?;
+
+        // External amplifiers (e.g. of laptop speakers) are powered down unless EAPD is set.
+        let (capabilities, _) = self.widgets[&path.pin].pin.unwrap();
+
+        if capabilities.get_bit(PIN_CAP_EAPD) {
+            self.command(ring, path.pin, VERB_SET_EAPD_BTL_ENABLE << 8 | 1 << 1)?;
+        }
+
+        log::info!(
+            "hda: codec {}: output through pin {:#x} ({:?}) from converter {:#x}",
+            self.address,
+            path.pin,
+            path.device,
+            path.dac
+        );
+
+        Ok(())
+    }
+
+    /// Unmutes the output amplifier of the widget `nid` at its 0dB gain, if it has one.
+    fn unmute_output(&self, ring: &mut CommandRing, nid: u8) -> Result<(), Error> {
+        if !self.widgets[&nid].capabilities.get_bit(WIDGET_CAP_OUT_AMP) {
+            return Ok(());
+        }
+
+        let gain = self.amplifier_capabilities(ring, nid, true)?.get_bits(0..7);
+        let payload = amplifier_gain(true, 0, gain as u8);
+
+        self.command(ring, nid, VERB_SET_AMPLIFIER_GAIN_MUTE << 16 | payload)?;
+        Ok(())
+    }
+
+    /// Returns the bitmap of the sample rates supported by the converter `nid`, which is
+    /// inherited from the function group if the converter reports none.
+    pub fn supported_rates(&self, ring: &mut CommandRing, nid: u8) -> Result<u32, Error> {
+        let rates = self.parameter(ring, nid, Parameter::SupportedPcmRates)?;
+
+        if rates.get_bits(0..12) != 0 {
+            return Ok(rates.get_bits(0..12));
+        }
+
+        Ok(self
+            .parameter(ring, self.afg, Parameter::SupportedPcmRates)?
+            .get_bits(0..12))
+    }
+
+    /// Sets the stream format of the converter `nid` and connects it to the stream `tag`.
+    pub fn set_format(
+        &self,
+        ring: &mut CommandRing,
+        nid: u8,
+        tag: u8,
+        format: u16,
+    ) -> Result<(), Error> {
+        self.command(ring, nid, VERB_SET_CONVERTER_FORMAT << 16 | format as u32)?;
+        self.command(ring, nid, VERB_SET_STREAM_CHANNEL << 8 | (tag as u32) << 4)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,911 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Intel High Definition Audio controller driver.
+//!
+//! The controller talks to the codecs on the HD Audio link through two DMA rings: commands
+//! (verbs) are placed on the CORB (Command Outbound Ring Buffer) and the codecs place their
+//! responses on the RIRB (Response Inbound Ring Buffer). The codecs are probed for a path from
+//! an output pin to a converter (see [`codec`]), which is then fed by an output stream.
+//!
+//! The output stream plays a ring buffer, split into fragments that are described by a buffer
+//! descriptor list (BDL). The controller raises an interrupt after each fragment, which is
+//! then cleared to silence and handed back to the writers. The ring buffer is exposed as an
+//! OSS style PCM device (`/dev/dsp`): the samples written to it are played with the format set
+//! using the `SNDCTL_DSP_*` ioctls.
+//!
+//! ## Notes
+//! * <https://www.intel.com/content/dam/www/public/us/en/documents/product-specifications/high-definition-audio-specification.pdf>
+//! * Only the first output stream and the first output path that is found are used.
+//! * Only signed 16-bit little-endian samples are supported.
+//! * The stream is only started once a full fragment has been written or when `SNDCTL_DSP_SYNC` is
+//!   issued, so the end of the samples should be flushed with it.
+
+mod codec;
+
+use core::sync::atomic::{fence, Ordering};
+use core::{ffi, ptr};
+
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use bit_field::BitField;
+use spin::Once;
+
+use uapi::sound::*;
+
+use crate::acpi::aml;
+use crate::arch::interrupts::{self, InterruptStack};
+use crate::fs::devfs::{self, Device};
+use crate::fs::inode::{INodeInterface, PollFlags, PollTable};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::*;
+use crate::userland::scheduler::hrtimer;
+use crate::utils::dma::Dma;
+use crate::utils::sync::{BMutex, Mutex, WaitQueue};
+use crate::utils::VolatileCell;
+
+use crate::drivers::pci::{self, *};
+
+use codec::{Codec, OutputPath};
+
+/// Size of a fragment of the playback buffer in bytes.
+const FRAGMENT_SIZE: usize = 4096;
+/// Number of fragments in the playback buffer.
+const FRAGMENT_COUNT: usize = 8;
+const BUFFER_SIZE: usize = FRAGMENT_SIZE * FRAGMENT_COUNT;
+
+/// Tag of the output stream, which identifies it on the link. Tag zero is reserved.
+const STREAM_TAG: u8 = 1;
+
+/// Time a codec has to respond to a command, in microseconds.
+const RESPONSE_TIMEOUT_US: u64 = 10_000;
+
+const GCTL_RESET: u32 = 1 << 0;
+
+const INTCTL_GLOBAL_ENABLE: u32 = 1 << 31;
+
+const CORB_RUN: u8 = 1 << 1;
+const CORBRP_RESET: u16 = 1 << 15;
+
+const RIRB_DMA_ENABLE: u8 = 1 << 1;
+const RIRBWP_RESET: u16 = 1 << 15;
+
+/// Interrupt on completion flag of a buffer descriptor.
+const BDL_IOC: u32 = 1 << 0;
+
+#[derive(Copy, Clone, Debug)]
+enum Error {
+    UnknownBar,
+    /// The controller or a codec did not respond in time.
+    Timeout,
+    NoCodec,
+    NoAudioFunctionGroup,
+    /// None of the codecs has a path from an output pin to a converter.
+    NoOutputPath,
+    NoOutputStream,
+    Interrupted,
+}
+
+#[repr(C)]
+struct Registers {
+    gcap: VolatileCell<u16>,
+    vmin: VolatileCell<u8>,
+    vmaj: VolatileCell<u8>,
+    _outpay: VolatileCell<u16>,
+    _inpay: VolatileCell<u16>,
+    gctl: VolatileCell<u32>,
+    _wakeen: VolatileCell<u16>,
+    statests: VolatileCell<u16>,
+    _gsts: VolatileCell<u16>,
+    _reserved1: [u8; 0xe],
+    intctl: VolatileCell<u32>,
+    intsts: VolatileCell<u32>,
+    _reserved2: [u32; 6],
+    corblbase: VolatileCell<u32>,
+    corbubase: VolatileCell<u32>,
+    corbwp: VolatileCell<u16>,
+    corbrp: VolatileCell<u16>,
+    corbctl: VolatileCell<u8>,
+    _corbsts: VolatileCell<u8>,
+    corbsize: VolatileCell<u8>,
+    _reserved3: u8,
+    rirblbase: VolatileCell<u32>,
+    rirbubase: VolatileCell<u32>,
+    rirbwp: VolatileCell<u16>,
+    rintcnt: VolatileCell<u16>,
+    rirbctl: VolatileCell<u8>,
+    _rirbsts: VolatileCell<u8>,
+    rirbsize: VolatileCell<u8>,
+}
+
+const_assert_eq!(core::mem::offset_of!(Registers, intctl), 0x20);
+const_assert_eq!(core::mem::offset_of!(Registers, corblbase), 0x40);
+const_assert_eq!(core::mem::offset_of!(Registers, rirblbase), 0x50);
+const_assert_eq!(core::mem::offset_of!(Registers, rirbsize), 0x5e);
+
+impl Registers {
+    fn input_streams(&self) -> usize {
+        self.gcap.get().get_bits(8..12) as usize
+    }
+
+    fn output_streams(&self) -> usize {
+        self.gcap.get().get_bits(12..16) as usize
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    struct StreamControl: u16 {
+        const RESET                    = 1 << 0;
+        const RUN                      = 1 << 1;
+        const COMPLETION_INTERRUPT     = 1 << 2;
+        const FIFO_ERROR_INTERRUPT     = 1 << 3;
+        const DESCRIPTOR_ERROR_INTERRUPT = 1 << 4;
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    struct StreamStatus: u8 {
+        const BUFFER_COMPLETE  = 1 << 2;
+        const FIFO_ERROR       = 1 << 3;
+        const DESCRIPTOR_ERROR = 1 << 4;
+    }
+}
+
+/// Stream descriptor registers, which follow the global registers (input streams first,
+/// then output streams and bidirectional streams).
+#[repr(C)]
+struct StreamRegisters {
+    ctl: VolatileCell<u16>,
+    /// Bits 16 to 23 of the control register, which hold the stream tag.
+    ctl_hi: VolatileCell<u8>,
+    sts: VolatileCell<u8>,
+    _lpib: VolatileCell<u32>,
+    cbl: VolatileCell<u32>,
+    lvi: VolatileCell<u16>,
+    _reserved1: u16,
+    _fifos: VolatileCell<u16>,
+    fmt: VolatileCell<u16>,
+    _reserved2: u32,
+    bdpl: VolatileCell<u32>,
+    bdpu: VolatileCell<u32>,
+}
+
+const_assert_eq!(core::mem::size_of::<StreamRegisters>(), 0x20);
+
+impl StreamRegisters {
+    fn control(&self) -> StreamControl {
+        StreamControl::from_bits_retain(self.ctl.get())
+    }
+}
+
+#[repr(C)]
+struct BufferDescriptor {
+    address: u64,
+    length: u32,
+    flags: u32,
+}
+
+/// Busy-waits until `condition` is true, for at most `timeout_us` microseconds.
+fn wait_for<F: FnMut() -> bool>(mut condition: F, timeout_us: u64) -> Result<(), Error> {
+    let deadline = hrtimer::now() + timeout_us * 1000;
+
+    loop {
+        if condition() {
+            return Ok(());
+        }
+
+        if hrtimer::now() >= deadline {
+            return Err(Error::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the value of the size select field and the number of entries of the largest ring
+/// supported by the controller, from the CORBSIZE or RIRBSIZE register.
+fn ring_size(size: u8) -> (u8, usize) {
+    if size.get_bit(6) {
+        (0b10, 256)
+    } else if size.get_bit(5) {
+        (0b01, 16)
+    } else {
+        (0b00, 2)
+    }
+}
+
+/// The CORB and RIRB, used to send commands to the codecs and to receive their responses.
+struct CommandRing {
+    registers: &'static Registers,
+    corb: Dma<[u32]>,
+    rirb: Dma<[u64]>,
+    /// Index of the last response that was read from the RIRB.
+    rirb_rp: usize,
+}
+
+unsafe impl Send for CommandRing {}
+
+impl CommandRing {
+    fn new(registers: &'static Registers) -> Result<Self, Error> {
+        // Stop the DMA engines before programming the rings.
+        registers.corbctl.set(0);
+        registers.rirbctl.set(0);
+
+        wait_for(
+            || {
+                registers.corbctl.get() & CORB_RUN == 0
+                    && registers.rirbctl.get() & RIRB_DMA_ENABLE == 0
+            },
+            1000,
+        )?;
+
+        let (corb_select, corb_entries) = ring_size(registers.corbsize.get());
+        let (rirb_select, rirb_entries) = ring_size(registers.rirbsize.get());
+
+        // SAFETY: Zeroed rings are valid.
+        let corb = unsafe { Dma::<u32>::new_zeroed_slice(corb_entries).assume_init() };
+        let rirb = unsafe { Dma::<u64>::new_zeroed_slice(rirb_entries).assume_init() };
+
+        registers.corblbase.set(corb.addr().as_u64() as u32);
+        registers.corbubase.set((corb.addr().as_u64() >> 32) as u32);
+        registers
+            .corbsize
+            .set(*registers.corbsize.get().set_bits(0..2, corb_select));
+
+        registers.corbwp.set(0);
+        registers.corbrp.set(CORBRP_RESET);
+
+        // NOTE: Some controllers (e.g. the one emulated by QEMU) reset the read pointer right
+        // away and never report the reset bit as set.
+        let _ = wait_for(|| registers.corbrp.get() & CORBRP_RESET != 0, 1000);
+
+        registers.corbrp.set(0);
+        wait_for(|| registers.corbrp.get() & CORBRP_RESET == 0, 1000)?;
+
+        registers.rirblbase.set(rirb.addr().as_u64() as u32);
+        registers.rirbubase.set((rirb.addr().as_u64() >> 32) as u32);
+        registers
+            .rirbsize
+            .set(*registers.rirbsize.get().set_bits(0..2, rirb_select));
+
+        registers.rirbwp.set(RIRBWP_RESET);
+        // Responses are polled, but some controllers do not write any response to the RIRB
+        // unless the response interrupt count is set.
+        registers.rintcnt.set(1);
+
+        registers.corbctl.set(CORB_RUN);
+        registers.rirbctl.set(RIRB_DMA_ENABLE);
+
+        Ok(Self {
+            registers,
+            corb,
+            rirb,
+            rirb_rp: 0,
+        })
+    }
+
+    /// Places the provided command on the CORB and waits for its response.
+    fn send(&mut self, command: u32) -> Result<u32, Error> {
+        let registers = self.registers;
+
+        let wp = (registers.corbwp.get().get_bits(0..8) as usize + 1) % self.corb.len();
+
+        // SAFETY: The entry is in bounds of the CORB.
+        unsafe { ptr::addr_of_mut!(self.corb[wp]).write_volatile(command) };
+
+        fence(Ordering::SeqCst);
+        registers.corbwp.set(wp as u16);
+
+        loop {
+            let rp = self.rirb_rp;
+
+            wait_for(
+                || registers.rirbwp.get().get_bits(0..8) as usize != rp,
+                RESPONSE_TIMEOUT_US,
+            )?;
+
+            self.rirb_rp = (self.rirb_rp + 1) % self.rirb.len();
+
+            // SAFETY: The entry is in bounds of the RIRB.
+            let response = unsafe { ptr::addr_of!(self.rirb[self.rirb_rp]).read_volatile() };
+
+            // Unsolicited responses (e.g. jack sense events) are not used.
+            if !response.get_bit(36) {
+                return Ok(response as u32);
+            }
+        }
+    }
+}
+
+/// A sample rate supported by the HD Audio link, which is a multiple or a fraction of either
+/// 48 kHz or 44.1 kHz.
+struct Rate {
+    hz: u32,
+    /// Bit of the rate in the supported PCM rates of a converter.
+    bit: usize,
+    base_44k1: bool,
+    multiplier: u16,
+    divisor: u16,
+}
+
+impl Rate {
+    const fn new(hz: u32, bit: usize, base_44k1: bool, multiplier: u16, divisor: u16) -> Self {
+        Self {
+            hz,
+            bit,
+            base_44k1,
+            multiplier,
+            divisor,
+        }
+    }
+}
+
+const RATES: [Rate; 11] = [
+    Rate::new(8000, 0, false, 1, 6),
+    Rate::new(11025, 1, true, 1, 4),
+    Rate::new(16000, 2, false, 1, 3),
+    Rate::new(22050, 3, true, 1, 2),
+    Rate::new(32000, 4, false, 2, 3),
+    Rate::new(44100, 5, true, 1, 1),
+    Rate::new(48000, 6, false, 1, 1),
+    Rate::new(88200, 7, true, 2, 1),
+    Rate::new(96000, 8, false, 2, 1),
+    Rate::new(176400, 9, true, 4, 1),
+    Rate::new(192000, 10, false, 4, 1),
+];
+
+/// Index of 48 kHz in [`RATES`], which every codec is required to support.
+const DEFAULT_RATE: usize = 6;
+
+/// Returns the index of the supported rate that is the closest to `hz`. `supported` is the
+/// bitmap of the supported PCM rates of the converter.
+fn closest_rate(supported: u32, hz: u32) -> usize {
+    RATES
+        .iter()
+        .enumerate()
+        .filter(|(_, rate)| supported.get_bit(rate.bit))
+        .min_by_key(|(_, rate)| rate.hz.abs_diff(hz))
+        .map_or(DEFAULT_RATE, |(index, _)| index)
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PcmFormat {
+    rate: usize,
+    channels: u8,
+}
+
+impl PcmFormat {
+    /// Returns the stream format descriptor, shared by the stream and the converter.
+    fn descriptor(&self) -> u16 {
+        let rate = &RATES[self.rate];
+        let mut format = 0u16;
+
+        format.set_bit(14, rate.base_44k1);
+        format.set_bits(11..14, rate.multiplier - 1);
+        format.set_bits(8..11, rate.divisor - 1);
+        format.set_bits(4..7, 0b001); // 16 bits per sample
+        format.set_bits(0..4, self.channels as u16 - 1);
+
+        format
+    }
+}
+
+/// The output stream and its playback buffer.
+struct Stream {
+    registers: &'static StreamRegisters,
+    /// Index of the stream descriptor, which is its bit in the interrupt registers.
+    index: usize,
+    format: PcmFormat,
+
+    bdl: Dma<[BufferDescriptor]>,
+    buffer: Dma<[u8]>,
+
+    running: bool,
+    /// Offset of the fragment that is being played.
+    play_pos: usize,
+    /// Offset at which the next samples are written.
+    write_pos: usize,
+    /// Number of bytes that were written but were not played yet, starting at `play_pos`.
+    queued: usize,
+}
+
+unsafe impl Send for Stream {}
+
+impl Stream {
+    fn new(base: VirtAddr, index: usize, format: PcmFormat) -> Self {
+        let registers = (base + 0x80u64 + 0x20 * index as u64)
+            .read_mut::<StreamRegisters>()
+            .unwrap();
+
+        // SAFETY: The buffer descriptors are initialized below and a zeroed buffer is silent.
+        let mut bdl =
+            unsafe { Dma::<BufferDescriptor>::new_zeroed_slice(FRAGMENT_COUNT).assume_init() };
+        let buffer = unsafe { Dma::<u8>::new_zeroed_slice(BUFFER_SIZE).assume_init() };
+
+        for (i, descriptor) in bdl.iter_mut().enumerate() {
+            descriptor.address = (buffer.addr() + i * FRAGMENT_SIZE).as_u64();
+            descriptor.length = FRAGMENT_SIZE as u32;
+            descriptor.flags = BDL_IOC;
+        }
+
+        Self {
+            registers,
+            index,
+            format,
+
+            bdl,
+            buffer,
+
+            running: false,
+            play_pos: 0,
+            write_pos: 0,
+            queued: 0,
+        }
+    }
+
+    /// Returns the number of bytes that can be written without blocking.
+    fn free(&self) -> usize {
+        BUFFER_SIZE - self.queued
+    }
+
+    /// Copies as much of `samples` as fits into the playback buffer. Returns the number of
+    /// bytes that were copied.
+    fn write(&mut self, samples: &[u8]) -> usize {
+        let count = samples.len().min(self.free());
+        let mut copied = 0;
+
+        while copied < count {
+            let size = (count - copied).min(BUFFER_SIZE - self.write_pos);
+
+            self.buffer[self.write_pos..self.write_pos + size]
+                .copy_from_slice(&samples[copied..copied + size]);
+
+            self.write_pos = (self.write_pos + size) % BUFFER_SIZE;
+            copied += size;
+        }
+
+        self.queued += count;
+        count
+    }
+
+    /// Resets the stream descriptor, which also resets its position to the start of the
+    /// buffer.
+    fn reset(&self) {
+        let registers = self.registers;
+
+        registers.ctl.set(StreamControl::RESET.bits());
+        let entered = wait_for(|| registers.control().contains(StreamControl::RESET), 1000);
+
+        registers.ctl.set(0);
+        let left = wait_for(|| !registers.control().contains(StreamControl::RESET), 1000);
+
+        if entered.and(left).is_err() {
+            log::warn!("hda: timed out while resetting stream {}", self.index);
+        }
+    }
+
+    fn start(&mut self) {
+        self.reset();
+
+        let registers = self.registers;
+        let bdl = self.bdl.addr().as_u64();
+
+        registers.bdpl.set(bdl as u32);
+        registers.bdpu.set((bdl >> 32) as u32);
+        registers.cbl.set(BUFFER_SIZE as u32);
+        registers.lvi.set(FRAGMENT_COUNT as u16 - 1);
+        registers.fmt.set(self.format.descriptor());
+        registers.ctl_hi.set(STREAM_TAG << 4);
+
+        fence(Ordering::SeqCst);
+
+        registers.ctl.set(
+            (StreamControl::RUN
+                | StreamControl::COMPLETION_INTERRUPT
+                | StreamControl::FIFO_ERROR_INTERRUPT
+                | StreamControl::DESCRIPTOR_ERROR_INTERRUPT)
+                .bits(),
+        );
+
+        self.running = true;
+    }
+
+    /// Stops the stream and discards the samples that were not played yet.
+    fn stop(&mut self) {
+        let registers = self.registers;
+
+        registers
+            .ctl
+            .set((registers.control() - StreamControl::RUN).bits());
+
+        if wait_for(|| !registers.control().contains(StreamControl::RUN), 1000).is_err() {
+            log::warn!("hda: timed out while stopping stream {}", self.index);
+        }
+
+        self.buffer.fill(0);
+
+        self.running = false;
+        self.play_pos = 0;
+        self.write_pos = 0;
+        self.queued = 0;
+    }
+
+    /// Called once the fragment at `play_pos` has been played. The stream is stopped once all
+    /// of the written samples have been played.
+    fn complete_fragment(&mut self) {
+        if !self.running {
+            return;
+        }
+
+        // Clear the fragment so that the stream plays silence if the writers fall behind.
+        self.buffer[self.play_pos..self.play_pos + FRAGMENT_SIZE].fill(0);
+        self.play_pos = (self.play_pos + FRAGMENT_SIZE) % BUFFER_SIZE;
+
+        if self.queued > FRAGMENT_SIZE {
+            self.queued -= FRAGMENT_SIZE;
+        } else {
+            self.stop();
+        }
+    }
+}
+
+struct Hda {
+    registers: &'static Registers,
+    ring: BMutex<CommandRing>,
+
+    codec: Codec,
+    path: OutputPath,
+    /// Bitmap of the sample rates supported by the converter.
+    rates: u32,
+
+    stream: Mutex<Stream>,
+    wq: WaitQueue,
+
+    marker: usize,
+}
+
+unsafe impl Sync for Hda {}
+unsafe impl Send for Hda {}
+
+impl Hda {
+    fn new(header: &PciHeader) -> Result<Self, Error> {
+        header.enable_bus_mastering();
+        header.enable_mmio();
+
+        let bar0 = header.get_bar(0).ok_or(Error::UnknownBar)?;
+
+        let base = match bar0 {
+            Bar::Memory64 { address, .. } => {
+                pci::map_bar(&bar0);
+                PhysAddr::new(address).as_hhdm_virt()
+            }
+
+            Bar::Memory32 { address, .. } => PhysAddr::new(address as u64).as_hhdm_virt(),
+            _ => return Err(Error::UnknownBar),
+        };
+
+        let registers: &'static Registers = base.read_mut::<Registers>().unwrap();
+
+        log::trace!(
+            "hda: version {}.{} (input streams={}, output streams={})",
+            registers.vmaj.get(),
+            registers.vmin.get(),
+            registers.input_streams(),
+            registers.output_streams()
+        );
+
+        registers.intctl.set(0);
+
+        // Reset the controller and the link.
+        registers.gctl.set(registers.gctl.get() & !GCTL_RESET);
+        wait_for(|| registers.gctl.get() & GCTL_RESET == 0, 100_000)?;
+
+        registers.gctl.set(registers.gctl.get() | GCTL_RESET);
+        wait_for(|| registers.gctl.get() & GCTL_RESET != 0, 100_000)?;
+
+        // The codecs request a state change within 521 microseconds after the link is out of
+        // reset.
+        hrtimer::sleep(1_000_000).map_err(|_| Error::Interrupted)?;
+
+        let codecs = registers.statests.get();
+
+        if codecs == 0 {
+            return Err(Error::NoCodec);
+        }
+
+        if registers.output_streams() == 0 {
+            return Err(Error::NoOutputStream);
+        }
+
+        let mut ring = CommandRing::new(registers)?;
+
+        let (codec, path) = (0..15)
+            .filter(|address| codecs.get_bit(*address))
+            .find_map(|address| {
+                let codec = Codec::probe(&mut ring, address as u8)
+                    .inspect_err(|err| log::warn!("hda: codec {address}: {err:?}"))
+                    .ok()?;
+
+                let path = codec.find_output()?;
+                Some((codec, path))
+            })
+            .ok_or(Error::NoOutputPath)?;
+
+        codec.enable_output(&mut ring, &path)?;
+
+        let rates = codec.supported_rates(&mut ring, path.dac)?;
+        let format = PcmFormat {
+            rate: closest_rate(rates, 48000),
+            channels: 2,
+        };
+
+        codec.set_format(&mut ring, path.dac, STREAM_TAG, format.descriptor())?;
+
+        // The output streams follow the input streams.
+        let stream = Stream::new(base, registers.input_streams(), format);
+
+        let gsi = aml::get_subsystem().pci_route_pin(
+            0,
+            header.bus(),
+            header.device(),
+            header.function(),
+            header.interrupt_pin(),
+        );
+
+        let vector = interrupts::allocate_vector();
+        interrupts::register_handler(vector, irq_handler);
+
+        crate::arch::apic::io_apic_setup_legacy_irq(gsi, vector, 0);
+
+        registers
+            .intctl
+            .set(INTCTL_GLOBAL_ENABLE | 1 << stream.index);
+
+        Ok(Self {
+            registers,
+            ring: BMutex::new(ring),
+
+            codec,
+            path,
+            rates,
+
+            stream: Mutex::new(stream),
+            wq: WaitQueue::new(),
+
+            marker: devfs::alloc_device_marker(),
+        })
+    }
+
+    /// Stops the stream, discarding the samples that were not played yet, and switches to the
+    /// provided format.
+    fn set_format(&self, format: PcmFormat) -> fs::Result<()> {
+        {
+            let mut stream = self.stream.lock_irq();
+            stream.stop();
+            stream.format = format;
+        }
+
+        self.wq.notify_all();
+
+        self.codec
+            .set_format(
+                &mut self.ring.lock(),
+                self.path.dac,
+                STREAM_TAG,
+                format.descriptor(),
+            )
+            .map_err(|err| {
+                log::warn!("hda: failed to set the converter format: {err:?}");
+                FileSystemError::Busy
+            })
+    }
+
+    /// Starts playing the samples that were written and waits until all of them have been
+    /// played.
+    fn drain(&self) -> fs::Result<()> {
+        {
+            let mut stream = self.stream.lock_irq();
+
+            if !stream.running && stream.queued != 0 {
+                stream.start();
+            }
+        }
+
+        self.wq
+            .block_on(&self.stream, |stream| stream.queued == 0)
+            .map_err(|_| FileSystemError::Interrupted)?;
+
+        Ok(())
+    }
+
+    fn handle_irq(&self) {
+        let status = self.registers.intsts.get();
+        let mut stream = self.stream.lock_irq();
+
+        if !status.get_bit(stream.index) {
+            return;
+        }
+
+        // Acknowledge the interrupt (the status bits are write-1-to-clear).
+        let stream_status = StreamStatus::from_bits_retain(stream.registers.sts.get());
+        stream.registers.sts.set(stream_status.bits());
+
+        if stream_status.intersects(StreamStatus::FIFO_ERROR | StreamStatus::DESCRIPTOR_ERROR) {
+            log::warn!("hda: stream {} error: {stream_status:?}", stream.index);
+        }
+
+        if stream_status.contains(StreamStatus::BUFFER_COMPLETE) {
+            stream.complete_fragment();
+        }
+
+        core::mem::drop(stream);
+        self.wq.notify_all();
+    }
+}
+
+impl Device for Hda {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        String::from("dsp")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        HDA.get().unwrap().clone()
+    }
+}
+
+impl INodeInterface for Hda {
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let mut written = 0;
+
+        while written < buffer.len() {
+            let stream = self.wq.block_on(&self.stream, |stream| stream.free() != 0);
+
+            let Ok(mut stream) = stream else {
+                if written != 0 {
+                    break;
+                }
+
+                return Err(FileSystemError::Interrupted);
+            };
+
+            written += stream.write(&buffer[written..]);
+
+            if !stream.running && stream.queued >= FRAGMENT_SIZE {
+                stream.start();
+            }
+        }
+
+        Ok(written)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        if self.stream.lock_irq().free() != 0 {
+            Ok(PollFlags::OUT)
+        } else {
+            Ok(PollFlags::empty())
+        }
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        let format = self.stream.lock_irq().format;
+
+        match command {
+            SNDCTL_DSP_RESET => {
+                self.stream.lock_irq().stop();
+                self.wq.notify_all();
+            }
+
+            SNDCTL_DSP_SYNC => self.drain()?,
+
+            SNDCTL_DSP_SPEED => {
+                let speed = VirtAddr::new(arg as u64).read_mut::<ffi::c_int>()?;
+                let rate = closest_rate(self.rates, (*speed).max(0) as u32);
+
+                self.set_format(PcmFormat { rate, ..format })?;
+                *speed = RATES[rate].hz as ffi::c_int;
+            }
+
+            SNDCTL_DSP_CHANNELS => {
+                let channels = VirtAddr::new(arg as u64).read_mut::<ffi::c_int>()?;
+                let count = (*channels).clamp(1, 2) as u8;
+
+                self.set_format(PcmFormat {
+                    channels: count,
+                    ..format
+                })?;
+
+                *channels = count as ffi::c_int;
+            }
+
+            // Signed 16-bit little-endian samples are the only supported format, which is
+            // reported back whatever the requested format is.
+            SNDCTL_DSP_SETFMT | SNDCTL_DSP_GETFMTS => {
+                *VirtAddr::new(arg as u64).read_mut::<ffi::c_int>()? = AFMT_S16_LE;
+            }
+
+            SNDCTL_DSP_GETBLKSIZE => {
+                *VirtAddr::new(arg as u64).read_mut::<ffi::c_int>()? = FRAGMENT_SIZE as _;
+            }
+
+            SNDCTL_DSP_GETOSPACE => {
+                let free = self.stream.lock_irq().free();
+
+                *VirtAddr::new(arg as u64).read_mut::<AudioBufInfo>()? = AudioBufInfo {
+                    fragments: (free / FRAGMENT_SIZE) as _,
+                    fragstotal: FRAGMENT_COUNT as _,
+                    fragsize: FRAGMENT_SIZE as _,
+                    bytes: free as _,
+                };
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+}
+
+static HDA: Once<Arc<Hda>> = Once::new();
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn handles(&self, _vendor_id: Vendor, device_id: DeviceType) -> bool {
+        device_id == DeviceType::HdAudioController
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        if HDA.get().is_some() {
+            log::warn!("hda: ignoring additional controller");
+            return;
+        }
+
+        let hda = match Hda::new(header) {
+            Ok(hda) => Arc::new(hda),
+            Err(err) => {
+                log::error!("hda: failed to initialize the controller: {err:?}");
+                return;
+            }
+        };
+
+        HDA.call_once(|| hda.clone());
+        devfs::install_device(hda).expect("hda: failed to install the PCM device");
+    }
+}
+
+fn irq_handler(_stack: &mut InterruptStack) {
+    if let Some(hda) = HDA.get() {
+        hda.handle_irq();
+    }
+}
+
+fn init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::module_init!(init, ModuleType::Block);
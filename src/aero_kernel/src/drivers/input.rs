@@ -0,0 +1,431 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Input core: `/dev/input/event[0-9]+`.
+//!
+//! Input drivers register an [`InputDevice`] with the event codes it supports and report key,
+//! relative, absolute and switch events through it. The events are timestamped and queued as evdev
+//! records, with a `SYN_REPORT` event marking the end of each packet (see [`InputDevice::sync`]).
+//! This is the interface expected by libinput and by most GUI toolkits.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::alloc::Global;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+
+use spin::Once;
+use uapi::input::*;
+use uapi::ioctl;
+
+use crate::fs::cache::INodeCacheItem;
+use crate::fs::devfs::{self, Device};
+use crate::fs::inode::{INodeInterface, PollFlags, PollTable};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::utils::bitmap::Bitmap;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+/// Maximum number of queued events. When the queue overflows, the queued events are dropped
+/// and replaced by a `SYN_DROPPED` event, so readers know they have to resynchronize.
+const EVDEV_BUFFER_SIZE: usize = 64;
+
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+static INPUT_DIR: Once<INodeCacheItem> = Once::new();
+
+/// The event types and codes supported by an input device.
+pub struct Capabilities {
+    events: Bitmap<Global>,
+    keys: Bitmap<Global>,
+    rel: Bitmap<Global>,
+    abs: Bitmap<Global>,
+    abs_info: BTreeMap<u16, InputAbsInfo>,
+    sw: Bitmap<Global>,
+}
+
+impl Capabilities {
+    pub fn new() -> Self {
+        let mut events = Bitmap::new_in(Global, EV_MAX as usize + 1);
+        events.set(EV_SYN as usize, true);
+
+        Self {
+            events,
+            keys: Bitmap::new_in(Global, KEY_MAX as usize + 1),
+            rel: Bitmap::new_in(Global, REL_MAX as usize + 1),
+            abs: Bitmap::new_in(Global, ABS_MAX as usize + 1),
+            abs_info: BTreeMap::new(),
+            sw: Bitmap::new_in(Global, SW_MAX as usize + 1),
+        }
+    }
+
+    /// Marks the device as capable of reporting `code` events of type `typ`.
+    pub fn set(&mut self, typ: u16, code: u16) {
+        match typ {
+            EV_KEY => self.keys.set(code as usize, true),
+            EV_REL => self.rel.set(code as usize, true),
+            EV_SW => self.sw.set(code as usize, true),
+            EV_ABS => return self.set_abs(code, InputAbsInfo::default()),
+            _ => unreachable!("input: unsupported event type {typ}"),
+        }
+
+        self.events.set(typ as usize, true);
+    }
+
+    /// Marks the device as capable of reporting the absolute axis `code`, with the range and
+    /// the initial value in `info`.
+    pub fn set_abs(&mut self, code: u16, info: InputAbsInfo) {
+        self.abs.set(code as usize, true);
+        self.abs_info.insert(code, info);
+        self.events.set(EV_ABS as usize, true);
+    }
+
+    fn bits(&self, typ: u16) -> Option<(&Bitmap<Global>, u16)> {
+        match typ {
+            // `EVIOCGBIT(0, len)` returns the supported event types.
+            EV_SYN => Some((&self.events, EV_MAX)),
+            EV_KEY => Some((&self.keys, KEY_MAX)),
+            EV_REL => Some((&self.rel, REL_MAX)),
+            EV_ABS => Some((&self.abs, ABS_MAX)),
+            EV_SW => Some((&self.sw, SW_MAX)),
+            _ => None,
+        }
+    }
+
+    fn supports(&self, typ: u16, code: u16) -> bool {
+        self.bits(typ)
+            .is_some_and(|(bits, max)| code <= max && bits.is_set(code as usize))
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct InputState {
+    keys_down: Bitmap<Global>,
+    switches: Bitmap<Global>,
+    abs_info: BTreeMap<u16, InputAbsInfo>,
+    queue: VecDeque<InputEvent>,
+    /// Whether events were reported since the last `SYN_REPORT`.
+    pending: bool,
+}
+
+impl InputState {
+    fn push(&mut self, typ: u16, code: u16, value: i32) {
+        let time = crate::arch::time::get_realtime_clock();
+
+        if self.queue.len() == EVDEV_BUFFER_SIZE {
+            self.queue.clear();
+            self.push(EV_SYN, SYN_DROPPED, 0);
+        }
+
+        self.queue.push_back(InputEvent {
+            sec: time.tv_sec as i64,
+            usec: time.tv_nsec as i64 / 1000,
+            typ,
+            code,
+            value,
+        });
+    }
+}
+
+pub struct InputDevice {
+    marker: usize,
+    index: usize,
+    name: String,
+    id: InputId,
+    capabilities: Capabilities,
+
+    state: Mutex<InputState>,
+    wq: WaitQueue,
+    sref: Weak<Self>,
+}
+
+impl InputDevice {
+    /// Reports an event. Events that the device does not support and events that do not
+    /// change the state of the device (e.g. a key that is already down being pressed, or a
+    /// relative motion of zero) are dropped.
+    pub fn report(&self, typ: u16, code: u16, value: i32) {
+        if !self.capabilities.supports(typ, code) {
+            return;
+        }
+
+        let mut state = self.state.lock_irq();
+
+        match typ {
+            // A value of 2 is an autorepeat.
+            EV_KEY if value != 2 => {
+                if state.keys_down.is_set(code as usize) == (value != 0) {
+                    return;
+                }
+
+                state.keys_down.set(code as usize, value != 0);
+            }
+
+            EV_REL if value == 0 => return,
+
+            EV_SW => {
+                if state.switches.is_set(code as usize) == (value != 0) {
+                    return;
+                }
+
+                state.switches.set(code as usize, value != 0);
+            }
+
+            EV_ABS => {
+                let info = state.abs_info.get_mut(&code).unwrap();
+
+                if info.value == value {
+                    return;
+                }
+
+                info.value = value;
+            }
+
+            _ => {}
+        }
+
+        state.push(typ, code, value);
+        state.pending = true;
+    }
+
+    /// Reports that a key was pressed or released.
+    pub fn report_key(&self, code: u16, pressed: bool) {
+        self.report(EV_KEY, code, pressed as i32);
+    }
+
+    /// Marks the end of a packet, making the events reported since the last packet visible
+    /// to the readers.
+    pub fn sync(&self) {
+        let mut state = self.state.lock_irq();
+
+        if !state.pending {
+            return;
+        }
+
+        state.push(EV_SYN, SYN_REPORT, 0);
+        state.pending = false;
+
+        core::mem::drop(state);
+        self.wq.notify_all();
+    }
+}
+
+/// Copies the bits `0..=max` of `bits` to `buffer`, using the layout of the Linux bitmaps.
+/// Returns the number of bytes written.
+fn copy_bitmap(bits: &Bitmap<Global>, max: u16, buffer: &mut [u8]) -> usize {
+    let size = core::cmp::min(buffer.len(), max as usize / 8 + 1);
+    buffer[..size].fill(0);
+
+    for code in 0..core::cmp::min(size * 8, max as usize + 1) {
+        if bits.is_set(code) {
+            buffer[code / 8] |= 1 << (code % 8);
+        }
+    }
+
+    size
+}
+
+impl Device for InputDevice {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        alloc::format!("event{}", self.index)
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl INodeInterface for InputDevice {
+    /// Reads as many whole events as fit in `buffer`. Reads do not block, so readers are
+    /// expected to poll the device.
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let size = core::mem::size_of::<InputEvent>();
+
+        if buffer.len() < size {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let mut state = self.state.lock_irq();
+
+        if state.queue.is_empty() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let mut read = 0;
+
+        for chunk in buffer.chunks_exact_mut(size) {
+            let Some(event) = state.queue.pop_front() else {
+                break;
+            };
+
+            unsafe {
+                chunk
+                    .as_mut_ptr()
+                    .cast::<InputEvent>()
+                    .write_unaligned(event)
+            };
+            read += size;
+        }
+
+        Ok(read)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(e) = table {
+            e.insert(&self.wq)
+        }
+
+        if !self.state.lock_irq().queue.is_empty() {
+            Ok(PollFlags::IN)
+        } else {
+            Ok(PollFlags::empty())
+        }
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        match command {
+            EVIOCGVERSION => {
+                *VirtAddr::new(arg as _).read_mut::<i32>()? = EV_VERSION;
+                Ok(0)
+            }
+
+            EVIOCGID => {
+                *VirtAddr::new(arg as _).read_mut::<InputId>()? = self.id;
+                Ok(0)
+            }
+
+            // There is a single reader per device for now, so grabbing it is a no-op.
+            EVIOCGRAB => Ok(0),
+
+            _ if is_evioc_read(command) => {
+                let nr = ioctl::ioc_nr(command);
+                let size = ioctl::ioc_size(command);
+
+                // The buffer is not necessarily aligned, so it cannot be validated as a slice.
+                let start = VirtAddr::new(arg as _).read_mut::<u8>()?;
+                let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, size) };
+
+                match nr {
+                    EVIOCGNAME_NR => {
+                        // Copy the name along with its NUL terminator.
+                        let name = self.name.bytes().chain(core::iter::once(0));
+                        let mut size = 0;
+
+                        for (dst, byte) in buffer.iter_mut().zip(name) {
+                            *dst = byte;
+                            size += 1;
+                        }
+
+                        Ok(size)
+                    }
+
+                    EVIOCGPHYS_NR | EVIOCGUNIQ_NR => Err(FileSystemError::EntryNotFound),
+
+                    EVIOCGKEY_NR => {
+                        let state = self.state.lock_irq();
+                        Ok(copy_bitmap(&state.keys_down, KEY_MAX, buffer))
+                    }
+
+                    EVIOCGSW_NR => {
+                        let state = self.state.lock_irq();
+                        Ok(copy_bitmap(&state.switches, SW_MAX, buffer))
+                    }
+
+                    // There are no device properties, LEDs or sounds.
+                    EVIOCGPROP_NR | EVIOCGLED_NR | EVIOCGSND_NR => {
+                        buffer.fill(0);
+                        Ok(buffer.len())
+                    }
+
+                    _ if (EVIOCGBIT_NR..EVIOCGBIT_NR + EV_MAX as usize + 1).contains(&nr) => {
+                        let typ = (nr - EVIOCGBIT_NR) as u16;
+
+                        match self.capabilities.bits(typ) {
+                            Some((bits, max)) => Ok(copy_bitmap(bits, max, buffer)),
+
+                            // The device does not report events of this type.
+                            None => {
+                                buffer.fill(0);
+                                Ok(buffer.len())
+                            }
+                        }
+                    }
+
+                    _ if (EVIOCGABS_NR..EVIOCGABS_NR + ABS_MAX as usize + 1).contains(&nr) => {
+                        let code = (nr - EVIOCGABS_NR) as u16;
+                        let state = self.state.lock_irq();
+                        let info = state
+                            .abs_info
+                            .get(&code)
+                            .ok_or(FileSystemError::InvalidArgument)?;
+
+                        *VirtAddr::new(arg as _).read_mut::<InputAbsInfo>()? = *info;
+                        Ok(0)
+                    }
+
+                    _ => Err(FileSystemError::NotSupported),
+                }
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+}
+
+/// Registers an input device and installs it at `/dev/input/eventN`.
+pub fn register(name: &str, id: InputId, capabilities: Capabilities) -> Arc<InputDevice> {
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+
+    let device = Arc::new_cyclic(|sref| InputDevice {
+        marker: devfs::alloc_device_marker(),
+        index,
+        name: String::from(name),
+        id,
+
+        state: Mutex::new(InputState {
+            keys_down: Bitmap::new_in(Global, KEY_MAX as usize + 1),
+            switches: Bitmap::new_in(Global, SW_MAX as usize + 1),
+            abs_info: capabilities.abs_info.clone(),
+            queue: VecDeque::new(),
+            pending: false,
+        }),
+
+        capabilities,
+        wq: WaitQueue::new(),
+        sref: sref.clone(),
+    });
+
+    let dir = INPUT_DIR.call_once(|| {
+        devfs::DEV_FILESYSTEM
+            .root_dir()
+            .inode()
+            .mkdir("input")
+            .expect("devfs: failed to create input directory")
+    });
+
+    devfs::install_device_at(dir.clone(), device.clone()).expect("input: failed to install device");
+
+    log::trace!("input: registered `{name}` as event{index}");
+    device
+}
@@ -17,7 +17,8 @@
 
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
-use spin::RwLock;
+use spin::{Once, RwLock};
+use uapi::input::{InputId, BUS_I8042, EV_KEY};
 
 use crate::arch::interrupts::{self, InterruptStack};
 use crate::fs;
@@ -25,6 +26,8 @@ use crate::fs;
 use crate::arch::{apic, io};
 use crate::fs::devfs::{self, Device};
 use crate::fs::inode::{INodeInterface, PollFlags};
+
+use super::input::{self, Capabilities, InputDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
 pub trait KeyboardListener: Send + Sync {
@@ -33,6 +36,7 @@ pub trait KeyboardListener: Send + Sync {
 
 static PS2_KEYBOARD_STATE: Mutex<Ps2KeyboardState> = Mutex::new(Ps2KeyboardState::new());
 static KEYBOARD_LISTENER: RwLock<Vec<Arc<dyn KeyboardListener>>> = RwLock::new(Vec::new());
+static KEYBOARD_INPUT: Once<Arc<InputDevice>> = Once::new();
 
 struct Ps2KeyboardState {
     special: bool,
@@ -297,10 +301,26 @@ pub fn ps2_keyboard_init() {
 
     super::mouse::ps2_mouse_init();
 
-    // TODO: Move this into /dev/input instead
     // TODO: Add support for multiple keyboards
     register_keyboard_listener(KEYBOARD.clone());
     devfs::install_device(KEYBOARD.clone()).expect("failed to install keyboard device");
+
+    KEYBOARD_INPUT.call_once(|| {
+        let mut capabilities = Capabilities::new();
+
+        for code in 1..=KeyCode::KEY_COMPOSE as u16 {
+            capabilities.set(EV_KEY, code);
+        }
+
+        let id = InputId {
+            bustype: BUS_I8042,
+            vendor: 0x0001,
+            product: 0x0001,
+            version: 0xab83,
+        };
+
+        input::register("AT Raw Set 2 keyboard", id, capabilities)
+    });
 }
 
 pub fn register_keyboard_listener(listener: Arc<dyn KeyboardListener>) {
@@ -439,6 +459,11 @@ pub fn keyboard_irq_handler(_stack: &mut InterruptStack) {
             for listener in listeners.iter() {
                 listener.on_key(keycode, released);
             }
+
+            if let Some(input) = KEYBOARD_INPUT.get() {
+                input.report_key(keycode as u16, !released);
+                input.sync();
+            }
         }
     }
 }
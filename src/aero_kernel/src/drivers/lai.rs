@@ -15,14 +15,18 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
+use crate::acpi::aml::{AmlHandle, AmlValue};
 use crate::acpi::{aml, fadt, get_acpi_table};
 
 use crate::mem::paging::PhysAddr;
 
 use crate::arch::io;
-use crate::userland::scheduler;
+use crate::userland::scheduler::hrtimer;
+use crate::utils::sync::Mutex;
 
 use super::pci::PciHeader;
 
@@ -50,10 +54,7 @@ impl lai::Host for LaiHost {
     }
 
     fn sleep(&self, ms: u64) {
-        scheduler::get_scheduler()
-            .inner
-            .sleep(Some(ms as usize / 1000))
-            .expect("lai: unexpected signal during sleep")
+        hrtimer::sleep(ms * 1000000).expect("lai: unexpected signal during sleep")
     }
 
     // Port I/O functions:
@@ -82,20 +83,18 @@ impl lai::Host for LaiHost {
     }
 
     // PCI read functions:
-    //
-    // todo: do not ignore the segment once we use MCFG.
-    fn pci_readb(&self, _seg: u16, bus: u8, slot: u8, fun: u8, offset: u16) -> u8 {
-        let header = PciHeader::new(bus, slot, fun);
+    fn pci_readb(&self, seg: u16, bus: u8, slot: u8, fun: u8, offset: u16) -> u8 {
+        let header = PciHeader::with_segment(seg, bus, slot, fun);
         unsafe { header.read::<u8>(offset as u32) as u8 }
     }
 
-    fn pci_readw(&self, _seg: u16, bus: u8, slot: u8, fun: u8, offset: u16) -> u16 {
-        let header = PciHeader::new(bus, slot, fun);
+    fn pci_readw(&self, seg: u16, bus: u8, slot: u8, fun: u8, offset: u16) -> u16 {
+        let header = PciHeader::with_segment(seg, bus, slot, fun);
         unsafe { header.read::<u16>(offset as u32) as u16 }
     }
 
-    fn pci_readd(&self, _seg: u16, bus: u8, slot: u8, fun: u8, offset: u16) -> u32 {
-        let header = PciHeader::new(bus, slot, fun);
+    fn pci_readd(&self, seg: u16, bus: u8, slot: u8, fun: u8, offset: u16) -> u32 {
+        let header = PciHeader::with_segment(seg, bus, slot, fun);
         unsafe { header.read::<u32>(offset as u32) }
     }
 
@@ -107,13 +106,197 @@ impl lai::Host for LaiHost {
     }
 }
 
-struct LaiSubsystem;
+/// Bindings to the parts of the LAI C API that are not wrapped by `lai-rs`.
+mod ffi {
+    use core::ffi::{c_char, c_int, c_void};
+
+    pub const LAI_ERROR_NONE: c_int = 0;
+
+    pub const LAI_TYPE_INTEGER: c_int = 1;
+    pub const LAI_TYPE_STRING: c_int = 2;
+    pub const LAI_TYPE_PACKAGE: c_int = 4;
+
+    /// `lai_variable_t`; zero-initialized is an empty variable (`LAI_VAR_INITIALIZER`).
+    #[repr(C)]
+    #[allow(unused)]
+    pub struct LaiVariable {
+        pub ty: c_int,
+        pub integer: u64,
+        pub object: [*mut c_void; 2],
+        pub index: c_int,
+    }
+
+    impl LaiVariable {
+        pub const fn empty() -> Self {
+            Self {
+                ty: 0,
+                integer: 0,
+                object: [core::ptr::null_mut(); 2],
+                index: 0,
+            }
+        }
+    }
+
+    /// `lai_state_t`, which is opaque to the kernel. It only has to be large enough for LAI,
+    /// which keeps the interpreter stacks in it.
+    #[repr(C, align(16))]
+    pub struct LaiState([u8; 0x4000]);
+
+    /// `struct lai_ns_iterator`
+    #[repr(C)]
+    #[allow(unused)]
+    pub struct LaiNsIterator {
+        pub index: usize,
+    }
+
+    pub type LaiNode = c_void;
+
+    extern "C" {
+        pub fn lai_ns_iterate(iterator: *mut LaiNsIterator) -> *mut LaiNode;
+        pub fn lai_ns_get_child(parent: *mut LaiNode, name: *const c_char) -> *mut LaiNode;
+        pub fn lai_resolve_path(context: *mut LaiNode, path: *const c_char) -> *mut LaiNode;
+
+        pub fn lai_init_state(state: *mut LaiState);
+        pub fn lai_finalize_state(state: *mut LaiState);
+        pub fn lai_eval(
+            result: *mut LaiVariable,
+            node: *mut LaiNode,
+            state: *mut LaiState,
+        ) -> c_int;
+        /// The arguments are `*mut LaiVariable`, terminated by a null pointer.
+        pub fn lai_eval_largs(
+            result: *mut LaiVariable,
+            node: *mut LaiNode,
+            state: *mut LaiState,
+            ...
+        ) -> c_int;
+
+        pub fn lai_obj_get_type(object: *mut LaiVariable) -> c_int;
+        pub fn lai_obj_get_integer(object: *mut LaiVariable, out: *mut u64) -> c_int;
+        pub fn lai_obj_get_pkg(
+            object: *mut LaiVariable,
+            index: usize,
+            out: *mut LaiVariable,
+        ) -> c_int;
+        pub fn lai_exec_pkg_size(object: *mut LaiVariable) -> usize;
+        pub fn lai_exec_string_access(object: *mut LaiVariable) -> *const c_char;
+        pub fn lai_exec_string_length(object: *mut LaiVariable) -> usize;
+        pub fn lai_var_finalize(object: *mut LaiVariable);
+    }
+}
+
+/// Converts the LAI variable `object` into an [`AmlValue`].
+unsafe fn convert_variable(object: &mut ffi::LaiVariable) -> AmlValue {
+    match ffi::lai_obj_get_type(object) {
+        ffi::LAI_TYPE_INTEGER => {
+            let mut value = 0;
+
+            if ffi::lai_obj_get_integer(object, &mut value) != ffi::LAI_ERROR_NONE {
+                return AmlValue::Unsupported;
+            }
+
+            AmlValue::Integer(value)
+        }
+
+        ffi::LAI_TYPE_STRING => {
+            let data = ffi::lai_exec_string_access(object).cast::<u8>();
+            let length = ffi::lai_exec_string_length(object);
+            let bytes = core::slice::from_raw_parts(data, length);
+
+            AmlValue::String(String::from_utf8_lossy(bytes).into_owned())
+        }
+
+        ffi::LAI_TYPE_PACKAGE => {
+            let elements = (0..ffi::lai_exec_pkg_size(object))
+                .map(|index| {
+                    let mut element = ffi::LaiVariable::empty();
+
+                    if ffi::lai_obj_get_pkg(object, index, &mut element) != ffi::LAI_ERROR_NONE {
+                        return AmlValue::Unsupported;
+                    }
+
+                    let value = convert_variable(&mut element);
+                    ffi::lai_var_finalize(&mut element);
+                    value
+                })
+                .collect();
+
+            AmlValue::Package(elements)
+        }
+
+        _ => AmlValue::Unsupported,
+    }
+}
+
+/// Returns `name` as a NUL-terminated name segment.
+fn name_segment(name: &str) -> Option<[u8; 5]> {
+    let name = name.as_bytes();
+
+    if name.len() != 4 {
+        return None;
+    }
+
+    let mut result = [0; 5];
+    result[..4].copy_from_slice(name);
+    Some(result)
+}
+
+struct LaiSubsystem {
+    /// Serializes the evaluation of AML, as the interpreter state is not shared between
+    /// evaluations but the namespace is.
+    lock: Mutex<()>,
+}
 
 impl aml::AmlSubsystem for LaiSubsystem {
     fn enter_state(&self, state: aml::SleepState) {
         lai::enter_sleep(state as u8)
     }
 
+    fn supports_state(&self, state: aml::SleepState) -> bool {
+        let path = [b'\\', b'_', b'S', b'0' + state as u8, b'_', 0];
+        let _guard = self.lock.lock();
+
+        unsafe { !ffi::lai_resolve_path(core::ptr::null_mut(), path.as_ptr().cast()).is_null() }
+    }
+
+    fn leave_state(&self, state: aml::SleepState) {
+        let _guard = self.lock.lock();
+        let node =
+            unsafe { ffi::lai_resolve_path(core::ptr::null_mut(), b"\\_WAK\0".as_ptr().cast()) };
+
+        // `\_WAK` is optional.
+        if node.is_null() {
+            return;
+        }
+
+        // SAFETY: The state is initialized by `lai_init_state`.
+        let mut lai_state = unsafe { Box::<ffi::LaiState>::new_zeroed().assume_init() };
+        let mut result = ffi::LaiVariable::empty();
+        let mut argument = ffi::LaiVariable::empty();
+
+        argument.ty = ffi::LAI_TYPE_INTEGER;
+        argument.integer = state as u64;
+
+        unsafe {
+            ffi::lai_init_state(&mut *lai_state);
+
+            let error = ffi::lai_eval_largs(
+                &mut result,
+                node,
+                &mut *lai_state,
+                &mut argument as *mut ffi::LaiVariable,
+                core::ptr::null_mut::<ffi::LaiVariable>(),
+            );
+
+            if error != ffi::LAI_ERROR_NONE {
+                log::warn!("lai: failed to evaluate \\_WAK (error={error})");
+            }
+
+            ffi::lai_var_finalize(&mut result);
+            ffi::lai_finalize_state(&mut *lai_state);
+        }
+    }
+
     fn enable_acpi(&self, mode: u32) {
         lai::enable_acpi(mode);
     }
@@ -123,6 +306,71 @@ impl aml::AmlSubsystem for LaiSubsystem {
             .expect("lai: failed to route pin")
             .base as u8
     }
+
+    fn find_devices(&self, name: &str) -> Vec<AmlHandle> {
+        let Some(name) = name_segment(name) else {
+            return Vec::new();
+        };
+
+        let _guard = self.lock.lock();
+
+        let mut iterator = ffi::LaiNsIterator { index: 0 };
+        let mut devices = Vec::new();
+
+        loop {
+            let node = unsafe { ffi::lai_ns_iterate(&mut iterator) };
+
+            if node.is_null() {
+                break;
+            }
+
+            let child = unsafe { ffi::lai_ns_get_child(node, name.as_ptr().cast()) };
+
+            if !child.is_null() {
+                devices.push(AmlHandle(node.addr()));
+            }
+        }
+
+        devices
+    }
+
+    fn resolve(&self, path: &str) -> Option<AmlHandle> {
+        let mut path = Vec::from(path.as_bytes());
+        path.push(0);
+
+        let _guard = self.lock.lock();
+        let node = unsafe { ffi::lai_resolve_path(core::ptr::null_mut(), path.as_ptr().cast()) };
+
+        (!node.is_null()).then_some(AmlHandle(node.addr()))
+    }
+
+    fn evaluate(&self, device: AmlHandle, name: &str) -> Option<AmlValue> {
+        let name = name_segment(name)?;
+        let _guard = self.lock.lock();
+
+        let device = device.0 as *mut ffi::LaiNode;
+        let node = unsafe { ffi::lai_ns_get_child(device, name.as_ptr().cast()) };
+
+        if node.is_null() {
+            return None;
+        }
+
+        // SAFETY: The state is initialized by `lai_init_state`.
+        let mut state = unsafe { Box::<ffi::LaiState>::new_zeroed().assume_init() };
+        let mut result = ffi::LaiVariable::empty();
+
+        unsafe {
+            ffi::lai_init_state(&mut *state);
+
+            let error = ffi::lai_eval(&mut result, node, &mut *state);
+            let value = (error == ffi::LAI_ERROR_NONE).then(|| convert_variable(&mut result));
+
+            ffi::lai_var_finalize(&mut result);
+            ffi::lai_finalize_state(&mut *state);
+
+            value
+        }
+    }
 }
 
 pub fn init_lai() {
@@ -132,7 +380,9 @@ pub fn init_lai() {
     lai::set_acpi_revision(get_acpi_table().revision() as _);
     lai::create_namespace();
 
-    let subsystem = Arc::new(LaiSubsystem);
+    let subsystem = Arc::new(LaiSubsystem {
+        lock: Mutex::new(()),
+    });
     aml::init(subsystem);
 }
 
@@ -29,11 +29,20 @@ pub mod lai;
 pub mod e1000;
 // #[cfg(feature = "gdbstub")]
 pub mod gdbstub;
+#[cfg(target_arch = "x86_64")]
+pub mod hda;
+pub mod input;
+pub mod modeset;
 pub mod mouse;
 #[cfg(target_arch = "x86_64")]
 pub mod pci;
 pub mod pty;
 pub mod tty;
+#[cfg(target_arch = "x86_64")]
+pub mod usb;
+#[cfg(target_arch = "x86_64")]
+pub mod virtio;
+pub mod watchdog;
 
 cfg_match! {
     cfg(target_arch = "x86_64") => {
@@ -0,0 +1,286 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Bochs/QEMU display (`-vga std` and `-device bochs-display`)
+//!
+//! Both devices implement the Bochs VBE extensions (the "dispi" interface), which allows the
+//! resolution and depth of the linear framebuffer in BAR0 to be programmed directly. The dispi
+//! registers are accessed through the MMIO BAR (BAR2) when the device has one, and through the
+//! legacy I/O ports otherwise.
+//!
+//! ## Notes
+//! * <https://wiki.osdev.org/Bochs_VBE_Extensions>
+
+use core::ptr;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::arch::io;
+use crate::drivers::pci::*;
+use crate::mem::paging::*;
+use crate::rendy::{self, PixelFormat, RendyInfo};
+use crate::utils::sync::Mutex;
+
+use super::{DisplayBackend, ModesetError, Scanout, VideoMode};
+
+/// PCI device ID shared by the QEMU standard VGA and the bochs-display devices.
+const DEVICE_ID: u16 = 0x1111;
+
+const VBE_DISPI_IOPORT_INDEX: u16 = 0x01ce;
+const VBE_DISPI_IOPORT_DATA: u16 = 0x01cf;
+
+/// Offset of the dispi registers into the MMIO BAR.
+const VBE_DISPI_MMIO_OFFSET: u64 = 0x500;
+
+const VBE_DISPI_INDEX_ID: u16 = 0x0;
+const VBE_DISPI_INDEX_XRES: u16 = 0x1;
+const VBE_DISPI_INDEX_YRES: u16 = 0x2;
+const VBE_DISPI_INDEX_BPP: u16 = 0x3;
+const VBE_DISPI_INDEX_ENABLE: u16 = 0x4;
+const VBE_DISPI_INDEX_VIRT_WIDTH: u16 = 0x6;
+const VBE_DISPI_INDEX_VIRT_HEIGHT: u16 = 0x7;
+const VBE_DISPI_INDEX_X_OFFSET: u16 = 0x8;
+const VBE_DISPI_INDEX_Y_OFFSET: u16 = 0x9;
+
+/// Oldest interface version supporting 32 bits per pixel and the linear framebuffer.
+const VBE_DISPI_ID2: u16 = 0xb0c2;
+const VBE_DISPI_ID5: u16 = 0xb0c5;
+
+const VBE_DISPI_ENABLED: u16 = 0x01;
+const VBE_DISPI_LFB_ENABLED: u16 = 0x40;
+
+/// Maximum resolution of the dispi interface.
+const VBE_DISPI_MAX_XRES: usize = 2560;
+const VBE_DISPI_MAX_YRES: usize = 1600;
+
+/// The resolutions advertised to userland; only those that fit in video memory are reported.
+const RESOLUTIONS: &[(usize, usize)] = &[
+    (640, 480),
+    (800, 600),
+    (1024, 768),
+    (1152, 864),
+    (1280, 720),
+    (1280, 768),
+    (1280, 800),
+    (1280, 960),
+    (1280, 1024),
+    (1360, 768),
+    (1400, 1050),
+    (1440, 900),
+    (1600, 900),
+    (1600, 1200),
+    (1680, 1050),
+    (1920, 1080),
+    (1920, 1200),
+    (2048, 1152),
+    (2560, 1440),
+    (2560, 1600),
+];
+
+enum Dispi {
+    Mmio(VirtAddr),
+    Io,
+}
+
+impl Dispi {
+    fn read(&self, index: u16) -> u16 {
+        match self {
+            Self::Mmio(base) => unsafe {
+                ptr::read_volatile((*base + (index as u64) * 2).as_ptr::<u16>())
+            },
+
+            Self::Io => unsafe {
+                io::outw(VBE_DISPI_IOPORT_INDEX, index);
+                io::inw(VBE_DISPI_IOPORT_DATA)
+            },
+        }
+    }
+
+    fn write(&self, index: u16, value: u16) {
+        match self {
+            Self::Mmio(base) => unsafe {
+                ptr::write_volatile((*base + (index as u64) * 2).as_mut_ptr::<u16>(), value)
+            },
+
+            Self::Io => unsafe {
+                io::outw(VBE_DISPI_IOPORT_INDEX, index);
+                io::outw(VBE_DISPI_IOPORT_DATA, value);
+            },
+        }
+    }
+}
+
+struct Bochs {
+    dispi: Mutex<Dispi>,
+    /// The physical address of the linear framebuffer.
+    framebuffer: PhysAddr,
+    /// The size of video memory in bytes.
+    vram_size: usize,
+}
+
+impl Bochs {
+    fn fits(&self, width: usize, height: usize) -> bool {
+        width <= VBE_DISPI_MAX_XRES
+            && height <= VBE_DISPI_MAX_YRES
+            && width * height * 4 <= self.vram_size
+    }
+}
+
+impl DisplayBackend for Bochs {
+    fn name(&self) -> &'static str {
+        "bochs"
+    }
+
+    fn modes(&self) -> Vec<VideoMode> {
+        RESOLUTIONS
+            .iter()
+            .filter(|(width, height)| self.fits(*width, *height))
+            .map(|(width, height)| VideoMode::new(*width, *height, 32))
+            .collect()
+    }
+
+    fn set_mode(&self, mode: VideoMode) -> Result<Scanout, ModesetError> {
+        // The horizontal resolution must be a multiple of 8.
+        if mode.bpp != 32 || mode.width % 8 != 0 || !self.fits(mode.width, mode.height) {
+            return Err(ModesetError::InvalidMode);
+        }
+
+        let dispi = self.dispi.lock_irq();
+
+        dispi.write(VBE_DISPI_INDEX_ENABLE, 0);
+        dispi.write(VBE_DISPI_INDEX_XRES, mode.width as u16);
+        dispi.write(VBE_DISPI_INDEX_YRES, mode.height as u16);
+        dispi.write(VBE_DISPI_INDEX_BPP, mode.bpp as u16);
+        dispi.write(VBE_DISPI_INDEX_VIRT_WIDTH, mode.width as u16);
+        dispi.write(VBE_DISPI_INDEX_VIRT_HEIGHT, mode.height as u16);
+        dispi.write(VBE_DISPI_INDEX_X_OFFSET, 0);
+        dispi.write(VBE_DISPI_INDEX_Y_OFFSET, 0);
+        dispi.write(
+            VBE_DISPI_INDEX_ENABLE,
+            VBE_DISPI_ENABLED | VBE_DISPI_LFB_ENABLED,
+        );
+
+        // The device rejects modes it does not support, so make sure that the mode stuck.
+        if dispi.read(VBE_DISPI_INDEX_XRES) as usize != mode.width
+            || dispi.read(VBE_DISPI_INDEX_YRES) as usize != mode.height
+        {
+            return Err(ModesetError::InvalidMode);
+        }
+
+        let stride = mode.width * 4;
+
+        Ok(Scanout {
+            address: self.framebuffer,
+            info: RendyInfo {
+                byte_len: stride * mode.height,
+                horizontal_resolution: mode.width,
+                vertical_resolution: mode.height,
+                pixel_format: PixelFormat::BGR,
+                bits_per_pixel: mode.bpp,
+                stride,
+
+                red_mask_shift: 16,
+                red_mask_size: 8,
+
+                green_mask_shift: 8,
+                green_mask_size: 8,
+
+                blue_mask_shift: 0,
+                blue_mask_size: 8,
+            },
+        })
+    }
+}
+
+fn bar_region(bar: &Bar) -> Option<(PhysAddr, usize)> {
+    match *bar {
+        Bar::Memory32 { address, size, .. } if address != 0 => {
+            Some((PhysAddr::new(address as u64), size as usize))
+        }
+
+        Bar::Memory64 { address, size, .. } if address != 0 => {
+            map_bar(bar);
+            Some((PhysAddr::new(address), size as usize))
+        }
+
+        _ => None,
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Qemu
+            && matches!(
+                device_id,
+                DeviceType::VgaCompatibleController | DeviceType::OtherDisplayController
+            )
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        if header.get_device_id() != DEVICE_ID {
+            return;
+        }
+
+        let Some((framebuffer, vram_size)) = header.get_bar(0).as_ref().and_then(bar_region) else {
+            log::warn!("bochs: framebuffer BAR is not present");
+            return;
+        };
+
+        header.enable_mmio();
+
+        // The bochs-display device only has the MMIO BAR, while it is optional on the standard
+        // VGA device which falls back to the legacy ports.
+        let dispi = match header.get_bar(2).as_ref().and_then(bar_region) {
+            Some((mmio, _)) => Dispi::Mmio(mmio.as_hhdm_virt() + VBE_DISPI_MMIO_OFFSET),
+            None => Dispi::Io,
+        };
+
+        let id = dispi.read(VBE_DISPI_INDEX_ID);
+        if !(VBE_DISPI_ID2..=VBE_DISPI_ID5).contains(&id) {
+            log::warn!("bochs: unsupported dispi interface (id={id:#x})");
+            return;
+        }
+
+        // Only take over the display if the terminal is on this device's framebuffer, as the
+        // boot framebuffer could belong to another display adapter.
+        let boot_framebuffer = rendy::get_framebuffer_address();
+        if boot_framebuffer < framebuffer || boot_framebuffer >= framebuffer + vram_size {
+            log::warn!("bochs: boot framebuffer is not on this device");
+            return;
+        }
+
+        log::info!(
+            "bochs: dispi id={id:#x}, framebuffer={framebuffer:#x}, vram={}KiB",
+            vram_size / 1024
+        );
+
+        super::register_backend(Arc::new(Bochs {
+            dispi: Mutex::new(dispi),
+            framebuffer,
+            vram_size,
+        }));
+    }
+}
+
+fn init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::module_init!(init, ModuleType::Block);
@@ -0,0 +1,194 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel mode-setting (KMS)
+//!
+//! The bootloader leaves the display in whatever mode the firmware (GOP or VBE) was asked to
+//! set. This module abstracts over the display backends that are able to program the display
+//! themselves (currently the Bochs/QEMU dispi interface), so that the kernel and userland can
+//! query the available video modes and switch between them. When no such backend is present,
+//! the boot framebuffer is used and the only available mode is the one set by the firmware.
+//!
+//! The debug terminal is moved onto the new framebuffer after every mode switch, and both the
+//! framebuffer device (`/dev/fb0`) and the DRM device (`/dev/dri/card0`) are backed by it.
+
+#[cfg(target_arch = "x86_64")]
+mod bochs;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::FileSystemError;
+use crate::mem::paging::PhysAddr;
+use crate::power::{self, PowerOps, SuspendError};
+use crate::rendy::{self, RendyInfo};
+use crate::utils::sync::Mutex;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VideoMode {
+    /// The width in pixels.
+    pub width: usize,
+    /// The height in pixels.
+    pub height: usize,
+    /// The number of bits per pixel.
+    pub bpp: usize,
+}
+
+impl VideoMode {
+    pub const fn new(width: usize, height: usize, bpp: usize) -> Self {
+        Self { width, height, bpp }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModesetError {
+    /// The display backend cannot change the video mode.
+    NotSupported,
+    /// The requested video mode is not supported by the display backend.
+    InvalidMode,
+}
+
+impl From<ModesetError> for FileSystemError {
+    fn from(value: ModesetError) -> Self {
+        match value {
+            ModesetError::NotSupported => Self::NotSupported,
+            ModesetError::InvalidMode => Self::InvalidArgument,
+        }
+    }
+}
+
+/// Describes the framebuffer that is scanned out after a mode switch.
+#[derive(Debug, Copy, Clone)]
+pub struct Scanout {
+    /// The physical address of the framebuffer.
+    pub address: PhysAddr,
+    pub info: RendyInfo,
+}
+
+pub trait DisplayBackend: Send + Sync {
+    /// Returns the name of the display backend.
+    fn name(&self) -> &'static str;
+
+    /// Returns all of the video modes that the display can be switched to.
+    fn modes(&self) -> Vec<VideoMode>;
+
+    /// Programs the display with the provided video mode and returns the new scanout
+    /// framebuffer.
+    fn set_mode(&self, mode: VideoMode) -> Result<Scanout, ModesetError>;
+}
+
+static BACKEND: Mutex<Option<Arc<dyn DisplayBackend>>> = Mutex::new(None);
+
+/// Registers a display backend that drives the display the boot framebuffer is on.
+pub fn register_backend(backend: Arc<dyn DisplayBackend>) {
+    log::info!("modeset: using the {} display backend", backend.name());
+    *BACKEND.lock_irq() = Some(backend);
+
+    power::register(Arc::new(DisplayPower));
+}
+
+/// Reprograms the display after waking up, as the display backend loses its video mode. The
+/// boot framebuffer is left to the firmware.
+struct DisplayPower;
+
+impl PowerOps for DisplayPower {
+    fn name(&self) -> &'static str {
+        "modeset"
+    }
+
+    fn suspend(&self) -> Result<(), SuspendError> {
+        Ok(())
+    }
+
+    fn resume(&self) {
+        let Some(backend) = BACKEND.lock_irq().clone() else {
+            return;
+        };
+
+        match backend.set_mode(current_mode()) {
+            Ok(scanout) => rendy::set_framebuffer(scanout.address, scanout.info),
+            Err(error) => log::warn!("modeset: failed to restore the video mode ({error:?})"),
+        }
+    }
+}
+
+/// Returns the name of the display backend in use.
+pub fn backend_name() -> &'static str {
+    BACKEND
+        .lock_irq()
+        .as_ref()
+        .map(|backend| backend.name())
+        .unwrap_or("bootfb")
+}
+
+/// Returns the current video mode.
+pub fn current_mode() -> VideoMode {
+    let info = rendy::get_rendy_info();
+
+    VideoMode::new(
+        info.horizontal_resolution,
+        info.vertical_resolution,
+        info.bits_per_pixel,
+    )
+}
+
+/// Returns all of the video modes that the display can be switched to. This always includes
+/// the current video mode.
+pub fn modes() -> Vec<VideoMode> {
+    let current = current_mode();
+    let mut modes = BACKEND
+        .lock_irq()
+        .as_ref()
+        .map(|backend| backend.modes())
+        .unwrap_or_default();
+
+    if !modes.contains(&current) {
+        modes.push(current);
+    }
+
+    modes
+}
+
+/// Switches the display to the provided video mode.
+pub fn set_mode(mode: VideoMode) -> Result<(), ModesetError> {
+    let backend = BACKEND.lock_irq();
+
+    // Setting the current mode is a no-op; this is also the only mode supported by the boot
+    // framebuffer.
+    if mode == current_mode() {
+        return Ok(());
+    }
+
+    // The debug terminal only supports 32 bits per pixel.
+    if mode.width == 0 || mode.height == 0 || mode.bpp != 32 {
+        return Err(ModesetError::InvalidMode);
+    }
+
+    let backend = backend.as_ref().ok_or(ModesetError::NotSupported)?;
+    let scanout = backend.set_mode(mode)?;
+
+    rendy::set_framebuffer(scanout.address, scanout.info);
+
+    log::info!(
+        "modeset: switched to {}x{}x{}",
+        mode.width,
+        mode.height,
+        mode.bpp
+    );
+
+    Ok(())
+}
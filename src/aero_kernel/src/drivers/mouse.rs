@@ -18,6 +18,9 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+use spin::Once;
+use uapi::input::*;
+
 use crate::arch::interrupts::InterruptStack;
 use crate::arch::{apic, interrupts, io};
 use crate::fs::devfs::Device;
@@ -25,6 +28,8 @@ use crate::fs::inode::{INodeInterface, PollFlags, PollTable};
 use crate::fs::{self, devfs};
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::input::{self, Capabilities, InputDevice};
+
 bitflags::bitflags! {
     /// Represents the flags currently set for the mouse.
     #[derive(Default, Debug, Copy, Clone)]
@@ -48,6 +53,7 @@ lazy_static::lazy_static! {
 }
 
 static PACKETS: Mutex<Vec<Packet>> = Mutex::new(Vec::new());
+static MOUSE_INPUT: Once<Arc<InputDevice>> = Once::new();
 
 #[derive(Default, Debug, Copy, Clone)]
 #[repr(C)]
@@ -112,6 +118,8 @@ impl Mouse {
 
                     PACKETS.lock_irq().push(*this);
                     self.wq.notify_all();
+
+                    report_packet(this);
                 }
             }
 
@@ -169,6 +177,24 @@ impl INodeInterface for Mouse {
     }
 }
 
+/// Reports the packet to the input core (see [`input`]).
+fn report_packet(packet: &Packet) {
+    let Some(input) = MOUSE_INPUT.get() else {
+        return;
+    };
+
+    let flags = packet.flags;
+
+    input.report_key(BTN_LEFT, flags.contains(MouseFlags::LEFT_BUTTON));
+    input.report_key(BTN_RIGHT, flags.contains(MouseFlags::RIGHT_BUTTON));
+    input.report_key(BTN_MIDDLE, flags.contains(MouseFlags::MIDDLE_BUTTON));
+
+    // The Y axis of PS/2 mice points up, while the one of evdev points down.
+    input.report(EV_REL, REL_X, packet.x.into());
+    input.report(EV_REL, REL_Y, -i32::from(packet.y));
+    input.sync();
+}
+
 fn irq_handler(_stack: &mut InterruptStack) {
     let data = unsafe { io::inb(0x60) };
     MOUSE.process_packet(data);
@@ -193,5 +219,24 @@ pub fn ps2_mouse_init() {
     apic::io_apic_setup_legacy_irq(12, irq_vector, 1);
 
     devfs::install_device(MOUSE.clone()).unwrap();
+
+    MOUSE_INPUT.call_once(|| {
+        let mut capabilities = Capabilities::new();
+
+        capabilities.set(EV_KEY, BTN_LEFT);
+        capabilities.set(EV_KEY, BTN_RIGHT);
+        capabilities.set(EV_KEY, BTN_MIDDLE);
+        capabilities.set(EV_REL, REL_X);
+        capabilities.set(EV_REL, REL_Y);
+
+        let id = InputId {
+            bustype: BUS_I8042,
+            vendor: 0x0002,
+            product: 0x0001,
+            version: 0x0000,
+        };
+
+        input::register("PS/2 Generic Mouse", id, capabilities)
+    });
     log::trace!("ps2: initialized mouse");
 }
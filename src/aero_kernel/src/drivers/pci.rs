@@ -23,18 +23,97 @@ use crate::utils::bitmap::Bitmap;
 use crate::utils::sync::Mutex;
 
 use crate::acpi::mcfg;
-use crate::mem::paging::{OffsetPageTable, PhysAddr};
+use crate::mem::paging::{OffsetPageTable, PhysAddr, VirtAddr};
+use crate::power::{self, PowerOps, SuspendError};
 use crate::utils::VolatileCell;
 
 use crate::arch::{apic, io};
 
 use bit_field::BitField;
+use spin::Once;
 
 static PCI_TABLE: Mutex<PciTable> = Mutex::new(PciTable::new());
 
 const PCI_CONFIG_ADDRESS_PORT: u16 = 0xCF8;
 const PCI_CONFIG_DATA_PORT: u16 = 0xCFC;
 
+/// Size of the configuration space of a function that is accessible through the legacy ports.
+const LEGACY_CONFIG_SIZE: u32 = 0x100;
+/// Size of the (extended) configuration space of a function that is accessible through ECAM.
+const ECAM_CONFIG_SIZE: u32 = 0x1000;
+
+/// A memory mapped configuration space (ECAM) region of a PCI segment group, as described by the
+/// MCFG table.
+struct EcamRegion {
+    /// Address of the configuration space of bus 0, even if the region starts at a later bus.
+    base: PhysAddr,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+impl EcamRegion {
+    fn config_address(&self, bus: u8, device: u8, function: u8) -> PhysAddr {
+        self.base + ((bus as u64) << 20 | (device as u64) << 15 | (function as u64) << 12)
+    }
+}
+
+static ECAM_REGIONS: Once<Vec<EcamRegion>> = Once::new();
+
+/// Number of dwords of the standard header of the configuration space, which is lost when the
+/// machine sleeps.
+const SAVED_CONFIG_DWORDS: usize = 16;
+
+/// The functions found by [`init`] and their configuration space headers, saved by
+/// [`PciPower::suspend`].
+static FUNCTIONS: Mutex<Vec<(PciHeader, [u32; SAVED_CONFIG_DWORDS])>> = Mutex::new(Vec::new());
+
+struct PciPower;
+
+impl PowerOps for PciPower {
+    fn name(&self) -> &'static str {
+        "pci"
+    }
+
+    fn suspend(&self) -> Result<(), SuspendError> {
+        for (header, config) in FUNCTIONS.lock_irq().iter_mut() {
+            for (i, value) in config.iter_mut().enumerate() {
+                *value = unsafe { header.read::<u32>(i as u32 * 4) };
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resume(&self) {
+        for (header, config) in FUNCTIONS.lock_irq().iter() {
+            unsafe {
+                // Restore the BARs and the rest of the header before the command register
+                // enables decoding and bus mastering.
+                for (i, &value) in config.iter().enumerate().skip(4).rev() {
+                    header.write::<u32>(i as u32 * 4, value);
+                }
+
+                // Writing the BIST register could start a self test.
+                header.write::<u16>(0x0c, config[3] & 0xffff);
+                header.write::<u16>(0x04, config[1] & 0xffff);
+            }
+        }
+    }
+}
+
+/// Returns the virtual address of the configuration space of the provided function or [`None`]
+/// if it is not covered by an ECAM region.
+fn ecam_address(segment: u16, bus: u8, device: u8, function: u8) -> Option<VirtAddr> {
+    ECAM_REGIONS
+        .get()?
+        .iter()
+        .find(|region| {
+            region.segment == segment && (region.start_bus..=region.end_bus).contains(&bus)
+        })
+        .map(|region| region.config_address(bus, device, function).as_hhdm_virt())
+}
+
 bitflags::bitflags! {
     pub struct ProgramInterface: u8 {
         const PRIMARY_PCI_NATIVE   = 0b00000001;
@@ -162,10 +241,64 @@ impl<'a> Msix<'a> {
     }
 }
 
+/// PCI Express extended capabilities, which are only accessible through ECAM.
+#[derive(PartialEq, Debug)]
+pub enum ExtendedCapability {
+    /// Advanced Error Reporting.
+    Aer,
+    /// Single Root I/O Virtualization.
+    SrIov,
+
+    Unknown(u16),
+}
+
+pub struct ExtendedCapabilityIter<'a> {
+    offset: u32,
+    header: &'a PciHeader,
+}
+
+impl<'a> Iterator for ExtendedCapabilityIter<'a> {
+    type Item = (u32, ExtendedCapability);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The list starts at the beginning of the extended configuration space and each entry
+        // is dword aligned.
+        if self.offset < LEGACY_CONFIG_SIZE || self.offset >= ECAM_CONFIG_SIZE {
+            return None;
+        }
+
+        // 31          20 19     16 15              0
+        // --------------------------------------------
+        // Next Pointer | Version | Capability ID     |
+        // --------------------------------------------
+        let entry = unsafe { self.header.read::<u32>(self.offset) };
+
+        // A function without extended capabilities has an entry of zero at offset 0x100, and
+        // reads are all ones if the extended configuration space is not accessible.
+        if entry == 0 || entry == u32::MAX {
+            return None;
+        }
+
+        let capability = match entry.get_bits(0..16) as u16 {
+            0x01 => ExtendedCapability::Aer,
+            0x10 => ExtendedCapability::SrIov,
+
+            id => ExtendedCapability::Unknown(id),
+        };
+
+        let old_offset = self.offset;
+        self.offset = entry.get_bits(20..32) & !0b11;
+
+        Some((old_offset, capability))
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Capability {
     Msi,
     Msix,
+    /// Vendor specific capability, whose layout is defined by the device (e.g. virtio).
+    Vendor,
 
     Unknown,
 }
@@ -201,6 +334,7 @@ impl<'a> Iterator for CapabilityIter<'a> {
         let id = unsafe { self.header.read::<u8>(self.offset) };
         let capability = match id {
             0x5 => Capability::Msi,
+            0x9 => Capability::Vendor,
             0x11 => Capability::Msix,
 
             _ => Capability::Unknown,
@@ -269,6 +403,7 @@ pub enum DeviceType {
     VideoDevice,
     AudioDevice,
     TelephonyDevice,
+    HdAudioController,
     OtherMultimediaDevice,
 
     // Base Class 0x05 - Memory Controllers
@@ -406,7 +541,8 @@ impl DeviceType {
             (0x04, 0x00) => DeviceType::VideoDevice,
             (0x04, 0x01) => DeviceType::AudioDevice,
             (0x04, 0x02) => DeviceType::TelephonyDevice,
-            (0x04, 0x03) => DeviceType::OtherMultimediaDevice,
+            (0x04, 0x03) => DeviceType::HdAudioController,
+            (0x04, 0x80) => DeviceType::OtherMultimediaDevice,
 
             (0x05, 0x00) => DeviceType::RamController,
             (0x05, 0x01) => DeviceType::FlashController,
@@ -507,6 +643,7 @@ pub enum Vendor {
     Amd,
     Nvidia,
     Qemu,
+    Virtio,
     Unknown(u32),
 }
 
@@ -517,6 +654,7 @@ impl Vendor {
             0x1022 => Self::Amd,
             0x10DE => Self::Nvidia,
             0x1234 => Self::Qemu,
+            0x1AF4 => Self::Virtio,
             _ => Self::Unknown(id),
         }
     }
@@ -533,16 +671,24 @@ pub struct PciHeader(u32);
 
 impl PciHeader {
     pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self::with_segment(0, bus, device, function)
+    }
+
+    pub fn with_segment(segment: u16, bus: u8, device: u8, function: u8) -> Self {
         let mut result: u32 = 0;
 
         result.set_bits(0..3, function as u32);
         result.set_bits(3..8, device as u32);
         result.set_bits(8..16, bus as u32);
-        result.set_bits(16..32, 0);
+        result.set_bits(16..32, segment as u32);
 
         Self(result)
     }
 
+    pub fn segment(&self) -> u16 {
+        self.0.get_bits(16..32) as u16
+    }
+
     pub fn bus(&self) -> u8 {
         self.0.get_bits(8..16) as u8
     }
@@ -555,18 +701,52 @@ impl PciHeader {
         self.0.get_bits(0..3) as u8
     }
 
-    pub unsafe fn read<T>(&self, offset: u32) -> u32 {
+    /// Returns the virtual address of the configuration space of the function, if it can be
+    /// accessed through ECAM.
+    fn ecam_address(&self) -> Option<VirtAddr> {
+        ecam_address(self.segment(), self.bus(), self.device(), self.function())
+    }
+
+    /// Returns the address of the legacy configuration space port for the dword at `offset`.
+    fn legacy_address(&self, offset: u32) -> u32 {
         let bus = self.bus() as u32;
         let device = self.device() as u32;
         let func = self.function() as u32;
-        let address = (bus << 16) | (device << 11) | (func << 8) | (offset & 0xFC) | 0x80000000;
 
-        io::outl(PCI_CONFIG_ADDRESS_PORT, address);
+        (bus << 16) | (device << 11) | (func << 8) | (offset & 0xFC) | 0x80000000
+    }
+
+    /// Returns whether the register at `offset` can only be accessed through ECAM, which is
+    /// the case for the extended configuration space and for segment groups other than 0.
+    fn requires_ecam(&self, offset: u32) -> bool {
+        offset >= LEGACY_CONFIG_SIZE || self.segment() != 0
+    }
+
+    pub unsafe fn read<T>(&self, offset: u32) -> u32 {
+        let size = core::mem::size_of::<T>();
+
+        if let Some(config) = self.ecam_address().filter(|_| offset < ECAM_CONFIG_SIZE) {
+            let address = config + offset as u64;
+
+            return match size {
+                1 => core::ptr::read_volatile(address.as_ptr::<u8>()) as u32,
+                2 => core::ptr::read_volatile(address.as_ptr::<u16>()) as u32,
+                4 => core::ptr::read_volatile(address.as_ptr::<u32>()),
+                width => unreachable!("unknown PCI read width: `{}`", width),
+            };
+        }
+
+        // Reads of registers that do not exist return all ones, like for absent functions.
+        if self.requires_ecam(offset) {
+            return u32::MAX >> (32 - size * 8);
+        }
+
+        io::outl(PCI_CONFIG_ADDRESS_PORT, self.legacy_address(offset));
 
         let offset = (offset & 0b11) * 8;
         let val = io::inl(PCI_CONFIG_DATA_PORT);
 
-        match core::mem::size_of::<T>() {
+        match size {
             1 => (val >> offset) as u8 as u32,  // u8
             2 => (val >> offset) as u16 as u32, // u16
             4 => val,                           // u32
@@ -574,21 +754,34 @@ impl PciHeader {
         }
     }
 
-    unsafe fn write<T>(&self, offset: u32, value: u32) {
-        let current = self.read::<u32>(offset);
+    pub unsafe fn write<T>(&self, offset: u32, value: u32) {
+        if let Some(config) = self.ecam_address().filter(|_| offset < ECAM_CONFIG_SIZE) {
+            let address = config + offset as u64;
 
-        let bus = self.bus() as u32;
-        let device = self.device() as u32;
-        let func = self.function() as u32;
+            match core::mem::size_of::<T>() {
+                1 => core::ptr::write_volatile(address.as_mut_ptr::<u8>(), value as u8),
+                2 => core::ptr::write_volatile(address.as_mut_ptr::<u16>(), value as u16),
+                4 => core::ptr::write_volatile(address.as_mut_ptr::<u32>(), value),
+                width => unreachable!("unknown PCI write width: `{}`", width),
+            }
 
-        let address = (bus << 16) | (device << 11) | (func << 8) | (offset & 0xFC) | 0x80000000;
+            return;
+        }
+
+        if self.requires_ecam(offset) {
+            return;
+        }
+
+        let current = self.read::<u32>(offset);
+
+        let address = self.legacy_address(offset);
         let noffset = (offset & 0b11) * 8;
 
         io::outl(PCI_CONFIG_ADDRESS_PORT, address);
         match core::mem::size_of::<T>() {
             1 => {
-                let mask = !(0xffu32 << offset);
-                let value = (current & mask) | ((value & 0xff) << offset);
+                let mask = !(0xffu32 << noffset);
+                let value = (current & mask) | ((value & 0xff) << noffset);
                 io::outl(PCI_CONFIG_DATA_PORT, value)
             } // u8
 
@@ -641,6 +834,12 @@ impl PciHeader {
         unsafe { Vendor::new(self.read::<u16>(0x00)) }
     }
 
+    /// Returns the value stored in the PCI device ID register which is used to identify
+    /// the particular device, it is allocated by the vendor.
+    pub fn get_device_id(&self) -> u16 {
+        unsafe { self.read::<u16>(0x02) as u16 }
+    }
+
     pub unsafe fn get_device(&self) -> DeviceType {
         let id = self.read::<u32>(0x08);
 
@@ -662,6 +861,15 @@ impl PciHeader {
         CapabilityIter::new(self, offset)
     }
 
+    /// Returns an iterator over the PCI Express extended capabilities of the function, which is
+    /// empty if the extended configuration space is not accessible.
+    pub fn extended_capabilities(&self) -> ExtendedCapabilityIter {
+        ExtendedCapabilityIter {
+            offset: LEGACY_CONFIG_SIZE,
+            header: self,
+        }
+    }
+
     pub fn msix(&self) -> Option<Msix> {
         self.capabilities()
             .find(|(_, e)| *e == Capability::Msix)
@@ -764,6 +972,12 @@ impl PciHeader {
         let bits = unsafe { self.read::<u8>(0x09) };
         ProgramInterface::from_bits_truncate(bits as u8)
     }
+
+    /// Returns the raw programming interface of the device, which selects the register-level
+    /// interface within the device class (e.g. UHCI, OHCI, EHCI or xHCI for USB controllers).
+    pub fn prog_if(&self) -> u8 {
+        unsafe { self.read::<u8>(0x09) as u8 }
+    }
 }
 
 pub trait PciDeviceHandle: Sync + Send {
@@ -791,6 +1005,18 @@ impl PciTable {
 }
 
 pub fn map_bar(bar: &Bar) {
+    let (addr, size) = match bar {
+        Bar::Memory64 { address, size, .. } => (PhysAddr::new(*address), *size),
+        Bar::Memory32 { address, size, .. } => (PhysAddr::new(*address as u64), *size as u64),
+        _ => unreachable!(),
+    };
+
+    map_mmio(addr, size);
+}
+
+/// Maps the physical range starting at `addr` of `size` bytes in the higher half direct map.
+/// Parts of the range that are covered by huge pages are already mapped.
+pub fn map_mmio(addr: PhysAddr, size: u64) {
     use crate::mem::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, UnmapError};
 
     use crate::mem::AddressSpace;
@@ -798,22 +1024,18 @@ pub fn map_bar(bar: &Bar) {
     let mut address_space = AddressSpace::this();
     let mut offset_table = address_space.offset_page_table();
 
-    let (addr, size) = match bar {
-        Bar::Memory64 { address, size, .. } => (PhysAddr::new(*address), *size),
-        _ => unreachable!(),
-    };
-
     for frame in PhysFrame::range(
-        PhysFrame::<Size4KiB>::from_start_address(addr).unwrap(),
+        PhysFrame::<Size4KiB>::containing_address(addr),
         PhysFrame::containing_address(addr + size),
     ) {
         let virt = frame.start_address().as_hhdm_virt();
         let page = Page::containing_address(virt);
 
-        // Map will fail if the bar was partially mapped.
+        // Map will fail if the range was partially mapped.
         match offset_table.unmap(page) {
             Ok((_, m)) => m.ignore(),
             Err(UnmapError::PageNotMapped) => {}
+            Err(UnmapError::ParentEntryHugePage) => continue,
             Err(e) => unreachable!("{:?}", e),
         }
 
@@ -829,50 +1051,121 @@ pub fn map_bar(bar: &Bar) {
     }
 }
 
+/// Maps the ECAM regions from the MCFG table, after which the configuration space is accessed
+/// through them instead of the legacy ports.
+fn init_ecam() {
+    if !mcfg::is_available() {
+        log::debug!("pci: MCFG not available, using the legacy configuration ports");
+        return;
+    }
+
+    let regions = mcfg::get_mcfg_table()
+        .entries()
+        .iter()
+        .filter(|entry| entry.start_bus <= entry.end_bus)
+        .map(|entry| {
+            let region = EcamRegion {
+                base: PhysAddr::new(entry.base_address),
+                segment: entry.pci_seg_group,
+                start_bus: entry.start_bus,
+                end_bus: entry.end_bus,
+            };
+
+            let start = region.config_address(region.start_bus, 0, 0);
+            let size = (region.end_bus - region.start_bus) as u64 + 1;
+
+            map_mmio(start, size << 20);
+
+            log::debug!(
+                "pci: ECAM region at {:#x} (segment={}, buses={}..={})",
+                start,
+                region.segment,
+                region.start_bus,
+                region.end_bus
+            );
+
+            region
+        })
+        .collect::<Vec<_>>();
+
+    ECAM_REGIONS.call_once(|| regions);
+}
+
+/// Returns the segment groups and their bus ranges that have to be enumerated.
+fn bus_ranges() -> Vec<(u16, u8, u8)> {
+    match ECAM_REGIONS.get() {
+        Some(regions) if !regions.is_empty() => regions
+            .iter()
+            .map(|region| (region.segment, region.start_bus, region.end_bus))
+            .collect(),
+
+        _ => alloc::vec![(0, 0, 255)],
+    }
+}
+
 pub fn register_device_driver(handle: Arc<dyn PciDeviceHandle>) {
     PCI_TABLE.lock().inner.push(PciDevice { handle })
 }
 
 /// Lookup and initialize all PCI devices.
 pub fn init(offset_table: &mut OffsetPageTable) {
-    // Check if the MCFG table is available.
-    if mcfg::is_available() {
-        let mcfg_table = mcfg::get_mcfg_table();
-        let _entry_count = mcfg_table.entry_count();
-    }
+    init_ecam();
+
+    // Registered before the drivers are started, so that the buses are resumed before the
+    // devices on them.
+    power::register(Arc::new(PciPower));
 
     // Use the brute force method to go through each possible bus,
     // device, function ID and check if we have a driver for it. If a driver
     // for the PCI device is found then initialize it.
-    for bus in 0..255 {
-        for device in 0..32 {
-            let function_count = if PciHeader::new(bus, device, 0x00).has_multiple_functions() {
-                8
-            } else {
-                1
-            };
+    for (segment, start_bus, end_bus) in bus_ranges() {
+        for bus in start_bus..=end_bus {
+            for device in 0..32 {
+                let function_count = if PciHeader::with_segment(segment, bus, device, 0x00)
+                    .has_multiple_functions()
+                {
+                    8
+                } else {
+                    1
+                };
+
+                for function in 0..function_count {
+                    let device = PciHeader::with_segment(segment, bus, device, function);
+
+                    unsafe {
+                        if !device.get_vendor().is_valid() {
+                            // Device does not exist.
+                            continue;
+                        }
 
-            for function in 0..function_count {
-                let device = PciHeader::new(bus, device, function);
+                        log::debug!(
+                            "PCI device (device={:?}, vendor={:?})",
+                            device.get_device(),
+                            device.get_vendor()
+                        );
 
-                unsafe {
-                    if !device.get_vendor().is_valid() {
-                        // Device does not exist.
-                        continue;
-                    }
+                        for (offset, capability) in device.extended_capabilities() {
+                            if capability != ExtendedCapability::Aer
+                                && capability != ExtendedCapability::SrIov
+                            {
+                                continue;
+                            }
+
+                            log::debug!("PCI device: {capability:?} capability at {offset:#x}");
+                        }
 
-                    log::debug!(
-                        "PCI device (device={:?}, vendor={:?})",
-                        device.get_device(),
-                        device.get_vendor()
-                    );
-
-                    for driver in &mut PCI_TABLE.lock().inner {
-                        if driver
-                            .handle
-                            .handles(device.get_vendor(), device.get_device())
-                        {
-                            driver.handle.start(&device, offset_table)
+                        FUNCTIONS.lock_irq().push((
+                            PciHeader::with_segment(segment, bus, device.device(), function),
+                            [0; SAVED_CONFIG_DWORDS],
+                        ));
+
+                        for driver in &mut PCI_TABLE.lock().inner {
+                            if driver
+                                .handle
+                                .handles(device.get_vendor(), device.get_device())
+                            {
+                                driver.handle.start(&device, offset_table)
+                            }
                         }
                     }
                 }
@@ -31,14 +31,16 @@ use uapi::pty::TIOCGPTN;
 use crate::arch::user_copy::UserRef;
 use crate::fs::cache::*;
 use crate::fs::devfs::DEV_FILESYSTEM;
+use crate::fs::file_table::FileHandle;
 use crate::fs::inode::{DirEntry, FileType, INodeInterface, PollFlags};
 use crate::fs::{self, cache, devfs, FileSystem, FileSystemError, Path, MOUNT_MANAGER};
 
 use crate::mem::paging::VirtAddr;
 use crate::userland::scheduler;
 use crate::userland::scheduler::ExitStatus;
+use crate::userland::task::sessions::Session;
 use crate::userland::task::Task;
-use crate::userland::terminal::{LineControl, LineDiscipline, TerminalDevice};
+use crate::userland::terminal::{self, LineControl, LineDiscipline, TerminalDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
 lazy_static::lazy_static! {
@@ -48,6 +50,9 @@ lazy_static::lazy_static! {
 static PTS_FS: Once<Arc<PtsFs>> = Once::new();
 static PTY_ID: AtomicU32 = AtomicU32::new(0);
 
+/// Maximum number of bytes written to the slave that are buffered until the master reads them.
+const PTY_BUF_SIZE: usize = 4096;
+
 #[derive(Debug, Ioctl)]
 pub enum TermiosCmd {
     /// Get window size.
@@ -92,9 +97,21 @@ pub enum TermiosCmd {
     /// Get the process group ID of the foreground process group on this terminal.
     ///
     /// When successful, equivalent to `*argp = tcgetpgrp(fd)`.
-    // FIXME: argument usize
     #[command(libc::TIOCGPGRP)]
-    GetProcGroupId,
+    GetProcGroupId(UserRef<i32>),
+
+    /// Set the foreground process group ID of this terminal. The process group must be part of
+    /// the session that this terminal is the controlling terminal of.
+    ///
+    /// When successful, equivalent to `tcsetpgrp(fd, *argp)`.
+    #[command(libc::TIOCSPGRP)]
+    SetProcGroupId(UserRef<i32>),
+
+    /// Insert the given byte in the input queue, as if it was typed on the terminal. Only
+    /// allowed on the controlling terminal of the calling process (see
+    /// [`terminal::check_tiocsti`]).
+    #[command(libc::TIOCSTI)]
+    SimulateInput(UserRef<u8>),
 }
 
 struct Master {
@@ -103,6 +120,7 @@ struct Master {
     window_size: Mutex<WinSize>,
     buffer: Mutex<Vec<u8>>,
     discipline: LineDiscipline,
+    handle: Once<Arc<FileHandle>>,
 }
 
 impl Master {
@@ -113,9 +131,16 @@ impl Master {
             window_size: Mutex::new(WinSize::default()),
             buffer: Mutex::new(Vec::new()),
             discipline: LineDiscipline::new(),
+            handle: Once::new(),
         }
     }
 
+    fn is_nonblock(&self) -> bool {
+        self.handle
+            .get()
+            .is_some_and(|handle| handle.flags().contains(libc::OpenFlags::O_NONBLOCK))
+    }
+
     #[inline]
     fn set_window_size(&self, size: WinSize) {
         *self.window_size.lock_irq() = size;
@@ -125,9 +150,25 @@ impl Master {
     fn get_window_size(&self) -> WinSize {
         *self.window_size.lock_irq()
     }
+
+    /// Passes the provided input to the line discipline of the slave and returns the number of
+    /// bytes consumed.
+    fn input(&self, buffer: &[u8]) -> usize {
+        let count = self.discipline.write(buffer, |ctrl| match ctrl {
+            LineControl::Echo(c) => self.buffer.lock_irq().push(c),
+        });
+
+        self.wq.notify_all();
+        count
+    }
 }
 
 impl INodeInterface for Master {
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
         let mut pty_buffer = self.buffer.lock_irq();
 
@@ -137,22 +178,58 @@ impl INodeInterface for Master {
 
         let size = core::cmp::min(pty_buffer.len(), buffer.len());
         buffer[..size].copy_from_slice(&pty_buffer.drain(..size).collect::<Vec<_>>());
+
+        // Wake up the writers of the slave waiting for room in the buffer.
+        drop(pty_buffer);
+        self.wq.notify_all();
+
         Ok(size)
     }
 
+    /// Writes the provided buffer to the input of the slave, blocking while the input buffer
+    /// of the line discipline is full unless the master is non-blocking.
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        self.discipline.write(buffer, |ctrl| match ctrl {
-            LineControl::Echo(c) => self.buffer.lock_irq().push(c),
-        });
-        self.wq.notify_all();
-        Ok(buffer.len())
+        let nonblock = self.is_nonblock();
+        let mut written = 0;
+
+        while written < buffer.len() {
+            if !nonblock {
+                match self.discipline.wait_for_space() {
+                    Ok(()) => {}
+                    // Interrupted by a signal after some of the data was written.
+                    Err(_) if written != 0 => break,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            let count = self.input(&buffer[written..]);
+
+            // The input buffer is full and the master is non-blocking.
+            if count == 0 {
+                if written == 0 {
+                    return Err(FileSystemError::WouldBlock);
+                }
+
+                break;
+            }
+
+            written += count;
+        }
+
+        Ok(written)
     }
 
     fn poll(&self, table: Option<&mut fs::inode::PollTable>) -> fs::Result<fs::inode::PollFlags> {
         if let Some(e) = table {
-            e.insert(&self.wq)
+            e.insert(&self.wq);
+            e.insert(self.discipline.wait_queue());
+        }
+
+        let mut flags = fs::inode::PollFlags::empty();
+
+        if self.discipline.has_space() {
+            flags |= fs::inode::PollFlags::OUT;
         }
-        let mut flags = fs::inode::PollFlags::OUT;
 
         if !self.buffer.lock_irq().is_empty() {
             flags |= fs::inode::PollFlags::IN;
@@ -185,14 +262,19 @@ impl INodeInterface for Master {
 impl TerminalDevice for Slave {
     fn attach(&self, task: Arc<Task>) {
         assert!(task.is_session_leader());
-        self.master.discipline.set_foreground(&task);
+        self.master.discipline.attach(&task);
     }
 
     fn detach(&self, task: Arc<Task>) {
         use aero_syscall::signal::SIGINT;
         use aero_syscall::VINTR;
 
-        if !self.master.discipline.termios.lock().is_cooked() {
+        // The terminal is hung up once the session leader disassociates from it.
+        if task.is_session_leader() {
+            self.master.discipline.hangup();
+        }
+
+        if !task.has_exited() || !self.master.discipline.termios.lock().is_cooked() {
             return;
         }
 
@@ -215,6 +297,7 @@ impl TerminalDevice for Slave {
 struct Slave {
     sref: Weak<Self>,
     master: Arc<Master>,
+    handle: Once<Arc<FileHandle>>,
 }
 
 impl Slave {
@@ -222,15 +305,38 @@ impl Slave {
         Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
             master,
+            handle: Once::new(),
         })
     }
 
     fn sref(&self) -> Arc<Self> {
         self.sref.upgrade().unwrap()
     }
+
+    fn is_nonblock(&self) -> bool {
+        self.handle
+            .get()
+            .is_some_and(|handle| handle.flags().contains(libc::OpenFlags::O_NONBLOCK))
+    }
+
+    /// Returns the session of the current process if this terminal is its controlling terminal.
+    fn controlling_session(&self) -> fs::Result<Arc<Session>> {
+        let current_task = scheduler::current_thread();
+
+        self.master
+            .discipline
+            .session()
+            .filter(|session| session.id() == current_task.session_id())
+            .ok_or(FileSystemError::NoTty)
+    }
 }
 
 impl INodeInterface for Slave {
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
     fn metadata(&self) -> fs::Result<fs::inode::Metadata> {
         Ok(fs::inode::Metadata::with_file_type(FileType::Device))
     }
@@ -253,13 +359,46 @@ impl INodeInterface for Slave {
 
             TermiosCmd::SetCtrlTerm => {
                 let current_task = scheduler::get_scheduler().current_task();
-                assert!(current_task.is_session_leader());
 
-                current_task.attach(self.sref());
+                match self.master.discipline.session() {
+                    // The terminal is already the controlling terminal of the session.
+                    Some(session) if session.id() == current_task.session_id() => {}
+                    Some(_) => return Err(FileSystemError::PermissionDenied),
+
+                    None => {
+                        if !current_task.attach(self.sref()) {
+                            return Err(FileSystemError::PermissionDenied);
+                        }
+                    }
+                }
+            }
+
+            TermiosCmd::GetProcGroupId(mut pgid) => {
+                self.controlling_session()?;
+
+                *pgid = self
+                    .master
+                    .discipline
+                    .foreground()
+                    .map_or(0, |group| group.id() as i32);
             }
 
-            // FIXME: the following ioctls are not implemented.
-            TermiosCmd::GetProcGroupId => return Err(FileSystemError::NotSupported),
+            TermiosCmd::SetProcGroupId(pgid) => {
+                let group = self
+                    .controlling_session()?
+                    .find_group(*pgid as usize)
+                    .ok_or(FileSystemError::PermissionDenied)?;
+
+                self.master.discipline.set_foreground(&group);
+            }
+
+            TermiosCmd::SimulateInput(byte) => {
+                terminal::check_tiocsti(self)?;
+
+                if self.master.input(&[*byte]) == 0 {
+                    return Err(FileSystemError::WouldBlock);
+                }
+            }
         }
 
         Ok(0)
@@ -271,7 +410,11 @@ impl INodeInterface for Slave {
             table.insert(self.master.discipline.wait_queue());
         }
 
-        let mut flags = PollFlags::OUT;
+        let mut flags = PollFlags::empty();
+
+        if self.master.buffer.lock_irq().len() < PTY_BUF_SIZE {
+            flags |= PollFlags::OUT;
+        }
 
         if !self.master.discipline.is_empty() {
             flags |= PollFlags::IN;
@@ -281,35 +424,75 @@ impl INodeInterface for Slave {
     }
 
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        self.master
+            .discipline
+            .check_job_control(aero_syscall::signal::SIGTTIN)?;
+
         Ok(self.master.discipline.read(buffer)?)
     }
 
+    /// Writes the provided buffer to the master, blocking while the output buffer is full
+    /// unless the slave is non-blocking.
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        if self
+        self.master
+            .discipline
+            .check_job_control(aero_syscall::signal::SIGTTOU)?;
+
+        let onlcr = self
             .master
             .discipline
             .termios()
             .c_oflag
-            .contains(aero_syscall::TermiosOFlag::ONLCR)
-        {
-            let mut master = self.master.buffer.lock_irq();
+            .contains(aero_syscall::TermiosOFlag::ONLCR);
+
+        let nonblock = self.is_nonblock();
+        let mut written = 0;
+
+        while written < buffer.len() {
+            let output = if nonblock {
+                Ok(self.master.buffer.lock_irq())
+            } else {
+                self.master
+                    .wq
+                    .block_on(&self.master.buffer, |lock| lock.len() < PTY_BUF_SIZE)
+            };
+
+            let mut output = match output {
+                Ok(output) => output,
+                // Interrupted by a signal after some of the data was written.
+                Err(_) if written != 0 => break,
+                Err(err) => return Err(err.into()),
+            };
 
-            for b in buffer.iter() {
-                if *b == b'\n' {
+            // The output buffer is full and the slave is non-blocking.
+            if output.len() >= PTY_BUF_SIZE {
+                if written == 0 {
+                    return Err(FileSystemError::WouldBlock);
+                }
+
+                break;
+            }
+
+            for &byte in &buffer[written..] {
+                if output.len() >= PTY_BUF_SIZE {
+                    break;
+                }
+
+                if byte == b'\n' && onlcr {
                     // ONLCR: Convert NL to CR + NL
-                    master.extend_from_slice(&[b'\r', b'\n']);
-                    continue;
+                    output.extend_from_slice(&[b'\r', b'\n']);
+                } else {
+                    output.push(byte);
                 }
 
-                master.push(*b);
+                written += 1;
             }
-        } else {
-            let mut pty_buffer = self.master.buffer.lock_irq();
-            pty_buffer.extend_from_slice(buffer);
+
+            drop(output);
+            self.master.wq.notify_all();
         }
 
-        self.master.wq.notify_all();
-        Ok(buffer.len())
+        Ok(written)
     }
 }
 
@@ -337,6 +520,10 @@ impl devfs::Device for Ptmx {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         PTMX.clone()
     }
+
+    fn device_number(&self) -> Option<devfs::DeviceNumber> {
+        Some(devfs::DeviceNumber::Char(5, 2))
+    }
 }
 
 impl INodeInterface for Ptmx {
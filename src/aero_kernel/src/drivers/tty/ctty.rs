@@ -24,6 +24,7 @@ use crate::fs::devfs::Device;
 use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
 use crate::fs::{self, devfs, FileSystemError};
 use crate::userland::scheduler;
+use crate::userland::task::sessions::SESSIONS;
 
 struct Ctty {
     device_id: usize,
@@ -86,6 +87,14 @@ impl INodeInterface for Ctty {
                 let current_task = scheduler::get_scheduler().current_task();
                 current_task.detach();
 
+                // If the session leader gives up the controlling terminal, the terminal is
+                // hung up and all of the processes in the session lose it.
+                if current_task.is_session_leader() {
+                    if let Some(session) = SESSIONS.find(current_task.session_id()) {
+                        session.clear_controlling_terminal();
+                    }
+                }
+
                 Ok(0)
             }
 
@@ -109,6 +118,11 @@ impl Device for Ctty {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         self.sref()
     }
+
+    #[inline]
+    fn device_number(&self) -> Option<devfs::DeviceNumber> {
+        Some(devfs::DeviceNumber::Char(5, 0))
+    }
 }
 
 /// Registers the `/dev/ctty` character device.
@@ -16,6 +16,7 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 mod ctty;
+pub mod keymap;
 mod vtty;
 
 fn init() {
@@ -30,14 +30,19 @@ use crate::fs::inode::INodeInterface;
 use crate::mem::paging::VirtAddr;
 use crate::userland::scheduler;
 use crate::userland::task::Task;
-use crate::userland::terminal::TerminalDevice;
+use crate::userland::terminal::{self, TerminalDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::keymap;
+
 #[cfg(target_arch = "x86_64")]
 use crate::drivers::keyboard::KeyCode;
 #[cfg(target_arch = "x86_64")]
 use crate::drivers::keyboard::KeyboardListener;
 
+#[cfg(target_arch = "x86_64")]
+use super::keymap::Modifiers;
+
 lazy_static::lazy_static! {
     static ref TTY: Arc<Tty> = Tty::new();
 
@@ -53,105 +58,6 @@ lazy_static::lazy_static! {
     });
 }
 
-// From the linux kernel: https://github.com/torvalds/linux/blob/master/drivers/tty/vt/defkeymap.c_shipped
-const PLAIN_MAP: &[u16; 128] = &[
-    0xf200, 0xf01b, 0xf031, 0xf032, 0xf033, 0xf034, 0xf035, 0xf036, 0xf037, 0xf038, 0xf039, 0xf030,
-    0xf02d, 0xf03d, 0xf07f, 0xf009, 0xfb71, 0xfb77, 0xfb65, 0xfb72, 0xfb74, 0xfb79, 0xfb75, 0xfb69,
-    0xfb6f, 0xfb70, 0xf05b, 0xf05d, 0xf201, 0xf702, 0xfb61, 0xfb73, 0xfb64, 0xfb66, 0xfb67, 0xfb68,
-    0xfb6a, 0xfb6b, 0xfb6c, 0xf03b, 0xf027, 0xf060, 0xf700, 0xf05c, 0xfb7a, 0xfb78, 0xfb63, 0xfb76,
-    0xfb62, 0xfb6e, 0xfb6d, 0xf02c, 0xf02e, 0xf02f, 0xf700, 0xf30c, 0xf703, 0xf020, 0xf207, 0xf100,
-    0xf101, 0xf102, 0xf103, 0xf104, 0xf105, 0xf106, 0xf107, 0xf108, 0xf109, 0xf208, 0xf209, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf03c, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const SHIFT_MAP: &[u16; 128] = &[
-    0xf200, 0xf01b, 0xf021, 0xf040, 0xf023, 0xf024, 0xf025, 0xf05e, 0xf026, 0xf02a, 0xf028, 0xf029,
-    0xf05f, 0xf02b, 0xf07f, 0xf009, 0xfb51, 0xfb57, 0xfb45, 0xfb52, 0xfb54, 0xfb59, 0xfb55, 0xfb49,
-    0xfb4f, 0xfb50, 0xf07b, 0xf07d, 0xf201, 0xf702, 0xfb41, 0xfb53, 0xfb44, 0xfb46, 0xfb47, 0xfb48,
-    0xfb4a, 0xfb4b, 0xfb4c, 0xf03a, 0xf022, 0xf07e, 0xf700, 0xf07c, 0xfb5a, 0xfb58, 0xfb43, 0xfb56,
-    0xfb42, 0xfb4e, 0xfb4d, 0xf03c, 0xf03e, 0xf03f, 0xf700, 0xf30c, 0xf703, 0xf020, 0xf207, 0xf10a,
-    0xf10b, 0xf10c, 0xf10d, 0xf10e, 0xf10f, 0xf110, 0xf111, 0xf112, 0xf113, 0xf213, 0xf203, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf03e, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf20b, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf20a, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const ALTGR_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf040, 0xf200, 0xf024, 0xf200, 0xf200, 0xf07b, 0xf05b, 0xf05d, 0xf07d,
-    0xf05c, 0xf200, 0xf200, 0xf200, 0xfb71, 0xfb77, 0xf918, 0xfb72, 0xfb74, 0xfb79, 0xfb75, 0xfb69,
-    0xfb6f, 0xfb70, 0xf200, 0xf07e, 0xf201, 0xf702, 0xf914, 0xfb73, 0xf917, 0xf919, 0xfb67, 0xfb68,
-    0xfb6a, 0xfb6b, 0xfb6c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xfb7a, 0xfb78, 0xf916, 0xfb76,
-    0xf915, 0xfb6e, 0xfb6d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf50c,
-    0xf50d, 0xf50e, 0xf50f, 0xf510, 0xf511, 0xf512, 0xf513, 0xf514, 0xf515, 0xf208, 0xf202, 0xf911,
-    0xf912, 0xf913, 0xf30b, 0xf90e, 0xf90f, 0xf910, 0xf30a, 0xf90b, 0xf90c, 0xf90d, 0xf90a, 0xf310,
-    0xf206, 0xf200, 0xf07c, 0xf516, 0xf517, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const CTRL_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf000, 0xf01b, 0xf01c, 0xf01d, 0xf01e, 0xf01f, 0xf07f, 0xf200, 0xf200,
-    0xf01f, 0xf200, 0xf008, 0xf200, 0xf011, 0xf017, 0xf005, 0xf012, 0xf014, 0xf019, 0xf015, 0xf009,
-    0xf00f, 0xf010, 0xf01b, 0xf01d, 0xf201, 0xf702, 0xf001, 0xf013, 0xf004, 0xf006, 0xf007, 0xf008,
-    0xf00a, 0xf00b, 0xf00c, 0xf200, 0xf007, 0xf000, 0xf700, 0xf01c, 0xf01a, 0xf018, 0xf003, 0xf016,
-    0xf002, 0xf00e, 0xf00d, 0xf200, 0xf20e, 0xf07f, 0xf700, 0xf30c, 0xf703, 0xf000, 0xf207, 0xf100,
-    0xf101, 0xf102, 0xf103, 0xf104, 0xf105, 0xf106, 0xf107, 0xf108, 0xf109, 0xf208, 0xf204, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf200, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const SHIFT_CTRL_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf000, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf01f, 0xf200, 0xf200, 0xf200, 0xf011, 0xf017, 0xf005, 0xf012, 0xf014, 0xf019, 0xf015, 0xf009,
-    0xf00f, 0xf010, 0xf200, 0xf200, 0xf201, 0xf702, 0xf001, 0xf013, 0xf004, 0xf006, 0xf007, 0xf008,
-    0xf00a, 0xf00b, 0xf00c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xf01a, 0xf018, 0xf003, 0xf016,
-    0xf002, 0xf00e, 0xf00d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf200,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf208, 0xf200, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const ALT_MAP: &[u16; 128] = &[
-    0xf200, 0xf81b, 0xf831, 0xf832, 0xf833, 0xf834, 0xf835, 0xf836, 0xf837, 0xf838, 0xf839, 0xf830,
-    0xf82d, 0xf83d, 0xf87f, 0xf809, 0xf871, 0xf877, 0xf865, 0xf872, 0xf874, 0xf879, 0xf875, 0xf869,
-    0xf86f, 0xf870, 0xf85b, 0xf85d, 0xf80d, 0xf702, 0xf861, 0xf873, 0xf864, 0xf866, 0xf867, 0xf868,
-    0xf86a, 0xf86b, 0xf86c, 0xf83b, 0xf827, 0xf860, 0xf700, 0xf85c, 0xf87a, 0xf878, 0xf863, 0xf876,
-    0xf862, 0xf86e, 0xf86d, 0xf82c, 0xf82e, 0xf82f, 0xf700, 0xf30c, 0xf703, 0xf820, 0xf207, 0xf500,
-    0xf501, 0xf502, 0xf503, 0xf504, 0xf505, 0xf506, 0xf507, 0xf508, 0xf509, 0xf208, 0xf209, 0xf907,
-    0xf908, 0xf909, 0xf30b, 0xf904, 0xf905, 0xf906, 0xf30a, 0xf901, 0xf902, 0xf903, 0xf900, 0xf310,
-    0xf206, 0xf200, 0xf83c, 0xf50a, 0xf50b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf210, 0xf211, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const CTRL_ALT_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf811, 0xf817, 0xf805, 0xf812, 0xf814, 0xf819, 0xf815, 0xf809,
-    0xf80f, 0xf810, 0xf200, 0xf200, 0xf201, 0xf702, 0xf801, 0xf813, 0xf804, 0xf806, 0xf807, 0xf808,
-    0xf80a, 0xf80b, 0xf80c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xf81a, 0xf818, 0xf803, 0xf816,
-    0xf802, 0xf80e, 0xf80d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf500,
-    0xf501, 0xf502, 0xf503, 0xf504, 0xf505, 0xf506, 0xf507, 0xf508, 0xf509, 0xf208, 0xf200, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf20c,
-    0xf206, 0xf200, 0xf200, 0xf50a, 0xf50b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf20c, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
 struct StdinBuffer {
     back_buffer: Vec<u8>,
     front_buffer: Vec<u8>, // more like a queue
@@ -182,6 +88,22 @@ impl StdinBuffer {
     fn advance_cursor(&mut self) {
         self.cursor += 1;
     }
+
+    /// Removes the last character, which may span multiple bytes, from the back buffer.
+    fn pop_char(&mut self) -> bool {
+        if self.back_buffer.is_empty() {
+            return false;
+        }
+
+        let start = self
+            .back_buffer
+            .iter()
+            .rposition(|byte| byte & 0xc0 != 0x80)
+            .unwrap_or(0);
+
+        self.back_buffer.truncate(start);
+        true
+    }
 }
 
 struct TtyState {
@@ -341,6 +263,59 @@ impl INodeInterface for Tty {
                 Ok(0x00)
             }
 
+            aero_syscall::KDGKBTYPE => {
+                let kb_type = VirtAddr::new(arg as u64);
+                let kb_type = unsafe { &mut *(kb_type.as_mut_ptr::<u8>()) };
+
+                *kb_type = aero_syscall::KB_101;
+                Ok(0x00)
+            }
+
+            aero_syscall::KDGKBENT => {
+                let entry = VirtAddr::new(arg as u64);
+                let entry = unsafe { &mut *(entry.as_mut_ptr::<aero_syscall::KbEntry>()) };
+
+                entry.kb_value =
+                    keymap::get_entry(entry.kb_table as usize, entry.kb_index as usize)
+                        .ok_or(FileSystemError::InvalidArgument)?;
+
+                Ok(0x00)
+            }
+
+            aero_syscall::KDSKBENT => {
+                let entry = VirtAddr::new(arg as u64);
+                let entry = unsafe { &*(entry.as_ptr::<aero_syscall::KbEntry>()) };
+
+                keymap::set_entry(
+                    entry.kb_table as usize,
+                    entry.kb_index as usize,
+                    entry.kb_value,
+                )
+                .ok_or(FileSystemError::InvalidArgument)?;
+
+                Ok(0x00)
+            }
+
+            aero_syscall::TIOCSTI => {
+                terminal::check_tiocsti(self)?;
+
+                let byte = VirtAddr::new(arg as u64);
+                let byte = unsafe { *byte.as_ptr::<u8>() };
+
+                self.stdin.lock_irq().back_buffer.push(byte);
+
+                if TERMIOS
+                    .lock_irq()
+                    .c_lflag
+                    .contains(aero_syscall::TermiosLFlag::ECHO)
+                {
+                    rendy::print!("{}", byte as char);
+                }
+
+                self.block_queue.notify_all();
+                Ok(0x00)
+            }
+
             _ => Err(fs::FileSystemError::NotSupported),
         }
     }
@@ -358,6 +333,10 @@ impl devfs::Device for Tty {
     fn inode(&self) -> Arc<dyn inode::INodeInterface> {
         self.sref.upgrade().unwrap()
     }
+
+    fn device_number(&self) -> Option<devfs::DeviceNumber> {
+        Some(devfs::DeviceNumber::Char(4, 0))
+    }
 }
 
 impl TerminalDevice for Tty {
@@ -386,36 +365,32 @@ impl KeyboardListener for Tty {
         };
 
         let lchar = || {
-            let mut shift = state.lshift || state.rshift;
-            let ctrl = state.lctrl || state.rctrl;
+            let mut modifiers = Modifiers::empty();
 
-            if state.caps {
-                shift = !shift;
-            }
+            modifiers.set(Modifiers::SHIFT, state.lshift || state.rshift);
+            modifiers.set(Modifiers::CTRL, state.lctrl || state.rctrl);
+            modifiers.set(Modifiers::ALT, state.lalt);
+            modifiers.set(Modifiers::ALTGR, state.altgr);
 
-            let map = match (shift, ctrl, state.lalt, state.altgr) {
-                (false, false, false, false) => PLAIN_MAP,
-                (true, false, false, false) => SHIFT_MAP,
-                (false, true, false, false) => CTRL_MAP,
-                (false, false, true, false) => ALT_MAP,
-                (false, false, false, true) => ALTGR_MAP,
-                (true, true, false, false) => SHIFT_CTRL_MAP,
-                (false, true, true, false) => CTRL_ALT_MAP,
-                _ => PLAIN_MAP,
-            };
+            let mut sym = keymap::lookup(modifiers, key as usize);
 
-            let character =
-                unsafe { core::char::from_u32_unchecked((map[key as usize] & 0xff) as _) };
+            // Caps lock only inverts shift for letters.
+            if state.caps && keymap::is_letter(sym) {
+                sym = keymap::lookup(modifiers ^ Modifiers::SHIFT, key as usize);
+            }
 
-            // Check if the character is actually printable printable.
-            if !(0x20..0x7e).contains(&(character as u32)) {
+            // Check if the character is actually printable.
+            let Some(character) = keymap::to_char(sym).filter(|c| !c.is_control()) else {
                 return;
-            }
+            };
 
             {
                 let mut stdin = self.stdin.lock_irq();
+                let mut bytes = [0; 4];
 
-                stdin.back_buffer.push(character as u8);
+                stdin
+                    .back_buffer
+                    .extend_from_slice(character.encode_utf8(&mut bytes).as_bytes());
                 stdin.advance_cursor();
             }
 
@@ -428,9 +403,7 @@ impl KeyboardListener for Tty {
             if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ICANON) {
                 let mut stdin = self.stdin.lock_irq();
 
-                if stdin.back_buffer.pop().is_some()
-                    && termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO)
-                {
+                if stdin.pop_char() && termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
                     rendy::backspace();
                     stdin.cursor -= 1;
                 }
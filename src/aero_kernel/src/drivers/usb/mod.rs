@@ -0,0 +1,427 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! USB (Universal Serial Bus) support.
+//!
+//! This module contains the definitions shared by the host controller drivers and the USB device
+//! drivers. Only the xHCI (USB 3) host controller is supported.
+//!
+//! Once a host controller driver has enumerated and configured a device, it hands the device
+//! over to [`probe`], which starts the registered [`UsbDriver`] that handles each of the
+//! interfaces of the device.
+//!
+//! ## Notes
+//! * <https://www.usb.org/document-library/usb-20-specification> (chapter 9)
+
+pub mod storage;
+pub mod xhci;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::mem::paging::PhysAddr;
+use crate::utils::sync::Mutex;
+
+/// Speed of a USB device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Speed {
+    /// 1.5 Mb/s (USB 1.0).
+    Low,
+    /// 12 Mb/s (USB 1.1).
+    Full,
+    /// 480 Mb/s (USB 2.0).
+    High,
+    /// 5 Gb/s (USB 3.0).
+    Super,
+    /// 10 Gb/s (USB 3.1) or faster.
+    SuperPlus,
+}
+
+impl Speed {
+    /// Returns the maximum packet size of the default control endpoint that is used until the
+    /// device descriptor has been read.
+    pub fn default_max_packet_size(&self) -> u16 {
+        match self {
+            Self::Low | Self::Full => 8,
+            Self::High => 64,
+            Self::Super | Self::SuperPlus => 512,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DescriptorType {
+    Device = 1,
+    Configuration = 2,
+    Interface = 4,
+    Endpoint = 5,
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Request {
+    GetDescriptor = 6,
+    SetConfiguration = 9,
+}
+
+bitflags::bitflags! {
+    /// The `bmRequestType` field of a [`SetupPacket`].
+    #[derive(Debug, Copy, Clone)]
+    pub struct RequestType: u8 {
+        /// The data stage transfers data from the device to the host.
+        const DEVICE_TO_HOST = 1 << 7;
+        const CLASS          = 1 << 5;
+        const INTERFACE      = 1;
+    }
+}
+
+/// Setup packet of a control transfer.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct SetupPacket {
+    pub request_type: RequestType,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+const_assert_eq!(core::mem::size_of::<SetupPacket>(), 8);
+
+impl SetupPacket {
+    /// Creates a standard `GET_DESCRIPTOR` request for the descriptor with the provided type
+    /// and index.
+    pub fn get_descriptor(typ: DescriptorType, index: u8, length: u16) -> Self {
+        Self {
+            request_type: RequestType::DEVICE_TO_HOST,
+            request: Request::GetDescriptor as u8,
+            value: (typ as u16) << 8 | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    /// Creates a standard `SET_CONFIGURATION` request.
+    pub fn set_configuration(value: u8) -> Self {
+        Self {
+            request_type: RequestType::empty(),
+            request: Request::SetConfiguration as u8,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    /// Returns whether the data stage of the transfer is from the device to the host.
+    pub fn is_in(&self) -> bool {
+        self.request_type.contains(RequestType::DEVICE_TO_HOST)
+    }
+
+    /// Returns the packet as the little-endian integer that is placed in the setup TRB.
+    pub fn as_u64(&self) -> u64 {
+        self.request_type.bits() as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct DeviceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    /// USB specification release number in binary-coded decimal (e.g. 0x0210 for USB 2.1).
+    pub usb: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    /// Maximum packet size of the default control endpoint. For SuperSpeed devices the size
+    /// is `1 << max_packet_size0` instead.
+    pub max_packet_size0: u8,
+    pub vendor: u16,
+    pub product: u16,
+    /// Device release number in binary-coded decimal.
+    pub device: u16,
+    pub manufacturer_index: u8,
+    pub product_index: u8,
+    pub serial_index: u8,
+    pub num_configurations: u8,
+}
+
+const_assert_eq!(core::mem::size_of::<DeviceDescriptor>(), 18);
+
+/// Type of the transfers that an endpoint performs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Endpoint {
+    /// Endpoint address; the endpoint number with bit 7 set for IN endpoints.
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl Endpoint {
+    /// Returns whether the endpoint transfers data from the device to the host.
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn number(&self) -> u8 {
+        self.address & 0xf
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0b11 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub number: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<Endpoint>,
+}
+
+impl Interface {
+    /// Returns the first endpoint of the interface with the provided transfer type and
+    /// direction.
+    pub fn find_endpoint(&self, typ: TransferType, is_in: bool) -> Option<Endpoint> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.transfer_type() == typ && endpoint.is_in() == is_in)
+            .copied()
+    }
+}
+
+/// A configuration of a device, parsed from the configuration descriptor and the interface
+/// and endpoint descriptors that follow it. Only the default alternate setting of each
+/// interface is included.
+#[derive(Debug, Clone)]
+pub struct Configuration {
+    pub value: u8,
+    pub interfaces: Vec<Interface>,
+}
+
+impl Configuration {
+    /// Size of the configuration descriptor itself.
+    pub const DESCRIPTOR_SIZE: usize = 9;
+
+    /// Returns the total length of the configuration, as reported by the configuration
+    /// descriptor at the start of `data`.
+    pub fn total_length(data: &[u8]) -> Option<u16> {
+        if data.len() < Self::DESCRIPTOR_SIZE || data[1] != DescriptorType::Configuration as u8 {
+            return None;
+        }
+
+        Some(u16::from_le_bytes([data[2], data[3]]))
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        Self::total_length(data)?;
+
+        let mut configuration = Self {
+            value: data[5],
+            interfaces: Vec::new(),
+        };
+
+        // Whether the descriptors belong to an alternate setting that is skipped.
+        let mut skip = false;
+        let mut offset = data[0] as usize;
+
+        while offset + 2 <= data.len() {
+            let length = data[offset] as usize;
+
+            if length < 2 || offset + length > data.len() {
+                return None;
+            }
+
+            let descriptor = &data[offset..offset + length];
+
+            match descriptor[1] {
+                typ if typ == DescriptorType::Interface as u8 && length >= 9 => {
+                    skip = descriptor[3] != 0;
+
+                    if !skip {
+                        configuration.interfaces.push(Interface {
+                            number: descriptor[2],
+                            class: descriptor[5],
+                            subclass: descriptor[6],
+                            protocol: descriptor[7],
+                            endpoints: Vec::new(),
+                        });
+                    }
+                }
+
+                typ if typ == DescriptorType::Endpoint as u8 && length >= 7 && !skip => {
+                    if let Some(interface) = configuration.interfaces.last_mut() {
+                        interface.endpoints.push(Endpoint {
+                            address: descriptor[2],
+                            attributes: descriptor[3],
+                            max_packet_size: u16::from_le_bytes([descriptor[4], descriptor[5]]),
+                            interval: descriptor[6],
+                        });
+                    }
+                }
+
+                // Class-specific and other descriptors are ignored.
+                _ => {}
+            }
+
+            offset += length;
+        }
+
+        Some(configuration)
+    }
+}
+
+/// Error returned by a failed transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    /// The transfer failed; for example because the device stalled the endpoint.
+    Failed,
+    /// The device has been disconnected.
+    Disconnected,
+    /// The wait for the transfer was interrupted by a signal.
+    Interrupted,
+}
+
+/// A configured device, as exposed by a host controller driver to the USB device drivers.
+pub trait UsbDevice: Send + Sync {
+    /// Performs a control transfer on the default control endpoint. `buffer` has to be
+    /// provided if the request has a data stage, and must be at least `setup.length` bytes.
+    fn control_transfer(
+        &self,
+        setup: SetupPacket,
+        buffer: Option<PhysAddr>,
+    ) -> Result<(), TransferError>;
+
+    /// Performs a bulk transfer of `length` bytes on the endpoint with the provided address.
+    /// Returns the number of bytes that were transferred.
+    fn bulk_transfer(
+        &self,
+        endpoint: u8,
+        buffer: PhysAddr,
+        length: usize,
+    ) -> Result<usize, TransferError>;
+}
+
+pub trait UsbDriver: Send + Sync {
+    /// Returns true if the driver handles the provided interface.
+    fn handles(&self, interface: &Interface) -> bool;
+
+    /// Starts the driver for `interface` of `device`. The endpoints of the interface have
+    /// been configured already.
+    fn start(&self, device: Arc<dyn UsbDevice>, interface: &Interface);
+}
+
+static DRIVERS: Mutex<Vec<Arc<dyn UsbDriver>>> = Mutex::new(Vec::new());
+
+pub fn register_driver(driver: Arc<dyn UsbDriver>) {
+    DRIVERS.lock().push(driver);
+}
+
+/// Starts the drivers for the interfaces of a newly configured device.
+pub fn probe(device: Arc<dyn UsbDevice>, configuration: &Configuration) {
+    for interface in configuration.interfaces.iter() {
+        let driver = DRIVERS
+            .lock()
+            .iter()
+            .find(|driver| driver.handles(interface))
+            .cloned();
+
+        if let Some(driver) = driver {
+            driver.start(device.clone(), interface);
+        } else {
+            log::debug!(
+                "usb: no driver for interface {} (class={:#04x}, subclass={:#04x}, protocol={:#04x})",
+                interface.number,
+                interface.class,
+                interface.subclass,
+                interface.protocol
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_configuration() {
+        #[rustfmt::skip]
+        let data = [
+            // configuration (value=1, total length=39)
+            9, 2, 39, 0, 2, 1, 0, 0x80, 50,
+            // interface 0
+            9, 4, 0, 0, 2, 0x08, 0x06, 0x50, 0,
+            // bulk IN endpoint 1
+            7, 5, 0x81, 2, 0x00, 0x02, 0,
+            // bulk OUT endpoint 2
+            7, 5, 0x02, 2, 0x00, 0x02, 0,
+            // interface 0, alternate setting 1
+            9, 4, 0, 1, 1, 0x08, 0x06, 0x62, 0,
+            // interrupt IN endpoint 3 (of the alternate setting)
+            7, 5, 0x83, 3, 0x08, 0x00, 10,
+        ];
+
+        assert_eq!(
+            Configuration::total_length(&data),
+            Some(data.len() as u16 - 2)
+        );
+
+        let configuration = Configuration::parse(&data).unwrap();
+        assert_eq!(configuration.value, 1);
+        assert_eq!(configuration.interfaces.len(), 1);
+
+        let interface = &configuration.interfaces[0];
+        assert_eq!(
+            (interface.class, interface.subclass, interface.protocol),
+            (8, 6, 0x50)
+        );
+        assert_eq!(interface.endpoints.len(), 2);
+
+        let bulk_in = interface.find_endpoint(TransferType::Bulk, true).unwrap();
+        assert_eq!((bulk_in.number(), bulk_in.max_packet_size), (1, 512));
+
+        let bulk_out = interface.find_endpoint(TransferType::Bulk, false).unwrap();
+        assert_eq!(bulk_out.address, 0x02);
+
+        assert!(interface
+            .find_endpoint(TransferType::Interrupt, true)
+            .is_none());
+        assert!(Configuration::parse(&data[1..]).is_none());
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! USB mass storage class driver.
+//!
+//! Only the bulk-only transport with the SCSI transparent command set is supported, which is
+//! what USB flash drives and most external disks implement. Each SCSI command is sent in a
+//! command block wrapper (CBW) on the bulk OUT endpoint, followed by the data stage on the bulk
+//! endpoint in the direction of the transfer and a command status wrapper (CSW) on the bulk IN
+//! endpoint.
+//!
+//! ## Notes
+//! * <https://www.usb.org/sites/default/files/usbmassbulk_10.pdf>
+//! * Only the first logical unit of a device is used.
+//! * The block device is not removed when the device is disconnected; its I/O fails instead.
+
+use core::mem::MaybeUninit;
+
+use alloc::sync::Arc;
+
+use crate::fs::block::{self, install_block_device, BlockDevice, BlockDeviceInterface};
+use crate::mem::paging::PhysAddr;
+use crate::userland::scheduler::hrtimer;
+use crate::utils::dma::Dma;
+use crate::utils::sync::BMutex;
+
+use super::{Interface, TransferError, TransferType, UsbDevice, UsbDriver};
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+
+/// Flag of a CBW whose data stage is from the device to the host.
+const CBW_DATA_IN: u8 = 1 << 7;
+
+/// Maximum number of bytes transferred by a single READ(10) or WRITE(10) command.
+const MAX_TRANSFER_SIZE: usize = 0x10000;
+
+/// Number of times TEST UNIT READY is retried while the device is becoming ready.
+const READY_RETRIES: usize = 10;
+
+#[derive(Debug, Copy, Clone)]
+enum Error {
+    /// The interface does not have a bulk IN and a bulk OUT endpoint.
+    MissingEndpoint,
+    TransferFailed,
+    Disconnected,
+    Interrupted,
+    /// The device did not send a valid CSW.
+    InvalidStatus,
+    CommandFailed,
+    PhaseError,
+    NotReady,
+    /// The medium reported a block size that is not supported.
+    InvalidBlockSize,
+}
+
+impl From<TransferError> for Error {
+    fn from(err: TransferError) -> Self {
+        match err {
+            TransferError::Failed => Error::TransferFailed,
+            TransferError::Disconnected => Error::Disconnected,
+            TransferError::Interrupted => Error::Interrupted,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Opcode {
+    TestUnitReady = 0x00,
+    RequestSense = 0x03,
+    Inquiry = 0x12,
+    ReadCapacity10 = 0x25,
+    Read10 = 0x28,
+    Write10 = 0x2a,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C, packed)]
+struct CommandBlockWrapper {
+    signature: u32,
+    tag: u32,
+    transfer_length: u32,
+    flags: u8,
+    lun: u8,
+    command_length: u8,
+    command: [u8; 16],
+}
+
+const_assert_eq!(core::mem::size_of::<CommandBlockWrapper>(), 31);
+
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C, packed)]
+struct CommandStatusWrapper {
+    signature: u32,
+    tag: u32,
+    residue: u32,
+    status: u8,
+}
+
+const_assert_eq!(core::mem::size_of::<CommandStatusWrapper>(), 13);
+
+/// Data stage of a command.
+#[derive(Debug, Copy, Clone)]
+enum Data {
+    None,
+    In(PhysAddr, usize),
+    Out(PhysAddr, usize),
+}
+
+struct Transport {
+    tag: u32,
+    cbw: Dma<CommandBlockWrapper>,
+    csw: Dma<CommandStatusWrapper>,
+}
+
+struct Disk {
+    device: Arc<dyn UsbDevice>,
+    bulk_in: u8,
+    bulk_out: u8,
+
+    block_size: usize,
+    block_count: usize,
+
+    /// The CBW, the data stage and the CSW of a command are not interleaved with those of
+    /// other commands.
+    transport: BMutex<Transport>,
+}
+
+impl Disk {
+    fn new(device: Arc<dyn UsbDevice>, interface: &Interface) -> Result<Arc<Self>, Error> {
+        let bulk_in = interface
+            .find_endpoint(TransferType::Bulk, true)
+            .ok_or(Error::MissingEndpoint)?;
+        let bulk_out = interface
+            .find_endpoint(TransferType::Bulk, false)
+            .ok_or(Error::MissingEndpoint)?;
+
+        let mut disk = Self {
+            device,
+            bulk_in: bulk_in.address,
+            bulk_out: bulk_out.address,
+
+            block_size: 0,
+            block_count: 0,
+
+            transport: BMutex::new(Transport {
+                tag: 0,
+                cbw: Dma::zeroed(),
+                csw: Dma::zeroed(),
+            }),
+        };
+
+        disk.inquiry()?;
+        disk.wait_ready()?;
+
+        let (block_size, block_count) = disk.read_capacity()?;
+        disk.block_size = block_size;
+        disk.block_count = block_count;
+
+        Ok(Arc::new(disk))
+    }
+
+    /// Executes a SCSI command and returns the number of bytes transferred in its data stage.
+    fn command(&self, command: &[u8], data: Data) -> Result<usize, Error> {
+        let mut transport = self.transport.lock();
+
+        transport.tag = transport.tag.wrapping_add(1);
+        let tag = transport.tag;
+
+        let (length, flags) = match data {
+            Data::None => (0, 0),
+            Data::In(_, length) => (length, CBW_DATA_IN),
+            Data::Out(_, length) => (length, 0),
+        };
+
+        let mut block = [0; 16];
+        block[..command.len()].copy_from_slice(command);
+
+        *transport.cbw = CommandBlockWrapper {
+            signature: CBW_SIGNATURE,
+            tag,
+            transfer_length: length as u32,
+            flags,
+            lun: 0,
+            command_length: command.len() as u8,
+            command: block,
+        };
+
+        let size = core::mem::size_of::<CommandBlockWrapper>();
+        self.device
+            .bulk_transfer(self.bulk_out, transport.cbw.addr(), size)?;
+
+        let transferred = match data {
+            Data::None => 0,
+            Data::In(buffer, length) => self.device.bulk_transfer(self.bulk_in, buffer, length)?,
+            Data::Out(buffer, length) => {
+                self.device.bulk_transfer(self.bulk_out, buffer, length)?
+            }
+        };
+
+        let size = core::mem::size_of::<CommandStatusWrapper>();
+        if self
+            .device
+            .bulk_transfer(self.bulk_in, transport.csw.addr(), size)?
+            != size
+        {
+            return Err(Error::InvalidStatus);
+        }
+
+        let csw = *transport.csw;
+        let (signature, csw_tag) = (csw.signature, csw.tag);
+
+        if signature != CSW_SIGNATURE || csw_tag != tag {
+            return Err(Error::InvalidStatus);
+        }
+
+        match csw.status {
+            0 => Ok(transferred),
+            1 => Err(Error::CommandFailed),
+            _ => Err(Error::PhaseError),
+        }
+    }
+
+    /// Returns the sense key and the additional sense code of the last failed command.
+    fn request_sense(&self) -> Result<(u8, u8), Error> {
+        let buffer = Dma::<[u8; 18]>::zeroed();
+        let command = [Opcode::RequestSense as u8, 0, 0, 0, 18, 0];
+
+        self.command(&command, Data::In(buffer.addr(), 18))?;
+        Ok((buffer[2] & 0xf, buffer[12]))
+    }
+
+    fn inquiry(&self) -> Result<(), Error> {
+        let buffer = Dma::<[u8; 36]>::zeroed();
+        let command = [Opcode::Inquiry as u8, 0, 0, 0, 36, 0];
+
+        self.command(&command, Data::In(buffer.addr(), 36))?;
+
+        let vendor = core::str::from_utf8(&buffer[8..16]).unwrap_or("?");
+        let product = core::str::from_utf8(&buffer[16..32]).unwrap_or("?");
+
+        log::info!(
+            "usb-storage: found `{} {}` (type={:#x})",
+            vendor.trim(),
+            product.trim(),
+            buffer[0] & 0x1f
+        );
+
+        Ok(())
+    }
+
+    /// Waits for the medium to become ready. Devices report a unit attention condition after
+    /// they have been reset, and might take a while to spin up.
+    fn wait_ready(&self) -> Result<(), Error> {
+        for _ in 0..READY_RETRIES {
+            match self.command(&[Opcode::TestUnitReady as u8, 0, 0, 0, 0, 0], Data::None) {
+                Ok(_) => return Ok(()),
+
+                Err(Error::CommandFailed) => {
+                    let (key, code) = self.request_sense()?;
+                    log::debug!("usb-storage: not ready (sense={key:#x}, code={code:#x})");
+
+                    hrtimer::sleep(100_000_000).map_err(|_| Error::Interrupted)?;
+                }
+
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(Error::NotReady)
+    }
+
+    /// Returns the block size and the number of blocks of the medium.
+    fn read_capacity(&self) -> Result<(usize, usize), Error> {
+        let buffer = Dma::<[u8; 8]>::zeroed();
+        let mut command = [0; 10];
+        command[0] = Opcode::ReadCapacity10 as u8;
+
+        self.command(&command, Data::In(buffer.addr(), 8))?;
+
+        let last = u32::from_be_bytes(buffer[0..4].try_into().unwrap());
+        let block_size = u32::from_be_bytes(buffer[4..8].try_into().unwrap());
+
+        // The transfers are split by the block size, so it has to be sane.
+        if !block_size.is_power_of_two() || !(512..=4096).contains(&block_size) {
+            log::warn!("usb-storage: unsupported block size of {block_size} bytes");
+            return Err(Error::InvalidBlockSize);
+        }
+
+        if last == u32::MAX {
+            log::warn!("usb-storage: only the first 2 TiB of the medium are accessible");
+        }
+
+        Ok((block_size as usize, last as usize + 1))
+    }
+
+    /// Reads or writes the blocks starting at `sector` with READ(10) or WRITE(10) commands.
+    fn read_write(
+        &self,
+        opcode: Opcode,
+        sector: usize,
+        buffer: PhysAddr,
+        size: usize,
+    ) -> Option<usize> {
+        let blocks = size.div_ceil(self.block_size);
+        let max_blocks = (MAX_TRANSFER_SIZE / self.block_size).max(1);
+
+        let mut done = 0;
+
+        while done < blocks {
+            let count = (blocks - done).min(max_blocks);
+            let lba = u32::try_from(sector + done).ok()?;
+
+            let address = buffer + done * self.block_size;
+            let length = count * self.block_size;
+
+            let data = if opcode == Opcode::Read10 {
+                Data::In(address, length)
+            } else {
+                Data::Out(address, length)
+            };
+
+            let mut command = [0; 10];
+            command[0] = opcode as u8;
+            command[2..6].copy_from_slice(&lba.to_be_bytes());
+            command[7..9].copy_from_slice(&(count as u16).to_be_bytes());
+
+            match self.command(&command, data) {
+                Ok(transferred) if transferred == length => done += count,
+
+                result => {
+                    log::error!(
+                        "usb-storage: {opcode:?} of {count} blocks at {lba:#x} failed: {:?}",
+                        result.err()
+                    );
+
+                    return None;
+                }
+            }
+        }
+
+        Some(size)
+    }
+}
+
+impl BlockDeviceInterface for Disk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        self.read_write(Opcode::Read10, sector, start, size)
+    }
+
+    fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        self.read_write(Opcode::Write10, sector, start, size)
+    }
+
+    fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        let buffer = Dma::<u8>::new_uninit_slice(dest.len());
+        self.read_dma(sector, buffer.addr(), dest.len())?;
+
+        dest.copy_from_slice(&buffer);
+        Some(dest.len())
+    }
+
+    fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
+        // SAFETY: The buffer is initialized with zeros.
+        let mut buffer = unsafe { Dma::<u8>::new_zeroed_slice(buf.len()).assume_init() };
+        buffer.copy_from_slice(buf);
+
+        self.write_dma(sector, buffer.addr(), buf.len())
+    }
+}
+
+struct Driver;
+
+impl UsbDriver for Driver {
+    fn handles(&self, interface: &Interface) -> bool {
+        interface.class == CLASS_MASS_STORAGE
+            && interface.subclass == SUBCLASS_SCSI
+            && interface.protocol == PROTOCOL_BULK_ONLY
+    }
+
+    fn start(&self, device: Arc<dyn UsbDevice>, interface: &Interface) {
+        let disk = match Disk::new(device, interface) {
+            Ok(disk) => disk,
+            Err(err) => {
+                log::warn!("usb-storage: failed to initialize the device: {err:?}");
+                return;
+            }
+        };
+
+        let name = block::alloc_disk_name();
+
+        log::info!(
+            "usb-storage: {name}: {} blocks of {} bytes",
+            disk.block_count,
+            disk.block_size
+        );
+
+        if let Err(err) = install_block_device(BlockDevice::new(name.clone(), disk)) {
+            log::warn!("usb-storage: {name}: failed to install the block device: {err:?}");
+        }
+    }
+}
+
+fn init() {
+    super::register_driver(Arc::new(Driver));
+}
+
+crate::module_init!(init, ModuleType::Block);
@@ -0,0 +1,177 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Device and input contexts.
+//!
+//! The device context of a slot is written by the controller and consists of a slot context
+//! followed by the contexts of the 31 endpoints. Software passes new contexts to the controller
+//! in an input context, which starts with an input control context that selects the contexts
+//! to use. Each context is 32 bytes, or 64 bytes if the controller sets `HCCPARAMS1.CSZ`.
+
+use bit_field::BitField;
+
+use crate::mem::paging::PhysAddr;
+use crate::utils::dma::Dma;
+
+/// Number of contexts in a device context.
+const DEVICE_CONTEXTS: usize = 32;
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+pub enum EndpointType {
+    BulkOut = 2,
+    InterruptOut = 3,
+    Control = 4,
+    BulkIn = 6,
+    InterruptIn = 7,
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct InputControlContext {
+    _drop_flags: u32,
+    /// Bit `i` selects the context with the device context index `i` (0 being the slot
+    /// context).
+    pub add_flags: u32,
+    _reserved: [u32; 6],
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct SlotContext([u32; 8]);
+
+impl SlotContext {
+    /// Sets the speed of the device to the provided port speed ID.
+    pub fn set_speed(&mut self, speed_id: u8) {
+        self.0[0].set_bits(20..24, speed_id as u32);
+    }
+
+    /// Sets the index of the last valid endpoint context.
+    pub fn set_context_entries(&mut self, entries: u8) {
+        self.0[0].set_bits(27..32, entries as u32);
+    }
+
+    /// Sets the root hub port number that the device is attached to.
+    pub fn set_root_hub_port(&mut self, port: u8) {
+        self.0[1].set_bits(16..24, port as u32);
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct EndpointContext([u32; 8]);
+
+impl EndpointContext {
+    pub fn set_endpoint_type(&mut self, typ: EndpointType) {
+        self.0[1].set_bits(3..6, typ as u32);
+    }
+
+    /// Sets the number of consecutive errors after which the endpoint is halted.
+    pub fn set_error_count(&mut self, count: u8) {
+        self.0[1].set_bits(1..3, count as u32);
+    }
+
+    pub fn set_max_packet_size(&mut self, size: u16) {
+        self.0[1].set_bits(16..32, size as u32);
+    }
+
+    /// Sets the transfer ring of the endpoint and its consumer cycle state.
+    pub fn set_dequeue_pointer(&mut self, addr: PhysAddr, cycle: bool) {
+        let addr = addr.as_u64();
+
+        self.0[2] = addr.get_bits(0..32) as u32;
+        self.0[2].set_bit(0, cycle);
+        self.0[3] = addr.get_bits(32..64) as u32;
+    }
+
+    /// Sets the service interval of a periodic endpoint to `2^interval * 125` microseconds.
+    pub fn set_interval(&mut self, interval: u8) {
+        self.0[0].set_bits(16..24, interval as u32);
+    }
+
+    pub fn set_average_trb_length(&mut self, length: u16) {
+        self.0[4].set_bits(0..16, length as u32);
+    }
+
+    /// Sets the maximum number of bytes that a periodic endpoint transfers per service
+    /// interval.
+    pub fn set_max_esit_payload(&mut self, payload: u16) {
+        self.0[4].set_bits(16..32, payload as u32);
+    }
+}
+
+fn alloc_contexts(count: usize, context_size: usize) -> Dma<[u8]> {
+    // SAFETY: The contexts are initialized to zero.
+    unsafe { Dma::<u8>::new_zeroed_slice(count * context_size).assume_init() }
+}
+
+pub struct InputContext {
+    buffer: Dma<[u8]>,
+    context_size: usize,
+}
+
+impl InputContext {
+    pub fn new(context_size: usize) -> Self {
+        Self {
+            buffer: alloc_contexts(DEVICE_CONTEXTS + 1, context_size),
+            context_size,
+        }
+    }
+
+    pub fn addr(&self) -> PhysAddr {
+        self.buffer.addr()
+    }
+
+    fn context<T>(&mut self, index: usize) -> &mut T {
+        let offset = index * self.context_size;
+        assert!(offset + core::mem::size_of::<T>() <= self.buffer.len());
+
+        // SAFETY: The context is in bounds and the buffer is page aligned, so every context is
+        // suitably aligned.
+        unsafe { &mut *self.buffer.as_mut_ptr().add(offset).cast::<T>() }
+    }
+
+    pub fn control(&mut self) -> &mut InputControlContext {
+        self.context(0)
+    }
+
+    pub fn slot(&mut self) -> &mut SlotContext {
+        self.context(1)
+    }
+
+    /// Returns the context of the endpoint with the provided device context index.
+    pub fn endpoint(&mut self, dci: usize) -> &mut EndpointContext {
+        self.context(dci + 1)
+    }
+}
+
+/// Output device context of a slot, owned by the controller.
+pub struct DeviceContext {
+    buffer: Dma<[u8]>,
+}
+
+impl DeviceContext {
+    pub fn new(context_size: usize) -> Self {
+        Self {
+            buffer: alloc_contexts(DEVICE_CONTEXTS, context_size),
+        }
+    }
+
+    pub fn addr(&self) -> PhysAddr {
+        self.buffer.addr()
+    }
+}
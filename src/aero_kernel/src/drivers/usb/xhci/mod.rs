@@ -0,0 +1,1189 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! xHCI (eXtensible Host Controller Interface) driver.
+//!
+//! Software talks to the controller through TRB rings (see [`ring`]): commands are placed on
+//! the command ring and transfers on the transfer ring of each endpoint. The controller reports
+//! completed commands and transfers, as well as port status changes, on the event ring of the
+//! primary interrupter, which is signalled with an MSI-X interrupt.
+//!
+//! When a device is connected to a root hub port, the port is reset, a device slot is enabled
+//! for the device and the device is addressed. Its device descriptor and its first configuration
+//! are then read through the default control endpoint, the bulk and interrupt endpoints of the
+//! configuration are configured and the device is handed over to the USB device drivers (see
+//! [`super::probe`]).
+//!
+//! ## Notes
+//! * <https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf>
+//! * Only devices attached to the root hub ports are enumerated; USB hubs are not supported.
+//! * A halted endpoint is not recovered; the device has to be reconnected.
+//! * Isochronous endpoints are not supported.
+
+mod context;
+mod ring;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use bit_field::BitField;
+
+use crate::arch::interrupts::{self, InterruptStack};
+use crate::drivers::pci::{self, *};
+use crate::mem::paging::*;
+use crate::userland::scheduler::hrtimer;
+use crate::userland::workqueue;
+use crate::utils::dma::Dma;
+use crate::utils::sync::{BMutex, Mutex, WaitQueue};
+use crate::utils::VolatileCell;
+
+use super::{
+    Configuration, DescriptorType, DeviceDescriptor, SetupPacket, Speed, TransferError,
+    TransferType, UsbDevice,
+};
+use context::{DeviceContext, EndpointContext, EndpointType, InputContext};
+use ring::{EventRing, Ring, Trb, TrbType};
+
+/// Programming interface of the xHCI controllers in the USB controller class.
+const PROG_IF_XHCI: u8 = 0x30;
+
+/// Device context index of the default control endpoint.
+const CONTROL_ENDPOINT: u8 = 1;
+
+/// TRB buffers must not cross a 64 KiB boundary.
+const TRB_BUFFER_BOUNDARY: usize = 0x10000;
+
+/// Bits of `PORTSC` that are preserved when it is written. All of the other bits are either
+/// read-only, write-1-to-clear or trigger an action when set.
+const PORTSC_PRESERVE: u32 = 0x0e00_c3e0;
+
+/// Event Handler Busy flag of `ERDP`.
+const ERDP_EHB: u64 = 1 << 3;
+
+/// Time to wait for a command to complete, in milliseconds.
+const COMMAND_TIMEOUT_MS: usize = 5000;
+
+#[derive(Copy, Clone, Debug)]
+enum Error {
+    UnknownBar,
+    NotMsixCapable,
+    /// The controller did not respond in time.
+    Timeout,
+    ControllerFatal,
+    CommandFailed,
+    TransferFailed,
+    UnknownSpeed,
+    /// The device does not have a valid configuration.
+    InvalidConfiguration,
+    Interrupted,
+}
+
+impl From<TransferError> for Error {
+    fn from(err: TransferError) -> Self {
+        match err {
+            TransferError::Interrupted => Error::Interrupted,
+            TransferError::Failed | TransferError::Disconnected => Error::TransferFailed,
+        }
+    }
+}
+
+#[repr(C)]
+struct CapabilityRegisters {
+    caplength: VolatileCell<u8>,
+    _reserved: u8,
+    hciversion: VolatileCell<u16>,
+    hcsparams1: VolatileCell<u32>,
+    hcsparams2: VolatileCell<u32>,
+    _hcsparams3: VolatileCell<u32>,
+    hccparams1: VolatileCell<u32>,
+    dboff: VolatileCell<u32>,
+    rtsoff: VolatileCell<u32>,
+}
+
+impl CapabilityRegisters {
+    fn max_slots(&self) -> u8 {
+        self.hcsparams1.get().get_bits(0..8) as u8
+    }
+
+    fn max_ports(&self) -> u8 {
+        self.hcsparams1.get().get_bits(24..32) as u8
+    }
+
+    fn max_scratchpad_buffers(&self) -> usize {
+        let params = self.hcsparams2.get();
+        let hi = params.get_bits(21..26) as usize;
+        let lo = params.get_bits(27..32) as usize;
+
+        hi << 5 | lo
+    }
+
+    /// Returns the size of the device and input contexts in bytes.
+    fn context_size(&self) -> usize {
+        if self.hccparams1.get().get_bit(2) {
+            64
+        } else {
+            32
+        }
+    }
+
+    /// Returns the offset of the first extended capability from the register base, or zero
+    /// if there are none.
+    fn extended_capabilities(&self) -> usize {
+        (self.hccparams1.get().get_bits(16..32) as usize) << 2
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    struct UsbCommand: u32 {
+        const RUN                      = 1 << 0;
+        const RESET                    = 1 << 1;
+        const INTERRUPTER_ENABLE       = 1 << 2;
+        const HOST_SYSTEM_ERROR_ENABLE = 1 << 3;
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    struct UsbStatus: u32 {
+        const HALTED           = 1 << 0;
+        const EVENT_INTERRUPT  = 1 << 3;
+        const NOT_READY        = 1 << 11;
+        const CONTROLLER_ERROR = 1 << 12;
+    }
+}
+
+#[repr(C)]
+struct OperationalRegisters {
+    usbcmd: VolatileCell<u32>,
+    usbsts: VolatileCell<u32>,
+    _pagesize: VolatileCell<u32>,
+    _reserved1: [u32; 2],
+    _dnctrl: VolatileCell<u32>,
+    crcr: VolatileCell<u64>,
+    _reserved2: [u32; 4],
+    dcbaap: VolatileCell<u64>,
+    config: VolatileCell<u32>,
+}
+
+const_assert_eq!(core::mem::offset_of!(OperationalRegisters, crcr), 0x18);
+const_assert_eq!(core::mem::offset_of!(OperationalRegisters, dcbaap), 0x30);
+const_assert_eq!(core::mem::offset_of!(OperationalRegisters, config), 0x38);
+
+impl OperationalRegisters {
+    fn command(&self) -> UsbCommand {
+        UsbCommand::from_bits_retain(self.usbcmd.get())
+    }
+
+    fn status(&self) -> UsbStatus {
+        UsbStatus::from_bits_retain(self.usbsts.get())
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    struct PortStatus: u32 {
+        const CONNECTED           = 1 << 0;
+        const ENABLED             = 1 << 1;
+        const RESET               = 1 << 4;
+        const POWER               = 1 << 9;
+        const CONNECT_CHANGE      = 1 << 17;
+        const ENABLE_CHANGE       = 1 << 18;
+        const WARM_RESET_CHANGE   = 1 << 19;
+        const OVER_CURRENT_CHANGE = 1 << 20;
+        const RESET_CHANGE        = 1 << 21;
+        const LINK_STATE_CHANGE   = 1 << 22;
+        const CONFIG_ERROR_CHANGE = 1 << 23;
+
+        const CHANGES = Self::CONNECT_CHANGE.bits()
+            | Self::ENABLE_CHANGE.bits()
+            | Self::WARM_RESET_CHANGE.bits()
+            | Self::OVER_CURRENT_CHANGE.bits()
+            | Self::RESET_CHANGE.bits()
+            | Self::LINK_STATE_CHANGE.bits()
+            | Self::CONFIG_ERROR_CHANGE.bits();
+    }
+}
+
+#[repr(C)]
+struct PortRegisters {
+    portsc: VolatileCell<u32>,
+    _portpmsc: VolatileCell<u32>,
+    _portli: VolatileCell<u32>,
+    _porthlpmc: VolatileCell<u32>,
+}
+
+impl PortRegisters {
+    fn status(&self) -> PortStatus {
+        PortStatus::from_bits_retain(self.portsc.get())
+    }
+
+    /// Returns the protocol speed ID of the device attached to the port.
+    fn speed_id(&self) -> u8 {
+        self.portsc.get().get_bits(10..14) as u8
+    }
+
+    /// Sets the provided bits in `PORTSC` (or clears them, for the write-1-to-clear bits)
+    /// without changing the state of the port otherwise.
+    fn write(&self, bits: PortStatus) {
+        self.portsc
+            .set((self.portsc.get() & PORTSC_PRESERVE) | bits.bits());
+    }
+}
+
+#[repr(C)]
+struct InterrupterRegisters {
+    iman: VolatileCell<u32>,
+    _imod: VolatileCell<u32>,
+    erstsz: VolatileCell<u32>,
+    _reserved: u32,
+    erstba: VolatileCell<u64>,
+    erdp: VolatileCell<u64>,
+}
+
+/// Returns the speed that corresponds to the provided protocol speed ID, using the default
+/// speed ID mapping.
+fn port_speed(speed_id: u8) -> Option<Speed> {
+    match speed_id {
+        1 => Some(Speed::Full),
+        2 => Some(Speed::Low),
+        3 => Some(Speed::High),
+        4 => Some(Speed::Super),
+        5 => Some(Speed::SuperPlus),
+        _ => None,
+    }
+}
+
+/// Returns the device context index of the endpoint with the provided address.
+fn endpoint_dci(address: u8) -> u8 {
+    (address & 0xf) * 2 + (address >> 7)
+}
+
+/// Returns the service interval of a periodic endpoint, as an exponent of 125 microseconds,
+/// from the `bInterval` field of its descriptor.
+fn endpoint_interval(speed: Speed, interval: u8) -> u8 {
+    match speed {
+        // The interval is in frames (1 millisecond).
+        Speed::Low | Speed::Full => (interval.max(1) as u32 * 8).ilog2().clamp(3, 10) as u8,
+        // The interval is an exponent of 125 microseconds, plus one.
+        _ => interval.clamp(1, 16) - 1,
+    }
+}
+
+/// Polls `condition` every millisecond until it is true, for at most `timeout_ms` milliseconds.
+fn wait_for<F: FnMut() -> bool>(mut condition: F, timeout_ms: usize) -> Result<(), Error> {
+    for _ in 0..timeout_ms {
+        if condition() {
+            return Ok(());
+        }
+
+        hrtimer::sleep(1_000_000).map_err(|_| Error::Interrupted)?;
+    }
+
+    if condition() {
+        Ok(())
+    } else {
+        Err(Error::Timeout)
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ExtendedCapabilityId {
+    LegacySupport = 1,
+    SupportedProtocol = 2,
+}
+
+/// Returns the ID and the address of each extended capability of the controller.
+fn extended_capabilities(base: VirtAddr, offset: usize) -> Vec<(u8, VirtAddr)> {
+    let mut capabilities = Vec::new();
+    let mut address = base + offset;
+
+    if offset == 0 {
+        return capabilities;
+    }
+
+    loop {
+        // SAFETY: The extended capabilities are in the register space mapped from BAR0.
+        let header = unsafe { &*address.as_ptr::<VolatileCell<u32>>() }.get();
+        capabilities.push((header.get_bits(0..8) as u8, address));
+
+        let next = (header.get_bits(8..16) as usize) << 2;
+
+        if next == 0 {
+            return capabilities;
+        }
+
+        address += next;
+    }
+}
+
+/// Returns the register at `offset` bytes into the extended capability at `capability`.
+fn capability_register(capability: VirtAddr, offset: usize) -> &'static VolatileCell<u32> {
+    // SAFETY: The extended capabilities are in the register space mapped from BAR0.
+    unsafe { &*(capability + offset).as_ptr::<VolatileCell<u32>>() }
+}
+
+/// Takes the ownership of the controller from the BIOS, which might be using it to emulate a
+/// PS/2 keyboard and mouse.
+fn take_ownership(capability: VirtAddr) -> Result<(), Error> {
+    let legacy_support = capability_register(capability, 0);
+
+    // Is the controller owned by the BIOS?
+    if !legacy_support.get().get_bit(16) {
+        return Ok(());
+    }
+
+    legacy_support.set(*legacy_support.get().set_bit(24, true));
+    wait_for(|| !legacy_support.get().get_bit(16), 1000)?;
+
+    // Disable the SMIs and clear the pending ones (the status bits are write-1-to-clear).
+    let control = capability_register(capability, 4);
+    control.set((control.get() & !0x0000_e011) | 0xe000_0000);
+
+    log::trace!("xhci: took the ownership of the controller from the BIOS");
+    Ok(())
+}
+
+/// A range of root hub ports that implement the same USB protocol, as described by a Supported
+/// Protocol capability.
+#[derive(Debug, Copy, Clone)]
+struct Protocol {
+    major: u8,
+    first_port: u8,
+    port_count: u8,
+    slot_type: u8,
+}
+
+impl Protocol {
+    fn new(capability: VirtAddr) -> Self {
+        let header = capability_register(capability, 0).get();
+        let ports = capability_register(capability, 8).get();
+        let slot_type = capability_register(capability, 12).get();
+
+        Self {
+            major: header.get_bits(24..32) as u8,
+            first_port: ports.get_bits(0..8) as u8,
+            port_count: ports.get_bits(8..16) as u8,
+            slot_type: slot_type.get_bits(0..5) as u8,
+        }
+    }
+
+    fn contains(&self, port: u8) -> bool {
+        (self.first_port..self.first_port + self.port_count).contains(&port)
+    }
+}
+
+/// A device attached to a root hub port.
+struct Device {
+    controller: Arc<Controller>,
+    slot: u8,
+    port: u8,
+    speed: Speed,
+    input: InputContext,
+    output: DeviceContext,
+    /// Transfer rings of the configured endpoints, by their device context index. A transfer
+    /// holds the lock of the ring until it completes, as the transfer events of an endpoint
+    /// are not told apart.
+    rings: BTreeMap<u8, BMutex<Ring>>,
+    /// Set once the device has been disconnected.
+    detached: AtomicBool,
+}
+
+impl Device {
+    fn new(controller: Arc<Controller>, slot: u8, port: u8, speed: Speed) -> Self {
+        let context_size = controller.capability.context_size();
+
+        let mut rings = BTreeMap::new();
+        rings.insert(CONTROL_ENDPOINT, BMutex::new(Ring::new()));
+
+        Self {
+            controller,
+            slot,
+            port,
+            speed,
+            input: InputContext::new(context_size),
+            output: DeviceContext::new(context_size),
+            rings,
+            detached: AtomicBool::new(false),
+        }
+    }
+
+    fn is_detached(&self) -> bool {
+        self.detached.load(Ordering::SeqCst)
+    }
+}
+
+impl UsbDevice for Device {
+    fn control_transfer(
+        &self,
+        setup: SetupPacket,
+        buffer: Option<PhysAddr>,
+    ) -> Result<(), TransferError> {
+        self.controller.control_transfer(self, setup, buffer)
+    }
+
+    fn bulk_transfer(
+        &self,
+        endpoint: u8,
+        buffer: PhysAddr,
+        length: usize,
+    ) -> Result<usize, TransferError> {
+        self.controller
+            .bulk_transfer(self, endpoint, buffer, length)
+    }
+}
+
+/// Events that completed the submitted commands and transfers, which have not been picked up
+/// by their submitters yet.
+#[derive(Default)]
+struct Completions {
+    /// Command completion events, by the physical address of the command TRB.
+    commands: BTreeMap<u64, Trb>,
+    /// Transfer events, by the slot ID and the device context index of the endpoint.
+    transfers: BTreeMap<(u8, u8), Trb>,
+}
+
+struct Controller {
+    capability: &'static CapabilityRegisters,
+    operational: &'static OperationalRegisters,
+    interrupter: &'static InterrupterRegisters,
+    doorbells: &'static [VolatileCell<u32>],
+    ports: &'static [PortRegisters],
+
+    protocols: Vec<Protocol>,
+
+    dcbaa: Mutex<Dma<[u64]>>,
+    _scratchpad: Option<Dma<[u64]>>,
+
+    command_ring: Mutex<Ring>,
+    event_ring: Mutex<EventRing>,
+
+    completions: Mutex<Completions>,
+    wq: WaitQueue,
+
+    /// Devices attached to the root hub ports, indexed by the port number minus one.
+    devices: BMutex<Vec<Option<Arc<Device>>>>,
+}
+
+unsafe impl Send for Controller {}
+unsafe impl Sync for Controller {}
+
+impl Controller {
+    fn new(header: &PciHeader) -> Result<Arc<Self>, Error> {
+        log::trace!("xhci: setting up xHCI controller");
+
+        header.enable_bus_mastering();
+        header.enable_mmio();
+
+        let bar0 = header.get_bar(0).ok_or(Error::UnknownBar)?;
+
+        let base = match bar0 {
+            Bar::Memory64 { address, .. } => PhysAddr::new(address).as_hhdm_virt(),
+            _ => return Err(Error::UnknownBar),
+        };
+
+        pci::map_bar(&bar0);
+
+        let capability = base.read_mut::<CapabilityRegisters>().unwrap();
+        let operational_base = base + capability.caplength.get() as u64;
+        let operational = operational_base.read_mut::<OperationalRegisters>().unwrap();
+
+        let runtime_base = base + (capability.rtsoff.get() & !0x1f) as u64;
+        // Interrupter 0 is the primary interrupter.
+        let interrupter = (runtime_base + 0x20u64)
+            .read_mut::<InterrupterRegisters>()
+            .unwrap();
+
+        // SAFETY: The doorbell array and the port register sets are in the register space
+        // mapped from BAR0.
+        let doorbells = unsafe {
+            let doorbell_base = base + (capability.dboff.get() & !0b11) as u64;
+            core::slice::from_raw_parts(doorbell_base.as_ptr::<VolatileCell<u32>>(), 256)
+        };
+
+        let ports = unsafe {
+            core::slice::from_raw_parts(
+                (operational_base + 0x400u64).as_ptr::<PortRegisters>(),
+                capability.max_ports() as usize,
+            )
+        };
+
+        log::trace!(
+            "xhci: version {:#x} (slots={}, ports={})",
+            capability.hciversion.get(),
+            capability.max_slots(),
+            capability.max_ports()
+        );
+
+        let mut protocols = Vec::new();
+
+        for (id, address) in extended_capabilities(base, capability.extended_capabilities()) {
+            if id == ExtendedCapabilityId::LegacySupport as u8 {
+                take_ownership(address)?;
+            } else if id == ExtendedCapabilityId::SupportedProtocol as u8 {
+                let protocol = Protocol::new(address);
+                log::trace!("xhci: {protocol:?}");
+
+                protocols.push(protocol);
+            }
+        }
+
+        // Stop and reset the controller.
+        operational
+            .usbcmd
+            .set((operational.command() - UsbCommand::RUN).bits());
+        wait_for(|| operational.status().contains(UsbStatus::HALTED), 20)?;
+
+        operational.usbcmd.set(UsbCommand::RESET.bits());
+        wait_for(
+            || {
+                !operational.command().contains(UsbCommand::RESET)
+                    && !operational.status().contains(UsbStatus::NOT_READY)
+            },
+            1000,
+        )?;
+
+        let max_slots = capability.max_slots();
+        operational
+            .config
+            .set(*operational.config.get().set_bits(0..8, max_slots as u32));
+
+        // SAFETY: A zeroed device context base address array is valid.
+        let mut dcbaa =
+            unsafe { Dma::<u64>::new_zeroed_slice(max_slots as usize + 1).assume_init() };
+
+        // The first entry of the DCBAA points to the scratchpad buffer array, if the controller
+        // requires any scratchpad buffers. The buffers are owned by the controller for as long
+        // as it is running, so they are never freed.
+        let scratchpad_count = capability.max_scratchpad_buffers();
+        let scratchpad = (scratchpad_count != 0).then(|| {
+            // SAFETY: The array is initialized below.
+            let mut buffers =
+                unsafe { Dma::<u64>::new_zeroed_slice(scratchpad_count).assume_init() };
+
+            for buffer in buffers.iter_mut() {
+                let frame: PhysFrame = FRAME_ALLOCATOR
+                    .allocate_frame()
+                    .expect("xhci: out of memory");
+
+                *buffer = frame.start_address().as_u64();
+            }
+
+            dcbaa[0] = buffers.addr().as_u64();
+            buffers
+        });
+
+        operational.dcbaap.set(dcbaa.addr().as_u64());
+
+        let command_ring = Ring::new();
+        // Set the Ring Cycle State, as the ring starts with a cycle state of one.
+        operational.crcr.set(command_ring.addr().as_u64() | 1);
+
+        let event_ring = EventRing::new();
+        interrupter.erstsz.set(event_ring.erst_len());
+        interrupter.erdp.set(event_ring.dequeue_addr().as_u64());
+        interrupter.erstba.set(event_ring.erst_addr().as_u64());
+
+        let mut msix = header.msix().ok_or(Error::NotMsixCapable)?;
+
+        let vector = interrupts::allocate_vector();
+        interrupts::register_handler(vector, irq_handler);
+
+        msix.set(vector);
+
+        // Clear the pending interrupt and enable the interrupter.
+        interrupter.iman.set(0b11);
+
+        let this = Arc::new(Self {
+            capability,
+            operational,
+            interrupter,
+            doorbells,
+            ports,
+
+            protocols,
+
+            dcbaa: Mutex::new(dcbaa),
+            _scratchpad: scratchpad,
+
+            command_ring: Mutex::new(command_ring),
+            event_ring: Mutex::new(event_ring),
+
+            completions: Mutex::new(Completions::default()),
+            wq: WaitQueue::new(),
+
+            devices: BMutex::new((0..ports.len()).map(|_| None).collect()),
+        });
+
+        CONTROLLERS.lock_irq().push(this.clone());
+
+        operational.usbcmd.set(
+            (UsbCommand::RUN
+                | UsbCommand::INTERRUPTER_ENABLE
+                | UsbCommand::HOST_SYSTEM_ERROR_ENABLE)
+                .bits(),
+        );
+
+        wait_for(|| !operational.status().contains(UsbStatus::HALTED), 20)?;
+
+        if operational.status().contains(UsbStatus::CONTROLLER_ERROR) {
+            return Err(Error::ControllerFatal);
+        }
+
+        log::trace!("xhci: successfully initialized xHCI controller");
+        Ok(this)
+    }
+
+    /// Returns the protocol implemented by the root hub port with the provided number.
+    fn protocol(&self, port: u8) -> Option<Protocol> {
+        self.protocols
+            .iter()
+            .find(|protocol| protocol.contains(port))
+            .copied()
+    }
+
+    /// Blocks until `f` returns an event, for at most `timeout_ms` milliseconds.
+    fn wait<F>(&self, timeout_ms: usize, mut f: F) -> Result<Trb, Error>
+    where
+        F: FnMut(&mut Completions) -> Option<Trb>,
+    {
+        let deadline = hrtimer::now() + timeout_ms as u64 * 1_000_000;
+        let mut event = None;
+
+        self.wq
+            .block_on_until(&self.completions, Some(deadline), |completions| {
+                event = event.or_else(|| f(completions));
+                event.is_some()
+            })
+            .map_err(|_| Error::Interrupted)?
+            .ok_or(Error::Timeout)?;
+
+        Ok(event.unwrap())
+    }
+
+    /// Submits the provided command and waits for it to complete.
+    fn command(&self, command: Trb) -> Result<Trb, Error> {
+        let address = self.command_ring.lock_irq().push(command).as_u64();
+        self.doorbells[0].set(0);
+
+        let event = self
+            .wait(COMMAND_TIMEOUT_MS, |completions| {
+                completions.commands.remove(&address)
+            })
+            .inspect_err(|err| log::error!("xhci: command {:?} failed: {err:?}", command.typ()))?;
+
+        if !event.is_success() {
+            log::error!(
+                "xhci: command {:?} failed (code={})",
+                command.typ(),
+                event.completion_code()
+            );
+
+            return Err(Error::CommandFailed);
+        }
+
+        Ok(event)
+    }
+
+    /// Places the TRBs of a transfer descriptor on `ring`, which is the transfer ring of the
+    /// endpoint with the device context index `dci`, and waits for the transfer to complete.
+    /// Returns the transfer event.
+    fn transfer<I>(
+        &self,
+        device: &Device,
+        dci: u8,
+        ring: &mut Ring,
+        trbs: I,
+    ) -> Result<Trb, TransferError>
+    where
+        I: IntoIterator<Item = Trb>,
+    {
+        if device.is_detached() {
+            return Err(TransferError::Disconnected);
+        }
+
+        let key = (device.slot, dci);
+
+        // Drop the event of a previous transfer whose wait was interrupted.
+        self.completions.lock_irq().transfers.remove(&key);
+
+        for trb in trbs {
+            ring.push(trb);
+        }
+
+        self.doorbells[device.slot as usize].set(dci as u32);
+
+        let mut event = None;
+
+        self.wq
+            .block_on(&self.completions, |completions| {
+                if event.is_none() {
+                    event = completions.transfers.remove(&key).map(Ok).or_else(|| {
+                        device
+                            .is_detached()
+                            .then_some(Err(TransferError::Disconnected))
+                    });
+                }
+
+                event.is_some()
+            })
+            .map_err(|_| TransferError::Interrupted)?;
+
+        let event = event.unwrap()?;
+
+        if !event.is_success() {
+            log::debug!(
+                "xhci: transfer on slot {} (endpoint={dci}) failed (code={})",
+                device.slot,
+                event.completion_code()
+            );
+
+            return Err(TransferError::Failed);
+        }
+
+        Ok(event)
+    }
+
+    /// Performs a control transfer on the default control endpoint of `device` and waits for it
+    /// to complete. `buffer` has to be provided if the request has a data stage.
+    fn control_transfer(
+        &self,
+        device: &Device,
+        setup: SetupPacket,
+        buffer: Option<PhysAddr>,
+    ) -> Result<(), TransferError> {
+        let data = buffer.map(|buffer| Trb::data_stage(buffer, setup.length, setup.is_in()));
+
+        // The status stage is in the opposite direction of the data stage, or from the device
+        // to the host if there is no data stage.
+        let status_in = buffer.is_none() || !setup.is_in();
+
+        let trbs = [
+            Some(Trb::setup_stage(&setup, buffer.is_some())),
+            data,
+            Some(Trb::status_stage(status_in).with_ioc()),
+        ];
+
+        let mut ring = device.rings[&CONTROL_ENDPOINT].lock();
+        self.transfer(
+            device,
+            CONTROL_ENDPOINT,
+            &mut ring,
+            trbs.into_iter().flatten(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Performs a bulk transfer of `length` bytes on the endpoint of `device` with the provided
+    /// address. Returns the number of bytes that were transferred, which is less than `length`
+    /// if the device sent a short packet.
+    fn bulk_transfer(
+        &self,
+        device: &Device,
+        endpoint: u8,
+        buffer: PhysAddr,
+        length: usize,
+    ) -> Result<usize, TransferError> {
+        let dci = endpoint_dci(endpoint);
+        let mut ring = device.rings.get(&dci).ok_or(TransferError::Failed)?.lock();
+
+        let mut transferred = 0;
+
+        // The buffer is split at the 64 KiB boundaries, with a transfer descriptor for each of
+        // the parts so that a short packet ends the transfer.
+        while transferred < length {
+            let address = buffer + transferred;
+            let offset = address.as_u64() as usize % TRB_BUFFER_BOUNDARY;
+            let size = (length - transferred).min(TRB_BUFFER_BOUNDARY - offset);
+
+            let event = self.transfer(
+                device,
+                dci,
+                &mut ring,
+                [Trb::normal(address, size as u32).with_ioc()],
+            )?;
+
+            let residual = (event.transfer_residual() as usize).min(size);
+            transferred += size - residual;
+
+            if residual != 0 {
+                break;
+            }
+        }
+
+        Ok(transferred)
+    }
+
+    /// Addresses `device` and reads its device descriptor.
+    fn address_device(&self, device: &mut Device, speed_id: u8) -> Result<DeviceDescriptor, Error> {
+        let max_packet_size = device.speed.default_max_packet_size();
+        let control = device.rings[&CONTROL_ENDPOINT].lock().addr();
+
+        let input = &mut device.input;
+
+        // Add the slot context and the context of the default control endpoint.
+        input.control().add_flags = 0b11;
+
+        let slot = input.slot();
+        slot.set_context_entries(CONTROL_ENDPOINT);
+        slot.set_speed(speed_id);
+        slot.set_root_hub_port(device.port);
+
+        let endpoint = input.endpoint(CONTROL_ENDPOINT as usize);
+        endpoint.set_endpoint_type(EndpointType::Control);
+        endpoint.set_error_count(3);
+        endpoint.set_max_packet_size(max_packet_size);
+        endpoint.set_dequeue_pointer(control, true);
+        endpoint.set_average_trb_length(8);
+
+        self.command(
+            Trb::new(TrbType::AddressDevice)
+                .with_parameter(input.addr().as_u64())
+                .with_slot_id(device.slot),
+        )?;
+
+        let descriptor = Dma::<DeviceDescriptor>::zeroed();
+
+        // The maximum packet size of the default control endpoint of full-speed devices is
+        // only known once the first 8 bytes of the device descriptor have been read.
+        if device.speed == Speed::Full {
+            let setup = SetupPacket::get_descriptor(DescriptorType::Device, 0, 8);
+            self.control_transfer(device, setup, Some(descriptor.addr()))?;
+
+            let size = descriptor.max_packet_size0 as u16;
+
+            if size != max_packet_size {
+                let input = &mut device.input;
+                input.control().add_flags = 1 << CONTROL_ENDPOINT;
+                input
+                    .endpoint(CONTROL_ENDPOINT as usize)
+                    .set_max_packet_size(size);
+
+                self.command(
+                    Trb::new(TrbType::EvaluateContext)
+                        .with_parameter(input.addr().as_u64())
+                        .with_slot_id(device.slot),
+                )?;
+            }
+        }
+
+        let length = core::mem::size_of::<DeviceDescriptor>() as u16;
+        let setup = SetupPacket::get_descriptor(DescriptorType::Device, 0, length);
+        self.control_transfer(device, setup, Some(descriptor.addr()))?;
+
+        Ok(*descriptor)
+    }
+
+    /// Reads the first configuration of `device`.
+    fn read_configuration(&self, device: &Device) -> Result<Configuration, Error> {
+        // The total length of the configuration is in the configuration descriptor.
+        let header = Dma::<[u8; Configuration::DESCRIPTOR_SIZE]>::zeroed();
+        let setup = SetupPacket::get_descriptor(
+            DescriptorType::Configuration,
+            0,
+            Configuration::DESCRIPTOR_SIZE as u16,
+        );
+
+        self.control_transfer(device, setup, Some(header.addr()))?;
+
+        let length = Configuration::total_length(&*header).ok_or(Error::InvalidConfiguration)?;
+
+        // SAFETY: A zeroed buffer is valid.
+        let buffer = unsafe { Dma::<u8>::new_zeroed_slice(length as usize).assume_init() };
+        let setup = SetupPacket::get_descriptor(DescriptorType::Configuration, 0, length);
+
+        self.control_transfer(device, setup, Some(buffer.addr()))?;
+        Configuration::parse(&buffer).ok_or(Error::InvalidConfiguration)
+    }
+
+    /// Configures the bulk and interrupt endpoints of `configuration` and selects it.
+    fn configure(&self, device: &mut Device, configuration: &Configuration) -> Result<(), Error> {
+        let input = &mut device.input;
+
+        // The slot context is added for the context entries to be updated.
+        let mut add_flags = 1;
+        let mut last = CONTROL_ENDPOINT;
+
+        let endpoints = configuration
+            .interfaces
+            .iter()
+            .flat_map(|interface| interface.endpoints.iter());
+
+        for endpoint in endpoints {
+            let typ = match (endpoint.transfer_type(), endpoint.is_in()) {
+                (TransferType::Bulk, true) => EndpointType::BulkIn,
+                (TransferType::Bulk, false) => EndpointType::BulkOut,
+                (TransferType::Interrupt, true) => EndpointType::InterruptIn,
+                (TransferType::Interrupt, false) => EndpointType::InterruptOut,
+                _ => continue,
+            };
+
+            let dci = endpoint_dci(endpoint.address);
+            // Bits 11 and 12 are the number of additional transactions per microframe.
+            let max_packet_size = endpoint.max_packet_size & 0x7ff;
+            let ring = Ring::new();
+
+            let context = input.endpoint(dci as usize);
+            *context = EndpointContext::default();
+
+            context.set_endpoint_type(typ);
+            context.set_error_count(3);
+            context.set_max_packet_size(max_packet_size);
+            context.set_dequeue_pointer(ring.addr(), true);
+
+            if endpoint.transfer_type() == TransferType::Bulk {
+                context.set_average_trb_length(3072);
+            } else {
+                context.set_interval(endpoint_interval(device.speed, endpoint.interval));
+                context.set_average_trb_length(1024);
+                context.set_max_esit_payload(max_packet_size);
+            }
+
+            device.rings.insert(dci, BMutex::new(ring));
+
+            add_flags |= 1 << dci;
+            last = last.max(dci);
+        }
+
+        if add_flags != 1 {
+            input.control().add_flags = add_flags;
+            input.slot().set_context_entries(last);
+
+            self.command(
+                Trb::new(TrbType::ConfigureEndpoint)
+                    .with_parameter(input.addr().as_u64())
+                    .with_slot_id(device.slot),
+            )?;
+        }
+
+        let setup = SetupPacket::set_configuration(configuration.value);
+        self.control_transfer(device, setup, None)?;
+
+        Ok(())
+    }
+
+    /// Enumerates the device connected to the root hub port with the provided number.
+    fn attach(self: &Arc<Self>, port: u8) -> Result<Arc<Device>, Error> {
+        let registers = &self.ports[port as usize - 1];
+        let protocol = self.protocol(port);
+
+        // USB 3 ports are enabled once the link has been trained, while USB 2 ports are only
+        // enabled after they have been reset.
+        if !protocol.is_some_and(|protocol| protocol.major >= 3) {
+            registers.write(PortStatus::RESET);
+            wait_for(
+                || registers.status().contains(PortStatus::RESET_CHANGE),
+                500,
+            )?;
+            registers.write(PortStatus::RESET_CHANGE);
+        }
+
+        wait_for(|| registers.status().contains(PortStatus::ENABLED), 500)?;
+
+        // Give the device some time to recover from the reset.
+        hrtimer::sleep(10_000_000).map_err(|_| Error::Interrupted)?;
+
+        let speed_id = registers.speed_id();
+        let speed = port_speed(speed_id).ok_or(Error::UnknownSpeed)?;
+
+        let slot_type = protocol.map_or(0, |protocol| protocol.slot_type);
+        let slot = self
+            .command(Trb::new(TrbType::EnableSlot).with_slot_type(slot_type))?
+            .slot_id();
+
+        let mut device = Device::new(self.clone(), slot, port, speed);
+        self.dcbaa.lock_irq()[slot as usize] = device.output.addr().as_u64();
+
+        let result = self
+            .address_device(&mut device, speed_id)
+            .and_then(|descriptor| {
+                let configuration = self.read_configuration(&device)?;
+                self.configure(&mut device, &configuration)?;
+
+                Ok((descriptor, configuration))
+            });
+
+        match result {
+            Ok((descriptor, configuration)) => {
+                log::info!(
+                    "xhci: port {port}: new {speed:?}-speed device (vendor={:#06x}, product={:#06x}, class={:#04x})",
+                    descriptor.vendor,
+                    descriptor.product,
+                    descriptor.class
+                );
+
+                let device = Arc::new(device);
+                super::probe(device.clone(), &configuration);
+
+                Ok(device)
+            }
+
+            Err(err) => {
+                self.release(&device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Disables the slot of `device` and fails its pending transfers.
+    fn release(&self, device: &Device) {
+        device.detached.store(true, Ordering::SeqCst);
+        self.wq.notify_all();
+
+        if let Err(err) = self.command(Trb::new(TrbType::DisableSlot).with_slot_id(device.slot)) {
+            log::warn!("xhci: failed to disable slot {}: {err:?}", device.slot);
+        }
+
+        // The contexts and the transfer rings are freed once the drivers of the device have
+        // dropped it.
+        self.dcbaa.lock_irq()[device.slot as usize] = 0;
+    }
+
+    /// Handles a change of the state of the root hub port with the provided number. The
+    /// device connected to the port is enumerated if the connection status has changed, or
+    /// if `rescan` is set.
+    fn probe_port(self: &Arc<Self>, port: u8, rescan: bool) {
+        let Some(registers) = port
+            .checked_sub(1)
+            .and_then(|index| self.ports.get(index as usize))
+        else {
+            return;
+        };
+
+        let mut devices = self.devices.lock();
+        let entry = &mut devices[port as usize - 1];
+
+        let status = registers.status();
+
+        // Acknowledge the changes.
+        registers.write(status & PortStatus::CHANGES);
+
+        if !status.contains(PortStatus::POWER) {
+            registers.write(PortStatus::POWER);
+        }
+
+        let connected = status.contains(PortStatus::CONNECTED);
+        let changed = status.contains(PortStatus::CONNECT_CHANGE);
+
+        if !connected || changed {
+            if let Some(device) = entry.take() {
+                log::info!("xhci: port {}: device disconnected", device.port);
+                self.release(&device);
+            }
+        }
+
+        if connected && entry.is_none() && (changed || rescan) {
+            match self.attach(port) {
+                Ok(device) => *entry = Some(device),
+                Err(err) => {
+                    log::warn!("xhci: port {port}: failed to enumerate the device: {err:?}")
+                }
+            }
+        }
+    }
+
+    fn handle_irq(self: &Arc<Self>) {
+        // Acknowledge the interrupt.
+        self.operational
+            .usbsts
+            .set(UsbStatus::EVENT_INTERRUPT.bits());
+        self.interrupter.iman.set(self.interrupter.iman.get());
+
+        let mut event_ring = self.event_ring.lock_irq();
+        let mut completions = self.completions.lock_irq();
+
+        while let Some(event) = event_ring.pop() {
+            match event.typ() {
+                Some(TrbType::CommandCompletion) => {
+                    completions.commands.insert(event.parameter, event);
+                }
+
+                Some(TrbType::Transfer) => {
+                    let key = (event.slot_id(), event.endpoint_id());
+                    completions.transfers.insert(key, event);
+                }
+
+                Some(TrbType::PortStatusChange) => {
+                    // Enumerating a device has to wait for the commands and the transfers to
+                    // complete, so it cannot be done in the interrupt handler.
+                    let this = self.clone();
+                    let port = event.port_id();
+
+                    workqueue::schedule_work(move || this.probe_port(port, false));
+                }
+
+                typ => log::debug!("xhci: unhandled event {typ:?}"),
+            }
+        }
+
+        // Update the dequeue pointer and clear the Event Handler Busy flag.
+        self.interrupter
+            .erdp
+            .set(event_ring.dequeue_addr().as_u64() | ERDP_EHB);
+
+        core::mem::drop(completions);
+        core::mem::drop(event_ring);
+
+        self.wq.notify_all();
+    }
+}
+
+static CONTROLLERS: Mutex<Vec<Arc<Controller>>> = Mutex::new(Vec::new());
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn handles(&self, _vendor_id: Vendor, device_id: DeviceType) -> bool {
+        device_id == DeviceType::UsbController
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        // UHCI, OHCI and EHCI controllers are not supported.
+        if header.prog_if() != PROG_IF_XHCI {
+            return;
+        }
+
+        let controller = match Controller::new(header) {
+            Ok(controller) => controller,
+            Err(err) => {
+                log::error!("xhci: failed to initialize the controller: {err:?}");
+                return;
+            }
+        };
+
+        for port in 1..=controller.ports.len() as u8 {
+            controller.probe_port(port, true);
+        }
+    }
+}
+
+fn irq_handler(_stack: &mut InterruptStack) {
+    for controller in CONTROLLERS.lock_irq().iter() {
+        controller.handle_irq();
+    }
+}
+
+fn init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::module_init!(init, ModuleType::Block);
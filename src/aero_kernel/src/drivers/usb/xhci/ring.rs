@@ -0,0 +1,349 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! TRB (Transfer Request Block) rings.
+//!
+//! The command ring and the transfer rings are produced by software and consumed by the
+//! controller, while the event ring is produced by the controller. The owner of each TRB is
+//! tracked with a cycle bit: a TRB belongs to the consumer when its cycle bit matches the
+//! consumer's cycle state, which is toggled every time the ring wraps around.
+
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use bit_field::BitField;
+
+use crate::drivers::usb::SetupPacket;
+use crate::mem::paging::PhysAddr;
+use crate::utils::dma::Dma;
+
+/// Number of TRBs in a command or transfer ring, including the link TRB.
+const RING_SIZE: usize = 256;
+/// Number of TRBs in the event ring.
+const EVENT_RING_SIZE: usize = 256;
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrbType {
+    Normal = 1,
+    Setup = 2,
+    Data = 3,
+    Status = 4,
+    Link = 6,
+    EnableSlot = 9,
+    DisableSlot = 10,
+    AddressDevice = 11,
+    ConfigureEndpoint = 12,
+    EvaluateContext = 13,
+
+    Transfer = 32,
+    CommandCompletion = 33,
+    PortStatusChange = 34,
+}
+
+impl TrbType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::Normal,
+            2 => Self::Setup,
+            3 => Self::Data,
+            4 => Self::Status,
+            6 => Self::Link,
+            9 => Self::EnableSlot,
+            10 => Self::DisableSlot,
+            11 => Self::AddressDevice,
+            12 => Self::ConfigureEndpoint,
+            13 => Self::EvaluateContext,
+            32 => Self::Transfer,
+            33 => Self::CommandCompletion,
+            34 => Self::PortStatusChange,
+            _ => return None,
+        })
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompletionCode {
+    Success = 1,
+    ShortPacket = 13,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct Trb {
+    pub parameter: u64,
+    pub status: u32,
+    pub control: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<Trb>(), 16);
+
+impl Trb {
+    pub fn new(typ: TrbType) -> Self {
+        let mut trb = Self::default();
+        trb.control.set_bits(10..16, typ as u32);
+        trb
+    }
+
+    /// Creates the setup stage TRB of a control transfer.
+    pub fn setup_stage(setup: &SetupPacket, has_data: bool) -> Self {
+        let mut trb = Self::new(TrbType::Setup).with_parameter(setup.as_u64());
+        trb.status = core::mem::size_of::<SetupPacket>() as u32;
+        trb.control.set_bit(6, true); // immediate data
+
+        let transfer_type = match (has_data, setup.is_in()) {
+            (false, _) => 0,
+            (true, false) => 2,
+            (true, true) => 3,
+        };
+
+        trb.control.set_bits(16..18, transfer_type);
+        trb
+    }
+
+    /// Creates the data stage TRB of a control transfer.
+    pub fn data_stage(buffer: PhysAddr, length: u16, is_in: bool) -> Self {
+        let mut trb = Self::new(TrbType::Data).with_parameter(buffer.as_u64());
+        trb.status.set_bits(0..17, length as u32);
+        trb.control.set_bit(16, is_in);
+        trb
+    }
+
+    /// Creates the status stage TRB of a control transfer.
+    pub fn status_stage(is_in: bool) -> Self {
+        let mut trb = Self::new(TrbType::Status);
+        trb.control.set_bit(16, is_in);
+        trb
+    }
+
+    /// Creates a normal TRB, which transfers `length` bytes on a bulk or interrupt endpoint.
+    /// The buffer must not cross a 64 KiB boundary.
+    pub fn normal(buffer: PhysAddr, length: u32) -> Self {
+        let mut trb = Self::new(TrbType::Normal).with_parameter(buffer.as_u64());
+        trb.status.set_bits(0..17, length);
+        trb
+    }
+
+    pub fn typ(&self) -> Option<TrbType> {
+        TrbType::from_u8(self.control.get_bits(10..16) as u8)
+    }
+
+    fn set_cycle(&mut self, cycle: bool) {
+        self.control.set_bit(0, cycle);
+    }
+
+    /// Returns the completion code of an event TRB.
+    pub fn completion_code(&self) -> u8 {
+        self.status.get_bits(24..32) as u8
+    }
+
+    /// Returns whether the command or the transfer that generated the event completed
+    /// successfully.
+    pub fn is_success(&self) -> bool {
+        let code = self.completion_code();
+
+        code == CompletionCode::Success as u8 || code == CompletionCode::ShortPacket as u8
+    }
+
+    /// Returns the number of bytes that were not transferred, for a transfer event.
+    pub fn transfer_residual(&self) -> u32 {
+        self.status.get_bits(0..24)
+    }
+
+    pub fn slot_id(&self) -> u8 {
+        self.control.get_bits(24..32) as u8
+    }
+
+    pub fn with_slot_id(mut self, slot_id: u8) -> Self {
+        self.control.set_bits(24..32, slot_id as u32);
+        self
+    }
+
+    /// Sets the slot type of an Enable Slot command (see the Supported Protocol capability).
+    pub fn with_slot_type(mut self, slot_type: u8) -> Self {
+        self.control.set_bits(16..21, slot_type as u32);
+        self
+    }
+
+    pub fn with_parameter(mut self, parameter: u64) -> Self {
+        self.parameter = parameter;
+        self
+    }
+
+    /// Returns the device context index of the endpoint that generated a transfer event.
+    pub fn endpoint_id(&self) -> u8 {
+        self.control.get_bits(16..21) as u8
+    }
+
+    /// Returns the root hub port number of a port status change event.
+    pub fn port_id(&self) -> u8 {
+        self.parameter.get_bits(24..32) as u8
+    }
+
+    /// Sets the Interrupt On Completion flag, so that an event is generated when the
+    /// controller has processed the TRB.
+    pub fn with_ioc(mut self) -> Self {
+        self.control.set_bit(5, true);
+        self
+    }
+}
+
+/// A ring that is produced by software; either the command ring or a transfer ring.
+///
+/// The ring consists of a single segment, with a link TRB in the last entry pointing back to
+/// the start of the segment.
+pub struct Ring {
+    trbs: Dma<[Trb]>,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    pub fn new() -> Self {
+        // SAFETY: A zeroed TRB is valid.
+        let mut trbs = unsafe { Dma::<Trb>::new_zeroed_slice(RING_SIZE).assume_init() };
+
+        let mut link = Trb::new(TrbType::Link);
+        link.parameter = trbs.addr().as_u64();
+        link.control.set_bit(1, true); // toggle cycle
+
+        trbs[RING_SIZE - 1] = link;
+
+        Self {
+            trbs,
+            enqueue: 0,
+            cycle: true,
+        }
+    }
+
+    /// Returns the physical address of the ring. The consumer cycle state starts as set.
+    pub fn addr(&self) -> PhysAddr {
+        self.trbs.addr()
+    }
+
+    fn write(&mut self, index: usize, mut trb: Trb) {
+        trb.set_cycle(self.cycle);
+
+        let entry = &mut self.trbs[index];
+
+        // SAFETY: The entry is a valid TRB. The cycle bit, which gives the ownership of the TRB
+        // to the controller, is written last.
+        unsafe {
+            ptr::write_volatile(&mut entry.parameter, trb.parameter);
+            ptr::write_volatile(&mut entry.status, trb.status);
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut entry.control, trb.control);
+        }
+    }
+
+    /// Places `trb` on the ring and returns its physical address, which is used to match the
+    /// TRB with the event that the controller generates for it. The doorbell has to be rung
+    /// afterwards for the controller to process the TRB.
+    pub fn push(&mut self, trb: Trb) -> PhysAddr {
+        let index = self.enqueue;
+        self.write(index, trb);
+
+        self.enqueue += 1;
+
+        if self.enqueue == RING_SIZE - 1 {
+            // Hand the link TRB over to the controller and wrap around.
+            let link = self.trbs[RING_SIZE - 1];
+            self.write(RING_SIZE - 1, link);
+
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        self.addr() + index * core::mem::size_of::<Trb>()
+    }
+}
+
+/// An entry in the Event Ring Segment Table.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+struct ErstEntry {
+    base: u64,
+    size: u32,
+    _reserved: u32,
+}
+
+/// The event ring of an interrupter, consisting of a single segment.
+pub struct EventRing {
+    trbs: Dma<[Trb]>,
+    erst: Dma<ErstEntry>,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    pub fn new() -> Self {
+        // SAFETY: A zeroed TRB is valid.
+        let trbs = unsafe { Dma::<Trb>::new_zeroed_slice(EVENT_RING_SIZE).assume_init() };
+        let mut erst = Dma::<ErstEntry>::zeroed();
+
+        erst.base = trbs.addr().as_u64();
+        erst.size = EVENT_RING_SIZE as u32;
+
+        Self {
+            trbs,
+            erst,
+            dequeue: 0,
+            cycle: true,
+        }
+    }
+
+    /// Returns the physical address of the Event Ring Segment Table.
+    pub fn erst_addr(&self) -> PhysAddr {
+        self.erst.addr()
+    }
+
+    /// Returns the number of entries in the Event Ring Segment Table.
+    pub fn erst_len(&self) -> u32 {
+        1
+    }
+
+    /// Returns the physical address of the next TRB that software will process.
+    pub fn dequeue_addr(&self) -> PhysAddr {
+        self.trbs.addr() + self.dequeue * core::mem::size_of::<Trb>()
+    }
+
+    /// Returns the next event posted by the controller, if any.
+    pub fn pop(&mut self) -> Option<Trb> {
+        let entry = &self.trbs[self.dequeue];
+
+        // SAFETY: The entry is a valid TRB. The rest of the TRB is only read once the cycle
+        // bit shows that the controller has written it.
+        let control = unsafe { ptr::read_volatile(&entry.control) };
+
+        if control.get_bit(0) != self.cycle {
+            return None;
+        }
+
+        fence(Ordering::Acquire);
+        let trb = unsafe { ptr::read_volatile(entry) };
+
+        self.dequeue += 1;
+
+        if self.dequeue == EVENT_RING_SIZE {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        Some(trb)
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Virtio Memory Balloon
+//!
+//! The balloon device allows the host to reclaim memory from the guest. The host sets the
+//! number of pages it wants the balloon to hold in the device configuration space and the
+//! guest inflates (takes pages away from the frame allocator and hands them over to the
+//! host) or deflates (takes them back) the balloon to match it. If the device supports free
+//! page reporting, unused high-order blocks are periodically reported to the host as well,
+//! so it can discard their backing memory without the balloon having to hold on to them.
+//!
+//! The configuration is polled by a kernel worker thread, so the configuration change
+//! interrupt is left unused.
+//!
+//! ## Notes
+//! * <https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html> (section 5.5)
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::drivers::pci::*;
+use crate::mem::paging::*;
+use crate::userland::{kthread, scheduler};
+use crate::utils::dma::Dma;
+use crate::utils::sync::Mutex;
+
+use super::{Buffer, VirtQueue, VirtioDevice};
+
+// Balloon configuration space.
+const CONFIG_NUM_PAGES: u16 = 0x00;
+const CONFIG_ACTUAL: u16 = 0x04;
+
+/// The host must be told before pages from the balloon are used.
+const F_MUST_TELL_HOST: u64 = 1 << 0;
+const F_FREE_PAGE_HINT: u64 = 1 << 3;
+const F_PAGE_REPORTING: u64 = 1 << 5;
+
+const QUEUE_INFLATE: u16 = 0;
+const QUEUE_DEFLATE: u16 = 1;
+
+/// The balloon always operates on 4KiB pages, independent of the guest page size.
+const BALLOON_PAGE_SHIFT: u64 = 12;
+
+/// Maximum number of page frame numbers sent to the host in a single request.
+const PFNS_PER_REQUEST: usize = 256;
+
+/// Order of the free blocks that are reported to the host.
+const REPORT_ORDER: usize = MAX_ORDER;
+/// Maximum number of blocks reported to the host in a single request.
+const REPORT_CAPACITY: usize = 32;
+/// Number of free 4KiB frames that are always left in the frame allocator while reporting,
+/// so that the rest of the kernel is not starved of memory in the meantime (64 MiB).
+const REPORT_RESERVE_FRAMES: usize = 16384;
+
+/// Interval (in seconds) between two checks of the requested balloon size.
+const POLL_INTERVAL: usize = 1;
+/// Interval (in polls) between two free page reports.
+const REPORT_INTERVAL: usize = 30;
+
+static BALLOON: Once<Mutex<Balloon>> = Once::new();
+
+struct Balloon {
+    device: VirtioDevice,
+
+    inflate: Arc<VirtQueue>,
+    deflate: Arc<VirtQueue>,
+    reporting: Option<Arc<VirtQueue>>,
+
+    pfns: Dma<[u32]>,
+    /// Frames that are currently held by the balloon.
+    pages: Vec<PhysAddr>,
+}
+
+impl Balloon {
+    fn new(header: &PciHeader) -> Result<Self, super::Error> {
+        let mut device = VirtioDevice::new(header)?;
+        let device_features = device.device_features();
+
+        device.negotiate(F_MUST_TELL_HOST | F_PAGE_REPORTING)?;
+
+        let inflate = device.queue(QUEUE_INFLATE)?;
+        let deflate = device.queue(QUEUE_DEFLATE)?;
+
+        let reporting = if device.has_feature(F_PAGE_REPORTING) {
+            // NOTE: The device always creates the statistics queue and creates the free page
+            // hinting queue if it offers the feature, regardless of what was negotiated.
+            let index = if device_features & F_FREE_PAGE_HINT != 0 {
+                4
+            } else {
+                3
+            };
+
+            device.queue(index).ok()
+        } else {
+            None
+        };
+
+        device.driver_ok();
+
+        log::info!(
+            "virtio-balloon: initialized (free page reporting={})",
+            reporting.is_some()
+        );
+
+        Ok(Self {
+            device,
+            inflate,
+            deflate,
+            reporting,
+            pfns: unsafe { Dma::new_zeroed_slice(PFNS_PER_REQUEST).assume_init() },
+            pages: Vec::new(),
+        })
+    }
+
+    /// Inflates or deflates the balloon to the size requested by the host.
+    fn update(&mut self) {
+        let target = self.device.read_config_u32(CONFIG_NUM_PAGES) as usize;
+        let current = self.pages.len();
+
+        if target > current {
+            if self.inflate(target - current) == 0 {
+                log::warn!("virtio-balloon: out of memory while inflating");
+            }
+        } else if target < current {
+            self.deflate(current - target);
+        } else {
+            return;
+        }
+
+        self.device
+            .write_config_u32(CONFIG_ACTUAL, self.pages.len() as u32);
+    }
+
+    /// Takes up to `count` frames from the frame allocator and hands them to the host.
+    /// Returns the number of frames added to the balloon.
+    fn inflate(&mut self, count: usize) -> usize {
+        let count = core::cmp::min(count, PFNS_PER_REQUEST);
+        let mut inflated = 0;
+
+        while inflated < count {
+            let Some(frame) = FRAME_ALLOCATOR.alloc_order(0) else {
+                break;
+            };
+
+            self.pfns[inflated] = (frame.as_u64() >> BALLOON_PAGE_SHIFT) as u32;
+            self.pages.push(frame);
+            inflated += 1;
+        }
+
+        if inflated != 0 {
+            let buffer = Buffer::readable(self.pfns.addr(), inflated * core::mem::size_of::<u32>());
+            let _ = self.inflate.transfer(&[buffer]);
+        }
+
+        inflated
+    }
+
+    /// Takes up to `count` frames back from the host and returns them to the frame
+    /// allocator.
+    fn deflate(&mut self, count: usize) {
+        let count = core::cmp::min(count, PFNS_PER_REQUEST);
+        let frames = self.pages.split_off(self.pages.len() - count);
+
+        for (pfn, frame) in self.pfns.iter_mut().zip(frames.iter()) {
+            *pfn = (frame.as_u64() >> BALLOON_PAGE_SHIFT) as u32;
+        }
+
+        let buffer = Buffer::readable(self.pfns.addr(), count * core::mem::size_of::<u32>());
+        let _ = self.deflate.transfer(&[buffer]);
+
+        for frame in frames {
+            FRAME_ALLOCATOR.dealloc_order(frame, 0);
+        }
+    }
+
+    /// Reports free blocks to the host. The blocks are taken out of the frame allocator
+    /// while the host is processing them and are returned afterwards.
+    fn report_free_pages(&mut self) {
+        let Some(queue) = self.reporting.as_ref() else {
+            return;
+        };
+
+        let frames_per_block = order_size(REPORT_ORDER) / Size4KiB::SIZE as usize;
+        let capacity = core::cmp::min(REPORT_CAPACITY, queue.size() as usize);
+        let mut blocks = Vec::with_capacity(capacity);
+
+        while blocks.len() < capacity
+            && FRAME_ALLOCATOR.free_frames() > REPORT_RESERVE_FRAMES + frames_per_block
+        {
+            let Some(block) = FRAME_ALLOCATOR.alloc_order(REPORT_ORDER) else {
+                break;
+            };
+
+            blocks.push(Buffer::writable(block, order_size(REPORT_ORDER)));
+        }
+
+        if blocks.is_empty() {
+            return;
+        }
+
+        // NOTE: The worker thread is never interrupted by a signal, so the blocks are not
+        // returned to the frame allocator while the host is still processing them.
+        let _ = queue.transfer(&blocks);
+
+        for block in blocks {
+            FRAME_ALLOCATOR.dealloc_order(block.address(), REPORT_ORDER);
+        }
+    }
+}
+
+fn balloon_thread() {
+    let balloon = BALLOON.get().unwrap();
+    let mut polls = 0;
+
+    loop {
+        {
+            let mut balloon = balloon.lock();
+            balloon.update();
+
+            if polls % REPORT_INTERVAL == 0 {
+                balloon.report_free_pages();
+            }
+        }
+
+        polls += 1;
+
+        let _ = scheduler::get_scheduler().inner.sleep(Some(POLL_INTERVAL));
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn handles(&self, vendor_id: Vendor, _device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Virtio
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        if super::device_id(header) != Some(super::ID_BALLOON) {
+            return;
+        }
+
+        if BALLOON.get().is_some() {
+            log::warn!("virtio-balloon: ignoring additional balloon device");
+            return;
+        }
+
+        let balloon = match Balloon::new(header) {
+            Ok(balloon) => balloon,
+            Err(err) => {
+                log::error!("virtio-balloon: failed to initialize the device: {err:?}");
+                return;
+            }
+        };
+
+        BALLOON.call_once(|| Mutex::new(balloon));
+        kthread::spawn("virtio-balloon", balloon_thread);
+    }
+}
+
+fn init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::module_init!(init, ModuleType::Block);
@@ -0,0 +1,269 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Virtio
+//!
+//! Shared core of the virtio device drivers. It implements the PCI transport (both the modern
+//! transport and the legacy one of transitional devices), feature negotiation and split
+//! virtqueues, so that the device drivers only have to deal with their own requests.
+//!
+//! Completions are signalled through MSI-X if the device supports it. All virtio devices share
+//! a single interrupt vector, whose handler checks every queue for completed requests. Without
+//! MSI-X, the queues are polled instead.
+//!
+//! ## Notes
+//! * <https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html>
+
+pub mod balloon;
+pub mod rng;
+mod queue;
+mod transport;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::interrupts::{self, InterruptStack};
+use crate::drivers::pci::PciHeader;
+use crate::utils::sync::Mutex;
+
+pub use self::queue::{Buffer, VirtQueue};
+use self::transport::Transport;
+
+// Device IDs (see section 5 of the specification).
+pub const ID_NET: u16 = 1;
+pub const ID_BLOCK: u16 = 2;
+pub const ID_CONSOLE: u16 = 3;
+pub const ID_ENTROPY: u16 = 4;
+pub const ID_BALLOON: u16 = 5;
+pub const ID_GPU: u16 = 16;
+
+// Device status bits.
+const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+const STATUS_DRIVER: u8 = 1 << 1;
+const STATUS_DRIVER_OK: u8 = 1 << 2;
+const STATUS_FEATURES_OK: u8 = 1 << 3;
+const STATUS_FAILED: u8 = 1 << 7;
+
+/// The device complies with version 1 of the specification (i.e. it is not a legacy device).
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Largest queue that is set up if the device allows choosing the size.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The device does not expose a transport that is supported.
+    UnsupportedTransport,
+    /// The device did not accept the negotiated features.
+    FeaturesRejected,
+    /// The queue with the provided index does not exist.
+    QueueUnavailable(u16),
+    /// The request was interrupted by a signal. It is still completed by the device, but its
+    /// result is discarded.
+    Interrupted,
+}
+
+/// Returns the virtio device ID of `header` or [`None`] if it is not a virtio device.
+pub fn device_id(header: &PciHeader) -> Option<u16> {
+    match header.get_device_id() {
+        // Modern devices encode the device ID in the PCI device ID.
+        id @ 0x1040..=0x107f => Some(id - 0x1040),
+        // Transitional devices report it as their subsystem ID.
+        0x1000..=0x103f => Some(unsafe { header.read::<u16>(0x2e) } as u16),
+        _ => None,
+    }
+}
+
+/// The shared interrupt vector of all virtio devices.
+static VECTOR: Once<u8> = Once::new();
+/// Queues that are signalled through the shared interrupt vector.
+static QUEUES: Mutex<Vec<Arc<VirtQueue>>> = Mutex::new(Vec::new());
+
+fn irq_handler(_stack: &mut InterruptStack) {
+    for queue in QUEUES.lock_irq().iter() {
+        queue.handle_irq();
+    }
+}
+
+/// Routes an MSI-X entry of the device to the shared interrupt vector. Returns the index of the
+/// entry or [`None`] if the device does not support MSI-X.
+fn setup_msix(header: &PciHeader) -> Option<u16> {
+    let mut msix = header.msix()?;
+
+    let vector = *VECTOR.call_once(|| {
+        let vector = interrupts::allocate_vector();
+        interrupts::register_handler(vector, irq_handler);
+        vector
+    });
+
+    Some(msix.set(vector) as u16)
+}
+
+/// A virtio device, which is set up in the following order:
+///
+/// 1. [`VirtioDevice::new`] resets the device and acknowledges it.
+/// 2. [`VirtioDevice::negotiate`] agrees on the features.
+/// 3. [`VirtioDevice::queue`] sets up each of the queues used by the driver.
+/// 4. [`VirtioDevice::driver_ok`] starts the device.
+pub struct VirtioDevice {
+    transport: Transport,
+    features: u64,
+    /// The MSI-X entry used by the device, if it supports MSI-X.
+    msix: Option<u16>,
+}
+
+impl VirtioDevice {
+    pub fn new(header: &PciHeader) -> Result<Self, Error> {
+        header.enable_bus_mastering();
+
+        let mut transport = Transport::new(header).ok_or(Error::UnsupportedTransport)?;
+
+        transport.set_status(0);
+        while transport.status() != 0 {
+            core::hint::spin_loop();
+        }
+
+        transport.set_status(STATUS_ACKNOWLEDGE);
+        transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let msix = setup_msix(header);
+
+        // The layout of the legacy configuration space depends on whether MSI-X is enabled,
+        // which is only known after it has been set up.
+        transport.set_msix_enabled(msix.is_some());
+
+        if let Some(entry) = msix {
+            transport.set_config_vector(entry);
+        }
+
+        log::debug!(
+            "virtio: found device (id={:?}, transport={}, msix={})",
+            device_id(header),
+            transport.name(),
+            msix.is_some()
+        );
+
+        Ok(Self {
+            transport,
+            features: 0,
+            msix,
+        })
+    }
+
+    /// Returns the features offered by the device.
+    pub fn device_features(&self) -> u64 {
+        self.transport.device_features()
+    }
+
+    /// Negotiates the features that are both offered by the device and in `supported`.
+    /// Returns the negotiated features.
+    pub fn negotiate(&mut self, supported: u64) -> Result<u64, Error> {
+        let mut supported = supported;
+
+        // Devices that are not legacy require the driver to accept version 1.
+        if self.transport.is_modern() {
+            supported |= F_VERSION_1;
+        }
+
+        let features = self.transport.device_features() & supported;
+        self.transport.set_driver_features(features);
+
+        if self.transport.is_modern() {
+            let status = self.transport.status();
+            self.transport.set_status(status | STATUS_FEATURES_OK);
+
+            if self.transport.status() & STATUS_FEATURES_OK == 0 {
+                self.fail();
+                return Err(Error::FeaturesRejected);
+            }
+        }
+
+        self.features = features;
+        Ok(features)
+    }
+
+    /// Returns whether the feature `feature` was negotiated.
+    pub fn has_feature(&self, feature: u64) -> bool {
+        self.features & feature != 0
+    }
+
+    /// Sets up the queue with the provided index.
+    pub fn queue(&mut self, index: u16) -> Result<Arc<VirtQueue>, Error> {
+        self.transport.select_queue(index);
+
+        let max_size = self.transport.queue_max_size();
+        if max_size == 0 {
+            return Err(Error::QueueUnavailable(index));
+        }
+
+        // Only modern devices allow choosing the queue size.
+        let size = if self.transport.is_modern() {
+            max_size.min(MAX_QUEUE_SIZE)
+        } else {
+            max_size
+        };
+
+        let interrupts = self
+            .msix
+            .is_some_and(|entry| self.transport.set_queue_vector(entry));
+
+        let queue = Arc::new(VirtQueue::new(
+            index,
+            size,
+            self.transport.notifier(),
+            interrupts,
+        ));
+
+        let (desc, avail, used) = queue.addresses();
+        self.transport.set_queue(size, desc, avail, used);
+
+        if interrupts {
+            QUEUES.lock_irq().push(queue.clone());
+        }
+
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is ready.
+    pub fn driver_ok(&mut self) {
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_DRIVER_OK);
+    }
+
+    /// Tells the device that the driver has given up on it.
+    pub fn fail(&mut self) {
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_FAILED);
+    }
+
+    pub fn read_config_u8(&self, offset: u16) -> u8 {
+        self.transport.read_config::<u8>(offset)
+    }
+
+    pub fn read_config_u16(&self, offset: u16) -> u16 {
+        self.transport.read_config::<u16>(offset)
+    }
+
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        self.transport.read_config::<u32>(offset)
+    }
+
+    pub fn write_config_u32(&self, offset: u16, value: u32) {
+        self.transport.write_config::<u32>(offset, value)
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Split virtqueues (see section 2.7 of the specification).
+//!
+//! A queue consists of the descriptor table, the available ring (written by the driver) and
+//! the used ring (written by the device). They are allocated contiguously with the used ring
+//! aligned to a page boundary, which is the layout required by the legacy interface.
+//!
+//! Requests are identified by the index of the first descriptor of their chain. Free
+//! descriptors are kept in a list that is linked through their `next` field.
+
+use core::sync::atomic::{fence, Ordering};
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::mem::paging::{align_up, PhysAddr};
+use crate::utils::dma::Dma;
+use crate::utils::sync::{Mutex, MutexGuard, WaitQueue};
+
+use super::transport::Notifier;
+use super::Error;
+
+const DESC_F_NEXT: u16 = 1 << 0;
+const DESC_F_WRITE: u16 = 1 << 1;
+
+/// Set in the available ring if the device should not interrupt the driver.
+const AVAIL_F_NO_INTERRUPT: u16 = 1 << 0;
+/// Set in the used ring if the device does not need to be notified about new buffers.
+const USED_F_NO_NOTIFY: u16 = 1 << 0;
+
+const RING_ALIGN: u64 = 4096;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer that is part of a request.
+#[derive(Debug, Copy, Clone)]
+pub struct Buffer {
+    address: PhysAddr,
+    len: usize,
+    writable: bool,
+}
+
+impl Buffer {
+    /// A buffer that is read by the device.
+    pub fn readable(address: PhysAddr, len: usize) -> Self {
+        Self {
+            address,
+            len,
+            writable: false,
+        }
+    }
+
+    /// A buffer that is written by the device.
+    pub fn writable(address: PhysAddr, len: usize) -> Self {
+        Self {
+            address,
+            len,
+            writable: true,
+        }
+    }
+
+    pub fn address(&self) -> PhysAddr {
+        self.address
+    }
+}
+
+struct QueueInner {
+    ring: Dma<[u8]>,
+
+    free_head: u16,
+    num_free: u16,
+    /// Index of the next entry in the available ring.
+    avail_idx: u16,
+    /// Index of the next entry in the used ring that has not been processed yet.
+    last_used: u16,
+
+    /// Requests that have been completed by the device, with the number of bytes written.
+    completed: BTreeMap<u16, u32>,
+    /// Requests whose waiter was interrupted; they are freed as soon as they are completed.
+    abandoned: BTreeSet<u16>,
+}
+
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    avail_offset: usize,
+    used_offset: usize,
+
+    notifier: Notifier,
+    /// Whether completions are signalled by an interrupt. Otherwise the queue is polled.
+    interrupts: bool,
+
+    inner: Mutex<QueueInner>,
+    wq: WaitQueue,
+}
+
+impl VirtQueue {
+    pub(super) fn new(index: u16, size: u16, notifier: Notifier, interrupts: bool) -> Self {
+        let avail_offset = size as usize * core::mem::size_of::<Descriptor>();
+        let used_offset = align_up((avail_offset + 6 + 2 * size as usize) as u64, RING_ALIGN);
+        let used_offset = used_offset as usize;
+
+        let ring = Dma::<u8>::new_zeroed_slice(
+            used_offset + 6 + core::mem::size_of::<UsedElement>() * size as usize,
+        );
+        let ring = unsafe { ring.assume_init() };
+
+        let mut inner = QueueInner {
+            ring,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+            completed: BTreeMap::new(),
+            abandoned: BTreeSet::new(),
+        };
+
+        let descriptors = inner.ring.as_mut_ptr().cast::<Descriptor>();
+
+        for i in 0..size {
+            unsafe { (*descriptors.add(i as usize)).next = i.wrapping_add(1) }
+        }
+
+        if !interrupts {
+            unsafe {
+                inner
+                    .ring
+                    .as_mut_ptr()
+                    .add(avail_offset)
+                    .cast::<u16>()
+                    .write_volatile(AVAIL_F_NO_INTERRUPT)
+            }
+        }
+
+        Self {
+            index,
+            size,
+            avail_offset,
+            used_offset,
+            notifier,
+            interrupts,
+            inner: Mutex::new(inner),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the physical addresses of the descriptor table, the available ring and the used
+    /// ring.
+    pub(super) fn addresses(&self) -> (PhysAddr, PhysAddr, PhysAddr) {
+        let desc = self.inner.lock_irq().ring.addr();
+
+        (
+            desc,
+            desc + self.avail_offset as u64,
+            desc + self.used_offset as u64,
+        )
+    }
+
+    /// Processes the requests that have been completed by the device.
+    fn poll(&self, inner: &mut QueueInner) {
+        let ring = inner.ring.as_mut_ptr();
+
+        loop {
+            let used_idx = unsafe { ring.add(self.used_offset + 2).cast::<u16>().read_volatile() };
+
+            if used_idx == inner.last_used {
+                break;
+            }
+
+            // Make sure that the element is read after the index.
+            fence(Ordering::Acquire);
+
+            let element = unsafe {
+                ring.add(self.used_offset + 4)
+                    .cast::<UsedElement>()
+                    .add((inner.last_used % self.size) as usize)
+                    .read_volatile()
+            };
+
+            inner.last_used = inner.last_used.wrapping_add(1);
+
+            let head = element.id as u16;
+
+            if inner.abandoned.remove(&head) {
+                self.free_chain(inner, head);
+            } else {
+                inner.completed.insert(head, element.len);
+            }
+        }
+    }
+
+    /// Returns the descriptors of the chain starting at `head` to the free list.
+    fn free_chain(&self, inner: &mut QueueInner, head: u16) {
+        let descriptors = inner.ring.as_mut_ptr().cast::<Descriptor>();
+        let mut index = head;
+
+        loop {
+            inner.num_free += 1;
+
+            let descriptor = unsafe { &mut *descriptors.add(index as usize) };
+
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                descriptor.next = inner.free_head;
+                break;
+            }
+
+            index = descriptor.next;
+        }
+
+        inner.free_head = head;
+    }
+
+    /// Blocks until `condition` is true, polling the queue while waiting.
+    fn wait_until<F>(&self, mut condition: F) -> Result<MutexGuard<QueueInner>, Error>
+    where
+        F: FnMut(&mut QueueInner) -> bool,
+    {
+        if self.interrupts {
+            return self
+                .wq
+                .block_on(&self.inner, |inner| {
+                    self.poll(inner);
+                    condition(inner)
+                })
+                .map_err(|_| Error::Interrupted);
+        }
+
+        loop {
+            let mut inner = self.inner.lock_irq();
+            self.poll(&mut inner);
+
+            if condition(&mut inner) {
+                return Ok(inner);
+            }
+
+            core::mem::drop(inner);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Makes the provided buffers available to the device as a single request. Blocks until
+    /// enough descriptors are free. Returns the token that identifies the request.
+    pub fn submit(&self, buffers: &[Buffer]) -> Result<u16, Error> {
+        assert!(!buffers.is_empty() && buffers.len() <= self.size as usize);
+
+        let mut inner = self.wait_until(|inner| inner.num_free as usize >= buffers.len())?;
+
+        let ring = inner.ring.as_mut_ptr();
+        let descriptors = ring.cast::<Descriptor>();
+
+        let head = inner.free_head;
+        let mut index = head;
+
+        for (i, buffer) in buffers.iter().enumerate() {
+            let descriptor = unsafe { &mut *descriptors.add(index as usize) };
+            let next = descriptor.next;
+
+            let mut flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+
+            if i != buffers.len() - 1 {
+                flags |= DESC_F_NEXT;
+            }
+
+            descriptor.addr = buffer.address.as_u64();
+            descriptor.len = buffer.len as u32;
+            descriptor.flags = flags;
+
+            inner.free_head = next;
+            index = next;
+        }
+
+        inner.num_free -= buffers.len() as u16;
+
+        unsafe {
+            let avail = ring.add(self.avail_offset);
+
+            avail
+                .add(4)
+                .cast::<u16>()
+                .add((inner.avail_idx % self.size) as usize)
+                .write_volatile(head);
+
+            inner.avail_idx = inner.avail_idx.wrapping_add(1);
+
+            // The descriptors and the ring entry have to be visible before the index.
+            fence(Ordering::SeqCst);
+            avail.add(2).cast::<u16>().write_volatile(inner.avail_idx);
+        }
+
+        fence(Ordering::SeqCst);
+
+        let used_flags = unsafe { ring.add(self.used_offset).cast::<u16>().read_volatile() };
+
+        if used_flags & USED_F_NO_NOTIFY == 0 {
+            self.notifier.notify(self.index);
+        }
+
+        Ok(head)
+    }
+
+    /// Waits until the request identified by `token` has been completed. Returns the number of
+    /// bytes written by the device.
+    pub fn wait(&self, token: u16) -> Result<u32, Error> {
+        match self.wait_until(|inner| inner.completed.contains_key(&token)) {
+            Ok(mut inner) => {
+                let len = inner.completed.remove(&token).unwrap();
+                self.free_chain(&mut inner, token);
+
+                // Wake up the submitters that are waiting for free descriptors.
+                core::mem::drop(inner);
+                self.wq.notify_all();
+
+                Ok(len)
+            }
+
+            Err(err) => {
+                let mut inner = self.inner.lock_irq();
+                self.poll(&mut inner);
+
+                // The device still owns the buffers, so they are freed once it is done.
+                if inner.completed.remove(&token).is_some() {
+                    self.free_chain(&mut inner, token);
+                } else {
+                    inner.abandoned.insert(token);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Submits the provided buffers as a single request and waits until it has been completed.
+    /// Returns the number of bytes written by the device.
+    pub fn transfer(&self, buffers: &[Buffer]) -> Result<u32, Error> {
+        let token = self.submit(buffers)?;
+        self.wait(token)
+    }
+
+    /// Called by the shared interrupt handler.
+    pub(super) fn handle_irq(&self) {
+        let mut inner = self.inner.lock_irq();
+        let last_used = inner.last_used;
+
+        self.poll(&mut inner);
+
+        if inner.last_used != last_used {
+            core::mem::drop(inner);
+            self.wq.notify_all();
+        }
+    }
+}
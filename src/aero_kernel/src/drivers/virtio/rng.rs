@@ -0,0 +1,140 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Virtio Entropy Device
+//!
+//! The entropy device supplies random bytes from the host, which are mixed into the kernel
+//! entropy pool (see [`crate::random`]) by a kernel worker thread.
+//!
+//! ## Notes
+//! * <https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html> (section 5.4)
+
+use alloc::sync::Arc;
+use spin::Once;
+
+use crate::drivers::pci::*;
+use crate::mem::paging::*;
+use crate::userland::{kthread, scheduler};
+use crate::utils::dma::Dma;
+
+use super::{Buffer, VirtQueue, VirtioDevice};
+
+const QUEUE_REQUEST: u16 = 0;
+
+/// Number of bytes requested from the host at once.
+const REQUEST_SIZE: usize = 64;
+
+/// Interval (in seconds) between two refills of the entropy pool once the CRNG is seeded.
+const REFILL_INTERVAL: usize = 60;
+
+static RNG: Once<Rng> = Once::new();
+
+struct Rng {
+    _device: VirtioDevice,
+    queue: Arc<VirtQueue>,
+}
+
+impl Rng {
+    fn new(header: &PciHeader) -> Result<Self, super::Error> {
+        let mut device = VirtioDevice::new(header)?;
+
+        device.negotiate(0)?;
+
+        let queue = device.queue(QUEUE_REQUEST)?;
+
+        device.driver_ok();
+        log::info!("virtio-rng: initialized");
+
+        Ok(Self {
+            _device: device,
+            queue,
+        })
+    }
+
+    /// Fills `buffer` with random bytes from the host. Returns the number of bytes written,
+    /// which may be less than the size of the buffer.
+    fn read(&self, buffer: &mut Dma<[u8]>) -> Result<usize, super::Error> {
+        let request = Buffer::writable(buffer.addr(), buffer.len());
+        let written = self.queue.transfer(&[request])? as usize;
+
+        Ok(core::cmp::min(written, buffer.len()))
+    }
+}
+
+fn rng_thread() {
+    let rng = RNG.get().unwrap();
+    let mut buffer: Dma<[u8]> = unsafe { Dma::new_zeroed_slice(REQUEST_SIZE).assume_init() };
+
+    loop {
+        match rng.read(&mut buffer) {
+            Ok(len) => {
+                // The host is trusted to supply full entropy.
+                crate::random::add_hwgenerator_randomness(&buffer[..len], len * 8);
+                buffer.fill(0);
+            }
+
+            Err(err) => {
+                log::error!("virtio-rng: request failed: {err:?}");
+                return;
+            }
+        }
+
+        // Keep requesting entropy until the CRNG is seeded, afterwards only refill the pool
+        // periodically.
+        if crate::random::is_ready() {
+            let _ = scheduler::get_scheduler()
+                .inner
+                .sleep(Some(REFILL_INTERVAL));
+        }
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn handles(&self, vendor_id: Vendor, _device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Virtio
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        if super::device_id(header) != Some(super::ID_ENTROPY) {
+            return;
+        }
+
+        if RNG.get().is_some() {
+            log::warn!("virtio-rng: ignoring additional entropy device");
+            return;
+        }
+
+        let rng = match Rng::new(header) {
+            Ok(rng) => rng,
+            Err(err) => {
+                log::error!("virtio-rng: failed to initialize the device: {err:?}");
+                return;
+            }
+        };
+
+        RNG.call_once(|| rng);
+        kthread::spawn("virtio-rng", rng_thread);
+    }
+}
+
+fn init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::module_init!(init, ModuleType::Block);
@@ -0,0 +1,406 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The virtio PCI transport. Modern devices describe the location of their register blocks
+//! with vendor specific PCI capabilities, while the legacy interface of transitional devices
+//! is a fixed register layout in the I/O space of BAR0.
+
+use bit_field::BitField;
+
+use crate::arch::io;
+use crate::drivers::pci::{map_bar, Bar, Capability, PciHeader};
+use crate::mem::paging::{PhysAddr, VirtAddr};
+
+// Legacy registers (offsets into BAR0).
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_ADDRESS: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0c;
+const LEGACY_QUEUE_SELECT: u16 = 0x0e;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_DEVICE_STATUS: u16 = 0x12;
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+
+/// The device configuration starts right after the common registers, which are followed by
+/// the MSI-X vector registers if MSI-X is enabled.
+const LEGACY_CONFIG: u16 = 0x14;
+const LEGACY_CONFIG_MSIX: u16 = 0x18;
+
+/// Legacy queues are addressed by their page frame number.
+const LEGACY_QUEUE_ALIGN_SHIFT: u64 = 12;
+
+// Types of the virtio PCI capabilities.
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_DEVICE_CFG: u8 = 4;
+
+// Common configuration registers of modern devices.
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+const COMMON_CONFIG_MSIX_VECTOR: u64 = 0x10;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: u64 = 0x1a;
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+/// Written to an MSI-X vector register to disable the interrupt; read back if the device
+/// failed to allocate the vector.
+const NO_VECTOR: u16 = 0xffff;
+
+/// A register that can be accessed in the I/O space.
+pub trait PortValue: Copy {
+    unsafe fn read_port(port: u16) -> Self;
+    unsafe fn write_port(port: u16, value: Self);
+}
+
+impl PortValue for u8 {
+    unsafe fn read_port(port: u16) -> Self {
+        io::inb(port)
+    }
+
+    unsafe fn write_port(port: u16, value: Self) {
+        io::outb(port, value)
+    }
+}
+
+impl PortValue for u16 {
+    unsafe fn read_port(port: u16) -> Self {
+        io::inw(port)
+    }
+
+    unsafe fn write_port(port: u16, value: Self) {
+        io::outw(port, value)
+    }
+}
+
+impl PortValue for u32 {
+    unsafe fn read_port(port: u16) -> Self {
+        io::inl(port)
+    }
+
+    unsafe fn write_port(port: u16, value: Self) {
+        io::outl(port, value)
+    }
+}
+
+unsafe fn mmio_read<T: Copy>(base: VirtAddr, offset: u64) -> T {
+    core::ptr::read_volatile((base + offset).as_ptr::<T>())
+}
+
+unsafe fn mmio_write<T: Copy>(base: VirtAddr, offset: u64, value: T) {
+    core::ptr::write_volatile((base + offset).as_mut_ptr::<T>(), value)
+}
+
+/// How the device is told that new buffers are available in a queue.
+pub enum Notifier {
+    /// The queue index is written to the notify register of the legacy interface.
+    Port(u16),
+    /// The queue index is written to the notification address of the queue.
+    Mmio(VirtAddr),
+}
+
+impl Notifier {
+    pub fn notify(&self, index: u16) {
+        unsafe {
+            match self {
+                Self::Port(port) => io::outw(*port, index),
+                Self::Mmio(address) => mmio_write::<u16>(*address, 0, index),
+            }
+        }
+    }
+}
+
+pub struct Legacy {
+    base: u16,
+    config: u16,
+}
+
+pub struct Modern {
+    common: VirtAddr,
+    device: VirtAddr,
+    notify: VirtAddr,
+    notify_multiplier: u32,
+}
+
+pub enum Transport {
+    Legacy(Legacy),
+    Modern(Modern),
+}
+
+/// Returns the address of the region described by the virtio capability at `offset`.
+fn capability_region(header: &PciHeader, offset: u32) -> Option<VirtAddr> {
+    let bar = unsafe { header.read::<u8>(offset + 4) } as u8;
+    let region_offset = unsafe { header.read::<u32>(offset + 8) };
+
+    let bar = header.get_bar(bar)?;
+
+    let address = match bar {
+        Bar::Memory32 { address, .. } => PhysAddr::new(address as u64),
+        Bar::Memory64 { address, .. } => PhysAddr::new(address),
+        Bar::IO(_) => return None,
+    };
+
+    map_bar(&bar);
+    Some(address.as_hhdm_virt() + region_offset as u64)
+}
+
+impl Transport {
+    /// Finds the transport of the device, preferring the modern one.
+    pub fn new(header: &PciHeader) -> Option<Self> {
+        Self::new_modern(header).or_else(|| Self::new_legacy(header))
+    }
+
+    fn new_modern(header: &PciHeader) -> Option<Self> {
+        let mut common = None;
+        let mut device = None;
+        let mut notify = None;
+
+        for (offset, _) in header
+            .capabilities()
+            .filter(|(_, capability)| *capability == Capability::Vendor)
+        {
+            let typ = unsafe { header.read::<u8>(offset + 3) } as u8;
+
+            match typ {
+                CAP_COMMON_CFG if common.is_none() => common = capability_region(header, offset),
+                CAP_DEVICE_CFG if device.is_none() => device = capability_region(header, offset),
+                CAP_NOTIFY_CFG if notify.is_none() => {
+                    let multiplier = unsafe { header.read::<u32>(offset + 16) };
+                    notify = capability_region(header, offset).map(|base| (base, multiplier));
+                }
+
+                _ => {}
+            }
+        }
+
+        let (notify, notify_multiplier) = notify?;
+
+        Some(Self::Modern(Modern {
+            common: common?,
+            // The device configuration is optional for devices without one (e.g. entropy).
+            device: device.unwrap_or(VirtAddr::zero()),
+            notify,
+            notify_multiplier,
+        }))
+    }
+
+    fn new_legacy(header: &PciHeader) -> Option<Self> {
+        // Only transitional devices have the legacy interface.
+        if header.get_device_id() >= 0x1040 {
+            return None;
+        }
+
+        let Some(Bar::IO(_)) = header.get_bar(0) else {
+            return None;
+        };
+
+        let base = header.base_address0().get_bits(2..32) << 2;
+
+        Some(Self::Legacy(Legacy {
+            base: base as u16,
+            config: LEGACY_CONFIG,
+        }))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Legacy(_) => "legacy",
+            Self::Modern(_) => "modern",
+        }
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Self::Modern(_))
+    }
+
+    pub fn set_msix_enabled(&mut self, enabled: bool) {
+        if let Self::Legacy(legacy) = self {
+            legacy.config = if enabled {
+                LEGACY_CONFIG_MSIX
+            } else {
+                LEGACY_CONFIG
+            };
+        }
+    }
+
+    pub fn status(&self) -> u8 {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => io::inb(legacy.base + LEGACY_DEVICE_STATUS),
+                Self::Modern(modern) => mmio_read::<u8>(modern.common, COMMON_DEVICE_STATUS),
+            }
+        }
+    }
+
+    pub fn set_status(&mut self, status: u8) {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => io::outb(legacy.base + LEGACY_DEVICE_STATUS, status),
+                Self::Modern(modern) => mmio_write(modern.common, COMMON_DEVICE_STATUS, status),
+            }
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        unsafe {
+            match self {
+                // The legacy interface only has 32 feature bits.
+                Self::Legacy(legacy) => io::inl(legacy.base + LEGACY_DEVICE_FEATURES) as u64,
+
+                Self::Modern(modern) => {
+                    mmio_write::<u32>(modern.common, COMMON_DEVICE_FEATURE_SELECT, 0);
+                    let low = mmio_read::<u32>(modern.common, COMMON_DEVICE_FEATURE);
+
+                    mmio_write::<u32>(modern.common, COMMON_DEVICE_FEATURE_SELECT, 1);
+                    let high = mmio_read::<u32>(modern.common, COMMON_DEVICE_FEATURE);
+
+                    (high as u64) << 32 | low as u64
+                }
+            }
+        }
+    }
+
+    pub fn set_driver_features(&mut self, features: u64) {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => {
+                    io::outl(legacy.base + LEGACY_DRIVER_FEATURES, features as u32)
+                }
+
+                Self::Modern(modern) => {
+                    mmio_write::<u32>(modern.common, COMMON_DRIVER_FEATURE_SELECT, 0);
+                    mmio_write(modern.common, COMMON_DRIVER_FEATURE, features as u32);
+
+                    mmio_write::<u32>(modern.common, COMMON_DRIVER_FEATURE_SELECT, 1);
+                    mmio_write(
+                        modern.common,
+                        COMMON_DRIVER_FEATURE,
+                        (features >> 32) as u32,
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn set_config_vector(&mut self, entry: u16) {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => io::outw(legacy.base + LEGACY_CONFIG_VECTOR, entry),
+                Self::Modern(modern) => mmio_write(modern.common, COMMON_CONFIG_MSIX_VECTOR, entry),
+            }
+        }
+    }
+
+    pub fn select_queue(&mut self, index: u16) {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => io::outw(legacy.base + LEGACY_QUEUE_SELECT, index),
+                Self::Modern(modern) => mmio_write(modern.common, COMMON_QUEUE_SELECT, index),
+            }
+        }
+    }
+
+    /// Returns the maximum size of the selected queue or zero if it does not exist.
+    pub fn queue_max_size(&self) -> u16 {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => io::inw(legacy.base + LEGACY_QUEUE_SIZE),
+                Self::Modern(modern) => mmio_read::<u16>(modern.common, COMMON_QUEUE_SIZE),
+            }
+        }
+    }
+
+    /// Routes the interrupts of the selected queue to the MSI-X entry `entry`. Returns `false`
+    /// if the device could not allocate the vector.
+    pub fn set_queue_vector(&mut self, entry: u16) -> bool {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => {
+                    io::outw(legacy.base + LEGACY_QUEUE_VECTOR, entry);
+                    io::inw(legacy.base + LEGACY_QUEUE_VECTOR) != NO_VECTOR
+                }
+
+                Self::Modern(modern) => {
+                    mmio_write(modern.common, COMMON_QUEUE_MSIX_VECTOR, entry);
+                    mmio_read::<u16>(modern.common, COMMON_QUEUE_MSIX_VECTOR) != NO_VECTOR
+                }
+            }
+        }
+    }
+
+    /// Returns how the device is notified about new buffers in the selected queue.
+    pub fn notifier(&self) -> Notifier {
+        match self {
+            Self::Legacy(legacy) => Notifier::Port(legacy.base + LEGACY_QUEUE_NOTIFY),
+
+            Self::Modern(modern) => {
+                let offset =
+                    unsafe { mmio_read::<u16>(modern.common, COMMON_QUEUE_NOTIFY_OFF) } as u64;
+
+                Notifier::Mmio(modern.notify + offset * modern.notify_multiplier as u64)
+            }
+        }
+    }
+
+    /// Sets the size and the addresses of the selected queue and enables it.
+    pub fn set_queue(&mut self, size: u16, desc: PhysAddr, avail: PhysAddr, used: PhysAddr) {
+        unsafe {
+            match self {
+                // The rings of legacy queues are contiguous, with the used ring aligned to a
+                // page boundary.
+                Self::Legacy(legacy) => {
+                    let pfn = desc.as_u64() >> LEGACY_QUEUE_ALIGN_SHIFT;
+                    io::outl(legacy.base + LEGACY_QUEUE_ADDRESS, pfn as u32);
+                }
+
+                Self::Modern(modern) => {
+                    mmio_write(modern.common, COMMON_QUEUE_SIZE, size);
+                    mmio_write(modern.common, COMMON_QUEUE_DESC, desc.as_u64());
+                    mmio_write(modern.common, COMMON_QUEUE_DRIVER, avail.as_u64());
+                    mmio_write(modern.common, COMMON_QUEUE_DEVICE, used.as_u64());
+                    mmio_write::<u16>(modern.common, COMMON_QUEUE_ENABLE, 1);
+                }
+            }
+        }
+    }
+
+    pub fn read_config<T: PortValue>(&self, offset: u16) -> T {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => T::read_port(legacy.base + legacy.config + offset),
+                Self::Modern(modern) => mmio_read::<T>(modern.device, offset as u64),
+            }
+        }
+    }
+
+    pub fn write_config<T: PortValue>(&self, offset: u16, value: T) {
+        unsafe {
+            match self {
+                Self::Legacy(legacy) => T::write_port(legacy.base + legacy.config + offset, value),
+                Self::Modern(modern) => mmio_write::<T>(modern.device, offset as u64, value),
+            }
+        }
+    }
+}
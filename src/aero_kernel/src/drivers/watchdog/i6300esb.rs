@@ -0,0 +1,230 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Intel 6300ESB Watchdog Timer
+//!
+//! The watchdog of the Intel 6300ESB I/O controller hub, which is also emulated by QEMU
+//! (`-device i6300esb`). It has two stages that are both loaded with the timeout: when the
+//! first stage expires, the second stage is started and when it expires as well, the machine
+//! is reset. The first stage is configured not to raise an interrupt.
+//!
+//! ## Notes
+//! * <https://www.intel.com/content/dam/doc/datasheet/6300esb-io-controller-hub-datasheet.pdf>
+//!   (section 15)
+
+use alloc::sync::Arc;
+use core::ops::RangeInclusive;
+
+use aero_syscall::prelude::WDIOF_CARDRESET;
+
+use crate::drivers::pci::*;
+use crate::mem::paging::*;
+
+use super::Watchdog;
+
+const DEVICE_ID: u16 = 0x25ab;
+
+// PCI configuration space registers.
+const CONFIG_REG: u32 = 0x60;
+const LOCK_REG: u32 = 0x68;
+
+// Memory mapped registers.
+const TIMER1_REG: u64 = 0x00;
+const TIMER2_REG: u64 = 0x04;
+const RELOAD_REG: u64 = 0x0c;
+
+/// Disables the first stage interrupt; the watchdog output (reset) is enabled and the timers
+/// count at approximately 1KHz (PCI clock divided by 2^15).
+const CONFIG_INT_DISABLED: u32 = 0b11;
+
+const LOCK_WDT_LOCK: u32 = 1 << 0;
+const LOCK_WDT_ENABLE: u32 = 1 << 1;
+
+const RELOAD_WDT_RELOAD: u16 = 1 << 8;
+/// Set if the machine was reset by the watchdog.
+const RELOAD_WDT_TIMEOUT: u16 = 1 << 9;
+
+// Writing these values to the reload register unlocks the next register write.
+const UNLOCK1: u16 = 0x80;
+const UNLOCK2: u16 = 0x86;
+
+struct I6300Esb {
+    header: PciHeader,
+    base: VirtAddr,
+    boot_status: u32,
+}
+
+impl I6300Esb {
+    fn new(header: &PciHeader) -> Option<Self> {
+        let (address, size) = match header.get_bar(0)? {
+            bar @ Bar::Memory32 { address, size, .. } => {
+                map_bar(&bar);
+                (address as u64, size as u64)
+            }
+
+            bar @ Bar::Memory64 { address, size, .. } => {
+                map_bar(&bar);
+                (address, size)
+            }
+
+            _ => return None,
+        };
+
+        if size < 0x10 {
+            return None;
+        }
+
+        header.enable_mmio();
+
+        let mut this = Self {
+            header: PciHeader::with_segment(
+                header.segment(),
+                header.bus(),
+                header.device(),
+                header.function(),
+            ),
+            base: PhysAddr::new(address).as_hhdm_virt(),
+            boot_status: 0,
+        };
+
+        unsafe {
+            this.header.write::<u16>(CONFIG_REG, CONFIG_INT_DISABLED);
+
+            if this.header.read::<u8>(LOCK_REG) & LOCK_WDT_LOCK != 0 {
+                log::warn!("i6300esb: the watchdog is locked and cannot be stopped");
+            }
+
+            // Disable the watchdog until it is opened.
+            this.header.write::<u8>(LOCK_REG, 0);
+        }
+
+        this.unlock();
+
+        if this.read_reload() & RELOAD_WDT_TIMEOUT != 0 {
+            log::warn!("i6300esb: the machine was reset by the watchdog");
+            this.boot_status = WDIOF_CARDRESET;
+        }
+
+        // Clear the timeout flag and reload the timers.
+        this.unlock();
+        this.write_reload(RELOAD_WDT_TIMEOUT | RELOAD_WDT_RELOAD);
+
+        Some(this)
+    }
+
+    /// Unlocks the next write to a memory mapped register.
+    fn unlock(&self) {
+        self.write_reload(UNLOCK1);
+        self.write_reload(UNLOCK2);
+    }
+
+    fn read_reload(&self) -> u16 {
+        unsafe { (self.base + RELOAD_REG).as_ptr::<u16>().read_volatile() }
+    }
+
+    fn write_reload(&self, value: u16) {
+        unsafe {
+            (self.base + RELOAD_REG)
+                .as_mut_ptr::<u16>()
+                .write_volatile(value)
+        }
+    }
+
+    fn write_timer(&self, register: u64, value: u32) {
+        self.unlock();
+        unsafe {
+            (self.base + register)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value)
+        }
+    }
+}
+
+impl Watchdog for I6300Esb {
+    fn identity(&self) -> &'static str {
+        "i6300ESB timer"
+    }
+
+    fn timeout_range(&self) -> RangeInclusive<u32> {
+        1..=2046
+    }
+
+    fn start(&self) {
+        unsafe { self.header.write::<u8>(LOCK_REG, LOCK_WDT_ENABLE) }
+    }
+
+    fn stop(&self) {
+        self.unlock();
+        self.write_reload(RELOAD_WDT_RELOAD);
+
+        unsafe {
+            self.header.write::<u8>(LOCK_REG, 0);
+
+            if self.header.read::<u8>(LOCK_REG) & LOCK_WDT_ENABLE != 0 {
+                log::error!("i6300esb: failed to stop the watchdog");
+            }
+        }
+    }
+
+    fn ping(&self) {
+        self.unlock();
+        self.write_reload(RELOAD_WDT_RELOAD);
+    }
+
+    fn set_timeout(&self, timeout: u32) {
+        // Both stages count down at approximately 1KHz and run one after another, so each of
+        // them is loaded with half of the timeout (512 ticks per second).
+        let value = timeout << 9;
+
+        self.write_timer(TIMER1_REG, value);
+        self.write_timer(TIMER2_REG, value);
+        self.ping();
+    }
+
+    fn boot_status(&self) -> u32 {
+        self.boot_status
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn handles(&self, vendor_id: Vendor, _device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Intel
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        if header.get_device_id() != DEVICE_ID {
+            return;
+        }
+
+        let Some(watchdog) = I6300Esb::new(header) else {
+            log::error!("i6300esb: the watchdog registers are not memory mapped");
+            return;
+        };
+
+        if !super::register(Arc::new(watchdog)) {
+            log::warn!("i6300esb: ignoring additional watchdog");
+        }
+    }
+}
+
+fn init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::module_init!(init, ModuleType::Block);
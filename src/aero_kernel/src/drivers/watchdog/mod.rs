@@ -0,0 +1,305 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Watchdog Timers
+//!
+//! A watchdog resets the machine if it is not pinged within its timeout, so that the machine
+//! recovers from a hang on its own. The watchdog is exposed to userland as `/dev/watchdog`
+//! with the Linux watchdog API: opening the device starts the watchdog and every write or
+//! `WDIOC_KEEPALIVE` ioctl pings it. Closing the device only stops the watchdog if the
+//! magic character `V` was written right before, so that a crashing watchdog daemon does not
+//! disable the watchdog.
+//!
+//! A hardware watchdog is used if one is found; otherwise, the software watchdog (which
+//! only catches hangs that leave the timer interrupt working) is registered.
+//!
+//! ## Notes
+//! * <https://docs.kernel.org/watchdog/watchdog-api.html>
+
+#[cfg(target_arch = "x86_64")]
+pub mod i6300esb;
+pub mod softdog;
+
+use alloc::sync::Arc;
+use core::ops::RangeInclusive;
+
+use spin::Once;
+
+use aero_syscall::prelude::*;
+use aero_syscall::OpenFlags;
+
+use crate::fs::cache::DirCacheItem;
+use crate::fs::devfs::{self, Device};
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::INodeInterface;
+use crate::fs::{FileSystemError, Result};
+use crate::mem::paging::VirtAddr;
+use crate::utils::sync::Mutex;
+
+/// Timeout (in seconds) used when the watchdog is first opened.
+const DEFAULT_TIMEOUT: u32 = 30;
+
+/// A watchdog timer driver.
+pub trait Watchdog: Send + Sync {
+    /// Name of the watchdog reported by `WDIOC_GETSUPPORT`.
+    fn identity(&self) -> &'static str;
+
+    /// Range of the supported timeouts, in seconds.
+    fn timeout_range(&self) -> RangeInclusive<u32>;
+
+    /// Starts the watchdog with the timeout set by the last [`Watchdog::set_timeout`] call.
+    fn start(&self);
+
+    fn stop(&self);
+
+    /// Restarts the countdown of the watchdog.
+    fn ping(&self);
+
+    /// Sets the timeout of the watchdog (in seconds) and restarts its countdown if it is
+    /// running.
+    fn set_timeout(&self, timeout: u32);
+
+    /// Returns the `WDIOF_*` flags describing why the machine was last reset.
+    fn boot_status(&self) -> u32 {
+        0
+    }
+
+    /// Returns the number of seconds left before the watchdog fires, if known.
+    fn time_left(&self) -> Option<u32> {
+        None
+    }
+}
+
+struct State {
+    /// Whether `/dev/watchdog` is currently open. Only one opener is allowed.
+    open: bool,
+    running: bool,
+    /// Whether the magic close character was written since the last ping.
+    expect_close: bool,
+    timeout: u32,
+}
+
+struct WatchdogDevice {
+    marker: usize,
+    driver: Arc<dyn Watchdog>,
+    state: Mutex<State>,
+}
+
+impl WatchdogDevice {
+    fn new(driver: Arc<dyn Watchdog>) -> Arc<Self> {
+        let range = driver.timeout_range();
+        let timeout = DEFAULT_TIMEOUT.clamp(*range.start(), *range.end());
+
+        driver.set_timeout(timeout);
+
+        Arc::new(Self {
+            marker: devfs::alloc_device_marker(),
+            driver,
+            state: Mutex::new(State {
+                open: false,
+                running: false,
+                expect_close: false,
+                timeout,
+            }),
+        })
+    }
+}
+
+impl Device for WatchdogDevice {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        String::from("watchdog")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        WATCHDOG.get().expect("watchdog: not registered").clone()
+    }
+
+    fn device_number(&self) -> Option<devfs::DeviceNumber> {
+        Some(devfs::DeviceNumber::Char(10, 130))
+    }
+}
+
+impl INodeInterface for WatchdogDevice {
+    fn open(&self, _handle: Arc<FileHandle>) -> Result<Option<DirCacheItem>> {
+        let mut state = self.state.lock_irq();
+
+        if state.open {
+            return Err(FileSystemError::Busy);
+        }
+
+        state.open = true;
+        state.expect_close = false;
+
+        if !state.running {
+            self.driver.start();
+            state.running = true;
+        } else {
+            self.driver.ping();
+        }
+
+        Ok(None)
+    }
+
+    fn close(&self, _flags: OpenFlags) {
+        let mut state = self.state.lock_irq();
+
+        state.open = false;
+
+        if state.running {
+            if state.expect_close {
+                self.driver.stop();
+                state.running = false;
+            } else {
+                log::error!("watchdog: unexpected close, not stopping the watchdog");
+            }
+        }
+
+        state.expect_close = false;
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.state.lock_irq();
+
+        state.expect_close = buffer.contains(&b'V');
+
+        if state.running {
+            self.driver.ping();
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        match command {
+            WDIOC_GETSUPPORT => {
+                let info = VirtAddr::new(arg as _).read_mut::<WatchdogInfo>()?;
+                let identity = self.driver.identity().as_bytes();
+                // Leave room for the NUL terminator.
+                let len = core::cmp::min(identity.len(), info.identity.len() - 1);
+
+                info.options = WDIOF_SETTIMEOUT | WDIOF_MAGICCLOSE | WDIOF_KEEPALIVEPING;
+                info.firmware_version = 0;
+                info.identity.fill(0);
+                info.identity[..len].copy_from_slice(&identity[..len]);
+                Ok(0)
+            }
+
+            WDIOC_GETSTATUS => {
+                *VirtAddr::new(arg as _).read_mut::<u32>()? = 0;
+                Ok(0)
+            }
+
+            WDIOC_GETBOOTSTATUS => {
+                *VirtAddr::new(arg as _).read_mut::<u32>()? = self.driver.boot_status();
+                Ok(0)
+            }
+
+            WDIOC_SETOPTIONS => {
+                let options = *VirtAddr::new(arg as _).read_mut::<u32>()?;
+                let mut state = self.state.lock_irq();
+
+                if options & WDIOS_DISABLECARD != 0 && state.running {
+                    self.driver.stop();
+                    state.running = false;
+                }
+
+                if options & WDIOS_ENABLECARD != 0 && !state.running {
+                    self.driver.start();
+                    state.running = true;
+                }
+
+                Ok(0)
+            }
+
+            WDIOC_KEEPALIVE => {
+                if self.state.lock_irq().running {
+                    self.driver.ping();
+                }
+
+                Ok(0)
+            }
+
+            WDIOC_SETTIMEOUT => {
+                let timeout = VirtAddr::new(arg as _).read_mut::<i32>()?;
+                let range = self.driver.timeout_range();
+
+                let requested = u32::try_from(*timeout)
+                    .ok()
+                    .filter(|timeout| range.contains(timeout))
+                    .ok_or(FileSystemError::InvalidArgument)?;
+
+                self.state.lock_irq().timeout = requested;
+                self.driver.set_timeout(requested);
+
+                // The new timeout is returned to the caller.
+                *timeout = requested as i32;
+                Ok(0)
+            }
+
+            WDIOC_GETTIMEOUT => {
+                *VirtAddr::new(arg as _).read_mut::<i32>()? = self.state.lock_irq().timeout as i32;
+                Ok(0)
+            }
+
+            WDIOC_GETTIMELEFT => {
+                let left = self
+                    .driver
+                    .time_left()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                *VirtAddr::new(arg as _).read_mut::<i32>()? = left as i32;
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+}
+
+static WATCHDOG: Once<Arc<WatchdogDevice>> = Once::new();
+
+/// Registers `driver` as the system watchdog and installs `/dev/watchdog`. Returns `false`
+/// if a watchdog is already registered.
+pub fn register(driver: Arc<dyn Watchdog>) -> bool {
+    if WATCHDOG.get().is_some() {
+        return false;
+    }
+
+    let identity = driver.identity();
+    let device = WATCHDOG.call_once(|| WatchdogDevice::new(driver));
+
+    if let Err(err) = devfs::install_device(device.clone()) {
+        log::error!("watchdog: failed to install /dev/watchdog: {err:?}");
+        return true;
+    }
+
+    log::info!("watchdog: registered {identity}");
+    true
+}
+
+/// Returns whether a watchdog is registered.
+pub fn is_registered() -> bool {
+    WATCHDOG.get().is_some()
+}
@@ -0,0 +1,102 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Software Watchdog
+//!
+//! Emulates a watchdog with a high-resolution timer that reboots the machine when it
+//! expires. As the timer is run from the timer interrupt, hangs with interrupts disabled are
+//! not detected; a hardware watchdog should be preferred if available.
+
+use alloc::sync::Arc;
+use core::ops::RangeInclusive;
+
+use crate::userland::scheduler::hrtimer::{self, HrTimer};
+use crate::utils::sync::Mutex;
+
+use super::Watchdog;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+struct SoftDog {
+    timeout: Mutex<u32>,
+    /// The pending timer while the watchdog is running.
+    timer: Mutex<Option<HrTimer>>,
+}
+
+impl SoftDog {
+    fn arm(&self, timer: &mut Option<HrTimer>) {
+        let expires = hrtimer::now() + *self.timeout.lock_irq() as u64 * NSEC_PER_SEC;
+
+        // Replacing the timer cancels the previous one.
+        *timer = Some(HrTimer::start(expires, || {
+            log::error!("softdog: watchdog expired, rebooting");
+            crate::arch::power::reboot();
+        }));
+    }
+}
+
+impl Watchdog for SoftDog {
+    fn identity(&self) -> &'static str {
+        "Software Watchdog"
+    }
+
+    fn timeout_range(&self) -> RangeInclusive<u32> {
+        1..=65535
+    }
+
+    fn start(&self) {
+        self.arm(&mut self.timer.lock_irq());
+    }
+
+    fn stop(&self) {
+        self.timer.lock_irq().take();
+    }
+
+    fn ping(&self) {
+        let mut timer = self.timer.lock_irq();
+
+        if timer.is_some() {
+            self.arm(&mut timer);
+        }
+    }
+
+    fn set_timeout(&self, timeout: u32) {
+        *self.timeout.lock_irq() = timeout;
+        self.ping();
+    }
+
+    fn time_left(&self) -> Option<u32> {
+        let timer = self.timer.lock_irq();
+        let expires = timer.as_ref()?.expires();
+
+        Some((expires.saturating_sub(hrtimer::now()) / NSEC_PER_SEC) as u32)
+    }
+}
+
+fn init() {
+    if super::is_registered() {
+        return;
+    }
+
+    super::register(Arc::new(SoftDog {
+        timeout: Mutex::new(0),
+        timer: Mutex::new(None),
+    }));
+}
+
+// NOTE: Registered after the PCI drivers, so that a hardware watchdog takes precedence.
+crate::module_init!(init, ModuleType::Other);
@@ -0,0 +1,320 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! UEFI runtime services.
+//!
+//! The runtime services stay available after the bootloader exited the boot services. The kernel
+//! only uses the variable services, which give access to the firmware settings and the boot
+//! entries (see [`crate::fs::efivarfs`]). The runtime services are called through the
+//! architecture, which keeps their memory mapped (see `arch::efi`).
+//!
+//! ## Notes
+//! * <https://uefi.org/specs/UEFI/2.10/08_Services_Runtime_Services.html>
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::fmt;
+
+use crate::arch;
+
+/// The data is appended to the variable instead of replacing it.
+pub const VARIABLE_APPEND_WRITE: u32 = 0x40;
+
+/// Signature of the EFI system table (`IBI SYST`).
+pub const SYSTEM_TABLE_SIGNATURE: u64 = 0x5453_5953_2049_4249;
+
+/// Set in the status codes that are errors.
+const ERROR_BIT: usize = 1 << (usize::BITS - 1);
+
+const STATUS_INVALID_PARAMETER: usize = ERROR_BIT | 2;
+const STATUS_UNSUPPORTED: usize = ERROR_BIT | 3;
+const STATUS_BUFFER_TOO_SMALL: usize = ERROR_BIT | 5;
+const STATUS_DEVICE_ERROR: usize = ERROR_BIT | 7;
+const STATUS_WRITE_PROTECTED: usize = ERROR_BIT | 8;
+const STATUS_OUT_OF_RESOURCES: usize = ERROR_BIT | 9;
+const STATUS_NOT_FOUND: usize = ERROR_BIT | 14;
+const STATUS_SECURITY_VIOLATION: usize = ERROR_BIT | 26;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EfiError {
+    /// The runtime services (or the requested service) are not available.
+    Unsupported,
+    InvalidParameter,
+    /// The provided buffer is too small; the required size is returned by the service.
+    BufferTooSmall,
+    DeviceError,
+    WriteProtected,
+    OutOfResources,
+    NotFound,
+    SecurityViolation,
+    /// Any other error status.
+    Other(usize),
+}
+
+impl EfiError {
+    fn from_status(status: usize) -> Result<(), Self> {
+        match status {
+            // Warnings are not errors.
+            status if status & ERROR_BIT == 0 => Ok(()),
+
+            STATUS_INVALID_PARAMETER => Err(Self::InvalidParameter),
+            STATUS_UNSUPPORTED => Err(Self::Unsupported),
+            STATUS_BUFFER_TOO_SMALL => Err(Self::BufferTooSmall),
+            STATUS_DEVICE_ERROR => Err(Self::DeviceError),
+            STATUS_WRITE_PROTECTED => Err(Self::WriteProtected),
+            STATUS_OUT_OF_RESOURCES => Err(Self::OutOfResources),
+            STATUS_NOT_FOUND => Err(Self::NotFound),
+            STATUS_SECURITY_VIOLATION => Err(Self::SecurityViolation),
+            status => Err(Self::Other(status & !ERROR_BIT)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(C)]
+pub struct Guid {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8],
+}
+
+impl Guid {
+    /// Parses a GUID in the registry format (`xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`).
+    pub fn parse(string: &str) -> Option<Self> {
+        let bytes = string.as_bytes();
+
+        if bytes.len() != 36 || [8, 13, 18, 23].iter().any(|&i| bytes[i] != b'-') {
+            return None;
+        }
+
+        let hex = |range: core::ops::Range<usize>| {
+            let digits = string.get(range)?;
+
+            if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            u64::from_str_radix(digits, 16).ok()
+        };
+
+        let mut data4 = [0; 8];
+        data4[0] = hex(19..21)? as u8;
+        data4[1] = hex(21..23)? as u8;
+
+        for (i, byte) in data4[2..].iter_mut().enumerate() {
+            *byte = hex(24 + i * 2..26 + i * 2)? as u8;
+        }
+
+        Some(Self {
+            data1: hex(0..8)? as u32,
+            data2: hex(9..13)? as u16,
+            data3: hex(14..18)? as u16,
+            data4,
+        })
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            self.data1, self.data2, self.data3, self.data4[0], self.data4[1]
+        )?;
+
+        for byte in &self.data4[2..] {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct TableHeader {
+    pub signature: u64,
+    pub revision: u32,
+    pub header_size: u32,
+    pub crc32: u32,
+    pub reserved: u32,
+}
+
+/// The EFI system table. All of the pointers are physical addresses.
+#[derive(Debug)]
+#[repr(C)]
+pub struct SystemTable {
+    pub header: TableHeader,
+    pub firmware_vendor: u64,
+    pub firmware_revision: u32,
+    pub console_in_handle: u64,
+    pub console_in: u64,
+    pub console_out_handle: u64,
+    pub console_out: u64,
+    pub standard_error_handle: u64,
+    pub standard_error: u64,
+    pub runtime_services: u64,
+    pub boot_services: u64,
+    pub number_of_table_entries: usize,
+    pub configuration_table: u64,
+}
+
+type GetVariable = unsafe extern "efiapi" fn(
+    name: *const u16,
+    guid: *const Guid,
+    attributes: *mut u32,
+    size: *mut usize,
+    data: *mut u8,
+) -> usize;
+
+type GetNextVariableName =
+    unsafe extern "efiapi" fn(size: *mut usize, name: *mut u16, guid: *mut Guid) -> usize;
+
+type SetVariable = unsafe extern "efiapi" fn(
+    name: *const u16,
+    guid: *const Guid,
+    attributes: u32,
+    size: usize,
+    data: *const u8,
+) -> usize;
+
+/// The EFI runtime services table. Only the variable services are used, the other services are
+/// left as addresses.
+#[repr(C)]
+pub struct RuntimeServices {
+    pub header: TableHeader,
+    get_time: u64,
+    set_time: u64,
+    get_wakeup_time: u64,
+    set_wakeup_time: u64,
+    set_virtual_address_map: u64,
+    convert_pointer: u64,
+    get_variable: GetVariable,
+    get_next_variable_name: GetNextVariableName,
+    set_variable: SetVariable,
+    get_next_high_monotonic_count: u64,
+    reset_system: u64,
+    update_capsule: u64,
+    query_capsule_capabilities: u64,
+    query_variable_info: u64,
+}
+
+/// Returns whether the UEFI runtime services are available.
+pub fn is_available() -> bool {
+    arch::efi::is_available()
+}
+
+fn call(f: impl FnOnce(&RuntimeServices) -> usize) -> Result<(), EfiError> {
+    let status = arch::efi::with_runtime_services(f).ok_or(EfiError::Unsupported)?;
+    EfiError::from_status(status)
+}
+
+/// Encodes the provided variable name as a NUL terminated UCS-2 string.
+pub fn encode_name(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(core::iter::once(0)).collect()
+}
+
+/// Decodes the provided (optionally NUL terminated) UCS-2 variable name.
+pub fn decode_name(name: &[u16]) -> String {
+    let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+    char::decode_utf16(name[..end].iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Returns the attributes and the data of the variable with the provided (NUL terminated) name.
+pub fn get_variable(name: &[u16], guid: &Guid) -> Result<(u32, Vec<u8>), EfiError> {
+    let mut attributes = 0;
+    let mut data = Vec::new();
+
+    loop {
+        let mut size = data.len();
+
+        let result = call(|rt| unsafe {
+            (rt.get_variable)(
+                name.as_ptr(),
+                guid,
+                &mut attributes,
+                &mut size,
+                data.as_mut_ptr(),
+            )
+        });
+
+        match result {
+            Ok(()) => {
+                data.truncate(size);
+                return Ok((attributes, data));
+            }
+
+            // NOTE: The loop is repeated as the variable might grow after the size is queried.
+            Err(EfiError::BufferTooSmall) => data.resize(size, 0),
+
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Sets the variable with the provided (NUL terminated) name. The variable is deleted if `data`
+/// is empty, unless [`VARIABLE_APPEND_WRITE`] is set.
+pub fn set_variable(
+    name: &[u16],
+    guid: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), EfiError> {
+    call(|rt| unsafe {
+        (rt.set_variable)(name.as_ptr(), guid, attributes, data.len(), data.as_ptr())
+    })
+}
+
+/// Returns the (NUL terminated) names and the vendor GUIDs of all of the variables.
+pub fn variables() -> Result<Vec<(Vec<u16>, Guid)>, EfiError> {
+    let mut variables = Vec::new();
+
+    // The enumeration starts with an empty name.
+    let mut name = alloc::vec![0u16; 64];
+    let mut guid = Guid::default();
+
+    loop {
+        // NOTE: The size is in bytes.
+        let mut size = name.len() * core::mem::size_of::<u16>();
+
+        let result = call(|rt| unsafe {
+            (rt.get_next_variable_name)(&mut size, name.as_mut_ptr(), &mut guid)
+        });
+
+        match result {
+            Ok(()) => {
+                let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+                let mut entry = name[..end].to_vec();
+                entry.push(0);
+                variables.push((entry, guid));
+            }
+
+            Err(EfiError::BufferTooSmall) => {
+                name.resize(size.div_ceil(core::mem::size_of::<u16>()), 0);
+            }
+
+            Err(EfiError::NotFound) => return Ok(variables),
+            Err(error) => return Err(error),
+        }
+    }
+}
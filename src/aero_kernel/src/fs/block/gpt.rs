@@ -16,6 +16,7 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::fs::block::BlockDeviceInterface;
+use crate::utils::uuid::Uuid;
 
 use super::BlockDevice;
 use core::mem::MaybeUninit;
@@ -66,6 +67,21 @@ impl GptGuid {
     };
 }
 
+impl From<GptGuid> for Uuid {
+    fn from(guid: GptGuid) -> Self {
+        // The first three fields are stored in little-endian, but written in big-endian.
+        let mut bytes = [0; 16];
+
+        bytes[0..4].copy_from_slice(&guid.a.to_be_bytes());
+        bytes[4..6].copy_from_slice(&guid.b.to_be_bytes());
+        bytes[6..8].copy_from_slice(&guid.c.to_be_bytes());
+        bytes[8..10].copy_from_slice(&guid.d);
+        bytes[10..16].copy_from_slice(&guid.e);
+
+        Uuid(bytes)
+    }
+}
+
 const_assert_eq!(core::mem::size_of::<GptGuid>(), 16);
 
 #[derive(Debug)]
@@ -88,6 +104,11 @@ impl GptEntry {
         self.last_lba - self.first_lba
     }
 
+    /// Returns the GUID that uniquely identifies the partition (`PARTUUID`).
+    pub fn unique_guid(&self) -> Uuid {
+        self.unique_guid.into()
+    }
+
     pub fn partition_name(&self) -> String {
         let mut result = String::new();
 
@@ -124,9 +145,7 @@ impl Gpt {
         // Get the GPT header.
         let mut header = Box::<GptTableHeader>::new_uninit();
 
-        controller
-            .read_block(1, header.as_bytes_mut())
-            .expect("gpt: failed to read first sector");
+        controller.read_block(1, header.as_bytes_mut())?;
 
         // SAFETY: The buffer is initialized above.
         let header = unsafe { header.assume_init() };
@@ -135,17 +154,21 @@ impl Gpt {
             return None;
         }
 
+        // The partition table can be rewritten by userland (see `BLKRRPART`), so it is not
+        // trusted.
         let entry_size = header.entry_size as usize;
-        assert_eq!(entry_size, core::mem::size_of::<GptEntry>());
+
+        if entry_size != core::mem::size_of::<GptEntry>() {
+            log::warn!("gpt: unsupported partition entry size {entry_size}");
+            return None;
+        }
 
         let mut entry_list = Box::<[GptEntry]>::new_uninit_slice(header.num_entries as usize);
 
-        controller
-            .read_block(
-                header.starting_lba as _,
-                MaybeUninit::slice_as_bytes_mut(&mut entry_list),
-            )
-            .expect("gpt: failed to read entry list");
+        controller.read_block(
+            header.starting_lba as _,
+            MaybeUninit::slice_as_bytes_mut(&mut entry_list),
+        )?;
 
         // SAFETY: The entries list is initialized above.
         let entries = unsafe { entry_list.assume_init() };
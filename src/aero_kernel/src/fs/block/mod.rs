@@ -16,28 +16,42 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 mod gpt;
+mod sg;
+mod stat;
+mod sysfs;
 
 use gpt::Gpt;
+pub use sg::{AtaProtocol, AtaTaskfile};
+use stat::{DiskStats, Op};
 
 use core::mem::MaybeUninit;
-use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::ops::{Deref, DerefMut, Range};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 
-use crate::fs::devfs::install_device;
-use crate::fs::{FileSystem, Result};
+use aero_syscall::prelude::*;
+use aero_syscall::OpenFlags;
 
+use crate::cmdline::{self, RootSpec};
+use crate::fs::devfs::{install_device, uninstall_device};
+use crate::fs::{FileSystem, FileSystemError, Result};
+
+use crate::fs::btrfs::Btrfs;
 use crate::fs::ext2::Ext2;
 use crate::mem::paging::*;
+use crate::mem::swap;
 use crate::mem::AddressSpace;
+use crate::userland::scheduler::hrtimer;
 use crate::utils::sync::Mutex;
+use crate::utils::uuid::Uuid;
 
-use super::cache::{Cache, CacheArc, CacheItem, Cacheable};
-use super::devfs::{alloc_device_marker, Device};
-use super::inode::INodeInterface;
+use super::cache::{Cache, CacheArc, CacheItem, Cacheable, DirCacheItem, INodeCacheItem};
+use super::devfs::{alloc_device_marker, Device, DeviceNumber};
+use super::file_table::FileHandle;
+use super::inode::{DirEntry, INodeInterface, Metadata};
 
 type PageCacheKey = (usize, usize); // (owner ptr, index)
 pub type PageCacheItem = CacheArc<CacheItem<PageCacheKey, CachedPage>>;
@@ -56,13 +70,20 @@ pub struct CachedPage {
 }
 
 impl CachedPage {
-    fn new(owner: Weak<dyn CachedAccess>, offset: usize) -> Self {
+    fn new(owner: Weak<dyn CachedAccess>, offset: usize) -> Result<Self> {
         let k = Self {
             owner,
             offset,
             page: FRAME_ALLOCATOR
                 .allocate_frame()
-                .expect("page_cache: out of memory"),
+                .or_else(|| {
+                    // Evict some clean pages from the page cache, shrink the kernel object
+                    // caches and try again.
+                    shrink_page_cache(swap::RECLAIM_BATCH);
+                    shrinker::shrink_all(swap::RECLAIM_BATCH);
+                    FRAME_ALLOCATOR.allocate_frame()
+                })
+                .ok_or(FileSystemError::OutOfMemory)?,
             dirty: AtomicBool::new(false),
             dirty_mappings: Mutex::new(Vec::new()),
         };
@@ -71,7 +92,7 @@ impl CachedPage {
         // will unmap this page which will decrease the refcnt to 0 and deallocate it.
         get_vm_frames().unwrap()[k.page.start_address().as_u64() as usize / 4096usize]
             .inc_ref_count();
-        k
+        Ok(k)
     }
 
     fn data_mut(&self) -> &mut [MaybeUninit<u8>] {
@@ -103,6 +124,8 @@ impl CachedPage {
         self.dirty.load(Ordering::SeqCst)
     }
 
+    /// Marks the page dirty, so it is written back by the periodic writeback (see
+    /// [`crate::fs::writeback`]).
     pub fn mark_dirty(&self) {
         self.dirty.store(true, Ordering::SeqCst);
     }
@@ -112,7 +135,9 @@ impl CachedPage {
     }
 
     fn sync(&self) {
-        if !self.is_dirty() {
+        // Clear the flag before writing the page back, so a write racing with the writeback
+        // marks the page dirty again.
+        if !self.dirty.swap(false, Ordering::SeqCst) {
             return;
         }
 
@@ -129,14 +154,21 @@ impl CachedPage {
                 .1
                 .flush();
         }
-
-        self.dirty.store(false, Ordering::SeqCst);
     }
 }
 
 impl Drop for CachedPage {
     fn drop(&mut self) {
-        self.sync()
+        self.sync();
+
+        // Drop the reference taken in `CachedPage::new`. The frame is deallocated if it is
+        // not mapped anywhere else.
+        let vm_frame = self.page.start_address().as_vm_frame().unwrap();
+        vm_frame.dec_ref_count();
+
+        if vm_frame.ref_count() == 0 {
+            FRAME_ALLOCATOR.deallocate_frame(self.page);
+        }
     }
 }
 
@@ -159,15 +191,21 @@ impl Cache<PageCacheKey, CachedPage> {
     /// * `device` - The device to get the page from.
     /// * `offset` - The offset in bytes to the data. This will be rounded down to the nearest page
     ///   boundary.
-    pub fn get_page(&self, device: &Weak<dyn CachedAccess>, offset: usize) -> PageCacheItem {
+    ///
+    /// Returns [`FileSystemError::OutOfMemory`] if no frame could be allocated for the page.
+    pub fn get_page(
+        &self,
+        device: &Weak<dyn CachedAccess>,
+        offset: usize,
+    ) -> Result<PageCacheItem> {
         let cache_offset = offset / Size4KiB::SIZE as usize;
         let cache_key = CachedPage::make_key(device, cache_offset);
 
         if let Some(page) = PAGE_CACHE.get(cache_key) {
-            return page;
+            return Ok(page);
         }
 
-        let page = CachedPage::new(device.clone(), cache_offset);
+        let page = CachedPage::new(device.clone(), cache_offset)?;
         let device = device.upgrade().expect("page_cache: device dropped");
 
         let aligned_offset = align_down(offset as u64, Size4KiB::SIZE) as usize;
@@ -175,10 +213,63 @@ impl Cache<PageCacheKey, CachedPage> {
             .read_direct(aligned_offset, page.page())
             .expect("page_cache: failed to read block");
 
-        PAGE_CACHE.make_item_cached(page)
+        Ok(PAGE_CACHE.make_item_cached(page))
+    }
+
+    /// Writes back the dirty pages of `device`.
+    pub fn sync(&self, device: &Weak<dyn CachedAccess>) {
+        let owner = CachedPage::make_key(device, 0).0;
+
+        for page in self.items(|page| page.cache_key().0 == owner) {
+            page.sync();
+        }
+    }
+
+    /// Writes back every dirty page whose owner is still around.
+    pub fn sync_all(&self) {
+        for page in self.items(|page| page.is_dirty() && page.owner.strong_count() != 0) {
+            page.sync();
+        }
+    }
+
+    /// Reads the pages of `device` overlapping the byte `range` into the page cache, if they
+    /// are not cached already. Stops early if the system is out of memory.
+    pub fn readahead(&self, device: &Weak<dyn CachedAccess>, range: Range<usize>) {
+        let start = align_down(range.start as u64, Size4KiB::SIZE) as usize;
+
+        for offset in (start..range.end).step_by(Size4KiB::SIZE as usize) {
+            if self.get_page(device, offset).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Evicts the pages of `device` overlapping the byte `range`, that are not in use, from the
+    /// page cache. Dirty pages are written back before they are evicted. Returns the number of
+    /// evicted pages.
+    pub fn invalidate(&self, device: &Weak<dyn CachedAccess>, range: Range<usize>) -> usize {
+        let owner = CachedPage::make_key(device, 0).0;
+        let page_size = Size4KiB::SIZE as usize;
+        let pages = range.start / page_size..range.end.div_ceil(page_size);
+
+        self.shrink(usize::MAX, |page| {
+            let (page_owner, index) = page.cache_key();
+            let vm_frame = page.data_addr().as_vm_frame().unwrap();
+
+            page_owner == owner && pages.contains(&index) && vm_frame.ref_count() == 1
+        })
     }
 }
 
+/// Evicts up to `count` clean pages, that are not mapped into any address space, from the
+/// page cache. Returns the number of freed frames.
+pub fn shrink_page_cache(count: usize) -> usize {
+    PAGE_CACHE.shrink(count, |page| {
+        let vm_frame = page.data_addr().as_vm_frame().unwrap();
+        !page.is_dirty() && vm_frame.ref_count() == 1
+    })
+}
+
 // TODO: cache hit miss stats
 
 pub struct DirtyRef<T: Sized> {
@@ -187,16 +278,16 @@ pub struct DirtyRef<T: Sized> {
 }
 
 impl<T> DirtyRef<T> {
-    pub fn new(device: &Weak<dyn CachedAccess>, offset: usize) -> Self {
-        let cache = PAGE_CACHE.get_page(device, offset);
+    pub fn new(device: &Weak<dyn CachedAccess>, offset: usize) -> Result<Self> {
+        let cache = PAGE_CACHE.get_page(device, offset)?;
 
         let ptr_offset = offset % Size4KiB::SIZE as usize;
         let ptr = &cache.data_mut()[ptr_offset..ptr_offset + core::mem::size_of::<T>()];
 
-        Self {
+        Ok(Self {
             ptr: ptr.as_ptr() as *mut T,
             cache,
-        }
+        })
     }
 }
 
@@ -220,12 +311,34 @@ unsafe impl<T> Send for DirtyRef<T> {}
 
 pub trait BlockDeviceInterface: Send + Sync {
     fn block_size(&self) -> usize;
+    /// Returns the capacity of the device in blocks.
+    fn block_count(&self) -> usize;
 
     fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize>;
     fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize>;
 
     fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize>;
     fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize>;
+
+    /// Flushes the volatile write cache of the device, so the data written so far reaches
+    /// stable media. Only devices with a write cache have to implement this.
+    fn flush(&self) -> Option<()> {
+        Some(())
+    }
+
+    /// Issues the ATA command in `taskfile`, transferring `data` as given by `protocol`, and
+    /// stores the output registers of the device in `taskfile`. Returns `None` if ATA commands
+    /// can not be passed through to the device and `Some(false)` if the command failed or did
+    /// not complete within `timeout` nanoseconds.
+    fn ata_passthrough(
+        &self,
+        _taskfile: &mut AtaTaskfile,
+        _protocol: AtaProtocol,
+        _data: &mut [u8],
+        _timeout: u64,
+    ) -> Option<bool> {
+        None
+    }
 }
 
 pub trait CachedAccess: Send + Sync {
@@ -238,7 +351,7 @@ pub trait CachedAccess: Send + Sync {
         let mut loc = 0;
 
         while loc < dest.len() {
-            let page = PAGE_CACHE.get_page(&self.sref(), offset);
+            let page = PAGE_CACHE.get_page(&self.sref(), offset).ok()?;
 
             let page_offset = offset % Size4KiB::SIZE as usize;
             let size = core::cmp::min(Size4KiB::SIZE as usize - page_offset, dest.len() - loc);
@@ -246,8 +359,6 @@ pub trait CachedAccess: Send + Sync {
             let data = &page.data_mut()[page_offset..page_offset + size];
             dest[loc..loc + size].copy_from_slice(data);
 
-            core::mem::forget(page);
-
             loc += size;
             offset = align_down(offset as u64 + Size4KiB::SIZE, Size4KiB::SIZE) as usize;
         }
@@ -260,7 +371,8 @@ pub trait CachedAccess: Send + Sync {
     ///
     /// ## Notes
     ///
-    /// * This function does **not** sync the written data to the disk.
+    /// * This function does **not** sync the written data to the disk. The dirty pages are
+    ///   written back by the periodic writeback (see [`crate::fs::writeback`]).
     fn write(&self, mut offset: usize, buffer: &[u8]) -> Option<usize> {
         let mut loc = 0;
 
@@ -268,7 +380,7 @@ pub trait CachedAccess: Send + Sync {
             // TODO: If it is not found in the page cache, then, when the write perfectly falls on
             // page size boundaries, the page is not even read from disk, but allocated and
             // immediately marked dirty.
-            let page = PAGE_CACHE.get_page(&self.sref(), offset);
+            let page = PAGE_CACHE.get_page(&self.sref(), offset).ok()?;
 
             let page_offset = offset % Size4KiB::SIZE as usize;
             let size = core::cmp::min(Size4KiB::SIZE as usize - page_offset, buffer.len() - loc);
@@ -279,7 +391,6 @@ pub trait CachedAccess: Send + Sync {
             );
 
             page.mark_dirty();
-            page.sync();
 
             loc += size;
             offset = align_down(offset as u64 + Size4KiB::SIZE, Size4KiB::SIZE) as usize;
@@ -302,19 +413,120 @@ pub fn install_block_device(dev: Arc<BlockDevice>) -> Result<()> {
     Ok(())
 }
 
+static DISK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocates the name of a new disk, named like SCSI disks on Linux (which also covers SATA and
+/// USB mass storage disks): `sda` to `sdz`, followed by `sdaa` and so on.
+pub fn alloc_disk_name() -> String {
+    let mut index = DISK_COUNT.fetch_add(1, Ordering::SeqCst);
+    let mut suffix = Vec::new();
+
+    loop {
+        suffix.push(b'a' + (index % 26) as u8);
+
+        if index < 26 {
+            break;
+        }
+
+        index = index / 26 - 1;
+    }
+
+    suffix.reverse();
+    alloc::format!("sd{}", core::str::from_utf8(&suffix).unwrap())
+}
+
+/// Removes the provided block `device` from the filesystem.
+fn uninstall_block_device(dev: &BlockDevice) -> Result<()> {
+    BLOCK_DEVS.lock().remove(&dev.id);
+    uninstall_device(dev)?;
+
+    log::debug!("block: uninstalled block device {}", dev.name());
+    Ok(())
+}
+
+/// Returns the contents of `/proc/diskstats`: the I/O statistics of every block device, preceded
+/// by its device number and name.
+pub fn diskstats() -> String {
+    BLOCK_DEVS
+        .lock()
+        .values()
+        .map(|device| {
+            alloc::format!(
+                "{:4} {:7} {} {}\n",
+                BLOCK_MAJOR,
+                device.minor,
+                device.name,
+                device.stats
+            )
+        })
+        .collect()
+}
+
+/// Returns the block device with the provided `name`.
+pub fn block_device_by_name(name: &str) -> Option<Arc<BlockDevice>> {
+    BLOCK_DEVS
+        .lock()
+        .values()
+        .find(|device| device.name == name)
+        .cloned()
+}
+
+/// Block devices are numbered like Linux's extended block devices (`BLOCK_EXT_MAJOR`), with
+/// minor numbers allocated in registration order.
+const BLOCK_MAJOR: u32 = 259;
+static BLOCK_MINOR: AtomicU32 = AtomicU32::new(0);
+
+/// An exclusive claim on a block device, held by the filesystem mounted from it or by the file
+/// that it was opened as with `O_EXCL` (see [`BlockDevice::claim`]). The claim is released when
+/// dropped.
+pub struct BlockDeviceClaim(Arc<BlockDevice>);
+
+impl Drop for BlockDeviceClaim {
+    fn drop(&mut self) {
+        self.0.claimed.store(false, Ordering::SeqCst);
+    }
+}
+
 pub struct BlockDevice {
     id: usize,
+    minor: u32,
     name: String,
     dev: Arc<dyn BlockDeviceInterface>,
+    /// The whole disk, if this device is a partition.
+    parent: Option<Weak<BlockDevice>>,
+    /// The unique GUID of the partition in the partition table (`PARTUUID`), if this device is
+    /// a partition.
+    part_uuid: Option<Uuid>,
+    /// The partitions of this device, if it is a whole disk (see
+    /// [`BlockDevice::scan_partitions`]).
+    partitions: Mutex<Vec<Arc<BlockDevice>>>,
+    claimed: AtomicBool,
+    /// The I/O statistics of the device; the ones of a disk include its partitions.
+    stats: DiskStats,
     sref: Weak<BlockDevice>,
 }
 
 impl BlockDevice {
     pub fn new(name: String, imp: Arc<dyn BlockDeviceInterface>) -> Arc<BlockDevice> {
+        Self::make(name, imp, None, None)
+    }
+
+    fn make(
+        name: String,
+        imp: Arc<dyn BlockDeviceInterface>,
+        parent: Option<Weak<BlockDevice>>,
+        part_uuid: Option<Uuid>,
+    ) -> Arc<BlockDevice> {
         Arc::new_cyclic(|sref| BlockDevice {
             id: alloc_device_marker(),
+            minor: BLOCK_MINOR.fetch_add(1, Ordering::SeqCst),
             name,
             dev: imp,
+            parent,
+            part_uuid,
+            partitions: Mutex::new(Vec::new()),
+            claimed: AtomicBool::new(false),
+            stats: DiskStats::new(),
             sref: sref.clone(),
         })
     }
@@ -322,6 +534,183 @@ impl BlockDevice {
     pub fn name(&self) -> String {
         self.name.clone()
     }
+
+    /// Returns the whole disk that this device is a part of; the device itself if it is not a
+    /// partition.
+    fn disk(&self) -> Arc<BlockDevice> {
+        self.parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .unwrap_or_else(|| self.sref.upgrade().unwrap())
+    }
+
+    /// Claims the device exclusively. Returns [`FileSystemError::Busy`] if the device is busy
+    /// (see [`BlockDevice::is_busy`]).
+    pub fn claim(&self) -> Result<BlockDeviceClaim> {
+        let disk = self.disk();
+        // The partition list of the disk serializes the claims of the disk and its partitions.
+        let partitions = disk.partitions.lock();
+
+        if self.is_busy_locked(&disk, &partitions) {
+            return Err(FileSystemError::Busy);
+        }
+
+        self.claimed.store(true, Ordering::SeqCst);
+        Ok(BlockDeviceClaim(self.sref.upgrade().unwrap()))
+    }
+
+    /// Returns whether the device can not be claimed, as it is claimed already or the claim
+    /// would overlap with a claim of another device; the whole disk if this device is a
+    /// partition or any of the partitions if it is a whole disk.
+    pub fn is_busy(&self) -> bool {
+        let disk = self.disk();
+        let partitions = disk.partitions.lock();
+
+        self.is_busy_locked(&disk, &partitions)
+    }
+
+    fn is_busy_locked(&self, disk: &BlockDevice, partitions: &[Arc<BlockDevice>]) -> bool {
+        if self.is_claimed() {
+            return true;
+        }
+
+        if self.parent.is_some() {
+            disk.is_claimed()
+        } else {
+            partitions.iter().any(|partition| partition.is_claimed())
+        }
+    }
+
+    pub fn is_claimed(&self) -> bool {
+        self.claimed.load(Ordering::SeqCst)
+    }
+
+    /// Returns the capacity of the device in bytes.
+    fn size(&self) -> usize {
+        self.block_count() * self.block_size()
+    }
+
+    pub fn partitions(&self) -> Vec<Arc<BlockDevice>> {
+        self.partitions.lock().clone()
+    }
+
+    /// Reads the partition table of this device and installs a block device for each of its
+    /// partitions.
+    fn scan_partitions(&self) -> Result<()> {
+        let Some(gpt) = Gpt::new(self) else {
+            return Ok(());
+        };
+
+        log::info!("block: found GPT on {}!", self.name());
+
+        let this = self.sref.upgrade().unwrap();
+        let mut partitions = Vec::new();
+
+        for (i, entry) in gpt
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.is_used())
+        {
+            let start = entry.start_lba() as usize;
+            let size = entry.size() as usize;
+            let part_uuid = entry.unique_guid();
+
+            log::info!(
+                "gpt: found partition (name=`{}`, partuuid={}, start={:#x}, size{:#x})!",
+                entry.partition_name(),
+                part_uuid,
+                start,
+                size
+            );
+
+            let name = alloc::format!("{}p{}", self.name(), i);
+            let partition_device = PartitionBlockDevice::new(start, size, this.clone());
+            let device = BlockDevice::make(
+                name,
+                partition_device,
+                Some(self.sref.clone()),
+                Some(part_uuid),
+            );
+
+            install_block_device(device.clone())?;
+            partitions.push(device);
+        }
+
+        self.partitions.lock().extend(partitions);
+        Ok(())
+    }
+
+    /// Re-reads the partition table of this device, replacing its partitions. Returns
+    /// [`FileSystemError::Busy`] if any of the partitions is claimed.
+    fn rescan_partitions(&self) -> Result<()> {
+        if self.parent.is_some() {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let partitions = self.partitions();
+
+        if partitions.iter().any(|partition| partition.is_claimed()) {
+            return Err(FileSystemError::Busy);
+        }
+
+        // Make sure that the new partition table has reached the disk.
+        self.flush_buffers();
+
+        for partition in partitions {
+            partition.flush_buffers();
+            uninstall_block_device(&partition)?;
+        }
+
+        self.partitions.lock().clear();
+        self.scan_partitions()
+    }
+
+    /// Evicts the pages of this device, that are not in use, from the page cache. Dirty pages are
+    /// written back before they are evicted; the ones that are in use are written back by the
+    /// periodic writeback.
+    fn flush_buffers(&self) {
+        PAGE_CACHE.invalidate(&self.sref(), 0..usize::MAX);
+    }
+
+    /// Writes back the dirty pages of this device and flushes the write cache of the disk, so
+    /// everything written before the barrier reaches stable media. Used by filesystems on
+    /// `fsync(2)`.
+    pub fn barrier(&self) -> Result<()> {
+        PAGE_CACHE.sync(&self.sref());
+        self.flush().ok_or(FileSystemError::Io)
+    }
+
+    /// Issues a request of `size` bytes at `sector` to the device, recording it in the
+    /// `block` tracepoints and in the statistics of the device. Requests to a partition are
+    /// passed on to its disk, so they are accounted to both.
+    fn request(
+        &self,
+        op: Op,
+        sector: usize,
+        size: usize,
+        f: impl FnOnce() -> Option<usize>,
+    ) -> Option<usize> {
+        let start = hrtimer::now();
+        crate::tracepoint!(BLOCK_RQ_ISSUE, "{} {op} {sector} + {size}", self.name);
+
+        self.stats.start(op, start);
+
+        let result = f();
+        let now = hrtimer::now();
+
+        self.stats.done(op, size, start, now);
+
+        crate::tracepoint!(
+            BLOCK_RQ_COMPLETE,
+            "{} {op} {sector} + {size} [{}] ({} ns)",
+            self.name,
+            if result.is_some() { 0 } else { -5 },
+            now - start
+        );
+
+        result
+    }
 }
 
 impl BlockDeviceInterface for BlockDevice {
@@ -329,20 +718,37 @@ impl BlockDeviceInterface for BlockDevice {
         self.dev.block_size()
     }
 
+    fn block_count(&self) -> usize {
+        self.dev.block_count()
+    }
+
     fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
-        self.dev.read_dma(sector, start, size)
+        self.request(Op::Read, sector, size, || {
+            self.dev.read_dma(sector, start, size)
+        })
     }
 
     fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
-        self.dev.write_dma(sector, start, size)
+        self.request(Op::Write, sector, size, || {
+            self.dev.write_dma(sector, start, size)
+        })
     }
 
     fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
-        self.dev.read_block(sector, dest)
+        self.request(Op::Read, sector, dest.len(), || {
+            self.dev.read_block(sector, dest)
+        })
     }
 
     fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
-        self.dev.write_block(sector, buf)
+        self.request(Op::Write, sector, buf.len(), || {
+            self.dev.write_block(sector, buf)
+        })
+    }
+
+    fn flush(&self) -> Option<()> {
+        self.request(Op::Flush, 0, 0, || self.dev.flush().map(|_| 0))
+            .map(|_| ())
     }
 }
 
@@ -352,7 +758,7 @@ impl CachedAccess for BlockDevice {
     }
 
     fn read_direct(&self, offset: usize, dest: PhysFrame) -> Option<usize> {
-        self.dev.read_dma(
+        self.read_dma(
             offset / self.dev.block_size(),
             dest.start_address(),
             Size4KiB::SIZE as _,
@@ -360,7 +766,7 @@ impl CachedAccess for BlockDevice {
     }
 
     fn write_direct(&self, offset: usize, src: PhysFrame) -> Option<usize> {
-        self.dev.write_dma(
+        self.write_dma(
             offset / self.dev.block_size(),
             src.start_address(),
             Size4KiB::SIZE as _,
@@ -368,7 +774,93 @@ impl CachedAccess for BlockDevice {
     }
 }
 
-impl INodeInterface for BlockDevice {}
+impl INodeInterface for BlockDevice {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let size = self.size();
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), size - offset);
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast::<MaybeUninit<u8>>(), count)
+        };
+
+        CachedAccess::read(self, offset, buffer).ok_or(FileSystemError::Io)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        let size = self.size();
+
+        if offset >= size {
+            return Err(FileSystemError::NoSpace);
+        }
+
+        let count = core::cmp::min(buffer.len(), size - offset);
+        CachedAccess::write(self, offset, &buffer[..count]).ok_or(FileSystemError::Io)
+    }
+
+    /// Opening the device with `O_EXCL` claims it (see [`BlockDevice::claim`]) until the file is
+    /// closed, which fails if the device is mounted.
+    fn open(&self, handle: Arc<FileHandle>) -> Result<Option<DirCacheItem>> {
+        if !handle.flags().contains(OpenFlags::O_EXCL) {
+            return Ok(None);
+        }
+
+        let inode = Arc::new(ExclusiveBlockDevice {
+            inode: handle.inode(),
+            claim: self.claim()?,
+            sg: sg::ExclusiveState::default(),
+        });
+
+        Ok(Some(DirEntry::from_inode(inode, handle.inode.name())))
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        match command {
+            BLKGETSIZE64 => {
+                *VirtAddr::new(arg as _).read_mut::<u64>()? = self.size() as u64;
+                Ok(0)
+            }
+
+            BLKSSZGET => {
+                *VirtAddr::new(arg as _).read_mut::<i32>()? = self.block_size() as i32;
+                Ok(0)
+            }
+
+            BLKFLSBUF => {
+                self.flush_buffers();
+                Ok(0)
+            }
+
+            BLKRRPART => {
+                self.rescan_partitions()?;
+                Ok(0)
+            }
+
+            SG_IO => sg::sg_io(self, arg, None),
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
+    fn readahead(&self, offset: usize, len: usize) -> Result<()> {
+        let end = core::cmp::min(offset.saturating_add(len), self.size());
+
+        PAGE_CACHE.readahead(&self.sref(), offset..end);
+        Ok(())
+    }
+
+    fn drop_cache(&self, offset: usize, len: usize) -> Result<()> {
+        PAGE_CACHE.invalidate(&self.sref(), offset..offset.saturating_add(len));
+        Ok(())
+    }
+
+    fn fsync(&self) -> Result<()> {
+        self.barrier()
+    }
+}
 
 impl Device for BlockDevice {
     fn device_marker(&self) -> usize {
@@ -382,6 +874,57 @@ impl Device for BlockDevice {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         self.sref.upgrade().unwrap()
     }
+
+    fn device_number(&self) -> Option<DeviceNumber> {
+        Some(DeviceNumber::Block(BLOCK_MAJOR, self.minor))
+    }
+}
+
+/// A block device opened with `O_EXCL`. The device is claimed as long as the file, or any of
+/// its duplicates, is open.
+struct ExclusiveBlockDevice {
+    /// The device node that was opened.
+    inode: INodeCacheItem,
+    claim: BlockDeviceClaim,
+    /// The state of `SG_IO`, which allows the security commands on exclusively opened devices.
+    sg: sg::ExclusiveState,
+}
+
+impl INodeInterface for ExclusiveBlockDevice {
+    fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+
+    fn stat(&self) -> Result<aero_syscall::Stat> {
+        self.inode.stat()
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        self.inode.read_at(offset, buffer)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        self.inode.write_at(offset, buffer)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        match command {
+            SG_IO => sg::sg_io(&self.claim.0, arg, Some(&self.sg)),
+            _ => self.inode.ioctl(command, arg),
+        }
+    }
+
+    fn fsync(&self) -> Result<()> {
+        self.inode.fsync()
+    }
+
+    fn readahead(&self, offset: usize, len: usize) -> Result<()> {
+        self.inode.readahead(offset, len)
+    }
+
+    fn drop_cache(&self, offset: usize, len: usize) -> Result<()> {
+        self.inode.drop_cache(offset, len)
+    }
 }
 
 struct PartitionBlockDevice {
@@ -436,6 +979,25 @@ impl BlockDeviceInterface for PartitionBlockDevice {
     fn block_size(&self) -> usize {
         self.device.block_size()
     }
+
+    fn block_count(&self) -> usize {
+        self.size
+    }
+
+    fn flush(&self) -> Option<()> {
+        self.device.flush()
+    }
+}
+
+/// Returns whether the filesystem, with the provided `uuid` and `label`, on `device` is the
+/// root filesystem given on the kernel command line.
+fn is_root(root: &RootSpec, device: &BlockDevice, uuid: Uuid, label: &str) -> bool {
+    match root {
+        RootSpec::Uuid(root) => *root == uuid,
+        RootSpec::PartUuid(root) => device.part_uuid == Some(*root),
+        RootSpec::Label(root) => *root == label,
+        RootSpec::Device(name) => device.name == *name,
+    }
 }
 
 pub fn launch() -> Result<()> {
@@ -445,48 +1007,74 @@ pub fn launch() -> Result<()> {
         blocks_copy.push(device.clone());
     }
 
+    let root = cmdline::root();
+
     for block in blocks_copy {
-        if let Some(gpt) = Gpt::new(&block) {
-            log::info!("block: found GPT on {}!", block.name());
-
-            for (i, entry) in gpt
-                .entries()
-                .iter()
-                .enumerate()
-                .filter(|(_, e)| e.is_used())
-            {
-                let start = entry.start_lba() as usize;
-                let size = entry.size() as usize;
+        block.scan_partitions()?;
 
+        for device in block.partitions() {
+            // Check what filesystem is on this partition and mount it. Without `root=`, the
+            // first ext2 filesystem found is the root filesystem.
+            let filesystem: Arc<dyn FileSystem> = if let Some(ext2) = Ext2::new(device.clone()) {
                 log::info!(
-                    "gpt: found partition (name=`{}`, start={:#x}, size{:#x})!",
-                    entry.partition_name(),
-                    start,
-                    size
+                    "gpt: found ext2 filesystem (label=`{}`, uuid={}) on {}!",
+                    ext2.label(),
+                    ext2.uuid(),
+                    device.name()
                 );
 
-                let name = alloc::format!("{}p{}", block.name(), i);
-                let partition_device = PartitionBlockDevice::new(start, size, block.clone());
-                let device = BlockDevice::new(name, partition_device);
+                match root {
+                    Some(root) if !is_root(root, &device, ext2.uuid(), ext2.label()) => continue,
+                    _ => ext2,
+                }
+            } else if let Some(btrfs) = Btrfs::new(device.clone()) {
+                log::info!(
+                    "gpt: found btrfs filesystem (label=`{}`, uuid={}) on {}!",
+                    btrfs.label(),
+                    btrfs.uuid(),
+                    device.name()
+                );
 
-                install_block_device(device.clone())?;
+                // Only mounted on request (see `sys_mount`), unless it is the root filesystem.
+                match root {
+                    Some(root) if is_root(root, &device, btrfs.uuid(), btrfs.label()) => btrfs,
+                    _ => continue,
+                }
+            } else {
+                continue;
+            };
 
-                // Check what filesystem is on this partition and mount it.
-                if let Some(ext2) = Ext2::new(device.clone()) {
-                    log::info!("gpt: found ext2 filesystem on {}!", device.name());
+            if super::ROOT_FS.get().is_none() {
+                log::info!("block: using {} as the root filesystem", device.name());
 
-                    super::ROOT_FS.call_once(|| ext2.clone());
-                    super::ROOT_DIR.call_once(|| ext2.root_dir());
-                }
+                super::ROOT_FS.call_once(|| filesystem.clone());
+                super::ROOT_DIR.call_once(|| filesystem.root_dir());
             }
         }
     }
 
+    if let Some(root) = root.filter(|_| super::ROOT_FS.get().is_none()) {
+        log::error!("block: root filesystem {root} not found");
+        return Err(FileSystemError::EntryNotFound);
+    }
+
     super::devfs::init()?;
     log::info!("installed devfs");
 
     super::procfs::init()?;
     log::info!("installed procfs");
 
+    super::cgroupfs::init()?;
+    log::info!("installed cgroupfs");
+
+    super::tracefs::init()?;
+    log::info!("installed tracefs");
+
+    super::efivarfs::init()?;
+    log::info!("installed efivarfs");
+
+    sysfs::init()?;
+    log::info!("installed /sys/block");
+
     Ok(())
 }
@@ -0,0 +1,504 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! `SG_IO` passthrough of ATA commands, wrapped in the `ATA PASS-THROUGH` SCSI commands of the
+//! SCSI/ATA Translation (SAT) standard, so disk utilities like `hdparm` and `smartctl` can query
+//! the device.
+//!
+//! Only a whitelist of commands is passed through. The security commands can lock the device
+//! or erase all of its data, so they are only allowed on a device opened with `O_EXCL` and
+//! `SECURITY ERASE UNIT` has to be confirmed by issuing `SECURITY ERASE PREPARE` right before
+//! it on the same file.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::prelude::*;
+use bit_field::BitField;
+
+use crate::arch::time;
+use crate::fs::{FileSystemError, Result};
+use crate::mem::paging::VirtAddr;
+use crate::utils::{validate_slice, validate_slice_mut};
+
+use super::BlockDevice;
+
+const ATA_16: u8 = 0x85;
+const ATA_12: u8 = 0xa1;
+
+const ATA_CMD_IDENTIFY_DEVICE: u8 = 0xec;
+const ATA_CMD_IDENTIFY_PACKET_DEVICE: u8 = 0xa1;
+const ATA_CMD_CHECK_POWER_MODE: u8 = 0xe5;
+const ATA_CMD_SMART: u8 = 0xb0;
+const ATA_CMD_SECURITY_SET_PASSWORD: u8 = 0xf1;
+const ATA_CMD_SECURITY_UNLOCK: u8 = 0xf2;
+const ATA_CMD_SECURITY_ERASE_PREPARE: u8 = 0xf3;
+const ATA_CMD_SECURITY_ERASE_UNIT: u8 = 0xf4;
+const ATA_CMD_SECURITY_DISABLE_PASSWORD: u8 = 0xf6;
+
+// SMART subcommands, selected by the features register:
+const SMART_READ_DATA: u16 = 0xd0;
+const SMART_READ_THRESHOLDS: u16 = 0xd1;
+const SMART_READ_LOG: u16 = 0xd5;
+const SMART_ENABLE_OPERATIONS: u16 = 0xd8;
+const SMART_RETURN_STATUS: u16 = 0xda;
+
+/// SMART commands carry this signature in the LBA mid and high registers.
+const SMART_SIGNATURE: u64 = 0xc2_4f00;
+
+/// The error bit of the status register.
+const ATA_STATUS_ERR: u8 = 0x01;
+
+/// Passed through commands transfer whole 512 byte blocks.
+const BLOCK_SIZE: usize = 512;
+/// The largest transfer of a passed through command.
+const MAX_TRANSFER: usize = 0x10000;
+/// Used if the caller does not set a timeout, in milliseconds.
+const DEFAULT_TIMEOUT: u32 = 60_000;
+
+const SAM_STAT_CHECK_CONDITION: u8 = 0x02;
+/// `SAM_STAT_CHECK_CONDITION` shifted right by one, as reported in `masked_status`.
+const CHECK_CONDITION: u8 = 0x01;
+const DRIVER_SENSE: u16 = 0x08;
+
+const RECOVERED_ERROR: u8 = 0x01;
+const ABORTED_COMMAND: u8 = 0x0b;
+
+/// The data transfer protocol of a passed through ATA command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AtaProtocol {
+    NonData,
+    PioIn,
+    PioOut,
+}
+
+/// The registers of an ATA command. Once the command has completed, they are replaced with
+/// the output registers of the device.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct AtaTaskfile {
+    pub command: u8,
+    pub features: u16,
+    pub count: u16,
+    pub lba: u64,
+    pub device: u8,
+    /// The status register, only valid once the command has completed.
+    pub status: u8,
+    /// The error register, only valid once the command has completed.
+    pub error: u8,
+}
+
+/// The passthrough state of a device opened with `O_EXCL`.
+#[derive(Default)]
+pub(super) struct ExclusiveState {
+    /// Whether the last command passed through was a successful `SECURITY ERASE PREPARE`.
+    erase_prepared: AtomicBool,
+}
+
+/// A decoded `ATA PASS-THROUGH` command.
+#[derive(Debug, PartialEq)]
+struct Passthrough {
+    taskfile: AtaTaskfile,
+    protocol: AtaProtocol,
+    /// Whether the registers are 48 bits wide (the `EXTEND` bit).
+    extend: bool,
+    /// Whether the output registers are returned in the sense data even if the command
+    /// succeeded (the `CK_COND` bit).
+    check_condition: bool,
+}
+
+/// Decodes the `ATA PASS-THROUGH (12)` or `ATA PASS-THROUGH (16)` command in `cdb`. Returns
+/// `None` if it is neither or its protocol is not supported.
+fn decode(cdb: &[u8]) -> Option<Passthrough> {
+    let mut taskfile = AtaTaskfile::default();
+
+    let extend = match (*cdb.first()?, cdb.len()) {
+        (ATA_16, 16) => {
+            taskfile.features = u16::from_be_bytes([cdb[3], cdb[4]]);
+            taskfile.count = u16::from_be_bytes([cdb[5], cdb[6]]);
+            taskfile.lba =
+                u64::from_be_bytes([0, 0, cdb[11], cdb[9], cdb[7], cdb[12], cdb[10], cdb[8]]);
+            taskfile.device = cdb[13];
+            taskfile.command = cdb[14];
+
+            cdb[1].get_bit(0)
+        }
+
+        (ATA_12, 12) => {
+            taskfile.features = cdb[3] as u16;
+            taskfile.count = cdb[4] as u16;
+            taskfile.lba = u32::from_be_bytes([0, cdb[7], cdb[6], cdb[5]]) as u64;
+            taskfile.device = cdb[8];
+            taskfile.command = cdb[9];
+
+            false
+        }
+
+        _ => return None,
+    };
+
+    if !extend {
+        // The high bytes of the registers are only sent with 48-bit commands.
+        taskfile.features &= 0xff;
+        taskfile.count &= 0xff;
+        taskfile.lba &= 0xff_ffff;
+    }
+
+    let protocol = match cdb[1].get_bits(1..=4) {
+        3 => AtaProtocol::NonData,
+        4 => AtaProtocol::PioIn,
+        5 => AtaProtocol::PioOut,
+        _ => return None,
+    };
+
+    Some(Passthrough {
+        taskfile,
+        protocol,
+        extend,
+        check_condition: cdb[2].get_bit(5),
+    })
+}
+
+/// Returns the protocol of `taskfile` if its command may be passed through.
+fn whitelisted(taskfile: &AtaTaskfile) -> Option<AtaProtocol> {
+    match taskfile.command {
+        ATA_CMD_IDENTIFY_DEVICE | ATA_CMD_IDENTIFY_PACKET_DEVICE => Some(AtaProtocol::PioIn),
+        ATA_CMD_CHECK_POWER_MODE => Some(AtaProtocol::NonData),
+
+        ATA_CMD_SMART if taskfile.lba & 0xff_ff00 == SMART_SIGNATURE => match taskfile.features {
+            SMART_READ_DATA | SMART_READ_THRESHOLDS | SMART_READ_LOG => Some(AtaProtocol::PioIn),
+            SMART_ENABLE_OPERATIONS | SMART_RETURN_STATUS => Some(AtaProtocol::NonData),
+            _ => None,
+        },
+
+        ATA_CMD_SECURITY_SET_PASSWORD
+        | ATA_CMD_SECURITY_UNLOCK
+        | ATA_CMD_SECURITY_ERASE_UNIT
+        | ATA_CMD_SECURITY_DISABLE_PASSWORD => Some(AtaProtocol::PioOut),
+        ATA_CMD_SECURITY_ERASE_PREPARE => Some(AtaProtocol::NonData),
+
+        _ => None,
+    }
+}
+
+/// Checks whether `command` may be passed through; `exclusive` is the state of the file if the
+/// device was opened with `O_EXCL`.
+fn check(command: &Passthrough, exclusive: Option<&ExclusiveState>) -> Result<()> {
+    let protocol = whitelisted(&command.taskfile).ok_or(FileSystemError::PermissionDenied)?;
+
+    if protocol != command.protocol {
+        return Err(FileSystemError::InvalidArgument);
+    }
+
+    match command.taskfile.command {
+        ATA_CMD_SECURITY_ERASE_UNIT => match exclusive {
+            Some(state) if state.erase_prepared.load(Ordering::SeqCst) => Ok(()),
+            _ => Err(FileSystemError::PermissionDenied),
+        },
+
+        ATA_CMD_SECURITY_SET_PASSWORD
+        | ATA_CMD_SECURITY_UNLOCK
+        | ATA_CMD_SECURITY_ERASE_PREPARE
+        | ATA_CMD_SECURITY_DISABLE_PASSWORD
+            if exclusive.is_none() =>
+        {
+            Err(FileSystemError::PermissionDenied)
+        }
+
+        _ => Ok(()),
+    }
+}
+
+/// Builds the descriptor format sense data of `command`, with the ATA Status Return descriptor
+/// holding the output registers.
+fn sense_data(command: &Passthrough, failed: bool) -> [u8; 22] {
+    let taskfile = &command.taskfile;
+    let count = taskfile.count.to_be_bytes();
+    let lba = taskfile.lba.to_le_bytes();
+
+    [
+        0x72, // current error, descriptor format
+        if failed {
+            ABORTED_COMMAND
+        } else {
+            RECOVERED_ERROR
+        },
+        0x00, // ATA PASS THROUGH INFORMATION AVAILABLE
+        0x1d,
+        0,
+        0,
+        0,
+        14,   // additional sense length
+        0x09, // ATA Status Return descriptor
+        0x0c,
+        command.extend as u8,
+        taskfile.error,
+        count[0],
+        count[1],
+        lba[3],
+        lba[0],
+        lba[4],
+        lba[1],
+        lba[5],
+        lba[2],
+        taskfile.device,
+        taskfile.status,
+    ]
+}
+
+/// Handles the `SG_IO` ioctl on `device`, with `exclusive` being the state of the file if the
+/// device was opened with `O_EXCL`.
+pub(super) fn sg_io(
+    device: &BlockDevice,
+    arg: usize,
+    exclusive: Option<&ExclusiveState>,
+) -> Result<usize> {
+    let hdr = VirtAddr::new(arg as u64).read_mut::<SgIoHdr>()?;
+
+    if hdr.interface_id != b'S' as i32 || hdr.iovec_count != 0 {
+        return Err(FileSystemError::InvalidArgument);
+    }
+
+    let cdb = validate_slice(hdr.cmdp as *const u8, hdr.cmd_len as usize)?;
+    let mut command = decode(cdb).ok_or(FileSystemError::InvalidArgument)?;
+
+    check(&command, exclusive)?;
+
+    let len = hdr.dxfer_len as usize;
+    let valid = match command.protocol {
+        AtaProtocol::NonData => len == 0,
+        AtaProtocol::PioIn => hdr.dxfer_direction == SG_DXFER_FROM_DEV,
+        AtaProtocol::PioOut => hdr.dxfer_direction == SG_DXFER_TO_DEV,
+    };
+
+    if !valid || len % BLOCK_SIZE != 0 || len > MAX_TRANSFER {
+        return Err(FileSystemError::InvalidArgument);
+    }
+
+    let data = validate_slice_mut(hdr.dxferp as *mut u8, len)?;
+    let timeout = match hdr.timeout {
+        0 => DEFAULT_TIMEOUT,
+        timeout => timeout,
+    };
+
+    if command.taskfile.command == ATA_CMD_SECURITY_ERASE_UNIT {
+        log::warn!("sg: {}: erasing the device", device.name());
+    }
+
+    let start = time::get_uptime_ns();
+    let completed = device
+        .dev
+        .ata_passthrough(
+            &mut command.taskfile,
+            command.protocol,
+            data,
+            timeout as u64 * 1_000_000,
+        )
+        .ok_or(FileSystemError::NoTty)?;
+
+    if let Some(state) = exclusive {
+        let prepared = completed && command.taskfile.command == ATA_CMD_SECURITY_ERASE_PREPARE;
+        state.erase_prepared.store(prepared, Ordering::SeqCst);
+    }
+
+    // The command did not reach the device or timed out.
+    if !completed && command.taskfile.status & ATA_STATUS_ERR == 0 {
+        return Err(FileSystemError::Io);
+    }
+
+    hdr.duration = ((time::get_uptime_ns() - start) / 1_000_000) as u32;
+    hdr.resid = if completed { 0 } else { len as i32 };
+    hdr.msg_status = 0;
+    hdr.host_status = 0;
+
+    if !completed || command.check_condition {
+        let sense = sense_data(&command, !completed);
+        let sb_len = core::cmp::min(hdr.mx_sb_len as usize, sense.len());
+
+        validate_slice_mut(hdr.sbp as *mut u8, sb_len)?.copy_from_slice(&sense[..sb_len]);
+
+        hdr.status = SAM_STAT_CHECK_CONDITION;
+        hdr.masked_status = CHECK_CONDITION;
+        hdr.driver_status = DRIVER_SENSE;
+        hdr.sb_len_wr = sb_len as u8;
+        hdr.info = SG_INFO_CHECK;
+    } else {
+        hdr.status = 0;
+        hdr.masked_status = 0;
+        hdr.driver_status = 0;
+        hdr.sb_len_wr = 0;
+        hdr.info = 0;
+    }
+
+    Ok(0)
+}
+
+mod selftests {
+    use super::*;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    /// Builds an `ATA PASS-THROUGH (16)` command with the given protocol.
+    fn ata_16(protocol: u8, command: u8, features: u16, lba: u64) -> [u8; 16] {
+        let lba = lba.to_le_bytes();
+        let features = features.to_be_bytes();
+
+        [
+            ATA_16,
+            protocol << 1 | 1,
+            1 << 5,
+            features[0],
+            features[1],
+            0,
+            1,
+            lba[3],
+            lba[0],
+            lba[4],
+            lba[1],
+            lba[5],
+            lba[2],
+            0x40,
+            command,
+            0,
+        ]
+    }
+
+    fn decode_ata_16() -> selftest::Result {
+        let cdb = ata_16(3, ATA_CMD_SMART, SMART_RETURN_STATUS, 0x0123_45c2_4f00);
+        let command = decode(&cdb).unwrap();
+
+        selftest_assert_eq!(command.protocol, AtaProtocol::NonData);
+        selftest_assert!(command.extend && command.check_condition);
+        selftest_assert_eq!(command.taskfile.lba, 0x0123_45c2_4f00);
+        selftest_assert_eq!(command.taskfile.features, SMART_RETURN_STATUS);
+        selftest_assert_eq!(command.taskfile.count, 1);
+        selftest_assert_eq!(command.taskfile.device, 0x40);
+
+        // Truncated commands and unsupported protocols are rejected.
+        selftest_assert!(decode(&cdb[..12]).is_none());
+        selftest_assert!(decode(&ata_16(6, ATA_CMD_IDENTIFY_DEVICE, 0, 0)).is_none());
+
+        Ok(())
+    }
+
+    fn decode_ata_12() -> selftest::Result {
+        let cdb = [
+            ATA_12,
+            4 << 1,
+            0x0e,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            ATA_CMD_IDENTIFY_DEVICE,
+            0,
+            0,
+        ];
+        let command = decode(&cdb).unwrap();
+
+        selftest_assert_eq!(command.protocol, AtaProtocol::PioIn);
+        selftest_assert!(!command.extend && !command.check_condition);
+        selftest_assert_eq!(command.taskfile.command, ATA_CMD_IDENTIFY_DEVICE);
+        selftest_assert_eq!(command.taskfile.count, 1);
+
+        Ok(())
+    }
+
+    fn whitelist() -> selftest::Result {
+        let command = |protocol, command, features, lba| {
+            decode(&ata_16(protocol, command, features, lba)).unwrap()
+        };
+
+        selftest_assert!(check(&command(4, ATA_CMD_IDENTIFY_DEVICE, 0, 0), None).is_ok());
+        selftest_assert!(check(
+            &command(4, ATA_CMD_SMART, SMART_READ_DATA, SMART_SIGNATURE),
+            None
+        )
+        .is_ok());
+
+        // SMART commands without the signature and writing SMART subcommands are refused.
+        selftest_assert!(matches!(
+            check(&command(4, ATA_CMD_SMART, SMART_READ_DATA, 0), None),
+            Err(FileSystemError::PermissionDenied)
+        ));
+        selftest_assert!(matches!(
+            check(&command(5, ATA_CMD_SMART, 0xd6, SMART_SIGNATURE), None),
+            Err(FileSystemError::PermissionDenied)
+        ));
+
+        // Neither are commands outside of the whitelist, such as `WRITE SECTORS`.
+        selftest_assert!(matches!(
+            check(&command(5, 0x30, 0, 0), None),
+            Err(FileSystemError::PermissionDenied)
+        ));
+
+        // The protocol has to match the command.
+        selftest_assert!(matches!(
+            check(&command(3, ATA_CMD_IDENTIFY_DEVICE, 0, 0), None),
+            Err(FileSystemError::InvalidArgument)
+        ));
+
+        Ok(())
+    }
+
+    fn security_erase() -> selftest::Result {
+        let prepare = decode(&ata_16(3, ATA_CMD_SECURITY_ERASE_PREPARE, 0, 0)).unwrap();
+        let erase = decode(&ata_16(5, ATA_CMD_SECURITY_ERASE_UNIT, 0, 0)).unwrap();
+        let state = ExclusiveState::default();
+
+        // The security commands require `O_EXCL`.
+        selftest_assert!(check(&prepare, None).is_err());
+        selftest_assert!(check(&prepare, Some(&state)).is_ok());
+
+        // `SECURITY ERASE UNIT` has to follow `SECURITY ERASE PREPARE`.
+        selftest_assert!(check(&erase, Some(&state)).is_err());
+        state.erase_prepared.store(true, Ordering::SeqCst);
+        selftest_assert!(check(&erase, Some(&state)).is_ok());
+        selftest_assert!(check(&erase, None).is_err());
+
+        Ok(())
+    }
+
+    fn sense() -> selftest::Result {
+        let mut command = decode(&ata_16(3, ATA_CMD_CHECK_POWER_MODE, 0, 0)).unwrap();
+
+        command.taskfile.count = 0xff;
+        command.taskfile.lba = 0x0605_0403_0201;
+        command.taskfile.status = 0x50;
+
+        let sense = sense_data(&command, false);
+
+        selftest_assert_eq!(sense[..4], [0x72, RECOVERED_ERROR, 0x00, 0x1d]);
+        selftest_assert_eq!(sense[7] as usize, sense.len() - 8);
+        selftest_assert_eq!(
+            sense[8..],
+            [0x09, 0x0c, 1, 0, 0, 0xff, 4, 1, 5, 2, 6, 3, 0x40, 0x50]
+        );
+        selftest_assert_eq!(sense_data(&command, true)[1], ABORTED_COMMAND);
+
+        Ok(())
+    }
+
+    crate::selftest!(
+        "sg",
+        decode_ata_16,
+        decode_ata_12,
+        whitelist,
+        security_erase,
+        sense
+    );
+}
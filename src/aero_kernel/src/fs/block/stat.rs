@@ -0,0 +1,230 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Block device I/O statistics, reported by `/sys/block/<dev>/stat` and `/proc/diskstats` in the
+//! same format as on Linux.
+//!
+//! The counters are kept per CPU, so requests issued concurrently on different CPUs do not
+//! contend on them, and are summed up when read. The block layer never merges requests and does
+//! not support discards, so their counts are always zero.
+//!
+//! ## Notes
+//! * <https://www.kernel.org/doc/html/latest/block/stat.html>
+
+use core::fmt;
+use core::sync::atomic::{AtomicIsize, AtomicU64, Ordering};
+
+use alloc::vec::Vec;
+
+use crate::arch::tls;
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::apic::get_cpu_count;
+
+#[cfg(target_arch = "aarch64")]
+fn get_cpu_count() -> usize {
+    1
+}
+
+/// The type of a block request.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    Read,
+    Write,
+    /// Flush of the write cache of the device.
+    Flush,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Read => "R",
+            Op::Write => "W",
+            Op::Flush => "F",
+        })
+    }
+}
+
+#[derive(Default)]
+struct OpCounters {
+    /// Number of completed requests.
+    ios: AtomicU64,
+    /// Number of 512 byte sectors transferred.
+    sectors: AtomicU64,
+    /// Time spent on the completed requests, in nanoseconds.
+    nsecs: AtomicU64,
+    /// Number of requests in flight. Negative if more requests completed than were issued on
+    /// this CPU, as requests can complete on another CPU than they were issued on.
+    in_flight: AtomicIsize,
+}
+
+/// The counters of a CPU, indexed by [`Op`].
+type CpuCounters = [OpCounters; 3];
+
+/// A snapshot of the counters of an operation, summed up over all of the CPUs.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct OpStat {
+    pub ios: u64,
+    pub sectors: u64,
+    pub nsecs: u64,
+    pub in_flight: isize,
+}
+
+pub struct DiskStats {
+    cpus: Vec<CpuCounters>,
+    /// Time (in nanoseconds since boot) at which `io_ticks` was last updated.
+    stamp: AtomicU64,
+    /// Time during which the device had requests in flight, in nanoseconds.
+    io_ticks: AtomicU64,
+}
+
+impl DiskStats {
+    pub fn new() -> Self {
+        Self {
+            cpus: (0..get_cpu_count()).map(|_| Default::default()).collect(),
+            stamp: AtomicU64::new(0),
+            io_ticks: AtomicU64::new(0),
+        }
+    }
+
+    fn counters(&self, op: Op) -> &OpCounters {
+        &self.cpus[tls::get_cpuid() % self.cpus.len()][op as usize]
+    }
+
+    /// Accounts the time since the last update to `io_ticks` if the device was `busy`. Only one
+    /// of the CPUs updating it at the same time does so.
+    fn update_io_ticks(&self, now: u64, busy: bool) {
+        let stamp = self.stamp.load(Ordering::Relaxed);
+
+        if now > stamp
+            && self
+                .stamp
+                .compare_exchange(stamp, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            && busy
+        {
+            self.io_ticks.fetch_add(now - stamp, Ordering::Relaxed);
+        }
+    }
+
+    /// Accounts the start of a request at `now` (in nanoseconds since boot).
+    pub fn start(&self, op: Op, now: u64) {
+        self.update_io_ticks(now, self.in_flight() > 0);
+        self.counters(op).in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts the completion of a request of `size` bytes that started at `start`.
+    pub fn done(&self, op: Op, size: usize, start: u64, now: u64) {
+        let counters = self.counters(op);
+
+        counters.ios.fetch_add(1, Ordering::Relaxed);
+        counters
+            .sectors
+            .fetch_add(size as u64 / 512, Ordering::Relaxed);
+        counters.nsecs.fetch_add(now - start, Ordering::Relaxed);
+
+        self.update_io_ticks(now, true);
+        counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns the counters of `op`, summed up over all of the CPUs.
+    pub fn get(&self, op: Op) -> OpStat {
+        self.cpus
+            .iter()
+            .map(|cpu| &cpu[op as usize])
+            .fold(OpStat::default(), |stat, counters| OpStat {
+                ios: stat.ios + counters.ios.load(Ordering::Relaxed),
+                sectors: stat.sectors + counters.sectors.load(Ordering::Relaxed),
+                nsecs: stat.nsecs + counters.nsecs.load(Ordering::Relaxed),
+                in_flight: stat.in_flight + counters.in_flight.load(Ordering::Relaxed),
+            })
+    }
+
+    /// Returns the number of requests in flight.
+    pub fn in_flight(&self) -> usize {
+        [Op::Read, Op::Write, Op::Flush]
+            .into_iter()
+            .map(|op| self.get(op).in_flight)
+            .sum::<isize>()
+            .max(0) as usize
+    }
+}
+
+/// Formats the statistics as the fields of `/sys/block/<dev>/stat`; times are in milliseconds.
+impl fmt::Display for DiskStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NS_PER_MS: u64 = 1_000_000;
+
+        let read = self.get(Op::Read);
+        let write = self.get(Op::Write);
+        let flush = self.get(Op::Flush);
+
+        let io_ticks = self.io_ticks.load(Ordering::Relaxed);
+        let time_in_queue = read.nsecs + write.nsecs + flush.nsecs;
+
+        write!(
+            f,
+            "{} 0 {} {} {} 0 {} {} {} {} {} 0 0 0 0 {} {}",
+            read.ios,
+            read.sectors,
+            read.nsecs / NS_PER_MS,
+            write.ios,
+            write.sectors,
+            write.nsecs / NS_PER_MS,
+            self.in_flight(),
+            io_ticks / NS_PER_MS,
+            time_in_queue / NS_PER_MS,
+            flush.ios,
+            flush.nsecs / NS_PER_MS,
+        )
+    }
+}
+
+mod selftests {
+    use super::*;
+
+    use crate::{selftest, selftest_assert_eq};
+
+    fn counters() -> selftest::Result {
+        const MS: u64 = 1_000_000;
+
+        let stats = DiskStats::new();
+
+        stats.start(Op::Read, MS);
+        stats.start(Op::Write, 2 * MS);
+        selftest_assert_eq!(stats.in_flight(), 2);
+
+        stats.done(Op::Read, 4096, MS, 5 * MS);
+        stats.done(Op::Write, 1024, 2 * MS, 9 * MS);
+        selftest_assert_eq!(stats.in_flight(), 0);
+
+        let read = stats.get(Op::Read);
+        selftest_assert_eq!((read.ios, read.sectors, read.nsecs), (1, 8, 4 * MS));
+
+        // The device was busy from the start of the first request until the end of the last
+        // one.
+        selftest_assert_eq!(stats.io_ticks.load(Ordering::Relaxed), 8 * MS);
+        selftest_assert_eq!(
+            alloc::format!("{stats}"),
+            "1 0 8 4 1 0 2 7 0 8 11 0 0 0 0 0 0"
+        );
+
+        Ok(())
+    }
+
+    crate::selftest!("blkstat", counters);
+}
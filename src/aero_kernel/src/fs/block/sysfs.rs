@@ -0,0 +1,248 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The block device directory of sysfs, mounted at `/sys/block`. It contains a directory for
+//! each disk, which in turn contains a directory for each of its partitions:
+//!
+//! * `<dev>/stat`: the I/O statistics of the device (see [`super::stat`]).
+//! * `<dev>/size`: the capacity of the device in 512 byte sectors.
+//!
+//! The inodes are created on demand, so the directories always reflect the installed devices.
+
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::fs;
+use crate::fs::cache::{self, CachedINode, DirCacheItem, INodeCacheItem};
+use crate::fs::inode::{DirEntry, FileType, INodeInterface, Metadata};
+use crate::fs::{FileSystem, FileSystemError, Path, MOUNT_MANAGER};
+
+use super::{BlockDevice, BLOCK_DEVS};
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Root,
+    /// The directory of a disk or a partition.
+    Device,
+    Stat,
+    Size,
+}
+
+struct SysBlockINode {
+    id: usize,
+    kind: Kind,
+    /// The device that the inode belongs to; dangling for the root directory.
+    device: Weak<BlockDevice>,
+    filesystem: Weak<SysBlockFs>,
+}
+
+impl SysBlockINode {
+    fn device(&self) -> fs::Result<Arc<BlockDevice>> {
+        self.device.upgrade().ok_or(FileSystemError::EntryNotFound)
+    }
+
+    fn filesystem(&self) -> Arc<SysBlockFs> {
+        self.filesystem.upgrade().unwrap()
+    }
+
+    /// Returns the entries of this directory, other than `.` and `..`.
+    fn children(&self) -> fs::Result<Vec<(String, Kind, Arc<BlockDevice>)>> {
+        match self.kind {
+            Kind::Root => Ok(BLOCK_DEVS
+                .lock()
+                .values()
+                .filter(|device| device.parent.is_none())
+                .map(|device| (device.name(), Kind::Device, device.clone()))
+                .collect()),
+
+            Kind::Device => {
+                let device = self.device()?;
+                let mut children = alloc::vec![
+                    (String::from("size"), Kind::Size, device.clone()),
+                    (String::from("stat"), Kind::Stat, device.clone()),
+                ];
+
+                for partition in device.partitions() {
+                    children.push((partition.name(), Kind::Device, partition));
+                }
+
+                Ok(children)
+            }
+
+            Kind::Stat | Kind::Size => Err(FileSystemError::NotDirectory),
+        }
+    }
+
+    /// Returns a new inode for the parent of this directory.
+    fn parent(&self) -> fs::Result<INodeCacheItem> {
+        let filesystem = self.filesystem();
+
+        if self.kind == Kind::Root {
+            return Ok(filesystem.make_inode(Kind::Root, None));
+        }
+
+        // The directory of a partition is in the one of its disk.
+        match self.device()?.parent.as_ref().and_then(Weak::upgrade) {
+            Some(disk) => Ok(filesystem.make_inode(Kind::Device, Some(&disk))),
+            None => Ok(filesystem.make_inode(Kind::Root, None)),
+        }
+    }
+}
+
+impl INodeInterface for SysBlockINode {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let data = match self.kind {
+            Kind::Stat => alloc::format!("{}\n", self.device()?.stats),
+            Kind::Size => alloc::format!("{}\n", self.device()?.size() / 512),
+            Kind::Root | Kind::Device => return Err(FileSystemError::IsDir),
+        };
+
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), data.len() - offset);
+        buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
+
+        Ok(count)
+    }
+
+    fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        let (name, kind, device) = self
+            .children()?
+            .into_iter()
+            .find(|(child, ..)| child == name)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let inode = self.filesystem().make_inode(kind, Some(&device));
+        Ok(DirEntry::new(dir, inode, name))
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        let file_type = match self.kind {
+            Kind::Root | Kind::Device => FileType::Directory,
+            Kind::Stat | Kind::Size => FileType::File,
+        };
+
+        Ok(Metadata {
+            id: self.id,
+            file_type,
+            size: 0,
+            children_len: self.children().map_or(0, |children| children.len()),
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> fs::Result<Option<DirCacheItem>> {
+        let children = self.children()?;
+
+        Ok(match index {
+            0x00 => {
+                let device = self.device.upgrade();
+                let inode = self.filesystem().make_inode(self.kind, device.as_ref());
+
+                Some(DirEntry::new(parent, inode, String::from(".")))
+            }
+
+            0x01 => Some(DirEntry::new(parent, self.parent()?, String::from(".."))),
+
+            // Subtract two because of the "." and ".." entries.
+            _ => children
+                .into_iter()
+                .nth(index - 2)
+                .map(|(name, kind, device)| {
+                    let inode = self.filesystem().make_inode(kind, Some(&device));
+                    DirEntry::new(parent, inode, name)
+                }),
+        })
+    }
+
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.filesystem.clone())
+    }
+}
+
+struct SysBlockFs {
+    root_dir: DirCacheItem,
+    sref: Weak<SysBlockFs>,
+}
+
+impl SysBlockFs {
+    fn new() -> Arc<Self> {
+        let fs = Arc::new_cyclic(|sref: &Weak<SysBlockFs>| {
+            let root = Arc::new(SysBlockINode {
+                id: 0,
+                kind: Kind::Root,
+                device: Weak::new(),
+                filesystem: sref.clone(),
+            });
+            let root_cached = cache::icache().make_item_no_cache(CachedINode::new(root));
+
+            Self {
+                root_dir: DirEntry::new_root(root_cached, String::from("/")),
+                sref: sref.clone(),
+            }
+        });
+
+        let copy: Arc<dyn FileSystem> = fs.clone();
+        fs.root_dir.filesystem.call_once(|| Arc::downgrade(&copy));
+
+        fs
+    }
+
+    /// Creates a new inode of `kind` for `device`.
+    fn make_inode(&self, kind: Kind, device: Option<&Arc<BlockDevice>>) -> INodeCacheItem {
+        // The inodes are created on demand, so their numbers are derived from the device.
+        let id = device.map_or(0, |device| device.id * 4 + kind as usize);
+
+        let inode = Arc::new(SysBlockINode {
+            id,
+            kind,
+            device: device.map_or_else(Weak::new, Arc::downgrade),
+            filesystem: self.sref.clone(),
+        });
+
+        cache::icache().make_item_no_cache(CachedINode::new(inode))
+    }
+}
+
+impl FileSystem for SysBlockFs {
+    #[inline]
+    fn root_dir(&self) -> DirCacheItem {
+        self.root_dir.clone()
+    }
+}
+
+static SYS_BLOCK_FS: Once<Arc<SysBlockFs>> = Once::new();
+
+pub(super) fn init() -> fs::Result<()> {
+    let fs = SYS_BLOCK_FS.call_once(SysBlockFs::new);
+
+    let inode = match fs::lookup_path(Path::new("/sys/block")) {
+        Ok(inode) => inode,
+        Err(FileSystemError::EntryNotFound) => {
+            log::warn!("block: `/sys/block` does not exist; not mounting");
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+
+    MOUNT_MANAGER.mount(inode, fs.clone())?;
+    Ok(())
+}
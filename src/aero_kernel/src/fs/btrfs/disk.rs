@@ -0,0 +1,352 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! On-disk structures of btrfs. All of the fields are little-endian.
+
+use core::cmp::Ordering;
+
+use bytemuck::{Pod, Zeroable};
+
+/// Byte offset of the primary superblock.
+pub const SUPERBLOCK_OFFSET: usize = 0x10000;
+
+pub const FS_TREE_OBJECTID: u64 = 5;
+pub const FIRST_CHUNK_TREE_OBJECTID: u64 = 256;
+
+pub const INODE_ITEM_KEY: u8 = 1;
+pub const DIR_INDEX_KEY: u8 = 96;
+pub const EXTENT_DATA_KEY: u8 = 108;
+pub const ROOT_ITEM_KEY: u8 = 132;
+pub const CHUNK_ITEM_KEY: u8 = 228;
+
+pub const FILE_EXTENT_INLINE: u8 = 0;
+pub const FILE_EXTENT_REG: u8 = 1;
+pub const FILE_EXTENT_PREALLOC: u8 = 2;
+
+/// Chunk profiles that stripe the data over multiple devices. Chunks with any other profile
+/// (single, DUP and RAID1) have a full copy of the data in their first stripe.
+pub const BLOCK_GROUP_STRIPED: u64 = (1 << 3) | (1 << 6) | (1 << 7) | (1 << 8); // RAID0/10/5/6
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct DevItem {
+    pub devid: u64,
+    pub total_bytes: u64,
+    pub bytes_used: u64,
+    pub io_align: u32,
+    pub io_width: u32,
+    pub sector_size: u32,
+    pub typ: u64,
+    pub generation: u64,
+    pub start_offset: u64,
+    pub dev_group: u32,
+    pub seek_speed: u8,
+    pub bandwidth: u8,
+    pub uuid: [u8; 16],
+    pub fsid: [u8; 16],
+}
+
+const_assert_eq!(core::mem::size_of::<DevItem>(), 0x62);
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct SuperBlock {
+    pub csum: [u8; 32],
+    pub fsid: [u8; 16],
+    pub bytenr: u64,
+    pub flags: u64,
+    pub magic: u64,
+    pub generation: u64,
+    /// Logical address of the root of the root tree.
+    pub root: u64,
+    /// Logical address of the root of the chunk tree.
+    pub chunk_root: u64,
+    pub log_root: u64,
+    pub log_root_transid: u64,
+    pub total_bytes: u64,
+    pub bytes_used: u64,
+    pub root_dir_objectid: u64,
+    pub num_devices: u64,
+    pub sectorsize: u32,
+    pub nodesize: u32,
+    pub leafsize: u32,
+    pub stripesize: u32,
+    pub sys_chunk_array_size: u32,
+    pub chunk_root_generation: u64,
+    pub compat_flags: u64,
+    pub compat_ro_flags: u64,
+    pub incompat_flags: u64,
+    pub csum_type: u16,
+    pub root_level: u8,
+    pub chunk_root_level: u8,
+    pub log_root_level: u8,
+    pub dev_item: DevItem,
+    pub label: [u8; 0x100],
+    pub cache_generation: u64,
+    pub uuid_tree_generation: u64,
+    pub metadata_uuid: [u8; 16],
+    pub reserved: [u8; 0xe0],
+    /// The chunk items of the system chunks, which hold the chunk tree. Each one is preceded by
+    /// its key.
+    pub sys_chunk_array: [u8; 0x800],
+    pub super_roots: [u8; 0x4d5],
+}
+
+const_assert_eq!(core::mem::size_of::<SuperBlock>(), 0x1000);
+
+impl SuperBlock {
+    pub const MAGIC: u64 = u64::from_le_bytes(*b"_BHRfS_M");
+
+    /// Returns the label of the filesystem.
+    pub fn label(&self) -> &str {
+        let len = self
+            .label
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.label.len());
+        core::str::from_utf8(&self.label[..len]).unwrap_or("")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct Key {
+    pub objectid: u64,
+    pub typ: u8,
+    pub offset: u64,
+}
+
+impl Key {
+    pub const fn new(objectid: u64, typ: u8, offset: u64) -> Self {
+        Self {
+            objectid,
+            typ,
+            offset,
+        }
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (*self, *other);
+
+        ({ a.objectid }, a.typ, { a.offset }).cmp(&({ b.objectid }, b.typ, { b.offset }))
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The header of every tree node.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct Header {
+    pub csum: [u8; 32],
+    pub fsid: [u8; 16],
+    pub bytenr: u64,
+    pub flags: u64,
+    pub chunk_tree_uuid: [u8; 16],
+    pub generation: u64,
+    pub owner: u64,
+    pub nritems: u32,
+    /// The level of the node in the tree; leaves are at level 0.
+    pub level: u8,
+}
+
+const_assert_eq!(core::mem::size_of::<Header>(), 101);
+
+/// An item in a leaf. `offset` is relative to the end of the header.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct Item {
+    pub key: Key,
+    pub offset: u32,
+    pub size: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<Item>(), 25);
+
+/// A pointer to a child node in an internal node.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct KeyPtr {
+    pub key: Key,
+    pub blockptr: u64,
+    pub generation: u64,
+}
+
+const_assert_eq!(core::mem::size_of::<KeyPtr>(), 33);
+
+/// Maps the logical address range `[key.offset, key.offset + length)` to the stripes that
+/// follow it.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct Chunk {
+    pub length: u64,
+    pub owner: u64,
+    pub stripe_len: u64,
+    pub typ: u64,
+    pub io_align: u32,
+    pub io_width: u32,
+    pub sector_size: u32,
+    pub num_stripes: u16,
+    pub sub_stripes: u16,
+}
+
+const_assert_eq!(core::mem::size_of::<Chunk>(), 48);
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct Stripe {
+    pub devid: u64,
+    /// Physical address of the stripe on the device.
+    pub offset: u64,
+    pub dev_uuid: [u8; 16],
+}
+
+const_assert_eq!(core::mem::size_of::<Stripe>(), 32);
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct Timespec {
+    pub sec: u64,
+    pub nsec: u32,
+}
+
+impl From<Timespec> for aero_syscall::TimeSpec {
+    fn from(time: Timespec) -> Self {
+        Self {
+            tv_sec: time.sec as isize,
+            tv_nsec: time.nsec as isize,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct InodeItem {
+    pub generation: u64,
+    pub transid: u64,
+    pub size: u64,
+    pub nbytes: u64,
+    pub block_group: u64,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub rdev: u64,
+    pub flags: u64,
+    pub sequence: u64,
+    pub reserved: [u64; 4],
+    pub atime: Timespec,
+    pub ctime: Timespec,
+    pub mtime: Timespec,
+    pub otime: Timespec,
+}
+
+const_assert_eq!(core::mem::size_of::<InodeItem>(), 160);
+
+/// The beginning of a root item, which describes the root of a tree (e.g. a subvolume).
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct RootItem {
+    pub inode: InodeItem,
+    pub generation: u64,
+    /// The object ID of the root directory of a subvolume.
+    pub root_dirid: u64,
+    /// Logical address of the root node of the tree.
+    pub bytenr: u64,
+    pub byte_limit: u64,
+    pub bytes_used: u64,
+    pub last_snapshot: u64,
+    pub flags: u64,
+    pub refs: u32,
+    pub drop_progress: Key,
+    pub drop_level: u8,
+    pub level: u8,
+}
+
+/// A directory entry, followed by its name.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct DirItem {
+    /// The key of the inode item of the entry or, for subvolumes, of their root item.
+    pub location: Key,
+    pub transid: u64,
+    pub data_len: u16,
+    pub name_len: u16,
+    pub typ: u8,
+}
+
+const_assert_eq!(core::mem::size_of::<DirItem>(), 30);
+
+/// The header of a file extent. The data of inline extents follows it directly, otherwise it
+/// is followed by a [`FileExtentRegular`].
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct FileExtentItem {
+    pub generation: u64,
+    pub ram_bytes: u64,
+    pub compression: u8,
+    pub encryption: u8,
+    pub other_encoding: u16,
+    pub typ: u8,
+}
+
+const_assert_eq!(core::mem::size_of::<FileExtentItem>(), 21);
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed)]
+pub struct FileExtentRegular {
+    /// Logical address of the extent on the disk; zero for holes.
+    pub disk_bytenr: u64,
+    pub disk_num_bytes: u64,
+    /// Offset of the data of the file within the extent.
+    pub offset: u64,
+    pub num_bytes: u64,
+}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(
+            // SAFETY: The on-disk structures are packed (so they have no padding) and made of
+            // integers, which are valid for any bit pattern.
+            unsafe impl Zeroable for $ty {}
+            unsafe impl Pod for $ty {}
+        )*
+    };
+}
+
+impl_pod!(
+    DevItem,
+    SuperBlock,
+    Key,
+    Header,
+    Item,
+    KeyPtr,
+    Chunk,
+    Stripe,
+    Timespec,
+    InodeItem,
+    RootItem,
+    DirItem,
+    FileExtentItem,
+    FileExtentRegular
+);
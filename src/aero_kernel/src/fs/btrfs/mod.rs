@@ -0,0 +1,628 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Read-only btrfs support, for pulling files off Linux volumes. Only single device volumes
+//! without compression are supported. The default filesystem tree is mounted and subvolumes
+//! appear as directories in it.
+//!
+//! ## Notes
+//! * <https://btrfs.readthedocs.io/en/latest/dev/On-disk-format.html>
+
+mod disk;
+
+use aero_syscall::Mode;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use bytemuck::Pod;
+
+use crate::fs::cache::CachedINode;
+use crate::mem::paging::*;
+use crate::utils::uuid::Uuid;
+
+use self::disk::*;
+
+use super::block::{BlockDevice, BlockDeviceClaim, CachedAccess, PAGE_CACHE};
+use super::cache::{self, DirCacheItem};
+use super::inode::{self, FileType, INodeInterface, MMapPage, Metadata};
+use super::path::PathBuf;
+use super::{FileSystem, FileSystemError};
+
+/// Maximum height of a tree.
+const MAX_LEVEL: u8 = 8;
+
+/// Reads a `T` at `offset` in `bytes`, if it is in bounds.
+fn read<T: Pod>(bytes: &[u8], offset: usize) -> Option<T> {
+    let end = offset.checked_add(core::mem::size_of::<T>())?;
+    bytes.get(offset..end).map(bytemuck::pod_read_unaligned)
+}
+
+struct ChunkMapping {
+    length: u64,
+    /// Physical address of the chunk on the device.
+    physical: u64,
+}
+
+/// The device of a volume, with the chunk mappings used to translate the logical addresses
+/// of the trees and extents into physical addresses on the device.
+struct Volume {
+    block: Arc<BlockDevice>,
+    fsid: [u8; 16],
+    nodesize: usize,
+    chunks: BTreeMap<u64, ChunkMapping>,
+}
+
+impl Volume {
+    /// Adds the mapping of the chunk item `data` that starts at the `logical` address.
+    fn add_chunk(&mut self, logical: u64, data: &[u8]) -> Option<()> {
+        let chunk: Chunk = read(data, 0)?;
+        let stripe: Stripe = read(data, core::mem::size_of::<Chunk>())?;
+
+        if chunk.typ & BLOCK_GROUP_STRIPED != 0 {
+            log::warn!("btrfs: unsupported chunk profile {:#x}", { chunk.typ });
+            return Some(());
+        }
+
+        let mapping = ChunkMapping {
+            length: chunk.length,
+            physical: stripe.offset,
+        };
+
+        self.chunks.insert(logical, mapping);
+        Some(())
+    }
+
+    /// Reads `size` bytes at the `logical` address. The range cannot span multiple chunks.
+    fn read(&self, logical: u64, size: usize) -> Option<Box<[u8]>> {
+        let (start, mapping) = self.chunks.range(..=logical).next_back()?;
+        let offset = logical - start;
+
+        if offset + size as u64 > mapping.length {
+            return None;
+        }
+
+        let mut buffer = Box::<[u8]>::new_uninit_slice(size);
+        self.block
+            .read((mapping.physical + offset) as usize, &mut buffer)?;
+
+        // SAFETY: We have initialized the buffer above.
+        Some(unsafe { buffer.assume_init() })
+    }
+
+    fn read_node(&self, logical: u64) -> Option<Box<[u8]>> {
+        let node = self.read(logical, self.nodesize)?;
+        let header: Header = read(&node, 0)?;
+
+        if header.bytenr != logical || header.fsid != self.fsid {
+            log::warn!("btrfs: invalid tree node at {logical:#x}");
+            return None;
+        }
+
+        Some(node)
+    }
+
+    /// Returns the items with keys in `[lo, hi]` in the tree with the root node at `root`.
+    fn items(&self, root: u64, lo: Key, hi: Key) -> Option<Vec<(Key, Box<[u8]>)>> {
+        let mut items = Vec::new();
+        self.collect(root, None, lo, hi, &mut items)?;
+
+        Some(items)
+    }
+
+    fn collect(
+        &self,
+        logical: u64,
+        level: Option<u8>,
+        lo: Key,
+        hi: Key,
+        items: &mut Vec<(Key, Box<[u8]>)>,
+    ) -> Option<()> {
+        const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+        let node = self.read_node(logical)?;
+        let header: Header = read(&node, 0)?;
+
+        // The level of a child must be one less than the level of its parent, which also
+        // prevents walking in circles on a corrupted tree.
+        if header.level >= MAX_LEVEL || level.is_some_and(|level| level != header.level) {
+            log::warn!("btrfs: invalid tree node level at {logical:#x}");
+            return None;
+        }
+
+        let nritems = header.nritems as usize;
+
+        if header.level == 0 {
+            for i in 0..nritems {
+                let item: Item = read(&node, HEADER_SIZE + i * core::mem::size_of::<Item>())?;
+
+                if item.key < lo {
+                    continue;
+                } else if item.key > hi {
+                    break;
+                }
+
+                let start = HEADER_SIZE + item.offset as usize;
+                let data = node.get(start..start + item.size as usize)?;
+
+                items.push((item.key, data.into()));
+            }
+        } else {
+            let ptrs = (0..nritems)
+                .map(|i| read::<KeyPtr>(&node, HEADER_SIZE + i * core::mem::size_of::<KeyPtr>()))
+                .collect::<Option<Vec<_>>>()?;
+
+            for (i, ptr) in ptrs.iter().enumerate() {
+                // A child holds the keys from its key up to the key of the next child.
+                if ptr.key > hi {
+                    break;
+                } else if ptrs.get(i + 1).is_some_and(|next| next.key <= lo) {
+                    continue;
+                }
+
+                self.collect(ptr.blockptr, Some(header.level - 1), lo, hi, items)?;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Returns the items of the provided `typ` of the object `objectid`.
+    fn object_items(&self, root: u64, objectid: u64, typ: u8) -> Option<Vec<(Key, Box<[u8]>)>> {
+        self.items(
+            root,
+            Key::new(objectid, typ, 0),
+            Key::new(objectid, typ, u64::MAX),
+        )
+    }
+}
+
+pub struct Btrfs {
+    volume: Volume,
+    _claim: BlockDeviceClaim,
+    superblock: Box<SuperBlock>,
+    /// Logical address of the root node of the default filesystem tree.
+    fs_tree: u64,
+
+    sref: Weak<Self>,
+}
+
+impl Btrfs {
+    /// Object ID of the root directory of a filesystem tree.
+    const ROOT_DIR_OBJECTID: u64 = 256;
+
+    pub fn new(block: Arc<BlockDevice>) -> Option<Arc<Self>> {
+        let mut superblock = Box::<SuperBlock>::new_uninit();
+        block.read(SUPERBLOCK_OFFSET, superblock.as_bytes_mut())?;
+
+        // SAFETY: We have initialized the superblock above.
+        let superblock = unsafe { superblock.assume_init() };
+
+        if superblock.magic != SuperBlock::MAGIC {
+            return None;
+        }
+
+        if superblock.num_devices != 1 {
+            log::warn!("btrfs: volumes with multiple devices are not supported");
+            return None;
+        }
+
+        let claim = block.claim().ok()?;
+
+        let mut volume = Volume {
+            block,
+            fsid: superblock.fsid,
+            nodesize: superblock.nodesize as usize,
+            chunks: BTreeMap::new(),
+        };
+
+        // Bootstrap the chunk mappings with the system chunks from the superblock, which hold
+        // the chunk tree with the rest of them.
+        let sys_chunk_array = superblock
+            .sys_chunk_array
+            .get(..superblock.sys_chunk_array_size as usize)?;
+
+        let mut offset = 0;
+
+        while offset < sys_chunk_array.len() {
+            let key: Key = read(sys_chunk_array, offset)?;
+            offset += core::mem::size_of::<Key>();
+
+            let chunk: Chunk = read(sys_chunk_array, offset)?;
+            let size = core::mem::size_of::<Chunk>()
+                + chunk.num_stripes as usize * core::mem::size_of::<Stripe>();
+
+            if key.typ != CHUNK_ITEM_KEY {
+                return None;
+            }
+
+            volume.add_chunk(key.offset, sys_chunk_array.get(offset..offset + size)?)?;
+            offset += size;
+        }
+
+        for (key, data) in volume.object_items(
+            superblock.chunk_root,
+            FIRST_CHUNK_TREE_OBJECTID,
+            CHUNK_ITEM_KEY,
+        )? {
+            volume.add_chunk(key.offset, &data)?;
+        }
+
+        let fs_tree = Self::root_item(&volume, superblock.root, FS_TREE_OBJECTID)?.bytenr;
+
+        log::trace!(
+            "btrfs: initialized (label=`{}`, nodesize={}, sectorsize={})",
+            superblock.label(),
+            { superblock.nodesize },
+            { superblock.sectorsize },
+        );
+
+        Some(Arc::new_cyclic(|sref| Self {
+            volume,
+            _claim: claim,
+            superblock,
+            fs_tree,
+
+            sref: sref.clone(),
+        }))
+    }
+
+    pub fn label(&self) -> &str {
+        self.superblock.label()
+    }
+
+    /// Returns the UUID of the filesystem (`fsid`).
+    pub fn uuid(&self) -> Uuid {
+        Uuid(self.fsid)
+    }
+
+    /// Returns the root item of the tree `objectid` (e.g. a subvolume) from the root tree.
+    fn root_item(volume: &Volume, root_tree: u64, objectid: u64) -> Option<RootItem> {
+        // Snapshots and relocation can leave multiple root items; the one with the highest
+        // offset is the current one.
+        let items = volume.object_items(root_tree, objectid, ROOT_ITEM_KEY)?;
+        let (_, data) = items.last()?;
+
+        read(data, 0)
+    }
+
+    /// Returns the inode `objectid` in the filesystem tree with the root node at `tree`.
+    fn find_inode(&self, tree: u64, objectid: u64) -> super::Result<Arc<INode>> {
+        let key = Key::new(objectid, INODE_ITEM_KEY, 0);
+        let items = self
+            .volume
+            .items(tree, key, key)
+            .ok_or(FileSystemError::Io)?;
+
+        let (_, data) = items.first().ok_or(FileSystemError::EntryNotFound)?;
+        let item: InodeItem = read(data, 0).ok_or(FileSystemError::Io)?;
+
+        Ok(Arc::new_cyclic(|sref| INode {
+            fs: self.sref.clone(),
+            tree,
+            objectid,
+            item,
+            sref: sref.clone(),
+        }))
+    }
+}
+
+impl FileSystem for Btrfs {
+    fn root_dir(&self) -> DirCacheItem {
+        let inode = self
+            .find_inode(self.fs_tree, Self::ROOT_DIR_OBJECTID)
+            .expect("btrfs: invalid filesystem (root inode not found)");
+
+        let inode = cache::icache().make_item_no_cache(CachedINode::new(inode));
+        inode::DirEntry::new_root(inode, String::from("/"))
+    }
+
+    fn caches_negative_entries(&self) -> bool {
+        // The filesystem is read-only.
+        true
+    }
+}
+
+pub struct INode {
+    fs: Weak<Btrfs>,
+    /// Logical address of the root node of the filesystem tree (subvolume) of the inode.
+    tree: u64,
+    objectid: u64,
+    item: InodeItem,
+
+    sref: Weak<INode>,
+}
+
+impl INode {
+    fn filesystem(&self) -> Arc<Btrfs> {
+        self.fs.upgrade().expect("btrfs: filesystem was dropped")
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::from_bits_truncate(self.item.mode)
+    }
+
+    /// Returns the names of the entries of this directory and the keys of their locations.
+    fn entries(&self) -> super::Result<Vec<(String, Key)>> {
+        if !self.metadata()?.is_directory() {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        let items = self
+            .filesystem()
+            .volume
+            .object_items(self.tree, self.objectid, DIR_INDEX_KEY)
+            .ok_or(FileSystemError::Io)?;
+
+        items
+            .iter()
+            .map(|(_, data)| {
+                let item: DirItem = read(data, 0)?;
+
+                let start = core::mem::size_of::<DirItem>();
+                let name = data.get(start..start + item.name_len as usize)?;
+
+                Some((String::from_utf8_lossy(name).into_owned(), item.location))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(FileSystemError::Io)
+    }
+
+    /// Returns the inode that the directory entry with the provided `location` refers to.
+    fn resolve(&self, location: Key) -> super::Result<Arc<INode>> {
+        let filesystem = self.filesystem();
+
+        match location.typ {
+            INODE_ITEM_KEY => filesystem.find_inode(self.tree, location.objectid),
+
+            // The entry is a subvolume, so continue in the root directory of its tree.
+            ROOT_ITEM_KEY => {
+                let root = Btrfs::root_item(
+                    &filesystem.volume,
+                    filesystem.superblock.root,
+                    location.objectid,
+                )
+                .ok_or(FileSystemError::Io)?;
+
+                filesystem.find_inode(root.bytenr, root.root_dirid)
+            }
+
+            _ => Err(FileSystemError::Io),
+        }
+    }
+
+    fn make_dirent(
+        &self,
+        parent: DirCacheItem,
+        name: String,
+        location: Key,
+    ) -> super::Result<DirCacheItem> {
+        let inode = self.resolve(location)?;
+        let inode = cache::icache().make_item_no_cache(CachedINode::new(inode));
+
+        Ok(inode::DirEntry::new(parent, inode, name))
+    }
+}
+
+impl CachedAccess for INode {
+    fn sref(&self) -> Weak<dyn CachedAccess> {
+        self.sref.clone()
+    }
+
+    fn read_direct(&self, offset: usize, dest: PhysFrame) -> Option<usize> {
+        let buffer = dest.as_slice_mut();
+        buffer.fill(0);
+
+        INodeInterface::read_at(self, offset, buffer).ok()
+    }
+
+    fn write_direct(&self, _offset: usize, _src: PhysFrame) -> Option<usize> {
+        None
+    }
+}
+
+impl INodeInterface for INode {
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.fs.clone())
+    }
+
+    fn metadata(&self) -> super::Result<Metadata> {
+        let typ = self.mode() & Mode::S_IFMT;
+
+        let file_type = if typ == Mode::S_IFDIR {
+            FileType::Directory
+        } else if typ == Mode::S_IFLNK {
+            FileType::Symlink
+        } else if typ == Mode::S_IFCHR || typ == Mode::S_IFBLK {
+            FileType::Device
+        } else if typ == Mode::S_IFSOCK {
+            FileType::Socket
+        } else {
+            FileType::File
+        };
+
+        Ok(Metadata {
+            id: self.objectid as usize,
+            file_type,
+            size: self.item.size as usize,
+            children_len: 0,
+        })
+    }
+
+    fn stat(&self) -> super::Result<aero_syscall::Stat> {
+        let item = self.item;
+
+        // NOTE: The device number is stored in the internal encoding of Linux (12-bit major
+        // and 20-bit minor numbers).
+        let rdev = aero_syscall::makedev((item.rdev >> 20) as u32, (item.rdev & 0xfffff) as u32);
+
+        Ok(aero_syscall::Stat {
+            st_ino: self.objectid as _,
+            st_mode: self.mode(),
+            st_nlink: item.nlink as _,
+            st_uid: item.uid,
+            st_gid: item.gid,
+            st_rdev: rdev,
+            st_size: item.size as _,
+            st_blksize: self.filesystem().superblock.sectorsize as _,
+            st_blocks: (item.nbytes / 512) as _,
+
+            st_atim: item.atime.into(),
+            st_mtim: item.mtime.into(),
+            st_ctim: item.ctime.into(),
+
+            ..Default::default()
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> super::Result<Option<DirCacheItem>> {
+        match index {
+            0x00 => {
+                let inode = parent.inode();
+                return Ok(Some(inode::DirEntry::new(parent, inode, String::from("."))));
+            }
+
+            0x01 => {
+                let inode = parent.parent().unwrap_or_else(|| parent.clone()).inode();
+                return Ok(Some(inode::DirEntry::new(
+                    parent,
+                    inode,
+                    String::from(".."),
+                )));
+            }
+
+            _ => {}
+        }
+
+        // Subtract two because of the "." and ".." entries.
+        match self.entries()?.into_iter().nth(index - 2) {
+            Some((name, location)) => self.make_dirent(parent, name, location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn lookup(&self, parent: DirCacheItem, name: &str) -> super::Result<DirCacheItem> {
+        let (name, location) = self
+            .entries()?
+            .into_iter()
+            .find(|(entry, _)| entry == name)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        self.make_dirent(parent, name, location)
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> super::Result<usize> {
+        if self.metadata()?.is_directory() {
+            return Err(FileSystemError::IsDir);
+        }
+
+        let size = self.item.size as usize;
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), size - offset);
+        let buffer = &mut buffer[..count];
+        let end = offset + count;
+
+        // Holes are not backed by an extent (or by one without a disk address), so they read
+        // as zeros.
+        buffer.fill(0);
+
+        let filesystem = self.filesystem();
+        let volume = &filesystem.volume;
+
+        let extents = volume
+            .items(
+                self.tree,
+                Key::new(self.objectid, EXTENT_DATA_KEY, 0),
+                Key::new(self.objectid, EXTENT_DATA_KEY, end as u64 - 1),
+            )
+            .ok_or(FileSystemError::Io)?;
+
+        for (key, data) in extents {
+            let start = key.offset as usize;
+            let extent: FileExtentItem = read(&data, 0).ok_or(FileSystemError::Io)?;
+            let header_size = core::mem::size_of::<FileExtentItem>();
+
+            if extent.compression != 0 || extent.encryption != 0 {
+                log::warn!("btrfs: compressed and encrypted extents are not supported");
+                return Err(FileSystemError::NotSupported);
+            }
+
+            match extent.typ {
+                FILE_EXTENT_INLINE => {
+                    let inline = &data[header_size..];
+                    let from = core::cmp::max(start, offset);
+                    let to = core::cmp::min(start + inline.len(), end);
+
+                    if from < to {
+                        buffer[from - offset..to - offset]
+                            .copy_from_slice(&inline[from - start..to - start]);
+                    }
+                }
+
+                FILE_EXTENT_REG | FILE_EXTENT_PREALLOC => {
+                    let regular: FileExtentRegular =
+                        read(&data, header_size).ok_or(FileSystemError::Io)?;
+
+                    // Preallocated extents have not been written to yet.
+                    if extent.typ == FILE_EXTENT_PREALLOC || regular.disk_bytenr == 0 {
+                        continue;
+                    }
+
+                    let from = core::cmp::max(start, offset);
+                    let to = core::cmp::min(start + regular.num_bytes as usize, end);
+
+                    if from >= to {
+                        continue;
+                    }
+
+                    let logical = regular.disk_bytenr + regular.offset + (from - start) as u64;
+                    let data = volume.read(logical, to - from).ok_or(FileSystemError::Io)?;
+
+                    buffer[from - offset..to - offset].copy_from_slice(&data);
+                }
+
+                _ => return Err(FileSystemError::Io),
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn resolve_link(&self) -> super::Result<PathBuf> {
+        if !self.metadata()?.is_symlink() {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let mut buffer = alloc::vec![0; self.item.size as usize];
+        self.read_at(0, &mut buffer)?;
+
+        let path = core::str::from_utf8(&buffer).or(Err(FileSystemError::InvalidPath))?;
+        Ok(path.into())
+    }
+
+    fn mmap_v2(&self, offset: usize) -> super::Result<MMapPage> {
+        Ok(MMapPage::PageCache(PAGE_CACHE.get_page(
+            &(self.sref.clone() as Weak<dyn CachedAccess>),
+            offset,
+        )?))
+    }
+}
@@ -28,14 +28,17 @@ use core::fmt::Debug;
 use core::hash::Hash;
 use core::num::NonZeroUsize;
 use core::ops;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 
 use alloc::vec::Vec;
 use spin::Once;
 
 use crate::fs::inode::{DirEntry, INodeInterface};
+use crate::mem::shrinker::{self, Shrinker};
+use crate::mem::slab::{self, SlabCache};
 use crate::utils::sync::BMutex;
 
 use super::path::PathBuf;
@@ -43,6 +46,42 @@ use super::FileSystem;
 
 pub static INODE_CACHE: Once<Arc<INodeCache>> = Once::new();
 pub static DIR_CACHE: Once<Arc<DirCache>> = Once::new();
+pub static NEGATIVE_DIR_CACHE: Once<NegativeDirCache> = Once::new();
+
+/// Inodes whose on-disk inode was modified in memory and has to be written back, keyed like the
+/// inode cache. Holding on to them keeps them in use, so they are not evicted from the inode
+/// cache before they have been written back.
+static DIRTY_INODES: BMutex<BTreeMap<INodeCacheKey, INodeCacheItem>> = BMutex::new(BTreeMap::new());
+
+static INODE_SLAB: SlabCache = SlabCache::new(
+    "inode",
+    slab::arc_layout::<CacheItem<INodeCacheKey, CachedINode>>(),
+);
+static DIR_SLAB: SlabCache = SlabCache::new(
+    "dentry",
+    slab::arc_layout::<CacheItem<DirCacheKey, DirEntry>>(),
+);
+
+// NOTE: The directory cache shrinker is registered first, since evicting a directory entry
+// drops its reference to the inode and to the parent directory entry.
+static DIR_SHRINKER: Shrinker = Shrinker::new(
+    "dentry",
+    || scaled_by_pressure(dcache().unused_count() + ndcache().count()),
+    |count| {
+        let freed = dcache().shrink(count, |_| true);
+        freed + ndcache().shrink(count - freed)
+    },
+);
+static INODE_SHRINKER: Shrinker = Shrinker::new(
+    "inode",
+    || scaled_by_pressure(icache().unused_count()),
+    |count| icache().shrink(count, |_| true),
+);
+
+/// Tendency of the kernel to reclaim the memory used by the inode and directory caches under
+/// memory pressure, as a percentage (see `/proc/sys/vm/vfs_cache_pressure`). At `0`, the caches
+/// are never shrunk and only evict their least recently used unused items once they are full.
+static CACHE_PRESSURE: AtomicUsize = AtomicUsize::new(100);
 
 // NOTE: We require a custom wrapper around [`Arc`] and [`Weak`] since we need to be able
 // to move the cache item from the used list to the unused list when the cache item is dropped.
@@ -50,12 +89,6 @@ pub static DIR_CACHE: Once<Arc<DirCache>> = Once::new();
 pub struct CacheArc<T: CacheDropper>(Arc<T>);
 
 impl<T: CacheDropper> CacheArc<T> {
-    /// Constructs a new `CacheArc<T>`.
-    #[inline]
-    pub fn new(data: T) -> Self {
-        Self(Arc::new(data))
-    }
-
     pub fn downgrade(&self) -> CacheWeak<T> {
         CacheWeak(Arc::downgrade(&self.0))
     }
@@ -155,12 +188,15 @@ pub struct CacheItem<K: CacheKey, V: Cacheable<K>> {
 }
 
 impl<K: CacheKey, V: Cacheable<K>> CacheItem<K, V> {
-    pub fn new(cache: &Weak<Cache<K, V>>, value: V) -> CacheArc<Self> {
-        CacheArc::new(Self {
+    /// Creates a new cache item, which is allocated from the provided object cache.
+    pub fn new(cache: &Weak<Cache<K, V>>, slab: &'static SlabCache, value: V) -> CacheArc<Self> {
+        let item = Self {
             cache: cache.clone(),
             value,
             used: AtomicBool::new(false),
-        })
+        };
+
+        CacheArc::from(slab.alloc_with(|| Arc::new(item)))
     }
 
     pub fn is_used(&self) -> bool {
@@ -194,16 +230,19 @@ struct CacheIndex<K: CacheKey, V: Cacheable<K>> {
 pub struct Cache<K: CacheKey, V: Cacheable<K>> {
     index: BMutex<CacheIndex<K, V>>,
     self_ref: Weak<Cache<K, V>>,
+    /// Object cache that the cache items are allocated from.
+    slab: &'static SlabCache,
 }
 
 impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
-    pub fn new() -> Arc<Self> {
+    pub fn new(slab: &'static SlabCache) -> Arc<Self> {
         Arc::new_cyclic(|this| Cache::<K, V> {
             index: BMutex::new(CacheIndex {
                 used: hashbrown::HashMap::new(),
                 unused: lru::LruCache::new(NonZeroUsize::new(4096).unwrap()),
             }),
             self_ref: this.clone(),
+            slab,
         })
     }
 
@@ -215,7 +254,7 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
     }
 
     pub fn make_item_cached(&self, value: V) -> CacheArc<CacheItem<K, V>> {
-        let item = CacheItem::<K, V>::new(&self.self_ref, value);
+        let item = CacheItem::<K, V>::new(&self.self_ref, self.slab, value);
 
         self.index
             .lock()
@@ -227,7 +266,7 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
     }
 
     pub fn make_item_no_cache(&self, value: V) -> CacheArc<CacheItem<K, V>> {
-        CacheItem::<K, V>::new(&Weak::default(), value)
+        CacheItem::<K, V>::new(&Weak::default(), self.slab, value)
     }
 
     pub fn get(&self, key: K) -> Option<CacheArc<CacheItem<K, V>>> {
@@ -276,6 +315,65 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
         }
     }
 
+    /// Evicts up to `count` of the least recently used items, that do not have any active
+    /// strong references and for which `filter` returns `true`, from the cache. Returns the
+    /// number of evicted items.
+    pub fn shrink<F>(&self, count: usize, filter: F) -> usize
+    where
+        F: Fn(&V) -> bool,
+    {
+        let evicted = {
+            let mut index = self.index.lock();
+
+            let keys = index
+                .unused
+                .iter()
+                .rev()
+                .filter(|(_, item)| filter(item))
+                .take(count)
+                .map(|(_, item)| item.cache_key())
+                .collect::<Vec<_>>();
+
+            keys.iter()
+                .filter_map(|key| index.unused.pop(key))
+                .collect::<Vec<_>>()
+        };
+
+        // NOTE: The items are dropped after the index lock is released, as dropping them
+        // might write them back.
+        evicted.len()
+    }
+
+    /// Returns the items, used or not, for which `filter` returns `true`.
+    pub fn items<F>(&self, filter: F) -> Vec<CacheArc<CacheItem<K, V>>>
+    where
+        F: Fn(&V) -> bool,
+    {
+        let items = {
+            let index = self.index.lock();
+
+            index
+                .used
+                .values()
+                .filter_map(Weak::upgrade)
+                .chain(index.unused.iter().map(|(_, item)| item.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // NOTE: The items are filtered after the index lock is released, as dropping the
+        // last reference to a used item marks it unused, which takes the lock.
+        items
+            .into_iter()
+            .map(CacheArc::from)
+            .filter(|item| filter(&item.value))
+            .collect()
+    }
+
+    /// Returns the number of items that do not have any active strong references.
+    pub fn unused_count(&self) -> usize {
+        self.index.lock().unused.len()
+    }
+
     /// Removes the item with the provided `key` from the cache.
     pub fn remove(&self, key: &K) {
         let mut index = self.index.lock();
@@ -315,6 +413,53 @@ pub type DirCacheKey = (usize, String);
 pub type DirCache = Cache<DirCacheKey, DirEntry>;
 pub type DirCacheItem = CacheArc<CacheItem<DirCacheKey, DirEntry>>;
 
+/// Names that are known to not exist in a directory (negative directory entries), keyed like
+/// the directory cache. This avoids scanning the directory again on repeated lookups of
+/// nonexistent names, which are very common while searching `PATH` and library paths.
+///
+/// Negative entries are only created in filesystems that can only be modified through the VFS
+/// (see [`FileSystem::caches_negative_entries`]) and are invalidated when a directory entry with
+/// the same name is created (see [`crate::fs::inode::invalidate_negative_dir_entry`]).
+pub struct NegativeDirCache(BMutex<lru::LruCache<DirCacheKey, ()>>);
+
+impl NegativeDirCache {
+    fn new() -> Self {
+        Self(BMutex::new(lru::LruCache::new(
+            NonZeroUsize::new(1024).unwrap(),
+        )))
+    }
+
+    pub fn contains(&self, key: &DirCacheKey) -> bool {
+        self.0.lock().get(key).is_some()
+    }
+
+    pub fn insert(&self, key: DirCacheKey) {
+        self.0.lock().put(key, ());
+    }
+
+    pub fn remove(&self, key: &DirCacheKey) {
+        self.0.lock().pop(key);
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().clear();
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.lock().len()
+    }
+
+    /// Evicts up to `count` of the least recently used negative entries. Returns the number of
+    /// evicted entries.
+    pub fn shrink(&self, count: usize) -> usize {
+        let mut entries = self.0.lock();
+
+        (0..count)
+            .take_while(|_| entries.pop_lru().is_some())
+            .count()
+    }
+}
+
 impl Debug for DirCacheItem {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("DirCacheItem")
@@ -410,6 +555,10 @@ pub fn clear_dir_cache() {
     if let Some(cache) = DIR_CACHE.get() {
         cache.clear()
     }
+
+    if let Some(cache) = NEGATIVE_DIR_CACHE.get() {
+        cache.clear()
+    }
 }
 
 pub fn icache() -> &'static Arc<INodeCache> {
@@ -424,8 +573,70 @@ pub fn dcache() -> &'static Arc<DirCache> {
         .expect("`dcache` was invoked before it was initialized")
 }
 
+pub fn ndcache() -> &'static NegativeDirCache {
+    NEGATIVE_DIR_CACHE
+        .get()
+        .expect("`ndcache` was invoked before it was initialized")
+}
+
+pub fn cache_pressure() -> usize {
+    CACHE_PRESSURE.load(Ordering::Relaxed)
+}
+
+pub fn set_cache_pressure(pressure: usize) {
+    CACHE_PRESSURE.store(pressure, Ordering::Relaxed);
+}
+
+/// Marks the cached inode with the provided `key` dirty, so it is written back by the next
+/// writeback (see [`writeback_inodes`]).
+pub fn mark_inode_dirty(key: INodeCacheKey) {
+    if let Some(inode) = icache().get(key) {
+        DIRTY_INODES.lock().insert(key, inode);
+    }
+}
+
+/// Writes back the dirty inodes (see [`INodeInterface::write_inode`]). Returns the number of
+/// inodes written back.
+pub fn writeback_inodes() -> usize {
+    let dirty = core::mem::take(&mut *DIRTY_INODES.lock());
+    let count = dirty.len();
+
+    for (key, inode) in dirty {
+        if let Err(err) = inode.write_inode() {
+            log::warn!("writeback: failed to write back inode {key:?}: {err:?}");
+        }
+    }
+
+    count
+}
+
+/// Evicts all of the unused entries from the directory and inode caches (see
+/// `/proc/sys/vm/drop_caches`). Returns the number of evicted entries.
+pub fn drop_caches() -> usize {
+    let mut freed = ndcache().shrink(usize::MAX);
+
+    // Evicting a directory entry drops its reference to the parent directory entry, which
+    // might leave the parent unused as well.
+    loop {
+        match dcache().shrink(usize::MAX, |_| true) {
+            0 => break,
+            count => freed += count,
+        }
+    }
+
+    freed + icache().shrink(usize::MAX, |_| true)
+}
+
+fn scaled_by_pressure(count: usize) -> usize {
+    count.saturating_mul(cache_pressure()) / 100
+}
+
 /// This function is responsible for initializing the inode cache.
 pub fn init() {
-    INODE_CACHE.call_once(INodeCache::new);
-    DIR_CACHE.call_once(DirCache::new);
+    INODE_CACHE.call_once(|| INodeCache::new(&INODE_SLAB));
+    DIR_CACHE.call_once(|| DirCache::new(&DIR_SLAB));
+    NEGATIVE_DIR_CACHE.call_once(NegativeDirCache::new);
+
+    shrinker::register(&DIR_SHRINKER);
+    shrinker::register(&INODE_SHRINKER);
 }
@@ -0,0 +1,503 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The control group filesystem, mounted at `/sys/fs/cgroup`. Each directory is a control group
+//! (see [`crate::userland::task::cgroups`]); creating a directory creates a child group and
+//! removing it removes the group. The interface files follow the cgroup v2 layout:
+//!
+//! * `cgroup.procs`: the PIDs of the processes in the group. Writing a PID moves the process
+//!   into the group.
+//! * `cpu.weight`: the relative CPU weight of the group (`1..=10000`, `100` by default).
+//! * `cpu.max`: `$QUOTA $PERIOD` in microseconds; `$QUOTA` is `max` if not limited.
+//! * `cpu.stat`: the CPU time used by the group and the number of times it was throttled.
+//! * `memory.max`: the memory limit in bytes or `max` if not limited.
+//! * `memory.current`: the resident memory of the group in bytes.
+//! * `memory.events`: the number of processes killed because the limit was reached.
+//!
+//! The limit files are not present in the root group.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+
+use spin::{Once, RwLock};
+
+use crate::fs;
+use crate::fs::inode::FileType;
+use crate::mem::paging::*;
+
+use crate::userland::scheduler;
+use crate::userland::task::cgroups::{CGroup, CGroupError, CpuMax};
+
+use super::cache::*;
+use super::{cache, FileSystem, Path, MOUNT_MANAGER};
+
+use super::inode::{DirEntry, INodeInterface, Metadata};
+use super::FileSystemError;
+
+impl From<CGroupError> for FileSystemError {
+    fn from(error: CGroupError) -> Self {
+        match error {
+            CGroupError::Exists => Self::EntryExists,
+            CGroupError::Busy => Self::Busy,
+            CGroupError::Root => Self::PermissionDenied,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ControlFile {
+    Procs,
+    CpuWeight,
+    CpuMax,
+    CpuStat,
+    MemoryMax,
+    MemoryCurrent,
+    MemoryEvents,
+}
+
+impl ControlFile {
+    /// Interface files present in every control group.
+    const COMMON: &'static [ControlFile] = &[Self::Procs, Self::CpuStat, Self::MemoryCurrent];
+
+    /// Interface files only present in the non-root control groups.
+    const LIMITS: &'static [ControlFile] = &[
+        Self::CpuWeight,
+        Self::CpuMax,
+        Self::MemoryMax,
+        Self::MemoryEvents,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Procs => "cgroup.procs",
+            Self::CpuWeight => "cpu.weight",
+            Self::CpuMax => "cpu.max",
+            Self::CpuStat => "cpu.stat",
+            Self::MemoryMax => "memory.max",
+            Self::MemoryCurrent => "memory.current",
+            Self::MemoryEvents => "memory.events",
+        }
+    }
+
+    fn read(&self, group: &CGroup) -> String {
+        match self {
+            Self::Procs => group
+                .pids(scheduler::current_thread().pid_ns())
+                .iter()
+                .map(|pid| alloc::format!("{pid}\n"))
+                .collect(),
+
+            Self::CpuWeight => alloc::format!("{}\n", group.cpu_weight()),
+
+            Self::CpuMax => {
+                let max = group.cpu_max();
+
+                match max.quota {
+                    Some(quota) => alloc::format!("{} {}\n", quota, max.period),
+                    None => alloc::format!("max {}\n", max.period),
+                }
+            }
+
+            Self::CpuStat => alloc::format!(
+                "usage_usec {}\nnr_throttled {}\n",
+                group.cpu_usage() / 1000,
+                group.nr_throttled()
+            ),
+
+            Self::MemoryMax => match group.memory_max() {
+                usize::MAX => String::from("max\n"),
+                pages => alloc::format!("{}\n", pages * Size4KiB::SIZE as usize),
+            },
+
+            Self::MemoryCurrent => {
+                alloc::format!("{}\n", group.memory_usage() * Size4KiB::SIZE as usize)
+            }
+
+            Self::MemoryEvents => alloc::format!("oom_kill {}\n", group.oom_kills()),
+        }
+    }
+
+    fn write(&self, group: &Arc<CGroup>, value: &str) -> fs::Result<()> {
+        match self {
+            Self::Procs => {
+                let pid = parse::<usize>(value)?;
+                let task = scheduler::current_thread()
+                    .pid_ns()
+                    .find_task(pid)
+                    .filter(|task| task.is_process_leader() && !task.has_exited())
+                    .ok_or(FileSystemError::EntryNotFound)?;
+
+                group.attach(&task);
+            }
+
+            Self::CpuWeight => {
+                if !group.set_cpu_weight(parse(value)?) {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+            }
+
+            Self::CpuMax => {
+                let mut fields = value.split_whitespace();
+
+                let quota = match fields.next() {
+                    Some("max") => None,
+                    Some(quota) => Some(parse::<u64>(quota)?),
+                    None => return Err(FileSystemError::InvalidArgument),
+                };
+
+                // The period is left unchanged if not provided.
+                let period = match fields.next() {
+                    Some(period) => parse::<u64>(period)?,
+                    None => group.cpu_max().period,
+                };
+
+                if quota == Some(0) || period == 0 || fields.next().is_some() {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                group.set_cpu_max(CpuMax { quota, period })?;
+            }
+
+            Self::MemoryMax => {
+                let pages = match value {
+                    "max" => usize::MAX,
+                    bytes => parse::<usize>(bytes)? / Size4KiB::SIZE as usize,
+                };
+
+                group.set_memory_max(pages)?;
+            }
+
+            Self::CpuStat | Self::MemoryCurrent | Self::MemoryEvents => {
+                return Err(FileSystemError::NotSupported)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse<T: core::str::FromStr>(value: &str) -> fs::Result<T> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| FileSystemError::InvalidArgument)
+}
+
+enum FileContents {
+    /// The directory of the control group.
+    Group(Arc<CGroup>),
+    /// An interface file of the control group.
+    Control(Arc<CGroup>, ControlFile),
+}
+
+struct CGroupINode {
+    id: usize,
+    parent: INodeCacheWeakItem,
+    node: INodeCacheWeakItem,
+    children: BTreeMap<String, INodeCacheItem>,
+    filesystem: Weak<CGroupFs>,
+    file_type: FileType,
+    contents: FileContents,
+}
+
+struct LockedCGroupINode(RwLock<CGroupINode>);
+
+impl LockedCGroupINode {
+    fn new(node: CGroupINode) -> Self {
+        Self(RwLock::new(node))
+    }
+
+    fn init(
+        &self,
+        parent: &INodeCacheWeakItem,
+        node: &INodeCacheWeakItem,
+        filesystem: &Weak<CGroupFs>,
+    ) {
+        let mut this = self.0.write();
+
+        this.parent = parent.clone();
+        this.node = node.clone();
+        this.filesystem = filesystem.clone();
+    }
+
+    fn make_inode(&self, name: &str, contents: FileContents) -> fs::Result<INodeCacheItem> {
+        let icache = cache::icache();
+        let mut this = self.0.write();
+
+        if this.children.contains_key(name) || ["", ".", ".."].contains(&name) {
+            return Err(FileSystemError::EntryExists);
+        }
+
+        let filesystem = this.filesystem.upgrade().unwrap();
+
+        let inode = filesystem.allocate_inode(contents);
+        let inode_cached = icache.make_item_no_cache(CachedINode::new(inode.clone()));
+
+        inode.init(&this.node, &inode_cached.downgrade(), &this.filesystem);
+
+        this.children
+            .insert(String::from(name), inode_cached.clone());
+
+        Ok(inode_cached)
+    }
+
+    /// Creates the interface files of the provided control group in this directory.
+    fn populate(&self, group: &Arc<CGroup>) -> fs::Result<()> {
+        let limits: &[ControlFile] = if group.is_root() {
+            &[]
+        } else {
+            ControlFile::LIMITS
+        };
+
+        for file in ControlFile::COMMON.iter().chain(limits) {
+            self.make_inode(file.name(), FileContents::Control(group.clone(), *file))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl INodeInterface for LockedCGroupINode {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let this = self.0.read();
+
+        let FileContents::Control(group, file) = &this.contents else {
+            return Err(FileSystemError::IsDir);
+        };
+
+        let data = file.read(group);
+
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), data.len() - offset);
+        buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
+
+        Ok(count)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let this = self.0.read();
+
+        let FileContents::Control(group, file) = &this.contents else {
+            return Err(FileSystemError::IsDir);
+        };
+
+        let value = core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+        file.write(group, value.trim())?;
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, _size: usize) -> fs::Result<()> {
+        // Shell redirections open the interface files with `O_TRUNC`; there is nothing to
+        // truncate.
+        Ok(())
+    }
+
+    fn mkdir(&self, name: &str) -> fs::Result<INodeCacheItem> {
+        let group = match &self.0.read().contents {
+            FileContents::Group(group) => group.clone(),
+            FileContents::Control(..) => return Err(FileSystemError::NotDirectory),
+        };
+
+        if self.0.read().children.contains_key(name) {
+            return Err(FileSystemError::EntryExists);
+        }
+
+        let child = group.create(name)?;
+        let inode = self.make_inode(name, FileContents::Group(child.clone()))?;
+
+        inode
+            .downcast_arc::<LockedCGroupINode>()
+            .unwrap()
+            .populate(&child)?;
+
+        Ok(inode)
+    }
+
+    fn rmdir(&self, name: &str) -> fs::Result<()> {
+        let this = self.0.read();
+
+        let FileContents::Group(group) = &this.contents else {
+            return Err(FileSystemError::NotDirectory);
+        };
+
+        group.remove()?;
+
+        // UNWRAP: The root group cannot be removed, so the parent must be alive.
+        let parent = this.parent.upgrade().unwrap();
+        core::mem::drop(this);
+
+        parent
+            .downcast_arc::<LockedCGroupINode>()
+            .unwrap()
+            .0
+            .write()
+            .children
+            .remove(name);
+
+        Ok(())
+    }
+
+    fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        let this = self.0.read();
+        let child = this
+            .children
+            .get(name)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        Ok(DirEntry::new(dir, child.clone(), String::from(name)))
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        let this = self.0.read();
+
+        Ok(Metadata {
+            id: this.id,
+            file_type: this.file_type,
+            size: 0,
+            children_len: this.children.len(),
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> fs::Result<Option<DirCacheItem>> {
+        let this = self.0.read();
+
+        if this.file_type != FileType::Directory {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        Ok(match index {
+            0x00 => Some(DirEntry::new(
+                parent,
+                // UNWRAP: The inner node value should not be dropped.
+                this.node.upgrade().unwrap(),
+                String::from("."),
+            )),
+
+            0x01 => Some(DirEntry::new(
+                parent,
+                // UNWRAP: The parent node value should not be dropped.
+                this.parent.upgrade().unwrap(),
+                String::from(".."),
+            )),
+
+            // Subtract two because of the "." and ".." entries.
+            _ => this
+                .children
+                .iter()
+                .nth(index - 2)
+                .map(|(name, inode)| DirEntry::new(parent, inode.clone(), name.clone())),
+        })
+    }
+
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.0.read().filesystem.clone())
+    }
+}
+
+struct CGroupFs {
+    root_inode: INodeCacheItem,
+    root_dir: DirCacheItem,
+    next_id: AtomicUsize,
+}
+
+impl CGroupFs {
+    fn new() -> fs::Result<Arc<Self>> {
+        let icache = cache::icache();
+
+        let root = CGroup::root();
+        let root_node = Arc::new(LockedCGroupINode::new(CGroupINode {
+            id: 0,
+            parent: CacheWeak::new(),
+            node: CacheWeak::new(),
+            children: BTreeMap::new(),
+            filesystem: Weak::default(),
+            file_type: FileType::Directory,
+            contents: FileContents::Group(root.clone()),
+        }));
+        let root_cached = icache.make_item_no_cache(CachedINode::new(root_node.clone()));
+
+        let root_dir = DirEntry::new_root(root_cached.clone(), String::from("/"));
+
+        let fs = Arc::new(Self {
+            root_inode: root_cached.clone(),
+            root_dir: root_dir.clone(),
+            next_id: AtomicUsize::new(1),
+        });
+
+        let copy: Arc<dyn FileSystem> = fs.clone();
+        root_dir.filesystem.call_once(|| Arc::downgrade(&copy));
+
+        root_node.init(
+            &fs.root_inode.downgrade(),
+            &root_cached.downgrade(),
+            &Arc::downgrade(&fs),
+        );
+
+        root_node.populate(root)?;
+        Ok(fs)
+    }
+
+    fn allocate_inode(&self, contents: FileContents) -> Arc<LockedCGroupINode> {
+        let file_type = match contents {
+            FileContents::Group(_) => FileType::Directory,
+            FileContents::Control(..) => FileType::File,
+        };
+
+        Arc::new(LockedCGroupINode::new(CGroupINode {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            parent: CacheWeak::new(),
+            node: CacheWeak::new(),
+            children: BTreeMap::new(),
+            filesystem: Weak::default(),
+            file_type,
+            contents,
+        }))
+    }
+}
+
+impl FileSystem for CGroupFs {
+    #[inline]
+    fn root_dir(&self) -> DirCacheItem {
+        self.root_dir.clone()
+    }
+}
+
+static CGROUP_FS: Once<Arc<CGroupFs>> = Once::new();
+
+pub fn init() -> fs::Result<()> {
+    let fs = CGroupFs::new()?;
+    let fs = CGROUP_FS.call_once(|| fs);
+
+    let inode = match super::lookup_path(Path::new("/sys/fs/cgroup")) {
+        Ok(inode) => inode,
+        Err(FileSystemError::EntryNotFound) => {
+            log::warn!("cgroupfs: `/sys/fs/cgroup` does not exist; not mounting");
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+
+    MOUNT_MANAGER.mount(inode, fs.clone())?;
+    Ok(())
+}
@@ -25,18 +25,18 @@ use alloc::sync::Arc;
 
 use spin::{Once, RwLock};
 
+use crate::drivers::modeset::{self, VideoMode};
 use crate::fs::{lookup_path, Path};
 use crate::logger;
 use crate::mem::paging::*;
-use crate::rendy::RendyInfo;
 
 use super::cache::{DirCacheItem, INodeCacheItem};
-use super::inode::{INodeInterface, MMapPage, PollFlags, PollTable};
+use super::inode::{self, INodeInterface, MMapPage, PollFlags, PollTable};
 use super::ramfs::RamFs;
 use super::{FileSystem, FileSystemError, Result, MOUNT_MANAGER};
 
 use aero_syscall::prelude::*;
-use aero_syscall::MMapFlags;
+use aero_syscall::{MMapFlags, Mode};
 
 lazy_static::lazy_static! {
     pub static ref DEV_FILESYSTEM: Arc<DevFs> = DevFs::new();
@@ -49,6 +49,25 @@ pub fn alloc_device_marker() -> usize {
     DEVICE_MARKER.fetch_add(1, Ordering::SeqCst)
 }
 
+/// The device number of a device, used to refer to it from a device node stored on another
+/// filesystem (see [`find_device`]). Character and block devices have separate namespaces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceNumber {
+    Char(u32, u32),
+    Block(u32, u32),
+}
+
+impl DeviceNumber {
+    /// Returns the device number encoded as a `dev_t` (see [`aero_syscall::makedev`]).
+    pub fn dev(&self) -> u64 {
+        match *self {
+            Self::Char(major, minor) | Self::Block(major, minor) => {
+                aero_syscall::makedev(major, minor)
+            }
+        }
+    }
+}
+
 /// A trait representing a device. A device has a device marker (or a device ID) and the
 /// device name (which is used in the creation of the device inode in the device filesystem).
 pub trait Device: Send + Sync {
@@ -60,6 +79,22 @@ pub trait Device: Send + Sync {
     /// information.)
     fn device_name(&self) -> String;
     fn inode(&self) -> Arc<dyn INodeInterface>;
+
+    /// Returns the device number of this device. Devices without a device number can only be
+    /// opened through their inode in the device filesystem.
+    fn device_number(&self) -> Option<DeviceNumber> {
+        None
+    }
+}
+
+/// Looks up the device with the provided device `number` in the global [DEVICES] b-tree map,
+/// returning its device inode.
+pub fn find_device(number: DeviceNumber) -> Option<Arc<DevINode>> {
+    DEVICES
+        .read()
+        .values()
+        .find(|device| device.device_number() == Some(number))
+        .map(|device| Arc::new(DevINode(device.clone())))
 }
 
 /// Installs the provided `device` in the device filesystem (ie. in /dev/) and the
@@ -89,6 +124,28 @@ pub fn install_device_at(at: INodeCacheItem, device: Arc<dyn Device>) -> Result<
     Ok(())
 }
 
+/// Removes the provided `device` from the device filesystem and the global [DEVICES] b-tree
+/// map. Files that are already open keep referring to the device.
+pub fn uninstall_device(device: &dyn Device) -> Result<()> {
+    let device_name = device.device_name();
+
+    DEVICES
+        .write()
+        .remove(&device.device_marker())
+        .ok_or(FileSystemError::EntryNotFound)?;
+
+    let root = DEV_FILESYSTEM.root_dir();
+
+    if let Some(entry) = inode::fetch_dir_entry(&root, device_name.clone()) {
+        entry.drop_from_cache();
+    }
+
+    root.inode().unlink(&device_name)?;
+    log::debug!("uninstalled device `{}`", device_name);
+
+    Ok(())
+}
+
 /// Structure representing a device inode. This is internally used by ram-fs
 /// to create a new inode with the file type of `device` and its contents as a
 /// reference-counting pointer to the device itself.
@@ -125,6 +182,10 @@ impl INodeInterface for DevINode {
         self.0.inode().ioctl(command, arg)
     }
 
+    fn fsync(&self) -> Result<()> {
+        self.0.inode().fsync()
+    }
+
     fn poll(&self, table: Option<&mut PollTable>) -> Result<PollFlags> {
         self.0.inode().poll(table)
     }
@@ -136,6 +197,27 @@ impl INodeInterface for DevINode {
     fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
         self.0.inode().mmap_v2(offset)
     }
+
+    fn stat(&self) -> Result<aero_syscall::Stat> {
+        let mut stat = aero_syscall::Stat::default();
+        stat.st_mode = Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IRGRP | Mode::S_IWGRP;
+
+        match self.0.device_number() {
+            Some(number @ DeviceNumber::Char(..)) => {
+                stat.st_mode.insert(Mode::S_IFCHR);
+                stat.st_rdev = number.dev();
+            }
+
+            Some(number @ DeviceNumber::Block(..)) => {
+                stat.st_mode.insert(Mode::S_IFBLK);
+                stat.st_rdev = number.dev();
+            }
+
+            None => {}
+        }
+
+        Ok(stat)
+    }
 }
 
 /// Implementation of dev filesystem. (See the module-level documentation for more
@@ -175,6 +257,10 @@ impl Device for DevNull {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         DEV_NULL.get().expect("device not initialized").clone()
     }
+
+    fn device_number(&self) -> Option<DeviceNumber> {
+        Some(DeviceNumber::Char(1, 3))
+    }
 }
 
 impl INodeInterface for DevNull {
@@ -211,6 +297,10 @@ impl Device for DevKmsg {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         DEV_KMSG.get().expect("device not initialized").clone()
     }
+
+    fn device_number(&self) -> Option<DeviceNumber> {
+        Some(DeviceNumber::Char(1, 11))
+    }
 }
 
 impl INodeInterface for DevKmsg {
@@ -228,61 +318,103 @@ impl INodeInterface for DevKmsg {
     }
 }
 
+/// Runs `f` with the framebuffer memory, as bytes.
+fn with_framebuffer<R>(f: impl FnOnce(&mut [u8]) -> R) -> R {
+    let mut lock = crate::rendy::DEBUG_RENDY
+        .get()
+        .expect("/dev/fb: terminal not initialized")
+        .lock_irq();
+
+    let fb = lock.get_framebuffer();
+
+    // SAFETY: The framebuffer is made of plain bytes and the lock is held for as long as the
+    // slice is used.
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(fb.as_mut_ptr().cast::<u8>(), mem::size_of_val(fb))
+    };
+
+    f(bytes)
+}
+
+/// Returns the physical address of the framebuffer.
+fn framebuffer_address() -> PhysAddr {
+    crate::rendy::get_framebuffer_address()
+}
+
 struct DevFb {
     marker: usize,
-    vinfo: RwLock<FramebufferVScreenInfo>,
-    finfo: FramebufferFScreenInfo,
 }
 
 impl DevFb {
-    fn new(info: RendyInfo) -> Arc<Self> {
+    fn new() -> Arc<Self> {
         Arc::new(Self {
             marker: alloc_device_marker(),
-            vinfo: RwLock::new(FramebufferVScreenInfo {
-                xres: info.horizontal_resolution as u32,
-                yres: info.vertical_resolution as u32,
+        })
+    }
 
-                xres_virtual: info.horizontal_resolution as u32,
-                yres_virtual: info.vertical_resolution as u32,
+    /// Returns the variable screen info for the current video mode.
+    fn vinfo(&self) -> FramebufferVScreenInfo {
+        let info = crate::rendy::get_rendy_info();
 
-                width: u32::MAX,  // -1
-                height: u32::MAX, // -1
+        FramebufferVScreenInfo {
+            xres: info.horizontal_resolution as u32,
+            yres: info.vertical_resolution as u32,
 
-                red: FramebufferBitField::new(
-                    info.red_mask_shift as u32,
-                    info.red_mask_size as u32,
-                ),
+            xres_virtual: info.horizontal_resolution as u32,
+            yres_virtual: info.vertical_resolution as u32,
 
-                green: FramebufferBitField::new(
-                    info.green_mask_shift as u32,
-                    info.green_mask_size as u32,
-                ),
+            width: u32::MAX,  // -1
+            height: u32::MAX, // -1
 
-                blue: FramebufferBitField::new(
-                    info.blue_mask_shift as u32,
-                    info.blue_mask_size as u32,
-                ),
+            red: FramebufferBitField::new(info.red_mask_shift as u32, info.red_mask_size as u32),
 
-                transp: FramebufferBitField::new(0, 0),
-                bits_per_pixel: info.bits_per_pixel as u32,
+            green: FramebufferBitField::new(
+                info.green_mask_shift as u32,
+                info.green_mask_size as u32,
+            ),
 
-                activate: FB_ACTIVATE_NOW,
-                vmode: FB_VMODE_NONINTERLACED,
+            blue: FramebufferBitField::new(info.blue_mask_shift as u32, info.blue_mask_size as u32),
 
-                // TODO: Implement rest of the members
-                ..Default::default()
-            }),
+            transp: FramebufferBitField::new(0, 0),
+            bits_per_pixel: info.bits_per_pixel as u32,
 
-            finfo: FramebufferFScreenInfo {
-                smem_len: (info.stride * info.vertical_resolution) as u32,
-                line_length: info.stride as u32,
+            activate: FB_ACTIVATE_NOW,
+            vmode: FB_VMODE_NONINTERLACED,
 
-                typee: FB_TYPE_PACKED_PIXELS,
-                visual: FB_VISUAL_TRUECOLOR,
+            // The timings of the display mode are not known.
+            ..Default::default()
+        }
+    }
 
-                ..Default::default()
-            },
-        })
+    /// Returns the fixed screen info for the current video mode.
+    fn finfo(&self) -> FramebufferFScreenInfo {
+        let info = crate::rendy::get_rendy_info();
+
+        let mut id = [0; 16];
+        let name = modeset::backend_name().as_bytes();
+        id[..name.len()].copy_from_slice(name);
+
+        FramebufferFScreenInfo {
+            id,
+
+            smem_start: framebuffer_address().as_u64(),
+            smem_len: info.byte_len as u32,
+            line_length: info.stride as u32,
+
+            typee: FB_TYPE_PACKED_PIXELS,
+            visual: FB_VISUAL_TRUECOLOR,
+
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the provided screen info describes a mode that can be switched to. The
+    /// virtual resolution must match the visible resolution as panning is not supported.
+    fn is_valid_mode(info: &FramebufferVScreenInfo) -> bool {
+        (info.xres_virtual == 0 || info.xres_virtual == info.xres)
+            && (info.yres_virtual == 0 || info.yres_virtual == info.yres)
+            && info.xoffset == 0
+            && info.yoffset == 0
     }
 }
 
@@ -298,112 +430,140 @@ impl Device for DevFb {
     fn inode(&self) -> Arc<dyn INodeInterface> {
         DEV_FB.get().expect("device not initialized").clone()
     }
+
+    fn device_number(&self) -> Option<DeviceNumber> {
+        Some(DeviceNumber::Char(29, 0))
+    }
 }
 
 impl INodeInterface for DevFb {
-    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
-        crate::rendy::DEBUG_RENDY
-            .get()
-            .map(|e| {
-                let mut lock = e.lock_irq();
-                let fb = lock.get_framebuffer();
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        with_framebuffer(|fb| {
+            if offset >= fb.len() {
+                return Ok(0);
+            }
 
-                let mut count = buffer.len();
+            let count = buffer.len().min(fb.len() - offset);
+            buffer[..count].copy_from_slice(&fb[offset..offset + count]);
 
-                if offset + buffer.len() > fb.len() {
-                    count = buffer.len() - ((offset + buffer.len()) - fb.len());
-                }
+            Ok(count)
+        })
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        with_framebuffer(|fb| {
+            if offset >= fb.len() {
+                return Err(FileSystemError::TooSmall);
+            }
 
-                let raw = buffer.as_ptr().cast::<u32>();
-                let src = unsafe { core::slice::from_raw_parts(raw, count) };
+            let count = buffer.len().min(fb.len() - offset);
+            fb[offset..offset + count].copy_from_slice(&buffer[..count]);
 
-                fb[offset..offset + count].copy_from_slice(src);
-                Ok(count)
-            })
-            .expect("/dev/fb: terminal not initialized")
+            Ok(count)
+        })
     }
 
     fn mmap(&self, offset: usize, size: usize, flags: MMapFlags) -> Result<PhysFrame> {
         let rinfo = crate::rendy::get_rendy_info();
 
-        // Make sure we are in bounds.
-        if offset > rinfo.byte_len || offset + Size4KiB::SIZE as usize > rinfo.byte_len {
+        // Make sure we are in bounds. The last page is only partially backed by the
+        // framebuffer if its size is not page aligned.
+        if offset >= rinfo.byte_len {
             return Ok(PhysFrame::containing_address(PhysAddr::zero()));
         }
 
-        crate::rendy::DEBUG_RENDY
-            .get()
-            .map(|e| unsafe {
-                let mut lock = e.lock_irq();
-
-                if flags.contains(MMapFlags::MAP_SHARED) {
-                    // This is a shared file mapping.
-                    let fb = lock.get_framebuffer();
-
-                    let fb_ptr = fb.as_ptr().cast::<u8>();
-                    let fb_ptr = fb_ptr.add(offset);
-
-                    let fb_phys_ptr = fb_ptr.sub(crate::PHYSICAL_MEMORY_OFFSET.as_u64() as usize);
+        if flags.contains(MMapFlags::MAP_SHARED) {
+            // This is a shared file mapping.
+            return Ok(PhysFrame::containing_address(
+                framebuffer_address() + offset,
+            ));
+        }
 
-                    Ok(PhysFrame::containing_address(PhysAddr::new_unchecked(
-                        fb_phys_ptr as u64,
-                    )))
-                } else {
-                    let fb = lock.get_framebuffer();
+        // This is a private file mapping.
+        let private_cp: PhysFrame = FRAME_ALLOCATOR
+            .allocate_frame()
+            .ok_or(FileSystemError::OutOfMemory)?;
 
-                    // This is a private file mapping.
-                    let private_cp: PhysFrame = FRAME_ALLOCATOR.allocate_frame().unwrap();
-                    private_cp.as_slice_mut()[..size].copy_from_slice(&fb[offset..offset + size]);
+        with_framebuffer(|fb| {
+            let size = size.min(fb.len() - offset);
+            private_cp.as_slice_mut()[..size].copy_from_slice(&fb[offset..offset + size]);
+        });
 
-                    Ok(private_cp)
-                }
-            })
-            .expect("/dev/fb: terminal not initialized")
+        Ok(private_cp)
     }
 
     fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
         let rinfo = crate::rendy::get_rendy_info();
 
         // Make sure we are in bounds.
-        if offset > rinfo.byte_len || offset + Size4KiB::SIZE as usize > rinfo.byte_len {
+        if offset >= rinfo.byte_len {
             return Err(FileSystemError::NotSupported);
         }
 
-        let mut lock = crate::rendy::DEBUG_RENDY.get().unwrap().lock_irq();
-        let fb = lock.get_framebuffer();
-
-        let fb_ptr = fb.as_ptr().cast::<u8>();
-        let fb_ptr = unsafe { fb_ptr.add(offset) };
-        let fb_phys_ptr = unsafe { fb_ptr.sub(crate::PHYSICAL_MEMORY_OFFSET.as_u64() as usize) };
-
-        Ok(MMapPage::Direct(PhysFrame::containing_address(unsafe {
-            PhysAddr::new_unchecked(fb_phys_ptr as u64)
-        })))
+        Ok(MMapPage::Direct(PhysFrame::containing_address(
+            framebuffer_address() + offset,
+        )))
     }
 
     fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
         match command {
             FBIOGET_VSCREENINFO => {
-                let struc = unsafe { &mut *(arg as *mut FramebufferVScreenInfo) };
+                let struc = VirtAddr::new(arg as _).read_mut::<FramebufferVScreenInfo>()?;
 
-                *struc = self.vinfo.read().clone();
+                *struc = self.vinfo();
                 Ok(0x00)
             }
 
             FBIOPUT_VSCREENINFO => {
-                let struc = unsafe { &mut *(arg as *mut FramebufferVScreenInfo) };
-                *self.vinfo.write() = struc.clone();
+                let struc = VirtAddr::new(arg as _).read_mut::<FramebufferVScreenInfo>()?;
+
+                if !Self::is_valid_mode(struc) {
+                    return Err(FileSystemError::InvalidArgument);
+                }
 
+                // A depth of zero keeps the current depth.
+                let bpp = match struc.bits_per_pixel {
+                    0 => modeset::current_mode().bpp,
+                    bpp => bpp as usize,
+                };
+
+                modeset::set_mode(VideoMode::new(
+                    struc.xres as usize,
+                    struc.yres as usize,
+                    bpp,
+                ))?;
+
+                // Report the effective screen info back.
+                *struc = self.vinfo();
                 Ok(0x00)
             }
 
             FBIOGET_FSCREENINFO => {
-                let struc = unsafe { &mut *(arg as *mut FramebufferFScreenInfo) };
+                let struc = VirtAddr::new(arg as _).read_mut::<FramebufferFScreenInfo>()?;
 
-                *struc = self.finfo.clone();
+                *struc = self.finfo();
                 Ok(0x00)
             }
 
+            // The virtual resolution is the same as the visible resolution, so the display can
+            // only be "panned" to the origin.
+            FBIOPAN_DISPLAY => {
+                let struc = VirtAddr::new(arg as _).read_mut::<FramebufferVScreenInfo>()?;
+
+                if struc.xoffset != 0 || struc.yoffset != 0 {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                Ok(0x00)
+            }
+
+            // Blanking is not supported by the display backends, so only unblanking succeeds.
+            FBIOBLANK => match arg {
+                FB_BLANK_UNBLANK => Ok(0x00),
+                FB_BLANK_NORMAL..=FB_BLANK_POWERDOWN => Err(FileSystemError::NotSupported),
+                _ => Err(FileSystemError::InvalidArgument),
+            },
+
             // Device independent colormap information can be get and set using
             // the `FBIOGETCMAP` and `FBIOPUTCMAP` ioctls.
             FBIOPUTCMAP => {
@@ -425,42 +585,129 @@ impl INodeInterface for DevFb {
     }
 }
 
-struct DevUrandom(usize);
+/// `/dev/random` and `/dev/urandom` (see [`crate::random`]). Reads from `/dev/random` block
+/// until the CRNG is initialized. Data written to either is mixed into the input pool without
+/// crediting any entropy.
+struct DevRandom {
+    marker: usize,
+    blocking: bool,
+}
 
-impl DevUrandom {
-    fn new() -> Arc<Self> {
-        Arc::new(Self(alloc_device_marker()))
+impl DevRandom {
+    fn new(blocking: bool) -> Arc<Self> {
+        Arc::new(Self {
+            marker: alloc_device_marker(),
+            blocking,
+        })
     }
 }
 
-impl Device for DevUrandom {
+impl Device for DevRandom {
     fn device_marker(&self) -> usize {
-        self.0
+        self.marker
     }
 
     fn device_name(&self) -> String {
-        String::from("urandom")
+        if self.blocking {
+            String::from("random")
+        } else {
+            String::from("urandom")
+        }
     }
 
     fn inode(&self) -> Arc<dyn INodeInterface> {
-        DEV_URANDOM.get().expect("device not initialized").clone()
+        let device = if self.blocking {
+            &DEV_RANDOM
+        } else {
+            &DEV_URANDOM
+        };
+
+        device.get().expect("device not initialized").clone()
+    }
+
+    fn device_number(&self) -> Option<DeviceNumber> {
+        let minor = if self.blocking { 8 } else { 9 };
+        Some(DeviceNumber::Char(1, minor))
     }
 }
 
-impl INodeInterface for DevUrandom {
+impl INodeInterface for DevRandom {
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
-        for (_, b) in buffer.iter_mut().enumerate() {
-            *b = 0;
+        if self.blocking {
+            crate::random::wait_for_ready()?;
         }
 
+        crate::random::fill_bytes(buffer);
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        crate::random::add_device_randomness(buffer);
         Ok(buffer.len())
     }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> Result<PollFlags> {
+        if !self.blocking || crate::random::is_ready() {
+            return Ok(PollFlags::IN | PollFlags::OUT);
+        }
+
+        if let Some(table) = table {
+            table.insert(crate::random::wait_queue());
+        }
+
+        Ok(PollFlags::OUT)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        // FIXME: Adding to the entropy count and reseeding should be restricted to privileged
+        // processes once there are user credentials.
+        match command {
+            RNDGETENTCNT => {
+                let count = VirtAddr::new(arg as _).read_mut::<i32>()?;
+                *count = crate::random::entropy_count() as i32;
+                Ok(0)
+            }
+
+            RNDADDTOENTCNT => {
+                let count = *VirtAddr::new(arg as _).read_mut::<i32>()?;
+                let count = usize::try_from(count).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                crate::random::credit_entropy(count);
+                Ok(0)
+            }
+
+            RNDADDENTROPY => {
+                let info = VirtAddr::new(arg as _).read_mut::<RandPoolInfo>()?;
+
+                let (Ok(count), Ok(size)) = (
+                    usize::try_from(info.entropy_count),
+                    usize::try_from(info.buf_size),
+                ) else {
+                    return Err(FileSystemError::InvalidArgument);
+                };
+
+                let data = VirtAddr::new((arg + core::mem::size_of::<RandPoolInfo>()) as _)
+                    .as_bytes_mut(size);
+
+                crate::random::add_hwgenerator_randomness(data, count);
+                Ok(0)
+            }
+
+            RNDRESEEDCRNG => {
+                crate::random::reseed();
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
 }
 
 static DEV_NULL: Once<Arc<DevNull>> = Once::new();
 static DEV_KMSG: Once<Arc<DevKmsg>> = Once::new();
 static DEV_FB: Once<Arc<DevFb>> = Once::new();
-static DEV_URANDOM: Once<Arc<DevUrandom>> = Once::new();
+static DEV_RANDOM: Once<Arc<DevRandom>> = Once::new();
+static DEV_URANDOM: Once<Arc<DevRandom>> = Once::new();
 
 /// Initializes the dev filesystem. (See the module-level documentation for more information).
 pub(super) fn init() -> Result<()> {
@@ -469,19 +716,25 @@ pub(super) fn init() -> Result<()> {
     let inode = lookup_path(Path::new("/dev"))?;
     MOUNT_MANAGER.mount(inode, DEV_FILESYSTEM.clone())?;
 
-    let rendy_info = crate::rendy::get_rendy_info();
-
     {
         let null = DEV_NULL.call_once(DevNull::new);
         let kmsg = DEV_KMSG.call_once(DevKmsg::new);
-        let fb = DEV_FB.call_once(|| DevFb::new(rendy_info));
-        let urandom = DEV_URANDOM.call_once(DevUrandom::new);
+        let fb = DEV_FB.call_once(DevFb::new);
+        let random = DEV_RANDOM.call_once(|| DevRandom::new(true));
+        let urandom = DEV_URANDOM.call_once(|| DevRandom::new(false));
 
         install_device(null.clone())?;
         install_device(kmsg.clone())?;
         install_device(fb.clone())?;
+        install_device(random.clone())?;
         install_device(urandom.clone())?;
     }
 
+    // Mount a tmpfs at `/dev/shm`, which is used for POSIX shared memory (`shm_open`).
+    DEV_FILESYSTEM.root_dir().inode().mkdir("shm")?;
+
+    let shm = lookup_path(Path::new("/dev/shm"))?;
+    MOUNT_MANAGER.mount(shm, RamFs::new_tmpfs())?;
+
     Ok(())
 }
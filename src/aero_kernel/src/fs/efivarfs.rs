@@ -0,0 +1,424 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The EFI variable filesystem, mounted at `/sys/firmware/efi/efivars`. Each UEFI variable is a
+//! file named `$NAME-$GUID` (see [`crate::efi`]):
+//!
+//! * Reading a file returns the attributes of the variable (a 32-bit little-endian integer)
+//!   followed by its data.
+//! * Writing the attributes followed by the data to a file sets the variable; the whole variable
+//!   has to be written at once. Writing only the attributes deletes it, unless
+//!   [`efi::VARIABLE_APPEND_WRITE`] is set.
+//! * Creating a file creates the variable once it is written to, and removing a file deletes the
+//!   variable.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use spin::{Once, RwLock};
+
+use crate::efi::{self, EfiError, Guid};
+use crate::fs;
+use crate::fs::inode::FileType;
+
+use super::cache::*;
+use super::{cache, FileSystem, Path, MOUNT_MANAGER};
+
+use super::inode::{DirEntry, INodeInterface, Metadata};
+use super::FileSystemError;
+
+/// Size of the attributes that precede the data of a variable.
+const ATTRIBUTES_SIZE: usize = core::mem::size_of::<u32>();
+
+impl From<EfiError> for FileSystemError {
+    fn from(error: EfiError) -> Self {
+        match error {
+            EfiError::NotFound => Self::EntryNotFound,
+            EfiError::OutOfResources => Self::OutOfMemory,
+            EfiError::WriteProtected | EfiError::SecurityViolation => Self::PermissionDenied,
+            EfiError::Unsupported => Self::NotSupported,
+            EfiError::InvalidParameter
+            | EfiError::BufferTooSmall
+            | EfiError::DeviceError
+            | EfiError::Other(_) => Self::InvalidArgument,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum FileContents {
+    Directory,
+    Variable {
+        /// The NUL terminated name of the variable.
+        name: Vec<u16>,
+        guid: Guid,
+    },
+}
+
+/// Splits the provided file name into the NUL terminated name of the variable and its GUID.
+fn parse_name(name: &str) -> fs::Result<(Vec<u16>, Guid)> {
+    // A GUID is 36 characters long.
+    let (name, guid) = name
+        .len()
+        .checked_sub(37)
+        .filter(|&i| i > 0 && name.is_char_boundary(i) && name.as_bytes()[i] == b'-')
+        .map(|i| (&name[..i], &name[i + 1..]))
+        .ok_or(FileSystemError::InvalidArgument)?;
+
+    let guid = Guid::parse(guid).ok_or(FileSystemError::InvalidArgument)?;
+    Ok((efi::encode_name(name), guid))
+}
+
+fn file_name(name: &[u16], guid: &Guid) -> String {
+    alloc::format!("{}-{}", efi::decode_name(name), guid)
+}
+
+struct EfiVarINode {
+    id: usize,
+    parent: INodeCacheWeakItem,
+    node: INodeCacheWeakItem,
+    children: BTreeMap<String, INodeCacheItem>,
+    filesystem: Weak<EfiVarFs>,
+    contents: FileContents,
+}
+
+struct LockedEfiVarINode(RwLock<EfiVarINode>);
+
+impl LockedEfiVarINode {
+    fn new(node: EfiVarINode) -> Self {
+        Self(RwLock::new(node))
+    }
+
+    fn init(
+        &self,
+        parent: &INodeCacheWeakItem,
+        node: &INodeCacheWeakItem,
+        filesystem: &Weak<EfiVarFs>,
+    ) {
+        let mut this = self.0.write();
+
+        this.parent = parent.clone();
+        this.node = node.clone();
+        this.filesystem = filesystem.clone();
+    }
+
+    /// Returns the file of the provided variable in this (root) directory, creating it if it
+    /// does not exist yet.
+    fn child(&self, file_name: &str, name: Vec<u16>, guid: Guid) -> INodeCacheItem {
+        let mut this = self.0.write();
+
+        if let Some(child) = this.children.get(file_name) {
+            return child.clone();
+        }
+
+        let filesystem = this.filesystem.upgrade().unwrap();
+
+        let inode = filesystem.allocate_inode(FileContents::Variable { name, guid });
+        let inode_cached = cache::icache().make_item_no_cache(CachedINode::new(inode.clone()));
+
+        inode.init(&this.node, &inode_cached.downgrade(), &this.filesystem);
+
+        this.children
+            .insert(String::from(file_name), inode_cached.clone());
+        inode_cached
+    }
+
+    /// Removes the file of a deleted variable from the parent (root) directory.
+    fn remove_from_parent(&self, name: &[u16], guid: &Guid) {
+        let Some(parent) = self.0.read().parent.upgrade() else {
+            return;
+        };
+
+        if let Ok(parent) = parent.inner().downcast_arc::<LockedEfiVarINode>() {
+            parent.0.write().children.remove(&file_name(name, guid));
+        }
+    }
+}
+
+impl INodeInterface for LockedEfiVarINode {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let FileContents::Variable { name, guid } = self.0.read().contents.clone() else {
+            return Err(FileSystemError::IsDir);
+        };
+
+        let (attributes, data) = efi::get_variable(&name, &guid)?;
+
+        let mut contents = Vec::with_capacity(ATTRIBUTES_SIZE + data.len());
+        contents.extend_from_slice(&attributes.to_le_bytes());
+        contents.extend_from_slice(&data);
+
+        if offset >= contents.len() {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), contents.len() - offset);
+        buffer[..count].copy_from_slice(&contents[offset..offset + count]);
+
+        Ok(count)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let FileContents::Variable { name, guid } = self.0.read().contents.clone() else {
+            return Err(FileSystemError::IsDir);
+        };
+
+        if offset != 0 || buffer.len() < ATTRIBUTES_SIZE {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let (attributes, data) = buffer.split_at(ATTRIBUTES_SIZE);
+        let attributes = u32::from_le_bytes(attributes.try_into().unwrap());
+
+        efi::set_variable(&name, &guid, attributes, data)?;
+
+        if data.is_empty() && attributes & efi::VARIABLE_APPEND_WRITE == 0 {
+            self.remove_from_parent(&name, &guid);
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, _size: usize) -> fs::Result<()> {
+        // Shell redirections open the files with `O_TRUNC`, the variable is replaced once it is
+        // written anyway.
+        Ok(())
+    }
+
+    fn touch(&self, parent: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        if !matches!(self.0.read().contents, FileContents::Directory) {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        let (variable, guid) = parse_name(name)?;
+
+        Ok(DirEntry::new(
+            parent,
+            self.child(name, variable, guid),
+            String::from(name),
+        ))
+    }
+
+    fn unlink(&self, name: &str) -> fs::Result<()> {
+        let (variable, guid) = parse_name(name)?;
+
+        match efi::set_variable(&variable, &guid, 0, &[]) {
+            // The file might have been created without writing the variable.
+            Ok(()) | Err(EfiError::NotFound) => {}
+            Err(error) => return Err(error.into()),
+        }
+
+        match self.0.write().children.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(FileSystemError::EntryNotFound),
+        }
+    }
+
+    fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        if !matches!(self.0.read().contents, FileContents::Directory) {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        if let Some(child) = self.0.read().children.get(name) {
+            return Ok(DirEntry::new(dir, child.clone(), String::from(name)));
+        }
+
+        let (variable, guid) = parse_name(name).map_err(|_| FileSystemError::EntryNotFound)?;
+
+        // Make sure that the variable exists.
+        efi::get_variable(&variable, &guid)?;
+
+        Ok(DirEntry::new(
+            dir,
+            self.child(name, variable, guid),
+            String::from(name),
+        ))
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        let this = self.0.read();
+
+        let (file_type, size) = match &this.contents {
+            FileContents::Directory => (FileType::Directory, 0),
+            FileContents::Variable { name, guid } => {
+                let size = efi::get_variable(name, guid)
+                    .map_or(0, |(_, data)| ATTRIBUTES_SIZE + data.len());
+
+                (FileType::File, size)
+            }
+        };
+
+        Ok(Metadata {
+            id: this.id,
+            file_type,
+            size,
+            children_len: this.children.len(),
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> fs::Result<Option<DirCacheItem>> {
+        let this = self.0.read();
+
+        if !matches!(this.contents, FileContents::Directory) {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        match index {
+            0x00 => {
+                return Ok(Some(DirEntry::new(
+                    parent,
+                    // UNWRAP: The inner node value should not be dropped.
+                    this.node.upgrade().unwrap(),
+                    String::from("."),
+                )));
+            }
+
+            0x01 => {
+                return Ok(Some(DirEntry::new(
+                    parent,
+                    // UNWRAP: The parent node value should not be dropped.
+                    this.parent.upgrade().unwrap(),
+                    String::from(".."),
+                )));
+            }
+
+            _ => {}
+        }
+
+        // The variables can be modified by the firmware, so they are listed every time. Files
+        // that were created but not written to yet are listed as well.
+        let mut variables = efi::variables()?
+            .into_iter()
+            .map(|(name, guid)| (file_name(&name, &guid), (name, guid)))
+            .collect::<BTreeMap<_, _>>();
+
+        let pending = this.children.keys().cloned().collect::<BTreeSet<_>>();
+        core::mem::drop(this);
+
+        for name in pending {
+            if let Ok(variable) = parse_name(&name) {
+                variables.entry(name).or_insert(variable);
+            }
+        }
+
+        // Subtract two because of the "." and ".." entries.
+        Ok(variables
+            .into_iter()
+            .nth(index - 2)
+            .map(|(file_name, (name, guid))| {
+                let inode = self.child(&file_name, name, guid);
+                DirEntry::new(parent, inode, file_name)
+            }))
+    }
+
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.0.read().filesystem.clone())
+    }
+}
+
+struct EfiVarFs {
+    root_inode: INodeCacheItem,
+    root_dir: DirCacheItem,
+    next_id: AtomicUsize,
+}
+
+impl EfiVarFs {
+    fn new() -> Arc<Self> {
+        let icache = cache::icache();
+
+        let root_node = Arc::new(LockedEfiVarINode::new(EfiVarINode {
+            id: 0,
+            parent: CacheWeak::new(),
+            node: CacheWeak::new(),
+            children: BTreeMap::new(),
+            filesystem: Weak::default(),
+            contents: FileContents::Directory,
+        }));
+        let root_cached = icache.make_item_no_cache(CachedINode::new(root_node.clone()));
+
+        let root_dir = DirEntry::new_root(root_cached.clone(), String::from("/"));
+
+        let fs = Arc::new(Self {
+            root_inode: root_cached.clone(),
+            root_dir: root_dir.clone(),
+            next_id: AtomicUsize::new(1),
+        });
+
+        let copy: Arc<dyn FileSystem> = fs.clone();
+        root_dir.filesystem.call_once(|| Arc::downgrade(&copy));
+
+        root_node.init(
+            &fs.root_inode.downgrade(),
+            &root_cached.downgrade(),
+            &Arc::downgrade(&fs),
+        );
+
+        fs
+    }
+
+    fn allocate_inode(&self, contents: FileContents) -> Arc<LockedEfiVarINode> {
+        Arc::new(LockedEfiVarINode::new(EfiVarINode {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            parent: CacheWeak::new(),
+            node: CacheWeak::new(),
+            children: BTreeMap::new(),
+            filesystem: Weak::default(),
+            contents,
+        }))
+    }
+}
+
+impl FileSystem for EfiVarFs {
+    #[inline]
+    fn root_dir(&self) -> DirCacheItem {
+        self.root_dir.clone()
+    }
+}
+
+/// Creates a new instance of the EFI variable filesystem (see `mount`).
+pub fn create() -> fs::Result<Arc<dyn FileSystem>> {
+    if !efi::is_available() {
+        return Err(FileSystemError::NotSupported);
+    }
+
+    Ok(EfiVarFs::new())
+}
+
+static EFIVAR_FS: Once<Arc<EfiVarFs>> = Once::new();
+
+pub fn init() -> fs::Result<()> {
+    if !efi::is_available() {
+        return Ok(());
+    }
+
+    let fs = EFIVAR_FS.call_once(EfiVarFs::new);
+
+    let inode = match super::lookup_path(Path::new("/sys/firmware/efi/efivars")) {
+        Ok(inode) => inode,
+        Err(FileSystemError::EntryNotFound) => {
+            log::warn!("efivarfs: `/sys/firmware/efi/efivars` does not exist; not mounting");
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+
+    MOUNT_MANAGER.mount(inode, fs.clone())?;
+    Ok(())
+}
@@ -21,7 +21,7 @@ use aero_syscall::SyscallError;
 use alloc::sync::Arc;
 use hashbrown::HashMap;
 
-use crate::userland::scheduler;
+use crate::userland::scheduler::{self, hrtimer};
 use crate::utils::sync::Mutex;
 
 use super::inode::{INodeInterface, PollTable};
@@ -181,6 +181,10 @@ impl EPoll {
             return Ok(0);
         }
 
+        let timer = (timeout as isize > 0).then(|| {
+            hrtimer::start_timeout(hrtimer::now().saturating_add(timeout as u64 * 1000000))
+        });
+
         'search: loop {
             scheduler::get_scheduler().inner.await_io()?;
 
@@ -209,6 +213,11 @@ impl EPoll {
                     break 'search;
                 }
             }
+
+            // The timeout expired without any events becoming ready.
+            if timer.as_ref().is_some_and(|timer| !timer.is_pending()) {
+                break 'search;
+            }
         }
 
         Ok(n)
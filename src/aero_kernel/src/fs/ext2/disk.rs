@@ -20,6 +20,7 @@ use core::time::Duration;
 use bit_field::BitField;
 
 use crate::fs::inode;
+use crate::utils::uuid::Uuid;
 use crate::utils::IncompleteArrayField;
 
 #[derive(Debug, PartialEq)]
@@ -126,6 +127,28 @@ impl SuperBlock {
             block_size * 2
         }
     }
+
+    /// Returns the UUID of the filesystem.
+    pub fn uuid(&self) -> Uuid {
+        // The UUID is stored as raw bytes.
+        let [low, high] = self.uuid;
+        let mut bytes = [0; 16];
+
+        bytes[..8].copy_from_slice(&low.to_le_bytes());
+        bytes[8..].copy_from_slice(&high.to_le_bytes());
+
+        Uuid(bytes)
+    }
+
+    /// Returns the label of the filesystem.
+    pub fn label(&self) -> &str {
+        let len = self
+            .volume_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.volume_name.len());
+        core::str::from_utf8(&self.volume_name[..len]).unwrap_or("")
+    }
 }
 
 #[repr(C)]
@@ -280,6 +303,40 @@ impl INode {
     pub fn creation_time(&self) -> Duration {
         Duration::from_secs(self.creation_time as u64)
     }
+
+    /// Returns the user ID of the owner. The high 16 bits are stored in the OS specific area.
+    pub fn uid(&self) -> u32 {
+        let high = u16::from_le_bytes([self.os_specific2[4], self.os_specific2[5]]);
+        self.user_id as u32 | (high as u32) << 16
+    }
+
+    /// Returns the group ID of the owner. The high 16 bits are stored in the OS specific area.
+    pub fn gid(&self) -> u32 {
+        let high = u16::from_le_bytes([self.os_specific2[6], self.os_specific2[7]]);
+        self.group_id as u32 | (high as u32) << 16
+    }
+
+    /// Returns the device number (`(major, minor)`) of a character or block device inode.
+    pub fn device(&self) -> (u32, u32) {
+        // The old encoding (8-bit major and minor numbers) is stored in the first block pointer
+        // and the new encoding (12-bit major and 20-bit minor numbers) in the second one.
+        match self.data_ptr {
+            [0, dev, ..] => ((dev & 0xfff00) >> 8, (dev & 0xff) | ((dev >> 12) & 0xfff00)),
+            [dev, ..] => ((dev >> 8) & 0xff, dev & 0xff),
+        }
+    }
+
+    /// Sets the device number of a character or block device inode. The old encoding is used
+    /// if both numbers fit in it, so that older implementations can read the device number.
+    pub fn set_device(&mut self, major: u32, minor: u32) {
+        if major < 256 && minor < 256 {
+            self.data_ptr[0] = (major << 8) | minor;
+            self.data_ptr[1] = 0;
+        } else {
+            self.data_ptr[0] = 0;
+            self.data_ptr[1] = (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12);
+        }
+    }
 }
 
 const_assert_eq!(core::mem::size_of::<INode>(), 128);
@@ -97,8 +97,8 @@ impl GroupDescriptors {
         Some(index)
     }
 
-    pub fn find_inode(&self, id: usize) -> Option<Box<disk::INode>> {
-        let fs = self.ext2.upgrade()?;
+    /// Returns the offset of the inode `id` on the disk.
+    fn inode_offset(&self, fs: &Ext2, id: usize) -> usize {
         let this = self.descriptors.read();
         let superblock = &fs.superblock;
 
@@ -113,18 +113,37 @@ impl GroupDescriptors {
         let group_descriptor = this[ino_block_group];
         let table_offset = group_descriptor.inode_table as usize * superblock.block_size();
 
+        table_offset + (ino_table_index * core::mem::size_of::<disk::INode>())
+    }
+
+    pub fn find_inode(&self, id: usize) -> Option<Box<disk::INode>> {
+        let fs = self.ext2.upgrade()?;
         let mut inode = Box::<disk::INode>::new_uninit();
 
-        fs.block.read(
-            table_offset + (ino_table_index * core::mem::size_of::<disk::INode>()),
-            inode.as_bytes_mut(),
-        )?;
+        fs.block
+            .read(self.inode_offset(&fs, id), inode.as_bytes_mut())?;
 
         // SAFETY: We have initialized the inode above.
         let inode = unsafe { inode.assume_init() };
         Some(inode)
     }
 
+    /// Writes `inode` back to the inode table, as the inode `id`.
+    pub fn write_inode(&self, id: usize, inode: &disk::INode) -> Option<()> {
+        let fs = self.ext2.upgrade()?;
+
+        // SAFETY: The on-disk inode is plain old data, so it can be viewed as bytes.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                (inode as *const disk::INode).cast::<u8>(),
+                core::mem::size_of::<disk::INode>(),
+            )
+        };
+
+        fs.block.write(self.inode_offset(&fs, id), bytes)?;
+        Some(())
+    }
+
     /// Allocates a block pointer using the first fit allocation strategy.
     pub fn alloc_block_ptr(&self) -> Option<usize> {
         let fs = self.ext2.upgrade()?;
@@ -19,9 +19,10 @@ mod disk;
 mod group_desc;
 
 use core::mem::MaybeUninit;
+use core::ops::Range;
 
 use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{MMapFlags, SyscallError};
+use aero_syscall::{MMapFlags, Mode, SyscallError};
 use alloc::boxed::Box;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
@@ -34,12 +35,15 @@ use crate::mem::paging::*;
 
 use crate::socket::unix::UnixSocket;
 use crate::socket::SocketAddrRef;
+use crate::utils::uuid::Uuid;
 
 use self::group_desc::GroupDescriptors;
 
-use super::block::{self, BlockDevice, CachedAccess, PAGE_CACHE};
+use super::block::{self, BlockDevice, BlockDeviceClaim, CachedAccess, PageCacheItem, PAGE_CACHE};
 
 use super::cache::{DirCacheItem, INodeCacheItem};
+use super::devfs::{self, DeviceNumber};
+use super::file_table::FileHandle;
 use super::path::PathBuf;
 use super::{cache, FileSystemError, Path};
 
@@ -52,7 +56,8 @@ pub struct INode {
     inode: RwLock<Box<disk::INode>>,
     // Forwards all of the inode operations to the proxy inode. Note that the
     // proxy inode is not saved on the disk. (e.g. This is useful for binding
-    // a socket inode to a file).
+    // a socket inode to a file). Device inodes are instead forwarded to the
+    // driver registered with their device number (see `INode::proxy`).
     proxy: Option<Arc<dyn INodeInterface>>,
 
     // TODO: Do not store this in the inode, but rather in a different
@@ -94,7 +99,7 @@ impl INode {
     ///
     /// ## Safety
     /// * The data being read should be of a valid value for `T`.
-    pub unsafe fn read_mut<T: Sized>(&self, offset: usize) -> block::DirtyRef<T> {
+    pub unsafe fn read_mut<T: Sized>(&self, offset: usize) -> super::Result<block::DirtyRef<T>> {
         assert!(core::mem::size_of::<T>() <= Size4KiB::SIZE as usize);
 
         let filesystem = self.fs.upgrade().unwrap();
@@ -177,6 +182,7 @@ impl INode {
             progress += chunk;
         }
         self.inode.write().set_size(offset + count);
+        self.mark_dirty();
 
         Ok(count)
     }
@@ -200,6 +206,9 @@ impl INode {
             let size = inode.size() + block_size;
             inode.set_size(size);
 
+            drop(inode);
+            self.mark_dirty();
+
             return Some(new_block);
         }
 
@@ -227,9 +236,16 @@ impl INode {
             inode.set_size(inode_size);
         }
 
+        self.mark_dirty();
         Some(new_block)
     }
 
+    /// Marks the inode dirty, so the on-disk inode is written back (see
+    /// [`cache::mark_inode_dirty`]). Called after the on-disk inode has been modified.
+    fn mark_dirty(&self) {
+        cache::mark_inode_dirty(INodeCacheItem::make_key(self.fs.clone(), self.id));
+    }
+
     pub fn get_block(&self, mut block: usize) -> Option<u32> {
         // There are pointers to the first 12 blocks which contain the file's
         // data in the inode. There is a pointer to an indirect block (which
@@ -290,6 +306,23 @@ impl INode {
         }
     }
 
+    /// Returns the byte ranges on the block device that hold the data in `len` bytes of the file
+    /// starting at `offset`. Holes are skipped.
+    fn device_ranges(&self, offset: usize, len: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let block_size = fs.superblock.block_size();
+
+        let end = core::cmp::min(offset.saturating_add(len), self.inode.read().size());
+
+        (offset / block_size..end.div_ceil(block_size))
+            .filter_map(|block| self.get_block(block))
+            .filter(|&block_index| block_index != 0)
+            .map(move |block_index| {
+                let start = block_index as usize * block_size;
+                start..start + block_size
+            })
+    }
+
     pub fn make_disk_dirent(&self, inode: &INode, file_type: u8, name: &str) {
         // TODO: scan for unused directory entries and check if this can be
         //       inserted into the existing block.
@@ -338,6 +371,8 @@ impl INode {
             inode.hl_count += 1;
         }
 
+        ext2_inode.mark_dirty();
+
         // FIXME: Fix the filetype!
         self.make_disk_dirent(&ext2_inode, 2, name);
         Ok(inode)
@@ -356,6 +391,34 @@ impl INode {
     pub fn sref(&self) -> Arc<INode> {
         self.sref.upgrade().unwrap()
     }
+
+    /// Returns the inode that the inode operations are forwarded to: the proxy inode or, for
+    /// character and block device inodes, the device inode of the driver registered with the
+    /// device number stored in the inode.
+    fn proxy(&self) -> super::Result<Option<Arc<dyn INodeInterface>>> {
+        if let Some(proxy) = self.proxy.as_ref() {
+            return Ok(Some(proxy.clone()));
+        }
+
+        let inode = self.inode.read();
+
+        let number = match inode.file_type() {
+            FileType::CharDev => {
+                let (major, minor) = inode.device();
+                DeviceNumber::Char(major, minor)
+            }
+
+            FileType::BlockDev => {
+                let (major, minor) = inode.device();
+                DeviceNumber::Block(major, minor)
+            }
+
+            _ => return Ok(None),
+        };
+
+        let device = devfs::find_device(number).ok_or(FileSystemError::NoDevice)?;
+        Ok(Some(device))
+    }
 }
 
 impl CachedAccess for INode {
@@ -389,30 +452,44 @@ impl INodeInterface for INode {
     }
 
     fn stat(&self) -> super::Result<aero_syscall::Stat> {
-        use super::inode::FileType;
         use aero_syscall::{Mode, Stat};
 
         let inode = self.inode.read();
 
         let filesystem = self.fs.upgrade().unwrap();
-        let filetype = inode.file_type().into();
+        let filetype = inode.file_type();
+
+        let mut mode = match filetype {
+            FileType::Fifo => Mode::S_IFIFO,
+            FileType::CharDev => Mode::S_IFCHR,
+            FileType::Directory => Mode::S_IFDIR,
+            FileType::BlockDev => Mode::S_IFBLK,
+            FileType::Symlink => Mode::S_IFLNK,
+            FileType::Socket => Mode::S_IFSOCK,
+            FileType::File | FileType::Unknown => Mode::S_IFREG,
+        };
 
-        let mut mode = Mode::empty();
+        let rdev = match filetype {
+            FileType::CharDev | FileType::BlockDev => {
+                let (major, minor) = inode.device();
+                aero_syscall::makedev(major, minor)
+            }
 
-        match filetype {
-            FileType::File => mode.insert(Mode::S_IFREG),
-            FileType::Directory => mode.insert(Mode::S_IFDIR),
-            FileType::Device => mode.insert(Mode::S_IFCHR),
-            FileType::Socket => mode.insert(Mode::S_IFSOCK),
-            FileType::Symlink => mode.insert(Mode::S_IFLNK),
-        }
+            _ => 0,
+        };
 
         // FIXME: read permission bits from the inode.
         mode.insert(Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO);
 
         Ok(Stat {
             st_ino: self.id as _,
+            st_nlink: inode.hl_count as _,
+            st_uid: inode.uid(),
+            st_gid: inode.gid(),
+            st_rdev: rdev,
             st_blksize: filesystem.superblock.block_size() as _,
+            // NOTE: The block count is in 512-byte sectors, like `st_blocks`.
+            st_blocks: inode.block_count as _,
             st_size: inode.size() as _,
             st_mode: mode,
 
@@ -433,15 +510,22 @@ impl INodeInterface for INode {
     }
 
     fn lookup(&self, parent: DirCacheItem, name: &str) -> super::Result<DirCacheItem> {
-        let entry = DirEntryIter::new(self.sref())
-            .find(|entry| entry.name() == name)
-            .ok_or(FileSystemError::EntryNotFound)?;
+        let entry = DirEntryIter::new(self.sref()).find(|entry| entry.name() == name);
+
+        crate::tracepoint!(
+            EXT2_LOOKUP,
+            "dir={} name={name} found={}",
+            self.id,
+            entry.is_some()
+        );
+
+        let entry = entry.ok_or(FileSystemError::EntryNotFound)?;
 
         Ok(self.make_dirent(parent, entry.name(), entry).unwrap())
     }
 
     fn read_at(&self, offset: usize, usr_buffer: &mut [u8]) -> super::Result<usize> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             return proxy.read_at(offset, usr_buffer);
         }
 
@@ -449,6 +533,13 @@ impl INodeInterface for INode {
             return Err(FileSystemError::NotSupported);
         }
 
+        crate::tracepoint!(
+            EXT2_READ,
+            "ino={} offset={offset} len={}",
+            self.id,
+            usr_buffer.len()
+        );
+
         let buffer = unsafe {
             core::slice::from_raw_parts_mut(usr_buffer.as_mut_ptr().cast(), usr_buffer.len())
         };
@@ -457,7 +548,7 @@ impl INodeInterface for INode {
     }
 
     fn write_at(&self, offset: usize, usr_buffer: &[u8]) -> super::Result<usize> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             return proxy.write_at(offset, usr_buffer);
         }
 
@@ -465,6 +556,13 @@ impl INodeInterface for INode {
             return Err(FileSystemError::NotSupported);
         }
 
+        crate::tracepoint!(
+            EXT2_WRITE,
+            "ino={} offset={offset} len={}",
+            self.id,
+            usr_buffer.len()
+        );
+
         self.write(offset, usr_buffer)
     }
 
@@ -518,6 +616,8 @@ impl INodeInterface for INode {
             return Err(FileSystemError::NotDirectory);
         }
 
+        crate::tracepoint!(EXT2_CREATE, "dir={} name={name} type=file", self.id);
+
         let inode = self.make_inode(name, FileType::File, None)?;
         Ok(inode::DirEntry::new(parent, inode, name.to_string()))
     }
@@ -527,9 +627,39 @@ impl INodeInterface for INode {
             return Err(FileSystemError::NotDirectory);
         }
 
+        crate::tracepoint!(EXT2_CREATE, "dir={} name={name} type=dir", self.id);
+
         self.make_inode(name, FileType::Directory, None)
     }
 
+    fn mknod(
+        &self,
+        parent: DirCacheItem,
+        name: &str,
+        mode: Mode,
+        dev: u64,
+    ) -> super::Result<DirCacheItem> {
+        let (typ, kind) = match mode & Mode::S_IFMT {
+            Mode::S_IFCHR => (FileType::CharDev, "chr"),
+            Mode::S_IFBLK => (FileType::BlockDev, "blk"),
+            _ => return Err(FileSystemError::InvalidArgument),
+        };
+
+        crate::tracepoint!(EXT2_CREATE, "dir={} name={name} type={kind}", self.id);
+
+        let inode = self.make_inode(name, typ, None)?;
+        let ext2_inode = inode.downcast_arc::<INode>().expect("ext2: invalid inode");
+
+        ext2_inode
+            .inode
+            .write()
+            .set_device(aero_syscall::major(dev), aero_syscall::minor(dev));
+
+        ext2_inode.mark_dirty();
+
+        Ok(inode::DirEntry::new(parent, inode, name.to_string()))
+    }
+
     fn make_local_socket_inode(
         &self,
         name: &str,
@@ -578,6 +708,9 @@ impl INodeInterface for INode {
         if target_len <= data_bytes.len() {
             data_bytes[..target_len].copy_from_slice(target.as_bytes());
             inode.set_size(target_len);
+
+            drop(inode);
+            self.mark_dirty();
         } else {
             drop(inode);
             assert_eq!(self.write(0, target.as_bytes())?, target_len);
@@ -587,12 +720,16 @@ impl INodeInterface for INode {
     }
 
     fn mmap(&self, offset: usize, size: usize, flags: MMapFlags) -> super::Result<PhysFrame> {
-        assert!(self.proxy.is_none());
+        if let Some(proxy) = self.proxy()? {
+            return proxy.mmap(offset, size, flags);
+        }
 
         // TODO: support shared file mappings.
         // assert!(!flags.contains(MMapFlags::MAP_SHARED));
 
-        let private_cp: PhysFrame = FRAME_ALLOCATOR.allocate_frame().unwrap();
+        let private_cp: PhysFrame = FRAME_ALLOCATOR
+            .allocate_frame()
+            .ok_or(FileSystemError::OutOfMemory)?;
         private_cp.as_slice_mut().fill(0);
 
         let buffer = &mut private_cp.as_slice_mut()[..size];
@@ -603,14 +740,18 @@ impl INodeInterface for INode {
 
     // TODO: cleanup
     fn mmap_v2(&self, offset: usize) -> super::Result<MMapPage> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.mmap_v2(offset);
+        }
+
         Ok(MMapPage::PageCache(PAGE_CACHE.get_page(
             &(self.sref.clone() as Weak<dyn CachedAccess>),
             offset,
-        )))
+        )?))
     }
 
     fn listen(&self, backlog: usize) -> Result<(), SyscallError> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             return proxy.listen(backlog);
         }
 
@@ -621,7 +762,7 @@ impl INodeInterface for INode {
     // is being is called on an EXT2 inode then, it has already been bound.
 
     fn connect(&self, address: SocketAddrRef, length: usize) -> super::Result<()> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             return proxy.connect(address, length);
         }
 
@@ -629,7 +770,7 @@ impl INodeInterface for INode {
     }
 
     fn accept(&self, address: Option<(VirtAddr, &mut u32)>) -> super::Result<Arc<UnixSocket>> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             return proxy.accept(address);
         }
 
@@ -637,7 +778,7 @@ impl INodeInterface for INode {
     }
 
     fn send(&self, message_hdr: &mut MessageHeader, flags: MessageFlags) -> super::Result<usize> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             proxy.send(message_hdr, flags)
         } else {
             Err(FileSystemError::NotSupported)
@@ -645,19 +786,137 @@ impl INodeInterface for INode {
     }
 
     fn recv(&self, message_hdr: &mut MessageHeader, flags: MessageFlags) -> super::Result<usize> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             proxy.recv(message_hdr, flags)
         } else {
             Err(FileSystemError::NotSupported)
         }
     }
 
+    fn open(&self, handle: Arc<FileHandle>) -> super::Result<Option<DirCacheItem>> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.open(handle);
+        }
+
+        Ok(None)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> super::Result<usize> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.ioctl(command, arg);
+        }
+
+        Err(FileSystemError::NotSupported)
+    }
+
+    fn fsync(&self) -> super::Result<()> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.fsync();
+        }
+
+        // The data of the inode is written through the page cache of the block device, and so
+        // is the on-disk inode once it has been written back.
+        self.write_inode()?;
+
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        fs.block.barrier()
+    }
+
+    fn write_inode(&self) -> super::Result<()> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let inode = self.inode.read();
+
+        fs.bgdt
+            .write_inode(self.id, &inode)
+            .ok_or(FileSystemError::Io)
+    }
+
+    fn readahead(&self, offset: usize, len: usize) -> super::Result<()> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.readahead(offset, len);
+        }
+
+        if !self.metadata()?.is_file() {
+            return Ok(());
+        }
+
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+
+        for range in self.device_ranges(offset, len) {
+            PAGE_CACHE.readahead(&fs.block.sref(), range);
+        }
+
+        Ok(())
+    }
+
+    fn cached_page(&self, offset: usize) -> super::Result<Option<(PageCacheItem, Range<usize>)>> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.cached_page(offset);
+        }
+
+        if !self.metadata()?.is_file() {
+            return Ok(None);
+        }
+
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let block_size = fs.superblock.block_size();
+        let size = self.inode.read().size();
+
+        if offset >= size {
+            return Ok(None);
+        }
+
+        // Holes are not backed by a block.
+        let block_index = match self.get_block(offset / block_size) {
+            Some(0) | None => return Ok(None),
+            Some(block_index) => block_index as usize,
+        };
+
+        // The file data is read through the page cache of the block device, and is contiguous
+        // on the device up to the end of the block.
+        let loc = offset % block_size;
+        let device_offset = block_index * block_size + loc;
+        let start = device_offset % Size4KiB::SIZE as usize;
+
+        let len = (block_size - loc)
+            .min(size - offset)
+            .min(Size4KiB::SIZE as usize - start);
+
+        let page = PAGE_CACHE.get_page(&fs.block.sref(), device_offset)?;
+        Ok(Some((page, start..start + len)))
+    }
+
+    fn drop_cache(&self, offset: usize, len: usize) -> super::Result<()> {
+        if let Some(proxy) = self.proxy()? {
+            return proxy.drop_cache(offset, len);
+        }
+
+        if !self.metadata()?.is_file() {
+            return Ok(());
+        }
+
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+
+        // The file data is read and written through the page cache of the block device, while
+        // the pages of the inode itself are only used by shared mappings of the file.
+        for range in self.device_ranges(offset, len) {
+            PAGE_CACHE.invalidate(&fs.block.sref(), range);
+        }
+
+        PAGE_CACHE.invalidate(
+            &(self.sref.clone() as Weak<dyn CachedAccess>),
+            offset..offset.saturating_add(len),
+        );
+
+        Ok(())
+    }
+
     fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
-        if let Some(proxy) = self.proxy.as_ref() {
+        if let Some(proxy) = self.proxy()? {
             return proxy.poll(table);
         }
 
-        Err(FileSystemError::NotSupported)
+        PollFlags::always_ready(self.metadata()?.file_type())
     }
 
     fn as_unix_socket(&self) -> super::Result<Arc<dyn INodeInterface>> {
@@ -697,37 +956,59 @@ impl<'a> Iterator for DirEntryIter<'a> {
     type Item = &'a mut disk::DirEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
+        const HEADER_SIZE: usize = core::mem::size_of::<disk::DirEntry>();
+
         // Read 1 block at a time.
         //
         // XXX: A directory entry cannot span between multiple data blocks.
         let file_size = self.inode.inode.read().size();
 
-        if self.offset + core::mem::size_of::<disk::DirEntry>() > file_size {
-            return None;
-        }
+        loop {
+            if self.offset + HEADER_SIZE > file_size {
+                return None;
+            }
 
-        let block_offset = self.offset % self.block_size;
-        if block_offset == 0 {
-            self.inode
-                .read(self.offset, &mut self.current_block)
-                .unwrap();
-        }
+            let block_offset = self.offset % self.block_size;
+            if block_offset == 0 {
+                self.inode.read(self.offset, &mut self.current_block).ok()?;
+            }
 
-        // SAFETY: We have initialized the current block above.
-        let entry = unsafe {
-            &mut *self
-                .current_block
-                .as_mut_ptr()
-                .add(block_offset)
-                .cast::<disk::DirEntry>()
-        };
+            // The header must fit in the current block before it can be read.
+            if block_offset + HEADER_SIZE > self.block_size {
+                log::warn!("ext2: directory {} is corrupted", self.inode.id);
+                return None;
+            }
 
-        if !entry.is_used() {
-            return None;
-        }
+            // SAFETY: We have initialized the current block above.
+            let entry = unsafe {
+                &mut *self
+                    .current_block
+                    .as_mut_ptr()
+                    .add(block_offset)
+                    .cast::<disk::DirEntry>()
+            };
+
+            let entry_size = entry.entry_size as usize;
+
+            // The record must be aligned, hold its name and must not span between multiple
+            // blocks. Otherwise the directory is corrupted and the rest of it cannot be walked.
+            if entry_size < HEADER_SIZE
+                || entry_size % 4 != 0
+                || block_offset + entry_size > self.block_size
+                || HEADER_SIZE + entry.name_size as usize > entry_size
+            {
+                log::warn!("ext2: directory {} is corrupted", self.inode.id);
+                return None;
+            }
+
+            self.offset += entry_size;
 
-        self.offset += entry.entry_size as usize;
-        Some(entry)
+            // Records with an inode number of zero are unused (e.g. deleted entries or the
+            // padding at the end of a block) and are skipped.
+            if entry.is_used() {
+                return Some(entry);
+            }
+        }
     }
 }
 
@@ -735,6 +1016,7 @@ pub struct Ext2 {
     superblock: Box<SuperBlock>,
     bgdt: GroupDescriptors,
     block: Arc<BlockDevice>,
+    _claim: BlockDeviceClaim,
 
     sref: Weak<Self>,
 }
@@ -753,6 +1035,8 @@ impl Ext2 {
             return None;
         }
 
+        let claim = block.claim().ok()?;
+
         log::trace!(
             "ext2: initialized (block_size={}, entries_per_block={})",
             superblock.block_size(),
@@ -770,6 +1054,7 @@ impl Ext2 {
                 .expect("ext2: failed to read group descriptors"),
             superblock,
             block,
+            _claim: claim,
 
             sref: sref.clone(),
         }))
@@ -782,6 +1067,14 @@ impl Ext2 {
     ) -> Option<INodeCacheItem> {
         INode::new(self.sref.clone(), id, proxy)
     }
+
+    pub fn uuid(&self) -> Uuid {
+        self.superblock.uuid()
+    }
+
+    pub fn label(&self) -> &str {
+        self.superblock.label()
+    }
 }
 
 impl FileSystem for Ext2 {
@@ -792,4 +1085,363 @@ impl FileSystem for Ext2 {
 
         inode::DirEntry::new_root(inode, String::from("/"))
     }
+
+    fn caches_negative_entries(&self) -> bool {
+        true
+    }
+}
+
+pub(super) mod selftests {
+    use core::mem::size_of;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::disk::{self, GroupDescriptor};
+    use super::*;
+
+    use crate::utils::sync::Mutex;
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    const BLOCK_SIZE: usize = 1024;
+    const BLOCK_COUNT: usize = 16;
+    const INODE_COUNT: usize = 16;
+
+    const INODE_TABLE: usize = 5;
+    const DIR_INODE: u32 = 12;
+    const FILE_INODE: u32 = 13;
+    const LINK_INODE: u32 = 14;
+
+    pub(in crate::fs) const FILE_DATA: &[u8] = b"hello, world!\n";
+    pub(in crate::fs) const LINK_TARGET: &str = "dir/hello";
+
+    const UUID: [u8; 16] = *b"aero-ext2-selft!";
+
+    /// A block device backed by an image in memory.
+    struct ImageDevice(Mutex<Vec<u8>>);
+
+    impl ImageDevice {
+        fn copy_out(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+            let image = self.0.lock();
+            let data = image.get(sector * 512..sector * 512 + dest.len())?;
+
+            for (dest, byte) in dest.iter_mut().zip(data) {
+                dest.write(*byte);
+            }
+
+            Some(dest.len())
+        }
+
+        fn copy_in(&self, sector: usize, buffer: &[u8]) -> Option<usize> {
+            let mut image = self.0.lock();
+            let data = image.get_mut(sector * 512..sector * 512 + buffer.len())?;
+
+            data.copy_from_slice(buffer);
+            Some(buffer.len())
+        }
+    }
+
+    impl BlockDeviceInterface for ImageDevice {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> usize {
+            self.0.lock().len() / 512
+        }
+
+        fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+            let dest =
+                unsafe { core::slice::from_raw_parts_mut(start.as_hhdm_virt().as_mut_ptr(), size) };
+
+            self.copy_out(sector, dest)
+        }
+
+        fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+            let buffer =
+                unsafe { core::slice::from_raw_parts(start.as_hhdm_virt().as_ptr(), size) };
+
+            self.copy_in(sector, buffer)
+        }
+
+        fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+            self.copy_out(sector, dest)
+        }
+
+        fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
+            self.copy_in(sector, buf)
+        }
+    }
+
+    /// Copies the raw bytes of `value` into the image at `offset`.
+    fn put<T: Copy>(image: &mut [u8], offset: usize, value: &T) {
+        let bytes = unsafe {
+            core::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>())
+        };
+
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn make_inode(file_type: FileType, size: usize, block: u32) -> disk::INode {
+        let mut inode = disk::INode::default();
+
+        inode.set_file_type(file_type);
+        inode.set_permissions(0o755);
+        inode.set_size(size);
+        inode.hl_count = 1;
+        inode.data_ptr[0] = block;
+        inode
+    }
+
+    fn put_inode(image: &mut [u8], id: u32, inode: &disk::INode) {
+        let offset = INODE_TABLE * BLOCK_SIZE + (id as usize - 1) * size_of::<disk::INode>();
+        put(image, offset, inode);
+    }
+
+    /// Writes the directory entries to `block`. The last entry takes up the rest of the block.
+    fn put_dir(image: &mut [u8], block: u32, entries: &[(u32, FileType, &str)]) {
+        let mut offset = block as usize * BLOCK_SIZE;
+        let end = offset + BLOCK_SIZE;
+
+        for (i, (inode, file_type, name)) in entries.iter().enumerate() {
+            let size = if i == entries.len() - 1 {
+                end - offset
+            } else {
+                (8 + name.len()).next_multiple_of(4)
+            };
+
+            image[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+            image[offset + 4..offset + 6].copy_from_slice(&(size as u16).to_le_bytes());
+            image[offset + 6] = name.len() as u8;
+            image[offset + 7] = *file_type as u8;
+            image[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+
+            offset += size;
+        }
+    }
+
+    /// Builds a single block group filesystem with 1KiB blocks containing:
+    ///
+    /// * `/dir/hello`, a file containing [`FILE_DATA`].
+    /// * `/link`, a fast symbolic link to [`LINK_TARGET`].
+    fn make_image() -> Vec<u8> {
+        let mut image = vec![0; BLOCK_COUNT * BLOCK_SIZE];
+
+        // SAFETY: All-zero is a valid superblock.
+        let mut superblock = unsafe { core::mem::zeroed::<SuperBlock>() };
+
+        superblock.inodes_count = INODE_COUNT as u32;
+        superblock.blocks_count = BLOCK_COUNT as u32;
+        superblock.free_blocks_count = 6;
+        superblock.free_inodes_count = 2;
+        superblock.first_data_block = 1;
+        superblock.blocks_per_group = 8192;
+        superblock.frags_per_group = 8192;
+        superblock.inodes_per_group = INODE_COUNT as u32;
+        superblock.magic = SuperBlock::MAGIC;
+        superblock.state = 1;
+        superblock.rev_level = 1;
+        superblock.first_ino = 11;
+        superblock.inode_size = size_of::<disk::INode>() as u16;
+        superblock.uuid = [
+            u64::from_le_bytes(UUID[..8].try_into().unwrap()),
+            u64::from_le_bytes(UUID[8..].try_into().unwrap()),
+        ];
+        superblock.volume_name[..8].copy_from_slice(b"selftest");
+
+        put(&mut image, 1024, &superblock);
+
+        let descriptor = GroupDescriptor {
+            block_bitmap: 3,
+            inode_bitmap: 4,
+            inode_table: INODE_TABLE as u32,
+            free_blocks_count: 6,
+            free_inodes_count: 2,
+            used_dirs_count: 2,
+            pad: 0,
+            reserved: [0; 12],
+        };
+
+        put(&mut image, 2 * BLOCK_SIZE, &descriptor);
+
+        // Blocks 1..=9 and inodes 1..=14 are in use.
+        image[3 * BLOCK_SIZE] = 0xff;
+        image[3 * BLOCK_SIZE + 1] = 0x01;
+        image[4 * BLOCK_SIZE] = 0xff;
+        image[4 * BLOCK_SIZE + 1] = 0x3f;
+
+        let root = make_inode(FileType::Directory, BLOCK_SIZE, 7);
+        let dir = make_inode(FileType::Directory, BLOCK_SIZE, 8);
+        let file = make_inode(FileType::File, FILE_DATA.len(), 9);
+        let mut link = make_inode(FileType::Symlink, LINK_TARGET.len(), 0);
+
+        // Symbolic links shorter than 60 bytes are stored in the block pointers.
+        let data: &mut [u8] = bytemuck::cast_slice_mut(&mut link.data_ptr);
+        data[..LINK_TARGET.len()].copy_from_slice(LINK_TARGET.as_bytes());
+
+        put_inode(&mut image, Ext2::ROOT_INODE_ID as u32, &root);
+        put_inode(&mut image, DIR_INODE, &dir);
+        put_inode(&mut image, FILE_INODE, &file);
+        put_inode(&mut image, LINK_INODE, &link);
+
+        put_dir(
+            &mut image,
+            7,
+            &[
+                (2, FileType::Directory, "."),
+                (2, FileType::Directory, ".."),
+                (DIR_INODE, FileType::Directory, "dir"),
+                (LINK_INODE, FileType::Symlink, "link"),
+            ],
+        );
+
+        put_dir(
+            &mut image,
+            8,
+            &[
+                (DIR_INODE, FileType::Directory, "."),
+                (2, FileType::Directory, ".."),
+                (FILE_INODE, FileType::File, "hello"),
+            ],
+        );
+
+        image[9 * BLOCK_SIZE..9 * BLOCK_SIZE + FILE_DATA.len()].copy_from_slice(FILE_DATA);
+        image
+    }
+
+    /// Mounts a new copy of the test image (see [`make_image`]).
+    pub(in crate::fs) fn mount_image() -> Option<Arc<Ext2>> {
+        let device = Arc::new(ImageDevice(Mutex::new(make_image())));
+        let fs = Ext2::new(BlockDevice::new(String::from("ext2-selftest"), device))?;
+
+        // The inode and page caches are keyed by the addresses of the filesystem and its inodes,
+        // so it is never freed to keep them from being reused.
+        core::mem::forget(fs.clone());
+        Some(fs)
+    }
+
+    fn superblock() -> selftest::Result {
+        let fs = mount_image();
+        selftest_assert!(fs.is_some(), "failed to mount the image");
+
+        let fs = fs.unwrap();
+
+        selftest_assert_eq!(fs.superblock.block_size(), BLOCK_SIZE);
+        selftest_assert_eq!(fs.superblock.bgdt_len(), 1);
+        selftest_assert_eq!(fs.label(), "selftest");
+        selftest_assert_eq!(fs.uuid(), Uuid(UUID));
+
+        Ok(())
+    }
+
+    fn directory_entries() -> selftest::Result {
+        let fs = mount_image().unwrap();
+        let root = fs.root_dir();
+
+        let names = (0..)
+            .map_while(|i| root.inode().dirent(root.clone(), i).ok().flatten())
+            .map(|entry| entry.name())
+            .collect::<Vec<_>>();
+
+        selftest_assert_eq!(names, [".", "..", "dir", "link"]);
+
+        let dir = root.inode().lookup(root.clone(), "dir");
+        selftest_assert!(dir.is_ok_and(|dir| dir.inode().metadata().unwrap().is_directory()));
+
+        let missing = root.inode().lookup(root.clone(), "missing");
+        selftest_assert!(matches!(missing, Err(FileSystemError::EntryNotFound)));
+
+        Ok(())
+    }
+
+    fn file_read() -> selftest::Result {
+        let fs = mount_image().unwrap();
+        let root = fs.root_dir();
+
+        let dir = root.inode().lookup(root.clone(), "dir").unwrap();
+        let file = dir.inode().lookup(dir.clone(), "hello").unwrap();
+        let inode = file.inode();
+
+        selftest_assert_eq!(inode.metadata().unwrap().size, FILE_DATA.len());
+
+        let mut buffer = [0; 64];
+        let size = inode.read_at(0, &mut buffer).unwrap();
+        selftest_assert_eq!(buffer[..size], *FILE_DATA);
+
+        let size = inode.read_at(7, &mut buffer).unwrap();
+        selftest_assert_eq!(buffer[..size], FILE_DATA[7..]);
+
+        Ok(())
+    }
+
+    fn fast_symlink() -> selftest::Result {
+        let fs = mount_image().unwrap();
+        let root = fs.root_dir();
+
+        let link = root.inode().lookup(root.clone(), "link").unwrap();
+        let target = link.inode().resolve_link().unwrap();
+
+        selftest_assert!(link.inode().metadata().unwrap().is_symlink());
+        selftest_assert_eq!(target.as_str(), LINK_TARGET);
+
+        Ok(())
+    }
+
+    fn inode_writeback() -> selftest::Result {
+        let fs = mount_image().unwrap();
+        let root = fs.root_dir();
+
+        let dir = root.inode().lookup(root.clone(), "dir").unwrap();
+        let file = dir.inode().lookup(dir.clone(), "hello").unwrap();
+
+        file.inode().write_at(FILE_DATA.len(), FILE_DATA).unwrap();
+        selftest_assert!(cache::writeback_inodes() >= 1);
+
+        // The new size has been written to the inode table.
+        let inode = fs.bgdt.find_inode(FILE_INODE as usize).unwrap();
+        selftest_assert_eq!(inode.size(), FILE_DATA.len() * 2);
+
+        Ok(())
+    }
+
+    fn device_nodes() -> selftest::Result {
+        let fs = mount_image().unwrap();
+        let root = fs.root_dir();
+
+        // The first device number fits in the old encoding, the second one does not.
+        let nodes = [
+            ("tty1", Mode::S_IFCHR, 4, 1),
+            ("disk", Mode::S_IFBLK, 259, 300),
+        ];
+
+        for (name, typ, major, minor) in nodes {
+            let dev = aero_syscall::makedev(major, minor);
+            let mode = typ | Mode::S_IRUSR | Mode::S_IWUSR;
+            let node = root.inode().mknod(root.clone(), name, mode, dev);
+            selftest_assert!(node.is_ok(), "failed to create {name}");
+        }
+
+        cache::writeback_inodes();
+
+        // The device numbers have been written to the inode table.
+        for (name, _, major, minor) in nodes {
+            let node = root.inode().lookup(root.clone(), name).unwrap();
+            let id = node.inode().metadata().unwrap().id;
+
+            let inode = fs.bgdt.find_inode(id).unwrap();
+            selftest_assert_eq!(inode.device(), (major, minor));
+        }
+
+        Ok(())
+    }
+
+    crate::selftest!(
+        "ext2",
+        superblock,
+        directory_entries,
+        file_read,
+        fast_symlink,
+        inode_writeback,
+        device_nodes
+    );
 }
@@ -15,8 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use aero_syscall::consts::{POSIX_FADV_NORMAL, POSIX_FADV_SEQUENTIAL};
 use aero_syscall::{OpenFlags, SysDirEntry};
 
 use alloc::sync::Arc;
@@ -25,11 +26,16 @@ use alloc::vec::Vec;
 use spin::RwLock;
 
 use crate::fs::cache::DirCacheImpl;
+use crate::userland::workqueue;
 
 use super::cache::{DirCacheItem, INodeCacheItem};
 use super::inode::FileType;
 use super::FileSystemError;
 
+/// Number of bytes that are read ahead of the file offset after each read from a file that is
+/// accessed sequentially (see [`FileHandle::set_advice`]).
+const READAHEAD_WINDOW: usize = 128 * 1024;
+
 #[derive(Debug, Copy, Clone)]
 pub enum DuplicateHint {
     Exact(usize),
@@ -43,17 +49,29 @@ pub struct FileHandle {
     // We need to store the `offset` behind an Arc since when the file handle
     // is duplicated, the `offset` needs to be in sync with the parent.
     pub offset: Arc<AtomicUsize>,
-    flags: RwLock<OpenFlags>,
+    // The access pattern advice (`POSIX_FADV_*`) is shared with the duplicates as well, since
+    // it describes how the shared `offset` moves.
+    advice: Arc<AtomicUsize>,
+    // So are the file status flags, unlike the close-on-exec flag which belongs to the file
+    // descriptor itself.
+    flags: Arc<RwLock<OpenFlags>>,
+    cloexec: AtomicBool,
 }
 
 impl FileHandle {
-    /// Creates a new file handle.
-    pub fn new(fd: usize, inode: DirCacheItem, flags: OpenFlags) -> Self {
+    /// Creates a new file handle. `O_CLOEXEC` in `flags` sets the close-on-exec flag of the
+    /// file descriptor.
+    pub fn new(fd: usize, inode: DirCacheItem, mut flags: OpenFlags) -> Self {
+        let cloexec = flags.contains(OpenFlags::O_CLOEXEC);
+        flags.remove(OpenFlags::O_CLOEXEC);
+
         Self {
             fd,
             inode,
             offset: Arc::new(AtomicUsize::new(0)),
-            flags: RwLock::new(flags),
+            advice: Arc::new(AtomicUsize::new(POSIX_FADV_NORMAL)),
+            flags: Arc::new(RwLock::new(flags)),
+            cloexec: AtomicBool::new(cloexec),
         }
     }
 
@@ -79,11 +97,36 @@ impl FileHandle {
         *self.flags.write() = flags;
     }
 
+    /// Returns whether the file descriptor is closed when the task executes a new program.
+    pub fn is_cloexec(&self) -> bool {
+        self.cloexec.load(Ordering::SeqCst)
+    }
+
+    pub fn set_cloexec(&self, cloexec: bool) {
+        self.cloexec.store(cloexec, Ordering::SeqCst);
+    }
+
+    /// Sets the access pattern advice (`POSIX_FADV_NORMAL`, `POSIX_FADV_RANDOM` or
+    /// `POSIX_FADV_SEQUENTIAL`) for the file. Sequential reads are followed by reading ahead
+    /// [`READAHEAD_WINDOW`] bytes of the file in the background.
+    pub fn set_advice(&self, advice: usize) {
+        self.advice.store(advice, Ordering::Relaxed);
+    }
+
     pub fn read(&self, buffer: &mut [u8]) -> super::Result<usize> {
         let offset = self.offset.load(Ordering::SeqCst);
         let new_offset = self.inode.inode().read_at(offset, buffer)?;
 
         self.offset.fetch_add(new_offset, Ordering::SeqCst);
+
+        if new_offset != 0 && self.advice.load(Ordering::Relaxed) == POSIX_FADV_SEQUENTIAL {
+            let inode = self.inode();
+
+            workqueue::schedule_work(move || {
+                let _ = inode.readahead(offset + new_offset, READAHEAD_WINDOW);
+            });
+        }
+
         Ok(new_offset)
     }
 
@@ -138,13 +181,16 @@ impl FileHandle {
         self.inode.inode()
     }
 
-    pub fn duplicate(&self, dupfd: usize, flags: OpenFlags) -> super::Result<Arc<FileHandle>> {
-        let flags = *self.flags.read() | flags;
+    /// Returns a new file descriptor `dupfd` that refers to the same open file, sharing the file
+    /// offset and status flags with this one. Its close-on-exec flag is set to `cloexec`.
+    pub fn duplicate(&self, dupfd: usize, cloexec: bool) -> super::Result<Arc<FileHandle>> {
         let new = Arc::new(Self {
             fd: dupfd,
             inode: self.inode.clone(),
             offset: self.offset.clone(),
-            flags: RwLock::new(flags),
+            advice: self.advice.clone(),
+            flags: self.flags.clone(),
+            cloexec: AtomicBool::new(cloexec),
         });
 
         new.inode.inode().open(new.clone())?;
@@ -237,10 +283,8 @@ impl FileTable {
 
         for file in files.iter_mut() {
             if let Some(handle) = file {
-                let flags = *handle.flags.read();
-
-                if flags.contains(OpenFlags::O_CLOEXEC) {
-                    handle.inode().close(flags);
+                if handle.is_cloexec() {
+                    handle.inode().close(handle.flags());
                     *file = None;
                 }
             }
@@ -249,54 +293,53 @@ impl FileTable {
 
     /// Duplicates the provided file descriptor based on the provided duplicate
     /// descriptor hint. Check out the documentation for [`DuplicateHint`] for more
-    /// information.
+    /// information. The close-on-exec flag of the duplicate is set to `cloexec`.
     pub fn duplicate(
         &self,
         fd: usize,
         hint: DuplicateHint,
-        flags: OpenFlags,
+        cloexec: bool,
     ) -> Result<usize, aero_syscall::SyscallError> {
         let handle = self
             .get_handle(fd)
-            .ok_or(aero_syscall::SyscallError::EINVAL)?;
+            .ok_or(aero_syscall::SyscallError::EBADF)?;
 
         let find_from = |files: &mut Vec<Option<Arc<FileHandle>>>, start: usize| {
-            let array = &mut files[start..];
+            let array = files
+                .get_mut(start..)
+                .ok_or(aero_syscall::SyscallError::EINVAL)?;
 
             // Loop over the current file descriptor table and find the first
             // available file descriptor.
             for (i, file) in array.iter_mut().enumerate() {
                 if file.is_none() {
-                    *file = Some(handle.duplicate(i, flags)?);
+                    *file = Some(handle.duplicate(start + i, cloexec)?);
                     return Ok(start + i);
                 }
             }
 
             // We ran out of file descriptors. Grow the FD table and insert the FD.
             let fd = files.len();
-            files.push(Some(handle.duplicate(fd, flags)?));
+            files.push(Some(handle.duplicate(fd, cloexec)?));
             Ok(fd)
         };
 
         match hint {
             DuplicateHint::Exact(new_fd) => {
                 let mut files = self.0.write();
+                let file = files
+                    .get_mut(new_fd)
+                    .ok_or(aero_syscall::SyscallError::EBADF)?;
 
-                // Ensure the file descriptor is available.
-                if files[new_fd].is_none() {
-                    files[new_fd] = Some(handle.duplicate(new_fd, flags)?);
-                    Ok(0)
-                } else {
-                    // If the file descriptor is not available, then we close the
-                    // old one and set its handle to the new duplicate handle.
-                    let handle = handle.duplicate(new_fd, flags)?;
-                    let old = files[new_fd].take().unwrap();
-
-                    old.inode.inode().close(*old.flags.read());
-                    files[new_fd] = Some(handle);
+                let handle = handle.duplicate(new_fd, cloexec)?;
 
-                    Ok(0)
+                // If the file descriptor is not available, then we close the
+                // old one and set its handle to the new duplicate handle.
+                if let Some(old) = file.replace(handle) {
+                    old.inode.inode().close(old.flags());
                 }
+
+                Ok(new_fd)
             }
 
             DuplicateHint::Any => {
@@ -314,15 +357,20 @@ impl FileTable {
     pub fn deep_clone(&self) -> Self {
         let files = self.0.read();
 
-        for handle in files.iter().flatten() {
-            handle
-                .inode
-                .inode()
-                .open(handle.clone())
-                .expect("FileTable::clone: failed to open file");
-        }
-
-        Self(RwLock::new(files.clone()))
+        // The file descriptors of the clone refer to the same open files, though each of them
+        // has its own close-on-exec flag.
+        let files = files
+            .iter()
+            .map(|file| {
+                file.as_ref().map(|handle| {
+                    handle
+                        .duplicate(handle.fd, handle.is_cloexec())
+                        .expect("FileTable::clone: failed to open file")
+                })
+            })
+            .collect();
+
+        Self(RwLock::new(files))
     }
 
     pub fn debug_open_file(&self, dirent: DirCacheItem, flags: OpenFlags) -> super::Result<usize> {
@@ -342,8 +390,8 @@ impl FileTable {
             let mut handle = Arc::new(FileHandle::new(i, dentry, flags));
 
             if let Some(inode) = handle.inode.inode().open(handle.clone())? {
-                // TODO: should open be called on the inner file as well???
-                handle = Arc::new(FileHandle::new(i, inode, flags))
+                handle = Arc::new(FileHandle::new(i, inode, flags));
+                handle.inode.inode().open(handle.clone())?;
             }
 
             *f = Some(handle);
@@ -354,8 +402,8 @@ impl FileTable {
             let mut handle = Arc::new(FileHandle::new(fd, dentry, flags));
 
             if let Some(inode) = handle.inode.inode().open(handle.clone())? {
-                // TODO: should open be called on the inner file as well???
-                handle = Arc::new(FileHandle::new(fd, inode, flags))
+                handle = Arc::new(FileHandle::new(fd, inode, flags));
+                handle.inode.inode().open(handle.clone())?;
             }
 
             files.push(Some(handle));
@@ -366,6 +414,32 @@ impl FileTable {
         }
     }
 
+    /// Installs a duplicate of the provided file `handle` (which may belong to the file table
+    /// of another task) at the lowest available file descriptor. The new file descriptor
+    /// shares the file offset and status flags with `handle`.
+    pub fn install_handle(&self, handle: &FileHandle, cloexec: bool) -> super::Result<usize> {
+        let mut files = self.0.write();
+
+        let fd = files
+            .iter()
+            .position(|file| file.is_none())
+            .unwrap_or(files.len());
+
+        if fd >= 256 {
+            return Err(FileSystemError::Busy);
+        }
+
+        let new = handle.duplicate(fd, cloexec)?;
+
+        if fd == files.len() {
+            files.push(Some(new));
+        } else {
+            files[fd] = Some(new);
+        }
+
+        Ok(fd)
+    }
+
     /// Closes a file descriptor, so that its no longer refers to any file
     /// and can be reused. This function will return false if the provided file
     /// descriptor index was invalid.
@@ -378,7 +452,7 @@ impl FileTable {
 
         if let Some(file) = files.get_mut(fd) {
             if let Some(handle) = file {
-                handle.inode.inode().close(*handle.flags.read());
+                handle.inode.inode().close(handle.flags());
                 *file = None;
 
                 return true;
@@ -15,10 +15,11 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::ops::Range;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use aero_syscall::prelude::{EPollEventFlags, PollEventFlags};
-use aero_syscall::socket::{MessageFlags, MessageHeader};
+use aero_syscall::socket::{MessageFlags, MessageHeader, SocketOptionLevel};
 use aero_syscall::{MMapFlags, OpenFlags, SyscallError};
 
 use alloc::sync::{Arc, Weak};
@@ -37,7 +38,9 @@ use super::block::PageCacheItem;
 use super::cache::{Cacheable, CachedINode, DirCacheItem, INodeCacheItem};
 use super::devfs::DevINode;
 use super::file_table::FileHandle;
+use super::memfd::MemFd;
 use super::path::PathBuf;
+use super::pipe::PipePage;
 use super::{cache, FileSystem, FileSystemError, Path, Result};
 
 static DIR_CACHE_MARKER: AtomicUsize = AtomicUsize::new(0x00);
@@ -71,6 +74,20 @@ bitflags::bitflags! {
         const OUT = 1 << 2;
         /// Error condition happened on the associated file descriptor.
         const ERR = 1 << 3;
+        /// The other end of the associated file has been closed.
+        const HUP = 1 << 4;
+    }
+}
+
+impl PollFlags {
+    /// Returns the readiness of an inode of the provided type that never blocks. Regular files
+    /// and directories are always ready for reading and writing (as required by POSIX); polling
+    /// any other type of inode is not supported.
+    pub fn always_ready(file_type: FileType) -> Result<Self> {
+        match file_type {
+            FileType::File | FileType::Directory => Ok(Self::IN | Self::OUT),
+            _ => Err(FileSystemError::NotSupported),
+        }
     }
 }
 
@@ -87,6 +104,9 @@ impl From<PollFlags> for EPollEventFlags {
         if poll.contains(PollFlags::ERR) {
             flags |= Self::ERR;
         }
+        if poll.contains(PollFlags::HUP) {
+            flags |= Self::HUP;
+        }
 
         flags
     }
@@ -105,6 +125,9 @@ impl From<PollFlags> for PollEventFlags {
         if poll.contains(PollFlags::ERR) {
             flags |= Self::ERR;
         }
+        if poll.contains(PollFlags::HUP) {
+            flags |= Self::HUP;
+        }
 
         flags
     }
@@ -164,6 +187,19 @@ pub trait INodeInterface: Send + Sync {
         Ok(aero_syscall::Stat::default())
     }
 
+    /// Creates a new character (`S_IFCHR`) or block (`S_IFBLK`) device node, as given by the
+    /// file type bits of `mode`, with the provided `name` and device number `dev`. Opening the
+    /// node opens the device registered with that device number.
+    fn mknod(
+        &self,
+        _parent: DirCacheItem,
+        _name: &str,
+        _mode: aero_syscall::Mode,
+        _dev: u64,
+    ) -> Result<DirCacheItem> {
+        Err(FileSystemError::NotSupported)
+    }
+
     fn shutdown(&self, _how: usize) -> Result<()> {
         Err(FileSystemError::NotSupported)
     }
@@ -229,6 +265,47 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSupported)
     }
 
+    /// Writes back the modified data and metadata of the file and waits for it to reach
+    /// stable media (see `fsync(2)`). Files that only live in memory have nothing to do.
+    fn fsync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes the on-disk inode, which was modified in memory, back to the filesystem. Called for
+    /// the inodes marked dirty with [`cache::mark_inode_dirty`] by the periodic writeback.
+    fn write_inode(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reads the data in `len` bytes of the file starting at `offset` into the page cache, so
+    /// later accesses do not have to wait for the device (see `posix_fadvise(2)`). Files that
+    /// are not backed by the page cache have nothing to do.
+    fn readahead(&self, _offset: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Evicts the data in `len` bytes of the file starting at `offset`, that is not in use,
+    /// from the page cache. Modified data is written back first.
+    fn drop_cache(&self, _offset: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the page of the page cache that holds the data of the file at `offset`, along
+    /// with the range of the page that the data continues in, so it can be spliced into a pipe
+    /// without being copied (see `splice(2)`). Files whose data is not read through the page
+    /// cache, holes and offsets past the end of the file return [`None`], and are read with
+    /// [`INodeInterface::read_at`] instead.
+    fn cached_page(&self, _offset: usize) -> Result<Option<(PageCacheItem, Range<usize>)>> {
+        Ok(None)
+    }
+
+    /// Takes ownership of a page spliced out of a pipe and writes its data at `offset`
+    /// (see `splice(2)`). Sockets override this to transmit the data straight from the
+    /// page instead of copying it into their send queue first.
+    fn splice_page(&self, offset: usize, page: PipePage) -> Result<usize> {
+        self.write_at(offset, page.as_slice())
+    }
+
     /// ## Safety
     ///
     /// The caller is responsible for removing the inode from the cache.
@@ -240,6 +317,18 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSupported)
     }
 
+    /// Called by `mmap(2)` before `size` bytes of the inode starting at `offset` are mapped,
+    /// so inodes that have to set up their backing memory for the whole mapping up front (e.g.
+    /// the ring buffer of a perf event) can do so or reject the mapping.
+    fn mmap_prepare(&self, _offset: usize, _size: usize, _flags: MMapFlags) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a shared mapping of the inode that can be made writable is created
+    /// (`mapped` is `true`) or destroyed, so inodes can tell whether they can still be written
+    /// to through a mapping.
+    fn map_writable(&self, _mapped: bool) {}
+
     fn mmap_v2(&self, _offset: usize) -> Result<MMapPage> {
         log::error!(
             "{} does not support mmap_v2!",
@@ -248,6 +337,26 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSupported)
     }
 
+    /// Returns the file seals (`F_SEAL_*`) set on this inode.
+    fn seals(&self) -> ::core::result::Result<usize, SyscallError> {
+        Err(SyscallError::EINVAL)
+    }
+
+    /// Adds the provided file seals (`F_SEAL_*`) to this inode.
+    fn add_seals(&self, _seals: usize) -> ::core::result::Result<(), SyscallError> {
+        Err(SyscallError::EINVAL)
+    }
+
+    /// Returns the capacity (in bytes) of this pipe.
+    fn pipe_size(&self) -> ::core::result::Result<usize, SyscallError> {
+        Err(SyscallError::EBADF)
+    }
+
+    /// Resizes this pipe to hold at least `size` bytes. Returns the new capacity of the pipe.
+    fn set_pipe_size(&self, _size: usize) -> ::core::result::Result<usize, SyscallError> {
+        Err(SyscallError::EBADF)
+    }
+
     // Socket operations:
     fn bind(&self, _address: SocketAddrRef, _length: usize) -> Result<()> {
         Err(FileSystemError::NotSocket)
@@ -281,13 +390,39 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSupported)
     }
 
+    /// Sets the value of the socket option `name` at the protocol `level`.
+    fn set_sockopt(
+        &self,
+        _level: SocketOptionLevel,
+        _name: i32,
+        _value: &[u8],
+    ) -> ::core::result::Result<(), SyscallError> {
+        Err(SyscallError::ENOTSOCK)
+    }
+
+    /// Writes the value of the socket option `name` at the protocol `level` into `value`
+    /// and returns its size.
+    fn get_sockopt(
+        &self,
+        _level: SocketOptionLevel,
+        _name: i32,
+        _value: &mut [u8],
+    ) -> ::core::result::Result<usize, SyscallError> {
+        Err(SyscallError::ENOTSOCK)
+    }
+
     /// Returns the inner UNIX socket inode if bound to one.
     fn as_unix_socket(&self) -> Result<Arc<dyn INodeInterface>> {
         Err(FileSystemError::NotSocket)
     }
 
+    /// Returns the events that the inode is ready for. If `table` is provided, the inode adds
+    /// the wait queues that are notified once its readiness changes to it.
+    ///
+    /// By default, regular files and directories are always ready (see
+    /// [`PollFlags::always_ready`]).
     fn poll(&self, _table: Option<&mut PollTable>) -> Result<PollFlags> {
-        Err(FileSystemError::NotSupported)
+        PollFlags::always_ready(self.metadata()?.file_type())
     }
 
     fn link(&self, _name: &str, _src: DirCacheItem) -> Result<()> {
@@ -366,6 +501,10 @@ pub enum FileContents {
     /// This variant is used to store the backing socket inode.
     Socket(Arc<dyn INodeInterface>),
 
+    /// This variant expresses a *normal file* whose contents are stored in pages which can
+    /// be directly mapped and shared between processes (used by tmpfs).
+    Memory(Arc<MemFd>),
+
     /// This file does *not* and *cannot* have any contents in bytes. This is useful
     /// in the cases of directories.
     None,
@@ -433,6 +572,10 @@ impl DirEntry {
         // ".." (ie. we do not want to re-cache the current directory's, parent directory).
         let cache_me = ![".", ".."].contains(&name.as_str());
 
+        if cache_me {
+            cache::ndcache().remove(&(parent.cache_marker, name.clone()));
+        }
+
         let filesystem = if let Some(fs) = inode.weak_filesystem() {
             Once::initialized(fs)
         } else {
@@ -511,6 +654,8 @@ impl DirEntry {
             .inode()
             .make_local_socket_inode(name.as_str(), inode)?;
 
+        invalidate_negative_dir_entry(&parent, &name);
+
         Ok(cache::dcache().make_item_no_cache(Self {
             data: BMutex::new(DirProtectedData {
                 parent: Some(parent),
@@ -562,3 +707,35 @@ pub fn fetch_dir_entry(parent: &DirCacheItem, name: String) -> Option<DirCacheIt
 
     dcache.get(cache_key)
 }
+
+/// Returns whether `name` is known to not exist in the `parent` directory (ie. there is a
+/// negative directory entry for it).
+pub fn is_negative_dir_entry(parent: &DirCacheItem, name: &str) -> bool {
+    cache::ndcache().contains(&(parent.cache_marker, String::from(name)))
+}
+
+/// Records that `name` does not exist in the `parent` directory, if the filesystem of the
+/// directory supports negative directory entries.
+pub fn make_negative_dir_entry(parent: &DirCacheItem, name: &str) {
+    // The directory is not in the directory cache (e.g. the "." and ".." entries).
+    if parent.cache_marker == 0 {
+        return;
+    }
+
+    let supported = parent
+        .inode()
+        .weak_filesystem()
+        .and_then(|filesystem| filesystem.upgrade())
+        .is_some_and(|filesystem| filesystem.caches_negative_entries());
+
+    if supported {
+        cache::ndcache().insert((parent.cache_marker, String::from(name)));
+    }
+}
+
+/// Invalidates the negative directory entry of `name` in the `parent` directory. This has to
+/// be called whenever `name` is created in the directory without creating its directory entry
+/// with [`DirEntry::new`] (which does so itself), e.g. by [`INodeInterface::mkdir`].
+pub fn invalidate_negative_dir_entry(parent: &DirCacheItem, name: &str) {
+    cache::ndcache().remove(&(parent.cache_marker, String::from(name)));
+}
@@ -0,0 +1,529 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Asynchronous I/O rings, created with `io_uring_setup(2)`.
+//!
+//! An I/O ring is a submission queue (SQ) and a completion queue (CQ) shared with userland
+//! through a single mapping of the ring file descriptor. Userland fills in SQ entries and
+//! advances the SQ tail, and `io_uring_enter(2)` consumes the entries and hands each request off
+//! to one of the I/O workers, so the caller does not wait for any of them to finish.
+//!
+//! The workers cannot access the address space of the submitter, so the data to write is copied
+//! when a request is submitted and the data that was read is copied out when the completion of
+//! the request is posted. Completions are therefore posted to the CQ by `io_uring_enter(2)`
+//! rather than by the workers: the ring file descriptor polls readable once a request has
+//! completed, and `io_uring_enter(fd, 0, 0, IORING_ENTER_GETEVENTS)` then posts its completion.
+//!
+//! ## Notes
+//! * Only regular files and block devices are supported.
+//! * A read or write request transfers at most [`MAX_IO_SIZE`] bytes.
+
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use aero_syscall::io_uring::*;
+use aero_syscall::{MMapFlags, SyscallError};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::mem::paging::*;
+use crate::userland::scheduler;
+use crate::userland::workqueue::WorkQueue;
+use crate::utils::sync::{BMutex, Mutex, WaitQueue};
+
+use super::file_table::FileHandle;
+use super::inode::{FileType, INodeInterface, MMapPage, PollFlags, PollTable};
+use super::{block, FileSystemError, Result};
+
+/// Maximum number of bytes transferred by a single read or write request.
+pub const MAX_IO_SIZE: usize = 1024 * 1024;
+
+/// Number of I/O workers. The requests of every ring are spread across all of them.
+const NR_WORKERS: usize = 4;
+
+static WORKERS: Once<Vec<Arc<WorkQueue>>> = Once::new();
+static NEXT_WORKER: AtomicUsize = AtomicUsize::new(0);
+
+/// Queues `work` on the next I/O worker.
+fn queue_work<F>(work: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let workers = WORKERS.call_once(|| {
+        (0..NR_WORKERS)
+            .map(|i| WorkQueue::new(&alloc::format!("io_uring/{i}")))
+            .collect()
+    });
+
+    let index = NEXT_WORKER.fetch_add(1, Ordering::Relaxed) % NR_WORKERS;
+    workers[index].queue(work);
+}
+
+/// A page of the ring mapping.
+struct RingPage(PhysFrame);
+
+impl RingPage {
+    fn new() -> Result<Self> {
+        let frame = FRAME_ALLOCATOR
+            .alloc_zeroed(Size4KiB::SIZE as usize)
+            .ok_or(FileSystemError::OutOfMemory)?;
+
+        // Take a reference to the frame, so it is not deallocated when the page is unmapped
+        // from a process.
+        frame.as_vm_frame().unwrap().inc_ref_count();
+        Ok(Self(PhysFrame::containing_address(frame)))
+    }
+}
+
+impl Drop for RingPage {
+    fn drop(&mut self) {
+        let vm_frame = self.0.start_address().as_vm_frame().unwrap();
+        vm_frame.dec_ref_count();
+
+        if vm_frame.ref_count() == 0 {
+            FRAME_ALLOCATOR.deallocate_frame(self.0);
+        }
+    }
+}
+
+/// The start of the ring mapping. The indices of the queues are free running: the entry of an
+/// index is at `index & mask`.
+#[repr(C)]
+struct RingHeader {
+    sq_head: AtomicU32,
+    sq_tail: AtomicU32,
+    sq_mask: AtomicU32,
+    sq_entries: AtomicU32,
+    cq_head: AtomicU32,
+    cq_tail: AtomicU32,
+    cq_mask: AtomicU32,
+    cq_entries: AtomicU32,
+}
+
+enum Operation {
+    Nop,
+    Read(Arc<FileHandle>, usize),
+    Write(Arc<FileHandle>, Vec<u8>),
+    Fsync(Arc<FileHandle>),
+}
+
+/// A request that is handed off to an I/O worker.
+struct Request {
+    operation: Operation,
+    /// Offset of the file to read from or write to, or [`None`] to use the file offset.
+    offset: Option<usize>,
+    addr: usize,
+    user_data: u64,
+}
+
+impl Request {
+    /// Prepares the request of `sqe`. Called in the context of the submitter.
+    fn new(sqe: &IoUringSqe) -> core::result::Result<Self, SyscallError> {
+        if sqe.flags != 0 {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let handle = || -> core::result::Result<Arc<FileHandle>, SyscallError> {
+            let handle = scheduler::current_thread()
+                .file_table
+                .get_handle(sqe.fd as usize)
+                .ok_or(SyscallError::EBADF)?;
+
+            let supported = match handle.inode().metadata()?.file_type() {
+                FileType::File => true,
+                FileType::Device => block::block_device_by_name(&handle.dirnode().name()).is_some(),
+                _ => false,
+            };
+
+            if !supported {
+                return Err(SyscallError::EOPNOTSUPP);
+            }
+
+            Ok(handle)
+        };
+
+        let len = core::cmp::min(sqe.len as usize, MAX_IO_SIZE);
+
+        let operation = match sqe.opcode {
+            IORING_OP_NOP => Operation::Nop,
+
+            IORING_OP_READ => {
+                let handle = handle()?;
+
+                if !handle.is_readable() {
+                    return Err(SyscallError::EBADF);
+                }
+
+                Operation::Read(handle, len)
+            }
+
+            IORING_OP_WRITE => {
+                let handle = handle()?;
+
+                if !handle.is_writable() {
+                    return Err(SyscallError::EBADF);
+                }
+
+                let data = crate::utils::validate_slice(sqe.addr as *const u8, len)?;
+                Operation::Write(handle, data.to_vec())
+            }
+
+            IORING_OP_FSYNC if sqe.op_flags & !IORING_FSYNC_DATASYNC == 0 => {
+                Operation::Fsync(handle()?)
+            }
+
+            _ => return Err(SyscallError::EINVAL),
+        };
+
+        Ok(Self {
+            operation,
+            offset: (sqe.off != u64::MAX).then_some(sqe.off as usize),
+            addr: sqe.addr as usize,
+            user_data: sqe.user_data,
+        })
+    }
+
+    /// Performs the request. Called on an I/O worker.
+    fn run(self) -> Completion {
+        let mut data = None;
+
+        let result = match self.operation {
+            Operation::Nop => Ok(0),
+
+            Operation::Read(handle, len) => {
+                let mut buffer = alloc::vec![0; len];
+                let result = match self.offset {
+                    Some(offset) => handle.inode().read_at(offset, &mut buffer),
+                    None => handle.read(&mut buffer),
+                };
+
+                result.map(|count| {
+                    buffer.truncate(count);
+                    data = Some((self.addr, buffer));
+                    count
+                })
+            }
+
+            Operation::Write(handle, buffer) => match self.offset {
+                Some(offset) => handle.inode().write_at(offset, &buffer),
+                None => handle.write(&buffer),
+            },
+
+            Operation::Fsync(handle) => handle.inode().fsync().map(|_| 0),
+        };
+
+        Completion {
+            user_data: self.user_data,
+            result: result.map_err(SyscallError::from),
+            data,
+        }
+    }
+}
+
+/// A request that has completed, but whose completion has not been posted to the CQ yet.
+struct Completion {
+    user_data: u64,
+    result: core::result::Result<usize, SyscallError>,
+    /// The data that was read and the address to copy it to.
+    data: Option<(usize, Vec<u8>)>,
+}
+
+pub struct IoUring {
+    pages: Vec<RingPage>,
+    sq_entries: u32,
+    cq_entries: u32,
+    /// Offset of the CQ entries in the ring mapping. The SQ entries start at the second page.
+    cqes_offset: usize,
+    /// Number of requests that were submitted and whose completion has not been posted yet. It
+    /// never exceeds the size of the CQ, so every completion can be posted eventually.
+    outstanding: AtomicUsize,
+    completed: Mutex<VecDeque<Completion>>,
+    /// Serializes the callers of `io_uring_enter(2)`, which consume the SQ and post to the CQ.
+    enter_lock: BMutex<()>,
+    wq: WaitQueue,
+}
+
+impl IoUring {
+    /// Creates a ring with `entries` SQ entries (rounded up to a power of two) and twice as many
+    /// CQ entries, and fills in `params` with the layout of the ring mapping.
+    pub fn new(entries: u32, params: &mut IoUringParams) -> Result<Arc<Self>> {
+        if entries == 0 || entries > IORING_MAX_ENTRIES || params.flags != 0 {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let sq_entries = entries.next_power_of_two();
+        let cq_entries = sq_entries * 2;
+
+        let page_size = Size4KiB::SIZE as usize;
+        let sqes_offset = page_size;
+        let cqes_offset = sqes_offset + sq_entries as usize * size_of::<IoUringSqe>();
+        let size = cqes_offset + cq_entries as usize * size_of::<IoUringCqe>();
+
+        let pages = (0..size.div_ceil(page_size))
+            .map(|_| RingPage::new())
+            .collect::<Result<Vec<_>>>()?;
+
+        let this = Arc::new(Self {
+            pages,
+            sq_entries,
+            cq_entries,
+            cqes_offset,
+            outstanding: AtomicUsize::new(0),
+            completed: Mutex::new(VecDeque::new()),
+            enter_lock: BMutex::new(()),
+            wq: WaitQueue::new(),
+        });
+
+        let header = this.header();
+
+        header.sq_mask.store(sq_entries - 1, Ordering::Relaxed);
+        header.sq_entries.store(sq_entries, Ordering::Relaxed);
+        header.cq_mask.store(cq_entries - 1, Ordering::Relaxed);
+        header.cq_entries.store(cq_entries, Ordering::Relaxed);
+
+        *params = IoUringParams {
+            sq_entries,
+            cq_entries,
+            flags: 0,
+            resv: 0,
+            ring_size: (this.pages.len() * page_size) as u64,
+            sq_off: IoSqringOffsets {
+                head: offset_of!(RingHeader, sq_head) as u32,
+                tail: offset_of!(RingHeader, sq_tail) as u32,
+                ring_mask: offset_of!(RingHeader, sq_mask) as u32,
+                ring_entries: offset_of!(RingHeader, sq_entries) as u32,
+                sqes: sqes_offset as u32,
+            },
+            cq_off: IoCqringOffsets {
+                head: offset_of!(RingHeader, cq_head) as u32,
+                tail: offset_of!(RingHeader, cq_tail) as u32,
+                ring_mask: offset_of!(RingHeader, cq_mask) as u32,
+                ring_entries: offset_of!(RingHeader, cq_entries) as u32,
+                cqes: cqes_offset as u32,
+            },
+        };
+
+        Ok(this)
+    }
+
+    /// Returns a pointer to the `T` at `offset` in the ring mapping. The `T` must not cross a
+    /// page boundary.
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        let page = &self.pages[offset / Size4KiB::SIZE as usize];
+        let address = page.0.start_address().as_hhdm_virt() + offset % Size4KiB::SIZE as usize;
+
+        address.as_mut_ptr::<T>()
+    }
+
+    fn header(&self) -> &RingHeader {
+        // SAFETY: The header is at the start of the first page and only has atomic fields, as
+        // it is shared with userland.
+        unsafe { &*self.ptr::<RingHeader>(0) }
+    }
+
+    /// Returns the number of completions in the CQ that userland has not consumed yet.
+    fn cq_ready(&self) -> u32 {
+        let header = self.header();
+
+        let tail = header.cq_tail.load(Ordering::Relaxed);
+        tail.wrapping_sub(header.cq_head.load(Ordering::Acquire))
+    }
+
+    /// Returns whether the CQ could not take the completion of another request.
+    fn is_busy(&self) -> bool {
+        self.outstanding.load(Ordering::SeqCst) >= self.cq_entries as usize
+    }
+
+    /// Consumes up to `count` SQ entries and returns the number of entries consumed.
+    fn submit(self: &Arc<Self>, count: usize) -> usize {
+        let header = self.header();
+        let mut submitted = 0;
+
+        while submitted < count && !self.is_busy() {
+            let head = header.sq_head.load(Ordering::Relaxed);
+
+            if head == header.sq_tail.load(Ordering::Acquire) {
+                break;
+            }
+
+            let index = (head & (self.sq_entries - 1)) as usize;
+            let offset = Size4KiB::SIZE as usize + index * size_of::<IoUringSqe>();
+
+            // SAFETY: The SQ entries start at a page boundary and evenly divide a page. The entry
+            // is read volatile, as userland could be writing to it.
+            let sqe = unsafe { self.ptr::<IoUringSqe>(offset).read_volatile() };
+
+            header
+                .sq_head
+                .store(head.wrapping_add(1), Ordering::Release);
+
+            self.outstanding.fetch_add(1, Ordering::SeqCst);
+            submitted += 1;
+
+            match Request::new(&sqe) {
+                Ok(request) => {
+                    let ring = self.clone();
+                    queue_work(move || ring.complete(request.run()));
+                }
+
+                Err(err) => self.complete(Completion {
+                    user_data: sqe.user_data,
+                    result: Err(err),
+                    data: None,
+                }),
+            }
+        }
+
+        submitted
+    }
+
+    fn complete(&self, completion: Completion) {
+        self.completed.lock_irq().push_back(completion);
+        self.wq.notify_all();
+    }
+
+    /// Posts the completions of the requests that have completed to the CQ, as long as it has
+    /// space for them.
+    fn reap(&self) {
+        let header = self.header();
+
+        while self.cq_ready() < self.cq_entries {
+            let Some(completion) = self.completed.lock_irq().pop_front() else {
+                break;
+            };
+
+            let mut res = match completion.result {
+                Ok(count) => count as i32,
+                Err(err) => -(err as i32),
+            };
+
+            if let Some((addr, data)) = completion.data {
+                match crate::utils::validate_slice_mut(addr as *mut u8, data.len()) {
+                    Ok(buffer) => buffer.copy_from_slice(&data),
+                    Err(_) => res = -(SyscallError::EFAULT as i32),
+                }
+            }
+
+            let tail = header.cq_tail.load(Ordering::Relaxed);
+            let index = (tail & (self.cq_entries - 1)) as usize;
+            let offset = self.cqes_offset + index * size_of::<IoUringCqe>();
+
+            let cqe = IoUringCqe {
+                user_data: completion.user_data,
+                res,
+                flags: 0,
+            };
+
+            // SAFETY: The CQ entries are aligned to their size, which evenly divides a page.
+            unsafe { self.ptr::<IoUringCqe>(offset).write_volatile(cqe) };
+
+            header
+                .cq_tail
+                .store(tail.wrapping_add(1), Ordering::Release);
+
+            self.outstanding.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Submits up to `to_submit` SQ entries and posts the completions of the requests that have
+    /// completed. With `IORING_ENTER_GETEVENTS`, waits until there are at least `min_complete`
+    /// completions in the CQ (or no requests left to complete). Returns the number of SQ entries
+    /// that were submitted.
+    pub fn enter(
+        self: &Arc<Self>,
+        to_submit: usize,
+        min_complete: usize,
+        flags: usize,
+    ) -> core::result::Result<usize, SyscallError> {
+        if flags & !IORING_ENTER_GETEVENTS != 0 {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let _guard = self.enter_lock.lock();
+
+        // Make space for the completions of the new requests first.
+        self.reap();
+
+        let submitted = self.submit(to_submit);
+
+        if submitted == 0 && to_submit != 0 && self.is_busy() {
+            return Err(SyscallError::EBUSY);
+        }
+
+        self.reap();
+
+        if flags & IORING_ENTER_GETEVENTS != 0 {
+            let min_complete = core::cmp::min(min_complete, self.cq_entries as usize) as u32;
+
+            while self.cq_ready() < min_complete && self.outstanding.load(Ordering::SeqCst) != 0 {
+                let _ = self
+                    .wq
+                    .block_on(&self.completed, |completed| !completed.is_empty())?;
+
+                self.reap();
+            }
+        }
+
+        Ok(submitted)
+    }
+}
+
+impl INodeInterface for IoUring {
+    fn poll(&self, table: Option<&mut PollTable>) -> Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        let header = self.header();
+        let mut events = PollFlags::empty();
+
+        if self.cq_ready() != 0 || !self.completed.lock_irq().is_empty() {
+            events.insert(PollFlags::IN);
+        }
+
+        let sq_tail = header.sq_tail.load(Ordering::Acquire);
+
+        if sq_tail.wrapping_sub(header.sq_head.load(Ordering::Relaxed)) < self.sq_entries {
+            events.insert(PollFlags::OUT);
+        }
+
+        Ok(events)
+    }
+
+    fn mmap_prepare(&self, offset: usize, size: usize, flags: MMapFlags) -> Result<()> {
+        // The ring is shared with the kernel and has to be mapped as a whole.
+        if offset != 0
+            || !flags.contains(MMapFlags::MAP_SHARED)
+            || size.div_ceil(Size4KiB::SIZE as usize) != self.pages.len()
+        {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
+        let page = self
+            .pages
+            .get(offset / Size4KiB::SIZE as usize)
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        Ok(MMapPage::Direct(page.0))
+    }
+}
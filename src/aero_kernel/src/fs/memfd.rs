@@ -0,0 +1,285 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Anonymous memory backed files, created using `memfd_create(2)`.
+//!
+//! A memfd behaves like a regular file which lives in memory and is not reachable from the
+//! filesystem. File seals (`F_ADD_SEALS`) can be used to restrict the operations that are
+//! allowed on the file, so processes receiving the file descriptor (e.g. Wayland compositors)
+//! can safely map it without having to worry about it being modified or shrunk under their
+//! feet.
+
+use aero_syscall::prelude::{F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK, F_SEAL_WRITE};
+use aero_syscall::{MMapFlags, SyscallError};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use crate::mem::paging::*;
+use crate::utils::sync::Mutex;
+
+use super::inode::{FileType, INodeInterface, MMapPage, Metadata};
+use super::{FileSystemError, Result};
+
+/// Maximum size of a memfd.
+const MAX_SIZE: usize = 1 << 40;
+
+/// A page holding the contents of a memfd.
+struct MemFdPage(PhysFrame);
+
+impl MemFdPage {
+    fn new() -> Result<Self> {
+        let frame = FRAME_ALLOCATOR
+            .alloc_zeroed(Size4KiB::SIZE as usize)
+            .ok_or(FileSystemError::OutOfMemory)?;
+
+        // Take a reference to the frame, so it is not deallocated when the page is unmapped
+        // from a process.
+        frame.as_vm_frame().unwrap().inc_ref_count();
+        Ok(Self(PhysFrame::containing_address(frame)))
+    }
+
+    fn as_slice_mut(&self) -> &mut [u8] {
+        self.0.as_slice_mut()
+    }
+}
+
+impl Drop for MemFdPage {
+    fn drop(&mut self) {
+        // Drop the reference taken in `MemFdPage::new`. The frame is deallocated if it is
+        // not mapped anywhere else.
+        let vm_frame = self.0.start_address().as_vm_frame().unwrap();
+        vm_frame.dec_ref_count();
+
+        if vm_frame.ref_count() == 0 {
+            FRAME_ALLOCATOR.deallocate_frame(self.0);
+        }
+    }
+}
+
+struct MemFdInner {
+    /// Pages of the file by page index. The pages that were never written to are not
+    /// allocated and read as zeros.
+    pages: BTreeMap<usize, MemFdPage>,
+    size: usize,
+    seals: usize,
+    /// Number of shared mappings of the file that can be made writable.
+    writable_mappings: usize,
+}
+
+impl MemFdInner {
+    /// Returns the page at the provided page index, allocating it if required.
+    fn page(&mut self, index: usize) -> Result<&MemFdPage> {
+        if !self.pages.contains_key(&index) {
+            self.pages.insert(index, MemFdPage::new()?);
+        }
+
+        Ok(&self.pages[&index])
+    }
+
+    fn resize(&mut self, size: usize) {
+        if size < self.size {
+            let npages = align_up(size as u64, Size4KiB::SIZE) / Size4KiB::SIZE;
+            self.pages.split_off(&(npages as usize));
+
+            // Zero out the tail of the last page, so the contents do not reappear if the
+            // file is extended again.
+            let offset = size % Size4KiB::SIZE as usize;
+
+            if offset != 0 {
+                if let Some(page) = self.pages.get(&(size / Size4KiB::SIZE as usize)) {
+                    page.as_slice_mut()[offset..].fill(0);
+                }
+            }
+        }
+
+        self.size = size;
+    }
+}
+
+pub struct MemFd {
+    inner: Mutex<MemFdInner>,
+}
+
+impl MemFd {
+    /// Creates a new, empty, memfd. If `allow_sealing` is `false`, the `F_SEAL_SEAL` seal is
+    /// set, so no further seals can be added.
+    pub fn new(allow_sealing: bool) -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(MemFdInner {
+                pages: BTreeMap::new(),
+                size: 0,
+                seals: if allow_sealing { 0 } else { F_SEAL_SEAL },
+                writable_mappings: 0,
+            }),
+        })
+    }
+}
+
+impl INodeInterface for MemFd {
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::with_file_type(FileType::File);
+        metadata.size = self.inner.lock().size;
+
+        Ok(metadata)
+    }
+
+    fn stat(&self) -> Result<aero_syscall::Stat> {
+        Ok(aero_syscall::Stat {
+            st_size: self.inner.lock().size as _,
+            ..Default::default()
+        })
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let inner = self.inner.lock();
+
+        if offset >= inner.size {
+            return Ok(0);
+        }
+
+        let size = core::cmp::min(buffer.len(), inner.size - offset);
+        let mut copied = 0;
+
+        while copied < size {
+            let position = offset + copied;
+            let page_offset = position % Size4KiB::SIZE as usize;
+            let chunk = core::cmp::min(size - copied, Size4KiB::SIZE as usize - page_offset);
+
+            let dest = &mut buffer[copied..copied + chunk];
+
+            match inner.pages.get(&(position / Size4KiB::SIZE as usize)) {
+                Some(page) => dest.copy_from_slice(&page.as_slice_mut()[page_offset..][..chunk]),
+                None => dest.fill(0),
+            }
+
+            copied += chunk;
+        }
+
+        Ok(size)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.lock();
+
+        if inner.seals & F_SEAL_WRITE != 0 {
+            return Err(FileSystemError::PermissionDenied);
+        }
+
+        let end = offset
+            .checked_add(buffer.len())
+            .filter(|end| *end <= MAX_SIZE)
+            .ok_or(FileSystemError::NoSpace)?;
+
+        if end > inner.size && inner.seals & F_SEAL_GROW != 0 {
+            return Err(FileSystemError::PermissionDenied);
+        }
+
+        let mut copied = 0;
+
+        while copied < buffer.len() {
+            let position = offset + copied;
+            let page_offset = position % Size4KiB::SIZE as usize;
+            let chunk =
+                core::cmp::min(buffer.len() - copied, Size4KiB::SIZE as usize - page_offset);
+
+            let page = inner.page(position / Size4KiB::SIZE as usize)?;
+            page.as_slice_mut()[page_offset..page_offset + chunk]
+                .copy_from_slice(&buffer[copied..copied + chunk]);
+
+            copied += chunk;
+        }
+
+        if end > inner.size {
+            inner.size = end;
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        let mut inner = self.inner.lock();
+
+        if (size < inner.size && inner.seals & F_SEAL_SHRINK != 0)
+            || (size > inner.size && inner.seals & F_SEAL_GROW != 0)
+        {
+            return Err(FileSystemError::PermissionDenied);
+        }
+
+        if size > MAX_SIZE {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        inner.resize(size);
+        Ok(())
+    }
+
+    fn mmap(&self, offset: usize, size: usize, _flags: MMapFlags) -> Result<PhysFrame> {
+        let mut inner = self.inner.lock();
+        let page = inner.page(offset / Size4KiB::SIZE as usize)?;
+
+        let private_cp: PhysFrame = FRAME_ALLOCATOR
+            .allocate_frame()
+            .ok_or(FileSystemError::OutOfMemory)?;
+        private_cp.as_slice_mut()[..size].copy_from_slice(&page.as_slice_mut()[..size]);
+
+        Ok(private_cp)
+    }
+
+    fn map_writable(&self, mapped: bool) {
+        let mut inner = self.inner.lock();
+
+        if mapped {
+            inner.writable_mappings += 1;
+        } else {
+            inner.writable_mappings -= 1;
+        }
+    }
+
+    fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
+        let mut inner = self.inner.lock();
+        let page = inner.page(offset / Size4KiB::SIZE as usize)?;
+
+        Ok(MMapPage::Direct(page.0))
+    }
+
+    fn seals(&self) -> ::core::result::Result<usize, SyscallError> {
+        Ok(self.inner.lock().seals)
+    }
+
+    fn add_seals(&self, seals: usize) -> ::core::result::Result<(), SyscallError> {
+        let mut inner = self.inner.lock();
+
+        if seals & !(F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE) != 0 {
+            return Err(SyscallError::EINVAL);
+        }
+
+        if inner.seals & F_SEAL_SEAL != 0 {
+            return Err(SyscallError::EPERM);
+        }
+
+        // The file could still be written to through a shared mapping.
+        if seals & F_SEAL_WRITE != 0
+            && inner.seals & F_SEAL_WRITE == 0
+            && inner.writable_mappings != 0
+        {
+            return Err(SyscallError::EBUSY);
+        }
+
+        inner.seals |= seals;
+        Ok(())
+    }
+}
@@ -34,22 +34,30 @@ use spin::Once;
 use self::cache::{Cacheable, DirCacheItem};
 
 pub mod block;
+pub mod btrfs;
 pub mod cache;
+pub mod cgroupfs;
 pub mod devfs;
+pub mod efivarfs;
 pub mod epoll;
 pub mod eventfd;
 pub mod ext2;
 pub mod file_table;
 pub mod inode;
+pub mod io_uring;
+pub mod memfd;
 pub mod pipe;
 pub mod procfs;
 pub mod ramfs;
+pub mod tracefs;
+pub mod writeback;
 
 static ROOT_FS: Once<Arc<dyn FileSystem>> = Once::new();
 static ROOT_DIR: Once<DirCacheItem> = Once::new();
 
 lazy_static::lazy_static! {
-    pub static ref MOUNT_MANAGER: MountManager = MountManager::new();
+    /// Mount table of the initial mount namespace.
+    pub static ref MOUNT_MANAGER: Arc<MountManager> = Arc::new(MountManager::new());
 }
 
 pub type Result<T> = core::result::Result<T, FileSystemError>;
@@ -63,6 +71,8 @@ struct MountPoint {
     origin_entry: DirCacheItem,
 }
 
+/// Mount table of a mount namespace. Each task refers to the mount table of its mount namespace
+/// (see [`crate::userland::task::Task::mount_ns`]).
 #[repr(transparent)]
 pub struct MountManager(Mutex<BTreeMap<MountKey, MountPoint>>);
 
@@ -103,6 +113,23 @@ impl MountManager {
         Ok(())
     }
 
+    /// Unmounts the filesystem mounted at `directory`.
+    pub fn unmount(&self, directory: &DirCacheItem) -> Result<()> {
+        // NOTE: The root entry of a mounted filesystem has the same cache key as the directory
+        // that it is mounted on, so `directory` may refer to either of them.
+        self.0
+            .lock()
+            .remove(&directory.cache_key())
+            .map(|_| ())
+            .ok_or(FileSystemError::InvalidArgument)
+    }
+
+    /// Creates a new mount namespace with a copy of the mounts of this namespace. Mounts made in
+    /// either of the namespaces afterwards are not visible in the other one.
+    pub fn duplicate(&self) -> Arc<MountManager> {
+        Arc::new(Self(Mutex::new(self.0.lock().clone())))
+    }
+
     fn find_mount(&self, dir: &DirCacheItem) -> Result<MountPoint> {
         let this = self.0.lock();
         let cache_key = dir.cache_key();
@@ -119,6 +146,14 @@ pub trait FileSystem: Send + Sync {
     fn root_dir(&self) -> DirCacheItem {
         todo!()
     }
+
+    /// Returns whether lookups of nonexistent names can be cached (see
+    /// [`cache::NegativeDirCache`]). This is only the case if the directories of the filesystem
+    /// can only be modified through the VFS, as otherwise the cached entries would not be
+    /// invalidated when the names are created.
+    fn caches_negative_entries(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -138,6 +173,23 @@ pub enum FileSystemError {
     NotConnected,
     WouldBlock,
     NoTty,
+    BadFileDescriptor,
+    AddressInUse,
+    InProgress,
+    AlreadyInProgress,
+    AlreadyConnected,
+    OutOfMemory,
+    PermissionDenied,
+    InvalidArgument,
+    NoDevice,
+    Io,
+    NoSpace,
+    BrokenPipe,
+    NetworkUnreachable,
+    HostUnreachable,
+    MessageTooLong,
+    ProtocolNotAvailable,
+    ProtocolError,
 }
 
 impl From<FileSystemError> for SyscallError {
@@ -158,6 +210,23 @@ impl From<FileSystemError> for SyscallError {
             FileSystemError::NotConnected => Self::ENOTCONN,
             FileSystemError::WouldBlock => Self::EAGAIN,
             FileSystemError::NoTty => Self::ENOTTY,
+            FileSystemError::BadFileDescriptor => Self::EBADF,
+            FileSystemError::AddressInUse => Self::EADDRINUSE,
+            FileSystemError::InProgress => Self::EINPROGRESS,
+            FileSystemError::AlreadyInProgress => Self::EALREADY,
+            FileSystemError::AlreadyConnected => Self::EISCONN,
+            FileSystemError::OutOfMemory => Self::ENOMEM,
+            FileSystemError::PermissionDenied => Self::EPERM,
+            FileSystemError::InvalidArgument => Self::EINVAL,
+            FileSystemError::NoDevice => Self::ENXIO,
+            FileSystemError::Io => Self::EIO,
+            FileSystemError::NoSpace => Self::ENOSPC,
+            FileSystemError::BrokenPipe => Self::EPIPE,
+            FileSystemError::NetworkUnreachable => Self::ENETUNREACH,
+            FileSystemError::HostUnreachable => Self::EHOSTUNREACH,
+            FileSystemError::MessageTooLong => Self::EMSGSIZE,
+            FileSystemError::ProtocolNotAvailable => Self::ENOPROTOOPT,
+            FileSystemError::ProtocolError => Self::EPROTO,
         }
     }
 }
@@ -203,7 +272,13 @@ pub fn lookup_path_with(
                 if let Some(entry) = cache_entry {
                     cwd = entry;
                 } else {
-                    match cwd.inode().lookup(cwd.clone(), component) {
+                    let entry = if inode::is_negative_dir_entry(&cwd, component) {
+                        Err(FileSystemError::EntryNotFound)
+                    } else {
+                        cwd.inode().lookup(cwd.clone(), component)
+                    };
+
+                    match entry {
                         Ok(entry) => cwd = entry,
 
                         Err(err)
@@ -215,6 +290,8 @@ pub fn lookup_path_with(
                             } else {
                                 // todo: fix this shit
                                 cwd.inode().mkdir(component)?;
+                                inode::invalidate_negative_dir_entry(&cwd, component);
+
                                 cwd = match lookup_path_with(
                                     cwd.clone(),
                                     Path::new(component),
@@ -230,7 +307,13 @@ pub fn lookup_path_with(
                             }
                         }
 
-                        Err(err) => return Err(err),
+                        Err(err) => {
+                            if err == FileSystemError::EntryNotFound {
+                                inode::make_negative_dir_entry(&cwd, component);
+                            }
+
+                            return Err(err);
+                        }
                     }
                 }
 
@@ -251,7 +334,7 @@ pub fn lookup_path_with(
                         resolve_last,
                     )?;
                 } else if metadata.is_directory() {
-                    if let Ok(mount_point) = MOUNT_MANAGER.find_mount(&cwd) {
+                    if let Ok(mount_point) = mount_namespace().find_mount(&cwd) {
                         cwd = mount_point.root_entry;
                     }
                 }
@@ -262,6 +345,16 @@ pub fn lookup_path_with(
     Ok(cwd)
 }
 
+/// Returns the mount table of the current task or the initial one if there is no current task
+/// yet.
+fn mount_namespace() -> Arc<MountManager> {
+    scheduler::is_initialized()
+        .then(|| scheduler::get_scheduler().inner.current_task_optional())
+        .flatten()
+        .map(|task| task.mount_ns())
+        .unwrap_or_else(|| MOUNT_MANAGER.clone())
+}
+
 pub fn lookup_path(path: &Path) -> Result<DirCacheItem> {
     let cwd = if !path.is_absolute() {
         scheduler::current_thread().cwd_dirent()
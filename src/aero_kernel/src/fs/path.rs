@@ -199,3 +199,82 @@ impl Display for PathBuf {
         self.0.fmt(f)
     }
 }
+
+mod selftests {
+    use super::*;
+
+    use crate::fs::ext2::selftests::mount_image;
+    use crate::fs::{lookup_path_with, FileSystem, FileSystemError, LookupMode};
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    fn components() -> selftest::Result {
+        let path = Path::new("/usr/./lib//modules/");
+
+        selftest_assert!(path.is_absolute());
+        selftest_assert!(path.components().eq(["usr", "lib", "modules"]));
+        selftest_assert!(Path::new("a/../b").components().eq(["a", "..", "b"]));
+
+        Ok(())
+    }
+
+    fn parent_and_basename() -> selftest::Result {
+        for (path, parent, basename) in [
+            ("/usr/lib", "/usr", "lib"),
+            ("/usr", "/", "usr"),
+            ("usr", "", "usr"),
+        ] {
+            let (p, b) = Path::new(path).parent_and_basename();
+            selftest_assert_eq!((p.as_str(), b), (parent, basename));
+        }
+
+        Ok(())
+    }
+
+    fn join() -> selftest::Result {
+        selftest_assert_eq!(Path::new("/usr").join("lib").as_str(), "/usr/lib");
+        selftest_assert_eq!(Path::new("/usr/").join("lib").as_str(), "/usr/lib");
+        selftest_assert_eq!(Path::new("/usr").join("/lib").as_str(), "/lib");
+
+        Ok(())
+    }
+
+    fn lookup() -> selftest::Result {
+        let fs = mount_image().unwrap();
+        let root = fs.root_dir();
+
+        let lookup = |path: &str, resolve_last| {
+            lookup_path_with(
+                root.clone(),
+                Path::new(path),
+                LookupMode::None,
+                resolve_last,
+            )
+        };
+
+        let file = lookup("dir/hello", true).unwrap();
+        let id = file.inode().metadata().unwrap().id();
+
+        // The parent of the root directory is the root directory itself.
+        for path in ["dir/./hello", "dir/../dir/hello", "../dir//hello", "link"] {
+            let entry = lookup(path, true);
+
+            selftest_assert!(
+                entry.is_ok_and(|entry| entry.inode().metadata().unwrap().id() == id),
+                "`{path}` does not resolve to `dir/hello`"
+            );
+        }
+
+        let link = lookup("link", false).unwrap();
+        selftest_assert!(link.inode().metadata().unwrap().is_symlink());
+
+        // The second lookup is answered from the negative dentry cache.
+        for _ in 0..2 {
+            let missing = lookup("dir/missing", true);
+            selftest_assert!(matches!(missing, Err(FileSystemError::EntryNotFound)));
+        }
+
+        Ok(())
+    }
+
+    crate::selftest!("path", components, parent_and_basename, join, lookup);
+}
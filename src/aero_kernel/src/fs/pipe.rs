@@ -17,24 +17,234 @@
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use aero_syscall::OpenFlags;
+use aero_syscall::signal::SIGPIPE;
+use aero_syscall::{OpenFlags, SyscallError, PIPE_BUF};
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use spin::Once;
 
-use crate::utils::buffer::Buffer;
+use crate::mem::paging::{PageSize, Size4KiB};
+use crate::userland::scheduler;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::block::PageCacheItem;
 use super::cache::DirCacheItem;
 use super::file_table::FileHandle;
 use super::inode::{INodeInterface, PollFlags, PollTable};
 use super::FileSystemError;
 
+/// Default capacity (in bytes) of a pipe.
+const PIPE_DEF_SIZE: usize = 16 * Size4KiB::SIZE as usize;
+
+/// Maximum capacity (in bytes) that a pipe can be resized to with `F_SETPIPE_SZ`.
+const PIPE_MAX_SIZE: usize = 1024 * 1024;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+#[derive(Clone)]
+enum PageData {
+    Owned(Arc<[u8]>),
+    /// A page of the page cache that has been spliced into the pipe.
+    Cached(PageCacheItem),
+}
+
+/// Part of a page of data in a pipe.
+///
+/// Pages are shared rather than copied when they are spliced from one pipe into another, and
+/// the pages of the page cache are spliced into a pipe as they are, so moving file data through
+/// pipes with `splice(2)` does not copy it.
+#[derive(Clone)]
+pub struct PipePage {
+    data: PageData,
+    start: usize,
+    end: usize,
+}
+
+impl PipePage {
+    /// Allocates a new page that can hold up to `len` bytes, which are all initialized to zero.
+    pub fn new(len: usize) -> Self {
+        let len = core::cmp::min(len, PAGE_SIZE);
+
+        Self {
+            data: PageData::Owned(Arc::from(alloc::vec![0; PAGE_SIZE])),
+            start: 0,
+            end: len,
+        }
+    }
+
+    /// Creates a page referring to the bytes in `start..end` of the provided cached page.
+    pub fn from_cache(page: PageCacheItem, start: usize, end: usize) -> Self {
+        assert!(start <= end && end <= PAGE_SIZE);
+
+        Self {
+            data: PageData::Cached(page),
+            start,
+            end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.data {
+            PageData::Owned(data) => &data[self.start..self.end],
+
+            PageData::Cached(page) => {
+                let data = page.data_addr().as_hhdm_virt().as_ptr::<u8>();
+
+                // SAFETY: The cached page is kept alive by the reference we hold to it and it
+                // is mapped in the higher half.
+                unsafe { &core::slice::from_raw_parts(data, PAGE_SIZE)[self.start..self.end] }
+            }
+        }
+    }
+
+    /// Returns the data of the page to fill it in, if it is not shared with anyone else.
+    pub fn as_slice_mut(&mut self) -> Option<&mut [u8]> {
+        match &mut self.data {
+            PageData::Owned(data) => Some(&mut Arc::get_mut(data)?[self.start..self.end]),
+            PageData::Cached(_) => None,
+        }
+    }
+
+    /// Drops the data of the page past its first `len` bytes.
+    pub fn truncate(&mut self, len: usize) {
+        self.end = core::cmp::min(self.end, self.start + len);
+    }
+
+    /// Drops the first `len` bytes of the page.
+    fn consume(&mut self, len: usize) {
+        self.start = core::cmp::min(self.end, self.start + len);
+    }
+
+    /// Splits off the first `len` bytes of the page, which share the data of the page.
+    fn split_front(&mut self, len: usize) -> Self {
+        let mut front = self.clone();
+
+        front.truncate(len);
+        self.consume(len);
+        front
+    }
+
+    /// Appends as much of `data` as fits to the page if it owns its data, and returns the
+    /// number of bytes appended.
+    fn append(&mut self, data: &[u8]) -> usize {
+        let PageData::Owned(page) = &mut self.data else {
+            return 0;
+        };
+
+        let Some(page) = Arc::get_mut(page) else {
+            return 0;
+        };
+
+        let count = core::cmp::min(data.len(), PAGE_SIZE - self.end);
+        page[self.end..self.end + count].copy_from_slice(&data[..count]);
+
+        self.end += count;
+        count
+    }
+}
+
+/// Queue of pages holding the data written to a pipe that has not been read yet.
+struct PipeBuffer {
+    pages: VecDeque<PipePage>,
+    /// Number of bytes of data queued.
+    len: usize,
+    capacity: usize,
+}
+
+impl PipeBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            pages: VecDeque::new(),
+            len: 0,
+            capacity,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes that can be written without blocking.
+    fn space(&self) -> usize {
+        self.capacity.saturating_sub(self.len)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut count = 0;
+
+        while count < buffer.len() {
+            let Some(page) = self.pop(buffer.len() - count) else {
+                break;
+            };
+
+            buffer[count..count + page.len()].copy_from_slice(page.as_slice());
+            count += page.len();
+        }
+
+        count
+    }
+
+    fn write(&mut self, mut data: &[u8]) {
+        self.len += data.len();
+
+        while !data.is_empty() {
+            let mut count = self.pages.back_mut().map_or(0, |page| page.append(data));
+
+            if count == 0 {
+                let mut page = PipePage::new(0);
+
+                count = page.append(data);
+                self.pages.push_back(page);
+            }
+
+            data = &data[count..];
+        }
+    }
+
+    /// Queues the provided page as it is.
+    fn push(&mut self, page: PipePage) {
+        self.len += page.len();
+        self.pages.push_back(page);
+    }
+
+    /// Puts back a page taken with [`PipeBuffer::pop`] at the front of the queue.
+    fn unpop(&mut self, page: PipePage) {
+        self.len += page.len();
+        self.pages.push_front(page);
+    }
+
+    /// Takes up to `len` bytes of data at the front of the queue, which come from a single
+    /// page. Returns [`None`] if the queue is empty.
+    fn pop(&mut self, len: usize) -> Option<PipePage> {
+        let front = self.pages.front_mut()?;
+
+        let page = if front.len() <= len {
+            self.pages.pop_front().unwrap()
+        } else {
+            front.split_front(len)
+        };
+
+        self.len -= page.len();
+        Some(page)
+    }
+}
+
 pub struct Pipe {
-    queue: Mutex<Buffer>,
+    buffer: Mutex<PipeBuffer>,
 
     readers: WaitQueue,
     writers: WaitQueue,
 
+    /// The number of readers currently connected to the pipe.
+    num_readers: AtomicUsize,
     /// The number of writers currently connected to the pipe.
     num_writers: AtomicUsize,
 
@@ -44,21 +254,151 @@ pub struct Pipe {
 impl Pipe {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            queue: Mutex::new(Buffer::new()),
+            buffer: Mutex::new(PipeBuffer::new(PIPE_DEF_SIZE)),
 
             readers: WaitQueue::new(),
             writers: WaitQueue::new(),
 
+            num_readers: AtomicUsize::new(0),
             num_writers: AtomicUsize::new(0),
 
             handle: Once::new(),
         })
     }
 
+    /// Returns the number of active readers of the pipe.
+    pub fn active_readers(&self) -> usize {
+        self.num_readers.load(Ordering::SeqCst)
+    }
+
     /// Returns the number of active writers to the pipe.
     pub fn active_writers(&self) -> usize {
         self.num_writers.load(Ordering::SeqCst)
     }
+
+    fn is_nonblock(&self) -> bool {
+        let handle = self.handle.get().expect("pipe: internal error");
+        handle.flags().contains(OpenFlags::O_NONBLOCK)
+    }
+
+    /// Splices up to `len` bytes out of the pipe into `sink`, one page at a time. `sink` returns
+    /// the number of bytes of the page that it consumed; the rest of the page is left in the
+    /// pipe. Returns the number of bytes spliced, which is zero once the pipe is empty and all
+    /// of the writers are gone.
+    pub fn splice_out<F>(&self, len: usize, nonblock: bool, mut sink: F) -> super::Result<usize>
+    where
+        F: FnMut(&PipePage) -> super::Result<usize>,
+    {
+        if nonblock && self.buffer.lock_irq().is_empty() && self.active_writers() != 0 {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        self.readers.block_on(&self.buffer, |lock| {
+            !lock.is_empty() || self.active_writers() == 0
+        })?;
+
+        let mut spliced = 0;
+
+        while spliced < len {
+            let Some(mut page) = self.buffer.lock_irq().pop(len - spliced) else {
+                break;
+            };
+
+            // The lock is not held while the page is passed on, as `sink` might block.
+            let count = match sink(&page) {
+                Ok(count) => count,
+
+                Err(err) => {
+                    self.buffer.lock_irq().unpop(page);
+
+                    if spliced == 0 {
+                        return Err(err);
+                    }
+
+                    break;
+                }
+            };
+
+            spliced += count;
+
+            if count < page.len() {
+                page.consume(count);
+                self.buffer.lock_irq().unpop(page);
+                break;
+            }
+        }
+
+        if spliced > 0 {
+            self.writers.notify_all();
+        }
+
+        Ok(spliced)
+    }
+
+    /// Splices up to `len` bytes into the pipe from `source`, one page at a time, blocking
+    /// while the pipe is full unless `nonblock` is set. `source` is called with the maximum
+    /// number of bytes that the page it returns can hold, and returns [`None`] once it has
+    /// no more data. Returns the number of bytes spliced.
+    pub fn splice_in<F>(&self, len: usize, nonblock: bool, mut source: F) -> super::Result<usize>
+    where
+        F: FnMut(usize) -> super::Result<Option<PipePage>>,
+    {
+        let mut spliced = 0;
+
+        while spliced < len {
+            let buffer = if nonblock {
+                Ok(self.buffer.lock_irq())
+            } else {
+                self.writers.block_on(&self.buffer, |lock| {
+                    lock.space() > 0 || self.active_readers() == 0
+                })
+            };
+
+            let buffer = match buffer {
+                Ok(buffer) => buffer,
+                // Interrupted by a signal after some of the data was spliced.
+                Err(_) if spliced != 0 => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            if self.active_readers() == 0 {
+                scheduler::current_thread().signal(SIGPIPE);
+
+                if spliced == 0 {
+                    return Err(FileSystemError::BrokenPipe);
+                }
+
+                break;
+            }
+
+            // The pipe is full and non-blocking.
+            if buffer.space() == 0 {
+                if spliced == 0 {
+                    return Err(FileSystemError::WouldBlock);
+                }
+
+                break;
+            }
+
+            let max = core::cmp::min(buffer.space(), len - spliced);
+
+            // The lock is not held while the page is produced, as `source` might block.
+            core::mem::drop(buffer);
+
+            let page = match source(max) {
+                Ok(Some(page)) if !page.is_empty() => page,
+                Ok(_) => break,
+                Err(err) if spliced == 0 => return Err(err),
+                Err(_) => break,
+            };
+
+            spliced += page.len();
+            self.buffer.lock_irq().push(page);
+            self.readers.notify_all();
+        }
+
+        Ok(spliced)
+    }
 }
 
 impl INodeInterface for Pipe {
@@ -67,6 +407,8 @@ impl INodeInterface for Pipe {
         if handle.flags().contains(OpenFlags::O_WRONLY) {
             self.num_writers.fetch_add(1, Ordering::SeqCst);
             self.handle.call_once(|| handle);
+        } else {
+            self.num_readers.fetch_add(1, Ordering::SeqCst);
         }
 
         Ok(None)
@@ -81,22 +423,27 @@ impl INodeInterface for Pipe {
             if active_writers == 0 {
                 self.readers.notify_all();
             }
+        } else {
+            let active_readers = self.num_readers.fetch_sub(1, Ordering::SeqCst) - 1;
+
+            // There are no active readers, so the blocked writers fail with `EPIPE`.
+            if active_readers == 0 {
+                self.writers.notify_all();
+            }
         }
     }
 
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> super::Result<usize> {
-        let flags = self.handle.get().expect("pipe: internal error").flags();
-
-        let nonblock = flags.contains(OpenFlags::O_NONBLOCK);
-        if nonblock && !self.queue.lock_irq().has_data() {
+        if self.is_nonblock() && self.buffer.lock_irq().is_empty() {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.readers.block_on(&self.queue, |lock| {
-            lock.has_data() || self.active_writers() == 0
+        let mut buffer = self.readers.block_on(&self.buffer, |lock| {
+            !lock.is_empty() || self.active_writers() == 0
         })?;
 
-        let read = buffer.read_data(buf);
+        let read = buffer.read(buf);
+        core::mem::drop(buffer);
 
         if read > 0 {
             // TODO: Notify only the first process
@@ -106,11 +453,61 @@ impl INodeInterface for Pipe {
         Ok(read)
     }
 
+    /// Writes the provided buffer to the pipe, blocking until all of it has been written unless
+    /// the pipe is non-blocking.
+    ///
+    /// Writes of up to [`PIPE_BUF`] bytes are atomic: they wait until all of the data fits in
+    /// the pipe, so they are never interleaved with the data of other writers.
     fn write_at(&self, _offset: usize, buf: &[u8]) -> super::Result<usize> {
-        let res = self.queue.lock_irq().write_data(buf);
-        self.readers.notify_all();
+        let nonblock = self.is_nonblock();
+        let needed = if buf.len() <= PIPE_BUF { buf.len() } else { 1 };
+
+        let mut written = 0;
+
+        while written < buf.len() {
+            let buffer = if nonblock {
+                Ok(self.buffer.lock_irq())
+            } else {
+                self.writers.block_on(&self.buffer, |lock| {
+                    lock.space() >= needed || self.active_readers() == 0
+                })
+            };
+
+            let mut buffer = match buffer {
+                Ok(buffer) => buffer,
+                // Interrupted by a signal after some of the data was written.
+                Err(_) if written != 0 => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            if self.active_readers() == 0 {
+                scheduler::current_thread().signal(SIGPIPE);
+
+                if written == 0 {
+                    return Err(FileSystemError::BrokenPipe);
+                }
+
+                break;
+            }
+
+            // The pipe is full and non-blocking.
+            if buffer.space() < needed {
+                if written == 0 {
+                    return Err(FileSystemError::WouldBlock);
+                }
+
+                break;
+            }
+
+            let count = core::cmp::min(buffer.space(), buf.len() - written);
+            buffer.write(&buf[written..written + count]);
+            written += count;
 
-        Ok(res)
+            core::mem::drop(buffer);
+            self.readers.notify_all();
+        }
+
+        Ok(written)
     }
 
     fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
@@ -119,12 +516,54 @@ impl INodeInterface for Pipe {
             table.insert(&self.writers);
         }
 
-        let mut flags = PollFlags::OUT;
+        let buffer = self.buffer.lock_irq();
+        let mut flags = PollFlags::empty();
 
-        if self.queue.lock_irq().has_data() {
+        if !buffer.is_empty() {
             flags |= PollFlags::IN;
         }
 
+        if buffer.space() >= PIPE_BUF {
+            flags |= PollFlags::OUT;
+        }
+
+        // Both ends of the pipe share the inode, but each condition can only be observed from
+        // one of them: the read end is hung up once all of the writers are gone, and writing is
+        // an error once all of the readers are gone.
+        if self.active_writers() == 0 {
+            flags |= PollFlags::HUP;
+        }
+
+        if self.active_readers() == 0 {
+            flags |= PollFlags::ERR;
+        }
+
         Ok(flags)
     }
+
+    fn pipe_size(&self) -> Result<usize, SyscallError> {
+        Ok(self.buffer.lock_irq().capacity)
+    }
+
+    fn set_pipe_size(&self, size: usize) -> Result<usize, SyscallError> {
+        if size > PIPE_MAX_SIZE {
+            return Err(SyscallError::EPERM);
+        }
+
+        // The capacity is rounded up to a power of two number of pages.
+        let size = core::cmp::max(size, Size4KiB::SIZE as usize).next_power_of_two();
+        let mut buffer = self.buffer.lock_irq();
+
+        // The data in the pipe must fit in the new capacity.
+        if buffer.len > size {
+            return Err(SyscallError::EBUSY);
+        }
+
+        buffer.capacity = size;
+        core::mem::drop(buffer);
+
+        // Wake up the writers that might fit now.
+        self.writers.notify_all();
+        Ok(size)
+    }
 }
@@ -27,11 +27,17 @@ use spin::{Once, RwLock};
 use crate::fs;
 use crate::fs::inode::FileType;
 
-use crate::arch::tls;
-use crate::userland::scheduler;
+#[cfg(target_arch = "x86_64")]
+use crate::arch::time;
+use crate::power::SuspendError;
+use crate::userland::scheduler::hotplug::HotplugError;
+use crate::userland::scheduler::stats::{self, CpuTimes};
+use crate::userland::task::namespaces::Namespace;
+use crate::userland::task::{Task, TaskId};
+use crate::userland::{scheduler, terminal, uts};
 
 use super::cache::*;
-use super::{cache, FileSystem, Path, MOUNT_MANAGER};
+use super::{block, cache, FileSystem, Path, MOUNT_MANAGER};
 
 use super::inode::{DirEntry, INodeInterface, Metadata};
 use super::FileSystemError;
@@ -58,6 +64,91 @@ fn get_cmdline_cached() -> &'static str {
     })
 }
 
+/// Converts the provided CPU time (in nanoseconds) to clock ticks (100 per second, see
+/// `sysconf(_SC_CLK_TCK)`).
+fn clock_ticks(ns: u64) -> u64 {
+    ns / 10000000
+}
+
+fn get_stat() -> String {
+    use core::fmt::Write;
+
+    let mut result = String::new();
+
+    let mut write_cpu = |name: &str, times: CpuTimes| {
+        // Only the user, nice, system and idle times are accounted.
+        let _ = writeln!(
+            result,
+            "{name} {} {} {} {} 0 0 0 0 0 0",
+            clock_ticks(times.user),
+            clock_ticks(times.nice),
+            clock_ticks(times.system),
+            clock_ticks(times.idle)
+        );
+    };
+
+    write_cpu("cpu ", stats::total_cpu_times());
+
+    for (cpu, times) in stats::cpu_times().enumerate() {
+        write_cpu(&alloc::format!("cpu{cpu}"), times);
+    }
+
+    let irqs = (0..=u8::MAX)
+        .map(|vector| stats::irq_counts(vector).sum::<u64>())
+        .collect::<vec::Vec<_>>();
+
+    let _ = write!(result, "intr {}", irqs.iter().sum::<u64>());
+
+    for count in irqs {
+        let _ = write!(result, " {count}");
+    }
+
+    let uptime = crate::arch::time::get_uptime_ns() / 1000000000;
+    let boot_time = crate::arch::time::get_realtime_clock().tv_sec - uptime as isize;
+    let (nr_running, _) = stats::nr_tasks();
+
+    let _ = writeln!(result);
+    let _ = writeln!(result, "ctxt {}", stats::context_switches());
+    let _ = writeln!(result, "btime {boot_time}");
+    let _ = writeln!(result, "processes {}", stats::tasks_created());
+    let _ = writeln!(result, "procs_running {nr_running}");
+
+    // NOTE: The tasks waiting for I/O cannot be told apart from the sleeping tasks, so none of
+    // them are reported as blocked.
+    let _ = writeln!(result, "procs_blocked 0");
+
+    result
+}
+
+fn get_interrupts() -> String {
+    use core::fmt::Write;
+
+    let mut result = String::from("    ");
+
+    for cpu in 0..stats::cpu_times().count() {
+        let _ = write!(result, " {:>10}", alloc::format!("CPU{cpu}"));
+    }
+
+    result.push('\n');
+
+    // Only the external interrupts are counted; exceptions are not.
+    for vector in 32..=u8::MAX {
+        if stats::irq_counts(vector).all(|count| count == 0) {
+            continue;
+        }
+
+        let _ = write!(result, "{vector:>3}:");
+
+        for count in stats::irq_counts(vector) {
+            let _ = write!(result, " {count:>10}");
+        }
+
+        result.push('\n');
+    }
+
+    result
+}
+
 fn get_cpuinfo_cached() -> &'static str {
     static CACHED: Once<String> = Once::new();
 
@@ -73,24 +164,77 @@ fn get_cpuinfo_cached() -> &'static str {
             let mut cpu_info = vec![];
 
             #[cfg(target_arch = "x86_64")]
-            tls::for_cpu_info_cached(|info| {
-                let mut processor = json!({});
-
-                processor["id"] = Value::Number(Number::from(info.cpuid));
-                processor["fpu"] = Value::Bool(info.fpu);
+            {
+                use crate::arch::cpuinfo;
+
+                // Frequency of the TSC in MHz, used if CPUID does not report the base
+                // frequency.
+                let tsc_frequency = time::tsc_clock().map(|(_, khz)| khz / 1000);
+                let mut infos = vec![];
+
+                cpuinfo::for_each_cpu(|info| {
+                    infos.push((info.package_id, info.core_id));
+
+                    let mut processor = json!({});
+
+                    processor["id"] = Value::Number(Number::from(info.cpu));
+                    processor["apic_id"] = Value::Number(Number::from(info.apic_id));
+                    processor["package_id"] = Value::Number(Number::from(info.package_id));
+                    processor["core_id"] = Value::Number(Number::from(info.core_id));
+                    processor["thread_id"] = Value::Number(Number::from(info.thread_id));
+                    processor["fpu"] = Value::Bool(info.features.contains(&"fpu"));
+
+                    push_string_if_some(&mut processor, "brand", info.brand.clone());
+                    push_string_if_some(&mut processor, "vendor", info.vendor.clone());
+
+                    processor["family"] = Value::Number(Number::from(info.family));
+                    processor["model"] = Value::Number(Number::from(info.model));
+                    processor["stepping"] = Value::Number(Number::from(info.stepping));
+
+                    if let Some(mhz) = info.base_frequency.map(u64::from).or(tsc_frequency) {
+                        processor["frequency_mhz"] = Value::Number(Number::from(mhz));
+                    }
+
+                    processor["caches"] = Value::Array(
+                        info.caches
+                            .iter()
+                            .map(|cache| {
+                                json!({
+                                    "level": cache.level,
+                                    "type": cache.kind.name(),
+                                    "size": cache.size,
+                                    "line_size": cache.line_size,
+                                    "ways": cache.ways,
+                                })
+                            })
+                            .collect(),
+                    );
+
+                    processor["features"] = Value::Array(
+                        info.features
+                            .iter()
+                            .map(|feature| Value::String(feature.to_string()))
+                            .collect(),
+                    );
+
+                    cpu_info.push(processor);
+                });
 
-                push_string_if_some(&mut processor, "brand", info.brand.clone());
-                push_string_if_some(&mut processor, "vendor", info.vendor.clone());
+                // Number of logical CPUs (siblings) and cores in the package of each CPU.
+                for (processor, (package, _)) in cpu_info.iter_mut().zip(infos.iter()) {
+                    let in_package = infos.iter().filter(|(p, _)| p == package);
 
-                processor["features"] = Value::Array(
-                    info.features
-                        .iter()
-                        .map(|feature| Value::String(feature.to_string()))
-                        .collect(),
-                );
+                    let mut cores = in_package
+                        .clone()
+                        .map(|(_, core)| *core)
+                        .collect::<vec::Vec<_>>();
+                    cores.sort_unstable();
+                    cores.dedup();
 
-                cpu_info.push(processor);
-            });
+                    processor["siblings"] = Value::Number(Number::from(in_package.count()));
+                    processor["cores"] = Value::Number(Number::from(cores.len()));
+                }
+            }
 
             *processors = cpu_info;
         }
@@ -114,7 +258,55 @@ enum FileContents {
     CpuInfo,
     CmdLine,
     SelfMaps,
-
+    /// Memory usage of the process with the provided task ID or of the current process if
+    /// `None`.
+    Statm(Option<usize>),
+    /// A namespace of a process (see `setns`).
+    Namespace(Namespace),
+    /// The clock source of the monotonic clock. Writing the name of an available clock source
+    /// switches to it.
+    #[cfg(target_arch = "x86_64")]
+    CurrentClocksource,
+    #[cfg(target_arch = "x86_64")]
+    AvailableClocksource,
+    /// The state of the batteries and AC adapters (see [`crate::acpi::power_supply`]).
+    PowerSupply,
+    /// Number of bits of entropy in the input pool of the random number generator.
+    EntropyAvail,
+    /// The loaded kernel modules (see [`crate::modules::loader`]).
+    Modules,
+    /// The physical memory map, including the memory reserved for the crash kernel.
+    IoMem,
+    /// The sleep states the machine supports. Writing `mem` suspends the machine to RAM (see
+    /// [`crate::power`]).
+    PowerState,
+    /// Whether the CPU with the provided logical ID is online. Writing `0` or `1` takes it
+    /// offline or brings it back online (see [`scheduler::hotplug`]).
+    CpuOnline(usize),
+    /// Tendency of the kernel to reclaim the memory used by the inode and directory caches
+    /// (see [`cache::set_cache_pressure`]).
+    VfsCachePressure,
+    /// Writing `1` drops the clean pages from the page cache, `2` drops the unused entries of
+    /// the inode and directory caches and `3` drops both.
+    DropCaches,
+    /// The host name (see [`uts`]).
+    Hostname,
+    /// The NIS domain name (see [`uts`]).
+    Domainname,
+    /// Whether input can be injected into terminals with `TIOCSTI` (see
+    /// [`terminal::check_tiocsti`]).
+    LegacyTiocsti,
+    /// Kernel and CPU statistics (see [`stats`]).
+    Stat,
+    /// Number of times that each interrupt was delivered to each CPU.
+    Interrupts,
+    /// The load average and the number of runnable tasks.
+    LoadAvg,
+    /// The I/O statistics of the block devices (see [`block::diskstats`]).
+    DiskStats,
+
+    /// The root directory; also contains a directory for each process.
+    Root,
     None,
 }
 
@@ -208,23 +400,248 @@ impl INodeInterface for LockedProcINode {
                 Ok(result.to_string())
             }
 
+            FileContents::Statm(id) => {
+                let task = match id {
+                    Some(id) => scheduler::get_scheduler()
+                        .find_task(TaskId::new(*id))
+                        .ok_or(FileSystemError::EntryNotFound)?,
+                    None => scheduler::current_thread(),
+                };
+
+                let usage = task.vm().memory_usage();
+
+                // size resident shared text lib data dt
+                Ok(alloc::format!(
+                    "{} {} {} {} 0 {} 0\n",
+                    usage.size,
+                    usage.resident,
+                    usage.shared,
+                    usage.text,
+                    usage.data
+                ))
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            FileContents::CurrentClocksource => {
+                Ok(alloc::format!("{}\n", time::current_clocksource().name()))
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            FileContents::AvailableClocksource => {
+                let names = time::available_clocksources()
+                    .map(|source| source.name())
+                    .collect::<alloc::vec::Vec<_>>();
+
+                Ok(alloc::format!("{}\n", names.join(" ")))
+            }
+
+            FileContents::PowerSupply => Ok(crate::acpi::power_supply::report()),
+            FileContents::EntropyAvail => {
+                Ok(alloc::format!("{}\n", crate::random::entropy_count()))
+            }
+
+            FileContents::Modules => Ok(crate::modules::loader::loaded()
+                .iter()
+                .map(|(name, size)| alloc::format!("{name} {size} 0 - Live\n"))
+                .collect()),
+
+            FileContents::IoMem => {
+                let ram = crate::arch::kexec::memory_ranges().map(|range| (range, "System RAM"));
+                let crash = crate::arch::kexec::crash_region().map(|range| (range, "Crash kernel"));
+
+                let mut ranges = ram.chain(crash).collect::<vec::Vec<_>>();
+                ranges.sort_unstable();
+
+                Ok(ranges
+                    .iter()
+                    .filter(|((_, length), _)| *length != 0)
+                    .map(|((base, length), name)| {
+                        alloc::format!("{:08x}-{:08x} : {name}\n", base, base + length - 1)
+                    })
+                    .collect())
+            }
+
+            FileContents::PowerState => Ok(if crate::power::is_supported() {
+                String::from("mem\n")
+            } else {
+                String::from("\n")
+            }),
+
+            FileContents::CpuOnline(cpu) => Ok(alloc::format!(
+                "{}\n",
+                scheduler::hotplug::is_online(*cpu) as u8
+            )),
+
+            FileContents::VfsCachePressure => Ok(alloc::format!("{}\n", cache::cache_pressure())),
+            FileContents::DropCaches => Ok("0\n".to_owned()),
+            FileContents::Hostname => Ok(alloc::format!("{}\n", uts::hostname())),
+            FileContents::Domainname => Ok(alloc::format!("{}\n", uts::domainname())),
+            FileContents::LegacyTiocsti => {
+                Ok(alloc::format!("{}\n", terminal::legacy_tiocsti() as u8))
+            }
+
+            FileContents::Stat => Ok(get_stat()),
+            FileContents::Interrupts => Ok(get_interrupts()),
+
+            FileContents::LoadAvg => {
+                let (nr_running, nr_tasks) = stats::nr_tasks();
+
+                Ok(alloc::format!(
+                    "{} {nr_running}/{nr_tasks} {}\n",
+                    stats::load_average(),
+                    TaskId::last().as_usize()
+                ))
+            }
+
+            FileContents::DiskStats => Ok(block::diskstats()),
+
             _ => Err(FileSystemError::NotSupported),
         }?;
 
+        // The contents may have shrunk since the previous read.
+        let offset = core::cmp::min(offset, data.len());
         let count = core::cmp::min(buffer.len(), data.len() - offset);
         buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
 
         Ok(count)
     }
 
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let this = self.0.read();
+
+        match &this.contents {
+            #[cfg(target_arch = "x86_64")]
+            FileContents::CurrentClocksource => {
+                let name =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                let source = time::Clocksource::from_name(name.trim())
+                    .ok_or(FileSystemError::InvalidArgument)?;
+
+                if !time::set_clocksource(source) {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                Ok(buffer.len())
+            }
+
+            FileContents::PowerState => {
+                let state =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                if state.trim() != "mem" {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                crate::power::suspend_to_ram().map_err(|error| match error {
+                    SuspendError::NotSupported => FileSystemError::InvalidArgument,
+                    SuspendError::Busy => FileSystemError::Busy,
+                })?;
+
+                Ok(buffer.len())
+            }
+
+            FileContents::CpuOnline(cpu) => {
+                let value =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                match value.trim() {
+                    "0" => scheduler::hotplug::offline(*cpu),
+                    "1" => scheduler::hotplug::online(*cpu),
+                    _ => return Err(FileSystemError::InvalidArgument),
+                }
+                .map_err(|error| match error {
+                    HotplugError::InvalidCpu => FileSystemError::InvalidArgument,
+                    HotplugError::NotSupported => FileSystemError::NotSupported,
+                })?;
+
+                Ok(buffer.len())
+            }
+
+            FileContents::VfsCachePressure => {
+                let pressure = core::str::from_utf8(buffer)
+                    .ok()
+                    .and_then(|value| value.trim().parse::<usize>().ok())
+                    .ok_or(FileSystemError::InvalidArgument)?;
+
+                cache::set_cache_pressure(pressure);
+                Ok(buffer.len())
+            }
+
+            FileContents::DropCaches => {
+                let value = core::str::from_utf8(buffer)
+                    .ok()
+                    .and_then(|value| value.trim().parse::<usize>().ok())
+                    .filter(|value| (1..=3).contains(value))
+                    .ok_or(FileSystemError::InvalidArgument)?;
+
+                if value & 1 != 0 {
+                    let freed = block::shrink_page_cache(usize::MAX);
+                    log::info!("drop_caches: dropped {freed} pages from the page cache");
+                }
+
+                if value & 2 != 0 {
+                    let freed = cache::drop_caches();
+                    log::info!("drop_caches: dropped {freed} inode and directory cache entries");
+                }
+
+                Ok(buffer.len())
+            }
+
+            FileContents::LegacyTiocsti => {
+                let value =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                match value.trim() {
+                    "0" => terminal::set_legacy_tiocsti(false),
+                    "1" => terminal::set_legacy_tiocsti(true),
+                    _ => return Err(FileSystemError::InvalidArgument),
+                }
+
+                Ok(buffer.len())
+            }
+
+            FileContents::Hostname | FileContents::Domainname => {
+                // The name ends at the first newline, e.g. `echo aero > /proc/sys/kernel/hostname`.
+                let len = buffer
+                    .iter()
+                    .position(|&c| c == b'\n')
+                    .unwrap_or(buffer.len());
+
+                if matches!(this.contents, FileContents::Hostname) {
+                    uts::set_hostname(&buffer[..len])
+                } else {
+                    uts::set_domainname(&buffer[..len])
+                }
+                .map_err(|_| FileSystemError::InvalidArgument)?;
+
+                Ok(buffer.len())
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
     fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
         let this = self.0.read();
-        let child = this
-            .children
-            .get(name)
-            .ok_or(FileSystemError::EntryNotFound)?;
 
-        Ok(DirEntry::new(dir, child.clone(), String::from(name)))
+        if let Some(child) = this.children.get(name) {
+            return Ok(DirEntry::new(dir, child.clone(), String::from(name)));
+        }
+
+        if !matches!(this.contents, FileContents::Root) {
+            return Err(FileSystemError::EntryNotFound);
+        }
+
+        let pid = name
+            .parse::<usize>()
+            .map_err(|_| FileSystemError::EntryNotFound)?;
+
+        let filesystem = this.filesystem.upgrade().unwrap();
+        core::mem::drop(this);
+
+        let inode = filesystem.process_dir(pid)?;
+        Ok(DirEntry::new(dir, inode, String::from(name)))
     }
 
     fn metadata(&self) -> fs::Result<Metadata> {
@@ -263,11 +680,28 @@ impl INodeInterface for LockedProcINode {
             }
 
             // Subtract two because of the "." and ".." entries.
-            _ => this
+            _ if index - 2 < this.children.len() => this
                 .children
                 .iter()
                 .nth(index - 2)
                 .map(|(name, inode)| DirEntry::new(parent, inode.clone(), name.clone())),
+
+            _ if matches!(this.contents, FileContents::Root) => {
+                // Only the processes in the PID namespace of the reader are listed.
+                let pids = scheduler::current_thread().pid_ns().pids();
+
+                let Some(pid) = pids.get(index - 2 - this.children.len()).copied() else {
+                    return Ok(None);
+                };
+
+                let filesystem = this.filesystem.upgrade().unwrap();
+                core::mem::drop(this);
+
+                let inode = filesystem.process_dir(pid)?;
+                Some(DirEntry::new(parent, inode, pid.to_string()))
+            }
+
+            _ => None,
         })
     }
 
@@ -286,7 +720,10 @@ impl ProcFs {
     pub fn new() -> fs::Result<Arc<Self>> {
         let icache = cache::icache();
 
-        let root_node = Arc::new(LockedProcINode::new(ProcINode::default()));
+        let root_node = Arc::new(LockedProcINode::new(ProcINode {
+            contents: FileContents::Root,
+            ..Default::default()
+        }));
         let root_cached = icache.make_item_no_cache(CachedINode::new(root_node));
 
         let root_dir = DirEntry::new_root(root_cached.clone(), String::from("/"));
@@ -315,15 +752,119 @@ impl ProcFs {
 
         inode.make_inode("cpuinfo", FileType::File, FileContents::CpuInfo)?;
         inode.make_inode("cmdline", FileType::File, FileContents::CmdLine)?;
+        inode.make_inode("power_supply", FileType::File, FileContents::PowerSupply)?;
+        inode.make_inode("modules", FileType::File, FileContents::Modules)?;
+        inode.make_inode("iomem", FileType::File, FileContents::IoMem)?;
+        inode.make_inode("stat", FileType::File, FileContents::Stat)?;
+        inode.make_inode("interrupts", FileType::File, FileContents::Interrupts)?;
+        inode.make_inode("loadavg", FileType::File, FileContents::LoadAvg)?;
+        inode.make_inode("diskstats", FileType::File, FileContents::DiskStats)?;
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            inode.make_inode(
+                "current_clocksource",
+                FileType::File,
+                FileContents::CurrentClocksource,
+            )?;
+            inode.make_inode(
+                "available_clocksource",
+                FileType::File,
+                FileContents::AvailableClocksource,
+            )?;
+        }
+
+        let sys = inode.make_inode("sys", FileType::Directory, FileContents::None)?;
+        let sys = sys.downcast_arc::<LockedProcINode>().unwrap();
+        let kernel = sys.make_inode("kernel", FileType::Directory, FileContents::None)?;
+        let kernel = kernel.downcast_arc::<LockedProcINode>().unwrap();
+        let random = kernel.make_inode("random", FileType::Directory, FileContents::None)?;
+        let random = random.downcast_arc::<LockedProcINode>().unwrap();
+
+        random.make_inode("entropy_avail", FileType::File, FileContents::EntropyAvail)?;
+
+        kernel.make_inode("hostname", FileType::File, FileContents::Hostname)?;
+        kernel.make_inode("domainname", FileType::File, FileContents::Domainname)?;
+
+        let power = sys.make_inode("power", FileType::Directory, FileContents::None)?;
+        let power = power.downcast_arc::<LockedProcINode>().unwrap();
+
+        power.make_inode("state", FileType::File, FileContents::PowerState)?;
+
+        let vm = sys.make_inode("vm", FileType::Directory, FileContents::None)?;
+        let vm = vm.downcast_arc::<LockedProcINode>().unwrap();
+
+        vm.make_inode(
+            "vfs_cache_pressure",
+            FileType::File,
+            FileContents::VfsCachePressure,
+        )?;
+        vm.make_inode("drop_caches", FileType::File, FileContents::DropCaches)?;
+
+        let dev = sys.make_inode("dev", FileType::Directory, FileContents::None)?;
+        let dev = dev.downcast_arc::<LockedProcINode>().unwrap();
+        let tty = dev.make_inode("tty", FileType::Directory, FileContents::None)?;
+        let tty = tty.downcast_arc::<LockedProcINode>().unwrap();
+
+        tty.make_inode(
+            "legacy_tiocsti",
+            FileType::File,
+            FileContents::LegacyTiocsti,
+        )?;
+
+        let cpus = sys.make_inode("cpu", FileType::Directory, FileContents::None)?;
+        let cpus = cpus.downcast_arc::<LockedProcINode>().unwrap();
+
+        for cpu in 0..crate::arch::apic::get_cpu_count() {
+            let dir = cpus.make_inode(
+                &alloc::format!("cpu{cpu}"),
+                FileType::Directory,
+                FileContents::None,
+            )?;
+            let dir = dir.downcast_arc::<LockedProcINode>().unwrap();
+
+            dir.make_inode("online", FileType::File, FileContents::CpuOnline(cpu))?;
+        }
 
         let proc_self = inode.make_inode("self", FileType::Directory, FileContents::None)?;
         let proc_self = proc_self.downcast_arc::<LockedProcINode>().unwrap();
 
         proc_self.make_inode("maps", FileType::File, FileContents::SelfMaps)?;
+        proc_self.make_inode("statm", FileType::File, FileContents::Statm(None))?;
 
         Ok(ramfs)
     }
 
+    /// Creates the `/proc/<pid>` directory for the process with the provided PID (in the PID
+    /// namespace of the current process).
+    fn process_dir(self: Arc<Self>, pid: usize) -> fs::Result<INodeCacheItem> {
+        let task = find_process(pid)?;
+
+        let inode = self.allocate_inode(FileType::Directory, FileContents::None);
+        let inode_cached = cache::icache().make_item_no_cache(CachedINode::new(inode.clone()));
+
+        inode.init(
+            &self.root_inode.downgrade(),
+            &inode_cached.downgrade(),
+            &Arc::downgrade(&self),
+            FileType::Directory,
+        );
+
+        let id = task.pid().as_usize();
+        inode.make_inode("statm", FileType::File, FileContents::Statm(Some(id)))?;
+
+        let ns = inode.make_inode("ns", FileType::Directory, FileContents::None)?;
+        let ns = ns.downcast_arc::<LockedProcINode>().unwrap();
+
+        let pid_ns = Namespace::Pid(task.pid_ns().clone());
+        let mount_ns = Namespace::Mount(task.mount_ns());
+
+        ns.make_inode("pid", FileType::File, FileContents::Namespace(pid_ns))?;
+        ns.make_inode("mnt", FileType::File, FileContents::Namespace(mount_ns))?;
+
+        Ok(inode_cached)
+    }
+
     fn allocate_inode(&self, file_type: FileType, contents: FileContents) -> Arc<LockedProcINode> {
         Arc::new(LockedProcINode::new(ProcINode {
             parent: CacheWeak::new(),
@@ -344,6 +885,31 @@ impl FileSystem for ProcFs {
     }
 }
 
+/// Returns the process leader with the provided PID in the PID namespace of the current process.
+fn find_process(pid: usize) -> fs::Result<Arc<Task>> {
+    scheduler::current_thread()
+        .pid_ns()
+        .find_task(pid)
+        .filter(|task| task.is_process_leader())
+        .ok_or(FileSystemError::EntryNotFound)
+}
+
+/// Returns the namespace that the provided `/proc/<pid>/ns` file refers to.
+pub fn namespace(inode: &INodeCacheItem) -> Option<Namespace> {
+    let inode = inode.clone().downcast_arc::<LockedProcINode>()?;
+    let this = inode.0.read();
+
+    match &this.contents {
+        FileContents::Namespace(ns) => Some(ns.clone()),
+        _ => None,
+    }
+}
+
+/// Creates a new instance of the proc filesystem (see `mount`).
+pub fn create() -> fs::Result<Arc<dyn FileSystem>> {
+    Ok(ProcFs::new()?)
+}
+
 static PROC_FS: Once<Arc<ProcFs>> = Once::new();
 
 pub fn init() -> fs::Result<()> {
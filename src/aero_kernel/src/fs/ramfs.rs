@@ -17,7 +17,7 @@
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use aero_syscall::MMapFlags;
+use aero_syscall::{MMapFlags, SyscallError};
 use alloc::collections::BTreeMap;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
@@ -35,6 +35,7 @@ use super::devfs::DevINode;
 use super::inode::{
     DirEntry, FileContents, FileType, INodeInterface, MMapPage, Metadata, PollFlags, PollTable,
 };
+use super::memfd::MemFd;
 use super::{FileSystem, FileSystemError, Result};
 
 #[derive(Default)]
@@ -125,6 +126,9 @@ impl INodeInterface for LockedRamINode {
                 stat.st_size = contents.len() as _;
             }
 
+            FileContents::Memory(memfd) => return memfd.stat(),
+            FileContents::Device(device) => return device.stat(),
+
             _ => {}
         }
 
@@ -132,13 +136,22 @@ impl INodeInterface for LockedRamINode {
     }
 
     fn touch(&self, parent: DirCacheItem, name: &str) -> Result<DirCacheItem> {
+        let is_tmpfs = self
+            .0
+            .read()
+            .filesystem
+            .upgrade()
+            .is_some_and(|filesystem| filesystem.is_tmpfs);
+
+        let contents = if is_tmpfs {
+            FileContents::Memory(MemFd::new(false))
+        } else {
+            FileContents::Content(Mutex::new(Vec::new()))
+        };
+
         Ok(DirEntry::new(
             parent,
-            self.make_inode(
-                name,
-                FileType::File,
-                FileContents::Content(Mutex::new(Vec::new())),
-            )?,
+            self.make_inode(name, FileType::File, contents)?,
             String::from(name),
         ))
     }
@@ -196,6 +209,7 @@ impl INodeInterface for LockedRamINode {
             }
 
             FileContents::Socket(e) => e.write_at(offset, buffer),
+            FileContents::Memory(memfd) => memfd.write_at(offset, buffer),
             FileContents::None => Err(FileSystemError::NotSupported),
         }
     }
@@ -254,6 +268,8 @@ impl INodeInterface for LockedRamINode {
                 Ok(())
             }
 
+            FileContents::Memory(memfd) => memfd.truncate(size),
+
             _ => {
                 log::warn!("ramfs: truncation is not supported");
                 Ok(())
@@ -298,6 +314,7 @@ impl INodeInterface for LockedRamINode {
             }
 
             FileContents::Socket(e) => e.read_at(offset, buffer),
+            FileContents::Memory(memfd) => memfd.read_at(offset, buffer),
             FileContents::None => Err(FileSystemError::NotSupported),
         }
     }
@@ -327,6 +344,7 @@ impl INodeInterface for LockedRamINode {
                 FileContents::Content(bytes) => bytes.lock().len(), // Temporary value dropped
                 // and lock is unlocked!
                 FileContents::StaticContent(bytes) => bytes.len(),
+                FileContents::Memory(memfd) => memfd.metadata()?.size,
                 _ => 0x00,
             },
             children_len: this.children.len(),
@@ -362,7 +380,9 @@ impl INodeInterface for LockedRamINode {
                 // TODO: Support shared static content ramfs file mappings.
                 assert!(!flags.contains(MMapFlags::MAP_SHARED));
 
-                let private_cp: PhysFrame = FRAME_ALLOCATOR.allocate_frame().unwrap();
+                let private_cp: PhysFrame = FRAME_ALLOCATOR
+                    .allocate_frame()
+                    .ok_or(FileSystemError::OutOfMemory)?;
                 private_cp.as_slice_mut()[..size].copy_from_slice(&contents[offset..offset + size]);
 
                 Ok(private_cp)
@@ -372,13 +392,17 @@ impl INodeInterface for LockedRamINode {
                 // TODO: Support shared content ramfs file mappings.
                 assert!(!flags.contains(MMapFlags::MAP_SHARED));
 
-                let private_cp: PhysFrame = FRAME_ALLOCATOR.allocate_frame().unwrap();
+                let private_cp: PhysFrame = FRAME_ALLOCATOR
+                    .allocate_frame()
+                    .ok_or(FileSystemError::OutOfMemory)?;
                 private_cp.as_slice_mut()[..size]
                     .copy_from_slice(&contents.lock()[offset..offset + size]);
 
                 Ok(private_cp)
             }
 
+            FileContents::Memory(memfd) => memfd.mmap(offset, size, flags),
+
             // TODO: Support other memory mapping ramfs files:
             _ => Err(FileSystemError::NotSupported),
         }
@@ -395,10 +419,26 @@ impl INodeInterface for LockedRamINode {
                 device.mmap_v2(offset)
             }
 
+            FileContents::Memory(memfd) => memfd.mmap_v2(offset),
+
             _ => todo!(),
         }
     }
 
+    fn seals(&self) -> ::core::result::Result<usize, SyscallError> {
+        match &self.0.read().contents {
+            FileContents::Memory(memfd) => memfd.seals(),
+            _ => Err(SyscallError::EINVAL),
+        }
+    }
+
+    fn add_seals(&self, seals: usize) -> ::core::result::Result<(), SyscallError> {
+        match &self.0.read().contents {
+            FileContents::Memory(memfd) => memfd.add_seals(seals),
+            _ => Err(SyscallError::EINVAL),
+        }
+    }
+
     fn lookup(&self, dir: DirCacheItem, name: &str) -> Result<DirCacheItem> {
         let this = self.0.read();
         let child = this
@@ -429,7 +469,7 @@ impl INodeInterface for LockedRamINode {
                 device.poll(table)
             }
 
-            _ => Err(FileSystemError::NotSupported),
+            _ => PollFlags::always_ready(this.file_type),
         }
     }
 
@@ -475,10 +515,23 @@ pub struct RamFs {
     root_inode: INodeCacheItem,
     root_dir: DirCacheItem,
     next_id: AtomicUsize,
+    /// Whether regular files are backed by pages which can be shared between processes
+    /// (tmpfs).
+    is_tmpfs: bool,
 }
 
 impl RamFs {
     pub fn new() -> Arc<Self> {
+        Self::with_options(false)
+    }
+
+    /// Creates a new tmpfs instance. Regular files created on it support shared memory
+    /// mappings (see [`MemFd`]).
+    pub fn new_tmpfs() -> Arc<Self> {
+        Self::with_options(true)
+    }
+
+    fn with_options(is_tmpfs: bool) -> Arc<Self> {
         let icache = cache::icache();
 
         let root_node = Arc::new(LockedRamINode::new(RamINode::default()));
@@ -490,6 +543,7 @@ impl RamFs {
             root_inode: root_cached.clone(),
             root_dir: root_dir.clone(),
             next_id: AtomicUsize::new(0x00),
+            is_tmpfs,
         });
 
         let copy: Arc<dyn FileSystem> = ramfs.clone();
@@ -528,4 +582,10 @@ impl FileSystem for RamFs {
     fn root_dir(&self) -> DirCacheItem {
         self.root_dir.clone()
     }
+
+    /// Device files are installed directly in the inodes of the device filesystem, so only
+    /// `tmpfs` instances can cache nonexistent names.
+    fn caches_negative_entries(&self) -> bool {
+        self.is_tmpfs
+    }
 }
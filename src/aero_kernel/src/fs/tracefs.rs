@@ -0,0 +1,541 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The tracing filesystem, mounted at `/sys/kernel/tracing`. It is the interface to the trace
+//! buffer and the tracepoints (see [`crate::trace`]):
+//!
+//! * `tracing_on`: `1` if events are recorded; writing `0` stops recording.
+//! * `trace`: the contents of the trace buffer. Opening it with `O_TRUNC` clears the buffer.
+//! * `trace_pipe`: consuming read of the trace buffer, which blocks until an event is recorded.
+//! * `available_events`: all of the tracepoints, as `$SYSTEM:$EVENT`.
+//! * `set_event`: the enabled tracepoints. Writing `$SYSTEM:$EVENT` enables a tracepoint and
+//!   `!$SYSTEM:$EVENT` disables it; opening it with `O_TRUNC` disables all of them.
+//! * `events/enable`, `events/$SYSTEM/enable` and `events/$SYSTEM/$EVENT/enable`: enable or disable
+//!   all of the tracepoints, the tracepoints of a subsystem, or a single tracepoint.
+//! * `current_tracer` and `available_tracers`: the tracer, `nop` or `function` (only available with
+//!   the `ftrace` feature).
+//! * `buffer_size_kb`: the size of the trace buffer of each CPU in kilobytes.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use spin::{Once, RwLock};
+
+use crate::fs;
+use crate::fs::inode::FileType;
+use crate::trace::{self, events, Tracepoint};
+use crate::userland::scheduler::hrtimer;
+use crate::utils::sync::Mutex;
+
+use super::cache::*;
+use super::{cache, FileSystem, Path, MOUNT_MANAGER};
+
+use super::inode::{DirEntry, INodeInterface, Metadata};
+use super::FileSystemError;
+
+/// Interval at which `trace_pipe` checks for new events.
+const TRACE_PIPE_INTERVAL_NS: u64 = 10_000_000;
+
+#[derive(Copy, Clone)]
+enum FileContents {
+    Directory,
+    TracingOn,
+    Trace,
+    TracePipe,
+    AvailableEvents,
+    SetEvent,
+    CurrentTracer,
+    AvailableTracers,
+    BufferSizeKb,
+    /// The `enable` file of a tracepoint.
+    Enable(&'static Tracepoint),
+    /// The `enable` file of a subsystem, or of all of the tracepoints if `None`.
+    SystemEnable(Option<&'static str>),
+}
+
+impl FileContents {
+    fn read(&self) -> String {
+        match self {
+            Self::Directory => unreachable!(),
+
+            Self::TracingOn => alloc::format!("{}\n", trace::is_on() as usize),
+
+            Self::Trace => {
+                let (entries, overrun) = trace::stats();
+                let mut data = alloc::format!(
+                    "# tracer: {}\n#\n# entries-in-buffer/entries-written: {}/{}\n#\n",
+                    current_tracer(),
+                    entries,
+                    entries as u64 + overrun
+                );
+
+                data.push_str("#           TASK-PID     CPU#     TIMESTAMP  FUNCTION\n");
+                data.push_str("#              | |         |         |         |\n");
+
+                for entry in trace::snapshot() {
+                    data.push_str(&entry.format());
+                }
+
+                data
+            }
+
+            Self::TracePipe => unreachable!(),
+
+            Self::AvailableEvents => events::TRACEPOINTS
+                .iter()
+                .map(|tracepoint| alloc::format!("{}:{}\n", tracepoint.system(), tracepoint.name()))
+                .collect(),
+
+            Self::SetEvent => events::TRACEPOINTS
+                .iter()
+                .filter(|tracepoint| tracepoint.is_enabled())
+                .map(|tracepoint| alloc::format!("{}:{}\n", tracepoint.system(), tracepoint.name()))
+                .collect(),
+
+            Self::CurrentTracer => alloc::format!("{}\n", current_tracer()),
+
+            Self::AvailableTracers => {
+                if cfg!(all(feature = "ftrace", target_arch = "x86_64")) {
+                    String::from("function nop\n")
+                } else {
+                    String::from("nop\n")
+                }
+            }
+
+            Self::BufferSizeKb => alloc::format!("{}\n", trace::buffer_size_kb()),
+
+            Self::Enable(tracepoint) => alloc::format!("{}\n", tracepoint.is_enabled() as usize),
+
+            Self::SystemEnable(system) => {
+                let mut tracepoints = system_tracepoints(*system);
+
+                match tracepoints.next().map(|tracepoint| tracepoint.is_enabled()) {
+                    Some(enabled) if tracepoints.all(|t| t.is_enabled() == enabled) => {
+                        alloc::format!("{}\n", enabled as usize)
+                    }
+
+                    // Only some of the tracepoints are enabled.
+                    Some(_) => String::from("X\n"),
+                    None => String::from("0\n"),
+                }
+            }
+        }
+    }
+
+    fn write(&self, value: &str) -> fs::Result<()> {
+        match self {
+            Self::TracingOn => trace::set_on(parse_bool(value)?),
+
+            Self::Trace => trace::clear(),
+
+            Self::SetEvent => {
+                for event in value.split_whitespace() {
+                    let (enable, event) = match event.strip_prefix('!') {
+                        Some(event) => (false, event),
+                        None => (true, event),
+                    };
+
+                    let (system, name) = event
+                        .split_once(':')
+                        .ok_or(FileSystemError::InvalidArgument)?;
+
+                    events::find(system, name)
+                        .ok_or(FileSystemError::InvalidArgument)?
+                        .set_enabled(enable);
+                }
+            }
+
+            Self::CurrentTracer => match value {
+                "nop" => {
+                    #[cfg(all(feature = "ftrace", target_arch = "x86_64"))]
+                    trace::function::set_enabled(false);
+                }
+
+                #[cfg(all(feature = "ftrace", target_arch = "x86_64"))]
+                "function" => trace::function::set_enabled(true),
+
+                _ => return Err(FileSystemError::InvalidArgument),
+            },
+
+            Self::BufferSizeKb => {
+                let size = value
+                    .parse::<usize>()
+                    .map_err(|_| FileSystemError::InvalidArgument)?;
+
+                if !trace::set_buffer_size_kb(size) {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+            }
+
+            Self::Enable(tracepoint) => tracepoint.set_enabled(parse_bool(value)?),
+
+            Self::SystemEnable(system) => {
+                let enabled = parse_bool(value)?;
+                system_tracepoints(*system).for_each(|t| t.set_enabled(enabled));
+            }
+
+            Self::Directory => return Err(FileSystemError::IsDir),
+
+            Self::TracePipe | Self::AvailableEvents | Self::AvailableTracers => {
+                return Err(FileSystemError::NotSupported)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn current_tracer() -> &'static str {
+    #[cfg(all(feature = "ftrace", target_arch = "x86_64"))]
+    if trace::function::is_enabled() {
+        return "function";
+    }
+
+    "nop"
+}
+
+/// Returns the tracepoints of the provided subsystem, or all of them if `None`.
+fn system_tracepoints(system: Option<&str>) -> impl Iterator<Item = &'static Tracepoint> + '_ {
+    events::TRACEPOINTS
+        .iter()
+        .copied()
+        .filter(move |tracepoint| system.map_or(true, |system| tracepoint.system() == system))
+}
+
+fn parse_bool(value: &str) -> fs::Result<bool> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(FileSystemError::InvalidArgument),
+    }
+}
+
+/// Part of the last entry read from `trace_pipe` that did not fit in the buffer.
+static PIPE_PENDING: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Reads the trace buffer for `trace_pipe`, removing the entries that were read. Blocks until
+/// at least one entry is available.
+fn read_pipe(buffer: &mut [u8]) -> fs::Result<usize> {
+    loop {
+        let mut pending = PIPE_PENDING.lock();
+        let mut count = 0;
+
+        while count < buffer.len() {
+            if pending.is_empty() {
+                match trace::consume() {
+                    Some(entry) => pending.extend_from_slice(entry.format().as_bytes()),
+                    None => break,
+                }
+            }
+
+            // Only split an entry if it does not fit in the whole buffer.
+            let size = core::cmp::min(pending.len(), buffer.len() - count);
+
+            if size < pending.len() && count != 0 {
+                break;
+            }
+
+            buffer[count..count + size].copy_from_slice(&pending[..size]);
+            pending.drain(..size);
+            count += size;
+        }
+
+        core::mem::drop(pending);
+
+        if count != 0 || buffer.is_empty() {
+            return Ok(count);
+        }
+
+        hrtimer::sleep(TRACE_PIPE_INTERVAL_NS)?;
+    }
+}
+
+struct TraceINode {
+    id: usize,
+    parent: INodeCacheWeakItem,
+    node: INodeCacheWeakItem,
+    children: BTreeMap<String, INodeCacheItem>,
+    filesystem: Weak<TraceFs>,
+    contents: FileContents,
+}
+
+struct LockedTraceINode(RwLock<TraceINode>);
+
+impl LockedTraceINode {
+    fn new(node: TraceINode) -> Self {
+        Self(RwLock::new(node))
+    }
+
+    fn init(
+        &self,
+        parent: &INodeCacheWeakItem,
+        node: &INodeCacheWeakItem,
+        filesystem: &Weak<TraceFs>,
+    ) {
+        let mut this = self.0.write();
+
+        this.parent = parent.clone();
+        this.node = node.clone();
+        this.filesystem = filesystem.clone();
+    }
+
+    fn make_inode(&self, name: &str, contents: FileContents) -> Arc<LockedTraceINode> {
+        let icache = cache::icache();
+        let mut this = self.0.write();
+
+        let filesystem = this.filesystem.upgrade().unwrap();
+
+        let inode = filesystem.allocate_inode(contents);
+        let inode_cached = icache.make_item_no_cache(CachedINode::new(inode.clone()));
+
+        inode.init(&this.node, &inode_cached.downgrade(), &this.filesystem);
+
+        this.children.insert(String::from(name), inode_cached);
+        inode
+    }
+
+    /// Creates the files of the tracing filesystem in this (root) directory.
+    fn populate(&self) {
+        let files = [
+            ("tracing_on", FileContents::TracingOn),
+            ("trace", FileContents::Trace),
+            ("trace_pipe", FileContents::TracePipe),
+            ("available_events", FileContents::AvailableEvents),
+            ("set_event", FileContents::SetEvent),
+            ("current_tracer", FileContents::CurrentTracer),
+            ("available_tracers", FileContents::AvailableTracers),
+            ("buffer_size_kb", FileContents::BufferSizeKb),
+        ];
+
+        for (name, contents) in files {
+            self.make_inode(name, contents);
+        }
+
+        let events_dir = self.make_inode("events", FileContents::Directory);
+        events_dir.make_inode("enable", FileContents::SystemEnable(None));
+
+        let mut systems = BTreeMap::new();
+
+        for tracepoint in events::TRACEPOINTS {
+            let system = systems.entry(tracepoint.system()).or_insert_with(|| {
+                let system = events_dir.make_inode(tracepoint.system(), FileContents::Directory);
+                system.make_inode(
+                    "enable",
+                    FileContents::SystemEnable(Some(tracepoint.system())),
+                );
+                system
+            });
+
+            system
+                .make_inode(tracepoint.name(), FileContents::Directory)
+                .make_inode("enable", FileContents::Enable(tracepoint));
+        }
+    }
+}
+
+impl INodeInterface for LockedTraceINode {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let contents = self.0.read().contents;
+
+        match contents {
+            FileContents::Directory => return Err(FileSystemError::IsDir),
+            FileContents::TracePipe => return read_pipe(buffer),
+            _ => {}
+        }
+
+        let data = contents.read();
+
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), data.len() - offset);
+        buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
+
+        Ok(count)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let contents = self.0.read().contents;
+        let value = core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+        contents.write(value.trim())?;
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, _size: usize) -> fs::Result<()> {
+        // Shell redirections open the files with `O_TRUNC`, which clears the trace buffer and
+        // the enabled events like on Linux.
+        match self.0.read().contents {
+            FileContents::Trace => trace::clear(),
+            FileContents::SetEvent => events::TRACEPOINTS
+                .iter()
+                .for_each(|tracepoint| tracepoint.set_enabled(false)),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        let this = self.0.read();
+        let child = this
+            .children
+            .get(name)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        Ok(DirEntry::new(dir, child.clone(), String::from(name)))
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        let this = self.0.read();
+
+        let file_type = match this.contents {
+            FileContents::Directory => FileType::Directory,
+            _ => FileType::File,
+        };
+
+        Ok(Metadata {
+            id: this.id,
+            file_type,
+            size: 0,
+            children_len: this.children.len(),
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> fs::Result<Option<DirCacheItem>> {
+        let this = self.0.read();
+
+        if !matches!(this.contents, FileContents::Directory) {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        Ok(match index {
+            0x00 => Some(DirEntry::new(
+                parent,
+                // UNWRAP: The inner node value should not be dropped.
+                this.node.upgrade().unwrap(),
+                String::from("."),
+            )),
+
+            0x01 => Some(DirEntry::new(
+                parent,
+                // UNWRAP: The parent node value should not be dropped.
+                this.parent.upgrade().unwrap(),
+                String::from(".."),
+            )),
+
+            // Subtract two because of the "." and ".." entries.
+            _ => this
+                .children
+                .iter()
+                .nth(index - 2)
+                .map(|(name, inode)| DirEntry::new(parent, inode.clone(), name.clone())),
+        })
+    }
+
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.0.read().filesystem.clone())
+    }
+}
+
+struct TraceFs {
+    root_inode: INodeCacheItem,
+    root_dir: DirCacheItem,
+    next_id: AtomicUsize,
+}
+
+impl TraceFs {
+    fn new() -> Arc<Self> {
+        let icache = cache::icache();
+
+        let root_node = Arc::new(LockedTraceINode::new(TraceINode {
+            id: 0,
+            parent: CacheWeak::new(),
+            node: CacheWeak::new(),
+            children: BTreeMap::new(),
+            filesystem: Weak::default(),
+            contents: FileContents::Directory,
+        }));
+        let root_cached = icache.make_item_no_cache(CachedINode::new(root_node.clone()));
+
+        let root_dir = DirEntry::new_root(root_cached.clone(), String::from("/"));
+
+        let fs = Arc::new(Self {
+            root_inode: root_cached.clone(),
+            root_dir: root_dir.clone(),
+            next_id: AtomicUsize::new(1),
+        });
+
+        let copy: Arc<dyn FileSystem> = fs.clone();
+        root_dir.filesystem.call_once(|| Arc::downgrade(&copy));
+
+        root_node.init(
+            &fs.root_inode.downgrade(),
+            &root_cached.downgrade(),
+            &Arc::downgrade(&fs),
+        );
+
+        root_node.populate();
+        fs
+    }
+
+    fn allocate_inode(&self, contents: FileContents) -> Arc<LockedTraceINode> {
+        Arc::new(LockedTraceINode::new(TraceINode {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            parent: CacheWeak::new(),
+            node: CacheWeak::new(),
+            children: BTreeMap::new(),
+            filesystem: Weak::default(),
+            contents,
+        }))
+    }
+}
+
+impl FileSystem for TraceFs {
+    #[inline]
+    fn root_dir(&self) -> DirCacheItem {
+        self.root_dir.clone()
+    }
+}
+
+/// Creates a new instance of the tracing filesystem (see `mount`).
+pub fn create() -> fs::Result<Arc<dyn FileSystem>> {
+    Ok(TraceFs::new())
+}
+
+static TRACE_FS: Once<Arc<TraceFs>> = Once::new();
+
+pub fn init() -> fs::Result<()> {
+    let fs = TRACE_FS.call_once(TraceFs::new);
+
+    let inode = match super::lookup_path(Path::new("/sys/kernel/tracing")) {
+        Ok(inode) => inode,
+        Err(FileSystemError::EntryNotFound) => {
+            log::warn!("tracefs: `/sys/kernel/tracing` does not exist; not mounting");
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+
+    MOUNT_MANAGER.mount(inode, fs.clone())?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Periodic Writeback
+//!
+//! Modified inodes and page-cache pages are not written to the disk straight away. Instead, the
+//! `writeback` kernel thread flushes the dirty inodes (see [`cache::mark_inode_dirty`]) and then
+//! the dirty pages every [`WRITEBACK_INTERVAL`] seconds, so that the data loss on a crash is
+//! bounded without requiring `fsync(2)`. The inodes are written back first as the on-disk inode
+//! lives in the page cache of the block device.
+
+use crate::fs::block::PAGE_CACHE;
+use crate::fs::cache;
+use crate::userland::{kthread, scheduler};
+
+/// The interval between two writebacks, in seconds.
+const WRITEBACK_INTERVAL: usize = 5;
+
+/// Writes back the dirty inodes and pages.
+pub fn writeback() {
+    cache::writeback_inodes();
+    PAGE_CACHE.sync_all();
+}
+
+fn writeback_thread() {
+    loop {
+        // A signal cut the sleep short; drop it and go back to sleep.
+        if scheduler::get_scheduler()
+            .inner
+            .sleep(Some(WRITEBACK_INTERVAL))
+            .is_err()
+        {
+            kthread::flush_signals();
+            continue;
+        }
+
+        writeback();
+    }
+}
+
+pub fn init() {
+    kthread::spawn("writeback", writeback_thread);
+}
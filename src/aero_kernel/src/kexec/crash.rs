@@ -0,0 +1,235 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Crash dumps (kdump). Memory for the crash kernel is reserved at boot with the
+//! `crashkernel=SIZE` option and shows up as `Crash kernel` in `/proc/iomem`. The crash kernel
+//! is loaded into it with `kexec_load(KEXEC_ON_CRASH)` and on a kernel panic the crashed
+//! kernel jumps into it, leaving the rest of the memory as it was so it can be saved and
+//! analyzed offline.
+//!
+//! The first [`ELFCOREHDR_SIZE`] bytes of the region are reserved for the ELF core header
+//! (`elfcorehdr`), which is written before jumping into the crash kernel. It contains:
+//!
+//! * A `PT_NOTE` segment with an `AERO` note of type [`NT_AERO_LOG`] holding the end of the kernel
+//!   log, including the panic message and the stack trace.
+//! * A `PT_LOAD` segment for each range of RAM of the crashed kernel.
+//!
+//! Like on Linux, the `p_offset` of each segment is the physical address of its data.
+
+use core::mem::size_of;
+
+use crate::arch::kexec::{crash_region, memory_ranges};
+use crate::mem::paging::PhysAddr;
+use crate::utils::sync::Mutex;
+
+use super::Image;
+
+/// Size of the ELF core header at the start of the crash kernel region.
+pub const ELFCOREHDR_SIZE: u64 = 0x10000;
+
+/// Type of the `AERO` note holding the kernel log.
+pub const NT_AERO_LOG: u32 = 1;
+
+/// The maximum size of the kernel log in the ELF core header.
+const LOG_SIZE: usize = 4096;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RWX: u32 = 0b111;
+
+#[cfg(target_arch = "x86_64")]
+const EM_NATIVE: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const EM_NATIVE: u16 = 183;
+
+#[repr(C)]
+struct Elf64Ehdr {
+    ident: [u8; 16],
+    typ: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+struct Elf64Phdr {
+    typ: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+#[repr(C)]
+struct Elf64Nhdr {
+    namesz: u32,
+    descsz: u32,
+    typ: u32,
+}
+
+pub(super) static CRASH_IMAGE: Mutex<Option<Image>> = Mutex::new(None);
+
+/// Returns whether the provided physical range can be used by the crash kernel.
+pub fn is_crash_ram(start: u64, size: u64) -> bool {
+    let Some((base, length)) = crash_region() else {
+        return false;
+    };
+
+    start >= base + ELFCOREHDR_SIZE && start.saturating_add(size) <= base + length
+}
+
+/// Returns whether a crash kernel is loaded.
+pub fn is_loaded() -> bool {
+    CRASH_IMAGE.lock().is_some()
+}
+
+fn put<T>(header: &mut [u8], offset: usize, value: T) -> usize {
+    let bytes = &mut header[offset..offset + size_of::<T>()];
+
+    // SAFETY: The slice above is large enough to hold `T`.
+    unsafe { bytes.as_mut_ptr().cast::<T>().write_unaligned(value) };
+    offset + size_of::<T>()
+}
+
+/// Writes the ELF core header at the start of the crash kernel region at `base`.
+fn write_elfcorehdr(base: u64) {
+    let header = PhysAddr::new(base)
+        .as_hhdm_virt()
+        .as_bytes_mut(ELFCOREHDR_SIZE as usize);
+
+    let note_size = size_of::<Elf64Nhdr>() + 8 + LOG_SIZE;
+    let max_loads = (header.len() - size_of::<Elf64Ehdr>() - note_size) / size_of::<Elf64Phdr>();
+
+    // The memory map cannot be collected into a vector, as the heap might be corrupted.
+    let loads = memory_ranges().count().min(max_loads - 1);
+    let phnum = loads + 1;
+
+    let note = size_of::<Elf64Ehdr>() + phnum * size_of::<Elf64Phdr>();
+
+    let mut ident = [0; 16];
+    ident[..4].copy_from_slice(b"\x7fELF");
+    ident[4] = 2; // ELFCLASS64
+    ident[5] = 1; // ELFDATA2LSB
+    ident[6] = 1; // EV_CURRENT
+
+    let mut offset = put(
+        header,
+        0,
+        Elf64Ehdr {
+            ident,
+            typ: ET_CORE,
+            machine: EM_NATIVE,
+            version: 1,
+            entry: 0,
+            phoff: size_of::<Elf64Ehdr>() as u64,
+            shoff: 0,
+            flags: 0,
+            ehsize: size_of::<Elf64Ehdr>() as u16,
+            phentsize: size_of::<Elf64Phdr>() as u16,
+            phnum: phnum as u16,
+            shentsize: 0,
+            shnum: 0,
+            shstrndx: 0,
+        },
+    );
+
+    // Write the note first, its size is required for the program header.
+    let log_size = {
+        let desc = note + size_of::<Elf64Nhdr>() + 8;
+        let size = crate::logger::copy_log_buffer(&mut header[desc..desc + LOG_SIZE]);
+
+        let name = put(
+            header,
+            note,
+            Elf64Nhdr {
+                namesz: 5,
+                descsz: size as u32,
+                typ: NT_AERO_LOG,
+            },
+        );
+
+        header[name..name + 8].copy_from_slice(b"AERO\0\0\0\0");
+        size
+    };
+
+    let note_addr = base + note as u64;
+    let note_size = (size_of::<Elf64Nhdr>() + 8 + log_size.next_multiple_of(4)) as u64;
+
+    offset = put(
+        header,
+        offset,
+        Elf64Phdr {
+            typ: PT_NOTE,
+            flags: 0,
+            offset: note_addr,
+            vaddr: 0,
+            paddr: note_addr,
+            filesz: note_size,
+            memsz: note_size,
+            align: 0,
+        },
+    );
+
+    for (base, length) in memory_ranges().take(loads) {
+        offset = put(
+            header,
+            offset,
+            Elf64Phdr {
+                typ: PT_LOAD,
+                flags: PF_RWX,
+                offset: base,
+                vaddr: unsafe { crate::PHYSICAL_MEMORY_OFFSET }.as_u64() + base,
+                paddr: base,
+                filesz: length,
+                memsz: length,
+                align: 0,
+            },
+        );
+    }
+}
+
+/// Jumps into the crash kernel, if one is loaded. Called by the panic handler.
+pub fn crash_kexec() {
+    // The lock is held if the kernel panicked while loading the crash kernel.
+    let Some(image) = CRASH_IMAGE.try_lock_irq() else {
+        return;
+    };
+
+    let (Some(image), Some((base, _))) = (image.as_ref(), crash_region()) else {
+        return;
+    };
+
+    write_elfcorehdr(base);
+
+    log::error!("kexec: jumping into the crash kernel at {:#x}", image.entry);
+
+    // SAFETY: The kernel panicked, so it does not matter whether the devices are still running.
+    unsafe { image.purgatory.execute(image.entry) }
+}
@@ -0,0 +1,276 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! kexec loads a new kernel into memory and jumps into it without going through the firmware.
+//!
+//! `kexec_load` copies the segments of the new kernel into memory that is not used as the
+//! destination of any segment (the staging area), since the destinations are usually used by
+//! the running kernel. `reboot(RB_KEXEC)` then stops the other CPUs and jumps into the
+//! purgatory (see [`crate::arch::kexec`]) which copies the staged segments to their
+//! destinations and enters the new kernel.
+//!
+//! A crash kernel (loaded with `KEXEC_ON_CRASH`) is executed on a kernel panic instead, see
+//! [`crash`].
+//!
+//! ## Notes
+//! * The devices are not shut down, so DMA that is in flight might corrupt the new kernel.
+//! * Aero itself expects to be booted with the Limine protocol, so booting it with kexec requires a
+//!   shim (loaded as one of the segments) that provides the boot information.
+
+pub mod crash;
+
+use alloc::vec::Vec;
+
+use crate::arch::kexec::{self, Copy, Purgatory};
+use crate::mem::paging::{order_size, PhysAddr, FRAME_ALLOCATOR, MAX_ORDER};
+use crate::utils::sync::Mutex;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KexecError {
+    /// A segment is not page aligned, overlaps another segment or the entry point does not
+    /// lie in any of the segments.
+    InvalidSegment,
+    /// The destination of a segment is not usable RAM, or not in the memory reserved for the
+    /// crash kernel.
+    NotRam,
+    OutOfMemory,
+}
+
+/// A segment of the new kernel; `data` is copied to the physical address `mem` and the rest
+/// of the `memsz` bytes are zeroed.
+pub struct Segment<'a> {
+    pub data: &'a [u8],
+    pub mem: u64,
+    pub memsz: u64,
+}
+
+/// A loaded kernel, ready to be executed.
+struct Image {
+    entry: u64,
+    /// The staging area, freed if the image is unloaded.
+    chunks: Vec<(PhysAddr, usize)>,
+    purgatory: Purgatory,
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        for &(chunk, order) in self.chunks.iter() {
+            FRAME_ALLOCATOR.dealloc_order(chunk, order);
+        }
+    }
+}
+
+static IMAGE: Mutex<Option<Image>> = Mutex::new(None);
+
+/// Checks the segments; `is_ram` returns whether a physical range may be used as a destination.
+fn validate(
+    entry: u64,
+    segments: &[Segment],
+    is_ram: impl Fn(u64, u64) -> bool,
+) -> Result<(), KexecError> {
+    for (i, segment) in segments.iter().enumerate() {
+        let end = segment
+            .mem
+            .checked_add(segment.memsz)
+            .ok_or(KexecError::InvalidSegment)?;
+
+        if segment.mem % PAGE_SIZE != 0
+            || segment.memsz % PAGE_SIZE != 0
+            || segment.memsz == 0
+            || segment.data.len() as u64 > segment.memsz
+        {
+            return Err(KexecError::InvalidSegment);
+        }
+
+        let overlaps = segments[..i]
+            .iter()
+            .any(|other| segment.mem < other.mem + other.memsz && other.mem < end);
+
+        if overlaps {
+            return Err(KexecError::InvalidSegment);
+        }
+
+        if !is_ram(segment.mem, segment.memsz) {
+            return Err(KexecError::NotRam);
+        }
+    }
+
+    let has_entry = segments
+        .iter()
+        .any(|segment| (segment.mem..segment.mem + segment.memsz).contains(&entry));
+
+    if !has_entry {
+        return Err(KexecError::InvalidSegment);
+    }
+
+    Ok(())
+}
+
+fn load_image(entry: u64, segments: &[Segment]) -> Result<Image, KexecError> {
+    let overlaps_destination = |addr: PhysAddr, order: usize| {
+        let start = addr.as_u64();
+        let end = start + order_size(order) as u64;
+
+        segments
+            .iter()
+            .any(|segment| start < segment.mem + segment.memsz && segment.mem < end)
+    };
+
+    // Blocks that overlap a destination are kept allocated until the image is loaded, so
+    // they are not handed out again.
+    let mut rejected = Vec::new();
+
+    let mut alloc = |order: usize| loop {
+        let addr = FRAME_ALLOCATOR.alloc_order_zeroed(order)?;
+
+        if !overlaps_destination(addr, order) {
+            break Some(addr);
+        }
+
+        rejected.push((addr, order));
+    };
+
+    let mut chunks = Vec::new();
+    let mut copies = Vec::new();
+
+    let result = (|| {
+        for segment in segments {
+            let mut offset = 0;
+
+            while offset < segment.memsz {
+                let size = (segment.memsz - offset).min(order_size(MAX_ORDER) as u64);
+                let order = (0..=MAX_ORDER)
+                    .find(|&order| order_size(order) as u64 >= size)
+                    .unwrap();
+
+                let chunk = alloc(order).ok_or(KexecError::OutOfMemory)?;
+                chunks.push((chunk, order));
+
+                let data = segment.data.get(offset as usize..).unwrap_or(&[]);
+                let len = data.len().min(size as usize);
+
+                chunk
+                    .as_hhdm_virt()
+                    .as_bytes_mut(len)
+                    .copy_from_slice(&data[..len]);
+
+                copies.push(Copy {
+                    source: chunk.as_u64(),
+                    destination: segment.mem + offset,
+                    size,
+                });
+
+                offset += size;
+            }
+        }
+
+        Purgatory::new(&copies, &mut alloc).ok_or(KexecError::OutOfMemory)
+    })();
+
+    for (addr, order) in rejected {
+        FRAME_ALLOCATOR.dealloc_order(addr, order);
+    }
+
+    match result {
+        Ok(purgatory) => Ok(Image {
+            entry,
+            chunks,
+            purgatory,
+        }),
+
+        Err(err) => {
+            for (chunk, order) in chunks {
+                FRAME_ALLOCATOR.dealloc_order(chunk, order);
+            }
+
+            Err(err)
+        }
+    }
+}
+
+/// Loads the crash kernel. The crash kernel region is not used by the running kernel, so the
+/// segments are copied to their destinations right away.
+fn load_crash_image(entry: u64, segments: &[Segment]) -> Result<Image, KexecError> {
+    for segment in segments {
+        let destination = PhysAddr::new(segment.mem).as_hhdm_virt();
+        let memory = destination.as_bytes_mut(segment.memsz as usize);
+
+        let (data, zeroed) = memory.split_at_mut(segment.data.len());
+        data.copy_from_slice(segment.data);
+        zeroed.fill(0);
+    }
+
+    let mut alloc = |order: usize| FRAME_ALLOCATOR.alloc_order_zeroed(order);
+    let purgatory = Purgatory::new(&[], &mut alloc).ok_or(KexecError::OutOfMemory)?;
+
+    Ok(Image {
+        entry,
+        chunks: Vec::new(),
+        purgatory,
+    })
+}
+
+/// Loads the new kernel, replacing the previously loaded one. The kernel is unloaded if no
+/// segments are provided. If `on_crash` is set, the kernel is loaded as the crash kernel.
+pub fn load(entry: u64, segments: &[Segment], on_crash: bool) -> Result<(), KexecError> {
+    if on_crash {
+        let mut image = crash::CRASH_IMAGE.lock();
+
+        // The previous crash kernel is overwritten, so it cannot be left loaded.
+        image.take();
+
+        if !segments.is_empty() {
+            validate(entry, segments, crash::is_crash_ram)?;
+            *image = Some(load_crash_image(entry, segments)?);
+        }
+
+        return Ok(());
+    }
+
+    if segments.is_empty() {
+        IMAGE.lock().take();
+        return Ok(());
+    }
+
+    validate(entry, segments, kexec::is_ram)?;
+
+    let image = load_image(entry, segments)?;
+    let old = IMAGE.lock().replace(image);
+
+    // The previous image is freed after the lock is released.
+    drop(old);
+    Ok(())
+}
+
+/// Returns whether a kernel is loaded.
+pub fn is_loaded() -> bool {
+    IMAGE.lock().is_some()
+}
+
+/// Jumps into the loaded kernel. Returns if no kernel is loaded.
+pub fn execute() {
+    let image = IMAGE.lock();
+
+    if let Some(image) = image.as_ref() {
+        log::info!("kexec: jumping into the new kernel at {:#x}", image.entry);
+
+        // SAFETY: The caller has shut down the devices.
+        unsafe { image.purgatory.execute(image.entry) }
+    }
+}
@@ -112,6 +112,21 @@ pub fn get_log_buffer() -> String {
         .expect("log: attempted to get the log ring buffer before it was initialized")
 }
 
+/// Copies the end of the log ring buffer into `buffer` without allocating, returning the number
+/// of bytes copied. Used while handling a panic.
+pub fn copy_log_buffer(buffer: &mut [u8]) -> usize {
+    let Some(log_ring) = LOG_RING_BUFFER.get() else {
+        return 0;
+    };
+
+    let mut log_ring = log_ring.lock_irq();
+    let log = log_ring.extract().as_bytes();
+    let log = &log[log.len().saturating_sub(buffer.len())..];
+
+    buffer[..log.len()].copy_from_slice(log);
+    log.len()
+}
+
 #[inline]
 pub fn enabled_rendy_debug() -> bool {
     RENDY_DEBUG.load(Ordering::SeqCst)
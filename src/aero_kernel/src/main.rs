@@ -48,6 +48,7 @@
     associated_type_defaults,
     trait_upcasting,
     new_zeroed_alloc, // https://github.com/rust-lang/rust/issues/129396
+    no_sanitize, // https://github.com/rust-lang/rust/issues/39699
     sync_unsafe_cell
 )]
 // TODO(andypython): can we remove the dependency of "prelude_import" and "lang_items"?
@@ -91,18 +92,25 @@ mod acpi;
 mod arch;
 mod cmdline;
 mod drivers;
+mod efi;
 #[cfg(feature = "ci")]
 mod emu;
 mod fs;
+mod kexec;
 mod logger;
 mod mem;
 mod modules;
 mod net;
+mod perf;
+mod power;
+mod random;
 mod rendy;
+mod selftest;
 mod socket;
 mod syscall;
 #[cfg(test)]
 mod tests;
+mod trace;
 mod unwind;
 mod userland;
 mod utils;
@@ -111,7 +119,7 @@ use self::mem::alloc::LockedHeap;
 use self::mem::paging::VirtAddr;
 
 use self::arch::interrupts;
-use self::userland::scheduler;
+use self::userland::{kthread, scheduler};
 
 use self::userland::task::Task;
 
@@ -129,6 +137,7 @@ pub fn relocate_self() {
 
     let unwind_info = unwind::UNWIND_INFO.get().unwrap();
     let kernel_elf = &unwind_info.kernel_elf;
+    let slide = unwind_info.slide;
 
     for section in kernel_elf.section_iter() {
         if let Ok(SectionData::Rela64(rela)) = section.get_data(kernel_elf) {
@@ -137,9 +146,13 @@ pub fn relocate_self() {
                     continue;
                 }
 
-                let offset = unsafe { &mut *(item.get_offset() as *mut usize) };
+                // The offset and the addend are link-time addresses, so they need to be
+                // adjusted by the KASLR slide.
+                let offset = unsafe {
+                    &mut *((item.get_offset() as usize).wrapping_add(slide) as *mut usize)
+                };
 
-                let resolver_ptr = item.get_addend() as *const u8;
+                let resolver_ptr = (item.get_addend() as usize).wrapping_add(slide) as *const u8;
                 let resolver: fn() -> usize = unsafe { core::mem::transmute(resolver_ptr) };
 
                 *offset = resolver();
@@ -158,9 +171,15 @@ fn aero_main() -> ! {
     crate::arch::time::init();
     log::info!("loaded timer");
 
+    random::init();
+    trace::init();
+
     userland::scheduler::init();
     log::info!("loaded scheduler");
 
+    userland::workqueue::init();
+    fs::writeback::init();
+
     #[cfg(target_arch = "x86_64")]
     crate::arch::apic::mark_bsp_ready(true);
 
@@ -169,9 +188,8 @@ fn aero_main() -> ! {
     // Now that all of the essential initialization is done we are going to schedule
     // the kernel main thread.
     let init = Task::new_kernel(kernel_main_thread, true);
-    let kdbg = Task::new_kernel(kernel_dbg_thread, true);
     scheduler::get_scheduler().register_task(init);
-    scheduler::get_scheduler().register_task(kdbg);
+    kthread::spawn("kdbg", kernel_dbg_thread);
 
     unsafe {
         interrupts::enable_interrupts();
@@ -179,9 +197,7 @@ fn aero_main() -> ! {
 
     // Pre-scheduler init done. Now we are waiting for the main kernel
     // thread to be scheduled.
-    loop {
-        unsafe { interrupts::halt() }
-    }
+    scheduler::idle::run()
 }
 
 fn kernel_main_thread() {
@@ -197,6 +213,10 @@ fn kernel_main_thread() {
     #[cfg(test)]
     test_main();
 
+    if cmdline::selftest() {
+        selftest::run();
+    }
+
     if logger::enabled_rendy_debug() {
         #[cfg(not(test))]
         rendy::clear_screen(true);
@@ -269,9 +289,13 @@ fn kernel_dbg_thread() {
 }
 
 extern "C" fn aero_ap_main(ap_id: usize) -> ! {
-    log::info!("AP{}: Loaded userland", ap_id);
+    scheduler::init_ap();
+    log::info!("AP{}: loaded scheduler", ap_id);
 
-    loop {
-        unsafe { interrupts::halt() }
+    unsafe {
+        interrupts::enable_interrupts();
     }
+
+    // Wait for the scheduler to pick up the tasks that are queued on this CPU.
+    scheduler::idle::run()
 }
@@ -18,7 +18,7 @@
 use core::alloc;
 use core::alloc::{GlobalAlloc, Layout};
 
-use super::slab::{SlabHeader, SmallSlab};
+use super::slab::{self, SlabHeader, SmallSlab};
 use super::vmalloc;
 use crate::mem::paging::*;
 use crate::utils::sync::IrqGuard;
@@ -47,6 +47,10 @@ impl Allocator {
     fn alloc(&self, layout: Layout) -> *mut u8 {
         let _guard = IrqGuard::new();
 
+        if let Some(ptr) = slab::alloc_active(&layout) {
+            return ptr;
+        }
+
         let size = align_up(layout.size() as _, layout.align() as _);
 
         for slab in self.zones.iter() {
@@ -71,6 +75,23 @@ impl Allocator {
         }
     }
 
+    /// Returns the size of the slot that the allocation at `ptr` with the provided `layout`
+    /// is served from.
+    #[cfg(feature = "kasan")]
+    fn slot_size(&self, ptr: *mut u8, layout: &Layout) -> usize {
+        let size = align_up(layout.size() as _, layout.align() as _) as usize;
+
+        if let Some(cache) = slab::owner(ptr) {
+            return cache.object_size();
+        }
+
+        self.zones
+            .iter()
+            .map(SmallSlab::size)
+            .find(|&zone| size <= zone)
+            .unwrap_or(size)
+    }
+
     fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let address = VirtAddr::new(ptr as u64);
 
@@ -80,7 +101,10 @@ impl Allocator {
         }
 
         let _guard = IrqGuard::new();
-        if layout.size() <= 1024 {
+
+        if let Some(cache) = slab::owner(ptr) {
+            cache.dealloc(ptr);
+        } else if layout.size() <= 1024 {
             let slab_header = SlabHeader::from_object(ptr);
             slab_header.as_slab().dealloc(ptr);
         }
@@ -214,7 +238,12 @@ unsafe impl GlobalAlloc for LockedHeap {
         #[cfg(feature = "kmemleak")]
         kmemleak::MEM_LEAK_CATCHER.track_caller(ptr, layout);
 
-        self.0.alloc(layout)
+        let ptr = self.0.alloc(layout);
+
+        #[cfg(feature = "kasan")]
+        super::kasan::alloc(ptr, layout.size(), self.0.slot_size(ptr, &layout));
+
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -224,6 +253,13 @@ unsafe impl GlobalAlloc for LockedHeap {
         #[cfg(feature = "kmemleak")]
         kmemleak::MEM_LEAK_CATCHER.unref(ptr);
 
+        // Freed objects are quarantined for a while, so accesses after the free are caught
+        // before the memory is reused.
+        #[cfg(feature = "kasan")]
+        let Some((ptr, layout)) = super::kasan::free(ptr, layout) else {
+            return;
+        };
+
         self.0.dealloc(ptr, layout)
     }
 
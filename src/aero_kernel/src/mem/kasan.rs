@@ -0,0 +1,500 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel address sanitizer (KASAN).
+//!
+//! The kernel has to be built with `-Zsanitizer=kernel-address` (see the `kasan` feature), which
+//! makes the compiler check every memory access against the shadow memory. Each 8-byte granule
+//! of the kernel half of the address space is described by the shadow byte at
+//! `(address >> 3) + SHADOW_OFFSET`: zero means that the whole granule is accessible, `1..=7`
+//! that only its first bytes are and a negative value that it is poisoned.
+//!
+//! The heap poisons the unused tail of each object and the objects that are freed, which are
+//! kept in a quarantine for a while so accesses after the free are caught before the memory is
+//! reused. The redzones around the variables on the stack are poisoned by the instrumented code
+//! itself.
+//!
+//! Until [`init`] is called, the whole shadow is mapped to a single shared page, so the stack
+//! poisoning done by the instrumented code during early boot does not fault. Afterwards, the
+//! shadow of the RAM and of the vmalloc areas is backed by real memory, and the shared page is
+//! cleared and made read-only so the shadow of everything else reads as accessible.
+
+use core::alloc::Layout;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use limine::memory_map::EntryType;
+use limine::response::MemoryMapResponse;
+
+use super::paging::*;
+use super::vmalloc::{VMALLOC_END, VMALLOC_START};
+use crate::utils::sync::Mutex;
+
+/// Offset of the shadow memory, the compiler has to be told about it with
+/// `-Cllvm-args=-asan-mapping-offset=0xdffff80000000000`.
+const SHADOW_OFFSET: u64 = 0xdffff80000000000;
+/// Start of the kernel half of the address space, only the kernel half has a shadow.
+const KERNEL_HALF: u64 = 0xffff800000000000;
+
+/// Start of the shadow memory; the shadow ends right at the start of the vmalloc area.
+const SHADOW_START: VirtAddr = VirtAddr::new((KERNEL_HALF >> 3) + SHADOW_OFFSET);
+const SHADOW_END: VirtAddr = VirtAddr::new((u64::MAX >> 3) + SHADOW_OFFSET + 1);
+
+const GRANULE: usize = 8;
+
+/// Shadow byte of the heap memory that does not belong to an object.
+pub const REDZONE: u8 = 0xfc;
+/// Shadow byte of freed heap objects.
+const FREED: u8 = 0xfb;
+
+// Shadow bytes of the stack redzones, picked by the compiler.
+const STACK_LEFT_REDZONE: u8 = 0xf1;
+const STACK_MID_REDZONE: u8 = 0xf2;
+const STACK_RIGHT_REDZONE: u8 = 0xf3;
+const STACK_AFTER_SCOPE: u8 = 0xf8;
+
+/// Number of freed objects that are kept in the quarantine.
+const QUARANTINE_SIZE: usize = 1024;
+
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+const FLAGS: u64 = PageTableFlags::PRESENT.bits() | PageTableFlags::WRITABLE.bits();
+
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+// The early shadow: every entry of the tables points to the next level, down to the shared
+// page. They live in the kernel image as there is no frame allocator in early boot.
+static mut EARLY_P3: Table = Table([0; 512]);
+static mut EARLY_P2: Table = Table([0; 512]);
+static mut EARLY_P1: Table = Table([0; 512]);
+static mut EARLY_PAGE: Table = Table([0; 512]);
+
+/// Difference between the virtual and physical address of the kernel image.
+static KERNEL_PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the shadow is set up and accesses are checked.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static POPULATE_LOCK: Mutex<()> = Mutex::new(());
+
+struct Quarantine {
+    objects: [Option<(usize, Layout)>; QUARANTINE_SIZE],
+    next: usize,
+}
+
+static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine {
+    objects: [None; QUARANTINE_SIZE],
+    next: 0,
+});
+
+#[inline]
+fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the address of the shadow byte of the granule containing `address`.
+#[inline]
+fn shadow(address: u64) -> *mut u8 {
+    ((address >> 3) + SHADOW_OFFSET) as *mut u8
+}
+
+#[no_sanitize(address)]
+fn early_phys(table: *mut Table) -> u64 {
+    table as u64 - KERNEL_PHYS_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Returns the index into the page table at the level translating `shift` bits.
+#[no_sanitize(address)]
+fn table_index(address: u64, shift: u64) -> usize {
+    ((address >> shift) & 511) as usize
+}
+
+/// Maps the whole shadow to the early shadow. This has to be called before any instrumented
+/// code runs, with the boot page tables still active.
+///
+/// ## Safety
+/// The arguments have to describe the direct map and the kernel image set up by the bootloader.
+#[no_sanitize(address)]
+pub unsafe fn init_early(hhdm: u64, virtual_base: u64, physical_base: u64) {
+    KERNEL_PHYS_OFFSET.store(virtual_base - physical_base, Ordering::Relaxed);
+
+    let p2 = early_phys(addr_of_mut!(EARLY_P2));
+    let p1 = early_phys(addr_of_mut!(EARLY_P1));
+    let page = early_phys(addr_of_mut!(EARLY_PAGE));
+
+    for i in 0..512 {
+        (*addr_of_mut!(EARLY_P3)).0[i] = p2 | FLAGS;
+        (*addr_of_mut!(EARLY_P2)).0[i] = p1 | FLAGS;
+        (*addr_of_mut!(EARLY_P1)).0[i] = page | FLAGS;
+    }
+
+    let cr3: u64;
+    core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack));
+
+    let p4 = ((cr3 & ADDRESS_MASK) + hhdm) as *mut u64;
+    let p3 = early_phys(addr_of_mut!(EARLY_P3));
+
+    for slot in table_index(SHADOW_START.as_u64(), 39)..table_index(SHADOW_END.as_u64(), 39) {
+        *p4.add(slot) = p3 | FLAGS;
+    }
+}
+
+/// Returns the table `entry` points to. If it points to the `early` table, it is replaced by a
+/// private copy first.
+#[no_sanitize(address)]
+unsafe fn private_table(entry: *mut u64, early: *mut Table) -> *mut u64 {
+    if *entry & ADDRESS_MASK == early_phys(early) {
+        let frame = FRAME_ALLOCATOR
+            .alloc(Size4KiB::SIZE as usize)
+            .expect("kasan: out of memory");
+
+        let table = frame.as_hhdm_virt().as_mut_ptr::<u64>();
+        table.copy_from_nonoverlapping(early.cast::<u64>(), 512);
+
+        *entry = frame.as_u64() | FLAGS;
+    }
+
+    PhysAddr::new(*entry & ADDRESS_MASK)
+        .as_hhdm_virt()
+        .as_mut_ptr()
+}
+
+/// Backs the shadow page at `address` with its own zeroed frame.
+#[no_sanitize(address)]
+unsafe fn populate_page(address: u64) {
+    let p4 = (active_level_4_table() as *mut PageTable).cast::<u64>();
+    let p3 = private_table(p4.add(table_index(address, 39)), addr_of_mut!(EARLY_P3));
+    let p2 = private_table(p3.add(table_index(address, 30)), addr_of_mut!(EARLY_P2));
+    let p1 = private_table(p2.add(table_index(address, 21)), addr_of_mut!(EARLY_P1));
+
+    let entry = p1.add(table_index(address, 12));
+
+    if *entry & ADDRESS_MASK == early_phys(addr_of_mut!(EARLY_PAGE)) {
+        let frame = FRAME_ALLOCATOR
+            .alloc_zeroed(Size4KiB::SIZE as usize)
+            .expect("kasan: out of memory");
+
+        *entry = frame.as_u64() | FLAGS;
+        core::arch::asm!("invlpg [{}]", in(reg) address, options(nostack));
+    }
+}
+
+/// Backs the shadow of `size` bytes at `address` with real memory.
+#[no_sanitize(address)]
+fn populate(address: VirtAddr, size: usize) {
+    let start = align_down(shadow(address.as_u64()) as u64, Size4KiB::SIZE);
+    let end = align_up(
+        shadow(address.as_u64() + size as u64) as u64,
+        Size4KiB::SIZE,
+    );
+
+    for page in (start..end).step_by(Size4KiB::SIZE as usize) {
+        unsafe { populate_page(page) }
+    }
+}
+
+/// Returns the start and the end of the shadow memory. Nothing else may be mapped in this
+/// range.
+pub fn shadow_range() -> (VirtAddr, VirtAddr) {
+    (SHADOW_START, SHADOW_END)
+}
+
+/// Sets up the shadow of the RAM and enables the checks. This has to be called right after the
+/// frame allocator is initialized, before any other address space is created.
+#[no_sanitize(address)]
+pub fn init(memmap: &MemoryMapResponse) {
+    if level_5_paging_enabled() {
+        log::warn!("kasan: 5-level paging is not supported");
+        return;
+    }
+
+    // Give each level 4 entry of the shadow its own level 3 table now, since the kernel half of
+    // the level 4 table is copied into each new address space.
+    for slot in table_index(SHADOW_START.as_u64(), 39)..table_index(SHADOW_END.as_u64(), 39) {
+        unsafe {
+            let p4 = (active_level_4_table() as *mut PageTable).cast::<u64>();
+            private_table(p4.add(slot), addr_of_mut!(EARLY_P3));
+        }
+    }
+
+    for entry in memmap.entries() {
+        if entry.entry_type == EntryType::USABLE
+            || entry.entry_type == EntryType::BOOTLOADER_RECLAIMABLE
+            || entry.entry_type == EntryType::KERNEL_AND_MODULES
+        {
+            let start = PhysAddr::new(entry.base).as_hhdm_virt();
+            populate(start, entry.length as usize);
+        }
+    }
+
+    unsafe {
+        (*addr_of_mut!(EARLY_PAGE)).0.fill(0);
+
+        let page = early_phys(addr_of_mut!(EARLY_PAGE));
+        (*addr_of_mut!(EARLY_P1))
+            .0
+            .fill(page | PageTableFlags::PRESENT.bits());
+
+        // Flush the whole TLB, as the early shadow is mapped in a lot of places.
+        core::arch::asm!("mov rax, cr3", "mov cr3, rax", out("rax") _, options(nostack));
+    }
+
+    ENABLED.store(true, Ordering::SeqCst);
+    log::info!("kasan: enabled");
+}
+
+/// Backs the shadow of a new mapping of `size` bytes at `address` with real memory and marks
+/// the mapping as accessible, as the addresses might have been used by a freed allocation.
+#[no_sanitize(address)]
+pub fn map_shadow(address: VirtAddr, size: usize) {
+    if !is_enabled() {
+        return;
+    }
+
+    {
+        let _guard = POPULATE_LOCK.lock_irq();
+        populate(address, size);
+    }
+
+    unpoison(address, size);
+}
+
+/// Poisons `size` bytes at `address` with `value`. Both have to be multiples of the granule
+/// size.
+#[no_sanitize(address)]
+pub fn poison(address: VirtAddr, size: usize, value: u8) {
+    if !is_enabled() {
+        return;
+    }
+
+    debug_assert!(address.is_aligned(GRANULE as u64) && size % GRANULE == 0);
+
+    unsafe { shadow(address.as_u64()).write_bytes(value, size / GRANULE) }
+}
+
+/// Marks `size` bytes at `address` as accessible. The address has to be a multiple of the
+/// granule size.
+#[no_sanitize(address)]
+pub fn unpoison(address: VirtAddr, size: usize) {
+    if !is_enabled() {
+        return;
+    }
+
+    debug_assert!(address.is_aligned(GRANULE as u64));
+
+    unsafe {
+        let shadow = shadow(address.as_u64());
+        shadow.write_bytes(0, size / GRANULE);
+
+        if size % GRANULE != 0 {
+            *shadow.add(size / GRANULE) = (size % GRANULE) as u8;
+        }
+    }
+}
+
+/// Marks the first `size` bytes of the new heap object at `ptr` as accessible and poisons the
+/// rest of the `slot_size` bytes it occupies.
+pub fn alloc(ptr: *mut u8, size: usize, slot_size: usize) {
+    if ptr.is_null() {
+        return;
+    }
+
+    let address = VirtAddr::new(ptr as u64);
+    let used = size.next_multiple_of(GRANULE);
+
+    unpoison(address, size);
+
+    if slot_size > used {
+        poison(address + used, slot_size - used, REDZONE);
+    }
+}
+
+/// Poisons the freed heap object at `ptr` and puts it into the quarantine. Returns the object
+/// that got evicted from the quarantine, which has to be freed for real.
+#[no_sanitize(address)]
+pub fn free(ptr: *mut u8, layout: Layout) -> Option<(*mut u8, Layout)> {
+    let address = VirtAddr::new(ptr as u64);
+
+    // Freed vmalloc memory is unmapped, so accesses to it fault anyway.
+    if !is_enabled() || (address >= VMALLOC_START && address < VMALLOC_END) {
+        return Some((ptr, layout));
+    }
+
+    if unsafe { *shadow(address.as_u64()) } == FREED {
+        ENABLED.store(false, Ordering::SeqCst);
+        panic!("kasan: double-free of {ptr:p} (size={})", layout.size());
+    }
+
+    poison(address, layout.size().next_multiple_of(GRANULE), FREED);
+
+    let mut quarantine = QUARANTINE.lock_irq();
+    let next = quarantine.next;
+
+    quarantine.next = (next + 1) % QUARANTINE_SIZE;
+    quarantine.objects[next]
+        .replace((ptr as usize, layout))
+        .map(|(address, layout)| (address as *mut u8, layout))
+}
+
+/// Returns the address of the first byte of the `size` bytes at `address` that is not
+/// accessible.
+#[no_sanitize(address)]
+fn find_poisoned(address: u64, size: usize) -> Option<u64> {
+    let end = address + size as u64;
+    let mut granule = address;
+
+    while granule < end {
+        let value = unsafe { *shadow(granule) } as i8;
+
+        if value != 0 {
+            // Offset of the last accessed byte in the granule.
+            let last = (core::cmp::min(end, (granule | 7) + 1) - 1) & 7;
+
+            if value < 0 || last as i8 >= value {
+                return Some(granule);
+            }
+        }
+
+        granule = (granule | 7) + 1;
+    }
+
+    None
+}
+
+#[no_sanitize(address)]
+fn check(address: u64, size: usize, write: bool, ip: usize) {
+    if !is_enabled() || address < KERNEL_HALF || size == 0 {
+        return;
+    }
+
+    if let Some(bad) = find_poisoned(address, size) {
+        report(bad, address, size, write, ip);
+    }
+}
+
+#[cold]
+#[no_sanitize(address)]
+fn report(bad: u64, address: u64, size: usize, write: bool, ip: usize) -> ! {
+    // Everything done from here on is checked as well.
+    ENABLED.store(false, Ordering::SeqCst);
+
+    let mut value = unsafe { *shadow(bad) };
+
+    // The access went past the end of an object that does not end at a granule boundary.
+    if (1..GRANULE as u8).contains(&value) {
+        value = unsafe { *shadow(bad + GRANULE as u64) };
+    }
+
+    let kind = match value {
+        REDZONE => "slab-out-of-bounds",
+        FREED => "use-after-free",
+        STACK_LEFT_REDZONE | STACK_MID_REDZONE | STACK_RIGHT_REDZONE => "stack-out-of-bounds",
+        STACK_AFTER_SCOPE => "stack-use-after-scope",
+        _ => "out-of-bounds",
+    };
+
+    let function = match crate::unwind::resolve_symbol(ip) {
+        Some((name, offset)) => alloc::format!("{name:#}+{offset:#x}"),
+        None => alloc::format!("{ip:#x}"),
+    };
+
+    panic!(
+        "kasan: {kind} in {function}: {} of size {size} at {address:#x} (shadow={value:#x})",
+        if write { "write" } else { "read" },
+    );
+}
+
+/// Returns the return address of the calling function, which is an address in the
+/// instrumented function for the check callbacks.
+#[inline(always)]
+fn return_address() -> usize {
+    let address: usize;
+
+    // SAFETY: The kernel is built with frame pointers.
+    unsafe {
+        core::arch::asm!("mov {}, [rbp + 8]", out(reg) address, options(nostack, readonly));
+    }
+
+    address
+}
+
+macro_rules! check_callbacks {
+    ($($size:literal => $load:ident, $store:ident;)*) => {
+        $(
+            #[no_mangle]
+            #[no_sanitize(address)]
+            extern "C" fn $load(address: u64) {
+                check(address, $size, false, return_address());
+            }
+
+            #[no_mangle]
+            #[no_sanitize(address)]
+            extern "C" fn $store(address: u64) {
+                check(address, $size, true, return_address());
+            }
+        )*
+    };
+}
+
+check_callbacks! {
+    1 => __asan_load1, __asan_store1;
+    2 => __asan_load2, __asan_store2;
+    4 => __asan_load4, __asan_store4;
+    8 => __asan_load8, __asan_store8;
+    16 => __asan_load16, __asan_store16;
+}
+
+#[no_mangle]
+#[no_sanitize(address)]
+#[allow(non_snake_case)]
+extern "C" fn __asan_loadN(address: u64, size: usize) {
+    check(address, size, false, return_address());
+}
+
+#[no_mangle]
+#[no_sanitize(address)]
+#[allow(non_snake_case)]
+extern "C" fn __asan_storeN(address: u64, size: usize) {
+    check(address, size, true, return_address());
+}
+
+// Called before calls to functions that do not return. The kernel stacks whose frames are
+// abandoned are unpoisoned explicitly instead (see `KernelStack::unpoison`).
+#[no_mangle]
+extern "C" fn __asan_handle_no_return() {}
+
+macro_rules! set_shadow_callbacks {
+    ($($value:literal => $name:ident;)*) => {
+        $(
+            // Used by the instrumented code to poison large stack frames.
+            #[no_mangle]
+            #[no_sanitize(address)]
+            unsafe extern "C" fn $name(shadow: *mut u8, size: usize) {
+                shadow.write_bytes($value, size);
+            }
+        )*
+    };
+}
+
+set_shadow_callbacks! {
+    0x00 => __asan_set_shadow_00;
+    0xf1 => __asan_set_shadow_f1;
+    0xf2 => __asan_set_shadow_f2;
+    0xf3 => __asan_set_shadow_f3;
+    0xf5 => __asan_set_shadow_f5;
+    0xf8 => __asan_set_shadow_f8;
+}
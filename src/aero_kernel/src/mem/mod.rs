@@ -16,11 +16,18 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 pub mod alloc;
+#[cfg(feature = "kasan")]
+pub mod kasan;
+pub mod oom;
 pub mod paging;
 pub mod pti;
-mod slab;
+pub mod shrinker;
+pub mod slab;
+pub mod swap;
 mod vmalloc;
 
+pub use vmalloc::{module_alloc, module_free, KernelStack};
+
 use ::alloc::boxed::Box;
 
 use paging::*;
@@ -66,6 +73,15 @@ impl AddressSpace {
         Ok(Self { cr3 })
     }
 
+    /// Returns the address space with its page table at the provided frame.
+    ///
+    /// ## Safety
+    /// The caller must ensure that `cr3` is the root of a page table that is not freed while
+    /// the returned address space is in use.
+    pub unsafe fn from_cr3(cr3: PhysFrame) -> Self {
+        Self { cr3 }
+    }
+
     /// Returns the current active address space.
     pub fn this() -> Self {
         #[cfg(target_arch = "x86_64")]
@@ -0,0 +1,113 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Out-of-memory killer.
+//!
+//! When reclaim fails to free up enough memory to handle a page fault, the process with the
+//! largest resident set is killed to make room. The same is done within a control group that
+//! reached its memory limit (see [`crate::userland::task::cgroups`]). The init process and kernel
+//! tasks are never picked.
+
+use aero_syscall::signal::SIGKILL;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::userland::scheduler::{self, ExitStatus};
+use crate::userland::task::{Task, TaskState};
+
+use super::paging::FRAME_ALLOCATOR;
+
+/// The OOM killer is invoked when the number of free frames stays below this watermark
+/// after reclaim.
+pub const OOM_WATERMARK: usize = 64;
+/// Maximum number of times the page fault handler invokes the OOM killer for a single fault.
+pub const MAX_RETRIES: usize = 4;
+
+/// Maximum number of times to yield while waiting for the victim to exit.
+const MAX_WAIT: usize = 1024;
+
+/// Returns the badness score of the provided task; the number of pages that would be freed
+/// by killing it.
+pub fn badness(task: &Arc<Task>) -> usize {
+    task.vm().memory_usage().resident
+}
+
+/// Picks the process with the largest resident set out of the provided `candidates`. Returns
+/// the process along with its badness score.
+pub fn select_victim(candidates: Vec<Arc<Task>>) -> Option<(Arc<Task>, usize)> {
+    // The VM lock is not taken while the task list is locked.
+    candidates
+        .into_iter()
+        .filter(|task| task.is_process_leader() && task.pid().as_usize() != 1)
+        .filter(|task| !task.has_exited())
+        .map(|task| {
+            let score = badness(&task);
+            (task, score)
+        })
+        .filter(|(_, score)| *score != 0)
+        .max_by_key(|(_, score)| *score)
+}
+
+/// Kills the provided `victim` and waits for its memory to be released. This function does not
+/// return if the current process is the victim.
+pub fn kill(victim: &Arc<Task>, score: usize) {
+    log::warn!(
+        "oom: killing process {} ({}) with {} resident pages (free frames: {})",
+        victim.pid().as_usize(),
+        victim
+            .path()
+            .as_ref()
+            .map(|path| path.as_str())
+            .unwrap_or("<unknown>"),
+        score,
+        FRAME_ALLOCATOR.free_frames()
+    );
+
+    victim.signal(SIGKILL);
+
+    let current = scheduler::get_scheduler().current_task();
+
+    if Arc::ptr_eq(victim.vm(), current.vm()) {
+        scheduler::get_scheduler().exit(ExitStatus::Signal(SIGKILL));
+    }
+
+    // Wait for the victim to be swept so that its memory is released.
+    for _ in 0..MAX_WAIT {
+        if victim.state() == TaskState::Zombie {
+            break;
+        }
+
+        scheduler::get_scheduler().inner.preempt();
+    }
+}
+
+/// Picks the process with the largest resident set and kills it. Returns `false` if there is
+/// no process that can be killed. This function does not return if the current process is
+/// picked.
+pub fn out_of_memory() -> bool {
+    let mut candidates = Vec::<Arc<Task>>::new();
+
+    scheduler::get_scheduler().for_each_task(|task| candidates.push(task.clone()));
+
+    let Some((victim, score)) = select_victim(candidates) else {
+        log::error!("oom: no killable process left");
+        return false;
+    };
+
+    kill(&victim, score);
+    true
+}
@@ -17,7 +17,7 @@
 
 use core::alloc::{AllocError, Allocator, Layout};
 use core::ptr::NonNull;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
 use alloc::vec::Vec;
 
@@ -30,6 +30,7 @@ use super::page::*;
 use super::addr::PhysAddr;
 
 use crate::mem::paging::align_up;
+use crate::mem::slab::SlabCache;
 use crate::utils::bitmap::Bitmap;
 use crate::utils::sync::Mutex;
 
@@ -46,6 +47,15 @@ const BUDDY_SIZE: [u64; 10] = [
     Size2MiB::SIZE,       // 2 MiB
 ];
 
+/// The largest order that can be allocated, an order `n` block spans `2^n` contiguous
+/// 4KiB frames.
+pub const MAX_ORDER: usize = BUDDY_SIZE.len() - 1;
+
+/// Returns the size in bytes of a block with the provided `order`.
+pub const fn order_size(order: usize) -> usize {
+    BUDDY_SIZE[order] as usize
+}
+
 const fn order_from_size(size: u64) -> usize {
     // UNSTABLE: We cannot make an iterator from `BUDDY_SIZE` or use a for loop
     //           in const context.
@@ -99,25 +109,61 @@ impl LockedFrameAllocator {
     }
 
     pub fn dealloc(&self, addr: PhysAddr, size_bytes: usize) {
-        let order = order_from_size(size_bytes as u64);
-
-        let mut allocator = self.0.lock_irq();
-        allocator.deallocate_frame_inner(addr, order);
+        self.dealloc_order(addr, order_from_size(size_bytes as u64));
     }
 
     pub fn alloc(&self, size_bytes: usize) -> Option<PhysAddr> {
-        let order = order_from_size(size_bytes as u64);
+        if size_bytes as u64 > BUDDY_SIZE[MAX_ORDER] {
+            return None;
+        }
+
+        self.alloc_order(order_from_size(size_bytes as u64))
+    }
+
+    /// Allocates a physically contiguous block of `2^order` frames, aligned to its size.
+    /// Returns `None` if `order` is larger than [`MAX_ORDER`] or if there is no free block
+    /// that large.
+    pub fn alloc_order(&self, order: usize) -> Option<PhysAddr> {
+        if order > MAX_ORDER {
+            return None;
+        }
 
         let mut allocator = self.0.lock_irq();
         allocator.allocate_frame_inner(order)
     }
 
+    /// Same as [`LockedFrameAllocator::alloc_order`] but the block is zeroed.
+    pub fn alloc_order_zeroed(&self, order: usize) -> Option<PhysAddr> {
+        let addr = self.alloc_order(order)?;
+        addr.as_hhdm_virt().as_bytes_mut(order_size(order)).fill(0);
+
+        Some(addr)
+    }
+
+    /// Frees the block at `addr` that was allocated with the provided `order`.
+    pub fn dealloc_order(&self, addr: PhysAddr, order: usize) {
+        assert!(order <= MAX_ORDER);
+        debug_assert!(addr.is_aligned(BUDDY_SIZE[order]));
+
+        let mut allocator = self.0.lock_irq();
+        allocator.deallocate_frame_inner(addr, order);
+    }
+
     pub fn alloc_zeroed(&self, size_bytes: usize) -> Option<PhysAddr> {
         let addr = self.alloc(size_bytes)?;
         addr.as_hhdm_virt().as_bytes_mut(size_bytes).fill(0);
 
         Some(addr)
     }
+
+    /// Returns the number of free 4KiB frames.
+    pub fn free_frames(&self) -> usize {
+        let allocator = self.0.lock_irq();
+
+        (0..BUDDY_SIZE.len())
+            .map(|order| allocator.free[order] * (BUDDY_SIZE[order] / Size4KiB::SIZE) as usize)
+            .sum()
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for LockedFrameAllocator {
@@ -188,22 +234,6 @@ impl<'a> Iterator for RangeMemoryIter<'a> {
     }
 }
 
-#[repr(usize)]
-pub enum BuddyOrdering {
-    Size4KiB = 0,
-    Size8KiB = 1,
-}
-
-// FIXME: REMOVE THIS FUNCTION
-pub fn pmm_alloc(order: BuddyOrdering) -> PhysAddr {
-    let order = order as usize;
-    debug_assert!(order <= BUDDY_SIZE.len());
-
-    super::FRAME_ALLOCATOR
-        .alloc(BUDDY_SIZE[order] as _)
-        .unwrap()
-}
-
 #[derive(Debug)]
 struct MemoryRange {
     addr: PhysAddr,
@@ -572,15 +602,32 @@ pub fn get_vm_frames() -> Option<&'static Vec<VmFrame>> {
 
 pub struct VmFrame {
     ref_count: AtomicUsize,
+    /// Object cache that the slab containing this frame belongs to.
+    slab: AtomicPtr<SlabCache>,
 }
 
 impl VmFrame {
     fn new() -> Self {
         Self {
             ref_count: AtomicUsize::new(0),
+            slab: AtomicPtr::new(core::ptr::null_mut()),
         }
     }
 
+    /// Returns the object cache that the slab containing this frame belongs to, if any.
+    pub fn slab_cache(&self) -> Option<&'static SlabCache> {
+        // SAFETY: Only `'static` caches are recorded.
+        unsafe { self.slab.load(Ordering::SeqCst).as_ref() }
+    }
+
+    pub fn set_slab_cache(&self, cache: Option<&'static SlabCache>) {
+        let ptr = cache.map_or(core::ptr::null_mut(), |cache| {
+            cache as *const SlabCache as *mut SlabCache
+        });
+
+        self.slab.store(ptr, Ordering::SeqCst);
+    }
+
     pub fn dec_ref_count(&self) {
         let ref_count = self.ref_count.load(Ordering::SeqCst);
 
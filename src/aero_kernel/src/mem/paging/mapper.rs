@@ -25,6 +25,8 @@ use super::page::{AddressNotAligned, Page, PageSize, PhysFrame, Size1GiB, Size2M
 use super::page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags};
 use super::FRAME_ALLOCATOR;
 
+use crate::mem::swap;
+
 /// A trait for types that can allocate a frame of memory.
 ///
 /// This trait is unsafe to implement because the implementer must guarantee that
@@ -354,6 +356,15 @@ pub enum TranslateError {
     InvalidFrameAddress(PhysAddr),
 }
 
+/// An error indicating that a `split_huge_page` call failed.
+#[derive(Debug)]
+pub enum SplitError {
+    /// The given page is not mapped to a huge physical frame.
+    PageNotMapped,
+    /// A frame for the level 1 page table was needed, but the frame allocator returned `None`.
+    FrameAllocationFailed,
+}
+
 /// A trait for types that can deallocate a frame of memory.
 pub trait FrameDeallocator<S: PageSize> {
     /// Deallocate the given unused frame.
@@ -457,6 +468,23 @@ impl<'a, P: PageTableFrameMapping> MappedPageTable<'a, P> {
         flags: PageTableFlags,
         parent_table_flags: PageTableFlags,
     ) -> Result<MapperFlush<Size4KiB>, MapToError<Size4KiB>> {
+        let entry = self.create_entry(page, parent_table_flags)?;
+
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(frame));
+        }
+
+        entry.set_frame(frame, flags);
+        Ok(MapperFlush::new(page))
+    }
+
+    /// Returns the level 1 page table entry for the provided `page`, creating the parent
+    /// page tables if required.
+    fn create_entry(
+        &mut self,
+        page: Page<Size4KiB>,
+        parent_table_flags: PageTableFlags,
+    ) -> Result<&mut PageTableEntry, MapToError<Size4KiB>> {
         let p4;
 
         let mut is_alloc_4 = false;
@@ -485,11 +513,9 @@ impl<'a, P: PageTableFrameMapping> MappedPageTable<'a, P> {
             .page_table_walker
             .create_next_table(&mut p2[page.p2_index()], parent_table_flags)?;
 
-        if !p1[page.p1_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped(frame));
-        }
-
-        p1[page.p1_index()].set_frame(frame, flags);
+        // The level 1 page table is not a part of the parent page tables, so it is fine to
+        // update their entry counters while we hold on to it.
+        let p1: *mut PageTable = p1;
 
         if is_alloc_1 {
             p2[page.p2_index()].inc_entry_count();
@@ -508,6 +534,96 @@ impl<'a, P: PageTableFrameMapping> MappedPageTable<'a, P> {
             p5[page.p5_index()].inc_entry_count();
         }
 
+        // SAFETY: See the comment above.
+        Ok(unsafe { &mut (*p1)[page.p1_index()] })
+    }
+
+    /// Returns the level 1 page table entry for the provided `page` or `None` if one of
+    /// the parent page tables is not present.
+    fn entry_mut(&mut self, page: Page<Size4KiB>) -> Option<&mut PageTableEntry> {
+        let p4 = if self.level_5_paging_enabled {
+            let p5 = &mut self.page_table;
+
+            self.page_table_walker
+                .next_table_mut(&mut p5[page.p5_index()])
+                .ok()?
+        } else {
+            &mut self.page_table
+        };
+
+        let p3 = self
+            .page_table_walker
+            .next_table_mut(&mut p4[page.p4_index()])
+            .ok()?;
+        let p2 = self
+            .page_table_walker
+            .next_table_mut(&mut p3[page.p3_index()])
+            .ok()?;
+        let p1 = self
+            .page_table_walker
+            .next_table_mut(&mut p2[page.p2_index()])
+            .ok()?;
+
+        Some(&mut p1[page.p1_index()])
+    }
+
+    fn split_huge_page(
+        &mut self,
+        page: Page<Size2MiB>,
+    ) -> Result<MapperFlush<Size2MiB>, SplitError> {
+        let p4 = if self.level_5_paging_enabled {
+            let p5 = &mut self.page_table;
+
+            self.page_table_walker
+                .next_table_mut(&mut p5[page.p5_index()])?
+        } else {
+            &mut self.page_table
+        };
+
+        let p3 = self
+            .page_table_walker
+            .next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self
+            .page_table_walker
+            .next_table_mut(&mut p3[page.p3_index()])?;
+
+        let p2_entry = &mut p2[page.p2_index()];
+        let flags = p2_entry.flags();
+
+        if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE) {
+            return Err(SplitError::PageNotMapped);
+        }
+
+        let table: PhysFrame = FRAME_ALLOCATOR
+            .allocate_frame()
+            .ok_or(SplitError::FrameAllocationFailed)?;
+
+        let p1 = unsafe {
+            &mut *self
+                .page_table_walker
+                .page_table_frame_mapping
+                .frame_to_pointer(table)
+        };
+
+        p1.zero();
+
+        let start = p2_entry.addr();
+
+        // Each of the level 1 entries takes a reference to its part of the huge frame.
+        for (i, entry) in p1.entries.iter_mut().enumerate() {
+            entry.set_addr(
+                start + (i as u64 * Size4KiB::SIZE),
+                flags & !PageTableFlags::HUGE_PAGE,
+            );
+        }
+
+        // Replacing the huge entry drops the reference it held to the first frame.
+        p2_entry.set_frame(
+            table,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        );
+        p2_entry.inc_entry_count();
+
         Ok(MapperFlush::new(page))
     }
 }
@@ -787,7 +903,7 @@ impl<'a, P: PageTableFrameMapping> Translate for MappedPageTable<'a, P> {
 
         let p1_entry = &p1[addr.p1_index()];
 
-        if p1_entry.is_unused() {
+        if p1_entry.is_unused() || p1_entry.swap_entry().is_some() {
             return TranslateResult::NotMapped;
         }
 
@@ -984,6 +1100,13 @@ impl From<PageTableWalkError> for TranslateError {
     }
 }
 
+impl From<PageTableWalkError> for SplitError {
+    #[inline]
+    fn from(_err: PageTableWalkError) -> Self {
+        SplitError::PageNotMapped
+    }
+}
+
 /// Provides a virtual address mapping for physical page table frames.
 ///
 /// This only works if the physical address space is somehow mapped to the virtual
@@ -1034,6 +1157,31 @@ impl<'a> OffsetPageTable<'a> {
     pub fn page_table(&mut self) -> &mut PageTable {
         self.inner.page_table
     }
+
+    /// Returns the level 1 page table entry for the provided `page` or `None` if one of
+    /// the parent page tables is not present.
+    pub fn entry_mut(&mut self, page: Page<Size4KiB>) -> Option<&mut PageTableEntry> {
+        self.inner.entry_mut(page)
+    }
+
+    /// Returns the level 1 page table entry for the provided `page`, creating the parent
+    /// page tables with the provided `parent_table_flags` if required.
+    pub fn create_entry(
+        &mut self,
+        page: Page<Size4KiB>,
+        parent_table_flags: PageTableFlags,
+    ) -> Result<&mut PageTableEntry, MapToError<Size4KiB>> {
+        self.inner.create_entry(page, parent_table_flags)
+    }
+
+    /// Splits the 2MiB page mapping of the provided `page` into 512 4KiB page mappings of
+    /// the same frames with the same flags.
+    pub fn split_huge_page(
+        &mut self,
+        page: Page<Size2MiB>,
+    ) -> Result<MapperFlush<Size2MiB>, SplitError> {
+        self.inner.split_huge_page(page)
+    }
 }
 
 #[derive(Debug)]
@@ -1158,7 +1306,40 @@ impl<'a> OffsetPageTable<'a> {
         let mut addr = *range.start();
 
         while addr != *range.end() {
+            let page = Page::<Size4KiB>::containing_address(addr);
+
+            // Swapped out pages are shared by the swap entry instead.
+            if let Some(swap) = src.entry_mut(page).and_then(|entry| entry.swap_entry()) {
+                swap::dup(swap);
+
+                self.create_entry(
+                    page,
+                    PageTableFlags::PRESENT
+                        | PageTableFlags::USER_ACCESSIBLE
+                        | PageTableFlags::WRITABLE,
+                )
+                .unwrap()
+                .set_swap_entry(swap);
+
+                addr += Size4KiB::SIZE;
+                continue;
+            }
+
             match src.translate(addr) {
+                // Huge pages are split, so that the 4KiB pages can be copied-on-write
+                // individually.
+                TranslateResult::Mapped {
+                    frame: MappedFrame::Size2MiB(_),
+                    ..
+                } => {
+                    src.split_huge_page(Page::containing_address(addr))
+                        .expect("failed to split huge page")
+                        // caller is required to invalidate the TLB
+                        .ignore();
+
+                    continue;
+                }
+
                 TranslateResult::Mapped {
                     frame,
                     offset,
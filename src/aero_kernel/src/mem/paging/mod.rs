@@ -27,6 +27,8 @@ pub use self::mapper::*;
 pub use self::page::*;
 pub use self::page_table::*;
 
+use spin::Once;
+
 use crate::PHYSICAL_MEMORY_OFFSET;
 
 pub static FRAME_ALLOCATOR: LockedFrameAllocator = LockedFrameAllocator::new_uninit();
@@ -83,6 +85,105 @@ pub fn init(
     Ok(offset_table)
 }
 
+/// Offset of the direct map of the physical memory set up by the bootloader.
+static BOOT_PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
+
+/// Moves the direct map of the physical memory to a random 1GiB aligned address in the
+/// higher half, picked using the provided `seed`.
+///
+/// **Note**: The direct map set up by the bootloader is left mapped, since the pointers
+/// in the boot protocol responses point into it. Use [`boot_to_hhdm`] to convert them.
+#[cfg(target_arch = "x86_64")]
+pub fn randomize_physical_map(seed: u64) -> Result<(), MapToError<Size4KiB>> {
+    /// Number of 1GiB regions mapped by a single level 4 entry.
+    const GIB_PER_SLOT: usize = 512;
+
+    let boot_offset = unsafe { PHYSICAL_MEMORY_OFFSET };
+    BOOT_PHYSICAL_MEMORY_OFFSET.call_once(|| boot_offset);
+
+    // TODO: Support randomizing the direct map with 5-level paging.
+    if level_5_paging_enabled() || !boot_offset.is_aligned(1u64 << 39) {
+        log::warn!("kaslr: not randomizing the physical memory map");
+        return Ok(());
+    }
+
+    let p4 = unsafe { active_level_4_table() };
+
+    let first_slot = usize::from(boot_offset.p4_index());
+    let slots = (first_slot..512)
+        .take_while(|&slot| !p4[slot].is_unused())
+        .count();
+
+    // Pick a base between the end of the bootloader's direct map and the start of the
+    // vmalloc area.
+    let start = (first_slot + slots) * GIB_PER_SLOT;
+    let end = usize::from(super::vmalloc::VMALLOC_START.p4_index()) * GIB_PER_SLOT;
+
+    // The KASAN shadow sits right below the vmalloc area and its level 4 entries are
+    // already in use by the early shadow, so the window has to end before its first slot.
+    #[cfg(feature = "kasan")]
+    let end = {
+        let (shadow_start, _) = super::kasan::shadow_range();
+        end.min(usize::from(shadow_start.p4_index()) * GIB_PER_SLOT)
+    };
+    let size = slots * GIB_PER_SLOT;
+
+    if start + size > end {
+        log::warn!("kaslr: not enough address space to randomize the physical memory map");
+        return Ok(());
+    }
+
+    let base = start + (seed % (end - start - size + 1) as u64) as usize;
+
+    #[cfg(feature = "kasan")]
+    {
+        let (shadow_start, shadow_end) = super::kasan::shadow_range();
+        let map_start = 0xffff_0000_0000_0000 | ((base as u64) << 30);
+        let map_end = map_start + ((size as u64) << 30);
+
+        assert!(
+            map_end <= shadow_start.as_u64() || map_start >= shadow_end.as_u64(),
+            "kaslr: physical memory map overlaps the KASAN shadow"
+        );
+    }
+
+    for gib in 0..size {
+        let src = p4[first_slot + gib / GIB_PER_SLOT].clone();
+        let src_p3: &PageTable = unsafe { &*src.addr().as_hhdm_virt().as_ptr() };
+
+        let dst = &mut p4[(base + gib) / GIB_PER_SLOT];
+
+        if dst.is_unused() {
+            let frame = FRAME_ALLOCATOR
+                .alloc_zeroed(Size4KiB::SIZE as usize)
+                .ok_or(MapToError::FrameAllocationFailed)?;
+
+            dst.set_addr(frame, src.flags());
+        }
+
+        let dst_p3: &mut PageTable = unsafe { &mut *dst.addr().as_hhdm_virt().as_mut_ptr() };
+        dst_p3[(base + gib) % GIB_PER_SLOT] = src_p3[gib % GIB_PER_SLOT].clone();
+    }
+
+    let offset = VirtAddr::new(0xffff_0000_0000_0000 | ((base as u64) << 30));
+
+    unsafe {
+        PHYSICAL_MEMORY_OFFSET = offset;
+    }
+
+    log::debug!("kaslr: physical memory map at {offset:#x}");
+    Ok(())
+}
+
+/// Converts an address in the direct map set up by the bootloader to the same address in
+/// the (possibly randomized) direct map used by the kernel.
+pub fn boot_to_hhdm(addr: VirtAddr) -> VirtAddr {
+    match BOOT_PHYSICAL_MEMORY_OFFSET.get() {
+        Some(boot_offset) => PhysAddr::new(addr - *boot_offset).as_hhdm_virt(),
+        None => addr,
+    }
+}
+
 /// Get a mutable reference to the active level 4 page table.
 #[cfg(target_arch = "x86_64")]
 pub unsafe fn active_level_4_table() -> &'static mut PageTable {
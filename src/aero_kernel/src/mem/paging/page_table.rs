@@ -21,9 +21,11 @@ use core::fmt;
 use core::ops::{Index, IndexMut};
 
 use super::addr::PhysAddr;
-use super::page::{PageSize, PhysFrame, Size4KiB};
+use super::page::{PageSize, PhysFrame, Size2MiB, Size4KiB};
 use super::{FrameAllocator, MapToError, FRAME_ALLOCATOR};
 
+use crate::mem::swap::SwapEntry;
+
 use bitflags::bitflags;
 
 /// The error returned by the `PageTableEntry::frame` method.
@@ -50,6 +52,8 @@ impl PageTableEntry {
     const COUNTER_MASK: u64 = 0x7ff0_0000_0000_0000;
     const COUNTER_SHIFT: u64 = 52;
     const FLAGS_MASK: u64 = 0x8000_0000_0000_01ff;
+    // Set in non-present entries of pages that have been swapped out.
+    const SWAP_MARKER: u64 = PageTableFlags::BIT_9.bits();
 
     /// Creates an unused page table entry.
     pub const fn new() -> Self {
@@ -81,8 +85,15 @@ impl PageTableEntry {
 
                 if count == 0 {
                     // No references to this frame, deallocate it.
-                    FRAME_ALLOCATOR
-                        .deallocate_frame(PhysFrame::<Size4KiB>::containing_address(self.addr()));
+                    if self.flags().contains(PageTableFlags::HUGE_PAGE) {
+                        FRAME_ALLOCATOR.deallocate_frame(
+                            PhysFrame::<Size2MiB>::containing_address(self.addr()),
+                        );
+                    } else {
+                        FRAME_ALLOCATOR.deallocate_frame(
+                            PhysFrame::<Size4KiB>::containing_address(self.addr()),
+                        );
+                    }
 
                     return true;
                 }
@@ -170,6 +181,22 @@ impl PageTableEntry {
         self.entry &= !Self::FLAGS_MASK;
         self.entry |= flags.bits();
     }
+
+    /// Returns the swap entry stored in this entry, if the page it mapped has been swapped out.
+    pub fn swap_entry(&self) -> Option<SwapEntry> {
+        if self.entry & (PageTableFlags::PRESENT.bits() | Self::SWAP_MARKER) == Self::SWAP_MARKER {
+            Some(SwapEntry::from_raw(self.entry))
+        } else {
+            None
+        }
+    }
+
+    /// Replaces this entry with a non-present entry pointing to the provided swap entry.
+    ///
+    /// **Note**: The reference to the previously mapped frame (if any) is *not* dropped.
+    pub fn set_swap_entry(&mut self, swap: SwapEntry) {
+        self.entry = swap.as_raw() | Self::SWAP_MARKER;
+    }
 }
 
 impl fmt::Debug for PageTableEntry {
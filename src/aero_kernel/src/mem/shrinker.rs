@@ -0,0 +1,110 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+//! Shrinkers free up memory held by kernel object caches under memory pressure.
+//!
+//! Caches that can drop objects which are not in use (such as the inode and directory caches,
+//! see [`crate::fs::cache`]) register a [`Shrinker`]. The reclaim path asks every registered
+//! shrinker to free up some of its objects, in proportion to the number of objects it can
+//! free, before resorting to swap or the OOM killer (see [`crate::mem::oom`]).
+
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::utils::sync::Mutex;
+
+/// Maximum number of shrinkers that can be registered.
+const MAX_SHRINKERS: usize = 16;
+
+static SHRINKERS: [AtomicPtr<Shrinker>; MAX_SHRINKERS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_SHRINKERS];
+static SHRINKER_COUNT: AtomicUsize = AtomicUsize::new(0);
+static REGISTER_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct Shrinker {
+    name: &'static str,
+    /// Returns the number of objects that can be freed.
+    count: fn() -> usize,
+    /// Frees up to the provided number of objects. Returns the number of freed objects.
+    scan: fn(usize) -> usize,
+}
+
+impl Shrinker {
+    pub const fn new(name: &'static str, count: fn() -> usize, scan: fn(usize) -> usize) -> Self {
+        Self { name, count, scan }
+    }
+}
+
+/// Registers the provided shrinker. Registering the same shrinker more than once has no
+/// effect.
+pub fn register(shrinker: &'static Shrinker) {
+    let _guard = REGISTER_LOCK.lock_irq();
+    let count = SHRINKER_COUNT.load(Ordering::Acquire);
+    let ptr = shrinker as *const Shrinker as *mut Shrinker;
+
+    if SHRINKERS[..count]
+        .iter()
+        .any(|e| e.load(Ordering::Relaxed) == ptr)
+    {
+        return;
+    }
+
+    assert!(count < MAX_SHRINKERS, "shrinker: too many shrinkers");
+
+    SHRINKERS[count].store(ptr, Ordering::Relaxed);
+    SHRINKER_COUNT.store(count + 1, Ordering::Release);
+
+    log::debug!("shrinker: registered the `{}` shrinker", shrinker.name);
+}
+
+fn shrinkers() -> impl Iterator<Item = &'static Shrinker> {
+    let count = SHRINKER_COUNT.load(Ordering::Acquire);
+
+    SHRINKERS[..count]
+        .iter()
+        // SAFETY: Only `'static` shrinkers are registered.
+        .map(|e| unsafe { &*e.load(Ordering::Relaxed) })
+}
+
+/// Asks the registered shrinkers to free up to `count` objects in total. Each shrinker is
+/// asked to free a share of `count` proportional to the number of objects it can free, so
+/// that large caches are shrunk the most. Returns the number of freed objects.
+///
+/// **Note**: Shrinkers may sleep, so this function must not be called with a spinlock held.
+pub fn shrink_all(count: usize) -> usize {
+    let total = shrinkers()
+        .map(|shrinker| (shrinker.count)())
+        .sum::<usize>();
+
+    if total == 0 {
+        return 0;
+    }
+
+    let freed = shrinkers()
+        .map(|shrinker| {
+            let freeable = (shrinker.count)();
+            let share = count.saturating_mul(freeable).div_ceil(total).min(freeable);
+
+            if share == 0 {
+                0
+            } else {
+                (shrinker.scan)(share)
+            }
+        })
+        .sum::<usize>();
+
+    log::trace!("shrinker: freed {freed} objects (freeable: {total})");
+    freed
+}
@@ -15,13 +15,20 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::alloc::Layout;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicPtr, Ordering};
 
 use intrusive_collections::UnsafeRef;
 
+use crate::arch::cpu_local;
 use crate::mem::paging::*;
+use crate::userland::scheduler::preempt;
 use crate::utils::sync::Mutex;
 
+/// Minimum number of objects that a slab of an object cache holds.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
 #[repr(C)]
 pub struct SlabHeader {
     /// Reference to the slab pool.
@@ -85,6 +92,8 @@ impl SmallSlab {
         }
     }
 
+    // The free-list is stored in the free objects, which are poisoned by KASAN.
+    #[no_sanitize(address)]
     pub fn alloc(&self) -> *mut u8 {
         let mut first_free = self.first_free.lock_irq();
 
@@ -99,6 +108,7 @@ impl SmallSlab {
         }
     }
 
+    #[no_sanitize(address)]
     pub fn dealloc(&self, ptr: *mut u8) {
         assert!(!ptr.is_null());
 
@@ -109,6 +119,7 @@ impl SmallSlab {
         *first_free = new_head;
     }
 
+    #[no_sanitize(address)]
     fn expand(&self) {
         let frame: PhysFrame<Size4KiB> = FRAME_ALLOCATOR.allocate_frame().expect("slab: OOM");
 
@@ -150,9 +161,368 @@ impl SmallSlab {
             let entry = &mut *first_free.add(max * fact);
             *entry = BufCtl::NULL;
         }
+
+        #[cfg(feature = "kasan")]
+        super::kasan::poison(
+            VirtAddr::new(first_free as u64),
+            avaliable_size,
+            super::kasan::REDZONE,
+        );
     }
 
     pub fn size(&self) -> usize {
         self.size
     }
 }
+
+/// Header at the start of every slab of an object cache.
+struct Slab {
+    first_free: BufCtl,
+    /// Number of objects of the slab that are in use.
+    in_use: usize,
+    /// Links of the list of slabs of the cache that have free objects.
+    prev: Option<NonNull<Slab>>,
+    next: Option<NonNull<Slab>>,
+}
+
+/// List of the slabs of an object cache that have free objects.
+struct PartialList(Option<NonNull<Slab>>);
+
+impl PartialList {
+    fn push(&mut self, slab: &mut Slab) {
+        slab.prev = None;
+        slab.next = self.0;
+
+        if let Some(mut head) = self.0 {
+            unsafe { head.as_mut() }.prev = Some(NonNull::from(&mut *slab));
+        }
+
+        self.0 = Some(NonNull::from(slab));
+    }
+
+    fn remove(&mut self, slab: &mut Slab) {
+        match slab.prev {
+            Some(mut prev) => unsafe { prev.as_mut() }.next = slab.next,
+            None => self.0 = slab.next,
+        }
+
+        if let Some(mut next) = slab.next {
+            unsafe { next.as_mut() }.prev = slab.prev;
+        }
+
+        slab.prev = None;
+        slab.next = None;
+    }
+}
+
+unsafe impl Send for PartialList {}
+unsafe impl Sync for PartialList {}
+
+/// Object cache that serves the next allocation made on this CPU with the layout of the
+/// cache. Set by [`SlabCache::alloc_with`].
+#[cpu_local]
+static ACTIVE_CACHE: AtomicPtr<SlabCache> = AtomicPtr::new(core::ptr::null_mut());
+
+/// A cache of fixed-size kernel objects (e.g. inodes or tasks).
+///
+/// Unlike the general purpose size classes, objects are not rounded up to the next power of
+/// two and a slab may span multiple pages, so objects are packed tightly and allocating or
+/// freeing one is a single free-list operation. Owners allocate their objects from the cache
+/// explicitly with [`SlabCache::alloc_with`]. Slabs whose objects have all been freed are
+/// returned to the frame allocator, except for the last one with free objects.
+pub struct SlabCache {
+    name: &'static str,
+    layout: Layout,
+    partial: Mutex<PartialList>,
+}
+
+impl SlabCache {
+    /// Creates a new object cache for objects with the provided `layout`. Use [`arc_layout`]
+    /// for objects that are allocated with `Arc::new`.
+    pub const fn new(name: &'static str, layout: Layout) -> Self {
+        let align = if layout.align() < core::mem::align_of::<BufCtl>() {
+            core::mem::align_of::<BufCtl>()
+        } else {
+            layout.align()
+        };
+
+        let size = align_up(layout.size() as u64, align as u64) as usize;
+
+        Self {
+            name,
+            // SAFETY: `align` is a power of two and `size` is a multiple of it.
+            layout: unsafe { Layout::from_size_align_unchecked(size, align) },
+            partial: Mutex::new(PartialList(None)),
+        }
+    }
+
+    /// Runs `f`, serving the first heap allocation it makes with the layout of the cache from
+    /// the cache. The allocation is served by the general purpose heap instead if the cache
+    /// cannot grow. For example:
+    ///
+    /// ```rust,ignore
+    /// let task = TASK_SLAB.alloc_with(|| Arc::new(task));
+    /// ```
+    pub fn alloc_with<R>(&'static self, f: impl FnOnce() -> R) -> R {
+        // The allocator only looks at the active cache once the CPU-local data is set up.
+        if !cpu_local::is_initialized() {
+            return f();
+        }
+
+        let this = self as *const SlabCache as *mut SlabCache;
+
+        // The active cache belongs to the CPU, so the task must not be moved to another
+        // CPU before it makes the allocation.
+        preempt::disable();
+        ACTIVE_CACHE.store(this, Ordering::SeqCst);
+
+        let result = f();
+
+        // Clear the active cache if `f` did not make an allocation with its layout.
+        let _ = ACTIVE_CACHE.compare_exchange(
+            this,
+            core::ptr::null_mut(),
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        preempt::enable();
+        result
+    }
+
+    /// Returns the size of the objects of the cache.
+    pub fn object_size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns whether an allocation with the provided `layout` is served by this cache.
+    fn matches(&self, layout: &Layout) -> bool {
+        let size = align_up(layout.size() as u64, layout.align() as u64) as usize;
+
+        size != 0
+            && align_up(size as u64, self.layout.align() as u64) as usize == self.layout.size()
+            && layout.align() <= self.layout.align()
+    }
+
+    /// Returns the offset of the first object of a slab, past the slab header.
+    fn header_size(&self) -> usize {
+        align_up(
+            core::mem::size_of::<Slab>() as u64,
+            self.layout.align() as u64,
+        ) as usize
+    }
+
+    /// Returns the size of a slab, which is big enough to hold at least
+    /// [`MIN_OBJECTS_PER_SLAB`] objects.
+    fn slab_size(&self) -> usize {
+        (self.header_size() + self.layout.size() * MIN_OBJECTS_PER_SLAB)
+            .next_power_of_two()
+            .max(Size4KiB::SIZE as usize)
+    }
+
+    #[no_sanitize(address)]
+    fn alloc(&'static self) -> *mut u8 {
+        let mut partial = self.partial.lock_irq();
+
+        let mut slab = match partial.0 {
+            Some(slab) => slab,
+            None => {
+                let Some(mut slab) = self.expand() else {
+                    return core::ptr::null_mut();
+                };
+
+                partial.push(unsafe { slab.as_mut() });
+                slab
+            }
+        };
+
+        let slab = unsafe { slab.as_mut() };
+        let entry = slab.first_free.0.unwrap();
+
+        slab.first_free = BufCtl(unsafe { entry.as_ref() }.0);
+        slab.in_use += 1;
+
+        if slab.first_free.0.is_none() {
+            partial.remove(slab);
+        }
+
+        entry.as_ptr().cast()
+    }
+
+    /// Frees an object allocated from this cache (see [`owner`]).
+    #[no_sanitize(address)]
+    pub fn dealloc(&self, ptr: *mut u8) {
+        assert!(!ptr.is_null());
+
+        let mut partial = self.partial.lock_irq();
+
+        // Slabs are aligned to their size.
+        let slab = align_down(ptr as u64, self.slab_size() as u64) as *mut Slab;
+        let slab = unsafe { &mut *slab };
+
+        let was_full = slab.first_free.0.is_none();
+
+        let mut new_head = BufCtl::from_ptr(ptr.cast());
+        new_head.0 = slab.first_free.0;
+        slab.first_free = new_head;
+        slab.in_use -= 1;
+
+        if was_full {
+            partial.push(slab);
+        }
+
+        // Keep the slab around if it is the only one with free objects, so that allocating
+        // and freeing an object in a loop does not allocate a new slab every time.
+        let only_partial = slab.prev.is_none() && slab.next.is_none();
+
+        if slab.in_use == 0 && !only_partial {
+            partial.remove(slab);
+            self.release(slab);
+        }
+    }
+
+    /// Allocates a new slab and builds the free-list of its objects. Returns [`None`] if the
+    /// frame allocator is out of memory.
+    #[no_sanitize(address)]
+    fn expand(&'static self) -> Option<NonNull<Slab>> {
+        let slab_size = self.slab_size();
+
+        let Some(addr) = FRAME_ALLOCATOR.alloc(slab_size) else {
+            log::warn!("slab: failed to grow the `{}` cache", self.name);
+            return None;
+        };
+
+        // The frames of the slab record the cache they belong to, so that the allocator can
+        // tell the objects of the cache apart when they are freed.
+        for offset in (0..slab_size).step_by(Size4KiB::SIZE as usize) {
+            let Some(frame) = (addr + offset as u64).as_vm_frame() else {
+                FRAME_ALLOCATOR.dealloc(addr, slab_size);
+                return None;
+            };
+
+            frame.set_slab_cache(Some(self));
+        }
+
+        let ptr = addr.as_hhdm_virt().as_mut_ptr::<u8>();
+        let header_size = self.header_size();
+        let count = (slab_size - header_size) / self.layout.size();
+
+        let mut first_free = BufCtl::NULL;
+
+        // Objects are linked in address order.
+        for i in (0..count).rev() {
+            unsafe {
+                let entry = ptr
+                    .add(header_size + i * self.layout.size())
+                    .cast::<BufCtl>();
+                entry.write(BufCtl(first_free.0));
+                first_free = BufCtl::from_ptr(entry);
+            }
+        }
+
+        let slab = ptr.cast::<Slab>();
+
+        unsafe {
+            slab.write(Slab {
+                first_free,
+                in_use: 0,
+                prev: None,
+                next: None,
+            });
+        }
+
+        #[cfg(feature = "kasan")]
+        super::kasan::poison(
+            VirtAddr::new(ptr as u64 + header_size as u64),
+            count * self.layout.size(),
+            super::kasan::REDZONE,
+        );
+
+        NonNull::new(slab)
+    }
+
+    /// Returns the memory of an empty slab to the frame allocator.
+    fn release(&self, slab: &mut Slab) {
+        let slab_size = self.slab_size();
+        let addr = VirtAddr::new(slab as *mut Slab as u64).as_hhdm_phys();
+
+        for offset in (0..slab_size).step_by(Size4KiB::SIZE as usize) {
+            if let Some(frame) = (addr + offset as u64).as_vm_frame() {
+                frame.set_slab_cache(None);
+            }
+        }
+
+        #[cfg(feature = "kasan")]
+        super::kasan::unpoison(addr.as_hhdm_virt(), slab_size);
+
+        FRAME_ALLOCATOR.dealloc(addr, slab_size);
+    }
+}
+
+/// Returns the layout of the allocation made by `Arc::new` for a value of type `T`.
+pub const fn arc_layout<T>() -> Layout {
+    // `ArcInner` is `#[repr(C)]` and holds the strong and weak counters before the value.
+    let align = if core::mem::align_of::<T>() > core::mem::align_of::<usize>() {
+        core::mem::align_of::<T>()
+    } else {
+        core::mem::align_of::<usize>()
+    };
+
+    let offset = align_up(2 * core::mem::size_of::<usize>() as u64, align as u64);
+    let size = align_up(offset + core::mem::size_of::<T>() as u64, align as u64);
+
+    // SAFETY: `align` is a power of two and `size` is a multiple of it.
+    unsafe { Layout::from_size_align_unchecked(size as usize, align) }
+}
+
+/// Serves an allocation with the provided `layout` from the object cache that is active on
+/// this CPU (see [`SlabCache::alloc_with`]), if the layout is the one of the cache.
+pub fn alloc_active(layout: &Layout) -> Option<*mut u8> {
+    if !cpu_local::is_initialized() {
+        return None;
+    }
+
+    let cache = ACTIVE_CACHE.load(Ordering::SeqCst);
+
+    // SAFETY: Only `'static` caches are made active.
+    let cache: &'static SlabCache = unsafe { cache.as_ref()? };
+
+    if !cache.matches(layout) {
+        return None;
+    }
+
+    ACTIVE_CACHE.store(core::ptr::null_mut(), Ordering::SeqCst);
+
+    let ptr = cache.alloc();
+    (!ptr.is_null()).then_some(ptr)
+}
+
+/// Returns the object cache that the heap object at `ptr` was allocated from, if any.
+pub fn owner(ptr: *mut u8) -> Option<&'static SlabCache> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    VirtAddr::new(ptr as u64)
+        .as_hhdm_phys()
+        .as_vm_frame()?
+        .slab_cache()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arc_layout_matches_arc() {
+        #[repr(align(64))]
+        struct Aligned(#[allow(dead_code)] u8);
+
+        assert_eq!(arc_layout::<u8>(), Layout::new::<[usize; 3]>());
+        assert_eq!(arc_layout::<[u64; 5]>(), Layout::new::<[usize; 7]>());
+        assert_eq!(
+            arc_layout::<Aligned>(),
+            Layout::from_size_align(128, 64).unwrap()
+        );
+    }
+}
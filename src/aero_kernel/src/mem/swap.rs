@@ -0,0 +1,330 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Swap space.
+//!
+//! Under memory pressure, private anonymous pages that are not shared with any other address
+//! space are written out to a swap area (a block device or a regular file) and the frames
+//! backing them are freed. The page table entry of a swapped out page is left non-present and
+//! instead records where the page is stored (see [`SwapEntry`]); the page is read back on the
+//! next page fault.
+//!
+//! Swap areas are activated with `swapon(2)` and deactivated with `swapoff(2)`.
+//!
+//! **Note**: Swap files are accessed through the file system, so writing out a page to a swap
+//! file might require allocating memory for the page cache. Swap partitions are accessed
+//! directly.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::SyscallError;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::block::{self, BlockDevice, BlockDeviceInterface, CachedAccess};
+use crate::fs::cache::{DirCacheImpl, DirCacheItem};
+use crate::fs::inode::FileType;
+use crate::fs::path::PathBuf;
+use crate::userland::scheduler;
+use crate::userland::vm::Vm;
+use crate::utils::sync::Mutex;
+
+use super::paging::*;
+
+/// Maximum number of swap areas that can be active at the same time.
+const MAX_SWAP_AREAS: usize = 32;
+
+/// Reclaim is started when the number of free frames drops below this watermark.
+pub const RECLAIM_WATERMARK: usize = 512;
+/// Number of frames that are attempted to be freed at once by the reclaim path.
+pub const RECLAIM_BATCH: usize = 256;
+
+/// Location of a swapped out page; the swap area index and the slot in the area.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SwapEntry(u64);
+
+impl SwapEntry {
+    // Only the bits that are ignored by the CPU in a non-present page table entry are used;
+    // bits 1..=8 for the swap area index and bits 12..=51 for the slot.
+    const AREA_SHIFT: u64 = 1;
+    const AREA_MASK: u64 = 0xff;
+    const SLOT_SHIFT: u64 = 12;
+    const SLOT_MASK: u64 = 0xff_ffff_ffff;
+
+    fn new(area: usize, slot: usize) -> Self {
+        Self(
+            ((area as u64 & Self::AREA_MASK) << Self::AREA_SHIFT)
+                | ((slot as u64 & Self::SLOT_MASK) << Self::SLOT_SHIFT),
+        )
+    }
+
+    /// Creates a swap entry from its page table entry representation. Bits that are not a
+    /// part of the swap entry are ignored.
+    pub fn from_raw(raw: u64) -> Self {
+        Self::new(
+            ((raw >> Self::AREA_SHIFT) & Self::AREA_MASK) as usize,
+            ((raw >> Self::SLOT_SHIFT) & Self::SLOT_MASK) as usize,
+        )
+    }
+
+    /// Returns the page table entry representation of the swap entry.
+    pub fn as_raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the index of the swap area the page is stored in.
+    pub fn area(&self) -> usize {
+        ((self.0 >> Self::AREA_SHIFT) & Self::AREA_MASK) as usize
+    }
+
+    /// Returns the slot in the swap area the page is stored in.
+    pub fn slot(&self) -> usize {
+        ((self.0 >> Self::SLOT_SHIFT) & Self::SLOT_MASK) as usize
+    }
+
+    /// Returns the offset in bytes of the page in the swap area.
+    fn offset(&self) -> usize {
+        self.slot() * Size4KiB::SIZE as usize
+    }
+}
+
+enum SwapBackend {
+    Device(Arc<BlockDevice>),
+    File(DirCacheItem),
+}
+
+impl SwapBackend {
+    fn read(&self, offset: usize, frame: PhysFrame) -> bool {
+        match self {
+            Self::Device(device) => device.read_direct(offset, frame).is_some(),
+            Self::File(file) => file
+                .inode()
+                .read_at(offset, frame.as_slice_mut())
+                .is_ok_and(|size| size == Size4KiB::SIZE as usize),
+        }
+    }
+
+    fn write(&self, offset: usize, frame: PhysFrame) -> bool {
+        match self {
+            Self::Device(device) => device.write_direct(offset, frame).is_some(),
+            Self::File(file) => file
+                .inode()
+                .write_at(offset, frame.as_slice_mut())
+                .is_ok_and(|size| size == Size4KiB::SIZE as usize),
+        }
+    }
+}
+
+struct SwapArea {
+    /// Absolute path to the swap file or device. Used to find the area on `swapoff(2)`.
+    path: PathBuf,
+    backend: SwapBackend,
+    priority: isize,
+    /// Reference count of each of the slots in the area; zero if the slot is free.
+    slots: Mutex<Vec<u16>>,
+    /// Whether pages can be swapped out to this area. Cleared while the area is being
+    /// deactivated.
+    active: AtomicBool,
+}
+
+impl SwapArea {
+    fn alloc_slot(&self) -> Option<usize> {
+        let mut slots = self.slots.lock_irq();
+        let slot = slots.iter().position(|count| *count == 0)?;
+
+        slots[slot] = 1;
+        Some(slot)
+    }
+}
+
+static SWAP_AREAS: Mutex<Vec<Option<Arc<SwapArea>>>> = Mutex::new(Vec::new());
+
+fn area(index: usize) -> Option<Arc<SwapArea>> {
+    SWAP_AREAS.lock_irq().get(index).cloned().flatten()
+}
+
+/// Returns whether there is a swap area that pages can be swapped out to.
+pub fn is_enabled() -> bool {
+    SWAP_AREAS
+        .lock_irq()
+        .iter()
+        .flatten()
+        .any(|area| area.active.load(Ordering::SeqCst))
+}
+
+/// Writes the contents of `frame` out to a free slot in one of the active swap areas (the
+/// ones with the higher priority are preferred). Returns `None` if all of the swap areas are
+/// full or the write failed.
+pub fn swap_out(frame: PhysFrame) -> Option<SwapEntry> {
+    let mut areas = SWAP_AREAS
+        .lock_irq()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, area)| Some((index, area.clone()?)))
+        .filter(|(_, area)| area.active.load(Ordering::SeqCst))
+        .collect::<Vec<_>>();
+
+    areas.sort_by_key(|(_, area)| -area.priority);
+
+    let (entry, area) = areas.into_iter().find_map(|(index, area)| {
+        let slot = area.alloc_slot()?;
+        Some((SwapEntry::new(index, slot), area))
+    })?;
+
+    if !area.backend.write(entry.offset(), frame) {
+        log::warn!("swap: failed to write out page to {}", area.path.as_str());
+
+        free(entry);
+        return None;
+    }
+
+    Some(entry)
+}
+
+/// Reads the page stored at `entry` into `frame`. The swap entry is not released.
+pub fn swap_in(entry: SwapEntry, frame: PhysFrame) -> bool {
+    area(entry.area()).is_some_and(|area| area.backend.read(entry.offset(), frame))
+}
+
+/// Takes an extra reference to the slot of the provided swap entry.
+pub fn dup(entry: SwapEntry) {
+    if let Some(area) = area(entry.area()) {
+        area.slots.lock_irq()[entry.slot()] += 1;
+    }
+}
+
+/// Drops a reference to the slot of the provided swap entry. The slot is freed once there
+/// are no references left.
+pub fn free(entry: SwapEntry) {
+    if let Some(area) = area(entry.area()) {
+        let mut slots = area.slots.lock_irq();
+        slots[entry.slot()] = slots[entry.slot()].saturating_sub(1);
+    }
+}
+
+/// Activates swapping to the provided swap file or block device, with the provided
+/// `priority`.
+pub fn activate(file: DirCacheItem, priority: isize) -> aero_syscall::Result<()> {
+    let metadata = file.inode().metadata()?;
+
+    let (backend, size) = match metadata.file_type() {
+        FileType::File => (SwapBackend::File(file.clone()), metadata.size),
+        FileType::Device => {
+            let device = block::block_device_by_name(&file.name()).ok_or(SyscallError::EINVAL)?;
+            let size = device.block_count() * device.block_size();
+
+            (SwapBackend::Device(device), size)
+        }
+
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    let pages = size / Size4KiB::SIZE as usize;
+
+    if pages == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let path = file.absolute_path();
+    let mut areas = SWAP_AREAS.lock_irq();
+
+    if areas.iter().flatten().any(|area| area.path == path) {
+        return Err(SyscallError::EBUSY);
+    }
+
+    log::info!(
+        "swap: adding {} ({} KiB, priority={priority})",
+        path.as_str(),
+        pages * 4
+    );
+
+    let area = Some(Arc::new(SwapArea {
+        path,
+        backend,
+        priority,
+        slots: Mutex::new(alloc::vec![0; pages]),
+        active: AtomicBool::new(true),
+    }));
+
+    if let Some(free) = areas.iter_mut().find(|area| area.is_none()) {
+        *free = area;
+    } else if areas.len() < MAX_SWAP_AREAS {
+        areas.push(area);
+    } else {
+        return Err(SyscallError::EPERM);
+    }
+
+    Ok(())
+}
+
+/// Deactivates swapping to the provided swap file or block device. All of the pages stored
+/// in the swap area are read back into memory.
+pub fn deactivate(file: &DirCacheItem) -> aero_syscall::Result<()> {
+    let path = file.absolute_path();
+    let (index, area) = SWAP_AREAS
+        .lock_irq()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, area)| Some((index, area.clone()?)))
+        .find(|(_, area)| area.path == path)
+        .ok_or(SyscallError::EINVAL)?;
+
+    // Stop swapping out new pages to the area.
+    area.active.store(false, Ordering::SeqCst);
+
+    // Collect the unique VMs; threads share the VM with their process.
+    let mut vms = Vec::<Arc<Vm>>::new();
+
+    scheduler::get_scheduler().for_each_task(|task| {
+        if !vms.iter().any(|vm| Arc::ptr_eq(vm, task.vm())) {
+            vms.push(task.vm().clone());
+        }
+    });
+
+    for vm in vms {
+        if !vm.swap_in_area(index) {
+            // Not enough memory to bring back all of the pages.
+            area.active.store(true, Ordering::SeqCst);
+            return Err(SyscallError::ENOMEM);
+        }
+    }
+
+    log::info!("swap: removed {}", path.as_str());
+
+    SWAP_AREAS.lock_irq()[index] = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_entry_encoding() {
+        let entry = SwapEntry::new(3, 0xdead);
+
+        assert_eq!(entry.area(), 3);
+        assert_eq!(entry.slot(), 0xdead);
+        assert_eq!(entry.offset(), 0xdead * 4096);
+
+        // The present bit and the bits outside of the swap entry are ignored.
+        let raw = entry.as_raw() | (1 << 9) | (1 << 63);
+
+        assert_eq!(raw & 1, 0);
+        assert_eq!(SwapEntry::from_raw(raw), entry);
+    }
+}
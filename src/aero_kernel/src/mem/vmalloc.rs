@@ -40,6 +40,13 @@ pub(super) const VMALLOC_END: VirtAddr =
 
 static VMALLOC: Once<Mutex<Vmalloc>> = Once::new();
 
+/// Area the loadable kernel modules are mapped in. It is right below the kernel image, so the
+/// modules can reach the kernel with 32-bit RIP-relative relocations.
+const MODULES_START: VirtAddr = VirtAddr::new(0xffffffff70000000);
+const MODULES_MAX_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+
+static MODULES: Once<Mutex<Vmalloc>> = Once::new();
+
 struct VmallocAreaProtected {
     addr: VirtAddr,
     size: usize,
@@ -85,21 +92,19 @@ pub(super) struct Vmalloc {
 }
 
 impl Vmalloc {
-    fn new() -> Self {
+    fn new(start: VirtAddr, size: usize) -> Self {
         let mut this = Self {
             free_list: VecDeque::new(),
         };
 
-        this.free_list
-            .push_back(VmallocArea::new(VMALLOC_START, VMALLOC_MAX_SIZE));
+        this.free_list.push_back(VmallocArea::new(start, size));
 
         this
     }
 
-    pub(super) fn alloc(&mut self, npages: usize) -> Option<VirtAddr> {
-        // +1: area for the guard page.
-        let size_bytes = (npages + 1) * Size4KiB::SIZE as usize;
-
+    /// Reserves `size_bytes` of virtual address space from the free list, without mapping
+    /// it.
+    fn reserve(&mut self, size_bytes: usize) -> Option<VirtAddr> {
         let (i, area) = self
             .free_list
             .iter()
@@ -120,22 +125,43 @@ impl Vmalloc {
             self.free_list.remove(i);
         }
 
-        // subtract the size of the guard page since we are not required to allocate
-        // a frame for that area.
-        let size_bytes = size_bytes - Size4KiB::SIZE as usize;
+        Some(address)
+    }
 
-        let mut address_space = AddressSpace::this();
-        let mut offset_table = address_space.offset_page_table();
+    /// Returns `size` bytes of virtual address space, starting at `addr`, back to the free
+    /// list.
+    fn release(&mut self, addr: VirtAddr, size: usize) {
+        // check if this block can be merged into another block.
+        let merge = self
+            .free_list
+            .iter()
+            .find(|area| addr + size == area.protected.lock().addr);
 
-        let page_range = {
-            let start_page: Page = Page::containing_address(address);
-            let end_page = Page::containing_address(address + size_bytes);
+        if let Some(merge) = merge {
+            let mut merge = merge.protected.lock();
 
-            Page::range(start_page, end_page)
-        };
+            merge.addr = addr;
+            merge.size += size;
+        } else {
+            // We add it to the back of the free list since, its more likely
+            // to find larger free areas in the front of the list.
+            self.free_list.push_back(VmallocArea::new(addr, size));
+        }
+    }
+
+    fn page_range(addr: VirtAddr, npages: usize) -> PageRange {
+        let start_page: Page = Page::containing_address(addr);
+        let end_page = Page::containing_address(addr + npages * Size4KiB::SIZE as usize);
+
+        Page::range(start_page, end_page)
+    }
+
+    fn map_pages(addr: VirtAddr, npages: usize) {
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
 
         // map the pages at the allocated address.
-        for page in page_range {
+        for page in Self::page_range(addr, npages) {
             let frame: PhysFrame<Size4KiB> = FRAME_ALLOCATOR
                 .allocate_frame()
                 .expect("vmalloc: physical memory exhausted");
@@ -151,53 +177,151 @@ impl Vmalloc {
             .flush();
         }
 
+        #[cfg(feature = "kasan")]
+        super::kasan::map_shadow(addr, npages * Size4KiB::SIZE as usize);
+    }
+
+    fn unmap_pages(addr: VirtAddr, npages: usize) {
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
+
+        for page in Self::page_range(addr, npages) {
+            // unmap the page at the address which in turn will deallocate
+            // the frame (refcnt == 0).
+            offset_table.unmap(page).unwrap().1.flush();
+        }
+    }
+
+    pub(super) fn alloc(&mut self, npages: usize) -> Option<VirtAddr> {
+        // +1: area for the guard page. We are not required to allocate a frame for
+        // that area.
+        let address = self.reserve((npages + 1) * Size4KiB::SIZE as usize)?;
+
+        Self::map_pages(address, npages);
         Some(address)
     }
 
     pub(super) fn dealloc(&mut self, addr: VirtAddr, npages: usize) {
         // +1: area for the guard page.
-        let size = (npages + 1) * Size4KiB::SIZE as usize;
+        self.release(addr, (npages + 1) * Size4KiB::SIZE as usize);
 
-        // check if this block can be merged into another block.
-        let merge = self
-            .free_list
-            .iter()
-            .find(|area| addr + size == area.protected.lock().addr);
+        // only unmap the allocated pages, since the guard page is not mapped.
+        Self::unmap_pages(addr, npages);
+    }
 
-        if let Some(merge) = merge {
-            let mut merge = merge.protected.lock();
+    /// Allocates `npages` with an unmapped guard page on *both* sides of the allocation
+    /// and returns the address of the first mapped page.
+    fn alloc_guarded(&mut self, npages: usize) -> Option<VirtAddr> {
+        // +2: area for the leading and trailing guard pages.
+        let address = self.reserve((npages + 2) * Size4KiB::SIZE as usize)? + Size4KiB::SIZE;
 
-            merge.addr = addr;
-            merge.size += size;
-        } else {
-            // We add it to the back of the free list since, its more likely
-            // to find larger free areas in the front of the list.
-            self.free_list.push_back(VmallocArea::new(addr, size));
-        }
+        Self::map_pages(address, npages);
+        Some(address)
+    }
 
-        // subtract the size of the guard page since its not mapped.
-        let size = size - Size4KiB::SIZE as usize;
+    fn dealloc_guarded(&mut self, addr: VirtAddr, npages: usize) {
+        self.release(
+            addr - Size4KiB::SIZE,
+            (npages + 2) * Size4KiB::SIZE as usize,
+        );
 
-        let mut address_space = AddressSpace::this();
-        let mut offset_table = address_space.offset_page_table();
+        Self::unmap_pages(addr, npages);
+    }
+}
 
-        let page_range = {
-            let start_page: Page = Page::containing_address(addr);
-            let end_page = Page::containing_address(addr + size);
+/// A kernel stack allocated from the [`vmalloc`] area.
+///
+/// The stack is surrounded by unmapped guard pages, so overflowing (or underflowing) the
+/// stack results in a page fault instead of silently corrupting the adjacent memory.
+pub struct KernelStack {
+    base: VirtAddr,
+    npages: usize,
+}
 
-            Page::range(start_page, end_page)
-        };
+impl KernelStack {
+    /// Allocates a new, zeroed, kernel stack of `npages` pages.
+    pub fn new(npages: usize) -> Option<Self> {
+        let base = get_vmalloc().alloc_guarded(npages)?;
 
-        for page in page_range {
-            // unmap the page at the address which in turn will deallocate
-            // the frame (refcnt == 0).
-            offset_table.unmap(page).unwrap().1.flush();
+        unsafe {
+            base.as_mut_ptr::<u8>()
+                .write_bytes(0, npages * Size4KiB::SIZE as usize);
         }
+
+        Some(Self { base, npages })
+    }
+
+    /// Returns the address of the top of the stack.
+    pub fn top(&self) -> VirtAddr {
+        self.base + self.npages * Size4KiB::SIZE as usize
+    }
+
+    /// Returns `true` if the provided address lies in the guard page below the stack.
+    pub fn is_guard_page(&self, addr: VirtAddr) -> bool {
+        addr < self.base && addr >= self.base - Size4KiB::SIZE
+    }
+
+    /// Marks the whole stack as accessible. This has to be done when the frames on the stack
+    /// are abandoned without returning (e.g. on exec), as their redzones would stay poisoned.
+    #[cfg(feature = "kasan")]
+    pub fn unpoison(&self) {
+        super::kasan::unpoison(self.base, self.npages * Size4KiB::SIZE as usize);
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        get_vmalloc().dealloc_guarded(self.base, self.npages);
     }
 }
 
 pub fn init() {
-    VMALLOC.call_once(|| Mutex::new(Vmalloc::new()));
+    // Make sure that the level 3 table covering the vmalloc area exists before any other
+    // address space is created, since the kernel half of the level 4 table is copied into
+    // each new address space. Otherwise, areas allocated afterwards would not be visible in
+    // those address spaces.
+    let p4 = unsafe { active_level_4_table() };
+    let entry = &mut p4[VMALLOC_START.p4_index()];
+
+    if entry.is_unused() {
+        let frame = FRAME_ALLOCATOR
+            .alloc_zeroed(Size4KiB::SIZE as usize)
+            .expect("vmalloc: physical memory exhausted");
+
+        entry.set_addr(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+
+    VMALLOC.call_once(|| Mutex::new(Vmalloc::new(VMALLOC_START, VMALLOC_MAX_SIZE)));
+
+    // NOTE: The modules area shares the level 4 entry of the kernel image, which always exists.
+    MODULES.call_once(|| Mutex::new(Vmalloc::new(MODULES_START, MODULES_MAX_SIZE)));
+}
+
+/// Allocates `npages` of zeroed memory for the image of a loadable kernel module. The memory is
+/// writable and executable.
+pub fn module_alloc(npages: usize) -> Option<VirtAddr> {
+    let address = MODULES
+        .get()
+        .expect("module_alloc: not initialized")
+        .lock_irq()
+        .alloc(npages)?;
+
+    unsafe {
+        address
+            .as_mut_ptr::<u8>()
+            .write_bytes(0, npages * Size4KiB::SIZE as usize);
+    }
+
+    Some(address)
+}
+
+/// Frees the memory of a module image allocated with [`module_alloc`].
+pub fn module_free(address: VirtAddr, npages: usize) {
+    MODULES
+        .get()
+        .expect("module_free: not initialized")
+        .lock_irq()
+        .dealloc(address, npages);
 }
 
 /// ## Panics
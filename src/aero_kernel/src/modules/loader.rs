@@ -0,0 +1,548 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Loader of the loadable kernel modules.
+//!
+//! A module is an x86-64 ELF relocatable object. Its allocated sections are laid out in memory
+//! allocated from the modules area, which is right below the kernel image, and its relocations
+//! are applied against its own symbols and the symbols exported by the kernel (see
+//! [`find_symbol`]). Modules should be built position independent; references to the kernel
+//! data and functions are then either RIP-relative or go through the GOT, which the loader
+//! creates for the module.
+//!
+//! ## Notes
+//! * Modules cannot reference the symbols of other modules.
+//! * A module without an exit function cannot be unloaded.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use xmas_elf::header::{Machine, Type};
+use xmas_elf::sections::{SectionData, ShType, SHF_ALLOC, SHN_ABS, SHN_UNDEF};
+use xmas_elf::symbol_table::Entry;
+use xmas_elf::ElfFile;
+
+use crate::mem::paging::*;
+use crate::mem::{module_alloc, module_free};
+use crate::utils::sync::BMutex;
+
+use super::{find_symbol, Module, ModuleExit};
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_32: u32 = 10;
+const R_X86_64_32S: u32 = 11;
+const R_X86_64_PC64: u32 = 24;
+const R_X86_64_GOTPCRELX: u32 = 41;
+const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+/// Size of an ELF64 section header.
+const SECTION_HEADER_SIZE: u64 = 64;
+/// Size of an ELF64 symbol and of an ELF64 relocation with addend.
+const SYMBOL_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ModuleError {
+    /// The image is not a valid x86-64 ELF relocatable object.
+    InvalidImage,
+    /// The module references a symbol that is not exported by the kernel.
+    UnknownSymbol,
+    /// The module uses an unsupported relocation or the target of a relocation is out of range.
+    BadRelocation,
+    OutOfMemory,
+    /// A module with the same name is already loaded.
+    Exists,
+    NotFound,
+    /// The module does not have an exit function, so it cannot be unloaded.
+    Busy,
+}
+
+struct LoadedModule {
+    base: VirtAddr,
+    npages: usize,
+    exits: Vec<ModuleExit>,
+}
+
+// NOTE: The init and exit functions of the modules are called with the lock held, as they are
+// allowed to sleep and loading the same module twice must not be possible.
+static MODULES: BMutex<BTreeMap<String, LoadedModule>> = BMutex::new(BTreeMap::new());
+
+/// Checks that the section header table and the contents of every section lie within the
+/// file, and that the symbol tables and relocations are made of whole entries. The ELF
+/// structures are sliced out of the file without any bounds checks, so this has to be done
+/// before any of them is accessed.
+fn validate(elf: &ElfFile) -> Result<(), ModuleError> {
+    let len = elf.input.len() as u64;
+    let count = elf.header.pt2.sh_count() as u64;
+
+    let table_end = count
+        .checked_mul(SECTION_HEADER_SIZE)
+        .and_then(|size| size.checked_add(elf.header.pt2.sh_offset()))
+        .ok_or(ModuleError::InvalidImage)?;
+
+    if elf.header.pt2.sh_entry_size() as u64 != SECTION_HEADER_SIZE || table_end > len {
+        return Err(ModuleError::InvalidImage);
+    }
+
+    if elf.header.pt2.sh_str_index() as u64 >= count {
+        return Err(ModuleError::InvalidImage);
+    }
+
+    let shstrtab = elf
+        .section_header(elf.header.pt2.sh_str_index())
+        .map_err(|_| ModuleError::InvalidImage)?;
+
+    if shstrtab.get_type() != Ok(ShType::StrTab) {
+        return Err(ModuleError::InvalidImage);
+    }
+
+    for index in 0..count as u16 {
+        let section = elf
+            .section_header(index)
+            .map_err(|_| ModuleError::InvalidImage)?;
+
+        let kind = section.get_type().map_err(|_| ModuleError::InvalidImage)?;
+
+        if section.name() as u64 >= shstrtab.size() {
+            return Err(ModuleError::InvalidImage);
+        }
+
+        if kind == ShType::NoBits {
+            continue;
+        }
+
+        let end = section
+            .offset()
+            .checked_add(section.size())
+            .ok_or(ModuleError::InvalidImage)?;
+
+        if end > len {
+            return Err(ModuleError::InvalidImage);
+        }
+
+        // The names are read up to the NUL terminator, which has to be in the table.
+        if kind == ShType::StrTab {
+            let data = section.raw_data(elf);
+
+            if data.last() != Some(&0) || !data.is_ascii() {
+                return Err(ModuleError::InvalidImage);
+            }
+
+            continue;
+        }
+
+        let entry_size = match kind {
+            ShType::SymTab => SYMBOL_SIZE,
+            ShType::Rela => RELA_SIZE,
+            // Relocations without addend are not used on x86-64.
+            ShType::Rel => return Err(ModuleError::InvalidImage),
+            _ => continue,
+        };
+
+        if section.size() % entry_size != 0 || section.link() as u64 >= count {
+            return Err(ModuleError::InvalidImage);
+        }
+    }
+
+    Ok(())
+}
+
+/// Memory image of a module being loaded.
+struct Image<'a> {
+    elf: ElfFile<'a>,
+    base: VirtAddr,
+    npages: usize,
+    /// Address of each of the sections of the ELF file, if allocated.
+    sections: Vec<Option<usize>>,
+    /// Address of the next free entry of the GOT.
+    got: usize,
+    /// Entries of the GOT that were already created, by symbol address.
+    got_entries: BTreeMap<usize, usize>,
+}
+
+impl<'a> Image<'a> {
+    fn new(elf: ElfFile<'a>) -> Result<Self, ModuleError> {
+        let count = elf.header.pt2.sh_count();
+        let mut offsets = Vec::with_capacity(count as usize);
+        let mut size = 0;
+        let mut got_size = 0;
+
+        for index in 0..count {
+            let section = elf
+                .section_header(index)
+                .map_err(|_| ModuleError::InvalidImage)?;
+
+            if let Ok(SectionData::Rela64(relocations)) = section.get_data(&elf) {
+                got_size += relocations
+                    .iter()
+                    .filter(|rela| is_got_relocation(rela.get_type()))
+                    .count()
+                    * core::mem::size_of::<usize>();
+            }
+
+            if section.flags() & SHF_ALLOC == 0 || section.size() == 0 {
+                offsets.push(None);
+                continue;
+            }
+
+            let align = core::cmp::max(section.align() as usize, 1);
+
+            if !align.is_power_of_two() || align > Size4KiB::SIZE as usize {
+                return Err(ModuleError::InvalidImage);
+            }
+
+            size = align_up(size as u64, align as u64) as usize;
+            offsets.push(Some(size));
+            size = size
+                .checked_add(section.size() as usize)
+                .ok_or(ModuleError::InvalidImage)?;
+        }
+
+        // The GOT goes after the sections.
+        let got_offset = align_up(size as u64, 8) as usize;
+        size = got_offset + got_size;
+
+        let npages = align_up(size as u64, Size4KiB::SIZE) as usize / Size4KiB::SIZE as usize;
+        let base = module_alloc(npages.max(1)).ok_or(ModuleError::OutOfMemory)?;
+
+        let mut image = Self {
+            elf,
+            base,
+            npages: npages.max(1),
+            sections: Vec::new(),
+            got: base.as_u64() as usize + got_offset,
+            got_entries: BTreeMap::new(),
+        };
+
+        image.sections = offsets
+            .iter()
+            .map(|offset| offset.map(|offset| base.as_u64() as usize + offset))
+            .collect();
+
+        Ok(image)
+    }
+
+    /// Copies the contents of the sections into the image. The `NOBITS` sections are left
+    /// zeroed.
+    fn copy_sections(&self) -> Result<(), ModuleError> {
+        for (index, address) in self.sections.iter().enumerate() {
+            let Some(address) = address else {
+                continue;
+            };
+
+            let section = self
+                .elf
+                .section_header(index as u16)
+                .map_err(|_| ModuleError::InvalidImage)?;
+
+            if section.get_type() == Ok(ShType::NoBits) {
+                continue;
+            }
+
+            // The section was checked to lie within the file by `validate`.
+            let data = section.raw_data(&self.elf);
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(data.as_ptr(), *address as *mut u8, data.len());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the address of the symbol `index` of the symbol table `symtab`.
+    fn symbol_address(&self, symtab: u32, index: u32) -> Result<usize, ModuleError> {
+        let section = self
+            .elf
+            .section_header(symtab as u16)
+            .map_err(|_| ModuleError::InvalidImage)?;
+
+        let Ok(SectionData::SymbolTable64(symbols)) = section.get_data(&self.elf) else {
+            return Err(ModuleError::InvalidImage);
+        };
+
+        let symbol = symbols
+            .get(index as usize)
+            .ok_or(ModuleError::InvalidImage)?;
+
+        match symbol.shndx() {
+            SHN_UNDEF => {
+                let strtab = self
+                    .elf
+                    .section_header(section.link() as u16)
+                    .map_err(|_| ModuleError::InvalidImage)?;
+
+                if strtab.get_type() != Ok(ShType::StrTab)
+                    || symbol.name() as u64 >= strtab.size()
+                {
+                    return Err(ModuleError::InvalidImage);
+                }
+
+                let name = symbol
+                    .get_name(&self.elf)
+                    .map_err(|_| ModuleError::InvalidImage)?;
+
+                find_symbol(name).ok_or_else(|| {
+                    log::warn!("module: unknown symbol `{name}`");
+                    ModuleError::UnknownSymbol
+                })
+            }
+
+            SHN_ABS => Ok(symbol.value() as usize),
+
+            shndx => self
+                .sections
+                .get(shndx as usize)
+                .copied()
+                .flatten()
+                .map(|base| base + symbol.value() as usize)
+                .ok_or(ModuleError::InvalidImage),
+        }
+    }
+
+    /// Returns the address of the GOT entry of the provided symbol address, creating it if
+    /// required.
+    fn got_entry(&mut self, symbol: usize) -> usize {
+        if let Some(entry) = self.got_entries.get(&symbol) {
+            return *entry;
+        }
+
+        let entry = self.got;
+        self.got += core::mem::size_of::<usize>();
+
+        unsafe { (entry as *mut usize).write(symbol) };
+
+        self.got_entries.insert(symbol, entry);
+        entry
+    }
+
+    fn relocate(&mut self) -> Result<(), ModuleError> {
+        for index in 0..self.sections.len() {
+            let section = self
+                .elf
+                .section_header(index as u16)
+                .map_err(|_| ModuleError::InvalidImage)?;
+
+            let Ok(SectionData::Rela64(relocations)) = section.get_data(&self.elf) else {
+                continue;
+            };
+
+            // Relocations of sections that are not loaded (e.g. debug information) are not
+            // applied.
+            let Some(target) = self
+                .sections
+                .get(section.info() as usize)
+                .copied()
+                .flatten()
+            else {
+                continue;
+            };
+
+            let target_size = self
+                .elf
+                .section_header(section.info() as u16)
+                .map_err(|_| ModuleError::InvalidImage)?
+                .size();
+
+            for rela in relocations {
+                let width = match rela.get_type() {
+                    R_X86_64_NONE => continue,
+                    R_X86_64_64 | R_X86_64_PC64 => 8,
+                    _ => 4,
+                };
+
+                // The place has to be within the target section.
+                if rela
+                    .get_offset()
+                    .checked_add(width)
+                    .map_or(true, |end| end > target_size)
+                {
+                    log::warn!("module: relocation offset is out of the target section");
+                    return Err(ModuleError::BadRelocation);
+                }
+
+                let place = target + rela.get_offset() as usize;
+                let addend = rela.get_addend() as i64;
+
+                let symbol =
+                    self.symbol_address(section.link(), rela.get_symbol_table_index())? as i64;
+
+                let pc_relative = |value: i64| {
+                    i32::try_from(value - place as i64).map_err(|_| {
+                        log::warn!("module: relocation at {place:#x} is out of range");
+                        ModuleError::BadRelocation
+                    })
+                };
+
+                unsafe {
+                    match rela.get_type() {
+                        R_X86_64_64 => (place as *mut i64).write_unaligned(symbol + addend),
+                        R_X86_64_PC64 => {
+                            (place as *mut i64).write_unaligned(symbol + addend - place as i64)
+                        }
+
+                        R_X86_64_PC32 | R_X86_64_PLT32 => {
+                            (place as *mut i32).write_unaligned(pc_relative(symbol + addend)?)
+                        }
+
+                        R_X86_64_32 => {
+                            let value = u32::try_from(symbol + addend)
+                                .map_err(|_| ModuleError::BadRelocation)?;
+                            (place as *mut u32).write_unaligned(value)
+                        }
+
+                        R_X86_64_32S => {
+                            let value = i32::try_from(symbol + addend)
+                                .map_err(|_| ModuleError::BadRelocation)?;
+                            (place as *mut i32).write_unaligned(value)
+                        }
+
+                        kind if is_got_relocation(kind) => {
+                            let entry = self.got_entry(symbol as usize) as i64;
+                            (place as *mut i32).write_unaligned(pc_relative(entry + addend)?)
+                        }
+
+                        kind => {
+                            log::warn!("module: unsupported relocation type {kind}");
+                            return Err(ModuleError::BadRelocation);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the entries of the section `name` of the image.
+    fn entries<T: Clone>(&self, name: &str) -> Vec<T> {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter_map(|(index, address)| {
+                let section = self.elf.section_header(index as u16).ok()?;
+
+                if section.get_name(&self.elf) != Ok(name) {
+                    return None;
+                }
+
+                let count = section.size() as usize / core::mem::size_of::<T>();
+                let entries =
+                    unsafe { core::slice::from_raw_parts((*address)? as *const T, count) };
+
+                Some(entries.to_vec())
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+fn is_got_relocation(kind: u32) -> bool {
+    matches!(
+        kind,
+        R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX
+    )
+}
+
+/// Loads the module `name` from the provided ELF relocatable object and calls its init
+/// functions.
+pub fn load(name: &str, data: &[u8]) -> Result<(), ModuleError> {
+    // The ELF structures are read in place, so the file has to be suitably aligned.
+    let mut buffer = alloc::vec![0u64; data.len().div_ceil(8)];
+    let buffer = &mut bytemuck::cast_slice_mut::<u64, u8>(&mut buffer)[..data.len()];
+    buffer.copy_from_slice(data);
+
+    let elf = ElfFile::new(buffer).map_err(|_| ModuleError::InvalidImage)?;
+
+    if elf.header.pt2.type_().as_type() != Type::Relocatable
+        || elf.header.pt2.machine().as_machine() != Machine::X86_64
+    {
+        return Err(ModuleError::InvalidImage);
+    }
+
+    validate(&elf)?;
+
+    let mut modules = MODULES.lock();
+
+    if modules.contains_key(name) {
+        return Err(ModuleError::Exists);
+    }
+
+    let mut image = Image::new(elf)?;
+
+    if let Err(error) = image.copy_sections().and_then(|()| image.relocate()) {
+        module_free(image.base, image.npages);
+        return Err(error);
+    }
+
+    let inits = image.entries::<Module>(".kernel_modules.init");
+    let exits = image.entries::<ModuleExit>(".kernel_modules.exit");
+
+    for module in inits.iter() {
+        let init = unsafe { core::mem::transmute::<*const (), fn()>(module.init) };
+        init();
+    }
+
+    log::info!("module: loaded `{name}` at {:#x}", image.base.as_u64());
+
+    modules.insert(
+        String::from(name),
+        LoadedModule {
+            base: image.base,
+            npages: image.npages,
+            exits,
+        },
+    );
+
+    Ok(())
+}
+
+/// Calls the exit functions of the module `name` and unloads it.
+pub fn unload(name: &str) -> Result<(), ModuleError> {
+    let mut modules = MODULES.lock();
+    let module = modules.get(name).ok_or(ModuleError::NotFound)?;
+
+    if module.exits.is_empty() {
+        return Err(ModuleError::Busy);
+    }
+
+    for exit in module.exits.iter() {
+        let exit = unsafe { core::mem::transmute::<*const (), fn()>(exit.exit) };
+        exit();
+    }
+
+    // UNWRAP: The module was found above and the lock is held.
+    let module = modules.remove(name).unwrap();
+    module_free(module.base, module.npages);
+
+    log::info!("module: unloaded `{name}`");
+    Ok(())
+}
+
+/// Returns the names of the loaded modules and the size of their image in bytes.
+pub fn loaded() -> Vec<(String, usize)> {
+    MODULES
+        .lock()
+        .iter()
+        .map(|(name, module)| (name.clone(), module.npages * Size4KiB::SIZE as usize))
+        .collect()
+}
@@ -0,0 +1,222 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! A kernel module is an object file that contains code that can extend
+//! the kernel functionality at runtime. When a kernel module is no longer needed,
+//! it can be unloaded. Most of the device drivers are used in the form of kernel modules.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! fn hello_init() {}
+//! fn hello_exit() {}
+//!
+//! aero_kernel::module_init!(hello_init);
+//! aero_kernel::module_exit!(hello_exit);
+//! ```
+//!
+//! ## Loadable modules
+//!
+//! Modules can also be built as x86-64 ELF relocatable objects (`.ko`) and loaded at runtime
+//! with `init_module` (see [`loader`]). Such a module registers its init and exit functions
+//! with the same sections as the built-in modules (`.kernel_modules.init` containing
+//! [`Module`] entries and `.kernel_modules.exit` containing [`ModuleExit`] entries) and can
+//! only call the kernel functions exported with [`export_symbol!`](crate::export_symbol).
+//! The exported functions use the C calling convention.
+
+pub mod loader;
+
+use core::alloc::Layout;
+use core::mem::size_of;
+
+use crate::{drivers, extern_sym, fs};
+
+/// Inner helper function to make sure the function provided to the [`module_init`] macro
+/// has a valid function signature. This function returns the passed module init function as
+/// a const void pointer.
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord)]
+#[repr(C)]
+pub enum ModuleType {
+    Block = 0,
+    Other = 1,
+}
+
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct Module {
+    pub init: *const (),
+    pub ty: ModuleType,
+}
+
+unsafe impl Sync for Module {}
+
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct ModuleExit {
+    pub exit: *const (),
+}
+
+unsafe impl Sync for ModuleExit {}
+
+/// A kernel function exported to the loadable modules.
+#[derive(Debug)]
+#[repr(C)]
+pub struct KernelSymbol {
+    pub name: &'static str,
+    pub address: *const (),
+}
+
+unsafe impl Sync for KernelSymbol {}
+
+#[macro_export]
+macro_rules! module_init {
+    ($init_function:expr, $ty:path) => {
+        use $crate::modules::ModuleType;
+
+        #[used]
+        #[link_section = ".kernel_modules.init"]
+        static __MODULE_INIT: $crate::modules::Module = $crate::modules::Module {
+            init: $init_function as *const (),
+            ty: $ty,
+        };
+    };
+}
+
+/// Registers the function that is called when the module is unloaded. The exit functions of the
+/// built-in modules are never called.
+#[macro_export]
+macro_rules! module_exit {
+    ($exit_function:expr) => {
+        #[used]
+        #[link_section = ".kernel_modules.exit"]
+        static __MODULE_EXIT: $crate::modules::ModuleExit = $crate::modules::ModuleExit {
+            exit: $exit_function as *const (),
+        };
+    };
+}
+
+/// Exports the provided function to the loadable modules, which can then reference it by its
+/// name. The function should use the C calling convention.
+#[macro_export]
+macro_rules! export_symbol {
+    ($symbol:ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".kernel_symbols"]
+            static __KERNEL_SYMBOL: $crate::modules::KernelSymbol = $crate::modules::KernelSymbol {
+                name: core::stringify!($symbol),
+                address: $symbol as *const (),
+            };
+        };
+    };
+}
+
+/// Returns the address of the exported kernel function `name`.
+pub fn find_symbol(name: &str) -> Option<usize> {
+    let symbols_start = extern_sym!(__kernel_symbols_start).cast::<KernelSymbol>();
+    let symbols_end = extern_sym!(__kernel_symbols_end).cast::<KernelSymbol>();
+
+    let size = (symbols_end.addr() - symbols_start.addr()) / size_of::<KernelSymbol>();
+    let symbols = unsafe { core::slice::from_raw_parts(symbols_start, size) };
+
+    symbols
+        .iter()
+        .find(|symbol| symbol.name == name)
+        .map(|symbol| symbol.address.addr())
+}
+
+/// Logs the UTF-8 `message` of `len` bytes with the provided level (`1` for errors up to `5`
+/// for traces).
+extern "C" fn aero_log(level: usize, message: *const u8, len: usize) {
+    let message = unsafe { core::slice::from_raw_parts(message, len) };
+    let message = core::str::from_utf8(message).unwrap_or("<invalid utf-8>");
+
+    let level = match level {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        3 => log::Level::Info,
+        4 => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+
+    log::log!(level, "{message}");
+}
+
+/// Allocates `size` bytes aligned to `align` from the kernel heap. Returns NULL on failure.
+extern "C" fn aero_alloc(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if size != 0 => unsafe { alloc::alloc::alloc(layout) },
+        _ => core::ptr::null_mut(),
+    }
+}
+
+/// Frees memory allocated with `aero_alloc`; `size` and `align` must be the ones it was
+/// allocated with.
+extern "C" fn aero_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    if let Ok(layout) = Layout::from_size_align(size, align) {
+        unsafe { alloc::alloc::dealloc(ptr, layout) }
+    }
+}
+
+/// Returns the time since boot in nanoseconds.
+extern "C" fn aero_uptime_ns() -> u64 {
+    crate::userland::scheduler::hrtimer::now()
+}
+
+crate::export_symbol!(aero_log);
+crate::export_symbol!(aero_alloc);
+crate::export_symbol!(aero_dealloc);
+crate::export_symbol!(aero_uptime_ns);
+
+/// This function is responsible for initializing all of the kernel modules. Since currently
+/// we cannot read the ext2 root filesystem, we link all of the kernel modules into the kernel
+/// itself (this is temporary and modules will be loaded from the filesystem in the future).
+pub(crate) fn init() {
+    let modules_start = extern_sym!(__kernel_modules_start).cast::<Module>();
+    let modules_end = extern_sym!(__kernel_modules_end).cast::<Module>();
+
+    let size = (modules_end.addr() - modules_start.addr()) / size_of::<Module>();
+    let modules = unsafe { core::slice::from_raw_parts(modules_start, size) };
+
+    unsafe {
+        // TODO: refactor this out
+        let mut modules = modules.to_vec();
+        modules.sort_by(|e, a| e.ty.cmp(&a.ty));
+
+        let mut launched_fs = false;
+
+        for module in modules {
+            log::debug!("{module:?} {launched_fs}");
+
+            if module.ty != ModuleType::Block && !launched_fs {
+                let mut address_space = crate::mem::AddressSpace::this();
+                let mut offset_table = address_space.offset_page_table();
+
+                #[cfg(target_arch = "x86_64")]
+                drivers::pci::init(&mut offset_table);
+                log::info!("loaded PCI driver");
+
+                fs::block::launch().unwrap();
+                launched_fs = true;
+            }
+
+            let init = core::mem::transmute::<*const (), fn() -> ()>(module.init);
+            init();
+        }
+    }
+}
@@ -18,20 +18,23 @@
 //! Address Resolution Protocol
 
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use spin::{Once, RwLock};
 
-use crate::net::default_device;
-use crate::net::shim::PacketSend;
+use crate::utils::dma::DmaAllocator;
 
 use crabnet::data_link::{Arp, ArpAddress, ArpHardwareType, ArpOpcode, Eth, EthType, MacAddr};
 use crabnet::network::Ipv4Addr;
+use crabnet::IntoBoxedBytes;
 
-use super::RawPacket;
+use super::{NetworkDevice, RawPacket};
 
 enum Status {
     Resolved,
-    Pending(Vec<RawPacket>),
+    /// The packets waiting for the address to be resolved and the device to send them
+    /// through.
+    Pending(Arc<NetworkDevice>, Vec<RawPacket>),
 }
 
 struct Entry {
@@ -56,18 +59,15 @@ impl Cache {
         if let Some(entry) = self.0.get_mut(&ip) {
             let status = core::mem::replace(&mut entry.status, Status::Resolved);
 
-            if let Status::Pending(queue) = status {
+            if let Status::Pending(device, queue) = status {
                 entry.mac = mac;
                 entry.status = Status::Resolved;
 
                 for mut packet in queue {
                     log::trace!("[ ARP ] (!!) Sending queued packed to {ip:?} {mac:?}");
 
-                    // FIXME: make this cleaner
-                    let eth = unsafe { &mut *packet.as_mut_ptr().cast::<Eth>() };
-                    eth.dest_mac = mac;
-
-                    super::default_device().send(packet);
+                    set_dest_mac(&mut packet, mac);
+                    device.send(packet);
                 }
             }
         } else {
@@ -75,14 +75,14 @@ impl Cache {
         }
     }
 
-    fn request(&mut self, ip: Ipv4Addr, packet: RawPacket) {
+    fn request(&mut self, device: &Arc<NetworkDevice>, ip: Ipv4Addr, packet: RawPacket) {
         assert!(ip != Ipv4Addr::LOOPBACK);
 
         if self.0.get_mut(&ip).is_some() {
             todo!()
         } else {
             let queue = alloc::vec![packet];
-            let entry = Entry::new(MacAddr::NULL, Status::Pending(queue));
+            let entry = Entry::new(MacAddr::NULL, Status::Pending(device.clone(), queue));
 
             self.0.insert(ip, entry);
         }
@@ -172,7 +172,8 @@ pub fn init() {
 //     }
 // }
 
-pub fn do_recv(arp: &Arp) {
+/// Handles the ARP packet received on `device`.
+pub fn do_recv(device: &NetworkDevice, arp: &Arp) {
     CACHE
         .get()
         .as_ref()
@@ -180,18 +181,20 @@ pub fn do_recv(arp: &Arp) {
         .write()
         .insert(arp.src_ip(), arp.src_mac());
 
-    let device = default_device();
-
     if arp.opcode() == ArpOpcode::Request && arp.dest_ip() == device.ip() {
         let addr = ArpAddress::new(arp.src_mac(), arp.src_ip());
-        let reply_arp = make_arp(ArpOpcode::Reply, addr);
+        let reply_arp = make_arp(device, ArpOpcode::Reply, addr);
 
-        reply_arp.send();
+        send_arp(device, reply_arp);
     }
 }
 
-pub fn request_ip(target: Ipv4Addr, to: RawPacket) {
-    let arp = make_arp(ArpOpcode::Request, ArpAddress::new(MacAddr::NULL, target));
+pub fn request_ip(device: &Arc<NetworkDevice>, target: Ipv4Addr, to: RawPacket) {
+    let arp = make_arp(
+        device,
+        ArpOpcode::Request,
+        ArpAddress::new(MacAddr::NULL, target),
+    );
 
     log::debug!("[ ARP ] (!!) Sending request for {target:?}");
 
@@ -200,13 +203,43 @@ pub fn request_ip(target: Ipv4Addr, to: RawPacket) {
         .as_ref()
         .expect("arp: cache not initialized")
         .write()
-        .request(target, to);
+        .request(device, target, to);
+
+    send_arp(device, arp);
+}
+
+/// Sends the link-layer `packet` through `device` to the neighbour with the provided IP
+/// address, resolving its MAC address first if it is not cached. Point-to-point devices have
+/// no link-layer addresses, so the packet is sent as is.
+pub fn send(device: &Arc<NetworkDevice>, next_hop: Ipv4Addr, mut packet: RawPacket) {
+    if device.is_point_to_point() {
+        device.send(packet);
+        return;
+    }
+
+    if let Some(addr) = get(next_hop) {
+        set_dest_mac(&mut packet, addr);
+        device.send(packet);
+    } else {
+        request_ip(device, next_hop, packet);
+    }
+}
+
+fn set_dest_mac(packet: &mut RawPacket, mac: MacAddr) {
+    // FIXME: make this cleaner
+    let eth = unsafe { &mut *packet.as_mut_ptr().cast::<Eth>() };
+    eth.dest_mac = mac;
+}
+
+fn send_arp(device: &NetworkDevice, arp: Arp) {
+    let eth = Eth::new(MacAddr::NULL, MacAddr::BROADCAST, EthType::Arp)
+        .set_dest_mac(arp.dest_mac())
+        .set_src_mac(device.mac());
 
-    arp.send();
+    device.send((eth / arp).into_boxed_bytes_in(DmaAllocator));
 }
 
-fn make_arp(opcode: ArpOpcode, dest_addr: ArpAddress) -> Arp {
-    let device = default_device();
+fn make_arp(device: &NetworkDevice, opcode: ArpOpcode, dest_addr: ArpAddress) -> Arp {
     let src_addr = ArpAddress::new(device.mac(), device.ip());
 
     Arp::new(
@@ -0,0 +1,388 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Internet Control Message Protocol (RFC 792).
+//!
+//! An ICMP error is sent back for each datagram that can not be delivered to a local protocol or
+//! socket (see [`send_error`]). The errors received for datagrams sent from a local UDP socket
+//! are delivered to that socket (see [`udp::on_error`]).
+//!
+//! **Note**: Time exceeded errors are only ever received, as packets are not forwarded.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use crabnet::data_link::MacAddr;
+use crabnet::network::Ipv4Addr;
+
+use crate::arch::time;
+use crate::fs::FileSystemError;
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::Mutex;
+
+use super::offload::{
+    self, Ipv4Frame, ETH_HEADER_SIZE, ETH_TYPE_IPV4, IPV4_HEADER_SIZE, IP_PROTOCOL_ICMP,
+    IP_PROTOCOL_UDP,
+};
+use super::{arp, route, udp, NetworkDevice, RawPacket};
+
+pub const ICMP_DEST_UNREACH: u8 = 3;
+pub const ICMP_TIME_EXCEEDED: u8 = 11;
+pub const ICMP_PARAMETER_PROBLEM: u8 = 12;
+
+// Codes for `ICMP_DEST_UNREACH`.
+pub const ICMP_NET_UNREACH: u8 = 0;
+pub const ICMP_HOST_UNREACH: u8 = 1;
+pub const ICMP_PROT_UNREACH: u8 = 2;
+pub const ICMP_PORT_UNREACH: u8 = 3;
+pub const ICMP_FRAG_NEEDED: u8 = 4;
+
+const ICMP_HEADER_SIZE: usize = 8;
+const UDP_HEADER_SIZE: usize = 8;
+
+/// Maximum size of the IPv4 packet carrying an ICMP error. As much of the offending datagram is
+/// quoted as fits (RFC 1812 section 4.3.2.3).
+const MAX_ERROR_SIZE: usize = 576;
+const ERROR_TTL: u8 = 64;
+
+/// Maximum number of ICMP errors sent in a burst.
+const ERROR_BURST: u64 = 50;
+/// Rate at which ICMP errors can be sent once the burst has been used up, in errors per second.
+const ERRORS_PER_SEC: u64 = 1000;
+
+static RATE_LIMIT: Mutex<RateLimit> = Mutex::new(RateLimit {
+    tokens: ERROR_BURST,
+    last: 0,
+});
+
+static IP_ID: AtomicU16 = AtomicU16::new(0);
+
+/// Token bucket limiting the rate at which ICMP errors are sent (RFC 1812 section 4.3.2.8).
+struct RateLimit {
+    tokens: u64,
+    /// Time at which the bucket was last refilled, in nanoseconds.
+    last: u64,
+}
+
+impl RateLimit {
+    fn allow(&mut self, now: u64) -> bool {
+        let refill = now.saturating_sub(self.last).saturating_mul(ERRORS_PER_SEC) / 1_000_000_000;
+
+        if refill > 0 {
+            self.tokens = (self.tokens + refill).min(ERROR_BURST);
+            self.last = now;
+        }
+
+        if self.tokens == 0 {
+            return false;
+        }
+
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// An ICMP error received for a datagram sent from a local socket.
+#[derive(Debug, Clone)]
+pub struct IcmpError {
+    pub typ: u8,
+    pub code: u8,
+    /// The next-hop MTU for `ICMP_FRAG_NEEDED` or the offset of the offending octet for
+    /// `ICMP_PARAMETER_PROBLEM`.
+    pub info: u32,
+    /// Address of the host that reported the error.
+    pub offender: Ipv4Addr,
+    /// Destination address of the offending datagram.
+    pub dest: Ipv4Addr,
+    /// Destination port of the offending datagram.
+    pub dest_port: u16,
+    /// Payload of the offending datagram, as much of it as was quoted.
+    pub payload: Vec<u8>,
+}
+
+impl IcmpError {
+    /// Returns the error to report to the socket and whether it is a hard error. Hard errors are
+    /// reported to connected sockets even if they did not ask for extended errors.
+    pub fn error(&self) -> (FileSystemError, bool) {
+        match (self.typ, self.code) {
+            (ICMP_DEST_UNREACH, ICMP_NET_UNREACH) => (FileSystemError::NetworkUnreachable, true),
+            (ICMP_DEST_UNREACH, ICMP_HOST_UNREACH) => (FileSystemError::HostUnreachable, true),
+            (ICMP_DEST_UNREACH, ICMP_PROT_UNREACH) => (FileSystemError::ProtocolNotAvailable, true),
+            (ICMP_DEST_UNREACH, ICMP_PORT_UNREACH) => (FileSystemError::ConnectionRefused, true),
+            (ICMP_DEST_UNREACH, ICMP_FRAG_NEEDED) => (FileSystemError::MessageTooLong, false),
+            (ICMP_PARAMETER_PROBLEM, _) => (FileSystemError::ProtocolError, true),
+            // Time exceeded and the remaining destination unreachable codes.
+            _ => (FileSystemError::HostUnreachable, false),
+        }
+    }
+}
+
+fn ipv4_addr(bytes: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[..4]).unwrap())
+}
+
+/// Returns whether `addr` names a single host, as opposed to a broadcast, multicast, loopback
+/// or unspecified address.
+fn is_unicast(device: &NetworkDevice, addr: Ipv4Addr) -> bool {
+    let host = !u32::from_be_bytes(device.subnet_mask().0);
+    let directed_broadcast = host != 0 && u32::from_be_bytes(addr.0) & host == host;
+
+    !(addr.0 == [0; 4] || addr.0[0] == 127 || addr.0[0] >= 224 || directed_broadcast)
+}
+
+/// Parses the ICMP error in `frame`. Returns the source port of the offending datagram and the
+/// error, or [`None`] if `frame` does not hold an ICMP error for a UDP datagram.
+fn parse_error(frame: &[u8], ipv4: &Ipv4Frame) -> Option<(u16, IcmpError)> {
+    let icmp = &frame[ipv4.transport..ipv4.end];
+
+    if ipv4.fragment || icmp.len() < ICMP_HEADER_SIZE || offload::checksum(icmp) != 0 {
+        return None;
+    }
+
+    let (typ, code) = (icmp[0], icmp[1]);
+    let info = match typ {
+        ICMP_DEST_UNREACH if code == ICMP_FRAG_NEEDED => {
+            u32::from(u16::from_be_bytes([icmp[6], icmp[7]]))
+        }
+
+        ICMP_DEST_UNREACH | ICMP_TIME_EXCEEDED => 0,
+        ICMP_PARAMETER_PROBLEM => u32::from(icmp[4]),
+        _ => return None,
+    };
+
+    // The error quotes the IP header and at least the first 8 bytes of the offending datagram.
+    let quoted = &icmp[ICMP_HEADER_SIZE..];
+
+    if quoted.len() < IPV4_HEADER_SIZE {
+        return None;
+    }
+
+    let header_size = usize::from(quoted[0] & 0xf) * 4;
+
+    if header_size < IPV4_HEADER_SIZE
+        || quoted[9] != IP_PROTOCOL_UDP
+        || quoted.len() < header_size + UDP_HEADER_SIZE
+    {
+        return None;
+    }
+
+    let udp = &quoted[header_size..];
+    let udp_len = usize::from(u16::from_be_bytes([udp[4], udp[5]]));
+
+    let error = IcmpError {
+        typ,
+        code,
+        info,
+        offender: ipv4_addr(&frame[ETH_HEADER_SIZE + 12..]),
+        dest: ipv4_addr(&quoted[16..]),
+        dest_port: u16::from_be_bytes([udp[2], udp[3]]),
+        payload: udp[UDP_HEADER_SIZE..udp_len.clamp(UDP_HEADER_SIZE, udp.len())].to_vec(),
+    };
+
+    Some((u16::from_be_bytes([udp[0], udp[1]]), error))
+}
+
+/// Returns an ethernet frame holding an ICMP error of the provided type and code for the IPv4
+/// packet in `frame`, sent from `src_mac` and `src_ip`. The destination MAC address is left
+/// zeroed.
+fn make_error(
+    src_mac: MacAddr,
+    src_ip: Ipv4Addr,
+    frame: &[u8],
+    ipv4: &Ipv4Frame,
+    typ: u8,
+    code: u8,
+) -> RawPacket {
+    let offending = &frame[ETH_HEADER_SIZE..ipv4.end];
+    let max_quote = MAX_ERROR_SIZE - IPV4_HEADER_SIZE - ICMP_HEADER_SIZE;
+    let quoted = &offending[..offending.len().min(max_quote)];
+
+    let total_len = IPV4_HEADER_SIZE + ICMP_HEADER_SIZE + quoted.len();
+
+    // SAFETY: An all-zero byte slice is valid.
+    let mut packet: RawPacket = unsafe {
+        Box::<[u8], _>::new_zeroed_slice_in(ETH_HEADER_SIZE + total_len, DmaAllocator).assume_init()
+    };
+
+    packet[6..12].copy_from_slice(&src_mac.0);
+    packet[12..14].copy_from_slice(&ETH_TYPE_IPV4.to_be_bytes());
+
+    let (ip, icmp) = packet[ETH_HEADER_SIZE..].split_at_mut(IPV4_HEADER_SIZE);
+
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    ip[4..6].copy_from_slice(&IP_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    ip[8] = ERROR_TTL;
+    ip[9] = IP_PROTOCOL_ICMP;
+    ip[12..16].copy_from_slice(&src_ip.0);
+    ip[16..20].copy_from_slice(&offending[12..16]);
+
+    let value = offload::checksum(ip);
+    ip[10..12].copy_from_slice(&value.to_be_bytes());
+
+    icmp[0] = typ;
+    icmp[1] = code;
+    icmp[ICMP_HEADER_SIZE..].copy_from_slice(quoted);
+
+    let value = offload::checksum(icmp);
+    icmp[2..4].copy_from_slice(&value.to_be_bytes());
+
+    packet
+}
+
+/// Handles the ICMP message in `frame`.
+pub fn on_packet(frame: &[u8], ipv4: &Ipv4Frame) {
+    match parse_error(frame, ipv4) {
+        Some((port, error)) => udp::on_error(port, error),
+        None => log::debug!("icmp: ignoring message of type {}", frame[ipv4.transport]),
+    }
+}
+
+/// Sends an ICMP error of the provided type and code back to the sender of the IPv4 packet in
+/// `frame`, received on `device`. No error is sent for ICMP messages, broadcasts and fragments
+/// other than the first (RFC 1122 section 3.2.2).
+pub fn send_error(device: &NetworkDevice, frame: &[u8], ipv4: &Ipv4Frame, typ: u8, code: u8) {
+    let ip = &frame[ETH_HEADER_SIZE..ipv4.end];
+    let src = ipv4_addr(&ip[12..]);
+    let dest = ipv4_addr(&ip[16..]);
+
+    // Any but the first fragment.
+    let fragment = u16::from_be_bytes([ip[6], ip[7]]) & 0x1fff != 0;
+    // Also set for link-layer multicasts.
+    let link_broadcast = frame[0] & 1 != 0;
+
+    if ipv4.protocol == IP_PROTOCOL_ICMP
+        || fragment
+        || link_broadcast
+        || !is_unicast(device, src)
+        || !is_unicast(device, dest)
+    {
+        return;
+    }
+
+    if !RATE_LIMIT.lock_irq().allow(time::get_uptime_ns()) {
+        return;
+    }
+
+    let Some((device, next_hop)) = route::lookup(src) else {
+        log::warn!("icmp: no route to host {src:?}");
+        return;
+    };
+
+    let packet = make_error(device.mac(), device.ip(), frame, ipv4, typ, code);
+    arp::send(&device, next_hop, packet);
+}
+
+mod selftests {
+    use super::*;
+
+    use alloc::vec;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    /// Returns an ethernet frame holding a UDP datagram from port 49152 of 10.0.2.15 to port 53
+    /// of 10.0.2.2 carrying `payload`.
+    fn udp_frame(payload: &[u8]) -> Vec<u8> {
+        let total_len = IPV4_HEADER_SIZE + UDP_HEADER_SIZE + payload.len();
+        let mut frame = vec![0; ETH_HEADER_SIZE + total_len];
+
+        frame[12..14].copy_from_slice(&ETH_TYPE_IPV4.to_be_bytes());
+
+        let ip = &mut frame[ETH_HEADER_SIZE..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        ip[8] = 64;
+        ip[9] = IP_PROTOCOL_UDP;
+        ip[12..16].copy_from_slice(&[10, 0, 2, 15]);
+        ip[16..20].copy_from_slice(&[10, 0, 2, 2]);
+
+        let udp = &mut ip[IPV4_HEADER_SIZE..];
+        udp[0..2].copy_from_slice(&49152u16.to_be_bytes());
+        udp[2..4].copy_from_slice(&53u16.to_be_bytes());
+        udp[4..6].copy_from_slice(&((UDP_HEADER_SIZE + payload.len()) as u16).to_be_bytes());
+        udp[UDP_HEADER_SIZE..].copy_from_slice(payload);
+
+        frame
+    }
+
+    /// Returns the port unreachable error sent by 10.0.2.2 for the datagram in `frame`.
+    fn error_for(frame: &[u8]) -> RawPacket {
+        let ipv4 = Ipv4Frame::parse(frame).unwrap();
+        let src_ip = Ipv4Addr::new(10, 0, 2, 2);
+
+        make_error(
+            MacAddr::NULL,
+            src_ip,
+            frame,
+            &ipv4,
+            ICMP_DEST_UNREACH,
+            ICMP_PORT_UNREACH,
+        )
+    }
+
+    fn port_unreachable() -> selftest::Result {
+        let error = error_for(&udp_frame(b"hello"));
+
+        selftest_assert!(offload::verify(&error));
+
+        let ipv4 = Ipv4Frame::parse(&error).unwrap();
+        selftest_assert_eq!(ipv4.protocol, IP_PROTOCOL_ICMP);
+
+        let (port, error) = parse_error(&error, &ipv4).unwrap();
+        selftest_assert_eq!(port, 49152);
+        selftest_assert_eq!(error.offender, Ipv4Addr::new(10, 0, 2, 2));
+        selftest_assert_eq!(error.dest, Ipv4Addr::new(10, 0, 2, 2));
+        selftest_assert_eq!(error.dest_port, 53);
+        selftest_assert_eq!(error.payload.as_slice(), b"hello");
+        selftest_assert_eq!(error.error(), (FileSystemError::ConnectionRefused, true));
+        Ok(())
+    }
+
+    fn quote_limit() -> selftest::Result {
+        let error = error_for(&udp_frame(&[0xaa; 1400]));
+
+        selftest_assert_eq!(error.len(), ETH_HEADER_SIZE + MAX_ERROR_SIZE);
+
+        // The payload is cut short where the quote ends.
+        let (_, error) = parse_error(&error, &Ipv4Frame::parse(&error).unwrap()).unwrap();
+        selftest_assert_eq!(
+            error.payload.len(),
+            MAX_ERROR_SIZE - 2 * IPV4_HEADER_SIZE - ICMP_HEADER_SIZE - UDP_HEADER_SIZE
+        );
+        Ok(())
+    }
+
+    fn rate_limit() -> selftest::Result {
+        let mut limit = RateLimit {
+            tokens: ERROR_BURST,
+            last: 0,
+        };
+
+        for _ in 0..ERROR_BURST {
+            selftest_assert!(limit.allow(0));
+        }
+
+        selftest_assert!(!limit.allow(0));
+        // Refilled at one error per millisecond.
+        selftest_assert!(limit.allow(1_000_000));
+        selftest_assert!(!limit.allow(1_000_000));
+        Ok(())
+    }
+
+    selftest!("icmp", port_unreachable, quote_limit, rate_limit);
+}
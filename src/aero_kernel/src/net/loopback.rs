@@ -24,12 +24,13 @@ use crabnet::network::Ipv4Addr;
 
 use crate::utils::dma::DmaAllocator;
 
+use super::offload::TxOffload;
 use super::{NetworkDevice, NetworkDriver, RecvPacket};
 
 pub struct Loopback;
 
 impl NetworkDriver for Loopback {
-    fn send(&self, _packet: Box<[u8], DmaAllocator>) {
+    fn send(&self, _packet: Box<[u8], DmaAllocator>, _offload: TxOffload) {
         todo!()
     }
 
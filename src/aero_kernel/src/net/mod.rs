@@ -16,37 +16,64 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use crabnet::transport::TcpOptions;
 use spin::RwLock;
 
 pub mod arp;
+pub mod icmp;
 pub mod loopback;
+pub mod offload;
+pub mod packet;
+pub mod route;
 pub mod tcp;
+pub mod tun;
 pub mod udp;
 
-use crate::userland::scheduler;
-use crate::userland::task::Task;
+use crate::socket::netlink;
+use crate::userland::kthread;
 use crate::utils::dma::DmaAllocator;
 
+use aero_syscall::netlink::MessageType;
 use crabnet::data_link::MacAddr;
 use crabnet::network::Ipv4Addr;
 
+use offload::{Features, RxChecksum, TxOffload};
+
 #[downcastable]
 pub trait NetworkDriver: Send + Sync {
-    fn send(&self, packet: Box<[u8], DmaAllocator>);
+    /// Transmits `packet`, performing the requested `offload`. Only the offloads advertised by
+    /// [`NetworkDriver::features`] are ever requested.
+    fn send(&self, packet: Box<[u8], DmaAllocator>, offload: TxOffload);
     fn recv(&self) -> RecvPacket;
     fn recv_end(&self, packet_id: usize);
     fn mac(&self) -> MacAddr;
+
+    /// Returns the offloads that the device can perform.
+    fn features(&self) -> Features {
+        Features::empty()
+    }
+
+    /// Returns the name of the device, or [`None`] if it should be named after the order it
+    /// was registered in (see [`device_name`]).
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns whether the device carries IP packets without a link layer (such as TUN
+    /// devices), in which case the link-layer addresses of the neighbours are not resolved.
+    fn is_point_to_point(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Default)]
 struct Metadata {
     ip: Ipv4Addr,
-    #[allow(dead_code)]
     subnet_mask: Ipv4Addr,
-    default_gateway: Ipv4Addr,
+    features: Features,
 }
 
 // FIXME(andypython): This is very inefficient. We store the driver as an Arc<dyn NetworkDriver> and
@@ -64,9 +91,8 @@ impl NetworkDevice {
         // https://wiki.qemu.org/Documentation/Networking
         let metadata = Metadata {
             ip: Ipv4Addr::new(192, 168, 100, 0),
-            // What should the default be? Also this should really be handled inside dhcpd.
-            default_gateway: Ipv4Addr::new(10, 0, 2, 2),
             subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            features: offload::negotiate(driver.features(), Features::all()),
         };
 
         Self {
@@ -76,11 +102,35 @@ impl NetworkDevice {
     }
 
     pub fn set_ip(&self, ip: Ipv4Addr) {
-        self.metadata.write().ip = ip;
+        self.set_address(ip, self.subnet_mask());
     }
 
     pub fn set_subnet_mask(&self, mask: Ipv4Addr) {
-        self.metadata.write().ip = mask;
+        self.set_address(self.ip(), mask);
+    }
+
+    /// Sets the IPv4 address and subnet mask of the device and notifies the netlink sockets
+    /// listening for address changes.
+    pub fn set_address(&self, ip: Ipv4Addr, mask: Ipv4Addr) {
+        let (old_ip, old_mask) = {
+            let mut metadata = self.metadata.write();
+            let old = (metadata.ip, metadata.subnet_mask);
+
+            metadata.ip = ip;
+            metadata.subnet_mask = mask;
+            old
+        };
+
+        // Devices that have not been registered yet are not visible to userspace.
+        let Some(ifindex) = device_index(self) else {
+            return;
+        };
+
+        if old_ip != ip {
+            netlink::notify_addr(MessageType::RtmDelAddr, ifindex, old_ip, old_mask);
+        }
+
+        netlink::notify_addr(MessageType::RtmNewAddr, ifindex, ip, mask);
     }
 
     pub fn ip(&self) -> Ipv4Addr {
@@ -91,9 +141,52 @@ impl NetworkDevice {
         self.metadata.read().subnet_mask
     }
 
-    #[inline]
-    pub fn default_gateway(&self) -> Ipv4Addr {
-        self.metadata.read().default_gateway
+    /// Returns the offloads enabled on this device.
+    pub fn features(&self) -> Features {
+        self.metadata.read().features
+    }
+
+    /// Transmits the provided link-layer frame through the device driver. The frame
+    /// is also delivered to any packet sockets listening on this device.
+    pub fn send(&self, packet: RawPacket) {
+        self.send_offload(packet, TxOffload::default());
+    }
+
+    /// Transmits the provided link-layer frame, requesting the provided `offload`. The
+    /// offloads that are not enabled on this device are performed in software.
+    pub fn send_offload(&self, mut packet: RawPacket, mut offload: TxOffload) {
+        packet::on_packet(self, &packet, true);
+
+        let features = self.features();
+
+        match offload.tso_mss {
+            Some(mss) if !features.contains(Features::TSO) => {
+                let segments = offload::segment(&packet, mss);
+
+                if segments.is_empty() {
+                    log::warn!("net: dropping a malformed TSO packet");
+                }
+
+                for segment in segments {
+                    self.driver.send(segment, TxOffload::default());
+                }
+
+                return;
+            }
+
+            _ => {}
+        }
+
+        match offload.csum {
+            Some(csum) if !features.contains(Features::TX_CSUM) => {
+                offload::complete_csum(&mut packet, csum);
+                offload.csum = None;
+            }
+
+            _ => {}
+        }
+
+        self.driver.send(packet, offload);
     }
 }
 
@@ -109,72 +202,204 @@ impl core::ops::Deref for NetworkDevice {
 pub struct RecvPacket<'a> {
     pub packet: &'a [u8],
     pub id: usize,
-}
-
-impl<'a> Drop for RecvPacket<'a> {
-    fn drop(&mut self) {
-        default_device().recv_end(self.id)
-    }
+    pub checksum: RxChecksum,
 }
 
 static DEVICES: RwLock<Vec<Arc<NetworkDevice>>> = RwLock::new(Vec::new());
 static DEFAULT_DEVICE: RwLock<Option<Arc<NetworkDevice>>> = RwLock::new(None);
 
-fn packet_processor_thread() {
+fn process_packet(device: &NetworkDevice, packet: &RecvPacket) {
     use crabnet::data_link::{Arp, Eth, EthType};
     use crabnet::network::{Ipv4, Ipv4Type};
     use crabnet::transport::{Tcp, Udp};
     use crabnet::PacketParser;
 
-    let device = default_device();
+    use icmp::{ICMP_DEST_UNREACH, ICMP_PORT_UNREACH, ICMP_PROT_UNREACH};
+    use offload::{Ipv4Frame, IP_PROTOCOL_ICMP, IP_PROTOCOL_TCP, IP_PROTOCOL_UDP};
 
-    loop {
-        let packet = device.recv();
+    packet::on_packet(device, packet.packet, false);
 
-        let mut parser = PacketParser::new(packet.packet);
-        let eth = parser.next::<Eth>();
+    let verified =
+        packet.checksum == RxChecksum::Unnecessary && device.features().contains(Features::RX_CSUM);
 
-        match eth.typ() {
-            EthType::Ip => {
-                let ip = parser.next::<Ipv4>();
+    if !verified && !offload::verify(packet.packet) {
+        log::debug!("net: dropping a packet with an invalid checksum");
+        return;
+    }
 
-                match ip.protocol() {
-                    Ipv4Type::Udp => {
-                        let udp = parser.next::<Udp>();
-                        let size = ip.payload_len() as usize - core::mem::size_of::<Udp>();
+    let mut parser = PacketParser::new(packet.packet);
+    let eth = parser.next::<Eth>();
+
+    match eth.typ() {
+        EthType::Ip => {
+            let Some(ipv4) = Ipv4Frame::parse(packet.packet) else {
+                return;
+            };
+
+            // crabnet only knows about TCP and UDP.
+            match ipv4.protocol {
+                IP_PROTOCOL_TCP | IP_PROTOCOL_UDP => {}
+                IP_PROTOCOL_ICMP => {
+                    icmp::on_packet(packet.packet, &ipv4);
+                    return;
+                }
+
+                _ => {
+                    let code = ICMP_PROT_UNREACH;
+                    icmp::send_error(device, packet.packet, &ipv4, ICMP_DEST_UNREACH, code);
+                    return;
+                }
+            }
 
-                        let payload = &parser.payload()[..size];
-                        udp::on_packet(udp, payload);
+            let ip = parser.next::<Ipv4>();
+
+            match ip.protocol() {
+                Ipv4Type::Udp => {
+                    let udp = parser.next::<Udp>();
+                    let size = ip.payload_len() as usize - core::mem::size_of::<Udp>();
+
+                    let payload = &parser.payload()[..size];
+                    if !udp::on_packet(udp, payload) {
+                        icmp::send_error(
+                            device,
+                            packet.packet,
+                            &ipv4,
+                            ICMP_DEST_UNREACH,
+                            ICMP_PORT_UNREACH,
+                        );
                     }
+                }
 
-                    Ipv4Type::Tcp => {
-                        let tcp = parser.next::<Tcp>();
-                        let size = ip.payload_len() as usize - tcp.header_size() as usize;
-                        let options = parser.next::<TcpOptions>();
-                        let payload = &parser.payload()[..size];
+                Ipv4Type::Tcp => {
+                    let tcp = parser.next::<Tcp>();
+                    let size = ip.payload_len() as usize - tcp.header_size() as usize;
+                    let options = parser.next::<TcpOptions>();
+                    let payload = &parser.payload()[..size];
 
-                        tcp::on_packet(tcp, &options, payload)
-                    }
+                    tcp::on_packet(tcp, &options, payload)
                 }
             }
+        }
 
-            EthType::Arp => {
-                arp::do_recv(parser.next::<Arp>());
-            }
+        EthType::Arp => {
+            arp::do_recv(device, parser.next::<Arp>());
         }
     }
 }
 
-pub fn add_device(device: NetworkDevice) {
+fn packet_processor_thread(device: Arc<NetworkDevice>) {
+    loop {
+        let packet = device.recv();
+
+        process_packet(&device, &packet);
+        device.recv_end(packet.id);
+    }
+}
+
+/// Registers the provided device and starts processing the packets that it receives.
+pub fn register_device(device: NetworkDevice) -> Arc<NetworkDevice> {
     let device = Arc::new(device);
     DEVICES.write().push(device.clone());
+    netlink::notify_link(MessageType::RtmNewLink, device_index(&device).unwrap());
+
+    log::info!("net: device offloads: {:?}", device.features());
+
+    let rx_device = device.clone();
+    kthread::spawn("net-rx", move || packet_processor_thread(rx_device));
+
+    device
+}
+
+/// Registers the provided device, making it the default device if there is none yet.
+pub fn add_device(device: NetworkDevice) {
+    let device = register_device(device);
 
     let mut default_device = DEFAULT_DEVICE.write();
     if default_device.is_none() {
+        let ifindex = device_index(&device).unwrap();
         *default_device = Some(device);
+
+        // What should the default be? Also this should really be handled inside dhcpd.
+        route::add(route::Route::default_route(
+            Ipv4Addr::new(10, 0, 2, 2),
+            ifindex,
+        ));
+    }
+}
+
+/// Returns the interface index of the provided device. Interface indices start from 1,
+/// as an index of 0 is used to refer to any interface.
+pub fn device_index(device: &NetworkDevice) -> Option<usize> {
+    DEVICES
+        .read()
+        .iter()
+        .position(|e| core::ptr::eq(Arc::as_ptr(e), device))
+        .map(|index| index + 1)
+}
+
+/// Returns the device with the provided interface index.
+pub fn device_by_index(index: usize) -> Option<Arc<NetworkDevice>> {
+    DEVICES.read().get(index.checked_sub(1)?).cloned()
+}
+
+/// Returns the device with the provided name (see [`device_index_by_name`]).
+pub fn device_by_name(name: &str) -> Option<Arc<NetworkDevice>> {
+    device_index_by_name(name).and_then(device_by_index)
+}
+
+/// Returns whether the device is named after the order it was registered in.
+fn is_ethernet(device: &Arc<NetworkDevice>) -> bool {
+    device.name().is_none() && !Arc::ptr_eq(device, &loopback::LOOPBACK)
+}
+
+/// Returns the name of the device with the provided interface index (see
+/// [`device_index_by_name`]).
+pub fn device_name(index: usize) -> Option<String> {
+    let device = device_by_index(index)?;
+
+    if let Some(name) = device.name() {
+        return Some(String::from(name));
+    }
+
+    if Arc::ptr_eq(&device, &loopback::LOOPBACK) {
+        return Some(String::from("lo"));
     }
 
-    scheduler::get_scheduler().register_task(Task::new_kernel(packet_processor_thread, true));
+    let nth = DEVICES
+        .read()
+        .iter()
+        .take(index - 1)
+        .filter(|e| is_ethernet(e))
+        .count();
+
+    Some(alloc::format!("eth{nth}"))
+}
+
+/// Returns the interface index of the device with the provided name. Devices that have a
+/// name of their own (see [`NetworkDriver::name`]) keep it, the loopback device is named
+/// `lo` and the rest of the devices are named `ethN` in the order that they were registered.
+pub fn device_index_by_name(name: &str) -> Option<usize> {
+    let devices = DEVICES.read();
+
+    if let Some(index) = devices.iter().position(|e| e.name() == Some(name)) {
+        return Some(index + 1);
+    }
+
+    if name == "lo" {
+        return devices
+            .iter()
+            .position(|e| Arc::ptr_eq(e, &loopback::LOOPBACK))
+            .map(|index| index + 1);
+    }
+
+    let nth = name.strip_prefix("eth")?.parse::<usize>().ok()?;
+
+    devices
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| is_ethernet(e))
+        .nth(nth)
+        .map(|(index, _)| index + 1)
 }
 
 pub fn has_default_device() -> bool {
@@ -191,23 +416,27 @@ pub fn default_device() -> Arc<NetworkDevice> {
 
 // Initialize the networking stack.
 pub fn init() {
+    // TUN/TAP devices can be created even if there are no network devices.
+    arp::init();
+    log::info!("net::arp: initialized cache");
+
+    tun::init();
+
     if !has_default_device() {
         // No network devices are avaliable.
         return;
     }
 
     DEVICES.write().push(loopback::LOOPBACK.clone());
-    arp::init();
-    log::info!("net::arp: initialized cache");
 }
 
 pub type RawPacket = Box<[u8], DmaAllocator>;
 
 pub mod shim {
-    use crate::net::{self, arp};
+    use crate::net::{arp, route};
     use crate::utils::dma::DmaAllocator;
 
-    use crabnet::data_link::{Arp, Eth, EthType, MacAddr};
+    use crabnet::data_link::Eth;
     use crabnet::network::Ipv4;
     use crabnet::{IntoBoxedBytes, Protocol, Stacked};
 
@@ -220,26 +449,16 @@ pub mod shim {
     // TODO(andypython): Can all of the packet send impls be refactored?
     impl<T: Protocol, U: Protocol> PacketSend for Stacked<Stacked<Stacked<Eth, Ipv4>, T>, U> {
         fn send(mut self) {
-            let device = net::default_device();
-
             let eth = &mut self.upper.upper.upper;
             let ip = &self.upper.upper.lower;
 
-            let mut dest_ip = ip.dest_ip();
-
-            if !dest_ip.is_broadcast() && !dest_ip.is_same_subnet(device.ip(), device.subnet_mask())
-            {
-                dest_ip = device.default_gateway();
-            }
+            let Some((device, dest_ip)) = route::lookup(ip.dest_ip()) else {
+                log::warn!("net: no route to host {:?}", ip.dest_ip());
+                return;
+            };
 
             eth.src_mac = device.mac();
-
-            if let Some(addr) = arp::get(dest_ip) {
-                eth.dest_mac = addr;
-                device.send(self.into_boxed_bytes_in(DmaAllocator));
-            } else {
-                arp::request_ip(dest_ip, self.into_boxed_bytes_in(DmaAllocator));
-            }
+            arp::send(&device, dest_ip, self.into_boxed_bytes_in(DmaAllocator));
         }
     }
 
@@ -247,38 +466,16 @@ pub mod shim {
         for Stacked<Stacked<Stacked<Stacked<Eth, Ipv4>, T>, U>, S>
     {
         fn send(mut self) {
-            let device = net::default_device();
-
             let eth = &mut self.upper.upper.upper.upper;
             let ip = &self.upper.upper.upper.lower;
 
-            let mut dest_ip = ip.dest_ip();
-
-            if !dest_ip.is_broadcast() && !dest_ip.is_same_subnet(device.ip(), device.subnet_mask())
-            {
-                dest_ip = device.default_gateway();
-            }
+            let Some((device, dest_ip)) = route::lookup(ip.dest_ip()) else {
+                log::warn!("net: no route to host {:?}", ip.dest_ip());
+                return;
+            };
 
             eth.src_mac = device.mac();
-
-            if let Some(addr) = arp::get(dest_ip) {
-                eth.dest_mac = addr;
-                device.send(self.into_boxed_bytes_in(DmaAllocator));
-            } else {
-                arp::request_ip(dest_ip, self.into_boxed_bytes_in(DmaAllocator));
-            }
-        }
-    }
-
-    impl PacketSend for Arp {
-        fn send(self) {
-            let device = net::default_device();
-
-            let eth = Eth::new(MacAddr::NULL, MacAddr::BROADCAST, EthType::Arp)
-                .set_dest_mac(self.dest_mac())
-                .set_src_mac(device.mac());
-
-            device.send((eth / self).into_boxed_bytes_in(DmaAllocator));
+            arp::send(&device, dest_ip, self.into_boxed_bytes_in(DmaAllocator));
         }
     }
 
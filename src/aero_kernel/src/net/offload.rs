@@ -0,0 +1,440 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Checksum and segmentation offloads.
+//!
+//! Network drivers advertise the offloads that their hardware can perform with
+//! [`NetworkDriver::features`] and the features of the device are negotiated from those (see
+//! [`negotiate`]). Every transmitted packet carries a [`TxOffload`] describing the work that is
+//! left to do on it; whatever the device can not do is done in software before the packet
+//! reaches the driver (see [`NetworkDevice::send_offload`]), so drivers are only ever asked to
+//! perform the offloads that they advertised.
+//!
+//! Received packets carry an [`RxChecksum`] telling whether the device has verified their
+//! checksums already. The checksums of the other ones are verified in software (see
+//! [`verify`]).
+//!
+//! [`NetworkDriver::features`]: super::NetworkDriver::features
+//! [`NetworkDevice::send_offload`]: super::NetworkDevice::send_offload
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::utils::dma::DmaAllocator;
+
+use super::RawPacket;
+
+pub(super) const ETH_HEADER_SIZE: usize = 14;
+pub(super) const ETH_TYPE_IPV4: u16 = 0x0800;
+
+pub(super) const IPV4_HEADER_SIZE: usize = 20;
+pub(super) const IP_PROTOCOL_ICMP: u8 = 1;
+pub(super) const IP_PROTOCOL_TCP: u8 = 6;
+pub(super) const IP_PROTOCOL_UDP: u8 = 17;
+
+const TCP_HEADER_SIZE: usize = 20;
+const TCP_FLAG_FIN: u8 = 1 << 0;
+const TCP_FLAG_PSH: u8 = 1 << 3;
+const TCP_FLAG_CWR: u8 = 1 << 7;
+
+bitflags::bitflags! {
+    /// Offloads that a network device can perform.
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+    pub struct Features: u32 {
+        /// Completing the checksum of transmitted packets (see [`CsumPartial`]).
+        const TX_CSUM = 1 << 0;
+        /// Verifying the IPv4, TCP and UDP checksums of received packets.
+        const RX_CSUM = 1 << 1;
+        /// Transmitting packets whose data is spread across several buffers.
+        const SG      = 1 << 2;
+        /// Splitting TCP segments that are larger than the MSS (TCP segmentation offload).
+        const TSO     = 1 << 3;
+    }
+}
+
+/// Returns the features to enable on a device that can perform the `hw` offloads, when the
+/// `wanted` ones are requested. Like on Linux, segmentation offload requires scatter-gather,
+/// which in turn requires checksum offload, as the device has to checksum the data that it
+/// gathers.
+pub fn negotiate(hw: Features, wanted: Features) -> Features {
+    let mut features = hw & wanted;
+
+    if !features.contains(Features::TX_CSUM) {
+        features.remove(Features::SG);
+    }
+
+    if !features.contains(Features::SG) {
+        features.remove(Features::TSO);
+    }
+
+    features
+}
+
+/// The checksum of a transmitted packet that is left for the device to complete.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CsumPartial {
+    /// Offset in the frame at which the checksummed data starts.
+    pub start: usize,
+    /// Offset of the checksum field from `start`. The field holds the folded sum of the pseudo
+    /// header, which the device adds up with the rest of the data.
+    pub offset: usize,
+}
+
+/// Offloads requested for a transmitted packet.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TxOffload {
+    pub csum: Option<CsumPartial>,
+    /// The maximum segment size to split the TCP segment in the packet into.
+    pub tso_mss: Option<usize>,
+}
+
+/// Whether the checksums of a received packet have been verified by the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RxChecksum {
+    /// The checksums have to be verified in software.
+    None,
+    /// The device has verified the checksums.
+    Unnecessary,
+}
+
+/// Offsets of the headers of an IPv4 packet in an ethernet frame.
+pub(super) struct Ipv4Frame {
+    pub(super) transport: usize,
+    pub(super) end: usize,
+    pub(super) protocol: u8,
+    pub(super) fragment: bool,
+}
+
+impl Ipv4Frame {
+    /// Returns [`None`] if `frame` does not hold an IPv4 packet or if the packet is malformed.
+    pub(super) fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_SIZE + IPV4_HEADER_SIZE
+            || u16::from_be_bytes([frame[12], frame[13]]) != ETH_TYPE_IPV4
+        {
+            return None;
+        }
+
+        let ip = &frame[ETH_HEADER_SIZE..];
+        let header_size = usize::from(ip[0] & 0xf) * 4;
+        let total_len = usize::from(u16::from_be_bytes([ip[2], ip[3]]));
+
+        if header_size < IPV4_HEADER_SIZE || total_len < header_size || total_len > ip.len() {
+            return None;
+        }
+
+        Some(Self {
+            transport: ETH_HEADER_SIZE + header_size,
+            end: ETH_HEADER_SIZE + total_len,
+            protocol: ip[9],
+            // The more fragments flag or a non-zero fragment offset.
+            fragment: u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0,
+        })
+    }
+
+    /// Returns the sum of the pseudo header of the transport layer segment.
+    fn pseudo_header_sum(&self, frame: &[u8]) -> u64 {
+        let addresses = &frame[ETH_HEADER_SIZE + 12..ETH_HEADER_SIZE + 20];
+        sum(
+            addresses,
+            u64::from(self.protocol) + (self.end - self.transport) as u64,
+        )
+    }
+
+    /// Returns the checksum of the transport layer segment, including its pseudo header.
+    fn transport_checksum(&self, frame: &[u8]) -> u16 {
+        !fold(sum(
+            &frame[self.transport..self.end],
+            self.pseudo_header_sum(frame),
+        ))
+    }
+}
+
+/// Adds up `data` as big-endian 16-bit words to `initial`, without folding the carries.
+fn sum(data: &[u8], initial: u64) -> u64 {
+    let mut chunks = data.chunks_exact(2);
+    let sum = chunks
+        .by_ref()
+        .map(|word| u64::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u64>();
+
+    // An odd byte at the end is padded with zero.
+    let last = chunks
+        .remainder()
+        .first()
+        .map_or(0, |byte| u64::from(*byte) << 8);
+    initial + sum + last
+}
+
+fn fold(mut sum: u64) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    sum as u16
+}
+
+/// Returns the internet checksum (RFC 1071) of `data`.
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum(data, 0))
+}
+
+/// Completes the checksum of `frame` in software.
+pub fn complete_csum(frame: &mut [u8], csum: CsumPartial) {
+    let field = csum.start + csum.offset;
+    let value = checksum(&frame[csum.start..]);
+
+    frame[field..field + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Verifies the IPv4 header checksum and the TCP or UDP checksum of `frame`. Frames that do not
+/// hold an IPv4 packet are not checked and the transport layer checksum of fragments is left to
+/// be verified after reassembly.
+pub fn verify(frame: &[u8]) -> bool {
+    if frame.len() < ETH_HEADER_SIZE || u16::from_be_bytes([frame[12], frame[13]]) != ETH_TYPE_IPV4
+    {
+        return true;
+    }
+
+    let Some(ipv4) = Ipv4Frame::parse(frame) else {
+        return false;
+    };
+
+    if checksum(&frame[ETH_HEADER_SIZE..ipv4.transport]) != 0 {
+        return false;
+    }
+
+    if ipv4.fragment {
+        return true;
+    }
+
+    let segment = &frame[ipv4.transport..ipv4.end];
+
+    match ipv4.protocol {
+        // A zero UDP checksum means that the sender did not compute one.
+        IP_PROTOCOL_UDP if segment.len() >= 8 && segment[6..8] == [0, 0] => true,
+        IP_PROTOCOL_TCP | IP_PROTOCOL_UDP => ipv4.transport_checksum(frame) == 0,
+        _ => true,
+    }
+}
+
+/// Splits the TCP segment in `frame` into segments that carry up to `mss` bytes of payload each,
+/// computing their checksums in software. Returns an empty vector if `frame` does not hold a
+/// TCP segment.
+pub fn segment(frame: &[u8], mss: usize) -> Vec<RawPacket> {
+    let Some(ipv4) = Ipv4Frame::parse(frame).filter(|ipv4| ipv4.protocol == IP_PROTOCOL_TCP) else {
+        return Vec::new();
+    };
+
+    let tcp = ipv4.transport;
+
+    if mss == 0 || tcp + TCP_HEADER_SIZE > ipv4.end {
+        return Vec::new();
+    }
+
+    let header_size = tcp + usize::from(frame[tcp + 12] >> 4) * 4;
+
+    if header_size < tcp + TCP_HEADER_SIZE || header_size > ipv4.end {
+        return Vec::new();
+    }
+
+    let id = u16::from_be_bytes([frame[18], frame[19]]);
+    let seq = u32::from_be_bytes([
+        frame[tcp + 4],
+        frame[tcp + 5],
+        frame[tcp + 6],
+        frame[tcp + 7],
+    ]);
+
+    let chunks = frame[header_size..ipv4.end].chunks(mss);
+    let count = chunks.len();
+
+    chunks
+        .enumerate()
+        .map(|(i, payload)| {
+            let mut segment: RawPacket = unsafe {
+                Box::<[u8], _>::new_zeroed_slice_in(header_size + payload.len(), DmaAllocator)
+                    .assume_init()
+            };
+
+            segment[..header_size].copy_from_slice(&frame[..header_size]);
+            segment[header_size..].copy_from_slice(payload);
+
+            let total_len = (segment.len() - ETH_HEADER_SIZE) as u16;
+            segment[16..18].copy_from_slice(&total_len.to_be_bytes());
+            segment[18..20].copy_from_slice(&id.wrapping_add(i as u16).to_be_bytes());
+            segment[24..26].fill(0);
+
+            let value = checksum(&segment[ETH_HEADER_SIZE..tcp]);
+            segment[24..26].copy_from_slice(&value.to_be_bytes());
+
+            let seq = seq.wrapping_add((i * mss) as u32);
+            segment[tcp + 4..tcp + 8].copy_from_slice(&seq.to_be_bytes());
+
+            // Congestion window reduced is only reported by the first segment, while FIN and
+            // PSH only apply to the last one.
+            if i != 0 {
+                segment[tcp + 13] &= !TCP_FLAG_CWR;
+            }
+
+            if i != count - 1 {
+                segment[tcp + 13] &= !(TCP_FLAG_FIN | TCP_FLAG_PSH);
+            }
+
+            segment[tcp + 16..tcp + 18].fill(0);
+
+            let ipv4 = Ipv4Frame::parse(&segment).unwrap();
+            let value = ipv4.transport_checksum(&segment);
+            segment[tcp + 16..tcp + 18].copy_from_slice(&value.to_be_bytes());
+
+            segment
+        })
+        .collect()
+}
+
+mod selftests {
+    use super::*;
+
+    use alloc::vec;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    /// Returns an ethernet frame holding an IPv4 packet of `protocol` with a transport header of
+    /// `header_size` bytes followed by `payload_len` bytes of payload. The checksums are left
+    /// zeroed.
+    fn frame(protocol: u8, header_size: usize, payload_len: usize) -> Vec<u8> {
+        let total_len = IPV4_HEADER_SIZE + header_size + payload_len;
+        let mut frame = vec![0; ETH_HEADER_SIZE + total_len];
+
+        frame[12..14].copy_from_slice(&ETH_TYPE_IPV4.to_be_bytes());
+
+        let ip = &mut frame[ETH_HEADER_SIZE..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        ip[4..6].copy_from_slice(&0x1234u16.to_be_bytes());
+        ip[8] = 64;
+        ip[9] = protocol;
+        ip[12..16].copy_from_slice(&[10, 0, 2, 15]);
+        ip[16..20].copy_from_slice(&[10, 0, 2, 2]);
+
+        let value = checksum(&ip[..IPV4_HEADER_SIZE]);
+        ip[10..12].copy_from_slice(&value.to_be_bytes());
+
+        for (i, byte) in ip[IPV4_HEADER_SIZE + header_size..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        frame
+    }
+
+    fn rfc1071() -> selftest::Result {
+        // The example from section 3 of RFC 1071.
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        selftest_assert_eq!(checksum(&data), 0x220d);
+
+        // An odd byte at the end is padded with zero.
+        selftest_assert_eq!(checksum(&[0x12, 0x34, 0x56]), 0x97cb);
+        Ok(())
+    }
+
+    fn features() -> selftest::Result {
+        let all = Features::all();
+
+        selftest_assert_eq!(negotiate(all, all), all);
+        selftest_assert_eq!(
+            negotiate(all, Features::TX_CSUM | Features::TSO),
+            Features::TX_CSUM
+        );
+        selftest_assert_eq!(
+            negotiate(Features::SG | Features::TSO, all),
+            Features::empty()
+        );
+        Ok(())
+    }
+
+    fn csum_partial() -> selftest::Result {
+        let mut udp = frame(IP_PROTOCOL_UDP, 8, 37);
+        let ipv4 = Ipv4Frame::parse(&udp).unwrap();
+        let len = (ipv4.end - ipv4.transport) as u16;
+
+        udp[ipv4.transport + 4..ipv4.transport + 6].copy_from_slice(&len.to_be_bytes());
+        udp[ipv4.transport + 6..ipv4.transport + 8]
+            .copy_from_slice(&fold(ipv4.pseudo_header_sum(&udp)).to_be_bytes());
+
+        complete_csum(
+            &mut udp,
+            CsumPartial {
+                start: ipv4.transport,
+                offset: 6,
+            },
+        );
+
+        selftest_assert!(verify(&udp));
+
+        // Corrupt the payload.
+        udp[ipv4.end - 1] ^= 0xff;
+        selftest_assert!(!verify(&udp));
+        Ok(())
+    }
+
+    fn tcp_segmentation() -> selftest::Result {
+        const MSS: usize = 1400;
+
+        let mut tcp = frame(IP_PROTOCOL_TCP, 20, 3000);
+        let transport = Ipv4Frame::parse(&tcp).unwrap().transport;
+
+        tcp[transport + 4..transport + 8].copy_from_slice(&0xffff_ff00u32.to_be_bytes());
+        tcp[transport + 12] = 5 << 4;
+        tcp[transport + 13] = TCP_FLAG_FIN | TCP_FLAG_PSH | TCP_FLAG_CWR;
+
+        let segments = segment(&tcp, MSS);
+        selftest_assert_eq!(segments.len(), 3);
+
+        for (i, segment) in segments.iter().enumerate() {
+            selftest_assert!(verify(segment));
+
+            let ipv4 = Ipv4Frame::parse(segment).unwrap();
+            let payload = &segment[transport + 20..ipv4.end];
+
+            selftest_assert_eq!(payload, &tcp[transport + 20 + i * MSS..][..payload.len()]);
+            selftest_assert_eq!(
+                u16::from_be_bytes([segment[18], segment[19]]),
+                0x1234 + i as u16
+            );
+            selftest_assert_eq!(
+                u32::from_be_bytes(segment[transport + 4..transport + 8].try_into().unwrap()),
+                0xffff_ff00u32.wrapping_add((i * MSS) as u32)
+            );
+        }
+
+        selftest_assert_eq!(segments[2].len(), transport + 20 + 200);
+        selftest_assert_eq!(segments[0][transport + 13], TCP_FLAG_CWR);
+        selftest_assert_eq!(segments[1][transport + 13], 0);
+        selftest_assert_eq!(segments[2][transport + 13], TCP_FLAG_FIN | TCP_FLAG_PSH);
+
+        // Segmenting anything but TCP is not supported.
+        selftest_assert!(segment(&frame(IP_PROTOCOL_UDP, 8, 3000), MSS).is_empty());
+        Ok(())
+    }
+
+    selftest!(
+        "netoffload",
+        rfc1071,
+        features,
+        csum_partial,
+        tcp_segmentation
+    );
+}
@@ -0,0 +1,68 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Link-layer packet taps used by `AF_PACKET` sockets.
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use super::NetworkDevice;
+
+static HANDLERS: RwLock<Vec<Weak<dyn PacketHandler>>> = RwLock::new(Vec::new());
+
+pub trait PacketHandler: Send + Sync {
+    /// Called for every link-layer frame received or transmitted (`outgoing`) on the device
+    /// with the interface index `ifindex`.
+    fn recv(&self, ifindex: usize, frame: &[u8], outgoing: bool);
+}
+
+/// Registers a packet handler. The handler is automatically unregistered when the last
+/// strong reference to it is dropped.
+pub fn register(handler: Arc<dyn PacketHandler>) {
+    HANDLERS.write().push(Arc::downgrade(&handler));
+}
+
+/// Delivers the provided link-layer frame to all of the registered packet handlers.
+pub fn on_packet(device: &NetworkDevice, frame: &[u8], outgoing: bool) {
+    let handlers = HANDLERS.read();
+
+    if handlers.is_empty() {
+        return;
+    }
+
+    let Some(ifindex) = super::device_index(device) else {
+        return;
+    };
+
+    let mut has_stale = false;
+
+    for handler in handlers.iter() {
+        if let Some(handler) = handler.upgrade() {
+            handler.recv(ifindex, frame, outgoing);
+        } else {
+            has_stale = true;
+        }
+    }
+
+    if has_stale {
+        core::mem::drop(handlers);
+        HANDLERS
+            .write()
+            .retain(|handler| handler.strong_count() > 0);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! IPv4 routing table.
+//!
+//! The routing table is consulted for every outgoing IPv4 packet in order to pick the device
+//! to send it through and the next hop to resolve the link-layer address of. Every device
+//! also has an implicit on-link route to its own subnet.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use aero_syscall::netlink::MessageType;
+use crabnet::network::Ipv4Addr;
+
+use crate::socket::netlink;
+
+use super::NetworkDevice;
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Destination network address.
+    pub dest: Ipv4Addr,
+    /// Destination network mask.
+    pub mask: Ipv4Addr,
+    /// Gateway to forward the packets to; [`None`] if the destination is on-link.
+    pub gateway: Option<Ipv4Addr>,
+    /// Interface index of the device to send the packets through.
+    pub ifindex: usize,
+    /// Routes with a lower metric are preferred over routes with the same prefix length.
+    pub metric: u32,
+}
+
+impl Route {
+    /// Creates a default route (`0.0.0.0/0`) through the provided gateway.
+    pub fn default_route(gateway: Ipv4Addr, ifindex: usize) -> Self {
+        Self {
+            dest: Ipv4Addr::new(0, 0, 0, 0),
+            mask: Ipv4Addr::new(0, 0, 0, 0),
+            gateway: Some(gateway),
+            ifindex,
+            metric: 0,
+        }
+    }
+
+    /// Returns the on-link route to the subnet that the provided device is part of.
+    fn on_link(device: &NetworkDevice, ifindex: usize) -> Self {
+        let mask = device.subnet_mask();
+
+        Self {
+            dest: apply_mask(device.ip(), mask),
+            mask,
+            gateway: None,
+            ifindex,
+            metric: 0,
+        }
+    }
+
+    pub fn prefix_len(&self) -> u32 {
+        u32::from_be_bytes(self.mask.0).count_ones()
+    }
+
+    fn matches(&self, addr: Ipv4Addr) -> bool {
+        apply_mask(addr, self.mask) == apply_mask(self.dest, self.mask)
+    }
+}
+
+fn apply_mask(addr: Ipv4Addr, mask: Ipv4Addr) -> Ipv4Addr {
+    let addr = u32::from_be_bytes(addr.0) & u32::from_be_bytes(mask.0);
+    Ipv4Addr::from(addr.to_be_bytes())
+}
+
+static ROUTES: RwLock<Vec<Route>> = RwLock::new(Vec::new());
+
+/// Adds the route to the routing table. Returns `false` if a route with the same destination,
+/// mask and metric already exists.
+pub fn add(route: Route) -> bool {
+    let mut routes = ROUTES.write();
+
+    if routes
+        .iter()
+        .any(|e| e.dest == route.dest && e.mask == route.mask && e.metric == route.metric)
+    {
+        return false;
+    }
+
+    log::debug!("route: adding {route:?}");
+    routes.push(route.clone());
+    drop(routes);
+
+    netlink::notify_route(MessageType::RtmNewRoute, &route);
+    true
+}
+
+/// Removes the routes to the provided destination network. Returns `false` if there was no
+/// such route.
+pub fn remove(dest: Ipv4Addr, mask: Ipv4Addr) -> bool {
+    let removed = {
+        let mut routes = ROUTES.write();
+        let (removed, kept) = routes
+            .drain(..)
+            .partition::<Vec<_>, _>(|e| e.dest == dest && e.mask == mask);
+
+        *routes = kept;
+        removed
+    };
+
+    for route in removed.iter() {
+        netlink::notify_route(MessageType::RtmDelRoute, route);
+    }
+
+    !removed.is_empty()
+}
+
+/// Returns the routes in the routing table, excluding the on-link routes of the devices.
+pub fn routes() -> Vec<Route> {
+    ROUTES.read().clone()
+}
+
+/// Returns the implicit on-link routes to the subnets of the devices.
+pub fn on_link_routes() -> Vec<Route> {
+    on_link(&super::DEVICES.read()).collect()
+}
+
+fn on_link(devices: &[Arc<NetworkDevice>]) -> impl Iterator<Item = Route> + '_ {
+    // The loopback driver is not able to transmit packets yet, so its subnet is not
+    // reachable. Devices without an address (such as new TUN devices) have no subnet.
+    devices
+        .iter()
+        .enumerate()
+        .filter(|(_, device)| !Arc::ptr_eq(device, &super::loopback::LOOPBACK))
+        .filter(|(_, device)| device.ip().0 != [0; 4])
+        .map(|(index, device)| Route::on_link(device, index + 1))
+}
+
+/// Looks up the route to the provided destination address using the longest prefix match.
+/// Returns the device to send the packet through and the address of the next hop, or
+/// [`None`] if the destination is unreachable.
+pub fn lookup(dest: Ipv4Addr) -> Option<(Arc<NetworkDevice>, Ipv4Addr)> {
+    if dest.is_broadcast() {
+        return Some((super::default_device(), dest));
+    }
+
+    let devices = super::DEVICES.read();
+
+    let routes = ROUTES.read();
+    let route = routes
+        .iter()
+        .cloned()
+        .chain(on_link(&devices))
+        .filter(|route| route.matches(dest))
+        .max_by(|a, b| {
+            a.prefix_len()
+                .cmp(&b.prefix_len())
+                .then(b.metric.cmp(&a.metric))
+        })?;
+
+    let device = devices.get(route.ifindex.checked_sub(1)?)?.clone();
+    Some((device, route.gateway.unwrap_or(dest)))
+}
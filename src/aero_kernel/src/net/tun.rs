@@ -0,0 +1,629 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! TUN/TAP Devices
+//!
+//! TUN/TAP devices are virtual network devices driven by userland instead of hardware: the
+//! packets that the network stack sends through the device are read from the file they are
+//! attached to, and the packets written to that file are received by the device. TUN devices
+//! carry IPv4 packets and TAP devices carry ethernet frames.
+//!
+//! A device is created by opening `/dev/net/tun` and issuing the `TUNSETIFF` ioctl, which
+//! attaches the file to the device with the requested name (creating it if needed). Unless
+//! `IFF_NO_PI` is set, every packet is preceded by a [`TunPi`] header. Interface indices are
+//! assigned by the order the devices were registered in, so devices are never unregistered;
+//! once the file is closed, the device is detached and drops the packets sent through it until
+//! a file is attached to it again.
+//!
+//! ## Notes
+//! * <https://docs.kernel.org/networking/tuntap.html>
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use aero_syscall::prelude::*;
+use aero_syscall::OpenFlags;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crabnet::data_link::MacAddr;
+use crabnet::network::Ipv4Addr;
+use spin::Once;
+
+use crate::fs::cache::DirCacheItem;
+use crate::fs::devfs::{self, Device, DeviceNumber};
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{DirEntry, FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::fs::{FileSystemError, Result};
+use crate::mem::paging::VirtAddr;
+use crate::userland::kthread;
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::offload::{RxChecksum, TxOffload, ETH_HEADER_SIZE, ETH_TYPE_IPV4};
+use super::{NetworkDevice, NetworkDriver, RecvPacket};
+
+/// Maximum number of packets queued in either direction before the device starts dropping
+/// them.
+const QUEUE_LEN: usize = 500;
+/// Maximum size of the packets written to a device, excluding the packet information.
+const MAX_PACKET_SIZE: usize = ETH_HEADER_SIZE + u16::MAX as usize;
+const PI_SIZE: usize = core::mem::size_of::<TunPi>();
+/// Maximum length of a device name, excluding the NUL terminator.
+const MAX_NAME_LEN: usize = 15;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    /// The device carries IPv4 packets.
+    Tun,
+    /// The device carries ethernet frames.
+    Tap,
+}
+
+/// Converts the packet written by userland into the ethernet frame received by a device in
+/// `mode` with the provided MAC address. `proto` is the ethertype from the packet information,
+/// if any. Returns [`None`] if the packet has to be dropped.
+fn encapsulate(mode: Mode, mac: MacAddr, proto: Option<u16>, packet: &[u8]) -> Option<Vec<u8>> {
+    match mode {
+        Mode::Tap => (packet.len() >= ETH_HEADER_SIZE).then(|| packet.to_vec()),
+        Mode::Tun => {
+            // The version is used if there is no packet information.
+            let proto = proto.unwrap_or(match packet.first()? >> 4 {
+                4 => ETH_TYPE_IPV4,
+                _ => return None,
+            });
+
+            if proto != ETH_TYPE_IPV4 {
+                return None;
+            }
+
+            let mut frame = Vec::with_capacity(ETH_HEADER_SIZE + packet.len());
+
+            frame.extend_from_slice(&mac.0);
+            frame.extend_from_slice(&MacAddr::NULL.0);
+            frame.extend_from_slice(&proto.to_be_bytes());
+            frame.extend_from_slice(packet);
+            Some(frame)
+        }
+    }
+}
+
+/// Converts the ethernet frame sent through a device in `mode` into the packet read by
+/// userland. Returns [`None`] if the frame can not be carried by the device.
+fn decapsulate(mode: Mode, frame: &[u8]) -> Option<&[u8]> {
+    match mode {
+        Mode::Tap => Some(frame),
+        Mode::Tun => {
+            let proto = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            (proto == ETH_TYPE_IPV4).then(|| &frame[ETH_HEADER_SIZE..])
+        }
+    }
+}
+
+struct TxQueue {
+    packets: VecDeque<Vec<u8>>,
+    /// Whether a file is attached to the device.
+    attached: bool,
+}
+
+struct Tun {
+    name: String,
+    mode: Mode,
+    mac: MacAddr,
+
+    /// Packets sent through the device, waiting to be read by userland.
+    tx: Mutex<TxQueue>,
+    tx_wq: WaitQueue,
+    /// Packets written by userland, waiting to be received by the network stack.
+    rx: Mutex<VecDeque<Vec<u8>>>,
+    rx_wq: WaitQueue,
+    /// The packet being processed by the network stack (see [`NetworkDriver::recv`]).
+    rx_current: Mutex<Option<Box<[u8]>>>,
+}
+
+impl Tun {
+    fn new(name: String, mode: Mode) -> Arc<Self> {
+        let mac = match mode {
+            Mode::Tun => MacAddr::NULL,
+            Mode::Tap => {
+                let mut mac = [0; 6];
+                crate::random::fill_bytes(&mut mac);
+
+                // Locally administered unicast address.
+                mac[0] = (mac[0] & 0xfe) | 0x02;
+                MacAddr(mac)
+            }
+        };
+
+        Arc::new(Self {
+            name,
+            mode,
+            mac,
+
+            tx: Mutex::new(TxQueue {
+                packets: VecDeque::new(),
+                attached: false,
+            }),
+            tx_wq: WaitQueue::new(),
+            rx: Mutex::new(VecDeque::new()),
+            rx_wq: WaitQueue::new(),
+            rx_current: Mutex::new(None),
+        })
+    }
+
+    /// Attaches a file to the device. Returns `false` if a file is already attached.
+    fn attach(&self) -> bool {
+        let mut tx = self.tx.lock_irq();
+        !core::mem::replace(&mut tx.attached, true)
+    }
+
+    fn detach(&self) {
+        let mut tx = self.tx.lock_irq();
+
+        tx.attached = false;
+        tx.packets.clear();
+    }
+}
+
+impl NetworkDriver for Tun {
+    fn send(&self, packet: Box<[u8], DmaAllocator>, _offload: TxOffload) {
+        let Some(packet) = decapsulate(self.mode, &packet) else {
+            return;
+        };
+
+        let mut tx = self.tx.lock_irq();
+
+        if !tx.attached || tx.packets.len() >= QUEUE_LEN {
+            return;
+        }
+
+        tx.packets.push_back(packet.to_vec());
+        drop(tx);
+
+        self.tx_wq.notify_all();
+    }
+
+    fn recv(&self) -> RecvPacket {
+        let mut rx = loop {
+            match self.rx_wq.block_on(&self.rx, |rx| !rx.is_empty()) {
+                Ok(rx) => break rx,
+                // Interrupted by a signal sent to the rx thread; ignore it and wait again.
+                Err(_) => kthread::flush_signals(),
+            }
+        };
+
+        let packet = rx.pop_front().unwrap().into_boxed_slice();
+        drop(rx);
+
+        // SAFETY: The packet is kept alive in `rx_current` until `recv_end` is called and
+        // moving the box does not move the packet.
+        let slice = unsafe { &*(&*packet as *const [u8]) };
+        *self.rx_current.lock_irq() = Some(packet);
+
+        RecvPacket {
+            packet: slice,
+            id: 0,
+            checksum: RxChecksum::None,
+        }
+    }
+
+    fn recv_end(&self, _packet_id: usize) {
+        self.rx_current.lock_irq().take();
+    }
+
+    fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn is_point_to_point(&self) -> bool {
+        self.mode == Mode::Tun
+    }
+}
+
+/// TUN/TAP devices that have been created.
+static TUN_DEVICES: Mutex<Vec<Arc<Tun>>> = Mutex::new(Vec::new());
+
+/// Attaches a file to the device with the provided name, creating it if it does not exist
+/// yet. `%d` in the name is replaced with the lowest number that makes the name unique.
+fn attach(name: &str, mode: Mode) -> Result<Arc<Tun>> {
+    let mut devices = TUN_DEVICES.lock();
+
+    let name = if name.contains("%d") {
+        (0..)
+            .map(|n| name.replacen("%d", &n.to_string(), 1))
+            .find(|name| super::device_by_name(name).is_none())
+            .unwrap()
+    } else {
+        name.to_string()
+    };
+
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(FileSystemError::InvalidArgument);
+    }
+
+    if let Some(tun) = devices.iter().find(|tun| tun.name == name) {
+        if tun.mode != mode {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        if !tun.attach() {
+            return Err(FileSystemError::Busy);
+        }
+
+        return Ok(tun.clone());
+    }
+
+    // The name is taken by a device that is not a TUN/TAP device.
+    if super::device_by_name(&name).is_some() {
+        return Err(FileSystemError::InvalidArgument);
+    }
+
+    let tun = Tun::new(name, mode);
+    tun.attach();
+
+    // The device has no address until userland configures one.
+    let device = NetworkDevice::new(tun.clone());
+    let unspecified = Ipv4Addr::new(0, 0, 0, 0);
+
+    device.set_address(unspecified, unspecified);
+    super::register_device(device);
+
+    log::debug!("tun: created {} ({:?})", tun.name, tun.mode);
+
+    devices.push(tun.clone());
+    Ok(tun)
+}
+
+struct Attachment {
+    tun: Arc<Tun>,
+    /// Whether the packets are preceded by the packet information.
+    pi: bool,
+}
+
+/// A file opened through `/dev/net/tun`.
+struct TunFile {
+    attachment: Once<Attachment>,
+    handle: Once<Arc<FileHandle>>,
+    /// Number of file handles referring to the file.
+    opened: AtomicUsize,
+}
+
+impl TunFile {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            attachment: Once::new(),
+            handle: Once::new(),
+            opened: AtomicUsize::new(0),
+        })
+    }
+
+    fn attachment(&self) -> Result<&Attachment> {
+        self.attachment
+            .get()
+            .ok_or(FileSystemError::BadFileDescriptor)
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle
+            .get()
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+}
+
+impl INodeInterface for TunFile {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata::with_file_type(FileType::Device))
+    }
+
+    fn open(&self, handle: Arc<FileHandle>) -> Result<Option<DirCacheItem>> {
+        self.opened.fetch_add(1, Ordering::SeqCst);
+        self.handle.call_once(|| handle);
+
+        Ok(None)
+    }
+
+    fn close(&self, _flags: OpenFlags) {
+        if self.opened.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(attachment) = self.attachment.get() {
+                attachment.tun.detach();
+            }
+        }
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let attachment = self.attachment()?;
+        let tun = &attachment.tun;
+
+        if tun.tx.lock_irq().packets.is_empty() && self.is_non_block() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let packet = tun
+            .tx_wq
+            .block_on(&tun.tx, |tx| !tx.packets.is_empty())?
+            .packets
+            .pop_front()
+            .unwrap();
+
+        let (header, buffer) = if attachment.pi {
+            if buffer.len() < PI_SIZE {
+                return Err(FileSystemError::InvalidArgument);
+            }
+
+            buffer.split_at_mut(PI_SIZE)
+        } else {
+            buffer.split_at_mut(0)
+        };
+
+        let size = core::cmp::min(buffer.len(), packet.len());
+        buffer[..size].copy_from_slice(&packet[..size]);
+
+        if !header.is_empty() {
+            let proto = match tun.mode {
+                Mode::Tun => ETH_TYPE_IPV4,
+                Mode::Tap => u16::from_be_bytes([packet[12], packet[13]]),
+            };
+
+            let flags = if size < packet.len() {
+                TUN_PKT_STRIP
+            } else {
+                0
+            };
+
+            header[..2].copy_from_slice(&flags.to_ne_bytes());
+            header[2..].copy_from_slice(&proto.to_be_bytes());
+        }
+
+        Ok(header.len() + size)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        let attachment = self.attachment()?;
+        let tun = &attachment.tun;
+
+        let (proto, packet) = if attachment.pi {
+            if buffer.len() < PI_SIZE {
+                return Err(FileSystemError::InvalidArgument);
+            }
+
+            let proto = u16::from_be_bytes([buffer[2], buffer[3]]);
+            (Some(proto), &buffer[PI_SIZE..])
+        } else {
+            (None, buffer)
+        };
+
+        if packet.len() > MAX_PACKET_SIZE {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        // Packets that can not be received by the device are dropped, as if they were lost.
+        if let Some(frame) = encapsulate(tun.mode, tun.mac, proto, packet) {
+            let mut rx = tun.rx.lock_irq();
+
+            if rx.len() < QUEUE_LEN {
+                rx.push_back(frame);
+                drop(rx);
+
+                tun.rx_wq.notify_all();
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> Result<PollFlags> {
+        let Ok(attachment) = self.attachment() else {
+            return Ok(PollFlags::ERR);
+        };
+
+        if let Some(table) = table {
+            table.insert(&attachment.tun.tx_wq);
+        }
+
+        let mut flags = PollFlags::OUT;
+
+        if !attachment.tun.tx.lock_irq().packets.is_empty() {
+            flags |= PollFlags::IN;
+        }
+
+        Ok(flags)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        match command {
+            TUNSETIFF => {
+                let ifreq = VirtAddr::new(arg as _).read_mut::<IfReq>()?;
+                let name = ifreq.name().ok_or(FileSystemError::InvalidArgument)?;
+                let flags = unsafe { ifreq.data.flags };
+
+                if flags & !(IFF_TUN | IFF_TAP | IFF_NO_PI) != 0 {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                let mode = match flags & (IFF_TUN | IFF_TAP) {
+                    IFF_TUN => Mode::Tun,
+                    IFF_TAP => Mode::Tap,
+                    _ => return Err(FileSystemError::InvalidArgument),
+                };
+
+                let name = match (name, mode) {
+                    ("", Mode::Tun) => "tun%d",
+                    ("", Mode::Tap) => "tap%d",
+                    (name, _) => name,
+                };
+
+                // Only one device can be attached to the file.
+                let mut attached = false;
+
+                self.attachment.try_call_once(|| {
+                    attached = true;
+
+                    attach(name, mode).map(|tun| Attachment {
+                        tun,
+                        pi: flags & IFF_NO_PI == 0,
+                    })
+                })?;
+
+                if !attached {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                let tun = &self.attachment()?.tun;
+
+                ifreq.name.fill(0);
+                ifreq.name[..tun.name.len()].copy_from_slice(tun.name.as_bytes());
+                Ok(0)
+            }
+
+            TUNGETIFF => {
+                let attachment = self.attachment()?;
+                let ifreq = VirtAddr::new(arg as _).read_mut::<IfReq>()?;
+
+                let mut flags = match attachment.tun.mode {
+                    Mode::Tun => IFF_TUN,
+                    Mode::Tap => IFF_TAP,
+                };
+
+                if !attachment.pi {
+                    flags |= IFF_NO_PI;
+                }
+
+                let name = attachment.tun.name.as_bytes();
+
+                ifreq.name.fill(0);
+                ifreq.name[..name.len()].copy_from_slice(name);
+                ifreq.data.flags = flags;
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+}
+
+/// `/dev/net/tun`: opening it creates a file that can be attached to a TUN/TAP device.
+struct TunControl {
+    marker: usize,
+}
+
+impl Device for TunControl {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        String::from("tun")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        TUN_CONTROL.get().expect("tun: not initialized").clone()
+    }
+
+    fn device_number(&self) -> Option<DeviceNumber> {
+        Some(DeviceNumber::Char(10, 200))
+    }
+}
+
+impl INodeInterface for TunControl {
+    fn open(&self, _handle: Arc<FileHandle>) -> Result<Option<DirCacheItem>> {
+        Ok(Some(DirEntry::from_inode(
+            TunFile::new(),
+            String::from("<tun>"),
+        )))
+    }
+}
+
+static TUN_CONTROL: Once<Arc<TunControl>> = Once::new();
+
+/// Installs `/dev/net/tun`.
+pub fn init() {
+    let control = TUN_CONTROL.call_once(|| {
+        Arc::new(TunControl {
+            marker: devfs::alloc_device_marker(),
+        })
+    });
+
+    let dir = devfs::DEV_FILESYSTEM
+        .root_dir()
+        .inode()
+        .mkdir("net")
+        .expect("tun: failed to create /dev/net");
+
+    devfs::install_device_at(dir, control.clone()).expect("tun: failed to install the device");
+}
+
+mod selftests {
+    use super::*;
+
+    use alloc::vec;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    /// Returns an IPv4 header followed by `payload`.
+    fn ipv4_packet(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; 20];
+        packet[0] = 0x45;
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn tun_roundtrip() -> selftest::Result {
+        let packet = ipv4_packet(b"hello");
+
+        for proto in [None, Some(ETH_TYPE_IPV4)] {
+            let frame = encapsulate(Mode::Tun, MacAddr::NULL, proto, &packet).unwrap();
+
+            selftest_assert_eq!(frame.len(), ETH_HEADER_SIZE + packet.len());
+            selftest_assert_eq!(frame[12..14], ETH_TYPE_IPV4.to_be_bytes());
+            selftest_assert_eq!(decapsulate(Mode::Tun, &frame), Some(packet.as_slice()));
+        }
+
+        Ok(())
+    }
+
+    fn tun_drops_non_ipv4() -> selftest::Result {
+        // An IPv6 packet and an ARP frame.
+        let mut packet = ipv4_packet(b"hello");
+        packet[0] = 0x60;
+        selftest_assert!(encapsulate(Mode::Tun, MacAddr::NULL, None, &packet).is_none());
+
+        let mut frame = vec![0; ETH_HEADER_SIZE + 28];
+        frame[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        selftest_assert!(decapsulate(Mode::Tun, &frame).is_none());
+        Ok(())
+    }
+
+    fn tap_passthrough() -> selftest::Result {
+        let mut frame = vec![0; ETH_HEADER_SIZE];
+        frame.extend_from_slice(&ipv4_packet(b"hello"));
+
+        let received = encapsulate(Mode::Tap, MacAddr::NULL, None, &frame).unwrap();
+        selftest_assert_eq!(received, frame);
+        selftest_assert_eq!(decapsulate(Mode::Tap, &frame), Some(frame.as_slice()));
+
+        // Runt frames are dropped.
+        selftest_assert!(encapsulate(Mode::Tap, MacAddr::NULL, None, &frame[..10]).is_none());
+        Ok(())
+    }
+
+    selftest!("tun", tun_roundtrip, tun_drops_non_ipv4, tap_passthrough);
+}
@@ -17,27 +17,66 @@
 
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::RwLock;
 
 use crabnet::network::Ipv4Addr;
 use crabnet::transport::Udp;
 
-pub fn on_packet(udp: &Udp, payload: &[u8]) {
+use super::icmp::IcmpError;
+
+/// Delivers the datagram to every handler bound to its destination port. Returns `false` if
+/// no handler is bound to the port.
+pub fn on_packet(udp: &Udp, payload: &[u8]) -> bool {
     let dest_port = udp.dst_port();
 
     let handlers = HANDLERS.read();
 
-    if let Some(handler) = handlers.get(&dest_port) {
-        handler.recv(udp, payload);
+    if let Some(binding) = handlers.get(&dest_port) {
+        for handler in binding.handlers.iter() {
+            handler.recv(udp, payload);
+        }
+
+        true
     } else {
         log::warn!("udp: no handler registered for port {}", dest_port);
+        false
+    }
+}
+
+/// Delivers an ICMP error received for a datagram sent from the local `port`.
+pub fn on_error(port: u16, error: IcmpError) {
+    if let Some(binding) = HANDLERS.read().get(&port) {
+        for handler in binding.handlers.iter() {
+            handler.on_error(error.clone());
+        }
+    }
+}
+
+/// Handlers bound to a port.
+struct Binding {
+    handlers: Vec<Arc<dyn UdpHandler>>,
+    /// Whether every handler bound to the port has set `SO_REUSEADDR`, in which case the
+    /// port can be shared with other sockets that have set it as well.
+    reuse_addr: bool,
+}
+
+impl Binding {
+    fn new(handler: Arc<dyn UdpHandler>, reuse_addr: bool) -> Self {
+        Self {
+            handlers: alloc::vec![handler],
+            reuse_addr,
+        }
     }
 }
 
-static HANDLERS: RwLock<BTreeMap<u16, Arc<dyn UdpHandler>>> = RwLock::new(BTreeMap::new());
+static HANDLERS: RwLock<BTreeMap<u16, Binding>> = RwLock::new(BTreeMap::new());
 
 pub trait UdpHandler: Send + Sync {
     fn recv(&self, udp: &Udp, payload: &[u8]);
+
+    /// Called when an ICMP error is received for a datagram sent from the handler's port.
+    fn on_error(&self, _error: IcmpError) {}
 }
 
 pub fn alloc_ephemeral_port(socket: Arc<dyn UdpHandler>) -> Option<u16> {
@@ -55,21 +94,32 @@ pub fn alloc_ephemeral_port(socket: Arc<dyn UdpHandler>) -> Option<u16> {
         }
 
         log::warn!("[ UDP ] Listening on port {port}");
-        handlers.insert(port, socket);
+        handlers.insert(port, Binding::new(socket, false));
         return Some(port);
     }
 
     None
 }
 
-pub fn bind(port: u16, socket: Arc<dyn UdpHandler>) {
-    log::trace!("udp: bind(port={port})");
+/// Binds the socket to the provided port. Returns `false` if the port is already in use,
+/// unless both the sockets bound to it and `socket` have set `SO_REUSEADDR` (`reuse_addr`), in
+/// which case they share the port and each of them receives the datagrams sent to it.
+pub fn bind(port: u16, socket: Arc<dyn UdpHandler>, reuse_addr: bool) -> bool {
+    log::trace!("udp: bind(port={port}, reuse_addr={reuse_addr})");
 
     let mut handlers = HANDLERS.write();
-    // check if the port is already in use
-    assert!(!handlers.contains_key(&port));
 
-    handlers.insert(port, socket);
+    if let Some(binding) = handlers.get_mut(&port) {
+        if !(binding.reuse_addr && reuse_addr) {
+            return false;
+        }
+
+        binding.handlers.push(socket);
+        return true;
+    }
+
+    handlers.insert(port, Binding::new(socket, reuse_addr));
+    true
 }
 
 pub fn connect(host: Ipv4Addr, port: u16) {
@@ -0,0 +1,795 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Performance events, created with `perf_event_open(2)`.
+//!
+//! A perf event counts a hardware event (cycles, instructions, cache misses, ...) either for a
+//! single task or for everything that runs on a CPU. An event is only bound to one of the
+//! general purpose counters of the PMU while its target is running: the counters of a CPU are
+//! reassigned on every context switch by [`sched_switch`]. Events which do not fit into the
+//! available counters are not counted until a counter frees up. There is no multiplexing, so
+//! userland can compare `time_running` with `time_enabled` to find out how long an event was
+//! actually counted.
+//!
+//! Sampling events (`sample_period` is non-zero) program their counter to overflow after
+//! `sample_period` events and write a `PERF_RECORD_SAMPLE` record into the ring buffer mapped
+//! by userland on every overflow.
+//!
+//! ## Notes
+//! * Changes to an event that is currently counting on another CPU (e.g. disabling it) take effect
+//!   on the next context switch of that CPU.
+//! * The overflow interrupt is not an NMI, so code running with interrupts disabled is never
+//!   sampled.
+
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use aero_syscall::perf::*;
+use aero_syscall::{MMapFlags, SyscallError};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::{pmu, tls};
+use crate::fs::inode::{INodeInterface, MMapPage, PollFlags, PollTable};
+use crate::fs::{FileSystemError, Result};
+use crate::mem::paging::*;
+use crate::userland::scheduler::{self, hrtimer};
+use crate::userland::task::TaskId;
+use crate::utils::sync::{IrqGuard, Mutex, WaitQueue};
+use crate::utils::PerCpu;
+
+/// Sample fields that can be recorded.
+const SUPPORTED_SAMPLE_TYPE: u64 =
+    PERF_SAMPLE_IP | PERF_SAMPLE_TID | PERF_SAMPLE_TIME | PERF_SAMPLE_CPU | PERF_SAMPLE_PERIOD;
+
+const SUPPORTED_READ_FORMAT: u64 =
+    PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING | PERF_FORMAT_ID;
+
+/// Attribute flags that are accepted. `pinned` and `exclusive` only matter for multiplexing
+/// and there is no hypervisor to exclude, so they are ignored.
+const SUPPORTED_FLAGS: PerfEventAttrFlags = PerfEventAttrFlags::from_bits_truncate(
+    PerfEventAttrFlags::DISABLED.bits()
+        | PerfEventAttrFlags::PINNED.bits()
+        | PerfEventAttrFlags::EXCLUSIVE.bits()
+        | PerfEventAttrFlags::EXCLUDE_USER.bits()
+        | PerfEventAttrFlags::EXCLUDE_KERNEL.bits()
+        | PerfEventAttrFlags::EXCLUDE_HV.bits()
+        | PerfEventAttrFlags::WATERMARK.bits(),
+);
+
+/// The events bound to the counters of a CPU, indexed by counter.
+type Counters = Vec<Option<Weak<PerfEvent>>>;
+
+static COUNTERS: Once<PerCpu<Mutex<Counters>>> = Once::new();
+
+/// Every perf event. Dead events are pruned whenever a new event is registered.
+static EVENTS: Mutex<Vec<Weak<PerfEvent>>> = Mutex::new(Vec::new());
+
+/// Number of live perf events; context switches do not touch the counters while it is zero.
+static NR_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// What an event counts.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    /// The task; if a CPU is provided, only while it runs on that CPU.
+    Task(TaskId, Option<usize>),
+    /// Everything that runs on the CPU.
+    Cpu(usize),
+}
+
+impl Target {
+    fn matches(&self, cpu: usize, task: Option<TaskId>) -> bool {
+        match *self {
+            Target::Task(id, filter) => task == Some(id) && filter.map_or(true, |c| c == cpu),
+            Target::Cpu(target) => target == cpu,
+        }
+    }
+}
+
+/// The counter an event is bound to.
+#[derive(Debug, Copy, Clone)]
+struct Binding {
+    cpu: usize,
+    index: usize,
+    /// Value of the counter when the count of the event was last updated.
+    start: u64,
+}
+
+struct EventState {
+    enabled: bool,
+    count: u64,
+    period: u64,
+    binding: Option<Binding>,
+    /// The CPU on which the target of the event is running and since when.
+    scheduled: Option<(usize, u64)>,
+    time_enabled: u64,
+    time_running: u64,
+    /// Number of samples since the last wakeup of the readers of the ring buffer.
+    pending_wakeup: u32,
+}
+
+impl EventState {
+    /// Adds the events counted since the last update to the count of the event. Must be called
+    /// on the CPU the event is bound to.
+    fn update(&mut self) {
+        let Some(binding) = self.binding.as_mut() else {
+            return;
+        };
+
+        debug_assert_eq!(binding.cpu, tls::get_cpuid());
+
+        let value = pmu::read_counter(binding.index);
+        let mask = (1 << pmu::counter_width()) - 1;
+
+        self.count += value.wrapping_sub(binding.start) & mask;
+        binding.start = value;
+    }
+
+    /// Returns the enabled and running time of the event.
+    fn times(&self, now: u64) -> (u64, u64) {
+        let mut enabled = self.time_enabled;
+        let mut running = self.time_running;
+
+        if let Some((_, since)) = self.scheduled {
+            enabled += now - since;
+
+            if self.binding.is_some() {
+                running += now - since;
+            }
+        }
+
+        (enabled, running)
+    }
+
+    /// Stops accounting time to the event, as its target stopped running.
+    fn deschedule(&mut self, now: u64) {
+        let (enabled, running) = self.times(now);
+
+        self.time_enabled = enabled;
+        self.time_running = running;
+        self.scheduled = None;
+    }
+}
+
+/// A page of the ring buffer.
+struct RingPage(PhysFrame);
+
+impl RingPage {
+    fn new() -> Result<Self> {
+        let frame = FRAME_ALLOCATOR
+            .alloc_zeroed(Size4KiB::SIZE as usize)
+            .ok_or(FileSystemError::OutOfMemory)?;
+
+        // Take a reference to the frame, so it is not deallocated when the page is unmapped
+        // from a process.
+        frame.as_vm_frame().unwrap().inc_ref_count();
+        Ok(Self(PhysFrame::containing_address(frame)))
+    }
+}
+
+impl Drop for RingPage {
+    fn drop(&mut self) {
+        let vm_frame = self.0.start_address().as_vm_frame().unwrap();
+        vm_frame.dec_ref_count();
+
+        if vm_frame.ref_count() == 0 {
+            FRAME_ALLOCATOR.deallocate_frame(self.0);
+        }
+    }
+}
+
+/// The ring buffer of a sampling event: a [`PerfEventMmapPage`] followed by the data pages
+/// holding the records.
+struct RingBuffer {
+    pages: Vec<RingPage>,
+    /// Number of records that were dropped because the ring buffer was full.
+    lost: Mutex<u64>,
+}
+
+impl RingBuffer {
+    fn new(data_pages: usize) -> Result<Self> {
+        let pages = (0..=data_pages)
+            .map(|_| RingPage::new())
+            .collect::<Result<Vec<_>>>()?;
+
+        let this = Self {
+            pages,
+            lost: Mutex::new(0),
+        };
+
+        let header = this.header();
+
+        // SAFETY: The header page is not mapped by userland yet.
+        unsafe {
+            (*header).data_offset = Size4KiB::SIZE;
+            (*header).data_size = this.data_size() as u64;
+            (*header).pmc_width = pmu::counter_width() as u16;
+        }
+
+        Ok(this)
+    }
+
+    /// Returns a pointer to the header page. It is shared with userland, so the fields which
+    /// are written by the kernel after the ring buffer is mapped must be written volatile.
+    fn header(&self) -> *mut PerfEventMmapPage {
+        let address = self.pages[0].0.start_address().as_hhdm_virt();
+        address.as_mut_ptr::<PerfEventMmapPage>()
+    }
+
+    fn set_times(&self, enabled: u64, running: u64) {
+        let header = self.header();
+
+        // SAFETY: The header page is owned by the ring buffer.
+        unsafe {
+            addr_of_mut!((*header).time_enabled).write_volatile(enabled);
+            addr_of_mut!((*header).time_running).write_volatile(running);
+        }
+    }
+
+    fn data_head(&self) -> &AtomicU64 {
+        // SAFETY: The field is aligned and only accessed atomically.
+        unsafe { &*addr_of!((*self.header()).data_head).cast::<AtomicU64>() }
+    }
+
+    fn data_tail(&self) -> &AtomicU64 {
+        // SAFETY: The field is aligned and only accessed atomically.
+        unsafe { &*addr_of!((*self.header()).data_tail).cast::<AtomicU64>() }
+    }
+
+    fn data_size(&self) -> usize {
+        (self.pages.len() - 1) * Size4KiB::SIZE as usize
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data_head().load(Ordering::Acquire) == self.data_tail().load(Ordering::Acquire)
+    }
+
+    /// Returns the number of bytes that are not pending to be read by userland.
+    fn space(&self) -> usize {
+        let head = self.data_head().load(Ordering::Relaxed);
+        let tail = self.data_tail().load(Ordering::Acquire);
+
+        self.data_size()
+            .saturating_sub(head.wrapping_sub(tail) as usize)
+    }
+
+    fn copy(&self, record: &[u8]) {
+        let size = self.data_size();
+        let head = self.data_head().load(Ordering::Relaxed);
+
+        for (i, byte) in record.iter().enumerate() {
+            let offset = (head as usize + i) % size;
+            let page = &self.pages[1 + offset / Size4KiB::SIZE as usize];
+
+            page.0.as_slice_mut()[offset % Size4KiB::SIZE as usize] = *byte;
+        }
+
+        self.data_head()
+            .store(head + record.len() as u64, Ordering::Release);
+    }
+
+    /// Writes the record into the ring buffer. If it does not fit, it is dropped and a
+    /// `PERF_RECORD_LOST` record is written once there is space again.
+    fn write(&self, id: u64, record: &[u8]) -> bool {
+        let mut lost = self.lost.lock_irq();
+
+        let mut lost_record = Record::new(PERF_RECORD_LOST, 0);
+        lost_record.push(id);
+        lost_record.push(*lost);
+
+        let required = record.len() + if *lost != 0 { lost_record.len() } else { 0 };
+
+        if self.data_size() == 0 || self.space() < required {
+            *lost += 1;
+            return false;
+        }
+
+        if *lost != 0 {
+            self.copy(lost_record.as_bytes());
+            *lost = 0;
+        }
+
+        self.copy(record);
+        true
+    }
+}
+
+/// A record written to the ring buffer.
+struct Record {
+    buffer: [u8; 64],
+    len: usize,
+}
+
+impl Record {
+    fn new(typ: u32, misc: u16) -> Self {
+        let mut this = Self {
+            buffer: [0; 64],
+            len: core::mem::size_of::<PerfEventHeader>(),
+        };
+
+        this.buffer[..4].copy_from_slice(&typ.to_ne_bytes());
+        this.buffer[4..6].copy_from_slice(&misc.to_ne_bytes());
+        this
+    }
+
+    fn push(&mut self, value: u64) {
+        self.buffer[self.len..self.len + 8].copy_from_slice(&value.to_ne_bytes());
+        self.len += 8;
+    }
+
+    fn push_pair(&mut self, low: u32, high: u32) {
+        self.push(low as u64 | (high as u64) << 32);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn as_bytes(&mut self) -> &[u8] {
+        let len = self.len as u16;
+
+        self.buffer[6..8].copy_from_slice(&len.to_ne_bytes());
+        &self.buffer[..self.len]
+    }
+}
+
+pub struct PerfEvent {
+    id: u64,
+    attr: PerfEventAttr,
+    flags: PerfEventAttrFlags,
+    /// The event select programmed into the counter.
+    event: u64,
+    target: Target,
+    state: Mutex<EventState>,
+    ring: Once<RingBuffer>,
+    wq: WaitQueue,
+}
+
+impl PerfEvent {
+    /// Creates a new perf event described by `attr`, counting on `target`.
+    pub fn new(
+        attr: PerfEventAttr,
+        target: Target,
+    ) -> core::result::Result<Arc<Self>, SyscallError> {
+        let flags = PerfEventAttrFlags::from_bits(attr.flags).ok_or(SyscallError::EINVAL)?;
+
+        if !SUPPORTED_FLAGS.contains(flags)
+            || attr.sample_type & !SUPPORTED_SAMPLE_TYPE != 0
+            || attr.read_format & !SUPPORTED_READ_FORMAT != 0
+        {
+            return Err(SyscallError::EINVAL);
+        }
+
+        if pmu::num_counters() == 0 {
+            return Err(SyscallError::ENOENT);
+        }
+
+        let event = match attr.typ {
+            PERF_TYPE_HARDWARE => pmu::hardware_event(attr.config).ok_or(SyscallError::ENOENT)?,
+            PERF_TYPE_RAW => pmu::raw_event(attr.config).ok_or(SyscallError::EINVAL)?,
+            _ => return Err(SyscallError::ENOENT),
+        };
+
+        if attr.sample_period > pmu::max_period() {
+            return Err(SyscallError::EINVAL);
+        }
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let this = Arc::new(Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            attr,
+            flags,
+            event,
+            target,
+            state: Mutex::new(EventState {
+                enabled: false,
+                count: 0,
+                period: attr.sample_period,
+                binding: None,
+                scheduled: None,
+                time_enabled: 0,
+                time_running: 0,
+                pending_wakeup: 0,
+            }),
+            ring: Once::new(),
+            wq: WaitQueue::new(),
+        });
+
+        COUNTERS.call_once(|| PerCpu::new(|| Mutex::new(Vec::new())));
+        NR_EVENTS.fetch_add(1, Ordering::SeqCst);
+
+        {
+            let mut events = EVENTS.lock_irq();
+
+            events.retain(|event| event.strong_count() != 0);
+            events.push(Arc::downgrade(&this));
+        }
+
+        if !flags.contains(PerfEventAttrFlags::DISABLED) {
+            this.set_enabled(true);
+        }
+
+        Ok(this)
+    }
+
+    fn is_sampling(&self) -> bool {
+        self.attr.sample_period != 0
+    }
+
+    /// Enables or disables the event. If the event targets the current CPU, the change takes
+    /// effect immediately.
+    fn set_enabled(&self, enabled: bool) {
+        let _guard = IrqGuard::new();
+
+        self.state.lock_irq().enabled = enabled;
+
+        let task = scheduler::get_scheduler().inner.current_task_optional();
+        reschedule(tls::get_cpuid(), task.map(|task| task.tid()));
+    }
+
+    /// Returns the count and the enabled and running time of the event.
+    fn read(&self) -> (u64, u64, u64) {
+        let _guard = IrqGuard::new();
+        let mut state = self.state.lock_irq();
+
+        if state.binding.is_some_and(|b| b.cpu == tls::get_cpuid()) {
+            state.update();
+        }
+
+        let (enabled, running) = state.times(hrtimer::now());
+        (state.count, enabled, running)
+    }
+
+    fn reset(&self) {
+        let _guard = IrqGuard::new();
+        let mut state = self.state.lock_irq();
+
+        if state.binding.is_some_and(|b| b.cpu == tls::get_cpuid()) {
+            state.update();
+        }
+
+        state.count = 0;
+    }
+
+    /// Binds the event to the counter at `index` of the current CPU and starts counting.
+    fn bind(&self, state: &mut EventState, cpu: usize, index: usize) {
+        let start = state.period.wrapping_neg() & ((1 << pmu::counter_width()) - 1);
+
+        state.binding = Some(Binding { cpu, index, start });
+
+        unsafe {
+            pmu::start_counter(
+                index,
+                self.event,
+                !self.flags.contains(PerfEventAttrFlags::EXCLUDE_USER),
+                !self.flags.contains(PerfEventAttrFlags::EXCLUDE_KERNEL),
+                self.is_sampling(),
+                start,
+            );
+        }
+    }
+
+    /// Stops counting and releases the counter of the event.
+    fn unbind(&self, state: &mut EventState) {
+        if let Some(binding) = state.binding {
+            unsafe { pmu::stop_counter(binding.index) };
+
+            state.update();
+            state.binding = None;
+        }
+    }
+
+    /// Called when the counter of the event overflowed after `sample_period` events.
+    fn overflow(&self, ip: u64, user: bool) {
+        let mut state = self.state.lock_irq();
+        state.update();
+
+        let Some(binding) = state.binding else {
+            return;
+        };
+
+        let period = state.period;
+        let start = period.wrapping_neg() & ((1 << pmu::counter_width()) - 1);
+
+        unsafe { pmu::write_counter(binding.index, start) };
+        state.binding = Some(Binding { start, ..binding });
+
+        let Some(ring) = self.ring.get() else {
+            return;
+        };
+
+        let now = hrtimer::now();
+        let (enabled, running) = state.times(now);
+
+        ring.set_times(enabled, running);
+
+        let misc = if user {
+            PERF_RECORD_MISC_USER
+        } else {
+            PERF_RECORD_MISC_KERNEL
+        };
+
+        let mut record = Record::new(PERF_RECORD_SAMPLE, misc);
+        let sample_type = self.attr.sample_type;
+
+        if sample_type & PERF_SAMPLE_IP != 0 {
+            record.push(ip);
+        }
+
+        if sample_type & PERF_SAMPLE_TID != 0 {
+            let (pid, tid) = scheduler::get_scheduler()
+                .inner
+                .current_task_optional()
+                .map(|task| (task.pid().as_usize(), task.tid().as_usize()))
+                .unwrap_or((0, 0));
+
+            record.push_pair(pid as u32, tid as u32);
+        }
+
+        if sample_type & PERF_SAMPLE_TIME != 0 {
+            record.push(now);
+        }
+
+        if sample_type & PERF_SAMPLE_CPU != 0 {
+            record.push_pair(binding.cpu as u32, 0);
+        }
+
+        if sample_type & PERF_SAMPLE_PERIOD != 0 {
+            record.push(period);
+        }
+
+        if !ring.write(self.id, record.as_bytes()) {
+            return;
+        }
+
+        state.pending_wakeup += 1;
+
+        let wakeup = if self.flags.contains(PerfEventAttrFlags::WATERMARK) {
+            // `wakeup_events` is `wakeup_watermark`: the number of bytes to wait for, half of
+            // the ring buffer by default.
+            let watermark = match self.attr.wakeup_events as usize {
+                0 => ring.data_size() / 2,
+                watermark => watermark,
+            };
+
+            ring.data_size() - ring.space() >= watermark
+        } else {
+            state.pending_wakeup >= self.attr.wakeup_events.max(1)
+        };
+
+        if wakeup {
+            state.pending_wakeup = 0;
+            core::mem::drop(state);
+
+            self.wq.notify_all();
+        }
+    }
+}
+
+impl Drop for PerfEvent {
+    fn drop(&mut self) {
+        // The counter of the event, if any, is released by the next context switch of its CPU.
+        NR_EVENTS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl INodeInterface for PerfEvent {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (count, enabled, running) = self.read();
+        let format = self.attr.read_format;
+
+        let mut values = Vec::with_capacity(4);
+        values.push(count);
+
+        if format & PERF_FORMAT_TOTAL_TIME_ENABLED != 0 {
+            values.push(enabled);
+        }
+
+        if format & PERF_FORMAT_TOTAL_TIME_RUNNING != 0 {
+            values.push(running);
+        }
+
+        if format & PERF_FORMAT_ID != 0 {
+            values.push(self.id);
+        }
+
+        let size = values.len() * core::mem::size_of::<u64>();
+
+        if buffer.len() < size {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        for (chunk, value) in buffer.chunks_exact_mut(8).zip(values) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+
+        Ok(size)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        match command {
+            PERF_EVENT_IOC_ENABLE => self.set_enabled(true),
+            PERF_EVENT_IOC_DISABLE => self.set_enabled(false),
+            PERF_EVENT_IOC_RESET => self.reset(),
+
+            PERF_EVENT_IOC_PERIOD => {
+                let period = *VirtAddr::new(arg as u64).read_mut::<u64>()?;
+
+                if !self.is_sampling() || period == 0 || period > pmu::max_period() {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                // Takes effect on the next overflow.
+                self.state.lock_irq().period = period;
+            }
+
+            PERF_EVENT_IOC_ID => {
+                *VirtAddr::new(arg as u64).read_mut::<u64>()? = self.id;
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        match self.ring.get() {
+            Some(ring) if !ring.is_empty() => Ok(PollFlags::IN),
+            _ => Ok(PollFlags::empty()),
+        }
+    }
+
+    fn mmap_prepare(&self, offset: usize, size: usize, flags: MMapFlags) -> Result<()> {
+        let pages = size.div_ceil(Size4KiB::SIZE as usize);
+        let data_pages = pages
+            .checked_sub(1)
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        // The ring buffer is shared with the kernel and has to be mapped as a whole: the header
+        // page followed by a power of two number of data pages.
+        if offset != 0
+            || !flags.contains(MMapFlags::MAP_SHARED)
+            || !self.is_sampling()
+            || !(data_pages == 0 || data_pages.is_power_of_two())
+        {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let ring = self.ring.try_call_once(|| RingBuffer::new(data_pages))?;
+
+        if ring.pages.len() != pages {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
+        let ring = self.ring.get().ok_or(FileSystemError::InvalidArgument)?;
+        let page = ring
+            .pages
+            .get(offset / Size4KiB::SIZE as usize)
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        Ok(MMapPage::Direct(page.0))
+    }
+}
+
+/// Rebinds the counters of `cpu` to the events whose target is running on it: the events of
+/// `task` and the events of the CPU itself. Must be called on `cpu` with interrupts disabled.
+fn reschedule(cpu: usize, task: Option<TaskId>) {
+    let Some(counters) = COUNTERS.get() else {
+        return;
+    };
+
+    let now = hrtimer::now();
+    let mut wanted = Vec::new();
+
+    for event in EVENTS.lock_irq().iter().filter_map(Weak::upgrade) {
+        let mut state = event.state.lock_irq();
+        let is_wanted = state.enabled && event.target.matches(cpu, task);
+
+        match state.scheduled {
+            Some((scheduled_cpu, _)) if scheduled_cpu == cpu && !is_wanted => state.deschedule(now),
+
+            None if is_wanted => state.scheduled = Some((cpu, now)),
+            _ => {}
+        }
+
+        core::mem::drop(state);
+
+        if is_wanted {
+            wanted.push(event);
+        }
+    }
+
+    let mut counters = counters.get_cpu(cpu).lock_irq();
+    counters.resize(pmu::num_counters(), None);
+
+    // Release the counters of the events that are not wanted anymore.
+    for (index, slot) in counters.iter_mut().enumerate() {
+        let Some(bound) = slot.as_ref() else {
+            continue;
+        };
+
+        match bound.upgrade() {
+            Some(event) if wanted.iter().any(|e| Arc::ptr_eq(e, &event)) => {
+                // Keep the count of events that stay bound up to date, so they can be read from
+                // any CPU.
+                event.state.lock_irq().update();
+            }
+
+            Some(event) => {
+                event.unbind(&mut event.state.lock_irq());
+                *slot = None;
+            }
+
+            None => {
+                unsafe { pmu::stop_counter(index) };
+                *slot = None;
+            }
+        }
+    }
+
+    // Bind the wanted events that are not counting yet to the free counters.
+    for event in wanted {
+        let mut state = event.state.lock_irq();
+
+        if state.binding.is_some() {
+            continue;
+        }
+
+        let Some(index) = counters.iter().position(Option::is_none) else {
+            break;
+        };
+
+        event.bind(&mut state, cpu, index);
+        counters[index] = Some(Arc::downgrade(&event));
+    }
+}
+
+/// Called by the scheduler before switching to `next` on the current CPU; [`None`] if the CPU
+/// is going idle.
+pub fn sched_switch(next: Option<TaskId>) {
+    if NR_EVENTS.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    reschedule(tls::get_cpuid(), next);
+}
+
+/// Called by the overflow interrupt handler of the PMU when the counter at `index` of the
+/// current CPU overflowed. `ip` is the instruction pointer of the interrupted code.
+pub fn counter_overflow(index: usize, ip: u64, user: bool) {
+    let event = COUNTERS
+        .get()
+        .and_then(|counters| counters.get().lock_irq().get(index).cloned().flatten())
+        .and_then(|event| event.upgrade());
+
+    match event {
+        Some(event) => event.overflow(ip, user),
+        // The event was closed while it was bound to the counter.
+        None => unsafe { pmu::stop_counter(index) },
+    }
+}
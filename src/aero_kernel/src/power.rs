@@ -0,0 +1,110 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Power management of devices for suspend-to-RAM.
+//!
+//! Drivers register a [`PowerOps`] implementation for each device that loses its state when the
+//! machine sleeps. Before entering the sleep state, the devices are suspended in the reverse
+//! order of registration, so that a device is suspended before the bus it is on. They are
+//! resumed in registration order after waking up.
+//!
+//! The secondary CPUs are taken offline before suspending the devices, so the machine only
+//! sleeps (and wakes up) on the BSP.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::arch;
+use crate::userland::scheduler::hotplug;
+use crate::utils::sync::{BMutex, Mutex};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SuspendError {
+    /// The machine (or a device) cannot be suspended.
+    NotSupported,
+    /// A device is in use and cannot be suspended right now.
+    Busy,
+}
+
+pub trait PowerOps: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Saves the state of the device and quiesces it. Called with interrupts enabled.
+    fn suspend(&self) -> Result<(), SuspendError>;
+    /// Restores the state saved by [`PowerOps::suspend`] after waking up.
+    fn resume(&self);
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn PowerOps>>> = Mutex::new(Vec::new());
+/// Serializes suspend requests.
+static SUSPEND_LOCK: BMutex<()> = BMutex::new(());
+
+pub fn register(device: Arc<dyn PowerOps>) {
+    DEVICES.lock_irq().push(device);
+}
+
+/// Returns whether the machine can be suspended to RAM.
+pub fn is_supported() -> bool {
+    arch::suspend::is_supported()
+}
+
+/// Resumes `devices` in registration order.
+fn resume_devices(devices: &[Arc<dyn PowerOps>]) {
+    for device in devices {
+        log::debug!("power: resuming {}", device.name());
+        device.resume();
+    }
+}
+
+/// Suspends the devices, puts the machine to sleep and resumes the devices after it woke up.
+pub fn suspend_to_ram() -> Result<(), SuspendError> {
+    if !is_supported() {
+        return Err(SuspendError::NotSupported);
+    }
+
+    let _guard = SUSPEND_LOCK.lock();
+
+    let cpus = hotplug::offline_secondary_cpus().map_err(|error| {
+        log::warn!("power: failed to take the secondary CPUs offline ({error:?})");
+        SuspendError::NotSupported
+    })?;
+
+    let result = suspend_devices_and_enter();
+
+    hotplug::online_cpus(&cpus);
+    result
+}
+
+fn suspend_devices_and_enter() -> Result<(), SuspendError> {
+    let devices = DEVICES.lock_irq().clone();
+
+    for (i, device) in devices.iter().enumerate().rev() {
+        log::debug!("power: suspending {}", device.name());
+
+        if let Err(error) = device.suspend() {
+            log::warn!("power: failed to suspend {} ({error:?})", device.name());
+
+            resume_devices(&devices[i + 1..]);
+            return Err(error);
+        }
+    }
+
+    let result = arch::suspend::enter_s3();
+
+    resume_devices(&devices);
+    result
+}
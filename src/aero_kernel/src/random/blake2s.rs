@@ -0,0 +1,175 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! BLAKE2s hash function (RFC 7693), used to mix the entropy pool and to derive keys.
+
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+pub const BLOCK_SIZE: usize = 64;
+pub const HASH_SIZE: usize = 32;
+pub const KEY_SIZE: usize = 32;
+
+#[derive(Clone)]
+pub struct Blake2s {
+    h: [u32; 8],
+    /// Number of bytes compressed so far.
+    t: u64,
+    buffer: [u8; BLOCK_SIZE],
+    buffered: usize,
+}
+
+impl Blake2s {
+    /// Creates a new BLAKE2s state producing a [`HASH_SIZE`] byte digest, keyed with `key` if it
+    /// is not empty.
+    pub fn new(key: &[u8]) -> Self {
+        assert!(key.len() <= KEY_SIZE);
+
+        let mut this = Self::unkeyed();
+        this.h[0] ^= (key.len() as u32) << 8;
+
+        if !key.is_empty() {
+            // The key is padded with zeros to a full block.
+            this.buffer[..key.len()].copy_from_slice(key);
+            this.buffered = BLOCK_SIZE;
+        }
+
+        this
+    }
+
+    /// Creates a new unkeyed BLAKE2s state producing a [`HASH_SIZE`] byte digest.
+    pub const fn unkeyed() -> Self {
+        let mut h = IV;
+        h[0] ^= 0x0101_0000 ^ HASH_SIZE as u32;
+
+        Self {
+            h,
+            t: 0,
+            buffer: [0; BLOCK_SIZE],
+            buffered: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // The last block has to be compressed with the finalization flag set, so a full
+            // buffer is only compressed once more data arrives.
+            if self.buffered == BLOCK_SIZE {
+                self.t += BLOCK_SIZE as u64;
+                self.compress(false);
+                self.buffered = 0;
+            }
+
+            let count = core::cmp::min(BLOCK_SIZE - self.buffered, data.len());
+
+            self.buffer[self.buffered..self.buffered + count].copy_from_slice(&data[..count]);
+            self.buffered += count;
+            data = &data[count..];
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; HASH_SIZE] {
+        self.t += self.buffered as u64;
+        self.buffer[self.buffered..].fill(0);
+        self.compress(true);
+
+        let mut digest = [0; HASH_SIZE];
+
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.h.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        digest
+    }
+
+    fn compress(&mut self, last: bool) {
+        let mut m = [0u32; 16];
+
+        for (word, chunk) in m.iter_mut().zip(self.buffer.chunks_exact(4)) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+
+        let mut v = [0u32; 16];
+
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+
+        v[12] ^= self.t as u32;
+        v[13] ^= (self.t >> 32) as u32;
+
+        if last {
+            v[14] = !v[14];
+        }
+
+        for s in SIGMA.iter() {
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+impl Drop for Blake2s {
+    fn drop(&mut self) {
+        // Do not leave key material behind.
+        self.h.fill(0);
+        self.buffer.fill(0);
+    }
+}
+
+#[inline(always)]
+fn g(v: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(12);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(8);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(7);
+}
+
+/// Computes the keyed BLAKE2s hash of `data`.
+pub fn hash(key: &[u8], data: &[u8]) -> [u8; HASH_SIZE] {
+    let mut state = Blake2s::new(key);
+    state.update(data);
+    state.finalize()
+}
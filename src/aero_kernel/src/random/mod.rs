@@ -0,0 +1,316 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel Random Number Generator
+//!
+//! Entropy from hardware random number generators (RDSEED/RDRAND and virtio-rng), the timing
+//! of interrupts and other unpredictable events is mixed into the input pool, which is a
+//! running BLAKE2s hash. Each input is credited with an estimate of the entropy it contains.
+//! The input pool is never used to produce output directly; instead, a seed is extracted from
+//! it to rekey the CRNG. Extracting a seed also replaces the pool state, so earlier pool
+//! contents cannot be recovered from a later state.
+//!
+//! The CRNG is considered initialized once it was seeded from a pool that was credited with
+//! [`POOL_READY_BITS`] of entropy. Afterwards, it is reseeded from the input pool every
+//! [`CRNG_RESEED_INTERVAL`] nanoseconds. Reads from `/dev/random` and `getrandom()` block until
+//! the CRNG is initialized, while reads from `/dev/urandom` never block.
+//!
+//! Output is generated with ChaCha20 using "fast key erasure": every request first generates
+//! one block with the CRNG key, whose first half replaces the CRNG key and whose second half
+//! is used as the key for the output of the request. Compromising the kernel memory therefore
+//! does not reveal previously generated output.
+//!
+//! ## Notes
+//! * <https://blog.cr.yp.to/20170723-random.html>
+
+pub mod blake2s;
+pub mod chacha20;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::userland::signals::SignalResult;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use self::blake2s::Blake2s;
+
+/// Maximum number of bits of entropy the input pool can hold.
+const POOL_BITS: usize = blake2s::HASH_SIZE * 8;
+
+/// Number of bits of entropy that have to be credited to the input pool before the CRNG is
+/// considered initialized.
+const POOL_READY_BITS: usize = 256;
+
+/// Interval (in nanoseconds) between two reseeds of the initialized CRNG.
+const CRNG_RESEED_INTERVAL: u64 = 60 * 1_000_000_000;
+
+/// Number of interrupts collected by the fast pool before it is mixed into the input pool,
+/// which credits it with one bit of entropy.
+const FAST_POOL_INTERRUPTS: usize = 64;
+
+struct InputPool {
+    hash: Blake2s,
+    /// Number of bits of entropy credited since the last extraction.
+    entropy: usize,
+}
+
+struct Crng {
+    key: [u8; chacha20::KEY_SIZE],
+    /// Uptime (in nanoseconds) of the last reseed.
+    last_reseed: u64,
+}
+
+static INPUT_POOL: Mutex<InputPool> = Mutex::new(InputPool {
+    hash: Blake2s::unkeyed(),
+    entropy: 0,
+});
+
+static CRNG: Mutex<Crng> = Mutex::new(Crng {
+    key: [0; chacha20::KEY_SIZE],
+    last_reseed: 0,
+});
+
+/// Set once the CRNG has been seeded with at least [`POOL_READY_BITS`] of entropy.
+static CRNG_READY: AtomicBool = AtomicBool::new(false);
+/// Tasks waiting for the CRNG to be initialized.
+static CRNG_WQ: WaitQueue = WaitQueue::new();
+
+/// Timing of interrupts that were not mixed into the input pool yet. Mixing every interrupt
+/// into the input pool would be too expensive, so they are collected here first.
+static FAST_POOL: AtomicU64 = AtomicU64::new(0);
+static FAST_POOL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a random number from the CPU's hardware random number generator, if any.
+#[cfg(target_arch = "x86_64")]
+fn arch_random() -> Option<u64> {
+    crate::arch::rdseed().or_else(crate::arch::rdrand)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn arch_random() -> Option<u64> {
+    None
+}
+
+/// Returns a high resolution timestamp.
+#[cfg(target_arch = "x86_64")]
+fn timestamp() -> u64 {
+    // SAFETY: Reading the time stamp counter has no side effects.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn timestamp() -> u64 {
+    crate::arch::time::get_uptime_ns()
+}
+
+/// Mixes `data` into the input pool without crediting any entropy. Used for data that is
+/// unique to the machine but possibly known to an attacker (e.g. hardware addresses) and for
+/// data written to `/dev/random` by userland.
+pub fn add_device_randomness(data: &[u8]) {
+    mix_pool(data, 0);
+}
+
+/// Mixes `data` from a hardware random number generator into the input pool and credits
+/// `entropy` bits of entropy to it.
+pub fn add_hwgenerator_randomness(data: &[u8], entropy: usize) {
+    if mix_pool(data, entropy) && !is_ready() {
+        reseed();
+    }
+}
+
+/// Mixes the timing of an interrupt into the input pool. Called for every external interrupt
+/// with interrupts disabled.
+pub fn add_interrupt_randomness(vector: usize) {
+    let cycles = timestamp();
+
+    // NOTE: Concurrent updates from other CPUs may overwrite each other, which only loses some
+    // of the collected timings.
+    let value =
+        FAST_POOL.load(Ordering::Relaxed).rotate_left(23) ^ cycles ^ ((vector as u64) << 56);
+    let value = value.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+    FAST_POOL.store(value, Ordering::Relaxed);
+
+    if FAST_POOL_COUNT.fetch_add(1, Ordering::Relaxed) + 1 < FAST_POOL_INTERRUPTS {
+        return;
+    }
+
+    FAST_POOL_COUNT.store(0, Ordering::Relaxed);
+
+    let mut data = [0u8; 16];
+
+    data[..8].copy_from_slice(&value.to_ne_bytes());
+    data[8..].copy_from_slice(&cycles.to_ne_bytes());
+
+    // The input pool and the CRNG are only ever locked with interrupts disabled, so they are
+    // never held by the interrupted code.
+    if mix_pool(&data, 1) && !is_ready() {
+        reseed();
+    }
+}
+
+/// Mixes `data` into the input pool and credits `entropy` bits of entropy to it. Returns
+/// whether enough entropy is available to initialize the CRNG.
+fn mix_pool(data: &[u8], entropy: usize) -> bool {
+    let mut pool = INPUT_POOL.lock_irq();
+
+    pool.hash.update(data);
+    pool.entropy = core::cmp::min(pool.entropy.saturating_add(entropy), POOL_BITS);
+    pool.entropy >= POOL_READY_BITS
+}
+
+/// Mixes the output of the CPU's hardware random number generator into the input pool.
+fn add_arch_randomness() {
+    let mut seed = [0u8; blake2s::KEY_SIZE];
+    let mut entropy = 0;
+
+    for chunk in seed.chunks_exact_mut(8) {
+        let Some(value) = arch_random() else {
+            break;
+        };
+
+        chunk.copy_from_slice(&value.to_ne_bytes());
+        entropy += 64;
+    }
+
+    mix_pool(&seed, entropy);
+}
+
+/// Returns the number of bits of entropy currently credited to the input pool.
+pub fn entropy_count() -> usize {
+    INPUT_POOL.lock_irq().entropy
+}
+
+/// Credits `entropy` bits of entropy to the input pool without mixing in any data. Only
+/// used by privileged userland that has already written the data to `/dev/random`.
+pub fn credit_entropy(entropy: usize) {
+    if mix_pool(&[], entropy) && !is_ready() {
+        reseed();
+    }
+}
+
+/// Extracts a seed from the input pool and replaces the pool state with a value derived
+/// from it. Returns the seed and the number of bits of entropy it was credited with.
+fn extract_seed() -> ([u8; blake2s::HASH_SIZE], usize) {
+    let mut pool = INPUT_POOL.lock_irq();
+
+    let digest = pool.hash.clone().finalize();
+    let entropy = core::mem::take(&mut pool.entropy);
+
+    pool.hash = Blake2s::unkeyed();
+    pool.hash.update(&blake2s::hash(&digest, b"input pool"));
+
+    (blake2s::hash(&digest, b"crng seed"), entropy)
+}
+
+/// Reseeds the CRNG from the input pool, after mixing in fresh output of the CPU's hardware
+/// random number generator.
+pub fn reseed() {
+    add_arch_randomness();
+    add_device_randomness(&timestamp().to_ne_bytes());
+
+    let (seed, entropy) = extract_seed();
+
+    let initialized = {
+        let mut crng = CRNG.lock_irq();
+
+        crng.key = blake2s::hash(&seed, &crng.key);
+        crng.last_reseed = crate::arch::time::get_uptime_ns();
+
+        // NOTE: The flag is set with the CRNG locked, since waiters check it with the CRNG
+        // locked (see [`wait_for_ready`]).
+        entropy >= POOL_READY_BITS && !CRNG_READY.swap(true, Ordering::AcqRel)
+    };
+
+    if initialized {
+        log::info!("random: crng initialized");
+        CRNG_WQ.notify_all();
+    }
+}
+
+/// Returns whether the CRNG has been seeded with enough entropy.
+pub fn is_ready() -> bool {
+    CRNG_READY.load(Ordering::Acquire)
+}
+
+/// Blocks the current task until the CRNG is initialized.
+pub fn wait_for_ready() -> SignalResult<()> {
+    if !is_ready() {
+        CRNG_WQ.block_on(&CRNG, |_| is_ready())?;
+    }
+
+    Ok(())
+}
+
+/// Returns the wait queue that is notified once the CRNG is initialized.
+pub fn wait_queue() -> &'static WaitQueue {
+    &CRNG_WQ
+}
+
+/// Fills `buffer` with random bytes from the CRNG. The bytes are only cryptographically
+/// secure if the CRNG is initialized (see [`is_ready`]); use [`wait_for_ready`] first if that
+/// is required.
+pub fn fill_bytes(buffer: &mut [u8]) {
+    if buffer.is_empty() {
+        return;
+    }
+
+    let reseed_due = is_ready() && {
+        let crng = CRNG.lock_irq();
+        let now = crate::arch::time::get_uptime_ns();
+
+        now.saturating_sub(crng.last_reseed) >= CRNG_RESEED_INTERVAL
+    };
+
+    if reseed_due {
+        reseed();
+    }
+
+    let mut block = [0u8; chacha20::BLOCK_SIZE];
+    let mut key = [0u8; chacha20::KEY_SIZE];
+
+    // Fast key erasure: replace the CRNG key before any output is generated. The output is
+    // generated without holding the lock.
+    {
+        let mut crng = CRNG.lock_irq();
+
+        chacha20::block(&crng.key, 0, 0, &mut block);
+
+        crng.key.copy_from_slice(&block[..chacha20::KEY_SIZE]);
+        key.copy_from_slice(&block[chacha20::KEY_SIZE..]);
+    }
+
+    for (counter, chunk) in buffer.chunks_mut(chacha20::BLOCK_SIZE).enumerate() {
+        chacha20::block(&key, counter as u64, 0, &mut block);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+
+    block.fill(0);
+    key.fill(0);
+}
+
+/// Seeds the CRNG from the CPU's hardware random number generator (if any) and the boot
+/// time. Further entropy is added by hardware random number generator drivers and
+/// interrupts.
+pub fn init() {
+    add_device_randomness(&timestamp().to_ne_bytes());
+
+    if arch_random().is_none() {
+        log::warn!("random: no CPU random number generator available");
+    }
+
+    reseed();
+}
@@ -30,7 +30,7 @@ use vte::ansi::{Handler, NamedColor, Timeout};
 
 use crate::cmdline::CommandLine;
 use crate::mem;
-use crate::mem::paging::align_up;
+use crate::mem::paging::{self, align_up, PhysAddr, VirtAddr};
 
 use crate::utils::sync::Mutex;
 
@@ -127,10 +127,10 @@ pub struct RendyInfo {
     pub pixel_format: PixelFormat,
     /// The number of bits per pixel.
     pub bits_per_pixel: usize,
-    /// Number of pixels between the start of a line and the start of the next.
+    /// Number of bytes between the start of a line and the start of the next.
     ///
     /// Some framebuffers use additional padding at the end of a line, so this
-    /// value might be larger than `horizontal_resolution`. It is
+    /// value might be larger than `horizontal_resolution` times the size of a pixel. It is
     /// therefore recommended to use this field for calculating the start address of a line.
     pub stride: usize,
 
@@ -277,10 +277,26 @@ impl Index<usize> for ColorList {
     }
 }
 
+/// A rectangle of the screen that was drawn on since the shadow buffer was last copied to the
+/// framebuffer.
+#[derive(Debug, Copy, Clone)]
+struct Damage {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
 pub struct Inner<'this> {
     buffer: &'this mut [u32],
     info: RendyInfo,
 
+    /// Everything is drawn on the shadow buffer (in normal memory) and the damaged part of it
+    /// is copied to the framebuffer by [`Inner::present`], which is much faster than drawing
+    /// on video memory directly and does not show partially drawn frames.
+    shadow: Box<[u32]>,
+    damage: Option<Damage>,
+
     x_pos: usize,
     y_pos: usize,
 
@@ -297,6 +313,7 @@ pub struct Inner<'this> {
     grid: Box<[Character]>,
     map: Box<[Option<NonNull<QueueCharacter>>]>,
     bg_canvas: Box<[u32]>,
+    background: Option<Image>,
 
     queue_cursor: usize,
 
@@ -376,7 +393,7 @@ impl<'a> Inner<'a> {
                 let i = blender(x, y, u32::from_le_bytes(img_pixel));
 
                 unsafe {
-                    *self.buffer.as_mut_ptr().add(fb_off + x) = i as u32;
+                    *self.shadow.as_mut_ptr().add(fb_off + x) = i as u32;
                     self.bg_canvas[canvas_off + x] = i as u32;
                 }
 
@@ -426,7 +443,7 @@ impl<'a> Inner<'a> {
         });
     }
 
-    fn generate_canvas(&mut self, image: Option<Image>) {
+    fn generate_canvas(&mut self, image: Option<&Image>) {
         let width = self.info.horizontal_resolution;
         let height = self.info.vertical_resolution;
 
@@ -443,12 +460,12 @@ impl<'a> Inner<'a> {
             let fwidth = frame_width - MARGIN_GRADIENT;
             let fwidth_end = frame_width_end + MARGIN_GRADIENT;
 
-            self.loop_external(&image, 0, width, 0, fheight);
-            self.loop_external(&image, 0, width, fheight_end, height);
-            self.loop_external(&image, 0, fwidth, fheight, fheight_end);
-            self.loop_external(&image, fwidth_end, width, fheight, fheight_end);
+            self.loop_external(image, 0, width, 0, fheight);
+            self.loop_external(image, 0, width, fheight_end, height);
+            self.loop_external(image, 0, fwidth, fheight, fheight_end);
+            self.loop_external(image, fwidth_end, width, fheight, fheight_end);
             self.loop_internal(
-                &image,
+                image,
                 frame_width,
                 frame_width_end,
                 frame_height,
@@ -462,6 +479,47 @@ impl<'a> Inner<'a> {
                 }
             }
         }
+
+        self.add_damage(0, 0, width, height);
+    }
+
+    /// Regenerates the background canvas, using the terminal background image if one was
+    /// provided on the kernel command line.
+    fn redraw_background(&mut self) {
+        let image = self.background.take();
+
+        self.generate_canvas(image.as_ref());
+        self.background = image;
+    }
+
+    /// Switches the terminal to a new framebuffer, recomputing the text grid for its geometry.
+    /// The contents of the screen are cleared and the cursor is moved to the origin.
+    fn resize(&mut self, buffer: &'a mut [u32], info: RendyInfo) {
+        let (offset_x, offset_y, rows, cols) = text_layout(&info);
+
+        self.buffer = buffer;
+        self.info = info;
+
+        self.offset_x = offset_x;
+        self.offset_y = offset_y;
+        self.rows = rows;
+        self.cols = cols;
+
+        self.grid = mem::alloc_boxed_buffer::<Character>(rows * cols);
+        self.queue = mem::alloc_boxed_buffer::<QueueCharacter>(rows * cols);
+        self.map = mem::alloc_boxed_buffer::<Option<NonNull<QueueCharacter>>>(rows * cols);
+        self.bg_canvas =
+            mem::alloc_boxed_buffer::<u32>(info.horizontal_resolution * info.vertical_resolution);
+        self.shadow = mem::alloc_boxed_buffer::<u32>(info.byte_len / DWORD_SIZE);
+        self.damage = None;
+
+        self.queue_cursor = 0;
+        self.old_x_pos = 0;
+        self.old_y_pos = 0;
+
+        self.redraw_background();
+        self.clear(true);
+        self.double_buffer_flush();
     }
 
     /// Plots a pixel at the given coordinates with the provided colour.
@@ -471,7 +529,46 @@ impl<'a> Inner<'a> {
         }
 
         let offset = x + (self.info.stride / DWORD_SIZE) * y;
-        self.buffer[offset] = colour;
+        self.shadow[offset] = colour;
+    }
+
+    /// Marks the `width` by `height` pixels at the given coordinates as damaged, so they are
+    /// copied to the framebuffer by the next [`Inner::present`].
+    fn add_damage(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let x1 = core::cmp::min(x + width, self.info.horizontal_resolution);
+        let y1 = core::cmp::min(y + height, self.info.vertical_resolution);
+
+        self.damage = Some(match self.damage {
+            Some(damage) => Damage {
+                x0: damage.x0.min(x),
+                y0: damage.y0.min(y),
+                x1: damage.x1.max(x1),
+                y1: damage.y1.max(y1),
+            },
+
+            None => Damage {
+                x0: x,
+                y0: y,
+                x1,
+                y1,
+            },
+        });
+    }
+
+    /// Copies the damaged part of the shadow buffer to the framebuffer.
+    fn present(&mut self) {
+        let Some(damage) = self.damage.take() else {
+            return;
+        };
+
+        let pitch = self.info.stride / DWORD_SIZE;
+
+        for y in damage.y0..damage.y1 {
+            let line = y * pitch;
+            let range = line + damage.x0..line + damage.x1;
+
+            self.buffer[range.clone()].copy_from_slice(&self.shadow[range]);
+        }
     }
 
     fn push_to_queue(&mut self, char: &Character, x: usize, y: usize) {
@@ -560,9 +657,11 @@ impl<'a> Inner<'a> {
         let glyph = &FONT[ch as usize];
 
         // naming: fx, fy for font coordinates and gx, gy for glyph coordinates
+        self.add_damage(x, y, FONT_WIDTH, FONT_HEIGHT);
+
         for (gy, glyph) in glyph.iter().enumerate().take(FONT_HEIGHT) {
             let fb_line = unsafe {
-                self.buffer
+                self.shadow
                     .as_mut_ptr()
                     .add(x + (y + gy) * (self.info.stride / 4))
             };
@@ -595,6 +694,24 @@ impl<'a> Inner<'a> {
             self.draw_cursor();
         }
 
+        self.draw_queue();
+
+        if self.old_x_pos != self.x_pos || self.old_y_pos != self.y_pos {
+            self.plot_char(
+                self.old_x_pos,
+                self.old_y_pos,
+                self.grid[self.old_x_pos + self.old_y_pos * self.cols],
+            );
+        }
+
+        self.old_x_pos = self.x_pos;
+        self.old_y_pos = self.y_pos;
+
+        self.present();
+    }
+
+    /// Draws the queued characters on the shadow buffer.
+    fn draw_queue(&mut self) {
         for i in 0..self.queue_cursor {
             let queue = self.queue[i].clone();
             let offset = queue.y * self.cols + queue.x;
@@ -609,17 +726,6 @@ impl<'a> Inner<'a> {
             self.map[offset] = None;
         }
 
-        if self.old_x_pos != self.x_pos || self.old_y_pos != self.y_pos {
-            self.plot_char(
-                self.old_x_pos,
-                self.old_y_pos,
-                self.grid[self.old_x_pos + self.old_y_pos * self.cols],
-            );
-        }
-
-        self.old_x_pos = self.x_pos;
-        self.old_y_pos = self.y_pos;
-
         self.queue_cursor = 0;
     }
 
@@ -677,23 +783,29 @@ impl<'a> Inner<'a> {
     }
 
     fn scroll(&mut self) {
-        for i in self.cols..self.rows * self.cols {
-            let queue = self.map[i];
-            let res;
-
-            if let Some(char) = queue {
-                unsafe {
-                    res = char.as_ref().char;
+        if self.background.is_none() {
+            self.scroll_pixels();
+        } else {
+            // The background image does not move along with the text, so every character has
+            // to be drawn again.
+            for i in self.cols..self.rows * self.cols {
+                let queue = self.map[i];
+                let res;
+
+                if let Some(char) = queue {
+                    unsafe {
+                        res = char.as_ref().char;
+                    }
+                } else {
+                    res = self.grid[i];
                 }
-            } else {
-                res = self.grid[i];
-            }
 
-            self.push_to_queue(
-                &res,
-                (i - self.cols) % self.cols,
-                (i - self.cols) / self.cols,
-            );
+                self.push_to_queue(
+                    &res,
+                    (i - self.cols) % self.cols,
+                    (i - self.cols) / self.cols,
+                );
+            }
         }
 
         // Clear the last line of the screen.
@@ -708,6 +820,34 @@ impl<'a> Inner<'a> {
         }
     }
 
+    /// Scrolls the text up by a line by moving the pixels of the text area, which is much
+    /// faster than drawing every character again. Only used if the background is a solid
+    /// color.
+    fn scroll_pixels(&mut self) {
+        self.draw_queue();
+
+        // The cursor would move along with the text, so erase it first.
+        self.plot_char(
+            self.old_x_pos,
+            self.old_y_pos,
+            self.grid[self.old_x_pos + self.old_y_pos * self.cols],
+        );
+
+        self.old_x_pos = self.x_pos;
+        self.old_y_pos = self.y_pos;
+
+        let pitch = self.info.stride / DWORD_SIZE;
+        let top = self.offset_y * pitch;
+        let bottom = (self.offset_y + self.rows * FONT_HEIGHT) * pitch;
+
+        self.shadow
+            .copy_within(top + FONT_HEIGHT * pitch..bottom, top);
+        self.grid.copy_within(self.cols.., 0);
+
+        let width = self.info.horizontal_resolution;
+        self.add_damage(0, self.offset_y, width, self.rows * FONT_HEIGHT);
+    }
+
     fn set_cursor_position(&mut self, x: usize, y: usize) {
         assert!(x <= self.cols && y <= self.rows);
 
@@ -729,22 +869,22 @@ impl<'this> DebugRendy<'this> {
         let width = info.horizontal_resolution;
         let height = info.vertical_resolution;
 
-        let offset_x = DEFAULT_MARGIN + ((width - DEFAULT_MARGIN * 2) % FONT_WIDTH) / 2;
-        let offset_y = DEFAULT_MARGIN + ((height - DEFAULT_MARGIN * 2) % FONT_HEIGHT) / 2;
-
-        let cols = (width - DEFAULT_MARGIN * 2) / FONT_WIDTH;
-        let rows = (height - DEFAULT_MARGIN * 2) / FONT_HEIGHT;
+        let (offset_x, offset_y, rows, cols) = text_layout(&info);
 
         let grid = mem::alloc_boxed_buffer::<Character>(rows * cols);
         let queue = mem::alloc_boxed_buffer::<QueueCharacter>(rows * cols);
         let map = mem::alloc_boxed_buffer::<Option<NonNull<QueueCharacter>>>(rows * cols);
         let bg_canvas = mem::alloc_boxed_buffer::<u32>(width * height);
+        let shadow = mem::alloc_boxed_buffer::<u32>(info.byte_len / DWORD_SIZE);
 
         let mut this = Self {
             inner: Inner {
                 buffer,
                 info,
 
+                shadow,
+                damage: None,
+
                 x_pos: 0,
                 y_pos: 0,
 
@@ -761,6 +901,7 @@ impl<'this> DebugRendy<'this> {
                 grid,
                 map,
                 bg_canvas,
+                background: cmdline.term_background.map(parse_bmp_image),
 
                 queue_cursor: 0,
 
@@ -775,9 +916,7 @@ impl<'this> DebugRendy<'this> {
             performer: Processor::new(),
         };
 
-        let image = cmdline.term_background.map(parse_bmp_image);
-
-        this.generate_canvas(image);
+        this.redraw_background();
         this.clear(true);
         this.double_buffer_flush();
 
@@ -785,6 +924,21 @@ impl<'this> DebugRendy<'this> {
     }
 }
 
+/// Returns the `(offset_x, offset_y, rows, columns)` of the text grid for a framebuffer
+/// with the provided geometry.
+fn text_layout(info: &RendyInfo) -> (usize, usize, usize, usize) {
+    let width = info.horizontal_resolution;
+    let height = info.vertical_resolution;
+
+    let offset_x = DEFAULT_MARGIN + ((width - DEFAULT_MARGIN * 2) % FONT_WIDTH) / 2;
+    let offset_y = DEFAULT_MARGIN + ((height - DEFAULT_MARGIN * 2) % FONT_HEIGHT) / 2;
+
+    let cols = (width - DEFAULT_MARGIN * 2) / FONT_WIDTH;
+    let rows = (height - DEFAULT_MARGIN * 2) / FONT_HEIGHT;
+
+    (offset_x, offset_y, rows, cols)
+}
+
 impl<'a> core::ops::Deref for DebugRendy<'a> {
     type Target = Inner<'a>;
 
@@ -925,7 +1079,16 @@ pub fn is_initialized() -> bool {
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    DEBUG_RENDY.get().map(|l| l.lock_irq().write_fmt(args));
+    if let Some(l) = DEBUG_RENDY.get() {
+        let mut this = l.lock_irq();
+
+        // Present the whole message at once, instead of after each character.
+        this.auto_flush = false;
+        let _ = this.write_fmt(args);
+        this.auto_flush = true;
+
+        this.double_buffer_flush();
+    }
 }
 
 /// Clears the screen and if `mv` is set to true, resets the
@@ -973,6 +1136,40 @@ pub fn get_rendy_info() -> RendyInfo {
         .expect("get_rendy_info: invoked before the terminal was initialized")
 }
 
+/// Returns the physical address of the framebuffer the terminal is drawn on.
+///
+/// # Panics
+/// This function was called before the terminal was initialized.
+pub fn get_framebuffer_address() -> PhysAddr {
+    DEBUG_RENDY
+        .get()
+        .map(|l| {
+            let this = l.lock_irq();
+            VirtAddr::new(this.buffer.as_ptr() as u64).as_hhdm_phys()
+        })
+        .expect("get_framebuffer_address: invoked before the terminal was initialized")
+}
+
+/// Moves the terminal onto the framebuffer at `address`, described by `info`. This is used after
+/// the video mode has been changed; the screen is cleared.
+///
+/// # Panics
+/// This function was called before the terminal was initialized.
+pub fn set_framebuffer(address: PhysAddr, info: RendyInfo) {
+    let framebuffer = unsafe {
+        core::slice::from_raw_parts_mut::<u32>(
+            address.as_hhdm_virt().as_mut_ptr::<u32>(),
+            info.byte_len / core::mem::size_of::<u32>(),
+        )
+    };
+
+    DEBUG_RENDY
+        .get()
+        .expect("set_framebuffer: invoked before the terminal was initialized")
+        .lock_irq()
+        .resize(framebuffer, info);
+}
+
 /// Returns the terminal's rows and columns in the form of a `(rows, columns)` tuple.
 pub fn get_rows_cols() -> (usize, usize) {
     DEBUG_RENDY
@@ -1023,7 +1220,7 @@ pub fn init(fb_info: Framebuffer, cmdline: &CommandLine) {
     let stride = fb_info.pitch() as usize;
     let height = fb_info.height() as usize;
     let bits_per_pixel = fb_info.bpp() as usize;
-    let byte_len = stride * height * (bits_per_pixel / 8);
+    let byte_len = stride * height;
 
     let framebuffer_info = RendyInfo {
         byte_len,
@@ -1043,10 +1240,13 @@ pub fn init(fb_info: Framebuffer, cmdline: &CommandLine) {
         blue_mask_size: fb_info.blue_mask_size(),
     };
 
+    // The framebuffer address points into the bootloader's direct map.
+    let framebuffer_addr = paging::boot_to_hhdm(VirtAddr::new(fb_info.addr() as u64));
+
     let framebuffer = unsafe {
         core::slice::from_raw_parts_mut::<u32>(
-            fb_info.addr().cast::<u32>(),
-            framebuffer_info.byte_len,
+            framebuffer_addr.as_mut_ptr::<u32>(),
+            framebuffer_info.byte_len / core::mem::size_of::<u32>(),
         )
     };
 
@@ -0,0 +1,183 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! In-kernel self tests, which are run at boot when the kernel is booted with the `--test`
+//! command line option. Unlike the unit tests (see `crate::tests`), they are built into the
+//! regular kernel and run after the kernel is fully initialized, so they can exercise the
+//! subsystems on the real hardware.
+//!
+//! The tests of a suite are registered with [`selftest!`](crate::selftest) and check their
+//! expectations with [`selftest_assert!`](crate::selftest_assert) and
+//! [`selftest_assert_eq!`](crate::selftest_assert_eq), which fail the test instead of panicking.
+//! If any of the tests fail, the boot is aborted.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! fn addition() -> selftest::Result {
+//!     selftest_assert_eq!(2 + 2, 4);
+//!     Ok(())
+//! }
+//!
+//! crate::selftest!("math", addition);
+//! ```
+
+use core::mem::size_of;
+use core::panic::Location;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[cfg(feature = "ci")]
+use crate::emu;
+use crate::extern_sym;
+
+/// A failed expectation of a self test.
+pub struct Failure {
+    message: String,
+    location: &'static Location<'static>,
+}
+
+impl Failure {
+    #[track_caller]
+    pub fn new(message: String) -> Self {
+        Self {
+            message,
+            location: Location::caller(),
+        }
+    }
+}
+
+pub type Result = core::result::Result<(), Failure>;
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct SelfTest {
+    pub suite: &'static str,
+    pub name: &'static str,
+    pub test_fn: fn() -> Result,
+}
+
+/// Registers the provided functions as the self tests of `suite`.
+#[macro_export]
+macro_rules! selftest {
+    ($suite:literal, $($test:ident),+ $(,)?) => {
+        $(
+            const _: () = {
+                #[used]
+                #[link_section = ".kernel_selftests"]
+                static __SELFTEST: $crate::selftest::SelfTest = $crate::selftest::SelfTest {
+                    suite: $suite,
+                    name: core::stringify!($test),
+                    test_fn: $test,
+                };
+            };
+        )+
+    };
+}
+
+/// Fails the self test if the condition is false.
+#[macro_export]
+macro_rules! selftest_assert {
+    ($cond:expr) => {
+        if !$cond {
+            return Err($crate::selftest::Failure::new(alloc::format!(
+                "assertion failed: {}",
+                core::stringify!($cond)
+            )));
+        }
+    };
+
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::selftest::Failure::new(alloc::format!($($arg)+)));
+        }
+    };
+}
+
+/// Fails the self test if the two expressions are not equal.
+#[macro_export]
+macro_rules! selftest_assert_eq {
+    ($left:expr, $right:expr) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left != *right {
+                    return Err($crate::selftest::Failure::new(alloc::format!(
+                        "assertion `{} == {}` failed (left: {:?}, right: {:?})",
+                        core::stringify!($left),
+                        core::stringify!($right),
+                        left,
+                        right
+                    )));
+                }
+            }
+        }
+    };
+}
+
+/// Runs all of the registered self tests, grouped by suite. Aborts the boot if any of them fail.
+pub fn run() {
+    let tests_start = extern_sym!(__kernel_selftests_start).cast::<SelfTest>();
+    let tests_end = extern_sym!(__kernel_selftests_end).cast::<SelfTest>();
+
+    let size = (tests_end.addr() - tests_start.addr()) / size_of::<SelfTest>();
+    let tests = unsafe { core::slice::from_raw_parts(tests_start, size) };
+
+    let mut tests = tests.iter().collect::<Vec<_>>();
+    tests.sort_by_key(|test| test.suite);
+
+    log::info!("selftest: running {} tests", tests.len());
+
+    let mut failed = 0;
+
+    for suite in tests.chunk_by(|a, b| a.suite == b.suite) {
+        let mut suite_failed = 0;
+
+        for test in suite {
+            match (test.test_fn)() {
+                Ok(()) => log::info!("selftest: {}::{} ... ok", test.suite, test.name),
+                Err(failure) => {
+                    log::error!("selftest: {}::{} ... FAILED", test.suite, test.name);
+                    log::error!("    {} at {}", failure.message, failure.location);
+
+                    suite_failed += 1;
+                }
+            }
+        }
+
+        log::info!(
+            "selftest: suite {}: {} passed; {} failed",
+            suite[0].suite,
+            suite.len() - suite_failed,
+            suite_failed
+        );
+
+        failed += suite_failed;
+    }
+
+    if failed != 0 {
+        log::error!("selftest: {failed} of {} tests failed", tests.len());
+
+        #[cfg(feature = "ci")]
+        emu::exit_qemu(emu::ExitStatus::Failure);
+
+        #[cfg(not(feature = "ci"))]
+        panic!("selftest: regressions found, aborting the boot");
+    }
+
+    log::info!("selftest: all {} tests passed", tests.len());
+}
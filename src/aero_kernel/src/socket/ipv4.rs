@@ -20,6 +20,8 @@
 use alloc::sync::Arc;
 
 use aero_syscall::prelude::{IfReq, SIOCGIFINDEX};
+use aero_syscall::socket::SocketOptionLevel;
+use aero_syscall::{SocketType, SyscallError};
 
 use crate::arch::user_copy::UserRef;
 
@@ -27,12 +29,19 @@ use crate::fs::inode::INodeInterface;
 use crate::fs::Result;
 
 use crate::mem::paging::VirtAddr;
+use crate::utils::sync::Mutex;
 
-pub struct Ipv4Socket {}
+use super::SocketOptions;
+
+pub struct Ipv4Socket {
+    options: Mutex<SocketOptions>,
+}
 
 impl Ipv4Socket {
     pub fn new() -> Arc<Self> {
-        Arc::new(Self {})
+        Arc::new(Self {
+            options: Mutex::new(SocketOptions::new(SocketType::Dgram)),
+        })
     }
 }
 
@@ -52,4 +61,28 @@ impl INodeInterface for Ipv4Socket {
             _ => unimplemented!(),
         }
     }
+
+    fn set_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &[u8],
+    ) -> core::result::Result<(), SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().set(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &mut [u8],
+    ) -> core::result::Result<usize, SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().get(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
 }
@@ -19,28 +19,34 @@ pub mod ipv4;
 pub mod tcp;
 // pub mod tcp2;
 pub mod netlink;
+pub mod packet;
 pub mod udp;
 pub mod unix;
 
 use aero_syscall::netlink::sockaddr_nl;
 use aero_syscall::prelude::IfReq;
+use aero_syscall::socket::{Linger, SocketOption};
+use aero_syscall::time::TimeVal;
 use aero_syscall::*;
+use num_traits::FromPrimitive;
 
 use crate::mem::paging::VirtAddr;
+use crate::userland::scheduler::hrtimer;
 
 #[derive(Debug)]
 pub enum SocketAddr {
     Inet(SocketAddrInet),
     Netlink(sockaddr_nl),
     Unix(SocketAddrUnix),
+    Packet(SocketAddrPacket),
 }
 
 #[derive(Debug)]
 pub enum SocketAddrRef<'a> {
     Unix(&'a SocketAddrUnix),
     INet(&'a SocketAddrInet),
-    // TODO: https://docs.huihoo.com/doxygen/linux/kernel/3.7/structsockaddr__nl.html
-    Netlink,
+    Netlink(&'a sockaddr_nl),
+    Packet(&'a SocketAddrPacket),
 }
 
 impl<'a> SocketAddrRef<'a> {
@@ -48,7 +54,10 @@ impl<'a> SocketAddrRef<'a> {
         match family {
             AF_UNIX => Ok(SocketAddrRef::Unix(address.read_mut::<SocketAddrUnix>()?)),
             AF_INET => Ok(SocketAddrRef::INet(address.read_mut::<SocketAddrInet>()?)),
-            AF_NETLINK => Ok(SocketAddrRef::Netlink),
+            AF_NETLINK => Ok(SocketAddrRef::Netlink(address.read_mut::<sockaddr_nl>()?)),
+            AF_PACKET => Ok(SocketAddrRef::Packet(
+                address.read_mut::<SocketAddrPacket>()?,
+            )),
 
             _ => Err(SyscallError::EINVAL),
         }
@@ -78,4 +87,196 @@ impl<'a> SocketAddrRef<'a> {
             _ => None,
         }
     }
+
+    pub fn as_packet(&self) -> Option<&'a SocketAddrPacket> {
+        match self {
+            SocketAddrRef::Packet(addr) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+/// Reads an option value of type `T` from the buffer passed to `setsockopt(2)`.
+pub fn read_option<T: Copy>(value: &[u8]) -> Result<T> {
+    if value.len() < core::mem::size_of::<T>() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    // SAFETY: The buffer is large enough to hold a `T` and all of the option value types
+    // are plain old data.
+    Ok(unsafe { value.as_ptr().cast::<T>().read_unaligned() })
+}
+
+/// Writes the option value into the buffer passed to `getsockopt(2)`, truncating it if the
+/// buffer is too small. Returns the number of bytes written.
+pub fn write_option<T: Copy>(value: &mut [u8], data: T) -> usize {
+    let size = core::cmp::min(value.len(), core::mem::size_of::<T>());
+
+    // SAFETY: `data` is valid for reads of `size_of::<T>()` bytes.
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&data as *const T as *const u8, core::mem::size_of::<T>())
+    };
+
+    value[..size].copy_from_slice(&bytes[..size]);
+    size
+}
+
+/// Converts a socket timeout into a deadline on the monotonic clock. A zero timeout never
+/// expires.
+fn deadline(timeout: &TimeVal) -> Option<u64> {
+    let timeout = (timeout.tv_sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(timeout.tv_usec as u64 * 1000);
+
+    (timeout != 0).then(|| hrtimer::now().saturating_add(timeout))
+}
+
+/// Socket level (`SOL_SOCKET`) options that are common to all socket types.
+pub struct SocketOptions {
+    typ: SocketType,
+    reuse_addr: bool,
+    reuse_port: bool,
+    keep_alive: bool,
+    broadcast: bool,
+    pass_cred: bool,
+    linger: Linger,
+    recv_buf: usize,
+    send_buf: usize,
+    recv_timeout: TimeVal,
+    send_timeout: TimeVal,
+}
+
+impl SocketOptions {
+    const MIN_BUF_SIZE: usize = 2048;
+    const MAX_BUF_SIZE: usize = 4 * 1024 * 1024;
+    const DEFAULT_BUF_SIZE: usize = 212992;
+
+    pub const fn new(typ: SocketType) -> Self {
+        Self {
+            typ,
+            reuse_addr: false,
+            reuse_port: false,
+            keep_alive: false,
+            broadcast: false,
+            pass_cred: false,
+            linger: Linger {
+                l_onoff: 0,
+                l_linger: 0,
+            },
+            recv_buf: Self::DEFAULT_BUF_SIZE,
+            send_buf: Self::DEFAULT_BUF_SIZE,
+            recv_timeout: TimeVal {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            send_timeout: TimeVal {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+        }
+    }
+
+    /// Returns whether the socket is allowed to reuse a local address that is already in
+    /// use (`SO_REUSEADDR` or `SO_REUSEPORT`).
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr || self.reuse_port
+    }
+
+    /// Returns the size of the receive buffer (`SO_RCVBUF`), in bytes.
+    pub fn recv_buf(&self) -> usize {
+        self.recv_buf
+    }
+
+    /// Returns whether the credentials of the sender are received along with each message
+    /// (`SO_PASSCRED`).
+    pub fn pass_cred(&self) -> bool {
+        self.pass_cred
+    }
+
+    pub fn set(&mut self, name: i32, value: &[u8]) -> Result<()> {
+        let option = SocketOption::from_i32(name).ok_or(SyscallError::ENOPROTOOPT)?;
+
+        let buf_size = |value: &[u8]| -> Result<usize> {
+            let size = read_option::<i32>(value)?;
+            Ok((size.max(0) as usize).clamp(Self::MIN_BUF_SIZE, Self::MAX_BUF_SIZE))
+        };
+
+        match option {
+            SocketOption::ReuseAddr => self.reuse_addr = read_option::<i32>(value)? != 0,
+            SocketOption::ReusePort => self.reuse_port = read_option::<i32>(value)? != 0,
+            SocketOption::KeepAlive => self.keep_alive = read_option::<i32>(value)? != 0,
+            SocketOption::Broadcast => self.broadcast = read_option::<i32>(value)? != 0,
+            SocketOption::PassCred => self.pass_cred = read_option::<i32>(value)? != 0,
+            SocketOption::Linger => self.linger = read_option::<Linger>(value)?,
+            SocketOption::RecvBuf => self.recv_buf = buf_size(value)?,
+            SocketOption::SendBuf => self.send_buf = buf_size(value)?,
+
+            SocketOption::RecvTimeout | SocketOption::SendTimeout => {
+                let timeout = read_option::<TimeVal>(value)?;
+
+                if timeout.tv_sec < 0 || !(0..1000000).contains(&timeout.tv_usec) {
+                    return Err(SyscallError::EDOM);
+                }
+
+                if option == SocketOption::RecvTimeout {
+                    self.recv_timeout = timeout;
+                } else {
+                    self.send_timeout = timeout;
+                }
+            }
+
+            // These options are read-only.
+            SocketOption::AcceptConn | SocketOption::Error | SocketOption::Type => {
+                return Err(SyscallError::ENOPROTOOPT)
+            }
+
+            _ => {
+                log::warn!("setsockopt: unsupported socket option {option:?}");
+                return Err(SyscallError::ENOPROTOOPT);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the time at which a receive operation started now times out (`SO_RCVTIMEO`),
+    /// or [`None`] if it may block indefinitely.
+    pub fn recv_deadline(&self) -> Option<u64> {
+        deadline(&self.recv_timeout)
+    }
+
+    /// Returns the time at which a send operation started now times out (`SO_SNDTIMEO`), or
+    /// [`None`] if it may block indefinitely.
+    pub fn send_deadline(&self) -> Option<u64> {
+        deadline(&self.send_timeout)
+    }
+
+    /// Writes the value of the option into `value` and returns its size.
+    pub fn get(&self, name: i32, value: &mut [u8]) -> Result<usize> {
+        let option = SocketOption::from_i32(name).ok_or(SyscallError::ENOPROTOOPT)?;
+
+        let size = match option {
+            SocketOption::ReuseAddr => write_option(value, self.reuse_addr as i32),
+            SocketOption::ReusePort => write_option(value, self.reuse_port as i32),
+            SocketOption::KeepAlive => write_option(value, self.keep_alive as i32),
+            SocketOption::Broadcast => write_option(value, self.broadcast as i32),
+            SocketOption::PassCred => write_option(value, self.pass_cred as i32),
+            SocketOption::Linger => write_option(value, self.linger),
+            SocketOption::RecvBuf => write_option(value, self.recv_buf as i32),
+            SocketOption::SendBuf => write_option(value, self.send_buf as i32),
+            SocketOption::RecvTimeout => write_option(value, self.recv_timeout),
+            SocketOption::SendTimeout => write_option(value, self.send_timeout),
+            SocketOption::Type => write_option(value, self.typ as i32),
+            // No asynchronous errors are tracked on the sockets yet.
+            SocketOption::Error => write_option(value, 0i32),
+            SocketOption::AcceptConn => write_option(value, 0i32),
+
+            _ => {
+                log::warn!("getsockopt: unsupported socket option {option:?}");
+                return Err(SyscallError::ENOPROTOOPT);
+            }
+        };
+
+        Ok(size)
+    }
 }
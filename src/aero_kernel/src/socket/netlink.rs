@@ -24,87 +24,245 @@
 //! Netlink is designed and used for transferring miscellaneous networking information between the
 //! kernel space and userspace processes. Networking utilities, such as the `iproute2` family use
 //! Netlink to communicate with the kernel from userspace.
-
-use aero_syscall::netlink::{MessageFlags, MessageType, RtAttrType};
-use aero_syscall::socket::{self, MessageHeader};
-use aero_syscall::{netlink, AF_INET, AF_NETLINK, AF_UNSPEC};
-use alloc::sync::Arc;
+//!
+//! Only the `NETLINK_ROUTE` family is implemented. It is used to dump and change the links,
+//! addresses and routes of the network devices, and the sockets subscribed to its multicast
+//! groups are notified whenever one of them changes (see [`notify_addr`] and [`notify_route`]).
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use aero_syscall::netlink::{
+    self, AddrAttrType, LinkAttrType, MessageFlags, MessageType, RtAttrType,
+};
+use aero_syscall::socket::{self, MessageHeader, SocketOptionLevel};
+use aero_syscall::*;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use crabnet::data_link::MacAddr;
 use crabnet::network::Ipv4Addr;
+use num_traits::FromPrimitive;
+use spin::Once;
 
-use crate::fs;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
 use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::fs::{self, FileSystemError};
+use crate::net::route::{self, Route};
+use crate::net::{self, NetworkDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
-use super::SocketAddrRef;
+use super::{read_option, SocketAddrRef, SocketOptions};
+
+/// MTU reported for the ethernet devices.
+const ETH_MTU: u32 = 1500;
+/// MTU reported for the loopback device.
+const LOOPBACK_MTU: u32 = 65536;
+
+/// Sockets that are subscribed to at least one multicast group.
+static LISTENERS: Mutex<Vec<Weak<NetLinkSocket>>> = Mutex::new(Vec::new());
 
-// TODO(andypython): can we use crabnet to construct netlink packets(?)
 struct NetlinkBuilder {
     buffer: Vec<u8>,
+    /// Offset of the header of the last message in the buffer.
+    start: usize,
 }
 
 impl NetlinkBuilder {
     fn new() -> Self {
-        // crate::scheduler::get_scheduler().for_each_task(|task| {
-        //     if task
-        //         .executable
-        //         .lock()
-        //         .as_ref()
-        //         .map(|x| x.absolute_path_str().contains("kit"))
-        //         .unwrap_or_default()
-        //     {
-        //         task.enable_systrace();
-        //     }
-        // });
-
-        Self { buffer: Vec::new() }
-    }
-
-    fn header(&mut self, header: &netlink::nlmsghdr) {
-        self.buffer.extend_from_slice(unsafe {
-            core::slice::from_raw_parts(
-                &header as *const _ as *const u8,
-                core::mem::size_of::<netlink::nlmsghdr>(),
-            )
-        });
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+        }
+    }
 
-        self.buffer_align();
+    /// Starts a new message. Its length is filled in once the next message is started or the
+    /// buffer is built.
+    fn header(&mut self, typ: MessageType, flags: MessageFlags, seq: u32) {
+        self.finish();
+        self.start = self.buffer.len();
+
+        self.push(&netlink::nlmsghdr {
+            nlmsg_len: 0,
+            nlmsg_type: typ as u16,
+            nlmsg_flags: flags,
+            nlmsg_seq: seq,
+            nlmsg_pid: 0,
+        });
     }
 
-    fn message(&mut self, message: &netlink::rtmsg) {
+    /// Appends `value` to the current message.
+    fn push<T>(&mut self, value: &T) {
+        // SAFETY: The netlink message types are plain old data.
         self.buffer.extend_from_slice(unsafe {
-            core::slice::from_raw_parts(
-                &message as *const _ as *const u8,
-                core::mem::size_of::<netlink::rtmsg>(),
-            )
+            core::slice::from_raw_parts((value as *const T).cast::<u8>(), core::mem::size_of::<T>())
         });
 
         self.buffer_align();
     }
 
-    fn rtattr<T>(&mut self, ty: RtAttrType, data: T) {
-        let rta_len = netlink::rta_length(core::mem::size_of::<T>() as u32);
+    fn rtattr(&mut self, ty: u16, data: &[u8]) {
+        let rta_len = netlink::rta_length(data.len() as u32);
 
-        let attr = netlink::rtattr {
+        self.push(&netlink::rtattr {
             rta_len: rta_len.try_into().unwrap(),
             rta_type: ty,
-        };
+        });
 
-        let padding = rta_len as usize - core::mem::size_of::<T>();
+        self.buffer.extend_from_slice(data);
+        self.buffer_align();
+    }
 
-        self.buffer.extend_from_slice(unsafe {
-            core::slice::from_raw_parts(
-                &attr as *const _ as *const u8,
-                core::mem::size_of::<netlink::rtattr>(),
+    /// Appends a link message describing the device with the provided interface index.
+    fn link(
+        &mut self,
+        typ: MessageType,
+        flags: MessageFlags,
+        seq: u32,
+        ifindex: usize,
+        device: &Arc<NetworkDevice>,
+    ) {
+        let loopback = Arc::ptr_eq(device, &net::loopback::LOOPBACK);
+        let (ifi_type, ifi_flags, mtu) = if loopback {
+            (
+                ARPHRD_LOOPBACK,
+                IFF_UP | IFF_RUNNING | IFF_LOOPBACK,
+                LOOPBACK_MTU,
             )
+        } else if device.is_point_to_point() {
+            let ifi_flags = IFF_UP | IFF_RUNNING | IFF_POINTOPOINT | IFF_NOARP;
+            (ARPHRD_NONE, ifi_flags, ETH_MTU)
+        } else {
+            let ifi_flags = IFF_UP | IFF_RUNNING | IFF_BROADCAST | IFF_MULTICAST;
+            (ARPHRD_ETHER, ifi_flags, ETH_MTU)
+        };
+
+        self.header(typ, flags, seq);
+        self.push(&netlink::ifinfomsg {
+            ifi_family: AF_UNSPEC as u8,
+            __ifi_pad: 0,
+            ifi_type,
+            ifi_index: ifindex as i32,
+            ifi_flags,
+            ifi_change: 0,
         });
 
-        self.buffer.extend_from_slice(unsafe {
-            core::slice::from_raw_parts(&data as *const _ as *const u8, core::mem::size_of::<T>())
+        if let Some(name) = net::device_name(ifindex) {
+            self.rtattr(LinkAttrType::IfName as u16, c_string(name).as_slice());
+        }
+
+        // Point-to-point devices have no link-layer addresses.
+        if ifi_type != ARPHRD_NONE {
+            self.rtattr(LinkAttrType::Address as u16, &device.mac().0);
+        }
+
+        if ifi_type == ARPHRD_ETHER {
+            self.rtattr(LinkAttrType::Broadcast as u16, &MacAddr::BROADCAST.0);
+        }
+
+        self.rtattr(LinkAttrType::Mtu as u16, &mtu.to_ne_bytes());
+    }
+
+    /// Appends an address message describing the IPv4 address of the device with the provided
+    /// interface index.
+    fn addr(
+        &mut self,
+        typ: MessageType,
+        flags: MessageFlags,
+        seq: u32,
+        ifindex: usize,
+        (ip, mask): (Ipv4Addr, Ipv4Addr),
+    ) {
+        let loopback = ip.0[0] == 127;
+
+        self.header(typ, flags, seq);
+        self.push(&netlink::ifaddrmsg {
+            ifa_family: AF_INET as u8,
+            ifa_prefixlen: prefix_len(mask),
+            ifa_flags: 0,
+            ifa_scope: if loopback {
+                netlink::RT_SCOPE_HOST
+            } else {
+                netlink::RT_SCOPE_UNIVERSE
+            },
+            ifa_index: ifindex as u32,
         });
 
-        self.buffer.resize(self.buffer.len() + padding, 0);
-        self.buffer_align();
+        self.rtattr(AddrAttrType::Address as u16, &ip.0);
+        self.rtattr(AddrAttrType::Local as u16, &ip.0);
+
+        if !loopback {
+            let broadcast = u32::from_be_bytes(ip.0) | !u32::from_be_bytes(mask.0);
+            self.rtattr(AddrAttrType::Broadcast as u16, &broadcast.to_be_bytes());
+        }
+
+        if let Some(name) = net::device_name(ifindex) {
+            self.rtattr(AddrAttrType::Label as u16, c_string(name).as_slice());
+        }
+    }
+
+    /// Appends a route message describing `route`, which originates from `protocol` (one of
+    /// the `RTPROT_*` constants).
+    fn route(
+        &mut self,
+        typ: MessageType,
+        flags: MessageFlags,
+        seq: u32,
+        route: &Route,
+        protocol: u8,
+    ) {
+        let dst_len = route.prefix_len() as u8;
+
+        self.header(typ, flags, seq);
+        self.push(&netlink::rtmsg {
+            rtm_family: AF_INET as u8,
+            rtm_dst_len: dst_len,
+            rtm_src_len: 0,
+            rtm_tos: 0,
+            rtm_table: netlink::RT_TABLE_MAIN,
+            rtm_protocol: protocol,
+            rtm_scope: if route.gateway.is_some() {
+                netlink::RT_SCOPE_UNIVERSE
+            } else {
+                netlink::RT_SCOPE_LINK
+            },
+            rtm_type: netlink::RTN_UNICAST,
+            rtm_flags: 0,
+        });
+
+        let table = u32::from(netlink::RT_TABLE_MAIN);
+        self.rtattr(RtAttrType::Table as u16, &table.to_ne_bytes());
+
+        if dst_len != 0 {
+            self.rtattr(RtAttrType::Dst as u16, &route.dest.0);
+        }
+
+        if let Some(gateway) = route.gateway {
+            self.rtattr(RtAttrType::Gateway as u16, &gateway.0);
+        }
+
+        if route.metric != 0 {
+            self.rtattr(RtAttrType::Priority as u16, &route.metric.to_ne_bytes());
+        }
+
+        let oif = route.ifindex as u32;
+        self.rtattr(RtAttrType::Oif as u16, &oif.to_ne_bytes());
+    }
+
+    /// Appends an error message for `request`. An error of zero acknowledges the request.
+    fn error(&mut self, error: i32, request: &netlink::nlmsghdr) {
+        self.header(MessageType::Error, MessageFlags::empty(), request.nlmsg_seq);
+        self.push(&netlink::nlmsgerr {
+            error,
+            msg: *request,
+        });
+    }
+
+    /// Appends the message terminating a dump.
+    fn done(&mut self, seq: u32) {
+        self.header(MessageType::Done, MessageFlags::MULTI, seq);
+        self.push(&0i32);
     }
 
     /// Aligns the buffer to the netlink message alignment.
@@ -115,86 +273,428 @@ impl NetlinkBuilder {
         self.buffer.resize(aligned_len as usize, 0);
     }
 
-    fn build(self) -> Vec<u8> {
-        let mut buffer = self.buffer;
+    /// Fills in the length of the last message.
+    fn finish(&mut self) {
+        if self.buffer.len() > self.start {
+            let len = (self.buffer.len() - self.start) as u32;
+            self.buffer[self.start..self.start + 4].copy_from_slice(&len.to_ne_bytes());
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    fn build(mut self) -> Vec<u8> {
+        self.finish();
+        self.buffer
+    }
+}
 
-        let msg_len = buffer.len();
-        let msg_hdr = unsafe { &mut *buffer.as_mut_ptr().cast::<netlink::nlmsghdr>() };
+fn c_string(name: String) -> Vec<u8> {
+    let mut bytes = name.into_bytes();
+    bytes.push(0);
+    bytes
+}
 
-        msg_hdr.nlmsg_len = msg_len as u32;
-        buffer
+fn prefix_len(mask: Ipv4Addr) -> u8 {
+    u32::from_be_bytes(mask.0).count_ones() as u8
+}
+
+fn prefix_mask(prefix_len: u8) -> Result<Ipv4Addr, SyscallError> {
+    if prefix_len > 32 {
+        return Err(SyscallError::EINVAL);
     }
+
+    let mask = u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0);
+
+    Ok(Ipv4Addr::from(mask.to_be_bytes()))
+}
+
+fn ipv4_attr(data: &[u8]) -> Result<Ipv4Addr, SyscallError> {
+    <[u8; 4]>::try_from(data)
+        .map(Ipv4Addr::from)
+        .map_err(|_| SyscallError::EINVAL)
+}
+
+fn u32_attr(data: &[u8]) -> Result<u32, SyscallError> {
+    read_option::<u32>(data)
+}
+
+/// Splits the payload of a request into its fixed-size header of type `T` and the attributes
+/// that follow it.
+fn parse_request<T: Copy>(payload: &[u8]) -> Result<(T, &[u8]), SyscallError> {
+    let message = read_option::<T>(payload)?;
+    let size = netlink::nlmsg_align(core::mem::size_of::<T>() as u32) as usize;
+
+    Ok((message, &payload[size.min(payload.len())..]))
+}
+
+/// Returns an iterator over the type and the payload of the attributes in `data`.
+fn attributes(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    core::iter::from_fn(move || {
+        let header_size = core::mem::size_of::<netlink::rtattr>();
+
+        if data.len() < header_size {
+            return None;
+        }
+
+        let len = usize::from(u16::from_ne_bytes([data[0], data[1]]));
+        let ty = u16::from_ne_bytes([data[2], data[3]]);
+
+        if len < header_size || len > data.len() {
+            return None;
+        }
+
+        let payload = &data[header_size..len];
+        let next = netlink::rta_align(len as u32) as usize;
+
+        data = &data[next.min(data.len())..];
+        Some((ty, payload))
+    })
+}
+
+/// Returns an iterator over the registered devices and their interface indices.
+fn devices() -> impl Iterator<Item = (usize, Arc<NetworkDevice>)> {
+    (1..).map_while(|ifindex| Some((ifindex, net::device_by_index(ifindex)?)))
+}
+
+/// Sends `message` to the sockets subscribed to any of the multicast groups in `group`.
+fn notify(group: u32, message: Vec<u8>) {
+    let mut listeners = LISTENERS.lock_irq();
+    listeners.retain(|socket| socket.strong_count() > 0);
+
+    for socket in listeners.iter().filter_map(Weak::upgrade) {
+        if socket.groups.load(Ordering::SeqCst) & group != 0 {
+            socket.queue(message.clone());
+        }
+    }
+}
+
+fn has_listeners() -> bool {
+    !LISTENERS.lock_irq().is_empty()
+}
+
+/// Notifies the sockets listening for link changes that the device with the provided interface
+/// index has been added or removed.
+pub fn notify_link(typ: MessageType, ifindex: usize) {
+    if !has_listeners() {
+        return;
+    }
+
+    let Some(device) = net::device_by_index(ifindex) else {
+        return;
+    };
+
+    let mut builder = NetlinkBuilder::new();
+    builder.link(typ, MessageFlags::empty(), 0, ifindex, &device);
+    notify(netlink::RTMGRP_LINK, builder.build());
+}
+
+/// Notifies the sockets listening for IPv4 address changes that the address of the device with
+/// the provided interface index has been added or removed.
+pub fn notify_addr(typ: MessageType, ifindex: usize, ip: Ipv4Addr, mask: Ipv4Addr) {
+    if !has_listeners() {
+        return;
+    }
+
+    let mut builder = NetlinkBuilder::new();
+    builder.addr(typ, MessageFlags::empty(), 0, ifindex, (ip, mask));
+    notify(netlink::RTMGRP_IPV4_IFADDR, builder.build());
+}
+
+/// Notifies the sockets listening for IPv4 route changes that `route` has been added to or
+/// removed from the routing table.
+pub fn notify_route(typ: MessageType, route: &Route) {
+    if !has_listeners() {
+        return;
+    }
+
+    let mut builder = NetlinkBuilder::new();
+    builder.route(typ, MessageFlags::empty(), 0, route, netlink::RTPROT_BOOT);
+    notify(netlink::RTMGRP_IPV4_ROUTE, builder.build());
 }
 
 pub struct NetLinkSocket {
-    recv_queue: Mutex<Vec<Vec<u8>>>,
+    recv_queue: Mutex<VecDeque<Vec<u8>>>,
     recv_wq: WaitQueue,
+    options: Mutex<SocketOptions>,
+    /// Multicast groups that the socket is subscribed to (`RTMGRP_*`).
+    groups: AtomicU32,
+    handle: Once<Arc<FileHandle>>,
+
+    sref: Weak<Self>,
 }
 
 impl NetLinkSocket {
     pub fn new() -> Arc<Self> {
-        Arc::new(Self {
-            recv_queue: Mutex::new(Vec::new()),
+        Arc::new_cyclic(|sref| Self {
+            recv_queue: Mutex::new(VecDeque::new()),
             recv_wq: WaitQueue::new(),
+            options: Mutex::new(SocketOptions::new(SocketType::Raw)),
+            groups: AtomicU32::new(0),
+            handle: Once::new(),
+
+            sref: sref.clone(),
         })
     }
 
-    fn validate_message<'a, T>(header: &'a netlink::nlmsghdr, payload: &'a [u8]) -> &'a T {
-        let hdr_len = core::mem::size_of::<netlink::nlmsghdr>() as u32;
-        let msg_len = core::mem::size_of::<T>() as u32;
+    fn is_non_block(&self) -> bool {
+        self.handle
+            .get()
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+
+    fn queue(&self, datagram: Vec<u8>) {
+        self.recv_queue.lock_irq().push_back(datagram);
+        self.recv_wq.notify_all();
+    }
+
+    fn set_groups(&self, groups: u32) {
+        self.groups.store(groups, Ordering::SeqCst);
 
-        // TODO(andypython): send an error message instead of panicking.
-        assert!(header.nlmsg_len == hdr_len + msg_len);
+        let mut listeners = LISTENERS.lock_irq();
 
-        // FIXME(andypython): use bytemuck to cast the payload to T.
-        unsafe { &*payload.as_ptr().cast::<T>() }
+        if groups != 0 && !listeners.iter().any(|e| Weak::ptr_eq(e, &self.sref)) {
+            listeners.push(self.sref.clone());
+        } else if groups == 0 {
+            listeners.retain(|e| !Weak::ptr_eq(e, &self.sref));
+        }
     }
 
-    fn send_route_packet(&self, header: &netlink::nlmsghdr) {
-        let mut builder = NetlinkBuilder::new();
+    /// Handles `request`, appending the replies to it to `builder`.
+    fn request(
+        &self,
+        request: &netlink::nlmsghdr,
+        payload: &[u8],
+        builder: &mut NetlinkBuilder,
+    ) -> Result<(), SyscallError> {
+        let seq = request.nlmsg_seq;
+        let dump = request.nlmsg_flags.contains(MessageFlags::DUMP);
+
+        // Dump requests start with either a `rtgenmsg` or the header of the dumped messages,
+        // both of which start with the address family.
+        let family = payload
+            .first()
+            .map_or(AF_UNSPEC, |family| u32::from(*family));
+        let inet = family == AF_UNSPEC || family == AF_INET;
+
+        match MessageType::from_u16(request.nlmsg_type) {
+            Some(MessageType::RtmGetLink) if dump => {
+                for (ifindex, device) in devices() {
+                    builder.link(
+                        MessageType::RtmNewLink,
+                        MessageFlags::MULTI,
+                        seq,
+                        ifindex,
+                        &device,
+                    );
+                }
 
-        builder.header(&netlink::nlmsghdr {
-            nlmsg_type: MessageType::RtmNewRoute,
-            nlmsg_flags: MessageFlags::MULTI,
-            nlmsg_seq: header.nlmsg_seq,
-            nlmsg_pid: 0,
-            nlmsg_len: 0,
-        });
+                builder.done(seq);
+            }
 
-        builder.message(&netlink::rtmsg {
-            rtm_family: AF_INET as u8,
-            rtm_dst_len: 0, // FIXME
-            rtm_src_len: 0,
-            rtm_tos: 0,
-            rtm_table: netlink::RT_TABLE_MAIN,
+            Some(MessageType::RtmGetLink) => {
+                let (message, attrs) = parse_request::<netlink::ifinfomsg>(payload)?;
+                let ifindex = if message.ifi_index > 0 {
+                    message.ifi_index as usize
+                } else {
+                    let name = attributes(attrs)
+                        .find(|(ty, _)| *ty == LinkAttrType::IfName as u16)
+                        .ok_or(SyscallError::EINVAL)?
+                        .1;
 
-            rtm_protocol: 0,
-            rtm_scope: 0,
-            rtm_type: 0,
-            rtm_flags: 0,
-        });
+                    let len = name.iter().position(|&x| x == 0).unwrap_or(name.len());
+                    let name =
+                        core::str::from_utf8(&name[..len]).map_err(|_| SyscallError::EINVAL)?;
 
-        builder.rtattr(RtAttrType::Table, netlink::RT_TABLE_MAIN);
+                    net::device_index_by_name(name).ok_or(SyscallError::ENODEV)?
+                };
 
-        // Qemu SLIRP
-        builder.rtattr(RtAttrType::Dst, Ipv4Addr::new(10, 0, 2, 15));
-        builder.rtattr(RtAttrType::Gateway, Ipv4Addr::new(10, 0, 2, 2));
+                let device = net::device_by_index(ifindex).ok_or(SyscallError::ENODEV)?;
+                let flags = MessageFlags::empty();
 
-        self.recv_queue.lock().push(builder.build());
-        self.recv_wq.notify();
-    }
+                builder.link(MessageType::RtmNewLink, flags, seq, ifindex, &device);
+            }
+
+            // Links can not be created nor brought down.
+            Some(MessageType::RtmNewLink | MessageType::RtmSetLink) => {
+                let (message, _) = parse_request::<netlink::ifinfomsg>(payload)?;
+                let ifindex = message.ifi_index.max(0) as usize;
+
+                net::device_by_index(ifindex).ok_or(SyscallError::ENODEV)?;
+
+                if message.ifi_change & IFF_UP != 0 && message.ifi_flags & IFF_UP == 0 {
+                    return Err(SyscallError::EOPNOTSUPP);
+                }
+            }
+
+            Some(MessageType::RtmGetAddr) if dump => {
+                // Devices without an address (such as new TUN devices) are skipped.
+                let devices = devices().filter(|(_, device)| inet && device.ip().0 != [0; 4]);
+
+                for (ifindex, device) in devices {
+                    builder.addr(
+                        MessageType::RtmNewAddr,
+                        MessageFlags::MULTI,
+                        seq,
+                        ifindex,
+                        (device.ip(), device.subnet_mask()),
+                    );
+                }
+
+                builder.done(seq);
+            }
+
+            Some(typ @ (MessageType::RtmNewAddr | MessageType::RtmDelAddr)) => {
+                let (message, attrs) = parse_request::<netlink::ifaddrmsg>(payload)?;
+
+                if u32::from(message.ifa_family) != AF_INET {
+                    return Err(SyscallError::EAFNOSUPPORT);
+                }
+
+                let device =
+                    net::device_by_index(message.ifa_index as usize).ok_or(SyscallError::ENODEV)?;
+
+                // The local address is the address of the interface itself; the address
+                // attribute only differs from it on point-to-point links.
+                let mut local = None;
+
+                for (ty, data) in attributes(attrs) {
+                    match AddrAttrType::from_u16(ty) {
+                        Some(AddrAttrType::Local) => local = Some(ipv4_attr(data)?),
+                        Some(AddrAttrType::Address) => local = local.or(Some(ipv4_attr(data)?)),
+                        _ => {}
+                    }
+                }
+
+                if typ == MessageType::RtmDelAddr {
+                    if local.is_some_and(|local| local != device.ip()) {
+                        return Err(SyscallError::EADDRNOTAVAIL);
+                    }
+
+                    let unspecified = Ipv4Addr::new(0, 0, 0, 0);
+                    device.set_address(unspecified, unspecified);
+                } else {
+                    let local = local.ok_or(SyscallError::EINVAL)?;
+                    device.set_address(local, prefix_mask(message.ifa_prefixlen)?);
+                }
+            }
+
+            Some(MessageType::RtmGetRoute) if dump => {
+                if inet {
+                    for route in route::on_link_routes() {
+                        let flags = MessageFlags::MULTI;
+                        let protocol = netlink::RTPROT_KERNEL;
+
+                        builder.route(MessageType::RtmNewRoute, flags, seq, &route, protocol);
+                    }
 
-    fn get_route(&self, header: &netlink::nlmsghdr, payload: &[u8]) {
-        assert!(header
-            .nlmsg_flags
-            .contains(MessageFlags::REQUEST | MessageFlags::DUMP));
+                    for route in route::routes() {
+                        let flags = MessageFlags::MULTI;
+                        let protocol = netlink::RTPROT_BOOT;
 
-        let payload = Self::validate_message::<netlink::rtgenmsg>(header, payload);
-        let rtgen_family = payload.rtgen_family as u32;
+                        builder.route(MessageType::RtmNewRoute, flags, seq, &route, protocol);
+                    }
+                }
+
+                builder.done(seq);
+            }
+
+            // Looks up the route to a destination (e.g. `ip route get`).
+            Some(MessageType::RtmGetRoute) => {
+                let (_, attrs) = parse_request::<netlink::rtmsg>(payload)?;
+                let dest = attributes(attrs)
+                    .find(|(ty, _)| *ty == RtAttrType::Dst as u16)
+                    .ok_or(SyscallError::EINVAL)
+                    .and_then(|(_, data)| ipv4_attr(data))?;
+
+                let (device, next_hop) = route::lookup(dest).ok_or(SyscallError::ENETUNREACH)?;
+                let route = Route {
+                    dest,
+                    mask: prefix_mask(32)?,
+                    gateway: (next_hop != dest).then_some(next_hop),
+                    ifindex: net::device_index(&device).ok_or(SyscallError::ENODEV)?,
+                    metric: 0,
+                };
+
+                let flags = MessageFlags::empty();
+                let protocol = netlink::RTPROT_BOOT;
+
+                builder.route(MessageType::RtmNewRoute, flags, seq, &route, protocol);
+            }
+
+            Some(typ @ (MessageType::RtmNewRoute | MessageType::RtmDelRoute)) => {
+                let (message, attrs) = parse_request::<netlink::rtmsg>(payload)?;
+
+                if u32::from(message.rtm_family) != AF_INET {
+                    return Err(SyscallError::EAFNOSUPPORT);
+                }
+
+                let mut dest = Ipv4Addr::new(0, 0, 0, 0);
+                let mut gateway = None;
+                let mut ifindex = None;
+                let mut metric = 0;
+
+                for (ty, data) in attributes(attrs) {
+                    match RtAttrType::from_u16(ty) {
+                        Some(RtAttrType::Dst) => dest = ipv4_attr(data)?,
+                        Some(RtAttrType::Gateway) => gateway = Some(ipv4_attr(data)?),
+                        Some(RtAttrType::Oif) => ifindex = Some(u32_attr(data)? as usize),
+                        Some(RtAttrType::Priority) => metric = u32_attr(data)?,
+                        _ => {}
+                    }
+                }
+
+                let mask = prefix_mask(message.rtm_dst_len)?;
 
-        assert!(rtgen_family == AF_UNSPEC || rtgen_family == AF_NETLINK);
+                if typ == MessageType::RtmDelRoute {
+                    return if route::remove(dest, mask) {
+                        Ok(())
+                    } else {
+                        Err(SyscallError::ESRCH)
+                    };
+                }
+
+                let ifindex = match ifindex {
+                    Some(ifindex) => {
+                        net::device_by_index(ifindex).ok_or(SyscallError::ENODEV)?;
+                        ifindex
+                    }
+
+                    // Use the device that the gateway (or the destination) is reachable
+                    // through.
+                    None => route::lookup(gateway.unwrap_or(dest))
+                        .and_then(|(device, _)| net::device_index(&device))
+                        .ok_or(SyscallError::ENETUNREACH)?,
+                };
+
+                if request.nlmsg_flags.contains(MessageFlags::REPLACE) {
+                    route::remove(dest, mask);
+                }
 
-        self.send_route_packet(header);
+                let route = Route {
+                    dest,
+                    mask,
+                    gateway,
+                    ifindex,
+                    metric,
+                };
+
+                if !route::add(route) {
+                    return Err(SyscallError::EEXIST);
+                }
+            }
+
+            typ => {
+                log::warn!("netlink: unsupported request {typ:?}");
+                return Err(SyscallError::EOPNOTSUPP);
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -203,7 +703,17 @@ impl INodeInterface for NetLinkSocket {
         Ok(Metadata::with_file_type(FileType::Socket))
     }
 
-    fn bind(&self, _addr: SocketAddrRef, _len: usize) -> fs::Result<()> {
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn bind(&self, address: SocketAddrRef, _len: usize) -> fs::Result<()> {
+        let SocketAddrRef::Netlink(address) = address else {
+            return Err(FileSystemError::NotSupported);
+        };
+
+        self.set_groups(address.nl_groups);
         Ok(())
     }
 
@@ -236,47 +746,58 @@ impl INodeInterface for NetLinkSocket {
             };
         }
 
+        let non_block = self.is_non_block() || flags.contains(socket::MessageFlags::DONTWAIT);
+
+        if self.recv_queue.lock_irq().is_empty() && non_block {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let deadline = self.options.lock_irq().recv_deadline();
         let mut queue = self
             .recv_wq
-            .block_on(&self.recv_queue, |queue| !queue.is_empty())?;
-
-        let mut bytes_copied = 0;
-        dbg!(message_hdr.iovecs_mut());
-        let mut iovecs = message_hdr.iovecs_mut().to_vec();
-
-        while let Some(data) = queue.pop() {
-            if let Some((index, ref mut iovec)) = iovecs
-                .iter_mut()
-                .enumerate()
-                .find(|(_, iovec)| iovec.len() >= data.len())
-            {
+            .block_on_until(&self.recv_queue, deadline, |queue| !queue.is_empty())?
+            .ok_or(FileSystemError::WouldBlock)?;
+
+        let datagram = if flags.contains(socket::MessageFlags::PEEK) {
+            queue.front().unwrap().clone()
+        } else {
+            queue.pop_front().unwrap()
+        };
+
+        drop(queue);
+
+        let mut data = datagram.as_slice();
+        let bytes_copied = message_hdr
+            .iovecs_mut()
+            .iter_mut()
+            .map(|iovec| {
                 let iovec = iovec.as_slice_mut();
-                assert!(iovec.len() >= data.len());
+                let size = core::cmp::min(iovec.len(), data.len());
 
-                let copy = core::cmp::min(iovec.len(), data.len());
-                iovec[..copy].copy_from_slice(&data[..copy]);
+                iovec[..size].copy_from_slice(&data[..size]);
+                data = &data[size..];
+                size
+            })
+            .sum::<usize>();
 
-                bytes_copied += copy;
-                iovecs.remove(index);
-            } else if flags.contains(socket::MessageFlags::TRUNC) && bytes_copied == 0 {
-                message_hdr.flags = socket::MessageFlags::TRUNC.bits() as i32;
-                return Ok(data.len());
-            } else {
-                unimplemented!()
-            }
+        if !data.is_empty() {
+            message_hdr.flags |= socket::MessageFlags::TRUNC.bits() as i32;
         }
 
-        Ok(bytes_copied)
+        // With `MSG_TRUNC`, the real length of the datagram is returned so that the caller
+        // can size its buffer.
+        if flags.contains(socket::MessageFlags::TRUNC) {
+            Ok(datagram.len())
+        } else {
+            Ok(bytes_copied)
+        }
     }
 
     fn send(
         &self,
         message_hdr: &mut MessageHeader,
-        flags: socket::MessageFlags,
+        _flags: socket::MessageFlags,
     ) -> fs::Result<usize> {
-        log::warn!("netlink::send(flags={flags:?})");
-
-        // FIXME(andypython): figure out the message header stuff...
         let data = message_hdr
             .iovecs()
             .iter()
@@ -285,32 +806,60 @@ impl INodeInterface for NetLinkSocket {
             .collect::<Vec<_>>();
 
         let hdr_size = core::mem::size_of::<netlink::nlmsghdr>();
-
+        let mut builder = NetlinkBuilder::new();
         let mut offset = 0;
 
         while offset + hdr_size <= data.len() {
-            let header = unsafe { &*(data.as_ptr().cast::<netlink::nlmsghdr>().byte_add(offset)) };
-            let payload = &data[offset + hdr_size..];
+            // SAFETY: The buffer is large enough to hold the header, which may be unaligned.
+            let header = unsafe {
+                data.as_ptr()
+                    .add(offset)
+                    .cast::<netlink::nlmsghdr>()
+                    .read_unaligned()
+            };
 
-            match header.nlmsg_type {
-                MessageType::Done => break,
-                MessageType::Error => {
-                    unimplemented!("netlink::send: error message received");
-                }
+            let len = header.nlmsg_len as usize;
 
-                MessageType::RtmGetRoute => self.get_route(header, payload),
+            if len < hdr_size || offset + len > data.len() {
+                break;
+            }
+
+            // Only requests are handled by the kernel.
+            if header.nlmsg_flags.contains(MessageFlags::REQUEST) {
+                let payload = &data[offset + hdr_size..offset + len];
 
-                ty => unimplemented!("netlink::send: unknown message type {ty:?}"),
+                match self.request(&header, payload, &mut builder) {
+                    Err(error) => builder.error(-(error as i32), &header),
+                    Ok(()) if header.nlmsg_flags.contains(MessageFlags::ACK) => {
+                        builder.error(0, &header)
+                    }
+
+                    Ok(()) => {}
+                }
             }
 
-            offset += header.nlmsg_len as usize;
+            offset += netlink::nlmsg_align(len as u32) as usize;
+        }
+
+        if !builder.is_empty() {
+            self.queue(builder.build());
         }
 
         Ok(data.len())
     }
 
-    fn poll(&self, _table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
-        unimplemented!()
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.recv_wq);
+        }
+
+        let mut flags = PollFlags::OUT;
+
+        if !self.recv_queue.lock_irq().is_empty() {
+            flags |= PollFlags::IN;
+        }
+
+        Ok(flags)
     }
 
     fn get_peername(&self) -> fs::Result<super::SocketAddr> {
@@ -318,12 +867,55 @@ impl INodeInterface for NetLinkSocket {
     }
 
     fn get_sockname(&self) -> fs::Result<super::SocketAddr> {
-        // TODO(andypython): fill in `nl_groups` and `nl_pid`.
         Ok(super::SocketAddr::Netlink(netlink::sockaddr_nl {
             nl_family: AF_NETLINK,
             nl_pad: 0,
             nl_pid: 0,
-            nl_groups: 0,
+            nl_groups: self.groups.load(Ordering::SeqCst),
         }))
     }
+
+    fn set_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().set(name, value),
+            SocketOptionLevel::Netlink => {
+                // The groups are numbered from 1, with group N being bit N - 1 of the mask.
+                let group = read_option::<u32>(value)?;
+
+                if !(1..=32).contains(&group) {
+                    return Err(SyscallError::EINVAL);
+                }
+
+                let mask = 1 << (group - 1);
+                let groups = self.groups.load(Ordering::SeqCst);
+
+                match name {
+                    netlink::NETLINK_ADD_MEMBERSHIP => self.set_groups(groups | mask),
+                    netlink::NETLINK_DROP_MEMBERSHIP => self.set_groups(groups & !mask),
+                    _ => return Err(SyscallError::ENOPROTOOPT),
+                }
+
+                Ok(())
+            }
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &mut [u8],
+    ) -> Result<usize, SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().get(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
 }
@@ -0,0 +1,387 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Packet Sockets
+//!
+//! Packet sockets (`AF_PACKET`) are used to send and receive raw link-layer frames directly
+//! from the network devices, bypassing the rest of the networking stack. `SOCK_RAW` sockets
+//! operate on the whole frame including the Ethernet header, while `SOCK_DGRAM` sockets have
+//! the Ethernet header removed on receive and built by the kernel on transmit.
+
+use aero_syscall::prelude::{IfReq, SIOCGIFINDEX};
+use aero_syscall::socket::{MessageFlags, MessageHeader, SocketOptionLevel};
+use aero_syscall::*;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::user_copy::UserRef;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::net::{self, packet, RawPacket};
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::{SocketAddr, SocketAddrRef, SocketOptions};
+
+/// Size of the Ethernet header (destination MAC, source MAC and ethertype).
+const ETH_HEADER_SIZE: usize = 14;
+/// Maximum number of frames queued on a socket before new frames start getting dropped.
+const MAX_QUEUED_FRAMES: usize = 256;
+
+struct Frame {
+    ifindex: usize,
+    pkttype: u8,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn protocol(&self) -> u16 {
+        u16::from_be_bytes([self.data[12], self.data[13]])
+    }
+
+    fn address(&self) -> SocketAddrPacket {
+        let mut addr = [0; 8];
+        addr[..6].copy_from_slice(&self.data[6..12]);
+
+        SocketAddrPacket {
+            family: AF_PACKET,
+            protocol: self.protocol().into(),
+            ifindex: self.ifindex as i32,
+            hatype: ARPHRD_ETHER,
+            pkttype: self.pkttype,
+            halen: 6,
+            addr,
+        }
+    }
+}
+
+struct PacketSocketInner {
+    /// Ethertype (in native byte order) of the frames to capture.
+    protocol: u16,
+    /// Interface index that the socket is bound to; zero for all interfaces.
+    ifindex: usize,
+    queue: VecDeque<Frame>,
+}
+
+pub struct PacketSocket {
+    typ: SocketType,
+    inner: Mutex<PacketSocketInner>,
+    options: Mutex<SocketOptions>,
+    wq: WaitQueue,
+    handle: Once<Arc<FileHandle>>,
+}
+
+impl PacketSocket {
+    /// Creates a new packet socket of the provided type, capturing frames with the
+    /// provided ethertype (in network byte order, as passed to `socket(2)`).
+    pub fn new(typ: SocketType, protocol: u16) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            typ,
+            inner: Mutex::new(PacketSocketInner {
+                protocol: u16::from_be(protocol),
+                ifindex: 0,
+                queue: VecDeque::new(),
+            }),
+            options: Mutex::new(SocketOptions::new(typ)),
+            wq: WaitQueue::new(),
+            handle: Once::new(),
+        });
+
+        packet::register(socket.clone());
+        socket
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle
+            .get()
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+
+    /// Returns the part of the frame that is visible to userland.
+    fn payload<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        match self.typ {
+            SocketType::Dgram => &frame[ETH_HEADER_SIZE..],
+            _ => frame,
+        }
+    }
+
+    fn transmit(&self, address: Option<&SocketAddrPacket>, data: &[u8]) -> fs::Result<usize> {
+        let (ifindex, protocol) = {
+            let inner = self.inner.lock_irq();
+
+            match address {
+                Some(addr) if addr.ifindex != 0 => (addr.ifindex as usize, addr.protocol()),
+                _ => (inner.ifindex, inner.protocol),
+            }
+        };
+
+        let device = net::device_by_index(ifindex).ok_or(FileSystemError::NotConnected)?;
+
+        let mut frame = Vec::with_capacity(data.len() + ETH_HEADER_SIZE);
+
+        if self.typ == SocketType::Dgram {
+            let address = address.ok_or(FileSystemError::NotConnected)?;
+
+            frame.extend_from_slice(&address.addr[..6]);
+            frame.extend_from_slice(device.mac().0.as_slice());
+            frame.extend_from_slice(&protocol.to_be_bytes());
+        }
+
+        frame.extend_from_slice(data);
+
+        if frame.len() < ETH_HEADER_SIZE {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        let mut packet: RawPacket =
+            unsafe { Box::<[u8], _>::new_zeroed_slice_in(frame.len(), DmaAllocator).assume_init() };
+
+        packet.copy_from_slice(&frame);
+        device.send(packet);
+
+        Ok(data.len())
+    }
+}
+
+impl INodeInterface for PacketSocket {
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata::with_file_type(FileType::Socket))
+    }
+
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn bind(&self, address: SocketAddrRef, _length: usize) -> fs::Result<()> {
+        let address = address.as_packet().ok_or(FileSystemError::NotSupported)?;
+
+        if address.ifindex != 0 && net::device_by_index(address.ifindex as usize).is_none() {
+            return Err(FileSystemError::EntryNotFound);
+        }
+
+        let mut inner = self.inner.lock_irq();
+
+        inner.ifindex = address.ifindex as usize;
+        inner.protocol = address.protocol();
+        inner.queue.clear();
+
+        Ok(())
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        if self.inner.lock_irq().queue.is_empty() && self.is_non_block() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let deadline = self.options.lock_irq().recv_deadline();
+        let mut inner = self
+            .wq
+            .block_on_until(&self.inner, deadline, |e| !e.queue.is_empty())?
+            .ok_or(FileSystemError::WouldBlock)?;
+        let frame = inner.queue.pop_front().unwrap();
+        let payload = self.payload(&frame.data);
+
+        let size = core::cmp::min(buffer.len(), payload.len());
+        buffer[..size].copy_from_slice(&payload[..size]);
+        Ok(size)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        self.transmit(None, buffer)
+    }
+
+    fn recv(&self, header: &mut MessageHeader, flags: MessageFlags) -> fs::Result<usize> {
+        let non_block = self.is_non_block() || flags.contains(MessageFlags::DONTWAIT);
+
+        if self.inner.lock_irq().queue.is_empty() && non_block {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let deadline = self.options.lock_irq().recv_deadline();
+        let mut inner = self
+            .wq
+            .block_on_until(&self.inner, deadline, |e| !e.queue.is_empty())?
+            .ok_or(FileSystemError::WouldBlock)?;
+
+        let frame = if flags.contains(MessageFlags::PEEK) {
+            let front = inner.queue.front().unwrap();
+
+            Frame {
+                ifindex: front.ifindex,
+                pkttype: front.pkttype,
+                data: front.data.clone(),
+            }
+        } else {
+            inner.queue.pop_front().unwrap()
+        };
+
+        core::mem::drop(inner);
+
+        if let Some(addr) = header.name_mut::<SocketAddrPacket>() {
+            *addr = frame.address();
+        }
+
+        let mut payload = self.payload(&frame.data);
+        let frame_len = payload.len();
+        let mut copied = 0;
+
+        for iovec in header.iovecs_mut() {
+            let iovec = iovec.as_slice_mut();
+            let size = core::cmp::min(iovec.len(), payload.len());
+
+            iovec[..size].copy_from_slice(&payload[..size]);
+            payload = &payload[size..];
+            copied += size;
+        }
+
+        header.write_control(&[]);
+
+        if !payload.is_empty() {
+            header.flags |= MessageFlags::TRUNC.bits() as i32;
+
+            // Return the real length of the frame, even if it was longer than the
+            // passed buffer.
+            if flags.contains(MessageFlags::TRUNC) {
+                return Ok(frame_len);
+            }
+        }
+
+        Ok(copied)
+    }
+
+    fn send(&self, header: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
+        let address = header.name_mut::<SocketAddrPacket>().cloned();
+        let data = header
+            .iovecs()
+            .iter()
+            .flat_map(|e| e.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.transmit(address.as_ref(), &data)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        match command {
+            SIOCGIFINDEX => {
+                let mut ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
+
+                let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let index =
+                    net::device_index_by_name(name).ok_or(FileSystemError::EntryNotFound)?;
+
+                ifreq.data.ifindex = index as i32;
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        let mut flags = PollFlags::OUT;
+
+        if !self.inner.lock_irq().queue.is_empty() {
+            flags |= PollFlags::IN;
+        }
+
+        Ok(flags)
+    }
+
+    fn get_sockname(&self) -> fs::Result<SocketAddr> {
+        let inner = self.inner.lock_irq();
+
+        Ok(SocketAddr::Packet(SocketAddrPacket {
+            family: AF_PACKET,
+            protocol: inner.protocol.into(),
+            ifindex: inner.ifindex as i32,
+            hatype: ARPHRD_ETHER,
+            pkttype: PACKET_HOST,
+            halen: 0,
+            addr: [0; 8],
+        }))
+    }
+
+    fn set_sockopt(&self, level: SocketOptionLevel, name: i32, value: &[u8]) -> Result<()> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().set(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_sockopt(&self, level: SocketOptionLevel, name: i32, value: &mut [u8]) -> Result<usize> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().get(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+}
+
+impl packet::PacketHandler for PacketSocket {
+    fn recv(&self, ifindex: usize, frame: &[u8], outgoing: bool) {
+        if frame.len() < ETH_HEADER_SIZE {
+            return;
+        }
+
+        let mut inner = self.inner.lock_irq();
+
+        if inner.ifindex != 0 && inner.ifindex != ifindex {
+            return;
+        }
+
+        let protocol = u16::from_be_bytes([frame[12], frame[13]]);
+
+        if inner.protocol == 0 || (inner.protocol != ETH_P_ALL && inner.protocol != protocol) {
+            return;
+        }
+
+        if inner.queue.len() >= MAX_QUEUED_FRAMES {
+            return;
+        }
+
+        let pkttype = if outgoing {
+            PACKET_OUTGOING
+        } else if frame[..6] == [0xff; 6] {
+            PACKET_BROADCAST
+        } else if frame[0] & 1 != 0 {
+            PACKET_MULTICAST
+        } else {
+            PACKET_HOST
+        };
+
+        inner.queue.push_back(Frame {
+            ifindex,
+            pkttype,
+            data: frame.to_vec(),
+        });
+
+        core::mem::drop(inner);
+        self.wq.notify_all();
+    }
+}
@@ -15,10 +15,15 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{InAddr, OpenFlags, SocketAddrInet, AF_INET};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::socket::{
+    MessageFlags, MessageHeader, SocketOption, SocketOptionLevel, TcpOption,
+};
+use aero_syscall::{InAddr, OpenFlags, SocketAddrInet, SocketType, SyscallError, AF_INET};
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use num_traits::FromPrimitive;
 
 use crabnet::network::Ipv4Addr;
 use spin::Once;
@@ -29,12 +34,18 @@ use crabnet_tcp::{Address, Error as TcpError, Packet as TcpPacket, State};
 
 use crate::fs::file_table::FileHandle;
 use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::fs::pipe::PipePage;
 use crate::fs::{self, FileSystemError};
 use crate::net;
 use crate::net::shim::PacketSend;
 use crate::net::{tcp, NetworkDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::{read_option, write_option, SocketOptions};
+
+/// Maximum size of the payload of a TCP segment sent over Ethernet.
+const TCP_MSS: usize = 1460;
+
 // ./aero.py -- -netdev user,id=mynet0 -device e1000,netdev=mynet0,id=ck_nic0 -object
 // filter-dump,id=mynet0,netdev=mynet0,file=qemulog.log
 
@@ -62,6 +73,10 @@ pub struct TcpSocket {
     handle: Once<Arc<FileHandle>>,
     sref: Weak<TcpSocket>,
     peer: Once<SocketAddrInet>,
+    options: Mutex<SocketOptions>,
+    /// Whether the Nagle algorithm is disabled (`TCP_NODELAY`). Segments are always sent
+    /// out immediately at the moment, so this is only recorded for `getsockopt(2)`.
+    nodelay: AtomicBool,
 }
 
 impl TcpSocket {
@@ -72,6 +87,8 @@ impl TcpSocket {
             sref: sref.clone(),
             handle: Once::new(),
             peer: Once::new(),
+            options: Mutex::new(SocketOptions::new(SocketType::Stream)),
+            nodelay: AtomicBool::new(false),
         })
     }
 
@@ -107,10 +124,14 @@ impl TcpSocket {
             Err(TcpError::WouldBlock) => {
                 drop(tcp);
 
-                let mut socket = self.wq.block_on(&self.tcp, |tcp| {
-                    tcp.as_ref()
-                        .map_or(true, |socket| !socket.recv_queue.is_empty())
-                })?;
+                let deadline = self.options.lock_irq().recv_deadline();
+                let mut socket = self
+                    .wq
+                    .block_on_until(&self.tcp, deadline, |tcp| {
+                        tcp.as_ref()
+                            .map_or(true, |socket| !socket.recv_queue.is_empty())
+                    })?
+                    .ok_or(FileSystemError::WouldBlock)?;
 
                 if let Some(socket) = socket.as_mut() {
                     Ok(socket.recv(buf).unwrap())
@@ -136,7 +157,13 @@ impl INodeInterface for TcpSocket {
     fn connect(&self, address: super::SocketAddrRef, _length: usize) -> crate::fs::Result<()> {
         {
             let mut tcp = self.tcp.lock_irq();
-            assert!(tcp.is_none(), "connect: socket is already initialized");
+
+            if let Some(socket) = tcp.as_ref() {
+                return match socket.state() {
+                    State::SynSent => Err(FileSystemError::AlreadyInProgress),
+                    _ => Err(FileSystemError::AlreadyConnected),
+                };
+            }
 
             let port = tcp::alloc_ephemeral_port(self.sref()).unwrap();
 
@@ -155,11 +182,25 @@ impl INodeInterface for TcpSocket {
             *tcp = Some(socket);
         }
 
-        let _ = self.wq.block_on(&self.tcp, |x| {
-            x.as_ref().unwrap().state() == State::Established
-        });
+        // The handshake is completed in the background and the socket becomes writable
+        // once the connection has been established.
+        if self.non_blocking() {
+            return Err(FileSystemError::InProgress);
+        }
 
-        Ok(())
+        // The handshake carries on in the background if the send timeout expires.
+        let deadline = self.options.lock_irq().send_deadline();
+        let tcp = self
+            .wq
+            .block_on_until(&self.tcp, deadline, |x| {
+                x.as_ref().unwrap().state() != State::SynSent
+            })?
+            .ok_or(FileSystemError::InProgress)?;
+
+        match tcp.as_ref().unwrap().state() {
+            State::Established => Ok(()),
+            _ => Err(FileSystemError::ConnectionRefused),
+        }
     }
 
     fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<fs::cache::DirCacheItem>> {
@@ -182,6 +223,25 @@ impl INodeInterface for TcpSocket {
         self.send(buf)
     }
 
+    fn splice_page(&self, _offset: usize, page: PipePage) -> fs::Result<usize> {
+        let mut tcp = self.tcp.lock_irq();
+        let socket = tcp.as_mut().ok_or(FileSystemError::NotConnected)?;
+
+        // The segments are built straight from the page, which is held on to until all of
+        // its data has been handed over to the device.
+        let mut sent = 0;
+
+        for chunk in page.as_slice().chunks(TCP_MSS) {
+            match socket.send(chunk) {
+                Ok(count) => sent += count,
+                Err(_) if sent != 0 => break,
+                Err(_) => return Err(FileSystemError::NotConnected),
+            }
+        }
+
+        Ok(sent)
+    }
+
     fn send(&self, message_hdr: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
         let data = message_hdr
             .iovecs()
@@ -194,7 +254,7 @@ impl INodeInterface for TcpSocket {
         let socket = tcp.as_mut().ok_or(FileSystemError::NotSupported)?;
 
         // TODO: handle fragmentation in crabnet_tcp
-        for chunk in data.chunks(1460) {
+        for chunk in data.chunks(TCP_MSS) {
             socket.send(chunk).expect("failed to send data");
         }
 
@@ -250,7 +310,15 @@ impl INodeInterface for TcpSocket {
         let mut tcp = self.tcp.lock_irq();
 
         if let Some(socket) = tcp.as_mut() {
-            assert_ne!(socket.state(), State::Closed);
+            // Connection still in progress.
+            if socket.state() == State::SynSent {
+                return Ok(flags);
+            }
+
+            // The connection attempt has failed or the connection has been closed.
+            if socket.state() == State::Closed {
+                return Ok(flags | PollFlags::OUT | PollFlags::ERR);
+            }
 
             flags |= PollFlags::OUT;
 
@@ -261,4 +329,69 @@ impl INodeInterface for TcpSocket {
 
         Ok(flags)
     }
+
+    fn set_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().set(name, value),
+            SocketOptionLevel::Tcp => match TcpOption::from_i32(name) {
+                Some(TcpOption::NoDelay) => {
+                    let nodelay = read_option::<i32>(value)? != 0;
+                    self.nodelay.store(nodelay, Ordering::SeqCst);
+                    Ok(())
+                }
+
+                option => {
+                    log::warn!("tcp: unsupported socket option {option:?}");
+                    Err(SyscallError::ENOPROTOOPT)
+                }
+            },
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &mut [u8],
+    ) -> Result<usize, SyscallError> {
+        match level {
+            SocketOptionLevel::Socket if name == SocketOption::Error as i32 => {
+                let closed = self
+                    .tcp
+                    .lock_irq()
+                    .as_ref()
+                    .is_some_and(|socket| socket.state() == State::Closed);
+
+                let error = if closed {
+                    SyscallError::ECONNREFUSED as i32
+                } else {
+                    0
+                };
+
+                Ok(write_option(value, error))
+            }
+
+            SocketOptionLevel::Socket => self.options.lock_irq().get(name, value),
+            SocketOptionLevel::Tcp => match TcpOption::from_i32(name) {
+                Some(TcpOption::NoDelay) => {
+                    let nodelay = self.nodelay.load(Ordering::SeqCst);
+                    Ok(write_option(value, nodelay as i32))
+                }
+
+                option => {
+                    log::warn!("tcp: unsupported socket option {option:?}");
+                    Err(SyscallError::ENOPROTOOPT)
+                }
+            },
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
 }
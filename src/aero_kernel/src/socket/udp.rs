@@ -15,11 +15,19 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::prelude::{IfReq, SIOCGIFHWADDR, SIOCSIFADDR, SIOCSIFNETMASK};
-use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{OpenFlags, SocketAddrInet};
+use aero_syscall::prelude::{
+    IfReq, RouteFlags, RtEntry, SockAddrStorage, SIOCADDRT, SIOCDELRT, SIOCGIFHWADDR, SIOCSIFADDR,
+    SIOCSIFNETMASK,
+};
+use aero_syscall::socket::{
+    ControlMessageType, IpOption, MessageFlags, MessageHeader, SockExtendedErr, SocketOption,
+    SocketOptionLevel, SO_EE_ORIGIN_ICMP,
+};
+use aero_syscall::{InAddr, OpenFlags, SocketAddrInet, SocketType, SyscallError, AF_INET};
+use alloc::collections::VecDeque;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use num_traits::FromPrimitive;
 use spin::Once;
 
 use crate::arch::user_copy::UserRef;
@@ -28,16 +36,52 @@ use crate::fs::file_table::FileHandle;
 use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags};
 use crate::fs::{self, FileSystemError};
 use crate::mem::paging::VirtAddr;
+use crate::net::icmp::IcmpError;
+use crate::net::route::{self, Route};
 use crate::net::udp::{self, UdpHandler};
 use crate::net::{self};
 use crate::utils::sync::{Mutex, WaitQueue};
 
-use super::SocketAddrRef;
+use super::{read_option, write_option, SocketAddrRef, SocketOptions};
 
 use crabnet::data_link::{Eth, EthType, MacAddr};
 use crabnet::network::{Ipv4, Ipv4Addr, Ipv4Type};
 use crabnet::transport::Udp;
 
+/// Converts the provided socket address into an IPv4 address.
+fn inet_addr(addr: &SockAddrStorage) -> fs::Result<Ipv4Addr> {
+    let addr = SocketAddrRef::from_family(VirtAddr::new(addr as *const _ as _), addr.sa_family)
+        .map_err(|_| FileSystemError::NotSupported)?
+        .as_inet()
+        .ok_or(FileSystemError::NotSupported)?;
+
+    Ok(Ipv4Addr::from(addr.addr()))
+}
+
+/// Maximum number of errors kept in the error queue of a socket.
+const MAX_QUEUED_ERRORS: usize = 16;
+
+fn inet_sockaddr(addr: Ipv4Addr, port: u16) -> SocketAddrInet {
+    SocketAddrInet {
+        family: AF_INET,
+        port: port.into(),
+        sin_addr: InAddr {
+            addr: u32::from_le_bytes(addr.0),
+        },
+        padding: [0; 8],
+    }
+}
+
+/// Returns the interface index of the device named by the `rt_dev` field of a routing
+/// table entry.
+fn route_device(name: *const u8) -> fs::Result<usize> {
+    let name = crate::utils::validate_slice(name, 16).map_err(|_| FileSystemError::InvalidPath)?;
+    let len = name.iter().position(|&x| x == 0).unwrap_or(name.len());
+    let name = core::str::from_utf8(&name[..len]).map_err(|_| FileSystemError::InvalidPath)?;
+
+    net::device_index_by_name(name).ok_or(FileSystemError::EntryNotFound)
+}
+
 #[derive(Default)]
 enum SocketState {
     /// The socket is not connected.
@@ -52,10 +96,16 @@ struct UdpSocketInner {
     address: Option<SocketAddrInet>,
     state: SocketState,
     incoming: Vec<Vec<u8>>,
+    /// Error reported by the next operation on the socket (`SO_ERROR`).
+    error: Option<FileSystemError>,
+    /// ICMP errors received with `IP_RECVERR` set, read using `MSG_ERRQUEUE`.
+    errors: VecDeque<IcmpError>,
+    recv_err: bool,
 }
 
 pub struct UdpSocket {
     inner: Mutex<UdpSocketInner>,
+    options: Mutex<SocketOptions>,
     wq: WaitQueue,
     handle: Once<Arc<FileHandle>>,
 
@@ -69,6 +119,7 @@ impl UdpSocket {
             handle: Once::new(),
 
             inner: Mutex::new(Default::default()),
+            options: Mutex::new(SocketOptions::new(SocketType::Dgram)),
             sref: sref.clone(),
         })
     }
@@ -100,6 +151,77 @@ impl UdpSocket {
         }
     }
 
+    fn take_error(&self) -> Option<FileSystemError> {
+        self.inner.lock_irq().error.take()
+    }
+
+    /// Receives the oldest error from the error queue of the socket, along with the payload
+    /// of the datagram that caused it.
+    fn recv_error(&self, message_hdr: &mut MessageHeader) -> fs::Result<usize> {
+        let mut inner = self.inner.lock_irq();
+        let error = inner
+            .errors
+            .pop_front()
+            .ok_or(FileSystemError::WouldBlock)?;
+
+        // The pending error is replaced by the one of the next queued error.
+        inner.error = inner.errors.front().map(|error| error.error().0);
+        drop(inner);
+
+        if let Some(name) = message_hdr.name_mut::<SocketAddrInet>() {
+            *name = inet_sockaddr(error.dest, error.dest_port);
+        }
+
+        let extended = SockExtendedErr {
+            ee_errno: SyscallError::from(error.error().0) as u32,
+            ee_origin: SO_EE_ORIGIN_ICMP,
+            ee_type: error.typ,
+            ee_code: error.code,
+            ee_info: error.info,
+            ..Default::default()
+        };
+
+        // The extended error is followed by the address of the host that reported it.
+        let offender = inet_sockaddr(error.offender, 0);
+        let mut data = alloc::vec![0; core::mem::size_of::<SockExtendedErr>()];
+        write_option(&mut data, extended);
+
+        // SAFETY: `SocketAddrInet` is plain old data.
+        data.extend_from_slice(unsafe {
+            core::slice::from_raw_parts(
+                (&offender as *const SocketAddrInet).cast::<u8>(),
+                core::mem::size_of::<SocketAddrInet>(),
+            )
+        });
+
+        message_hdr.write_control(&[(
+            SocketOptionLevel::Ip,
+            ControlMessageType::RecvErr,
+            data.as_slice(),
+        )]);
+
+        let mut payload = error.payload.as_slice();
+        let size = message_hdr
+            .iovecs_mut()
+            .iter_mut()
+            .map(|iovec| {
+                let iovec = iovec.as_slice_mut();
+                let size = core::cmp::min(iovec.len(), payload.len());
+                iovec[..size].copy_from_slice(&payload[..size]);
+                payload = &payload[size..];
+                size
+            })
+            .sum::<usize>();
+
+        if !payload.is_empty() {
+            message_hdr.flags |= MessageFlags::TRUNC.bits() as i32;
+        }
+
+        message_hdr.flags |= MessageFlags::ERRQUEUE.bits() as i32;
+
+        Ok(size)
+    }
+
     pub fn is_non_block(&self) -> bool {
         self.handle
             .get()
@@ -126,9 +248,13 @@ impl INodeInterface for UdpSocket {
 
     fn bind(&self, address: super::SocketAddrRef, _length: usize) -> fs::Result<()> {
         let address = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+        let reuse_addr = self.options.lock_irq().reuse_addr();
+
+        if !udp::bind(address.port.to_native(), self.sref(), reuse_addr) {
+            return Err(FileSystemError::AddressInUse);
+        }
 
         self.set_addr(address.clone());
-        udp::bind(address.port.to_native(), self.sref());
         Ok(())
     }
 
@@ -143,6 +269,10 @@ impl INodeInterface for UdpSocket {
     }
 
     fn send(&self, message_hdr: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
+        if let Some(error) = self.take_error() {
+            return Err(error);
+        }
+
         let name = message_hdr
             .name_mut::<SocketAddrInet>()
             .cloned()
@@ -185,14 +315,31 @@ impl INodeInterface for UdpSocket {
         Ok(data.len())
     }
 
-    fn recv(&self, message_hdr: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
-        // assert!(flags.is_empty());
+    fn recv(&self, message_hdr: &mut MessageHeader, flags: MessageFlags) -> fs::Result<usize> {
+        if flags.contains(MessageFlags::ERRQUEUE) {
+            return self.recv_error(message_hdr);
+        }
+
+        if let Some(error) = self.take_error() {
+            return Err(error);
+        }
 
         if self.inner.lock_irq().incoming.is_empty() && self.is_non_block() {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut this = self.wq.block_on(&self.inner, |e| !e.incoming.is_empty())?;
+        let deadline = self.options.lock_irq().recv_deadline();
+        let mut this = self
+            .wq
+            .block_on_until(&self.inner, deadline, |e| {
+                !e.incoming.is_empty() || e.error.is_some()
+            })?
+            .ok_or(FileSystemError::WouldBlock)?;
+
+        if let Some(error) = this.error.take() {
+            return Err(error);
+        }
+
         let packet = this.incoming.pop().expect("recv: someone was greedy");
 
         let mut data = packet.as_slice().to_vec();
@@ -215,7 +362,7 @@ impl INodeInterface for UdpSocket {
                 let mut ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
 
                 let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
-                assert!(name == "eth0");
+                let device = net::device_by_name(name).ok_or(FileSystemError::NoDevice)?;
 
                 let hwaddr = unsafe {
                     core::slice::from_raw_parts_mut(
@@ -224,8 +371,7 @@ impl INodeInterface for UdpSocket {
                     )
                 };
 
-                let mac_addr = net::default_device().mac();
-                hwaddr.copy_from_slice(mac_addr.0.as_slice());
+                hwaddr.copy_from_slice(device.mac().0.as_slice());
                 Ok(0)
             }
 
@@ -237,11 +383,8 @@ impl INodeInterface for UdpSocket {
                     .ok_or(FileSystemError::NotSupported)?;
 
                 let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::device_by_name(name).ok_or(FileSystemError::NoDevice)?;
 
-                // FIXME:
-                assert!(name == "eth0");
-
-                let device = net::default_device();
                 device.set_ip(Ipv4Addr::from(socket.addr()));
                 Ok(0)
             }
@@ -254,16 +397,62 @@ impl INodeInterface for UdpSocket {
                     .ok_or(FileSystemError::NotSupported)?;
 
                 let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::device_by_name(name).ok_or(FileSystemError::NoDevice)?;
 
-                // FIXME:
-                assert!(name == "eth0");
-
-                let device = net::default_device();
                 device.set_subnet_mask(Ipv4Addr::from(socket.addr()));
 
                 Ok(0)
             }
 
+            SIOCADDRT | SIOCDELRT => {
+                let entry = unsafe { UserRef::<RtEntry>::new(VirtAddr::new(arg as _)) };
+                let flags = entry.flags();
+
+                let dest = inet_addr(&entry.rt_dst)?;
+                let mask = if flags.contains(RouteFlags::HOST) {
+                    Ipv4Addr::new(255, 255, 255, 255)
+                } else {
+                    inet_addr(&entry.rt_genmask)?
+                };
+
+                if command == SIOCDELRT {
+                    return if route::remove(dest, mask) {
+                        Ok(0)
+                    } else {
+                        Err(FileSystemError::EntryNotFound)
+                    };
+                }
+
+                let gateway = if flags.contains(RouteFlags::GATEWAY) {
+                    Some(inet_addr(&entry.rt_gateway)?)
+                } else {
+                    None
+                };
+
+                let ifindex = if entry.rt_dev.is_null() {
+                    // Use the device that the gateway (or the destination) is reachable through.
+                    route::lookup(gateway.unwrap_or(dest))
+                        .and_then(|(device, _)| net::device_index(&device))
+                        .ok_or(FileSystemError::EntryNotFound)?
+                } else {
+                    route_device(entry.rt_dev)?
+                };
+
+                let route = Route {
+                    dest,
+                    mask,
+                    gateway,
+                    ifindex,
+                    metric: entry.rt_metric.max(0) as u32,
+                };
+
+                if !route::add(route) {
+                    return Err(FileSystemError::EntryExists);
+                }
+
+                Ok(0)
+            }
+
             _ => unreachable!("inet::ioctl(): unknown command {command}"),
         }
     }
@@ -274,13 +463,82 @@ impl INodeInterface for UdpSocket {
         }
 
         let mut flags = PollFlags::OUT;
+        let inner = self.inner.lock_irq();
 
-        if !self.inner.lock_irq().incoming.is_empty() {
+        if !inner.incoming.is_empty() {
             flags |= PollFlags::IN;
         }
 
+        if inner.error.is_some() || !inner.errors.is_empty() {
+            flags |= PollFlags::ERR;
+        }
+
         Ok(flags)
     }
+
+    fn set_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().set(name, value),
+            SocketOptionLevel::Ip => match IpOption::from_i32(name) {
+                Some(IpOption::RecvErr) => {
+                    let recv_err = read_option::<i32>(value)? != 0;
+                    let mut inner = self.inner.lock_irq();
+
+                    inner.recv_err = recv_err;
+
+                    if !recv_err {
+                        inner.errors.clear();
+                    }
+
+                    Ok(())
+                }
+
+                option => {
+                    log::warn!("udp: unsupported socket option {option:?}");
+                    Err(SyscallError::ENOPROTOOPT)
+                }
+            },
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &mut [u8],
+    ) -> Result<usize, SyscallError> {
+        match level {
+            SocketOptionLevel::Socket if name == SocketOption::Error as i32 => {
+                let error = self
+                    .take_error()
+                    .map_or(0, |error| SyscallError::from(error) as i32);
+
+                Ok(write_option(value, error))
+            }
+
+            SocketOptionLevel::Socket => self.options.lock_irq().get(name, value),
+            SocketOptionLevel::Ip => match IpOption::from_i32(name) {
+                Some(IpOption::RecvErr) => {
+                    let recv_err = self.inner.lock_irq().recv_err;
+                    Ok(write_option(value, recv_err as i32))
+                }
+
+                option => {
+                    log::warn!("udp: unsupported socket option {option:?}");
+                    Err(SyscallError::ENOPROTOOPT)
+                }
+            },
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
 }
 
 impl UdpHandler for UdpSocket {
@@ -288,4 +546,23 @@ impl UdpHandler for UdpSocket {
         self.inner.lock_irq().incoming.push(payload.to_vec());
         self.wq.notify_all();
     }
+
+    fn on_error(&self, error: IcmpError) {
+        let mut inner = self.inner.lock_irq();
+        let (errno, hard) = error.error();
+
+        if inner.recv_err {
+            if inner.errors.len() < MAX_QUEUED_ERRORS {
+                inner.errors.push_back(error);
+            }
+        } else if !hard || !matches!(inner.state, SocketState::Connected(_)) {
+            // Without `IP_RECVERR`, only hard errors are reported and only to connected sockets.
+            return;
+        }
+
+        inner.error = Some(errno);
+        drop(inner);
+
+        self.wq.notify_all();
+    }
 }
@@ -15,9 +15,11 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::{OpenFlags, SocketAddrUnix, SyscallError, AF_UNIX};
+use aero_syscall::{OpenFlags, SocketAddrUnix, SocketType, SyscallError, AF_UNIX};
 
-use aero_syscall::socket::{MessageFlags, MessageHeader};
+use aero_syscall::socket::{
+    ucred, ControlMessageType, MessageFlags, MessageHeader, SocketOption, SocketOptionLevel,
+};
 
 use alloc::collections::VecDeque;
 use alloc::sync::{Arc, Weak};
@@ -33,9 +35,11 @@ use crate::fs::inode::{DirEntry, FileType, INodeInterface, Metadata, PollFlags,
 use crate::fs::{FileSystemError, Path};
 
 use crate::mem::paging::VirtAddr;
+use crate::userland::scheduler;
+use crate::userland::task::Task;
 use crate::utils::sync::{Mutex, WaitQueue};
 
-use super::SocketAddrRef;
+use super::{write_option, SocketAddrRef, SocketOptions};
 
 fn path_from_unix_sock(address: &SocketAddrUnix) -> fs::Result<&Path> {
     // The abstract namespace socket allows the creation of a socket
@@ -57,21 +61,160 @@ fn path_from_unix_sock(address: &SocketAddrUnix) -> fs::Result<&Path> {
     Ok(Path::new(path_str))
 }
 
-#[derive(Debug, Default)]
+/// Credentials of the process at the other end of a connection or of the sender of a
+/// message, reported by `SO_PEERCRED` and `SCM_CREDENTIALS`.
+#[derive(Clone)]
+struct Credentials(Arc<Task>);
+
+impl Credentials {
+    /// Returns the credentials of the current process.
+    fn current() -> Self {
+        Self(scheduler::current_thread().process_leader())
+    }
+
+    /// Checks the `SCM_CREDENTIALS` control messages of `header` against the credentials
+    /// of the current process, which are the ones attached to the message regardless.
+    fn check_header(header: &MessageHeader) -> fs::Result<()> {
+        let current = scheduler::current_thread();
+
+        for (cmsg, data) in header.control() {
+            if cmsg.level() != Some(SocketOptionLevel::Socket)
+                || cmsg.typ() != Some(ControlMessageType::Credentials)
+            {
+                continue;
+            }
+
+            if data.len() < core::mem::size_of::<ucred>() {
+                return Err(FileSystemError::InvalidArgument);
+            }
+
+            // SAFETY: We have checked above that `data` holds a whole `ucred`.
+            let cred = unsafe { data.as_ptr().cast::<ucred>().read_unaligned() };
+
+            // Processes cannot claim to be someone else. User and group IDs are not
+            // implemented yet, so every process runs as root.
+            if cred.pid as usize != current.vpid() || cred.uid != 0 || cred.gid != 0 {
+                return Err(FileSystemError::PermissionDenied);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the credentials as seen by the current task. The PID is reported as 0 if
+    /// the process is not visible in the PID namespace of the current task.
+    fn to_ucred(&self) -> ucred {
+        let pid = self.0.pid_in(scheduler::current_thread().pid_ns());
+
+        ucred {
+            pid: pid.unwrap_or(0) as i32,
+            uid: 0,
+            gid: 0,
+        }
+    }
+}
+
+/// File descriptors in flight, attached to a message using `SCM_RIGHTS`.
+///
+/// Each in-flight handle holds an open reference to the underlying file, which is
+/// released once the handle has been installed in the receiver's file table or the
+/// message is dropped without ever being received.
+#[derive(Default)]
+pub struct ScmRights(Vec<Arc<FileHandle>>);
+
+impl ScmRights {
+    /// Parses the `SCM_RIGHTS` control messages of `header` and takes a reference to each
+    /// of the passed file descriptors from the file table of the current task.
+    fn from_header(header: &MessageHeader) -> fs::Result<Self> {
+        let file_table = &scheduler::current_thread().file_table;
+        let mut rights = Self::default();
+
+        for (cmsg, data) in header.control() {
+            match (cmsg.level(), cmsg.typ()) {
+                (Some(SocketOptionLevel::Socket), Some(ControlMessageType::Rights)) => {
+                    for fd in data.chunks_exact(core::mem::size_of::<i32>()) {
+                        let fd = i32::from_ne_bytes(fd.try_into().unwrap());
+                        let handle = file_table
+                            .get_handle(fd as usize)
+                            .ok_or(FileSystemError::BadFileDescriptor)?;
+
+                        rights.0.push(handle.duplicate(handle.fd, false)?);
+                    }
+                }
+
+                // Checked by `Credentials::check_header()`.
+                (Some(SocketOptionLevel::Socket), Some(ControlMessageType::Credentials)) => {}
+                _ => log::warn!("unix: unsupported control message {cmsg:?}"),
+            }
+        }
+
+        Ok(rights)
+    }
+
+    /// Installs the in-flight file descriptors in the file table of the current task
+    /// and returns their numbers as the payload of a `SCM_RIGHTS` control message that
+    /// has to fit in `space` bytes of the ancillary data buffer of `header`. The file
+    /// descriptors that do not fit, or that cannot be installed because the file table
+    /// is full, are closed and `MSG_CTRUNC` is set.
+    fn deliver(self, header: &mut MessageHeader, space: usize, flags: MessageFlags) -> Vec<u8> {
+        let file_table = &scheduler::current_thread().file_table;
+        let cloexec = flags.contains(MessageFlags::CMSG_CLOEXEC);
+
+        let capacity =
+            space.saturating_sub(aero_syscall::socket::cmsg_len(0)) / core::mem::size_of::<i32>();
+
+        let mut fds = Vec::new();
+
+        for handle in self.0.iter().take(capacity) {
+            // Stop installing once the file table is full; the remaining file descriptors
+            // are closed and reported as truncated.
+            match file_table.install_handle(handle, cloexec) {
+                Ok(fd) => fds.push(fd as i32),
+                Err(_) => break,
+            }
+        }
+
+        if fds.len() < self.0.len() {
+            header.flags |= MessageFlags::CTRUNC.bits() as i32;
+        }
+
+        // Dropping `self` releases the references held by the in-flight handles.
+        fds.iter().flat_map(|fd| fd.to_ne_bytes()).collect()
+    }
+}
+
+impl Drop for ScmRights {
+    fn drop(&mut self) {
+        for handle in self.0.drain(..) {
+            handle.inode().close(handle.flags());
+        }
+    }
+}
+
 pub struct Message {
     data: Vec<u8>,
-    // TODO: Keep track of the sender of the message here?
+    rights: ScmRights,
+    sender: Credentials,
+    /// Set once part of the data of the message has been read.
+    partial: bool,
 }
 
 impl Message {
-    pub fn new(data: Vec<u8>) -> Self {
-        Self { data }
+    fn new(data: Vec<u8>, rights: ScmRights, sender: Credentials) -> Self {
+        Self {
+            data,
+            rights,
+            sender,
+            partial: false,
+        }
     }
 }
 
 #[derive(Default)]
 pub struct MessageQueue {
     messages: VecDeque<Message>,
+    /// Number of bytes of data queued.
+    len: usize,
 }
 
 impl MessageQueue {
@@ -79,15 +222,22 @@ impl MessageQueue {
         self.messages.is_empty()
     }
 
+    /// Returns the number of bytes of data queued.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
     pub fn read(&mut self, buffer: &mut [u8]) -> usize {
         if let Some(message) = self.messages.front_mut() {
             let message_len = message.data.len();
             let size = core::cmp::min(buffer.len(), message_len);
 
             buffer[..size].copy_from_slice(&message.data[..size]);
+            self.len -= size;
 
             if size < message_len {
                 message.data.drain(..size);
+                message.partial = true;
                 return size;
             }
 
@@ -98,10 +248,41 @@ impl MessageQueue {
         }
     }
 
-    pub fn write(&mut self, buffer: &[u8]) {
-        let message = Message::new(buffer.to_vec());
+    fn write(&mut self, buffer: &[u8], rights: ScmRights, sender: Credentials) {
+        let message = Message::new(buffer.to_vec(), rights, sender);
+
+        self.len += buffer.len();
         self.messages.push_back(message);
     }
+
+    /// Returns the credentials of the sender of the message at the front of the queue.
+    fn sender(&self) -> Option<&Credentials> {
+        self.messages.front().map(|message| &message.sender)
+    }
+
+    /// Returns `true` if no data of the message at the front of the queue has been read.
+    fn at_boundary(&self) -> bool {
+        self.messages
+            .front()
+            .is_some_and(|message| !message.partial)
+    }
+
+    /// Returns `true` if the message at the front of the queue has file descriptors in
+    /// flight attached to it.
+    fn has_rights(&self) -> bool {
+        self.messages
+            .front()
+            .is_some_and(|message| !message.rights.0.is_empty())
+    }
+
+    /// Takes the file descriptors in flight attached to the message at the front of
+    /// the queue.
+    fn take_rights(&mut self) -> ScmRights {
+        self.messages
+            .front_mut()
+            .map(|message| core::mem::take(&mut message.rights))
+            .unwrap_or_default()
+    }
 }
 
 pub struct AcceptQueue {
@@ -163,6 +344,9 @@ enum UnixSocketState {
     /// The socket is listening for new connections.
     Listening(AcceptQueue),
 
+    /// The socket is waiting for the listening peer to accept the connection.
+    Connecting,
+
     /// The socket has connected to a peer.
     Connected(Arc<UnixSocket>),
 }
@@ -187,11 +371,17 @@ struct UnixSocketInner {
     address: Option<SocketAddrUnix>,
 
     state: UnixSocketState,
+
+    /// Credentials of the process that listened or connected on the socket.
+    cred: Option<Credentials>,
+    /// Credentials of the peer, recorded when the connection was established.
+    peer_cred: Option<Credentials>,
 }
 
 pub struct UnixSocket {
     inner: Mutex<UnixSocketInner>,
     buffer: Mutex<MessageQueue>,
+    options: Mutex<SocketOptions>,
     wq: WaitQueue,
     weak: Weak<UnixSocket>,
     handle: Once<Arc<FileHandle>>,
@@ -203,6 +393,7 @@ impl UnixSocket {
             inner: Mutex::new(UnixSocketInner::default()),
 
             buffer: Mutex::new(MessageQueue::default()),
+            options: Mutex::new(SocketOptions::new(SocketType::Stream)),
             wq: WaitQueue::new(),
             weak: weak.clone(),
             handle: Once::new(),
@@ -220,8 +411,14 @@ impl UnixSocket {
             .downcast_arc::<UnixSocket>()
             .ok_or(FileSystemError::NotSocket)?;
 
-        a.inner.lock_irq().state = UnixSocketState::Connected(b.clone());
-        b.inner.lock_irq().state = UnixSocketState::Connected(a);
+        for (this, peer) in [(&a, &b), (&b, &a)] {
+            let mut inner = this.inner.lock_irq();
+
+            inner.state = UnixSocketState::Connected(peer.clone());
+            inner.cred = Some(Credentials::current());
+            inner.peer_cred = Some(Credentials::current());
+        }
+
         Ok(())
     }
 
@@ -236,6 +433,78 @@ impl UnixSocket {
             .flags()
             .contains(OpenFlags::O_NONBLOCK)
     }
+
+    /// Returns the peer that the socket is connected to.
+    fn peer(&self) -> fs::Result<Arc<UnixSocket>> {
+        match &self.inner.lock_irq().state {
+            UnixSocketState::Connected(peer) => Ok(peer.clone()),
+            _ => Err(FileSystemError::NotConnected),
+        }
+    }
+
+    /// Returns the number of bytes that can be queued on the socket before the senders block.
+    fn capacity(&self) -> usize {
+        self.options.lock_irq().recv_buf()
+    }
+
+    /// Returns whether there is room for more data in the receive queue of the socket.
+    fn has_space(&self) -> bool {
+        let capacity = self.capacity();
+        self.buffer.lock_irq().len() < capacity
+    }
+
+    /// Queues the provided buffer on the peer, blocking while its receive queue is full unless
+    /// the socket is non-blocking. The file descriptors in flight are attached to the first
+    /// part of the data that is queued.
+    fn send_to_peer(&self, buffer: &[u8], rights: ScmRights) -> fs::Result<usize> {
+        let peer = self.peer()?;
+        let capacity = peer.capacity();
+
+        let nonblock = self.is_non_block();
+        let deadline = self.options.lock_irq().send_deadline();
+
+        let mut rights = Some(rights);
+        let mut written = 0;
+
+        // Empty messages are queued as well, as they can carry ancillary data.
+        while written < buffer.len() || rights.is_some() {
+            let queue = if nonblock {
+                Ok(Some(peer.buffer.lock_irq()))
+            } else {
+                peer.wq
+                    .block_on_until(&peer.buffer, deadline, |queue| queue.len() < capacity)
+            };
+
+            let queue = match queue {
+                Ok(queue) => queue,
+                // Interrupted by a signal after some of the data was written.
+                Err(_) if written != 0 => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            // The receive queue of the peer is full and the socket is non-blocking, or the send
+            // timeout expired.
+            let mut queue = match queue.filter(|queue| queue.len() < capacity) {
+                Some(queue) => queue,
+                None if written == 0 => return Err(FileSystemError::WouldBlock),
+                None => break,
+            };
+
+            let count = core::cmp::min(capacity - queue.len(), buffer.len() - written);
+            queue.write(
+                &buffer[written..written + count],
+                rights.take().unwrap_or_default(),
+                Credentials::current(),
+            );
+
+            written += count;
+
+            core::mem::drop(queue);
+            peer.wq.notify_all();
+        }
+
+        Ok(written)
+    }
 }
 
 impl INodeInterface for UnixSocket {
@@ -258,23 +527,23 @@ impl INodeInterface for UnixSocket {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.wq.block_on(&self.buffer, |e| !e.is_empty())?;
+        let deadline = self.options.lock_irq().recv_deadline();
+        let mut buffer = self
+            .wq
+            .block_on_until(&self.buffer, deadline, |e| !e.is_empty())?
+            .ok_or(FileSystemError::WouldBlock)?;
 
         let read = buffer.read(user_buffer);
+
+        // Wake up the peer if it is waiting for room in the queue.
+        core::mem::drop(buffer);
+        self.wq.notify_all();
+
         Ok(read)
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        let inner = self.inner.lock_irq();
-        let peer = match inner.state {
-            UnixSocketState::Connected(ref peer) => peer,
-            _ => return Err(FileSystemError::NotConnected),
-        };
-
-        peer.buffer.lock_irq().write(buffer);
-        peer.wq.notify_all();
-
-        Ok(buffer.len())
+        self.send_to_peer(buffer, ScmRights::default())
     }
 
     fn listen(&self, backlog: usize) -> Result<(), SyscallError> {
@@ -285,6 +554,7 @@ impl INodeInterface for UnixSocket {
             // We cannot listen on a socket that has not been bound.
             UnixSocketState::Disconnected if is_bound => {
                 inner.state = UnixSocketState::Listening(AcceptQueue::new(backlog));
+                inner.cred = Some(Credentials::current());
                 Ok(())
             }
 
@@ -293,7 +563,7 @@ impl INodeInterface for UnixSocket {
                 Ok(())
             }
 
-            _ => unreachable!(),
+            _ => Err(SyscallError::EINVAL),
         }
     }
 
@@ -325,6 +595,18 @@ impl INodeInterface for UnixSocket {
             .downcast_arc::<UnixSocket>()
             .ok_or(FileSystemError::NotSocket)?;
 
+        // A socket cannot connect to itself; the lock of the target is taken below, while the
+        // socket's own lock is taken again.
+        if core::ptr::eq(self, Arc::as_ptr(&target)) {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        match self.inner.lock_irq().state {
+            UnixSocketState::Connected(_) => return Err(FileSystemError::AlreadyConnected),
+            UnixSocketState::Connecting => return Err(FileSystemError::AlreadyInProgress),
+            _ => {}
+        }
+
         let mut itarget = target.inner.lock_irq();
 
         let queue = match &mut itarget.state {
@@ -332,18 +614,52 @@ impl INodeInterface for UnixSocket {
             _ => return Err(FileSystemError::ConnectionRefused),
         };
 
-        queue.push(self.sref()).unwrap();
+        queue
+            .push(self.sref())
+            .map_err(|_| FileSystemError::WouldBlock)?;
+
+        {
+            let mut inner = self.inner.lock_irq();
+
+            inner.state = UnixSocketState::Connecting;
+            inner.cred = Some(Credentials::current());
+            inner.peer_cred.clone_from(&itarget.cred);
+        }
+
         target.wq.notify_all();
         core::mem::drop(itarget); // release the lock
 
-        let _ = self.wq.block_on(&self.inner, |e| e.state.is_connected())?;
+        // The connection is completed once the peer accepts it, which is reported by
+        // the socket becoming writable.
+        if self.is_non_block() {
+            return Err(FileSystemError::InProgress);
+        }
+
+        let deadline = self.options.lock_irq().send_deadline();
+        self.wq
+            .block_on_until(&self.inner, deadline, |e| e.state.is_connected())?
+            .ok_or(FileSystemError::InProgress)?;
+
         Ok(())
     }
 
     fn accept(&self, address: Option<(VirtAddr, &mut u32)>) -> fs::Result<Arc<UnixSocket>> {
-        let mut inner = self.wq.block_on(&self.inner, |e| {
-            e.state.queue().is_some_and(|x| !x.is_empty())
-        })?;
+        {
+            let mut inner = self.inner.lock_irq();
+            let queue = inner.state.queue().ok_or(FileSystemError::InvalidPath)?;
+
+            if queue.is_empty() && self.is_non_block() {
+                return Err(FileSystemError::WouldBlock);
+            }
+        }
+
+        let deadline = self.options.lock_irq().recv_deadline();
+        let mut inner = self
+            .wq
+            .block_on_until(&self.inner, deadline, |e| {
+                e.state.queue().map_or(true, |x| !x.is_empty())
+            })?
+            .ok_or(FileSystemError::WouldBlock)?;
 
         let queue = inner
             .state
@@ -357,6 +673,8 @@ impl INodeInterface for UnixSocket {
         {
             let mut sock_inner = sock.inner.lock_irq();
             sock_inner.state = UnixSocketState::Connected(peer.clone());
+            sock_inner.cred.clone_from(&inner.cred);
+            sock_inner.peer_cred.clone_from(&peer.inner.lock_irq().cred);
         }
 
         {
@@ -384,34 +702,77 @@ impl INodeInterface for UnixSocket {
     }
 
     fn recv(&self, header: &mut MessageHeader, flags: MessageFlags) -> fs::Result<usize> {
-        // assert!(flags.is_empty());
-
-        let inner = self.inner.lock_irq();
-
-        let peer = match &inner.state {
-            UnixSocketState::Connected(peer) => peer,
-            _ => return Err(FileSystemError::NotConnected),
-        };
+        let peer = self.peer()?;
 
         if self.buffer.lock_irq().is_empty() && self.is_non_block() {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.wq.block_on(&self.buffer, |e| !e.is_empty())?;
+        let deadline = self.options.lock_irq().recv_deadline();
+        let mut buffer = self
+            .wq
+            .block_on_until(&self.buffer, deadline, |e| !e.is_empty())?
+            .ok_or(FileSystemError::WouldBlock)?;
 
         if let Some(addr) = header.name_mut::<SocketAddrUnix>() {
             *addr = peer.inner.lock_irq().address.as_ref().cloned().unwrap();
         }
 
-        Ok(header
-            .iovecs_mut()
-            .iter_mut()
-            .map(|iovec| buffer.read(iovec.as_slice_mut()))
-            .sum::<usize>())
+        let mut space = header.control_capacity();
+        let cred = if self.options.lock_irq().pass_cred() {
+            let mut data = alloc::vec![0; core::mem::size_of::<ucred>()];
+            write_option(&mut data, buffer.sender().unwrap().to_ucred());
+
+            space = space.saturating_sub(aero_syscall::socket::cmsg_space(data.len()));
+            Some(data)
+        } else {
+            None
+        };
+
+        let fds = buffer.take_rights().deliver(header, space, flags);
+        let mut control = Vec::new();
+
+        if let Some(cred) = cred.as_deref() {
+            control.push((
+                SocketOptionLevel::Socket,
+                ControlMessageType::Credentials,
+                cred,
+            ));
+        }
+
+        if !fds.is_empty() {
+            control.push((
+                SocketOptionLevel::Socket,
+                ControlMessageType::Rights,
+                &fds[..],
+            ));
+        }
+
+        header.write_control(&control);
+
+        let mut read = 0;
+
+        for iovec in header.iovecs_mut() {
+            // Stop at a message boundary if the next message carries file descriptors in
+            // flight, as they can only be delivered along with the start of their message.
+            if buffer.is_empty() || (read != 0 && buffer.at_boundary() && buffer.has_rights()) {
+                break;
+            }
+
+            read += buffer.read(iovec.as_slice_mut());
+        }
+
+        // Wake up the peer if it is waiting for room in the queue.
+        core::mem::drop(buffer);
+        self.wq.notify_all();
+
+        Ok(read)
     }
 
     fn send(&self, header: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
-        // FIXME(andyython): figure out the message header stuff...
+        Credentials::check_header(header)?;
+
+        let rights = ScmRights::from_header(header)?;
         let data = header
             .iovecs()
             .iter()
@@ -419,27 +780,52 @@ impl INodeInterface for UnixSocket {
             .copied()
             .collect::<Vec<_>>();
 
-        self.write_at(0, &data)
+        self.send_to_peer(&data, rights)
     }
 
     fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
-        let buffer = self.buffer.lock_irq();
-        let inner = self.inner.lock_irq();
-
-        if let Some(e) = table {
-            e.insert(&self.wq)
-        }
+        let mut events = PollFlags::empty();
+
+        // The locks of the peer are only taken after ours have been released, as the peer
+        // might be polled concurrently.
+        let peer = {
+            let inner = self.inner.lock_irq();
+
+            match &inner.state {
+                // A socket with a pending connection becomes writable once the peer accepts it.
+                UnixSocketState::Connecting => None,
+                UnixSocketState::Connected(peer) => Some(peer.clone()),
+
+                UnixSocketState::Listening(queue) => {
+                    if !queue.is_empty() {
+                        events.insert(PollFlags::IN);
+                    }
+
+                    events.insert(PollFlags::OUT);
+                    None
+                }
+
+                UnixSocketState::Disconnected => {
+                    events.insert(PollFlags::OUT);
+                    None
+                }
+            }
+        };
 
-        let mut events = PollFlags::OUT;
+        if let Some(table) = table {
+            table.insert(&self.wq);
 
-        if let UnixSocketState::Listening(queue) = &inner.state {
-            if !queue.is_empty() {
-                events.insert(PollFlags::IN);
-                return Ok(events);
+            // The peer wakes up its own wait queue when it makes room in its receive queue.
+            if let Some(peer) = peer.as_ref() {
+                table.insert(&peer.wq);
             }
         }
 
-        if !buffer.is_empty() {
+        if peer.is_some_and(|peer| peer.has_space()) {
+            events.insert(PollFlags::OUT);
+        }
+
+        if !self.buffer.lock_irq().is_empty() {
             events.insert(PollFlags::IN);
         }
 
@@ -476,4 +862,45 @@ impl INodeInterface for UnixSocket {
 
         Ok(super::SocketAddr::Unix(address.clone()))
     }
+
+    fn set_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        match level {
+            SocketOptionLevel::Socket => self.options.lock_irq().set(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_sockopt(
+        &self,
+        level: SocketOptionLevel,
+        name: i32,
+        value: &mut [u8],
+    ) -> Result<usize, SyscallError> {
+        match level {
+            SocketOptionLevel::Socket if name == SocketOption::AcceptConn as i32 => {
+                let listening =
+                    matches!(self.inner.lock_irq().state, UnixSocketState::Listening(_));
+                Ok(write_option(value, listening as i32))
+            }
+
+            SocketOptionLevel::Socket if name == SocketOption::PeerCred as i32 => {
+                let inner = self.inner.lock_irq();
+                let cred = inner
+                    .peer_cred
+                    .as_ref()
+                    .ok_or(SyscallError::ENOTCONN)?
+                    .to_ucred();
+
+                Ok(write_option(value, cred))
+            }
+
+            SocketOptionLevel::Socket => self.options.lock_irq().get(name, value),
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
 }
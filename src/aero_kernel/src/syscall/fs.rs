@@ -16,21 +16,27 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use core::fmt;
+use core::sync::atomic::Ordering;
 
 use aero_syscall::prelude::*;
 use aero_syscall::signal::SigProcMask;
-use aero_syscall::{AtFlags, OpenFlags, Stat, TimeSpec, AT_FDCWD};
+use aero_syscall::{AtFlags, Mode, OpenFlags, Stat, TimeSpec, AT_FDCWD};
 use alloc::sync::{Arc, Weak};
 
+use crate::fs::btrfs::Btrfs;
 use crate::fs::cache::{self, DirCacheImpl};
 use crate::fs::epoll::EPoll;
 use crate::fs::eventfd::EventFd;
 use crate::fs::file_table::{DuplicateHint, FileHandle};
-use crate::fs::inode::{DirEntry, PollTable};
-use crate::fs::pipe::Pipe;
-use crate::fs::{self, LookupMode};
+use crate::fs::inode::{self, DirEntry, PollTable};
+use crate::fs::memfd::MemFd;
+use crate::fs::pipe::{Pipe, PipePage};
+use crate::fs::ramfs::RamFs;
+use crate::fs::{self, block, FileSystem, LookupMode};
+use crate::mem::swap;
 use crate::syscall::SysArg;
-use crate::userland::scheduler;
+use crate::userland::scheduler::{self, hrtimer};
+use crate::userland::workqueue;
 
 use crate::fs::Path;
 
@@ -142,19 +148,35 @@ pub fn open(fd: usize, path: &Path, flags: usize, _mode: usize) -> Result<usize,
 #[syscall]
 pub fn dup(fd: FileDescriptor, flags: usize) -> Result<usize, SyscallError> {
     let task = scheduler::get_scheduler().current_task();
-    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)? & OpenFlags::O_CLOEXEC;
+    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
 
-    task.file_table
-        .duplicate(fd.into(), DuplicateHint::Any, flags)
+    task.file_table.duplicate(
+        fd.into(),
+        DuplicateHint::Any,
+        flags.contains(OpenFlags::O_CLOEXEC),
+    )
 }
 
 #[syscall]
 pub fn dup2(fd: FileDescriptor, new_fd: usize, flags: usize) -> Result<usize, SyscallError> {
     let task = scheduler::get_scheduler().current_task();
-    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)? & OpenFlags::O_CLOEXEC;
+    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let cloexec = flags.contains(OpenFlags::O_CLOEXEC);
+
+    // Duplicating a file descriptor onto itself leaves it as it is, though dup3() (which is
+    // the only one to pass `O_CLOEXEC`) fails instead.
+    if usize::from(fd) == new_fd {
+        fd.handle()?;
+
+        return if cloexec {
+            Err(SyscallError::EINVAL)
+        } else {
+            Ok(new_fd)
+        };
+    }
 
     task.file_table
-        .duplicate(fd.into(), DuplicateHint::Exact(new_fd), flags)
+        .duplicate(fd.into(), DuplicateHint::Exact(new_fd), cloexec)
 }
 
 #[syscall]
@@ -210,16 +232,16 @@ pub fn mkdirat(dfd: usize, path: &Path) -> Result<usize, SyscallError> {
     // relative to the directory referred to by the file descriptor (rather than relative
     // to the current working directory of the calling task, as is done by mkdir() for a
     // relative pathname).
-    let (parent_inode, child) = if path.is_absolute() {
+    let (parent, child) = if path.is_absolute() {
         let (path, child) = path.parent_and_basename();
-        (fs::lookup_path(path)?.inode(), child)
+        (fs::lookup_path(path)?, child)
     } else {
         // If pathname is relative and fd is the special value AT_FDCWD, then
         // pathname is interpreted relative to the current working directory of the
         // calling task.
         if dfd as isize == aero_syscall::AT_FDCWD {
             let cwd = scheduler::get_scheduler().current_task().cwd_dirent();
-            (cwd.inode(), path.as_str())
+            (cwd, path.as_str())
         } else {
             let handle = scheduler::get_scheduler()
                 .current_task()
@@ -227,10 +249,12 @@ pub fn mkdirat(dfd: usize, path: &Path) -> Result<usize, SyscallError> {
                 .get_handle(dfd)
                 .ok_or(SyscallError::EBADFD)?;
 
-            (handle.inode(), path.as_str())
+            (handle.inode.clone(), path.as_str())
         }
     };
 
+    let parent_inode = parent.inode();
+
     if !parent_inode.metadata()?.is_directory() {
         // A component of path is not a directory.
         return Err(SyscallError::ENOTDIR);
@@ -242,6 +266,8 @@ pub fn mkdirat(dfd: usize, path: &Path) -> Result<usize, SyscallError> {
     }
 
     parent_inode.mkdir(child)?;
+    inode::invalidate_negative_dir_entry(&parent, child);
+
     Ok(0x00)
 }
 
@@ -282,8 +308,13 @@ pub fn ioctl(fd: FileDescriptor, command: usize, argument: usize) -> Result<usiz
         // Sets the close-on-exec file descriptor flag. This is equivalent
         // to `fcntl(fd, F_SETFD, FD_CLOEXEC)`
         FIOCLEX => {
-            let flags = handle.flags();
-            handle.set_flags(flags | OpenFlags::O_CLOEXEC);
+            handle.set_cloexec(true);
+            Ok(0)
+        }
+
+        // Clears the close-on-exec file descriptor flag.
+        FIONCLEX => {
+            handle.set_cloexec(false);
             Ok(0)
         }
 
@@ -304,9 +335,17 @@ pub fn seek(fd: FileDescriptor, offset: usize, whence: usize) -> Result<usize, S
     Ok(handle.seek(offset as isize, aero_syscall::SeekWhence::from(whence))?)
 }
 
+/// The flags accepted by pipe2().
+const PIPE_FLAGS_MASK: OpenFlags = OpenFlags::from_bits_truncate(
+    OpenFlags::O_CLOEXEC.bits() | OpenFlags::O_NONBLOCK.bits() | OpenFlags::O_DIRECT.bits(),
+);
+
 #[syscall]
 pub fn pipe(fds: &mut [i32; 2], flags: usize) -> Result<usize, SyscallError> {
-    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let flags = OpenFlags::from_bits(flags)
+        .filter(|flags| PIPE_FLAGS_MASK.contains(*flags))
+        .ok_or(SyscallError::EINVAL)?;
+
     let pipe = Pipe::new();
 
     let entry = DirEntry::from_inode(pipe, String::from("<pipe>"));
@@ -400,21 +439,20 @@ pub fn fcntl(fd: FileDescriptor, command: usize, arg: usize) -> Result<usize, Sy
         aero_syscall::prelude::F_DUPFD => scheduler::current_thread().file_table.duplicate(
             fd.into(),
             DuplicateHint::GreatorOrEqual(arg),
-            handle.flags(),
+            false,
         ),
 
         aero_syscall::prelude::F_DUPFD_CLOEXEC => scheduler::current_thread().file_table.duplicate(
             fd.into(),
             DuplicateHint::GreatorOrEqual(arg),
-            handle.flags() | OpenFlags::O_CLOEXEC,
+            true,
         ),
 
         // Get the value of file descriptor flags.
         aero_syscall::prelude::F_GETFD => {
-            let flags = handle.flags();
             let mut result = FdFlags::empty();
 
-            if flags.contains(OpenFlags::O_CLOEXEC) {
+            if handle.is_cloexec() {
                 result.insert(FdFlags::CLOEXEC);
             }
 
@@ -423,16 +461,9 @@ pub fn fcntl(fd: FileDescriptor, command: usize, arg: usize) -> Result<usize, Sy
 
         // Set the value of file descriptor flags:
         aero_syscall::prelude::F_SETFD => {
-            let mut flags = handle.flags();
             let fd_flags = FdFlags::from_bits_truncate(arg);
 
-            if fd_flags.contains(FdFlags::CLOEXEC) {
-                flags.insert(OpenFlags::O_CLOEXEC);
-            } else {
-                flags.remove(OpenFlags::O_CLOEXEC);
-            }
-
-            handle.set_flags(flags);
+            handle.set_cloexec(fd_flags.contains(FdFlags::CLOEXEC));
             Ok(0)
         }
 
@@ -447,6 +478,23 @@ pub fn fcntl(fd: FileDescriptor, command: usize, arg: usize) -> Result<usize, Sy
             Ok(0)
         }
 
+        // Add the seals in `arg` to the set of seals of the file.
+        aero_syscall::prelude::F_ADD_SEALS => {
+            if !handle.is_writable() {
+                return Err(SyscallError::EPERM);
+            }
+
+            handle.inode().add_seals(arg)?;
+            Ok(0)
+        }
+
+        // Get the set of seals of the file.
+        aero_syscall::prelude::F_GET_SEALS => handle.inode().seals(),
+
+        // Change or get the capacity of the pipe.
+        aero_syscall::prelude::F_SETPIPE_SZ => handle.inode().set_pipe_size(arg),
+        aero_syscall::prelude::F_GETPIPE_SZ => handle.inode().pipe_size(),
+
         aero_syscall::prelude::F_SETLKW | aero_syscall::prelude::F_SETLK => {
             log::warn!("fcntl: F_SETLKW,F_SETLK are a stub!");
             Ok(0)
@@ -525,15 +573,21 @@ pub fn read_link(path: &Path, buffer: &mut [u8]) -> Result<usize, SyscallError>
 /// Returns a file descriptor referring to the new epoll instance.
 #[syscall]
 pub fn epoll_create(flags: usize) -> Result<usize, SyscallError> {
-    let _flags = EPollFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let flags = EPollFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
 
     let epoll_file = EPoll::new();
     let entry = DirEntry::from_inode(epoll_file, String::from("<epoll>"));
 
+    let mut open_flags = OpenFlags::O_RDWR;
+
+    if flags.contains(EPollFlags::CLOEXEC) {
+        open_flags.insert(OpenFlags::O_CLOEXEC);
+    }
+
     Ok(scheduler::get_scheduler()
         .current_task()
         .file_table
-        .open_file(entry, OpenFlags::O_RDWR)?)
+        .open_file(entry, open_flags)?)
 }
 
 /// Used to add, modify, or remove entries in the interest list of the
@@ -613,9 +667,267 @@ pub fn event_fd(_initval: usize, flags: usize) -> Result<usize, SyscallError> {
 
     let current_task = scheduler::get_scheduler().current_task();
 
-    Ok(current_task
-        .file_table
-        .open_file(entry, OpenFlags::O_RDWR)?)
+    let mut open_flags = OpenFlags::O_RDWR;
+
+    if flags.contains(EventFdFlags::CLOEXEC) {
+        open_flags.insert(OpenFlags::O_CLOEXEC);
+    }
+
+    if flags.contains(EventFdFlags::NONBLOCK) {
+        open_flags.insert(OpenFlags::O_NONBLOCK);
+    }
+
+    Ok(current_task.file_table.open_file(entry, open_flags)?)
+}
+
+/// Creates an anonymous file which lives in memory and returns a file descriptor
+/// referring to it.
+#[syscall]
+pub fn memfd_create(name: &str, flags: usize) -> Result<usize, SyscallError> {
+    let flags = MemFdFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    let memfd_file = MemFd::new(flags.contains(MemFdFlags::ALLOW_SEALING));
+    let entry = DirEntry::from_inode(memfd_file, alloc::format!("memfd:{name}"));
+
+    let mut open_flags = OpenFlags::O_RDWR;
+
+    if flags.contains(MemFdFlags::CLOEXEC) {
+        open_flags.insert(OpenFlags::O_CLOEXEC);
+    }
+
+    let current_task = scheduler::get_scheduler().current_task();
+    Ok(current_task.file_table.open_file(entry, open_flags)?)
+}
+
+/// Truncates (or extends) the file referred to by `fd` to `length` bytes.
+#[syscall]
+pub fn ftruncate(fd: FileDescriptor, length: usize) -> Result<usize, SyscallError> {
+    let handle = fd.handle()?;
+
+    if !handle.is_writable() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    handle.inode().truncate(length)?;
+    Ok(0)
+}
+
+/// Writes back the modified data and metadata of the file referred to by `fd` and waits for the
+/// device to report that it reached stable media.
+#[syscall]
+pub fn fsync(fd: FileDescriptor) -> Result<usize, SyscallError> {
+    let handle = fd.handle()?;
+
+    handle.inode().fsync()?;
+    Ok(0)
+}
+
+/// Announces how `len` bytes (or the rest of the file, if `len` is zero) of the file referred to
+/// by `fd` starting at `offset` are going to be accessed, so the data that will be needed can be
+/// read ahead and the data that will not can be dropped from the page cache.
+///
+/// ## Errors
+/// * `EINVAL`: `advice` is invalid or `offset` or `len` is negative.
+/// * `ESPIPE`: `fd` refers to a pipe or a socket.
+#[syscall]
+pub fn fadvise(
+    fd: FileDescriptor,
+    offset: usize,
+    len: usize,
+    advice: usize,
+) -> Result<usize, SyscallError> {
+    let handle = fd.handle()?;
+
+    if (offset as isize) < 0 || (len as isize) < 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let metadata = handle.inode().metadata().ok();
+
+    if metadata.map_or(true, |metadata| metadata.is_socket()) {
+        return Err(SyscallError::ESPIPE);
+    }
+
+    let len = if len == 0 { usize::MAX } else { len };
+
+    match advice {
+        POSIX_FADV_NORMAL | POSIX_FADV_RANDOM | POSIX_FADV_SEQUENTIAL => handle.set_advice(advice),
+
+        POSIX_FADV_WILLNEED => {
+            let inode = handle.inode();
+
+            // Like `readahead(2)`, the data is read in the background.
+            workqueue::schedule_work(move || {
+                let _ = inode.readahead(offset, len);
+            });
+        }
+
+        POSIX_FADV_DONTNEED => handle.inode().drop_cache(offset, len)?,
+        POSIX_FADV_NOREUSE => {}
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
+
+/// Reads the data of a file that is spliced into a pipe, one page at a time.
+struct SpliceSource<'a> {
+    handle: &'a FileHandle,
+    offset: usize,
+    eof: bool,
+}
+
+impl SpliceSource<'_> {
+    /// Returns a page with up to `max` bytes of the data of the file at the current offset and
+    /// advances it. The data in the page cache is spliced as it is, without being copied.
+    fn next_page(&mut self, max: usize) -> fs::Result<Option<PipePage>> {
+        if self.eof {
+            return Ok(None);
+        }
+
+        let inode = self.handle.inode();
+
+        let page = if let Some((page, range)) = inode.cached_page(self.offset)? {
+            let end = core::cmp::min(range.end, range.start + max);
+            PipePage::from_cache(page, range.start, end)
+        } else {
+            let mut page = PipePage::new(max);
+
+            // UNWRAP: The page has just been allocated, so its data is not shared.
+            let count = inode.read_at(self.offset, page.as_slice_mut().unwrap())?;
+
+            // Stop at a short read rather than blocking for more data (e.g. on a socket).
+            self.eof = count < page.len();
+            page.truncate(count);
+            page
+        };
+
+        self.offset += page.len();
+        Ok((!page.is_empty()).then_some(page))
+    }
+}
+
+/// Returns the offset of the file to splice at: the one pointed to by `ptr`, or the file offset
+/// of `handle` if it is NULL.
+fn splice_offset(ptr: usize, handle: &FileHandle) -> Result<usize, SyscallError> {
+    if ptr == 0 {
+        return Ok(handle.offset.load(Ordering::SeqCst));
+    }
+
+    let offset = *crate::utils::validate_ptr(ptr as *const i64)?;
+    usize::try_from(offset).map_err(|_| SyscallError::EINVAL)
+}
+
+/// Stores the offset of the file after splicing where [`splice_offset`] got it from.
+fn update_splice_offset(
+    ptr: usize,
+    handle: &FileHandle,
+    offset: usize,
+) -> Result<(), SyscallError> {
+    if ptr == 0 {
+        handle.offset.store(offset, Ordering::SeqCst);
+    } else {
+        *crate::utils::validate_mut_ptr(ptr as *mut i64)? = offset as i64;
+    }
+
+    Ok(())
+}
+
+/// Moves up to `len` bytes from `fd_in` to `fd_out`, one of which has to refer to a pipe,
+/// without copying them through user space. The pages of a pipe are handed over as they are,
+/// the data of files that is read through the page cache is spliced into a pipe without
+/// being copied, and TCP sockets transmit the pages spliced out of a pipe directly.
+///
+/// `off_in` and `off_out` point to the offset of the file to read from or write to, which is
+/// advanced. If they are NULL, the file offset is used instead.
+///
+/// ## Errors
+/// * `EBADF`: `fd_in` is not open for reading or `fd_out` is not open for writing.
+/// * `EINVAL`: Neither file descriptor refers to a pipe, both refer to the same pipe or `flags` is
+///   invalid.
+/// * `ESPIPE`: An offset was provided for a pipe.
+/// * `EAGAIN`: The pipe is empty or full, and either it is non-blocking or `SPLICE_F_NONBLOCK` was
+///   given.
+#[syscall]
+pub fn splice(
+    fd_in: FileDescriptor,
+    off_in: usize,
+    fd_out: FileDescriptor,
+    off_out: usize,
+    len: usize,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    let flags = SpliceFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    let input = fd_in.handle()?;
+    let output = fd_out.handle()?;
+
+    if !input.is_readable() || !output.is_writable() {
+        return Err(SyscallError::EBADF);
+    }
+
+    let input_pipe = input.inode().downcast_arc::<Pipe>();
+    let output_pipe = output.inode().downcast_arc::<Pipe>();
+
+    // Pipes do not have a file offset.
+    if (input_pipe.is_some() && off_in != 0) || (output_pipe.is_some() && off_out != 0) {
+        return Err(SyscallError::ESPIPE);
+    }
+
+    let nonblock = |handle: &FileHandle| {
+        flags.contains(SpliceFlags::NONBLOCK) || handle.flags().contains(OpenFlags::O_NONBLOCK)
+    };
+
+    match (input_pipe, output_pipe) {
+        (Some(input_pipe), Some(output_pipe)) => {
+            if Arc::ptr_eq(&input_pipe, &output_pipe) {
+                return Err(SyscallError::EINVAL);
+            }
+
+            // The pages are moved to the other pipe as they are.
+            Ok(input_pipe.splice_out(len, nonblock(&input), |page| {
+                let mut next = Some(page.clone());
+
+                output_pipe.splice_in(page.len(), nonblock(&output), |max| {
+                    Ok(next.take().map(|mut page| {
+                        page.truncate(max);
+                        page
+                    }))
+                })
+            })?)
+        }
+
+        (Some(pipe), None) => {
+            let inode = output.inode();
+            let mut offset = splice_offset(off_out, &output)?;
+
+            let spliced = pipe.splice_out(len, nonblock(&input), |page| {
+                let count = inode.splice_page(offset, page.clone())?;
+
+                offset += count;
+                Ok(count)
+            })?;
+
+            update_splice_offset(off_out, &output, offset)?;
+            Ok(spliced)
+        }
+
+        (None, Some(pipe)) => {
+            let mut source = SpliceSource {
+                offset: splice_offset(off_in, &input)?,
+                handle: &input,
+                eof: false,
+            };
+
+            let spliced = pipe.splice_in(len, nonblock(&output), |max| source.next_page(max))?;
+
+            update_splice_offset(off_in, &input, source.offset)?;
+            Ok(spliced)
+        }
+
+        (None, None) => Err(SyscallError::EINVAL),
+    }
 }
 
 /// Creates a new link (also known as a hard link) to an existing
@@ -625,7 +937,8 @@ pub fn link(src_path: &Path, dest_path: &Path) -> Result<usize, SyscallError> {
     let src = fs::lookup_path(src_path)?;
     let (dest_dir, dest_name) = dest_path.parent_and_basename();
 
-    let dest_dir = fs::lookup_path(dest_dir)?.inode();
+    let dest_dir = fs::lookup_path(dest_dir)?;
+    let dest_inode = dest_dir.inode();
 
     // Cannot create a hardlink to a file on a different filesystem.
     //
@@ -634,79 +947,91 @@ pub fn link(src_path: &Path, dest_path: &Path) -> Result<usize, SyscallError> {
     //
     // TODO: Should this be moved to the inode impl?
     if !Weak::ptr_eq(
-        &dest_dir.weak_filesystem().unwrap(),
+        &dest_inode.weak_filesystem().unwrap(),
         &src.inode().weak_filesystem().unwrap(),
     ) {
         return Err(SyscallError::EINVAL);
     }
 
-    dest_dir.link(dest_name, src)?;
+    dest_inode.link(dest_name, src)?;
+    inode::invalidate_negative_dir_entry(&dest_dir, dest_name);
+
     Ok(0)
 }
 
-fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, SyscallError> {
-    let current_task = scheduler::get_scheduler().current_task();
-
-    let mut poll_table = PollTable::default();
+/// Updates the returned events of `fds` with the readiness of the files referred to by
+/// `handles` and returns the number of entries with events to report. Negative file
+/// descriptors are ignored and the ones that are not open are reported with `POLLNVAL`. Errors
+/// and hang-ups are reported whether they were requested or not.
+fn poll_fds(
+    fds: &mut [PollFd],
+    handles: &[Option<Arc<FileHandle>>],
+    mut table: Option<&mut PollTable>,
+) -> Result<usize, SyscallError> {
     let mut n = 0;
-    let mut refds = alloc::vec![];
 
-    // Iterate over all the registered events and check if they are ready.
-    for (i, fd) in fds.iter_mut().enumerate() {
-        fd.revents = PollEventFlags::empty();
+    for (fd, handle) in fds.iter_mut().zip(handles) {
+        fd.revents = match handle {
+            _ if fd.fd < 0 => PollEventFlags::empty(),
+            None => PollEventFlags::NVAL,
 
-        // TODO: If an invalid file descriptor is provided then return EBADFD. Not implemented
-        // currently, since the init process (libc?) tries to POLL on the stdout, stdin and
-        // stdout file descriptors which are currently not present.
-        //
-        // One possible solution is to open the file descriptors when the init process
-        // is a kernel process?
-        let handle = match current_task.file_table.get_handle(fd.fd as usize) {
-            Some(v) => v,
-            None => {
-                return Ok(0);
+            Some(handle) => {
+                let ready: PollEventFlags = handle.inode().poll(table.as_deref_mut())?.into();
+                ready & (fd.events | PollEventFlags::ERR | PollEventFlags::HUP)
             }
         };
 
-        let ready: PollEventFlags = handle.inode().poll(None)?.into();
-
-        if !(ready & fd.events).is_empty() {
-            // The registered event is ready; increment the number of ready events
-            // and update revents mask for this event.
-            fd.revents = ready & fd.events;
+        if !fd.revents.is_empty() {
             n += 1;
-            continue;
         }
-
-        // Not ready; add the event to the poll table.
-        handle.inode().poll(Some(&mut poll_table))?;
-        refds.push((handle, i));
     }
 
-    // If all events are ready, we can return now.
+    Ok(n)
+}
+
+fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+    let handles = fds
+        .iter()
+        .map(|fd| {
+            usize::try_from(fd.fd)
+                .ok()
+                .and_then(|fd| current_task.file_table.get_handle(fd))
+        })
+        .collect::<Vec<_>>();
+
+    // Wait on all of the files while checking whether they are ready, so an event that happens
+    // right after a file has been checked still wakes us up.
+    let mut poll_table = PollTable::default();
+    let n = poll_fds(fds, &handles, Some(&mut poll_table))?;
+
     if n > 0 {
         return Ok(n);
     }
 
     // Start the timer if timeout specified, if not, we can block indefinitely.
-    if let Some(timeout) = timeout {
+    let timer = match timeout.map(super::time::timespec_to_ns).transpose()? {
         // If the timeout is zero, then we have to return without blocking.
-        if timeout.tv_nsec == 0 && timeout.tv_sec == 0 {
-            return Ok(0);
-        }
-    }
+        Some(0) => return Ok(0),
+        Some(timeout) => Some(hrtimer::start_timeout(
+            hrtimer::now().saturating_add(timeout),
+        )),
+        None => None,
+    };
 
-    'search: loop {
+    loop {
         scheduler::get_scheduler().inner.await_io()?;
 
-        for (handle, index) in refds.iter() {
-            let pollfd = &mut fds[*index];
-            let ready: PollEventFlags = handle.inode().poll(None)?.into();
+        // Report all of the file descriptors that became ready, not only the one that woke us.
+        let n = poll_fds(fds, &handles, None)?;
 
-            if !(ready & pollfd.events).is_empty() {
-                pollfd.revents = ready & pollfd.events;
-                break 'search Ok(1);
-            }
+        if n > 0 {
+            return Ok(n);
+        }
+
+        // The timeout expired without any events becoming ready.
+        if timer.as_ref().is_some_and(|timer| !timer.is_pending()) {
+            return Ok(0);
         }
     }
 }
@@ -749,6 +1074,7 @@ pub fn rename(src: &Path, dest: &Path) -> Result<usize, SyscallError> {
     };
 
     dest.inode().rename(src.clone(), name)?;
+    inode::invalidate_negative_dir_entry(&dest, name);
 
     cache::dcache().rehash(src.clone(), || {
         src.set_name(name);
@@ -779,3 +1105,108 @@ pub fn symlink(link_dirfd: usize, target: &Path, linkpath: &Path) -> Result<usiz
 
     Ok(0)
 }
+
+/// Creates a regular file or a character or block device node at `path`, as given by the file
+/// type bits of `mode`. `dev` is the device number of the device the node refers to.
+#[syscall]
+pub fn mknodat(dfd: usize, path: &Path, mode: usize, dev: usize) -> Result<usize, SyscallError> {
+    let at = match dfd as isize {
+        AT_FDCWD if !path.is_absolute() => scheduler::current_thread().cwd_dirent(),
+        _ if !path.is_absolute() => FileDescriptor::from_usize(dfd).handle()?.inode.clone(),
+        _ => fs::root_dir().clone(),
+    };
+
+    let (dir, name) = path.parent_and_basename();
+
+    if ["", ".", ".."].contains(&name) {
+        return Err(SyscallError::EEXIST);
+    }
+
+    let parent = fs::lookup_path_with(at, dir, LookupMode::None, true)?;
+
+    if !parent.inode().metadata()?.is_directory() {
+        return Err(SyscallError::ENOTDIR);
+    }
+
+    let mode = Mode::from_bits_truncate(mode as u32);
+    let typ = mode & Mode::S_IFMT;
+
+    if typ == Mode::S_IFCHR || typ == Mode::S_IFBLK {
+        parent
+            .inode()
+            .mknod(parent.clone(), name, mode, dev as u64)?;
+    } else if typ.is_empty() || typ == Mode::S_IFREG {
+        parent.inode().touch(parent.clone(), name)?;
+    } else {
+        return Err(SyscallError::EINVAL);
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn swapon(path: &Path, flags: usize) -> Result<usize, SyscallError> {
+    let file = fs::lookup_path(path)?;
+
+    let priority = if flags & SWAP_FLAG_PREFER == SWAP_FLAG_PREFER {
+        (flags & SWAP_FLAG_PRIO_MASK) as isize
+    } else {
+        -1
+    };
+
+    swap::activate(file, priority)?;
+    Ok(0)
+}
+
+#[syscall]
+pub fn swapoff(path: &Path) -> Result<usize, SyscallError> {
+    let file = fs::lookup_path(path)?;
+
+    swap::deactivate(&file)?;
+    Ok(0)
+}
+
+/// Mounts a new instance of the filesystem of the provided type at `target`, in the mount
+/// namespace of the calling process. Only the `tmpfs`, `proc`, `tracefs`, `efivarfs` and
+/// (read-only) `btrfs` filesystems can be mounted. `source` is the block device holding the
+/// filesystem and is ignored by the filesystems that are not backed by one.
+#[syscall]
+pub fn mount(fstype: &str, target: &Path, source: &Path) -> Result<usize, SyscallError> {
+    let directory = fs::lookup_path(target)?;
+
+    if !directory.inode().metadata()?.is_directory() {
+        return Err(SyscallError::ENOTDIR);
+    }
+
+    let filesystem: Arc<dyn FileSystem> = match fstype {
+        "tmpfs" => RamFs::new_tmpfs(),
+        "proc" => fs::procfs::create()?,
+        "tracefs" => fs::tracefs::create()?,
+        "efivarfs" => fs::efivarfs::create().map_err(|_| SyscallError::ENODEV)?,
+        "btrfs" => {
+            let device = fs::lookup_path(source)?;
+            let block = block::block_device_by_name(&device.name()).ok_or(SyscallError::ENOTBLK)?;
+
+            if block.is_busy() {
+                return Err(SyscallError::EBUSY);
+            }
+
+            Btrfs::new(block).ok_or(SyscallError::EINVAL)?
+        }
+        _ => return Err(SyscallError::ENODEV),
+    };
+
+    scheduler::current_thread()
+        .mount_ns()
+        .mount(directory, filesystem)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn umount(target: &Path) -> Result<usize, SyscallError> {
+    let directory = fs::lookup_path(target)?;
+
+    scheduler::current_thread().mount_ns().unmount(&directory)?;
+    Ok(0)
+}
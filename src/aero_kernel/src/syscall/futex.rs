@@ -23,7 +23,7 @@ use spin::Once;
 
 use crate::mem::paging::{PhysAddr, Translate, VirtAddr};
 use crate::mem::AddressSpace;
-use crate::userland::scheduler;
+use crate::userland::scheduler::{self, hrtimer};
 use crate::utils::sync::{Mutex, WaitQueue};
 
 pub struct FutexContainer {
@@ -78,11 +78,14 @@ impl FutexContainer {
     /// Tests the that the value at the futex word pointed to by `uaddr` still contains the
     /// `expected` value, and if so, it sleeps waiting for a futex wake operation on the
     /// futex word.
+    ///
+    /// If `timeout` (in nanoseconds) is provided, the wait is aborted with `ETIMEDOUT` once it
+    /// expires.
     fn wait(
         &self,
         uaddr: VirtAddr,
         expected: u32,
-        _timeout: &TimeSpec,
+        timeout: Option<u64>,
     ) -> Result<(), SyscallError> {
         Self::validate_futex_ptr(uaddr)?;
 
@@ -95,15 +98,23 @@ impl FutexContainer {
             let scheduler = scheduler::get_scheduler();
             let current_task = scheduler.current_task();
 
+            let timer = timeout
+                .map(|timeout| hrtimer::start_timeout(hrtimer::now().saturating_add(timeout)));
+
             futex.insert(current_task.clone());
-            scheduler.inner.await_io()?;
+            let result = scheduler.inner.await_io();
             futex.remove(&current_task);
 
             if futex.is_empty() {
                 self.futexes.lock().remove(&key);
             }
 
-            Ok(())
+            result?;
+
+            match timer {
+                Some(timer) if !timer.cancel() => Err(SyscallError::ETIMEDOUT),
+                _ => Ok(()),
+            }
         } else {
             Err(SyscallError::EAGAIN)
         }
@@ -130,9 +141,17 @@ fn get_futex_container() -> &'static FutexContainer {
 }
 
 #[syscall]
-pub fn wait(ptr: usize, expected: usize, timeout: &TimeSpec) -> Result<usize, SyscallError> {
+pub fn wait(ptr: usize, expected: usize, timeout: usize) -> Result<usize, SyscallError> {
     let ptr = VirtAddr::new(ptr as u64);
 
+    // The timeout can be NULL, in which case the wait does not time out.
+    let timeout = if timeout != 0x00 {
+        let timeout = crate::utils::validate_ptr(timeout as *const TimeSpec)?;
+        Some(super::time::timespec_to_ns(timeout)?)
+    } else {
+        None
+    };
+
     let futex_container = get_futex_container();
     futex_container.wait(ptr, expected as u32, timeout)?;
 
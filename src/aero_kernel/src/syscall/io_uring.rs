@@ -0,0 +1,68 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+use aero_syscall::io_uring::IoUringParams;
+use aero_syscall::{OpenFlags, SyscallError};
+use alloc::string::String;
+
+use crate::fs::inode::DirEntry;
+use crate::fs::io_uring::IoUring;
+use crate::userland::scheduler;
+
+/// Creates an I/O ring with at least `entries` submission queue entries and returns a file
+/// descriptor referring to it. The layout of the ring mapping is written to `params`.
+///
+/// ## Notes
+/// * Like on Linux, the file descriptor is close-on-exec.
+#[syscall]
+pub fn io_uring_setup(entries: usize, params: usize) -> Result<usize, SyscallError> {
+    let params = crate::utils::validate_mut_ptr(params as *mut IoUringParams)?;
+    let entries = u32::try_from(entries).map_err(|_| SyscallError::EINVAL)?;
+
+    let ring = IoUring::new(entries, params)?;
+    let entry = DirEntry::from_inode(ring, String::from("<io_uring>"));
+
+    Ok(scheduler::current_thread()
+        .file_table
+        .open_file(entry, OpenFlags::O_RDWR | OpenFlags::O_CLOEXEC)?)
+}
+
+/// Submits up to `to_submit` requests of the I/O ring `fd` and, with `IORING_ENTER_GETEVENTS`,
+/// waits for at least `min_complete` completions. Returns the number of requests submitted.
+///
+/// ## Errors
+/// * `EBADF`: `fd` is not a valid file descriptor.
+/// * `EOPNOTSUPP`: `fd` does not refer to an I/O ring.
+/// * `EBUSY`: As many requests as fit into the completion queue are outstanding, so no request
+///   could be submitted.
+#[syscall]
+pub fn io_uring_enter(
+    fd: usize,
+    to_submit: usize,
+    min_complete: usize,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    let ring = scheduler::current_thread()
+        .file_table
+        .get_handle(fd)
+        .ok_or(SyscallError::EBADF)?
+        .inode()
+        .downcast_arc::<IoUring>()
+        .ok_or(SyscallError::EOPNOTSUPP)?;
+
+    ring.enter(to_submit, min_complete, flags)
+}
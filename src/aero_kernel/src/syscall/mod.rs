@@ -24,14 +24,20 @@ use aero_syscall::prelude::*;
 
 mod fs;
 mod futex;
+mod io_uring;
 pub mod ipc;
+mod module;
 mod net;
+mod perf;
 mod process;
+mod random;
+pub mod shm;
 pub mod time;
 
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 
+use crate::userland::scheduler;
 use crate::utils::StackHelper;
 
 #[derive(Default)]
@@ -179,9 +185,20 @@ pub fn generic_do_syscall(
     f: usize,
     g: usize,
 ) -> usize {
+    // The CPU time consumed during the system call is accounted as system time.
+    //
+    // NOTE: The current task is not kept around, as `exit` does not return.
+    scheduler::current_thread().set_in_syscall(true);
+    crate::tracepoint!(
+        SYS_ENTER,
+        "NR {a} ({b:#x}, {c:#x}, {d:#x}, {e:#x}, {f:#x}, {g:#x})"
+    );
+
     let result = match a {
         SYS_EXIT => process::exit(b),
         SYS_SHUTDOWN => process::shutdown(),
+        SYS_REBOOT => process::reboot(b),
+        SYS_KEXEC_LOAD => process::kexec_load(b, c, d, e),
         SYS_FORK => process::fork(),
         SYS_MMAP => process::mmap(b, c, d, e, f, g),
         SYS_MUNMAP => process::munmap(b, c),
@@ -189,22 +206,48 @@ pub fn generic_do_syscall(
         SYS_EXEC => process::exec(b, c, d, e, f, g),
         SYS_LOG => process::log(b, c),
         SYS_UNAME => process::uname(b),
-        SYS_WAITPID => process::waitpid(b, c, d),
+        SYS_WAITPID => process::waitpid(b, c, d, e),
+        SYS_WAITID => process::waitid(b, c, d, e, f),
         SYS_GETPID => process::getpid(),
         SYS_GETPPID => process::getppid(),
         SYS_GETTID => process::gettid(),
         SYS_GETHOSTNAME => process::gethostname(b, c),
         SYS_SETHOSTNAME => process::sethostname(b, c),
+        SYS_SETDOMAINNAME => process::setdomainname(b, c),
         SYS_INFO => process::info(b),
         SYS_SIGACTION => process::sigaction(b, c, d, e),
         SYS_SIGPROCMASK => process::sigprocmask(b, c, d),
         SYS_CLONE => process::clone(b, c),
+        SYS_CLONE3 => process::clone3(b, c),
         SYS_KILL => process::kill(b, c),
         SYS_BACKTRACE => process::backtrace(),
         SYS_TRACE => process::trace(),
         SYS_SETPGID => process::setpgid(b, c),
         SYS_SETSID => process::setsid(),
         SYS_GETPGID => process::getpgid(b),
+        SYS_GETSID => process::getsid(b),
+        SYS_GETRUSAGE => process::getrusage(b, c),
+        SYS_TIMES => process::times(b),
+        SYS_SIGQUEUE => process::sigqueue(b, c, d),
+        SYS_UNSHARE => process::unshare(b),
+        SYS_SETNS => process::setns(b, c),
+        SYS_SECCOMP => process::seccomp(b, c, d),
+        SYS_SPAWN => process::spawn(b, c, d, e, f, g),
+        SYS_SCHED_SETAFFINITY => process::sched_setaffinity(b, c, d),
+        SYS_SCHED_GETAFFINITY => process::sched_getaffinity(b, c, d),
+        SYS_GETCPU => process::getcpu(b, c),
+        SYS_GETRANDOM => random::getrandom(b, c, d),
+        SYS_PERF_EVENT_OPEN => perf::perf_event_open(b, c, d, e, f),
+        SYS_IO_URING_SETUP => io_uring::io_uring_setup(b, c),
+        SYS_IO_URING_ENTER => io_uring::io_uring_enter(b, c, d, e),
+        SYS_INIT_MODULE => module::init_module(b, c, d, e),
+        SYS_DELETE_MODULE => module::delete_module(b, c),
+        SYS_GETPRIORITY => process::getpriority(b, c),
+        SYS_SETPRIORITY => process::setpriority(b, c, d),
+        SYS_SCHED_SETSCHEDULER => process::sched_setscheduler(b, c, d),
+        SYS_SCHED_GETSCHEDULER => process::sched_getscheduler(b),
+        SYS_SCHED_SETPARAM => process::sched_setparam(b, c),
+        SYS_SCHED_GETPARAM => process::sched_getparam(b, c),
 
         SYS_READ => fs::read(b, c, d),
         SYS_OPEN => fs::open(b, c, d, e, f),
@@ -231,6 +274,16 @@ pub fn generic_do_syscall(
         SYS_POLL => fs::poll(b, c, d, e),
         SYS_RENAME => fs::rename(b, c, d, e),
         SYS_SYMLINK_AT => fs::symlink(b, c, d, e, f),
+        SYS_SWAPON => fs::swapon(b, c, d),
+        SYS_SWAPOFF => fs::swapoff(b, c),
+        SYS_MOUNT => fs::mount(b, c, d, e, f, g),
+        SYS_UMOUNT => fs::umount(b, c),
+        SYS_MKNOD_AT => fs::mknodat(b, c, d, e, f),
+        SYS_FSYNC => fs::fsync(b),
+        SYS_FADVISE64 => fs::fadvise(b, c, d, e),
+        SYS_SPLICE => fs::splice(b, c, d, e, f, g),
+        SYS_MEMFD_CREATE => fs::memfd_create(b, c, d),
+        SYS_FTRUNCATE => fs::ftruncate(b, c),
 
         // epoll calls:
         SYS_EPOLL_CREATE => fs::epoll_create(b),
@@ -241,20 +294,30 @@ pub fn generic_do_syscall(
         SYS_BIND => net::bind(b, c, d),
         SYS_CONNECT => net::connect(b, c, d),
         SYS_LISTEN => net::listen(b, c),
-        SYS_ACCEPT => net::accept(b, c, d),
+        SYS_ACCEPT => net::accept(b, c, d, 0),
+        SYS_ACCEPT4 => net::accept(b, c, d, e),
         SYS_SOCK_RECV => net::sock_recv(b, c, d),
         SYS_SOCK_SEND => net::sock_send(b, c, d),
         SYS_SOCKET_PAIR => net::socket_pair(b, c, d, e),
         SYS_SOCK_SHUTDOWN => net::shutdown(b, c),
         SYS_GETPEERNAME => net::get_peername(b, c, d),
         SYS_GETSOCKNAME => net::get_sockname(b, c, d),
-        SYS_SETSOCKOPT => net::setopt(a, b, c, d, e),
+        SYS_SETSOCKOPT => net::setopt(b, c, d, e, f),
+        SYS_GETSOCKOPT => net::getopt(b, c, d, e, f),
 
         SYS_GETTIME => time::gettime(b, c),
+        SYS_SETTIME => time::settime(b, c),
+        SYS_CLOCK_ADJTIME => time::clock_adjtime(b, c),
         SYS_SLEEP => time::sleep(b),
+        SYS_CLOCK_NANOSLEEP => time::clock_nanosleep(b, c, d, e),
 
         SYS_SETITIMER => time::setitimer(b, c, d),
         SYS_GETITIMER => time::getitimer(b, c),
+        SYS_TIMER_CREATE => time::timer_create(b, c),
+        SYS_TIMER_SETTIME => time::timer_settime(b, c, d, e),
+        SYS_TIMER_GETTIME => time::timer_gettime(b, c),
+        SYS_TIMER_GETOVERRUN => time::timer_getoverrun(b),
+        SYS_TIMER_DELETE => time::timer_delete(b),
 
         SYS_IPC_SEND => ipc::send(b, c, d),
         SYS_IPC_RECV => ipc::recv(b, c, d, e),
@@ -264,6 +327,11 @@ pub fn generic_do_syscall(
         SYS_FUTEX_WAIT => futex::wait(b, c, d),
         SYS_FUTEX_WAKE => futex::wake(b),
 
+        SYS_SHMGET => shm::shmget(b, c, d),
+        SYS_SHMAT => shm::shmat(b, c, d),
+        SYS_SHMDT => shm::shmdt(b),
+        SYS_SHMCTL => shm::shmctl(b, c, d),
+
         // Syscall aliases (this should be handled in aero_syscall)
         SYS_MKDIR => fs::mkdirat(aero_syscall::AT_FDCWD as _, b, c),
 
@@ -275,12 +343,16 @@ pub fn generic_do_syscall(
         }
     };
 
-    aero_syscall::syscall_result_as_usize(result)
+    scheduler::current_thread().set_in_syscall(false);
+
+    let result = aero_syscall::syscall_result_as_usize(result);
+    crate::tracepoint!(SYS_EXIT, "NR {a} = {}", result as isize);
+
+    result
 }
 
 #[syscall]
 pub fn tag_memory(ptr: *const u8, size: usize, tag: &str) -> Result<usize, SyscallError> {
-    use crate::userland::scheduler;
     use alloc::string::ToString;
 
     let addr = ptr as usize;
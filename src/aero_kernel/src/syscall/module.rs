@@ -0,0 +1,53 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+use aero_syscall::SyscallError;
+
+use crate::modules::loader::{self, ModuleError};
+
+impl From<ModuleError> for SyscallError {
+    fn from(error: ModuleError) -> Self {
+        match error {
+            ModuleError::InvalidImage => Self::ENOEXEC,
+            ModuleError::UnknownSymbol | ModuleError::BadRelocation => Self::ENOEXEC,
+            ModuleError::OutOfMemory => Self::ENOMEM,
+            ModuleError::Exists => Self::EEXIST,
+            ModuleError::NotFound => Self::ENOENT,
+            ModuleError::Busy => Self::EBUSY,
+        }
+    }
+}
+
+/// Loads the kernel module `name` from the provided ELF relocatable object (see
+/// [`crate::modules::loader`]).
+///
+/// ## Notes
+/// * Unlike Linux, the name of the module is provided by the caller (usually the name of the file
+///   without the `.ko` extension) instead of being embedded in the module.
+/// * There are no credentials, so any process can load modules.
+#[syscall]
+pub fn init_module(image: &[u8], name: &str) -> Result<usize, SyscallError> {
+    loader::load(name, image)?;
+    Ok(0)
+}
+
+/// Unloads the kernel module `name`. Fails with `EBUSY` if the module has no exit function.
+#[syscall]
+pub fn delete_module(name: &str) -> Result<usize, SyscallError> {
+    loader::unload(name)?;
+    Ok(0)
+}
@@ -29,6 +29,7 @@ use crate::mem::paging::VirtAddr;
 
 use crate::socket::ipv4::Ipv4Socket;
 use crate::socket::netlink::NetLinkSocket;
+use crate::socket::packet::PacketSocket;
 use crate::socket::tcp::TcpSocket;
 use crate::socket::udp::UdpSocket;
 use crate::socket::unix::*;
@@ -68,9 +69,11 @@ pub fn connect(fd: usize, address: usize, length: usize) -> Result<usize> {
     Ok(0)
 }
 
-/// Accept a connection on a socket.
+/// Accept a connection on a socket. `SOCK_NONBLOCK` and `SOCK_CLOEXEC` in `flags` are set on
+/// the file descriptor of the new connection (see accept4()).
 #[syscall]
-pub fn accept(fd: usize, address: usize, length: usize) -> Result<usize> {
+pub fn accept(fd: usize, address: usize, length: usize, flags: usize) -> Result<usize> {
+    let flags = SocketFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
     let file_table = scheduler::get_scheduler().current_task().file_table.clone();
     let socket = file_table.get_handle(fd).ok_or(SyscallError::EINVAL)?;
 
@@ -86,7 +89,7 @@ pub fn accept(fd: usize, address: usize, length: usize) -> Result<usize> {
     let connection_sock = socket.inode().accept(address)?;
     let handle = file_table.open_file(
         DirEntry::from_inode(connection_sock, String::from("<socket>")),
-        OpenFlags::O_RDWR,
+        OpenFlags::O_RDWR | OpenFlags::from(flags),
     )?;
 
     Ok(handle)
@@ -94,8 +97,6 @@ pub fn accept(fd: usize, address: usize, length: usize) -> Result<usize> {
 
 #[syscall]
 pub fn sock_send(fd: usize, header: &mut MessageHeader, flags: usize) -> Result<usize> {
-    dbg!(header.control());
-
     let flags = MessageFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
 
     let current_task = scheduler::get_scheduler().current_task();
@@ -120,27 +121,26 @@ pub fn sock_recv(sockfd: usize, header: &mut MessageHeader, flags: usize) -> Res
     Ok(socket.inode().recv(header, flags)?)
 }
 
+/// Sets the value of a socket option.
 #[syscall]
 pub fn setopt(fd: FileDescriptor, layer: usize, number: usize, buf: &[u8]) -> Result<usize> {
-    let layer = SocketOptionLevel::from_usize(layer).ok_or(SyscallError::EINVAL)?;
-
-    match layer {
-        SocketOptionLevel::Socket => {
-            unimplemented!(
-                "setsockopt(fd={:?}, layer={:?}, number={:?}, buf={:?})",
-                fd,
-                layer,
-                number,
-                buf
-            )
-        }
-
-        _ => todo!(),
-    }
+    let layer = SocketOptionLevel::from_usize(layer).ok_or(SyscallError::ENOPROTOOPT)?;
 
+    fd.handle()?
+        .inode()
+        .set_sockopt(layer, number as i32, buf)?;
     Ok(0)
 }
 
+/// Gets the value of a socket option. Returns the size of the option value written into
+/// the provided buffer.
+#[syscall]
+pub fn getopt(fd: FileDescriptor, layer: usize, number: usize, buf: &mut [u8]) -> Result<usize> {
+    let layer = SocketOptionLevel::from_usize(layer).ok_or(SyscallError::ENOPROTOOPT)?;
+
+    fd.handle()?.inode().get_sockopt(layer, number as i32, buf)
+}
+
 /// Marks the socket as a passive socket (i.e. as a socket that will be used to accept incoming
 /// connection requests).
 #[syscall]
@@ -151,6 +151,35 @@ pub fn listen(fd: FileDescriptor, backlog: usize) -> Result<usize> {
 
 fn create_socket(domain: usize, socket_type: usize, protocol: usize) -> Result<DirCacheItem> {
     let typ = SocketType::from_usize(socket_type & 0b1111).ok_or(SyscallError::EINVAL)?;
+
+    // Packet sockets take an ethertype as the protocol instead.
+    if domain as u32 == AF_PACKET {
+        if !matches!(typ, SocketType::Raw | SocketType::Dgram) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let socket = PacketSocket::new(typ, protocol as u16);
+        return Ok(DirEntry::from_inode(
+            socket,
+            String::from("<packet_socket>"),
+        ));
+    }
+
+    if domain as u32 == AF_NETLINK {
+        if protocol as u32 != netlink::NETLINK_ROUTE {
+            return Err(SyscallError::EPROTONOSUPPORT);
+        }
+
+        if !matches!(typ, SocketType::Raw | SocketType::Dgram) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        return Ok(DirEntry::from_inode(
+            NetLinkSocket::new(),
+            String::from("<netlink_socket>"),
+        ));
+    }
+
     let protocol = IpProtocol::from_usize(protocol).ok_or(SyscallError::EINVAL)?;
 
     let (name, socket) = match domain as u32 {
@@ -177,8 +206,6 @@ fn create_socket(domain: usize, socket_type: usize, protocol: usize) -> Result<D
             }
         },
 
-        AF_NETLINK => ("netlink", NetLinkSocket::new() as Arc<dyn INodeInterface>),
-
         _ => {
             log::warn!(
                 "unsupported socket type: domain={domain}, socket_type={socket_type}, protocol={protocol:?}"
@@ -199,7 +226,8 @@ pub fn socket(domain: usize, socket_type: usize, protocol: usize) -> Result<usiz
 
     let current_task = scheduler::get_scheduler().current_task();
 
-    let sockfd_flags = SocketFlags::from_bits_truncate(socket_type).into();
+    let sockfd_flags =
+        OpenFlags::O_RDWR | OpenFlags::from(SocketFlags::from_bits_truncate(socket_type));
     let fd = dbg!(current_task.file_table.debug_open_file(entry, sockfd_flags))?;
     // if fd == 17 {
     //     scheduler::get_scheduler()
@@ -259,6 +287,8 @@ pub fn get_peername(fd: usize, addr: usize, len: &mut u32) -> Result<usize> {
         }
 
         SocketAddr::Netlink(peer) => unimplemented!("{:?}", peer),
+        // Packet sockets are not connected to a peer.
+        SocketAddr::Packet(_) => return Err(SyscallError::EOPNOTSUPP),
         SocketAddr::Unix(peer) => {
             let size = core::mem::size_of::<SocketAddrUnix>() as u32;
             assert!(*len >= size);
@@ -307,6 +337,16 @@ pub fn get_sockname(fd: usize, addr: usize, len: &mut u32) -> Result<usize> {
             *len = size;
         }
 
+        SocketAddr::Packet(name) => {
+            let size = core::mem::size_of::<SocketAddrPacket>() as u32;
+            assert!(*len >= size);
+
+            let mut target =
+                unsafe { UserRef::<SocketAddrPacket>::new(VirtAddr::new(addr as u64)) };
+            *target = name;
+            *len = size;
+        }
+
         SocketAddr::Unix(name) => {
             let size = core::mem::size_of::<SocketAddrUnix>() as u32;
             assert!(*len >= size);
@@ -332,7 +372,8 @@ pub fn socket_pair(
     fds: &mut [i32; 2],
 ) -> Result<usize> {
     let current_task = scheduler::get_scheduler().current_task();
-    let sockfd_flags = SocketFlags::from_bits_truncate(type_and_flags).into();
+    let sockfd_flags =
+        OpenFlags::O_RDWR | OpenFlags::from(SocketFlags::from_bits_truncate(type_and_flags));
 
     let a = create_socket(domain, type_and_flags, protocol)?;
     let b = create_socket(domain, type_and_flags, protocol)?;
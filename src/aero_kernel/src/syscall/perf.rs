@@ -0,0 +1,98 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+use aero_syscall::perf::*;
+use aero_syscall::{OpenFlags, SyscallError};
+use alloc::string::String;
+
+use crate::fs::inode::DirEntry;
+use crate::perf::{PerfEvent, Target};
+use crate::userland::scheduler;
+
+/// Creates a perf event counting on the task `pid` (the calling thread if zero) and/or the CPU
+/// `cpu` (any CPU if -1) and returns a file descriptor referring to it.
+///
+/// ## Notes
+/// * Event groups (`group_fd`) and inherited events are not supported.
+/// * There are no credentials, so any task can count events of the whole system or of other tasks.
+#[syscall]
+pub fn perf_event_open(
+    attr: usize,
+    pid: usize,
+    cpu: usize,
+    group_fd: usize,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    if flags & !PERF_FLAG_FD_CLOEXEC != 0 || group_fd as isize != -1 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let size = match *crate::utils::validate_ptr((attr + 4) as *const u32)? {
+        0 => PERF_ATTR_SIZE_VER0,
+        size => size,
+    } as usize;
+
+    if size < PERF_ATTR_SIZE_VER0 as usize {
+        return Err(SyscallError::E2BIG);
+    }
+
+    // Newer versions of the structure can be used as long as the fields that are not known to
+    // the kernel are zero.
+    let known = core::mem::size_of::<PerfEventAttr>();
+
+    if size > known {
+        let extra = crate::utils::validate_slice((attr + known) as *const u8, size - known)?;
+
+        if extra.iter().any(|byte| *byte != 0) {
+            return Err(SyscallError::E2BIG);
+        }
+    }
+
+    let attr = *crate::utils::validate_ptr(attr as *const PerfEventAttr)?;
+
+    let cpu = match cpu as isize {
+        -1 => None,
+        cpu if (cpu as usize) < crate::arch::apic::get_cpu_count() => Some(cpu as usize),
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    let current_task = scheduler::current_thread();
+
+    let target = match pid as isize {
+        -1 => Target::Cpu(cpu.ok_or(SyscallError::EINVAL)?),
+        0 => Target::Task(current_task.tid(), cpu),
+        _ => {
+            let task = current_task
+                .pid_ns()
+                .find_task(pid)
+                .ok_or(SyscallError::ESRCH)?;
+
+            Target::Task(task.tid(), cpu)
+        }
+    };
+
+    let event = PerfEvent::new(attr, target)?;
+    let entry = DirEntry::from_inode(event, String::from("<perf_event>"));
+
+    let mut open_flags = OpenFlags::O_RDWR;
+
+    if flags & PERF_FLAG_FD_CLOEXEC != 0 {
+        open_flags.insert(OpenFlags::O_CLOEXEC);
+    }
+
+    Ok(current_task.file_table.open_file(entry, open_flags)?)
+}
@@ -15,26 +15,33 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::signal::{SigAction, SigProcMask};
+use aero_syscall::prelude::{PRIO_PGRP, PRIO_PROCESS, SCHED_FIFO, SCHED_OTHER, SCHED_RR};
+use aero_syscall::seccomp::{
+    SockFilter, SockFprog, SECCOMP_GET_ACTION_AVAIL, SECCOMP_SET_MODE_FILTER,
+    SECCOMP_SET_MODE_STRICT,
+};
+use aero_syscall::signal::{
+    SigAction, SigInfo, SigProcMask, CLD_CONTINUED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, SIGCHLD,
+    SIGCONT, SIGRTMAX, SI_QUEUE, SI_USER,
+};
+use aero_syscall::time::{RUsage, Tms};
 use aero_syscall::*;
-use spin::{Mutex, Once};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
-use crate::acpi::aml;
 use crate::fs;
 use crate::fs::Path;
+use crate::kexec::{self, KexecError};
+use crate::syscall::fs::FileDescriptor;
 
 use crate::mem::paging::VirtAddr;
 use crate::userland::scheduler::{self, ExitStatus};
 use crate::userland::signals::SignalEntry;
+use crate::userland::task::namespaces::Namespace;
 use crate::userland::task::sessions::SESSIONS;
-use crate::userland::task::TaskId;
-use crate::utils::sync::IrqGuard;
-
-static HOSTNAME: Once<Mutex<String>> = Once::new();
-
-fn hostname() -> &'static Mutex<String> {
-    HOSTNAME.call_once(|| Mutex::new(String::from("aero")))
-}
+use crate::userland::task::{ChildState, SchedPolicy, Task, WaitTarget};
+use crate::userland::vm::{self, ElfLoadError};
+use crate::userland::{seccomp, uts};
 
 #[syscall(no_return)]
 pub fn exit(status: usize) -> Result<usize> {
@@ -50,13 +57,20 @@ pub fn exit(status: usize) -> Result<usize> {
         log::trace!("exiting the process (pid={pid}, path={path:?}) with status: {status}");
 
         crate::unwind::unwind_stack_trace();
+
+        // The threads are children of the process leader, so the leader exiting ends the whole
+        // process.
+        if current_task.is_process_leader() {
+            scheduler::get_scheduler().exit_group(ExitStatus::Normal(status as isize));
+        }
+
         scheduler::get_scheduler().exit(ExitStatus::Normal(status as isize));
     }
 }
 
 #[syscall]
 pub fn uname(buffer: &mut Utsname) -> Result<usize> {
-    fn init_array(fixed: &mut [u8; 65], init: &'static str) {
+    fn init_array(fixed: &mut [u8; 65], init: &str) {
         let init_bytes = init.as_bytes();
         let len = init.len();
 
@@ -65,7 +79,8 @@ pub fn uname(buffer: &mut Utsname) -> Result<usize> {
     }
 
     init_array(&mut buffer.sysname, "Aero");
-    init_array(&mut buffer.nodename, "unknown");
+    init_array(&mut buffer.nodename, &uts::hostname());
+    init_array(&mut buffer.domainname, &uts::domainname());
     init_array(&mut buffer.version, env!("CARGO_PKG_VERSION"));
     init_array(
         &mut buffer.release,
@@ -87,7 +102,48 @@ pub fn fork() -> Result<usize> {
     let forked = scheduler.current_task().fork();
 
     scheduler.register_task(forked.clone());
-    Ok(forked.pid().as_usize())
+    Ok(scheduler.current_task().child_pid(&forked))
+}
+
+/// Creates a child process that executes the program at `path`, without duplicating the address
+/// space of the calling process (the fast path for `posix_spawn` and `fork` followed by `exec`).
+/// Returns the PID of the child.
+///
+/// ## Notes
+/// * The file actions and attributes of `posix_spawn` are not supported; the child inherits the
+///   file descriptors (except the close-on-exec ones), working directory and signal mask of the
+///   calling process, as with `fork`.
+#[syscall]
+pub fn spawn(path: &Path, args: usize, argc: usize, envs: usize, envc: usize) -> Result<usize> {
+    let executable = fs::lookup_path(path)?;
+
+    if executable.inode().metadata()?.is_directory() {
+        return Err(SyscallError::EISDIR);
+    }
+
+    // The child only loads the program once it has been created, so make sure that it is an ELF
+    // file up front rather than leaving the child without a program to run.
+    vm::check_elf_header(&executable).map_err(|err| match err {
+        ElfLoadError::IOError(err) => SyscallError::from(err),
+        _ => SyscallError::ENOEXEC,
+    })?;
+
+    let argv = if argc > 0 {
+        Some(super::exec_args_from_slice(args, argc))
+    } else {
+        None
+    };
+    let envv = if envc > 0 {
+        Some(super::exec_args_from_slice(envs, envc))
+    } else {
+        None
+    };
+
+    let scheduler = scheduler::get_scheduler();
+    let spawned = scheduler.current_task().spawn(executable, argv, envv);
+
+    scheduler.register_task(spawned.clone());
+    Ok(scheduler.current_task().child_pid(&spawned))
 }
 
 #[syscall]
@@ -96,24 +152,297 @@ pub fn clone(entry: usize, stack: usize) -> Result<usize> {
     let cloned = scheduler.current_task().clone_process(entry, stack);
 
     scheduler.register_task(cloned.clone());
-    Ok(cloned.pid().as_usize())
+    Ok(scheduler.current_task().child_pid(&cloned))
 }
 
+/// Creates a child task that shares the resources selected by `flags` with the calling task
+/// (see `clone3(2)`). Returns the thread ID of the child.
+///
+/// ## Notes
+/// * The parent is always notified with `SIGCHLD` when a child process exits, so the exit signal
+///   must either be 0 or `SIGCHLD`.
+/// * `CLONE_PIDFD`, `CLONE_*_SETTID`, `CLONE_CHILD_CLEARTID`, `CLONE_INTO_CGROUP` and `set_tid` are
+///   not supported.
 #[syscall]
-pub fn kill(pid: usize, signal: usize) -> Result<usize> {
-    // If pid is positive, then signal is sent to the process with that pid.
-    if pid > 0 {
-        crate::unwind::unwind_stack_trace();
+pub fn clone3(args: usize, size: usize) -> Result<usize> {
+    if size < CLONE_ARGS_SIZE_VER0 {
+        return Err(SyscallError::EINVAL);
+    }
 
-        let task = scheduler::get_scheduler()
-            .find_task(TaskId::new(pid))
-            .ok_or(SyscallError::ESRCH)?;
+    // Newer versions of the structure can be used as long as the fields that are not known to
+    // the kernel are zero.
+    let known = core::mem::size_of::<CloneArgs>();
 
-        task.signal(signal);
-        Ok(0)
+    if size > known {
+        let extra = crate::utils::validate_slice((args + known) as *const u8, size - known)?;
+
+        if extra.iter().any(|byte| *byte != 0) {
+            return Err(SyscallError::E2BIG);
+        }
+    }
+
+    let mut clone_args = CloneArgs::default();
+    let bytes = crate::utils::validate_slice(args as *const u8, size.min(known))?;
+
+    // SAFETY: The structure only contains integers, so any byte pattern is valid.
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            &mut clone_args as *mut CloneArgs as *mut u8,
+            bytes.len(),
+        );
+    }
+
+    let flags = CloneFlags::from_bits(clone_args.flags as usize).ok_or(SyscallError::EINVAL)?;
+
+    // Threads share the signal handlers of the process, which in turn can only be shared by tasks
+    // in the same address space.
+    let invalid = (flags.contains(CloneFlags::CLONE_THREAD)
+        && !flags.contains(CloneFlags::CLONE_SIGHAND))
+        || (flags.contains(CloneFlags::CLONE_SIGHAND) && !flags.contains(CloneFlags::CLONE_VM))
+        || (flags.contains(CloneFlags::CLONE_FS) && flags.contains(CloneFlags::CLONE_NEWNS))
+        || (flags.contains(CloneFlags::CLONE_THREAD)
+            && flags.intersects(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID));
+
+    // Threads do not have an exit signal.
+    let exit_signal = clone_args.exit_signal as usize;
+    let invalid_signal = if flags.contains(CloneFlags::CLONE_THREAD) {
+        exit_signal != 0
     } else {
-        unimplemented!()
+        exit_signal != 0 && exit_signal != SIGCHLD
+    };
+
+    if invalid
+        || invalid_signal
+        || clone_args.pidfd != 0
+        || clone_args.set_tid_size != 0
+        || clone_args.cgroup != 0
+        || (clone_args.stack == 0) != (clone_args.stack_size == 0)
+    {
+        return Err(SyscallError::EINVAL);
     }
+
+    // The stack grows down, from the end of the provided region.
+    let stack = match clone_args.stack {
+        0 => None,
+        base => Some(
+            base.checked_add(clone_args.stack_size)
+                .ok_or(SyscallError::EINVAL)? as usize,
+        ),
+    };
+
+    let tls = flags
+        .contains(CloneFlags::CLONE_SETTLS)
+        .then_some(clone_args.tls as usize);
+
+    let scheduler = scheduler::get_scheduler();
+    let current_task = scheduler.current_task();
+    let cloned = current_task.clone_task(flags, stack, tls)?;
+
+    scheduler.register_task(cloned.clone());
+
+    if flags.contains(CloneFlags::CLONE_THREAD) {
+        Ok(cloned.tid().as_usize())
+    } else {
+        Ok(current_task.child_pid(&cloned))
+    }
+}
+
+/// Moves the calling process into new namespaces (see `unshare(2)`). The calling process itself
+/// stays in its PID namespace; only the children that it creates afterwards are created in the
+/// new one.
+#[syscall]
+pub fn unshare(flags: usize) -> Result<usize> {
+    let flags = CloneFlags::from_bits(flags)
+        .filter(|flags| (CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID).contains(*flags))
+        .ok_or(SyscallError::EINVAL)?;
+    let current_task = scheduler::current_thread();
+
+    if flags.contains(CloneFlags::CLONE_NEWPID) {
+        // The PID namespace of the children can only be changed once.
+        if !Arc::ptr_eq(&current_task.child_pid_ns(), current_task.pid_ns()) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let ns = current_task
+            .pid_ns()
+            .create_child()
+            .ok_or(SyscallError::ENOSPC)?;
+
+        current_task.set_child_pid_ns(ns);
+    }
+
+    if flags.contains(CloneFlags::CLONE_NEWNS) {
+        current_task.set_mount_ns(current_task.mount_ns().duplicate());
+    }
+
+    Ok(0)
+}
+
+/// Moves the calling process into the namespace referred to by the `/proc/<pid>/ns` file `fd`
+/// (see `setns(2)`). If `nstype` is not 0, it must match the type of the namespace.
+///
+/// ## Notes
+/// * Joining a mount namespace does not change the working directory of the process.
+#[syscall]
+pub fn setns(fd: FileDescriptor, nstype: usize) -> Result<usize> {
+    let nstype = CloneFlags::from_bits(nstype).ok_or(SyscallError::EINVAL)?;
+    let ns = fs::procfs::namespace(&fd.handle()?.inode()).ok_or(SyscallError::EINVAL)?;
+
+    let current_task = scheduler::current_thread();
+
+    match ns {
+        Namespace::Pid(ns) if nstype.is_empty() || nstype == CloneFlags::CLONE_NEWPID => {
+            // The children can only be moved into the PID namespace of the process or into a
+            // descendant of it.
+            if !current_task.pid_ns().contains(&ns) {
+                return Err(SyscallError::EINVAL);
+            }
+
+            current_task.set_child_pid_ns(ns);
+        }
+
+        Namespace::Mount(ns) if nstype.is_empty() || nstype == CloneFlags::CLONE_NEWNS => {
+            current_task.set_mount_ns(ns);
+        }
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
+
+/// Restricts the system calls that the calling process can make (see `seccomp(2)`).
+///
+/// ## Notes
+/// * No flags are supported.
+#[syscall]
+pub fn seccomp(operation: usize, flags: usize, args: usize) -> Result<usize> {
+    if flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let current_task = scheduler::current_thread();
+    let mut mode = current_task.seccomp();
+
+    match operation {
+        SECCOMP_SET_MODE_STRICT if args == 0 => mode.set_strict()?,
+
+        SECCOMP_SET_MODE_FILTER => {
+            let fprog = crate::utils::validate_ptr(args as *const SockFprog)?;
+            let program =
+                crate::utils::validate_slice::<SockFilter>(fprog.filter, fprog.len as usize)?;
+
+            mode.attach(program)?;
+        }
+
+        SECCOMP_GET_ACTION_AVAIL => {
+            let action = crate::utils::validate_ptr(args as *const u32)?;
+
+            return if seccomp::is_action_available(*action) {
+                Ok(0)
+            } else {
+                Err(SyscallError::EOPNOTSUPP)
+            };
+        }
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    current_task.set_seccomp(mode);
+    Ok(0)
+}
+
+#[syscall]
+pub fn kill(pid: usize, signal: usize) -> Result<usize> {
+    if signal > SIGRTMAX {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let current_task = scheduler::current_thread().process_leader();
+
+    let targets = match pid as isize {
+        // If pid is positive, then signal is sent to the process with that pid.
+        pid if pid > 0 => {
+            crate::unwind::unwind_stack_trace();
+            alloc::vec![find_task(pid as usize)?]
+        }
+
+        // If pid is 0, then signal is sent to every process in the process group of the
+        // calling process.
+        0 => SESSIONS
+            .find_group(&current_task)
+            .ok_or(SyscallError::ESRCH)?
+            .tasks(),
+
+        // If pid is -1, then signal is sent to every process that is visible to the calling
+        // process, except for init and the calling process itself.
+        -1 => {
+            let ns = current_task.pid_ns().clone();
+            let mut targets = Vec::new();
+
+            scheduler::get_scheduler().for_each_task(|task| {
+                if task.is_process_leader()
+                    && task.kthread().is_none()
+                    && task.pid() != current_task.pid()
+                    && task.pid_in(&ns).is_some_and(|pid| pid != 1)
+                {
+                    targets.push(task.clone());
+                }
+            });
+
+            targets
+        }
+
+        // Otherwise, signal is sent to every process in the process group -pid.
+        pid => SESSIONS
+            .find_group_by_id(pid.unsigned_abs())
+            .ok_or(SyscallError::ESRCH)?
+            .tasks(),
+    };
+
+    if targets.is_empty() {
+        return Err(SyscallError::ESRCH);
+    }
+
+    // If signal is 0, then no signal is sent, but the existence of the process is still
+    // checked.
+    if signal != 0 {
+        for task in targets {
+            let mut info = SigInfo::new(signal, SI_USER);
+            info.si_pid = sender_pid(&task);
+
+            task.signal_info(info);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Sends `signal` along with `value` to the process with the provided PID (see `sigqueue(3)`).
+/// Unlike `kill`, every occurrence of a real-time signal is queued.
+#[syscall]
+pub fn sigqueue(pid: usize, signal: usize, value: usize) -> Result<usize> {
+    if signal > SIGRTMAX {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = find_task(pid)?;
+
+    if signal == 0 {
+        return Ok(0);
+    }
+
+    if task.signals().is_queue_full() {
+        return Err(SyscallError::EAGAIN);
+    }
+
+    let mut info = SigInfo::new(signal, SI_QUEUE);
+    info.si_pid = sender_pid(&task);
+    info.set_value(value as u64);
+
+    task.signal_info(info);
+    Ok(0)
 }
 
 #[syscall(no_return)]
@@ -152,12 +481,165 @@ pub fn log(msg: &str) -> Result<usize> {
     Ok(0x00)
 }
 
+/// Encodes the state change of a child into a wait status (see `W_EXITCODE` and `W_STOPCODE`
+/// in `mlibc/abis/linux/wait.h`).
+fn wait_status(state: &ChildState) -> u32 {
+    match state {
+        ChildState::Exited(ExitStatus::Normal(code)) => (*code as u32) << 8,
+        ChildState::Exited(ExitStatus::Signal(signal)) => *signal as u32,
+        ChildState::Stopped(signal) => ((*signal as u32) << 8) | 0x7f,
+        ChildState::Continued => 0xffff,
+    }
+}
+
 #[syscall]
-pub fn waitpid(pid: usize, status: &mut u32, flags: usize) -> Result<usize> {
-    let flags = WaitPidFlags::from_bits_truncate(flags);
+pub fn waitpid(
+    pid: usize,
+    status: &mut u32,
+    flags: usize,
+    rusage: usize, // FIXME: Option<&mut RUsage>
+) -> Result<usize> {
+    let flags = WaitPidFlags::from_bits_truncate(flags)
+        & (WaitPidFlags::WNOHANG | WaitPidFlags::WUNTRACED | WaitPidFlags::WCONTINUED);
+
     let current_task = scheduler::get_scheduler().current_task();
 
-    Ok(current_task.waitpid(pid as isize, status, flags)?)
+    let rusage = if rusage != 0x00 {
+        Some(crate::utils::validate_mut_ptr(rusage as *mut RUsage)?)
+    } else {
+        None
+    };
+
+    let target = match pid as isize {
+        -1 => WaitTarget::Any,
+        0 => WaitTarget::Group(current_task.group_id()),
+        pid if pid < 0 => WaitTarget::Group(pid.unsigned_abs()),
+        pid => WaitTarget::Pid(pid as usize),
+    };
+
+    // Terminated children are always reported.
+    let Some(result) = current_task.wait(target, flags | WaitPidFlags::WEXITED)? else {
+        // If `WNOHANG` was specified in flags and there were no children in a waitable
+        // state, then waipid() returns 0 immediately.
+        *status = 0;
+        return Ok(0);
+    };
+
+    *status = wait_status(&result.state);
+
+    if let Some(rusage) = rusage {
+        *rusage = result.usage.rusage();
+    }
+
+    Ok(result.pid)
+}
+
+#[syscall]
+pub fn waitid(
+    idtype: usize,
+    id: usize,
+    info: &mut SigInfo,
+    options: usize,
+    rusage: usize, // FIXME: Option<&mut RUsage>
+) -> Result<usize> {
+    let flags = WaitPidFlags::from_bits(options).ok_or(SyscallError::EINVAL)?;
+
+    if !flags.intersects(WaitPidFlags::WEXITED | WaitPidFlags::WSTOPPED | WaitPidFlags::WCONTINUED)
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let current_task = scheduler::get_scheduler().current_task();
+
+    let rusage = if rusage != 0x00 {
+        Some(crate::utils::validate_mut_ptr(rusage as *mut RUsage)?)
+    } else {
+        None
+    };
+
+    let target = match idtype {
+        P_ALL => WaitTarget::Any,
+        P_PID => WaitTarget::Pid(id),
+        // If `id` is 0, the process group of the calling process is used.
+        P_PGID if id == 0 => WaitTarget::Group(current_task.group_id()),
+        P_PGID => WaitTarget::Group(id),
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    *info = SigInfo::default();
+
+    // If `WNOHANG` was specified in flags and there were no children in a waitable state, then
+    // the returned `si_pid` is 0.
+    let Some(result) = current_task.wait(target, flags)? else {
+        return Ok(0);
+    };
+
+    let (code, status) = match result.state {
+        ChildState::Exited(ExitStatus::Normal(code)) => (CLD_EXITED, code as i32),
+        ChildState::Exited(ExitStatus::Signal(signal)) => (CLD_KILLED, signal as i32),
+        ChildState::Stopped(signal) => (CLD_STOPPED, signal as i32),
+        ChildState::Continued => (CLD_CONTINUED, SIGCONT as i32),
+    };
+
+    info.si_signo = SIGCHLD as i32;
+    info.si_code = code;
+    info.si_pid = result.pid as i32;
+    info.si_status = status;
+    info.si_utime = clock_ticks(result.usage.user);
+    info.si_stime = clock_ticks(result.usage.system);
+
+    if let Some(rusage) = rusage {
+        *rusage = result.usage.rusage();
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn getrusage(who: usize, usage: &mut RUsage) -> Result<usize> {
+    let current_task = scheduler::get_scheduler().current_task();
+    current_task.account_cpu_time();
+
+    let value = match who as isize {
+        RUSAGE_SELF => {
+            current_task.sample_rss();
+            current_task.usage().get()
+        }
+
+        // NOTE: The memory usage is only accounted per process, so only the CPU time of the
+        // calling thread is reported.
+        RUSAGE_THREAD => current_task.cpu_time().get(),
+        RUSAGE_CHILDREN => current_task.usage().children(),
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    *usage = value.rusage();
+    Ok(0)
+}
+
+/// Converts the provided CPU time (in nanoseconds) to clock ticks (100 per second, see
+/// `sysconf(_SC_CLK_TCK)`).
+fn clock_ticks(ns: u64) -> i64 {
+    (ns / 10000000) as i64
+}
+
+#[syscall]
+pub fn times(tms: &mut Tms) -> Result<usize> {
+    let current_task = scheduler::get_scheduler().current_task();
+    current_task.account_cpu_time();
+
+    let usage = current_task.usage().get();
+    let children = current_task.usage().children();
+
+    *tms = Tms {
+        tms_utime: clock_ticks(usage.user),
+        tms_stime: clock_ticks(usage.system),
+        tms_cutime: clock_ticks(children.user),
+        tms_cstime: clock_ticks(children.system),
+    };
+
+    // The elapsed real time since boot, in clock ticks.
+    Ok(clock_ticks(crate::arch::time::get_uptime_ns()) as usize)
 }
 
 #[syscall]
@@ -240,28 +722,41 @@ pub fn trace() -> Result<usize> {
 
 #[syscall]
 pub fn getpid() -> Result<usize> {
-    Ok(scheduler::get_scheduler().current_task().pid().as_usize())
+    Ok(scheduler::get_scheduler().current_task().vpid())
 }
 
 #[syscall]
 pub fn getppid() -> Result<usize> {
-    Ok(scheduler::get_scheduler()
-        .current_task()
-        .parent_pid()
-        .as_usize())
+    let current_task = scheduler::get_scheduler().current_task();
+
+    // The threads of a process are children of its main thread.
+    match current_task.process_leader().get_parent() {
+        // The parent is not visible if it is outside of the PID namespace of the process.
+        Some(parent) => Ok(parent.pid_in(current_task.pid_ns()).unwrap_or(0)),
+        // On top of the family tree.
+        None => Ok(current_task.vpid()),
+    }
 }
 
 #[syscall]
 pub fn gettid() -> Result<usize> {
-    Ok(scheduler::get_scheduler().current_task().tid().as_usize())
+    let current_task = scheduler::get_scheduler().current_task();
+
+    // NOTE: The thread ID of the main thread is the same as the process ID.
+    if current_task.is_process_leader() {
+        Ok(current_task.vpid())
+    } else {
+        Ok(current_task.tid().as_usize())
+    }
 }
 
 #[syscall]
 pub fn gethostname(buffer: &mut [u8]) -> Result<usize> {
-    let hostname = hostname().lock();
+    let hostname = uts::hostname();
     let bytes = hostname.as_bytes();
 
-    if bytes.len() > buffer.len() {
+    // Leave room for the null terminator.
+    if bytes.len() >= buffer.len() {
         Err(SyscallError::ENAMETOOLONG)
     } else {
         buffer[0..bytes.len()].copy_from_slice(bytes);
@@ -278,16 +773,18 @@ pub fn info(struc: &mut SysInfo) -> Result<usize> {
     Ok(0x00)
 }
 
+/// Sets the host name, which is at most [`HOST_NAME_MAX`] bytes long.
 #[syscall]
 pub fn sethostname(name: &[u8]) -> Result<usize> {
-    match core::str::from_utf8(name) {
-        Ok(name) => {
-            *hostname().lock() = name.into();
+    uts::set_hostname(name)?;
+    Ok(0)
+}
 
-            Ok(0)
-        }
-        Err(_) => Err(SyscallError::EINVAL),
-    }
+/// Sets the NIS domain name, which is at most [`HOST_NAME_MAX`] bytes long.
+#[syscall]
+pub fn setdomainname(name: &[u8]) -> Result<usize> {
+    uts::set_domainname(name)?;
+    Ok(0)
 }
 
 #[syscall]
@@ -343,57 +840,136 @@ pub fn sigaction(
     let task = scheduler.current_task();
     let signals = task.signals();
 
-    signals.set_signal(sig, entry, old);
+    signals.set_signal(sig, entry, old)?;
 
     Ok(0)
 }
 
-#[syscall(no_return)]
-pub fn shutdown() -> Result<usize> {
+/// Writes back the caches before the machine is powered off or rebooted.
+fn prepare_power_off() {
     fs::cache::dcache().log();
 
     fs::cache::clear_inode_cache();
     fs::cache::clear_dir_cache();
+}
+
+#[syscall(no_return)]
+pub fn shutdown() -> Result<usize> {
+    prepare_power_off();
+    crate::arch::power::poweroff()
+}
+
+#[syscall]
+pub fn reboot(cmd: usize) -> Result<usize> {
+    match cmd {
+        RB_AUTOBOOT => {
+            prepare_power_off();
+            crate::arch::power::reboot()
+        }
+
+        RB_POWER_OFF => {
+            prepare_power_off();
+            crate::arch::power::poweroff()
+        }
 
-    let _guard = IrqGuard::new();
-    aml::get_subsystem().enter_state(aml::SleepState::S5);
+        RB_HALT_SYSTEM => {
+            prepare_power_off();
+            crate::arch::power::halt()
+        }
+
+        RB_KEXEC => {
+            if !kexec::is_loaded() {
+                return Err(SyscallError::EINVAL);
+            }
+
+            prepare_power_off();
+            kexec::execute();
+
+            // The image was unloaded concurrently.
+            Err(SyscallError::EINVAL)
+        }
+
+        _ => Err(SyscallError::EINVAL),
+    }
+}
 
-    unreachable!("aml: failed to shutdown (enter state S5)")
+impl From<KexecError> for SyscallError {
+    fn from(error: KexecError) -> Self {
+        match error {
+            KexecError::InvalidSegment => Self::EINVAL,
+            KexecError::NotRam => Self::EADDRNOTAVAIL,
+            KexecError::OutOfMemory => Self::ENOMEM,
+        }
+    }
 }
 
+/// Loads a new kernel to be executed with `reboot(RB_KEXEC)` (see [`crate::kexec`]), or the
+/// crash kernel if `KEXEC_ON_CRASH` is set (see [`crate::kexec::crash`]). Providing no segments
+/// unloads the kernel.
 #[syscall]
-pub fn getpgid(pid: usize) -> Result<usize> {
-    let current_task = scheduler::current_thread();
+pub fn kexec_load(
+    entry: usize,
+    nr_segments: usize,
+    segments: usize,
+    flags: usize,
+) -> Result<usize> {
+    // Only kernels for the current architecture (`KEXEC_ARCH_DEFAULT`) can be loaded.
+    if flags & !KEXEC_ON_CRASH != 0 {
+        return Err(SyscallError::EINVAL);
+    }
 
-    // If `pid` is 0, the process ID of the calling process is used.
-    let task = if pid == 0 || pid == current_task.pid().as_usize() {
-        current_task
-    } else {
-        scheduler::get_scheduler()
-            .find_task(TaskId::new(pid))
-            .ok_or(SyscallError::ESRCH)?
-    };
+    if nr_segments > KEXEC_SEGMENT_MAX {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let segments = crate::utils::validate_slice(segments as *const KexecSegment, nr_segments)?;
+    let mut loaded = Vec::with_capacity(segments.len());
+
+    for segment in segments {
+        if segment.bufsz > segment.memsz {
+            return Err(SyscallError::EINVAL);
+        }
+
+        loaded.push(kexec::Segment {
+            data: crate::utils::validate_slice(segment.buf as *const u8, segment.bufsz)?,
+            mem: segment.mem as u64,
+            memsz: segment.memsz as u64,
+        });
+    }
+
+    kexec::load(entry as u64, &loaded, flags & KEXEC_ON_CRASH != 0)?;
+    Ok(0)
+}
+
+#[syscall]
+pub fn getpgid(pid: usize) -> Result<usize> {
+    Ok(find_task_or_current(pid)?.group_id())
+}
 
-    let group = SESSIONS.find_group(&task).unwrap();
-    Ok(group.id())
+#[syscall]
+pub fn getsid(pid: usize) -> Result<usize> {
+    Ok(find_task_or_current(pid)?.session_id())
 }
 
 #[syscall]
 pub fn setpgid(pid: usize, pgid: usize) -> Result<usize> {
     let current_task = scheduler::current_thread();
-    let task = if pid == 0 || pid == current_task.pid().as_usize() {
+    let task = if pid == 0 || pid == current_task.vpid() {
         current_task.clone()
     } else {
-        let task = scheduler::get_scheduler()
-            .find_task(TaskId::new(pid))
-            .ok_or(SyscallError::ESRCH)?;
+        let task = find_task(pid)?;
 
         if let Some(parent) = task.get_parent() {
             if parent.tid() != current_task.tid() {
-                return Err(SyscallError::EPERM);
+                return Err(SyscallError::ESRCH);
             }
         } else {
-            return Err(SyscallError::EPERM);
+            return Err(SyscallError::ESRCH);
+        }
+
+        // The process group of a child cannot be changed after it has called exec.
+        if task.did_exec() {
+            return Err(SyscallError::EACCES);
         }
 
         task
@@ -406,17 +982,244 @@ pub fn setpgid(pid: usize, pgid: usize) -> Result<usize> {
         return Err(SyscallError::EPERM);
     }
 
-    log::error!("setpgid: is a stub! (pid={pid} pgid={pgid})");
+    // If `pgid` is 0, the process ID of the target process is used.
+    let pgid = if pgid == 0 {
+        task.pid().as_usize()
+    } else {
+        pgid
+    };
+
+    // The process can only join an existing process group in the same session or create a new
+    // process group, of which it becomes the leader.
+    if !SESSIONS.set_group(&task, pgid) {
+        return Err(SyscallError::EPERM);
+    }
+
     Ok(0)
 }
 
 #[syscall]
 pub fn setsid() -> Result<usize> {
     let current_task = scheduler::get_scheduler().current_task();
-    if current_task.is_group_leader() {
+    let pid = current_task.pid().as_usize();
+
+    // The calling process cannot create a new session if it is a process group leader, or if any
+    // other process group has the same ID as the process (as its ID would not be unique).
+    if current_task.is_group_leader() || SESSIONS.group_exists(pid) {
         return Err(SyscallError::EPERM);
     }
 
     SESSIONS.isolate(&current_task);
+
+    // The new session does not have a controlling terminal.
+    current_task.clear_controlling_terminal();
+    Ok(pid)
+}
+
+/// Returns the mask of the CPUs that are online.
+fn online_cpus() -> u64 {
+    let count = crate::arch::apic::get_cpu_count();
+
+    if count >= u64::BITS as usize {
+        u64::MAX
+    } else {
+        (1 << count) - 1
+    }
+}
+
+fn find_task_or_current(pid: usize) -> Result<Arc<Task>> {
+    let current_task = scheduler::current_thread();
+
+    // If `pid` is 0, the calling thread is used.
+    if pid == 0 || pid == current_task.vpid() {
+        Ok(current_task)
+    } else {
+        find_task(pid)
+    }
+}
+
+/// Returns the process with the provided PID in the PID namespace of the calling process.
+fn find_task(pid: usize) -> Result<Arc<Task>> {
+    scheduler::current_thread()
+        .pid_ns()
+        .find_task(pid)
+        .ok_or(SyscallError::ESRCH)
+}
+
+/// Returns the PID of the calling process as seen by `target`, which is reported to it as the
+/// sender of a signal; 0 if the calling process is not visible to it.
+fn sender_pid(target: &Task) -> i32 {
+    scheduler::current_thread()
+        .pid_in(target.pid_ns())
+        .unwrap_or(0) as i32
+}
+
+#[syscall]
+pub fn sched_setaffinity(pid: usize, mask: &[u8]) -> Result<usize> {
+    let mut affinity = [0; 8];
+    let size = core::cmp::min(mask.len(), affinity.len());
+
+    affinity[..size].copy_from_slice(&mask[..size]);
+
+    let affinity = u64::from_ne_bytes(affinity) & online_cpus();
+
+    if affinity == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = find_task_or_current(pid)?;
+
+    // Threads are always scheduled on the same CPU, so the affinity of the whole thread
+    // group is updated.
+    scheduler::get_scheduler().for_each_task(|e| {
+        if Arc::ptr_eq(&e.vm, &task.vm) {
+            e.set_affinity(affinity);
+        }
+    });
+
+    // If the calling thread is not allowed to run on this CPU anymore, reschedule so that the
+    // scheduler moves it to an allowed CPU.
+    let current_task = scheduler::current_thread();
+
+    if Arc::ptr_eq(&current_task.vm, &task.vm)
+        && !current_task.can_run_on(crate::arch::tls::get_cpuid())
+    {
+        scheduler::get_scheduler().inner.preempt();
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn sched_getaffinity(pid: usize, mask: &mut [u8]) -> Result<usize> {
+    let affinity = find_task_or_current(pid)?.affinity() & online_cpus();
+    let bytes = affinity.to_ne_bytes();
+
+    if mask.len() < bytes.len() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    mask[..bytes.len()].copy_from_slice(&bytes);
+    Ok(bytes.len())
+}
+
+#[syscall]
+pub fn getcpu(cpu: usize, node: usize) -> Result<usize> {
+    if cpu != 0x00 {
+        *crate::utils::validate_mut_ptr(cpu as *mut u32)? = crate::arch::tls::get_cpuid() as u32;
+    }
+
+    // There is only a single NUMA node.
+    if node != 0x00 {
+        *crate::utils::validate_mut_ptr(node as *mut u32)? = 0;
+    }
+
+    Ok(0)
+}
+
+/// Returns the tasks selected by the `which` and `who` arguments of `getpriority` and
+/// `setpriority`.
+fn priority_targets(which: usize, who: usize) -> Result<Vec<Arc<Task>>> {
+    match which {
+        PRIO_PROCESS => Ok(alloc::vec![find_task_or_current(who)?]),
+
+        PRIO_PGRP => {
+            // If `who` is 0, the process group of the calling process is used.
+            let group_id = if who == 0 {
+                scheduler::current_thread().group_id()
+            } else {
+                who
+            };
+
+            let mut tasks = Vec::new();
+
+            scheduler::get_scheduler().for_each_task(|e| {
+                if e.group_id() == group_id {
+                    tasks.push(e.clone());
+                }
+            });
+
+            if tasks.is_empty() {
+                return Err(SyscallError::ESRCH);
+            }
+
+            Ok(tasks)
+        }
+
+        // `PRIO_USER` is not supported as there are no users.
+        _ => Err(SyscallError::EINVAL),
+    }
+}
+
+#[syscall]
+pub fn getpriority(which: usize, who: usize) -> Result<usize> {
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|task| task.nice())
+        .min()
+        .unwrap();
+
+    // Negative return values are reserved for errors, so the nice value is returned as
+    // `20 - nice` (ranging from 1 to 40). Userland converts it back.
+    Ok((20 - nice as isize) as usize)
+}
+
+#[syscall]
+pub fn setpriority(which: usize, who: usize, prio: usize) -> Result<usize> {
+    for task in priority_targets(which, who)? {
+        task.set_nice(prio as isize);
+    }
+
+    Ok(0)
+}
+
+/// Converts the provided `SCHED_*` policy and its parameters into a [`SchedPolicy`].
+fn sched_policy(policy: usize, param: &SchedParam) -> Result<SchedPolicy> {
+    let priority = param.sched_priority;
+
+    match policy {
+        SCHED_OTHER if priority == 0 => Ok(SchedPolicy::Normal),
+        SCHED_FIFO if (1..=99).contains(&priority) => Ok(SchedPolicy::Fifo(priority as u8)),
+        SCHED_RR if (1..=99).contains(&priority) => Ok(SchedPolicy::RoundRobin(priority as u8)),
+        _ => Err(SyscallError::EINVAL),
+    }
+}
+
+/// Returns the `SCHED_*` constant of the provided policy.
+fn sched_policy_id(policy: SchedPolicy) -> usize {
+    match policy {
+        SchedPolicy::Normal => SCHED_OTHER,
+        SchedPolicy::Fifo(_) => SCHED_FIFO,
+        SchedPolicy::RoundRobin(_) => SCHED_RR,
+    }
+}
+
+#[syscall]
+pub fn sched_setscheduler(pid: usize, policy: usize, param: &SchedParam) -> Result<usize> {
+    let policy = sched_policy(policy, param)?;
+
+    find_task_or_current(pid)?.set_sched_policy(policy);
+    Ok(0)
+}
+
+#[syscall]
+pub fn sched_getscheduler(pid: usize) -> Result<usize> {
+    Ok(sched_policy_id(find_task_or_current(pid)?.sched_policy()))
+}
+
+#[syscall]
+pub fn sched_setparam(pid: usize, param: &SchedParam) -> Result<usize> {
+    let task = find_task_or_current(pid)?;
+    let policy = sched_policy_id(task.sched_policy());
+
+    task.set_sched_policy(sched_policy(policy, param)?);
+    Ok(0)
+}
+
+#[syscall]
+pub fn sched_getparam(pid: usize, param: &mut SchedParam) -> Result<usize> {
+    let task = find_task_or_current(pid)?;
+
+    param.sched_priority = task.sched_policy().rt_priority().unwrap_or(0) as i32;
     Ok(0)
 }
@@ -0,0 +1,40 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+use aero_syscall::{GetRandomFlags, SyscallError};
+
+#[syscall]
+pub fn getrandom(buffer: &mut [u8], flags: usize) -> Result<usize, SyscallError> {
+    let flags = GetRandomFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    if flags.contains(GetRandomFlags::RANDOM | GetRandomFlags::INSECURE) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    // `GRND_RANDOM` is only kept for compatibility; there is no separate blocking pool and
+    // both sources block until the CRNG is initialized.
+    if !flags.contains(GetRandomFlags::INSECURE) && !crate::random::is_ready() {
+        if flags.contains(GetRandomFlags::NONBLOCK) {
+            return Err(SyscallError::EAGAIN);
+        }
+
+        crate::random::wait_for_ready()?;
+    }
+
+    crate::random::fill_bytes(buffer);
+    Ok(buffer.len())
+}
@@ -0,0 +1,300 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! System V shared memory segments (`shmget`, `shmat`, `shmdt` and `shmctl`).
+//!
+//! Each segment is backed by a [`MemFd`] which is mapped into the processes that attach
+//! the segment as a shared file mapping.
+
+use aero_syscall::prelude::*;
+use aero_syscall::{MMapFlags, MMapProt, OpenFlags, ShmIdDs};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::arch::time;
+use crate::arch::user_copy::UserRef;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{DirEntry, INodeInterface};
+use crate::fs::memfd::MemFd;
+use crate::mem::paging::VirtAddr;
+use crate::userland::scheduler;
+use crate::utils::sync::Mutex;
+
+struct Segment {
+    file: DirCacheItem,
+    info: Mutex<ShmIdDs>,
+    /// The processes that have this segment attached, along with the attach address.
+    attachments: Mutex<Vec<(usize, VirtAddr)>>,
+    /// Set by `IPC_RMID`; the segment is destroyed after the last process detaches it.
+    removed: bool,
+}
+
+impl Segment {
+    fn key(&self) -> usize {
+        self.info.lock().shm_perm.key as usize
+    }
+
+    fn size(&self) -> usize {
+        self.info.lock().shm_segsz
+    }
+}
+
+struct Segments {
+    segments: BTreeMap<usize, Segment>,
+    next_id: usize,
+}
+
+static SEGMENTS: Mutex<Segments> = Mutex::new(Segments {
+    segments: BTreeMap::new(),
+    next_id: 0,
+});
+
+fn current_time() -> i64 {
+    time::get_realtime_clock().tv_sec as i64
+}
+
+/// Duplicates the attachments of the process `parent` for its forked child `child`, which
+/// inherits the mappings of the segments.
+pub fn fork(parent: usize, child: usize) {
+    let segments = SEGMENTS.lock();
+
+    for segment in segments.segments.values() {
+        let mut attachments = segment.attachments.lock();
+        let inherited = attachments
+            .iter()
+            .filter(|(pid, _)| *pid == parent)
+            .map(|&(_, address)| (child, address))
+            .collect::<Vec<_>>();
+
+        if inherited.is_empty() {
+            continue;
+        }
+
+        attachments.extend(inherited);
+        segment.info.lock().shm_nattch = attachments.len() as u64;
+    }
+}
+
+/// Detaches all of the segments attached by the process `pid`, whose address space is being
+/// torn down on exit or exec. The mappings themselves are released along with the address space.
+pub fn detach_all(pid: usize) {
+    let mut segments = SEGMENTS.lock();
+    let now = current_time();
+
+    segments.segments.retain(|_, segment| {
+        let mut attachments = segment.attachments.lock();
+        let count = attachments.len();
+
+        attachments.retain(|(owner, _)| *owner != pid);
+
+        if attachments.len() != count {
+            let mut info = segment.info.lock();
+
+            info.shm_dtime = now;
+            info.shm_lpid = pid as i32;
+            info.shm_nattch = attachments.len() as u64;
+        }
+
+        !(segment.removed && attachments.is_empty())
+    });
+}
+
+#[syscall]
+pub fn shmget(key: usize, size: usize, flags: usize) -> Result<usize, SyscallError> {
+    let mut segments = SEGMENTS.lock();
+
+    if key != IPC_PRIVATE {
+        let existing = segments
+            .segments
+            .iter()
+            .find(|(_, segment)| !segment.removed && segment.key() == key);
+
+        if let Some((id, segment)) = existing {
+            if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                return Err(SyscallError::EEXIST);
+            }
+
+            if size > segment.size() {
+                return Err(SyscallError::EINVAL);
+            }
+
+            return Ok(*id);
+        }
+
+        if flags & IPC_CREAT == 0 {
+            return Err(SyscallError::ENOENT);
+        }
+    }
+
+    if size == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let memfd = MemFd::new(false);
+    memfd.truncate(size)?;
+
+    let id = segments.next_id;
+    segments.next_id += 1;
+
+    let mut info = ShmIdDs::default();
+
+    info.shm_perm.key = key as i32;
+    info.shm_perm.mode = (flags & 0o777) as u32;
+    info.shm_perm.seq = id as i32;
+    info.shm_segsz = size;
+    info.shm_ctime = current_time();
+    info.shm_cpid = scheduler::current_thread().pid().as_usize() as i32;
+
+    segments.segments.insert(
+        id,
+        Segment {
+            file: DirEntry::from_inode(memfd, alloc::format!("SYSV{key:08x}")),
+            info: Mutex::new(info),
+            attachments: Mutex::new(Vec::new()),
+            removed: false,
+        },
+    );
+
+    Ok(id)
+}
+
+#[syscall]
+pub fn shmat(id: usize, address: usize, flags: usize) -> Result<usize, SyscallError> {
+    let segments = SEGMENTS.lock();
+    let segment = segments.segments.get(&id).ok_or(SyscallError::EINVAL)?;
+
+    let mut address = VirtAddr::new(address as u64);
+
+    if !address.is_aligned(SHMLBA as u64) {
+        if flags & SHM_RND == 0 {
+            return Err(SyscallError::EINVAL);
+        }
+
+        address = address.align_down(SHMLBA as u64);
+    }
+
+    let (protection, open_flags) = if flags & SHM_RDONLY != 0 {
+        (MMapProt::PROT_READ, OpenFlags::O_RDONLY)
+    } else {
+        (
+            MMapProt::PROT_READ | MMapProt::PROT_WRITE,
+            OpenFlags::O_RDWR,
+        )
+    };
+
+    let mut mmap_flags = MMapFlags::MAP_SHARED;
+
+    if !address.is_zero() {
+        mmap_flags.insert(MMapFlags::MAP_FIXED);
+    }
+
+    let handle = Arc::new(FileHandle::new(0, segment.file.clone(), open_flags));
+    let task = scheduler::current_thread();
+
+    let address = task
+        .vm()
+        .mmap(
+            address,
+            segment.size(),
+            protection,
+            mmap_flags,
+            0,
+            Some(handle),
+        )
+        .ok_or(SyscallError::ENOMEM)?;
+
+    let pid = task.pid().as_usize();
+    let mut attachments = segment.attachments.lock();
+    attachments.push((pid, address));
+
+    let mut info = segment.info.lock();
+
+    info.shm_atime = current_time();
+    info.shm_lpid = pid as i32;
+    info.shm_nattch = attachments.len() as u64;
+
+    Ok(address.as_u64() as usize)
+}
+
+#[syscall]
+pub fn shmdt(address: usize) -> Result<usize, SyscallError> {
+    let mut segments = SEGMENTS.lock();
+
+    let task = scheduler::current_thread();
+    let attachment = (task.pid().as_usize(), VirtAddr::new(address as u64));
+
+    let (&id, segment) = segments
+        .segments
+        .iter()
+        .find(|(_, segment)| segment.attachments.lock().contains(&attachment))
+        .ok_or(SyscallError::EINVAL)?;
+
+    task.vm().munmap(attachment.1, segment.size());
+
+    let mut attachments = segment.attachments.lock();
+    attachments.retain(|e| *e != attachment);
+
+    {
+        let mut info = segment.info.lock();
+
+        info.shm_dtime = current_time();
+        info.shm_lpid = attachment.0 as i32;
+        info.shm_nattch = attachments.len() as u64;
+    }
+
+    if segment.removed && attachments.is_empty() {
+        core::mem::drop(attachments);
+        segments.segments.remove(&id);
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn shmctl(id: usize, command: usize, buffer: usize) -> Result<usize, SyscallError> {
+    let mut segments = SEGMENTS.lock();
+    let segment = segments.segments.get_mut(&id).ok_or(SyscallError::EINVAL)?;
+
+    match command {
+        IPC_STAT => {
+            let mut target = unsafe { UserRef::<ShmIdDs>::new(VirtAddr::new(buffer as u64)) };
+            *target = *segment.info.lock();
+
+            Ok(0)
+        }
+
+        IPC_RMID => {
+            if segment.attachments.lock().is_empty() {
+                segments.segments.remove(&id);
+            } else {
+                // NOTE: The key is released so a new segment can be created with it, while
+                // the attached processes keep using this segment.
+                segment.removed = true;
+            }
+
+            Ok(0)
+        }
+
+        _ => {
+            log::warn!("shmctl: unknown command {command}");
+            Err(SyscallError::EINVAL)
+        }
+    }
+}
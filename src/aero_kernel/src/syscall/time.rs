@@ -15,31 +15,91 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::time::{ITimerVal, ITIMER_REAL};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::signal::{SigEvent, SIGALRM, SIGEV_NONE, SIGEV_SIGNAL, SIGRTMAX};
+use aero_syscall::time::*;
 use aero_syscall::{SyscallError, TimeSpec};
 use alloc::sync::Arc;
-use alloc::vec::Vec;
 
-use crate::userland::scheduler;
-use crate::userland::task::Task;
-use crate::utils::sync::{IrqGuard, Mutex};
+use crate::userland::scheduler::{self, hrtimer};
+use crate::userland::task::timers::{RealTimer, TimerValue};
+
+/// Converts the provided (user-provided) time specification to nanoseconds.
+pub(super) fn timespec_to_ns(timespec: &TimeSpec) -> Result<u64, SyscallError> {
+    if timespec.tv_sec < 0 || !(0..1000000000).contains(&timespec.tv_nsec) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    Ok((timespec.tv_sec as u64)
+        .saturating_mul(1000000000)
+        .saturating_add(timespec.tv_nsec as u64))
+}
 
-const CLOCK_TYPE_REALTIME: usize = 0;
-const CLOCK_TYPE_MONOTONIC: usize = 1;
+fn ns_to_timespec(ns: u64) -> TimeSpec {
+    TimeSpec {
+        tv_sec: (ns / 1000000000) as isize,
+        tv_nsec: (ns % 1000000000) as isize,
+    }
+}
+
+/// Sleeps until the monotonic clock reaches `expires`. If interrupted by a signal, the remaining
+/// time is written to `remaining` (if provided).
+fn do_sleep(expires: u64, remaining: Option<&mut TimeSpec>) -> Result<usize, SyscallError> {
+    if let Err(err) = hrtimer::sleep_until(expires) {
+        if let Some(remaining) = remaining {
+            *remaining = ns_to_timespec(expires.saturating_sub(hrtimer::now()));
+        }
+
+        return Err(err.into());
+    }
+
+    Ok(0)
+}
 
 #[syscall]
 pub fn sleep(timespec: &TimeSpec) -> Result<usize, SyscallError> {
-    let duration = (timespec.tv_nsec as usize).div_ceil(1000000000) + timespec.tv_sec as usize;
+    let duration = timespec_to_ns(timespec)?;
+    do_sleep(hrtimer::now().saturating_add(duration), None)
+}
 
-    scheduler::get_scheduler().inner.sleep(Some(duration))?;
+#[syscall]
+pub fn clock_nanosleep(
+    clock: usize,
+    flags: usize,
+    request: &TimeSpec,
+    remaining: usize, // FIXME: Option<&mut TimeSpec>
+) -> Result<usize, SyscallError> {
+    let request = timespec_to_ns(request)?;
+    let now = hrtimer::now();
+    let absolute = flags & TIMER_ABSTIME == TIMER_ABSTIME;
+
+    let expires = match (clock, absolute) {
+        (CLOCK_REALTIME | CLOCK_MONOTONIC, false) => now.saturating_add(request),
+        (CLOCK_MONOTONIC, true) => request,
+        (CLOCK_REALTIME, true) => {
+            let realtime = timespec_to_ns(&crate::arch::time::get_realtime_clock())?;
+            now.saturating_add(request.saturating_sub(realtime))
+        }
 
-    Ok(0x00)
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    // The remaining time is not reported for absolute sleeps, as they can simply be restarted
+    // with the same request.
+    let remaining = if remaining != 0 && !absolute {
+        Some(crate::utils::validate_mut_ptr(remaining as *mut TimeSpec)?)
+    } else {
+        None
+    };
+
+    do_sleep(expires, remaining)
 }
 
 #[syscall]
 pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallError> {
     match clock {
-        CLOCK_TYPE_REALTIME => {
+        CLOCK_REALTIME => {
             let clock = crate::arch::time::get_realtime_clock();
 
             timespec.tv_sec = clock.tv_sec;
@@ -48,52 +108,321 @@ pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallEr
             Ok(0x00)
         }
 
-        CLOCK_TYPE_MONOTONIC => {
-            // FIXME: implement
-            let clock = crate::arch::time::get_realtime_clock();
+        CLOCK_MONOTONIC => {
+            *timespec = ns_to_timespec(hrtimer::now());
+            Ok(0x00)
+        }
 
-            timespec.tv_sec = clock.tv_sec;
-            timespec.tv_nsec = clock.tv_nsec;
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => {
+            let current_task = scheduler::current_thread();
+
+            // Include the CPU time that the calling thread has consumed so far.
+            //
+            // NOTE: The CPU time of the other running threads of the process is only accounted
+            // once they are switched out or enter a system call.
+            current_task.account_cpu_time();
+
+            let usage = if clock == CLOCK_PROCESS_CPUTIME_ID {
+                current_task.usage().get()
+            } else {
+                current_task.cpu_time().get()
+            };
+
+            *timespec = ns_to_timespec(usage.cpu_time());
+            Ok(0x00)
+        }
+
+        _ => Err(SyscallError::EINVAL),
+    }
+}
 
+#[syscall]
+pub fn settime(clock: usize, timespec: &TimeSpec) -> Result<usize, SyscallError> {
+    match clock {
+        CLOCK_REALTIME => {
+            crate::arch::time::set_realtime_ns(timespec_to_ns(timespec)?);
             Ok(0x00)
         }
 
+        // The monotonic clock cannot be set.
         _ => Err(SyscallError::EINVAL),
     }
 }
 
-static TIMERS: Mutex<Vec<Arc<Task>>> = Mutex::new(Vec::new());
+/// Whether the offsets of `clock_adjtime` are in nanoseconds (`ADJ_NANO`) instead of
+/// microseconds (`ADJ_MICRO`).
+static ADJTIME_NANO: AtomicBool = AtomicBool::new(false);
+
+/// The maximum offset that can be slewed by `clock_adjtime`, in nanoseconds.
+const MAX_ADJTIME_OFFSET: i64 = 500_000_000;
+
+/// The `clock_adjtime` modes that only update the informational parameters of the NTP clock
+/// discipline, which are not kept.
+const ADJ_IGNORED: u32 = ADJ_MAXERROR | ADJ_ESTERROR | ADJ_STATUS | ADJ_TIMECONST | ADJ_TAI;
+
+/// Adjusts the realtime clock (see `clock_adjtime(2)`). Offsets are slewed into the clock at a
+/// rate of at most 500 ppm, and the frequency of the clock can be adjusted by up to ±500 ppm.
+#[syscall]
+pub fn clock_adjtime(clock: usize, timex: &mut Timex) -> Result<usize, SyscallError> {
+    use crate::arch::time;
+
+    if clock != CLOCK_REALTIME {
+        return Err(SyscallError::EINVAL);
+    }
+
+    match timex.modes {
+        // The `adjtime(3)` modes always use microseconds and return the old offset.
+        ADJ_OFFSET_SINGLESHOT => {
+            let offset = timex
+                .offset
+                .saturating_mul(1000)
+                .clamp(-MAX_ADJTIME_OFFSET, MAX_ADJTIME_OFFSET);
+
+            timex.offset = time::slew_realtime(offset) / 1000;
+            return Ok(TIME_OK);
+        }
+
+        ADJ_OFFSET_SS_READ => {
+            timex.offset = time::realtime_offset() / 1000;
+            return Ok(TIME_OK);
+        }
+
+        modes => {
+            let supported = ADJ_OFFSET | ADJ_FREQUENCY | ADJ_SETOFFSET | ADJ_MICRO | ADJ_NANO;
+
+            if modes & !(supported | ADJ_IGNORED) != 0 {
+                return Err(SyscallError::EINVAL);
+            }
+        }
+    }
+
+    let modes = timex.modes;
+
+    if modes & ADJ_NANO != 0 {
+        ADJTIME_NANO.store(true, Ordering::Relaxed);
+    } else if modes & ADJ_MICRO != 0 {
+        ADJTIME_NANO.store(false, Ordering::Relaxed);
+    }
+
+    let nano = ADJTIME_NANO.load(Ordering::Relaxed);
+    let unit = if nano { 1 } else { 1000 };
+
+    if modes & ADJ_SETOFFSET != 0 {
+        // With `ADJ_NANO`, the `tv_usec` field holds nanoseconds.
+        if !(0..1000000000 / unit).contains(&timex.time.tv_usec) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let delta = timex
+            .time
+            .tv_sec
+            .saturating_mul(1000000000)
+            .saturating_add(timex.time.tv_usec * unit);
+
+        time::step_realtime(delta);
+    }
+
+    if modes & ADJ_FREQUENCY != 0 {
+        // The frequency is in ppm with a 16-bit fractional part.
+        time::set_realtime_frequency(timex.freq.saturating_mul(1000) >> 16);
+    }
+
+    if modes & ADJ_OFFSET != 0 {
+        let offset = timex
+            .offset
+            .saturating_mul(unit)
+            .clamp(-MAX_ADJTIME_OFFSET, MAX_ADJTIME_OFFSET);
+
+        time::slew_realtime(offset);
+    }
+
+    let realtime = time::get_realtime_ns();
+
+    *timex = Timex {
+        modes: timex.modes,
+        offset: time::realtime_offset() / unit,
+        freq: (time::realtime_frequency() << 16) / 1000,
+        status: if nano { STA_NANO } else { 0 },
+        precision: 1,
+        // The maximum frequency adjustment, 500 ppm.
+        tolerance: 500 << 16,
+        time: TimeVal {
+            tv_sec: (realtime / 1000000000) as i64,
+            tv_usec: (realtime % 1000000000) as i64 / unit,
+        },
+        // The length of a clock tick, in microseconds.
+        tick: 1000,
+        ..Default::default()
+    };
+
+    Ok(TIME_OK)
+}
+
+fn timeval_to_ns(timeval: &TimeVal) -> Result<u64, SyscallError> {
+    if timeval.tv_sec < 0 || !(0..1000000).contains(&timeval.tv_usec) {
+        return Err(SyscallError::EINVAL);
+    }
 
-pub fn check_timers() {
-    // for task in TIMERS.lock_irq().iter() {
-    //     task.signal(aero_syscall::signal::SIGALRM);
-    // }
+    Ok((timeval.tv_sec as u64)
+        .saturating_mul(1000000000)
+        .saturating_add(timeval.tv_usec as u64 * 1000))
+}
+
+fn ns_to_timeval(ns: u64) -> TimeVal {
+    // NOTE: Round up, as a timer that is still armed must not be reported as disarmed. The
+    // rounding is done before the split, so it carries into the seconds.
+    let us = ns.div_ceil(1000);
+
+    TimeVal {
+        tv_sec: (us / 1000000) as i64,
+        tv_usec: (us % 1000000) as i64,
+    }
 }
 
 #[syscall]
 pub fn setitimer(
     which: usize,
-    _new_value: &ITimerVal,
-    _old_value: usize, // FIXME: Option<&mut ITimerVal>
+    new_value: &ITimerVal,
+    old_value: usize, // FIXME: Option<&mut ITimerVal>
 ) -> Result<usize, SyscallError> {
-    let _guard = IrqGuard::new();
+    let value = TimerValue {
+        value: timeval_to_ns(&new_value.it_value)?,
+        interval: timeval_to_ns(&new_value.it_interval)?,
+    };
+
+    let old_value = if old_value != 0x00 {
+        Some(crate::utils::validate_mut_ptr(old_value as *mut ITimerVal)?)
+    } else {
+        None
+    };
+
+    let old = scheduler::current_thread()
+        .timers()
+        .set_itimer(which, value)
+        .ok_or(SyscallError::EINVAL)?;
+
+    if let Some(old_value) = old_value {
+        old_value.it_value = ns_to_timeval(old.value);
+        old_value.it_interval = ns_to_timeval(old.interval);
+    }
 
-    match which {
-        // The interval timer value is decremented in real time. The SIGALRM signal is
-        // generated for the process when this timer expires.
-        ITIMER_REAL => {}
+    Ok(0)
+}
+
+#[syscall]
+pub fn getitimer(which: usize, curr_value: &mut ITimerVal) -> Result<usize, SyscallError> {
+    let value = scheduler::current_thread()
+        .timers()
+        .get_itimer(which)
+        .ok_or(SyscallError::EINVAL)?;
+
+    curr_value.it_value = ns_to_timeval(value.value);
+    curr_value.it_interval = ns_to_timeval(value.interval);
+
+    Ok(0)
+}
 
-        _ => unreachable!("setitimer: unimplemented timer (ty={which})"),
+/// Creates a new POSIX timer that measures the provided clock. Returns the ID of the timer.
+#[syscall]
+pub fn timer_create(clock: usize, sevp: usize) -> Result<usize, SyscallError> {
+    if !matches!(clock, CLOCK_REALTIME | CLOCK_MONOTONIC) {
+        return Err(SyscallError::EINVAL);
     }
 
-    TIMERS
-        .lock_irq()
-        .push(scheduler::get_scheduler().current_task());
+    // If `sevp` is NULL, the process is notified with SIGALRM.
+    let (signal, value) = if sevp != 0x00 {
+        let sevp = crate::utils::validate_ptr(sevp as *const SigEvent)?;
+
+        match sevp.sigev_notify {
+            SIGEV_NONE => (None, 0),
+            SIGEV_SIGNAL if (1..=SIGRTMAX).contains(&(sevp.sigev_signo as usize)) => {
+                (Some(sevp.sigev_signo as usize), sevp.sigev_value)
+            }
+
+            _ => return Err(SyscallError::EINVAL),
+        }
+    } else {
+        (Some(SIGALRM), 0)
+    };
+
+    let timers = scheduler::current_thread().timers();
+    Ok(timers.create(clock, signal, value))
+}
+
+fn get_timer(id: usize) -> Result<Arc<RealTimer>, SyscallError> {
+    scheduler::current_thread()
+        .timers()
+        .get(id)
+        .ok_or(SyscallError::EINVAL)
+}
+
+fn set_timer_spec(spec: &mut ITimerSpec, value: TimerValue) {
+    spec.it_value = ns_to_timespec(value.value);
+    spec.it_interval = ns_to_timespec(value.interval);
+}
+
+#[syscall]
+pub fn timer_settime(
+    id: usize,
+    flags: usize,
+    new_value: &ITimerSpec,
+    old_value: usize, // FIXME: Option<&mut ITimerSpec>
+) -> Result<usize, SyscallError> {
+    let timer = get_timer(id)?;
+
+    let value = timespec_to_ns(&new_value.it_value)?;
+    let interval = timespec_to_ns(&new_value.it_interval)?;
+
+    let old_value = if old_value != 0x00 {
+        Some(crate::utils::validate_mut_ptr(
+            old_value as *mut ITimerSpec,
+        )?)
+    } else {
+        None
+    };
+
+    let now = hrtimer::now();
+
+    let expires = match (timer.clock(), flags & TIMER_ABSTIME == TIMER_ABSTIME) {
+        // A value of zero disarms the timer.
+        _ if value == 0 => 0,
+        (_, false) => now.saturating_add(value),
+        (CLOCK_MONOTONIC, true) => value,
+        (_, true) => {
+            let realtime = timespec_to_ns(&crate::arch::time::get_realtime_clock())?;
+            now.saturating_add(value.saturating_sub(realtime))
+        }
+    };
+
+    let old = if expires == 0 {
+        timer.set(0, 0)
+    } else {
+        timer.set(expires, interval)
+    };
+
+    if let Some(old_value) = old_value {
+        set_timer_spec(old_value, old);
+    }
 
     Ok(0)
 }
 
 #[syscall]
-pub fn getitimer(_which: usize, _curr_value: &mut ITimerVal) -> Result<usize, SyscallError> {
+pub fn timer_gettime(id: usize, curr_value: &mut ITimerSpec) -> Result<usize, SyscallError> {
+    set_timer_spec(curr_value, get_timer(id)?.get());
     Ok(0)
 }
+
+#[syscall]
+pub fn timer_getoverrun(id: usize) -> Result<usize, SyscallError> {
+    Ok(get_timer(id)?.overrun())
+}
+
+#[syscall]
+pub fn timer_delete(id: usize) -> Result<usize, SyscallError> {
+    if scheduler::current_thread().timers().delete(id) {
+        Ok(0)
+    } else {
+        Err(SyscallError::EINVAL)
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Static tracepoints of the kernel, recorded with the [`tracepoint!`](crate::tracepoint) macro.
+
+use super::Tracepoint;
+
+pub static SCHED_SWITCH: Tracepoint = Tracepoint::new("sched", "sched_switch");
+pub static SCHED_WAKEUP: Tracepoint = Tracepoint::new("sched", "sched_wakeup");
+pub static SCHED_PROCESS_EXIT: Tracepoint = Tracepoint::new("sched", "sched_process_exit");
+
+pub static SYS_ENTER: Tracepoint = Tracepoint::new("raw_syscalls", "sys_enter");
+pub static SYS_EXIT: Tracepoint = Tracepoint::new("raw_syscalls", "sys_exit");
+
+pub static BLOCK_RQ_ISSUE: Tracepoint = Tracepoint::new("block", "block_rq_issue");
+pub static BLOCK_RQ_COMPLETE: Tracepoint = Tracepoint::new("block", "block_rq_complete");
+
+pub static EXT2_LOOKUP: Tracepoint = Tracepoint::new("ext2", "ext2_lookup");
+pub static EXT2_READ: Tracepoint = Tracepoint::new("ext2", "ext2_read");
+pub static EXT2_WRITE: Tracepoint = Tracepoint::new("ext2", "ext2_write");
+pub static EXT2_CREATE: Tracepoint = Tracepoint::new("ext2", "ext2_create");
+
+/// All of the tracepoints, grouped by subsystem.
+pub static TRACEPOINTS: &[&Tracepoint] = &[
+    &SCHED_SWITCH,
+    &SCHED_WAKEUP,
+    &SCHED_PROCESS_EXIT,
+    &SYS_ENTER,
+    &SYS_EXIT,
+    &BLOCK_RQ_ISSUE,
+    &BLOCK_RQ_COMPLETE,
+    &EXT2_LOOKUP,
+    &EXT2_READ,
+    &EXT2_WRITE,
+    &EXT2_CREATE,
+];
+
+/// Returns the tracepoint `name` of the subsystem `system`.
+pub fn find(system: &str, name: &str) -> Option<&'static Tracepoint> {
+    TRACEPOINTS
+        .iter()
+        .copied()
+        .find(|tracepoint| tracepoint.system() == system && tracepoint.name() == name)
+}
@@ -0,0 +1,109 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Function tracer.
+//!
+//! The kernel has to be built with `-Zinstrument-mcount` (see the `ftrace` feature), which
+//! makes the compiler emit a call to `mcount` after the prologue of every function. The call
+//! returns immediately unless the `function` tracer is selected, in which case the address of
+//! the function and of its caller are recorded into the trace buffer.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::string::String;
+
+use super::{Entry, Payload};
+
+/// Whether the function tracer is the current tracer. Read by `mcount`.
+static FTRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set while the CPU records a function call, as every function called by the tracer is
+/// instrumented as well.
+#[cpu_local]
+static mut FTRACE_RECURSION: u8 = 0;
+
+// `mcount` is called with the frame of the instrumented function already set up, so its
+// return address is the address in the instrumented function and the return address of the
+// frame at `RBP` is the address in its caller. The call is a normal call from the point of view
+// of the compiler, so the caller-saved registers do not have to be preserved.
+//
+// The recursion guard is accessed with the CPU local self pointer directly, as the accessors of
+// `CpuLocal` are instrumented.
+core::arch::global_asm!(
+    ".global mcount",
+    "mcount:",
+    "cmp byte ptr [rip + {enabled}], 0",
+    "je 1f",
+    "mov rax, qword ptr gs:[0]",
+    "lea rcx, [rip + {recursion}]",
+    "lea rdx, [rip + __cpu_local_start]",
+    "sub rcx, rdx",
+    "add rax, rcx",
+    "cmp byte ptr [rax], 0",
+    "jne 1f",
+    "mov byte ptr [rax], 1",
+    // Save the address of the guard; this also aligns the stack.
+    "push rax",
+    "mov rdi, qword ptr [rsp + 8]",
+    "mov rsi, qword ptr [rbp + 8]",
+    "call {handler}",
+    "pop rax",
+    "mov byte ptr [rax], 0",
+    "1:",
+    "ret",
+    enabled = sym FTRACE_ENABLED,
+    recursion = sym FTRACE_RECURSION,
+    handler = sym ftrace_handler,
+);
+
+extern "C" fn ftrace_handler(ip: usize, parent: usize) {
+    let Some(buffers) = super::BUFFERS.get() else {
+        return;
+    };
+
+    if !super::is_on() {
+        return;
+    }
+
+    // The buffer might be locked by the code being traced on this CPU (e.g. by a reader of the
+    // trace buffer), in which case the call is not recorded.
+    if let Some(mut buffer) = buffers.get().try_lock_irq() {
+        let entry = Entry::new("function", Payload::Function { ip, parent });
+        super::push(&mut buffer, entry);
+    }
+}
+
+/// Returns whether the function tracer is the current tracer.
+pub fn is_enabled() -> bool {
+    FTRACE_ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn set_enabled(enabled: bool) {
+    if enabled {
+        super::reserve();
+    }
+
+    FTRACE_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns the name of the kernel function containing `address`.
+pub fn symbol_name(address: usize) -> String {
+    match crate::unwind::resolve_symbol(address) {
+        Some((name, offset)) => alloc::format!("{name:#}+{offset:#x}"),
+        None => alloc::format!("{address:#x}"),
+    }
+}
@@ -0,0 +1,406 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel tracing.
+//!
+//! Tracepoints are static hooks in the kernel (see [`events`]) which record an event into the
+//! trace buffer when they are enabled; a disabled tracepoint only costs a couple of atomic
+//! loads. Each CPU has its own buffer of fixed size in which the oldest entries are overwritten
+//! once it is full. The buffers are consumed through tracefs (see [`crate::fs::tracefs`]),
+//! mounted at `/sys/kernel/tracing`.
+//!
+//! With the `ftrace` feature, the function tracer records every kernel function call (see
+//! [`function`]).
+
+pub mod events;
+#[cfg(all(feature = "ftrace", target_arch = "x86_64"))]
+pub mod function;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::tls;
+use crate::userland::scheduler::{self, hrtimer};
+use crate::userland::task::TaskId;
+use crate::utils::sync::Mutex;
+use crate::utils::PerCpu;
+
+/// Records the event `$event` (a tracepoint in [`events`]) with a message formatted from the
+/// remaining arguments, if the tracepoint is enabled. The arguments are not evaluated otherwise.
+#[macro_export]
+macro_rules! tracepoint {
+    ($event:ident, $($arg:tt)*) => {
+        if $crate::trace::events::$event.is_active() {
+            $crate::trace::record(&$crate::trace::events::$event, format_args!($($arg)*));
+        }
+    };
+}
+
+/// Whether tracing is turned on (`tracing_on` in tracefs). Turning it off stops recording
+/// without disabling the tracepoints.
+static TRACING_ON: AtomicBool = AtomicBool::new(true);
+
+/// Number of entries in the buffer of each CPU.
+static BUFFER_ENTRIES: AtomicUsize = AtomicUsize::new(1024);
+
+/// Number of entries that were overwritten before they were read.
+static OVERRUN: AtomicU64 = AtomicU64::new(0);
+
+static BUFFERS: Once<PerCpu<Mutex<VecDeque<Entry>>>> = Once::new();
+
+/// A static tracepoint.
+pub struct Tracepoint {
+    system: &'static str,
+    name: &'static str,
+    enabled: AtomicBool,
+}
+
+impl Tracepoint {
+    pub const fn new(system: &'static str, name: &'static str) -> Self {
+        Self {
+            system,
+            name,
+            enabled: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the name of the subsystem the tracepoint belongs to.
+    pub fn system(&self) -> &'static str {
+        self.system
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        if enabled {
+            reserve();
+        }
+
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether the tracepoint has to record its events.
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.is_enabled() && TRACING_ON.load(Ordering::Relaxed)
+    }
+}
+
+/// Maximum length of the message of an entry; longer messages are truncated.
+const MESSAGE_SIZE: usize = 96;
+
+#[derive(Clone)]
+struct Message {
+    buffer: [u8; MESSAGE_SIZE],
+    len: usize,
+}
+
+impl Message {
+    fn as_str(&self) -> &str {
+        // The message is truncated on a character boundary.
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or("<invalid>")
+    }
+}
+
+#[derive(Clone)]
+enum Payload {
+    Message(Message),
+
+    /// A function call recorded by the function tracer: the address in the called function
+    /// and the address it returns to.
+    #[cfg(all(feature = "ftrace", target_arch = "x86_64"))]
+    Function {
+        ip: usize,
+        parent: usize,
+    },
+}
+
+impl Write for Message {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        let mut size = core::cmp::min(string.len(), MESSAGE_SIZE - self.len);
+
+        // Do not split a character when truncating the message.
+        while !string.is_char_boundary(size) {
+            size -= 1;
+        }
+
+        self.buffer[self.len..self.len + size].copy_from_slice(&string.as_bytes()[..size]);
+        self.len += size;
+
+        Ok(())
+    }
+}
+
+/// An entry of the trace buffer.
+#[derive(Clone)]
+pub struct Entry {
+    timestamp: u64,
+    cpu: usize,
+    pid: usize,
+    event: &'static str,
+    payload: Payload,
+}
+
+impl Entry {
+    fn new(event: &'static str, payload: Payload) -> Self {
+        let pid = scheduler::is_initialized()
+            .then(|| scheduler::get_scheduler().inner.current_task_optional())
+            .flatten()
+            .map(|task| task.tid().as_usize())
+            .unwrap_or(0);
+
+        Self {
+            timestamp: hrtimer::now(),
+            cpu: tls::get_cpuid(),
+            pid,
+            event,
+            payload,
+        }
+    }
+
+    /// Returns the name of the task that recorded the entry, if it is still alive.
+    fn comm(&self) -> String {
+        if self.pid == 0 {
+            return String::from("<idle>");
+        }
+
+        let Some(task) = scheduler::get_scheduler().find_task(TaskId::new(self.pid)) else {
+            return String::from("<...>");
+        };
+
+        if let Some(path) = task.path() {
+            return String::from(path.as_str().rsplit('/').next().unwrap_or("<...>"));
+        }
+
+        task.kthread()
+            .map(|kthread| String::from(kthread.name()))
+            .unwrap_or_else(|| String::from("<...>"))
+    }
+
+    /// Formats the entry as a line of the `trace` file.
+    pub fn format(&self) -> String {
+        let mut line = alloc::format!(
+            "{:>16}-{:<7} [{:03}] {:>5}.{:06}: {}: ",
+            self.comm(),
+            self.pid,
+            self.cpu,
+            self.timestamp / 1_000_000_000,
+            (self.timestamp % 1_000_000_000) / 1000,
+            self.event
+        );
+
+        match &self.payload {
+            Payload::Message(message) => line.push_str(message.as_str()),
+
+            #[cfg(all(feature = "ftrace", target_arch = "x86_64"))]
+            Payload::Function { ip, parent } => {
+                let _ = write!(
+                    line,
+                    "{} <-{}",
+                    function::symbol_name(*ip),
+                    function::symbol_name(*parent)
+                );
+            }
+        }
+
+        line.push('\n');
+        line
+    }
+}
+
+/// Allocates the buffers, so recording an entry never allocates memory. Recording has to work
+/// in any context, including while the heap is locked by the interrupted code.
+fn reserve() {
+    let Some(buffers) = BUFFERS.get() else {
+        return;
+    };
+
+    let entries = BUFFER_ENTRIES.load(Ordering::SeqCst);
+
+    for buffer in buffers.iter() {
+        let mut buffer = buffer.lock_irq();
+        let additional = entries.saturating_sub(buffer.len());
+
+        buffer.reserve_exact(additional);
+    }
+}
+
+fn push(buffer: &mut VecDeque<Entry>, entry: Entry) {
+    let entries = BUFFER_ENTRIES.load(Ordering::Relaxed);
+
+    while buffer.len() >= entries {
+        buffer.pop_front();
+        OVERRUN.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The buffer is only grown by `reserve`.
+    if buffer.len() < buffer.capacity() {
+        buffer.push_back(entry);
+    }
+}
+
+/// Records an event of `tracepoint` with the provided message. Use the [`tracepoint!`] macro
+/// instead, which only formats the message if the tracepoint is enabled.
+pub fn record(tracepoint: &'static Tracepoint, args: fmt::Arguments) {
+    let Some(buffers) = BUFFERS.get() else {
+        return;
+    };
+
+    let mut message = Message {
+        buffer: [0; MESSAGE_SIZE],
+        len: 0,
+    };
+
+    let _ = message.write_fmt(args);
+
+    let entry = Entry::new(tracepoint.name(), Payload::Message(message));
+    push(&mut buffers.get().lock_irq(), entry);
+}
+
+/// Returns whether tracing is turned on.
+pub fn is_on() -> bool {
+    TRACING_ON.load(Ordering::Relaxed)
+}
+
+pub fn set_on(on: bool) {
+    TRACING_ON.store(on, Ordering::Relaxed);
+}
+
+/// Returns the size of the buffer of each CPU in kilobytes.
+pub fn buffer_size_kb() -> usize {
+    BUFFER_ENTRIES.load(Ordering::SeqCst) * core::mem::size_of::<Entry>() / 1024
+}
+
+/// Resizes the buffer of each CPU to `size` kilobytes. The buffers are cleared.
+pub fn set_buffer_size_kb(size: usize) -> bool {
+    let entries = size * 1024 / core::mem::size_of::<Entry>();
+
+    if entries == 0 {
+        return false;
+    }
+
+    BUFFER_ENTRIES.store(entries, Ordering::SeqCst);
+
+    if let Some(buffers) = BUFFERS.get() {
+        for buffer in buffers.iter() {
+            *buffer.lock_irq() = VecDeque::new();
+        }
+    }
+
+    if events::TRACEPOINTS
+        .iter()
+        .any(|tracepoint| tracepoint.is_enabled())
+    {
+        reserve();
+    }
+
+    true
+}
+
+/// Returns the number of entries in the buffers and the number of entries that were
+/// overwritten.
+pub fn stats() -> (usize, u64) {
+    let entries = BUFFERS
+        .get()
+        .map(|buffers| buffers.iter().map(|buffer| buffer.lock_irq().len()).sum())
+        .unwrap_or(0);
+
+    (entries, OVERRUN.load(Ordering::Relaxed))
+}
+
+/// Returns a copy of the entries of all of the CPUs, ordered by time.
+pub fn snapshot() -> Vec<Entry> {
+    let Some(buffers) = BUFFERS.get() else {
+        return Vec::new();
+    };
+
+    let mut entries = Vec::new();
+
+    for buffer in buffers.iter() {
+        // Copy the entries in chunks, so interrupts are not disabled for too long.
+        let mut index = 0;
+
+        loop {
+            let buffer = buffer.lock_irq();
+            let chunk = buffer.iter().skip(index).take(64).cloned();
+            let len = entries.len();
+
+            entries.extend(chunk);
+
+            if entries.len() == len {
+                break;
+            }
+
+            index += entries.len() - len;
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.timestamp);
+    entries
+}
+
+/// Removes and returns the oldest entry of all of the CPUs.
+pub fn consume() -> Option<Entry> {
+    let buffers = BUFFERS.get()?;
+
+    loop {
+        // Find the CPU with the oldest entry and remove it, unless an older entry was
+        // recorded in the meantime.
+        let (cpu, timestamp) = buffers
+            .iter()
+            .enumerate()
+            .filter_map(|(cpu, buffer)| {
+                let buffer = buffer.lock_irq();
+                buffer.front().map(|entry| (cpu, entry.timestamp))
+            })
+            .min_by_key(|(_, timestamp)| *timestamp)?;
+
+        let mut buffer = buffers.get_cpu(cpu).lock_irq();
+
+        match buffer.front() {
+            Some(entry) if entry.timestamp == timestamp => return buffer.pop_front(),
+
+            _ => continue,
+        }
+    }
+}
+
+/// Removes all of the entries from the buffers.
+pub fn clear() {
+    if let Some(buffers) = BUFFERS.get() {
+        for buffer in buffers.iter() {
+            buffer.lock_irq().clear();
+        }
+    }
+
+    OVERRUN.store(0, Ordering::Relaxed);
+}
+
+pub fn init() {
+    BUFFERS.call_once(|| PerCpu::new(|| Mutex::new(VecDeque::new())));
+}
@@ -62,21 +62,64 @@ pub fn prepare_panic() {
 
 pub struct UnwindInfo {
     pub kernel_elf: ElfFile<'static>,
+    /// Difference between the address the kernel was loaded at and the address it was linked
+    /// at; non-zero if KASLR is enabled.
+    pub slide: usize,
 }
 
 impl UnwindInfo {
-    pub fn new(elf: ElfFile<'static>) -> Self {
-        Self { kernel_elf: elf }
+    pub fn new(elf: ElfFile<'static>, slide: usize) -> Self {
+        Self {
+            kernel_elf: elf,
+            slide,
+        }
     }
 }
 
+/// Returns the demangled name of the kernel function containing `address` and the offset of
+/// `address` within it.
+pub fn resolve_symbol(address: usize) -> Option<(rustc_demangle::Demangle<'static>, usize)> {
+    let unwind_info = UNWIND_INFO.get()?;
+    let kernel_elf = &unwind_info.kernel_elf;
+
+    let symbol_table = kernel_elf.section_iter().find_map(|section| {
+        if section.get_type() != Ok(ShType::SymTab) {
+            return None;
+        }
+
+        match section.get_data(kernel_elf) {
+            Ok(SectionData::SymbolTable64(symtab)) => Some(symtab),
+            _ => None,
+        }
+    })?;
+
+    // The symbol table contains the link-time addresses.
+    let link_address = address.wrapping_sub(unwind_info.slide);
+
+    symbol_table.iter().find_map(|data| {
+        let st_value = data.value() as usize;
+        let st_size = data.size() as usize;
+
+        if link_address >= st_value && link_address < (st_value + st_size) {
+            let mangled_name = data.get_name(kernel_elf).ok()?;
+            Some((
+                rustc_demangle::demangle(mangled_name),
+                link_address - st_value,
+            ))
+        } else {
+            None
+        }
+    })
+}
+
 pub fn unwind_stack_trace() {
     let _guard = IrqGuard::new();
 
     let mut address_space = AddressSpace::this();
     let offset_table = address_space.offset_page_table();
 
-    let kernel_elf = &UNWIND_INFO.get().unwrap().kernel_elf;
+    let unwind_info = UNWIND_INFO.get().unwrap();
+    let kernel_elf = &unwind_info.kernel_elf;
     let mut symbol_table = None;
 
     for section in kernel_elf.section_iter() {
@@ -130,12 +173,14 @@ pub fn unwind_stack_trace() {
             }
 
             let mut name = None;
+            // The symbol table contains the link-time addresses.
+            let link_rip = rip.wrapping_sub(unwind_info.slide);
 
             for data in symbol_table {
                 let st_value = data.value() as usize;
                 let st_size = data.size() as usize;
 
-                if rip >= st_value && rip < (st_value + st_size) {
+                if link_rip >= st_value && link_rip < (st_value + st_size) {
                     let mangled_name = data.get_name(kernel_elf).unwrap_or("<unknown>");
                     let demangled_name = rustc_demangle::demangle(mangled_name);
 
@@ -202,6 +247,9 @@ fn rust_begin_unwind(info: &PanicInfo) -> ! {
 
     unwind_stack_trace();
 
+    // Returns if no crash kernel is loaded.
+    crate::kexec::crash::crash_kexec();
+
     #[cfg(feature = "ci")]
     emu::exit_qemu(emu::ExitStatus::Success);
 
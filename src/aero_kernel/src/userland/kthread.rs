@@ -0,0 +1,102 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel threads.
+//!
+//! A kernel thread is a task that only runs in the kernel and shares the kernel address space
+//! with every other kernel thread. [`spawn`] creates a named kernel thread that runs the provided
+//! closure; the thread exits when the closure returns. Deferred work that does not need a thread
+//! of its own should be queued on a [`WorkQueue`](super::workqueue::WorkQueue) instead.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+use crate::utils::sync::Mutex;
+
+use super::scheduler::{self, ExitStatus};
+use super::task::Task;
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+pub struct KThread {
+    name: String,
+    /// Closure that the thread runs, taken once the thread starts running.
+    entry: Mutex<Option<Entry>>,
+}
+
+impl KThread {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Discards the signals sent to the current kernel thread. Kernel threads never return to
+/// userland, so their signals are never delivered and would interrupt every later wait.
+pub fn flush_signals() {
+    scheduler::current_thread().signals().clear_all_pending();
+}
+
+fn kthread_entry() {
+    let entry = {
+        let task = scheduler::current_thread();
+        let kthread = task.kthread().expect("kthread: not a kernel thread");
+
+        let entry = kthread.entry.lock_irq().take();
+        entry.expect("kthread: entry already taken")
+    };
+
+    entry();
+    scheduler::get_scheduler().exit(ExitStatus::Normal(0))
+}
+
+fn create<F>(name: &str, entry: F) -> Arc<Task>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = Task::new_kernel(kthread_entry, true);
+
+    task.set_kthread(Arc::new(KThread {
+        name: name.into(),
+        entry: Mutex::new(Some(Box::new(entry))),
+    }));
+
+    task
+}
+
+/// Creates a kernel thread with the provided `name` that runs `entry` and schedules it.
+pub fn spawn<F>(name: &str, entry: F) -> Arc<Task>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = create(name, entry);
+
+    scheduler::get_scheduler().register_task(task.clone());
+    task
+}
+
+/// Creates a kernel thread with the provided `name` that runs `entry` and schedules it on the
+/// CPU with the provided logical ID. The thread is bound to that CPU.
+pub fn spawn_on<F>(cpu: usize, name: &str, entry: F) -> Arc<Task>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = create(name, entry);
+    task.set_affinity(1 << cpu);
+
+    scheduler::get_scheduler().register_task_on(cpu, task.clone());
+    task
+}
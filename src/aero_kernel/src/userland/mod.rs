@@ -18,11 +18,15 @@
 use crate::fs;
 use crate::fs::Path;
 
+pub mod kthread;
 pub mod scheduler;
+pub mod seccomp;
 pub mod signals;
 pub mod task;
 pub mod terminal;
+pub mod uts;
 pub mod vm;
+pub mod workqueue;
 
 pub fn run() -> fs::Result<()> {
     let init_path = Path::new("/usr/bin/init");
@@ -0,0 +1,954 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::sync::Arc;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use intrusive_collections::LinkedList;
+
+use crate::arch;
+use crate::userland::kthread;
+use crate::userland::signals::{SignalError, SignalResult};
+use crate::userland::task::{SchedPolicy, SchedTaskAdapter, Task, TaskState, NICE_MIN};
+use crate::userland::vm::Vm;
+
+use crate::utils::sync::{IrqGuard, Mutex, MutexGuard, WaitQueue};
+use crate::utils::PerCpu;
+
+use super::{hotplug, preempt, stats, ExitStatus, SchedulerInterface, SCHEDULER_TIMER_US};
+
+/// Weight of a task for each nice value (from -20 to 19). Each nice level is roughly 10% more
+/// or less CPU time than the adjacent level.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, // -20 .. -16
+    29154, 23254, 18705, 14949, 11916, // -15 .. -11
+    9548, 7620, 6100, 4904, 3906, // -10 .. -6
+    3121, 2501, 1991, 1586, 1277, // -5 .. -1
+    1024, 820, 655, 526, 423, // 0 .. 4
+    335, 272, 215, 172, 137, // 5 .. 9
+    110, 87, 70, 56, 45, // 10 .. 14
+    36, 29, 23, 18, 15, // 15 .. 19
+];
+
+/// Weight of a task with a nice value of 0.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Targeted preemption latency (in microseconds) for CPU-bound tasks. Each runnable task gets to
+/// run at least once in this period.
+const SCHED_LATENCY_US: usize = 20000;
+/// Minimal time slice (in microseconds) of a task. If there are too many runnable tasks to give
+/// each of them this time slice in [`SCHED_LATENCY_US`], the period is extended.
+const MIN_GRANULARITY_US: usize = 2000;
+/// Amount of virtual runtime (in microseconds) that a woken up task needs to be behind the
+/// current task for it to preempt the current task.
+const WAKEUP_GRANULARITY_US: u64 = 1000;
+
+/// Amount of virtual runtime that a task which was sleeping is placed ahead of the task with the
+/// smallest virtual runtime. This makes sure interactive tasks are picked as soon as they wake
+/// up, without letting them monopolize the CPU after sleeping for a long time.
+const SLEEPER_BONUS: u64 = SCHED_LATENCY_US as u64 / 2;
+
+/// Time slice (in microseconds) of the tasks with the [`SchedPolicy::RoundRobin`] policy.
+const RR_TIMESLICE_US: usize = 100000;
+
+/// Returns the weight of the task, based on its nice value and the weights of its control
+/// group (see [`crate::userland::task::cgroups::CGroup::scale_weight`]).
+fn task_weight(task: &Task) -> u64 {
+    let weight = NICE_TO_WEIGHT[(task.nice() as isize - NICE_MIN) as usize];
+    task.cgroup().scale_weight(weight)
+}
+
+/// Converts the provided amount of time (in microseconds) that the task ran for into the
+/// virtual runtime, weighted by the priority of the task.
+fn calc_delta_vruntime(delta: usize, task: &Task) -> u64 {
+    delta as u64 * NICE_0_WEIGHT / task_weight(task)
+}
+
+/// The lists of tasks that belong to a CPU. These can be modified by other CPUs (for example,
+/// when a task is woken up) so they are protected by a lock.
+struct TaskLists {
+    runnable: LinkedList<SchedTaskAdapter>,
+    dead: LinkedList<SchedTaskAdapter>,
+    awaiting: LinkedList<SchedTaskAdapter>,
+    deadline_awaiting: LinkedList<SchedTaskAdapter>,
+    /// The task that was last switched to on the CPU. Unlike [`TaskQueue::current_task`], this
+    /// is only updated with the lock held so that other CPUs can check whether a task is still
+    /// running before migrating it.
+    running: Option<Arc<Task>>,
+    /// Smallest virtual runtime of the tasks on the CPU. Monotonically increasing.
+    min_vruntime: u64,
+    /// Whether the CPU is parked. No tasks can be added to the lists of an offline CPU.
+    offline: bool,
+}
+
+impl TaskLists {
+    fn push_runnable(&mut self, task: Arc<Task>) {
+        debug_assert!(!task.link.is_linked()); // Make sure the task is not already linked
+
+        // Do not let tasks that were sleeping (or are new) catch up with the tasks that were
+        // running in the meantime.
+        let min_vruntime = self.min_vruntime.saturating_sub(SLEEPER_BONUS);
+        task.set_vruntime(task.vruntime().max(min_vruntime));
+
+        task.update_state(TaskState::Runnable);
+        self.runnable.push_back(task);
+    }
+
+    /// Pushes the provided real-time task to the front of the runnable list, so that it is
+    /// picked before the other real-time tasks with the same priority.
+    fn push_runnable_front(&mut self, task: Arc<Task>) {
+        debug_assert!(!task.link.is_linked()); // Make sure the task is not already linked
+        debug_assert!(task.sched_policy().rt_priority().is_some());
+
+        task.update_state(TaskState::Runnable);
+        self.runnable.push_front(task);
+    }
+
+    /// Removes the next task to run from the runnable list. Real-time tasks are always picked
+    /// first (by priority), otherwise the task with the smallest virtual runtime is picked. The
+    /// tasks of the control groups that used up their CPU quota are skipped.
+    fn pop_runnable(&mut self) -> Option<Arc<Task>> {
+        let runnable = || {
+            self.runnable
+                .iter()
+                .filter(|task| !task.cgroup().is_throttled())
+        };
+
+        let task = runnable()
+            .filter_map(|task| Some((task, task.sched_policy().rt_priority()?)))
+            // NOTE: `min_by_key` returns the first task if there are multiple tasks with the
+            // same priority.
+            .min_by_key(|(_, priority)| core::cmp::Reverse(*priority))
+            .map(|(task, _)| task)
+            .or_else(|| runnable().min_by_key(|task| task.vruntime()))?
+            as *const Task;
+
+        let mut cursor = unsafe { self.runnable.cursor_mut_from_ptr(task) };
+        cursor.remove()
+    }
+
+    /// Returns the length of the time slice (in microseconds) of the provided task, which is
+    /// its share of the scheduling period based on its weight.
+    fn timeslice(&self, task: &Task) -> usize {
+        match task.sched_policy() {
+            SchedPolicy::Normal => {}
+            // Real-time FIFO tasks do not have a time slice, the timer is only used to check
+            // the deadlines and to balance the load.
+            SchedPolicy::Fifo(_) => return SCHEDULER_TIMER_US,
+            SchedPolicy::RoundRobin(_) => return RR_TIMESLICE_US,
+        }
+
+        let (nr_running, load) = self
+            .runnable
+            .iter()
+            .fold((1, task_weight(task)), |(nr, load), e| {
+                (nr + 1, load + task_weight(e))
+            });
+
+        let period = core::cmp::max(SCHED_LATENCY_US, nr_running * MIN_GRANULARITY_US);
+        let slice = (period as u64 * task_weight(task) / load) as usize;
+
+        core::cmp::max(slice, MIN_GRANULARITY_US)
+    }
+
+    fn push_dead(&mut self, task: Arc<Task>) {
+        debug_assert_eq!(task.state(), TaskState::Runnable);
+        debug_assert!(!task.link.is_linked()); // Make sure the task is not already linked
+
+        self.dead.push_back(task);
+    }
+
+    fn push_deadline_awaiting(&mut self, task: Arc<Task>, duration: usize) {
+        debug_assert!(!task.link.is_linked()); // Make sure the task is not already linked
+
+        task.update_state(TaskState::AwaitingIo);
+        task.set_sleep_duration(crate::arch::time::get_uptime_ticks() + duration);
+
+        self.deadline_awaiting.push_back(task);
+    }
+
+    fn push_awaiting(&mut self, task: Arc<Task>) {
+        debug_assert!(!task.link.is_linked()); // Make sure the task is not already linked
+
+        task.update_state(TaskState::AwaitingIo);
+        self.awaiting.push_back(task);
+    }
+
+    /// Returns whether a task of the thread group with the provided address space is running on
+    /// the CPU.
+    fn is_running(&self, vm: &Arc<Vm>) -> bool {
+        self.running
+            .as_ref()
+            .is_some_and(|task| Arc::ptr_eq(&task.vm, vm))
+    }
+
+    /// Moves all of the tasks of the thread group with the provided address space to the
+    /// lists of `dest`, which belong to the CPU `cpu`. Returns the number of tasks moved.
+    fn move_group(&mut self, dest: &mut TaskLists, vm: &Arc<Vm>, cpu: usize) -> usize {
+        self.move_tasks(dest, cpu, |task| Arc::ptr_eq(&task.vm, vm))
+    }
+
+    /// Moves all of the tasks to the lists of `dest`, which belong to the CPU `cpu`. Returns
+    /// the number of tasks moved, excluding the dead tasks.
+    fn move_all(&mut self, dest: &mut TaskLists, cpu: usize) -> usize {
+        let moved = self.move_tasks(dest, cpu, |_| true);
+
+        dest.dead.back_mut().splice_after(core::mem::replace(
+            &mut self.dead,
+            LinkedList::new(SchedTaskAdapter::new()),
+        ));
+
+        moved
+    }
+
+    /// Moves the tasks that match the provided predicate to the lists of `dest`, which belong
+    /// to the CPU `cpu`. Returns the number of tasks moved.
+    fn move_tasks(
+        &mut self,
+        dest: &mut TaskLists,
+        cpu: usize,
+        predicate: impl Fn(&Task) -> bool,
+    ) -> usize {
+        // The virtual runtime is relative to the CPU that the task is on.
+        let vruntime = (self.min_vruntime, dest.min_vruntime);
+
+        fn move_list(
+            from: &mut LinkedList<SchedTaskAdapter>,
+            to: &mut LinkedList<SchedTaskAdapter>,
+            predicate: &dyn Fn(&Task) -> bool,
+            cpu: usize,
+            (from_min, to_min): (u64, u64),
+        ) -> usize {
+            let mut moved = 0;
+            let mut cursor = from.front_mut();
+
+            while let Some(task) = cursor.get() {
+                if predicate(task) {
+                    let task = cursor.remove().unwrap();
+
+                    task.set_cpu(cpu);
+                    task.set_vruntime(task.vruntime().saturating_sub(from_min) + to_min);
+                    to.push_back(task);
+                    moved += 1;
+                } else {
+                    cursor.move_next();
+                }
+            }
+
+            moved
+        }
+
+        let predicate = &predicate;
+
+        move_list(
+            &mut self.runnable,
+            &mut dest.runnable,
+            predicate,
+            cpu,
+            vruntime,
+        ) + move_list(
+            &mut self.awaiting,
+            &mut dest.awaiting,
+            predicate,
+            cpu,
+            vruntime,
+        ) + move_list(
+            &mut self.deadline_awaiting,
+            &mut dest.deadline_awaiting,
+            predicate,
+            cpu,
+            vruntime,
+        )
+    }
+}
+
+/// Scheduler queue containing a vector of all of the task of the enqueued
+/// taskes.
+struct TaskQueue {
+    /// The kernel idle task is a special kind of task that is run when
+    /// no taskes in the scheduler's queue are available to execute. The idle task
+    /// is to be created for each CPU.
+    idle_task: Arc<Task>,
+    preempt_task: Arc<Task>,
+    /// The task currently running on the CPU. Only accessed by the CPU that owns the
+    /// queue.
+    current_task: Option<Arc<Task>>,
+
+    lists: Mutex<TaskLists>,
+    /// Number of tasks (excluding the idle and preempt tasks) assigned to the CPU.
+    nr_tasks: AtomicUsize,
+    /// Uptime (in seconds) at which the CPU last tried to balance its load with the other
+    /// CPUs. Only accessed by the CPU that owns the queue.
+    last_balance: usize,
+    /// Set when the CPU has to be parked (see [`super::hotplug`]).
+    park_requested: AtomicBool,
+    /// Length (in microseconds) that the scheduler timer was last armed with.
+    slice_us: AtomicUsize,
+
+    dead_wq: WaitQueue,
+}
+
+impl TaskQueue {
+    /// Creates a new task queue with no taskes by default.
+    fn new() -> Self {
+        Self {
+            idle_task: Task::new_idle(),
+            preempt_task: Task::new_kernel(preempter, false),
+            current_task: None,
+
+            lists: Mutex::new(TaskLists {
+                runnable: LinkedList::new(SchedTaskAdapter::new()),
+                dead: LinkedList::new(SchedTaskAdapter::new()),
+                awaiting: LinkedList::new(SchedTaskAdapter::new()),
+                deadline_awaiting: LinkedList::new(SchedTaskAdapter::new()),
+                running: None,
+                min_vruntime: 0,
+                offline: false,
+            }),
+            nr_tasks: AtomicUsize::new(0),
+            last_balance: 0,
+            park_requested: AtomicBool::new(false),
+            slice_us: AtomicUsize::new(SCHEDULER_TIMER_US),
+
+            dead_wq: WaitQueue::new(),
+        }
+    }
+}
+
+/// Completely Fair Scheduler (CFS)
+///
+/// Each task keeps track of its virtual runtime, which is the amount of time it ran for,
+/// weighted by its nice value. The runnable task with the smallest virtual runtime (i.e. the
+/// task that received the least amount of CPU time relative to its weight) is always picked
+/// next and gets to run for its share of the scheduling period.
+///
+/// Tasks that were sleeping (for example, interactive tasks waiting for input) are placed
+/// slightly ahead of the runnable tasks when woken up and preempt the current task if it ran
+/// for long enough, so they are not starved by CPU-bound tasks.
+///
+/// Real-time tasks ([`SchedPolicy::Fifo`] and [`SchedPolicy::RoundRobin`]) always take
+/// precedence over the time-sharing tasks and are picked strictly by their priority.
+///
+/// Each CPU has its own queue. Tasks that share an address space (i.e. a thread group) are
+/// always kept on the same CPU, so they are migrated between CPUs together:
+///
+/// * When a CPU runs out of runnable tasks, it steals a thread group from another CPU.
+/// * Once a second, a CPU pulls a thread group from a CPU that has more tasks than itself.
+/// * When a task is picked on a CPU that is not in its affinity mask, its thread group is moved
+///   to an allowed CPU.
+/// * When a CPU is taken offline, all of its tasks are moved to the least loaded online CPU.
+///
+/// ## Notes
+/// * <https://en.wikipedia.org/wiki/Completely_Fair_Scheduler>
+/// * <https://docs.kernel.org/scheduler/sched-design-CFS.html>
+pub struct Cfs {
+    /// The per-cpu scheduler queues.
+    queue: PerCpu<TaskQueue>,
+}
+
+impl Cfs {
+    /// Creates a new instance of the completely fair scheduler and return a
+    /// reference-counting pointer to itself.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            queue: PerCpu::new(TaskQueue::new),
+        })
+    }
+
+    /// Selects the CPU that the provided new task is going to be scheduled on.
+    fn select_cpu(&self, task: &Task) -> usize {
+        let this_cpu = arch::tls::get_cpuid();
+
+        // Tasks that share the address space with the current task (i.e. threads) are kept on
+        // the same CPU. This way an address space is never active on more than one CPU, so
+        // the TLB of the other CPUs does not need to be invalidated when it is modified.
+        if let Some(current) = self.queue.get().current_task.as_ref() {
+            if Arc::ptr_eq(&current.vm, &task.vm) {
+                return this_cpu;
+            }
+        }
+
+        self.least_loaded_cpu(task).unwrap_or(this_cpu)
+    }
+
+    /// Returns the CPU, that the provided task is allowed to run on, with the least amount of
+    /// tasks.
+    fn least_loaded_cpu(&self, task: &Task) -> Option<usize> {
+        self.queue
+            .iter()
+            .enumerate()
+            .filter(|(cpu, _)| task.can_run_on(*cpu) && hotplug::is_online(*cpu))
+            .min_by_key(|(_, queue)| queue.nr_tasks.load(Ordering::SeqCst))
+            .map(|(cpu, _)| cpu)
+    }
+
+    /// Returns whether all of the CPUs that the provided task is allowed to run on are offline.
+    fn is_stranded(&self, task: &Task) -> bool {
+        let mut allowed = (0..self.queue.iter().count())
+            .filter(|cpu| task.can_run_on(*cpu))
+            .peekable();
+
+        allowed.peek().is_some() && allowed.all(|cpu| !hotplug::is_online(cpu))
+    }
+
+    /// Locks the task lists of both of the provided CPUs. The locks are always acquired in the
+    /// order of the CPU IDs to avoid deadlocks.
+    fn lock_pair(&self, a: usize, b: usize) -> (MutexGuard<TaskLists>, MutexGuard<TaskLists>) {
+        debug_assert_ne!(a, b);
+
+        if a < b {
+            let a = self.queue.get_cpu(a).lists.lock_irq();
+            let b = self.queue.get_cpu(b).lists.lock_irq();
+            (a, b)
+        } else {
+            let b = self.queue.get_cpu(b).lists.lock_irq();
+            let a = self.queue.get_cpu(a).lists.lock_irq();
+            (a, b)
+        }
+    }
+
+    /// Moves the thread group of the provided task from the CPU `src` to the CPU `dest`.
+    fn migrate_group(&self, task: &Task, src: usize, dest: usize) {
+        let (mut from, mut to) = self.lock_pair(src, dest);
+
+        // The task might have been migrated in the meantime.
+        if task.cpu() != src || from.is_running(&task.vm) || to.offline {
+            return;
+        }
+
+        let moved = from.move_group(&mut to, &task.vm, dest);
+
+        self.queue
+            .get_cpu(src)
+            .nr_tasks
+            .fetch_sub(moved, Ordering::SeqCst);
+        self.queue
+            .get_cpu(dest)
+            .nr_tasks
+            .fetch_add(moved, Ordering::SeqCst);
+    }
+
+    /// Pulls a runnable thread group from another CPU to the CPU `this_cpu`. If `idle` is
+    /// false, the group is only pulled from a CPU that has more tasks than this one. Returns
+    /// whether any tasks were pulled.
+    fn balance(&self, this_cpu: usize, idle: bool) -> bool {
+        let this_queue = self.queue.get_cpu(this_cpu);
+
+        for (cpu, queue) in self.queue.iter().enumerate() {
+            if cpu == this_cpu {
+                continue;
+            }
+
+            let nr_tasks = this_queue.nr_tasks.load(Ordering::SeqCst);
+
+            if !idle && queue.nr_tasks.load(Ordering::SeqCst) <= nr_tasks + 1 {
+                continue;
+            }
+
+            let (mut this_lists, mut lists) = self.lock_pair(this_cpu, cpu);
+
+            if this_lists.offline {
+                return false;
+            }
+
+            let Some(vm) = lists
+                .runnable
+                .iter()
+                .find(|task| task.can_run_on(this_cpu) && !lists.is_running(&task.vm))
+                .map(|task| task.vm.clone())
+            else {
+                continue;
+            };
+
+            let moved = lists.move_group(&mut this_lists, &vm, this_cpu);
+
+            queue.nr_tasks.fetch_sub(moved, Ordering::SeqCst);
+            this_queue.nr_tasks.fetch_add(moved, Ordering::SeqCst);
+            return true;
+        }
+
+        false
+    }
+
+    /// Adds the provided task to the queue of the provided CPU. If the CPU is offline, the task
+    /// is added to the queue of another CPU instead.
+    fn enqueue(&self, mut cpu: usize, task: Arc<Task>) {
+        loop {
+            let queue = self.queue.get_cpu(cpu);
+            let mut lists = queue.lists.lock_irq();
+
+            if !lists.offline {
+                task.set_cpu(cpu);
+                queue.nr_tasks.fetch_add(1, Ordering::SeqCst);
+                lists.push_runnable(task);
+                return;
+            }
+
+            core::mem::drop(lists);
+
+            // NOTE: The BSP cannot be taken offline.
+            cpu = self.least_loaded_cpu(&task).unwrap_or(0);
+        }
+    }
+
+    /// Moves all of the tasks of the current CPU to the least loaded online CPU and parks the
+    /// current CPU until it is brought back online.
+    fn park(&self, this_cpu: usize) {
+        let queue = self.queue.get_mut();
+        queue.current_task = None;
+
+        let Some(dest) = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(cpu, _)| *cpu != this_cpu && hotplug::is_online(*cpu))
+            .min_by_key(|(_, queue)| queue.nr_tasks.load(Ordering::SeqCst))
+            .map(|(cpu, _)| cpu)
+        else {
+            return;
+        };
+
+        {
+            let (mut from, mut to) = self.lock_pair(this_cpu, dest);
+            from.offline = true;
+
+            let moved = from.move_all(&mut to, dest);
+
+            queue.nr_tasks.fetch_sub(moved, Ordering::SeqCst);
+            self.queue
+                .get_cpu(dest)
+                .nr_tasks
+                .fetch_add(moved, Ordering::SeqCst);
+        }
+
+        // The dead tasks were moved as well.
+        self.queue.get_cpu(dest).dead_wq.notify_all();
+
+        arch::hotplug::park();
+
+        queue.lists.lock_irq().offline = false;
+        queue.last_balance = 0;
+    }
+
+    fn sweep_dead(&self) {
+        let _guard = IrqGuard::new();
+        let queue = self.queue.get_mut();
+
+        let task = queue.lists.lock_irq().dead.pop_front();
+
+        if let Some(task) = task {
+            task.update_state(TaskState::Zombie);
+            task.make_zombie();
+            // TODO: assert strong count here
+        } else {
+            queue.dead_wq.insert(self.current_task());
+            self.await_io().unwrap();
+        }
+    }
+
+    fn schedule_check_deadline(&self, lists: &mut TaskLists) {
+        let time = crate::arch::time::get_uptime_ticks();
+
+        let mut expired = LinkedList::new(SchedTaskAdapter::new());
+        let mut cursor = lists.deadline_awaiting.front_mut();
+
+        while let Some(task) = cursor.get() {
+            if task.load_sleep_duration() <= time {
+                let ptr = cursor.remove().unwrap();
+
+                assert!(!ptr.link.is_linked());
+
+                ptr.set_sleep_duration(0);
+                expired.push_back(ptr);
+            } else {
+                cursor.move_next();
+            }
+        }
+
+        while let Some(task) = expired.pop_front() {
+            lists.push_runnable(task);
+        }
+    }
+
+    fn schedule_next_task(&self) {
+        let guard = IrqGuard::new();
+        let this_cpu = arch::tls::get_cpuid();
+        let queue = self.queue.get_mut();
+
+        let slice = queue.slice_us.load(Ordering::SeqCst);
+        let elapsed = slice.saturating_sub(super::timer_remaining());
+        let prev = queue.current_task.as_ref().map_or(0, |task| task.tid().as_usize());
+
+        if prev == 0 {
+            stats::idle_exit(crate::arch::time::get_uptime_ns());
+        }
+
+        // Put the preempted task back into the runnable queue.
+        {
+            let mut lists = queue.lists.lock_irq();
+
+            if let Some(current_task) = lists.running.take() {
+                let policy = current_task.sched_policy();
+
+                if policy == SchedPolicy::Normal {
+                    // Charge the task for the time that it ran for.
+                    let delta = calc_delta_vruntime(elapsed, &current_task);
+                    current_task.set_vruntime(current_task.vruntime() + delta);
+                }
+
+                if !current_task.link.is_linked() {
+                    match policy {
+                        // A preempted real-time task stays at the front of its priority,
+                        // unless it used up its time slice.
+                        SchedPolicy::Fifo(_) => lists.push_runnable_front(current_task),
+                        SchedPolicy::RoundRobin(_) if elapsed < slice => {
+                            lists.push_runnable_front(current_task)
+                        }
+
+                        _ => lists.push_runnable(current_task),
+                    }
+                }
+            }
+        }
+
+        // NOTE: The lists must not be locked while charging the CPU time, as the process might
+        // get signaled.
+        if let Some(current_task) = queue.current_task.as_ref() {
+            let elapsed = elapsed as u64 * 1000;
+
+            current_task.timers().charge(elapsed);
+            current_task.account_cpu_time();
+            current_task.cgroup().charge_cpu(elapsed);
+        }
+
+        if queue.park_requested.swap(false, Ordering::SeqCst) {
+            self.park(this_cpu);
+        }
+
+        let uptime = crate::arch::time::get_uptime_ticks();
+
+        if queue.last_balance != uptime {
+            queue.last_balance = uptime;
+            self.balance(this_cpu, false);
+        }
+
+        loop {
+            let mut lists = queue.lists.lock_irq();
+
+            self.schedule_check_deadline(&mut lists);
+
+            // Switch to the next runnable task in the runnable queue.
+            match lists.pop_runnable() {
+                // NOTE: If all of the CPUs that the task is allowed to run on are offline, it is
+                // run on this CPU until one of them is brought back online.
+                Some(task) if task.can_run_on(this_cpu) || self.is_stranded(&task) => {
+                    let slice = lists.timeslice(&task);
+
+                    lists.min_vruntime = lists.min_vruntime.max(task.vruntime());
+                    lists.running = Some(task.clone());
+                    core::mem::drop(lists);
+
+                    queue.current_task = Some(task.clone());
+                    task.cpu_time().start(crate::arch::time::get_uptime_ns());
+                    self.arm_timer(slice);
+
+                    if task.tid().as_usize() != prev {
+                        stats::context_switch();
+                    }
+
+                    crate::perf::sched_switch(Some(task.tid()));
+                    crate::tracepoint!(
+                        SCHED_SWITCH,
+                        "prev_pid={prev} ==> next_pid={}",
+                        task.tid().as_usize()
+                    );
+
+                    core::mem::drop(guard);
+                    arch::task::arch_task_spinup(
+                        queue.preempt_task.arch_task_mut(),
+                        task.arch_task(),
+                    );
+                    return;
+                }
+
+                // The task is not allowed to run on this CPU anymore, so move its thread group
+                // to a CPU that it is allowed to run on.
+                Some(task) => {
+                    lists.runnable.push_back(task.clone());
+                    core::mem::drop(lists);
+
+                    let dest = self.least_loaded_cpu(&task).unwrap_or(this_cpu);
+
+                    if dest == this_cpu {
+                        // The task is not allowed to run on any of the CPUs, run it anyway.
+                        task.set_affinity(u64::MAX);
+                    } else {
+                        self.migrate_group(&task, this_cpu, dest);
+                    }
+                }
+
+                None => {
+                    core::mem::drop(lists);
+
+                    // Try to steal work from the other CPUs before going idle.
+                    if !self.balance(this_cpu, true) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        queue.current_task = None;
+        self.arm_timer(SCHEDULER_TIMER_US);
+        stats::idle_enter(crate::arch::time::get_uptime_ns());
+
+        if prev != 0 {
+            stats::context_switch();
+        }
+
+        crate::perf::sched_switch(None);
+        crate::tracepoint!(SCHED_SWITCH, "prev_pid={prev} ==> next_pid=0");
+
+        core::mem::drop(guard);
+        arch::task::arch_task_spinup(
+            queue.preempt_task.arch_task_mut(),
+            queue.idle_task.arch_task(),
+        );
+    }
+
+    /// Arms the scheduler timer of the current CPU to fire after `us` microseconds.
+    fn arm_timer(&self, us: usize) {
+        self.queue.get().slice_us.store(us, Ordering::SeqCst);
+        super::arm_timer(us);
+    }
+
+    /// Returns whether the provided task, which was just woken up on the current CPU, should
+    /// preempt the current task.
+    fn should_preempt(&self, task: &Task) -> bool {
+        let Some(current) = self.queue.get().current_task.as_ref() else {
+            // The CPU is idle.
+            return true;
+        };
+
+        match (
+            task.sched_policy().rt_priority(),
+            current.sched_policy().rt_priority(),
+        ) {
+            (Some(priority), Some(current_priority)) => priority > current_priority,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => current.vruntime() > task.vruntime() + WAKEUP_GRANULARITY_US,
+        }
+    }
+}
+
+impl SchedulerInterface for Cfs {
+    fn register_task(&self, task: Arc<Task>) {
+        let cpu = self.select_cpu(&task);
+        self.enqueue(cpu, task);
+    }
+
+    fn current_task_optional(&self) -> Option<Arc<Task>> {
+        self.queue.get().current_task.as_ref().cloned()
+    }
+
+    fn init(&self) {
+        // Register the sweeper task in each of the CPU's queue, as the dead tasks are
+        // queued on the CPU that they were running on.
+        for (cpu, _) in self.queue.iter().enumerate() {
+            kthread::spawn_on(cpu, &alloc::format!("sweeper/{cpu}"), sweeper);
+        }
+    }
+
+    fn register_task_on(&self, cpu: usize, task: Arc<Task>) {
+        self.enqueue(cpu, task);
+    }
+
+    fn park_cpu(&self, cpu: usize) {
+        self.queue
+            .get_cpu(cpu)
+            .park_requested
+            .store(true, Ordering::SeqCst);
+    }
+
+    fn wake_up(&self, task: Arc<Task>) {
+        // NOTE: The state of the task must be checked with the lock held, as the task might be
+        // going to sleep on another CPU.
+        let mut lists = loop {
+            let cpu = task.cpu();
+            let lists = self.queue.get_cpu(cpu).lists.lock_irq();
+
+            // The task might have been migrated before the lock was acquired.
+            if task.cpu() == cpu {
+                break lists;
+            }
+        };
+
+        if task.state() == TaskState::AwaitingIo {
+            let mut cursor = unsafe { lists.awaiting.cursor_mut_from_ptr(task.as_ref()) };
+
+            if let Some(task) = cursor.remove() {
+                lists.push_runnable(task);
+            }
+
+            crate::tracepoint!(
+                SCHED_WAKEUP,
+                "pid={} target_cpu={:03}",
+                task.tid().as_usize(),
+                task.cpu()
+            );
+
+            if task.cpu() == arch::tls::get_cpuid() && self.should_preempt(&task) {
+                self.resched();
+            }
+        } else {
+            task.set_pending_io(true)
+        }
+    }
+
+    fn sleep(&self, duration: Option<usize>) -> SignalResult<()> {
+        let _guard = IrqGuard::new();
+        let queue = self.queue.get_mut();
+
+        let task = queue
+            .current_task
+            .as_ref()
+            .expect("IDLE task should not await for anything")
+            .clone();
+
+        {
+            let mut lists = queue.lists.lock_irq();
+
+            if task.has_pending_io() {
+                task.set_pending_io(false);
+                return Ok(());
+            }
+
+            if let Some(duration) = duration {
+                lists.push_deadline_awaiting(task, duration);
+            } else {
+                lists.push_awaiting(task);
+            }
+        }
+
+        self.preempt();
+
+        let task = queue
+            .current_task
+            .as_ref()
+            .expect("IDLE task should not await for anything")
+            .clone();
+
+        if task.signals().has_pending() {
+            Err(SignalError::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn preempt(&self) {
+        // We want to preempt under the following circumstances:
+        //
+        // 1. When a process switches from the running state to the waiting state.
+        // 2. When the timer interrupt fires.
+        // 3. When the process switches from the waiting state to the runnable state (for example,
+        //    on completion of I/O operation).
+        // 4. When the process is terminated.
+
+        let guard = IrqGuard::new();
+        let queue = self.queue.get();
+
+        // The preemption state belongs to the task, as it might be resumed on another CPU.
+        let preempt_state = preempt::save();
+
+        if let Some(current) = queue.current_task.as_ref() {
+            core::mem::drop(guard);
+            arch::task::arch_task_spinup(current.arch_task_mut(), queue.preempt_task.arch_task());
+        } else {
+            core::mem::drop(guard);
+            arch::task::arch_task_spinup(
+                queue.idle_task.arch_task_mut(),
+                queue.preempt_task.arch_task(),
+            );
+        }
+
+        preempt::restore(preempt_state);
+    }
+
+    fn resched(&self) {
+        let slice = self.queue.get().slice_us.load(Ordering::SeqCst);
+        let elapsed = slice.saturating_sub(super::timer_remaining());
+
+        // NOTE: The current task cannot be preempted directly, as the caller might be holding
+        // locks (or be in an interrupt handler). So, end the time slice right away.
+        self.queue
+            .get()
+            .slice_us
+            .store(elapsed + 1, Ordering::SeqCst);
+        super::arm_timer(1);
+    }
+
+    fn await_io(&self) -> SignalResult<()> {
+        self.sleep(None)
+    }
+
+    fn exit(&self, status: ExitStatus) -> ! {
+        let guard = IrqGuard::new();
+        let queue = self.queue.get_mut();
+
+        let current_task = queue
+            .current_task
+            .as_ref()
+            .expect("attempted to exit current task before it was initialized")
+            .clone();
+
+        current_task.exit_status.call_once(|| status);
+
+        queue.nr_tasks.fetch_sub(1, Ordering::SeqCst);
+        queue.lists.lock_irq().push_dead(current_task);
+        queue.dead_wq.notify_all();
+
+        core::mem::drop(guard);
+        self.preempt();
+
+        unreachable!()
+    }
+}
+
+unsafe impl Send for Cfs {}
+unsafe impl Sync for Cfs {}
+
+/// Special scheduler task which is responsible to terminate a child process
+/// that has previously exited, thereby removing it from the process table. Until
+/// the child process is sweeped, it will be listed in the process table as a zombie
+/// or defunct process.
+fn sweeper() {
+    let scheduler_ref = super::get_scheduler().inner.downcast_arc::<Cfs>().unwrap();
+
+    loop {
+        scheduler_ref.sweep_dead();
+    }
+}
+
+fn preempter() {
+    let scheduler_ref = super::get_scheduler().inner.downcast_arc::<Cfs>().unwrap();
+
+    loop {
+        scheduler_ref.schedule_next_task();
+    }
+}
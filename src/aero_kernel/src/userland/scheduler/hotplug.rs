@@ -0,0 +1,172 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! CPU hotplug.
+//!
+//! A CPU is taken offline by moving all of its tasks to the least loaded online CPU and parking
+//! it (see [`crate::arch::hotplug`]). The interrupts delivered to it are steered to the BSP and
+//! its pending high-resolution timers are run by the BSP. The scheduler does not place any tasks
+//! on an offline CPU; the tasks that are only allowed to run on offline CPUs run on the other
+//! CPUs until one of them is brought back online.
+//!
+//! The BSP (CPU 0) cannot be taken offline.
+//!
+//! ## Notes
+//! * The high-resolution timers of an offline CPU may expire up to a time slice late, until the
+//!   scheduler timer of the BSP is re-armed.
+
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Once;
+
+use crate::arch;
+use crate::utils::sync::BMutex;
+use crate::utils::PerCpu;
+
+use super::hrtimer;
+
+/// Time to wait between checking whether a CPU was parked or woken up.
+const POLL_INTERVAL_NS: u64 = 1_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HotplugError {
+    /// The CPU does not exist or cannot be taken offline.
+    InvalidCpu,
+    /// The architecture does not support taking CPUs offline.
+    NotSupported,
+}
+
+static ONLINE: Once<PerCpu<AtomicBool>> = Once::new();
+/// Serializes taking CPUs offline and bringing them online.
+static HOTPLUG_LOCK: BMutex<()> = BMutex::new(());
+
+/// Returns whether the CPU `cpu` is online.
+pub fn is_online(cpu: usize) -> bool {
+    ONLINE
+        .get()
+        .map_or(true, |online| online.get_cpu(cpu).load(Ordering::SeqCst))
+}
+
+/// Returns the number of online CPUs.
+pub fn nr_online() -> usize {
+    (0..arch::apic::get_cpu_count())
+        .filter(|&cpu| is_online(cpu))
+        .count()
+}
+
+fn set_online(cpu: usize, online: bool) {
+    ONLINE
+        .get()
+        .expect("hotplug: not initialized")
+        .get_cpu(cpu)
+        .store(online, Ordering::SeqCst);
+}
+
+/// Waits until the CPU `cpu` is parked (or not, if `parked` is false).
+fn wait_parked(cpu: usize, parked: bool) {
+    while arch::hotplug::is_parked(cpu) != parked {
+        // NOTE: The CPU cannot be left half offline, so signals are ignored.
+        if hrtimer::sleep(POLL_INTERVAL_NS).is_err() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+fn check_cpu(cpu: usize) -> Result<(), HotplugError> {
+    if !arch::hotplug::is_supported() {
+        return Err(HotplugError::NotSupported);
+    }
+
+    // The BSP handles the interrupts and the timers of the offline CPUs.
+    if cpu == 0 || cpu >= arch::apic::get_cpu_count() {
+        return Err(HotplugError::InvalidCpu);
+    }
+
+    Ok(())
+}
+
+/// Takes the CPU `cpu` offline. Returns once the CPU is parked.
+pub fn offline(cpu: usize) -> Result<(), HotplugError> {
+    check_cpu(cpu)?;
+
+    let _guard = HOTPLUG_LOCK.lock();
+
+    if !is_online(cpu) {
+        return Ok(());
+    }
+
+    set_online(cpu, false);
+    arch::hotplug::migrate_irqs(cpu);
+
+    super::get_scheduler().inner.park_cpu(cpu);
+    wait_parked(cpu, true);
+
+    log::info!("hotplug: CPU {cpu} is offline");
+    Ok(())
+}
+
+/// Brings the CPU `cpu` back online. Returns once the CPU left the parked state.
+pub fn online(cpu: usize) -> Result<(), HotplugError> {
+    check_cpu(cpu)?;
+
+    let _guard = HOTPLUG_LOCK.lock();
+
+    if is_online(cpu) {
+        return Ok(());
+    }
+
+    set_online(cpu, true);
+
+    arch::hotplug::wake(cpu);
+    wait_parked(cpu, false);
+
+    log::info!("hotplug: CPU {cpu} is online");
+    Ok(())
+}
+
+/// Takes all of the CPUs except for the BSP offline. Returns the CPUs that were taken offline,
+/// which have to be brought back online with [`online_cpus`].
+pub fn offline_secondary_cpus() -> Result<Vec<usize>, HotplugError> {
+    let mut offlined = Vec::new();
+
+    for cpu in (1..arch::apic::get_cpu_count()).filter(|&cpu| is_online(cpu)) {
+        if let Err(error) = offline(cpu) {
+            online_cpus(&offlined);
+            return Err(error);
+        }
+
+        offlined.push(cpu);
+    }
+
+    Ok(offlined)
+}
+
+/// Brings the provided CPUs back online.
+pub fn online_cpus(cpus: &[usize]) {
+    for &cpu in cpus {
+        if let Err(error) = online(cpu) {
+            log::error!("hotplug: failed to bring CPU {cpu} online ({error:?})");
+        }
+    }
+}
+
+pub(super) fn init() {
+    ONLINE.call_once(|| PerCpu::new(|| AtomicBool::new(true)));
+    arch::hotplug::init();
+}
@@ -0,0 +1,203 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! High-Resolution Timers
+//!
+//! High-resolution timers are one-shot timers with nanosecond resolution, which expire at an
+//! absolute time on the monotonic clock (see [`now`]). Each CPU keeps its pending timers in a
+//! tree ordered by the expiry time and the scheduler timer of the CPU is armed to fire at the
+//! end of the current time slice or when the earliest timer expires, whichever comes first.
+//!
+//! The timers of the offline CPUs are run by the BSP (see [`super::hotplug`]).
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Once;
+
+use crate::arch;
+use crate::userland::signals::SignalResult;
+use crate::utils::sync::Mutex;
+use crate::utils::PerCpu;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct TimerKey {
+    expires: u64,
+    /// Unique ID of the timer, used to order the timers that expire at the same time.
+    id: usize,
+}
+
+static QUEUES: Once<PerCpu<Mutex<BTreeMap<TimerKey, Callback>>>> = Once::new();
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn queue(cpu: usize) -> &'static Mutex<BTreeMap<TimerKey, Callback>> {
+    QUEUES
+        .get()
+        .expect("hrtimer: attempted to use a timer before initialization")
+        .get_cpu(cpu)
+}
+
+/// Returns the current time (in nanoseconds) of the monotonic clock.
+pub fn now() -> u64 {
+    arch::time::get_uptime_ns()
+}
+
+/// A pending high-resolution timer. The timer is cancelled when dropped.
+pub struct HrTimer {
+    cpu: usize,
+    key: TimerKey,
+}
+
+impl HrTimer {
+    /// Starts a timer on the current CPU, which invokes `callback` (in interrupt context) once
+    /// the monotonic clock reaches `expires`.
+    pub fn start<F>(expires: u64, callback: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let cpu = arch::tls::get_cpuid();
+        let key = TimerKey {
+            expires,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let earliest = {
+            let mut timers = queue(cpu).lock_irq();
+            timers.insert(key, Box::new(callback));
+            timers.first_key_value().map(|(key, _)| *key) == Some(key)
+        };
+
+        // Re-arm the scheduler timer if the new timer expires before all of the other ones.
+        if earliest {
+            super::program_timer();
+        }
+
+        Self { cpu, key }
+    }
+
+    /// Returns the time (on the monotonic clock) at which the timer expires.
+    pub fn expires(&self) -> u64 {
+        self.key.expires
+    }
+
+    /// Returns whether the timer has not expired (or been cancelled) yet.
+    pub fn is_pending(&self) -> bool {
+        queue(self.cpu).lock_irq().contains_key(&self.key)
+    }
+
+    /// Cancels the timer. Returns whether the timer was still pending.
+    pub fn cancel(&self) -> bool {
+        queue(self.cpu).lock_irq().remove(&self.key).is_some()
+    }
+}
+
+impl Drop for HrTimer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Returns the timer queues that are serviced by the current CPU.
+fn serviced_queues(
+    queues: &'static PerCpu<Mutex<BTreeMap<TimerKey, Callback>>>,
+) -> impl Iterator<Item = &'static Mutex<BTreeMap<TimerKey, Callback>>> {
+    let this_cpu = arch::tls::get_cpuid();
+
+    queues
+        .iter()
+        .enumerate()
+        .filter(move |(cpu, _)| {
+            *cpu == this_cpu || (this_cpu == 0 && !super::hotplug::is_online(*cpu))
+        })
+        .map(|(_, timers)| timers)
+}
+
+/// Returns the expiry time of the earliest pending timer serviced by the current CPU.
+pub(super) fn next_expiry() -> Option<u64> {
+    serviced_queues(QUEUES.get()?)
+        .filter_map(|timers| {
+            timers
+                .lock_irq()
+                .first_key_value()
+                .map(|(key, _)| key.expires)
+        })
+        .min()
+}
+
+/// Runs the callbacks of the expired timers serviced by the current CPU.
+pub(super) fn run_expired() {
+    let Some(queues) = QUEUES.get() else {
+        return;
+    };
+
+    for timers in serviced_queues(queues) {
+        run_expired_in(timers);
+    }
+}
+
+fn run_expired_in(timers: &Mutex<BTreeMap<TimerKey, Callback>>) {
+    loop {
+        let time = now();
+
+        // NOTE: The lock must not be held while running the callback, as it might start or
+        // cancel timers.
+        let callback = {
+            let mut timers = timers.lock_irq();
+
+            match timers.first_entry() {
+                Some(entry) if entry.key().expires <= time => entry.remove(),
+                _ => return,
+            }
+        };
+
+        callback();
+    }
+}
+
+/// Starts a timer that wakes up the current task once the monotonic clock reaches `expires`.
+/// Used to implement timeouts for blocking operations.
+pub fn start_timeout(expires: u64) -> HrTimer {
+    let task = super::get_scheduler().current_task();
+
+    HrTimer::start(expires, move || {
+        super::get_scheduler().inner.wake_up(task);
+    })
+}
+
+/// Puts the current task to sleep until the monotonic clock reaches `expires`.
+pub fn sleep_until(expires: u64) -> SignalResult<()> {
+    let timer = start_timeout(expires);
+
+    while timer.is_pending() {
+        super::get_scheduler().inner.await_io()?;
+    }
+
+    Ok(())
+}
+
+/// Puts the current task to sleep for `duration` nanoseconds.
+pub fn sleep(duration: u64) -> SignalResult<()> {
+    sleep_until(now().saturating_add(duration))
+}
+
+pub(super) fn init() {
+    QUEUES.call_once(|| PerCpu::new(|| Mutex::new(BTreeMap::new())));
+}
@@ -0,0 +1,231 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Idle Loop
+//!
+//! When there is nothing to run, a CPU runs its idle loop, which puts the CPU into an idle state
+//! (see [`crate::arch::cpuidle`]) until the next interrupt. Deeper states save more power but take
+//! longer to wake up from, so they are only worth entering if the CPU is going to stay idle
+//! long enough. The state is selected by the menu governor, which predicts the idle duration from
+//! the time until the next timer event, corrected by how long the CPU actually stayed idle in the
+//! past and by the recent idle intervals if they follow a repeating pattern (e.g. a periodic
+//! wakeup from a device).
+
+use spin::Once;
+
+use crate::arch::{cpuidle, interrupts};
+use crate::utils::sync::Mutex;
+use crate::utils::PerCpu;
+
+use super::{hrtimer, preempt};
+
+/// Number of the recent idle intervals used to detect a repeating pattern.
+const INTERVALS: usize = 8;
+/// Number of buckets of the correction factors, by the order of magnitude of the time until the
+/// next timer event (starting from 10us).
+const BUCKETS: usize = 6;
+
+/// Scale of the correction factors.
+const RESOLUTION: u64 = 1024;
+/// Weight of a new sample in the correction factors is `1 / DECAY`.
+const DECAY: u64 = 8;
+
+/// Longer idle intervals are not taken into account, as the prediction would be dominated by
+/// them (in nanoseconds).
+const MAX_INTERESTING: u64 = 50000000;
+
+fn which_bucket(duration: u64) -> usize {
+    let mut bucket = 0;
+    let mut limit = 10000;
+
+    while bucket < BUCKETS - 1 && duration >= limit {
+        bucket += 1;
+        limit *= 10;
+    }
+
+    bucket
+}
+
+/// The menu governor.
+struct Menu {
+    /// Ratio of the measured idle time to the time until the next timer event, scaled by
+    /// `RESOLUTION * DECAY`.
+    correction: [u64; BUCKETS],
+    intervals: [u64; INTERVALS],
+    /// Number of the recorded idle intervals.
+    recorded: usize,
+
+    bucket: usize,
+    next_timer: u64,
+}
+
+impl Menu {
+    const fn new() -> Self {
+        Self {
+            correction: [RESOLUTION * DECAY; BUCKETS],
+            intervals: [0; INTERVALS],
+            recorded: 0,
+
+            bucket: 0,
+            next_timer: 0,
+        }
+    }
+
+    /// Returns the average of the recent idle intervals, if they are close to each other.
+    fn typical_interval(&self) -> Option<u64> {
+        if self.recorded < INTERVALS {
+            return None;
+        }
+
+        let avg = self.intervals.iter().sum::<u64>() / INTERVALS as u64;
+        let variance = self
+            .intervals
+            .iter()
+            .map(|interval| interval.abs_diff(avg).pow(2))
+            .sum::<u64>()
+            / INTERVALS as u64;
+
+        // The intervals are repeating if the standard deviation is either small (at most 20us)
+        // or small compared to the average (at most 1/6 of it).
+        if variance <= 20000u64.pow(2) || avg * avg > 36 * variance {
+            Some(avg)
+        } else {
+            None
+        }
+    }
+
+    /// Predicts how long the CPU is going to stay idle (in nanoseconds), given the time until
+    /// the next timer event.
+    fn predict(&mut self, next_timer: u64) -> u64 {
+        self.next_timer = next_timer;
+        self.bucket = which_bucket(next_timer);
+
+        let predicted = (next_timer as u128 * self.correction[self.bucket] as u128
+            / (RESOLUTION * DECAY) as u128) as u64;
+
+        match self.typical_interval() {
+            Some(typical) => predicted.min(typical),
+            None => predicted,
+        }
+    }
+
+    /// Records that the CPU stayed idle for `measured` nanoseconds after the last prediction.
+    fn reflect(&mut self, measured: u64) {
+        let measured = measured.min(self.next_timer);
+
+        let mut factor = self.correction[self.bucket];
+        factor -= factor / DECAY;
+
+        if self.next_timer > 0 && measured < MAX_INTERESTING {
+            factor += RESOLUTION * measured / self.next_timer;
+        } else {
+            factor += RESOLUTION;
+        }
+
+        // The factor must not drop to zero, so it can still grow again.
+        self.correction[self.bucket] = factor.max(1);
+
+        self.intervals[self.recorded % INTERVALS] = measured.min(MAX_INTERESTING);
+        self.recorded = self.recorded.saturating_add(1);
+    }
+}
+
+static GOVERNORS: Once<PerCpu<Mutex<Menu>>> = Once::new();
+
+pub(super) fn init() {
+    GOVERNORS.call_once(|| PerCpu::new(|| Mutex::new(Menu::new())));
+}
+
+/// Runs the idle loop of the current CPU. Interrupts must be enabled.
+pub fn run() -> ! {
+    let states = cpuidle::states();
+    let governor = GOVERNORS
+        .get()
+        .expect("idle: attempted to run the idle loop before initialization")
+        .get();
+
+    loop {
+        // Preemption is disabled, so the interrupt that wakes up the CPU does not switch to
+        // another task before the idle time is measured. The reschedule is done once
+        // preemption is enabled again.
+        preempt::disable();
+
+        unsafe { interrupts::disable_interrupts() }
+
+        let mut menu = governor.lock();
+        let now = hrtimer::now();
+        let predicted = menu.predict(super::next_timer_event().saturating_sub(now));
+
+        let state = states
+            .iter()
+            .rev()
+            .find(|state| state.target_residency <= predicted)
+            .unwrap_or(&states[0]);
+
+        cpuidle::enter(state);
+
+        let measured = hrtimer::now().saturating_sub(now);
+        menu.reflect(measured.saturating_sub(state.exit_latency));
+
+        core::mem::drop(menu);
+
+        unsafe { interrupts::enable_interrupts() }
+        preempt::enable();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unity_prediction() {
+        let mut menu = Menu::new();
+
+        // Without any history, the CPU is expected to stay idle until the next timer event.
+        assert_eq!(menu.predict(5000000), 5000000);
+    }
+
+    #[test]
+    fn repeating_interval() {
+        let mut menu = Menu::new();
+
+        for _ in 0..INTERVALS {
+            menu.predict(5000000);
+            menu.reflect(100000);
+        }
+
+        assert_eq!(menu.predict(5000000), 100000);
+    }
+
+    #[test]
+    fn irregular_intervals() {
+        let mut menu = Menu::new();
+
+        for i in 0..INTERVALS * 4 {
+            menu.predict(4000000);
+            menu.reflect(if i % 2 == 0 { 10000 } else { 4000000 });
+        }
+
+        // The intervals are too irregular to be used, but the correction factor has learned
+        // that the CPU is usually woken up before the timer fires.
+        assert!(menu.typical_interval().is_none());
+
+        let predicted = menu.predict(4000000);
+        assert!(predicted > 10000 && predicted < 4000000);
+    }
+}
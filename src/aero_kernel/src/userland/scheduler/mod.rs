@@ -15,19 +15,26 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-#[cfg(feature = "round-robin")]
-pub mod round_robin;
+#[cfg(feature = "cfs")]
+pub mod cfs;
+pub mod hotplug;
+pub mod hrtimer;
+pub mod idle;
+pub mod preempt;
+pub mod stats;
 
 use alloc::sync::Arc;
 
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use crate::arch::interrupts::{self, InterruptStack};
 use crate::fs::cache::DirCacheItem;
 use crate::syscall::ExecArgs;
-use crate::utils::sync::Mutex;
+use crate::utils::sync::{IrqGuard, Mutex};
 
 use spin::Once;
 
-use self::round_robin::RoundRobin;
+use self::cfs::Cfs;
 use super::signals::SignalResult;
 use super::task::sessions::SESSIONS;
 use super::task::{Task, TaskId};
@@ -39,6 +46,14 @@ pub trait SchedulerInterface: Send + Sync {
     /// Register the provided task into the task scheduler queue.
     fn register_task(&self, task: Arc<Task>);
 
+    /// Register the provided task into the task scheduler queue of the CPU with the
+    /// provided logical ID.
+    fn register_task_on(&self, cpu: usize, task: Arc<Task>);
+
+    /// Makes the CPU with the provided logical ID move all of its tasks to the other CPUs and
+    /// park itself, the next time it reschedules. See [`hotplug::offline`].
+    fn park_cpu(&self, cpu: usize);
+
     fn current_task(&self) -> Arc<Task> {
         self.current_task_optional()
             .expect("current_task: current task not found")
@@ -55,6 +70,10 @@ pub trait SchedulerInterface: Send + Sync {
     /// Yields execution to another task.
     fn preempt(&self);
 
+    /// Makes the current CPU reschedule as soon as possible. Unlike [`Self::preempt`], this can
+    /// be called with interrupts disabled or locks held.
+    fn resched(&self);
+
     /// Exits the current task.
     fn exit(&self, status: ExitStatus) -> !;
 }
@@ -71,7 +90,7 @@ impl TaskContainer {
     }
 
     fn remove_task(&self, task: &Task) {
-        self.0.lock().remove(&task.pid());
+        self.0.lock().remove(&task.tid());
     }
 }
 
@@ -96,18 +115,34 @@ impl Scheduler {
         Self {
             tasks: TaskContainer::new(),
 
-            #[cfg(feature = "round-robin")]
-            inner: RoundRobin::new(),
+            #[cfg(feature = "cfs")]
+            inner: Cfs::new(),
         }
     }
 
     /// Registers the provided task in the schedulers queue.
     pub fn register_task(&self, task: Arc<Task>) {
-        self.tasks.register_task(task.pid(), task.clone());
-        SESSIONS.register_task(task.clone());
+        self.tasks.register_task(task.tid(), task.clone());
+        if task.is_process_leader() {
+            SESSIONS.register_task(task.clone());
+        }
+        task.cgroup().add_task(task.clone());
+        stats::task_created();
         self.inner.register_task(task);
     }
 
+    /// Registers the provided task in the schedulers queue of the CPU with the provided
+    /// logical ID.
+    pub fn register_task_on(&self, cpu: usize, task: Arc<Task>) {
+        self.tasks.register_task(task.tid(), task.clone());
+        if task.is_process_leader() {
+            SESSIONS.register_task(task.clone());
+        }
+        task.cgroup().add_task(task.clone());
+        stats::task_created();
+        self.inner.register_task_on(cpu, task);
+    }
+
     #[inline]
     pub fn exec(&self, executable: &DirCacheItem, argv: Option<ExecArgs>, envv: Option<ExecArgs>) {
         self.inner
@@ -124,16 +159,53 @@ impl Scheduler {
 
     pub fn exit(&self, status: ExitStatus) -> ! {
         let current_task = self.inner.current_task();
-        SESSIONS.remove_task(&current_task);
+
+        // A thread that is killed because its process is exiting takes the exit status of the
+        // process.
+        let status = current_task.group_exit_status().unwrap_or(status);
+
+        crate::tracepoint!(
+            SCHED_PROCESS_EXIT,
+            "pid={} status={:?}",
+            current_task.tid().as_usize(),
+            status
+        );
+
+        if current_task.is_process_leader() {
+            SESSIONS.remove_task(&current_task);
+            current_task.pid_ns().detach(&current_task);
+        }
+
+        current_task.cgroup().remove_task(&current_task);
         self.tasks.remove_task(&current_task);
         self.inner.exit(status)
     }
 
+    /// Exits all of the threads of the current process. The other threads are killed and the
+    /// process is reported to have exited with `status`.
+    pub fn exit_group(&self, status: ExitStatus) -> ! {
+        let current_task = self.inner.current_task();
+
+        if current_task.start_group_exit(status.clone()) {
+            for thread in current_task.threads() {
+                if thread.tid() != current_task.tid() {
+                    thread.kill_thread();
+                }
+            }
+        }
+
+        self.exit(status)
+    }
+
     pub fn log_ptable(&self) {
         self.tasks.0.lock().iter().for_each(|(pid, task)| {
             let path: String = task
                 .path()
                 .map(|path| path.into())
+                .or_else(|| {
+                    task.kthread()
+                        .map(|kthread| alloc::format!("[{}]", kthread.name()))
+                })
                 .unwrap_or("<unknown>".into());
 
             log::info!(
@@ -175,26 +247,86 @@ pub fn is_initialized() -> bool {
 static SCHEDULER_VECTOR: Once<u8> = Once::new();
 const SCHEDULER_TIMER_US: usize = 5000;
 
+/// Time (on the monotonic clock) at which the current time slice of the CPU ends.
+#[cpu_local]
+static SLICE_END: AtomicU64 = AtomicU64::new(0);
+
 fn scheduler_irq_handler(_stack: &mut InterruptStack) {
+    // NOTE: The timer is re-armed by the scheduler with the time slice of the next task.
     #[cfg(target_arch = "x86_64")]
-    {
-        crate::arch::apic::get_local_apic()
-            .timer_oneshot(*SCHEDULER_VECTOR.get().unwrap(), SCHEDULER_TIMER_US);
+    crate::arch::interrupts::INTERRUPT_CONTROLLER.eoi();
+
+    hrtimer::run_expired();
 
-        crate::arch::interrupts::INTERRUPT_CONTROLLER.eoi();
+    // The timer fired for a high-resolution timer before the end of the time slice.
+    if timer_remaining() != 0 {
+        program_timer();
+        return;
     }
 
-    self::get_scheduler().inner.preempt();
+    if preempt::timer_can_preempt() {
+        self::get_scheduler().inner.preempt();
+    }
+}
+
+/// Starts a new time slice of `us` microseconds on the current CPU.
+fn arm_timer(us: usize) {
+    SLICE_END.store(hrtimer::now() + us as u64 * 1000, Ordering::SeqCst);
+
+    program_timer();
+}
+
+/// Returns the amount of microseconds left until the current time slice of the CPU ends.
+fn timer_remaining() -> usize {
+    let remaining = SLICE_END
+        .load(Ordering::SeqCst)
+        .saturating_sub(hrtimer::now());
+    remaining.div_ceil(1000) as usize
+}
+
+/// Returns the time at which the scheduler timer of the current CPU fires next: at the end of
+/// the current time slice or when the earliest high-resolution timer expires, whichever comes
+/// first.
+fn next_timer_event() -> u64 {
+    let expires = SLICE_END.load(Ordering::SeqCst);
+    hrtimer::next_expiry().map_or(expires, |next| expires.min(next))
+}
+
+/// Arms the scheduler timer of the current CPU to fire at the next timer event (see
+/// [`next_timer_event`]).
+fn program_timer() {
+    let _guard = IrqGuard::new();
+    let expires = next_timer_event();
+
+    // The timer must fire at least a microsecond from now, even if the deadline has passed.
+    let us = core::cmp::max(expires.saturating_sub(hrtimer::now()).div_ceil(1000), 1);
+
+    #[cfg(target_arch = "x86_64")]
+    crate::arch::apic::get_local_apic()
+        .timer_oneshot(*SCHEDULER_VECTOR.get().unwrap(), us as usize);
 }
 
 /// Initialize the scheduler and set up the scheduler interrupt.
 pub fn init() {
+    hrtimer::init();
+    hotplug::init();
+    idle::init();
     SCHEDULER.call_once(Scheduler::new).inner.init();
+    stats::init();
 
     let scheduler_vector = interrupts::allocate_vector();
     interrupts::register_handler(scheduler_vector, scheduler_irq_handler);
 
-    #[cfg(target_arch = "x86_64")]
-    crate::arch::apic::get_local_apic().timer_oneshot(scheduler_vector, SCHEDULER_TIMER_US);
     SCHEDULER_VECTOR.call_once(|| scheduler_vector);
+    arm_timer(SCHEDULER_TIMER_US);
+}
+
+/// Starts the scheduler timer on an application processor.
+pub fn init_ap() {
+    assert!(
+        SCHEDULER_VECTOR.get().is_some(),
+        "scheduler: attempted to initialize an AP before the BSP"
+    );
+
+    arm_timer(SCHEDULER_TIMER_US);
 }
@@ -0,0 +1,162 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel Preemption
+//!
+//! The kernel can be preempted at any point, except in critical sections (for example, while a
+//! spinlock is held) and in nested interrupt handlers. The nesting of both is tracked per CPU. If
+//! the scheduler timer fires while the CPU cannot be preempted, the reschedule is deferred until
+//! the outermost critical section (or interrupt handler) is left.
+//!
+//! Until the current CPU has set up its CPU-local data, the counts are not tracked. The check
+//! is done per CPU, so a critical section is always either fully tracked or not at all.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::arch::{cpu_local, interrupts};
+
+/// Nesting level of the critical sections that disable preemption.
+#[cpu_local]
+static PREEMPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Nesting level of the interrupt handlers.
+#[cpu_local]
+static IRQ_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Set if the scheduler timer fired while the CPU could not be preempted.
+#[cpu_local]
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+/// Preemption state of a task, which is saved and restored across context switches as the task
+/// might be resumed on another CPU.
+pub(super) struct PreemptState {
+    count: usize,
+    irq_count: usize,
+    #[cfg(feature = "lockdep")]
+    held_locks: crate::utils::lockdep::HeldLocks,
+}
+
+/// Disables kernel preemption on the current CPU until the matching call to [`enable`].
+pub fn disable() {
+    if !cpu_local::is_initialized() {
+        return;
+    }
+
+    PREEMPT_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Re-enables kernel preemption on the current CPU. If the scheduler timer fired in the
+/// meantime, the current task is preempted once the outermost critical section is left.
+pub fn enable() {
+    if !cpu_local::is_initialized() {
+        return;
+    }
+
+    let count = PREEMPT_COUNT.fetch_sub(1, Ordering::SeqCst);
+    debug_assert_ne!(count, 0, "preempt::enable: unbalanced call");
+
+    if count == 1 && IRQ_COUNT.load(Ordering::SeqCst) == 0 && take_need_resched() {
+        resched();
+    }
+}
+
+/// Marks the entry into an interrupt handler.
+pub fn irq_enter() {
+    if cpu_local::is_initialized() {
+        IRQ_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Marks the exit from an interrupt handler.
+pub fn irq_exit() {
+    if !cpu_local::is_initialized() {
+        return;
+    }
+
+    let count = IRQ_COUNT.fetch_sub(1, Ordering::SeqCst);
+
+    if count == 1 && PREEMPT_COUNT.load(Ordering::SeqCst) == 0 && take_need_resched() {
+        resched();
+    }
+}
+
+/// Returns whether the scheduler timer interrupt, which is currently being handled, is allowed
+/// to preempt the current task. Otherwise, the reschedule is deferred.
+pub(super) fn timer_can_preempt() -> bool {
+    if !cpu_local::is_initialized() {
+        return true;
+    }
+
+    if PREEMPT_COUNT.load(Ordering::SeqCst) == 0 && IRQ_COUNT.load(Ordering::SeqCst) <= 1 {
+        return true;
+    }
+
+    NEED_RESCHED.store(true, Ordering::SeqCst);
+    false
+}
+
+/// Saves and resets the preemption state of the current CPU before switching to another task.
+pub(super) fn save() -> PreemptState {
+    if !cpu_local::is_initialized() {
+        return PreemptState {
+            count: 0,
+            irq_count: 0,
+            #[cfg(feature = "lockdep")]
+            held_locks: crate::utils::lockdep::save(),
+        };
+    }
+
+    // The CPU is about to reschedule, so any deferred reschedule is done.
+    NEED_RESCHED.store(false, Ordering::SeqCst);
+
+    PreemptState {
+        count: PREEMPT_COUNT.swap(0, Ordering::SeqCst),
+        irq_count: IRQ_COUNT.swap(0, Ordering::SeqCst),
+        #[cfg(feature = "lockdep")]
+        held_locks: crate::utils::lockdep::save(),
+    }
+}
+
+/// Restores the preemption state of the task that was switched back to.
+pub(super) fn restore(state: PreemptState) {
+    if !cpu_local::is_initialized() {
+        return;
+    }
+
+    PREEMPT_COUNT.store(state.count, Ordering::SeqCst);
+    IRQ_COUNT.store(state.irq_count, Ordering::SeqCst);
+
+    #[cfg(feature = "lockdep")]
+    crate::utils::lockdep::restore(state.held_locks);
+}
+
+fn take_need_resched() -> bool {
+    NEED_RESCHED.swap(false, Ordering::SeqCst)
+}
+
+/// Performs the deferred reschedule.
+fn resched() {
+    let scheduler = super::get_scheduler();
+
+    if interrupts::is_enabled() {
+        scheduler.inner.preempt();
+    } else {
+        // The task cannot be switched with interrupts disabled, so reschedule as soon as they
+        // are enabled again.
+        scheduler.inner.resched();
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Scheduler Statistics
+//!
+//! Kernel-wide counters reported by `/proc/stat`, `/proc/interrupts` and `/proc/loadavg`: the
+//! CPU time spent by each CPU, the number of interrupts delivered to each CPU, the number of
+//! context switches and of tasks created since boot, and the load average.
+//!
+//! The load average is the exponentially-decaying average of the number of runnable tasks over
+//! the last 1, 5 and 15 minutes. It is sampled every 5 seconds and kept in fixed-point, the same
+//! way as on Linux.
+//!
+//! ## Notes
+//! * <https://man7.org/linux/man-pages/man5/proc_stat.5.html>
+//! * <https://man7.org/linux/man-pages/man5/proc_loadavg.5.html>
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use spin::Once;
+
+use crate::userland::kthread;
+use crate::userland::task::TaskState;
+use crate::utils::PerCpu;
+
+/// CPU time (in nanoseconds) spent by a CPU in each of the modes.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CpuTimes {
+    /// Time spent running tasks in user mode.
+    pub user: u64,
+    /// Time spent running tasks with a positive nice value in user mode.
+    pub nice: u64,
+    /// Time spent running tasks in kernel mode (including kernel threads).
+    pub system: u64,
+    /// Time spent idle.
+    pub idle: u64,
+}
+
+impl CpuTimes {
+    fn add(&mut self, other: &CpuTimes) {
+        self.user += other.user;
+        self.nice += other.nice;
+        self.system += other.system;
+        self.idle += other.idle;
+    }
+}
+
+struct CpuStat {
+    user: AtomicU64,
+    nice: AtomicU64,
+    system: AtomicU64,
+    idle: AtomicU64,
+    /// Time (in nanoseconds since boot) at which the CPU went idle, or zero if it is running a
+    /// task.
+    idle_since: AtomicU64,
+    /// Number of times that each of the interrupt vectors was delivered to the CPU.
+    irqs: [AtomicU64; 256],
+}
+
+impl CpuStat {
+    fn new() -> Self {
+        Self {
+            user: AtomicU64::new(0),
+            nice: AtomicU64::new(0),
+            system: AtomicU64::new(0),
+            idle: AtomicU64::new(0),
+            idle_since: AtomicU64::new(0),
+            irqs: core::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn times(&self) -> CpuTimes {
+        let mut idle = self.idle.load(Ordering::Relaxed);
+        let since = self.idle_since.load(Ordering::Relaxed);
+
+        // Include the time that the CPU has been idle for so far.
+        if since != 0 {
+            idle += crate::arch::time::get_uptime_ns().saturating_sub(since);
+        }
+
+        CpuTimes {
+            user: self.user.load(Ordering::Relaxed),
+            nice: self.nice.load(Ordering::Relaxed),
+            system: self.system.load(Ordering::Relaxed),
+            idle,
+        }
+    }
+}
+
+static CPU_STATS: Once<PerCpu<CpuStat>> = Once::new();
+
+static CONTEXT_SWITCHES: AtomicU64 = AtomicU64::new(0);
+static TASKS_CREATED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of fractional bits of the load average.
+const FSHIFT: usize = 11;
+const FIXED_1: usize = 1 << FSHIFT;
+
+/// Decay factors of the 1, 5 and 15 minute load averages, for a sample every 5 seconds
+/// (`FIXED_1 / exp(5s / 1min)` and so on).
+const EXP: [usize; 3] = [1884, 2014, 2037];
+
+/// Interval (in seconds) at which the load average is sampled.
+const LOAD_FREQ: usize = 5;
+
+static LOAD_AVG: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+fn cpu_stat() -> Option<&'static CpuStat> {
+    CPU_STATS.get().map(|stats| stats.get())
+}
+
+/// Charges the current CPU for `ns` nanoseconds that it spent running a task.
+pub fn account(ns: u64, system: bool, nice: bool) {
+    let Some(stat) = cpu_stat() else {
+        return;
+    };
+
+    let counter = match (system, nice) {
+        (true, _) => &stat.system,
+        (false, true) => &stat.nice,
+        (false, false) => &stat.user,
+    };
+
+    counter.fetch_add(ns, Ordering::Relaxed);
+}
+
+/// Called by the scheduler when the current CPU switches to its idle task at `now`.
+pub fn idle_enter(now: u64) {
+    if let Some(stat) = cpu_stat() {
+        stat.idle_since.store(now, Ordering::Relaxed);
+    }
+}
+
+/// Called by the scheduler when the current CPU leaves its idle task at `now`.
+pub fn idle_exit(now: u64) {
+    if let Some(stat) = cpu_stat() {
+        let since = stat.idle_since.swap(0, Ordering::Relaxed);
+
+        if since != 0 {
+            stat.idle
+                .fetch_add(now.saturating_sub(since), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Called by the interrupt handler when the interrupt `vector` is delivered to the current CPU.
+pub fn interrupt(vector: usize) {
+    if let Some(stat) = cpu_stat() {
+        stat.irqs[vector].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Called by the scheduler when a CPU switches from one task to another.
+pub fn context_switch() {
+    CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called when a task is registered in the scheduler.
+pub fn task_created() {
+    TASKS_CREATED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the CPU time spent by each of the CPUs, ordered by their logical ID.
+pub fn cpu_times() -> impl Iterator<Item = CpuTimes> {
+    CPU_STATS
+        .get()
+        .into_iter()
+        .flat_map(|stats| stats.iter())
+        .map(CpuStat::times)
+}
+
+/// Returns the CPU time spent by all of the CPUs.
+pub fn total_cpu_times() -> CpuTimes {
+    cpu_times().fold(CpuTimes::default(), |mut total, times| {
+        total.add(&times);
+        total
+    })
+}
+
+/// Returns the number of times that the interrupt `vector` was delivered to each of the CPUs,
+/// ordered by their logical ID.
+pub fn irq_counts(vector: u8) -> impl Iterator<Item = u64> {
+    CPU_STATS
+        .get()
+        .into_iter()
+        .flat_map(|stats| stats.iter())
+        .map(move |stat| stat.irqs[vector as usize].load(Ordering::Relaxed))
+}
+
+/// Returns the number of context switches since boot.
+pub fn context_switches() -> u64 {
+    CONTEXT_SWITCHES.load(Ordering::Relaxed)
+}
+
+/// Returns the number of tasks created since boot.
+pub fn tasks_created() -> u64 {
+    TASKS_CREATED.load(Ordering::Relaxed)
+}
+
+/// Returns the number of runnable (including running) tasks and the total number of tasks.
+pub fn nr_tasks() -> (usize, usize) {
+    let mut nr_running = 0;
+    let mut nr_tasks = 0;
+
+    super::get_scheduler().for_each_task(|task| {
+        if task.state() == TaskState::Runnable {
+            nr_running += 1;
+        }
+
+        nr_tasks += 1;
+    });
+
+    (nr_running, nr_tasks)
+}
+
+/// The 1, 5 and 15 minute load averages.
+#[derive(Debug, Copy, Clone)]
+pub struct LoadAverage(pub [usize; 3]);
+
+impl fmt::Display for LoadAverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, load) in self.0.iter().enumerate() {
+            let int = load >> FSHIFT;
+            let frac = ((load & (FIXED_1 - 1)) * 100) >> FSHIFT;
+
+            if i != 0 {
+                write!(f, " ")?;
+            }
+
+            write!(f, "{int}.{frac:02}")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn load_average() -> LoadAverage {
+    LoadAverage(LOAD_AVG.each_ref().map(|load| load.load(Ordering::Relaxed)))
+}
+
+/// Decays the provided load average towards the number of `active` tasks (in fixed-point).
+fn calc_load(load: usize, exp: usize, active: usize) -> usize {
+    let mut new_load = load * exp + active * (FIXED_1 - exp);
+
+    // Round up while the load is increasing, so that it reaches the number of active tasks.
+    if active >= load {
+        new_load += FIXED_1 - 1;
+    }
+
+    new_load / FIXED_1
+}
+
+fn loadavg_thread() {
+    loop {
+        let _ = super::get_scheduler().inner.sleep(Some(LOAD_FREQ));
+
+        // Do not count this thread.
+        let nr_running = nr_tasks().0.saturating_sub(1);
+
+        for (load, exp) in LOAD_AVG.iter().zip(EXP) {
+            let value = calc_load(load.load(Ordering::Relaxed), exp, nr_running * FIXED_1);
+            load.store(value, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn init() {
+    CPU_STATS.call_once(|| PerCpu::new(CpuStat::new));
+    kthread::spawn("loadavg", loadavg_thread);
+}
@@ -0,0 +1,443 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Seccomp restricts the system calls that a process can make, either to a fixed set (strict
+//! mode) or through classic BPF programs that are run on every system call (filter mode).
+//!
+//! The mode is inherited by the children of the process and is kept across `exec`. Filters can
+//! only be added, never removed: all of the attached filters are run and the action with the
+//! highest precedence is taken.
+
+use aero_syscall::prelude::*;
+use aero_syscall::seccomp::*;
+use aero_syscall::signal::{SigInfo, SIGKILL, SIGSYS};
+use aero_syscall::syscall_result_as_usize;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::scheduler::{self, ExitStatus};
+
+/// Maximum total number of instructions in the filters attached to a process.
+const MAX_INSNS_PER_PATH: usize = 32768;
+
+/// Length of the [`SeccompData`] passed to the filters.
+const DATA_LEN: u32 = core::mem::size_of::<SeccompData>() as u32;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = AUDIT_ARCH_X86_64;
+
+/// System calls that are allowed in strict mode.
+const STRICT_SYSCALLS: &[usize] = &[SYS_READ, SYS_WRITE, SYS_EXIT, SYS_SIGRETURN];
+
+/// Checks that `program` is a valid seccomp filter: it only uses the instructions that are
+/// allowed in filters, only loads from within the [`SeccompData`], does not jump out of the
+/// program and always ends with a return.
+fn validate(program: &[SockFilter]) -> Result<(), SyscallError> {
+    if program.is_empty() || program.len() > BPF_MAXINSNS {
+        return Err(SyscallError::EINVAL);
+    }
+
+    for (pc, insn) in program.iter().enumerate() {
+        let code = insn.code;
+        let k = insn.k;
+
+        // Number of instructions after this one.
+        let remaining = program.len() - pc - 1;
+
+        let valid = match bpf_class(code) {
+            BPF_LD | BPF_LDX => match (bpf_size(code), bpf_mode(code)) {
+                (BPF_W, BPF_ABS) => bpf_class(code) == BPF_LD && k < DATA_LEN && k % 4 == 0,
+                (BPF_W, BPF_IMM | BPF_LEN) => true,
+                (BPF_W, BPF_MEM) => (k as usize) < BPF_MEMWORDS,
+                _ => false,
+            },
+
+            BPF_ST | BPF_STX => code & !0x07 == 0 && (k as usize) < BPF_MEMWORDS,
+
+            BPF_ALU => match bpf_op(code) {
+                BPF_NEG => bpf_src(code) == BPF_K,
+                BPF_DIV | BPF_MOD => !(bpf_src(code) == BPF_K && k == 0),
+                BPF_ADD | BPF_SUB | BPF_MUL | BPF_OR | BPF_AND | BPF_LSH | BPF_RSH | BPF_XOR => {
+                    true
+                }
+                _ => false,
+            },
+
+            BPF_JMP => match bpf_op(code) {
+                BPF_JA => bpf_src(code) == BPF_K && (k as usize) < remaining,
+                BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                    (insn.jt as usize) < remaining && (insn.jf as usize) < remaining
+                }
+                _ => false,
+            },
+
+            BPF_RET => matches!(bpf_rval(code), BPF_K | BPF_A) && code & !0x1f == 0,
+            BPF_MISC => matches!(bpf_miscop(code), BPF_TAX | BPF_TXA),
+
+            _ => false,
+        };
+
+        if !valid || code & !0xff != 0 {
+            return Err(SyscallError::EINVAL);
+        }
+    }
+
+    // UNWRAP: The program is not empty.
+    if bpf_class(program.last().unwrap().code) != BPF_RET {
+        return Err(SyscallError::EINVAL);
+    }
+
+    Ok(())
+}
+
+/// Runs the (validated) BPF `program` on `data` and returns its result.
+fn run(program: &[SockFilter], data: &SeccompData) -> u32 {
+    // The data is loaded in 32-bit words, in native byte order.
+    let mut words = [0u32; DATA_LEN as usize / 4];
+
+    words[0] = data.nr as u32;
+    words[1] = data.arch;
+    words[2] = data.instruction_pointer as u32;
+    words[3] = (data.instruction_pointer >> 32) as u32;
+
+    for (i, arg) in data.args.iter().enumerate() {
+        words[4 + i * 2] = *arg as u32;
+        words[5 + i * 2] = (*arg >> 32) as u32;
+    }
+
+    let mut a = 0u32;
+    let mut x = 0u32;
+    let mut mem = [0u32; BPF_MEMWORDS];
+    let mut pc = 0;
+
+    loop {
+        let insn = program[pc];
+        let code = insn.code;
+        let k = insn.k;
+
+        pc += 1;
+
+        let load = || match bpf_mode(code) {
+            BPF_ABS => words[k as usize / 4],
+            BPF_MEM => mem[k as usize],
+            BPF_LEN => DATA_LEN,
+            _ => k,
+        };
+
+        let operand = if bpf_src(code) == BPF_X { x } else { k };
+
+        match bpf_class(code) {
+            BPF_LD => a = load(),
+            BPF_LDX => x = load(),
+            BPF_ST => mem[k as usize] = a,
+            BPF_STX => mem[k as usize] = x,
+
+            BPF_ALU => {
+                a = match bpf_op(code) {
+                    BPF_ADD => a.wrapping_add(operand),
+                    BPF_SUB => a.wrapping_sub(operand),
+                    BPF_MUL => a.wrapping_mul(operand),
+                    // A division by zero terminates the program with a result of 0.
+                    BPF_DIV if operand == 0 => return 0,
+                    BPF_DIV => a / operand,
+                    BPF_MOD if operand == 0 => return 0,
+                    BPF_MOD => a % operand,
+                    BPF_OR => a | operand,
+                    BPF_AND => a & operand,
+                    BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                    BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                    BPF_NEG => a.wrapping_neg(),
+                    BPF_XOR => a ^ operand,
+                    _ => unreachable!(),
+                }
+            }
+
+            BPF_JMP => {
+                let offset = match bpf_op(code) {
+                    BPF_JA => k as usize,
+                    op => {
+                        let taken = match op {
+                            BPF_JEQ => a == operand,
+                            BPF_JGT => a > operand,
+                            BPF_JGE => a >= operand,
+                            BPF_JSET => a & operand != 0,
+                            _ => unreachable!(),
+                        };
+
+                        if taken {
+                            insn.jt as usize
+                        } else {
+                            insn.jf as usize
+                        }
+                    }
+                };
+
+                pc += offset;
+            }
+
+            BPF_RET if bpf_rval(code) == BPF_A => return a,
+            BPF_RET => return k,
+
+            BPF_MISC if bpf_miscop(code) == BPF_TAX => x = a,
+            BPF_MISC => a = x,
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Returns the precedence of the action of the filter result `ret`; the lower, the higher the
+/// precedence.
+fn precedence(ret: u32) -> i32 {
+    (ret & SECCOMP_RET_ACTION_FULL) as i32
+}
+
+/// Returns whether the provided filter action is supported.
+pub fn is_action_available(action: u32) -> bool {
+    matches!(
+        action,
+        SECCOMP_RET_KILL_PROCESS
+            | SECCOMP_RET_KILL_THREAD
+            | SECCOMP_RET_TRAP
+            | SECCOMP_RET_ERRNO
+            | SECCOMP_RET_LOG
+            | SECCOMP_RET_ALLOW
+    )
+}
+
+pub struct Filter {
+    program: Vec<SockFilter>,
+    /// The filter that was attached before this one.
+    prev: Option<Arc<Filter>>,
+}
+
+impl Filter {
+    /// Returns an iterator over this filter and the ones that were attached before it.
+    fn filters(&self) -> impl Iterator<Item = &Filter> {
+        core::iter::successors(Some(self), |filter| filter.prev.as_deref())
+    }
+
+    /// Runs all of the filters and returns the result with the highest precedence.
+    fn run(&self, data: &SeccompData) -> u32 {
+        self.filters()
+            .map(|filter| run(&filter.program, data))
+            .min_by_key(|ret| precedence(*ret))
+            // UNWRAP: There is always at least one filter.
+            .unwrap()
+    }
+}
+
+/// Seccomp mode of a process.
+#[derive(Clone, Default)]
+pub enum Seccomp {
+    #[default]
+    Disabled,
+    /// Only the system calls in [`STRICT_SYSCALLS`] are allowed; any other system call kills the
+    /// process.
+    Strict,
+    Filter(Arc<Filter>),
+}
+
+impl Seccomp {
+    pub fn set_strict(&mut self) -> Result<(), SyscallError> {
+        match self {
+            Self::Disabled => {
+                *self = Self::Strict;
+                Ok(())
+            }
+
+            _ => Err(SyscallError::EINVAL),
+        }
+    }
+
+    /// Attaches the filter `program` on top of the already attached filters.
+    pub fn attach(&mut self, program: &[SockFilter]) -> Result<(), SyscallError> {
+        validate(program)?;
+
+        let prev = match self {
+            Self::Disabled => None,
+            Self::Filter(filter) => Some(filter.clone()),
+            Self::Strict => return Err(SyscallError::EINVAL),
+        };
+
+        let total = prev.as_ref().map_or(0, |prev| {
+            prev.filters()
+                .map(|filter| filter.program.len())
+                .sum::<usize>()
+        });
+
+        if total + program.len() > MAX_INSNS_PER_PATH {
+            return Err(SyscallError::ENOMEM);
+        }
+
+        *self = Self::Filter(Arc::new(Filter {
+            program: program.to_vec(),
+            prev,
+        }));
+
+        Ok(())
+    }
+}
+
+/// Checks whether the current task is allowed to make the system call `nr` with the provided
+/// arguments from `ip`. Called on every system call entry. Returns the value to return from the
+/// system call if it must not be executed.
+///
+/// ## Notes
+/// * The system call fails with `ENOSYS` for [`SECCOMP_RET_TRACE`] and
+///   [`SECCOMP_RET_USER_NOTIF`], as there is no tracer or supervisor to hand it to.
+pub fn check(nr: usize, args: [usize; 6], ip: usize) -> Option<usize> {
+    let task = scheduler::current_thread();
+
+    let filter = match task.seccomp() {
+        Seccomp::Disabled => return None,
+        Seccomp::Strict if STRICT_SYSCALLS.contains(&nr) => return None,
+        Seccomp::Strict => {
+            log::warn!(
+                "seccomp: killed pid={} (syscall={nr})",
+                task.pid().as_usize()
+            );
+            scheduler::get_scheduler().exit(ExitStatus::Signal(SIGKILL))
+        }
+
+        Seccomp::Filter(filter) => filter,
+    };
+
+    let data = SeccompData {
+        nr: nr as i32,
+        arch: AUDIT_ARCH,
+        instruction_pointer: ip as u64,
+        args: args.map(|arg| arg as u64),
+    };
+
+    let ret = filter.run(&data);
+    let value = ret & SECCOMP_RET_DATA;
+
+    match ret & SECCOMP_RET_ACTION_FULL {
+        SECCOMP_RET_ALLOW => None,
+
+        SECCOMP_RET_LOG => {
+            log::info!("seccomp: pid={} syscall={nr}", task.pid().as_usize());
+            None
+        }
+
+        SECCOMP_RET_ERRNO => Some(-(value as isize) as usize),
+
+        SECCOMP_RET_TRAP => {
+            let mut info = SigInfo::new(SIGSYS, aero_syscall::seccomp::SYS_SECCOMP);
+
+            info.si_errno = value as i32;
+            info.set_addr(ip as u64);
+            info.set_syscall(nr, AUDIT_ARCH);
+
+            task.force_signal(info);
+            Some(syscall_result_as_usize(Err(SyscallError::ENOSYS)))
+        }
+
+        SECCOMP_RET_TRACE | SECCOMP_RET_USER_NOTIF => {
+            Some(syscall_result_as_usize(Err(SyscallError::ENOSYS)))
+        }
+
+        SECCOMP_RET_KILL_THREAD => {
+            log::warn!(
+                "seccomp: killed tid={} (syscall={nr})",
+                task.tid().as_usize()
+            );
+            scheduler::get_scheduler().exit(ExitStatus::Signal(SIGSYS))
+        }
+
+        // Unknown actions kill the process as well.
+        _ => {
+            log::warn!(
+                "seccomp: killed pid={} (syscall={nr})",
+                task.pid().as_usize()
+            );
+            scheduler::get_scheduler().exit_group(ExitStatus::Signal(SIGSYS))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALLOW: SockFilter = SockFilter::stmt(BPF_RET | BPF_K, SECCOMP_RET_ALLOW);
+
+    fn data(nr: usize) -> SeccompData {
+        SeccompData {
+            nr: nr as i32,
+            arch: AUDIT_ARCH,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn seccomp_filter() {
+        // Fails `SYS_OPEN` with `EPERM` and allows everything else.
+        let program = [
+            SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, 0),
+            SockFilter::jump(BPF_JMP | BPF_JEQ | BPF_K, SYS_OPEN as u32, 0, 1),
+            SockFilter::stmt(
+                BPF_RET | BPF_K,
+                SECCOMP_RET_ERRNO | SyscallError::EPERM as u32,
+            ),
+            ALLOW,
+        ];
+
+        assert_eq!(validate(&program), Ok(()));
+        assert_eq!(
+            run(&program, &data(SYS_OPEN)),
+            SECCOMP_RET_ERRNO | SyscallError::EPERM as u32
+        );
+        assert_eq!(run(&program, &data(SYS_READ)), SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn seccomp_validate() {
+        // Misaligned and out of bounds loads.
+        let misaligned = [SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, 2), ALLOW];
+        let out_of_bounds = [SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, DATA_LEN), ALLOW];
+        // Jump past the end of the program.
+        let jump = [SockFilter::stmt(BPF_JMP | BPF_JA, 1), ALLOW];
+        // Does not end with a return.
+        let no_return = [ALLOW, SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, 0)];
+
+        let programs: [&[SockFilter]; 5] = [&misaligned, &out_of_bounds, &jump, &no_return, &[]];
+
+        for program in programs {
+            assert_eq!(validate(program), Err(SyscallError::EINVAL));
+        }
+    }
+
+    #[test]
+    fn seccomp_precedence() {
+        let mut seccomp = Seccomp::Disabled;
+
+        seccomp.attach(&[ALLOW]).unwrap();
+        seccomp
+            .attach(&[SockFilter::stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS)])
+            .unwrap();
+        seccomp.attach(&[ALLOW]).unwrap();
+
+        let Seccomp::Filter(filter) = seccomp else {
+            unreachable!()
+        };
+
+        assert_eq!(filter.run(&data(SYS_READ)), SECCOMP_RET_KILL_PROCESS);
+    }
+}
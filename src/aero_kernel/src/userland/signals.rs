@@ -17,13 +17,12 @@
 
 use aero_syscall::signal::*;
 
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 
 use core::ops::{Index, IndexMut};
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use bit_field::BitField;
-
 use aero_syscall::SyscallError;
 
 use super::scheduler::{self, ExitStatus};
@@ -31,6 +30,8 @@ use crate::fs::FileSystemError;
 use crate::utils::sync::{Mutex, MutexGuard};
 
 mod default {
+    use aero_syscall::signal::*;
+
     use crate::userland::scheduler;
     use crate::userland::scheduler::ExitStatus;
 
@@ -40,55 +41,62 @@ mod default {
         Handle(fn(usize)),
     }
 
-    /// Some of the default actions for the signals.
-    static DEFAULT_ACTIONS: [Action; super::SIGNAL_COUNT] = [
-        Action::Ignore,                   // UNUSED
-        Action::Handle(terminate),        // SIGHUP
-        Action::Handle(terminate),        // SIGINT
-        Action::Handle(terminate),        // SIGQUIT
-        Action::Handle(terminate),        // SIGILL
-        Action::Ignore,                   // UNUSED
-        Action::Handle(terminate),        // SIGABRT
-        Action::Handle(terminate),        // SIGBUS
-        Action::Handle(terminate),        // SIGFPE
-        Action::Handle(terminate),        // SIGKILL
-        Action::Ignore,                   // UNUSED
-        Action::Handle(terminate),        // SIGSEGV
-        Action::Ignore,                   // UNUSED
-        Action::Handle(terminate),        // SIGPIPE
-        Action::Ignore,                   // SIGALRM
-        Action::Handle(terminate),        // SIGTERM
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // SIGCHLD
-        Action::Handle(terminate),        // SIGCONT
-        Action::Handle(stop),             // SIGSTOP
-        Action::Handle(stop),             // SIGTSTP
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Handle(terminate_thread), // UNUSED
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
-    ];
+    /// The default actions for the signals. Any signal that is not listed here, including all of
+    /// the real-time signals, terminates the process.
+    ///
+    /// ## Notes
+    /// * `SIGCONT` is ignored here, as the stopped process is continued when the signal is sent
+    ///   (see [`crate::userland::task::Task::signal`]).
+    static DEFAULT_ACTIONS: [Action; super::SIGNAL_COUNT] = {
+        let mut actions = [Action::Handle(terminate); super::SIGNAL_COUNT];
+
+        actions[0] = Action::Ignore; // UNUSED
+
+        actions[SIGQUIT] = Action::Handle(core_dump);
+        actions[SIGILL] = Action::Handle(core_dump);
+        actions[SIGTRAP] = Action::Handle(core_dump);
+        actions[SIGABRT] = Action::Handle(core_dump);
+        actions[SIGBUS] = Action::Handle(core_dump);
+        actions[SIGFPE] = Action::Handle(core_dump);
+        actions[SIGSEGV] = Action::Handle(core_dump);
+        actions[SIGXCPU] = Action::Handle(core_dump);
+        actions[SIGXFSZ] = Action::Handle(core_dump);
+        actions[SIGSYS] = Action::Handle(core_dump);
+
+        actions[SIGSTOP] = Action::Handle(stop);
+        actions[SIGTSTP] = Action::Handle(stop);
+        actions[SIGTTIN] = Action::Handle(stop);
+        actions[SIGTTOU] = Action::Handle(stop);
+
+        actions[SIGCHLD] = Action::Ignore;
+        actions[SIGCONT] = Action::Ignore;
+        actions[SIGURG] = Action::Ignore;
+        actions[SIGWINCH] = Action::Ignore;
+
+        actions
+    };
 
     fn terminate(signal: usize) {
-        scheduler::get_scheduler().exit(ExitStatus::Signal(signal));
+        scheduler::get_scheduler().exit_group(ExitStatus::Signal(signal));
     }
 
-    fn terminate_thread(_signal: usize) {
-        unimplemented!()
+    /// Core dumps are not supported, so the process is only terminated.
+    fn core_dump(signal: usize) {
+        terminate(signal)
     }
 
-    fn stop(_signal: usize) {
-        unimplemented!()
+    fn stop(signal: usize) {
+        use crate::userland::task::sessions::SESSIONS;
+
+        let task = scheduler::current_thread();
+
+        // The terminal stop signals are discarded for the processes in an orphaned process
+        // group, as nothing would ever continue them.
+        if signal != SIGSTOP && SESSIONS.is_orphaned(&task) {
+            return;
+        }
+
+        task.stop(signal);
     }
 
     /// Get the default action for the provided `signal`.
@@ -111,11 +119,26 @@ pub enum SignalError {
     Interrupted,
 }
 
-const IMMUTABLE_MASK: u64 = (1u64 << SIGSTOP) | (1u64 << SIGCONT) | (1u64 << SIGKILL);
+/// Returns the bit of the provided `signal` in a signal mask. The signal masks have the same
+/// layout as `sigset_t`, where signal `n` is represented by bit `n - 1`.
+const fn sigmask(signal: usize) -> u64 {
+    1u64 << (signal - 1)
+}
+
+const IMMUTABLE_MASK: u64 = sigmask(SIGSTOP) | sigmask(SIGKILL);
 
 /// Returns [`true`] if the provided `signal` is overridable.
 fn can_override(signal: usize) -> bool {
-    !IMMUTABLE_MASK.get_bit(signal)
+    IMMUTABLE_MASK & sigmask(signal) == 0
+}
+
+/// Returns [`true`] if the provided `signal` is discarded when its handler is `handler`.
+fn is_ignored(signal: usize, handler: SignalHandler) -> bool {
+    match handler {
+        SignalHandler::Ignore => true,
+        SignalHandler::Default => default::action(signal) == default::Action::Ignore,
+        SignalHandler::Handle(_) => false,
+    }
 }
 
 pub type SignalResult<T> = core::result::Result<T, SignalError>;
@@ -172,6 +195,15 @@ impl SignalEntry {
     pub fn flags(&self) -> SignalFlags {
         self.flags
     }
+
+    /// Returns the signals to be blocked while the handler of `signal` is executing.
+    pub fn handler_mask(&self, signal: usize) -> u64 {
+        if self.flags.contains(SignalFlags::SA_NODEFER) {
+            self.mask
+        } else {
+            self.mask | sigmask(signal)
+        }
+    }
 }
 
 impl SignalEntry {
@@ -185,19 +217,82 @@ impl SignalEntry {
     }
 }
 
-const SIGNAL_COUNT: usize = 35;
+/// Signals are numbered from 1 to [`SIGRTMAX`].
+const SIGNAL_COUNT: usize = SIGRTMAX + 1;
+
+/// Maximum number of signals that can be pending in a [`PendingSet`] at once.
+const QUEUE_LIMIT: usize = 128;
+
+/// Set of pending signals, along with the information they were sent with.
+#[derive(Default)]
+struct PendingSet {
+    mask: u64,
+    /// Pending signals, in the order in which they were sent.
+    queue: VecDeque<SigInfo>,
+}
+
+impl PendingSet {
+    /// Adds the signal described by `info` to the set. A standard signal is pending at most
+    /// once, while every occurrence of a real-time signal is queued. Returns `false` if the
+    /// queue is full.
+    fn add(&mut self, info: SigInfo) -> bool {
+        let signal = info.si_signo as usize;
+
+        if signal < SIGRTMIN && self.mask & sigmask(signal) != 0 {
+            return true;
+        }
+
+        if self.queue.len() >= QUEUE_LIMIT {
+            return false;
+        }
+
+        self.mask |= sigmask(signal);
+        self.queue.push_back(info);
+        true
+    }
+
+    /// Removes the oldest occurrence of `signal` from the set.
+    fn take(&mut self, signal: usize) -> Option<SigInfo> {
+        let index = self
+            .queue
+            .iter()
+            .position(|info| info.si_signo as usize == signal)?;
+
+        let info = self.queue.remove(index);
+
+        if !self
+            .queue
+            .iter()
+            .any(|info| info.si_signo as usize == signal)
+        {
+            self.mask &= !sigmask(signal);
+        }
+
+        info
+    }
+
+    /// Discards all of the occurrences of `signal` from the set.
+    fn remove(&mut self, signal: usize) {
+        self.queue.retain(|info| info.si_signo as usize != signal);
+        self.mask &= !sigmask(signal);
+    }
+
+    fn is_full(&self) -> bool {
+        self.queue.len() >= QUEUE_LIMIT
+    }
+}
 
-#[derive(Copy, Clone)]
 pub struct Entries {
     entries: [SignalEntry; SIGNAL_COUNT],
-    pending_mask: u64,
+    /// Signals pending for the process, which can be handled by any of its threads.
+    pending: PendingSet,
 }
 
 impl Default for Entries {
     fn default() -> Entries {
         Entries {
             entries: [SignalEntry::default(); SIGNAL_COUNT],
-            pending_mask: 0,
+            pending: PendingSet::default(),
         }
     }
 }
@@ -219,24 +314,47 @@ impl IndexMut<usize> for Entries {
 impl Entries {
     /// Returns the pending mask.
     pub fn pending(&self) -> u64 {
-        self.pending_mask
+        self.pending.mask
+    }
+}
+
+/// Alternate signal stack (see `sigaltstack(2)`).
+#[derive(Debug, Copy, Clone)]
+pub struct AltStack {
+    base: u64,
+    size: usize,
+}
+
+impl AltStack {
+    pub fn new(base: u64, size: usize) -> Self {
+        Self { base, size }
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
     }
 
-    /// Marks the provided `signal` as not pending.
-    pub fn clear_pending(&mut self, signal: u64) {
-        self.pending_mask.set_bit(signal as usize, false);
+    pub fn size(&self) -> usize {
+        self.size
     }
 
-    /// Sets the provided `signal` to be pending.
-    pub fn set_pending(&mut self, signal: u64) {
-        self.pending_mask.set_bit(signal as usize, true);
+    /// Returns the address of the top of the stack.
+    pub fn top(&self) -> u64 {
+        self.base + self.size as u64
+    }
+
+    /// Returns [`true`] if the provided stack pointer is on the stack.
+    pub fn contains(&self, rsp: u64) -> bool {
+        rsp > self.base && rsp <= self.top()
     }
 }
 
 pub struct Signals {
     entries: Arc<Mutex<Entries>>,
     blocked_mask: AtomicU64,
-    thread_pending_mask: AtomicU64,
+    /// Signals pending for this thread only.
+    thread_pending: Mutex<PendingSet>,
+    alt_stack: Mutex<Option<AltStack>>,
 }
 
 impl Signals {
@@ -244,7 +362,8 @@ impl Signals {
         Self {
             entries: Arc::new(Mutex::new(Default::default())),
             blocked_mask: AtomicU64::new(0),
-            thread_pending_mask: AtomicU64::new(0),
+            thread_pending: Mutex::new(PendingSet::default()),
+            alt_stack: Mutex::new(None),
         }
     }
 }
@@ -254,7 +373,8 @@ impl Clone for Signals {
         Signals {
             entries: self.entries.clone(),
             blocked_mask: AtomicU64::new(self.blocked_mask.load(Ordering::SeqCst)),
-            thread_pending_mask: AtomicU64::new(0),
+            thread_pending: Mutex::new(PendingSet::default()),
+            alt_stack: Mutex::new(None),
         }
     }
 }
@@ -271,7 +391,7 @@ impl Signals {
     }
 
     pub fn thread_pending(&self) -> u64 {
-        self.thread_pending_mask.load(Ordering::SeqCst)
+        self.thread_pending.lock_irq().mask
     }
 
     pub fn pending(&self) -> u64 {
@@ -279,27 +399,36 @@ impl Signals {
     }
 
     pub fn is_pending(&self, signal: u64) -> bool {
-        self.pending().get_bit(signal as usize)
+        self.pending() & sigmask(signal as usize) != 0
     }
 
+    /// Discards the provided `signal` if it is pending, for either the thread or the process.
     pub fn clear_pending(&self, signal: u64) {
-        if self.thread_pending().get_bit(signal as usize) {
-            self.thread_pending_mask
-                .fetch_and(!(1u64 << signal), Ordering::SeqCst);
-        } else {
-            self.entries().clear_pending(signal);
-        }
+        self.thread_pending.lock_irq().remove(signal as usize);
+        self.entries().pending.remove(signal as usize);
     }
 
-    pub fn set_pending(&self, signal: u64, thread_scope: bool) {
-        if thread_scope {
-            self.thread_pending_mask
-                .fetch_or(1u64 << signal, Ordering::SeqCst);
-        } else {
-            self.entries().set_pending(signal);
+    /// Discards all of the pending signals, for both the thread and the process.
+    pub fn clear_all_pending(&self) {
+        for signal in 1..SIGNAL_COUNT {
+            self.clear_pending(signal as u64);
         }
     }
 
+    /// Removes the oldest pending occurrence of `signal`, preferring the signals pending for
+    /// the thread over the ones pending for the process.
+    fn dequeue(&self, signal: usize) -> Option<SigInfo> {
+        self.thread_pending
+            .lock_irq()
+            .take(signal)
+            .or_else(|| self.entries().pending.take(signal))
+    }
+
+    /// Returns [`true`] if no more signals can be queued for the process.
+    pub fn is_queue_full(&self) -> bool {
+        self.entries().pending.is_full()
+    }
+
     /// Returns [`true`] if has pending signals.
     pub fn has_pending(&self) -> bool {
         (self.entries().pending() | self.thread_pending()) & !self.blocked_mask() > 0
@@ -310,48 +439,71 @@ impl Signals {
         self.blocked_mask.load(Ordering::SeqCst)
     }
 
+    /// Returns [`true`] if the provided `signal` is ignored by the process.
+    pub fn is_ignored(&self, signal: usize) -> bool {
+        is_ignored(signal, self.entries()[signal].handler())
+    }
+
     /// Returns [`true`] if the provided `signal` is blocked.
     pub fn is_blocked(&self, signal: usize) -> bool {
-        self.blocked_mask().get_bit(signal)
+        self.blocked_mask() & sigmask(signal) != 0
     }
 
-    pub fn trigger(&self, signal: usize, this_thread: bool) -> TriggerResult {
-        assert!(signal < SIGNAL_COUNT);
+    pub fn trigger(&self, info: SigInfo, this_thread: bool) -> TriggerResult {
+        let signal = info.si_signo as usize;
+        assert!(signal > 0 && signal < SIGNAL_COUNT);
 
-        let sigs = self.entries();
-        let handler = sigs[signal].handler();
+        let mut entries = self.entries();
 
-        if match handler {
-            SignalHandler::Ignore => false,
+        if is_ignored(signal, entries[signal].handler()) {
+            return TriggerResult::Ignored;
+        }
 
-            SignalHandler::Default => {
-                let action = default::action(signal);
+        let queued = if this_thread {
+            core::mem::drop(entries); // drop the lock
+            self.thread_pending.lock_irq().add(info)
+        } else {
+            entries.pending.add(info)
+        };
 
-                match action {
-                    default::Action::Ignore => false,
-                    default::Action::Handle(_) => true,
-                }
-            }
+        // NOTE: The signal is lost if the queue is full.
+        if !queued {
+            log::warn!("signal queue overflow, dropping signal {signal}");
+            TriggerResult::Ignored
+        } else if self.is_blocked(signal) {
+            TriggerResult::Blocked
+        } else {
+            TriggerResult::Triggered
+        }
+    }
 
-            SignalHandler::Handle(_) => true,
-        } {
-            core::mem::drop(sigs); // drop the lock
-            self.set_pending(signal as u64, this_thread);
+    /// Ensures that the provided `signal` is delivered, by resetting its handler to the default
+    /// and unblocking it if it is ignored or blocked. Used for the signals generated by faults,
+    /// which would otherwise re-occur forever.
+    pub fn force(&self, signal: usize) {
+        let mut entries = self.entries();
 
-            if self.is_blocked(signal) {
-                TriggerResult::Blocked
-            } else {
-                TriggerResult::Triggered
-            }
-        } else {
-            TriggerResult::Ignored
+        if self.is_blocked(signal) || entries[signal].handler() == SignalHandler::Ignore {
+            entries[signal] = SignalEntry::default();
+
+            self.blocked_mask
+                .fetch_and(!sigmask(signal), Ordering::SeqCst);
         }
     }
 
-    /// Clear the signal entries and blocked mask.
+    /// Resets the signals on `exec`. The handlers are reset to the default (as they no longer
+    /// exist in the new executable), apart from the ignored signals, which stay ignored. The
+    /// blocked mask and the pending signals are preserved.
     pub fn clear(&self) {
-        *self.entries.lock_irq() = Entries::default();
-        self.blocked_mask.store(0, Ordering::SeqCst);
+        let mut entries = self.entries();
+
+        for entry in entries.entries.iter_mut() {
+            if let SignalHandler::Handle(_) = entry.handler() {
+                *entry = SignalEntry::default();
+            }
+        }
+
+        *self.alt_stack.lock_irq() = None;
     }
 
     pub fn set_signal(
@@ -359,14 +511,13 @@ impl Signals {
         signal: usize,
         handler: Option<SignalEntry>,
         old: Option<&mut SigAction>,
-    ) {
-        if signal >= SIGNAL_COUNT {
-            log::warn!("signal index is out of bounds");
-            return;
+    ) -> Result<(), SyscallError> {
+        if signal == 0 || signal >= SIGNAL_COUNT {
+            return Err(SyscallError::EINVAL);
         }
 
-        if !can_override(signal) {
-            return;
+        if handler.is_some() && !can_override(signal) {
+            return Err(SyscallError::EINVAL);
         }
 
         let mut signals = self.entries();
@@ -377,19 +528,32 @@ impl Signals {
 
         if let Some(handler) = handler {
             signals[signal] = handler;
+
+            // Setting the disposition of a pending signal to be ignored discards the signal.
+            if is_ignored(signal, handler.handler()) {
+                signals.pending.remove(signal);
+                core::mem::drop(signals);
+
+                self.thread_pending.lock_irq().remove(signal);
+            }
         }
+
+        Ok(())
     }
 
     /// Copy over the signals from the provided `signals`.
     pub fn copy_from(&self, signals: &Signals) {
-        // Copy over the signl entries.
-        *self.entries() = *signals.entries();
+        // Copy over the signal entries. The pending signals are not inherited.
+        let entries = signals.entries().entries;
+        self.entries().entries = entries;
 
         // Copy over the blocked mask.
         self.blocked_mask.store(
             signals.blocked_mask.load(Ordering::SeqCst),
             Ordering::SeqCst,
         );
+
+        *self.alt_stack.lock_irq() = signals.alt_stack();
     }
 
     /// Used to update or read the signal mask of a task.
@@ -421,9 +585,27 @@ impl Signals {
             }
         }
     }
+
+    /// Returns the alternate signal stack, if one is installed.
+    pub fn alt_stack(&self) -> Option<AltStack> {
+        *self.alt_stack.lock_irq()
+    }
+
+    pub fn set_alt_stack(&self, stack: Option<AltStack>) {
+        *self.alt_stack.lock_irq() = stack;
+    }
 }
 
-pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
+/// Checks for the pending signals of the current task. The default action is run for the
+/// signals without a handler, otherwise the information of the signal to be handled is returned
+/// along with its handler.
+///
+/// ## Notes
+/// * The pending signals are delivered in order of their signal number, so the standard signals
+///   are delivered before the real-time signals and the lower numbered real-time signals have a
+///   higher priority. Multiple occurrences of the same real-time signal are delivered in the
+///   order in which they were sent.
+pub fn check_for_signals() -> Option<(SigInfo, SignalEntry)> {
     let task = scheduler::get_scheduler().current_task();
     let signals = task.signals();
 
@@ -435,30 +617,38 @@ pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
     // Check if a SIGKILL is pending, and if so, kill the task.
     if signals.is_pending(SIGKILL as u64) {
         signals.clear_pending(SIGKILL as u64);
-        scheduler::get_scheduler().exit(ExitStatus::Normal(1));
+        scheduler::get_scheduler().exit(ExitStatus::Signal(SIGKILL));
     }
 
-    for i in 0..SIGNAL_COUNT {
-        if !signals.is_blocked(i) && signals.is_pending(i as u64) {
-            signals.clear_pending(i as u64);
+    for i in 1..SIGNAL_COUNT {
+        if signals.is_blocked(i) || !signals.is_pending(i as u64) {
+            continue;
+        }
 
-            let entries = signals.entries();
-            let entry = entries[i];
+        let Some(info) = signals.dequeue(i) else {
+            continue;
+        };
 
-            match entry.handler() {
-                SignalHandler::Default => {
-                    drop(entries);
-                    default::handle_default(i);
-                }
+        let mut entries = signals.entries();
+        let entry = entries[i];
 
-                SignalHandler::Handle(_) => {
-                    return Some((i, entry));
-                }
+        match entry.handler() {
+            SignalHandler::Default => {
+                drop(entries);
+                default::handle_default(i);
+            }
 
-                SignalHandler::Ignore => {
-                    unreachable!()
+            SignalHandler::Handle(_) => {
+                // The disposition is reset to the default on entry to the handler.
+                if entry.flags().contains(SignalFlags::SA_RESETHAND) {
+                    entries[i] = SignalEntry::default();
                 }
+
+                return Some((info, entry));
             }
+
+            // The signal was ignored after it was queued by another thread.
+            SignalHandler::Ignore => {}
         }
     }
 
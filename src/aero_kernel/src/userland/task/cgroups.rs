@@ -0,0 +1,430 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Control Groups
+//!
+//! Control groups organize the processes in a hierarchy, where each group can restrict the
+//! resources used by all of the processes in its subtree. Every process belongs to exactly one
+//! control group, which is inherited from its parent on fork. The hierarchy is managed through
+//! the cgroup filesystem (see `crate::fs::cgroupfs`).
+//!
+//! The following controllers are supported:
+//! * CPU weight (`cpu.weight`): scales the scheduling weight of the tasks in the group.
+//! * CPU bandwidth (`cpu.max`): the tasks in the group are throttled once the group has used up
+//!   its quota of CPU time in the current period.
+//! * Memory limit (`memory.max`): the largest process in the group is killed if the resident
+//!   memory of the group exceeds the limit.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use hashbrown::HashMap;
+
+use crate::mem::oom;
+use crate::userland::scheduler::hrtimer;
+use crate::utils::sync::Mutex;
+
+use super::namespaces::PidNamespace;
+use super::{Task, TaskId};
+
+/// Default weight of a control group (see `cpu.weight`).
+pub const CPU_WEIGHT_DEFAULT: u64 = 100;
+pub const CPU_WEIGHT_MIN: u64 = 1;
+pub const CPU_WEIGHT_MAX: u64 = 10000;
+
+/// Default period (in microseconds) of the CPU bandwidth controller.
+pub const CPU_PERIOD_DEFAULT_US: u64 = 100000;
+
+/// Number of page faults after which the memory usage of a group with a memory limit is sampled
+/// again, if the estimated usage did not reach the limit in the meantime.
+const MEMORY_SAMPLE_FAULTS: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CGroupError {
+    /// A control group with the same name already exists.
+    Exists,
+    /// The control group still contains processes or child groups.
+    Busy,
+    /// The root control group cannot be removed or limited.
+    Root,
+}
+
+/// Maximum CPU bandwidth of a control group (see `cpu.max`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CpuMax {
+    /// CPU time (in microseconds) that the group may use in each period; [`None`] if the group
+    /// is not limited.
+    pub quota: Option<u64>,
+    /// Length of the period (in microseconds).
+    pub period: u64,
+}
+
+impl Default for CpuMax {
+    fn default() -> Self {
+        Self {
+            quota: None,
+            period: CPU_PERIOD_DEFAULT_US,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CpuState {
+    max: CpuMax,
+    /// Start of the current period (in nanoseconds, on the monotonic clock).
+    period_start: u64,
+    /// CPU time (in nanoseconds) used in the current period.
+    runtime: u64,
+}
+
+impl CpuState {
+    /// Starts a new period if the current one has ended.
+    fn refresh(&mut self, now: u64) {
+        if now.saturating_sub(self.period_start) >= self.max.period * 1000 {
+            self.period_start = now;
+            self.runtime = 0;
+        }
+    }
+
+    fn is_throttled(&mut self, now: u64) -> bool {
+        let Some(quota) = self.max.quota else {
+            return false;
+        };
+
+        self.refresh(now);
+        self.runtime >= quota * 1000
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    /// Resident memory (in 4KiB pages) of the group at the time of the last sample.
+    sampled: usize,
+    /// Number of page faults since the last sample. Each of them might have mapped in a new
+    /// page, so they are added to the sampled usage to estimate the current usage.
+    faults: usize,
+}
+
+pub struct CGroup {
+    name: String,
+    parent: Option<Arc<CGroup>>,
+    children: Mutex<BTreeMap<String, Arc<CGroup>>>,
+    tasks: Mutex<HashMap<TaskId, Arc<Task>>>,
+
+    cpu_weight: AtomicU64,
+    cpu: Mutex<CpuState>,
+    /// Total CPU time (in nanoseconds) used by the tasks in the subtree.
+    cpu_usage: AtomicU64,
+    /// Number of periods in which the group has been throttled.
+    nr_throttled: AtomicUsize,
+
+    /// Maximum resident memory (in 4KiB pages) of the subtree; [`usize::MAX`] if the group is not
+    /// limited.
+    memory_max: AtomicUsize,
+    memory: Mutex<MemoryState>,
+    /// Number of processes killed because the memory limit was reached.
+    oom_kills: AtomicUsize,
+}
+
+impl CGroup {
+    fn new(name: String, parent: Option<Arc<CGroup>>) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            parent,
+            children: Mutex::new(BTreeMap::new()),
+            tasks: Mutex::new(HashMap::new()),
+
+            cpu_weight: AtomicU64::new(CPU_WEIGHT_DEFAULT),
+            cpu: Mutex::new(CpuState::default()),
+            cpu_usage: AtomicU64::new(0),
+            nr_throttled: AtomicUsize::new(0),
+
+            memory_max: AtomicUsize::new(usize::MAX),
+            memory: Mutex::new(MemoryState::default()),
+            oom_kills: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the root control group, which contains all of the processes by default.
+    pub fn root() -> &'static Arc<CGroup> {
+        &ROOT
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    /// Returns an iterator over this group and all of its ancestors.
+    fn ancestors(&self) -> impl Iterator<Item = &CGroup> {
+        core::iter::successors(Some(self), |group| group.parent.as_deref())
+    }
+
+    /// Creates a new child control group with the provided name.
+    pub fn create(self: &Arc<Self>, name: &str) -> Result<Arc<CGroup>, CGroupError> {
+        let mut children = self.children.lock_irq();
+
+        if children.contains_key(name) {
+            return Err(CGroupError::Exists);
+        }
+
+        let group = CGroup::new(String::from(name), Some(self.clone()));
+        children.insert(String::from(name), group.clone());
+
+        Ok(group)
+    }
+
+    /// Removes the control group from its parent. Only empty groups can be removed.
+    pub fn remove(&self) -> Result<(), CGroupError> {
+        let parent = self.parent.as_ref().ok_or(CGroupError::Root)?;
+
+        if !self.tasks.lock_irq().is_empty() || !self.children.lock_irq().is_empty() {
+            return Err(CGroupError::Busy);
+        }
+
+        parent.children.lock_irq().remove(&self.name);
+        Ok(())
+    }
+
+    /// Adds a newly created task to the group.
+    pub fn add_task(&self, task: Arc<Task>) {
+        self.tasks.lock_irq().insert(task.tid(), task);
+    }
+
+    pub fn remove_task(&self, task: &Task) {
+        self.tasks.lock_irq().remove(&task.tid());
+    }
+
+    /// Moves the process of `task`, with all of its threads, from its current control group
+    /// into this group.
+    pub fn attach(self: &Arc<Self>, task: &Arc<Task>) {
+        for thread in task.threads() {
+            let old = thread.set_cgroup(self.clone());
+
+            old.remove_task(&thread);
+            self.add_task(thread);
+        }
+    }
+
+    /// Returns the PIDs (in the provided PID namespace) of the processes in the group that are
+    /// visible in the namespace, excluding the ones in the child groups.
+    pub fn pids(&self, ns: &PidNamespace) -> Vec<usize> {
+        let mut pids = self
+            .tasks
+            .lock_irq()
+            .values()
+            .filter(|task| task.is_process_leader())
+            .filter_map(|task| task.pid_in(ns))
+            .collect::<Vec<_>>();
+
+        pids.sort_unstable();
+        pids
+    }
+
+    /// Returns all of the processes in the subtree of the group.
+    fn subtree_tasks(&self) -> Vec<Arc<Task>> {
+        let mut tasks = self.tasks.lock_irq().values().cloned().collect::<Vec<_>>();
+
+        // NOTE: The children are collected first, as the locks of two groups must never be held
+        // at the same time.
+        let children = self
+            .children
+            .lock_irq()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for child in children {
+            tasks.extend(child.subtree_tasks());
+        }
+
+        tasks
+    }
+
+    pub fn cpu_weight(&self) -> u64 {
+        self.cpu_weight.load(Ordering::Relaxed)
+    }
+
+    /// Sets the weight of the group. Returns `false` if the weight is out of range.
+    pub fn set_cpu_weight(&self, weight: u64) -> bool {
+        if !(CPU_WEIGHT_MIN..=CPU_WEIGHT_MAX).contains(&weight) {
+            return false;
+        }
+
+        self.cpu_weight.store(weight, Ordering::Relaxed);
+        true
+    }
+
+    /// Scales the scheduling weight of a task in the group by the weights of the group and of its
+    /// ancestors.
+    ///
+    /// ## Notes
+    /// * The weight is applied to each task instead of the group as a whole, so the share of the
+    ///   group also grows with the number of its runnable tasks.
+    pub fn scale_weight(&self, weight: u64) -> u64 {
+        let weight = self
+            .ancestors()
+            .filter(|group| !group.is_root())
+            .fold(weight, |weight, group| {
+                weight * group.cpu_weight() / CPU_WEIGHT_DEFAULT
+            });
+
+        core::cmp::max(weight, 1)
+    }
+
+    pub fn cpu_max(&self) -> CpuMax {
+        self.cpu.lock_irq().max
+    }
+
+    pub fn set_cpu_max(&self, max: CpuMax) -> Result<(), CGroupError> {
+        if self.is_root() {
+            return Err(CGroupError::Root);
+        }
+
+        let mut cpu = self.cpu.lock_irq();
+
+        cpu.max = max;
+        cpu.period_start = hrtimer::now();
+        cpu.runtime = 0;
+
+        Ok(())
+    }
+
+    /// Charges the group and its ancestors for `ns` nanoseconds of CPU time.
+    pub fn charge_cpu(&self, ns: u64) {
+        let now = hrtimer::now();
+
+        for group in self.ancestors() {
+            group.cpu_usage.fetch_add(ns, Ordering::Relaxed);
+
+            let mut cpu = group.cpu.lock_irq();
+
+            if let Some(quota) = cpu.max.quota {
+                cpu.refresh(now);
+
+                let runtime = cpu.runtime;
+                cpu.runtime += ns;
+
+                if runtime < quota * 1000 && cpu.runtime >= quota * 1000 {
+                    group.nr_throttled.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the group or any of its ancestors has used up its CPU quota in the
+    /// current period, in which case the tasks in the group must not be scheduled.
+    pub fn is_throttled(&self) -> bool {
+        let now = hrtimer::now();
+
+        self.ancestors()
+            .any(|group| group.cpu.lock_irq().is_throttled(now))
+    }
+
+    /// Returns the total CPU time (in nanoseconds) used by the subtree of the group.
+    pub fn cpu_usage(&self) -> u64 {
+        self.cpu_usage.load(Ordering::Relaxed)
+    }
+
+    pub fn nr_throttled(&self) -> usize {
+        self.nr_throttled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the memory limit (in pages) of the group; [`usize::MAX`] if not limited.
+    pub fn memory_max(&self) -> usize {
+        self.memory_max.load(Ordering::Relaxed)
+    }
+
+    pub fn set_memory_max(&self, pages: usize) -> Result<(), CGroupError> {
+        if self.is_root() {
+            return Err(CGroupError::Root);
+        }
+
+        self.memory_max.store(pages, Ordering::Relaxed);
+
+        // Make sure the next page fault checks the usage against the new limit.
+        self.memory.lock_irq().faults = MEMORY_SAMPLE_FAULTS;
+        Ok(())
+    }
+
+    /// Returns the resident memory (in 4KiB pages) of the processes in the subtree.
+    pub fn memory_usage(&self) -> usize {
+        let usage = self
+            .subtree_tasks()
+            .iter()
+            .filter(|task| !task.has_exited())
+            .map(oom::badness)
+            .sum();
+
+        *self.memory.lock_irq() = MemoryState {
+            sampled: usage,
+            faults: 0,
+        };
+
+        usage
+    }
+
+    pub fn oom_kills(&self) -> usize {
+        self.oom_kills.load(Ordering::Relaxed)
+    }
+
+    /// Called before a page fault of a task in the group is handled. If the group or any of its
+    /// ancestors is over its memory limit, the largest process in that subtree is killed.
+    ///
+    /// ## Notes
+    /// * The usage is only sampled (see [`CGroup::memory_usage`]) once the estimated usage
+    ///   reaches the limit or after [`MEMORY_SAMPLE_FAULTS`] page faults, as sampling walks the
+    ///   page tables of all of the processes in the group.
+    pub fn charge_fault(&self) {
+        for group in self.ancestors() {
+            let max = group.memory_max();
+
+            if max == usize::MAX {
+                continue;
+            }
+
+            let sample = {
+                let mut memory = group.memory.lock_irq();
+                memory.faults += 1;
+
+                memory.faults >= MEMORY_SAMPLE_FAULTS || memory.sampled + memory.faults >= max
+            };
+
+            if !sample || group.memory_usage() < max {
+                continue;
+            }
+
+            log::warn!("cgroup: `{}` reached its memory limit", group.name());
+
+            if let Some((victim, score)) = oom::select_victim(group.subtree_tasks()) {
+                group.oom_kills.fetch_add(1, Ordering::Relaxed);
+                oom::kill(&victim, score);
+            }
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref ROOT: Arc<CGroup> = CGroup::new(String::new(), None);
+}
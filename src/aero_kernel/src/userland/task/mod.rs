@@ -15,38 +15,64 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod cgroups;
+pub mod namespaces;
+pub mod rusage;
 pub mod sessions;
+pub mod timers;
 
-use aero_syscall::WaitPidFlags;
+use aero_syscall::signal::{SigInfo, SI_KERNEL};
+use aero_syscall::{CloneFlags, SyscallError, WaitPidFlags};
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 
 use hashbrown::HashMap;
 use spin::{Once, RwLock};
 
 use core::cell::UnsafeCell;
 use core::ops::Range;
-use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::sync::atomic::{
+    AtomicBool, AtomicI8, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
 
 use crate::fs::cache::{DirCacheImpl, DirCacheItem};
 use crate::fs::path::PathBuf;
-use crate::fs::{self, FileSystem};
+use crate::fs::{self, FileSystem, MountManager, MOUNT_MANAGER};
 use crate::mem::paging::*;
+use crate::mem::slab::{self, SlabCache};
+use crate::mem::AddressSpace;
 
 use crate::arch::task::ArchTask;
 use crate::fs::file_table::FileTable;
 use crate::syscall::ipc::MessageQueue;
+use crate::syscall::shm;
 use crate::syscall::ExecArgs;
 use crate::utils::sync::{Mutex, WaitQueue};
 
 use crate::userland::signals::Signals;
 
+use self::cgroups::CGroup;
+use self::namespaces::PidNamespace;
+use self::rusage::{CpuTime, ResourceUsage, Usage};
+use self::timers::Timers;
+
 use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListLink};
 
+use super::kthread::KThread;
 use super::scheduler::{self, ExitStatus};
-use super::signals::{SignalResult, TriggerResult};
+use super::seccomp::Seccomp;
+use super::signals::TriggerResult;
 use super::terminal::TerminalDevice;
 use super::vm::Vm;
 
+/// Nice value of the task with the highest priority.
+pub const NICE_MIN: isize = -20;
+/// Nice value of the task with the lowest priority.
+pub const NICE_MAX: isize = 19;
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+static TASK_SLAB: SlabCache = SlabCache::new("task", slab::arc_layout::<Task>());
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct TaskId(usize);
@@ -58,11 +84,14 @@ impl TaskId {
 
     /// Allocates a new task ID.
     fn allocate() -> Self {
-        static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
-
         Self::new(NEXT_PID.fetch_add(1, Ordering::AcqRel))
     }
 
+    /// Returns the most recently allocated task ID.
+    pub fn last() -> Self {
+        Self::new(NEXT_PID.load(Ordering::Acquire) - 1)
+    }
+
     pub fn as_usize(&self) -> usize {
         self.0
     }
@@ -86,6 +115,53 @@ impl From<u8> for TaskState {
     }
 }
 
+/// Scheduling policy of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Time-sharing policy, where the CPU time is distributed based on the nice value of the
+    /// tasks.
+    Normal,
+    /// Real-time policy with the provided priority (from 1 to 99). The task runs until it blocks
+    /// or a real-time task with a higher priority becomes runnable.
+    Fifo(u8),
+    /// Same as [`SchedPolicy::Fifo`], except that real-time tasks with the same priority are
+    /// time-sliced.
+    RoundRobin(u8),
+}
+
+impl SchedPolicy {
+    /// Returns the real-time priority of the policy or `None` if it is not a real-time policy.
+    pub fn rt_priority(&self) -> Option<u8> {
+        match self {
+            SchedPolicy::Normal => None,
+            SchedPolicy::Fifo(priority) | SchedPolicy::RoundRobin(priority) => Some(*priority),
+        }
+    }
+}
+
+impl From<u16> for SchedPolicy {
+    fn from(x: u16) -> Self {
+        let priority = x as u8;
+
+        match x >> 8 {
+            0 => SchedPolicy::Normal,
+            1 => SchedPolicy::Fifo(priority),
+            2 => SchedPolicy::RoundRobin(priority),
+            _ => panic!("invalid scheduling policy"),
+        }
+    }
+}
+
+impl From<SchedPolicy> for u16 {
+    fn from(policy: SchedPolicy) -> Self {
+        match policy {
+            SchedPolicy::Normal => 0,
+            SchedPolicy::Fifo(priority) => (1 << 8) | priority as u16,
+            SchedPolicy::RoundRobin(priority) => (2 << 8) | priority as u16,
+        }
+    }
+}
+
 struct Cwd {
     inode: DirCacheItem,
     filesystem: Arc<dyn FileSystem>,
@@ -134,58 +210,90 @@ impl Zombies {
         list.push_back(zombie);
         self.block.notify_all();
     }
+}
 
-    fn waitpid(
-        &self,
-        pids: &[usize],
-        status: &mut u32,
-        flags: WaitPidFlags,
-    ) -> SignalResult<usize> {
-        let mut captured = None;
-
-        self.block.block_on(&self.list, |l| {
-            let mut cursor = l.front_mut();
-
-            while let Some(t) = cursor.get() {
-                for pid in pids {
-                    if t.pid().as_usize() == *pid {
-                        captured = Some((t.pid(), t.exit_status().clone()));
-                        cursor.remove();
+/// Change of the job control state of a task, which is reported to its parent by
+/// [`Task::wait`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum WaitEvent {
+    /// The task was stopped by the provided signal.
+    Stopped(usize),
+    /// The task was continued by `SIGCONT`.
+    Continued,
+}
 
-                        return true;
-                    }
-                }
+/// Children that [`Task::wait`] waits for.
+#[derive(Debug, Copy, Clone)]
+pub enum WaitTarget {
+    /// Any child.
+    Any,
+    /// The child with the provided process ID, in the PID namespace of the parent.
+    Pid(usize),
+    /// Any child in the process group with the provided ID.
+    Group(usize),
+}
 
-                cursor.move_next();
-            }
+impl WaitTarget {
+    fn matches(&self, parent: &Task, task: &Task) -> bool {
+        // The threads of the parent are not its children.
+        if !task.is_process_leader() {
+            return false;
+        }
 
-            if flags.contains(WaitPidFlags::WNOHANG) {
-                return true;
-            }
+        match self {
+            WaitTarget::Any => true,
+            WaitTarget::Pid(pid) => parent.child_pid(task) == *pid,
+            WaitTarget::Group(group_id) => task.group_id() == *group_id,
+        }
+    }
+}
 
-            false
-        })?;
+/// State change of a child, as reported by [`Task::wait`].
+#[derive(Debug, Clone)]
+pub enum ChildState {
+    Exited(ExitStatus),
+    Stopped(usize),
+    Continued,
+}
 
-        if let Some((tid, exit_status)) = captured {
-            // mlibc/abis/linux/wait.h (`W_EXITCODE`)
-            match exit_status {
-                ExitStatus::Normal(code) => {
-                    *status = (code as u32) << 8;
-                }
+pub struct WaitResult {
+    pub pid: usize,
+    pub state: ChildState,
+    /// Resource usage of the child, including its children that have been waited for.
+    pub usage: Usage,
+}
 
-                ExitStatus::Signal(signal) => {
-                    *status = signal as u32;
-                }
-            }
+/// Program to execute in a process created by [`Task::spawn`].
+struct PendingExec {
+    executable: DirCacheItem,
+    argv: Option<ExecArgs>,
+    envv: Option<ExecArgs>,
+}
 
-            Ok(tid.as_usize())
-        } else {
-            // If `WNOHANG` was specified in flags and there were no children in a waitable
-            // state, then waipid() returns 0 immediately.
-            *status = 0;
-            Ok(0)
-        }
+/// Entry point of the processes created by [`Task::spawn`].
+fn spawn_init() {
+    let task = scheduler::current_thread();
+    let PendingExec {
+        executable,
+        argv,
+        envv,
+    } = task
+        .pending_exec
+        .lock_irq()
+        .take()
+        .expect("spawn_init: no program to execute");
+
+    if let Err(err) = task.exec(&executable, argv, envv) {
+        log::warn!("spawn: failed to execute the program: {err:?}");
+
+        // The parent already has the PID of the child, so report the failure through its exit
+        // status, the same one that shells use for commands that cannot be executed.
+        core::mem::drop(executable);
+        core::mem::drop(task);
+        scheduler::get_scheduler().exit(ExitStatus::Normal(127));
     }
+
+    unreachable!()
 }
 
 pub struct Task {
@@ -206,22 +314,69 @@ pub struct Task {
     zombies: Zombies,
 
     sleep_duration: AtomicUsize,
+    /// Logical ID of the CPU that the task is scheduled on.
+    cpu: AtomicUsize,
+    /// Mask of the CPUs (by logical ID) that the task is allowed to run on.
+    affinity: AtomicU64,
+    /// Nice value of the task, ranging from -20 (highest priority) to 19 (lowest priority).
+    nice: AtomicI8,
+    /// Amount of CPU time that the task has consumed, weighted by its priority.
+    vruntime: AtomicU64,
+    /// Scheduling policy of the task (see [`SchedPolicy`]).
+    policy: AtomicU16,
     signals: Signals,
 
     pub executable: Mutex<Option<DirCacheItem>>,
     pending_io: AtomicBool,
+    /// Whether the task has been stopped by a job control signal.
+    stopped: AtomicBool,
+    /// Whether the task has called `exec` since it was created.
+    did_exec: AtomicBool,
+    /// Program that the task executes once it starts running (see [`Task::spawn`]).
+    pending_exec: Mutex<Option<PendingExec>>,
+    /// Whether the task is executing a system call.
+    in_syscall: AtomicBool,
+    /// CPU time consumed by the thread.
+    cpu_time: CpuTime,
+    /// Change of the job control state that has not been reported to the parent yet.
+    wait_event: Mutex<Option<WaitEvent>>,
 
     pub(super) link: intrusive_collections::LinkedListLink,
     pub(super) clink: intrusive_collections::LinkedListLink,
 
     pub vm: Arc<Vm>,
     pub file_table: Arc<FileTable>,
+    /// Timers of the process, shared by all of the threads in the process.
+    timers: Arc<Timers>,
+    /// Resource usage of the process, shared by all of the threads in the process.
+    usage: Arc<ResourceUsage>,
+    /// Control group of the process.
+    cgroup: Mutex<Arc<CGroup>>,
+
+    /// PID namespace of the process.
+    pid_ns: Arc<PidNamespace>,
+    /// PIDs of the process in its PID namespace and in each of the ancestors of it, indexed by
+    /// the level of the namespace.
+    ns_pids: Vec<usize>,
+    /// PID namespace that the children of the process are created in (see `unshare`).
+    child_pid_ns: Mutex<Arc<PidNamespace>>,
+    /// Mount namespace of the process.
+    mount_ns: Mutex<Arc<MountManager>>,
+    /// Seccomp mode of the process (see `seccomp`).
+    seccomp: Mutex<Seccomp>,
+    /// Name and entry point of the task if it is a kernel thread created with
+    /// [`spawn`](super::kthread::spawn).
+    kthread: Once<Arc<KThread>>,
 
     pub message_queue: MessageQueue,
 
-    cwd: RwLock<Option<Cwd>>,
+    /// Working directory of the task, shared with the tasks created with `CLONE_FS`.
+    cwd: Arc<RwLock<Option<Cwd>>>,
 
     pub(super) exit_status: Once<ExitStatus>,
+    /// Exit status of the whole process, set on the process leader once one of its threads
+    /// exits the process (see `Scheduler::exit_group`).
+    group_exit: Once<ExitStatus>,
 
     controlling_terminal: Mutex<Option<Arc<dyn TerminalDevice>>>,
     systrace: AtomicBool,
@@ -231,18 +386,31 @@ pub struct Task {
 }
 
 impl Task {
+    /// Same as [`Arc::new_cyclic`], but the task is allocated from the task cache.
+    fn new_cyclic(data_fn: impl FnOnce(&Weak<Task>) -> Task) -> Arc<Task> {
+        TASK_SLAB.alloc_with(|| Arc::new_cyclic(data_fn))
+    }
+
     /// Creates a per-cpu idle task. An idle task is a special *kernel* process
     /// which is executed when there are no runnable taskes in the scheduler's
     /// queue.
     pub fn new_idle() -> Arc<Task> {
         let pid = TaskId::allocate();
 
-        Arc::new_cyclic(|sref| Self {
+        Self::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
 
             arch_task: UnsafeCell::new(ArchTask::new_idle()),
             file_table: Arc::new(FileTable::new()),
+            timers: Arc::new(Timers::new(sref.clone())),
+            usage: Arc::new(ResourceUsage::new()),
+            cgroup: Mutex::new(CGroup::root().clone()),
+            seccomp: Mutex::new(Seccomp::default()),
+            pid_ns: PidNamespace::root().clone(),
+            ns_pids: alloc::vec![pid.as_usize()],
+            child_pid_ns: Mutex::new(PidNamespace::root().clone()),
+            mount_ns: Mutex::new(MOUNT_MANAGER.clone()),
 
             message_queue: MessageQueue::new(),
 
@@ -260,20 +428,33 @@ impl Task {
             clink: Default::default(),
 
             pending_io: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            did_exec: AtomicBool::new(false),
+            pending_exec: Mutex::new(None),
+            in_syscall: AtomicBool::new(false),
+            cpu_time: CpuTime::new(),
+            wait_event: Mutex::new(None),
 
             sleep_duration: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(0),
+            affinity: AtomicU64::new(u64::MAX),
+            nice: AtomicI8::new(0),
+            policy: AtomicU16::new(0),
+            vruntime: AtomicU64::new(0),
             exit_status: Once::new(),
+            group_exit: Once::new(),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             signals: Signals::new(),
-            cwd: RwLock::new(None),
+            cwd: Arc::new(RwLock::new(None)),
 
             systrace: AtomicBool::new(false),
             controlling_terminal: Mutex::new(None),
 
             mem_tags: Mutex::new(HashMap::new()),
+            kthread: Once::new(),
         })
     }
 
@@ -281,7 +462,7 @@ impl Task {
     pub fn new_kernel(entry_point: fn(), enable_interrupts: bool) -> Arc<Self> {
         let pid = TaskId::allocate();
 
-        Arc::new_cyclic(|sref| Self {
+        Self::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
 
@@ -290,6 +471,14 @@ impl Task {
                 enable_interrupts,
             )),
             file_table: Arc::new(FileTable::new()),
+            timers: Arc::new(Timers::new(sref.clone())),
+            usage: Arc::new(ResourceUsage::new()),
+            cgroup: Mutex::new(CGroup::root().clone()),
+            seccomp: Mutex::new(Seccomp::default()),
+            pid_ns: PidNamespace::root().clone(),
+            ns_pids: alloc::vec![pid.as_usize()],
+            child_pid_ns: Mutex::new(PidNamespace::root().clone()),
+            mount_ns: Mutex::new(MOUNT_MANAGER.clone()),
             message_queue: MessageQueue::new(),
             vm: Arc::new(Vm::new()),
             state: AtomicU8::new(TaskState::Runnable as _),
@@ -303,21 +492,34 @@ impl Task {
             clink: Default::default(),
 
             sleep_duration: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(0),
+            affinity: AtomicU64::new(u64::MAX),
+            nice: AtomicI8::new(0),
+            policy: AtomicU16::new(0),
+            vruntime: AtomicU64::new(0),
             exit_status: Once::new(),
+            group_exit: Once::new(),
 
             executable: Mutex::new(None),
             pending_io: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            did_exec: AtomicBool::new(false),
+            pending_exec: Mutex::new(None),
+            in_syscall: AtomicBool::new(false),
+            cpu_time: CpuTime::new(),
+            wait_event: Mutex::new(None),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             signals: Signals::new(),
-            cwd: RwLock::new(None),
+            cwd: Arc::new(RwLock::new(None)),
 
             systrace: AtomicBool::new(false),
             controlling_terminal: Mutex::new(None),
 
             mem_tags: Mutex::new(HashMap::new()),
+            kthread: Once::new(),
         })
     }
 
@@ -329,10 +531,101 @@ impl Task {
         self.pending_io.store(yes, Ordering::SeqCst)
     }
 
+    /// Returns whether the task is executing a system call.
+    pub fn in_syscall(&self) -> bool {
+        self.in_syscall.load(Ordering::Relaxed)
+    }
+
+    pub fn set_in_syscall(&self, yes: bool) {
+        // Account the CPU time consumed until the boundary of the system call, so that it is
+        // charged to the mode that it was spent in.
+        self.account_cpu_time();
+        self.in_syscall.store(yes, Ordering::Relaxed)
+    }
+
+    /// Charges the thread and its process for the CPU time that the thread has consumed since
+    /// it was last accounted.
+    pub fn account_cpu_time(&self) {
+        let system = self.in_syscall();
+        let elapsed = self.cpu_time.account(crate::arch::time::get_uptime_ns(), system);
+
+        self.usage.charge(elapsed, system);
+        // Kernel threads only ever run in kernel mode.
+        scheduler::stats::account(elapsed, system || self.kthread().is_some(), self.nice() > 0);
+    }
+
+    pub fn cpu_time(&self) -> &CpuTime {
+        &self.cpu_time
+    }
+
     pub fn signals(&self) -> &Signals {
         &self.signals
     }
 
+    pub fn timers(&self) -> &Arc<Timers> {
+        &self.timers
+    }
+
+    pub fn usage(&self) -> &Arc<ResourceUsage> {
+        &self.usage
+    }
+
+    pub fn cgroup(&self) -> Arc<CGroup> {
+        self.cgroup.lock_irq().clone()
+    }
+
+    /// Sets the control group of the task. Returns the previous control group.
+    fn set_cgroup(&self, cgroup: Arc<CGroup>) -> Arc<CGroup> {
+        core::mem::replace(&mut *self.cgroup.lock_irq(), cgroup)
+    }
+
+    pub fn seccomp(&self) -> Seccomp {
+        self.seccomp.lock_irq().clone()
+    }
+
+    pub fn set_seccomp(&self, seccomp: Seccomp) {
+        *self.seccomp.lock_irq() = seccomp;
+    }
+
+    pub fn pid_ns(&self) -> &Arc<PidNamespace> {
+        &self.pid_ns
+    }
+
+    /// Returns the PID of the process in its own PID namespace.
+    pub fn vpid(&self) -> usize {
+        // UNWRAP: The process always has a PID in its own namespace.
+        *self.ns_pids.last().unwrap()
+    }
+
+    /// Returns the PID of the process in the provided namespace; [`None`] if the process is not
+    /// visible in it.
+    pub fn pid_in(&self, ns: &PidNamespace) -> Option<usize> {
+        ns.contains(&self.pid_ns).then(|| self.ns_pids[ns.level()])
+    }
+
+    /// Returns the PID of `child` in the PID namespace of this process.
+    pub fn child_pid(&self, child: &Task) -> usize {
+        // UNWRAP: The children are always created in the namespace of the process or in a
+        // descendant of it.
+        child.pid_in(&self.pid_ns).unwrap()
+    }
+
+    pub fn child_pid_ns(&self) -> Arc<PidNamespace> {
+        self.child_pid_ns.lock_irq().clone()
+    }
+
+    pub fn set_child_pid_ns(&self, ns: Arc<PidNamespace>) {
+        *self.child_pid_ns.lock_irq() = ns;
+    }
+
+    pub fn mount_ns(&self) -> Arc<MountManager> {
+        self.mount_ns.lock_irq().clone()
+    }
+
+    pub fn set_mount_ns(&self, ns: Arc<MountManager>) {
+        *self.mount_ns.lock_irq() = ns;
+    }
+
     pub fn clone_process(&self, entry: usize, stack: usize) -> Arc<Task> {
         let arch_task = UnsafeCell::new(
             self.arch_task_mut()
@@ -341,13 +634,23 @@ impl Task {
         );
 
         let pid = TaskId::allocate();
+        let pid_ns = self.child_pid_ns();
+        let ns_pids = pid_ns.alloc_pids(pid);
 
-        let this = Arc::new_cyclic(|sref| Self {
+        let this = Self::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
 
             arch_task,
             file_table: self.process_leader().file_table.clone(),
+            timers: self.process_leader().timers.clone(),
+            usage: self.process_leader().usage.clone(),
+            cgroup: Mutex::new(self.cgroup()),
+            seccomp: Mutex::new(self.seccomp()),
+            child_pid_ns: Mutex::new(pid_ns.clone()),
+            pid_ns,
+            ns_pids,
+            mount_ns: Mutex::new(self.mount_ns()),
             message_queue: MessageQueue::new(),
             vm: self.process_leader().vm.clone(),
             state: AtomicU8::new(TaskState::Runnable as _),
@@ -356,7 +659,13 @@ impl Task {
             clink: Default::default(),
 
             sleep_duration: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(0),
+            affinity: AtomicU64::new(self.affinity()),
+            nice: AtomicI8::new(self.nice()),
+            policy: AtomicU16::new(self.sched_policy().into()),
+            vruntime: AtomicU64::new(0),
             exit_status: Once::new(),
+            group_exit: Once::new(),
 
             tid: pid,
             sid: AtomicUsize::new(self.session_id()),
@@ -365,12 +674,18 @@ impl Task {
 
             executable: Mutex::new(self.executable.lock().clone()),
             pending_io: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            did_exec: AtomicBool::new(false),
+            pending_exec: Mutex::new(None),
+            in_syscall: AtomicBool::new(false),
+            cpu_time: CpuTime::new(),
+            wait_event: Mutex::new(None),
 
             children: Mutex::new(Default::default()),
             // sus? fixme?
             parent: Mutex::new(None),
 
-            cwd: RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())),
+            cwd: Arc::new(RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork()))),
             signals: Signals::new(),
 
             systrace: AtomicBool::new(self.process_leader().systrace()),
@@ -382,6 +697,7 @@ impl Task {
             ),
 
             mem_tags: Mutex::new(self.mem_tags.lock().clone()),
+            kthread: Once::new(),
         });
 
         self.add_child(this.clone());
@@ -394,20 +710,156 @@ impl Task {
         let vm = Arc::new(Vm::new());
         let address_space = vm.fork_from(self.vm());
 
-        let arch_task = UnsafeCell::new(
-            self.arch_task_mut()
-                .fork(address_space)
-                .expect("failed to fork arch task"),
+        let arch_task = self
+            .arch_task_mut()
+            .fork(address_space)
+            .expect("failed to fork arch task");
+
+        let child = self.new_child(vm, arch_task, CloneFlags::empty(), self.child_pid_ns());
+
+        // The child inherits the shared memory segments mapped into the address space.
+        shm::fork(self.pid().as_usize(), child.pid().as_usize());
+        child
+    }
+
+    /// Creates a child task that shares the resources selected by `flags` with this task (see
+    /// `clone3`). Like with [`Task::fork`], the child returns from the system call with a result
+    /// of 0; on the user stack `stack` and with the thread pointer `tls`, if provided.
+    pub fn clone_task(
+        &self,
+        flags: CloneFlags,
+        stack: Option<usize>,
+        tls: Option<usize>,
+    ) -> Result<Arc<Task>, SyscallError> {
+        let pid_ns = if flags.contains(CloneFlags::CLONE_THREAD) {
+            self.pid_ns.clone()
+        } else if flags.contains(CloneFlags::CLONE_NEWPID) {
+            self.child_pid_ns()
+                .create_child()
+                .ok_or(SyscallError::ENOSPC)?
+        } else {
+            self.child_pid_ns()
+        };
+
+        let (vm, address_space) = if flags.contains(CloneFlags::CLONE_VM) {
+            (self.vm.clone(), AddressSpace::this())
+        } else {
+            let vm = Arc::new(Vm::new());
+            let address_space = vm.fork_from(self.vm());
+
+            (vm, address_space)
+        };
+
+        let arch_task = self
+            .arch_task_mut()
+            .clone_task(address_space, stack, tls)
+            .map_err(|_| SyscallError::ENOMEM)?;
+
+        let child = self.new_child(vm, arch_task, flags, pid_ns);
+
+        if !flags.contains(CloneFlags::CLONE_VM) {
+            shm::fork(self.pid().as_usize(), child.pid().as_usize());
+        }
+
+        Ok(child)
+    }
+
+    /// Creates a child process that executes `executable`. Unlike [`Task::fork`] followed by
+    /// an `exec`, the address space of the parent is never duplicated: the child starts in the
+    /// kernel and loads the program into a new, empty address space.
+    pub fn spawn(
+        &self,
+        executable: DirCacheItem,
+        argv: Option<ExecArgs>,
+        envv: Option<ExecArgs>,
+    ) -> Arc<Task> {
+        let arch_task = ArchTask::new_kernel(VirtAddr::new(spawn_init as u64), true);
+        let this = self.new_child(
+            Arc::new(Vm::new()),
+            arch_task,
+            CloneFlags::empty(),
+            self.child_pid_ns(),
         );
 
-        let pid = TaskId::allocate();
+        *this.pending_exec.lock_irq() = Some(PendingExec {
+            executable,
+            argv,
+            envv,
+        });
+
+        this
+    }
+
+    /// Creates a child task with the provided VM and architecture-specific task. The rest of its
+    /// state is either shared with this task or copied from it, as selected by `flags`. Unless
+    /// `CLONE_THREAD` is set, the child is a new process in the PID namespace `pid_ns`.
+    fn new_child(
+        &self,
+        vm: Arc<Vm>,
+        arch_task: ArchTask,
+        flags: CloneFlags,
+        pid_ns: Arc<PidNamespace>,
+    ) -> Arc<Task> {
+        let arch_task = UnsafeCell::new(arch_task);
+        let leader = self.process_leader();
+        let thread = flags.contains(CloneFlags::CLONE_THREAD);
+
+        let tid = TaskId::allocate();
+
+        // The threads of a process share its PIDs.
+        let (pid, ns_pids, child_pid_ns) = if thread {
+            (self.pid, self.ns_pids.clone(), self.child_pid_ns())
+        } else {
+            (tid, pid_ns.alloc_pids(tid), pid_ns.clone())
+        };
+
+        let file_table = if flags.contains(CloneFlags::CLONE_FILES) {
+            self.file_table.clone()
+        } else {
+            Arc::new(self.file_table.deep_clone())
+        };
+
+        let cwd = if flags.contains(CloneFlags::CLONE_FS) {
+            self.cwd.clone()
+        } else {
+            Arc::new(RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())))
+        };
 
-        let this = Arc::new_cyclic(|sref| Self {
+        let mount_ns = if flags.contains(CloneFlags::CLONE_NEWNS) {
+            self.mount_ns().duplicate()
+        } else {
+            self.mount_ns()
+        };
+
+        // The signal handlers are shared along with the signals pending for the process.
+        let signals = if flags.contains(CloneFlags::CLONE_SIGHAND) {
+            self.signals().clone()
+        } else {
+            Signals::new()
+        };
+
+        let this = Self::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
 
             arch_task,
-            file_table: Arc::new(self.file_table.deep_clone()),
+            file_table,
+            timers: if thread {
+                leader.timers.clone()
+            } else {
+                Arc::new(Timers::new(sref.clone()))
+            },
+            usage: if thread {
+                leader.usage.clone()
+            } else {
+                Arc::new(ResourceUsage::new())
+            },
+            cgroup: Mutex::new(self.cgroup()),
+            seccomp: Mutex::new(self.seccomp()),
+            child_pid_ns: Mutex::new(child_pid_ns),
+            pid_ns,
+            ns_pids,
+            mount_ns: Mutex::new(mount_ns),
             message_queue: MessageQueue::new(),
             vm,
             state: AtomicU8::new(TaskState::Runnable as _),
@@ -416,30 +868,52 @@ impl Task {
             clink: Default::default(),
 
             sleep_duration: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(0),
+            affinity: AtomicU64::new(self.affinity()),
+            nice: AtomicI8::new(self.nice()),
+            policy: AtomicU16::new(self.sched_policy().into()),
+            vruntime: AtomicU64::new(0),
             exit_status: Once::new(),
+            group_exit: Once::new(),
 
-            tid: pid,
+            tid,
             sid: AtomicUsize::new(self.session_id()),
             gid: AtomicUsize::new(self.group_id()),
             pid,
 
             executable: Mutex::new(self.executable.lock().clone()),
             pending_io: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            did_exec: AtomicBool::new(false),
+            pending_exec: Mutex::new(None),
+            in_syscall: AtomicBool::new(false),
+            cpu_time: CpuTime::new(),
+            wait_event: Mutex::new(None),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
-            cwd: RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())),
-            signals: Signals::new(),
+            cwd,
+            signals,
 
             systrace: AtomicBool::new(self.systrace()),
             controlling_terminal: Mutex::new(self.controlling_terminal.lock_irq().clone()),
 
             mem_tags: Mutex::new(self.mem_tags.lock().clone()),
+            kthread: Once::new(),
         });
 
-        self.add_child(this.clone());
-        this.signals().copy_from(self.signals());
+        // The threads of a process are children of the main thread.
+        if thread {
+            leader.add_child(this.clone());
+        } else {
+            self.add_child(this.clone());
+        }
+
+        if !flags.contains(CloneFlags::CLONE_SIGHAND) {
+            this.signals().copy_from(self.signals());
+        }
+
         this
     }
 
@@ -469,10 +943,58 @@ impl Task {
         children.push_back(child);
     }
 
+    /// Returns the threads of the process, including the process leader and the task itself.
+    pub fn threads(&self) -> Vec<Arc<Task>> {
+        let leader = self.process_leader();
+        let mut threads = alloc::vec![leader.clone()];
+
+        threads.extend(
+            leader
+                .children
+                .lock_irq()
+                .iter()
+                .filter(|child| child.pid() == leader.pid())
+                .map(|child| child.this()),
+        );
+
+        threads
+    }
+
+    /// Marks the process as exiting with `status`. Returns false if the process is already
+    /// exiting.
+    pub(super) fn start_group_exit(&self, status: ExitStatus) -> bool {
+        let mut first = false;
+
+        self.process_leader().group_exit.call_once(|| {
+            first = true;
+            status
+        });
+
+        first
+    }
+
+    /// Returns the exit status of the process if it is exiting.
+    pub fn group_exit_status(&self) -> Option<ExitStatus> {
+        self.process_leader().group_exit.get().cloned()
+    }
+
+    /// Sends `SIGKILL` to this thread only, rather than to the whole process.
+    pub(super) fn kill_thread(&self) {
+        use aero_syscall::signal::SIGKILL;
+
+        self.signals().trigger(SigInfo::new(SIGKILL, SI_KERNEL), true);
+        self.wake_up();
+    }
+
     pub fn exit_status(&self) -> &ExitStatus {
         self.exit_status.get().unwrap()
     }
 
+    /// Returns `true` if the task has exited.
+    pub fn has_exited(&self) -> bool {
+        self.exit_status.get().is_some()
+    }
+
     pub fn set_sleep_duration(&self, duration: usize) {
         self.sleep_duration.store(duration, Ordering::SeqCst);
     }
@@ -481,31 +1003,167 @@ impl Task {
         self.sleep_duration.load(Ordering::SeqCst)
     }
 
-    pub fn waitpid(
+    /// Returns the logical ID of the CPU that the task is scheduled on.
+    pub fn cpu(&self) -> usize {
+        self.cpu.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn set_cpu(&self, cpu: usize) {
+        self.cpu.store(cpu, Ordering::SeqCst);
+    }
+
+    /// Returns the mask of the CPUs that the task is allowed to run on.
+    pub fn affinity(&self) -> u64 {
+        self.affinity.load(Ordering::SeqCst)
+    }
+
+    pub fn set_affinity(&self, mask: u64) {
+        self.affinity.store(mask, Ordering::SeqCst);
+    }
+
+    pub fn nice(&self) -> i8 {
+        self.nice.load(Ordering::SeqCst)
+    }
+
+    /// Sets the nice value of the task, clamping it to the valid range.
+    pub fn set_nice(&self, nice: isize) {
+        self.nice
+            .store(nice.clamp(NICE_MIN, NICE_MAX) as i8, Ordering::SeqCst);
+    }
+
+    pub fn vruntime(&self) -> u64 {
+        self.vruntime.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn set_vruntime(&self, vruntime: u64) {
+        self.vruntime.store(vruntime, Ordering::SeqCst);
+    }
+
+    pub fn sched_policy(&self) -> SchedPolicy {
+        self.policy.load(Ordering::SeqCst).into()
+    }
+
+    pub fn set_sched_policy(&self, policy: SchedPolicy) {
+        self.policy.store(policy.into(), Ordering::SeqCst);
+    }
+
+    /// Returns whether the task is allowed to run on the CPU with the provided logical ID.
+    pub fn can_run_on(&self, cpu: usize) -> bool {
+        // CPUs that do not fit in the affinity mask cannot be excluded.
+        cpu >= u64::BITS as usize || self.affinity() & (1 << cpu) != 0
+    }
+
+    /// Waits for a state change of a child that matches `target`. Returns [`None`] if `WNOHANG`
+    /// was specified and none of the children have changed state yet.
+    pub fn wait(
         &self,
-        pid: isize,
-        status: &mut u32,
+        target: WaitTarget,
         flags: WaitPidFlags,
-    ) -> SignalResult<usize> {
-        if pid == -1 {
-            // wait for any child process if no specific process is requested.
-            //
-            // NOTE: we collect all of the zombie list's process IDs with the children
-            // list since the child could have been removed from the children list and
-            // become a zombie before the parent had a chance to wait for it.
-            let mut pids = self
-                .zombies
-                .list
-                .lock_irq()
-                .iter()
-                .map(|e| e.pid().as_usize())
-                .collect::<alloc::vec::Vec<_>>();
+    ) -> Result<Option<WaitResult>, SyscallError> {
+        let reap = !flags.contains(WaitPidFlags::WNOWAIT);
 
-            pids.extend(self.children.lock_irq().iter().map(|e| e.pid().as_usize()));
-            self.zombies.waitpid(&pids, status, flags)
-        } else {
-            self.zombies.waitpid(&[pid as _], status, flags)
+        let mut result = None;
+        let mut has_children = false;
+
+        self.zombies.block.block_on(&self.zombies.list, |zombies| {
+            has_children = false;
+
+            // NOTE: The zombie list is checked along with the children list, since the child
+            // could have been removed from the children list and become a zombie before the
+            // parent had a chance to wait for it.
+            let mut cursor = zombies.front_mut();
+
+            while let Some(zombie) = cursor.get() {
+                if !target.matches(self, zombie) {
+                    cursor.move_next();
+                    continue;
+                }
+
+                has_children = true;
+
+                if !flags.contains(WaitPidFlags::WEXITED) {
+                    cursor.move_next();
+                    continue;
+                }
+
+                let usage = zombie.usage.total();
+
+                result = Some(WaitResult {
+                    pid: self.child_pid(zombie),
+                    state: ChildState::Exited(zombie.exit_status().clone()),
+                    usage,
+                });
+
+                if reap {
+                    let zombie = cursor.remove().unwrap();
+
+                    // The threads of the process share its resource usage.
+                    if !Arc::ptr_eq(&zombie.usage, &self.usage) {
+                        self.usage.add_child(&usage);
+                    }
+                }
+
+                return true;
+            }
+
+            for child in self.children.lock_irq().iter() {
+                if !target.matches(self, child) {
+                    continue;
+                }
+
+                has_children = true;
+
+                let mut event = child.wait_event.lock_irq();
+                let state = match *event {
+                    Some(WaitEvent::Stopped(signal)) if flags.contains(WaitPidFlags::WUNTRACED) => {
+                        ChildState::Stopped(signal)
+                    }
+
+                    Some(WaitEvent::Continued) if flags.contains(WaitPidFlags::WCONTINUED) => {
+                        ChildState::Continued
+                    }
+
+                    _ => continue,
+                };
+
+                if reap {
+                    *event = None;
+                }
+
+                result = Some(WaitResult {
+                    pid: self.child_pid(child),
+                    state,
+                    usage: child.usage.total(),
+                });
+
+                return true;
+            }
+
+            // Do not block if there are no children to wait for.
+            !has_children || flags.contains(WaitPidFlags::WNOHANG)
+        })?;
+
+        if !has_children {
+            return Err(SyscallError::ECHILD);
         }
+
+        Ok(result)
+    }
+
+    /// Records the current resident set size of the process for its resource usage.
+    pub fn sample_rss(&self) {
+        let usage = self.vm.memory_usage();
+        self.usage.update_rss(usage.resident);
+    }
+
+    /// Returns the kernel thread information of the task if it was created with
+    /// [`spawn`](super::kthread::spawn).
+    pub fn kthread(&self) -> Option<&Arc<KThread>> {
+        self.kthread.get()
+    }
+
+    pub(super) fn set_kthread(&self, kthread: Arc<KThread>) {
+        self.kthread.call_once(|| kthread);
     }
 
     pub fn path(&self) -> Option<PathBuf> {
@@ -531,6 +1189,11 @@ impl Task {
 
         *self.mem_tags.lock() = HashMap::new();
         self.file_table.close_on_exec();
+        self.timers.clear_posix();
+        self.did_exec.store(true, Ordering::SeqCst);
+
+        // The memory of the old program is released, so record its peak usage first.
+        self.sample_rss();
 
         self.file_table.log();
 
@@ -538,10 +1201,15 @@ impl Task {
 
         let vm = self.vm();
         vm.clear();
+        shm::detach_all(self.pid().as_usize());
 
         // Clear the signals that are pending for this task on exec.
         self.signals().clear();
 
+        // The new program is entered straight from here, without returning from the system
+        // call.
+        self.set_in_syscall(false);
+
         self.arch_task_mut().exec(vm, executable, argv, envv)
     }
 
@@ -683,8 +1351,36 @@ impl Task {
         }
     }
 
+    /// Sends the provided `signal` to the task on behalf of the kernel.
     pub fn signal(&self, signal: usize) -> bool {
-        match self.signals().trigger(signal, false) {
+        self.signal_info(SigInfo::new(signal, SI_KERNEL))
+    }
+
+    /// Sends a signal generated by a fault of the task itself. Unlike [`Task::signal`], the
+    /// signal is delivered even if it is blocked or ignored, since the faulting instruction
+    /// would otherwise be retried forever.
+    pub fn force_signal(&self, info: SigInfo) -> bool {
+        self.signals().force(info.si_signo as usize);
+        self.signal_info(info)
+    }
+
+    /// Sends the signal described by `info` to the task.
+    pub fn signal_info(&self, info: SigInfo) -> bool {
+        use aero_syscall::signal::{SIGCONT, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU};
+
+        let signal = info.si_signo as usize;
+
+        // SIGCONT continues a stopped task, even if the signal is blocked or ignored, and
+        // discards any pending stop signals.
+        if signal == SIGCONT {
+            for stop in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU] {
+                self.signals().clear_pending(stop as u64);
+            }
+
+            self.resume();
+        }
+
+        match self.signals().trigger(info, false) {
             TriggerResult::Triggered => {
                 self.wake_up();
                 true
@@ -720,16 +1416,113 @@ impl Task {
         }
     }
 
+    /// Returns whether the task has been stopped by a job control signal.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the task has called `exec` since it was created.
+    pub fn did_exec(&self) -> bool {
+        self.did_exec.load(Ordering::SeqCst)
+    }
+
+    /// Stops the current task until it is continued by `SIGCONT` or killed by `SIGKILL`.
+    pub fn stop(&self, signal: usize) {
+        use aero_syscall::signal::SIGKILL;
+
+        self.stopped.store(true, Ordering::SeqCst);
+        self.notify_parent(WaitEvent::Stopped(signal));
+
+        // NOTE: Any other signals remain pending until the task is continued.
+        while self.is_stopped() && !self.signals().is_pending(SIGKILL as u64) {
+            let _ = scheduler::get_scheduler().inner.await_io();
+        }
+
+        self.stopped.store(false, Ordering::SeqCst);
+    }
+
+    /// Continues the task if it was stopped.
+    fn resume(&self) {
+        if self.stopped.swap(false, Ordering::SeqCst) {
+            self.wake_up();
+            self.notify_parent(WaitEvent::Continued);
+        }
+    }
+
+    /// Records a change of the job control state of the task and notifies the parent about it.
+    fn notify_parent(&self, event: WaitEvent) {
+        use aero_syscall::signal::{SignalFlags, CLD_CONTINUED, CLD_STOPPED, SIGCHLD, SIGCONT};
+
+        *self.wait_event.lock_irq() = Some(event);
+
+        if let Some(parent) = self.get_parent() {
+            parent.zombies.block.notify_all();
+
+            // The parent does not want to be notified when its children stop or continue.
+            let flags = parent.signals().entries()[SIGCHLD].flags();
+            if flags.contains(SignalFlags::SA_NOCLDSTOP) {
+                return;
+            }
+
+            let (code, status) = match event {
+                WaitEvent::Stopped(signal) => (CLD_STOPPED, signal),
+                WaitEvent::Continued => (CLD_CONTINUED, SIGCONT),
+            };
+
+            parent.signal_info(self.child_info(&parent, code, status as i32));
+        }
+    }
+
+    /// Returns the information of the `SIGCHLD` signal sent to the parent when the state of
+    /// the task changes.
+    fn child_info(&self, parent: &Task, code: i32, status: i32) -> SigInfo {
+        let mut info = SigInfo::new(aero_syscall::signal::SIGCHLD, code);
+        let usage = self.usage.get();
+
+        info.si_pid = parent.child_pid(self) as i32;
+        info.si_status = status;
+        info.si_utime = (usage.user / 10000000) as i64;
+        info.si_stime = (usage.system / 10000000) as i64;
+        info
+    }
+
     pub(super) fn make_zombie(&self) {
-        self.detach();
-        self.arch_task_mut().dealloc();
+        if self.is_process_leader() {
+            self.detach();
+        }
+
+        // Free up the memory used by the process if this was the last task using the VM.
+        let last_vm_user = Arc::strong_count(&self.vm) == 1;
+
+        if last_vm_user {
+            self.sample_rss();
+            self.vm.release(self.arch_task_mut().address_space());
+            shm::detach_all(self.pid().as_usize());
+        }
+
+        self.arch_task_mut().dealloc(last_vm_user);
+
+        // Stop the timers of the process if this was the last task in the process.
+        if Arc::strong_count(&self.timers) == 1 {
+            self.timers.clear();
+        }
 
         if let Some(parent) = self.get_parent() {
             parent.remove_child(self);
-            parent.zombies.add_zombie(self.this());
 
+            // The threads of a process are released as soon as they exit; only the exit of the
+            // process itself is reported to the parent.
             if self.is_process_leader() {
-                parent.signal(aero_syscall::signal::SIGCHLD);
+                use aero_syscall::signal::{CLD_EXITED, CLD_KILLED};
+
+                parent.zombies.add_zombie(self.this());
+
+                let (code, status) = match *self.exit_status() {
+                    ExitStatus::Normal(code) => (CLD_EXITED, code as i32),
+                    ExitStatus::Signal(signal) => (CLD_KILLED, signal as i32),
+                };
+
+                parent.signal_info(self.child_info(&parent, code, status));
             }
         }
     }
@@ -751,15 +1544,29 @@ impl Task {
         }
     }
 
-    pub fn attach(&self, terminal: Arc<dyn TerminalDevice>) {
-        if self.is_session_leader() {
-            terminal.attach(self.sref.upgrade().unwrap());
-            *self.controlling_terminal.lock_irq() = Some(terminal);
-        } else {
-            // FIXME: If its not the session leader then we needs to be a part of the the same
-            // session as the terminal's foreground group.
-            *self.controlling_terminal.lock_irq() = Some(terminal);
+    /// Makes `terminal` the controlling terminal of the session. Only a session leader that does
+    /// not have a controlling terminal yet can acquire one; returns whether the terminal was
+    /// attached.
+    ///
+    /// ## Notes
+    /// * The processes that are already part of the session do not inherit the controlling
+    ///   terminal, only the processes that are created afterwards.
+    pub fn attach(&self, terminal: Arc<dyn TerminalDevice>) -> bool {
+        let mut controlling_terminal = self.controlling_terminal.lock_irq();
+
+        if !self.is_session_leader() || controlling_terminal.is_some() {
+            return false;
         }
+
+        terminal.attach(self.this());
+        *controlling_terminal = Some(terminal);
+
+        true
+    }
+
+    /// Disassociates the task from its controlling terminal, without notifying the terminal.
+    pub fn clear_controlling_terminal(&self) {
+        self.controlling_terminal.lock_irq().take();
     }
 
     /// Returns the controlling terminal of the task.
@@ -769,12 +1576,12 @@ impl Task {
 
     /// Returns whether the task is the session leader (`pid` == `sid`).
     pub fn is_session_leader(&self) -> bool {
-        self.session_id() == self.pid().as_usize()
+        self.is_process_leader() && self.session_id() == self.pid().as_usize()
     }
 
     /// Returns whether the task is the group leader (`pid` == `gid`).
     pub fn is_group_leader(&self) -> bool {
-        self.group_id() == self.pid().as_usize()
+        self.is_process_leader() && self.group_id() == self.pid().as_usize()
     }
 
     /// Returns the group identifier of the task (`GID`).
@@ -0,0 +1,177 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! PID and mount namespaces.
+//!
+//! A PID namespace gives the processes created in it their own PIDs, starting from 1. The
+//! namespaces form a tree: a process is visible (with a different PID) in its own namespace and
+//! in all of the ancestors of it, but not in any other namespace. The PIDs in the root namespace
+//! are the task IDs.
+//!
+//! A mount namespace is a separate mount table (see [`MountManager`]).
+//!
+//! ## Notes
+//! * Process group and session IDs are not translated; they are always the task IDs of the
+//!   leaders.
+//! * Thread IDs are not translated either; the threads of a process share its PIDs.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::fs::MountManager;
+use crate::userland::scheduler;
+use crate::utils::sync::Mutex;
+
+use super::{Task, TaskId};
+
+/// Maximum nesting depth of the PID namespaces.
+const MAX_PID_NS_LEVEL: usize = 32;
+
+pub struct PidNamespace {
+    parent: Option<Arc<PidNamespace>>,
+    level: usize,
+    next_pid: AtomicUsize,
+    /// Task IDs of the processes in the namespace and its descendants, by their PID in this
+    /// namespace. Not used for the root namespace, where the PIDs are the task IDs.
+    tasks: Mutex<BTreeMap<usize, TaskId>>,
+}
+
+impl PidNamespace {
+    fn new(parent: Option<Arc<PidNamespace>>) -> Arc<Self> {
+        Arc::new(Self {
+            level: parent.as_ref().map_or(0, |parent| parent.level + 1),
+            parent,
+            next_pid: AtomicUsize::new(1),
+            tasks: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn root() -> &'static Arc<PidNamespace> {
+        &ROOT
+    }
+
+    /// Creates a new child namespace. Returns [`None`] if the maximum nesting depth has been
+    /// reached.
+    pub fn create_child(self: &Arc<Self>) -> Option<Arc<PidNamespace>> {
+        if self.level + 1 >= MAX_PID_NS_LEVEL {
+            return None;
+        }
+
+        Some(Self::new(Some(self.clone())))
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    /// Returns an iterator over this namespace and all of its ancestors.
+    fn ancestors(&self) -> impl Iterator<Item = &PidNamespace> {
+        core::iter::successors(Some(self), |ns| ns.parent.as_deref())
+    }
+
+    /// Returns whether this namespace is `other` or one of its ancestors.
+    pub fn contains(&self, other: &PidNamespace) -> bool {
+        other.ancestors().any(|ns| core::ptr::eq(ns, self))
+    }
+
+    /// Allocates the PIDs of a new process in this namespace and in each of its ancestors.
+    /// Returns the PIDs indexed by the level of the namespace.
+    pub fn alloc_pids(&self, id: TaskId) -> Vec<usize> {
+        let mut pids = vec![0; self.level + 1];
+
+        for ns in self.ancestors() {
+            pids[ns.level] = if ns.is_root() {
+                id.as_usize()
+            } else {
+                let pid = ns.next_pid.fetch_add(1, Ordering::SeqCst);
+
+                ns.tasks.lock_irq().insert(pid, id);
+                pid
+            };
+        }
+
+        pids
+    }
+
+    /// Called when `task` exits. If the task was the init process (PID 1) of the namespace, all
+    /// of the other processes in the namespace are killed.
+    pub fn detach(&self, task: &Task) {
+        for ns in self.ancestors().filter(|ns| !ns.is_root()) {
+            ns.tasks.lock_irq().remove(&task.ns_pids[ns.level]);
+        }
+
+        if self.is_root() || task.vpid() != 1 {
+            return;
+        }
+
+        let tasks = self.tasks.lock_irq().values().copied().collect::<Vec<_>>();
+
+        for id in tasks {
+            if let Some(task) = scheduler::get_scheduler().find_task(id) {
+                task.signal(aero_syscall::signal::SIGKILL);
+            }
+        }
+    }
+
+    /// Returns the process with the provided PID in this namespace.
+    pub fn find_task(&self, pid: usize) -> Option<Arc<Task>> {
+        let id = if self.is_root() {
+            TaskId::new(pid)
+        } else {
+            *self.tasks.lock_irq().get(&pid)?
+        };
+
+        scheduler::get_scheduler().find_task(id)
+    }
+
+    /// Returns the sorted PIDs of the processes visible in this namespace.
+    pub fn pids(&self) -> Vec<usize> {
+        if !self.is_root() {
+            return self.tasks.lock_irq().keys().copied().collect();
+        }
+
+        let mut pids = Vec::new();
+
+        scheduler::get_scheduler().for_each_task(|task| {
+            if task.is_process_leader() {
+                pids.push(task.pid().as_usize());
+            }
+        });
+
+        pids.sort_unstable();
+        pids
+    }
+}
+
+/// A reference to a namespace, as obtained from the `/proc/<pid>/ns` files (see `setns`).
+#[derive(Clone)]
+pub enum Namespace {
+    Pid(Arc<PidNamespace>),
+    Mount(Arc<MountManager>),
+}
+
+lazy_static::lazy_static! {
+    static ref ROOT: Arc<PidNamespace> = PidNamespace::new(None);
+}
@@ -0,0 +1,184 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Resource Usage
+//!
+//! Each process keeps track of the CPU time and memory that it has consumed, which is shared by
+//! all of the threads in the process. The usage of the children is accumulated once they have
+//! been waited for (see `getrusage(2)`).
+//!
+//! The CPU time is accounted when a thread is switched out and at the system call boundaries,
+//! so that it is split precisely between user and system time. Each thread additionally keeps
+//! track of its own CPU time (see [`CpuTime`]).
+
+use aero_syscall::time::{RUsage, TimeVal};
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::mem::paging::{PageSize, Size4KiB};
+use crate::utils::sync::Mutex;
+
+/// Snapshot of the resource usage of a process.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Usage {
+    /// CPU time spent in user mode (in nanoseconds).
+    pub user: u64,
+    /// CPU time spent in kernel mode (in nanoseconds).
+    pub system: u64,
+    /// Maximum resident set size (in 4KiB pages).
+    pub max_rss: usize,
+}
+
+impl Usage {
+    /// Accumulates the usage of `other` into this usage.
+    fn add(&mut self, other: &Usage) {
+        self.user += other.user;
+        self.system += other.system;
+        self.max_rss = core::cmp::max(self.max_rss, other.max_rss);
+    }
+
+    /// Returns the total CPU time (in nanoseconds).
+    pub fn cpu_time(&self) -> u64 {
+        self.user + self.system
+    }
+
+    pub fn rusage(&self) -> RUsage {
+        let timeval = |ns: u64| TimeVal {
+            tv_sec: (ns / 1000000000) as i64,
+            tv_usec: (ns % 1000000000 / 1000) as i64,
+        };
+
+        RUsage {
+            ru_utime: timeval(self.user),
+            ru_stime: timeval(self.system),
+            ru_maxrss: (self.max_rss * Size4KiB::SIZE as usize / 1024) as i64,
+            ..Default::default()
+        }
+    }
+}
+
+/// CPU time consumed by a single thread.
+pub struct CpuTime {
+    user: AtomicU64,
+    system: AtomicU64,
+    /// Time (in nanoseconds since boot) from which the CPU time of the thread has not been
+    /// accounted yet.
+    since: AtomicU64,
+}
+
+impl CpuTime {
+    pub fn new() -> Self {
+        Self {
+            user: AtomicU64::new(0),
+            system: AtomicU64::new(0),
+            since: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts accounting the CPU time of the thread from `now`, as it is switched in.
+    pub fn start(&self, now: u64) {
+        self.since.store(now, Ordering::Relaxed);
+    }
+
+    /// Accounts the CPU time that the thread has consumed up to `now`, spent in kernel mode if
+    /// `system` is set. Returns the amount of CPU time (in nanoseconds) that was accounted.
+    pub fn account(&self, now: u64, system: bool) -> u64 {
+        let elapsed = now.saturating_sub(self.since.swap(now, Ordering::Relaxed));
+
+        if system {
+            self.system.fetch_add(elapsed, Ordering::Relaxed);
+        } else {
+            self.user.fetch_add(elapsed, Ordering::Relaxed);
+        }
+
+        elapsed
+    }
+
+    /// Returns the CPU time that the thread has consumed.
+    pub fn get(&self) -> Usage {
+        Usage {
+            user: self.user.load(Ordering::Relaxed),
+            system: self.system.load(Ordering::Relaxed),
+            max_rss: 0,
+        }
+    }
+}
+
+pub struct ResourceUsage {
+    user: AtomicU64,
+    system: AtomicU64,
+    max_rss: AtomicUsize,
+    /// Accumulated usage of the children that have been waited for.
+    children: Mutex<Usage>,
+}
+
+impl ResourceUsage {
+    pub fn new() -> Self {
+        Self {
+            user: AtomicU64::new(0),
+            system: AtomicU64::new(0),
+            max_rss: AtomicUsize::new(0),
+            children: Mutex::new(Usage::default()),
+        }
+    }
+
+    /// Charges the process for `ns` nanoseconds of CPU time, spent in kernel mode if `system`
+    /// is set.
+    pub fn charge(&self, ns: u64, system: bool) {
+        if system {
+            self.system.fetch_add(ns, Ordering::Relaxed);
+        } else {
+            self.user.fetch_add(ns, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the current resident set size (in 4KiB pages) of the process.
+    ///
+    /// ## Notes
+    /// * The resident set size is sampled (for example, on `exec` and on exit) instead of being
+    ///   tracked on every page fault, so the maximum might be underestimated.
+    pub fn update_rss(&self, resident: usize) {
+        self.max_rss.fetch_max(resident, Ordering::Relaxed);
+    }
+
+    /// Returns the resource usage of the process itself.
+    pub fn get(&self) -> Usage {
+        Usage {
+            user: self.user.load(Ordering::Relaxed),
+            system: self.system.load(Ordering::Relaxed),
+            max_rss: self.max_rss.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the accumulated resource usage of the children that have been waited for.
+    pub fn children(&self) -> Usage {
+        *self.children.lock_irq()
+    }
+
+    /// Returns the resource usage of the process, including the children that have been waited
+    /// for.
+    pub fn total(&self) -> Usage {
+        let mut usage = self.get();
+        usage.add(&self.children());
+        usage
+    }
+
+    /// Accumulates the usage of a child that has been waited for.
+    pub fn add_child(&self, usage: &Usage) {
+        self.children.lock_irq().add(usage);
+    }
+}
@@ -15,7 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use aero_syscall::signal::{SIGCONT, SIGHUP};
+
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use hashbrown::HashMap;
 
 use crate::utils::sync::Mutex;
@@ -34,18 +37,11 @@ pub struct Group {
 }
 
 impl Group {
-    /// Creates a new process group.
-    pub fn new(leader: Arc<Task>) -> Arc<Self> {
-        leader.set_group_id(leader.pid().as_usize());
-
-        let id = leader.pid().as_usize();
-
-        let mut tasks = HashMap::new();
-        tasks.insert(leader.pid(), leader);
-
+    /// Creates a new empty process group.
+    fn new(id: usize) -> Arc<Self> {
         Arc::new(Self {
             id,
-            tasks: Mutex::new(tasks),
+            tasks: Mutex::new(HashMap::new()),
         })
     }
 
@@ -53,18 +49,33 @@ impl Group {
         self.id
     }
 
-    pub fn register_task(&self, task: Arc<Task>) {
+    fn register_task(&self, task: Arc<Task>) {
+        task.set_group_id(self.id);
         assert!(self.tasks.lock_irq().insert(task.pid(), task).is_none());
     }
 
-    pub fn remove_task(&self, task: &Arc<Task>) {
+    fn remove_task(&self, task: &Arc<Task>) {
         assert!(self.tasks.lock_irq().remove(&task.pid()).is_some());
     }
 
+    fn contains(&self, task: &Task) -> bool {
+        self.tasks.lock_irq().contains_key(&task.pid())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.tasks.lock_irq().is_empty()
     }
 
+    /// Returns the processes in the process group.
+    pub fn tasks(&self) -> Vec<Arc<Task>> {
+        self.tasks.lock_irq().values().cloned().collect()
+    }
+
+    /// Returns whether any of the processes in the process group are stopped.
+    fn has_stopped_tasks(&self) -> bool {
+        self.tasks.lock_irq().values().any(|task| task.is_stopped())
+    }
+
     pub fn signal(&self, target: usize) {
         for (_, task) in self.tasks.lock_irq().iter() {
             log::error!("Sending signal to task: {:?}", task.path());
@@ -76,53 +87,148 @@ impl Group {
 
 /// Process Session
 pub struct Session {
+    /// Unique identifier of the session (the PID of the session leader).
+    id: usize,
     groups: Mutex<HashMap<usize, Arc<Group>>>,
 }
 
 impl Session {
     /// Creates a new process session.
     pub fn new(leader: Arc<Task>) -> Arc<Self> {
-        leader.set_session_id(leader.pid().as_usize());
+        let id = leader.pid().as_usize();
+        leader.set_session_id(id);
+
+        let group = Group::new(id);
+        group.register_task(leader);
 
         let mut groups = HashMap::new();
-        groups.insert(leader.pid().as_usize(), Group::new(leader));
+        groups.insert(id, group);
 
         Arc::new(Self {
+            id,
             groups: Mutex::new(groups),
         })
     }
 
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
     pub fn find(&self, target: &Arc<Task>) -> Option<Arc<Group>> {
-        self.groups.lock_irq().get(&target.group_id()).cloned()
+        self.find_group(target.group_id())
     }
 
-    pub fn register_task(&self, task: Arc<Task>) {
+    /// Returns the process group with the provided ID in the session.
+    pub fn find_group(&self, group_id: usize) -> Option<Arc<Group>> {
+        self.groups.lock_irq().get(&group_id).cloned()
+    }
+
+    fn register_task(&self, task: Arc<Task>) {
         assert!(!task.is_session_leader());
 
         let mut groups = self.groups.lock_irq();
-        if let Some(group) = groups.get(&task.group_id()) {
-            assert!(!task.is_group_leader());
-            group.register_task(task);
-        } else {
-            assert!(task.is_group_leader());
-            groups.insert(task.group_id(), Group::new(task));
-        }
+        let group_id = task.group_id();
+
+        groups
+            .entry(group_id)
+            .or_insert_with(|| Group::new(group_id))
+            .register_task(task);
     }
 
-    pub fn remove_task(&self, task: &Arc<Task>) {
-        let mut groups = self.groups.lock();
+    fn remove_task(&self, task: &Arc<Task>) {
+        let mut groups = self.groups.lock_irq();
+        Self::remove_from(&mut groups, task);
+    }
+
+    fn remove_from(groups: &mut HashMap<usize, Arc<Group>>, task: &Arc<Task>) {
         let group = groups
             .get(&task.group_id())
             .expect("Session::remove_task: ESRCH");
 
         group.remove_task(task);
 
+        // NOTE: The process group lives on after its leader exits, as long as any other process
+        // is still part of it.
         if group.is_empty() {
-            assert!(task.is_group_leader());
             groups.remove(&task.group_id());
         }
     }
 
+    /// Moves `task` into the process group `group_id` of the session. A new process group is
+    /// created if `group_id` is the PID of the task. Returns `false` if there is no such process
+    /// group in the session.
+    fn move_task(&self, task: &Arc<Task>, group_id: usize) -> bool {
+        let mut groups = self.groups.lock_irq();
+
+        if task.group_id() == group_id {
+            return true;
+        }
+
+        let group = match groups.get(&group_id) {
+            Some(group) => group.clone(),
+            None if group_id == task.pid().as_usize() => {
+                let group = Group::new(group_id);
+                groups.insert(group_id, group.clone());
+                group
+            }
+
+            None => return false,
+        };
+
+        Self::remove_from(&mut groups, task);
+        group.register_task(task.clone());
+
+        true
+    }
+
+    /// Returns all of the processes in the session.
+    fn tasks(&self) -> Vec<Arc<Task>> {
+        self.groups
+            .lock_irq()
+            .values()
+            .flat_map(|group| group.tasks.lock_irq().values().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// Disassociates all of the processes in the session from their controlling terminal.
+    pub fn clear_controlling_terminal(&self) {
+        for task in self.tasks() {
+            task.clear_controlling_terminal();
+        }
+    }
+
+    /// Returns whether the provided process group is orphaned. A process group is orphaned when
+    /// the parent of every member is either a member of the group itself or is not a member of
+    /// the session, in which case no process can control the group through job control.
+    fn is_orphaned(&self, group: &Group) -> bool {
+        // NOTE: The parents are collected first, as the locks of two process groups must never be
+        // held at the same time.
+        let parents = group
+            .tasks
+            .lock_irq()
+            .values()
+            .filter_map(|task| task.get_parent())
+            .collect::<Vec<_>>();
+
+        !parents.iter().any(|parent| {
+            parent.group_id() != group.id()
+                && self
+                    .find_group(parent.group_id())
+                    .is_some_and(|parent_group| parent_group.contains(parent))
+        })
+    }
+
+    /// Sends `SIGHUP` followed by `SIGCONT` to each of the provided process groups that are
+    /// orphaned and have stopped members, as nothing would ever continue them otherwise.
+    fn hangup_orphaned(&self, group_ids: &[usize]) {
+        for group in group_ids.iter().filter_map(|id| self.find_group(*id)) {
+            if group.has_stopped_tasks() && self.is_orphaned(&group) {
+                group.signal(SIGHUP);
+                group.signal(SIGCONT);
+            }
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.groups.lock_irq().is_empty()
     }
@@ -138,10 +244,39 @@ impl SessionList {
             .insert(leader.pid().as_usize(), Session::new(leader));
     }
 
+    /// Returns the session with the provided ID.
+    pub fn find(&self, session_id: usize) -> Option<Arc<Session>> {
+        self.0.lock_irq().get(&session_id).cloned()
+    }
+
     pub fn find_group(&self, target: &Arc<Task>) -> Option<Arc<Group>> {
         self.0.lock_irq().get(&target.session_id())?.find(target)
     }
 
+    /// Returns whether the process group of `task` is orphaned.
+    pub fn is_orphaned(&self, task: &Arc<Task>) -> bool {
+        let Some(session) = self.find(task.session_id()) else {
+            return true;
+        };
+
+        session
+            .find(task)
+            .map_or(true, |group| session.is_orphaned(&group))
+    }
+
+    /// Returns whether a process group with the provided ID exists in any of the sessions.
+    pub fn group_exists(&self, group_id: usize) -> bool {
+        self.find_group_by_id(group_id).is_some()
+    }
+
+    /// Returns the process group with the provided ID, in any of the sessions.
+    pub fn find_group_by_id(&self, group_id: usize) -> Option<Arc<Group>> {
+        self.0
+            .lock_irq()
+            .values()
+            .find_map(|session| session.find_group(group_id))
+    }
+
     pub fn register_task(&self, task: Arc<Task>) {
         assert!(task.is_process_leader());
 
@@ -155,31 +290,62 @@ impl SessionList {
         }
     }
 
-    pub fn remove_task(&self, task: &Arc<Task>) {
+    /// Removes the session of `task` from the list if the task was the last process in it.
+    /// Returns the session.
+    fn detach_task(&self, task: &Arc<Task>) -> Arc<Session> {
         let mut sessions = self.0.lock_irq();
         let session = sessions
             .get(&task.session_id())
+            .cloned()
             .expect("SessionList::remove_task: ESRCH");
 
         session.remove_task(task);
 
         if session.is_empty() {
-            assert!(task.is_session_leader());
             sessions.remove(&task.session_id());
         }
-    }
 
-    pub fn isolate(&self, task: &Arc<Task>) {
-        assert!(!task.is_group_leader() && !task.is_session_leader());
+        session
+    }
 
-        let leader = task.process_leader();
+    pub fn remove_task(&self, task: &Arc<Task>) {
+        let session = self.detach_task(task);
 
-        {
-            let mut sessions = self.0.lock_irq();
-            sessions.remove(&task.session_id());
+        // The controlling terminal is disassociated from the session once the session leader
+        // exits. The foreground process group is hung up by the terminal itself (see
+        // [`Task::detach`]).
+        if task.is_session_leader() {
+            session.clear_controlling_terminal();
         }
 
-        self.create_session(leader)
+        // The exit of `task` might orphan its own process group and the process groups of its
+        // children, if it was their only link to another process group in the session.
+        let mut group_ids = alloc::vec![task.group_id()];
+        group_ids.extend(
+            task.children
+                .lock_irq()
+                .iter()
+                .filter(|child| child.session_id() == session.id())
+                .map(|child| child.group_id()),
+        );
+
+        session.hangup_orphaned(&group_ids);
+    }
+
+    /// Moves `task` into the process group `group_id` of its session. Returns `false` if there
+    /// is no such process group in the session.
+    pub fn set_group(&self, task: &Arc<Task>, group_id: usize) -> bool {
+        self.find(task.session_id())
+            .expect("SessionList::set_group: ESRCH")
+            .move_task(task, group_id)
+    }
+
+    /// Moves `task` into a new session, of which it becomes the leader.
+    pub fn isolate(&self, task: &Arc<Task>) {
+        assert!(!task.is_group_leader() && !task.is_session_leader());
+
+        self.detach_task(task);
+        self.create_session(task.clone());
     }
 }
 
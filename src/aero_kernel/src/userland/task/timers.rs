@@ -0,0 +1,343 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Process Timers
+//!
+//! Each process has three interval timers (see `setitimer(2)`) and any number of POSIX timers
+//! (see `timer_create(2)`), which are shared by all of the threads in the process. The real
+//! time interval timer and the POSIX timers are driven by high-resolution timers, while the
+//! virtual and profiling interval timers count down the CPU time consumed by the process.
+
+use aero_syscall::signal::{SigInfo, SIGALRM, SIGPROF, SIGVTALRM, SI_KERNEL, SI_TIMER};
+use aero_syscall::time::{CLOCK_MONOTONIC, ITIMER_PROF, ITIMER_REAL, ITIMER_VIRTUAL};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::userland::scheduler::hrtimer::{self, HrTimer};
+use crate::utils::sync::Mutex;
+
+use super::Task;
+
+/// Value of a timer (in nanoseconds).
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct TimerValue {
+    /// Time until the next expiration; zero if the timer is disarmed.
+    pub value: u64,
+    /// Period of the timer; zero if the timer is a one-shot timer.
+    pub interval: u64,
+}
+
+#[derive(Default)]
+struct RealTimerState {
+    /// Expiry time on the monotonic clock; zero if the timer is disarmed.
+    expires: u64,
+    interval: u64,
+    /// Number of expirations that happened while the signal of the timer was still pending.
+    overrun: usize,
+    hrtimer: Option<HrTimer>,
+}
+
+/// Timer that expires in real time and delivers a signal to the process on expiry.
+pub struct RealTimer {
+    sref: Weak<RealTimer>,
+    task: Weak<Task>,
+    /// ID of the clock that the timer measures (one of `CLOCK_*`).
+    clock: usize,
+    /// Signal to deliver on expiry; [`None`] if the expirations are not notified.
+    signal: Option<SigInfo>,
+    state: Mutex<RealTimerState>,
+}
+
+impl RealTimer {
+    fn new(task: Weak<Task>, clock: usize, signal: Option<SigInfo>) -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            sref: sref.clone(),
+            task,
+            clock,
+            signal,
+            state: Mutex::new(RealTimerState::default()),
+        })
+    }
+
+    fn start(&self, state: &mut RealTimerState, expires: u64) {
+        let timer = self.sref.clone();
+
+        state.expires = expires;
+        state.hrtimer = Some(HrTimer::start(expires, move || {
+            if let Some(timer) = timer.upgrade() {
+                timer.expire();
+            }
+        }));
+    }
+
+    fn expire(&self) {
+        let now = hrtimer::now();
+        let mut state = self.state.lock_irq();
+
+        // The timer was disarmed or re-armed before the callback was invoked.
+        if state.expires == 0 || state.expires > now {
+            return;
+        }
+
+        if state.interval == 0 {
+            state.expires = 0;
+            state.hrtimer = None;
+        } else {
+            // Skip the periods that were missed entirely, counting them as overruns.
+            let missed = (now - state.expires) / state.interval;
+            let expires = state.expires + (missed + 1) * state.interval;
+
+            state.overrun += missed as usize;
+            self.start(&mut state, expires);
+        }
+
+        let (Some(signal), Some(task)) = (self.signal, self.task.upgrade()) else {
+            return;
+        };
+
+        if task.signals().is_pending(signal.si_signo as u64) {
+            state.overrun += 1;
+        } else {
+            state.overrun = 0;
+            core::mem::drop(state);
+
+            task.signal_info(signal);
+        }
+    }
+
+    /// Arms the timer with the provided value, where `expires` is the first expiry time on the
+    /// monotonic clock (or zero to disarm the timer). Returns the previous value of the timer.
+    pub fn set(&self, expires: u64, interval: u64) -> TimerValue {
+        let mut state = self.state.lock_irq();
+        let old = Self::value(&state);
+
+        state.hrtimer = None;
+        state.expires = 0;
+        state.interval = interval;
+        state.overrun = 0;
+
+        if expires != 0 {
+            self.start(&mut state, expires);
+        }
+
+        old
+    }
+
+    pub fn clock(&self) -> usize {
+        self.clock
+    }
+
+    /// Returns the current value of the timer.
+    pub fn get(&self) -> TimerValue {
+        Self::value(&self.state.lock_irq())
+    }
+
+    /// Returns the overrun count of the last expiration that was notified.
+    pub fn overrun(&self) -> usize {
+        self.state.lock_irq().overrun
+    }
+
+    fn value(state: &RealTimerState) -> TimerValue {
+        if state.expires == 0 {
+            return TimerValue {
+                value: 0,
+                interval: state.interval,
+            };
+        }
+
+        TimerValue {
+            // NOTE: An expired timer that is about to be re-armed is reported with the smallest
+            // possible non-zero value, as zero means that the timer is disarmed.
+            value: core::cmp::max(state.expires.saturating_sub(hrtimer::now()), 1),
+            interval: state.interval,
+        }
+    }
+}
+
+/// Timer that counts down the CPU time consumed by the process.
+#[derive(Default)]
+struct CpuTimer {
+    value: TimerValue,
+}
+
+impl CpuTimer {
+    /// Charges the timer for `ns` nanoseconds of CPU time. Returns whether the timer expired.
+    fn charge(&mut self, ns: u64) -> bool {
+        let value = &mut self.value;
+
+        if value.value == 0 {
+            return false;
+        }
+
+        if value.value > ns {
+            value.value -= ns;
+            return false;
+        }
+
+        let overshoot = ns - value.value;
+
+        value.value = if value.interval == 0 {
+            0
+        } else {
+            value.interval - overshoot % value.interval
+        };
+
+        true
+    }
+}
+
+pub struct Timers {
+    task: Weak<Task>,
+
+    real: Arc<RealTimer>,
+    virt: Mutex<CpuTimer>,
+    prof: Mutex<CpuTimer>,
+
+    posix: Mutex<BTreeMap<usize, Arc<RealTimer>>>,
+    next_id: AtomicUsize,
+}
+
+impl Timers {
+    /// Creates the timers of the provided process, all of which are disarmed.
+    pub fn new(task: Weak<Task>) -> Self {
+        Self {
+            real: RealTimer::new(
+                task.clone(),
+                CLOCK_MONOTONIC,
+                Some(SigInfo::new(SIGALRM, SI_KERNEL)),
+            ),
+            virt: Mutex::new(CpuTimer::default()),
+            prof: Mutex::new(CpuTimer::default()),
+
+            posix: Mutex::new(BTreeMap::new()),
+            next_id: AtomicUsize::new(0),
+
+            task,
+        }
+    }
+
+    /// Sets the interval timer `which` (one of `ITIMER_*`) to the provided value. Returns the
+    /// previous value of the timer or [`None`] if `which` is invalid.
+    pub fn set_itimer(&self, which: usize, value: TimerValue) -> Option<TimerValue> {
+        match which {
+            ITIMER_REAL => {
+                let expires = if value.value == 0 {
+                    0
+                } else {
+                    hrtimer::now().saturating_add(value.value)
+                };
+
+                Some(self.real.set(expires, value.interval))
+            }
+
+            ITIMER_VIRTUAL => Some(core::mem::replace(&mut self.virt.lock_irq().value, value)),
+            ITIMER_PROF => Some(core::mem::replace(&mut self.prof.lock_irq().value, value)),
+            _ => None,
+        }
+    }
+
+    /// Returns the current value of the interval timer `which` (one of `ITIMER_*`) or [`None`]
+    /// if `which` is invalid.
+    pub fn get_itimer(&self, which: usize) -> Option<TimerValue> {
+        match which {
+            ITIMER_REAL => Some(self.real.get()),
+            ITIMER_VIRTUAL => Some(self.virt.lock_irq().value),
+            ITIMER_PROF => Some(self.prof.lock_irq().value),
+            _ => None,
+        }
+    }
+
+    /// Charges the process for `ns` nanoseconds of CPU time.
+    ///
+    /// ## Notes
+    /// * The time spent in the kernel is not accounted separately, so the virtual interval
+    ///   timer counts down the same CPU time as the profiling interval timer.
+    pub fn charge(&self, ns: u64) {
+        let virt_expired = self.virt.lock_irq().charge(ns);
+        let prof_expired = self.prof.lock_irq().charge(ns);
+
+        if !virt_expired && !prof_expired {
+            return;
+        }
+
+        let Some(task) = self.task.upgrade() else {
+            return;
+        };
+
+        if virt_expired {
+            task.signal(SIGVTALRM);
+        }
+
+        if prof_expired {
+            task.signal(SIGPROF);
+        }
+    }
+
+    /// Creates a new disarmed POSIX timer, which delivers `signal` along with `value` on expiry
+    /// (if provided). Returns the ID of the timer.
+    pub fn create(&self, clock: usize, signal: Option<usize>, value: u64) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let signal = signal.map(|signal| {
+            let mut info = SigInfo::new(signal, SI_TIMER);
+            info.set_value(value);
+            info
+        });
+
+        let timer = RealTimer::new(self.task.clone(), clock, signal);
+
+        self.posix.lock_irq().insert(id, timer);
+        id
+    }
+
+    /// Returns the POSIX timer with the provided ID.
+    pub fn get(&self, id: usize) -> Option<Arc<RealTimer>> {
+        self.posix.lock_irq().get(&id).cloned()
+    }
+
+    /// Deletes the POSIX timer with the provided ID. Returns whether the timer existed.
+    pub fn delete(&self, id: usize) -> bool {
+        // NOTE: The timer is disarmed before being dropped, as the lock must not be held while
+        // its high-resolution timer is cancelled.
+        let Some(timer) = self.posix.lock_irq().remove(&id) else {
+            return false;
+        };
+
+        timer.set(0, 0);
+        true
+    }
+
+    /// Deletes all of the POSIX timers, as they are not preserved across `exec`.
+    pub fn clear_posix(&self) {
+        let timers = core::mem::take(&mut *self.posix.lock_irq());
+
+        for timer in timers.values() {
+            timer.set(0, 0);
+        }
+    }
+
+    /// Disarms all of the timers of the process.
+    pub fn clear(&self) {
+        self.clear_posix();
+        self.real.set(0, 0);
+
+        *self.virt.lock_irq() = CpuTimer::default();
+        *self.prof.lock_irq() = CpuTimer::default();
+    }
+}
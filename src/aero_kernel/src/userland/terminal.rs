@@ -15,6 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use aero_syscall::{signal, Termios, TermiosIFlag, TermiosLFlag};
 
 use alloc::sync::{Arc, Weak};
@@ -23,10 +25,12 @@ use alloc::vec::Vec;
 use spin::RwLock;
 
 use crate::fs::inode::INodeInterface;
+use crate::fs::FileSystemError;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::scheduler;
 use super::signals::SignalError;
-use super::task::sessions::{Group, SESSIONS};
+use super::task::sessions::{Group, Session, SESSIONS};
 use super::task::Task;
 
 /// Terminal Device
@@ -40,6 +44,46 @@ pub trait TerminalDevice: Send + Sync + INodeInterface {
     fn detach(&self, task: Arc<Task>);
 }
 
+/// Whether input can be injected into terminals with `TIOCSTI` (see
+/// `/proc/sys/dev/tty/legacy_tiocsti`).
+static LEGACY_TIOCSTI: AtomicBool = AtomicBool::new(true);
+
+pub fn legacy_tiocsti() -> bool {
+    LEGACY_TIOCSTI.load(Ordering::Relaxed)
+}
+
+pub fn set_legacy_tiocsti(enabled: bool) {
+    LEGACY_TIOCSTI.store(enabled, Ordering::Relaxed);
+}
+
+/// Checks whether the current process is allowed to inject input into `terminal` with
+/// `TIOCSTI`, which is only the case for its controlling terminal.
+///
+/// ## Errors
+/// * `FileSystemError::Io` - Injecting input has been disabled.
+/// * `FileSystemError::PermissionDenied` - `terminal` is not the controlling terminal of the
+///   current process.
+pub fn check_tiocsti(terminal: &dyn TerminalDevice) -> Result<(), FileSystemError> {
+    if !legacy_tiocsti() {
+        return Err(FileSystemError::Io);
+    }
+
+    let is_controlling = scheduler::current_thread()
+        .controlling_terminal()
+        .is_some_and(|controlling| {
+            core::ptr::addr_eq(
+                Arc::as_ptr(&controlling),
+                terminal as *const dyn TerminalDevice,
+            )
+        });
+
+    if !is_controlling {
+        return Err(FileSystemError::PermissionDenied);
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum LineControl {
     Echo(u8),
@@ -56,11 +100,17 @@ pub struct LineDiscipline {
     wq: WaitQueue,
     buffer: Mutex<Vec<u8>>,
     foreground: RwLock<Weak<Group>>,
+    /// Session that the terminal is the controlling terminal of.
+    session: RwLock<Weak<Session>>,
     // TODO: Make this private.
     pub termios: Mutex<Termios>,
 }
 
 impl LineDiscipline {
+    /// Maximum number of bytes of input that are buffered until they are read
+    /// (`N_TTY_BUF_SIZE`).
+    pub const BUF_SIZE: usize = 4096;
+
     /// Creates a new line discipline.
     pub fn new() -> Self {
         use aero_syscall::{TermiosCFlag, TermiosOFlag};
@@ -100,6 +150,7 @@ impl LineDiscipline {
             wq: WaitQueue::new(),
             buffer: Mutex::new(Vec::new()),
             foreground: RwLock::new(Weak::default()),
+            session: RwLock::new(Weak::default()),
             termios: Mutex::new(termios),
         }
     }
@@ -120,10 +171,16 @@ impl LineDiscipline {
         let size = core::cmp::min(target.len(), buffer.len());
         target[..size].copy_from_slice(&buffer.drain(..size).collect::<Vec<_>>());
 
+        // Wake up the writers waiting for room in the buffer.
+        drop(buffer);
+        self.wq.notify_all();
+
         Ok(size)
     }
 
-    pub fn write<F>(&self, target: &[u8], callback: F)
+    /// Passes `target` through the line discipline and returns the number of bytes consumed,
+    /// which stops short once the input buffer is full.
+    pub fn write<F>(&self, target: &[u8], callback: F) -> usize
     where
         F: Fn(LineControl),
     {
@@ -131,7 +188,15 @@ impl LineDiscipline {
         let termios = self.termios.lock();
         let should_echo = termios.c_lflag.contains(TermiosLFlag::ECHO);
 
+        let mut count = 0;
+
         for byte in target {
+            if buffer.len() >= Self::BUF_SIZE {
+                break;
+            }
+
+            count += 1;
+
             match byte {
                 // ETX: End of Text (`Ctrl+C`)
                 0x3 if termios.is_cooked() => {
@@ -164,14 +229,90 @@ impl LineDiscipline {
 
         drop(buffer);
         self.wq.notify_all();
+        count
     }
 
     pub fn foreground(&self) -> Option<Arc<Group>> {
         self.foreground.read().upgrade()
     }
 
-    pub fn set_foreground(&self, task: &Arc<Task>) {
-        *self.foreground.write() = Arc::downgrade(&SESSIONS.find_group(task).unwrap());
+    pub fn set_foreground(&self, group: &Arc<Group>) {
+        *self.foreground.write() = Arc::downgrade(group);
+    }
+
+    /// Returns the session that the terminal is the controlling terminal of.
+    pub fn session(&self) -> Option<Arc<Session>> {
+        self.session.read().upgrade()
+    }
+
+    /// Makes the terminal the controlling terminal of the session of `task`, with the process
+    /// group of `task` as the foreground process group.
+    pub fn attach(&self, task: &Arc<Task>) {
+        let session = SESSIONS.find(task.session_id()).unwrap();
+
+        self.set_foreground(&session.find(task).unwrap());
+        *self.session.write() = Arc::downgrade(&session);
+    }
+
+    /// Disassociates the terminal from its session and sends `SIGHUP` followed by `SIGCONT` to
+    /// the foreground process group.
+    pub fn hangup(&self) {
+        *self.session.write() = Weak::default();
+
+        let foreground = core::mem::take(&mut *self.foreground.write()).upgrade();
+
+        if let Some(foreground) = foreground {
+            foreground.signal(signal::SIGHUP);
+            foreground.signal(signal::SIGCONT);
+        }
+    }
+
+    /// Applies job control to an access to the terminal by the current process: a process in
+    /// a background process group of the session of the terminal is sent `signal` (`SIGTTIN`
+    /// for reads and `SIGTTOU` for writes, if `TOSTOP` is set) and the access is interrupted,
+    /// so that it is retried once the process is continued in the foreground.
+    ///
+    /// ## Errors
+    /// * `FileSystemError::Io` - The process reads from the terminal but cannot be stopped, as
+    ///   it ignores or blocks `SIGTTIN`, or its process group is orphaned.
+    /// * `FileSystemError::Interrupted` - `signal` was sent to the process group.
+    pub fn check_job_control(&self, signal: usize) -> Result<(), FileSystemError> {
+        let (Some(session), Some(foreground)) = (self.session(), self.foreground()) else {
+            return Ok(());
+        };
+
+        let task = scheduler::current_thread().process_leader();
+
+        // Only the background processes of the session of the terminal are affected.
+        if task.session_id() != session.id() || task.group_id() == foreground.id() {
+            return Ok(());
+        }
+
+        let is_write = signal == signal::SIGTTOU;
+
+        if is_write && !self.termios().c_lflag.contains(TermiosLFlag::TOSTOP) {
+            return Ok(());
+        }
+
+        if task.signals().is_ignored(signal) || task.signals().is_blocked(signal) {
+            // The write goes through if it cannot stop the process.
+            return if is_write {
+                Ok(())
+            } else {
+                Err(FileSystemError::Io)
+            };
+        }
+
+        // Nothing would continue a stopped orphaned process group.
+        if SESSIONS.is_orphaned(&task) {
+            return Err(FileSystemError::Io);
+        }
+
+        if let Some(group) = SESSIONS.find_group(&task) {
+            group.signal(signal);
+        }
+
+        Err(FileSystemError::Interrupted)
     }
 
     /// Returns whether the line discipline buffer is empty.
@@ -179,8 +320,111 @@ impl LineDiscipline {
         self.buffer.lock_irq().is_empty()
     }
 
+    /// Returns whether there is room for more input in the line discipline buffer.
+    pub fn has_space(&self) -> bool {
+        self.buffer.lock_irq().len() < Self::BUF_SIZE
+    }
+
+    /// Blocks until there is room for more input in the line discipline buffer.
+    pub fn wait_for_space(&self) -> Result<(), SignalError> {
+        self.wq
+            .block_on(&self.buffer, |buf| buf.len() < Self::BUF_SIZE)?;
+        Ok(())
+    }
+
     /// Returns the line discipline's wait queue.
     pub fn wait_queue(&self) -> &WaitQueue {
         &self.wq
     }
 }
+
+mod selftests {
+    use core::cell::RefCell;
+
+    use super::*;
+
+    use crate::{selftest, selftest_assert, selftest_assert_eq};
+
+    /// Writes `input` to the line discipline and returns the echoed bytes.
+    fn write(discipline: &LineDiscipline, input: &[u8]) -> Vec<u8> {
+        let echo = RefCell::new(Vec::new());
+
+        discipline.write(input, |control| match control {
+            LineControl::Echo(byte) => echo.borrow_mut().push(byte),
+        });
+
+        echo.into_inner()
+    }
+
+    fn read(discipline: &LineDiscipline) -> Vec<u8> {
+        let mut buffer = [0; 64];
+        let size = discipline.read(&mut buffer).unwrap_or(0);
+
+        buffer[..size].to_vec()
+    }
+
+    fn canonical_echo() -> selftest::Result {
+        let discipline = LineDiscipline::new();
+
+        selftest_assert_eq!(write(&discipline, b"ab\r"), b"ab\r\n");
+        selftest_assert_eq!(read(&discipline), b"ab\n");
+        selftest_assert!(discipline.is_empty());
+
+        Ok(())
+    }
+
+    fn raw_mode() -> selftest::Result {
+        let discipline = LineDiscipline::new();
+
+        let mut termios = discipline.termios();
+        termios
+            .c_lflag
+            .remove(TermiosLFlag::ICANON | TermiosLFlag::ECHO);
+        discipline.set_termios(termios.clone());
+
+        // The interrupt character is passed through as is.
+        selftest_assert_eq!(write(&discipline, b"x\r\x03"), b"");
+        selftest_assert_eq!(read(&discipline), b"x\n\x03");
+
+        termios.c_iflag.remove(TermiosIFlag::ICRNL);
+        discipline.set_termios(termios);
+
+        selftest_assert_eq!(write(&discipline, b"\r"), b"");
+        selftest_assert_eq!(read(&discipline), b"\r");
+
+        Ok(())
+    }
+
+    fn interrupt_character() -> selftest::Result {
+        let discipline = LineDiscipline::new();
+
+        // There is no foreground process group to signal, but the character is consumed.
+        selftest_assert_eq!(write(&discipline, b"\x03"), b"");
+        selftest_assert!(discipline.is_empty());
+
+        Ok(())
+    }
+
+    fn input_limit() -> selftest::Result {
+        let discipline = LineDiscipline::new();
+        let input = [b'x'; LineDiscipline::BUF_SIZE + 16];
+
+        // Only the input that fits in the buffer is consumed.
+        selftest_assert_eq!(discipline.write(&input, |_| {}), LineDiscipline::BUF_SIZE);
+        selftest_assert!(!discipline.has_space());
+        selftest_assert_eq!(discipline.write(b"y", |_| {}), 0);
+
+        selftest_assert_eq!(read(&discipline).len(), 64);
+        selftest_assert!(discipline.has_space());
+
+        Ok(())
+    }
+
+    crate::selftest!(
+        "tty",
+        canonical_echo,
+        raw_mode,
+        interrupt_character,
+        input_limit
+    );
+}
@@ -0,0 +1,61 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The names that identify the system (see `uname(2)`): the host name and the NIS domain name.
+//! They are set by userland on boot, usually from `/etc/hostname`, with `sethostname(2)` and
+//! `setdomainname(2)` or through `/proc/sys/kernel/{hostname,domainname}`.
+
+use aero_syscall::{SyscallError, HOST_NAME_MAX};
+use spin::{Mutex, Once};
+
+static HOSTNAME: Once<Mutex<String>> = Once::new();
+static DOMAINNAME: Once<Mutex<String>> = Once::new();
+
+fn hostname_lock() -> &'static Mutex<String> {
+    HOSTNAME.call_once(|| Mutex::new(String::from("aero")))
+}
+
+fn domainname_lock() -> &'static Mutex<String> {
+    DOMAINNAME.call_once(|| Mutex::new(String::from("(none)")))
+}
+
+/// Validates the provided name; it is at most [`HOST_NAME_MAX`] bytes long.
+fn validate(name: &[u8]) -> Result<&str, SyscallError> {
+    if name.len() > HOST_NAME_MAX || name.contains(&b'\0') {
+        return Err(SyscallError::EINVAL);
+    }
+
+    core::str::from_utf8(name).map_err(|_| SyscallError::EINVAL)
+}
+
+pub fn hostname() -> String {
+    hostname_lock().lock().clone()
+}
+
+pub fn set_hostname(name: &[u8]) -> Result<(), SyscallError> {
+    *hostname_lock().lock() = validate(name)?.into();
+    Ok(())
+}
+
+pub fn domainname() -> String {
+    domainname_lock().lock().clone()
+}
+
+pub fn set_domainname(name: &[u8]) -> Result<(), SyscallError> {
+    *domainname_lock().lock() = validate(name)?.into();
+    Ok(())
+}
@@ -18,6 +18,7 @@
 use core::fmt::Write;
 use core::ops::Range;
 
+use aero_syscall::prelude::F_SEAL_WRITE;
 use aero_syscall::{MMapFlags, MMapProt};
 
 use alloc::boxed::Box;
@@ -26,23 +27,90 @@ use alloc::collections::LinkedList;
 
 use alloc::sync::Arc;
 use hashbrown::HashMap;
+use spin::Once;
 use xmas_elf::header::*;
 use xmas_elf::program::*;
 use xmas_elf::*;
 
 use crate::arch::task::userland_last_address;
-use crate::fs::block::PageCacheItem;
+use crate::fs::block::{self, PageCacheItem};
 use crate::fs::cache::{DirCacheImpl, DirCacheItem};
 use crate::fs::file_table::FileHandle;
 use crate::fs::inode::MMapPage;
 use crate::fs::{FileSystemError, Path};
 use crate::mem::paging::*;
-use crate::mem::AddressSpace;
+use crate::mem::swap::{self, SwapEntry};
+use crate::mem::{oom, shrinker, AddressSpace};
 use crate::{fs, mem};
 
 use crate::syscall::ExecArgs;
+use crate::userland::scheduler;
 use crate::utils::sync::BMutex;
 
+/// Frame filled with zeros that is shared by all of the private anonymous pages that have
+/// been read from but not yet written to.
+static ZERO_FRAME: Once<PhysFrame> = Once::new();
+
+fn zero_frame() -> PhysFrame {
+    *ZERO_FRAME.call_once(|| {
+        let frame: PhysFrame = PhysFrame::containing_address(
+            FRAME_ALLOCATOR
+                .alloc_zeroed(Size4KiB::SIZE as usize)
+                .expect("vm: failed to allocate the zero frame"),
+        );
+
+        // Hold an extra reference to the frame, so that it is never deallocated and always
+        // gets copied on write.
+        frame.start_address().as_vm_frame().unwrap().inc_ref_count();
+        frame
+    })
+}
+
+/// Returns whether the provided address is mapped to the zero frame.
+fn is_zero_page(offset_table: &OffsetPageTable, address: VirtAddr) -> bool {
+    match offset_table.translate(address) {
+        TranslateResult::Mapped { frame, .. } => ZERO_FRAME
+            .get()
+            .is_some_and(|zero| frame.start_address() == zero.start_address()),
+
+        _ => false,
+    }
+}
+
+/// Reads the swapped out page at `swap` into a newly allocated frame and maps it at `page`,
+/// releasing the swap entry.
+fn map_swapped(
+    offset_table: &mut OffsetPageTable,
+    page: Page<Size4KiB>,
+    swap: SwapEntry,
+    flags: VmFlag,
+) -> bool {
+    let Some(frame) = FRAME_ALLOCATOR.allocate_frame() else {
+        return false;
+    };
+
+    if !swap::swap_in(swap, frame) {
+        FRAME_ALLOCATOR.deallocate_frame(frame);
+        return false;
+    }
+
+    swap::free(swap);
+    offset_table.entry_mut(page).unwrap().set_unused();
+
+    unsafe {
+        offset_table.map_to(
+            page,
+            frame,
+            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT | flags.into(),
+        )
+    }
+    .expect("failed to map swapped in page")
+    // The page was not present, so there is nothing to flush.
+    .ignore();
+
+    true
+}
+
 bitflags::bitflags! {
     #[derive(Debug, Copy, Clone, PartialEq)]
     pub struct VmFlag: u8 {
@@ -88,6 +156,12 @@ impl From<VmFlag> for PageTableFlags {
 
 const ELF_HEADER_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 
+/// Address that position independent executables are loaded at.
+const PIE_LOAD_BASE: u64 = 0x4000_0000;
+/// Address that the program interpreter (dynamic linker) is loaded at, right below the area used
+/// for the memory mappings.
+const INTERP_LOAD_BASE: u64 = 0x6fff_0000_0000;
+
 const ELF_PT1_SIZE: usize = core::mem::size_of::<HeaderPt1>();
 const ELF_PT2_64_SIZE: usize = core::mem::size_of::<HeaderPt2_<P64>>();
 
@@ -104,6 +178,30 @@ pub enum ElfLoadError {
     /// Unexpected file system error occurred when memory mapping an
     /// ELF segment.
     MemoryMapError,
+    /// The path of the program interpreter is not valid.
+    InvalidInterpreter,
+}
+
+/// Checks that `file` starts with a valid ELF identification, without loading it.
+pub fn check_elf_header(file: &DirCacheItem) -> Result<(), ElfLoadError> {
+    let mut buffer = [0u8; ELF_PT1_SIZE];
+
+    let size = file
+        .inode()
+        .read_at(0, &mut buffer)
+        .map_err(ElfLoadError::IOError)?;
+
+    // SAFETY: `HeaderPt1` only consists of bytes.
+    let header = unsafe { &*buffer.as_ptr().cast::<HeaderPt1>() };
+
+    if size < ELF_PT1_SIZE || header.magic != ELF_HEADER_MAGIC {
+        return Err(ElfLoadError::InvalidMagic);
+    }
+
+    match header.class() {
+        Class::SixtyFour | Class::ThirtyTwo => Ok(()),
+        Class::None | Class::Other(_) => Err(ElfLoadError::InvalidClass),
+    }
 }
 
 fn parse_elf_header<'header>(file: &DirCacheItem) -> Result<Header<'header>, ElfLoadError> {
@@ -293,6 +391,45 @@ impl<'this> Elf<'this> {
     fn program_iter(&self) -> ProgramHeaderIter<'this> {
         ProgramHeaderIter::new(self.header, self.file.clone())
     }
+
+    /// Returns the path of the program interpreter requested by the `PT_INTERP` segment; [`None`]
+    /// if the executable is statically linked.
+    fn interpreter(&self) -> Result<Option<String>, ElfLoadError> {
+        let Some(header) = self
+            .program_iter()
+            .find(|header| header.get_type() == Ok(xmas_elf::program::Type::Interp))
+        else {
+            return Ok(None);
+        };
+
+        /// Maximum length of a path, including the terminating NUL byte (`PATH_MAX`).
+        const PATH_MAX: u64 = 4096;
+
+        if header.file_size() > PATH_MAX {
+            return Err(ElfLoadError::InvalidInterpreter);
+        }
+
+        let mut path = alloc::vec![0u8; header.file_size() as usize];
+
+        self.file
+            .inode()
+            .read_at(header.offset() as usize, &mut path)
+            .map_err(ElfLoadError::IOError)?;
+
+        // The path is terminated by a NUL byte.
+        let len = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+
+        String::from_utf8(path[..len].to_vec())
+            .map(Some)
+            .map_err(|_| ElfLoadError::InvalidInterpreter)
+    }
+}
+
+/// ELF image mapped into a VM, either an executable or its program interpreter.
+struct LoadedImage {
+    entry_point: VirtAddr,
+    /// Address of the program headers of the image in memory.
+    phdr: VirtAddr,
 }
 
 struct ProgramHeaderIter<'this> {
@@ -343,26 +480,58 @@ enum UnmapResult {
 pub struct LoadedBinary<'header> {
     pub elf: Elf<'header>,
 
+    /// Address that execution starts at: the entry point of the program interpreter if there
+    /// is one, otherwise the one of the executable.
     pub entry_point: VirtAddr,
-    pub base_addr: VirtAddr,
+    /// Entry point of the executable.
+    pub program_entry: VirtAddr,
+    /// Address of the program headers of the executable in memory.
+    pub phdr: VirtAddr,
+    /// Load address of the program interpreter, if the executable is dynamically linked.
+    pub interp_base: Option<VirtAddr>,
 
     pub argv: Option<ExecArgs>,
     pub envv: Option<ExecArgs>,
 }
 
+/// Marks a shared mapping of `file` that can be made writable for as long as it lives (see
+/// [`INodeInterface::map_writable`](crate::fs::inode::INodeInterface::map_writable)).
+struct WritableMapping(DirCacheItem);
+
+impl WritableMapping {
+    fn new(file: DirCacheItem) -> Self {
+        file.inode().map_writable(true);
+        Self(file)
+    }
+}
+
+impl Clone for WritableMapping {
+    fn clone(&self) -> Self {
+        Self::new(self.0.clone())
+    }
+}
+
+impl Drop for WritableMapping {
+    fn drop(&mut self) {
+        self.0.inode().map_writable(false);
+    }
+}
+
 #[derive(Clone)]
 pub struct MMapFile {
     offset: usize,
     file: DirCacheItem,
     size: usize,
     mappings: HashMap<VirtAddr, PageCacheItem>,
+    writable: Option<WritableMapping>,
 }
 
 impl MMapFile {
     #[inline]
-    fn new(file: DirCacheItem, offset: usize, size: usize) -> Self {
+    fn new(file: DirCacheItem, offset: usize, size: usize, writable: bool) -> Self {
         Self {
             offset,
+            writable: writable.then(|| WritableMapping::new(file.clone())),
             file,
             size,
             mappings: HashMap::new(),
@@ -401,7 +570,10 @@ impl Mapping {
     }
 
     /// Handler routine for private anonymous pages. Since its an anonymous page is not
-    /// backed by a file, we have to alloctate a frame and map it at the faulted address.
+    /// backed by a file, reads map the shared zero frame at the faulted address and writes
+    /// allocate a zeroed frame (or copy the zero frame). Writes to an untouched 2MiB region
+    /// are backed by a huge page if possible. Pages that have been swapped out are read back
+    /// from the swap area.
     fn handle_pf_private_anon(
         &mut self,
         offset_table: &mut OffsetPageTable,
@@ -409,10 +581,52 @@ impl Mapping {
         address: VirtAddr,
     ) -> bool {
         let addr_aligned = address.align_down(Size4KiB::SIZE);
+        let page: Page<Size4KiB> = Page::containing_address(addr_aligned);
+
+        if reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            // Copy-on-write and flag updates are done on 4KiB pages, so the huge page (if any)
+            // backing the faulted address has to be split first.
+            match offset_table.split_huge_page(Page::containing_address(address)) {
+                Ok(flusher) => flusher.flush(),
+                Err(SplitError::PageNotMapped) => {}
+                Err(SplitError::FrameAllocationFailed) => return false,
+            }
+        }
+
+        let swapped = offset_table
+            .entry_mut(page)
+            .and_then(|entry| entry.swap_entry());
+
+        if let Some(swap) = swapped {
+            map_swapped(offset_table, page, swap, self.flags)
+        } else if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            && reason.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+            && self.map_huge(offset_table, address)
+        {
+            true
+        } else if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            && !reason.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        {
+            // Defer allocating a frame until the page is written to.
+            unsafe {
+                offset_table.map_to(
+                    Page::containing_address(addr_aligned),
+                    zero_frame(),
+                    PageTableFlags::USER_ACCESSIBLE
+                        | PageTableFlags::PRESENT
+                        | (self.flags & !VmFlag::WRITE).into(),
+                )
+            }
+            .expect("failed to map the zero frame")
+            .flush();
+
+            true
+        } else if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            let Some(frame) = FRAME_ALLOCATOR.alloc_zeroed(Size4KiB::SIZE as usize) else {
+                return false;
+            };
 
-        if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
-            let frame: PhysFrame =
-                PhysFrame::containing_address(pmm_alloc(BuddyOrdering::Size4KiB));
+            let frame: PhysFrame = PhysFrame::containing_address(frame);
 
             unsafe {
                 offset_table.map_to(
@@ -435,6 +649,13 @@ impl Mapping {
                 return false;
             }
 
+            let mut flags = self.flags;
+
+            // The zero frame must stay read-only so that it gets copied on write.
+            if is_zero_page(offset_table, addr_aligned) {
+                flags.remove(VmFlag::WRITE);
+            }
+
             unsafe {
                 // The page is present but most likely the flags need to be updated after
                 // mprotect(2).
@@ -442,9 +663,7 @@ impl Mapping {
                 offset_table
                     .update_flags(
                         page,
-                        PageTableFlags::USER_ACCESSIBLE
-                            | PageTableFlags::PRESENT
-                            | self.flags.into(),
+                        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT | flags.into(),
                     )
                     .unwrap()
                     .flush();
@@ -455,6 +674,41 @@ impl Mapping {
         }
     }
 
+    /// Backs the 2MiB aligned region containing `address` with a zeroed huge page. Returns
+    /// `false` if the region does not lie entirely within this mapping, if a part of it has
+    /// already been mapped or if there is no free contiguous 2MiB frame.
+    fn map_huge(&self, offset_table: &mut OffsetPageTable, address: VirtAddr) -> bool {
+        let page: Page<Size2MiB> = Page::containing_address(address);
+        let start = page.start_address();
+
+        if start < self.start_addr || start + Size2MiB::SIZE > self.end_addr {
+            return false;
+        }
+
+        if !matches!(
+            offset_table.translate_page(page),
+            Err(TranslateError::PageNotMapped)
+        ) {
+            return false;
+        }
+
+        let Some(frame) = FRAME_ALLOCATOR.alloc_zeroed(Size2MiB::SIZE as usize) else {
+            return false;
+        };
+
+        unsafe {
+            offset_table.map_to(
+                page,
+                PhysFrame::containing_address(frame),
+                PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT | self.flags.into(),
+            )
+        }
+        .expect("failed to map huge page for private anonymous mapping")
+        .flush();
+
+        true
+    }
+
     /// Handler routine for pages backed by a file. This function will allocate a frame and
     /// read a page-sized amount from the disk into the allocated frame. Then it maps
     /// the allocated frame at the faulted address.
@@ -493,35 +747,39 @@ impl Mapping {
         size: usize,
     ) -> bool {
         let mmap_file = self.file.as_mut().unwrap();
-        let page_cache = if let MMapPage::PageCache(page_cache) =
-            mmap_file.file.inode().mmap_v2(offset).unwrap()
-        {
-            page_cache
-        } else {
-            todo!()
-        };
+        let mmap_page = mmap_file.file.inode().mmap_v2(offset).unwrap();
 
         if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
             && !reason.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
         {
-            let frame = if size == Size4KiB::SIZE as usize {
-                page_cache.page()
-            } else {
-                // The end needs to be zeroed out so we cannot directly map the cached page.
-                let page: Page = Page::containing_address(page_cache.data_addr().as_hhdm_virt());
+            let frame = match &mmap_page {
+                MMapPage::PageCache(page_cache) if size == Size4KiB::SIZE as usize => {
+                    page_cache.page()
+                }
 
-                let new_frame: PhysFrame = PhysFrame::containing_address(
-                    FRAME_ALLOCATOR
-                        .alloc_zeroed(Size4KiB::SIZE as usize)
-                        .unwrap(),
-                );
+                MMapPage::PageCache(page_cache) => {
+                    // The end needs to be zeroed out so we cannot directly map the cached page.
+                    let page: Page =
+                        Page::containing_address(page_cache.data_addr().as_hhdm_virt());
+
+                    let Some(new_frame) = FRAME_ALLOCATOR.alloc_zeroed(Size4KiB::SIZE as usize)
+                    else {
+                        return false;
+                    };
+
+                    let new_frame: PhysFrame = PhysFrame::containing_address(new_frame);
 
-                let new_slice = new_frame.as_slice_mut::<u8>();
-                new_slice[..size].copy_from_slice(unsafe {
-                    core::slice::from_raw_parts(page.start_address().as_ptr::<u8>(), size)
-                });
+                    let new_slice = new_frame.as_slice_mut::<u8>();
+                    new_slice[..size].copy_from_slice(unsafe {
+                        core::slice::from_raw_parts(page.start_address().as_ptr::<u8>(), size)
+                    });
 
-                new_frame
+                    new_frame
+                }
+
+                // The page is not backed by the page cache (e.g. a memfd page), so it can be
+                // mapped directly. Writes to it are handled by copy-on-write.
+                MMapPage::Direct(frame) => *frame,
             };
 
             unsafe {
@@ -541,11 +799,13 @@ impl Mapping {
             && reason.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
         {
             // We are writing to private file mapping so copy the content of the page.
-            let frame = mmap_file
+            let Ok(frame) = mmap_file
                 .file
                 .inode()
                 .mmap(offset, size, MMapFlags::empty())
-                .expect("handle_pf_file: file does not support mmap");
+            else {
+                return false;
+            };
 
             unsafe {
                 offset_table.map_to(
@@ -653,7 +913,7 @@ impl Mapping {
         // Allocate a new frame to hold the contents.
         let new_frame: PhysFrame<Size4KiB> = FRAME_ALLOCATOR
             .allocate_frame()
-            .expect("map_copied: failed to allocate frame");
+            .ok_or(MapToError::FrameAllocationFailed)?;
 
         let old_slice = unsafe {
             let ptr = page.start_address().as_ptr::<u8>();
@@ -706,7 +966,9 @@ impl Mapping {
             if let Some(vm_frame) = phys_addr.as_vm_frame() {
                 if vm_frame.ref_count() > 1 || copy {
                     // This page is used by more then one process, so make it a private copy.
-                    Self::map_copied(offset_table, page, self.flags).unwrap();
+                    if Self::map_copied(offset_table, page, self.flags).is_err() {
+                        return false;
+                    }
                 } else {
                     // This page is used by only one process, so make it writable.
                     unsafe {
@@ -735,13 +997,46 @@ impl Mapping {
         end: VirtAddr,
     ) -> Result<UnmapResult, UnmapError> {
         let mut unmap_range_inner = |range: Range<VirtAddr>| -> Result<(), UnmapError> {
-            for addr in range.step_by(Size4KiB::SIZE as usize) {
+            let mut addr = range.start;
+
+            while addr < range.end {
                 let page: Page = Page::containing_address(addr);
                 match offset_table.unmap(page) {
                     Ok((_, flusher)) => flusher.flush(),
-                    Err(UnmapError::PageNotMapped) => {}
+                    Err(UnmapError::PageNotMapped) => {
+                        // Release the swap slot if the page has been swapped out.
+                        if let Some(entry) = offset_table.entry_mut(page) {
+                            if let Some(swap) = entry.swap_entry() {
+                                swap::free(swap);
+                                entry.set_unused();
+                            }
+                        }
+                    }
+                    Err(UnmapError::ParentEntryHugePage) => {
+                        let huge_page: Page<Size2MiB> = Page::containing_address(addr);
+                        let huge_end = huge_page.start_address() + Size2MiB::SIZE;
+
+                        if huge_page.start_address() >= range.start && huge_end <= range.end {
+                            // The whole huge page is being unmapped.
+                            let (_, flusher) = offset_table.unmap(huge_page)?;
+                            flusher.flush();
+
+                            addr = huge_end;
+                        } else {
+                            // Only a part of the huge page is being unmapped, so split it
+                            // and retry.
+                            offset_table
+                                .split_huge_page(huge_page)
+                                .map_err(|_| UnmapError::ParentEntryHugePage)?
+                                .flush();
+                        }
+
+                        continue;
+                    }
                     Err(e) => return Err(e),
                 }
+
+                addr += Size4KiB::SIZE;
             }
 
             Ok(())
@@ -758,7 +1053,7 @@ impl Mapping {
                 let offset = file.offset + (end - self.start_addr) as usize;
                 let size = file.size - (offset - file.offset);
 
-                MMapFile::new(file.file.clone(), offset, size)
+                MMapFile::new(file.file.clone(), offset, size, file.writable.is_some())
             });
 
             let new_mapping = Mapping {
@@ -828,17 +1123,43 @@ impl Mapping {
     }
 }
 
+/// Memory usage of a VM, in 4KiB pages.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MemoryUsage {
+    /// Total size of the mappings (VSZ).
+    pub size: usize,
+    /// Number of pages that are resident in memory (RSS).
+    pub resident: usize,
+    /// Number of resident pages that are backed by the page cache.
+    pub shared: usize,
+    /// Size of the executable file mappings.
+    pub text: usize,
+    /// Size of the private writable mappings.
+    pub data: usize,
+}
+
 struct VmProtected {
     mappings: LinkedList<Mapping>,
+    /// Root of the page table that the VM is mapped into. Only changed with the VM lock held,
+    /// so that the page table can be walked on behalf of other tasks.
+    page_table: Option<PhysFrame>,
 }
 
 impl VmProtected {
     fn new() -> Self {
         Self {
             mappings: LinkedList::new(),
+            page_table: None,
         }
     }
 
+    /// Returns the address space that the VM is mapped into, if any.
+    fn address_space(&self) -> Option<AddressSpace> {
+        // SAFETY: The page table is only freed after it has been detached from the VM.
+        self.page_table
+            .map(|cr3| unsafe { AddressSpace::from_cr3(cr3) })
+    }
+
     fn handle_page_fault(
         &mut self,
         reason: PageFaultErrorCode,
@@ -888,6 +1209,131 @@ impl VmProtected {
         }
     }
 
+    /// Frees up memory by evicting clean pages from the page cache, shrinking the kernel object
+    /// caches and (if that was not enough) swapping out private anonymous pages of this VM,
+    /// except the page at `exclude`.
+    fn reclaim(&mut self, exclude: VirtAddr) {
+        let mut freed = block::shrink_page_cache(swap::RECLAIM_BATCH);
+
+        // NOTE: The freed objects are not counted as freed frames, since their memory is only
+        // returned to the heap.
+        shrinker::shrink_all(swap::RECLAIM_BATCH);
+
+        if freed < swap::RECLAIM_BATCH && swap::is_enabled() {
+            let mut address_space = AddressSpace::this();
+            let mut offset_table = address_space.offset_page_table();
+
+            freed += self.swap_out(&mut offset_table, swap::RECLAIM_BATCH - freed, exclude);
+        }
+
+        log::trace!("vm: reclaimed {freed} frames");
+    }
+
+    /// Swaps out up to `count` private anonymous pages, that are not shared with any other
+    /// address space, except the page at `exclude`. Returns the number of pages swapped out.
+    fn swap_out(
+        &self,
+        offset_table: &mut OffsetPageTable,
+        count: usize,
+        exclude: VirtAddr,
+    ) -> usize {
+        let mut swapped = 0;
+
+        // Recently accessed pages are given a second chance; the first pass only clears
+        // their accessed flag.
+        for _ in 0..2 {
+            for map in self
+                .mappings
+                .iter()
+                .filter(|map| !map.flags.contains(VmFlag::SHARED) && map.file.is_none())
+            {
+                for addr in (map.start_addr..map.end_addr).step_by(Size4KiB::SIZE as usize) {
+                    if swapped == count {
+                        return swapped;
+                    }
+
+                    if addr == exclude {
+                        continue;
+                    }
+
+                    let TranslateResult::Mapped {
+                        frame: MappedFrame::Size4KiB(frame),
+                        flags,
+                        ..
+                    } = offset_table.translate(addr)
+                    else {
+                        continue;
+                    };
+
+                    // NOTE: This also skips the zero frame.
+                    if frame.start_address().as_vm_frame().unwrap().ref_count() != 1 {
+                        continue;
+                    }
+
+                    let page: Page<Size4KiB> = Page::containing_address(addr);
+
+                    if flags.contains(PageTableFlags::ACCESSED) {
+                        unsafe {
+                            offset_table.update_flags(page, flags - PageTableFlags::ACCESSED)
+                        }
+                        .unwrap()
+                        .flush();
+                        continue;
+                    }
+
+                    // Write protect the page while it is being written out.
+                    unsafe { offset_table.update_flags(page, flags - PageTableFlags::WRITABLE) }
+                        .unwrap()
+                        .flush();
+
+                    let Some(swap) = swap::swap_out(frame) else {
+                        unsafe { offset_table.update_flags(page, flags) }
+                            .unwrap()
+                            .flush();
+
+                        // The swap areas are full.
+                        return swapped;
+                    };
+
+                    // Unmapping the page drops the last reference to the frame and frees it.
+                    offset_table.unmap(page).unwrap().1.flush();
+                    offset_table.entry_mut(page).unwrap().set_swap_entry(swap);
+
+                    swapped += 1;
+                }
+            }
+        }
+
+        swapped
+    }
+
+    /// Reads all of the pages of this VM that are stored in the swap area with the provided
+    /// index back into memory. Returns `false` if there is not enough memory to do so.
+    fn swap_in_area(&self, offset_table: &mut OffsetPageTable, area: usize) -> bool {
+        for map in self
+            .mappings
+            .iter()
+            .filter(|map| !map.flags.contains(VmFlag::SHARED) && map.file.is_none())
+        {
+            for addr in (map.start_addr..map.end_addr).step_by(Size4KiB::SIZE as usize) {
+                let page: Page<Size4KiB> = Page::containing_address(addr);
+
+                let swapped = offset_table
+                    .entry_mut(page)
+                    .and_then(|entry| entry.swap_entry())
+                    .filter(|swap| swap.area() == area);
+
+                if let Some(swap) = swapped {
+                    if !map_swapped(offset_table, page, swap, map.flags) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
     fn find_fixed_mapping(
         &mut self,
         address: VirtAddr,
@@ -1034,7 +1480,10 @@ impl VmProtected {
                 start_addr: addr,
                 end_addr: addr + size_aligned,
 
-                file: file.map(|f| MMapFile::new(f, offset, size)),
+                file: file.map(|f| {
+                    let writable = vm_flags.contains(VmFlag::SHARED | VmFlag::MAY_WRITE);
+                    MMapFile::new(f, offset, size, writable)
+                }),
                 refresh_flags: true,
                 flags: vm_flags,
             });
@@ -1115,22 +1564,59 @@ impl VmProtected {
         }
 
         let elf = Elf::new(bin.clone())?;
-        let header = &elf.header;
 
-        let load_offset = VirtAddr::new(
-            if header.pt2.type_().as_type() == header::Type::SharedObject {
-                0x4000_0000_u64
-            } else {
-                0u64
-            },
-        );
+        let load_offset = if elf.header.pt2.type_().as_type() == header::Type::SharedObject {
+            VirtAddr::new(PIE_LOAD_BASE)
+        } else {
+            VirtAddr::zero()
+        };
+
+        log::debug!("entry point type: {:?}", elf.header.pt2.type_().as_type());
+
+        let image = self.load_image(&elf, load_offset)?;
+
+        // Dynamically linked executables are started through their program interpreter, which
+        // loads the shared libraries and then jumps to the entry point of the executable.
+        let interp = match elf.interpreter()? {
+            Some(path) => {
+                log::debug!("interpreter: {path}");
 
-        let mut entry_point = load_offset + header.pt2.entry_point();
+                let interp = fs::lookup_path(Path::new(&path)).map_err(ElfLoadError::IOError)?;
+                let interp = Elf::new(interp)?;
+
+                Some(self.load_image(&interp, VirtAddr::new(INTERP_LOAD_BASE))?)
+            }
+
+            None => None,
+        };
+
+        Ok(LoadedBinary {
+            elf,
+            entry_point: interp
+                .as_ref()
+                .map_or(image.entry_point, |interp| interp.entry_point),
+            program_entry: image.entry_point,
+            phdr: image.phdr,
+            interp_base: interp.map(|_| VirtAddr::new(INTERP_LOAD_BASE)),
+
+            argv,
+            envv,
+        })
+    }
+
+    /// Maps the loadable segments of `elf` at `load_offset`.
+    fn load_image(
+        &mut self,
+        elf: &Elf,
+        load_offset: VirtAddr,
+    ) -> Result<LoadedImage, ElfLoadError> {
+        let header = &elf.header;
+        let entry_point = load_offset + header.pt2.entry_point();
 
         log::debug!("entry point: {:#x}", entry_point);
-        log::debug!("entry point type: {:?}", header.pt2.type_().as_type());
 
         let mut base_addr = VirtAddr::zero();
+        let mut phdr = None;
 
         for header in elf.program_iter() {
             let header_type = header
@@ -1139,7 +1625,20 @@ impl VmProtected {
 
             let header_flags = header.flags();
 
-            if header_type == xmas_elf::program::Type::Load {
+            if header_type == xmas_elf::program::Type::Phdr {
+                phdr = Some(load_offset + header.virtual_addr());
+            } else if header_type == xmas_elf::program::Type::Load {
+                // Without a `PT_PHDR` segment, the program headers are found through the
+                // segment that contains them.
+                let ph_offset = elf.header.pt2.ph_offset();
+
+                if phdr.is_none()
+                    && (header.offset()..header.offset() + header.file_size()).contains(&ph_offset)
+                {
+                    phdr =
+                        Some(load_offset + header.virtual_addr() + (ph_offset - header.offset()));
+                }
+
                 let virtual_start = VirtAddr::new(header.virtual_addr()).align_down(Size4KiB::SIZE)
                     + load_offset.as_u64();
 
@@ -1203,7 +1702,7 @@ impl VmProtected {
                         data_size as usize,
                         MMapFlags::MAP_PRIVATE | MMapFlags::MAP_FIXED,
                         file_offset as usize,
-                        Some(bin.clone()),
+                        Some(elf.file.clone()),
                         flags
                     )
                     .ok_or(ElfLoadError::MemoryMapError)?;
@@ -1223,30 +1722,90 @@ impl VmProtected {
                     )
                     .ok_or(ElfLoadError::MemoryMapError)?;
                 }
-            } else if header_type == xmas_elf::program::Type::Tls {
-            } else if header_type == xmas_elf::program::Type::Interp {
-                let ld = fs::lookup_path(fs::Path::new("/usr/lib/ld.so")).unwrap();
-
-                let res = self.load_bin(&ld, None, None)?;
-                entry_point = res.entry_point;
             }
         }
 
-        Ok(LoadedBinary {
-            elf,
+        Ok(LoadedImage {
             entry_point,
+            phdr: phdr.unwrap_or(base_addr + elf.header.pt2.ph_offset()),
+        })
+    }
 
-            base_addr,
+    /// Walks the page table and accounts the memory used by this VM. Pages mapped to the shared
+    /// zero frame are not counted as resident.
+    fn memory_usage(&self, offset_table: &OffsetPageTable) -> MemoryUsage {
+        let zero = ZERO_FRAME.get().map(|frame| frame.start_address());
+        let mut usage = MemoryUsage::default();
 
-            argv,
-            envv,
-        })
+        for map in self.mappings.iter() {
+            let pages = map.size() / Size4KiB::SIZE as usize;
+
+            usage.size += pages;
+
+            if map.file.is_some() && map.flags.contains(VmFlag::EXEC) {
+                usage.text += pages;
+            }
+
+            if map.flags.contains(VmFlag::WRITE) && !map.flags.contains(VmFlag::SHARED) {
+                usage.data += pages;
+            }
+
+            let mut addr = map.start_addr;
+
+            while addr < map.end_addr {
+                match offset_table.translate(addr) {
+                    TranslateResult::Mapped {
+                        frame,
+                        offset,
+                        flags,
+                    } => {
+                        let next = (addr - offset + frame.size()).min(map.end_addr);
+                        let count = ((next - addr) / Size4KiB::SIZE) as usize;
+
+                        if Some(frame.start_address()) != zero {
+                            usage.resident += count;
+
+                            // File pages that have not been copied on write are still backed
+                            // by the page cache.
+                            if map.file.is_some()
+                                && (map.flags.contains(VmFlag::SHARED)
+                                    || !flags.contains(PageTableFlags::WRITABLE))
+                            {
+                                usage.shared += count;
+                            }
+                        }
+
+                        addr = next;
+                    }
+
+                    _ => addr += Size4KiB::SIZE,
+                }
+            }
+        }
+
+        usage
+    }
+
+    /// Unmaps and removes all of the mappings, releasing the frames and the swap slots that
+    /// back them.
+    fn release(&mut self, offset_table: &mut OffsetPageTable) {
+        while let Some(mut map) = self.mappings.pop_front() {
+            let (start, end) = (map.start_addr, map.end_addr);
+
+            if let Err(err) = map.unmap(offset_table, start, end) {
+                log::warn!("vm: failed to release {start:#x}..{end:#x}: {err:?}");
+            }
+        }
+
+        self.page_table = None;
     }
 
-    /// Clears all of the mappings without unmapping them. The caller is responsible
-    /// for going through the page table and unmapping all of the pages.
+    /// Clears all of the mappings without unmapping them and detaches the VM from its page
+    /// table. The caller is responsible for going through the page table and unmapping all of
+    /// the pages.
     fn clear(&mut self) {
-        self.mappings.clear()
+        self.mappings.clear();
+        self.page_table = None;
     }
 
     fn munmap(&mut self, address: VirtAddr, size: usize) -> bool {
@@ -1355,6 +1914,8 @@ impl VmProtected {
         }
 
         let mut address_space = AddressSpace::new().unwrap();
+        self.page_table = Some(address_space.cr3());
+
         let mut offset_table = address_space.offset_page_table();
 
         let mut current = AddressSpace::this();
@@ -1397,6 +1958,10 @@ impl Vm {
 
         let map_type = flags & (MMapFlags::MAP_SHARED | MMapFlags::MAP_PRIVATE);
 
+        // Held until the mapping is created, so a write seal cannot be added between the check
+        // below and the creation of the mapping.
+        let mut writable = None;
+
         match (map_type, file.as_ref()) {
             (MMapFlags::MAP_SHARED, Some(file)) => {
                 vm_flags.insert(VmFlag::SHARED);
@@ -1409,12 +1974,25 @@ impl Vm {
                     // The mapping is going to be read-only forever so, it can be converted into a
                     // private mapping.
                     vm_flags.remove(VmFlag::MAY_WRITE | VmFlag::SHARED);
+                } else {
+                    writable = Some(WritableMapping::new(file.dirnode()));
                 }
 
                 if !file.is_readable() {
                     return None; // EACCES
                 }
 
+                // Writable shared mappings of a write sealed file are not allowed.
+                let seals = file.inode().seals().unwrap_or(0);
+
+                if seals & F_SEAL_WRITE != 0 {
+                    if protection.contains(MMapProt::PROT_WRITE) {
+                        return None; // EPERM
+                    }
+
+                    vm_flags.remove(VmFlag::MAY_WRITE);
+                }
+
                 // TODO: * check if the filsystem is noexec mounted and remove the MAY_EXEC flag.
                 //       * error out if prot contains PROT_EXEC & filesystem is noexec.
             }
@@ -1432,10 +2010,46 @@ impl Vm {
             _ => {}
         }
 
+        if let Some(file) = file.as_ref() {
+            if file.inode().mmap_prepare(offset, size, flags).is_err() {
+                return None;
+            }
+        }
+
         let file = file.map(|file| file.dirnode());
-        self.inner
+        let address = self
+            .inner
             .lock()
-            .mmap(address, size, flags, offset, file, vm_flags)
+            .mmap(address, size, flags, offset, file, vm_flags);
+
+        drop(writable);
+        address
+    }
+
+    /// Maps `size` bytes of the kernel provided `file` (e.g. the vDSO) at the fixed `address`.
+    /// Unlike with [`Vm::mmap`], the protection of the mapping cannot be extended afterwards.
+    pub fn map_special(
+        &self,
+        address: VirtAddr,
+        size: usize,
+        protection: MMapProt,
+        offset: usize,
+        file: &DirCacheItem,
+    ) -> Option<VirtAddr> {
+        let mut vm_flags = VmFlag::from(protection) | VmFlag::MAY_READ;
+
+        if protection.contains(MMapProt::PROT_EXEC) {
+            vm_flags.insert(VmFlag::MAY_EXEC);
+        }
+
+        self.inner.lock().mmap(
+            address,
+            size,
+            MMapFlags::MAP_PRIVATE | MMapFlags::MAP_FIXED,
+            offset,
+            Some(file.clone()),
+            vm_flags,
+        )
     }
 
     pub fn munmap(&self, address: VirtAddr, size: usize) -> bool {
@@ -1469,6 +2083,17 @@ impl Vm {
         self.inner.lock().clear()
     }
 
+    /// Reads all of the pages of this VM that are stored in the swap area with the provided
+    /// index back into memory. Returns `false` if there is not enough memory to do so. The page
+    /// table is updated with the VM lock held, so this can be called on behalf of other tasks.
+    pub fn swap_in_area(&self, area: usize) -> bool {
+        let inner = self.inner.lock();
+
+        inner.address_space().map_or(true, |mut address_space| {
+            inner.swap_in_area(&mut address_space.offset_page_table(), area)
+        })
+    }
+
     /// This function is responsible for handling page faults occurred in
     /// user mode. It determines the address, the reason of the page fault
     /// and then passes it off to one of the appropriate page fault handlers.
@@ -1477,11 +2102,60 @@ impl Vm {
         reason: PageFaultErrorCode,
         accessed_address: VirtAddr,
     ) -> bool {
+        // Enforce the memory limit of the control group before mapping in any more memory.
+        scheduler::current_thread().cgroup().charge_fault();
+
+        for _ in 0..oom::MAX_RETRIES {
+            {
+                let mut inner = self.inner.lock();
+
+                // Try to free up some memory before handling the fault if we are running
+                // low on it.
+                if FRAME_ALLOCATOR.free_frames() < swap::RECLAIM_WATERMARK {
+                    inner.reclaim(accessed_address.align_down(Size4KiB::SIZE));
+                }
+
+                if FRAME_ALLOCATOR.free_frames() >= oom::OOM_WATERMARK {
+                    return inner.handle_page_fault(reason, accessed_address);
+                }
+            }
+
+            // Reclaim was not able to free up enough memory. The VM lock has been dropped
+            // above since the OOM killer might pick this process.
+            if !oom::out_of_memory() {
+                break;
+            }
+        }
+
         self.inner
             .lock()
             .handle_page_fault(reason, accessed_address)
     }
 
+    /// Returns the memory usage of this VM. The page table is walked with the VM lock held, so
+    /// this can be called on behalf of other tasks.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let inner = self.inner.lock();
+
+        inner
+            .address_space()
+            .map(|mut address_space| inner.memory_usage(&address_space.offset_page_table()))
+            .unwrap_or_default()
+    }
+
+    /// Attaches the VM to the provided address space, which the VM is mapped into from now on.
+    pub fn set_address_space(&self, address_space: &AddressSpace) {
+        self.inner.lock().page_table = Some(address_space.cr3());
+    }
+
+    /// Unmaps all of the mappings in the VM and frees the memory backing them. Called when
+    /// the last task using this VM exits; the VM is detached from `address_space`, so its page
+    /// table can be freed afterwards.
+    pub(super) fn release(&self, address_space: &mut AddressSpace) {
+        let mut offset_table = address_space.offset_page_table();
+        self.inner.lock().release(&mut offset_table)
+    }
+
     pub fn for_each_mapping<F>(&self, mut f: F)
     where
         F: FnMut(&Mapping),
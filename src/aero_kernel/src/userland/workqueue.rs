@@ -0,0 +1,98 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Workqueues.
+//!
+//! A workqueue runs deferred work on a dedicated kernel thread, so that code which cannot block
+//! (such as an interrupt handler) or should not wait for the work to finish can hand it off. The
+//! work items of a workqueue are run one at a time, in the order that they were queued.
+//!
+//! Work that does not need a workqueue of its own should be queued on the shared system
+//! workqueue with [`schedule_work`].
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use spin::Once;
+
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::kthread;
+
+type Work = Box<dyn FnOnce() + Send>;
+
+pub struct WorkQueue {
+    items: Mutex<VecDeque<Work>>,
+    wq: WaitQueue,
+}
+
+impl WorkQueue {
+    /// Creates a new workqueue and spawns its worker thread with the provided `name`.
+    pub fn new(name: &str) -> Arc<Self> {
+        let this = Arc::new(Self {
+            items: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+        });
+
+        let worker = this.clone();
+        kthread::spawn(name, move || worker.run());
+
+        this
+    }
+
+    /// Queues `work` to be run by the worker thread. Can be called from an interrupt handler.
+    pub fn queue<F>(&self, work: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.items.lock_irq().push_back(Box::new(work));
+        self.wq.notify();
+    }
+
+    fn run(&self) -> ! {
+        loop {
+            let Ok(mut items) = self.wq.block_on(&self.items, |items| !items.is_empty()) else {
+                // Interrupted by a signal sent to the worker thread; ignore it and wait again.
+                kthread::flush_signals();
+                continue;
+            };
+
+            let work = items.pop_front().unwrap();
+            drop(items);
+
+            work();
+        }
+    }
+}
+
+static SYSTEM_WQ: Once<Arc<WorkQueue>> = Once::new();
+
+/// Queues `work` on the shared system workqueue.
+pub fn schedule_work<F>(work: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    SYSTEM_WQ
+        .get()
+        .expect("workqueue: attempted to schedule work before initialization")
+        .queue(work)
+}
+
+pub fn init() {
+    SYSTEM_WQ.call_once(|| WorkQueue::new("events"));
+}
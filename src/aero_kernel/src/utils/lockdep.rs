@@ -0,0 +1,444 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Lock dependency validator.
+//!
+//! With the `lockdep` feature, every [`Mutex`] belongs to a lock class, which is the location
+//! of the [`Mutex::new`] call that created it. Each acquisition is recorded and validated
+//! against everything that was seen before:
+//!
+//! * Taking a lock that is already held by the current CPU is a deadlock.
+//! * If a lock of class `B` is taken while holding a lock of class `A`, then taking `A` while
+//!   holding `B` (directly or through other locks) can deadlock.
+//! * A lock that is taken in an interrupt handler must never be taken with interrupts enabled (i.e.
+//!   with [`Mutex::lock`] instead of [`Mutex::lock_irq`]), as the interrupt handler spins forever
+//!   if it interrupts the holder of the lock.
+//!
+//! The first problem found is reported along with a backtrace, after which the validator turns
+//! itself off.
+//!
+//! [`Mutex`]: super::sync::Mutex
+//! [`Mutex::new`]: super::sync::Mutex::new
+//! [`Mutex::lock`]: super::sync::Mutex::lock
+//! [`Mutex::lock_irq`]: super::sync::Mutex::lock_irq
+
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::{cpu_local, interrupts};
+use crate::utils::sync::IrqGuard;
+
+pub type LockClass = &'static Location<'static>;
+
+const MAX_HELD_LOCKS: usize = 32;
+const MAX_CLASSES: usize = 1024;
+const MAX_EDGES: usize = 4096;
+
+#[derive(Copy, Clone)]
+struct HeldLock {
+    lock: usize,
+    class: LockClass,
+    location: &'static Location<'static>,
+    /// Interrupt handler nesting level the lock was taken at.
+    hardirq: usize,
+}
+
+/// Locks held by a task, which are saved and restored across context switches along with its
+/// preemption state.
+#[derive(Copy, Clone)]
+pub struct HeldLocks {
+    locks: [Option<HeldLock>; MAX_HELD_LOCKS],
+    depth: usize,
+    hardirq: usize,
+}
+
+impl HeldLocks {
+    const fn new() -> Self {
+        Self {
+            locks: [None; MAX_HELD_LOCKS],
+            depth: 0,
+            hardirq: 0,
+        }
+    }
+}
+
+#[cpu_local]
+static mut HELD_LOCKS: HeldLocks = HeldLocks::new();
+
+#[derive(Copy, Clone)]
+struct Class {
+    key: LockClass,
+    /// Where the lock was taken in an interrupt handler.
+    hardirq: Option<&'static Location<'static>>,
+    /// Where the lock was taken with interrupts enabled.
+    hardirq_unsafe: Option<&'static Location<'static>>,
+}
+
+/// The lock of class `to` was taken at `location` while holding a lock of class `from`.
+#[derive(Copy, Clone)]
+struct Edge {
+    from: u16,
+    to: u16,
+    location: &'static Location<'static>,
+}
+
+struct Graph {
+    classes: [Option<Class>; MAX_CLASSES],
+    edges: [Option<Edge>; MAX_EDGES],
+    nr_edges: usize,
+
+    // Scratch space of the cycle search.
+    visited: [bool; MAX_CLASSES],
+    parent: [Option<u16>; MAX_CLASSES],
+    stack: [u16; MAX_CLASSES],
+}
+
+/// Kept in a raw spinlock, as the validator must not validate itself.
+static GRAPH: spin::Mutex<Graph> = spin::Mutex::new(Graph {
+    classes: [None; MAX_CLASSES],
+    edges: [None; MAX_EDGES],
+    nr_edges: 0,
+
+    visited: [false; MAX_CLASSES],
+    parent: [None; MAX_CLASSES],
+    stack: [0; MAX_CLASSES],
+});
+
+static DEBUG_LOCKS: AtomicBool = AtomicBool::new(true);
+
+enum Problem {
+    Recursive(HeldLock),
+    Circular(HeldLock, LockClass),
+    IrqUnsafe {
+        class: LockClass,
+        hardirq: &'static Location<'static>,
+        hardirq_unsafe: &'static Location<'static>,
+    },
+    Overflow(&'static str),
+}
+
+impl Graph {
+    /// Returns the index of `key` in the class table, inserting it if it is new.
+    fn class(&mut self, key: LockClass) -> Result<u16, Problem> {
+        let hash = ((key as *const Location as usize) >> 3) % MAX_CLASSES;
+
+        for i in 0..MAX_CLASSES {
+            let index = (hash + i) % MAX_CLASSES;
+
+            match &self.classes[index] {
+                Some(class) if core::ptr::eq(class.key, key) => return Ok(index as u16),
+                Some(_) => continue,
+
+                None => {
+                    self.classes[index] = Some(Class {
+                        key,
+                        hardirq: None,
+                        hardirq_unsafe: None,
+                    });
+
+                    return Ok(index as u16);
+                }
+            }
+        }
+
+        Err(Problem::Overflow("too many lock classes"))
+    }
+
+    fn key(&self, class: u16) -> LockClass {
+        self.classes[class as usize].unwrap().key
+    }
+
+    /// Returns whether `to` can be reached from `from`, recording the path in `parent`.
+    fn reachable(&mut self, from: u16, to: u16) -> bool {
+        self.visited.fill(false);
+        self.parent.fill(None);
+
+        self.visited[from as usize] = true;
+        self.stack[0] = from;
+
+        let mut top = 1;
+
+        while top != 0 {
+            top -= 1;
+            let class = self.stack[top];
+
+            if class == to {
+                return true;
+            }
+
+            for edge in self.edges[..self.nr_edges].iter().flatten() {
+                if edge.from == class && !self.visited[edge.to as usize] {
+                    self.visited[edge.to as usize] = true;
+                    self.parent[edge.to as usize] = Some(class);
+                    self.stack[top] = edge.to;
+                    top += 1;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Records that `to` was taken at `location` while holding `from`.
+    fn add_edge(
+        &mut self,
+        from: u16,
+        to: u16,
+        location: &'static Location<'static>,
+    ) -> Result<(), Problem> {
+        if self.nr_edges == MAX_EDGES {
+            return Err(Problem::Overflow("too many lock dependencies"));
+        }
+
+        self.edges[self.nr_edges] = Some(Edge { from, to, location });
+        self.nr_edges += 1;
+
+        Ok(())
+    }
+
+    /// Returns where `to` was taken while holding `from`.
+    fn edge_location(&self, from: u16, to: u16) -> Option<&'static Location<'static>> {
+        self.edges[..self.nr_edges]
+            .iter()
+            .flatten()
+            .find(|e| e.from == from && e.to == to)
+            .map(|e| e.location)
+    }
+}
+
+fn is_enabled() -> bool {
+    DEBUG_LOCKS.load(Ordering::Relaxed) && cpu_local::is_initialized()
+}
+
+fn held_locks() -> &'static mut HeldLocks {
+    // SAFETY: The CPU-local data is only accessed with interrupts disabled.
+    unsafe { &mut *HELD_LOCKS.addr().as_mut_ptr::<HeldLocks>() }
+}
+
+/// Validates and records the acquisition of `lock` of the provided `class` at `location`.
+/// This is called before spinning on the lock, so deadlocks are reported instead of hanging
+/// silently. Failed attempts to take a lock without spinning are not recorded.
+pub fn acquire(lock: usize, class: LockClass, location: &'static Location<'static>, trylock: bool) {
+    if !is_enabled() {
+        return;
+    }
+
+    let irqs_enabled = interrupts::is_enabled();
+    let _guard = IrqGuard::new();
+
+    let held = held_locks();
+
+    let result = validate(held, lock, class, location, irqs_enabled, trylock);
+
+    if held.depth == MAX_HELD_LOCKS {
+        report(Problem::Overflow("too many held locks"), location, held);
+        return;
+    }
+
+    held.locks[held.depth] = Some(HeldLock {
+        lock,
+        class,
+        location,
+        hardirq: held.hardirq,
+    });
+    held.depth += 1;
+
+    if let Err(problem) = result {
+        report(problem, location, held);
+    }
+}
+
+fn validate(
+    held: &HeldLocks,
+    lock: usize,
+    class: LockClass,
+    location: &'static Location<'static>,
+    irqs_enabled: bool,
+    trylock: bool,
+) -> Result<(), Problem> {
+    let mut graph = GRAPH.lock();
+    let index = graph.class(class)?;
+
+    let state = graph.classes[index as usize].as_mut().unwrap();
+
+    if held.hardirq != 0 {
+        state.hardirq.get_or_insert(location);
+    } else if irqs_enabled {
+        state.hardirq_unsafe.get_or_insert(location);
+    }
+
+    if let (Some(hardirq), Some(hardirq_unsafe)) = (state.hardirq, state.hardirq_unsafe) {
+        return Err(Problem::IrqUnsafe {
+            class,
+            hardirq,
+            hardirq_unsafe,
+        });
+    }
+
+    for other in held.locks[..held.depth].iter().flatten() {
+        if other.lock == lock {
+            return Err(Problem::Recursive(*other));
+        }
+
+        // Locks held by the interrupted code do not nest with the ones taken by the interrupt
+        // handler. Locks taken without spinning cannot deadlock.
+        if other.hardirq != held.hardirq || trylock || core::ptr::eq(other.class, class) {
+            continue;
+        }
+
+        let from = graph.class(other.class)?;
+
+        if graph.edge_location(from, index).is_some() {
+            continue;
+        }
+
+        if graph.reachable(index, from) {
+            return Err(Problem::Circular(*other, class));
+        }
+
+        graph.add_edge(from, index, location)?;
+    }
+
+    Ok(())
+}
+
+/// Records the release of `lock`.
+pub fn release(lock: usize) {
+    if !is_enabled() {
+        return;
+    }
+
+    let _guard = IrqGuard::new();
+    let held = held_locks();
+
+    // Locks are not necessarily released in the reverse order they were taken in.
+    if let Some(i) = held.locks[..held.depth]
+        .iter()
+        .rposition(|e| e.is_some_and(|e| e.lock == lock))
+    {
+        held.locks.copy_within(i + 1..held.depth, i);
+        held.depth -= 1;
+        held.locks[held.depth] = None;
+    }
+}
+
+/// Marks the entry into the handler of an external interrupt.
+pub fn hardirq_enter() {
+    if is_enabled() {
+        held_locks().hardirq += 1;
+    }
+}
+
+/// Marks the exit from the handler of an external interrupt.
+pub fn hardirq_exit() {
+    if is_enabled() {
+        let held = held_locks();
+        held.hardirq = held.hardirq.saturating_sub(1);
+    }
+}
+
+/// Saves and resets the locks held on the current CPU before switching to another task.
+pub fn save() -> HeldLocks {
+    if !is_enabled() {
+        return HeldLocks::new();
+    }
+
+    core::mem::replace(held_locks(), HeldLocks::new())
+}
+
+/// Restores the locks held by the task that was switched back to.
+pub fn restore(state: HeldLocks) {
+    if is_enabled() {
+        *held_locks() = state;
+    }
+}
+
+#[cold]
+fn report(problem: Problem, location: &'static Location<'static>, held: &HeldLocks) {
+    // Reporting takes locks as well.
+    if !DEBUG_LOCKS.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    match problem {
+        Problem::Recursive(other) => {
+            log::error!("lockdep: deadlock: lock already held by this CPU");
+            log::error!(
+                "lockdep: taken at {location}, already taken at {}",
+                other.location
+            );
+        }
+
+        Problem::Circular(other, class) => {
+            log::error!("lockdep: possible circular locking dependency");
+            log::error!("lockdep: lock of {class} taken at {location}");
+            log::error!(
+                "lockdep: while holding lock of {} taken at {}",
+                other.class,
+                other.location
+            );
+
+            let mut graph = GRAPH.lock();
+
+            if let (Ok(from), Ok(to)) = (graph.class(class), graph.class(other.class)) {
+                if graph.reachable(from, to) {
+                    log::error!("lockdep: but the locks were taken in the reverse order:");
+
+                    let mut node = to;
+
+                    while let Some(parent) = graph.parent[node as usize] {
+                        log::error!(
+                            "lockdep:   lock of {} taken at {} while holding lock of {}",
+                            graph.key(node),
+                            graph.edge_location(parent, node).unwrap(),
+                            graph.key(parent)
+                        );
+
+                        node = parent;
+                    }
+                }
+            }
+        }
+
+        Problem::IrqUnsafe {
+            class,
+            hardirq,
+            hardirq_unsafe,
+        } => {
+            log::error!("lockdep: inconsistent interrupt state of lock of {class}");
+            log::error!("lockdep: taken in an interrupt handler at {hardirq}");
+            log::error!("lockdep: but taken with interrupts enabled at {hardirq_unsafe}");
+        }
+
+        Problem::Overflow(what) => {
+            log::warn!("lockdep: {what}, turning off the validator");
+            return;
+        }
+    }
+
+    log::error!("lockdep: locks held by this CPU:");
+
+    for lock in held.locks[..held.depth].iter().flatten() {
+        log::error!(
+            "lockdep:   lock of {} taken at {}",
+            lock.class,
+            lock.location
+        );
+    }
+
+    crate::unwind::unwind_stack_trace();
+}
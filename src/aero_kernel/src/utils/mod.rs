@@ -37,7 +37,10 @@ fn get_cpu_count() -> usize {
 pub mod bitmap;
 pub mod buffer;
 pub mod dma;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod sync;
+pub mod uuid;
 
 pub fn validate_mut_ptr<T>(ptr: *mut T) -> Result<&'static mut T, ReadErr> {
     VirtAddr::new(ptr as _).read_mut::<T>()
@@ -151,14 +154,28 @@ impl<T> PerCpu<T> {
         unsafe { (*self.data.get()).as_mut() }
     }
 
+    /// Returns a reference to the data of the current CPU.
     #[inline]
     pub fn get(&self) -> &T {
-        unsafe { &*self.as_mut_ptr().offset(0) }
+        self.get_cpu(crate::arch::tls::get_cpuid())
     }
 
+    /// Returns a mutable reference to the data of the current CPU.
     #[inline]
     pub fn get_mut(&self) -> &mut T {
-        unsafe { &mut *self.as_mut_ptr().offset(0) }
+        unsafe { &mut *self.as_mut_ptr().add(crate::arch::tls::get_cpuid()) }
+    }
+
+    /// Returns a reference to the data of the CPU with the provided logical ID.
+    #[inline]
+    pub fn get_cpu(&self, cpu_id: usize) -> &T {
+        assert!(cpu_id < get_cpu_count());
+        unsafe { &*self.as_mut_ptr().add(cpu_id) }
+    }
+
+    /// Returns an iterator over the data of all of the CPUs, ordered by their logical ID.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..get_cpu_count()).map(|cpu_id| self.get_cpu(cpu_id))
     }
 }
 
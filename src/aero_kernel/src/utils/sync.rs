@@ -19,9 +19,11 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use crate::arch::interrupts;
-use crate::userland::scheduler;
+use crate::userland::scheduler::{self, hrtimer, preempt};
 use crate::userland::signals::SignalResult;
 use crate::userland::task::Task;
+#[cfg(feature = "lockdep")]
+use crate::utils::lockdep;
 
 /// Used to manage and block threads that are waiting for a condition to be true.
 pub struct WaitQueue {
@@ -30,6 +32,7 @@ pub struct WaitQueue {
 
 impl WaitQueue {
     /// Creates a new block queue.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new() -> Self {
         Self {
             queue: Mutex::new(Vec::new()),
@@ -58,7 +61,11 @@ impl WaitQueue {
         // Wait until the future is completed.
         while !future(&mut lock) {
             core::mem::drop(lock); // Drop the IRQ lock and await for IO to complete.
-            scheduler.inner.await_io()?;
+
+            if let Err(signal) = scheduler.inner.await_io() {
+                self.remove(&task);
+                return Err(signal);
+            }
 
             // Re-acquire the lock.
             lock = mutex.lock_irq();
@@ -68,6 +75,52 @@ impl WaitQueue {
         Ok(lock)
     }
 
+    /// Same as [`WaitQueue::block_on`], except that the wait is given up once the monotonic
+    /// clock reaches `deadline` (if provided), in which case [`None`] is returned.
+    pub fn block_on_until<'future, T, F: FnMut(&mut MutexGuard<T>) -> bool>(
+        &self,
+        mutex: &'future Mutex<T>,
+        deadline: Option<u64>,
+        mut future: F,
+    ) -> SignalResult<Option<MutexGuard<'future, T>>> {
+        let Some(deadline) = deadline else {
+            return self.block_on(mutex, future).map(Some);
+        };
+
+        let mut lock = mutex.lock_irq();
+
+        // Check if the future was already completed.
+        if future(&mut lock) {
+            return Ok(Some(lock));
+        }
+
+        let scheduler = scheduler::get_scheduler();
+        let task = scheduler.current_task();
+        let timer = hrtimer::start_timeout(deadline);
+
+        self.queue.lock_irq().push(task.clone());
+
+        // Wait until the future is completed or the timer expires.
+        while !future(&mut lock) {
+            if !timer.is_pending() {
+                self.remove(&task);
+                return Ok(None);
+            }
+
+            core::mem::drop(lock);
+
+            if let Err(signal) = scheduler.inner.await_io() {
+                self.remove(&task);
+                return Err(signal);
+            }
+
+            lock = mutex.lock_irq();
+        }
+
+        self.remove(&task);
+        Ok(Some(lock))
+    }
+
     pub fn insert(&self, task: Arc<Task>) {
         self.queue.lock_irq().push(task);
     }
@@ -79,7 +132,7 @@ impl WaitQueue {
             .iter()
             .enumerate()
             .find_map(|(id, this)| {
-                if this.pid() == task.pid() {
+                if this.tid() == task.tid() {
                     Some(id)
                 } else {
                     None
@@ -150,6 +203,7 @@ pub struct BMutex<T: ?Sized> {
 }
 
 impl<T> BMutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(value: T) -> Self {
         Self {
             wq: WaitQueue::new(),
@@ -202,13 +256,19 @@ impl<'a, T: ?Sized> Drop for BMutexGuard<'a, T> {
 
 /// A spin-based lock providing mutually exclusive access to data.
 pub struct Mutex<T: ?Sized> {
+    /// With the `lockdep` feature, the location the lock was created at identifies its class.
+    #[cfg(feature = "lockdep")]
+    class: lockdep::LockClass,
     inner: spin::Mutex<T>,
 }
 
 impl<T> Mutex<T> {
     /// Creates a new [`Mutex`] wrapping the supplied data.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(value: T) -> Self {
         Self {
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
             inner: spin::Mutex::new(value),
         }
     }
@@ -216,11 +276,20 @@ impl<T> Mutex<T> {
     /// Locks the [`Mutex`] and returns a guard that permits access to the inner data.
     ///
     /// The returned value may be dereferenced for data access and the lock will be dropped
-    /// when the guard falls out of scope.
+    /// when the guard falls out of scope. Kernel preemption is disabled while the lock is held.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock(&self) -> MutexGuard<T> {
+        preempt::disable();
+
+        #[cfg(feature = "lockdep")]
+        self.lockdep_acquire(false);
+
         MutexGuard {
             guard: core::mem::ManuallyDrop::new(self.inner.lock()),
             irq_lock: false,
+            preempt_lock: true,
+            #[cfg(feature = "lockdep")]
+            lock: self.lockdep_key(),
         }
     }
 
@@ -231,6 +300,7 @@ impl<T> Mutex<T> {
     /// interrupts will be re-enabled when the guard falls out of scope. Deadlocks occur if a thread
     /// tries to acquire a lock that will never become free. Thus, locking interrupts is useful for
     /// volatile operations where we might be interrupted.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock_irq(&self) -> MutexGuard<T> {
         let irq_lock = interrupts::is_enabled();
 
@@ -238,9 +308,51 @@ impl<T> Mutex<T> {
             interrupts::disable_interrupts();
         }
 
+        #[cfg(feature = "lockdep")]
+        self.lockdep_acquire(false);
+
         MutexGuard {
             guard: core::mem::ManuallyDrop::new(self.inner.lock()),
             irq_lock,
+            preempt_lock: false,
+            #[cfg(feature = "lockdep")]
+            lock: self.lockdep_key(),
+        }
+    }
+
+    /// Attempts to lock the [`Mutex`] without spinning. Like [`Mutex::lock_irq`], interrupts
+    /// are disabled while the lock is held. Returns [`None`] if the lock is already held.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock_irq(&self) -> Option<MutexGuard<T>> {
+        let irq_lock = interrupts::is_enabled();
+
+        unsafe {
+            interrupts::disable_interrupts();
+        }
+
+        match self.inner.try_lock() {
+            Some(guard) => {
+                #[cfg(feature = "lockdep")]
+                self.lockdep_acquire(true);
+
+                Some(MutexGuard {
+                    guard: core::mem::ManuallyDrop::new(guard),
+                    irq_lock,
+                    preempt_lock: false,
+                    #[cfg(feature = "lockdep")]
+                    lock: self.lockdep_key(),
+                })
+            }
+
+            None => {
+                if irq_lock {
+                    unsafe {
+                        interrupts::enable_interrupts();
+                    }
+                }
+
+                None
+            }
         }
     }
 
@@ -256,9 +368,25 @@ impl<T> Mutex<T> {
     }
 }
 
+#[cfg(feature = "lockdep")]
+impl<T: ?Sized> Mutex<T> {
+    fn lockdep_key(&self) -> usize {
+        (self as *const Self).cast::<u8>() as usize
+    }
+
+    #[track_caller]
+    fn lockdep_acquire(&self, trylock: bool) {
+        let location = core::panic::Location::caller();
+        lockdep::acquire(self.lockdep_key(), self.class, location, trylock);
+    }
+}
+
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
     guard: core::mem::ManuallyDrop<spin::MutexGuard<'a, T>>,
     irq_lock: bool,
+    preempt_lock: bool,
+    #[cfg(feature = "lockdep")]
+    lock: usize,
 }
 
 impl<'a, T: ?Sized> core::ops::Deref for MutexGuard<'a, T> {
@@ -284,6 +412,13 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
             core::mem::ManuallyDrop::drop(&mut self.guard);
         }
 
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock);
+
+        if self.preempt_lock {
+            preempt::enable();
+        }
+
         if self.irq_lock {
             unsafe {
                 interrupts::enable_interrupts();
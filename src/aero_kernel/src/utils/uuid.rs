@@ -0,0 +1,73 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Universally unique identifiers, as used to identify filesystems and GPT partitions.
+
+use core::fmt;
+use core::str::FromStr;
+
+use alloc::vec::Vec;
+
+/// A UUID, with its bytes in the order they are written in its textual representation
+/// (e.g. `123e4567-e89b-12d3-a456-426614174000`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Uuid(pub [u8; 16]);
+
+impl FromStr for Uuid {
+    type Err = ();
+
+    /// Parses a UUID in its canonical textual representation.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut digits = Vec::with_capacity(32);
+
+        for (i, &c) in string.as_bytes().iter().enumerate() {
+            if matches!(i, 8 | 13 | 18 | 23) {
+                if c != b'-' {
+                    return Err(());
+                }
+            } else {
+                digits.push((c as char).to_digit(16).ok_or(())? as u8);
+            }
+        }
+
+        if digits.len() != 32 {
+            return Err(());
+        }
+
+        let mut bytes = [0; 16];
+
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks_exact(2)) {
+            *byte = (pair[0] << 4) | pair[1];
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
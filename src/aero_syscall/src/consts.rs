@@ -102,6 +102,89 @@ pub const SYS_DEBUG: usize = 78;
 pub const SYS_SETSOCKOPT: usize = 79;
 pub const SYS_GETSOCKOPT: usize = 80;
 pub const SYS_SYMLINK_AT: usize = 81;
+pub const SYS_SWAPON: usize = 82;
+pub const SYS_SWAPOFF: usize = 83;
+pub const SYS_MEMFD_CREATE: usize = 84;
+pub const SYS_FTRUNCATE: usize = 85;
+pub const SYS_SHMGET: usize = 86;
+pub const SYS_SHMAT: usize = 87;
+pub const SYS_SHMDT: usize = 88;
+pub const SYS_SHMCTL: usize = 89;
+pub const SYS_SCHED_SETAFFINITY: usize = 90;
+pub const SYS_SCHED_GETAFFINITY: usize = 91;
+pub const SYS_GETPRIORITY: usize = 92;
+pub const SYS_SETPRIORITY: usize = 93;
+pub const SYS_SCHED_SETSCHEDULER: usize = 94;
+pub const SYS_SCHED_GETSCHEDULER: usize = 95;
+pub const SYS_SCHED_SETPARAM: usize = 96;
+pub const SYS_SCHED_GETPARAM: usize = 97;
+pub const SYS_CLOCK_NANOSLEEP: usize = 98;
+pub const SYS_TIMER_CREATE: usize = 99;
+pub const SYS_TIMER_SETTIME: usize = 100;
+pub const SYS_TIMER_GETTIME: usize = 101;
+pub const SYS_TIMER_GETOVERRUN: usize = 102;
+pub const SYS_TIMER_DELETE: usize = 103;
+pub const SYS_GETSID: usize = 104;
+pub const SYS_WAITID: usize = 105;
+pub const SYS_GETRUSAGE: usize = 106;
+pub const SYS_SIGALTSTACK: usize = 107;
+pub const SYS_SIGQUEUE: usize = 108;
+pub const SYS_UNSHARE: usize = 109;
+pub const SYS_SETNS: usize = 110;
+pub const SYS_MOUNT: usize = 111;
+pub const SYS_UMOUNT: usize = 112;
+pub const SYS_SECCOMP: usize = 113;
+pub const SYS_SPAWN: usize = 114;
+pub const SYS_CLONE3: usize = 115;
+pub const SYS_GETCPU: usize = 116;
+pub const SYS_SETTIME: usize = 117;
+pub const SYS_CLOCK_ADJTIME: usize = 118;
+pub const SYS_GETRANDOM: usize = 119;
+pub const SYS_PERF_EVENT_OPEN: usize = 120;
+pub const SYS_INIT_MODULE: usize = 121;
+pub const SYS_DELETE_MODULE: usize = 122;
+pub const SYS_KEXEC_LOAD: usize = 123;
+pub const SYS_MKNOD_AT: usize = 124;
+pub const SYS_FSYNC: usize = 125;
+pub const SYS_SETDOMAINNAME: usize = 126;
+pub const SYS_TIMES: usize = 127;
+pub const SYS_FADVISE64: usize = 128;
+pub const SYS_SPLICE: usize = 129;
+pub const SYS_ACCEPT4: usize = 130;
+pub const SYS_IO_URING_SETUP: usize = 131;
+pub const SYS_IO_URING_ENTER: usize = 132;
+
+// constants for swapon()'s flags argument:
+pub const SWAP_FLAG_PREFER: usize = 0x8000;
+pub const SWAP_FLAG_PRIO_MASK: usize = 0x7fff;
+
+// constants for getpriority() and setpriority()'s which argument:
+pub const PRIO_PROCESS: usize = 0;
+pub const PRIO_PGRP: usize = 1;
+pub const PRIO_USER: usize = 2;
+
+// constants for sched_setscheduler()'s policy argument:
+pub const SCHED_OTHER: usize = 0;
+pub const SCHED_FIFO: usize = 1;
+pub const SCHED_RR: usize = 2;
+
+// constants for waitid()'s idtype argument:
+pub const P_ALL: usize = 0;
+pub const P_PID: usize = 1;
+pub const P_PGID: usize = 2;
+
+// constants for getrusage()'s who argument:
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+pub const RUSAGE_THREAD: isize = 1;
+
+// constants for posix_fadvise()'s advice argument:
+pub const POSIX_FADV_NORMAL: usize = 0;
+pub const POSIX_FADV_RANDOM: usize = 1;
+pub const POSIX_FADV_SEQUENTIAL: usize = 2;
+pub const POSIX_FADV_WILLNEED: usize = 3;
+pub const POSIX_FADV_DONTNEED: usize = 4;
+pub const POSIX_FADV_NOREUSE: usize = 5;
 
 // constants for fcntl()'s command argument:
 // mlibc/abis/linux/fcntl.h
@@ -126,6 +209,8 @@ pub const F_GETOWN_EX: usize = 16;
 pub const F_GETOWNER_UIDS: usize = 17;
 
 pub const F_DUPFD_CLOEXEC: usize = 1030;
+pub const F_SETPIPE_SZ: usize = 1031;
+pub const F_GETPIPE_SZ: usize = 1032;
 pub const F_ADD_SEALS: usize = 1033;
 pub const F_GET_SEALS: usize = 1034;
 
@@ -246,6 +331,46 @@ bitflags::bitflags! {
     }
 }
 
+// constants for memfd_create()'s flags argument:
+bitflags::bitflags! {
+    // mlibc/options/linux/include/sys/mman.h
+    pub struct MemFdFlags: usize {
+        const CLOEXEC       = 1;
+        const ALLOW_SEALING = 2;
+    }
+}
+
+// constants for splice()'s flags argument:
+bitflags::bitflags! {
+    // mlibc/options/linux/include/fcntl.h
+    pub struct SpliceFlags: usize {
+        const MOVE     = 1;
+        const NONBLOCK = 2;
+        const MORE     = 4;
+        const GIFT     = 8;
+    }
+}
+
+// constants for the System V IPC API:
+// mlibc/abis/linux/ipc.h
+pub const IPC_PRIVATE: usize = 0;
+
+pub const IPC_CREAT: usize = 0o1000;
+pub const IPC_EXCL: usize = 0o2000;
+pub const IPC_NOWAIT: usize = 0o4000;
+
+pub const IPC_RMID: usize = 0;
+pub const IPC_SET: usize = 1;
+pub const IPC_STAT: usize = 2;
+
+// constants for shmat()'s flags argument:
+// mlibc/abis/linux/shm.h
+pub const SHM_RDONLY: usize = 0o10000;
+pub const SHM_RND: usize = 0o20000;
+
+/// Segment low boundary address multiple.
+pub const SHMLBA: usize = 4096;
+
 // framebuffer constants:
 //
 // NOTE: The framebuffer constants and structs are derived from the layout
@@ -259,6 +384,8 @@ pub const FBIOPUT_VSCREENINFO: usize = 0x4601;
 pub const FBIOGET_FSCREENINFO: usize = 0x4602;
 pub const FBIOGETCMAP: usize = 0x4604;
 pub const FBIOPUTCMAP: usize = 0x4605;
+pub const FBIOPAN_DISPLAY: usize = 0x4606;
+pub const FBIOBLANK: usize = 0x4611;
 
 pub const FB_TYPE_PACKED_PIXELS: u32 = 0;
 pub const FB_TYPE_PLANES: u32 = 1;
@@ -286,6 +413,12 @@ pub const FB_VMODE_DOUBLE: u32 = 2;
 pub const FB_VMODE_ODD_FLD_FIRST: u32 = 4;
 pub const FB_VMODE_MASK: u32 = 255;
 
+pub const FB_BLANK_UNBLANK: usize = 0;
+pub const FB_BLANK_NORMAL: usize = 1;
+pub const FB_BLANK_VSYNC_SUSPEND: usize = 2;
+pub const FB_BLANK_HSYNC_SUSPEND: usize = 3;
+pub const FB_BLANK_POWERDOWN: usize = 4;
+
 #[derive(Default, Debug, Clone)]
 #[repr(C)]
 pub struct FramebufferBitField {
@@ -390,6 +523,32 @@ pub const SIOCGIFINDEX: usize = 0x8933;
 pub const SIOCGIFHWADDR: usize = 0x8927;
 pub const SIOCSIFADDR: usize = 0x8916; // set PA address
 pub const SIOCSIFNETMASK: usize = 0x891c; // set network PA mask
+pub const SIOCADDRT: usize = 0x890b; // add routing table entry
+pub const SIOCDELRT: usize = 0x890c; // delete routing table entry
+
+// tun ioctls:
+//
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/if_tun.h
+pub const TUNSETIFF: usize = 0x400454ca;
+pub const TUNGETIFF: usize = 0x800454d2;
+
+// `TUNSETIFF` flags (passed in `IfrIfru::flags`):
+pub const IFF_TUN: ffi::c_short = 0x0001;
+pub const IFF_TAP: ffi::c_short = 0x0002;
+pub const IFF_NO_PI: ffi::c_short = 0x1000;
+
+/// Set in [`TunPi::flags`] if the packet was truncated to fit in the buffer it was read into.
+pub const TUN_PKT_STRIP: u16 = 0x0001;
+
+/// Packet information prepended to the packets read from and written to a TUN/TAP device,
+/// unless the device was created with `IFF_NO_PI`.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct TunPi {
+    pub flags: u16,
+    /// Ethertype of the packet (big endian).
+    pub proto: u16,
+}
 
 const IF_NAME_SIZE: usize = 16;
 
@@ -428,3 +587,140 @@ impl IfReq {
         core::str::from_utf8(name).ok()
     }
 }
+
+bitflags::bitflags! {
+    // mlibc/options/linux-headers/include/linux/route.h
+    pub struct RouteFlags: u16 {
+        /// The route is usable.
+        const UP = 0x1;
+        /// The destination is reached through a gateway.
+        const GATEWAY = 0x2;
+        /// The route is a host route (i.e. the netmask is ignored).
+        const HOST = 0x4;
+    }
+}
+
+/// Routing table entry passed to the `SIOCADDRT` and `SIOCDELRT` ioctls.
+#[repr(C)]
+pub struct RtEntry {
+    pub rt_pad1: u64,
+    /// Target address.
+    pub rt_dst: SockAddrStorage,
+    /// Gateway address; used if [`RouteFlags::GATEWAY`] is set.
+    pub rt_gateway: SockAddrStorage,
+    /// Target network mask.
+    pub rt_genmask: SockAddrStorage,
+    pub rt_flags: u16,
+    pub rt_pad2: i16,
+    pub rt_pad3: u64,
+    pub rt_pad4: *mut u8,
+    /// Metric of the route; routes with a lower metric are preferred.
+    pub rt_metric: i16,
+    /// Name of the interface to use for the route (NUL terminated) or null.
+    pub rt_dev: *const u8,
+    pub rt_mtu: u64,
+    pub rt_window: u64,
+    pub rt_irtt: u16,
+}
+
+impl RtEntry {
+    pub fn flags(&self) -> RouteFlags {
+        RouteFlags::from_bits_truncate(self.rt_flags)
+    }
+}
+
+// random ioctls:
+//
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/random.h
+pub const RNDGETENTCNT: usize = 0x80045200;
+pub const RNDADDTOENTCNT: usize = 0x40045201;
+pub const RNDADDENTROPY: usize = 0x40085203;
+pub const RNDRESEEDCRNG: usize = 0x5207;
+
+/// Header of the argument of the `RNDADDENTROPY` ioctl, followed by `buf_size` bytes of data.
+#[repr(C)]
+pub struct RandPoolInfo {
+    pub entropy_count: i32,
+    pub buf_size: i32,
+}
+
+// watchdog ioctls:
+//
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/watchdog.h
+pub const WDIOC_GETSUPPORT: usize = 0x80285700;
+pub const WDIOC_GETSTATUS: usize = 0x80045701;
+pub const WDIOC_GETBOOTSTATUS: usize = 0x80045702;
+pub const WDIOC_SETOPTIONS: usize = 0x80045704;
+pub const WDIOC_KEEPALIVE: usize = 0x80045705;
+pub const WDIOC_SETTIMEOUT: usize = 0xc0045706;
+pub const WDIOC_GETTIMEOUT: usize = 0x80045707;
+pub const WDIOC_GETTIMELEFT: usize = 0x8004570a;
+
+/// The machine was last rebooted by the watchdog.
+pub const WDIOF_CARDRESET: u32 = 0x0020;
+/// The timeout can be set with `WDIOC_SETTIMEOUT`.
+pub const WDIOF_SETTIMEOUT: u32 = 0x0080;
+/// The watchdog is only stopped on close if `V` was written to it first.
+pub const WDIOF_MAGICCLOSE: u32 = 0x0100;
+/// The watchdog can be pinged with `WDIOC_KEEPALIVE`.
+pub const WDIOF_KEEPALIVEPING: u32 = 0x8000;
+
+pub const WDIOS_DISABLECARD: u32 = 0x0001;
+pub const WDIOS_ENABLECARD: u32 = 0x0002;
+
+/// Argument of the `WDIOC_GETSUPPORT` ioctl.
+#[repr(C)]
+pub struct WatchdogInfo {
+    pub options: u32,
+    pub firmware_version: u32,
+    pub identity: [u8; 32],
+}
+
+// block device ioctls:
+//
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/fs.h
+pub const BLKRRPART: usize = 0x125f;
+pub const BLKFLSBUF: usize = 0x1261;
+pub const BLKSSZGET: usize = 0x1268;
+pub const BLKGETSIZE64: usize = 0x80081272;
+
+// SCSI generic ioctls:
+//
+// https://github.com/torvalds/linux/blob/master/include/scsi/sg.h
+pub const SG_IO: usize = 0x2285;
+
+pub const SG_DXFER_NONE: i32 = -1;
+pub const SG_DXFER_TO_DEV: i32 = -2;
+pub const SG_DXFER_FROM_DEV: i32 = -3;
+
+/// The command completed with sense data in the sense buffer.
+pub const SG_INFO_CHECK: u32 = 0x1;
+
+/// Argument of the `SG_IO` ioctl.
+#[repr(C)]
+pub struct SgIoHdr {
+    /// Always `'S'`.
+    pub interface_id: i32,
+    pub dxfer_direction: i32,
+    pub cmd_len: u8,
+    pub mx_sb_len: u8,
+    pub iovec_count: u16,
+    pub dxfer_len: u32,
+    pub dxferp: usize,
+    pub cmdp: usize,
+    pub sbp: usize,
+    /// The timeout in milliseconds.
+    pub timeout: u32,
+    pub flags: u32,
+    pub pack_id: i32,
+    pub usr_ptr: usize,
+    pub status: u8,
+    pub masked_status: u8,
+    pub msg_status: u8,
+    pub sb_len_wr: u8,
+    pub host_status: u16,
+    pub driver_status: u16,
+    pub resid: i32,
+    pub duration: u32,
+    pub info: u32,
+}
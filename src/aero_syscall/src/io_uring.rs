@@ -0,0 +1,112 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Definitions for the asynchronous I/O rings of `io_uring_setup(2)` and `io_uring_enter(2)`.
+//!
+//! The structures follow the ones of Linux, except that the submission queue entries are
+//! consumed in the order of the ring (there is no indirection array) and that the whole ring is
+//! mapped with a single `mmap(2)` of [`IoUringParams::ring_size`] bytes at offset zero.
+//!
+//! https://github.com/torvalds/linux/blob/master/include/uapi/linux/io_uring.h
+
+use static_assertions::const_assert_eq;
+
+/// Maximum number of submission queue entries of a ring.
+pub const IORING_MAX_ENTRIES: u32 = 4096;
+
+// Opcodes of the submission queue entries.
+pub const IORING_OP_NOP: u8 = 0;
+pub const IORING_OP_FSYNC: u8 = 3;
+pub const IORING_OP_READ: u8 = 22;
+pub const IORING_OP_WRITE: u8 = 23;
+
+// Flags of `IORING_OP_FSYNC`.
+pub const IORING_FSYNC_DATASYNC: u32 = 1 << 0;
+
+// Flags of `io_uring_enter(2)`.
+pub const IORING_ENTER_GETEVENTS: usize = 1 << 0;
+
+/// Offsets of the submission queue in the ring mapping.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IoSqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    /// Offset of the array of [`IoUringSqe`]s.
+    pub sqes: u32,
+}
+
+/// Offsets of the completion queue in the ring mapping.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IoCqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    /// Offset of the array of [`IoUringCqe`]s.
+    pub cqes: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IoUringParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    /// Setup flags, none of which are supported yet.
+    pub flags: u32,
+    pub resv: u32,
+    /// Size of the ring mapping.
+    pub ring_size: u64,
+    pub sq_off: IoSqringOffsets,
+    pub cq_off: IoCqringOffsets,
+}
+
+/// A submission queue entry.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IoUringSqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    /// Offset of the file to read from or write to, or `u64::MAX` to use (and advance) the
+    /// file offset.
+    pub off: u64,
+    pub addr: u64,
+    pub len: u32,
+    /// `IORING_FSYNC_*` flags of `IORING_OP_FSYNC`.
+    pub op_flags: u32,
+    /// Passed back in the completion queue entry of the request.
+    pub user_data: u64,
+    pub pad: [u64; 3],
+}
+
+/// A completion queue entry.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IoUringCqe {
+    pub user_data: u64,
+    /// Result of the request, or the negated error number if it failed.
+    pub res: i32,
+    pub flags: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<IoUringSqe>(), 64);
+const_assert_eq!(core::mem::size_of::<IoUringCqe>(), 16);
@@ -24,7 +24,10 @@
 extern crate num_derive;
 
 pub mod consts;
+pub mod io_uring;
 pub mod netlink;
+pub mod perf;
+pub mod seccomp;
 pub mod signal;
 pub mod socket;
 pub mod syscall;
@@ -107,6 +110,40 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    pub struct CloneFlags: usize {
+        const CLONE_VM      = 0x00000100;
+        const CLONE_FS      = 0x00000200;
+        const CLONE_FILES   = 0x00000400;
+        const CLONE_SIGHAND = 0x00000800;
+        const CLONE_THREAD  = 0x00010000;
+        const CLONE_NEWNS   = 0x00020000;
+        const CLONE_SETTLS  = 0x00080000;
+        const CLONE_NEWPID  = 0x20000000;
+    }
+}
+
+/// Size of the first published version of [`CloneArgs`].
+pub const CLONE_ARGS_SIZE_VER0: usize = 64;
+
+/// Arguments of `clone3`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CloneArgs {
+    pub flags: u64,
+    pub pidfd: u64,
+    pub child_tid: u64,
+    pub parent_tid: u64,
+    pub exit_signal: u64,
+    /// Lowest address of the stack of the child.
+    pub stack: u64,
+    pub stack_size: u64,
+    pub tls: u64,
+    pub set_tid: u64,
+    pub set_tid_size: u64,
+    pub cgroup: u64,
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(isize)]
 #[allow(clippy::enum_clike_unportable_variant)]
@@ -222,6 +259,12 @@ pub struct SysDirEntry {
     pub name: [u8; 0],
 }
 
+// limits.h
+/// The maximum length of the host and domain names, excluding the terminating null byte.
+pub const HOST_NAME_MAX: usize = 64;
+/// The maximum number of bytes that are written atomically to a pipe.
+pub const PIPE_BUF: usize = 4096;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct Utsname {
@@ -253,6 +296,10 @@ impl Utsname {
     pub fn machine(&self) -> &str {
         unsafe { core::str::from_utf8_unchecked(&self.machine) }
     }
+
+    pub fn domainname(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.domainname) }
+    }
 }
 
 impl Default for Utsname {
@@ -306,12 +353,14 @@ impl From<usize> for SeekWhence {
 
 pub const TIOCGWINSZ: usize = 0x5413;
 pub const TIOCSWINSZ: usize = 0x5414;
+pub const TIOCSTI: usize = 0x5412;
 pub const TCGETS: usize = 0x5401;
 pub const TCSETSW: usize = 0x5403;
 pub const TCSETSF: usize = 0x5404;
 pub const TIOCSCTTY: usize = 0x540e;
 pub const TIOCNOTTY: usize = 0x5422;
 pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
 
 #[derive(Default, Debug, Copy, Clone)]
 #[repr(C)]
@@ -322,6 +371,22 @@ pub struct WinSize {
     pub ws_ypixel: u16,
 }
 
+pub const KDGKBTYPE: usize = 0x4b33;
+pub const KDGKBENT: usize = 0x4b46;
+pub const KDSKBENT: usize = 0x4b47;
+
+/// Keyboard type reported by `KDGKBTYPE`.
+pub const KB_101: u8 = 0x02;
+
+/// An entry of a keyboard translation table (see `KDGKBENT` and `KDSKBENT`).
+#[derive(Default, Debug, Copy, Clone)]
+#[repr(C)]
+pub struct KbEntry {
+    pub kb_table: u8,
+    pub kb_index: u8,
+    pub kb_value: u16,
+}
+
 // indices for the c_cc array in struct termios
 //
 // abis/linux/termios.h
@@ -596,8 +661,34 @@ impl SocketAddrInet {
     }
 }
 
+// mlibc/options/linux-headers/include/linux/if_packet.h
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct SocketAddrPacket {
+    pub family: u32,
+    /// Physical-layer protocol (ethertype) in network byte order.
+    pub protocol: BigEndian<u16>,
+    /// Interface index; zero matches any interface.
+    pub ifindex: i32,
+    /// ARP hardware type.
+    pub hatype: u16,
+    /// Packet type (one of the `PACKET_*` constants).
+    pub pkttype: u8,
+    /// Length of the physical-layer address.
+    pub halen: u8,
+    /// Physical-layer address.
+    pub addr: [u8; 8],
+}
+
+impl SocketAddrPacket {
+    pub fn protocol(&self) -> u16 {
+        self.protocol.to_native()
+    }
+}
+
 impl SocketAddr for SocketAddrUnix {}
 impl SocketAddr for SocketAddrInet {}
+impl SocketAddr for SocketAddrPacket {}
 
 // mlibc/abi-bits/mlibc/in.h
 #[derive(Debug, Copy, Clone, FromPrimitive, PartialEq)]
@@ -664,6 +755,7 @@ pub const PF_LOCAL: u32 = 3;
 pub const PF_UNSPEC: u32 = 4;
 pub const PF_NETLINK: u32 = 5;
 pub const PF_BRIDGE: u32 = 6;
+pub const PF_PACKET: u32 = 13;
 
 pub const AF_INET: u32 = PF_INET;
 pub const AF_INET6: u32 = PF_INET6;
@@ -672,6 +764,33 @@ pub const AF_LOCAL: u32 = PF_LOCAL;
 pub const AF_UNSPEC: u32 = PF_UNSPEC;
 pub const AF_NETLINK: u32 = PF_NETLINK;
 pub const AF_BRIDGE: u32 = PF_BRIDGE;
+pub const AF_PACKET: u32 = PF_PACKET;
+
+// Ethernet protocol IDs (linux/if_ether.h):
+pub const ETH_P_ALL: u16 = 0x0003;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+
+// ARP hardware types (linux/if_arp.h):
+pub const ARPHRD_ETHER: u16 = 1;
+pub const ARPHRD_LOOPBACK: u16 = 772;
+pub const ARPHRD_NONE: u16 = 0xfffe;
+
+// Interface flags (net/if.h):
+pub const IFF_UP: u32 = 0x1;
+pub const IFF_BROADCAST: u32 = 0x2;
+pub const IFF_LOOPBACK: u32 = 0x8;
+pub const IFF_POINTOPOINT: u32 = 0x10;
+pub const IFF_RUNNING: u32 = 0x40;
+pub const IFF_NOARP: u32 = 0x80;
+pub const IFF_MULTICAST: u32 = 0x1000;
+
+// Packet types (linux/if_packet.h):
+pub const PACKET_HOST: u8 = 0;
+pub const PACKET_BROADCAST: u8 = 1;
+pub const PACKET_MULTICAST: u8 = 2;
+pub const PACKET_OTHERHOST: u8 = 3;
+pub const PACKET_OUTGOING: u8 = 4;
 
 // mlibc/abis/linux/stat.h
 bitflags::bitflags! {
@@ -732,6 +851,62 @@ pub struct Stat {
     pub __unused: [ffi::c_long; 3],
 }
 
+// mlibc/options/linux/include/sys/sysmacros.h
+pub const fn makedev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+
+    ((major & 0xfffff000) << 32)
+        | ((major & 0x00000fff) << 8)
+        | ((minor & 0xffffff00) << 12)
+        | (minor & 0x000000ff)
+}
+
+pub const fn major(dev: u64) -> u32 {
+    (((dev >> 32) & 0xfffff000) | ((dev >> 8) & 0x00000fff)) as u32
+}
+
+pub const fn minor(dev: u64) -> u32 {
+    (((dev >> 12) & 0xffffff00) | (dev & 0x000000ff)) as u32
+}
+
+// mlibc/abis/linux/ipc.h
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IpcPerm {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: i32,
+    // FIXME: make this private
+    pub __pad: [ffi::c_long; 2],
+}
+
+// mlibc/abis/linux/shm.h
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ShmIdDs {
+    pub shm_perm: IpcPerm,
+    pub shm_segsz: usize,
+    pub shm_atime: i64,
+    pub shm_dtime: i64,
+    pub shm_ctime: i64,
+    pub shm_cpid: i32,
+    pub shm_lpid: i32,
+    pub shm_nattch: u64,
+    // FIXME: make this private
+    pub __unused: [ffi::c_ulong; 2],
+}
+
+// mlibc/options/posix/include/bits/posix/posix_sched.h
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
 bitflags::bitflags! {
     // mlibc/abis/linux/fcntl.h
     #[repr(transparent)]
@@ -755,3 +930,42 @@ bitflags::bitflags! {
         const NO_AUTOMOUNT = 0x800;
     }
 }
+
+// sys/reboot.h
+pub const RB_AUTOBOOT: usize = 0x01234567;
+pub const RB_HALT_SYSTEM: usize = 0xcdef0123;
+pub const RB_POWER_OFF: usize = 0x4321fedc;
+/// Jump into the kernel loaded with `kexec_load`.
+pub const RB_KEXEC: usize = 0x45584543;
+
+// linux/kexec.h
+/// Load the kernel to be executed on a kernel panic instead.
+pub const KEXEC_ON_CRASH: usize = 0x00000001;
+pub const KEXEC_ARCH_MASK: usize = 0xffff0000;
+/// The maximum number of segments accepted by `kexec_load`.
+pub const KEXEC_SEGMENT_MAX: usize = 16;
+
+/// A segment of the kernel image passed to `kexec_load`; `buf` is a user buffer of `bufsz`
+/// bytes which is copied to the physical address `mem`. The rest of the `memsz` bytes are
+/// zeroed.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct KexecSegment {
+    pub buf: usize,
+    pub bufsz: usize,
+    pub mem: usize,
+    pub memsz: usize,
+}
+
+bitflags::bitflags! {
+    // sys/random.h
+    #[repr(transparent)]
+    pub struct GetRandomFlags: usize {
+        /// Return `EAGAIN` instead of blocking if the random number generator is not seeded.
+        const NONBLOCK = 0x1;
+        /// Draw from the blocking `/dev/random` source.
+        const RANDOM = 0x2;
+        /// Never block, even if the random number generator is not seeded yet.
+        const INSECURE = 0x4;
+    }
+}
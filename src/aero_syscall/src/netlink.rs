@@ -2,6 +2,18 @@
 
 use static_assertions::const_assert_eq;
 
+/// Routing and link updates (the only supported netlink family).
+pub const NETLINK_ROUTE: u32 = 0;
+
+// Multicast groups of `NETLINK_ROUTE`, as used in the `nl_groups` mask.
+pub const RTMGRP_LINK: u32 = 0x1;
+pub const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+pub const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+
+// Options available at the `SOL_NETLINK` level.
+pub const NETLINK_ADD_MEMBERSHIP: i32 = 1;
+pub const NETLINK_DROP_MEMBERSHIP: i32 = 2;
+
 const NLMSG_ALIGNTO: u32 = 4;
 
 /// Aligns `len` to the netlink message alignment.
@@ -21,7 +33,8 @@ pub const fn rta_length(len: u32) -> u32 {
     rta_align(core::mem::size_of::<rtattr>() as u32) + len
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// Attributes of route messages (`RTA_*`).
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
 #[repr(u16)]
 pub enum RtAttrType {
     Unspec,
@@ -57,7 +70,29 @@ pub enum RtAttrType {
     // RtaNH_ID,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// Attributes of link messages (`IFLA_*`).
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(u16)]
+pub enum LinkAttrType {
+    Unspec,
+    Address,
+    Broadcast,
+    IfName,
+    Mtu,
+}
+
+/// Attributes of address messages (`IFA_*`).
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(u16)]
+pub enum AddrAttrType {
+    Unspec,
+    Address,
+    Local,
+    Label,
+    Broadcast,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
 #[repr(u16)]
 pub enum MessageType {
     Noop,
@@ -156,6 +191,12 @@ bitflags::bitflags! {
         const MATCH = 0x200; // return all matching.
         const ATOMIC = 0x400; // atomic GET.
         const DUMP = MessageFlags::ROOT.bits() | MessageFlags::MATCH.bits();
+
+        // Modifiers to NEW request.
+        const REPLACE = 0x100; // Override existing.
+        const EXCL = 0x200; // Do not touch, if it exists.
+        const CREATE = 0x400; // Create, if it does not exist.
+        const APPEND = 0x800; // Add to end of list.
     }
 }
 
@@ -171,13 +212,14 @@ pub struct sockaddr_nl {
 impl super::SocketAddr for sockaddr_nl {}
 
 /// Fixed format metadata header of Netlink messages.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct nlmsghdr {
     /// Length of message including header.
     pub nlmsg_len: u32,
-    /// Message content type.
-    pub nlmsg_type: MessageType,
+    /// Message content type (see [`MessageType`]). Kept as a plain integer as the message
+    /// types sent by userspace are not guaranteed to be known.
+    pub nlmsg_type: u16,
     // Additional flags.
     pub nlmsg_flags: MessageFlags,
     /// Sequence number.
@@ -188,8 +230,18 @@ pub struct nlmsghdr {
 
 const_assert_eq!(core::mem::size_of::<nlmsghdr>(), 16);
 
+/// Payload of [`MessageType::Error`] messages, also used to acknowledge requests.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct nlmsgerr {
+    /// Negative errno or zero for acknowledgements.
+    pub error: i32,
+    /// Header of the message that caused the error.
+    pub msg: nlmsghdr,
+}
+
 /// General form of address family dependent message.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct rtgenmsg {
     pub rtgen_family: u8,
@@ -198,7 +250,7 @@ pub struct rtgenmsg {
 const_assert_eq!(core::mem::size_of::<rtgenmsg>(), 1);
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct rtmsg {
     pub rtm_family: u8,
     pub rtm_dst_len: u8,
@@ -213,6 +265,36 @@ pub struct rtmsg {
 
 const_assert_eq!(core::mem::size_of::<rtmsg>(), 12);
 
+/// Payload of link messages.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct ifinfomsg {
+    pub ifi_family: u8,
+    pub __ifi_pad: u8,
+    /// ARP hardware type (one of the `ARPHRD_*` constants).
+    pub ifi_type: u16,
+    pub ifi_index: i32,
+    /// Interface flags (`IFF_*`).
+    pub ifi_flags: u32,
+    /// Mask of the flags to change.
+    pub ifi_change: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<ifinfomsg>(), 16);
+
+/// Payload of address messages.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct ifaddrmsg {
+    pub ifa_family: u8,
+    pub ifa_prefixlen: u8,
+    pub ifa_flags: u8,
+    pub ifa_scope: u8,
+    pub ifa_index: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<ifaddrmsg>(), 8);
+
 // FIXME(andypython): This should be an enum.
 //
 // Reserved table identifiers.
@@ -222,9 +304,22 @@ pub const RT_TABLE_DEFAULT: u8 = 253;
 pub const RT_TABLE_MAIN: u8 = 254;
 pub const RT_TABLE_LOCAL: u8 = 255;
 
+// Route origins (`rtm_protocol`).
+pub const RTPROT_KERNEL: u8 = 2;
+pub const RTPROT_BOOT: u8 = 3;
+
+// Route scopes (`rtm_scope` and `ifa_scope`).
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+pub const RT_SCOPE_LINK: u8 = 253;
+pub const RT_SCOPE_HOST: u8 = 254;
+
+// Route types (`rtm_type`).
+pub const RTN_UNICAST: u8 = 1;
+
 // Generic structure for encapsulation of optional route information. It is reminiscent of sockaddr,
 // but with sa_family replaced with attribute type.
 pub struct rtattr {
     pub rta_len: u16,
-    pub rta_type: RtAttrType,
+    /// Attribute type, which depends on the type of the message (e.g. [`RtAttrType`]).
+    pub rta_type: u16,
 }
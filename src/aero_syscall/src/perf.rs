@@ -0,0 +1,161 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Definitions for `perf_event_open(2)`.
+//!
+//! https://github.com/torvalds/linux/blob/master/include/uapi/linux/perf_event.h
+
+use static_assertions::const_assert_eq;
+
+pub const PERF_TYPE_HARDWARE: u32 = 0;
+pub const PERF_TYPE_RAW: u32 = 4;
+
+// Generalized hardware events (`PERF_TYPE_HARDWARE`).
+pub const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+pub const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+pub const PERF_COUNT_HW_CACHE_REFERENCES: u64 = 2;
+pub const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+pub const PERF_COUNT_HW_BRANCH_INSTRUCTIONS: u64 = 4;
+pub const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+pub const PERF_COUNT_HW_BUS_CYCLES: u64 = 6;
+pub const PERF_COUNT_HW_STALLED_CYCLES_FRONTEND: u64 = 7;
+pub const PERF_COUNT_HW_STALLED_CYCLES_BACKEND: u64 = 8;
+pub const PERF_COUNT_HW_REF_CPU_CYCLES: u64 = 9;
+
+// Values of `PerfEventAttr::sample_type`.
+pub const PERF_SAMPLE_IP: u64 = 1 << 0;
+pub const PERF_SAMPLE_TID: u64 = 1 << 1;
+pub const PERF_SAMPLE_TIME: u64 = 1 << 2;
+pub const PERF_SAMPLE_CPU: u64 = 1 << 7;
+pub const PERF_SAMPLE_PERIOD: u64 = 1 << 8;
+
+// Values of `PerfEventAttr::read_format`.
+pub const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+pub const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+pub const PERF_FORMAT_ID: u64 = 1 << 2;
+pub const PERF_FORMAT_GROUP: u64 = 1 << 3;
+
+// Flags of `perf_event_open(2)`.
+pub const PERF_FLAG_FD_NO_GROUP: usize = 1 << 0;
+pub const PERF_FLAG_FD_OUTPUT: usize = 1 << 1;
+pub const PERF_FLAG_PID_CGROUP: usize = 1 << 2;
+pub const PERF_FLAG_FD_CLOEXEC: usize = 1 << 3;
+
+// ioctl(2) commands of a perf event file descriptor.
+pub const PERF_EVENT_IOC_ENABLE: usize = 0x2400;
+pub const PERF_EVENT_IOC_DISABLE: usize = 0x2401;
+pub const PERF_EVENT_IOC_REFRESH: usize = 0x2402;
+pub const PERF_EVENT_IOC_RESET: usize = 0x2403;
+pub const PERF_EVENT_IOC_PERIOD: usize = 0x40082404;
+pub const PERF_EVENT_IOC_ID: usize = 0x80082407;
+
+// Types of the records written to the ring buffer.
+pub const PERF_RECORD_LOST: u32 = 2;
+pub const PERF_RECORD_SAMPLE: u32 = 9;
+
+// Values of `PerfEventHeader::misc`.
+pub const PERF_RECORD_MISC_KERNEL: u16 = 1;
+pub const PERF_RECORD_MISC_USER: u16 = 2;
+
+/// Size of the first published version of [`PerfEventAttr`].
+pub const PERF_ATTR_SIZE_VER0: u32 = 64;
+
+bitflags::bitflags! {
+    /// The bitfield following `read_format` in [`PerfEventAttr`].
+    #[repr(transparent)]
+    pub struct PerfEventAttrFlags: u64 {
+        const DISABLED = 1 << 0;
+        const INHERIT = 1 << 1;
+        const PINNED = 1 << 2;
+        const EXCLUSIVE = 1 << 3;
+        const EXCLUDE_USER = 1 << 4;
+        const EXCLUDE_KERNEL = 1 << 5;
+        const EXCLUDE_HV = 1 << 6;
+        const EXCLUDE_IDLE = 1 << 7;
+        const MMAP = 1 << 8;
+        const COMM = 1 << 9;
+        const FREQ = 1 << 10;
+        const INHERIT_STAT = 1 << 11;
+        const ENABLE_ON_EXEC = 1 << 12;
+        const TASK = 1 << 13;
+        const WATERMARK = 1 << 14;
+    }
+}
+
+/// The attributes of the event to be created by `perf_event_open(2)`. Only the fields of
+/// [`PERF_ATTR_SIZE_VER0`] are defined; any bytes past them must be zero.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PerfEventAttr {
+    pub typ: u32,
+    pub size: u32,
+    pub config: u64,
+    /// Also `sample_freq` if [`PerfEventAttrFlags::FREQ`] is set.
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    pub flags: u64,
+    /// Also `wakeup_watermark` if [`PerfEventAttrFlags::WATERMARK`] is set.
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+}
+
+/// Header of every record written to the ring buffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PerfEventHeader {
+    pub typ: u32,
+    pub misc: u16,
+    pub size: u16,
+}
+
+/// The first page of the ring buffer mapped by `mmap(2)` on a perf event file descriptor.
+/// The records follow at `data_offset`.
+#[repr(C)]
+pub struct PerfEventMmapPage {
+    pub version: u32,
+    pub compat_version: u32,
+    pub lock: u32,
+    pub index: u32,
+    pub offset: i64,
+    pub time_enabled: u64,
+    pub time_running: u64,
+    pub capabilities: u64,
+    pub pmc_width: u16,
+    pub time_shift: u16,
+    pub time_mult: u32,
+    pub time_offset: u64,
+    pub time_zero: u64,
+    pub size: u32,
+    pub __reserved_1: u32,
+    pub time_cycles: u64,
+    pub time_mask: u64,
+    pub __reserved: [u8; 116 * 8],
+    /// Offset past the last record written by the kernel; it only ever increases.
+    pub data_head: u64,
+    /// Offset past the last record consumed by userspace; written by userspace.
+    pub data_tail: u64,
+    pub data_offset: u64,
+    pub data_size: u64,
+}
+
+const_assert_eq!(
+    core::mem::size_of::<PerfEventAttr>(),
+    PERF_ATTR_SIZE_VER0 as usize
+);
+const_assert_eq!(core::mem::offset_of!(PerfEventMmapPage, data_head), 1024);
@@ -0,0 +1,176 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Definitions for `seccomp(2)` and the classic BPF programs used by its filters.
+
+pub const SECCOMP_SET_MODE_STRICT: usize = 0;
+pub const SECCOMP_SET_MODE_FILTER: usize = 1;
+pub const SECCOMP_GET_ACTION_AVAIL: usize = 2;
+
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000;
+pub const SECCOMP_RET_KILL_THREAD: u32 = 0x00000000;
+pub const SECCOMP_RET_TRAP: u32 = 0x00030000;
+pub const SECCOMP_RET_ERRNO: u32 = 0x00050000;
+pub const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc00000;
+pub const SECCOMP_RET_TRACE: u32 = 0x7ff00000;
+pub const SECCOMP_RET_LOG: u32 = 0x7ffc0000;
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff0000;
+
+pub const SECCOMP_RET_ACTION_FULL: u32 = 0xffff0000;
+pub const SECCOMP_RET_DATA: u32 = 0x0000ffff;
+
+/// `si_code` of the `SIGSYS` signal sent by the [`SECCOMP_RET_TRAP`] action.
+pub const SYS_SECCOMP: i32 = 1;
+
+pub const AUDIT_ARCH_X86_64: u32 = 0xc000003e;
+
+/// Maximum number of instructions in a BPF program.
+pub const BPF_MAXINSNS: usize = 4096;
+/// Number of words in the scratch memory of a BPF program.
+pub const BPF_MEMWORDS: usize = 16;
+
+// Instruction classes.
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_LDX: u16 = 0x01;
+pub const BPF_ST: u16 = 0x02;
+pub const BPF_STX: u16 = 0x03;
+pub const BPF_ALU: u16 = 0x04;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_MISC: u16 = 0x07;
+
+// Sizes of the load instructions.
+pub const BPF_W: u16 = 0x00;
+pub const BPF_H: u16 = 0x08;
+pub const BPF_B: u16 = 0x10;
+
+// Addressing modes of the load instructions.
+pub const BPF_IMM: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_IND: u16 = 0x40;
+pub const BPF_MEM: u16 = 0x60;
+pub const BPF_LEN: u16 = 0x80;
+pub const BPF_MSH: u16 = 0xa0;
+
+// ALU operations.
+pub const BPF_ADD: u16 = 0x00;
+pub const BPF_SUB: u16 = 0x10;
+pub const BPF_MUL: u16 = 0x20;
+pub const BPF_DIV: u16 = 0x30;
+pub const BPF_OR: u16 = 0x40;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_LSH: u16 = 0x60;
+pub const BPF_RSH: u16 = 0x70;
+pub const BPF_NEG: u16 = 0x80;
+pub const BPF_MOD: u16 = 0x90;
+pub const BPF_XOR: u16 = 0xa0;
+
+// Jump operations.
+pub const BPF_JA: u16 = 0x00;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_JGE: u16 = 0x30;
+pub const BPF_JSET: u16 = 0x40;
+
+// Sources of the operand of the ALU and jump instructions.
+pub const BPF_K: u16 = 0x00;
+pub const BPF_X: u16 = 0x08;
+
+// Return value of the return instructions.
+pub const BPF_A: u16 = 0x10;
+
+// Miscellaneous operations.
+pub const BPF_TAX: u16 = 0x00;
+pub const BPF_TXA: u16 = 0x80;
+
+#[inline]
+pub const fn bpf_class(code: u16) -> u16 {
+    code & 0x07
+}
+
+#[inline]
+pub const fn bpf_size(code: u16) -> u16 {
+    code & 0x18
+}
+
+#[inline]
+pub const fn bpf_mode(code: u16) -> u16 {
+    code & 0xe0
+}
+
+#[inline]
+pub const fn bpf_op(code: u16) -> u16 {
+    code & 0xf0
+}
+
+#[inline]
+pub const fn bpf_src(code: u16) -> u16 {
+    code & 0x08
+}
+
+#[inline]
+pub const fn bpf_rval(code: u16) -> u16 {
+    code & 0x18
+}
+
+#[inline]
+pub const fn bpf_miscop(code: u16) -> u16 {
+    code & 0xf8
+}
+
+/// A classic BPF instruction.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl SockFilter {
+    pub const fn stmt(code: u16, k: u32) -> Self {
+        Self {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    pub const fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+/// A classic BPF program.
+#[derive(Debug)]
+#[repr(C)]
+pub struct SockFprog {
+    pub len: u16,
+    pub filter: *const SockFilter,
+}
+
+/// The input of a seccomp filter, describing the system call being made.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct SeccompData {
+    pub nr: i32,
+    pub arch: u32,
+    pub instruction_pointer: u64,
+    pub args: [u64; 6],
+}
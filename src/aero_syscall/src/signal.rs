@@ -60,6 +60,18 @@ pub const SIGSYS: usize = 31;
 pub const SIGUNUSED: usize = SIGSYS;
 pub const SIGCANCEL: usize = 32;
 
+// constants for sigaltstack()
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+pub const MINSIGSTKSZ: usize = 2048;
+pub const SIGSTKSZ: usize = 8192;
+
+// constants for sigevent
+pub const SIGEV_SIGNAL: i32 = 0;
+pub const SIGEV_NONE: i32 = 1;
+pub const SIGEV_THREAD: i32 = 2;
+pub const SIGEV_THREAD_ID: i32 = 4;
+
 pub const SIG_ERR: i64 = -1; // error
 pub const SIG_DFL: i64 = 0; // default
 pub const SIG_IGN: i64 = 1; // ignore
@@ -165,3 +177,147 @@ impl From<SigProcMask> for usize {
         s as u64 as usize
     }
 }
+
+/// Specifies how to notify the process about an event (for example, the expiration of a
+/// timer).
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SigEvent {
+    pub sigev_value: u64,
+    pub sigev_signo: i32,
+    pub sigev_notify: i32,
+    pub sigev_notify_function: u64,
+    pub sigev_notify_attributes: u64,
+    pub __pad: [u64; 4],
+}
+
+/// Alternate signal stack (see `sigaltstack(2)`).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StackT {
+    pub ss_sp: u64,
+    pub ss_flags: i32,
+    pub ss_size: usize,
+}
+
+/// Register state of the interrupted code, laid out like `mcontext_t` on x86_64 (see
+/// `getcontext(3)`).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MContext {
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rsp: u64,
+    pub rip: u64,
+    pub eflags: u64,
+    /// The `cs`, `gs`, `fs` and `ss` selectors, 16 bits each.
+    pub csgsfs: u64,
+    pub err: u64,
+    pub trapno: u64,
+    pub oldmask: u64,
+    pub cr2: u64,
+    /// Pointer to the saved floating point state, if any.
+    pub fpregs: u64,
+    pub __reserved: [u64; 8],
+}
+
+/// User context passed as the third argument to `SA_SIGINFO` signal handlers (see
+/// `sigaction(2)`). The context is restored when the handler returns.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UContext {
+    pub uc_flags: u64,
+    pub uc_link: u64,
+    pub uc_stack: StackT,
+    pub uc_mcontext: MContext,
+    pub uc_sigmask: u64,
+}
+
+// Values of `si_code` for any signal:
+pub const SI_USER: i32 = 0;
+pub const SI_KERNEL: i32 = 0x80;
+pub const SI_QUEUE: i32 = -1;
+pub const SI_TIMER: i32 = -2;
+
+// Values of `si_code` for `SIGILL`:
+pub const ILL_ILLOPN: i32 = 2;
+
+// Values of `si_code` for `SIGFPE`:
+pub const FPE_INTDIV: i32 = 1;
+pub const FPE_FLTINV: i32 = 7;
+
+// Values of `si_code` for `SIGSEGV`:
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+
+// Values of `si_code` for `SIGBUS`:
+pub const BUS_ADRALN: i32 = 1;
+
+// Values of `si_code` for `SIGCHLD`:
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_TRAPPED: i32 = 4;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// Information about a signal (see `sigaction(2)`).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    pub __pad0: i32,
+    pub si_pid: i32,
+    pub si_uid: u32,
+    pub si_status: i32,
+    pub __pad1: i32,
+    pub si_utime: i64,
+    pub si_stime: i64,
+    pub __pad: [u64; 10],
+}
+
+impl SigInfo {
+    pub fn new(signal: usize, code: i32) -> Self {
+        Self {
+            si_signo: signal as i32,
+            si_code: code,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the address of the fault (`si_addr`), which shares its storage with `si_pid` and
+    /// `si_uid`.
+    pub fn set_addr(&mut self, addr: u64) {
+        self.si_pid = addr as u32 as i32;
+        self.si_uid = (addr >> 32) as u32;
+    }
+
+    /// Sets the system call that caused a `SIGSYS` signal (`si_syscall` and `si_arch`), which
+    /// share their storage with `si_status`.
+    pub fn set_syscall(&mut self, syscall: usize, arch: u32) {
+        self.si_status = syscall as i32;
+        self.__pad1 = arch as i32;
+    }
+
+    /// Sets the value sent along with the signal (`si_value`), which shares its storage with
+    /// `si_status`.
+    pub fn set_value(&mut self, value: u64) {
+        self.si_status = value as u32 as i32;
+        self.__pad1 = (value >> 32) as u32 as i32;
+    }
+}
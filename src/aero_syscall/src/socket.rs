@@ -9,10 +9,44 @@ mod c {
     pub const SCM_RIGHTS: i32 = 1;
     pub const SCM_CREDENTIALS: i32 = 2;
 
+    pub const SOL_IP: i32 = 0;
     pub const SOL_SOCKET: i32 = 1;
     pub const SOL_IPV6: i32 = 41;
     pub const SOL_PACKET: i32 = 263;
     pub const SOL_NETLINK: i32 = 270;
+    // IPPROTO_TCP from mlibc/abi-bits/mlibc/in.h
+    pub const SOL_TCP: i32 = 5;
+
+    // mlibc/abis/mlibc/socket.h
+    pub const SO_ACCEPTCONN: i32 = 1;
+    pub const SO_BROADCAST: i32 = 2;
+    pub const SO_DEBUG: i32 = 3;
+    pub const SO_DONTROUTE: i32 = 4;
+    pub const SO_ERROR: i32 = 5;
+    pub const SO_KEEPALIVE: i32 = 6;
+    pub const SO_LINGER: i32 = 7;
+    pub const SO_OOBINLINE: i32 = 8;
+    pub const SO_RCVBUF: i32 = 9;
+    pub const SO_RCVLOWAT: i32 = 10;
+    pub const SO_RCVTIMEO: i32 = 11;
+    pub const SO_REUSEADDR: i32 = 12;
+    pub const SO_SNDBUF: i32 = 13;
+    pub const SO_SNDLOWAT: i32 = 14;
+    pub const SO_SNDTIMEO: i32 = 15;
+    pub const SO_TYPE: i32 = 16;
+    pub const SO_PEERCRED: i32 = 18;
+    pub const SO_PASSCRED: i32 = 20;
+    pub const SO_REUSEPORT: i32 = 24;
+
+    // mlibc/abis/mlibc/tcp.h
+    pub const TCP_NODELAY: i32 = 1;
+    pub const TCP_MAXSEG: i32 = 2;
+    pub const TCP_KEEPIDLE: i32 = 4;
+    pub const TCP_KEEPINTVL: i32 = 5;
+    pub const TCP_KEEPCNT: i32 = 6;
+
+    // linux/in.h
+    pub const IP_RECVERR: i32 = 11;
 }
 
 bitflags::bitflags! {
@@ -37,6 +71,9 @@ bitflags::bitflags! {
         const DONTWAIT = 0x1000;
         const CMSG_CLOEXEC = 0x2000;
         const MORE = 0x4000;
+        /// Receives a queued error from the socket's error queue instead of data (see
+        /// [`IpOption::RecvErr`]).
+        const ERRQUEUE = 0x8000;
         const FASTOPEN = 0x20000000;
     }
 }
@@ -53,7 +90,7 @@ pub struct MessageHeader {
     iovec: *mut IoVec, // todo: use Option<NonNull<IoVec>>
     iovec_len: i32,    // todo: use ffi::c_int
 
-    control: *const u8,
+    control: *mut u8,
     control_len: c::socklen_t,
 
     pub flags: i32, // todo: use ffi::c_int
@@ -78,9 +115,129 @@ impl MessageHeader {
         unsafe { core::slice::from_raw_parts_mut(self.iovec, self.iovec_len as usize) }
     }
 
-    pub fn control(&self) -> &[ControlMessage] {
-        assert!(self.control_len == 0);
-        &[]
+    /// Returns an iterator over the control messages (`struct cmsghdr`) present in the
+    /// ancillary data buffer.
+    pub fn control(&self) -> ControlMessageIter<'_> {
+        let buffer = if self.control.is_null() {
+            &[]
+        } else {
+            // SAFETY: The caller has validated that the control buffer is valid and mapped.
+            unsafe { core::slice::from_raw_parts(self.control, self.control_len as usize) }
+        };
+
+        ControlMessageIter { buffer, offset: 0 }
+    }
+
+    /// Returns the capacity of the ancillary data buffer.
+    pub fn control_capacity(&self) -> usize {
+        if self.control.is_null() {
+            0
+        } else {
+            self.control_len as usize
+        }
+    }
+
+    /// Writes the provided control messages into the ancillary data buffer and updates
+    /// `msg_controllen` to the number of bytes written. If the buffer is too small to hold
+    /// all of them, the remaining messages are dropped and `MSG_CTRUNC` is set.
+    ///
+    /// Returns the number of control messages that were written.
+    pub fn write_control(
+        &mut self,
+        messages: &[(SocketOptionLevel, ControlMessageType, &[u8])],
+    ) -> usize {
+        let capacity = self.control_capacity();
+        let mut offset = 0;
+        let mut written = 0;
+
+        for (level, typ, data) in messages {
+            let len = cmsg_len(data.len());
+
+            if offset + len > capacity {
+                self.flags |= MessageFlags::CTRUNC.bits() as i32;
+                break;
+            }
+
+            let header = ControlMessage {
+                cmsg_len: len as c::socklen_t,
+                cmsg_level: *level as i32,
+                cmsg_type: *typ as i32,
+            };
+
+            // SAFETY: We have checked above that the message fits in the control buffer.
+            unsafe {
+                let ptr = self.control.add(offset);
+
+                ptr.cast::<ControlMessage>().write_unaligned(header);
+                ptr.add(cmsg_align(core::mem::size_of::<ControlMessage>()))
+                    .copy_from_nonoverlapping(data.as_ptr(), data.len());
+            }
+
+            offset += cmsg_space(data.len()).min(capacity - offset);
+            written += 1;
+        }
+
+        self.control_len = offset as c::socklen_t;
+        written
+    }
+}
+
+/// Rounds `len` up to the alignment of ancillary data objects (`CMSG_ALIGN`).
+pub const fn cmsg_align(len: usize) -> usize {
+    (len + core::mem::size_of::<usize>() - 1) & !(core::mem::size_of::<usize>() - 1)
+}
+
+/// Returns the value to store in `cmsg_len` for a control message carrying `len` bytes
+/// of data (`CMSG_LEN`).
+pub const fn cmsg_len(len: usize) -> usize {
+    cmsg_align(core::mem::size_of::<ControlMessage>()) + len
+}
+
+/// Returns the number of bytes a control message carrying `len` bytes of data occupies,
+/// including padding (`CMSG_SPACE`).
+pub const fn cmsg_space(len: usize) -> usize {
+    cmsg_align(core::mem::size_of::<ControlMessage>()) + cmsg_align(len)
+}
+
+pub struct ControlMessageIter<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for ControlMessageIter<'a> {
+    type Item = (ControlMessage, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_size = core::mem::size_of::<ControlMessage>();
+        let remaining = self.buffer.len().checked_sub(self.offset)?;
+
+        if remaining < header_size {
+            return None;
+        }
+
+        // SAFETY: We have checked above that the header is in bounds.
+        let header = unsafe {
+            self.buffer
+                .as_ptr()
+                .add(self.offset)
+                .cast::<ControlMessage>()
+                .read_unaligned()
+        };
+
+        let len = header.cmsg_len as usize;
+
+        if len < header_size || len > remaining {
+            return None;
+        }
+
+        let data_start = self.offset + cmsg_align(header_size);
+        let data = self
+            .buffer
+            .get(data_start..self.offset + len)
+            .unwrap_or(&[]);
+
+        self.offset += cmsg_align(len);
+        Some((header, data))
     }
 }
 
@@ -111,30 +268,132 @@ impl IoVec {
 }
 
 /// Control Message Header (`struct cmsghdr`).
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct ControlMessage {
     /// Data byte count, including the header.
     pub cmsg_len: c::socklen_t,
     /// Originating protocol.
-    pub cmsg_level: SocketOptionLevel,
+    pub cmsg_level: i32,
     /// Protocol-specific type.
-    pub cmsg_type: ControlMessageType,
+    pub cmsg_type: i32,
     // followed by cmsg_data: [u8; cmsg_len - sizeof(struct cmsghdr)]
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+impl ControlMessage {
+    /// Returns the originating protocol, or [`None`] if it is not known.
+    pub fn level(&self) -> Option<SocketOptionLevel> {
+        num_traits::FromPrimitive::from_i32(self.cmsg_level)
+    }
+
+    /// Returns the protocol-specific type, or [`None`] if it is not known.
+    pub fn typ(&self) -> Option<ControlMessageType> {
+        num_traits::FromPrimitive::from_i32(self.cmsg_type)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
 #[repr(i32)]
 pub enum ControlMessageType {
     Rights = c::SCM_RIGHTS,
     Credentials = c::SCM_CREDENTIALS,
+    /// An extended error from the error queue, sent at the [`SocketOptionLevel::Ip`] level.
+    RecvErr = c::IP_RECVERR,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
 #[repr(i32)]
 pub enum SocketOptionLevel {
+    Ip = c::SOL_IP,
     Socket = c::SOL_SOCKET,
     Ipv6 = c::SOL_IPV6,
     Packet = c::SOL_PACKET,
     Netlink = c::SOL_NETLINK,
+    Tcp = c::SOL_TCP,
+}
+
+/// Options available at the [`SocketOptionLevel::Socket`] level.
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(i32)]
+pub enum SocketOption {
+    AcceptConn = c::SO_ACCEPTCONN,
+    Broadcast = c::SO_BROADCAST,
+    Debug = c::SO_DEBUG,
+    DontRoute = c::SO_DONTROUTE,
+    Error = c::SO_ERROR,
+    KeepAlive = c::SO_KEEPALIVE,
+    Linger = c::SO_LINGER,
+    OobInline = c::SO_OOBINLINE,
+    RecvBuf = c::SO_RCVBUF,
+    RecvLowat = c::SO_RCVLOWAT,
+    RecvTimeout = c::SO_RCVTIMEO,
+    ReuseAddr = c::SO_REUSEADDR,
+    SendBuf = c::SO_SNDBUF,
+    SendLowat = c::SO_SNDLOWAT,
+    SendTimeout = c::SO_SNDTIMEO,
+    Type = c::SO_TYPE,
+    PeerCred = c::SO_PEERCRED,
+    PassCred = c::SO_PASSCRED,
+    ReusePort = c::SO_REUSEPORT,
+}
+
+/// Options available at the [`SocketOptionLevel::Tcp`] level.
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(i32)]
+pub enum TcpOption {
+    NoDelay = c::TCP_NODELAY,
+    MaxSegment = c::TCP_MAXSEG,
+    KeepIdle = c::TCP_KEEPIDLE,
+    KeepInterval = c::TCP_KEEPINTVL,
+    KeepCount = c::TCP_KEEPCNT,
+}
+
+/// Options available at the [`SocketOptionLevel::Ip`] level.
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(i32)]
+pub enum IpOption {
+    /// Queues the errors reported by ICMP for the socket, to be received using
+    /// [`MessageFlags::ERRQUEUE`].
+    RecvErr = c::IP_RECVERR,
+}
+
+// linux/errqueue.h
+pub const SO_EE_ORIGIN_NONE: u8 = 0;
+pub const SO_EE_ORIGIN_LOCAL: u8 = 1;
+pub const SO_EE_ORIGIN_ICMP: u8 = 2;
+
+/// Extended error received from the error queue of a socket, as the payload of a
+/// [`ControlMessageType::RecvErr`] control message. For ICMP errors, it is followed by the
+/// address of the host that reported the error.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct SockExtendedErr {
+    pub ee_errno: u32,
+    /// Where the error originated from (one of the `SO_EE_ORIGIN_*` constants).
+    pub ee_origin: u8,
+    pub ee_type: u8,
+    pub ee_code: u8,
+    pub ee_pad: u8,
+    pub ee_info: u32,
+    pub ee_data: u32,
+}
+
+/// Credentials of a process (`struct ucred`), as the value of the `SO_PEERCRED` socket
+/// option and the payload of a [`ControlMessageType::Credentials`] control message.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct ucred {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Value of the `SO_LINGER` socket option.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct Linger {
+    /// Whether lingering is enabled.
+    pub l_onoff: i32,
+    /// Linger time, in seconds.
+    pub l_linger: i32,
 }
@@ -15,11 +15,21 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::TimeSpec;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+
 pub const ITIMER_REAL: usize = 0;
 pub const ITIMER_VIRTUAL: usize = 1;
 pub const ITIMER_PROF: usize = 2;
 
-#[derive(Default, PartialEq)]
+/// The sleep request of `clock_nanosleep` is an absolute time of the clock.
+pub const TIMER_ABSTIME: usize = 1;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 #[repr(C)]
 pub struct TimeVal {
     pub tv_sec: i64,
@@ -32,3 +42,93 @@ pub struct ITimerVal {
     pub it_interval: TimeVal, // Interval for periodic timer
     pub it_value: TimeVal,    // Time until next expiration
 }
+
+#[derive(Debug, Default, Clone)]
+#[repr(C)]
+pub struct ITimerSpec {
+    pub it_interval: TimeSpec, // Interval for periodic timer
+    pub it_value: TimeSpec,    // Time until next expiration
+}
+
+// Modes of `clock_adjtime`:
+pub const ADJ_OFFSET: u32 = 0x0001;
+pub const ADJ_FREQUENCY: u32 = 0x0002;
+pub const ADJ_MAXERROR: u32 = 0x0004;
+pub const ADJ_ESTERROR: u32 = 0x0008;
+pub const ADJ_STATUS: u32 = 0x0010;
+pub const ADJ_TIMECONST: u32 = 0x0020;
+pub const ADJ_TAI: u32 = 0x0080;
+pub const ADJ_SETOFFSET: u32 = 0x0100;
+pub const ADJ_MICRO: u32 = 0x1000;
+pub const ADJ_NANO: u32 = 0x2000;
+pub const ADJ_TICK: u32 = 0x4000;
+/// The `adjtime(3)` compatible mode; the offset is slewed and always in microseconds.
+pub const ADJ_OFFSET_SINGLESHOT: u32 = 0x8001;
+/// Only reads the remaining `adjtime(3)` offset.
+pub const ADJ_OFFSET_SS_READ: u32 = 0xa001;
+
+/// The offset and time are in nanoseconds instead of microseconds.
+pub const STA_NANO: i32 = 0x2000;
+
+/// The clock is synchronized.
+pub const TIME_OK: usize = 0;
+
+/// Parameters of the NTP clock discipline (see `clock_adjtime(2)`).
+#[derive(Debug, Default, Clone)]
+#[repr(C)]
+pub struct Timex {
+    pub modes: u32,
+    pub offset: i64,
+    /// The frequency offset, in parts per million with a 16-bit fractional part.
+    pub freq: i64,
+    pub maxerror: i64,
+    pub esterror: i64,
+    pub status: i32,
+    pub constant: i64,
+    pub precision: i64,
+    pub tolerance: i64,
+    pub time: TimeVal,
+    pub tick: i64,
+    pub ppsfreq: i64,
+    pub jitter: i64,
+    pub shift: i32,
+    pub stabil: i64,
+    pub jitcnt: i64,
+    pub calcnt: i64,
+    pub errcnt: i64,
+    pub stbcnt: i64,
+    pub tai: i32,
+    pub __padding: [i32; 11],
+}
+
+/// Resource usage of a process (see `getrusage(2)`).
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct RUsage {
+    pub ru_utime: TimeVal, // User CPU time used
+    pub ru_stime: TimeVal, // System CPU time used
+    pub ru_maxrss: i64,    // Maximum resident set size (in kilobytes)
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    pub ru_minflt: i64,
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    pub ru_nvcsw: i64,
+    pub ru_nivcsw: i64,
+}
+
+/// CPU times of a process, in clock ticks (see `times(2)`).
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct Tms {
+    pub tms_utime: i64,  // User CPU time
+    pub tms_stime: i64,  // System CPU time
+    pub tms_cutime: i64, // User CPU time of the waited for children
+    pub tms_cstime: i64, // System CPU time of the waited for children
+}
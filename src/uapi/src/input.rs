@@ -0,0 +1,130 @@
+//! evdev input event device (`/dev/input/event*`) interface.
+
+use crate::ioctl;
+use core::ffi;
+
+pub const EV_VERSION: ffi::c_int = 0x010001;
+
+// Event types.
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_SW: u16 = 0x05;
+pub const EV_MAX: u16 = 0x1f;
+
+// Synchronization events.
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+// Keys and buttons. The key codes are the same as the ones of the keyboard driver.
+pub const KEY_POWER: u16 = 116;
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+pub const KEY_MAX: u16 = 0x2ff;
+
+// Relative axes.
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_WHEEL: u16 = 0x08;
+pub const REL_MAX: u16 = 0x0f;
+
+// Absolute axes.
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_MAX: u16 = 0x3f;
+
+// Switches.
+pub const SW_LID: u16 = 0x00;
+pub const SW_MAX: u16 = 0x10;
+
+// Bus types.
+pub const BUS_USB: u16 = 0x03;
+pub const BUS_I8042: u16 = 0x11;
+pub const BUS_HOST: u16 = 0x19;
+
+/// A timestamped input event (`struct input_event`).
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct InputEvent {
+    pub sec: i64,
+    pub usec: i64,
+    pub typ: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Identifies the bus and the model of an input device (`struct input_id`).
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+/// The range and state of an absolute axis (`struct input_absinfo`).
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct InputAbsInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+const EVIOC_BASE: usize = 'E' as usize;
+
+pub const EVIOCGVERSION: usize = ioctl::ior::<ffi::c_int>(EVIOC_BASE, 0x01);
+pub const EVIOCGID: usize = ioctl::ior::<InputId>(EVIOC_BASE, 0x02);
+pub const EVIOCGRAB: usize = ioctl::iow::<ffi::c_int>(EVIOC_BASE, 0x90);
+
+// The following take the size of the user buffer, so they have to be matched using
+// `ioctl::ioc_nr` once the type and direction have been checked.
+
+/// `EVIOCGNAME(len)`: get the name of the device.
+pub const EVIOCGNAME_NR: usize = 0x06;
+/// `EVIOCGPHYS(len)`: get the physical location of the device.
+pub const EVIOCGPHYS_NR: usize = 0x07;
+/// `EVIOCGUNIQ(len)`: get the unique identifier of the device.
+pub const EVIOCGUNIQ_NR: usize = 0x08;
+/// `EVIOCGPROP(len)`: get the device properties.
+pub const EVIOCGPROP_NR: usize = 0x09;
+/// `EVIOCGKEY(len)`: get the state of the keys (which ones are down).
+pub const EVIOCGKEY_NR: usize = 0x18;
+/// `EVIOCGLED(len)`: get the state of the LEDs.
+pub const EVIOCGLED_NR: usize = 0x19;
+/// `EVIOCGSND(len)`: get the state of the sounds.
+pub const EVIOCGSND_NR: usize = 0x1a;
+/// `EVIOCGSW(len)`: get the state of the switches.
+pub const EVIOCGSW_NR: usize = 0x1b;
+/// `EVIOCGBIT(ev, len)`: get the codes supported for the event type `ev`, or the supported
+/// event types if `ev` is zero.
+pub const EVIOCGBIT_NR: usize = 0x20;
+/// `EVIOCGABS(abs)`: get the state of the absolute axis `abs`.
+pub const EVIOCGABS_NR: usize = 0x40;
+
+#[inline]
+pub const fn eviocgname(len: usize) -> usize {
+    ioctl::ioc(ioctl::IOC_READ, EVIOC_BASE, EVIOCGNAME_NR, len)
+}
+
+#[inline]
+pub const fn eviocgbit(ev: usize, len: usize) -> usize {
+    ioctl::ioc(ioctl::IOC_READ, EVIOC_BASE, EVIOCGBIT_NR + ev, len)
+}
+
+#[inline]
+pub const fn eviocgabs(abs: usize) -> usize {
+    ioctl::ior::<InputAbsInfo>(EVIOC_BASE, EVIOCGABS_NR + abs)
+}
+
+/// Returns whether `command` is an evdev ioctl that reads into the user buffer.
+#[inline]
+pub const fn is_evioc_read(command: usize) -> bool {
+    ioctl::ioc_type(command) == EVIOC_BASE && ioctl::ioc_dir(command) == ioctl::IOC_READ
+}
@@ -6,6 +6,7 @@
 pub const IOC_NRBITS: usize = 8;
 pub const IOC_TYPEBITS: usize = 8;
 pub const IOC_SIZEBITS: usize = 14;
+pub const IOC_DIRBITS: usize = 2;
 
 pub const IOC_NRSHIFT: usize = 0;
 pub const IOC_TYPESHIFT: usize = IOC_NRSHIFT + IOC_NRBITS;
@@ -46,3 +47,24 @@ pub const fn iow<T>(typ: usize, nr: usize) -> usize {
 pub const fn iowr<T>(typ: usize, nr: usize) -> usize {
     ioc(IOC_READ | IOC_WRITE, typ, nr, core::mem::size_of::<T>())
 }
+
+// Used to decode numbers, e.g. the ones with a variable size.
+#[inline]
+pub const fn ioc_dir(nr: usize) -> usize {
+    (nr >> IOC_DIRSHIFT) & ((1 << IOC_DIRBITS) - 1)
+}
+
+#[inline]
+pub const fn ioc_type(nr: usize) -> usize {
+    (nr >> IOC_TYPESHIFT) & ((1 << IOC_TYPEBITS) - 1)
+}
+
+#[inline]
+pub const fn ioc_nr(nr: usize) -> usize {
+    (nr >> IOC_NRSHIFT) & ((1 << IOC_NRBITS) - 1)
+}
+
+#[inline]
+pub const fn ioc_size(nr: usize) -> usize {
+    (nr >> IOC_SIZESHIFT) & ((1 << IOC_SIZEBITS) - 1)
+}
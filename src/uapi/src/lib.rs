@@ -1,5 +1,7 @@
 #![no_std]
 
 pub mod drm;
+pub mod input;
 pub mod ioctl;
 pub mod pty;
+pub mod sound;
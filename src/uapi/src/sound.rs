@@ -0,0 +1,29 @@
+//! OSS compatible PCM device (`/dev/dsp`) interface.
+
+use crate::ioctl;
+use core::ffi;
+
+pub const SNDCTL_DSP_RESET: usize = ioctl::io('P' as usize, 0);
+pub const SNDCTL_DSP_SYNC: usize = ioctl::io('P' as usize, 1);
+pub const SNDCTL_DSP_SPEED: usize = ioctl::iowr::<ffi::c_int>('P' as usize, 2);
+pub const SNDCTL_DSP_GETBLKSIZE: usize = ioctl::iowr::<ffi::c_int>('P' as usize, 4);
+pub const SNDCTL_DSP_SETFMT: usize = ioctl::iowr::<ffi::c_int>('P' as usize, 5);
+pub const SNDCTL_DSP_CHANNELS: usize = ioctl::iowr::<ffi::c_int>('P' as usize, 6);
+pub const SNDCTL_DSP_GETFMTS: usize = ioctl::ior::<ffi::c_int>('P' as usize, 11);
+pub const SNDCTL_DSP_GETOSPACE: usize = ioctl::ior::<AudioBufInfo>('P' as usize, 12);
+
+/// Signed 16-bit little-endian samples.
+pub const AFMT_S16_LE: ffi::c_int = 0x00000010;
+
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct AudioBufInfo {
+    /// Number of fragments that can be written without blocking.
+    pub fragments: ffi::c_int,
+    /// Total number of fragments in the buffer.
+    pub fragstotal: ffi::c_int,
+    /// Size of a fragment in bytes.
+    pub fragsize: ffi::c_int,
+    /// Number of bytes that can be written without blocking.
+    pub bytes: ffi::c_int,
+}
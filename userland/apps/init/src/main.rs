@@ -22,6 +22,7 @@ use std::process::Command;
 
 const TTY_PATH: &str = "/dev/vtty";
 const DEV_NULL: &str = "/dev/null";
+const HOSTNAME_PATH: &str = "/etc/hostname";
 
 struct FileSet<const N: usize>([File; N]);
 
@@ -47,6 +48,12 @@ impl<const N: usize> FileSet<N> {
     }
 }
 
+/// Sets the host name of the system to the one in `/etc/hostname`.
+fn set_hostname() -> std::io::Result<()> {
+    let hostname = std::fs::read_to_string(HOSTNAME_PATH)?;
+    std::fs::write("/proc/sys/kernel/hostname", hostname.trim())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     // Open the stdin, stdout and stderr file descriptors.
     let stdin = OpenOptions::new().read(true).open(TTY_PATH)?; // fd=0
@@ -57,6 +64,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         let stdset = FileSet::new([stdin, stdout, stderr]);
         stdset.remove_cloexec();
 
+        if let Err(err) = set_hostname() {
+            eprintln!("init: failed to set the host name: {err}");
+        }
+
         Command::new("dhcpd").spawn()?;
     }
 
@@ -752,6 +752,59 @@ DEFINE_TEST(sysenter_system_call, ([] {
 }))
 #endif
 
+#if defined(__aero__)
+DEFINE_TEST(spawn, ([] {
+#ifndef SYS_SPAWN
+#define SYS_SPAWN 114
+#endif
+
+	// The kernel takes the path and the arguments as (pointer, length) pairs.
+	struct slice {
+		const char *ptr;
+		size_t len;
+	};
+
+	const char *path = "/usr/bin/bash";
+	const char *argv[] = {"bash", "-c", "exit 3"};
+
+	slice args[3];
+	for (size_t i = 0; i < 3; i++)
+		args[i] = {argv[i], strlen(argv[i])};
+
+	register long r10 __asm__("r10") = 3;
+	register long r8 __asm__("r8") = 0;
+	register long r9 __asm__("r9") = 0;
+
+	long pid;
+	asm volatile("syscall"
+		: "=a"(pid)
+		: "a"(SYS_SPAWN), "D"(path), "S"(strlen(path)), "d"(args), "r"(r10), "r"(r8), "r"(r9)
+		: "rcx", "r11", "memory");
+
+	if (pid < 0) {
+		errno = -pid;
+		assert_errno("spawn", false);
+	}
+
+	int status = 0;
+	if (waitpid(pid, &status, 0) == -1)
+		assert(!"waitpid() failed");
+
+	assert(WIFEXITED(status));
+	assert(WEXITSTATUS(status) == 3);
+
+	// The program is looked up before the child is created.
+	const char *missing = "/does/not/exist";
+
+	asm volatile("syscall"
+		: "=a"(pid)
+		: "a"(SYS_SPAWN), "D"(missing), "S"(strlen(missing)), "d"(args), "r"(r10), "r"(r8), "r"(r9)
+		: "rcx", "r11", "memory");
+
+	assert(pid == -ENOENT);
+}))
+#endif
+
 std::vector<abstract_test_case *> &test_case_ptrs() {
 	static std::vector<abstract_test_case *> singleton;
 	return singleton;